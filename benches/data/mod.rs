@@ -0,0 +1,64 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Game data for the benchmarks.
+//!
+//! Most benchmarks need files from the game, which can't be distributed with the engine. These are
+//! read from the game directory named by the `RICHTER_BENCH_BASEDIR` environment variable, or from
+//! `id1` if it isn't set. Benchmarks whose files can't be found are skipped with a message rather
+//! than failing, so that the rest of the suite still runs.
+
+use std::{env, io::Read};
+
+use richter::common::{self, vfs::Vfs};
+
+/// The map used by benchmarks that need a level.
+pub const BENCH_MAP: &str = "maps/e1m1.bsp";
+
+/// Returns the virtual filesystem for the benchmark game directory.
+pub fn vfs() -> Option<Vfs> {
+    let base_dir =
+        env::var("RICHTER_BENCH_BASEDIR").unwrap_or_else(|_| common::DEFAULT_BASEDIR.to_owned());
+
+    match Vfs::with_base_dir(&base_dir) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            eprintln!(
+                "Skipping benchmarks that need game data: {}: {}",
+                base_dir, e
+            );
+            None
+        }
+    }
+}
+
+/// Reads the whole of `path` from the virtual filesystem.
+///
+/// Returns `None` and prints a message if the file doesn't exist.
+pub fn read(vfs: &Vfs, path: &str) -> Option<Vec<u8>> {
+    let mut file = match vfs.open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("Skipping benchmarks that need {}: {}", path, e);
+            return None;
+        }
+    };
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).unwrap();
+    Some(data)
+}
@@ -0,0 +1,62 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Benchmarks for loading BSP and MDL files.
+
+mod data;
+
+use std::io::Cursor;
+
+use richter::common::{bsp, mdl};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+const BENCH_MDL: &str = "progs/player.mdl";
+
+fn bench_bsp(c: &mut Criterion) {
+    let bsp_data = match data::vfs().and_then(|vfs| data::read(&vfs, data::BENCH_MAP)) {
+        Some(d) => d,
+        None => return,
+    };
+
+    let mut group = c.benchmark_group("bsp");
+    group.throughput(Throughput::Bytes(bsp_data.len() as u64));
+    group.bench_function("load", |b| {
+        b.iter(|| bsp::load(Cursor::new(black_box(bsp_data.as_slice()))).unwrap())
+    });
+    group.bench_function("load_entities", |b| {
+        b.iter(|| bsp::load_entities(Cursor::new(black_box(bsp_data.as_slice()))).unwrap())
+    });
+    group.finish();
+}
+
+fn bench_mdl(c: &mut Criterion) {
+    let mdl_data = match data::vfs().and_then(|vfs| data::read(&vfs, BENCH_MDL)) {
+        Some(d) => d,
+        None => return,
+    };
+
+    let mut group = c.benchmark_group("mdl");
+    group.throughput(Throughput::Bytes(mdl_data.len() as u64));
+    group.bench_function("load", |b| {
+        b.iter(|| mdl::load(Cursor::new(black_box(mdl_data.as_slice()))).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_bsp, bench_mdl);
+criterion_main!(benches);
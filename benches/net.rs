@@ -0,0 +1,140 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Benchmarks for encoding and decoding network messages.
+//!
+//! The server message is a typical frame in a busy level: the server time, updates for a few dozen
+//! entities and a handful of sounds and impacts.
+
+use std::io::BufReader;
+
+use richter::common::net::{
+    ButtonFlags, ClientCmd, EntityEffects, EntityUpdate, PointEntityKind, ServerCmd, TempEntity,
+};
+
+use cgmath::{Deg, Vector3};
+use chrono::Duration;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+const ENTITY_UPDATES: u16 = 64;
+
+fn server_frame() -> Vec<ServerCmd> {
+    let mut cmds = vec![ServerCmd::Time { time: 123.45 }];
+
+    for i in 0..ENTITY_UPDATES {
+        let moving = i % 3 != 0;
+        cmds.push(ServerCmd::FastUpdate(EntityUpdate {
+            ent_id: i + 1,
+            model_id: Some((i % 20) as u8),
+            frame_id: Some((i % 8) as u8),
+            colormap: None,
+            skin_id: None,
+            effects: if i % 16 == 0 {
+                Some(EntityEffects::MUZZLE_FLASH)
+            } else {
+                None
+            },
+            origin_x: Some(16.0 * i as f32),
+            pitch: None,
+            origin_y: Some(-8.0 * i as f32),
+            yaw: Some(Deg(45.0)),
+            origin_z: if moving { Some(24.0) } else { None },
+            roll: None,
+            no_lerp: false,
+        }));
+    }
+
+    for i in 0..4 {
+        cmds.push(ServerCmd::Sound {
+            volume: None,
+            attenuation: None,
+            entity_id: i + 1,
+            channel: 1,
+            sound_id: 5,
+            position: Vector3::new(64.0, 128.0, 24.0),
+        });
+        cmds.push(ServerCmd::TempEntity {
+            temp_entity: TempEntity::Point {
+                kind: PointEntityKind::Gunshot,
+                origin: Vector3::new(256.0, 32.0 * i as f32, 0.0),
+            },
+        });
+    }
+
+    cmds
+}
+
+fn encode(cmds: &[ServerCmd]) -> Vec<u8> {
+    let mut packet = Vec::new();
+    for cmd in cmds {
+        cmd.serialize(&mut packet).unwrap();
+    }
+    packet
+}
+
+fn bench_server_cmd(c: &mut Criterion) {
+    let cmds = server_frame();
+    let packet = encode(&cmds);
+
+    let mut group = c.benchmark_group("server_cmd");
+    group.throughput(Throughput::Bytes(packet.len() as u64));
+    group.bench_function("encode_frame", |b| b.iter(|| encode(black_box(&cmds))));
+    group.bench_function("decode_frame", |b| {
+        b.iter(|| {
+            let mut reader = BufReader::new(black_box(packet.as_slice()));
+            let mut count = 0;
+            while let Some(cmd) = ServerCmd::deserialize(&mut reader).unwrap() {
+                black_box(cmd);
+                count += 1;
+            }
+            count
+        })
+    });
+    group.finish();
+}
+
+fn bench_client_cmd(c: &mut Criterion) {
+    let cmd = ClientCmd::Move {
+        send_time: Duration::milliseconds(123450),
+        angles: Vector3::new(Deg(10.0), Deg(90.0), Deg(0.0)),
+        fwd_move: 200,
+        side_move: -350,
+        up_move: 0,
+        button_flags: ButtonFlags::ATTACK,
+        impulse: 0,
+    };
+    let mut packet = Vec::new();
+    cmd.serialize(&mut packet).unwrap();
+
+    let mut group = c.benchmark_group("client_cmd");
+    group.bench_function("encode_move", |b| {
+        b.iter(|| {
+            let mut packet = Vec::with_capacity(16);
+            black_box(&cmd).serialize(&mut packet).unwrap();
+            packet
+        })
+    });
+    group.bench_function("decode_move", |b| {
+        b.iter(|| {
+            ClientCmd::deserialize(&mut BufReader::new(black_box(packet.as_slice()))).unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_server_cmd, bench_client_cmd);
+criterion_main!(benches);
@@ -0,0 +1,95 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Benchmarks for the QuakeC virtual machine.
+//!
+//! Every server frame begins by calling the progs' `StartFrame` function, which in the standard
+//! progs reads a number of cvars and updates the game rules. The level is loaded and `worldspawn`
+//! run once beforehand, since `StartFrame` assumes both have happened.
+
+mod data;
+
+use std::io::Cursor;
+
+use richter::{
+    common::{bsp, console::CvarRegistry},
+    server::{
+        self,
+        progs::{self, EntityId, GlobalAddrEntity, GlobalAddrFloat},
+        world::World,
+        Server,
+    },
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+fn bench_start_frame(c: &mut Criterion) {
+    let vfs = match data::vfs() {
+        Some(v) => v,
+        None => return,
+    };
+    let (progs_data, bsp_data) = match (
+        data::read(&vfs, "progs.dat"),
+        data::read(&vfs, data::BENCH_MAP),
+    ) {
+        (Some(p), Some(b)) => (p, b),
+        _ => return,
+    };
+
+    let (mut ctx, mut globals, type_def, string_table) = progs::load(&progs_data).unwrap();
+    let (brush_models, _) = bsp::load(Cursor::new(bsp_data)).unwrap();
+    string_table.insert(brush_models[0].name());
+    let mut world = World::create(brush_models, type_def, string_table.clone()).unwrap();
+    let mut server = Server::new(string_table);
+    let mut cvars = CvarRegistry::new();
+    server::register_cvars(&cvars).unwrap();
+
+    globals
+        .put_entity_id(EntityId(0), GlobalAddrEntity::Self_ as i16)
+        .unwrap();
+    ctx.execute_program_by_name(
+        &mut globals,
+        &mut world,
+        &mut cvars,
+        &mut server,
+        &vfs,
+        "worldspawn",
+    )
+    .unwrap();
+
+    let mut time = 1.0;
+    c.bench_function("progs/start_frame", |b| {
+        b.iter(|| {
+            time += 0.05;
+            globals
+                .put_float(time, GlobalAddrFloat::Time as i16)
+                .unwrap();
+            ctx.execute_program_by_name(
+                &mut globals,
+                &mut world,
+                &mut cvars,
+                &mut server,
+                &vfs,
+                "StartFrame",
+            )
+            .unwrap();
+        })
+    });
+}
+
+criterion_group!(benches, bench_start_frame);
+criterion_main!(benches);
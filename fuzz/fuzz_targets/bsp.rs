@@ -0,0 +1,10 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use richter::common::bsp;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = bsp::load(Cursor::new(data));
+});
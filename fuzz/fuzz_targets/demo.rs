@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use richter::client::demo::DemoServer;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(demo) = DemoServer::new(data) {
+        let _ = demo.info();
+    }
+});
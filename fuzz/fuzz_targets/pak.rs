@@ -0,0 +1,10 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use richter::common::{limits::LoadLimits, pak::Pak};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Pak::from_reader(Cursor::new(data), &LoadLimits::default());
+});
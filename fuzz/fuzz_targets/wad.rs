@@ -0,0 +1,12 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use richter::common::wad::Wad;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(wad) = Wad::load(Cursor::new(data)) {
+        let _ = wad.open_conchars();
+    }
+});
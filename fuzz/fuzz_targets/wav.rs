@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use richter::client::sound::AudioSource;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = AudioSource::from_bytes(data.to_vec());
+});
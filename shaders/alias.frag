@@ -3,6 +3,10 @@
 layout(location = 0) in vec3 f_normal;
 layout(location = 1) in vec2 f_diffuse;
 
+layout(push_constant) uniform PushConstants {
+  layout(offset = 4) float light[3];
+} push_constants;
+
 // set 1: per-entity
 layout(set = 1, binding = 1) uniform sampler u_diffuse_sampler;
 
@@ -19,8 +23,13 @@ void main() {
     f_diffuse
   );
 
-  // TODO: get ambient light from uniform
-  light_attachment = vec4(1.0, 1.0, 1.0, 1.0);
+  // stored at a quarter of its value, like world lighting (see brush.frag)
+  vec3 light = vec3(
+    push_constants.light[0],
+    push_constants.light[1],
+    push_constants.light[2]
+  );
+  light_attachment = vec4(light / 4.0, 1.0);
 
   // rescale normal to [0, 1]
   normal_attachment = vec4(f_normal / 2.0 + 0.5, 1.0);
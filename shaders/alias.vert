@@ -1,10 +1,14 @@
 #version 450
 
 layout(location = 0) in vec3 a_position1;
-// layout(location = 1) in vec3 a_position2;
+layout(location = 1) in vec3 a_position2;
 layout(location = 2) in vec3 a_normal;
 layout(location = 3) in vec2 a_diffuse;
 
+layout(push_constant) uniform PushConstants {
+  float blend;
+} push_constants;
+
 layout(location = 0) out vec3 f_normal;
 layout(location = 1) out vec2 f_diffuse;
 
@@ -21,6 +25,8 @@ vec3 convert(vec3 from) {
 void main() {
   f_normal = mat3(transpose(inverse(entity_uniforms.u_model))) * convert(a_normal);
   f_diffuse = a_diffuse;
+  // a_position1 is the current pose, a_position2 the previous one
+  vec3 position = mix(a_position2, a_position1, push_constants.blend);
   gl_Position = entity_uniforms.u_transform
-      * vec4(convert(a_position1), 1.0);
+      * vec4(convert(position), 1.0);
 }
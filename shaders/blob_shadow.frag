@@ -0,0 +1,26 @@
+#version 450
+
+layout(location = 0) in vec2 f_texcoord;
+layout(location = 1) in float f_alpha;
+layout(location = 2) in float f_squareness;
+
+layout(location = 0) out vec4 diffuse_attachment;
+layout(location = 1) out vec4 normal_attachment;
+layout(location = 2) out vec4 light_attachment;
+
+void main() {
+  // darkest at the center, fading smoothly to nothing at the edge. rectangular shadows take the
+  // larger of the two axis distances and keep a solid core, fading only near their edges
+  vec2 t = abs(f_texcoord);
+  float dist = mix(length(t), max(t.x, t.y), f_squareness);
+  if (dist > 1.0) {
+    discard;
+  }
+
+  float shadow = f_alpha * (1.0 - smoothstep(0.5 * f_squareness, 1.0, dist));
+
+  // only the light attachment is written (see BlobShadowPipeline)
+  diffuse_attachment = vec4(0.0);
+  normal_attachment = vec4(0.0);
+  light_attachment = vec4(0.0, 0.0, 0.0, shadow);
+}
@@ -0,0 +1,21 @@
+#version 450
+
+layout(location = 0) in vec3 a_position;
+layout(location = 1) in vec2 a_texcoord;
+layout(location = 2) in float a_alpha;
+layout(location = 3) in float a_squareness;
+
+layout(push_constant) uniform PushConstants {
+  mat4 transform;
+} push_constants;
+
+layout(location = 0) out vec2 f_texcoord;
+layout(location = 1) out float f_alpha;
+layout(location = 2) out float f_squareness;
+
+void main() {
+  f_texcoord = a_texcoord;
+  f_alpha = a_alpha;
+  f_squareness = a_squareness;
+  gl_Position = push_constants.transform * vec4(a_position, 1.0);
+}
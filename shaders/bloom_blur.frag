@@ -0,0 +1,36 @@
+#version 450
+
+// 9-tap gaussian, center weight first
+const float WEIGHTS[5] = float[](
+  0.2270270270,
+  0.1945945946,
+  0.1216216216,
+  0.0540540541,
+  0.0162162162
+);
+
+layout(location = 0) in vec2 a_texcoord;
+
+layout(location = 0) out vec4 color_attachment;
+
+layout(set = 0, binding = 0) uniform sampler u_sampler;
+layout(set = 0, binding = 1) uniform texture2D u_color;
+layout(set = 0, binding = 2) uniform BlurUniforms {
+  ivec2 direction;
+} blur_uniforms;
+
+void main() {
+  ivec2 dims = textureSize(sampler2D(u_color, u_sampler), 0);
+  ivec2 texcoord = ivec2(gl_FragCoord.xy);
+
+  vec3 color = WEIGHTS[0] * texelFetch(sampler2D(u_color, u_sampler), texcoord, 0).rgb;
+  for (int i = 1; i < 5; i++) {
+    ivec2 offset = blur_uniforms.direction * i;
+    ivec2 pos = clamp(texcoord + offset, ivec2(0), dims - 1);
+    ivec2 neg = clamp(texcoord - offset, ivec2(0), dims - 1);
+    color += WEIGHTS[i] * texelFetch(sampler2D(u_color, u_sampler), pos, 0).rgb;
+    color += WEIGHTS[i] * texelFetch(sampler2D(u_color, u_sampler), neg, 0).rgb;
+  }
+
+  color_attachment = vec4(color, 1.0);
+}
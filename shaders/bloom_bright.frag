@@ -0,0 +1,35 @@
+#version 450
+
+// only light brighter than this contributes to bloom
+const float BLOOM_THRESHOLD = 1.0;
+
+layout(location = 0) in vec2 a_texcoord;
+
+layout(location = 0) out vec4 color_attachment;
+
+layout(set = 0, binding = 0) uniform sampler u_sampler;
+layout(set = 0, binding = 1) uniform texture2DMS u_color;
+
+void main() {
+  ivec2 dims = textureSize(sampler2DMS(u_color, u_sampler));
+  int samples = textureSamples(sampler2DMS(u_color, u_sampler));
+
+  // each bloom texel covers a 2x2 block of the full-resolution input
+  ivec2 base = ivec2(gl_FragCoord.xy) * 2;
+
+  vec3 color = vec3(0.0);
+  for (int y = 0; y < 2; y++) {
+    for (int x = 0; x < 2; x++) {
+      ivec2 texcoord = min(base + ivec2(x, y), dims - 1);
+      for (int s = 0; s < samples; s++) {
+        color += texelFetch(sampler2DMS(u_color, u_sampler), texcoord, s).rgb;
+      }
+    }
+  }
+  color /= float(4 * samples);
+
+  // keep only the part of the color above the threshold
+  float brightness = max(color.r, max(color.g, color.b));
+  float excess = max(brightness - BLOOM_THRESHOLD, 0.0);
+  color_attachment = vec4(color * (excess / max(brightness, 0.0001)), 1.0);
+}
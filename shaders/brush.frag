@@ -4,18 +4,38 @@
 const uint TEXTURE_KIND_REGULAR = 0;
 const uint TEXTURE_KIND_WARP = 1;
 const uint TEXTURE_KIND_SKY = 2;
+const uint TEXTURE_KIND_MASKED = 3;
+
+// masked texels below this alpha are discarded
+const float MASK_THRESHOLD = 0.5;
 
 const float WARP_AMPLITUDE = 0.15;
 const float WARP_FREQUENCY = 0.25;
 const float WARP_SCALE = 1.0;
 
+// stored light level that shows a texture at its original brightness (see calc_light)
+const float UNLIT_LIGHT = 0.25;
+
+// diffuse color that shows a lightmap at its original brightness when r_lightmap is set
+const vec4 LIGHTMAP_DIFFUSE = vec4(0.5, 0.5, 0.5, 1.0);
+
+// caustics repeat roughly every 2 pi / CAUSTICS_SCALE world units
+const float CAUSTICS_SCALE = 1.0 / 40.0;
+const float CAUSTICS_SPEED = 0.5;
+
+// how much brighter the brightest caustics make a surface at r_caustics 1
+const float CAUSTICS_STRENGTH = 0.6;
+
 layout(location = 0) in vec3 f_normal;
 layout(location = 1) in vec2 f_diffuse; // also used for fullbright
 layout(location = 2) in vec2 f_lightmap;
 flat layout(location = 3) in uvec4 f_lightmap_anim;
+flat layout(location = 4) in uint f_surface_id;
+layout(location = 5) in vec3 f_position;
 
 layout(push_constant) uniform PushConstants {
   layout(offset = 128) uint texture_kind;
+  uint caustics;
 } push_constants;
 
 // set 0: per-frame
@@ -24,6 +44,10 @@ layout(set = 0, binding = 0) uniform FrameUniforms {
     vec4 camera_pos;
     float time;
     bool r_lightmap;
+    bool r_coloredlight;
+    bool r_fullbright;
+    bool r_flatcolor;
+    float r_caustics;
 } frame_uniforms;
 
 // set 1: per-entity
@@ -45,30 +69,65 @@ layout(location = 1) out vec4 normal_attachment;
 layout(location = 2) out vec4 light_attachment;
 
 vec4 calc_light() {
-    vec4 light = vec4(0.0, 0.0, 0.0, 0.0);
+    vec3 light = vec3(0.0, 0.0, 0.0);
     for (int i = 0; i < 4 && f_lightmap_anim[i] != LIGHTMAP_ANIM_END; i++) {
-        float map = texture(
+        // colored light is stored in rgb, monochrome light in alpha
+        vec4 texel = texture(
             sampler2D(u_lightmap_texture[i], u_lightmap_sampler),
             f_lightmap
-        ).r * 2.0;
+        );
+        vec3 map = (frame_uniforms.r_coloredlight ? texel.rgb : texel.aaa) * 2.0;
 
         // range [0, 4]
         float style = frame_uniforms.light_anim_frames[f_lightmap_anim[i]];
-        light[i] = map * style;
+        light += map * style;
     }
 
     // scale by quarter so values don't get clamped
-    return light / 4.0;
+    return vec4(light / 4.0, 1.0);
+}
+
+// returns the brightness of the light rippling over a surface under water, from 0 to 1.
+//
+// the bright lines where refracted light converges are traced by a few iterations of
+// interfering waves. the pattern is projected from above and leans with height, so walls show it
+// as well as floors.
+float caustics(vec3 position, float time) {
+    vec2 p = (position.xy + position.z * vec2(0.4, 0.3)) * CAUSTICS_SCALE;
+    vec2 i = p;
+    float c = 1.0;
+
+    for (int n = 0; n < 3; n++) {
+        float t = time * CAUSTICS_SPEED * (1.0 - 3.5 / float(n + 1));
+        i = p + vec2(cos(t - i.x) + sin(t + i.y), sin(t - i.y) + cos(t + i.x));
+        c += 1.0 / length(vec2(p.x / sin(i.x + t), p.y / cos(i.y + t)) * 200.0);
+    }
+
+    c = 1.17 - pow(c / 3.0, 1.4);
+    return clamp(pow(abs(c), 8.0), 0.0, 1.0);
+}
+
+// returns a color that stays the same for each surface but differs between neighbors
+vec3 surface_color(uint id) {
+    uint hash = id * 2654435761u;
+    return vec3(uvec3(hash >> 8, hash >> 16, hash >> 24) & 0xFFu) / 255.0;
 }
 
 void main() {
     switch (push_constants.texture_kind) {
         case TEXTURE_KIND_REGULAR:
+        case TEXTURE_KIND_MASKED:
             diffuse_attachment = texture(
                 sampler2D(u_diffuse_texture, u_diffuse_sampler),
                 f_diffuse
             );
 
+            // palette index 255 is transparent in masked textures
+            if (push_constants.texture_kind == TEXTURE_KIND_MASKED
+                    && diffuse_attachment.a < MASK_THRESHOLD) {
+                discard;
+            }
+
             float fullbright = texture(
                 sampler2D(u_fullbright_texture, u_diffuse_sampler),
                 f_diffuse
@@ -76,8 +135,22 @@ void main() {
 
             if (fullbright != 0.0) {
                 light_attachment = vec4(1.0, 1.0, 1.0, 1.0);
+            } else if (frame_uniforms.r_fullbright) {
+                light_attachment = vec4(UNLIT_LIGHT, UNLIT_LIGHT, UNLIT_LIGHT, 1.0);
             } else {
                 light_attachment = calc_light();
+
+                // caustics brighten the surface in proportion to its light, so they fade into
+                // the shadows like real ones
+                if (push_constants.caustics != 0 && frame_uniforms.r_caustics > 0.0) {
+                    float ripple = caustics(f_position, frame_uniforms.time);
+                    light_attachment.rgb *= 1.0
+                        + CAUSTICS_STRENGTH * frame_uniforms.r_caustics * ripple;
+                }
+            }
+
+            if (frame_uniforms.r_lightmap) {
+                diffuse_attachment = LIGHTMAP_DIFFUSE;
             }
             break;
 
@@ -127,6 +200,12 @@ void main() {
             break;
     }
 
-    // rescale normal to [0, 1]
-    normal_attachment = vec4(f_normal / 2.0 + 0.5, 1.0);
+    if (frame_uniforms.r_flatcolor) {
+        diffuse_attachment = vec4(surface_color(f_surface_id), 1.0);
+    }
+
+    // rescale normal to [0, 1]. sky is marked with a zero alpha so the deferred pass can fog it
+    // separately from solid geometry.
+    float normal_alpha = push_constants.texture_kind == TEXTURE_KIND_SKY ? 0.0 : 1.0;
+    normal_attachment = vec4(f_normal / 2.0 + 0.5, normal_alpha);
 }
@@ -20,6 +20,8 @@ layout(location = 0) out vec3 f_normal;
 layout(location = 1) out vec2 f_diffuse;
 layout(location = 2) out vec2 f_lightmap;
 layout(location = 3) out uvec4 f_lightmap_anim;
+flat layout(location = 4) out uint f_surface_id;
+layout(location = 5) out vec3 f_position;
 
 layout(set = 0, binding = 0) uniform FrameUniforms {
     float light_anim_frames[64];
@@ -48,6 +50,8 @@ void main() {
     f_normal = mat3(transpose(inverse(push_constants.model_view))) * convert(a_normal);
     f_lightmap = a_lightmap;
     f_lightmap_anim = a_lightmap_anim;
+    f_surface_id = uint(gl_InstanceIndex);
+    f_position = a_position;
     gl_Position = push_constants.transform * vec4(convert(a_position), 1.0);
 
 }
@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 0) in vec3 a_position;
+layout(location = 1) in vec4 a_color;
+
+layout(push_constant) uniform PushConstants {
+  mat4 transform;
+} push_constants;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+  f_color = a_color;
+  gl_Position = push_constants.transform * vec4(a_position, 1.0);
+}
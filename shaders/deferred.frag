@@ -3,6 +3,16 @@
 // if this is changed, it must also be changed in client::entity
 const uint MAX_LIGHTS = 32;
 
+// if this is changed, it must also be changed in client::render::world::shadow
+const uint MAX_SHADOW_LIGHTS = 4;
+const uint SHADOW_FACE_COUNT = 6;
+
+// offset applied to shadow comparisons to prevent self-shadowing
+const float SHADOW_BIAS = 0.01;
+
+// fog densities are given per 64 units, as in other engines
+const float FOG_DENSITY_SCALE = 1.0 / 64.0;
+
 layout(location = 0) in vec2 a_texcoord;
 
 layout(set = 0, binding = 0) uniform sampler u_sampler;
@@ -13,10 +23,15 @@ layout(set = 0, binding = 4) uniform texture2DMS u_depth;
 layout(set = 0, binding = 5) uniform DeferredUniforms {
   mat4 inv_projection;
   uint light_count;
-  uint _pad1;
-  uvec2 _pad2;
+  uint shadow_count;
+  float sky_fog;
+  uint _pad;
+  vec4 fog;
   vec4 lights[MAX_LIGHTS];
+  mat4 shadow_transforms[MAX_SHADOW_LIGHTS * SHADOW_FACE_COUNT];
 } u_deferred;
+layout(set = 0, binding = 6) uniform texture2DArray u_shadow_map;
+layout(set = 0, binding = 7) uniform samplerShadow u_shadow_sampler;
 
 layout(location = 0) out vec4 color_attachment;
 
@@ -36,15 +51,40 @@ vec3 reconstruct_position(float depth) {
   return view.xyz / view.w;
 }
 
+// returns 0.0 if the position is occluded from the shadowed light, 1.0 otherwise.
+float shadow_factor(uint shadow_id, vec3 position, float dist_ratio) {
+  for (uint face = 0; face < SHADOW_FACE_COUNT; face++) {
+    uint layer = shadow_id * SHADOW_FACE_COUNT + face;
+    vec4 clip = u_deferred.shadow_transforms[layer] * vec4(position, 1.0);
+
+    if (clip.w <= 0.0) {
+      continue;
+    }
+
+    vec2 ndc = clip.xy / clip.w;
+    if (abs(ndc.x) > 1.0 || abs(ndc.y) > 1.0) {
+      continue;
+    }
+
+    vec2 uv = vec2(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    return texture(
+      sampler2DArrayShadow(u_shadow_map, u_shadow_sampler),
+      vec4(uv, float(layer), dist_ratio - SHADOW_BIAS)
+    );
+  }
+
+  return 1.0;
+}
+
 void main() {
   ivec2 dims = textureSize(sampler2DMS(u_diffuse, u_sampler));
   ivec2 texcoord = ivec2(vec2(dims) * a_texcoord);
   vec4 in_color = texelFetch(sampler2DMS(u_diffuse, u_sampler), texcoord, gl_SampleID);
 
+  vec4 normal_sample = texelFetch(sampler2DMS(u_normal, u_sampler), texcoord, gl_SampleID);
+
   // scale from [0, 1] to [-1, 1]
-  vec3 in_normal = 2.0
-    * texelFetch(sampler2DMS(u_normal, u_sampler), texcoord, gl_SampleID).xyz
-    - 1.0;
+  vec3 in_normal = 2.0 * normal_sample.xyz - 1.0;
 
   // scale up by 4.0 (see brush.frag)
   vec4 in_light = 4.0 * texelFetch(sampler2DMS(u_light, u_sampler), texcoord, gl_SampleID);
@@ -53,7 +93,7 @@ void main() {
 
   vec4 out_color = in_color;
 
-  float light = in_light.x + in_light.y + in_light.z + in_light.w;
+  vec3 light = in_light.rgb;
   for (uint i = 0; i < u_deferred.light_count && i < MAX_LIGHTS; i++) {
     vec4 dlight = u_deferred.lights[i];
     vec3 dir = normalize(position - dlight_origin(dlight));
@@ -61,13 +101,35 @@ void main() {
     float radius = dlight_radius(dlight);
 
     if (dist < radius && dot(dir, in_normal) < 0.0) {
+      float shadow = 1.0;
+
+      // lights are sorted so that shadowed lights come first
+      if (i < u_deferred.shadow_count && i < MAX_SHADOW_LIGHTS) {
+        shadow = shadow_factor(i, position, dist / radius);
+      }
+
       // linear attenuation
-      light += (radius - dist) / radius;
+      light += vec3(shadow * (radius - dist) / radius);
     }
   }
 
   // allow 200% light saturation
-  light = min(light, 4.0);
+  light = min(light, vec3(4.0));
+
+  vec3 lit_color = out_color.rgb * light;
+
+  // exponential squared distance fog. the sky is marked by a zero normal alpha (see brush.frag)
+  // and is covered by a fixed amount rather than by distance.
+  float fog_density = u_deferred.fog.w * FOG_DENSITY_SCALE;
+  float fog_amount = 0.0;
+  if (fog_density > 0.0) {
+    if (normal_sample.a == 0.0) {
+      fog_amount = u_deferred.sky_fog;
+    } else {
+      float fog_depth = fog_density * length(position);
+      fog_amount = 1.0 - exp(-fog_depth * fog_depth);
+    }
+  }
 
-  color_attachment = vec4(out_color.rgb * light, 1.0);
+  color_attachment = vec4(mix(lit_color, u_deferred.fog.rgb, fog_amount), 1.0);
 }
@@ -8,12 +8,15 @@ layout(location = 1) in vec2 a_texcoord;
 layout(location = 2) in vec2 a_instance_position;
 layout(location = 3) in vec2 a_instance_scale;
 layout(location = 4) in uint a_instance_layer;
+layout(location = 5) in vec3 a_instance_color;
 
 layout(location = 0) out vec2 f_texcoord;
 layout(location = 1) out uint f_layer;
+layout(location = 2) out vec3 f_color;
 
 void main() {
   f_texcoord = a_texcoord;
   f_layer = a_instance_layer;
+  f_color = a_instance_color;
   gl_Position = vec4(a_instance_scale * a_position + a_instance_position, 0.0, 1.0);
 }
@@ -0,0 +1,184 @@
+#version 450
+
+// the light tool halves light values before storing them, so that lightmaps can brighten surfaces
+const float RANGE_SCALE = 0.5;
+
+// how much of a lamp's light depends on the angle at which it strikes the surface
+const float ANGLE_SCALE = 0.5;
+
+const uint FALLOFF_LINEAR = 0;
+const uint FALLOFF_INVERSE = 1;
+const uint FALLOFF_INVERSE_SQUARE = 2;
+
+// the distance at which inverse falloffs start to dim light
+const float FALLOFF_DISTANCE = 128.0;
+
+// node children below zero are leaves (see bake.rs)
+const int SOLID_LEAF = -1;
+
+// how many crossed planes a trace can return to
+const int MAX_TRACE_DEPTH = 64;
+
+// texels are lifted off the surface so their traces don't start inside the wall behind it
+const float SURFACE_OFFSET = 1.0;
+
+struct Lamp {
+  vec4 origin; // w: light level
+  vec4 color; // w: distance scale
+  vec4 spot; // xyz: direction, w: cosine of the cone's half-angle
+  uvec4 params; // x: light style, y: falloff
+};
+
+struct Node {
+  vec4 plane; // xyz: normal, w: distance from the origin
+  ivec4 children; // x: front, y: back
+};
+
+layout(location = 0) in vec2 f_texcoord; // unused, texels are found from gl_FragCoord
+
+layout(push_constant) uniform PushConstants {
+  vec4 origin; // position of the first texel
+  vec4 s_step; // offset to the next texel in the row
+  vec4 t_step; // offset to the next row
+  vec4 normal; // w: minimum light level
+  uint style;
+  uint lamp_count;
+} push_constants;
+
+layout(std430, set = 0, binding = 0) readonly buffer Lamps {
+  Lamp lamps[];
+};
+
+layout(std430, set = 0, binding = 1) readonly buffer Nodes {
+  Node nodes[];
+};
+
+layout(location = 0) out vec4 lightmap;
+
+// returns true if the segment from start to end passes through a solid leaf
+bool occluded(vec3 start, vec3 end) {
+  int stack_nodes[MAX_TRACE_DEPTH];
+  vec3 stack_starts[MAX_TRACE_DEPTH];
+  vec3 stack_ends[MAX_TRACE_DEPTH];
+  int depth = 0;
+
+  int node = 0;
+  vec3 a = start;
+  vec3 b = end;
+
+  while (true) {
+    if (node < 0) {
+      if (node == SOLID_LEAF) {
+        return true;
+      }
+
+      // this part of the segment is clear, so go back to the far side of the last split
+      if (depth == 0) {
+        return false;
+      }
+
+      depth -= 1;
+      node = stack_nodes[depth];
+      a = stack_starts[depth];
+      b = stack_ends[depth];
+      continue;
+    }
+
+    vec4 plane = nodes[node].plane;
+    ivec4 children = nodes[node].children;
+    float a_dist = dot(plane.xyz, a) - plane.w;
+    float b_dist = dot(plane.xyz, b) - plane.w;
+
+    if (a_dist >= 0.0 && b_dist >= 0.0) {
+      node = children.x;
+    } else if (a_dist < 0.0 && b_dist < 0.0) {
+      node = children.y;
+    } else {
+      // split the segment, follow the near half and come back for the far one
+      vec3 mid = mix(a, b, a_dist / (a_dist - b_dist));
+      int near = a_dist >= 0.0 ? children.x : children.y;
+      int far = a_dist >= 0.0 ? children.y : children.x;
+
+      if (depth < MAX_TRACE_DEPTH) {
+        stack_nodes[depth] = far;
+        stack_starts[depth] = mid;
+        stack_ends[depth] = b;
+        depth += 1;
+      }
+
+      node = near;
+      b = mid;
+    }
+  }
+
+  return false;
+}
+
+// the light level of a lamp at a distance, before the angle is considered
+float falloff(Lamp lamp, float dist) {
+  float light = lamp.origin.w;
+  float scaled = dist * lamp.color.w;
+
+  switch (lamp.params.y) {
+    case FALLOFF_LINEAR:
+      // negative lamps darken by the same amount they would brighten
+      return sign(light) * max(abs(light) - scaled, 0.0);
+
+    case FALLOFF_INVERSE:
+      return light / max(scaled / FALLOFF_DISTANCE, 1.0);
+
+    case FALLOFF_INVERSE_SQUARE: {
+      float d = max(scaled / FALLOFF_DISTANCE, 1.0);
+      return light / (d * d);
+    }
+
+    default:
+      return light;
+  }
+}
+
+void main() {
+  vec2 texel = gl_FragCoord.xy - 0.5;
+  vec3 normal = push_constants.normal.xyz;
+  vec3 position = push_constants.origin.xyz
+    + texel.x * push_constants.s_step.xyz
+    + texel.y * push_constants.t_step.xyz
+    + SURFACE_OFFSET * normal;
+
+  // colored light in rgb, monochrome light in a, as in the map's own lightmaps
+  vec4 total = vec4(0.0);
+  if (push_constants.style == 0) {
+    total = vec4(push_constants.normal.w);
+  }
+
+  for (uint i = 0; i < push_constants.lamp_count; i++) {
+    Lamp lamp = lamps[i];
+    if (lamp.params.x != push_constants.style) {
+      continue;
+    }
+
+    vec3 to_lamp = lamp.origin.xyz - position;
+    float dist = length(to_lamp);
+    vec3 dir = to_lamp / max(dist, 0.001);
+
+    // lamps behind the surface don't light it
+    float facing = dot(dir, normal);
+    if (facing <= 0.0) {
+      continue;
+    }
+
+    // nor do spotlights pointed elsewhere
+    if (dot(-dir, lamp.spot.xyz) < lamp.spot.w) {
+      continue;
+    }
+
+    float value = falloff(lamp, dist) * ((1.0 - ANGLE_SCALE) + ANGLE_SCALE * facing);
+    if (value == 0.0 || occluded(position, lamp.origin.xyz)) {
+      continue;
+    }
+
+    total += value * vec4(lamp.color.rgb, 1.0);
+  }
+
+  lightmap = clamp(total * RANGE_SCALE / 255.0, 0.0, 1.0);
+}
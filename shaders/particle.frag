@@ -1,9 +1,11 @@
 #version 450
+#extension GL_EXT_nonuniform_qualifier : require
 
 layout(location = 0) in vec2 f_texcoord;
+layout(location = 1) flat in uint f_color;
 
 layout(push_constant) uniform PushConstants {
-  layout(offset = 64) uint color;
+  layout(offset = 136) uint style;
 } push_constants;
 
 layout(set = 0, binding = 0) uniform sampler u_sampler;
@@ -13,10 +15,14 @@ layout(location = 0) out vec4 diffuse_attachment;
 // layout(location = 1) out vec4 normal_attachment;
 layout(location = 2) out vec4 light_attachment;
 
+const uint STYLE_SQUARE = 1;
+
 void main() {
+  // square particles are solid, so sample the opaque center of the round texture
+  vec2 texcoord = push_constants.style == STYLE_SQUARE ? vec2(0.5) : f_texcoord;
   vec4 tex_color = texture(
-    sampler2D(u_texture[push_constants.color], u_sampler),
-    f_texcoord
+    sampler2D(u_texture[nonuniformEXT(f_color)], u_sampler),
+    texcoord
   );
 
   if (tex_color.a == 0.0) {
@@ -1,15 +1,44 @@
 #version 450
 
+// vertex rate
 layout(location = 0) in vec3 a_position;
 layout(location = 1) in vec2 a_texcoord;
 
+// instance rate
+layout(location = 2) in vec3 a_instance_position;
+layout(location = 3) in uint a_instance_color;
+
 layout(push_constant) uniform PushConstants {
-  mat4 transform;
+  mat4 view_projection;
+  mat4 billboard;
+  vec2 viewport_size;
+  uint style;
 } push_constants;
 
 layout(location = 0) out vec2 f_texcoord;
+layout(location = 1) flat out uint f_color;
+
+const uint STYLE_SQUARE = 1;
+
+// side length in pixels of a square particle at the given depth.
+// must match square_particle_size() in particle.rs.
+float square_size(float depth) {
+  float scale = push_constants.viewport_size.x / 320.0;
+  float min_size = max(floor(scale), 1.0);
+  float max_size = floor(scale * 4.0 + 0.5);
+  return clamp(floor(256.0 * scale / depth), min_size, max_size);
+}
 
 void main() {
   f_texcoord = a_texcoord;
-  gl_Position = push_constants.transform * vec4(a_position, 1.0);
+  f_color = a_instance_color;
+
+  vec4 center = push_constants.view_projection * vec4(a_instance_position, 1.0);
+  if (push_constants.style == STYLE_SQUARE) {
+    // offset in clip space so the size is exact in pixels. w is the particle's depth.
+    vec2 offset = a_position.xy * square_size(center.w) / push_constants.viewport_size;
+    gl_Position = center + vec4(offset * center.w, 0.0, 0.0);
+  } else {
+    gl_Position = center + push_constants.billboard * vec4(a_position, 0.0);
+  }
 }
@@ -1,5 +1,14 @@
 #version 450
 
+const uint TONEMAP_NONE = 0u;
+const uint TONEMAP_REINHARD = 1u;
+const uint TONEMAP_ACES = 2u;
+
+// underwater view distortion, in texture space at r_waterwarp 1
+const float WATER_WARP_AMPLITUDE = 0.005;
+const float WATER_WARP_CYCLES = 4.0;
+const float WATER_WARP_SPEED = 2.0;
+
 layout(location = 0) in vec2 a_texcoord;
 
 layout(location = 0) out vec4 color_attachment;
@@ -8,18 +17,65 @@ layout(set = 0, binding = 0) uniform sampler u_sampler;
 layout(set = 0, binding = 1) uniform texture2DMS u_color;
 layout(set = 0, binding = 2) uniform PostProcessUniforms {
   vec4 color_shift;
+  float bloom_intensity;
+  uint tonemap;
+  float time;
+  float water_warp;
 } postprocess_uniforms;
+layout(set = 0, binding = 3) uniform texture2D u_bloom;
+layout(set = 0, binding = 4) uniform sampler u_bloom_sampler;
+
+// fitted ACES filmic curve (Krzysztof Narkowicz)
+vec3 tonemap_aces(vec3 x) {
+  return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+vec3 tonemap(vec3 hdr) {
+  switch (postprocess_uniforms.tonemap) {
+    case TONEMAP_REINHARD:
+      return hdr / (1.0 + hdr);
+
+    case TONEMAP_ACES:
+      return tonemap_aces(hdr);
+
+    default:
+      return clamp(hdr, 0.0, 1.0);
+  }
+}
+
+vec2 warp_texcoord(vec2 texcoord) {
+  vec2 phase = 6.28318530718 * WATER_WARP_CYCLES * texcoord.ts
+    + WATER_WARP_SPEED * postprocess_uniforms.time;
+  vec2 offset = WATER_WARP_AMPLITUDE * postprocess_uniforms.water_warp
+    * vec2(sin(phase.s), cos(phase.t));
+
+  // keep the screen edges from sampling outside the buffer
+  return clamp(texcoord + offset, 0.0, 1.0);
+}
 
 void main() {
+  vec2 screen_texcoord = a_texcoord;
+  if (postprocess_uniforms.water_warp > 0.0) {
+    screen_texcoord = warp_texcoord(a_texcoord);
+  }
+
   ivec2 dims = textureSize(sampler2DMS(u_color, u_sampler));
-  ivec2 texcoord = ivec2(vec2(dims) * a_texcoord);
+  ivec2 texcoord = min(ivec2(vec2(dims) * screen_texcoord), dims - 1);
 
   vec4 in_color = texelFetch(sampler2DMS(u_color, u_sampler), texcoord, gl_SampleID);
 
+  vec3 hdr = in_color.rgb;
+  if (postprocess_uniforms.bloom_intensity > 0.0) {
+    vec3 bloom = texture(sampler2D(u_bloom, u_bloom_sampler), screen_texcoord).rgb;
+    hdr += postprocess_uniforms.bloom_intensity * bloom;
+  }
+
+  vec4 ldr = vec4(tonemap(hdr), in_color.a);
+
   float src_factor = postprocess_uniforms.color_shift.a;
   float dst_factor = 1.0 - src_factor;
   vec4 color_shifted = src_factor * postprocess_uniforms.color_shift
-    + dst_factor * in_color;
+    + dst_factor * ldr;
 
   color_attachment = color_shifted;
 }
@@ -7,9 +7,11 @@ layout(location = 0) out vec2 f_texcoord;
 
 layout(set = 2, binding = 0) uniform QuadUniforms {
   mat4 transform;
+  vec4 tint;
+  vec2 texcoord_scale;
 } quad_uniforms;
 
 void main() {
-  f_texcoord = a_texcoord;
+  f_texcoord = a_texcoord * quad_uniforms.texcoord_scale;
   gl_Position = quad_uniforms.transform * vec4(a_position, 0.0, 1.0);
 }
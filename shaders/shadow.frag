@@ -0,0 +1,8 @@
+#version 450
+
+layout(location = 0) in vec3 f_light_vec;
+
+void main() {
+  // store distance from the light as a fraction of its radius (see deferred.frag)
+  gl_FragDepth = clamp(length(f_light_vec), 0.0, 1.0);
+}
@@ -0,0 +1,22 @@
+#version 450
+
+layout(location = 0) in vec3 a_position;
+
+layout(push_constant) uniform PushConstants {
+  mat4 transform;
+  vec4 light;
+} push_constants;
+
+// vector from the light to the vertex, scaled by the inverse of the light radius
+layout(location = 0) out vec3 f_light_vec;
+
+// convert from Quake coordinates
+vec3 convert(vec3 from) {
+  return vec3(-from.y, from.z, -from.x);
+}
+
+void main() {
+  vec3 position = convert(a_position);
+  f_light_vec = (position - push_constants.light.xyz) / push_constants.light.w;
+  gl_Position = push_constants.transform * vec4(position, 1.0);
+}
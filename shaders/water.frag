@@ -0,0 +1,114 @@
+#version 450
+
+const float WARP_AMPLITUDE = 0.15;
+const float WARP_FREQUENCY = 0.25;
+const float WARP_SCALE = 1.0;
+
+// fullbright surfaces are scaled by this much in the deferred pass (see deferred.frag)
+const float FULLBRIGHT_SCALE = 4.0;
+
+// fraction of light reflected by water viewed head-on
+const float WATER_REFLECTANCE = 0.02;
+
+// how much the warp ripples the reflection
+const float RIPPLE_SCALE = 0.05;
+
+layout(location = 0) in vec3 f_normal;
+layout(location = 1) in vec2 f_diffuse;
+layout(location = 2) in vec2 f_lightmap;
+flat layout(location = 3) in uvec4 f_lightmap_anim;
+layout(location = 5) in vec3 f_position;
+
+layout(push_constant) uniform PushConstants {
+  layout(offset = 128) uint texture_kind;
+  uint caustics; // shared with the brush pipeline, unused
+  float alpha;
+  float reflection;
+} push_constants;
+
+// set 0: per-frame
+layout(set = 0, binding = 0) uniform FrameUniforms {
+    float light_anim_frames[64];
+    vec4 camera_pos;
+    float time;
+    bool r_lightmap;
+    bool r_coloredlight;
+    bool r_fullbright;
+    bool r_flatcolor;
+} frame_uniforms;
+
+// set 1: per-entity
+layout(set = 1, binding = 1) uniform sampler u_diffuse_sampler;
+
+// set 2: per-texture
+layout(set = 2, binding = 0) uniform texture2D u_diffuse_texture;
+
+// set 3: sky
+layout(set = 3, binding = 0) uniform texture2D u_sky_texture;
+
+layout(location = 0) out vec4 color_attachment;
+
+// samples the sky in direction `dir`, projected the same way as sky surfaces (see brush.vert and
+// brush.frag)
+vec3 sky_color(vec3 dir) {
+    dir.z *= 3.0;
+    float len = 6.0 * 63.0 / length(dir);
+    vec2 texcoord = (mod(8.0 * frame_uniforms.time, 128.0) + dir.xy * len) / 128.0;
+
+    vec2 base = mod(texcoord + frame_uniforms.time, 1.0);
+    vec4 sky = texture(
+        sampler2D(u_sky_texture, u_diffuse_sampler),
+        vec2(base.s * 0.5 + 0.5, base.t)
+    );
+    vec4 cloud = texture(
+        sampler2D(u_sky_texture, u_diffuse_sampler),
+        vec2(base.s * 0.5, base.t)
+    );
+
+    // black cloud texels are transparent
+    return cloud.r + cloud.g + cloud.b == 0.0 ? sky.rgb : cloud.rgb;
+}
+
+void main() {
+    // same warp as brush.frag, note the texcoord transpose here
+    vec2 wave1 = 3.14159265359
+        * (WARP_SCALE * f_diffuse.ts
+            + WARP_FREQUENCY * frame_uniforms.time);
+
+    vec2 warp_texcoord = f_diffuse.st + WARP_AMPLITUDE
+        * vec2(sin(wave1.s), sin(wave1.t));
+
+    vec4 diffuse = texture(
+        sampler2D(u_diffuse_texture, u_diffuse_sampler),
+        warp_texcoord
+    );
+
+    color_attachment = vec4(diffuse.rgb * FULLBRIGHT_SCALE, push_constants.alpha);
+
+    if (push_constants.reflection > 0.0) {
+        vec3 view_dir = normalize(f_position - frame_uniforms.camera_pos.xyz);
+
+        // liquid surfaces are flat, so take the normal from the position derivatives and
+        // turn it to face the camera
+        vec3 normal = normalize(cross(dFdx(f_position), dFdy(f_position)));
+        if (dot(normal, view_dir) > 0.0) {
+            normal = -normal;
+        }
+        normal = normalize(normal + RIPPLE_SCALE * vec3(sin(wave1.s), sin(wave1.t), 0.0));
+
+        // Schlick's approximation of the Fresnel factor
+        float cos_theta = clamp(dot(-view_dir, normal), 0.0, 1.0);
+        float fresnel = WATER_REFLECTANCE
+            + (1.0 - WATER_REFLECTANCE) * pow(1.0 - cos_theta, 5.0);
+        float amount = fresnel * push_constants.reflection;
+
+        // the sky is always above, even when looking at the underside of the surface
+        vec3 reflected = reflect(view_dir, normal);
+        reflected.z = abs(reflected.z);
+
+        color_attachment = vec4(
+            mix(color_attachment.rgb, sky_color(reflected) * FULLBRIGHT_SCALE, amount),
+            mix(color_attachment.a, 1.0, amount)
+        );
+    }
+}
@@ -1,26 +1,109 @@
 use std::{
     cell::RefCell,
-    fs::File,
-    io::BufWriter,
+    fs::{self, File},
+    io::{self, BufWriter, Write as _},
     path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
     rc::Rc,
 };
 
-use richter::client::render::Extent2d;
-
-use chrono::Utc;
+use richter::{
+    client::render::Extent2d,
+    common::{self, console::CvarRegistry, tga},
+};
 
 const BYTES_PER_PIXEL: u32 = 4;
 
+// highest number tried when picking a screenshot file name
+const MAX_SCREENSHOTS: usize = 10000;
+
+/// An image file format that captures can be written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Tga,
+}
+
+impl ImageFormat {
+    /// Returns the format with the given file extension, if it is supported.
+    pub fn from_extension(ext: &str) -> Option<ImageFormat> {
+        match ext.to_ascii_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "tga" => Some(ImageFormat::Tga),
+            _ => None,
+        }
+    }
+
+    /// Returns the format named by `scr_screenshotformat`, defaulting to PNG.
+    pub fn from_cvars(cvars: &CvarRegistry) -> ImageFormat {
+        cvars
+            .get("scr_screenshotformat")
+            .ok()
+            .and_then(|f| ImageFormat::from_extension(&f))
+            .unwrap_or(ImageFormat::Png)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Tga => "tga",
+        }
+    }
+
+    /// Writes RGBA pixel data to `path` in this format.
+    pub fn write<P>(&self, path: P, width: u32, height: u32, rgba: &[u8]) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        match self {
+            ImageFormat::Png => {
+                let mut png_encoder = png::Encoder::new(writer, width, height);
+                png_encoder.set_color(png::ColorType::RGBA);
+                png_encoder.set_depth(png::BitDepth::Eight);
+                png_encoder
+                    .write_header()
+                    .and_then(|mut w| w.write_image_data(rgba))
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+            }
+
+            ImageFormat::Tga => writer.write_all(&tga::encode(width as u16, height as u16, rgba)),
+        }
+    }
+}
+
+// Returns the first unused screenshot path in the game directory.
+fn next_screenshot_path(format: ImageFormat) -> Option<PathBuf> {
+    (0..MAX_SCREENSHOTS)
+        .map(|i| {
+            Path::new(common::DEFAULT_BASEDIR).join(format!(
+                "richter{:04}.{}",
+                i,
+                format.extension()
+            ))
+        })
+        .find(|path| !path.exists())
+}
+
 /// Implements the "screenshot" command.
 ///
 /// This function returns a boxed closure which sets the `screenshot_path`
-/// argument to `Some` when called.
-pub fn cmd_screenshot(screenshot_path: Rc<RefCell<Option<PathBuf>>>) -> Box<dyn Fn(&[&str])> {
+/// argument to `Some` when called. Without an argument, the screenshot is given the next free
+/// numbered name in the game directory and saved in the format set by `scr_screenshotformat`.
+pub fn cmd_screenshot(
+    cvars: Rc<RefCell<CvarRegistry>>,
+    screenshot_path: Rc<RefCell<Option<PathBuf>>>,
+) -> Box<dyn Fn(&[&str])> {
     Box::new(move |args| {
         let path = match args.len() {
-            // TODO: make default path configurable
-            0 => PathBuf::from(format!("richter-{}.png", Utc::now().format("%FT%H-%M-%S"))),
+            0 => match next_screenshot_path(ImageFormat::from_cvars(&cvars.borrow())) {
+                Some(p) => p,
+                None => {
+                    log::error!("Too many screenshots in {}", common::DEFAULT_BASEDIR);
+                    return;
+                }
+            },
             1 => PathBuf::from(args[0]),
             _ => {
                 log::error!("Usage: screenshot [PATH]");
@@ -28,7 +111,7 @@ pub fn cmd_screenshot(screenshot_path: Rc<RefCell<Option<PathBuf>>>) -> Box<dyn
             }
         };
 
-        screenshot_path.replace(Some(PathBuf::from(path)));
+        screenshot_path.replace(Some(path));
     })
 }
 
@@ -63,6 +146,10 @@ impl Capture {
         }
     }
 
+    pub fn size(&self) -> Extent2d {
+        self.capture_size
+    }
+
     pub fn copy_from_texture(
         &self,
         encoder: &mut wgpu::CommandEncoder,
@@ -82,10 +169,8 @@ impl Capture {
         );
     }
 
-    pub fn write_to_file<P>(&self, device: &wgpu::Device, path: P)
-    where
-        P: AsRef<Path>,
-    {
+    /// Reads back the captured image as RGBA, row by row from the top left.
+    pub fn read_rgba(&self, device: &wgpu::Device) -> Vec<u8> {
         let mut data = Vec::new();
         {
             // map the buffer
@@ -109,15 +194,168 @@ impl Capture {
         }
         self.buffer.unmap();
 
-        let f = File::create(path).unwrap();
-        let mut png_encoder = png::Encoder::new(
-            BufWriter::new(f),
-            self.capture_size.width,
-            self.capture_size.height,
-        );
-        png_encoder.set_color(png::ColorType::RGBA);
-        png_encoder.set_depth(png::BitDepth::Eight);
-        let mut writer = png_encoder.write_header().unwrap();
-        writer.write_image_data(&data).unwrap();
+        data
+    }
+}
+
+/// Writes captured image data to `path`, choosing the format by its extension.
+pub fn write_screenshot<P>(path: P, size: Extent2d, rgba: &[u8])
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let format = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(ImageFormat::from_extension)
+        .unwrap_or(ImageFormat::Png);
+
+    match format.write(path, size.width, size.height, rgba) {
+        Ok(()) => log::info!("Wrote {}", path.display()),
+        Err(e) => log::error!("Couldn't write screenshot to {}: {}", path.display(), e),
     }
 }
+
+enum VideoOutput {
+    // one numbered image per frame in the given directory
+    Frames {
+        dir: PathBuf,
+        format: ImageFormat,
+    },
+
+    // raw frames piped to an ffmpeg process
+    Ffmpeg {
+        output: PathBuf,
+        child: Option<Child>,
+    },
+}
+
+/// A video being captured from demo playback.
+///
+/// Frames are either written as individual images or, if the output is a video file, piped to
+/// `ffmpeg` for encoding. The ffmpeg process is started with the first frame, once the size of the
+/// frames is known.
+pub struct VideoCapture {
+    fps: f32,
+    output: VideoOutput,
+    frame_count: usize,
+}
+
+impl VideoCapture {
+    /// Starts a capture of the demo `demo_name`.
+    ///
+    /// If `output` has an image extension or is `None`, frames are written to a directory named
+    /// after the demo under `capture/` in the game directory. Otherwise `output` is treated as a
+    /// video file to be encoded by ffmpeg.
+    pub fn new(
+        cvars: &CvarRegistry,
+        demo_name: &str,
+        output: Option<&str>,
+    ) -> io::Result<VideoCapture> {
+        let fps = cvars.get_value("capture_fps").unwrap_or(30.0).max(1.0);
+
+        let image_format = output
+            .map(|o| {
+                Path::new(o)
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .and_then(ImageFormat::from_extension)
+            })
+            .unwrap_or_else(|| Some(ImageFormat::from_cvars(cvars)));
+
+        let output = match (output, image_format) {
+            (Some(path), None) => VideoOutput::Ffmpeg {
+                output: PathBuf::from(path),
+                child: None,
+            },
+
+            (_, Some(format)) => {
+                let stem = Path::new(demo_name)
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| demo_name.to_owned());
+                let dir = Path::new(common::DEFAULT_BASEDIR)
+                    .join("capture")
+                    .join(stem);
+                fs::create_dir_all(&dir)?;
+                VideoOutput::Frames { dir, format }
+            }
+        };
+
+        Ok(VideoCapture {
+            fps,
+            output,
+            frame_count: 0,
+        })
+    }
+
+    /// The fixed length of each captured frame.
+    pub fn frame_duration(&self) -> f32 {
+        1.0 / self.fps
+    }
+
+    /// Adds a frame of RGBA image data to the capture.
+    pub fn write_frame(&mut self, size: Extent2d, rgba: &[u8]) -> io::Result<()> {
+        match self.output {
+            VideoOutput::Frames { ref dir, format } => {
+                let path = dir.join(format!("{:06}.{}", self.frame_count, format.extension()));
+                format.write(path, size.width, size.height, rgba)?;
+            }
+
+            VideoOutput::Ffmpeg {
+                ref output,
+                ref mut child,
+            } => {
+                if child.is_none() {
+                    *child = Some(spawn_ffmpeg(output, size, self.fps)?);
+                }
+
+                let stdin = child.as_mut().unwrap().stdin.as_mut().unwrap();
+                stdin.write_all(rgba)?;
+            }
+        }
+
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    /// Ends the capture, waiting for ffmpeg to finish encoding if it is in use.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let VideoOutput::Ffmpeg {
+            ref mut child,
+            ref output,
+        } = self.output
+        {
+            if let Some(mut child) = child.take() {
+                // closing stdin tells ffmpeg there are no more frames
+                drop(child.stdin.take());
+                let status = child.wait()?;
+                if !status.success() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("ffmpeg exited with {}", status),
+                    ));
+                }
+
+                log::info!("Wrote {}", output.display());
+            }
+        }
+
+        log::info!("Captured {} frames", self.frame_count);
+        Ok(())
+    }
+}
+
+fn spawn_ffmpeg(output: &Path, size: Extent2d, fps: f32) -> io::Result<Child> {
+    Command::new("ffmpeg")
+        .args(&["-y", "-loglevel", "error"])
+        .args(&["-f", "rawvideo", "-pix_fmt", "rgba"])
+        .arg("-s")
+        .arg(format!("{}x{}", size.width, size.height))
+        .arg("-r")
+        .arg(fps.to_string())
+        .args(&["-i", "-", "-pix_fmt", "yuv420p"])
+        .arg(output)
+        .stdin(Stdio::piped())
+        .spawn()
+}
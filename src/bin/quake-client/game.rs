@@ -20,40 +20,47 @@
 
 use std::{
     cell::{Cell, RefCell},
+    cmp::Ordering,
     path::PathBuf,
     rc::Rc,
 };
 
 use crate::{
-    capture::{cmd_screenshot, Capture},
+    capture::{cmd_screenshot, write_screenshot, Capture, VideoCapture},
     trace::{cmd_trace_begin, cmd_trace_end},
 };
 
 use richter::{
     client::{
-        entity::MAX_LIGHTS,
+        entity::{ClientEntity, MAX_LIGHTS},
         input::{Input, InputFocus},
         menu::Menu,
         render::{
-            Camera, DeferredRenderer, DeferredUniforms, Extent2d, GraphicsState, HudState,
-            PointLight, PostProcessRenderer, RenderTarget as _, RenderTargetResolve as _,
-            SwapChainTarget, UiOverlay, UiRenderer, UiState, WorldRenderer,
+            bake, blob, debug, load_console_background, BakeLights, BlobShadowVertex, BlobShadows,
+            BloomRenderer, Camera, ConsoleSettings, DebugLines, DebugVertex, DeferredRenderer,
+            DeferredUniforms, Extent2d, GraphicsState, HudSettings, HudState, LightBake,
+            PointLight, PostProcessRenderer, PostProcessUniforms, QuadTexture, RenderTarget as _,
+            RenderTargetResolve as _, ShadowLight, ShadowRenderer, StatusBarMode, SwapChainTarget,
+            Tonemap, Transition, UiOverlay, UiRenderer, UiState, ViewRect, WorldRenderer,
+            DEFAULT_SHADOW_SIZE, MAX_SHADOW_LIGHTS, SHADOW_FACE_COUNT, VIEWSIZE_MAX, VIEWSIZE_MIN,
         },
         trace::TraceFrame,
         Client,
     },
     common::{
+        arena::FrameArena,
         console::{CmdRegistry, Console, CvarRegistry},
-        math,
-        net::SignOnStage,
+        engine, frustum, math,
+        model::{Model, ModelKind},
     },
+    server::{self, save::QUICKSAVE_SLOT},
 };
 
-use bumpalo::Bump;
-use cgmath::{self, SquareMatrix as _, Vector3, Zero as _};
+use bumpalo::collections::Vec as BumpVec;
+use cgmath::{self, InnerSpace as _, Matrix4, SquareMatrix as _, Vector3, Zero as _};
 use chrono::Duration;
 use failure::Error;
-use log::info;
+use log::{info, warn};
 
 #[derive(Clone, Copy)]
 enum InGameFocus {
@@ -69,24 +76,44 @@ enum InGameFocus {
 
 struct InGameState {
     world_renderer: WorldRenderer,
+    shadow_renderer: ShadowRenderer,
     deferred_renderer: DeferredRenderer,
+    bloom_renderer: Option<BloomRenderer>,
     postprocess_renderer: PostProcessRenderer,
     focus: Rc<Cell<InGameFocus>>,
+
+    // the render target generation the renderers above were built against
+    target_generation: usize,
+
+    // wireframe of the collision hull selected by r_showhull
+    shown_hull: usize,
+    hull_lines: Option<DebugLines>,
+
+    // custom console background selected by scr_conback
+    conback_name: String,
+    conback: Option<QuadTexture>,
+
+    // true if the game was paused automatically when the menu was opened
+    auto_paused: bool,
 }
 
 impl InGameState {
     pub fn new(
         cmds: Rc<RefCell<CmdRegistry>>,
         world_renderer: WorldRenderer,
+        shadow_renderer: ShadowRenderer,
         deferred_renderer: DeferredRenderer,
+        bloom_renderer: Option<BloomRenderer>,
         postprocess_renderer: PostProcessRenderer,
         focus: InGameFocus,
+        target_generation: usize,
     ) -> InGameState {
         let focus_rc = Rc::new(Cell::new(focus));
         let toggleconsole_focus = focus_rc.clone();
 
         cmds.borrow_mut().insert_or_replace(
             "toggleconsole",
+            "open or close the console",
             Box::new(move |_| match toggleconsole_focus.get() {
                 InGameFocus::Game => {
                     println!("toggleconsole: ON");
@@ -106,6 +133,7 @@ impl InGameState {
 
         cmds.borrow_mut().insert_or_replace(
             "togglemenu",
+            "open or close the menu",
             Box::new(move |_| match togglemenu_focus.get() {
                 InGameFocus::Game => {
                     println!("togglemenu: ON");
@@ -121,9 +149,17 @@ impl InGameState {
 
         InGameState {
             world_renderer,
+            shadow_renderer,
             deferred_renderer,
+            bloom_renderer,
             postprocess_renderer,
             focus: focus_rc,
+            target_generation,
+            shown_hull: 0,
+            hull_lines: None,
+            conback_name: String::new(),
+            conback: None,
+            auto_paused: false,
         }
     }
 }
@@ -134,6 +170,152 @@ impl ::std::ops::Drop for InGameState {
     }
 }
 
+/// Returns the wireframe of a world collision hull, numbered from 1 as in `r_showhull`.
+fn hull_lines(models: &[Model], hull: usize) -> Vec<DebugVertex> {
+    let mut vertices = Vec::new();
+
+    if hull == 0 {
+        return vertices;
+    }
+
+    if let ModelKind::Brush(ref bmodel) = models[1].kind() {
+        match bmodel.hull(hull - 1) {
+            Ok(h) => {
+                for polygon in h.surface_polygons() {
+                    debug::push_polygon(&mut vertices, &polygon, debug::HULL_COLOR);
+                }
+            }
+
+            Err(e) => warn!("Can't show hull {}: {}", hull, e),
+        }
+    }
+
+    vertices
+}
+
+// rebake the world's lightmaps from the lights in the map's .ent file, or its own entities
+fn bake_lights(gfx_state: &GraphicsState, world_renderer: &mut WorldRenderer, models: &[Model]) {
+    let bmodel = match models[1].kind() {
+        ModelKind::Brush(ref bmodel) => bmodel,
+        _ => return,
+    };
+
+    let (path, ent_string) = match bake::load_entities(gfx_state.vfs(), models[1].name()) {
+        Ok(e) => e,
+        Err(e) => {
+            println!("Couldn't read entities for {}: {}", models[1].name(), e);
+            return;
+        }
+    };
+
+    let lights = match BakeLights::from_entities(&ent_string) {
+        Some(l) => l,
+        None => {
+            println!("Couldn't parse entities in {}", path);
+            return;
+        }
+    };
+
+    let bake = LightBake::new(gfx_state, bmodel.bsp_data(), &lights);
+    let count = world_renderer.bake_lights(gfx_state, &bake);
+    println!(
+        "Baked {} lightmaps from {} lights in {}",
+        count,
+        lights.lamps.len(),
+        path
+    );
+}
+
+/// Returns blob shadows cast on the world below `entities`.
+///
+/// `alias` enables round shadows under alias models and `movers` enables rectangular shadows
+/// under brush entities.
+fn blob_shadow_vertices<'a, 'b, I>(
+    arena: &'b FrameArena,
+    models: &[Model],
+    entities: I,
+    alias: bool,
+    movers: bool,
+) -> BumpVec<'b, BlobShadowVertex>
+where
+    I: Iterator<Item = &'a ClientEntity>,
+{
+    let mut vertices = arena.vec();
+
+    let bsp_data = match models[1].kind() {
+        ModelKind::Brush(ref bmodel) => bmodel.bsp_data(),
+        _ => return vertices,
+    };
+
+    for ent in entities {
+        let model = &models[ent.model_id()];
+        let origin = ent.get_origin();
+
+        match model.kind() {
+            ModelKind::Alias(_) if alias => {
+                let end = origin - Vector3::unit_z() * blob::BLOB_SHADOW_DISTANCE;
+                let hit = match bsp_data.trace_surface(origin, end) {
+                    Some(h) => h,
+                    None => continue,
+                };
+
+                // shadows fade out as their entity rises above the surface
+                let normal = bsp_data.face_normal(hit.face_id);
+                let height = origin.z - hit.point.z;
+                if normal.z <= 0.0 || height < 0.0 {
+                    continue;
+                }
+
+                let size = model.max() - model.min();
+                blob::push_blob_shadow(
+                    &mut vertices,
+                    hit.point,
+                    normal,
+                    (size.x + size.y) / 4.0,
+                    blob::BLOB_SHADOW_ALPHA * (1.0 - height / blob::BLOB_SHADOW_DISTANCE),
+                );
+            }
+
+            // model 1 is the world itself
+            ModelKind::Brush(_) if movers && ent.model_id() > 1 => {
+                let mins = origin + model.min();
+                let maxs = origin + model.max();
+
+                // start just inside the bottom of the entity in case it rests on the surface
+                let center = (mins + maxs) / 2.0;
+                let start = Vector3::new(center.x, center.y, mins.z + 1.0);
+                let end = start - Vector3::unit_z() * blob::MOVER_SHADOW_DISTANCE;
+                if let Some(hit) = bsp_data.trace_surface(start, end) {
+                    let normal = bsp_data.face_normal(hit.face_id);
+                    blob::push_mover_shadow(&mut vertices, mins, maxs, hit.point, normal);
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    vertices
+}
+
+/// Returns the shadow map resolution specified by `r_shadow_size`.
+fn shadow_size(cvars: &CvarRegistry) -> u32 {
+    match cvars.get_value("r_shadow_size") {
+        Ok(size) if size >= 64.0 && size <= 4096.0 => size as u32,
+        _ => DEFAULT_SHADOW_SIZE,
+    }
+}
+
+// how long HUD messages stay on screen, in milliseconds
+const HUD_MESSAGE_DURATION: i64 = 2000;
+
+// a save or load to ask the server for, with the slot to use
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum SaveRequest {
+    Save(String),
+    Load(String),
+}
+
 enum GameState {
     // loading level resources
     Loading,
@@ -146,7 +328,7 @@ pub struct Game {
     cvars: Rc<RefCell<CvarRegistry>>,
     cmds: Rc<RefCell<CmdRegistry>>,
     ui_renderer: Rc<UiRenderer>,
-    render_pass_bump: Bump,
+    frame_arena: FrameArena,
     state: GameState,
     input: Rc<RefCell<Input>>,
     client: Client,
@@ -156,6 +338,24 @@ pub struct Game {
 
     // if Some(path), take a screenshot and save it to path
     screenshot_path: Rc<RefCell<Option<PathBuf>>>,
+
+    // if Some, every rendered frame is added to the capture
+    video_capture: Option<VideoCapture>,
+
+    // if true, ask the server to toggle pause on the next frame
+    pause_requested: Rc<Cell<bool>>,
+
+    // if Some, ask the server to save or load on the next frame
+    save_requested: Rc<RefCell<Option<SaveRequest>>>,
+
+    // if true, rebake the world's lighting on the next frame
+    bake_requested: Rc<Cell<bool>>,
+
+    // message shown at the top of the view and the time left to show it
+    hud_message: Option<(String, Duration)>,
+
+    // time left in the level transition and its total length
+    transition: Option<(Duration, Duration)>,
 }
 
 impl Game {
@@ -168,19 +368,104 @@ impl Game {
     ) -> Result<Game, Error> {
         input.borrow().register_cmds(&mut cmds.borrow_mut());
 
-        // set up screenshots
+        // set up screenshots. the command outlives the game, and each new game takes it over.
         let screenshot_path = Rc::new(RefCell::new(None));
-        cmds.borrow_mut()
-            .insert("screenshot", cmd_screenshot(screenshot_path.clone()))
-            .unwrap();
+        cmds.borrow_mut().insert_or_replace(
+            "screenshot",
+            "screenshot [filename]: save an image of the screen",
+            cmd_screenshot(cvars.clone(), screenshot_path.clone()),
+        );
 
         // set up frame tracing
         let trace = Rc::new(RefCell::new(None));
         cmds.borrow_mut()
-            .insert("trace_begin", cmd_trace_begin(trace.clone()))
+            .insert(
+                "trace_begin",
+                "start recording the state of the view entity each frame",
+                cmd_trace_begin(trace.clone()),
+            )
             .unwrap();
         cmds.borrow_mut()
-            .insert("trace_end", cmd_trace_end(cvars.clone(), trace.clone()))
+            .insert(
+                "trace_end",
+                "stop recording the view entity and save the trace",
+                cmd_trace_end(cvars.clone(), trace.clone()),
+            )
+            .unwrap();
+
+        // pausing is handled by the server, so the command is forwarded there
+        let pause_requested = Rc::new(Cell::new(false));
+        let cmd_pause_requested = pause_requested.clone();
+        cmds.borrow_mut()
+            .insert(
+                "pause",
+                "pause or unpause the game",
+                Box::new(move |_| cmd_pause_requested.set(true)),
+            )
+            .unwrap();
+
+        // saving and loading are also handled by the server
+        let save_requested = Rc::new(RefCell::new(None));
+        let cmd_save_requested = save_requested.clone();
+        cmds.borrow_mut()
+            .insert(
+                "quicksave",
+                "save the game to the quicksave slot",
+                Box::new(move |_| {
+                    cmd_save_requested.replace(Some(SaveRequest::Save(QUICKSAVE_SLOT.to_owned())));
+                }),
+            )
+            .unwrap();
+        let cmd_save_requested = save_requested.clone();
+        cmds.borrow_mut()
+            .insert(
+                "quickload",
+                "load the game from the quicksave slot",
+                Box::new(move |_| {
+                    cmd_save_requested.replace(Some(SaveRequest::Load(QUICKSAVE_SLOT.to_owned())));
+                }),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert(
+                "save",
+                "save (savename): save the game",
+                cmd_save_slot(save_requested.clone(), SaveRequest::Save),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert(
+                "load",
+                "load (savename): load a saved game, such as one of the autosaves",
+                cmd_save_slot(save_requested.clone(), SaveRequest::Load),
+            )
+            .unwrap();
+
+        // lighting previews for mappers
+        let bake_requested = Rc::new(Cell::new(false));
+        let cmd_bake_requested = bake_requested.clone();
+        cmds.borrow_mut()
+            .insert(
+                "r_bakelights",
+                "recompute the map's lighting from its .ent file, or its own lights",
+                Box::new(move |_| cmd_bake_requested.set(true)),
+            )
+            .unwrap();
+
+        // resize the view in steps of 10%
+        cmds.borrow_mut()
+            .insert(
+                "sizeup",
+                "enlarge the view",
+                cmd_resize_view(cvars.clone(), 10.0),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert(
+                "sizedown",
+                "shrink the view",
+                cmd_resize_view(cvars.clone(), -10.0),
+            )
             .unwrap();
 
         Ok(Game {
@@ -188,28 +473,105 @@ impl Game {
             cmds,
             ui_renderer,
             // TODO: specify a capacity
-            render_pass_bump: Bump::new(),
+            frame_arena: FrameArena::new(),
             state: GameState::Loading,
             input,
             client,
             trace,
             screenshot_path,
+            video_capture: None,
+            pause_requested,
+            save_requested,
+            bake_requested,
+            hud_message: None,
+            transition: None,
         })
     }
 
+    // Ask the server to save to or load from a slot.
+    fn request_save(&mut self, request: SaveRequest) {
+        let quick = match request {
+            SaveRequest::Save(ref slot) | SaveRequest::Load(ref slot) => slot == QUICKSAVE_SLOT,
+        };
+        let (cmd, slot, message) = match request {
+            SaveRequest::Save(slot) if quick => ("save", slot, "Quicksaving..."),
+            SaveRequest::Load(slot) if quick => ("load", slot, "Quickloading..."),
+            SaveRequest::Save(slot) => ("save", slot, "Saving..."),
+            SaveRequest::Load(slot) => ("load", slot, "Loading..."),
+        };
+
+        // the server only saves single-player games
+        if self.client.demo_playback() || self.client.max_players() != 1 {
+            self.show_message("Can't save or load in this game");
+            return;
+        }
+
+        self.client
+            .forward_cmd(format!("{} {}", cmd, slot))
+            .unwrap();
+        self.show_message(message);
+    }
+
+    // Show a message at the top of the view for a few seconds.
+    fn show_message<S>(&mut self, message: S)
+    where
+        S: AsRef<str>,
+    {
+        self.hud_message = Some((
+            message.as_ref().to_owned(),
+            Duration::milliseconds(HUD_MESSAGE_DURATION),
+        ));
+    }
+
     // advance the simulation
+    /// Returns the rate at which the game clock runs relative to real time.
+    pub fn timescale(&self) -> f32 {
+        // demo playback doesn't affect anyone else, so it can always be scaled
+        let max_clients = if self.client.demo_playback() {
+            1
+        } else {
+            self.client.max_players()
+        };
+
+        server::timescale(&self.cvars.borrow(), max_clients)
+    }
+
+    /// Starts adding every rendered frame to `capture`.
+    pub fn start_video_capture(&mut self, capture: VideoCapture) {
+        self.video_capture = Some(capture);
+    }
+
+    pub fn video_capture(&self) -> Option<&VideoCapture> {
+        self.video_capture.as_ref()
+    }
+
+    /// Stops capturing frames, returning the capture in progress if there is one.
+    pub fn stop_video_capture(&mut self) -> Option<VideoCapture> {
+        self.video_capture.take()
+    }
+
+    /// Returns `true` if the client is playing a demo and has run out of messages.
+    pub fn demo_finished(&self) -> bool {
+        self.client.demo_finished()
+    }
+
+    /// Moves demo playback to the last keyframe at or before `offset` from the start of the demo.
+    pub fn seek_demo(&mut self, offset: Duration) {
+        self.client.seek_demo(offset);
+    }
+
     pub fn frame(&mut self, gfx_state: &GraphicsState, frame_duration: Duration) {
         self.client.frame(frame_duration).unwrap();
 
         // make sure we set loading state for reconnects
-        if self.client.signon_stage() != SignOnStage::Done {
+        if !self.client.host_state().in_game() {
             self.state = GameState::Loading;
         }
 
         if let GameState::Loading = self.state {
             println!("loading...");
             // check if we've finished getting server info yet
-            if self.client.signon_stage() == SignOnStage::Done {
+            if self.client.host_state().in_game() {
                 println!("finished loading");
                 // if we have, build renderers
                 let world_renderer = WorldRenderer::new(
@@ -219,26 +581,113 @@ impl Game {
                     &mut self.cvars.borrow_mut(),
                 );
 
+                let shadow_renderer =
+                    ShadowRenderer::new(gfx_state, shadow_size(&self.cvars.borrow()));
+
                 let deferred_renderer = DeferredRenderer::new(
                     gfx_state,
                     gfx_state.initial_pass_target().diffuse_view(),
                     gfx_state.initial_pass_target().normal_view(),
                     gfx_state.initial_pass_target().light_view(),
                     gfx_state.initial_pass_target().depth_view(),
+                    shadow_renderer.shadow_map_view(),
                 );
 
+                let bloom_renderer =
+                    BloomRenderer::new(gfx_state, gfx_state.deferred_pass_target().color_view());
+
                 let postprocess_renderer = PostProcessRenderer::new(
                     gfx_state,
                     gfx_state.deferred_pass_target().color_view(),
+                    gfx_state.bloom_view(),
                 );
 
                 self.state = GameState::InGame(InGameState::new(
                     self.cmds.clone(),
                     world_renderer,
+                    shadow_renderer,
                     deferred_renderer,
+                    bloom_renderer,
                     postprocess_renderer,
                     InGameFocus::Game,
+                    gfx_state.target_generation(),
                 ));
+
+                // reveal the new level gradually
+                let transition_time = self
+                    .cvars
+                    .borrow()
+                    .get_value("scr_transitiontime")
+                    .unwrap_or(0.0);
+                self.transition = if transition_time > 0.0 {
+                    let length = engine::duration_from_f32(transition_time);
+                    Some((length, length))
+                } else {
+                    None
+                };
+            }
+        }
+
+        // rebuild shadow maps if the resolution has changed, and rebind the render targets if
+        // they were recreated for a new framebuffer size
+        if let GameState::InGame(ref mut state) = self.state {
+            let size = shadow_size(&self.cvars.borrow());
+            let shadows_changed = state.shadow_renderer.size() != size;
+            let targets_changed = state.target_generation != gfx_state.target_generation();
+
+            if shadows_changed {
+                state.shadow_renderer = ShadowRenderer::new(gfx_state, size);
+            }
+
+            if shadows_changed || targets_changed {
+                state.deferred_renderer = DeferredRenderer::new(
+                    gfx_state,
+                    gfx_state.initial_pass_target().diffuse_view(),
+                    gfx_state.initial_pass_target().normal_view(),
+                    gfx_state.initial_pass_target().light_view(),
+                    gfx_state.initial_pass_target().depth_view(),
+                    state.shadow_renderer.shadow_map_view(),
+                );
+            }
+
+            if targets_changed {
+                state.bloom_renderer =
+                    BloomRenderer::new(gfx_state, gfx_state.deferred_pass_target().color_view());
+                state.postprocess_renderer = PostProcessRenderer::new(
+                    gfx_state,
+                    gfx_state.deferred_pass_target().color_view(),
+                    gfx_state.bloom_view(),
+                );
+                state.target_generation = gfx_state.target_generation();
+            }
+
+            let show_hull = self.cvars.borrow().get_value("r_showhull").unwrap_or(0.0) as usize;
+            if state.shown_hull != show_hull {
+                let vertices = hull_lines(self.client.models().unwrap(), show_hull);
+                state.hull_lines = if vertices.is_empty() {
+                    None
+                } else {
+                    Some(DebugLines::new(gfx_state, &vertices))
+                };
+                state.shown_hull = show_hull;
+            }
+
+            let conback_name = self.cvars.borrow().get("scr_conback").unwrap_or_default();
+            if state.conback_name != conback_name {
+                state.conback = if conback_name.is_empty() {
+                    None
+                } else {
+                    load_console_background(gfx_state, &conback_name)
+                };
+                state.conback_name = conback_name;
+            }
+
+            if self.bake_requested.replace(false) {
+                bake_lights(
+                    gfx_state,
+                    &mut state.world_renderer,
+                    self.client.models().unwrap(),
+                );
             }
         }
 
@@ -249,14 +698,56 @@ impl Game {
 
             GameState::InGame(ref state) => {
                 // set the proper focus
-                self.input
-                    .borrow_mut()
+                let mut input = self.input.borrow_mut();
+                input
                     .set_focus(match state.focus.get() {
                         InGameFocus::Game => InputFocus::Game,
                         InGameFocus::Menu => InputFocus::Menu,
                         InGameFocus::Console => InputFocus::Console,
                     })
                     .unwrap();
+                input.set_demo_playback(self.client.demo_playback());
+            }
+        }
+
+        if self.pause_requested.replace(false) {
+            self.client.forward_cmd("pause").unwrap();
+        }
+
+        if let Some(request) = self.save_requested.take() {
+            self.request_save(request);
+        }
+
+        self.hud_message = self
+            .hud_message
+            .take()
+            .map(|(message, remaining)| (message, remaining - frame_duration))
+            .filter(|(_, remaining)| *remaining > Duration::zero());
+
+        self.transition = self
+            .transition
+            .map(|(remaining, length)| (remaining - frame_duration, length))
+            .filter(|(remaining, _)| *remaining > Duration::zero());
+
+        // pause single-player games while the menu is open, or while the window is in the
+        // background if cl_autopause is set
+        if let GameState::InGame(ref mut state) = self.state {
+            let in_menu = match state.focus.get() {
+                InGameFocus::Menu => true,
+                _ => false,
+            };
+            let in_background = !self.input.borrow().window_focused()
+                && self.cvars.borrow().get_value("cl_autopause").unwrap_or(0.0) != 0.0;
+            let should_pause = in_menu || in_background;
+
+            if self.client.max_players() == 1 {
+                if should_pause && !state.auto_paused && !self.client.paused() {
+                    self.client.forward_cmd("pause").unwrap();
+                    state.auto_paused = true;
+                } else if !should_pause && state.auto_paused {
+                    self.client.forward_cmd("pause").unwrap();
+                    state.auto_paused = false;
+                }
             }
         }
 
@@ -282,22 +773,56 @@ impl Game {
         menu: &Menu,
     ) {
         // we don't need to keep this data between frames
-        self.render_pass_bump.reset();
+        self.frame_arena.reset();
 
         match self.state {
             // TODO: loading screen
             GameState::Loading => (),
 
             GameState::InGame(ref state) => {
-                let aspect_ratio = width as f32 / height as f32;
-                let fov_x = self.cvars.borrow().get_value("fov").unwrap();
-                let fov_y = math::fov_x_to_fov_y(cgmath::Deg(fov_x), aspect_ratio).unwrap();
+                let display = Extent2d { width, height };
+                let hud_settings = HudSettings::from_cvars(&self.cvars.borrow());
+                let (status_bar, view) = match self.client.intermission() {
+                    Some(_) => (StatusBarMode::Hidden, ViewRect::full(display)),
+                    None => {
+                        let viewsize = self.cvars.borrow().get_value("viewsize").unwrap_or(100.0);
+                        let status_bar = StatusBarMode::from_viewsize(viewsize);
+                        let view = ViewRect::from_viewsize(
+                            display,
+                            viewsize,
+                            hud_settings.reserved_status_bar(status_bar),
+                            hud_settings.sbar_scale,
+                        );
+                        (status_bar, view)
+                    }
+                };
 
-                let projection = cgmath::perspective(fov_y, aspect_ratio, 4.0, 4096.0);
+                let aspect_ratio = view.aspect_ratio();
+                let fov_adapt = self.cvars.borrow().get_value("fov_adapt").unwrap_or(0.0) != 0.0;
+                let adapt = |fov_x| match fov_adapt {
+                    true => math::adapt_fov_x(fov_x, aspect_ratio),
+                    false => fov_x,
+                };
+                let fov_x = adapt(cgmath::Deg(self.cvars.borrow().get_value("fov").unwrap()));
+                let projection = frustum::perspective(fov_x, aspect_ratio, 4.0, 4096.0).unwrap();
                 let camera = Camera::new(
+                    self.client.camera_origin(),
+                    self.client.camera_angles(self.client.time()).unwrap(),
+                    projection,
+                );
+
+                // the view weapon keeps its own field of view so that it isn't stretched at
+                // high fov values. the clip planes must match for it to be depth tested against
+                // the world
+                let viewmodel = self.client.viewmodel();
+                let viewmodel_fov_x = match self.cvars.borrow().get_value("r_viewmodelfov") {
+                    Ok(v) if v > 0.0 => adapt(cgmath::Deg(v)),
+                    _ => fov_x,
+                };
+                let viewmodel_camera = Camera::new(
                     self.client.view_origin(),
                     self.client.view_angles(self.client.time()).unwrap(),
-                    projection,
+                    frustum::perspective(viewmodel_fov_x, aspect_ratio, 4.0, 4096.0).unwrap(),
                 );
 
                 info!("Beginning render pass");
@@ -305,6 +830,32 @@ impl Game {
                     .device()
                     .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
+                let shadow_maps = self.cvars.borrow().get_value("r_shadows").unwrap_or(0.0) != 0.0;
+                let entity_blobs = self
+                    .cvars
+                    .borrow()
+                    .get_value("r_blobshadows")
+                    .unwrap_or(0.0)
+                    != 0.0;
+                let mover_shadows = self
+                    .cvars
+                    .borrow()
+                    .get_value("r_movershadows")
+                    .unwrap_or(0.0)
+                    != 0.0;
+                let blob_shadows = if entity_blobs || mover_shadows {
+                    let vertices = blob_shadow_vertices(
+                        &self.frame_arena,
+                        self.client.models().unwrap(),
+                        self.client.iter_visible_entities(),
+                        entity_blobs,
+                        mover_shadows,
+                    );
+                    Some(BlobShadows::new(gfx_state, &vertices))
+                } else {
+                    None
+                };
+
                 // initial render pass
                 {
                     let init_pass_builder = gfx_state.initial_pass_target().render_pass_builder();
@@ -314,7 +865,7 @@ impl Game {
                     state.world_renderer.render_pass(
                         gfx_state,
                         &mut init_pass,
-                        &self.render_pass_bump,
+                        &self.frame_arena,
                         &camera,
                         self.client.time(),
                         self.client.iter_visible_entities(),
@@ -322,8 +873,68 @@ impl Game {
                         self.client.lightstyle_values().unwrap().as_slice(),
                         &self.cvars.borrow(),
                     );
+
+                    if let Some(ref blobs) = blob_shadows {
+                        blobs.record_draw(
+                            gfx_state,
+                            &mut init_pass,
+                            &self.frame_arena,
+                            &camera,
+                        );
+                    }
+                }
+
+                // view weapon pass
+                if let Some(viewmodel) = viewmodel {
+                    let viewmodel_pass_builder =
+                        gfx_state.initial_pass_target().overlay_pass_builder();
+                    let mut viewmodel_pass =
+                        encoder.begin_render_pass(&viewmodel_pass_builder.descriptor());
+
+                    state.world_renderer.render_viewmodel_pass(
+                        gfx_state,
+                        &mut viewmodel_pass,
+                        &self.frame_arena,
+                        &viewmodel_camera,
+                        self.client.time(),
+                        viewmodel,
+                        &self.cvars.borrow(),
+                    );
                 }
 
+                // sort lights by distance so the nearest lights cast shadows. a light with a bad
+                // origin from the server shouldn't take the client down with it.
+                let view_origin = self.client.camera_origin();
+                let mut visible_lights = self.frame_arena.vec();
+                visible_lights.extend(self.client.iter_lights());
+                visible_lights.sort_by(|a, b| {
+                    let dist_a = (a.origin() - view_origin).magnitude2();
+                    let dist_b = (b.origin() - view_origin).magnitude2();
+                    dist_a.partial_cmp(&dist_b).unwrap_or(Ordering::Equal)
+                });
+                visible_lights.truncate(MAX_LIGHTS);
+
+                // shadow pass
+                let shadow_transforms = if shadow_maps {
+                    let mut shadow_lights = self.frame_arena.vec_with_capacity(MAX_SHADOW_LIGHTS);
+                    shadow_lights.extend(visible_lights.iter().take(MAX_SHADOW_LIGHTS).map(
+                        |light| ShadowLight {
+                            origin: light.origin(),
+                            radius: self.client.light_radius(light),
+                        },
+                    ));
+
+                    state.shadow_renderer.render_shadow_maps(
+                        gfx_state,
+                        &mut encoder,
+                        &self.frame_arena,
+                        &state.world_renderer,
+                        &shadow_lights,
+                    )
+                } else {
+                    Vec::new()
+                };
+
                 // deferred lighting pass
                 {
                     let deferred_pass_builder =
@@ -337,21 +948,37 @@ impl Game {
                     }; MAX_LIGHTS];
 
                     let mut light_count = 0;
-                    for (light_id, light) in self.client.iter_lights().enumerate() {
+                    for (light_id, light) in visible_lights.iter().enumerate() {
                         light_count += 1;
-                        let light_origin = light.origin();
-                        let converted_origin =
-                            Vector3::new(-light_origin.y, light_origin.z, -light_origin.x);
+                        let converted_origin = frustum::quake_to_wgpu(light.origin());
                         lights[light_id].origin =
                             (camera.view() * converted_origin.extend(1.0)).truncate();
-                        lights[light_id].radius = light.radius(self.client.time());
+                        lights[light_id].radius = self.client.light_radius(light);
+                    }
+
+                    // shadow transforms operate on view space positions
+                    let inv_view = camera.view().invert().unwrap();
+                    let mut deferred_shadow_transforms =
+                        [Matrix4::identity().into(); MAX_SHADOW_LIGHTS * SHADOW_FACE_COUNT];
+                    for (shadow_id, face_transforms) in shadow_transforms.iter().enumerate() {
+                        for (face_id, face_transform) in face_transforms.iter().enumerate() {
+                            deferred_shadow_transforms[shadow_id * SHADOW_FACE_COUNT + face_id] =
+                                (face_transform * inv_view).into();
+                        }
                     }
 
+                    let fog = self.client.fog();
+                    let sky_fog = self.cvars.borrow().get_value("r_skyfog").unwrap_or(0.5);
+
                     let uniforms = DeferredUniforms {
                         inv_projection: projection.invert().unwrap().into(),
                         light_count,
-                        _pad: [0; 3],
+                        shadow_count: shadow_transforms.len() as u32,
+                        sky_fog: sky_fog.max(0.0).min(1.0),
+                        _pad: 0,
+                        fog: [fog.color[0], fog.color[1], fog.color[2], fog.density],
                         lights,
+                        shadow_transforms: deferred_shadow_transforms,
                     };
 
                     state
@@ -359,6 +986,55 @@ impl Game {
                         .record_draw(gfx_state, &mut deferred_pass, uniforms);
                 }
 
+                // translucent liquid pass. opaque liquids were drawn with the rest of the world.
+                if state
+                    .world_renderer
+                    .has_translucent_water(&self.cvars.borrow())
+                {
+                    let water_pass_builder = gfx_state
+                        .deferred_pass_target()
+                        .translucent_pass_builder(gfx_state.initial_pass_target().depth_view());
+                    let mut water_pass =
+                        encoder.begin_render_pass(&water_pass_builder.descriptor());
+
+                    state.world_renderer.render_water_pass(
+                        gfx_state,
+                        &mut water_pass,
+                        &self.frame_arena,
+                        &camera,
+                        self.client.time(),
+                        &self.cvars.borrow(),
+                    );
+                }
+
+                // bloom passes
+                let bloom_intensity = if let Some(ref bloom_renderer) = state.bloom_renderer {
+                    bloom_renderer.render_bloom(gfx_state, &mut encoder);
+                    self.cvars
+                        .borrow()
+                        .get_value("r_bloom_intensity")
+                        .unwrap_or(0.0)
+                } else {
+                    0.0
+                };
+
+                let tonemap = self
+                    .cvars
+                    .borrow()
+                    .get_value("r_tonemap")
+                    .ok()
+                    .and_then(Tonemap::from_value)
+                    .unwrap_or_default();
+
+                let water_warp = if self.client.view_underwater() {
+                    self.cvars.borrow().get_value("r_waterwarp").unwrap_or(0.0)
+                } else {
+                    0.0
+                };
+
+                let console_settings =
+                    ConsoleSettings::from_cvars(&self.cvars.borrow(), state.conback.as_ref());
+
                 let ui_state = UiState::InGame {
                     hud: match self.client.intermission() {
                         Some(kind) => HudState::Intermission {
@@ -366,6 +1042,7 @@ impl Game {
                             completion_duration: self.client.completion_time().unwrap()
                                 - self.client.start_time(),
                             stats: self.client.stats(),
+                            scale: hud_settings.sbar_scale,
                         },
 
                         None => HudState::InGame {
@@ -373,13 +1050,114 @@ impl Game {
                             item_pickup_time: self.client.item_get_time(),
                             stats: self.client.stats(),
                             face_anim_time: self.client.face_anim_time(),
+                            paused: self.client.paused(),
+                            status_bar,
+                            view,
+                            settings: hud_settings,
+                            message: self.hud_message.as_ref().map(|(m, _)| m.as_str()),
                         },
                     },
+                    transition: self.transition.map(|(remaining, length)| {
+                        let kind = self
+                            .cvars
+                            .borrow()
+                            .get_value("scr_transition")
+                            .ok()
+                            .and_then(Transition::from_value)
+                            .unwrap_or_default();
+                        (
+                            kind,
+                            engine::duration_to_f32(remaining) / engine::duration_to_f32(length),
+                        )
+                    }),
                     overlay: match state.focus.get() {
                         InGameFocus::Game => None,
-                        InGameFocus::Console => Some(UiOverlay::Console(console)),
+                        InGameFocus::Console => Some(UiOverlay::Console(console, console_settings)),
                         InGameFocus::Menu => Some(UiOverlay::Menu(menu)),
                     },
+                    notify: match state.focus.get() {
+                        InGameFocus::Game => Some((console, console_settings)),
+                        _ => None,
+                    },
+                };
+
+                // collision debugging overlays
+                let mut debug_vertices = Vec::new();
+                if self.cvars.borrow().get_value("r_showbboxes").unwrap_or(0.0) != 0.0 {
+                    let models = self.client.models().unwrap();
+                    for ent in self.client.iter_visible_entities() {
+                        // skip the null model and the world
+                        if ent.model_id() <= 1 {
+                            continue;
+                        }
+
+                        let model = &models[ent.model_id()];
+                        let origin = ent.get_origin();
+                        debug::push_box(
+                            &mut debug_vertices,
+                            origin + model.min(),
+                            origin + model.max(),
+                            debug::BBOX_COLOR,
+                        );
+                    }
+                }
+
+                let show_traces =
+                    self.cvars.borrow().get_value("r_showtraces").unwrap_or(0.0) as usize;
+                for trace in self.client.iter_debug_traces().take(show_traces) {
+                    debug::push_line(
+                        &mut debug_vertices,
+                        trace.start,
+                        trace.stop,
+                        debug::TRACE_COLOR,
+                    );
+                    if trace.stop != trace.end {
+                        debug::push_line(
+                            &mut debug_vertices,
+                            trace.stop,
+                            trace.end,
+                            debug::TRACE_BLOCKED_COLOR,
+                        );
+                    }
+                }
+
+                if let Some(face_id) = self.client.highlighted_surface() {
+                    if let ModelKind::Brush(ref bmodel) = self.client.models().unwrap()[1].kind() {
+                        let polygon: Vec<_> =
+                            bmodel.bsp_data().face_iter_vertices(face_id).collect();
+                        debug::push_polygon(&mut debug_vertices, &polygon, debug::SURFACE_COLOR);
+                    }
+                }
+
+                // practice route and the marker racing along it
+                {
+                    let practice = self.client.practice();
+                    if let Some(route) = practice.visible_route() {
+                        for pair in route.points().windows(2) {
+                            debug::push_line(
+                                &mut debug_vertices,
+                                pair[0].origin,
+                                pair[1].origin,
+                                debug::ROUTE_COLOR,
+                            );
+                        }
+                    }
+
+                    if let Some(marker) = practice.marker() {
+                        // the size of the player's bounding box
+                        debug::push_box(
+                            &mut debug_vertices,
+                            marker + Vector3::new(-16.0, -16.0, -24.0),
+                            marker + Vector3::new(16.0, 16.0, 32.0),
+                            debug::ROUTE_MARKER_COLOR,
+                        );
+                    }
+                }
+
+                let debug_lines = if debug_vertices.is_empty() {
+                    None
+                } else {
+                    Some(DebugLines::new(gfx_state, &debug_vertices))
                 };
 
                 // final render pass
@@ -392,16 +1170,43 @@ impl Game {
                     let mut final_pass =
                         encoder.begin_render_pass(&final_pass_builder.descriptor());
 
+                    // the scene is drawn into the view, and the UI over the whole screen
+                    final_pass.set_viewport(
+                        view.x as f32,
+                        view.y as f32,
+                        view.width as f32,
+                        view.height as f32,
+                        0.0,
+                        1.0,
+                    );
+
                     state.postprocess_renderer.record_draw(
                         gfx_state,
                         &mut final_pass,
-                        self.client.color_shift(),
+                        PostProcessUniforms {
+                            color_shift: self.client.color_shift(),
+                            bloom_intensity,
+                            tonemap: tonemap as u32,
+                            time: engine::duration_to_f32(self.client.time()),
+                            water_warp,
+                        },
                     );
 
+                    for lines in state.hull_lines.iter().chain(debug_lines.iter()) {
+                        lines.record_draw(
+                            gfx_state,
+                            &mut final_pass,
+                            &self.frame_arena,
+                            &camera,
+                        );
+                    }
+
+                    final_pass.set_viewport(0.0, 0.0, width as f32, height as f32, 0.0, 1.0);
+
                     self.ui_renderer.render_pass(
                         &gfx_state,
                         &mut final_pass,
-                        Extent2d { width, height },
+                        display,
                         self.client.time(),
                         &ui_state,
                         &mut quad_commands,
@@ -410,7 +1215,9 @@ impl Game {
                 }
 
                 // screenshot setup
-                let capture = self.screenshot_path.borrow().as_ref().map(|_| {
+                let capture_requested =
+                    self.screenshot_path.borrow().is_some() || self.video_capture.is_some();
+                let capture = if capture_requested {
                     let cap = Capture::new(gfx_state.device(), Extent2d { width, height });
                     cap.copy_from_texture(
                         &mut encoder,
@@ -420,8 +1227,10 @@ impl Game {
                             origin: wgpu::Origin3d::ZERO,
                         },
                     );
-                    cap
-                });
+                    Some(cap)
+                } else {
+                    None
+                };
 
                 // blit to swap chain
                 {
@@ -438,13 +1247,21 @@ impl Game {
                     gfx_state.device().poll(wgpu::Maintain::Wait);
                 }
 
-                // write screenshot if requested and clear screenshot path
-                self.screenshot_path.replace(None).map(|path| {
-                    capture
-                        .as_ref()
-                        .unwrap()
-                        .write_to_file(gfx_state.device(), path)
-                });
+                if let Some(capture) = capture {
+                    let rgba = capture.read_rgba(gfx_state.device());
+
+                    // write screenshot if requested and clear screenshot path
+                    if let Some(path) = self.screenshot_path.replace(None) {
+                        write_screenshot(path, capture.size(), &rgba);
+                    }
+
+                    if let Some(ref mut video) = self.video_capture {
+                        if let Err(e) = video.write_frame(capture.size(), &rgba) {
+                            log::error!("Video capture failed: {}", e);
+                            self.video_capture = None;
+                        }
+                    }
+                }
             }
         }
     }
@@ -454,5 +1271,38 @@ impl std::ops::Drop for Game {
     fn drop(&mut self) {
         let _ = self.cmds.borrow_mut().remove("trace_begin");
         let _ = self.cmds.borrow_mut().remove("trace_end");
+        let _ = self.cmds.borrow_mut().remove("pause");
+        let _ = self.cmds.borrow_mut().remove("quicksave");
+        let _ = self.cmds.borrow_mut().remove("quickload");
+        let _ = self.cmds.borrow_mut().remove("save");
+        let _ = self.cmds.borrow_mut().remove("load");
+        let _ = self.cmds.borrow_mut().remove("r_bakelights");
+        let _ = self.cmds.borrow_mut().remove("sizeup");
+        let _ = self.cmds.borrow_mut().remove("sizedown");
     }
 }
+
+// Ask the server to save or load the slot named by the command's argument.
+fn cmd_save_slot(
+    save_requested: Rc<RefCell<Option<SaveRequest>>>,
+    request: fn(String) -> SaveRequest,
+) -> Box<dyn Fn(&[&str])> {
+    Box::new(move |args| match args {
+        [slot] => {
+            save_requested.replace(Some(request((*slot).to_owned())));
+        }
+        _ => println!("usage: save|load (savename)"),
+    })
+}
+
+// Change viewsize by `delta`, keeping it within the allowed range.
+fn cmd_resize_view(cvars: Rc<RefCell<CvarRegistry>>, delta: f32) -> Box<dyn Fn(&[&str])> {
+    Box::new(move |_| {
+        let cvars = cvars.borrow();
+        let viewsize = cvars.get_value("viewsize").unwrap_or(100.0) + delta;
+        let viewsize = viewsize.max(VIEWSIZE_MIN).min(VIEWSIZE_MAX);
+        if let Err(e) = cvars.set("viewsize", viewsize.to_string().as_str()) {
+            warn!("Couldn't set viewsize: {}", e);
+        }
+    })
+}
@@ -0,0 +1,147 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Saving and restoring the window's size, position and fullscreen state.
+//!
+//! The geometry is kept in the archived `vid_*` cvars, which are written to the config file when
+//! the client exits and applied to the window when it starts.
+
+use richter::common::console::CvarRegistry;
+
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    monitor::MonitorHandle,
+    window::{Fullscreen, Window},
+};
+
+pub struct WindowGeometry {
+    size: Option<PhysicalSize<u32>>,
+    position: Option<PhysicalPosition<i32>>,
+    monitor: Option<String>,
+    fullscreen: bool,
+}
+
+impl WindowGeometry {
+    pub fn from_cvars(cvars: &CvarRegistry) -> WindowGeometry {
+        let parse = |name| {
+            cvars
+                .get(name)
+                .ok()
+                .and_then(|v| v.parse::<f32>().ok())
+                .map(|v| v as i32)
+        };
+
+        let size = match (parse("vid_width"), parse("vid_height")) {
+            (Some(w), Some(h)) if w > 0 && h > 0 => Some(PhysicalSize::new(w as u32, h as u32)),
+            _ => None,
+        };
+
+        let position = match (parse("vid_x"), parse("vid_y")) {
+            (Some(x), Some(y)) => Some(PhysicalPosition::new(x, y)),
+            _ => None,
+        };
+
+        let monitor = cvars.get("vid_monitor").ok().filter(|m| !m.is_empty());
+        let fullscreen = parse("vid_fullscreen").map_or(false, |f| f != 0);
+
+        WindowGeometry {
+            size,
+            position,
+            monitor,
+            fullscreen,
+        }
+    }
+
+    /// Restores the saved geometry.
+    ///
+    /// The position is only restored if it's still on a connected monitor, and the window goes
+    /// fullscreen on the saved monitor if it's connected or on the current one if not.
+    pub fn apply(&self, window: &Window) {
+        if let Some(size) = self.size {
+            window.set_inner_size(size);
+        }
+
+        if let Some(position) = self.position {
+            if window
+                .available_monitors()
+                .any(|m| monitor_contains(&m, position))
+            {
+                window.set_outer_position(position);
+            }
+        }
+
+        if self.fullscreen {
+            let monitor = self
+                .monitor
+                .as_ref()
+                .and_then(|name| {
+                    window
+                        .available_monitors()
+                        .find(|m| m.name().as_ref() == Some(name))
+                })
+                .unwrap_or_else(|| window.current_monitor());
+            window.set_fullscreen(Some(Fullscreen::Borderless(monitor)));
+        }
+    }
+
+    /// Saves the window's current geometry to the `vid_*` cvars.
+    ///
+    /// A fullscreen window covers its monitor, so only the windowed size and position are saved
+    /// and those from before it went fullscreen are kept.
+    pub fn store(window: &Window, cvars: &CvarRegistry) {
+        let fullscreen = window.fullscreen().is_some();
+        let monitor = window.current_monitor().name().unwrap_or_default();
+
+        let mut values = vec![
+            (
+                "vid_fullscreen",
+                if fullscreen { "1" } else { "0" }.to_owned(),
+            ),
+            ("vid_monitor", monitor),
+        ];
+
+        if !fullscreen {
+            let size = window.inner_size();
+            values.push(("vid_width", size.width.to_string()));
+            values.push(("vid_height", size.height.to_string()));
+
+            // not every platform can report the window position
+            if let Ok(position) = window.outer_position() {
+                values.push(("vid_x", position.x.to_string()));
+                values.push(("vid_y", position.y.to_string()));
+            }
+        }
+
+        for (name, value) in values {
+            if let Err(e) = cvars.set(name, value.as_str()) {
+                log::warn!("Couldn't save window geometry: {}", e);
+            }
+        }
+    }
+}
+
+fn monitor_contains(monitor: &MonitorHandle, position: PhysicalPosition<i32>) -> bool {
+    let origin = monitor.position();
+    let size = monitor.size();
+    position.x >= origin.x
+        && position.y >= origin.y
+        && position.x < origin.x + size.width as i32
+        && position.y < origin.y + size.height as i32
+}
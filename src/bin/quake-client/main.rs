@@ -20,31 +20,40 @@
 
 mod capture;
 mod game;
+mod geometry;
 mod menu;
 mod trace;
 
 use std::{
     cell::{Cell, Ref, RefCell, RefMut},
+    io::ErrorKind,
     net::{SocketAddr, ToSocketAddrs},
-    path::Path,
+    path::{Path, PathBuf},
     rc::Rc,
 };
 
+use capture::VideoCapture;
 use game::Game;
+use geometry::WindowGeometry;
 
 use chrono::Duration;
 use richter::{
     client::{
-        self,
+        self, demo,
         input::{Input, InputFocus},
         menu::Menu,
-        render::{self, Extent2d, GraphicsState, UiRenderer, DIFFUSE_ATTACHMENT_FORMAT},
-        Client,
+        preset,
+        render::{
+            self, Extent2d, GraphicsState, TextureMode, UiRenderer, DIFFUSE_ATTACHMENT_FORMAT,
+        },
+        Client, ClientError,
     },
     common::{
-        self,
-        console::{CmdRegistry, Console, CvarRegistry},
+        self, args,
+        console::{self, CmdRegistry, Console, CvarRegistry, PrintLevel},
+        engine,
         host::{Host, Program},
+        limits::LoadLimits,
         vfs::Vfs,
     },
 };
@@ -55,6 +64,18 @@ use winit::{
     window::{Window, WindowBuilder},
 };
 
+// lowest value of r_scale, below which the scene is unrecognizable
+const MIN_RENDER_SCALE: f32 = 0.25;
+
+// console command history, saved in the game directory between runs
+const HISTORY_FILE: &str = "history.txt";
+
+// archived cvars, saved in the game directory between runs
+const CONFIG_FILE: &str = "vars.rc";
+
+// console output is appended to this file in the game directory with -condebug
+const CONSOLE_LOG_FILE: &str = "qconsole.log";
+
 enum TitleState {
     Menu,
     Console,
@@ -67,6 +88,10 @@ enum ProgramState {
 
 struct ClientProgram {
     vfs: Rc<Vfs>,
+
+    // the directory settings are saved to: the mod directory if there is one, otherwise id1
+    game_dir: PathBuf,
+
     cvars: Rc<RefCell<CvarRegistry>>,
     cmds: Rc<RefCell<CmdRegistry>>,
     console: Rc<RefCell<Console>>,
@@ -75,6 +100,9 @@ struct ClientProgram {
     window: Window,
     window_dimensions_changed: Cell<bool>,
 
+    // whether the cursor is currently grabbed and hidden
+    cursor_grabbed: Cell<bool>,
+
     instance: wgpu::Instance,
     surface: wgpu::Surface,
     adapter: wgpu::Adapter,
@@ -86,30 +114,29 @@ struct ClientProgram {
 
     state: RefCell<ProgramState>,
     input: Rc<RefCell<Input>>,
-}
 
-impl ClientProgram {
-    pub async fn new(window: Window, audio_device: rodio::Device, trace: bool) -> ClientProgram {
-        let mut vfs = Vfs::new();
-
-        // add basedir first
-        vfs.add_directory(common::DEFAULT_BASEDIR).unwrap();
-
-        // then add PAK archives
-        for vfs_id in 0..common::MAX_PAKFILES {
-            // TODO: check `-basedir` command line argument
-            let basedir = common::DEFAULT_BASEDIR;
-            let path_string = format!("{}/pak{}.pak", basedir, vfs_id);
-            let path = Path::new(&path_string);
-
-            // keep adding PAKs until we don't find one or we hit MAX_PAKFILES
-            if !path.exists() {
-                break;
-            }
+    // if Some((path, start)), start playing the demo at path on the next frame, resuming from
+    // the keyframe before start if there is one
+    pending_demo: Rc<RefCell<Option<(String, Option<Duration>)>>>,
 
-            vfs.add_pakfile(path).unwrap();
-        }
+    // if Some((path, output)), start capturing the demo at path to output on the next frame
+    pending_capture: Rc<RefCell<Option<(String, Option<String>)>>>,
+
+    // console commands from the command line, run once the startup scripts have finished
+    startup_commands: RefCell<Vec<String>>,
+
+    // set when a demo capture finishes, so the program exits after this frame
+    exit_requested: Cell<bool>,
+}
 
+impl ClientProgram {
+    pub async fn new(
+        window: Window,
+        audio_device: rodio::Device,
+        opt: &Opt,
+        commands: Vec<String>,
+    ) -> ClientProgram {
+        let game_dir = opt.game_dir();
         let cvars = Rc::new(RefCell::new(CvarRegistry::new()));
         client::register_cvars(&cvars.borrow()).unwrap();
         render::register_cvars(&cvars.borrow());
@@ -117,8 +144,55 @@ impl ClientProgram {
         let cmds = Rc::new(RefCell::new(CmdRegistry::new()));
         // TODO: register commands as other subsystems come online
 
+        let pending_demo = Rc::new(RefCell::new(None));
+        cmds.borrow_mut()
+            .insert(
+                "playdemo",
+                "playdemo (demoname) [seconds]: play back a recorded demo, optionally from a time",
+                cmd_playdemo(pending_demo.clone()),
+            )
+            .unwrap();
+
+        let pending_capture = Rc::new(RefCell::new(None));
+        cmds.borrow_mut()
+            .insert(
+                "capturedemo",
+                "capturedemo (demoname) [output]: render a demo to images or a video file",
+                cmd_capturedemo(pending_capture.clone()),
+            )
+            .unwrap();
+
         let console = Rc::new(RefCell::new(Console::new(cmds.clone(), cvars.clone())));
-        let menu = Rc::new(RefCell::new(menu::build_main_menu().unwrap()));
+        if opt.condebug {
+            if let Err(e) = console.borrow().log_to(game_dir.join(CONSOLE_LOG_FILE)) {
+                log::warn!("Couldn't open console log: {}", e);
+            }
+        }
+
+        // restore the archived cvars from the last run, and with them the load limits and the
+        // window geometry, before any archives are opened or the surface is created. the video
+        // options on the command line override the saved geometry. config.cfg and autoexec.cfg
+        // are executed later and take precedence.
+        if let Err(e) = console.borrow().load_config(game_dir.join(CONFIG_FILE)) {
+            if e.kind() != ErrorKind::NotFound {
+                log::warn!("Couldn't load config: {}", e);
+            }
+        }
+        for (name, value) in opt.video_cvars() {
+            cvars.borrow_mut().set(name, &value).unwrap();
+        }
+        WindowGeometry::from_cvars(&cvars.borrow()).apply(&window);
+
+        let mut vfs = Vfs::with_limits(LoadLimits::from_cvars(&cvars.borrow()));
+        vfs.add_game_dir(opt.basedir.join(common::DEFAULT_BASEDIR))
+            .unwrap();
+        if game_dir != opt.basedir.join(common::DEFAULT_BASEDIR) {
+            vfs.add_game_dir(&game_dir).unwrap();
+        }
+
+        let menu = Rc::new(RefCell::new(
+            menu::build_main_menu(&vfs, console.clone()).unwrap(),
+        ));
 
         let input = Rc::new(RefCell::new(Input::new(
             InputFocus::Game,
@@ -142,7 +216,9 @@ impl ClientProgram {
                     features: wgpu::Features::PUSH_CONSTANTS
                         | wgpu::Features::SAMPLED_TEXTURE_BINDING_ARRAY
                         | wgpu::Features::SAMPLED_TEXTURE_ARRAY_DYNAMIC_INDEXING
-                        | wgpu::Features::SAMPLED_TEXTURE_ARRAY_NON_UNIFORM_INDEXING,
+                        | wgpu::Features::SAMPLED_TEXTURE_ARRAY_NON_UNIFORM_INDEXING
+                        // used by r_texturecompression where available
+                        | (adapter.features() & wgpu::Features::TEXTURE_COMPRESSION_BC),
                     limits: wgpu::Limits {
                         max_sampled_textures_per_shader_stage: 256,
                         max_uniform_buffer_binding_size: 65536,
@@ -151,7 +227,7 @@ impl ClientProgram {
                     },
                     shader_validation: true,
                 },
-                if trace {
+                if opt.trace {
                     Some(Path::new("./trace/"))
                 } else {
                     None
@@ -173,6 +249,65 @@ impl ClientProgram {
 
         let vfs = Rc::new(vfs);
 
+        let demo_vfs = vfs.clone();
+        cmds.borrow_mut().insert_completer(
+            "playdemo",
+            Box::new(move || console::files_with_extension(&demo_vfs, "", "dem", true)),
+        );
+        let demo_vfs = vfs.clone();
+        cmds.borrow_mut().insert_completer(
+            "capturedemo",
+            Box::new(move || console::files_with_extension(&demo_vfs, "", "dem", true)),
+        );
+        cmds.borrow_mut()
+            .insert(
+                "deminfo",
+                "deminfo (demoname): show the map, protocol, length and player of a demo",
+                cmd_deminfo(vfs.clone()),
+            )
+            .unwrap();
+        let demo_vfs = vfs.clone();
+        cmds.borrow_mut().insert_completer(
+            "deminfo",
+            Box::new(move || console::files_with_extension(&demo_vfs, "", "dem", true)),
+        );
+
+        cmds.borrow_mut()
+            .insert(
+                "which",
+                "which (path): show where a game file is loaded from and what it overrides",
+                cmd_which(vfs.clone()),
+            )
+            .unwrap();
+
+        cmds.borrow_mut()
+            .insert(
+                "preset",
+                "preset [name]: list presets, or apply a bundle of settings",
+                cmd_preset(vfs.clone(), cvars.clone()),
+            )
+            .unwrap();
+        let preset_vfs = vfs.clone();
+        cmds.borrow_mut()
+            .insert_completer("preset", Box::new(move || preset::names(&preset_vfs)));
+
+        // there's no local server yet, but map names are still useful to complete for commands
+        // forwarded to a remote one
+        let map_vfs = vfs.clone();
+        cmds.borrow_mut().insert_completer(
+            "map",
+            Box::new(move || console::files_with_extension(&map_vfs, "maps", "bsp", true)),
+        );
+
+        if let Err(e) = console
+            .borrow_mut()
+            .load_history(game_dir.join(HISTORY_FILE))
+        {
+            if e.kind() != ErrorKind::NotFound {
+                log::warn!("Couldn't load console history: {}", e);
+            }
+        }
+
         // TODO: warn user if r_msaa_samples is invalid
         let mut sample_count = cvars.borrow().get_value("r_msaa_samples").unwrap_or(2.0) as u32;
         if !&[2, 4].contains(&sample_count) {
@@ -187,12 +322,14 @@ impl ClientProgram {
 
         ClientProgram {
             vfs,
+            game_dir,
             cvars,
             cmds,
             console,
             menu,
             window,
             window_dimensions_changed: Cell::new(false),
+            cursor_grabbed: Cell::new(false),
             instance,
             surface,
             adapter,
@@ -202,21 +339,44 @@ impl ClientProgram {
             audio_device: Rc::new(audio_device),
             state: RefCell::new(ProgramState::Title),
             input,
+            pending_demo,
+            pending_capture,
+            startup_commands: RefCell::new(commands),
+            exit_requested: Cell::new(false),
         }
     }
 
-    fn connect<A>(&mut self, server_addrs: A)
+    fn connect<A>(&mut self, server_addrs: A, quakeworld: bool)
     where
-        A: ToSocketAddrs,
+        A: ToSocketAddrs + Copy,
     {
-        let cl = Client::connect(
-            server_addrs,
-            self.vfs.clone(),
-            self.cvars.clone(),
-            self.cmds.clone(),
-            self.console.clone(),
-            self.audio_device.clone(),
-        )
+        let connect_qw = || {
+            Client::connect_qw(
+                server_addrs,
+                self.vfs.clone(),
+                self.cvars.clone(),
+                self.cmds.clone(),
+                self.console.clone(),
+                self.audio_device.clone(),
+            )
+        };
+
+        let cl = if quakeworld {
+            connect_qw()
+        } else {
+            match Client::connect(
+                server_addrs,
+                self.vfs.clone(),
+                self.cvars.clone(),
+                self.cmds.clone(),
+                self.console.clone(),
+                self.audio_device.clone(),
+            ) {
+                // QuakeWorld servers ignore NetQuake connection requests
+                Err(ClientError::NoResponse) => connect_qw(),
+                cl => cl,
+            }
+        }
         .unwrap();
 
         cl.register_cmds(&mut self.cmds.borrow_mut());
@@ -233,32 +393,83 @@ impl ClientProgram {
         ));
     }
 
-    fn play_demo<S>(&mut self, demo_path: S)
+    /// Plays the demo at `demo_path` in place of the current game.
+    ///
+    /// If the demo can't be played, the error is printed to the console and the current game
+    /// carries on. Returns whether the demo started.
+    fn play_demo<S>(&mut self, demo_path: S) -> bool
     where
         S: AsRef<str>,
     {
-        let cl = Client::play_demo(
+        let demo_path = demo_path.as_ref();
+        let cl = match Client::play_demo(
             demo_path,
             self.vfs.clone(),
             self.cvars.clone(),
             self.cmds.clone(),
             self.console.clone(),
             self.audio_device.clone(),
-        )
-        .unwrap();
+        ) {
+            Ok(cl) => cl,
+            Err(e) => {
+                self.console.borrow().print(
+                    PrintLevel::Game,
+                    format!("Couldn't play {}: {}\n", demo_path, e),
+                );
+                return false;
+            }
+        };
+
+        // end the current game first so its commands can be registered again
+        self.state.replace(ProgramState::Title);
 
         cl.register_cmds(&mut self.cmds.borrow_mut());
 
-        self.state.replace(ProgramState::Game(
-            Game::new(
-                self.cvars.clone(),
-                self.cmds.clone(),
-                self.ui_renderer.clone(),
-                self.input.clone(),
-                cl,
-            )
-            .unwrap(),
-        ));
+        match Game::new(
+            self.cvars.clone(),
+            self.cmds.clone(),
+            self.ui_renderer.clone(),
+            self.input.clone(),
+            cl,
+        ) {
+            Ok(game) => {
+                self.state.replace(ProgramState::Game(game));
+                true
+            }
+
+            Err(e) => {
+                self.console.borrow().print(
+                    PrintLevel::Game,
+                    format!("Couldn't play {}: {}\n", demo_path, e),
+                );
+                false
+            }
+        }
+    }
+
+    /// Plays the demo at `demo_path`, capturing every frame to `output`.
+    ///
+    /// The demo is rendered at the fixed rate set by `capture_fps` rather than in real time, and
+    /// the program exits once the demo has finished.
+    fn capture_demo<S>(&mut self, demo_path: S, output: Option<&str>)
+    where
+        S: AsRef<str>,
+    {
+        let capture = match VideoCapture::new(&self.cvars.borrow(), demo_path.as_ref(), output) {
+            Ok(c) => c,
+            Err(e) => {
+                log::error!("Couldn't start capture: {}", e);
+                return;
+            }
+        };
+
+        if !self.play_demo(demo_path) {
+            return;
+        }
+
+        if let ProgramState::Game(ref mut game) = *self.state.borrow_mut() {
+            game.start_video_capture(capture);
+        }
     }
 
     /// Builds a new swap chain with the specified present mode and the window's current dimensions.
@@ -335,37 +546,140 @@ impl Program for ClientProgram {
             sample_count = 2;
         }
 
+        let render_scale = self
+            .cvars
+            .borrow()
+            .get_value("r_scale")
+            .unwrap_or(1.0)
+            .max(MIN_RENDER_SCALE)
+            .min(1.0);
+
+        // TODO: warn user if gl_texturemode is invalid
+        let texture_mode = self
+            .cvars
+            .borrow()
+            .get("gl_texturemode")
+            .ok()
+            .and_then(TextureMode::from_name)
+            .unwrap_or_default();
+        let anisotropy = self
+            .cvars
+            .borrow()
+            .get_value("r_anisotropy")
+            .unwrap_or(1.0)
+            .max(0.0)
+            .min(16.0) as u8;
+
+        let bloom = self.cvars.borrow().get_value("r_bloom").unwrap_or(0.0) != 0.0;
+
         // recreate attachments and rebuild pipelines if necessary
-        self.gfx_state.borrow_mut().update(size, sample_count);
+        self.gfx_state
+            .borrow_mut()
+            .update(size, render_scale, sample_count, bloom);
+        self.gfx_state
+            .borrow_mut()
+            .set_texture_filter(texture_mode, anisotropy);
+        self.gfx_state.borrow().set_texture_compression(
+            self.cvars
+                .borrow()
+                .get_value("r_texturecompression")
+                .unwrap_or(0.0)
+                != 0.0,
+        );
+
+        self.input.borrow_mut().poll_gamepads();
+
+        // start any demo requested by `playdemo` since the last frame
+        let pending_demo = self.pending_demo.borrow_mut().take();
+        if let Some((demo_path, start)) = pending_demo {
+            if self.play_demo(demo_path) {
+                if let Some(start) = start {
+                    if let ProgramState::Game(ref mut game) = *self.state.borrow_mut() {
+                        game.seek_demo(start);
+                    }
+                }
+            }
+        }
+
+        let pending_capture = self.pending_capture.borrow_mut().take();
+        if let Some((demo_path, output)) = pending_capture {
+            self.capture_demo(demo_path, output.as_deref());
+        }
+
+        // a demo capture ends the program when the demo runs out
+        if let ProgramState::Game(ref mut game) = *self.state.borrow_mut() {
+            if game.demo_finished() {
+                if let Some(capture) = game.stop_video_capture() {
+                    if let Err(e) = capture.finish() {
+                        log::error!("Couldn't finish capture: {}", e);
+                    }
+                    self.exit_requested.set(true);
+                    return;
+                }
+            }
+        }
 
         match *self.state.borrow_mut() {
             ProgramState::Title => unimplemented!(),
 
             ProgramState::Game(ref mut game) => {
+                // captured frames are a fixed length regardless of how long they take to render
+                let frame_duration = match game.video_capture() {
+                    Some(capture) => engine::duration_from_f32(capture.frame_duration()),
+                    None => frame_duration,
+                };
+
                 game.frame(&self.gfx_state.borrow(), frame_duration);
             }
         }
 
-        match self.input.borrow().current_focus() {
-            InputFocus::Game => {
-                self.window.set_cursor_grab(true).unwrap();
-                self.window.set_cursor_visible(false);
-            }
-
-            _ => {
-                self.window.set_cursor_grab(false).unwrap();
-                self.window.set_cursor_visible(true);
+        // only hold on to the cursor while playing in the foreground, so that it's free to use in
+        // other windows after alt-tabbing away
+        let grab = {
+            let input = self.input.borrow();
+            input.window_focused() && input.current_focus() == InputFocus::Game
+        };
+        if grab != self.cursor_grabbed.get() {
+            if let Err(e) = self.window.set_cursor_grab(grab) {
+                log::warn!("Couldn't set cursor grab: {}", e);
             }
+            self.window.set_cursor_visible(!grab);
+            self.cursor_grabbed.set(grab);
         }
 
         // run console commands
         self.console.borrow().execute();
 
+        // commands from the command line follow everything quake.rc runs, so that they override
+        // config.cfg and autoexec.cfg
+        if !self.startup_commands.borrow().is_empty() && self.console.borrow().idle() {
+            for command in self.startup_commands.replace(Vec::new()) {
+                self.console.borrow().stuff_text(command);
+            }
+        }
+
         self.render();
     }
 
     fn shutdown(&mut self) {
-        // TODO: do cleanup things here
+        if let Err(e) = self
+            .console
+            .borrow()
+            .save_history(self.game_dir.join(HISTORY_FILE))
+        {
+            log::warn!("Couldn't save console history: {}", e);
+        }
+
+        WindowGeometry::store(&self.window, &self.cvars.borrow());
+        if let Err(e) = self
+            .console
+            .borrow()
+            .save_config(self.game_dir.join(CONFIG_FILE))
+        {
+            log::warn!("Couldn't save config: {}", e);
+        }
+
+        // TODO: do other cleanup things here
     }
 
     fn cvars(&self) -> Ref<CvarRegistry> {
@@ -375,23 +689,265 @@ impl Program for ClientProgram {
     fn cvars_mut(&self) -> RefMut<CvarRegistry> {
         self.cvars.borrow_mut()
     }
+
+    fn timescale(&self) -> f32 {
+        match *self.state.borrow() {
+            ProgramState::Title => 1.0,
+            ProgramState::Game(ref game) => game.timescale(),
+        }
+    }
+
+    fn focused(&self) -> bool {
+        self.input.borrow().window_focused()
+    }
+
+    fn exit_requested(&self) -> bool {
+        self.exit_requested.get()
+    }
+}
+
+fn cmd_playdemo(
+    pending_demo: Rc<RefCell<Option<(String, Option<Duration>)>>>,
+) -> Box<dyn Fn(&[&str])> {
+    Box::new(move |args| {
+        let start = match args.len() {
+            1 => None,
+            2 => match args[1].parse::<f32>() {
+                Ok(s) if s >= 0.0 => Some(engine::duration_from_f32(s)),
+                _ => {
+                    println!("playdemo: start time must be a non-negative number of seconds");
+                    return;
+                }
+            },
+            _ => {
+                println!("playdemo <demoname> [seconds]: play back a recorded demo");
+                return;
+            }
+        };
+
+        let mut demo_path = args[0].to_owned();
+        if !demo_path.ends_with(".dem") {
+            demo_path.push_str(".dem");
+        }
+
+        pending_demo.replace(Some((demo_path, start)));
+    })
+}
+
+fn cmd_deminfo(vfs: Rc<Vfs>) -> Box<dyn Fn(&[&str])> {
+    Box::new(move |args| {
+        if args.len() != 1 {
+            println!("deminfo <demoname>: show information about a recorded demo");
+            return;
+        }
+
+        let mut demo_path = args[0].to_owned();
+        if !demo_path.ends_with(".dem") {
+            demo_path.push_str(".dem");
+        }
+
+        let info = match vfs.open(&demo_path) {
+            Ok(mut file) => match demo::read_info(&mut file) {
+                Ok(info) => info,
+                Err(e) => {
+                    println!("Couldn't read {}: {}", demo_path, e);
+                    return;
+                }
+            },
+            Err(e) => {
+                println!("Couldn't open {}: {}", demo_path, e);
+                return;
+            }
+        };
+
+        let secs = info.duration().num_seconds();
+        println!("{}", demo_path);
+        println!(
+            "map:      {} ({})",
+            info.map().unwrap_or("unknown"),
+            info.level_name().unwrap_or("")
+        );
+        match info.protocol() {
+            Some(p) => println!("protocol: {}", p),
+            None => println!("protocol: unknown"),
+        }
+        println!("duration: {}:{:02}", secs / 60, secs % 60);
+        println!("player:   {}", info.player_name().unwrap_or("unknown"));
+
+        if let Some(metadata) = info.metadata() {
+            for (key, value) in metadata.iter().filter(|(k, _)| *k != "player") {
+                println!("{}: {}", key, value);
+            }
+        }
+    })
 }
 
+fn cmd_which(vfs: Rc<Vfs>) -> Box<dyn Fn(&[&str])> {
+    Box::new(move |args| {
+        if args.len() != 1 {
+            println!("which <path>: show where a game file is loaded from");
+            return;
+        }
+
+        let sources = vfs.sources(args[0]);
+        let mut iter = sources.iter();
+        match iter.next() {
+            Some(source) => println!("{} is loaded from {}", args[0], source),
+            None => {
+                println!("{} wasn't found", args[0]);
+                return;
+            }
+        }
+
+        for source in iter {
+            println!("  overrides copy in {}", source);
+        }
+    })
+}
+
+fn cmd_preset(vfs: Rc<Vfs>, cvars: Rc<RefCell<CvarRegistry>>) -> Box<dyn Fn(&[&str])> {
+    Box::new(move |args| match args.len() {
+        0 => {
+            for name in preset::names(&vfs) {
+                match preset::find(&vfs, &name) {
+                    Ok(Some(p)) => println!("    {}: {}", name, p.description()),
+                    _ => println!("    {}", name),
+                }
+            }
+        }
+
+        1 => match preset::find(&vfs, args[0]) {
+            Ok(Some(p)) => match p.apply(&cvars.borrow()) {
+                Ok(()) => println!("Applied preset {}", p.name()),
+                Err(e) => println!("Couldn't apply preset {}: {}", p.name(), e),
+            },
+            Ok(None) => println!("No preset named {}", args[0]),
+            Err(e) => println!("Couldn't load preset {}: {}", args[0], e),
+        },
+
+        _ => println!("preset [name]: list presets, or apply a bundle of settings"),
+    })
+}
+
+fn cmd_capturedemo(
+    pending_capture: Rc<RefCell<Option<(String, Option<String>)>>>,
+) -> Box<dyn Fn(&[&str])> {
+    Box::new(move |args| {
+        if args.is_empty() || args.len() > 2 {
+            println!("capturedemo <demoname> [output]: render a demo to images or a video file");
+            return;
+        }
+
+        let mut demo_path = args[0].to_owned();
+        if !demo_path.ends_with(".dem") {
+            demo_path.push_str(".dem");
+        }
+
+        pending_capture.replace(Some((demo_path, args.get(1).map(|o| o.to_string()))));
+    })
+}
+
+// the order in which the command line takes effect, shown after the options in --help
+const STARTUP_ORDER: &str = "\
+STARTUP:
+    1. id1 in the base directory is loaded, followed by the -game directory if one is given.
+    2. The settings saved in vars.rc in the game directory are restored.
+    3. -width, -height, -window and -fullscreen override the saved window settings.
+    4. With -condebug, console output is appended to qconsole.log in the game directory.
+    5. quake.rc is executed, which runs config.cfg and autoexec.cfg.
+    6. +commands are executed in the order given.
+    7. --connect or --demo starts the game.";
+
+/// Richter client.
+///
+/// Options may be given with a single dash, as in Quake: `-game hipnotic`. Any argument starting
+/// with `+` is a console command, which takes every argument up to the next option or command:
+/// `+map e1m1 +skill 2`.
 #[derive(StructOpt, Debug)]
+#[structopt(after_help = STARTUP_ORDER)]
 struct Opt {
+    /// Directory containing id1 and any mods
+    #[structopt(long, parse(from_os_str), default_value = ".")]
+    basedir: PathBuf,
+
+    /// Mod directory in the base directory, whose files replace those in id1
+    #[structopt(long)]
+    game: Option<String>,
+
+    /// Unsupported: the client can't run a dedicated server, use richter-dedicated instead
+    #[structopt(long)]
+    dedicated: Option<Option<u8>>,
+
+    /// Run in a window
+    #[structopt(long, conflicts_with = "fullscreen")]
+    window: bool,
+
+    /// Run fullscreen
+    #[structopt(long)]
+    fullscreen: bool,
+
+    /// Width of the window in pixels
+    #[structopt(long)]
+    width: Option<u32>,
+
+    /// Height of the window in pixels
+    #[structopt(long)]
+    height: Option<u32>,
+
+    /// Append console output to qconsole.log in the game directory
+    #[structopt(long)]
+    condebug: bool,
+
+    /// Record a wgpu API trace to ./trace
     #[structopt(long)]
     trace: bool,
 
     #[structopt(long)]
     connect: Option<SocketAddr>,
 
+    /// Connect using the QuakeWorld protocol
+    #[structopt(long)]
+    qw: bool,
+
     #[structopt(long)]
     demo: Option<String>,
 }
 
+impl Opt {
+    // the directory settings and logs are written to
+    fn game_dir(&self) -> PathBuf {
+        self.basedir
+            .join(self.game.as_deref().unwrap_or(common::DEFAULT_BASEDIR))
+    }
+
+    // the cvars set by the video options. these are archived, so they are kept for later runs.
+    fn video_cvars(&self) -> Vec<(&'static str, String)> {
+        let mut cvars = Vec::new();
+        if self.window {
+            cvars.push(("vid_fullscreen", "0".to_owned()));
+        }
+        if self.fullscreen {
+            cvars.push(("vid_fullscreen", "1".to_owned()));
+        }
+        if let Some(width) = self.width {
+            cvars.push(("vid_width", width.to_string()));
+        }
+        if let Some(height) = self.height {
+            cvars.push(("vid_height", height.to_string()));
+        }
+        cvars
+    }
+}
+
 fn main() {
     env_logger::init();
-    let opt = Opt::from_args();
+    let command_line = args::split(std::env::args());
+    let opt = Opt::from_iter(command_line.options);
+
+    if opt.dedicated.is_some() {
+        eprintln!("The client can't run a dedicated server. Use richter-dedicated instead.");
+        std::process::exit(1);
+    }
 
     let audio_device = rodio::default_output_device().unwrap();
 
@@ -419,10 +975,14 @@ fn main() {
         }
     };
 
-    let mut client_program =
-        futures::executor::block_on(ClientProgram::new(window, audio_device, opt.trace));
+    let mut client_program = futures::executor::block_on(ClientProgram::new(
+        window,
+        audio_device,
+        &opt,
+        command_line.commands,
+    ));
     if let Some(ref server) = opt.connect {
-        client_program.connect(server);
+        client_program.connect(server, opt.qw);
     } else if let Some(ref demo) = opt.demo {
         client_program.play_demo(demo);
     }
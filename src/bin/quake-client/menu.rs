@@ -18,15 +18,29 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use richter::client::menu::{Menu, MenuBodyView, MenuBuilder, MenuView};
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    rc::Rc,
+};
+
+use richter::{
+    client::{
+        demo::DemoServer,
+        menu::{EnumItem, Menu, MenuBodyView, MenuBuilder, MenuView},
+    },
+    common::{bsp, console::Console, parse, vfs::Vfs},
+    server::save,
+};
 
 use failure::Error;
+use log::warn;
 
-pub fn build_main_menu() -> Result<Menu, Error> {
+pub fn build_main_menu(vfs: &Vfs, console: Rc<RefCell<Console>>) -> Result<Menu, Error> {
     Ok(MenuBuilder::new()
-        .add_submenu("Single Player", build_menu_sp()?)
+        .add_submenu("Single Player", build_menu_sp(vfs, console.clone())?)
         .add_submenu("Multiplayer", build_menu_mp()?)
-        .add_submenu("Options", build_menu_options()?)
+        .add_submenu("Options", build_menu_options(vfs, console)?)
         .add_action("Help/Ordering", Box::new(|| ()))
         .add_action("Quit", Box::new(|| ()))
         .build(MenuView {
@@ -38,10 +52,10 @@ pub fn build_main_menu() -> Result<Menu, Error> {
         }))
 }
 
-fn build_menu_sp() -> Result<Menu, Error> {
+fn build_menu_sp(vfs: &Vfs, console: Rc<RefCell<Console>>) -> Result<Menu, Error> {
     Ok(MenuBuilder::new()
-        .add_action("New Game", Box::new(|| ()))
-        // .add_submenu("Load", unimplemented!())
+        .add_submenu("New Game", build_menu_levels(vfs, console.clone())?)
+        .add_submenu("Load", build_menu_load(console)?)
         // .add_submenu("Save", unimplemented!())
         .build(MenuView {
             draw_plaque: true,
@@ -100,10 +114,11 @@ fn build_menu_mp_join_tcp() -> Result<Menu, Error> {
         }))
 }
 
-fn build_menu_options() -> Result<Menu, Error> {
+fn build_menu_options(vfs: &Vfs, console: Rc<RefCell<Console>>) -> Result<Menu, Error> {
     Ok(MenuBuilder::new()
         // .add_submenu("Customize controls", unimplemented!())
         .add_action("Go to console", Box::new(|| ()))
+        .add_submenu("Demos", build_menu_demos(vfs, console)?)
         .add_action("Reset to defaults", Box::new(|| ()))
         .add_slider("Render scale", 0.25, 1.0, 2, 0, Box::new(|_| ()))?
         .add_slider("Screen Size", 0.0, 1.0, 10, 9, Box::new(|_| ()))?
@@ -122,3 +137,201 @@ fn build_menu_options() -> Result<Menu, Error> {
             body: MenuBodyView::Dynamic,
         }))
 }
+
+fn build_menu_demos(vfs: &Vfs, console: Rc<RefCell<Console>>) -> Result<Menu, Error> {
+    let mut builder = MenuBuilder::new();
+    let mut demo_count = 0;
+
+    for path in vfs.list("").into_iter().filter(|p| p.ends_with(".dem")) {
+        // pre-scan the demo for its map and length
+        let info = match vfs.open(&path) {
+            Ok(mut file) => match DemoServer::new(&mut file) {
+                Ok(demo) => demo.info(),
+                Err(e) => {
+                    warn!("Skipping invalid demo {}: {}", path, e);
+                    continue;
+                }
+            },
+            Err(_) => continue,
+        };
+
+        let secs = info.duration().num_seconds();
+        let name = format!(
+            "{} {} {}:{:02}",
+            path.trim_end_matches(".dem"),
+            info.map().unwrap_or("?"),
+            secs / 60,
+            secs % 60,
+        );
+
+        let console = console.clone();
+        builder = builder.add_action(
+            name,
+            Box::new(move || {
+                console.borrow().stuff_text(format!("playdemo {}\n", path));
+            }),
+        );
+        demo_count += 1;
+    }
+
+    // menus can't be empty
+    if demo_count == 0 {
+        builder = builder.add_action("No demos found", Box::new(|| ()));
+    }
+
+    Ok(builder.build(MenuView {
+        draw_plaque: true,
+        title_path: "gfx/p_load.lmp".to_string(),
+        body: MenuBodyView::Dynamic,
+    }))
+}
+
+// saves are kept by the server, so the slots it writes are offered rather than files
+fn build_menu_load(console: Rc<RefCell<Console>>) -> Result<Menu, Error> {
+    let slots = std::iter::once((save::QUICKSAVE_SLOT.to_owned(), "Quicksave".to_owned())).chain(
+        (0..save::AUTOSAVE_SLOTS)
+            .map(|i| (save::autosave_slot_name(i), format!("Autosave {}", i + 1))),
+    );
+
+    let mut builder = MenuBuilder::new();
+    for (slot, label) in slots {
+        let console = console.clone();
+        builder = builder.add_action(
+            label,
+            Box::new(move || {
+                console.borrow().stuff_text(format!("load {}\n", slot));
+            }),
+        );
+    }
+
+    Ok(builder.build(MenuView {
+        draw_plaque: true,
+        title_path: "gfx/p_load.lmp".to_string(),
+        body: MenuBodyView::Dynamic,
+    }))
+}
+
+const SKILL_NAMES: [&str; 4] = ["Easy", "Normal", "Hard", "Nightmare"];
+
+// groups are sorted by their index first so that the original episodes come before mods
+fn map_group(name: &str) -> (usize, &'static str) {
+    let episode = name
+        .strip_prefix('e')
+        .and_then(|rest| rest.split('m').next())
+        .and_then(|ep| ep.parse::<usize>().ok());
+
+    match (name, episode) {
+        ("start", _) | ("end", _) => (0, "Quake"),
+        (_, Some(1)) => (1, "Episode 1"),
+        (_, Some(2)) => (2, "Episode 2"),
+        (_, Some(3)) => (3, "Episode 3"),
+        (_, Some(4)) => (4, "Episode 4"),
+        _ if name.starts_with("dm") => (5, "Deathmatch"),
+        _ if name.starts_with("hip") => (6, "Scourge of Armagon"),
+        _ if name.starts_with("r1m") || name.starts_with("r2m") => (7, "Dissolution of Eternity"),
+        _ => (8, "Other"),
+    }
+}
+
+/// Reads the worldspawn message (the level title) of the map at `path`.
+fn map_title(vfs: &Vfs, path: &str) -> Option<String> {
+    let ent_string = match vfs.open(path).map(bsp::load_entities) {
+        Ok(Ok(s)) => s,
+        Ok(Err(e)) => {
+            warn!("Couldn't read entities from {}: {}", path, e);
+            return None;
+        }
+        Err(_) => return None,
+    };
+
+    let (_, entities) = parse::entities(&ent_string).ok()?;
+    let worldspawn = entities
+        .iter()
+        .find(|ent| ent.get("classname") == Some(&"worldspawn"))?;
+
+    // some titles contain line breaks, only the first line is shown
+    worldspawn
+        .get("message")
+        .and_then(|msg| msg.split("\\n").next())
+        .map(|msg| msg.trim().to_owned())
+        .filter(|msg| !msg.is_empty())
+}
+
+fn build_menu_levels(vfs: &Vfs, console: Rc<RefCell<Console>>) -> Result<Menu, Error> {
+    let mut groups: BTreeMap<(usize, &'static str), Vec<(String, Option<String>)>> =
+        BTreeMap::new();
+
+    for path in vfs
+        .list("maps")
+        .into_iter()
+        .filter(|p| p.to_lowercase().ends_with(".bsp"))
+    {
+        let name = path["maps/".len()..path.len() - ".bsp".len()].to_owned();
+
+        // skip brush models used by the progs (e.g. maps/b_bh10.bsp)
+        if name.starts_with("b_") {
+            continue;
+        }
+
+        let title = map_title(vfs, &path);
+        groups
+            .entry(map_group(&name.to_lowercase()))
+            .or_default()
+            .push((name, title));
+    }
+
+    let skill = Rc::new(Cell::new(1));
+    let skill_items = SKILL_NAMES
+        .iter()
+        .enumerate()
+        .map(|(id, name)| {
+            let skill = skill.clone();
+            EnumItem::new(*name, Box::new(move || skill.set(id)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut builder = MenuBuilder::new().add_enum("Skill", skill_items, 1)?;
+
+    for ((_, group), maps) in groups {
+        builder = builder.add_submenu(group, build_menu_level_group(maps, &skill, &console));
+    }
+
+    // menus can't be empty, but the skill selector ensures this one never is
+    Ok(builder.build(MenuView {
+        draw_plaque: true,
+        title_path: "gfx/ttl_sgl.lmp".to_string(),
+        body: MenuBodyView::Dynamic,
+    }))
+}
+
+fn build_menu_level_group(
+    maps: Vec<(String, Option<String>)>,
+    skill: &Rc<Cell<usize>>,
+    console: &Rc<RefCell<Console>>,
+) -> Menu {
+    let mut builder = MenuBuilder::new();
+
+    for (name, title) in maps {
+        let label = match title {
+            Some(t) => format!("{} {}", name, t),
+            None => name.clone(),
+        };
+
+        let skill = skill.clone();
+        let console = console.clone();
+        builder = builder.add_action(
+            label,
+            Box::new(move || {
+                console
+                    .borrow()
+                    .stuff_text(format!("skill {}\nmap {}\n", skill.get(), name));
+            }),
+        );
+    }
+
+    builder.build(MenuView {
+        draw_plaque: true,
+        title_path: "gfx/ttl_sgl.lmp".to_string(),
+        body: MenuBodyView::Dynamic,
+    })
+}
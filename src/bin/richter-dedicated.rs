@@ -0,0 +1,1035 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Headless dedicated server.
+//!
+//! This runs the host frame loop, console, connection listener and game without creating a window
+//! or initializing graphics or audio. A level is started with the `map` command, from standard
+//! input or `server.cfg`. Console commands are read from standard input and console output is
+//! written to standard output.
+//!
+//! Every option can also be given in an environment variable, and nothing is written to the game
+//! directory, so the server can run in a container with the game data mounted read-only. If a data
+//! directory is given, archived cvars are saved there when the server quits or receives SIGTERM,
+//! and saved games are kept there.
+
+use std::{
+    cell::{Cell, Ref, RefCell, RefMut},
+    fs::File,
+    io::{self, BufRead, ErrorKind, Read, Write},
+    net::{SocketAddr, UdpSocket},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, TryRecvError},
+        Arc,
+    },
+    thread,
+    time::Instant,
+};
+
+use chrono::Duration;
+use log::{debug, info, warn};
+use richter::{
+    common::{
+        console::{CmdRegistry, Console, CvarRegistry},
+        engine,
+        host::{Host, Program},
+        net::{
+            self,
+            connect::{
+                ConnectListener, ListenerPacket, Request, Response, ResponseAccept,
+                ResponseChallenge, ResponsePlayerInfo, ResponseReject, ResponseRuleInfo,
+                ResponseServerInfo,
+            },
+            NetError, QSocket,
+        },
+        vfs::Vfs,
+    },
+    server::{
+        self,
+        challenge::{ConnectGuard, ConnectVerdict},
+        level::Level,
+        master::{self, Heartbeat, ServerDetails},
+        rcon::{self, RconLimiter},
+        save::{self, SaveError, SaveGame},
+        ServerStatics,
+    },
+};
+use structopt::StructOpt;
+use winit::{
+    event::Event,
+    event_loop::{ControlFlow, EventLoopWindowTarget},
+};
+
+// archived cvars are saved here in the data directory
+const CONFIG_FILE: &str = "dedicated.cfg";
+
+// a level change requested from the console, run once the console commands have finished
+enum LevelCmd {
+    Map(String),
+    ChangeLevel(String),
+    Load(String),
+}
+
+struct DedicatedProgram {
+    vfs: Rc<Vfs>,
+    cvars: Rc<RefCell<CvarRegistry>>,
+    cmds: Rc<RefCell<CmdRegistry>>,
+    console: Rc<RefCell<Console>>,
+    // archived cvars and saved games are only kept when there is a data directory to keep them in
+    data_dir: Option<PathBuf>,
+    config_path: Option<PathBuf>,
+    json_logs: bool,
+
+    listener: ConnectListener,
+    connect_guard: RefCell<ConnectGuard>,
+    rcon_limiter: RefCell<RconLimiter>,
+    heartbeat: RefCell<Heartbeat>,
+    max_clients: u8,
+
+    level: Rc<RefCell<Option<Level>>>,
+    level_cmd: Rc<RefCell<Option<LevelCmd>>>,
+
+    // lines read from standard input since the last frame
+    stdin: Receiver<String>,
+
+    // number of console output lines already written to standard output
+    printed_lines: Cell<usize>,
+
+    quit: Rc<Cell<bool>>,
+}
+
+impl DedicatedProgram {
+    fn new(opt: &Opt) -> DedicatedProgram {
+        let data_dir = opt.datadir.as_ref().unwrap_or(&opt.basedir);
+        let mut vfs = Vfs::with_base_dir(&opt.basedir).unwrap();
+
+        // configs in the data directory take precedence over the game directory
+        if data_dir != &opt.basedir {
+            vfs.add_directory(data_dir).unwrap();
+        }
+        let vfs = Rc::new(vfs);
+
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new()));
+        server::register_cvars(&cvars.borrow()).unwrap();
+
+        let cmds = Rc::new(RefCell::new(CmdRegistry::new()));
+        let console = Rc::new(RefCell::new(Console::new(cmds.clone(), cvars.clone())));
+
+        let quit = Rc::new(Cell::new(false));
+        let cmd_quit = quit.clone();
+        cmds.borrow_mut()
+            .insert(
+                "quit",
+                "shut down the server",
+                Box::new(move |_| cmd_quit.set(true)),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert(
+                "exec",
+                "exec (filename): execute a script file",
+                cmd_exec(vfs.clone(), console.clone()),
+            )
+            .unwrap();
+
+        let level = Rc::new(RefCell::new(None));
+        let level_cmd = Rc::new(RefCell::new(None));
+        cmds.borrow_mut()
+            .insert(
+                "map",
+                "map (mapname): start a new game on the given map",
+                cmd_map(level_cmd.clone()),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert(
+                "changelevel",
+                "changelevel (mapname): move the players to the given map",
+                cmd_changelevel(level.clone(), level_cmd.clone()),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert(
+                "save",
+                "save (savename): save a single-player game",
+                cmd_save(level.clone(), opt.datadir.clone()),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert(
+                "load",
+                "load (savename): load a saved game",
+                cmd_load(level_cmd.clone()),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert(
+                "status",
+                "print the map and the connected players",
+                cmd_status(level.clone(), cvars.clone()),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert(
+                "kick",
+                "kick (name | # number): disconnect a player",
+                cmd_kick(level.clone(), cvars.clone()),
+            )
+            .unwrap();
+
+        let listener = ConnectListener::bind(("0.0.0.0", opt.port)).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        info!("Listening on {}", listener.local_addr().unwrap());
+
+        let config_path = opt.datadir.as_ref().map(|dir| dir.join(CONFIG_FILE));
+        if let Some(ref path) = config_path {
+            if let Err(e) = console.borrow().load_config(path) {
+                if e.kind() != ErrorKind::NotFound {
+                    warn!("Couldn't load {}: {}", path.display(), e);
+                }
+            }
+        }
+
+        if vfs.open("server.cfg").is_ok() {
+            console.borrow().stuff_text("exec server.cfg\n");
+        }
+
+        // rcon_password isn't archived, so this is never written to the data directory
+        if let Some(ref password) = opt.rcon_password {
+            cvars
+                .borrow()
+                .set("rcon_password", password.as_str())
+                .unwrap();
+        }
+
+        DedicatedProgram {
+            vfs,
+            cvars,
+            cmds,
+            console,
+            data_dir: opt.datadir.clone(),
+            config_path,
+            json_logs: opt.log_format == LogFormat::Json,
+            listener,
+            connect_guard: RefCell::new(ConnectGuard::new()),
+            rcon_limiter: RefCell::new(RconLimiter::new()),
+            heartbeat: RefCell::new(Heartbeat::new()),
+            max_clients: opt.maxplayers.min(net::MAX_CLIENTS as u8),
+            level,
+            level_cmd,
+            stdin: spawn_stdin_reader(),
+            printed_lines: Cell::new(0),
+            quit,
+        }
+    }
+
+    fn read_stdin(&self) {
+        loop {
+            match self.stdin.try_recv() {
+                Ok(line) => self.console.borrow().stuff_text(line),
+
+                // standard input may be closed when running as a service
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+            }
+        }
+    }
+
+    /// Writes any new console output to standard output.
+    fn print_console_output(&self) {
+        let console = self.console.borrow();
+        let output = console.output();
+        let total = output.lines().count();
+
+        // the buffer shrinks when it's cleared or trimmed, and what's left is all new
+        if total < self.printed_lines.get() {
+            self.printed_lines.set(0);
+        }
+
+        // console output is stored newest first
+        let mut lines: Vec<String> = output
+            .lines()
+            .take(total.saturating_sub(self.printed_lines.get()))
+            .map(|line| line.iter().collect())
+            .collect();
+        lines.reverse();
+
+        for line in lines {
+            if self.json_logs {
+                println!("{}", json_log_line("INFO", "console", &line));
+            } else {
+                println!("{}", line);
+            }
+        }
+
+        self.printed_lines.set(total);
+    }
+
+    // starts the level requested by `map` or `changelevel`, if any
+    fn run_level_cmd(&self) {
+        let cmd = match self.level_cmd.borrow_mut().take() {
+            Some(c) => c,
+            None => return,
+        };
+
+        // check before shutting anything down, so a typo doesn't end the game
+        let (map_name, save) = match cmd {
+            LevelCmd::Map(ref name) | LevelCmd::ChangeLevel(ref name) => (name.clone(), None),
+            LevelCmd::Load(ref slot) => match read_save(self.data_dir.as_deref(), slot) {
+                Ok(save) => (save.map.clone(), Some(save)),
+                Err(e) => {
+                    println!("Couldn't load {}: {}", slot, e);
+                    return;
+                }
+            },
+        };
+
+        let map_path = format!("maps/{}.bsp", map_name);
+        if let Err(e) = self.vfs.open(&map_path) {
+            println!("Can't load {}: {}", map_path, e);
+            return;
+        }
+
+        let mut cvars = self.cvars.borrow_mut();
+        let old_level = self.level.borrow_mut().take();
+        let result = match (cmd, save, old_level) {
+            (LevelCmd::ChangeLevel(_), _, Some(level)) => level.change_level(&mut cvars, &map_name),
+
+            (LevelCmd::Load(_), Some(save), Some(level)) => level.load_game(&mut cvars, &save),
+
+            (_, save, old_level) => {
+                if let Some(level) = old_level {
+                    level.shutdown(&mut cvars);
+                }
+
+                let statics = ServerStatics::new(self.max_clients as usize);
+                match save {
+                    Some(save) => Level::restore_game(self.vfs.clone(), &mut cvars, &save, statics),
+                    None => Level::spawn(self.vfs.clone(), &mut cvars, &map_name, statics),
+                }
+            }
+        };
+
+        match result {
+            Ok(level) => {
+                server::register_edict_cmds(
+                    &mut self.cmds.borrow_mut(),
+                    level.world(),
+                    level.functions(),
+                );
+                *self.level.borrow_mut() = Some(level);
+            }
+
+            Err(e) => println!("Couldn't spawn server {}: {}", map_name, e),
+        }
+    }
+
+    fn run_level(&self, frame_duration: Duration) {
+        let mut level_slot = self.level.borrow_mut();
+        let level = match level_slot.as_mut() {
+            Some(l) => l,
+            None => return,
+        };
+
+        let result = level.frame(&mut self.cvars.borrow_mut(), frame_duration);
+
+        if let Some(save) = level.autosave(&self.cvars.borrow()) {
+            let slot = next_autosave_slot_name(self.data_dir.as_deref());
+            if let Err(e) = write_save(self.data_dir.as_deref(), &slot, &save) {
+                println!("Couldn't autosave: {}", e);
+            }
+        }
+
+        // commands from the progs, such as a level change at the end of a map
+        let local_cmds = level.take_local_cmds();
+        if !local_cmds.is_empty() {
+            self.console.borrow().stuff_text(local_cmds);
+        }
+
+        // the map's script has run once the console has caught up
+        if self.console.borrow().idle() {
+            level.settle_map_config(&self.cvars.borrow());
+        }
+
+        // the game can't go on after an error in the progs
+        if let Err(e) = result {
+            println!("Server error: {}", e);
+            if let Some(level) = level_slot.take() {
+                level.shutdown(&mut self.cvars.borrow_mut());
+            }
+        }
+    }
+
+    // the name and player count reported to server browsers
+    fn level_summary(&self) -> (String, u8) {
+        match *self.level.borrow() {
+            Some(ref level) => (level.map_name().to_owned(), level.client_count() as u8),
+            None => (String::new(), 0),
+        }
+    }
+
+    fn check_new_connections(&self) {
+        loop {
+            let (packet, remote) = match self.listener.recv_packet() {
+                Ok(p) => p,
+                Err(NetError::Io(ref e)) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Invalid connection request: {}", e);
+                    continue;
+                }
+            };
+
+            let result = match packet {
+                ListenerPacket::Request(request) => self.handle_request(request, remote),
+                ListenerPacket::OutOfBand(data) => self.handle_out_of_band(&data, remote),
+            };
+
+            if let Err(e) = result {
+                warn!("Failed to respond to {}: {}", remote, e);
+            }
+        }
+    }
+
+    // answers queries from master servers and server browsers, and remote console commands
+    fn handle_out_of_band(&self, data: &[u8], remote: SocketAddr) -> Result<(), NetError> {
+        if let Some(request) = rcon::parse_rcon(data) {
+            return self.handle_rcon(request, remote);
+        }
+
+        let challenge = match master::parse_getinfo(data) {
+            Some(c) => c,
+            None => {
+                debug!("Ignoring connectionless packet from {}", remote);
+                return Ok(());
+            }
+        };
+
+        let (map, client_count) = self.level_summary();
+        let details = ServerDetails {
+            hostname: self.cvars.borrow().get("hostname").unwrap_or_default(),
+            map,
+            client_count,
+            client_max: self.max_clients,
+        };
+        self.listener
+            .send_out_of_band(&master::info_response(challenge, &details), remote)
+    }
+
+    fn handle_rcon(&self, request: rcon::RconRequest, remote: SocketAddr) -> Result<(), NetError> {
+        // the password is checked only for hosts under the rate limit, so it can't be brute-forced
+        if !self
+            .rcon_limiter
+            .borrow_mut()
+            .allow(remote.ip(), Instant::now())
+        {
+            debug!("Ignoring rcon from {}: too many attempts", remote);
+            return Ok(());
+        }
+
+        let password = self.cvars.borrow().get("rcon_password").unwrap_or_default();
+        if !rcon::check_password(&password, request.password) {
+            warn!("Bad rcon from {}", remote);
+            return self
+                .listener
+                .send_out_of_band(&rcon::print_response("Bad rcon password.\n"), remote);
+        }
+
+        info!("Rcon from {}: {}", remote, request.command);
+
+        // run the command now so that its output can be sent back
+        let console = self.console.borrow();
+        let before = console.output().lines().count();
+        console.stuff_text(format!("{}\n", request.command));
+        console.execute();
+
+        // console output is stored newest first
+        let output = console.output();
+        let mut lines: Vec<String> = output
+            .lines()
+            .take(output.lines().count().saturating_sub(before))
+            .map(|line| line.iter().collect())
+            .collect();
+        lines.reverse();
+
+        let mut text = lines.join("\n");
+        text.push('\n');
+        self.listener
+            .send_out_of_band(&rcon::print_response(&text), remote)
+    }
+
+    fn send_heartbeats(&self) {
+        let public = self.cvars.borrow().get_value("sv_public").unwrap_or(0.0) != 0.0;
+        if self.heartbeat.borrow_mut().due(public, Instant::now()) {
+            self.heartbeat_masters();
+        }
+    }
+
+    fn heartbeat_masters(&self) {
+        let masters = self.cvars.borrow().get("sv_masters").unwrap_or_default();
+        for master in master::resolve_masters(&masters) {
+            debug!("Sending heartbeat to {}", master);
+            if let Err(e) = self.listener.send_out_of_band(master::heartbeat(), master) {
+                warn!("Couldn't send heartbeat to {}: {}", master, e);
+            }
+        }
+    }
+
+    fn handle_request(&self, request: Request, remote: SocketAddr) -> Result<(), NetError> {
+        debug!("Request from {}: {:?}", remote, request);
+
+        match request {
+            Request::ServerInfo(info) => {
+                if info.game_name != net::GAME_NAME {
+                    return Ok(());
+                }
+
+                let (levelname, client_count) = self.level_summary();
+                let response = Response::ServerInfo(ResponseServerInfo {
+                    address: self.listener.local_addr()?.to_string(),
+                    hostname: self.cvars.borrow().get("hostname").unwrap_or_default(),
+                    levelname,
+                    client_count,
+                    client_max: self.max_clients,
+                    protocol_version: net::PROTOCOL_VERSION,
+                });
+                self.listener.send_response(response, remote)
+            }
+
+            Request::Connect(connect) => {
+                if connect.game_name != net::GAME_NAME {
+                    return Ok(());
+                }
+
+                if connect.proto_ver != net::connect::CONNECT_PROTOCOL_VERSION {
+                    return self.listener.send_response(
+                        Response::Reject(ResponseReject {
+                            message: "Incompatible version.\n".to_owned(),
+                        }),
+                        remote,
+                    );
+                }
+
+                let require_challenge =
+                    self.cvars.borrow().get_value("sv_challenge").unwrap_or(0.0) != 0.0;
+                match self.connect_guard.borrow_mut().check(
+                    remote,
+                    connect.challenge,
+                    require_challenge,
+                    Instant::now(),
+                ) {
+                    ConnectVerdict::Ignore => {
+                        debug!("Ignoring connection request from {}", remote);
+                        return Ok(());
+                    }
+
+                    ConnectVerdict::Challenge(challenge) => {
+                        return self.listener.send_response(
+                            Response::Challenge(ResponseChallenge { challenge }),
+                            remote,
+                        );
+                    }
+
+                    ConnectVerdict::Accept => (),
+                }
+
+                self.accept_client(remote)
+            }
+
+            Request::PlayerInfo(info) => {
+                let statuses = match *self.level.borrow() {
+                    Some(ref level) => level.client_statuses().unwrap_or_default(),
+                    None => Vec::new(),
+                };
+
+                // the original engine doesn't respond to requests for players that don't exist
+                let status = match statuses.into_iter().nth(info.player_id as usize) {
+                    Some(s) => s,
+                    None => return Ok(()),
+                };
+
+                self.listener.send_response(
+                    Response::PlayerInfo(ResponsePlayerInfo {
+                        player_id: info.player_id,
+                        player_name: status.name,
+                        colors: status.colors.bits() as i32,
+                        frags: status.frags,
+                        // connection times aren't tracked
+                        connect_duration: 0,
+                        address: status.address.to_string(),
+                    }),
+                    remote,
+                )
+            }
+
+            Request::RuleInfo(info) => {
+                let (cvar_name, cvar_val) =
+                    server::next_rule(&self.cvars.borrow(), &info.prev_cvar).unwrap_or_default();
+
+                self.listener.send_response(
+                    Response::RuleInfo(ResponseRuleInfo {
+                        cvar_name,
+                        cvar_val,
+                    }),
+                    remote,
+                )
+            }
+        }
+    }
+}
+
+impl DedicatedProgram {
+    // gives a client that passed the challenge a connection of its own and a slot in the game
+    fn accept_client(&self, remote: SocketAddr) -> Result<(), NetError> {
+        let mut level_slot = self.level.borrow_mut();
+        let reject = match *level_slot {
+            None => Some("Server is not running a level.\n"),
+            Some(ref level) if level.client_count() >= level.max_clients() => {
+                Some("Server is full.\n")
+            }
+            Some(_) => None,
+        };
+
+        if let Some(message) = reject {
+            return self.listener.send_response(
+                Response::Reject(ResponseReject {
+                    message: message.to_owned(),
+                }),
+                remote,
+            );
+        }
+
+        // the client talks to the game on this socket from now on
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        let port = socket.local_addr()?.port();
+        self.listener.send_response(
+            Response::Accept(ResponseAccept { port: port as i32 }),
+            remote,
+        )?;
+
+        let level = level_slot.as_mut().unwrap();
+        match level.connect_client(&mut self.cvars.borrow_mut(), QSocket::new(socket, remote)) {
+            Ok(Some(e_id)) => info!("Client {} connected from {}", e_id.0, remote),
+            Ok(None) => warn!("No free slot for {}", remote),
+            Err(e) => warn!("Couldn't connect {}: {}", remote, e),
+        }
+
+        Ok(())
+    }
+}
+
+impl Program for DedicatedProgram {
+    // the dedicated server has no window, so it never receives events
+    fn handle_event<T>(
+        &mut self,
+        _event: Event<T>,
+        _target: &EventLoopWindowTarget<T>,
+        _control_flow: &mut ControlFlow,
+    ) {
+    }
+
+    fn frame(&mut self, frame_duration: Duration) {
+        self.read_stdin();
+
+        // run console commands
+        self.console.borrow().execute();
+        self.run_level_cmd();
+
+        self.check_new_connections();
+        self.run_level(frame_duration);
+        self.print_console_output();
+
+        self.send_heartbeats();
+    }
+
+    fn shutdown(&mut self) {
+        info!("Shutting down");
+
+        if let Some(level) = self.level.borrow_mut().take() {
+            level.shutdown(&mut self.cvars.borrow_mut());
+        }
+
+        // the masters check on the server after a heartbeat, and drop it when it doesn't answer
+        if self.heartbeat.borrow().announced() {
+            self.heartbeat_masters();
+        }
+
+        // the data directory may be read-only too, in which case settings just aren't kept
+        if let Some(ref path) = self.config_path {
+            if let Err(e) = self.console.borrow().save_config(path) {
+                warn!("Couldn't save {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    fn cvars(&self) -> Ref<CvarRegistry> {
+        self.cvars.borrow()
+    }
+
+    fn cvars_mut(&self) -> RefMut<CvarRegistry> {
+        self.cvars.borrow_mut()
+    }
+
+    fn timescale(&self) -> f32 {
+        server::timescale(&self.cvars.borrow(), self.max_clients as usize)
+    }
+}
+
+fn cmd_exec(vfs: Rc<Vfs>, console: Rc<RefCell<Console>>) -> Box<dyn Fn(&[&str])> {
+    Box::new(move |args| {
+        if args.len() != 1 {
+            println!("exec (filename): execute a script file");
+            return;
+        }
+
+        let mut script_file = match vfs.open(args[0]) {
+            Ok(s) => s,
+            Err(e) => {
+                println!("Couldn't exec {}: {:?}", args[0], e);
+                return;
+            }
+        };
+
+        let mut script = String::new();
+        script_file.read_to_string(&mut script).unwrap();
+
+        console.borrow().stuff_text(script);
+    })
+}
+
+fn cmd_map(level_cmd: Rc<RefCell<Option<LevelCmd>>>) -> Box<dyn Fn(&[&str])> {
+    Box::new(move |args| match args {
+        [map_name] => *level_cmd.borrow_mut() = Some(LevelCmd::Map((*map_name).to_owned())),
+        _ => println!("map (mapname): start a new game on the given map"),
+    })
+}
+
+fn cmd_changelevel(
+    level: Rc<RefCell<Option<Level>>>,
+    level_cmd: Rc<RefCell<Option<LevelCmd>>>,
+) -> Box<dyn Fn(&[&str])> {
+    Box::new(move |args| {
+        let map_name = match args {
+            [map_name] => map_name,
+            _ => {
+                println!("changelevel (mapname): move the players to the given map");
+                return;
+            }
+        };
+
+        if level.borrow().is_none() {
+            println!("Only the server may changelevel");
+            return;
+        }
+
+        *level_cmd.borrow_mut() = Some(LevelCmd::ChangeLevel((*map_name).to_owned()));
+    })
+}
+
+fn cmd_save(level: Rc<RefCell<Option<Level>>>, data_dir: Option<PathBuf>) -> Box<dyn Fn(&[&str])> {
+    Box::new(move |args| {
+        let slot = match args {
+            [slot] => slot,
+            _ => {
+                println!("save (savename): save a single-player game");
+                return;
+            }
+        };
+
+        let save = match *level.borrow() {
+            Some(ref level) => level.save_game(),
+            None => {
+                println!("Not playing a local game.");
+                return;
+            }
+        };
+
+        let result = save
+            .map_err(|e| e.to_string())
+            .and_then(|save| write_save(data_dir.as_deref(), slot, &save));
+        match result {
+            Ok(path) => println!("Saved game to {}", path.display()),
+            Err(e) => println!("Couldn't save {}: {}", slot, e),
+        }
+    })
+}
+
+fn cmd_load(level_cmd: Rc<RefCell<Option<LevelCmd>>>) -> Box<dyn Fn(&[&str])> {
+    Box::new(move |args| match args {
+        [slot] => *level_cmd.borrow_mut() = Some(LevelCmd::Load((*slot).to_owned())),
+        _ => println!("load (savename): load a saved game"),
+    })
+}
+
+// saved games are kept in the data directory, since the game directory may be read-only
+fn save_path(data_dir: Option<&Path>, slot: &str) -> Result<PathBuf, String> {
+    let data_dir = data_dir.ok_or("saved games need a data directory (--datadir)")?;
+    let file_name = save::save_file_name(slot).map_err(|e| e.to_string())?;
+    Ok(data_dir.join(file_name))
+}
+
+fn write_save(data_dir: Option<&Path>, slot: &str, save: &SaveGame) -> Result<PathBuf, String> {
+    let path = save_path(data_dir, slot)?;
+    File::create(&path)
+        .map_err(SaveError::from)
+        .and_then(|mut file| save.write(&mut file))
+        .map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+// the autosave slot written longest ago, so the last few autosaves are kept
+fn next_autosave_slot_name(data_dir: Option<&Path>) -> String {
+    let modified: Vec<_> = (0..save::AUTOSAVE_SLOTS)
+        .map(|i| {
+            save_path(data_dir, &save::autosave_slot_name(i))
+                .ok()
+                .and_then(|path| path.metadata().ok())
+                .and_then(|meta| meta.modified().ok())
+        })
+        .collect();
+
+    save::autosave_slot_name(save::next_autosave_slot(&modified))
+}
+
+fn read_save(data_dir: Option<&Path>, slot: &str) -> Result<SaveGame, String> {
+    let path = save_path(data_dir, slot)?;
+    File::open(&path)
+        .map_err(SaveError::from)
+        .and_then(SaveGame::read)
+        .map_err(|e| e.to_string())
+}
+
+fn cmd_status(
+    level: Rc<RefCell<Option<Level>>>,
+    cvars: Rc<RefCell<CvarRegistry>>,
+) -> Box<dyn Fn(&[&str])> {
+    Box::new(move |_| {
+        let level = level.borrow();
+        let level = match *level {
+            Some(ref l) => l,
+            None => {
+                println!("No level is running");
+                return;
+            }
+        };
+
+        let statuses = match level.client_statuses() {
+            Ok(s) => s,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+
+        println!(
+            "host:    {}",
+            cvars.borrow().get("hostname").unwrap_or_default()
+        );
+        println!("map:     {}", level.map_name());
+        println!(
+            "players: {} active ({} max)",
+            statuses.len(),
+            level.max_clients()
+        );
+        for status in statuses {
+            println!(
+                "#{:<2} {:<16} {:3}  {}",
+                status.entity_id.0, status.name, status.frags, status.address
+            );
+        }
+    })
+}
+
+fn cmd_kick(
+    level: Rc<RefCell<Option<Level>>>,
+    cvars: Rc<RefCell<CvarRegistry>>,
+) -> Box<dyn Fn(&[&str])> {
+    Box::new(move |args| {
+        let mut level = level.borrow_mut();
+        let level = match *level {
+            Some(ref mut l) => l,
+            None => {
+                println!("No level is running");
+                return;
+            }
+        };
+
+        let statuses = level.client_statuses().unwrap_or_default();
+        let target = match args {
+            ["#", number] => number
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| statuses.iter().find(|s| s.entity_id.0 == n)),
+            [name] => statuses.iter().find(|s| s.name.eq_ignore_ascii_case(name)),
+            _ => {
+                println!("kick (name | # number): disconnect a player");
+                return;
+            }
+        };
+
+        let status = match target {
+            Some(s) => s,
+            None => {
+                println!("No such player");
+                return;
+            }
+        };
+
+        println!("Kicked {}", status.name);
+        if let Err(e) = level.drop_client(&mut cvars.borrow_mut(), status.entity_id, false) {
+            println!("Couldn't kick {}: {}", status.name, e);
+        }
+    })
+}
+
+// reads standard input on a separate thread so the frame loop never blocks on it
+fn spawn_stdin_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(l) => {
+                    if sender.send(l).is_err() {
+                        break;
+                    }
+                }
+
+                Err(_) => break,
+            }
+        }
+    });
+
+    receiver
+}
+
+// formats a log record as a single line of JSON
+fn json_log_line(level: &str, target: &str, message: &str) -> String {
+    serde_json::json!({
+        "time": chrono::Utc::now().to_rfc3339(),
+        "level": level,
+        "target": target,
+        "message": message,
+    })
+    .to_string()
+}
+
+fn init_logging(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            writeln!(
+                buf,
+                "{}",
+                json_log_line(
+                    &record.level().to_string(),
+                    record.target(),
+                    &record.args().to_string()
+                )
+            )
+        });
+    }
+
+    builder.init();
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format \"{}\"", s)),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+struct Opt {
+    #[structopt(long, env = "RICHTER_PORT", default_value = "26000")]
+    port: u16,
+
+    #[structopt(long, env = "RICHTER_MAXPLAYERS", default_value = "8")]
+    maxplayers: u8,
+
+    /// Game directory, which is only read from
+    #[structopt(
+        long,
+        env = "RICHTER_BASEDIR",
+        parse(from_os_str),
+        default_value = "id1"
+    )]
+    basedir: PathBuf,
+
+    /// Directory for configs and saved settings. Without one, settings are not saved
+    #[structopt(long, env = "RICHTER_DATADIR", parse(from_os_str))]
+    datadir: Option<PathBuf>,
+
+    /// Sets rcon_password, overriding any config
+    #[structopt(long, env = "RICHTER_RCON_PASSWORD", hide_env_values = true)]
+    rcon_password: Option<String>,
+
+    /// Format of log and console output: text or json
+    #[structopt(long, env = "RICHTER_LOG_FORMAT", default_value = "text")]
+    log_format: LogFormat,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    init_logging(opt.log_format);
+
+    let program = DedicatedProgram::new(&opt);
+    let cvars = program.cvars.clone();
+    let quit = program.quit.clone();
+
+    // docker stop and systemd send SIGTERM, after which the server shuts down like it does for
+    // the quit command
+    let terminated = Arc::new(AtomicBool::new(false));
+    let handler_terminated = terminated.clone();
+    if let Err(e) = ctrlc::set_handler(move || handler_terminated.store(true, Ordering::SeqCst)) {
+        warn!("Couldn't install signal handler: {}", e);
+    }
+
+    let mut host = Host::new(program);
+
+    while !quit.get() && !terminated.load(Ordering::SeqCst) {
+        host.frame();
+
+        // sleep until the next server tick
+        let ticrate = cvars
+            .borrow()
+            .get_value("sys_ticrate")
+            .unwrap_or(0.05)
+            .max(0.001);
+        thread::sleep(engine::duration_from_f32(ticrate).to_std().unwrap());
+    }
+
+    host.shutdown();
+}
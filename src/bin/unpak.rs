@@ -25,7 +25,7 @@ use std::{
     process::exit,
 };
 
-use richter::common::pak::Pak;
+use richter::common::{limits::LoadLimits, pak::Pak};
 
 use structopt::StructOpt;
 
@@ -58,7 +58,7 @@ fn main() {
         exit(0);
     }
 
-    let pak = match Pak::new(&opt.input_pak) {
+    let pak = match Pak::new(&opt.input_pak, &LoadLimits::default()) {
         Ok(p) => p,
         Err(why) => {
             println!("Couldn't open {:#?}: {}", &opt.input_pak, why);
@@ -0,0 +1,110 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Synchronization of client time with server time.
+//!
+//! Entities are interpolated between the two most recent server messages, so client time should
+//! stay between the server times those messages carry. Network jitter and uneven frame times push
+//! it outside that window. Jumping straight back into the window makes movement judder, so the
+//! clock is instead run slightly fast or slow until it drifts back in. Only large errors, such as
+//! those after a level change or a seek in a demo, are corrected at once.
+
+use chrono::Duration;
+
+// the most the clock is sped up or slowed down, as a percentage of frame time
+const MAX_CORRECTION_PERCENT: i32 = 10;
+
+// errors larger than this are corrected at once
+const SNAP_THRESHOLD_MS: i64 = 250;
+
+/// Returns the adjustment to make to client time this frame.
+///
+/// `msg_times` holds the latest and previous server times, in that order. The adjustment moves
+/// `time` toward the window between them by at most a tenth of `frame_time`, unless `time` is so
+/// far outside it that it should be corrected at once.
+pub fn correction(time: Duration, msg_times: [Duration; 2], frame_time: Duration) -> Duration {
+    let [latest, previous] = msg_times;
+
+    let error = if time < previous {
+        previous - time
+    } else if time > latest {
+        latest - time
+    } else {
+        return Duration::zero();
+    };
+
+    if error.num_milliseconds().abs() > SNAP_THRESHOLD_MS {
+        return error;
+    }
+
+    let max_step = frame_time * MAX_CORRECTION_PERCENT / 100;
+    if error > max_step {
+        max_step
+    } else if error < -max_step {
+        -max_step
+    } else {
+        error
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ms(n: i64) -> Duration {
+        Duration::milliseconds(n)
+    }
+
+    #[test]
+    fn test_correction_inside_window() {
+        assert_eq!(correction(ms(1050), [ms(1100), ms(1000)], ms(16)), ms(0));
+        assert_eq!(correction(ms(1100), [ms(1100), ms(1000)], ms(16)), ms(0));
+    }
+
+    #[test]
+    fn test_correction_drifts() {
+        let frame_time = ms(20);
+
+        // ahead of the latest message: slow down by at most 10% of the frame
+        assert_eq!(
+            correction(ms(1110), [ms(1100), ms(1000)], frame_time),
+            ms(-2)
+        );
+        assert_eq!(
+            correction(ms(1101), [ms(1100), ms(1000)], frame_time),
+            ms(-1)
+        );
+
+        // behind the previous message: speed up
+        assert_eq!(correction(ms(950), [ms(1100), ms(1000)], frame_time), ms(2));
+
+        // no frame time, no drift
+        assert_eq!(correction(ms(1110), [ms(1100), ms(1000)], ms(0)), ms(0));
+    }
+
+    #[test]
+    fn test_correction_snaps_large_errors() {
+        assert_eq!(correction(ms(0), [ms(5100), ms(5000)], ms(16)), ms(5000));
+        assert_eq!(
+            correction(ms(9000), [ms(5100), ms(5000)], ms(16)),
+            ms(-3900)
+        );
+    }
+}
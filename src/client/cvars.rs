@@ -18,47 +18,326 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::common::console::{CvarRegistry, ConsoleError};
+use crate::common::console::{ConsoleError, CvarRegistry};
 
 pub fn register_cvars(cvars: &CvarRegistry) -> Result<(), ConsoleError> {
-    cvars.register("cl_anglespeedkey", "1.5")?;
-    cvars.register_archive("cl_backspeed", "200")?;
-    cvars.register("cl_bob", "0.02")?;
-    cvars.register("cl_bobcycle", "0.6")?;
-    cvars.register("cl_bobup", "0.5")?;
-    cvars.register_archive("_cl_color", "0")?;
-    cvars.register("cl_crossx", "0")?;
-    cvars.register("cl_crossy", "0")?;
-    cvars.register_archive("cl_forwardspeed", "400")?;
-    cvars.register("cl_movespeedkey", "2.0")?;
-    cvars.register_archive("_cl_name", "player")?;
-    cvars.register("cl_nolerp", "0")?;
-    cvars.register("cl_pitchspeed", "150")?;
-    cvars.register("cl_rollangle", "2.0")?;
-    cvars.register("cl_rollspeed", "200")?;
-    cvars.register("cl_shownet", "0")?;
-    cvars.register("cl_sidespeed", "350")?;
-    cvars.register("cl_upspeed", "200")?;
-    cvars.register("cl_yawspeed", "140")?;
-    cvars.register("fov", "90")?;
-    cvars.register_archive("m_pitch", "0.022")?;
-    cvars.register_archive("m_yaw", "0.022")?;
-    cvars.register_archive("sensitivity", "3")?;
-    cvars.register("v_idlescale", "0")?;
-    cvars.register("v_ipitch_cycle", "1")?;
-    cvars.register("v_ipitch_level", "0.3")?;
-    cvars.register("v_iroll_cycle", "0.5")?;
-    cvars.register("v_iroll_level", "0.1")?;
-    cvars.register("v_iyaw_cycle", "2")?;
-    cvars.register("v_iyaw_level", "0.3")?;
-    cvars.register("v_kickpitch", "0.6")?;
-    cvars.register("v_kickroll", "0.6")?;
-    cvars.register("v_kicktime", "0.5")?;
+    cvars.register_archive("bgmvolume", "1", "volume of the music, from 0 to 1")?;
+    cvars.register_archive(
+        "capture_fps",
+        "30",
+        "frame rate of videos made by capturedemo",
+    )?;
+    cvars.register(
+        "cl_anglespeedkey",
+        "1.5",
+        "turning speed multiplier while +speed is held",
+    )?;
+    cvars.register_archive(
+        "cl_autodemo",
+        "0",
+        "if nonzero, record a demo of every level that is played",
+    )?;
+    cvars.register_archive(
+        "cl_autodemo_keep",
+        "20",
+        "number of automatic demos to keep, or 0 to keep them all",
+    )?;
+    cvars.register_archive(
+        "cl_autopause",
+        "0",
+        "if nonzero, pause single-player games while the window is in the background",
+    )?;
+    cvars.register_archive("cl_backspeed", "200", "backward movement speed")?;
+    cvars.register_archive(
+        "cl_beamjitter",
+        "2",
+        "how far lightning bolts crackle to each side, or 0 to keep them straight",
+    )?;
+    cvars.register("cl_bob", "0.02", "amount the view bobs while moving")?;
+    cvars.register("cl_bobcycle", "0.6", "seconds per view bob")?;
+    cvars.register(
+        "cl_bobup",
+        "0.5",
+        "fraction of each view bob spent moving up",
+    )?;
+    cvars.register_archive("_cl_color", "0", "shirt and pants colors of the player")?;
+    cvars.register("cl_crossx", "0", "horizontal offset of the crosshair")?;
+    cvars.register("cl_crossy", "0", "vertical offset of the crosshair")?;
+    cvars.register_archive(
+        "cl_demokeyframe",
+        "10",
+        "seconds between keyframes in recorded demos, or 0 for none",
+    )?;
+    cvars.register_archive(
+        "cl_demometadata",
+        "1",
+        "if nonzero, record the player name and date at the start of demos",
+    )?;
+    cvars.register_archive("cl_forwardspeed", "400", "forward movement speed")?;
+    // sanity limits on maps and models, which may come from any server
+    cvars.register_archive(
+        "cl_maxentities",
+        "32768",
+        "maximum number of entities in a map",
+    )?;
+    cvars.register_archive(
+        "cl_maxlumpsize",
+        "64",
+        "maximum size in megabytes of each section of a map",
+    )?;
+    cvars.register_archive(
+        "cl_maxtexturesize",
+        "4096",
+        "maximum width or height of a map or model texture",
+    )?;
+    cvars.register(
+        "cl_movespeedkey",
+        "2.0",
+        "movement speed multiplier while +speed is held",
+    )?;
+    cvars.register_archive("_cl_name", "player", "name of the player")?;
+    cvars.register(
+        "cl_nolerp",
+        "0",
+        "if nonzero, don't interpolate between server updates",
+    )?;
+    cvars.register_archive(
+        "cl_nopred",
+        "0",
+        "if nonzero, don't predict player movement",
+    )?;
+    cvars.register("cl_pitchspeed", "150", "speed of +lookup and +lookdown")?;
+    cvars.register_archive(
+        "cl_predictattack",
+        "0",
+        "if nonzero, show muzzle flashes and impacts before the server confirms them",
+    )?;
+    cvars.register_archive(
+        "cl_predictping",
+        "100",
+        "milliseconds of latency to predict movement across",
+    )?;
+    cvars.register(
+        "cl_rollangle",
+        "2.0",
+        "degrees the view rolls while strafing",
+    )?;
+    cvars.register(
+        "cl_rollspeed",
+        "200",
+        "strafing speed at which the view fully rolls",
+    )?;
+    cvars.register(
+        "cl_shownet",
+        "0",
+        "if nonzero, print information about server messages",
+    )?;
+    cvars.register("cl_sidespeed", "350", "strafing movement speed")?;
+    cvars.register("cl_upspeed", "200", "swimming speed up and down")?;
+    cvars.register("cl_yawspeed", "140", "speed of +left and +right")?;
+    cvars.register_archive(
+        "con_filter",
+        "dev",
+        "print levels to hide from the console, from game, chat and dev",
+    )?;
+    cvars.register_archive(
+        "con_notifylines",
+        "4",
+        "number of recent lines shown at the top of the screen",
+    )?;
+    cvars.register_archive(
+        "con_notifytime",
+        "3",
+        "seconds recent lines stay at the top of the screen",
+    )?;
+    cvars.register_archive(
+        "crosshair",
+        "1",
+        "crosshair style: 0 = off, 1-5 = glyphs, 6 = dot, 7 = cross, 8 = circle",
+    )?;
+    cvars.register_archive(
+        "crosshaircolor",
+        "255 255 255",
+        "red, green and blue components of the crosshair color",
+    )?;
+    cvars.register_archive("crosshairsize", "1", "size of the crosshair")?;
+    cvars.register("fov", "90", "horizontal field of view in degrees")?;
+    cvars.register_archive(
+        "fov_adapt",
+        "0",
+        "if nonzero, fov is measured at 4:3 and widened to fit wider screens",
+    )?;
+    cvars.register_archive(
+        "gl_cshiftpercent",
+        "100",
+        "strength of all screen color shifts, in percent",
+    )?;
+    cvars.register_archive(
+        "gl_polyblend",
+        "1",
+        "if nonzero, tint the screen with color shifts",
+    )?;
+    cvars.register_archive(
+        "joy_deadzone",
+        "0.2",
+        "fraction of gamepad stick travel that is ignored",
+    )?;
+    cvars.register_archive(
+        "joy_exponent",
+        "2",
+        "response curve of the gamepad sticks, where 1 is linear",
+    )?;
+    cvars.register_archive("joy_pitchspeed", "150", "gamepad vertical turning speed")?;
+    cvars.register_archive("joy_yawspeed", "200", "gamepad horizontal turning speed")?;
+    cvars.register_archive("m_pitch", "0.022", "vertical mouse sensitivity multiplier")?;
+    cvars.register_archive("m_yaw", "0.022", "horizontal mouse sensitivity multiplier")?;
+    cvars.register(
+        "net_messagetimeout",
+        "300",
+        "seconds without hearing from the server before the connection is dropped",
+    )?;
+    cvars.register_archive(
+        "r_flatlightstyles",
+        "0",
+        "if nonzero, flickering and flashing lights hold steady at their average brightness",
+    )?;
+    cvars.register_archive(
+        "r_lerpmove",
+        "1",
+        "if nonzero, smooth entity movement between server updates",
+    )?;
+    cvars.register_archive("scr_conalpha", "1", "opacity of the console background")?;
+    cvars.register_archive("scr_conback", "", "image to use as the console background")?;
+    cvars.register_archive(
+        "scr_conbackcolor",
+        "",
+        "color of the console background, overriding the image",
+    )?;
+    cvars.register_archive("scr_conscale", "2", "scale of the console text")?;
+    cvars.register_archive("scr_crosshairscale", "2", "scale of the crosshair")?;
+    cvars.register_archive(
+        "scr_highcontrast",
+        "0",
+        "if nonzero, draw the console and HUD text on solid dark backgrounds",
+    )?;
+    cvars.register_archive(
+        "scr_hudstyle",
+        "0",
+        "HUD layout: 0 = status bar, 1 = minimal",
+    )?;
+    cvars.register_archive(
+        "scr_safearea",
+        "0",
+        "widest aspect ratio of the area the minimal HUD is drawn in, or 0 for the whole screen",
+    )?;
+    cvars.register_archive("scr_sbarscale", "2", "scale of the HUD")?;
+    cvars.register_archive("scr_screenshotformat", "png", "image format of screenshots")?;
+    cvars.register_archive(
+        "scr_transition",
+        "1",
+        "effect revealing a new level: 0 = none, 1 = fade, 2 = wipe",
+    )?;
+    cvars.register_archive(
+        "scr_transitiontime",
+        "0.5",
+        "seconds taken to reveal a new level",
+    )?;
+    cvars.register_archive("sensitivity", "3", "mouse sensitivity")?;
+    cvars.register_archive(
+        "snd_occlusion",
+        "0.5",
+        "volume of sounds heard through walls, from 0 to 1",
+    )?;
+    cvars.register_archive(
+        "snd_occlusion_cutoff",
+        "1000",
+        "cutoff frequency in Hz of sounds heard through walls, or 0 to disable filtering",
+    )?;
+    cvars.register_archive("snd_reverb", "0.25", "strength of room reverb, from 0 to 1")?;
+    cvars.register_archive(
+        "snd_underwater",
+        "800",
+        "cutoff frequency in Hz of the underwater filter, or 0 to disable it",
+    )?;
+    cvars.register_archive("v_bonusflash", "1", "strength of the item pickup flash")?;
+    cvars.register_archive(
+        "v_contentblend",
+        "1",
+        "strength of the tint while underwater or in slime or lava",
+    )?;
+    cvars.register_archive("v_damagecshift", "1", "strength of the damage flash")?;
+    cvars.register_archive(
+        "v_flashlimit",
+        "1",
+        "greatest opacity of the damage and pickup flashes, from 0 to 1",
+    )?;
+    cvars.register("v_idlescale", "0", "amount the view sways while idle")?;
+    cvars.register("v_ipitch_cycle", "1", "speed of the idle sway in pitch")?;
+    cvars.register("v_ipitch_level", "0.3", "amount of idle sway in pitch")?;
+    cvars.register("v_iroll_cycle", "0.5", "speed of the idle sway in roll")?;
+    cvars.register("v_iroll_level", "0.1", "amount of idle sway in roll")?;
+    cvars.register("v_iyaw_cycle", "2", "speed of the idle sway in yaw")?;
+    cvars.register("v_iyaw_level", "0.3", "amount of idle sway in yaw")?;
+    cvars.register("v_kickpitch", "0.6", "amount the view pitches when damaged")?;
+    cvars.register("v_kickroll", "0.6", "amount the view rolls when damaged")?;
+    cvars.register(
+        "v_kicktime",
+        "0.5",
+        "seconds the view kick lasts when damaged",
+    )?;
+    cvars.register_archive(
+        "v_motionscale",
+        "1",
+        "scale of view roll, kicks and sway, from 0 to 1",
+    )?;
+    cvars.register_archive("v_powerupcshift", "1", "strength of the powerup tint")?;
+    // window geometry, saved when the client exits
+    cvars.register_archive(
+        "vid_fullscreen",
+        "0",
+        "if nonzero, fill the monitor with a borderless window",
+    )?;
+    cvars.register_archive("vid_height", "768", "height of the window")?;
+    cvars.register_archive(
+        "vid_monitor",
+        "",
+        "name of the monitor to go fullscreen on, or empty for the current one",
+    )?;
+    cvars.register_archive("vid_width", "1366", "width of the window")?;
+    cvars.register_archive(
+        "vid_x",
+        "",
+        "horizontal position of the window, or empty to let the OS place it",
+    )?;
+    cvars.register_archive(
+        "vid_y",
+        "",
+        "vertical position of the window, or empty to let the OS place it",
+    )?;
+    cvars.register_archive(
+        "viewsize",
+        "100",
+        "size of the view from 30 to 120, where 110 hides the inventory and 120 the status bar",
+    )?;
 
     // some server cvars are needed by the client, but if the server is running
     // in the same process they will have been set already, so we can ignore
     // the duplicate cvar error
-    let _ = cvars.register("sv_gravity", "800");
+    let _ = cvars.register("edgefriction", "2", "extra friction applied near ledges");
+    let _ = cvars.register("skill", "1", "difficulty from 0 (easy) to 3 (nightmare)");
+    let _ = cvars.register("sv_accelerate", "10", "player acceleration on the ground");
+    let _ = cvars.register("sv_friction", "4", "player friction on the ground");
+    let _ = cvars.register(
+        "sv_gravity",
+        "800",
+        "downward acceleration in units per second",
+    );
+    let _ = cvars.register("sv_maxspeed", "320", "maximum player movement speed");
+    let _ = cvars.register(
+        "sv_stopspeed",
+        "100",
+        "speed below which friction brings players to a stop",
+    );
 
     Ok(())
 }
@@ -1,18 +1,22 @@
 use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
     fs::File,
-    io::{self, BufRead, Read as _},
+    io::{self, BufWriter, Read, Write},
     ops::Range,
+    path::Path,
 };
 
 use crate::common::{
+    engine,
     net::{self, NetError, ServerCmd},
     util::read_f32_3,
-    vfs::VirtualFile,
 };
 
 use arrayvec::ArrayVec;
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use cgmath::{Deg, Vector3};
+use chrono::{DateTime, Duration, TimeZone};
 use io::BufReader;
 use thiserror::Error;
 
@@ -30,6 +34,142 @@ pub enum DemoServerError {
     Net(#[from] NetError),
 }
 
+/// The text of the `StuffText` command that begins each keyframe.
+///
+/// Clients execute it as a console comment, so demos with keyframes still play in clients that
+/// don't know about them.
+pub const KEYFRAME_MARKER: &str = "//keyframe\n";
+
+/// The first line of the `StuffText` command that holds a demo's metadata block.
+///
+/// Like keyframes, the block is made of console comments, so clients that don't know about it
+/// play the demo as usual.
+pub const METADATA_MARKER: &str = "//metadata\n";
+
+/// The start of the file name of every demo recorded by `cl_autodemo`.
+pub const AUTODEMO_PREFIX: &str = "autodemo_";
+
+/// Returns the short name of a map from its worldmodel, e.g. `e1m1` for `maps/e1m1.bsp`.
+pub fn map_name(worldmodel: &str) -> &str {
+    worldmodel
+        .trim_start_matches("maps/")
+        .trim_end_matches(".bsp")
+}
+
+/// Returns the file name of a demo of `map` automatically recorded at `time`.
+///
+/// Names sort in the order the demos were recorded, e.g. `autodemo_20180314-153000_e1m1.dem`.
+pub fn autodemo_name<Tz>(time: DateTime<Tz>, map: &str) -> String
+where
+    Tz: TimeZone,
+    Tz::Offset: Display,
+{
+    format!(
+        "{}{}_{}.dem",
+        AUTODEMO_PREFIX,
+        time.format("%Y%m%d-%H%M%S"),
+        map
+    )
+}
+
+/// Returns the automatically recorded demos among `file_names` that should be deleted so that
+/// only the `keep` most recent remain.
+///
+/// Other files are never returned. If `keep` is 0, every demo is kept.
+pub fn expired_autodemos<S>(file_names: &[S], keep: usize) -> Vec<&str>
+where
+    S: AsRef<str>,
+{
+    if keep == 0 {
+        return Vec::new();
+    }
+
+    let mut demos: Vec<&str> = file_names
+        .iter()
+        .map(|n| n.as_ref())
+        .filter(|n| n.starts_with(AUTODEMO_PREFIX) && n.ends_with(".dem"))
+        .collect();
+    demos.sort();
+
+    let expired = demos.len().saturating_sub(keep);
+    demos.truncate(expired);
+    demos
+}
+
+/// Information about a demo that isn't part of the recorded messages, such as who recorded it.
+///
+/// Fields are stored in the order they were added. Keys are single words; line breaks in values
+/// are replaced with spaces.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DemoMetadata {
+    fields: Vec<(String, String)>,
+}
+
+impl DemoMetadata {
+    pub fn new() -> DemoMetadata {
+        DemoMetadata { fields: Vec::new() }
+    }
+
+    /// Adds a field, replacing any previous value of `key`.
+    pub fn with<S>(mut self, key: &str, value: S) -> DemoMetadata
+    where
+        S: AsRef<str>,
+    {
+        let key: String = key.split_whitespace().collect();
+        let value = value.as_ref().replace(|c| c == '\n' || c == '\r', " ");
+
+        match self.fields.iter_mut().find(|(k, _)| *k == key) {
+            Some(field) => field.1 = value,
+            None => self.fields.push((key, value)),
+        }
+
+        self
+    }
+
+    /// Returns the value of `key`, if present.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    // Returns the text of the `StuffText` command that stores this block.
+    fn to_text(&self) -> String {
+        let mut text = METADATA_MARKER.to_owned();
+        for (key, value) in self.fields.iter() {
+            text.push_str(&format!("//{} {}\n", key, value));
+        }
+        text
+    }
+
+    /// Reads a block from the text of a `StuffText` command, if it holds one.
+    pub fn from_text(text: &str) -> Option<DemoMetadata> {
+        if !text.starts_with(METADATA_MARKER) {
+            return None;
+        }
+
+        let mut metadata = DemoMetadata::new();
+        for line in text[METADATA_MARKER.len()..].lines() {
+            if !line.starts_with("//") {
+                continue;
+            }
+            let line = &line[2..];
+
+            let mut parts = line.splitn(2, ' ');
+            if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+                metadata = metadata.with(key, value);
+            }
+        }
+
+        Some(metadata)
+    }
+}
+
 struct DemoMessage {
     view_angles: Vector3<Deg<f32>>,
     msg_range: Range<usize>,
@@ -50,6 +190,140 @@ impl<'a> DemoMessageView<'a> {
     }
 }
 
+/// Summary information about a recorded demo.
+#[derive(Clone, Debug)]
+pub struct DemoInfo {
+    map: Option<String>,
+    level_name: Option<String>,
+    protocol: Option<i32>,
+    player_name: Option<String>,
+    duration: Duration,
+    metadata: Option<DemoMetadata>,
+}
+
+impl DemoInfo {
+    /// The name of the map the demo was recorded on, e.g. `e1m1`.
+    pub fn map(&self) -> Option<&str> {
+        self.map.as_ref().map(|m| m.as_str())
+    }
+
+    /// The level name sent by the server, e.g. `the Slipgate Complex`.
+    pub fn level_name(&self) -> Option<&str> {
+        self.level_name.as_ref().map(|m| m.as_str())
+    }
+
+    /// The protocol version sent by the server.
+    pub fn protocol(&self) -> Option<i32> {
+        self.protocol
+    }
+
+    /// The name of the player who recorded the demo.
+    ///
+    /// This is taken from the metadata block if there is one, or otherwise from the name the
+    /// server gave the player whose view was recorded.
+    pub fn player_name(&self) -> Option<&str> {
+        self.metadata
+            .as_ref()
+            .and_then(|m| m.get("player"))
+            .or(self.player_name.as_ref().map(|n| n.as_str()))
+    }
+
+    /// The elapsed game time between the first and last messages of the demo.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The demo's metadata block, if it has one.
+    pub fn metadata(&self) -> Option<&DemoMetadata> {
+        self.metadata.as_ref()
+    }
+}
+
+/// Reads a demo and scans it for information. See `DemoServer::info`.
+pub fn read_info<R>(file: R) -> Result<DemoInfo, DemoServerError>
+where
+    R: Read,
+{
+    Ok(DemoServer::new(file)?.info())
+}
+
+// A point from which playback can resume without the messages before it.
+#[derive(Clone, Debug, PartialEq)]
+struct Keyframe {
+    // server time of the keyframe
+    time: f32,
+
+    // id of the first message of the keyframe
+    message_id: usize,
+
+    // messages that load the keyframe's level
+    signon: Range<usize>,
+}
+
+// Finds the server time of the first update and the keyframes in a demo's messages.
+//
+// A keyframe takes the time of the update before it. Like `DemoServer::info`, this stops at the
+// first message that fails to parse.
+fn index_keyframes(messages: &[DemoMessage], message_data: &[u8]) -> (Option<f32>, Vec<Keyframe>) {
+    let mut start_time = None;
+    let mut last_time = None;
+    let mut keyframes = Vec::new();
+    let mut signon_start = None;
+    let mut signon_end = None;
+
+    'messages: for (message_id, msg) in messages.iter().enumerate() {
+        let mut reader = BufReader::new(&message_data[msg.msg_range.clone()]);
+        let mut first = true;
+        let mut keyframe_time = None;
+
+        loop {
+            match ServerCmd::deserialize(&mut reader) {
+                Ok(Some(ServerCmd::StuffText { text })) => {
+                    if first && text == KEYFRAME_MARKER {
+                        keyframe_time = last_time;
+                    }
+                }
+
+                // a new level restarts the sign-on
+                Ok(Some(ServerCmd::ServerInfo { .. })) => {
+                    signon_start = Some(message_id);
+                    signon_end = None;
+                    last_time = None;
+                }
+
+                // the sign-on ends where the updates begin
+                Ok(Some(ServerCmd::Time { time })) => {
+                    if start_time.is_none() {
+                        start_time = Some(time);
+                    }
+
+                    if signon_end.is_none() {
+                        signon_end = Some(message_id);
+                    }
+
+                    last_time = Some(time);
+                }
+
+                Ok(Some(_)) => (),
+                Ok(None) => break,
+                Err(_) => break 'messages,
+            }
+
+            first = false;
+        }
+
+        if let (Some(time), Some(start)) = (keyframe_time, signon_start) {
+            keyframes.push(Keyframe {
+                time,
+                message_id,
+                signon: start..signon_end.unwrap_or(message_id),
+            });
+        }
+    }
+
+    (start_time, keyframes)
+}
+
 pub struct DemoServer {
     track_override: Option<u32>,
 
@@ -60,10 +334,22 @@ pub struct DemoServer {
 
     // all message data
     message_data: Vec<u8>,
+
+    // server time of the first update
+    start_time: Option<f32>,
+
+    keyframes: Vec<Keyframe>,
+
+    // ids of messages to send before resuming from `message_id`, used to load the level again
+    // when seeking
+    replay: VecDeque<usize>,
 }
 
 impl DemoServer {
-    pub fn new(file: &mut VirtualFile) -> Result<DemoServer, DemoServerError> {
+    pub fn new<R>(file: R) -> Result<DemoServer, DemoServerError>
+    where
+        R: Read,
+    {
         let mut dem_reader = BufReader::new(file);
         let mut buf = ArrayVec::<[u8; net::MAX_MESSAGE]>::new();
 
@@ -82,7 +368,7 @@ impl DemoServer {
         }
 
         let track_override = {
-            let track_str = match std::str::from_utf8(&buf[..buf.len() - 1]) {
+            let track_str = match std::str::from_utf8(&buf) {
                 Ok(s) => s,
                 Err(_) => Err(DemoServerError::InvalidCdTrack)?,
             };
@@ -108,6 +394,10 @@ impl DemoServer {
 
         // read all messages
         while let Ok(msg_len) = dem_reader.read_u32::<LittleEndian>() {
+            if msg_len as usize > net::MAX_MESSAGE {
+                Err(DemoServerError::MessageTooLong(msg_len))?;
+            }
+
             // get view angles
             let view_angles_f32 = read_f32_3(&mut dem_reader)?;
             let view_angles = Vector3::new(
@@ -129,21 +419,147 @@ impl DemoServer {
             });
         }
 
+        let (start_time, keyframes) = index_keyframes(&messages, &message_data);
+
         Ok(DemoServer {
             track_override,
             message_id: 0,
             messages,
             message_data,
+            start_time,
+            keyframes,
+            replay: VecDeque::new(),
         })
     }
 
-    pub fn next(&mut self) -> Option<DemoMessageView> {
-        if self.message_id >= self.messages.len() {
-            return None;
+    /// Scans the demo's messages for the recorded map, protocol, player and duration.
+    ///
+    /// Scanning stops at the first message that fails to parse, so a truncated or corrupt demo
+    /// reports whatever information precedes the damage.
+    pub fn info(&self) -> DemoInfo {
+        let mut map = None;
+        let mut level_name = None;
+        let mut protocol = None;
+        let mut view_ent = None;
+        let mut player_names = HashMap::new();
+        let mut first_time = None;
+        let mut last_time = None;
+        let mut metadata = None;
+
+        'messages: for msg in self.messages.iter() {
+            let mut reader = BufReader::new(&self.message_data[msg.msg_range.clone()]);
+
+            loop {
+                match ServerCmd::deserialize(&mut reader) {
+                    Ok(Some(ServerCmd::ServerInfo {
+                        protocol_version,
+                        message,
+                        model_precache,
+                        ..
+                    })) => {
+                        // the first model is always the worldmodel
+                        map = model_precache.first().map(|m| map_name(m).to_owned());
+                        level_name = Some(message);
+                        protocol = Some(protocol_version);
+                    }
+
+                    Ok(Some(ServerCmd::Time { time })) => {
+                        if first_time.is_none() {
+                            first_time = Some(time);
+                        }
+                        last_time = Some(time);
+                    }
+
+                    Ok(Some(ServerCmd::SetView { ent_id })) => view_ent = Some(ent_id),
+
+                    Ok(Some(ServerCmd::UpdateName {
+                        player_id,
+                        new_name,
+                    })) => {
+                        player_names.insert(player_id, new_name);
+                    }
+
+                    Ok(Some(ServerCmd::StuffText { text })) => {
+                        if metadata.is_none() {
+                            metadata = DemoMetadata::from_text(&text);
+                        }
+                    }
+
+                    Ok(Some(_)) => (),
+                    Ok(None) => break,
+                    Err(_) => break 'messages,
+                }
+            }
         }
 
-        let msg = &self.messages[self.message_id];
-        self.message_id += 1;
+        // player entities are numbered from 1
+        let player_name = view_ent
+            .filter(|e| *e > 0)
+            .and_then(|e| player_names.remove(&((e - 1) as u8)));
+
+        let duration = match (first_time, last_time) {
+            (Some(first), Some(last)) => engine::duration_from_f32(last - first),
+            _ => Duration::zero(),
+        };
+
+        DemoInfo {
+            map,
+            level_name,
+            protocol,
+            player_name,
+            duration,
+            metadata,
+        }
+    }
+
+    /// Returns `true` if every message in the demo has been read.
+    pub fn finished(&self) -> bool {
+        self.replay.is_empty() && self.message_id >= self.messages.len()
+    }
+
+    /// Returns the number of keyframes in the demo.
+    pub fn keyframe_count(&self) -> usize {
+        self.keyframes.len()
+    }
+
+    /// Moves playback to the last keyframe at or before `offset` from the start of the demo.
+    ///
+    /// The keyframe's level is loaded again before playback resumes from it. If no keyframe
+    /// precedes `offset`, playback restarts from the beginning of the demo. Returns the offset
+    /// playback resumes from.
+    pub fn seek(&mut self, offset: Duration) -> Duration {
+        let start_time = self.start_time.unwrap_or(0.0);
+        let target = start_time + engine::duration_to_f32(offset);
+
+        self.replay.clear();
+        match self.keyframes.iter().rev().find(|k| k.time <= target) {
+            Some(keyframe) => {
+                self.replay.extend(keyframe.signon.clone());
+                self.message_id = keyframe.message_id;
+                engine::duration_from_f32(keyframe.time - start_time)
+            }
+
+            None => {
+                self.message_id = 0;
+                Duration::zero()
+            }
+        }
+    }
+
+    pub fn next(&mut self) -> Option<DemoMessageView> {
+        let id = match self.replay.pop_front() {
+            Some(id) => id,
+            None => {
+                if self.message_id >= self.messages.len() {
+                    return None;
+                }
+
+                self.message_id += 1;
+                self.message_id - 1
+            }
+        };
+
+        let msg = &self.messages[id];
 
         Some(DemoMessageView {
             view_angles: msg.view_angles,
@@ -151,3 +567,482 @@ impl DemoServer {
         })
     }
 }
+
+/// Writes server messages in the demo file format.
+pub struct DemoWriter<W>
+where
+    W: Write,
+{
+    writer: W,
+}
+
+impl<W> DemoWriter<W>
+where
+    W: Write,
+{
+    /// Begins a demo by writing its header.
+    ///
+    /// If `track` is `Some`, that CD track plays for the whole demo instead of the ones chosen by
+    /// the server.
+    pub fn new(mut writer: W, track: Option<u32>) -> Result<DemoWriter<W>, DemoServerError> {
+        match track {
+            Some(t) => write!(writer, "{}\n", t)?,
+            None => writer.write_all(b"-1\n")?,
+        }
+
+        Ok(DemoWriter { writer })
+    }
+
+    /// Writes a message as it was received, along with the player's view angles at the time.
+    pub fn write_message(
+        &mut self,
+        view_angles: Vector3<Deg<f32>>,
+        message: &[u8],
+    ) -> Result<(), DemoServerError> {
+        if message.len() > net::MAX_MESSAGE {
+            Err(DemoServerError::MessageTooLong(message.len() as u32))?;
+        }
+
+        self.writer
+            .write_u32::<LittleEndian>(message.len() as u32)?;
+        for angle in &[view_angles.x, view_angles.y, view_angles.z] {
+            self.writer.write_f32::<LittleEndian>(angle.0)?;
+        }
+        self.writer.write_all(message)?;
+
+        Ok(())
+    }
+
+    /// Writes a keyframe made up of `cmds`.
+    ///
+    /// The commands should restate the client's state as of the last update, so that reading the
+    /// keyframe in order changes nothing. The keyframe takes the time of that update, which is
+    /// not sent again. The commands are preceded by the keyframe marker and split across as many
+    /// messages as needed.
+    pub fn write_keyframe(
+        &mut self,
+        view_angles: Vector3<Deg<f32>>,
+        cmds: &[ServerCmd],
+    ) -> Result<(), DemoServerError> {
+        let mut message = Vec::new();
+        ServerCmd::StuffText {
+            text: KEYFRAME_MARKER.to_owned(),
+        }
+        .serialize(&mut message)?;
+
+        let mut buf = Vec::new();
+        for cmd in cmds {
+            buf.clear();
+            cmd.serialize(&mut buf)?;
+
+            if message.len() + buf.len() > net::MAX_MESSAGE {
+                self.write_message(view_angles, &message)?;
+                message.clear();
+            }
+
+            message.extend_from_slice(&buf);
+        }
+
+        self.write_message(view_angles, &message)
+    }
+
+    /// Writes a metadata block.
+    ///
+    /// The block should follow the sign-on's first message, so that it is read whether playback
+    /// starts at the beginning or at a keyframe.
+    pub fn write_metadata(
+        &mut self,
+        view_angles: Vector3<Deg<f32>>,
+        metadata: &DemoMetadata,
+    ) -> Result<(), DemoServerError> {
+        let mut message = Vec::new();
+        ServerCmd::StuffText {
+            text: metadata.to_text(),
+        }
+        .serialize(&mut message)?;
+        self.write_message(view_angles, &message)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Records the messages received from a server to a demo file.
+pub struct DemoRecorder {
+    writer: DemoWriter<BufWriter<File>>,
+
+    // a demo must include the sign-on, so nothing is written until the next level starts
+    started: bool,
+
+    // server time of the last keyframe
+    last_keyframe: Option<f32>,
+
+    // written after the first message of the demo
+    metadata: Option<DemoMetadata>,
+}
+
+impl DemoRecorder {
+    /// Creates a demo file at `path` and waits for the next level to begin recording.
+    pub fn create<P>(path: P) -> Result<DemoRecorder, DemoServerError>
+    where
+        P: AsRef<Path>,
+    {
+        let file = BufWriter::new(File::create(path)?);
+
+        Ok(DemoRecorder {
+            writer: DemoWriter::new(file, None)?,
+            started: false,
+            last_keyframe: None,
+            metadata: None,
+        })
+    }
+
+    /// Adds a metadata block to the start of the demo.
+    pub fn with_metadata(mut self, metadata: DemoMetadata) -> DemoRecorder {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Returns `true` once the demo has begun with the start of a level.
+    pub fn started(&self) -> bool {
+        self.started
+    }
+
+    /// Records a message received from the server.
+    ///
+    /// `level_start` should be `true` if the message begins a new level.
+    pub fn record(
+        &mut self,
+        view_angles: Vector3<Deg<f32>>,
+        message: &[u8],
+        level_start: bool,
+    ) -> Result<(), DemoServerError> {
+        if level_start {
+            self.started = true;
+            self.last_keyframe = None;
+        }
+
+        if self.started {
+            self.writer.write_message(view_angles, message)?;
+
+            if let Some(metadata) = self.metadata.take() {
+                self.writer.write_metadata(view_angles, &metadata)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if a keyframe should be written at server time `time`.
+    ///
+    /// Keyframes are written every `interval` seconds from the first update of each level, or not
+    /// at all if `interval` is not positive.
+    pub fn keyframe_due(&mut self, time: f32, interval: f32) -> bool {
+        if !self.started || interval <= 0.0 {
+            return false;
+        }
+
+        match self.last_keyframe {
+            Some(last) => time - last >= interval,
+            None => {
+                self.last_keyframe = Some(time);
+                false
+            }
+        }
+    }
+
+    /// Writes a keyframe at server time `time`. See `DemoWriter::write_keyframe`.
+    pub fn write_keyframe(
+        &mut self,
+        view_angles: Vector3<Deg<f32>>,
+        time: f32,
+        cmds: &[ServerCmd],
+    ) -> Result<(), DemoServerError> {
+        self.writer.write_keyframe(view_angles, cmds)?;
+        self.last_keyframe = Some(time);
+        Ok(())
+    }
+
+    /// Ends the demo, writing a disconnect so that playback stops cleanly.
+    pub fn stop(mut self, view_angles: Vector3<Deg<f32>>) -> Result<(), DemoServerError> {
+        if self.started {
+            let mut message = Vec::new();
+            ServerCmd::Disconnect.serialize(&mut message)?;
+            self.writer.write_message(view_angles, &message)?;
+        }
+
+        self.writer.into_inner().flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::common::net::{ClientStat, GameType, SignOnStage};
+
+    fn message(cmds: &[ServerCmd]) -> Vec<u8> {
+        let mut msg = Vec::new();
+        for cmd in cmds {
+            cmd.serialize(&mut msg).unwrap();
+        }
+        msg
+    }
+
+    fn no_angles() -> Vector3<Deg<f32>> {
+        Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0))
+    }
+
+    // Writes a demo of one level with updates at each of `times` and keyframes at each of
+    // `keyframe_times`.
+    fn write_demo(times: &[f32], keyframe_times: &[f32]) -> Vec<u8> {
+        let mut writer = DemoWriter::new(Vec::new(), None).unwrap();
+        writer
+            .write_message(
+                no_angles(),
+                &message(&[
+                    ServerCmd::ServerInfo {
+                        protocol_version: net::PROTOCOL_VERSION as i32,
+                        max_clients: 1,
+                        game_type: GameType::CoOp,
+                        message: String::from("Test level"),
+                        model_precache: vec![String::from("maps/test.bsp")],
+                        sound_precache: Vec::new(),
+                    },
+                    ServerCmd::SignOnStage {
+                        stage: SignOnStage::Prespawn,
+                    },
+                ]),
+            )
+            .unwrap();
+        writer
+            .write_message(
+                no_angles(),
+                &message(&[ServerCmd::SignOnStage {
+                    stage: SignOnStage::Begin,
+                }]),
+            )
+            .unwrap();
+
+        for time in times {
+            writer
+                .write_message(no_angles(), &message(&[ServerCmd::Time { time: *time }]))
+                .unwrap();
+
+            if keyframe_times.contains(time) {
+                writer
+                    .write_keyframe(
+                        no_angles(),
+                        &[ServerCmd::UpdateStat {
+                            stat: ClientStat::Health,
+                            value: 100,
+                        }],
+                    )
+                    .unwrap();
+            }
+        }
+
+        writer.into_inner()
+    }
+
+    // Returns the time sent by the next message from `demo_srv`, if any.
+    fn next_time(demo_srv: &mut DemoServer) -> Option<f32> {
+        let view = demo_srv.next().unwrap();
+        let mut reader = BufReader::new(view.message());
+        while let Some(cmd) = ServerCmd::deserialize(&mut reader).unwrap() {
+            if let ServerCmd::Time { time } = cmd {
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_write_read_demo() {
+        let data = write_demo(&[1.0, 1.5, 2.0], &[]);
+        assert!(data.starts_with(b"-1\n"));
+
+        let mut demo_srv = DemoServer::new(data.as_slice()).unwrap();
+        let info = demo_srv.info();
+        assert_eq!(info.map(), Some("test"));
+        assert_eq!(info.duration(), Duration::seconds(1));
+        assert_eq!(demo_srv.keyframe_count(), 0);
+
+        assert_eq!(next_time(&mut demo_srv), None);
+        assert_eq!(next_time(&mut demo_srv), None);
+        assert_eq!(next_time(&mut demo_srv), Some(1.0));
+    }
+
+    #[test]
+    fn test_seek_to_keyframe() {
+        let data = write_demo(&[1.0, 2.0, 3.0, 4.0, 5.0], &[2.0, 4.0]);
+        let mut demo_srv = DemoServer::new(data.as_slice()).unwrap();
+        assert_eq!(demo_srv.keyframe_count(), 2);
+
+        // 1 second after the start of the demo at 1.0
+        let resumed = demo_srv.seek(Duration::milliseconds(2500));
+        assert_eq!(resumed, Duration::seconds(1));
+
+        // the sign-on is replayed before the keyframe, which doesn't send the time again
+        assert_eq!(next_time(&mut demo_srv), None);
+        assert_eq!(next_time(&mut demo_srv), None);
+        assert!(!demo_srv.finished());
+        assert_eq!(next_time(&mut demo_srv), None);
+        assert_eq!(next_time(&mut demo_srv), Some(3.0));
+    }
+
+    #[test]
+    fn test_seek_before_first_keyframe_restarts() {
+        let data = write_demo(&[1.0, 2.0, 3.0], &[2.0]);
+        let mut demo_srv = DemoServer::new(data.as_slice()).unwrap();
+
+        while demo_srv.next().is_some() {}
+        assert!(demo_srv.finished());
+
+        assert_eq!(demo_srv.seek(Duration::milliseconds(500)), Duration::zero());
+        assert!(!demo_srv.finished());
+        assert_eq!(next_time(&mut demo_srv), None);
+        assert_eq!(next_time(&mut demo_srv), None);
+        assert_eq!(next_time(&mut demo_srv), Some(1.0));
+    }
+
+    #[test]
+    fn test_split_keyframe() {
+        let mut cmds = Vec::new();
+        for id in 0..64 {
+            cmds.push(ServerCmd::LightStyle {
+                id,
+                value: "abcdefghijklmnopqrstuvwxyz".repeat(8),
+            });
+        }
+
+        let mut writer = DemoWriter::new(Vec::new(), Some(2)).unwrap();
+        writer
+            .write_message(
+                no_angles(),
+                &message(&[ServerCmd::ServerInfo {
+                    protocol_version: net::PROTOCOL_VERSION as i32,
+                    max_clients: 1,
+                    game_type: GameType::CoOp,
+                    message: String::new(),
+                    model_precache: vec![String::from("maps/test.bsp")],
+                    sound_precache: Vec::new(),
+                }]),
+            )
+            .unwrap();
+        writer
+            .write_message(no_angles(), &message(&[ServerCmd::Time { time: 1.0 }]))
+            .unwrap();
+        writer.write_keyframe(no_angles(), &cmds).unwrap();
+        let data = writer.into_inner();
+
+        let demo_srv = DemoServer::new(data.as_slice()).unwrap();
+        assert_eq!(demo_srv.track_override, Some(2));
+        assert!(demo_srv.messages.len() > 3);
+        assert_eq!(demo_srv.keyframe_count(), 1);
+        assert_eq!(demo_srv.keyframes[0].time, 1.0);
+
+        // every command survives the split
+        let mut read_cmds = Vec::new();
+        for msg in demo_srv.messages.iter().skip(2) {
+            assert!(msg.msg_range.len() <= net::MAX_MESSAGE);
+            let mut reader = BufReader::new(&demo_srv.message_data[msg.msg_range.clone()]);
+            while let Some(cmd) = ServerCmd::deserialize(&mut reader).unwrap() {
+                read_cmds.push(cmd);
+            }
+        }
+        assert_eq!(read_cmds.len(), cmds.len() + 1);
+        assert_eq!(&read_cmds[1..], cmds.as_slice());
+    }
+
+    #[test]
+    fn test_demo_info() {
+        let metadata = DemoMetadata::new()
+            .with("player", "Ranger")
+            .with("note", "first\nsecond");
+        assert_eq!(metadata.get("note"), Some("first second"));
+
+        let mut writer = DemoWriter::new(Vec::new(), None).unwrap();
+        writer
+            .write_message(
+                no_angles(),
+                &message(&[
+                    ServerCmd::ServerInfo {
+                        protocol_version: net::PROTOCOL_VERSION as i32,
+                        max_clients: 4,
+                        game_type: GameType::Deathmatch,
+                        message: String::from("Test level"),
+                        model_precache: vec![String::from("maps/test.bsp")],
+                        sound_precache: Vec::new(),
+                    },
+                    ServerCmd::SetView { ent_id: 2 },
+                ]),
+            )
+            .unwrap();
+        writer
+            .write_message(
+                no_angles(),
+                &message(&[
+                    ServerCmd::UpdateName {
+                        player_id: 0,
+                        new_name: String::from("someone"),
+                    },
+                    ServerCmd::UpdateName {
+                        player_id: 1,
+                        new_name: String::from("Ranger"),
+                    },
+                    ServerCmd::Time { time: 3.0 },
+                ]),
+            )
+            .unwrap();
+        let plain = writer.into_inner();
+
+        // without metadata, the player is found from the view entity
+        let info = read_info(plain.as_slice()).unwrap();
+        assert_eq!(info.map(), Some("test"));
+        assert_eq!(info.protocol(), Some(net::PROTOCOL_VERSION as i32));
+        assert_eq!(info.player_name(), Some("Ranger"));
+        assert!(info.metadata().is_none());
+
+        let mut writer = DemoWriter::new(Vec::new(), None).unwrap();
+        writer
+            .write_metadata(no_angles(), &metadata.clone().with("player", "Ogre"))
+            .unwrap();
+        let mut data = writer.into_inner();
+        data.extend_from_slice(&plain[3..]);
+
+        let info = read_info(data.as_slice()).unwrap();
+        assert_eq!(info.player_name(), Some("Ogre"));
+        assert_eq!(info.metadata().unwrap().get("note"), Some("first second"));
+        assert_eq!(
+            info.metadata().unwrap().iter().collect::<Vec<_>>(),
+            vec![("player", "Ogre"), ("note", "first second")]
+        );
+    }
+
+    #[test]
+    fn test_expired_autodemos() {
+        use chrono::Utc;
+
+        let first = autodemo_name(Utc.ymd(2018, 3, 14).and_hms(15, 30, 0), "e1m1");
+        assert_eq!(first, "autodemo_20180314-153000_e1m1.dem");
+        let second = autodemo_name(Utc.ymd(2018, 3, 14).and_hms(15, 42, 10), "e1m2");
+        let third = autodemo_name(Utc.ymd(2018, 3, 15).and_hms(9, 0, 0), "start");
+
+        let names = vec![
+            third.clone(),
+            String::from("mydemo.dem"),
+            first.clone(),
+            second.clone(),
+        ];
+        assert_eq!(expired_autodemos(&names, 2), vec![first.as_str()]);
+        assert_eq!(
+            expired_autodemos(&names, 1),
+            vec![first.as_str(), second.as_str()]
+        );
+        assert!(expired_autodemos(&names, 5).is_empty());
+        assert!(expired_autodemos(&names, 0).is_empty());
+    }
+}
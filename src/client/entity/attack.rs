@@ -0,0 +1,373 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Client-side prediction of the effects of the player's own attacks.
+//!
+//! Without prediction, the muzzle flash, lightning bolt and bullet impacts of the player's weapon
+//! only appear once the server has processed the attack, a full round trip after the button was
+//! pressed. When `cl_predictattack` is set, these effects are spawned as soon as the attack is
+//! sent, assuming the refire times of the standard progs. The server's own effects for the attack
+//! arrive later and are matched against the predictions, so that each effect only appears once.
+//! The server's bolt simply replaces the predicted one, since both belong to the player.
+
+use std::collections::VecDeque;
+
+use crate::common::net::ItemFlags;
+
+use cgmath::{InnerSpace as _, Vector3};
+use chrono::Duration;
+
+/// How long a predicted effect waits for the server's matching effect before it is forgotten.
+pub const CONFIRM_WINDOW_MS: i64 = 1000;
+
+// pellets in a blast from each shotgun in the standard progs
+const SHOTGUN_PELLETS: u32 = 6;
+const SUPER_SHOTGUN_PELLETS: u32 = 14;
+
+// how far shotgun pellets can stray from the aim point, per unit of distance. the super shotgun's
+// spread is 0.14 horizontally, with some allowance for the player moving between prediction and
+// the server's shot.
+const MAX_SPREAD: f32 = 0.25;
+const MIN_IMPACT_RADIUS: f32 = 16.0;
+
+/// Returns the minimum time between attacks with `weapon`, or `None` if it isn't a weapon.
+pub fn refire_interval(weapon: ItemFlags) -> Option<Duration> {
+    let ms = match weapon {
+        w if w == ItemFlags::AXE => 500,
+        w if w == ItemFlags::SHOTGUN => 500,
+        w if w == ItemFlags::SUPER_SHOTGUN => 700,
+        w if w == ItemFlags::NAILGUN => 100,
+        w if w == ItemFlags::SUPER_NAILGUN => 100,
+        w if w == ItemFlags::GRENADE_LAUNCHER => 600,
+        w if w == ItemFlags::ROCKET_LAUNCHER => 800,
+        w if w == ItemFlags::LIGHTNING => 100,
+        _ => return None,
+    };
+
+    Some(Duration::milliseconds(ms))
+}
+
+/// An attack predicted to have been fired this frame.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PredictedAttack {
+    /// Whether the weapon shows a muzzle flash.
+    pub muzzle_flash: bool,
+
+    /// The number of hitscan pellets fired, which leave impacts where they hit the world.
+    pub pellets: u32,
+
+    /// Whether the weapon fires a lightning bolt from the player to whatever it hits.
+    pub tracer: bool,
+}
+
+// a predicted impact awaiting confirmation from the server
+#[derive(Debug)]
+struct PendingImpact {
+    time: Duration,
+    origin: Vector3<f32>,
+    radius: f32,
+}
+
+#[derive(Debug)]
+pub struct AttackPrediction {
+    // the earliest time the next attack can be predicted
+    next_attack: Duration,
+
+    // predicted muzzle flashes and impacts not yet matched by the server
+    flashes: VecDeque<Duration>,
+    impacts: VecDeque<PendingImpact>,
+
+    // the server message time of the last muzzle flash checked, and whether it was predicted.
+    // the flash effect lasts for a whole message, but only counts once.
+    last_flash: Option<(Duration, bool)>,
+}
+
+impl AttackPrediction {
+    pub fn new() -> AttackPrediction {
+        AttackPrediction {
+            next_attack: Duration::zero(),
+            flashes: VecDeque::new(),
+            impacts: VecDeque::new(),
+            last_flash: None,
+        }
+    }
+
+    /// Forgets all predictions, e.g. when the player dies or disconnects.
+    pub fn clear(&mut self) {
+        self.next_attack = Duration::zero();
+        self.flashes.clear();
+        self.impacts.clear();
+        self.last_flash = None;
+    }
+
+    /// Predicts whether the player fires at `time`.
+    ///
+    /// `weapon` is the active weapon and `ammo` the ammunition remaining for it.
+    pub fn fire(
+        &mut self,
+        time: Duration,
+        attack_held: bool,
+        weapon: ItemFlags,
+        ammo: i32,
+    ) -> Option<PredictedAttack> {
+        self.expire(time);
+
+        if !attack_held || time < self.next_attack {
+            return None;
+        }
+
+        let interval = refire_interval(weapon)?;
+        if weapon != ItemFlags::AXE && ammo <= 0 {
+            return None;
+        }
+
+        self.next_attack = time + interval;
+
+        let muzzle_flash = weapon != ItemFlags::AXE;
+        if muzzle_flash {
+            self.flashes.push_back(time);
+        }
+
+        let pellets = match weapon {
+            w if w == ItemFlags::SHOTGUN => SHOTGUN_PELLETS,
+            w if w == ItemFlags::SUPER_SHOTGUN && ammo >= 2 => SUPER_SHOTGUN_PELLETS,
+            w if w == ItemFlags::SUPER_SHOTGUN => SHOTGUN_PELLETS,
+            _ => 0,
+        };
+
+        Some(PredictedAttack {
+            muzzle_flash,
+            pellets,
+            tracer: weapon == ItemFlags::LIGHTNING,
+        })
+    }
+
+    /// Records a predicted impact of a shot fired from `start` and aimed at `origin`.
+    ///
+    /// Only one impact is drawn for a blast of pellets, so only one of the server's impacts is
+    /// suppressed in return.
+    pub fn add_impact(&mut self, time: Duration, start: Vector3<f32>, origin: Vector3<f32>) {
+        let radius = ((origin - start).magnitude() * MAX_SPREAD).max(MIN_IMPACT_RADIUS);
+        self.impacts.push_back(PendingImpact {
+            time,
+            origin,
+            radius,
+        });
+    }
+
+    /// Matches a muzzle flash on the player's entity from the server against the predictions.
+    ///
+    /// `msg_time` is the time of the server message carrying the flash. Returns `true` if the
+    /// flash was predicted and should not be shown again.
+    pub fn confirm_muzzle_flash(&mut self, time: Duration, msg_time: Duration) -> bool {
+        if let Some((t, predicted)) = self.last_flash {
+            if t == msg_time {
+                return predicted;
+            }
+        }
+
+        self.expire(time);
+        let predicted = self.flashes.pop_front().is_some();
+        self.last_flash = Some((msg_time, predicted));
+        predicted
+    }
+
+    /// Matches a bullet impact from the server against the predictions.
+    ///
+    /// Returns `true` if the impact was predicted and should not be shown again. Each predicted
+    /// impact is matched by the nearest of the server's impacts within its spread.
+    pub fn confirm_impact(&mut self, time: Duration, origin: Vector3<f32>) -> bool {
+        self.expire(time);
+
+        let pos = self
+            .impacts
+            .iter()
+            .enumerate()
+            .map(|(p, i)| (p, (i.origin - origin).magnitude(), i.radius))
+            .filter(|(_, dist, radius)| dist <= radius)
+            .min_by(|(_, a, _), (_, b, _)| a.partial_cmp(b).unwrap())
+            .map(|(p, _, _)| p);
+
+        match pos {
+            Some(p) => {
+                self.impacts.remove(p);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expire(&mut self, time: Duration) {
+        let window = Duration::milliseconds(CONFIRM_WINDOW_MS);
+        while self.flashes.front().map_or(false, |t| time - *t > window) {
+            self.flashes.pop_front();
+        }
+        self.impacts.retain(|i| time - i.time <= window);
+    }
+}
+
+/// Returns `true` if the line segment from `start` to `end` passes through the box from `min` to
+/// `max`.
+pub fn segment_hits_box(
+    start: Vector3<f32>,
+    end: Vector3<f32>,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+) -> bool {
+    let delta = end - start;
+    let mut enter = 0.0f32;
+    let mut exit = 1.0f32;
+
+    for axis in 0..3 {
+        if delta[axis] == 0.0 {
+            if start[axis] < min[axis] || start[axis] > max[axis] {
+                return false;
+            }
+            continue;
+        }
+
+        let t0 = (min[axis] - start[axis]) / delta[axis];
+        let t1 = (max[axis] - start[axis]) / delta[axis];
+        enter = enter.max(t0.min(t1));
+        exit = exit.min(t0.max(t1));
+        if enter > exit {
+            return false;
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ms(ms: i64) -> Duration {
+        Duration::milliseconds(ms)
+    }
+
+    #[test]
+    fn test_fire_respects_refire_interval() {
+        let mut attack = AttackPrediction::new();
+        let shotgun = ItemFlags::SHOTGUN;
+
+        assert_eq!(
+            attack.fire(ms(0), true, shotgun, 10),
+            Some(PredictedAttack {
+                muzzle_flash: true,
+                pellets: SHOTGUN_PELLETS,
+                tracer: false,
+            })
+        );
+        assert_eq!(attack.fire(ms(200), true, shotgun, 10), None);
+        assert!(attack.fire(ms(500), true, shotgun, 10).is_some());
+
+        // not without ammo, or without the button held
+        assert_eq!(attack.fire(ms(1500), true, shotgun, 0), None);
+        assert_eq!(attack.fire(ms(1500), false, shotgun, 10), None);
+
+        // the axe needs no ammo and has no flash
+        assert_eq!(
+            attack.fire(ms(1500), true, ItemFlags::AXE, 0),
+            Some(PredictedAttack {
+                muzzle_flash: false,
+                pellets: 0,
+                tracer: false,
+            })
+        );
+
+        // the lightning gun fires a bolt
+        assert_eq!(
+            attack.fire(ms(2000), true, ItemFlags::LIGHTNING, 10),
+            Some(PredictedAttack {
+                muzzle_flash: true,
+                pellets: 0,
+                tracer: true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_confirm_muzzle_flash() {
+        let mut attack = AttackPrediction::new();
+        attack.fire(ms(0), true, ItemFlags::ROCKET_LAUNCHER, 5);
+
+        // the flash is seen on every frame until the next server message
+        assert!(attack.confirm_muzzle_flash(ms(100), ms(90)));
+        assert!(attack.confirm_muzzle_flash(ms(110), ms(90)));
+        assert!(!attack.confirm_muzzle_flash(ms(150), ms(140)));
+
+        // predictions the server never confirms are eventually forgotten
+        attack.fire(ms(1000), true, ItemFlags::ROCKET_LAUNCHER, 5);
+        let late = ms(1000 + CONFIRM_WINDOW_MS + 1);
+        assert!(!attack.confirm_muzzle_flash(late, late));
+    }
+
+    #[test]
+    fn test_confirm_impact() {
+        let mut attack = AttackPrediction::new();
+        let start = Vector3::new(0.0, 0.0, 0.0);
+        let aim = Vector3::new(400.0, 0.0, 0.0);
+        attack.add_impact(ms(0), start, aim);
+
+        // too far from the aim point to be one of the pellets
+        assert!(!attack.confirm_impact(ms(100), Vector3::new(400.0, 200.0, 0.0)));
+
+        // only one of the server's impacts is suppressed
+        assert!(attack.confirm_impact(ms(100), Vector3::new(400.0, 40.0, 0.0)));
+        assert!(!attack.confirm_impact(ms(100), Vector3::new(400.0, -40.0, 0.0)));
+        assert!(!attack.confirm_impact(ms(100), aim));
+
+        // each impact is matched by the nearest of the server's impacts
+        let near = Vector3::new(400.0, 30.0, 0.0);
+        attack.add_impact(ms(200), start, aim);
+        attack.add_impact(ms(200), start, near);
+        assert!(attack.confirm_impact(ms(300), near));
+        assert!(attack.confirm_impact(ms(300), Vector3::new(400.0, 50.0, 0.0)));
+        assert!(!attack.confirm_impact(ms(300), aim));
+    }
+
+    #[test]
+    fn test_segment_hits_box() {
+        let min = Vector3::new(-16.0, -16.0, -24.0);
+        let max = Vector3::new(16.0, 16.0, 32.0);
+
+        assert!(segment_hits_box(
+            Vector3::new(-100.0, 0.0, 0.0),
+            Vector3::new(100.0, 0.0, 0.0),
+            min,
+            max
+        ));
+        assert!(!segment_hits_box(
+            Vector3::new(-100.0, 20.0, 0.0),
+            Vector3::new(100.0, 20.0, 0.0),
+            min,
+            max
+        ));
+
+        // stops short of the box
+        assert!(!segment_hits_box(
+            Vector3::new(-100.0, 0.0, 0.0),
+            Vector3::new(-50.0, 0.0, 0.0),
+            min,
+            max
+        ));
+    }
+}
@@ -0,0 +1,186 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Continuous beams, such as the lightning gun's bolt.
+//!
+//! The server sends a beam's endpoints every frame the attack continues, and the beam disappears
+//! shortly after the updates stop. In between, the bolt is rebuilt every client frame with new
+//! jitter, so it crackles rather than hanging still.
+//!
+//! The server also restarts the weapon's hum every 0.6 seconds while it fires. Instead of playing
+//! each restart to the end, the client loops the hum for as long as the entity's beam lasts, so
+//! there are no gaps when updates arrive late and the sound stops as soon as the attack ends.
+
+use cgmath::{Angle as _, Deg, InnerSpace as _, Vector3};
+use chrono::Duration;
+use rand::Rng;
+
+/// How long a beam lasts after the server last updated it.
+pub const BEAM_LIFETIME_MS: i64 = 200;
+
+// the length of each model in a beam
+const SEGMENT_LENGTH: f32 = 30.0;
+
+/// Sounds that are looped for as long as their entity fires a beam.
+pub const LOOPED_SOUNDS: [&str; 1] = ["weapons/lhit.wav"];
+
+// how long a looped sound plays if its entity never fires a beam, as when a mod plays it for some
+// other purpose. this is about the length of the sample.
+const UNTIED_LOOP_MS: i64 = 600;
+
+/// One model in a beam.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BeamSegment {
+    pub origin: Vector3<f32>,
+    pub angles: Vector3<Deg<f32>>,
+}
+
+/// Splits the beam from `start` to `end` into segments.
+///
+/// Every segment but the first is moved up to `jitter` units to each side of the beam, and each is
+/// rolled at random, so the beam looks different every time it is built. The first segment stays
+/// put so the beam remains attached to its source.
+pub fn segments<R>(
+    start: Vector3<f32>,
+    end: Vector3<f32>,
+    jitter: f32,
+    rng: &mut R,
+) -> Vec<BeamSegment>
+where
+    R: Rng,
+{
+    let vec = end - start;
+    let len = vec.magnitude();
+    if len < SEGMENT_LENGTH {
+        return Vec::new();
+    }
+
+    let yaw = Deg::from(cgmath::Rad(vec.y.atan2(vec.x))).normalize();
+    let forward = (vec.x.powf(2.0) + vec.y.powf(2.0)).sqrt();
+    let pitch = Deg::from(cgmath::Rad(vec.z.atan2(forward))).normalize();
+
+    // two directions across the beam to offset segments along
+    let direction = vec / len;
+    let up = if direction.z.abs() < 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_x()
+    };
+    let side = direction.cross(up).normalize();
+    let up = side.cross(direction);
+
+    (0..(len / SEGMENT_LENGTH) as i32)
+        .map(|interval| {
+            let mut origin = start + SEGMENT_LENGTH * interval as f32 * direction;
+            if interval > 0 && jitter > 0.0 {
+                origin += side * rng.gen_range(-jitter, jitter);
+                origin += up * rng.gen_range(-jitter, jitter);
+            }
+
+            BeamSegment {
+                origin,
+                angles: Vector3::new(pitch, yaw, Deg(rng.gen_range(0.0, 360.0))),
+            }
+        })
+        .collect()
+}
+
+/// Ties a looped sound to the beam fired by the same entity.
+#[derive(Clone, Copy, Debug)]
+pub struct BeamLoop {
+    start_time: Duration,
+    beam_seen: bool,
+}
+
+impl BeamLoop {
+    pub fn new(start_time: Duration) -> BeamLoop {
+        BeamLoop {
+            start_time,
+            beam_seen: false,
+        }
+    }
+
+    /// Returns whether the sound should keep playing at `time`, given whether its entity is
+    /// firing a beam.
+    ///
+    /// The sound usually starts in the same message as the beam, but may arrive just before it, so
+    /// it isn't stopped for the lack of a beam until the beam has been seen or the sample would
+    /// have finished anyway.
+    pub fn update(&mut self, time: Duration, beam: bool) -> bool {
+        if beam {
+            self.beam_seen = true;
+            return true;
+        }
+
+        !self.beam_seen && time - self.start_time < Duration::milliseconds(UNTIED_LOOP_MS)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rand::{rngs::SmallRng, SeedableRng as _};
+
+    #[test]
+    fn test_segments() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let start = Vector3::new(0.0, 0.0, 0.0);
+        let end = Vector3::new(100.0, 0.0, 0.0);
+
+        let straight = segments(start, end, 0.0, &mut rng);
+        assert_eq!(straight.len(), 3);
+        for (i, segment) in straight.iter().enumerate() {
+            assert_eq!(segment.origin, Vector3::new(30.0 * i as f32, 0.0, 0.0));
+            assert_eq!(segment.angles.x, Deg(0.0));
+            assert_eq!(segment.angles.y, Deg(0.0));
+        }
+
+        let jittered = segments(start, end, 4.0, &mut rng);
+        assert_eq!(jittered[0].origin, start);
+        for (segment, straight) in jittered.iter().zip(straight.iter()) {
+            let offset = segment.origin - straight.origin;
+            assert_eq!(offset.x, 0.0);
+            assert!(offset.y.abs() <= 4.0 && offset.z.abs() <= 4.0);
+        }
+
+        // rebuilding the beam gives it a new shape
+        assert_ne!(jittered, segments(start, end, 4.0, &mut rng));
+
+        assert!(segments(start, Vector3::new(0.0, 0.0, 10.0), 4.0, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn test_beam_loop() {
+        let ms = Duration::milliseconds;
+
+        // the loop outlasts the sample while the beam continues, then stops with it
+        let mut beam_loop = BeamLoop::new(ms(0));
+        assert!(beam_loop.update(ms(0), false));
+        assert!(beam_loop.update(ms(100), true));
+        assert!(beam_loop.update(ms(2000), true));
+        assert!(!beam_loop.update(ms(2100), false));
+
+        // without a beam, the sound plays about once
+        let mut beam_loop = BeamLoop::new(ms(0));
+        assert!(beam_loop.update(ms(500), false));
+        assert!(!beam_loop.update(ms(700), false));
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Effects left behind by moving entities.
+
+use crate::{client::entity::particle::TrailKind, common::model::ModelFlags};
+
+/// Returns the kind of trail left by entities whose model has the given flags.
+///
+/// Models should only have one trail flag, but if several are set the first of gib, zombie gib,
+/// green tracer, red tracer, rocket, grenade and vore tracer is used.
+pub fn trail_kind(flags: ModelFlags) -> Option<TrailKind> {
+    const TRAILS: [(ModelFlags, TrailKind); 7] = [
+        (ModelFlags::GIB, TrailKind::Blood),
+        (ModelFlags::ZOMGIB, TrailKind::BloodSlight),
+        (ModelFlags::TRACER, TrailKind::TracerGreen),
+        (ModelFlags::TRACER2, TrailKind::TracerRed),
+        (ModelFlags::ROCKET, TrailKind::Rocket),
+        (ModelFlags::GRENADE, TrailKind::Smoke),
+        (ModelFlags::TRACER3, TrailKind::Vore),
+    ];
+
+    TRAILS
+        .iter()
+        .find(|(flag, _)| flags.contains(*flag))
+        .map(|(_, kind)| *kind)
+}
+
+/// The progress of an entity's trail from one frame to the next.
+///
+/// Entities usually move less than a particle interval each frame, so the distance left over
+/// after the last particle is carried into the next move. This keeps particles evenly spaced
+/// regardless of frame rate.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TrailState {
+    // distance left to move before the next particle
+    until_next: f32,
+
+    // number of particles emitted so far, used to alternate tracer colors and directions
+    count: u32,
+}
+
+impl TrailState {
+    pub fn new() -> TrailState {
+        TrailState::default()
+    }
+
+    /// Starts the trail over, so that the next move emits a particle at its start.
+    pub fn reset(&mut self) {
+        self.until_next = 0.0;
+    }
+
+    /// Advances the trail along a move of `length` units with particles `interval` units apart.
+    ///
+    /// Returns the distance along the move and the sequence number of each particle to emit.
+    pub fn advance(&mut self, length: f32, interval: f32) -> impl Iterator<Item = (f32, u32)> {
+        let first = self.until_next;
+        let first_count = self.count;
+
+        let emitted = if interval <= 0.0 || first >= length {
+            0
+        } else {
+            ((length - first) / interval) as u32 + 1
+        };
+
+        self.until_next = first + emitted as f32 * interval - length;
+        self.count = self.count.wrapping_add(emitted);
+
+        (0..emitted).map(move |i| (first + i as f32 * interval, first_count.wrapping_add(i)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_trail_kind() {
+        assert_eq!(trail_kind(ModelFlags::ROCKET), Some(TrailKind::Rocket));
+        assert_eq!(
+            trail_kind(ModelFlags::ROTATE | ModelFlags::GRENADE),
+            Some(TrailKind::Smoke)
+        );
+        assert_eq!(
+            trail_kind(ModelFlags::ROCKET | ModelFlags::ZOMGIB),
+            Some(TrailKind::BloodSlight)
+        );
+        assert_eq!(trail_kind(ModelFlags::ROTATE), None);
+    }
+
+    #[test]
+    fn test_trail_spacing_carries_over() {
+        let mut state = TrailState::new();
+
+        let points: Vec<_> = state.advance(7.0, 3.0).collect();
+        assert_eq!(points, vec![(0.0, 0), (3.0, 1), (6.0, 2)]);
+
+        // 2 units remain before the next particle
+        assert_eq!(state.advance(1.5, 3.0).count(), 0);
+        let points: Vec<_> = state.advance(4.0, 3.0).collect();
+        assert_eq!(points, vec![(0.5, 3), (3.5, 4)]);
+
+        // a stationary entity emits nothing
+        assert_eq!(state.advance(0.0, 3.0).count(), 0);
+
+        state.reset();
+        let points: Vec<_> = state.advance(1.0, 3.0).collect();
+        assert_eq!(points, vec![(0.0, 5)]);
+    }
+}
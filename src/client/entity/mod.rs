@@ -18,12 +18,19 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+pub mod attack;
+pub mod beam;
+pub mod effects;
 pub mod particle;
-
-use crate::common::{
-    alloc::LinkedSlab,
-    engine,
-    net::{EntityEffects, EntityState, EntityUpdate},
+pub mod predict;
+
+use crate::{
+    client::entity::effects::TrailState,
+    common::{
+        alloc::LinkedSlab,
+        engine,
+        net::{EntityEffects, EntityState, EntityUpdate},
+    },
 };
 
 use cgmath::{Deg, Vector3};
@@ -35,10 +42,17 @@ pub const MAX_BEAMS: usize = 24;
 pub const MAX_TEMP_ENTITIES: usize = 64;
 pub const MAX_STATIC_ENTITIES: usize = 128;
 
+// alias models are animated at the server tick rate, so frame blends never take longer than this
+const MAX_FRAME_INTERVAL_MS: i64 = 100;
+
 #[derive(Debug)]
 pub struct ClientEntity {
     pub force_link: bool,
     pub baseline: EntityState,
+
+    // the most recently received state, with fields missing from the update taken from the baseline
+    msg_state: EntityState,
+
     pub msg_time: Duration,
     pub msg_origins: [Vector3<f32>; 2],
     pub origin: Vector3<f32>,
@@ -47,11 +61,24 @@ pub struct ClientEntity {
     pub model_id: usize,
     model_changed: bool,
     pub frame_id: usize,
+
+    // the frame shown before frame_id, the time the change to frame_id began and how long it
+    // takes to complete
+    prev_frame_id: usize,
+    frame_start: Duration,
+    frame_interval: Duration,
+
     pub skin_id: usize,
     colormap: Option<u8>,
     pub sync_base: Duration,
     pub effects: EntityEffects,
     pub light_id: Option<usize>,
+
+    // the world light sampled at the entity's origin, smoothed as it moves
+    light: Option<[f32; 3]>,
+
+    // the spacing of the trail left by the entity's model, if any
+    pub trail: TrailState,
     // vis_frame: usize,
 }
 
@@ -60,6 +87,7 @@ impl ClientEntity {
         ClientEntity {
             force_link: false,
             baseline: baseline.clone(),
+            msg_state: baseline.clone(),
             msg_time: Duration::zero(),
             msg_origins: [Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)],
             origin: baseline.origin,
@@ -71,11 +99,19 @@ impl ClientEntity {
             model_id: baseline.model_id,
             model_changed: false,
             frame_id: baseline.frame_id,
+            prev_frame_id: baseline.frame_id,
+            frame_start: Duration::zero(),
+            frame_interval: Duration::milliseconds(MAX_FRAME_INTERVAL_MS),
             skin_id: baseline.skin_id,
-            colormap: None,
+            colormap: match baseline.colormap {
+                0 => None,
+                c => Some(c),
+            },
             sync_base: Duration::zero(),
             effects: baseline.effects,
             light_id: None,
+            light: None,
+            trail: TrailState::new(),
         }
     }
 
@@ -83,6 +119,7 @@ impl ClientEntity {
         ClientEntity {
             force_link: false,
             baseline: EntityState::uninitialized(),
+            msg_state: EntityState::uninitialized(),
             msg_time: Duration::zero(),
             msg_origins: [Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)],
             origin: Vector3::new(0.0, 0.0, 0.0),
@@ -94,11 +131,16 @@ impl ClientEntity {
             model_id: 0,
             model_changed: false,
             frame_id: 0,
+            prev_frame_id: 0,
+            frame_start: Duration::zero(),
+            frame_interval: Duration::milliseconds(MAX_FRAME_INTERVAL_MS),
             skin_id: 0,
             colormap: None,
             sync_base: Duration::zero(),
             effects: EntityEffects::empty(),
             light_id: None,
+            light: None,
+            trail: TrailState::new(),
         }
     }
 
@@ -109,6 +151,7 @@ impl ClientEntity {
     pub fn update(&mut self, msg_times: [Duration; 2], update: EntityUpdate) {
         // enable lerping
         self.force_link = false;
+        self.model_changed = false;
 
         if update.no_lerp || self.msg_time != msg_times[1] {
             self.force_link = true;
@@ -130,12 +173,35 @@ impl ClientEntity {
             self.model_id = new_state.model_id;
         }
 
-        self.frame_id = new_state.frame_id;
+        if self.frame_id != new_state.frame_id {
+            // blend over the time since the last frame change, which the client interpolates
+            // through while it catches up to the newest message
+            let interval = msg_times[1] - self.frame_start;
+            let max_interval = Duration::milliseconds(MAX_FRAME_INTERVAL_MS);
+            self.frame_interval = if interval > Duration::zero() && interval < max_interval {
+                interval
+            } else {
+                max_interval
+            };
+            self.frame_start = msg_times[1];
+            self.prev_frame_id = self.frame_id;
+            self.frame_id = new_state.frame_id;
+        }
+
         self.skin_id = new_state.skin_id;
         self.effects = new_state.effects;
-        self.colormap = update.colormap;
+
+        // colormap 0 uses the model's own colors
+        self.colormap = match new_state.colormap {
+            0 => None,
+            c => Some(c),
+        };
+
+        self.msg_state = new_state;
 
         if self.force_link {
+            self.trail.reset();
+            self.prev_frame_id = self.frame_id;
             self.msg_origins[1] = self.msg_origins[0];
             self.origin = self.msg_origins[0];
             self.msg_angles[1] = self.msg_angles[0];
@@ -143,6 +209,18 @@ impl ClientEntity {
         }
     }
 
+    /// Replaces the baseline that future updates are applied against.
+    pub fn set_baseline(&mut self, baseline: EntityState) {
+        self.baseline = baseline;
+    }
+
+    /// Returns the most recent state received from the server.
+    ///
+    /// Fields that were omitted from the last update are filled in from the baseline.
+    pub fn msg_state(&self) -> &EntityState {
+        &self.msg_state
+    }
+
     /// Returns the timestamp of the last message that updated this entity.
     pub fn msg_time(&self) -> Duration {
         self.msg_time
@@ -179,6 +257,31 @@ impl ClientEntity {
         self.model_id
     }
 
+    /// Switches this entity to a new model and frame without blending.
+    ///
+    /// This is for entities animated by the client rather than by server updates, like the view
+    /// weapon. Frames of the old model can't be blended into the new one, so the new frame is
+    /// shown immediately.
+    pub fn set_model(&mut self, model_id: usize, frame_id: usize) {
+        self.model_id = model_id;
+        self.frame_id = frame_id;
+        self.prev_frame_id = frame_id;
+    }
+
+    /// Changes this entity's animation frame at `time`, blending from the current frame.
+    ///
+    /// Like `set_model`, this is for entities animated by the client.
+    pub fn set_frame(&mut self, frame_id: usize, time: Duration) {
+        if frame_id == self.frame_id {
+            return;
+        }
+
+        self.frame_start = time;
+        self.frame_interval = Duration::milliseconds(MAX_FRAME_INTERVAL_MS);
+        self.prev_frame_id = self.frame_id;
+        self.frame_id = frame_id;
+    }
+
     pub fn get_frame_id(&self) -> usize {
         self.frame_id
     }
@@ -186,6 +289,38 @@ impl ClientEntity {
     pub fn get_skin_id(&self) -> usize {
         self.skin_id
     }
+
+    /// Returns the previous animation frame and how far the entity has blended from it to the
+    /// current frame at the given time, from 0 to 1.
+    pub fn frame_blend(&self, time: Duration) -> (usize, f32) {
+        let elapsed = engine::duration_to_f32(time - self.frame_start);
+        let interval = engine::duration_to_f32(self.frame_interval);
+        (self.prev_frame_id, (elapsed / interval).max(0.0).min(1.0))
+    }
+
+    /// Returns the light sampled from the world at this entity's origin, if any.
+    pub fn light(&self) -> Option<[f32; 3]> {
+        self.light
+    }
+
+    /// Moves this entity's light toward `target` by the fraction `blend`.
+    ///
+    /// If the entity has no light yet, it takes on `target` immediately.
+    pub fn update_light(&mut self, target: [f32; 3], blend: f32) {
+        self.light = Some(match self.light {
+            Some(l) => [
+                l[0] + (target[0] - l[0]) * blend,
+                l[1] + (target[1] - l[1]) * blend,
+                l[2] + (target[2] - l[2]) * blend,
+            ],
+            None => target,
+        });
+    }
+
+    /// Discards this entity's light so that it is drawn with constant light.
+    pub fn clear_light(&mut self) {
+        self.light = None;
+    }
 }
 
 /// A descriptor used to spawn dynamic lights.
@@ -205,6 +340,9 @@ pub struct LightDesc {
 
     /// Time-to-live of the light.
     pub ttl: Duration,
+
+    /// The light style that animates the radius of the light, if any.
+    pub style: Option<u8>,
 }
 
 /// A dynamic point light.
@@ -216,6 +354,7 @@ pub struct Light {
     min_radius: Option<f32>,
     spawned: Duration,
     ttl: Duration,
+    style: Option<u8>,
 }
 
 impl Light {
@@ -228,6 +367,7 @@ impl Light {
             min_radius: desc.min_radius,
             spawned: time,
             ttl: desc.ttl,
+            style: desc.style,
         }
     }
 
@@ -253,6 +393,11 @@ impl Light {
         radius
     }
 
+    /// Return the light style that animates the light's radius, if any.
+    pub fn style(&self) -> Option<u8> {
+        self.style
+    }
+
     /// Returns `true` if the light should be retained at the specified time.
     pub fn retain(&mut self, time: Duration) -> bool {
         self.spawned + self.ttl > time
@@ -322,3 +467,151 @@ pub struct Beam {
     pub start: Vector3<f32>,
     pub end: Vector3<f32>,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn empty_update(ent_id: u16) -> EntityUpdate {
+        EntityUpdate {
+            ent_id,
+            model_id: None,
+            frame_id: None,
+            colormap: None,
+            skin_id: None,
+            effects: None,
+            origin_x: None,
+            pitch: None,
+            origin_y: None,
+            yaw: None,
+            origin_z: None,
+            roll: None,
+            no_lerp: false,
+        }
+    }
+
+    fn baseline() -> EntityState {
+        EntityState {
+            origin: Vector3::new(1.0, 2.0, 3.0),
+            angles: Vector3::new(Deg(0.0), Deg(90.0), Deg(0.0)),
+            model_id: 2,
+            frame_id: 4,
+            colormap: 1,
+            skin_id: 0,
+            effects: EntityEffects::empty(),
+        }
+    }
+
+    #[test]
+    fn test_update_fills_missing_fields_from_baseline() {
+        let mut ent = ClientEntity::from_baseline(baseline());
+        let msg_times = [Duration::milliseconds(100), Duration::zero()];
+
+        let mut update = empty_update(1);
+        update.origin_x = Some(5.0);
+        update.frame_id = Some(7);
+        ent.update(msg_times, update);
+
+        let state = ent.msg_state();
+        assert_eq!(state.origin, Vector3::new(5.0, 2.0, 3.0));
+        assert_eq!(state.angles, baseline().angles);
+        assert_eq!(state.model_id, 2);
+        assert_eq!(state.frame_id, 7);
+        assert_eq!(ent.colormap(), Some(1));
+
+        // fields are always deltas against the baseline, not the previous update
+        ent.update(
+            [Duration::milliseconds(200), Duration::milliseconds(100)],
+            empty_update(1),
+        );
+        assert_eq!(ent.msg_state(), &baseline());
+    }
+
+    #[test]
+    fn test_frame_blend() {
+        let mut ent = ClientEntity::from_baseline(baseline());
+        ent.update(
+            [Duration::milliseconds(100), Duration::zero()],
+            empty_update(1),
+        );
+        assert_eq!(ent.frame_blend(Duration::milliseconds(100)), (4, 1.0));
+
+        let mut update = empty_update(1);
+        update.frame_id = Some(5);
+        ent.update(
+            [Duration::milliseconds(200), Duration::milliseconds(100)],
+            update,
+        );
+        assert_eq!(ent.frame_blend(Duration::milliseconds(100)), (4, 0.0));
+        let (prev, blend) = ent.frame_blend(Duration::milliseconds(150));
+        assert_eq!(prev, 4);
+        assert!((blend - 0.5).abs() < 1e-6);
+        assert_eq!(ent.frame_blend(Duration::milliseconds(300)), (4, 1.0));
+
+        // missed updates force a link, which skips the blend
+        let mut update = empty_update(1);
+        update.frame_id = Some(6);
+        ent.update(
+            [Duration::milliseconds(400), Duration::milliseconds(300)],
+            update,
+        );
+        assert_eq!(ent.frame_blend(Duration::milliseconds(300)).0, 6);
+    }
+
+    #[test]
+    fn test_set_frame_blends() {
+        let mut ent = ClientEntity::uninitialized();
+        ent.set_model(3, 1);
+        assert_eq!(ent.frame_blend(Duration::zero()).0, 1);
+
+        ent.set_frame(2, Duration::milliseconds(500));
+        let (prev, blend) = ent.frame_blend(Duration::milliseconds(550));
+        assert_eq!(prev, 1);
+        assert!((blend - 0.5).abs() < 1e-6);
+
+        // setting the same frame again doesn't restart the blend
+        ent.set_frame(2, Duration::milliseconds(550));
+        assert_eq!(ent.frame_blend(Duration::milliseconds(600)), (1, 1.0));
+
+        // a new model starts on its frame
+        ent.set_model(4, 0);
+        assert_eq!(ent.frame_blend(Duration::milliseconds(600)).0, 0);
+    }
+
+    #[test]
+    fn test_update_model_changed() {
+        let mut ent = ClientEntity::uninitialized();
+
+        let mut update = empty_update(1);
+        update.model_id = Some(3);
+        ent.update(
+            [Duration::milliseconds(100), Duration::zero()],
+            update.clone(),
+        );
+        assert!(ent.model_changed());
+        assert_eq!(ent.model_id, 3);
+
+        ent.update(
+            [Duration::milliseconds(200), Duration::milliseconds(100)],
+            update,
+        );
+        assert!(!ent.model_changed());
+    }
+
+    #[test]
+    fn test_update_light() {
+        let mut ent = ClientEntity::uninitialized();
+        assert_eq!(ent.light(), None);
+
+        // the first sample is taken as is
+        ent.update_light([0.5, 0.5, 0.5], 0.25);
+        assert_eq!(ent.light(), Some([0.5, 0.5, 0.5]));
+
+        // later samples are blended in
+        ent.update_light([1.0, 0.0, 0.5], 0.5);
+        assert_eq!(ent.light(), Some([0.75, 0.25, 0.5]));
+
+        ent.clear_light();
+        assert_eq!(ent.light(), None);
+    }
+}
@@ -21,21 +21,17 @@
 use std::ops::RangeInclusive;
 
 use crate::{
-    client::ClientEntity,
+    client::{entity::effects::TrailState, ClientEntity},
     common::{
-        alloc::LinkedSlab,
         engine,
         math::{self, VERTEX_NORMAL_COUNT},
+        random::GameRng,
     },
 };
 
 use cgmath::{InnerSpace as _, Vector3, Zero as _};
 use chrono::Duration;
-use rand::{
-    distributions::{Distribution as _, Uniform},
-    rngs::SmallRng,
-    SeedableRng,
-};
+use rand::distributions::{Distribution as _, Uniform};
 
 lazy_static! {
     static ref COLOR_RAMP_EXPLOSION_FAST: ColorRamp = ColorRamp {
@@ -208,6 +204,7 @@ impl Particle {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TrailKind {
     Rocket = 0,
     Smoke = 1,
@@ -218,16 +215,39 @@ pub enum TrailKind {
     Vore = 6,
 }
 
+impl TrailKind {
+    /// Returns the distance between particles in units.
+    pub fn interval(self) -> f32 {
+        match self {
+            TrailKind::BloodSlight => 6.0,
+            _ => 3.0,
+        }
+    }
+
+    /// Returns how long each particle lasts.
+    pub fn ttl(self) -> Duration {
+        match self {
+            TrailKind::TracerGreen | TrailKind::TracerRed => Duration::milliseconds(500),
+            TrailKind::Vore => Duration::milliseconds(300),
+            _ => Duration::seconds(2),
+        }
+    }
+}
+
 /// A list of particles.
 ///
-/// Space for new particles is allocated from an internal [`Slab`](slab::Slab) of fixed
-/// size.
+/// Live particles are stored contiguously in a `Vec` which is allocated once at its full
+/// capacity, so creating particles never allocates and the renderer can upload them in a single
+/// pass.
 pub struct Particles {
-    // allocation pool
-    slab: LinkedSlab<Particle>,
+    // live particles
+    particles: Vec<Particle>,
+
+    // maximum number of live particles
+    capacity: usize,
 
     // random number generator
-    rng: SmallRng,
+    rng: GameRng,
 
     angle_velocities: [Vector3<f32>; VERTEX_NORMAL_COUNT],
 }
@@ -235,29 +255,38 @@ pub struct Particles {
 impl Particles {
     /// Create a new particle list with the given capacity.
     ///
-    /// This determines the capacity of both the underlying `Slab` and the set of
-    /// live particles.
+    /// This determines the maximum number of live particles, up to `MAX_PARTICLES`.
     pub fn with_capacity(capacity: usize) -> Particles {
-        lazy_static! {
-            // avelocities initialized with (rand() & 255) * 0.01;
-            static ref VELOCITY_DISTRIBUTION: Uniform<f32> = Uniform::new(0.0, 2.56);
-        }
-
-        let slab = LinkedSlab::with_capacity(capacity.min(MAX_PARTICLES));
-        let rng = SmallRng::from_entropy();
-        let angle_velocities = [Vector3::zero(); VERTEX_NORMAL_COUNT];
+        let capacity = capacity.min(MAX_PARTICLES);
 
         let mut particles = Particles {
-            slab,
-            rng,
-            angle_velocities,
+            particles: Vec::with_capacity(capacity),
+            capacity,
+            rng: GameRng::from_entropy(),
+            angle_velocities: [Vector3::zero(); VERTEX_NORMAL_COUNT],
         };
+        particles.init_angle_velocities();
 
-        for i in 0..angle_velocities.len() {
-            particles.angle_velocities[i] = particles.random_vector3(&VELOCITY_DISTRIBUTION);
+        particles
+    }
+
+    /// Restarts the random numbers used by particle effects from `seed`.
+    ///
+    /// Two particle lists reseeded alike produce the same effects from the same calls.
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = GameRng::new(seed);
+        self.init_angle_velocities();
+    }
+
+    fn init_angle_velocities(&mut self) {
+        lazy_static! {
+            // avelocities initialized with (rand() & 255) * 0.01;
+            static ref VELOCITY_DISTRIBUTION: Uniform<f32> = Uniform::new(0.0, 2.56);
         }
 
-        particles
+        for i in 0..self.angle_velocities.len() {
+            self.angle_velocities[i] = self.random_vector3(&VELOCITY_DISTRIBUTION);
+        }
     }
 
     /// Insert a particle into the live list.
@@ -265,22 +294,22 @@ impl Particles {
     // the original engine ignores new particles if at capacity, but it's not ideal
     pub fn insert(&mut self, particle: Particle) -> bool {
         // check capacity
-        if self.slab.len() == self.slab.capacity() {
+        if self.particles.len() == self.capacity {
             return false;
         }
 
         // insert it
-        self.slab.insert(particle);
+        self.particles.push(particle);
         true
     }
 
     /// Clears all particles.
     pub fn clear(&mut self) {
-        self.slab.clear();
+        self.particles.clear();
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &Particle> {
-        self.slab.iter()
+        self.particles.iter()
     }
 
     /// Update all live particles, deleting any that are expired.
@@ -289,8 +318,16 @@ impl Particles {
     /// function's return value indicates whether the particle should be retained
     /// or not.
     pub fn update(&mut self, time: Duration, frame_time: Duration, sv_gravity: f32) {
-        self.slab
-            .retain(|_, particle| particle.update(time, frame_time, sv_gravity));
+        // compact live particles toward the front, preserving their order
+        let mut live = 0;
+        for i in 0..self.particles.len() {
+            if self.particles[i].update(time, frame_time, sv_gravity) {
+                self.particles.swap(live, i);
+                live += 1;
+            }
+        }
+
+        self.particles.truncate(live);
     }
 
     fn scatter(&mut self, origin: Vector3<f32>, scatter_distr: &Uniform<f32>) -> Vector3<f32> {
@@ -586,15 +623,16 @@ impl Particles {
 
     /// Create a particle trail between two points.
     ///
-    /// Used for rocket fire/smoke trails, blood spatter, and projectile tracers.
-    /// If `sparse` is true, the interval between particles is increased by 3 units.
+    /// Used for rocket fire/smoke trails, blood spatter, and projectile tracers. `state` carries
+    /// the spacing of the trail over from the previous segment, so an entity should keep the same
+    /// state from frame to frame.
     pub fn create_trail(
         &mut self,
         time: Duration,
         start: Vector3<f32>,
         end: Vector3<f32>,
         kind: TrailKind,
-        sparse: bool,
+        state: &mut TrailState,
     ) {
         use TrailKind::*;
 
@@ -606,18 +644,13 @@ impl Particles {
         }
 
         let distance = (end - start).magnitude();
-        let direction = (end - start).normalize();
-
-        // particle interval in units
-        let interval = if sparse { 3.0 } else { 1.0 }
-            + match kind {
-                BloodSlight => 3.0,
-                _ => 0.0,
-            };
-
-        let ttl = Duration::seconds(2);
+        if distance == 0.0 {
+            return;
+        }
+        let direction = (end - start) / distance;
+        let ttl = kind.ttl();
 
-        for step in 0..(distance / interval) as i32 {
+        for (offset, step) in state.advance(distance, kind.interval()) {
             let frame_skip = FRAME_SKIP_DISTRIBUTION.sample(&mut self.rng);
             let particle_kind = match kind {
                 Rocket => ParticleKind::Fire { frame_skip },
@@ -631,8 +664,10 @@ impl Particles {
             let scatter = self.random_vector3(&SCATTER_DISTRIBUTION);
 
             let origin = start
-                + direction * interval
+                + direction * offset
                 + match kind {
+                    // tracers are laid exactly along the path
+                    TracerGreen | TracerRed => Vector3::zero(),
                     // vore scatter is [-16, 15] in original
                     // this gives range of ~[-16, 16]
                     Vore => scatter * 5.33,
@@ -715,4 +750,19 @@ mod tests {
             .zip(expected.iter())
             .for_each(|(p1, p2)| assert!(particles_eq(p1, p2)));
     }
+
+    #[test]
+    fn test_reseed_reproduces_effects() {
+        let mut a = Particles::with_capacity(MAX_PARTICLES);
+        let mut b = Particles::with_capacity(MAX_PARTICLES);
+        a.reseed(5);
+        b.reseed(5);
+
+        let origin = Vector3::new(64.0, 0.0, 32.0);
+        a.create_explosion(Duration::zero(), origin);
+        b.create_explosion(Duration::zero(), origin);
+
+        assert!(a.iter().count() > 0);
+        assert!(a.iter().zip(b.iter()).all(|(p1, p2)| particles_eq(p1, p2)));
+    }
 }
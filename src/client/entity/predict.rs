@@ -0,0 +1,346 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Client-side prediction of the player's movement.
+//!
+//! Every move sent to the server is also kept here until the server is assumed to have applied it.
+//! The server doesn't acknowledge individual moves, so a move counts as applied once a round trip
+//! (`cl_predictping`) has passed since it was sent. Each frame, the moves still outstanding are
+//! replayed on top of the last position received from the server. When a server update disagrees
+//! with the prediction, the difference is smoothed out over a short time rather than jolting the
+//! view.
+//!
+//! Moving brush entities aren't part of the prediction, so a player riding a lift or train would
+//! stay put between server updates and then jump with each one. Instead, the view is carried along
+//! with the platform as it is drawn, by the offset of its interpolated position from its position
+//! in the last update.
+
+use std::collections::VecDeque;
+
+use crate::common::{
+    bsp::BspError,
+    engine,
+    pmove::{MoveCmd, PlayerMove, PlayerState},
+};
+
+use cgmath::{InnerSpace, Vector3, Zero};
+use chrono::Duration;
+
+// moves older than this are dropped even if they haven't been acknowledged
+const MAX_PENDING_MOVES: usize = 128;
+
+// corrections larger than this are assumed to be teleports and aren't smoothed
+const MAX_CORRECTION: f32 = 64.0;
+
+// the rate at which prediction errors are smoothed out, per second
+const CORRECTION_RATE: f32 = 10.0;
+
+// the player's bounding box relative to its origin
+const PLAYER_HALF_WIDTH: f32 = 16.0;
+const PLAYER_MINS_Z: f32 = -24.0;
+
+// how far the player's feet may be from the top of a platform to count as standing on it
+const GROUND_EPSILON: f32 = 2.0;
+
+/// Returns `true` if a player at `origin` is standing on the box from `min` to `max`.
+pub fn stands_on(origin: Vector3<f32>, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+    origin.x + PLAYER_HALF_WIDTH > min.x
+        && origin.x - PLAYER_HALF_WIDTH < max.x
+        && origin.y + PLAYER_HALF_WIDTH > min.y
+        && origin.y - PLAYER_HALF_WIDTH < max.y
+        && (origin.z + PLAYER_MINS_Z - max.z).abs() <= GROUND_EPSILON
+}
+
+#[derive(Debug)]
+pub struct Prediction {
+    // moves that the server may not have applied yet, with the time each was sent
+    pending: VecDeque<(Duration, MoveCmd)>,
+
+    // time elapsed since prediction began, used to timestamp moves
+    time: Duration,
+
+    // the server time of the last authoritative update
+    server_time: Duration,
+
+    // whether jump was held in the last move the server applied
+    server_jump_held: bool,
+
+    predicted: Option<PlayerState>,
+
+    // offset from the predicted origin to where the view was before the last correction
+    error: Vector3<f32>,
+
+    // offset of the platform the player is riding from its position in the last update
+    platform: Vector3<f32>,
+}
+
+impl Prediction {
+    pub fn new() -> Prediction {
+        Prediction {
+            pending: VecDeque::new(),
+            time: Duration::zero(),
+            server_time: Duration::zero(),
+            server_jump_held: false,
+            predicted: None,
+            error: Vector3::zero(),
+            platform: Vector3::zero(),
+        }
+    }
+
+    /// Discards all predicted state, leaving the server's position in control of the view.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.predicted = None;
+        self.error = Vector3::zero();
+        self.platform = Vector3::zero();
+    }
+
+    /// Advances the prediction clock and decays any outstanding correction.
+    pub fn advance(&mut self, frame_time: Duration) {
+        self.time = self.time + frame_time;
+        self.error *= (-CORRECTION_RATE * engine::duration_to_f32(frame_time)).exp();
+    }
+
+    /// Records a move sent to the server.
+    pub fn add_move(&mut self, cmd: MoveCmd) {
+        if self.pending.len() >= MAX_PENDING_MOVES {
+            self.pending.pop_front();
+        }
+
+        self.pending.push_back((self.time, cmd));
+    }
+
+    /// Predicts the player's current state.
+    ///
+    /// `server` is the player's state as of the server update at `server_time`, and `latency` is
+    /// the assumed round trip time to the server. `platform` is the offset of the platform the
+    /// player stands on from its position in that update, or zero if there is none.
+    ///
+    /// The traces made by the newest move are kept by `pmove`. See `PlayerMove::take_traces`.
+    pub fn update(
+        &mut self,
+        pmove: &PlayerMove,
+        server: PlayerState,
+        server_time: Duration,
+        latency: Duration,
+        platform: Vector3<f32>,
+    ) -> Result<(), BspError> {
+        let new_update = server_time != self.server_time;
+        if new_update {
+            // moves sent more than a round trip ago have been applied by the server
+            let acked_before = self.time - latency;
+            while let Some(&(sent, cmd)) = self.pending.front() {
+                if sent > acked_before {
+                    break;
+                }
+
+                self.server_jump_held = cmd.jump;
+                self.pending.pop_front();
+            }
+
+            self.server_time = server_time;
+        }
+
+        let mut state = server;
+        state.jump_released = !self.server_jump_held;
+        for (i, (_, cmd)) in self.pending.iter().enumerate() {
+            // the older moves were logged when they were new
+            if i + 1 == self.pending.len() {
+                pmove.log_traces();
+            }
+
+            state = pmove.run(&state, cmd)?;
+        }
+
+        if new_update {
+            // keep the view where it was and let the difference fade out. the platform offset is
+            // reset by each update, but the platform's movement is already in the new origin.
+            if let Some(old) = self.predicted {
+                let error = old.origin + self.error + self.platform - (state.origin + platform);
+                self.error = if error.magnitude() < MAX_CORRECTION {
+                    error
+                } else {
+                    Vector3::zero()
+                };
+            }
+        }
+
+        self.predicted = Some(state);
+        self.platform = platform;
+        Ok(())
+    }
+
+    /// Returns the predicted origin of the player, if prediction is active.
+    pub fn origin(&self) -> Option<Vector3<f32>> {
+        self.predicted
+            .map(|p| p.origin + self.error + self.platform)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::pmove::test::floor_move;
+    use cgmath::Deg;
+
+    fn forward_cmd() -> MoveCmd {
+        MoveCmd {
+            angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+            forward_move: 400.0,
+            side_move: 0.0,
+            up_move: 0.0,
+            jump: false,
+            duration: 0.02,
+        }
+    }
+
+    #[test]
+    fn test_acknowledged_moves_are_dropped() {
+        let pmove = floor_move();
+        let server = PlayerState::new(Vector3::new(0.0, 0.0, 1.0), Vector3::zero(), true);
+        let latency = Duration::milliseconds(100);
+        let mut pred = Prediction::new();
+
+        for _ in 0..10 {
+            pred.add_move(forward_cmd());
+            pred.advance(Duration::milliseconds(20));
+        }
+
+        // only moves sent within the last round trip are replayed
+        pred.update(
+            &pmove,
+            server,
+            Duration::milliseconds(50),
+            latency,
+            Vector3::zero(),
+        )
+        .unwrap();
+        assert_eq!(pred.pending.len(), 4);
+        assert!(pred.origin().unwrap().x > server.origin.x);
+
+        // nothing more is acknowledged until the next update arrives
+        pred.advance(Duration::milliseconds(60));
+        pred.update(
+            &pmove,
+            server,
+            Duration::milliseconds(50),
+            latency,
+            Vector3::zero(),
+        )
+        .unwrap();
+        assert_eq!(pred.pending.len(), 4);
+
+        pred.update(
+            &pmove,
+            server,
+            Duration::milliseconds(100),
+            latency,
+            Vector3::zero(),
+        )
+        .unwrap();
+        assert_eq!(pred.pending.len(), 1);
+    }
+
+    #[test]
+    fn test_large_correction_snaps() {
+        let pmove = floor_move();
+        let latency = Duration::milliseconds(100);
+        let mut pred = Prediction::new();
+
+        let here = PlayerState::new(Vector3::new(0.0, 0.0, 1.0), Vector3::zero(), true);
+        pred.update(
+            &pmove,
+            here,
+            Duration::milliseconds(50),
+            latency,
+            Vector3::zero(),
+        )
+        .unwrap();
+
+        // a small correction is smoothed
+        let near = PlayerState::new(Vector3::new(8.0, 0.0, 1.0), Vector3::zero(), true);
+        pred.update(
+            &pmove,
+            near,
+            Duration::milliseconds(100),
+            latency,
+            Vector3::zero(),
+        )
+        .unwrap();
+        assert!(pred.origin().unwrap().x < 1.0);
+
+        // but a teleport is not
+        let far = PlayerState::new(Vector3::new(1024.0, 0.0, 1.0), Vector3::zero(), true);
+        pred.update(
+            &pmove,
+            far,
+            Duration::milliseconds(150),
+            latency,
+            Vector3::zero(),
+        )
+        .unwrap();
+        assert_eq!(pred.origin().unwrap().x, 1024.0);
+    }
+
+    #[test]
+    fn test_stands_on() {
+        let min = Vector3::new(-64.0, -64.0, -16.0);
+        let max = Vector3::new(64.0, 64.0, 0.0);
+
+        assert!(stands_on(Vector3::new(0.0, 0.0, 24.0), min, max));
+        assert!(stands_on(Vector3::new(75.0, 0.0, 25.0), min, max));
+
+        // off the edge, above, and inside
+        assert!(!stands_on(Vector3::new(90.0, 0.0, 24.0), min, max));
+        assert!(!stands_on(Vector3::new(0.0, 0.0, 40.0), min, max));
+        assert!(!stands_on(Vector3::new(0.0, 0.0, 8.0), min, max));
+    }
+
+    #[test]
+    fn test_platform_carries_view() {
+        let pmove = floor_move();
+        let latency = Duration::milliseconds(100);
+        let mut pred = Prediction::new();
+
+        let start = PlayerState::new(Vector3::new(0.0, 0.0, 1.0), Vector3::zero(), true);
+        pred.update(
+            &pmove,
+            start,
+            Duration::milliseconds(50),
+            latency,
+            Vector3::zero(),
+        )
+        .unwrap();
+
+        // halfway to the next update, the platform is drawn 4 units along
+        let half = Vector3::new(4.0, 0.0, 0.0);
+        pred.update(&pmove, start, Duration::milliseconds(50), latency, half)
+            .unwrap();
+        assert_eq!(pred.origin().unwrap().x, 4.0);
+
+        // the next update moves the player with the platform, and restarts its interpolation
+        // where the last update left it, so the view doesn't jump
+        let moved = PlayerState::new(Vector3::new(8.0, 0.0, 1.0), Vector3::zero(), true);
+        let behind = Vector3::new(-8.0, 0.0, 0.0);
+        pred.update(&pmove, moved, Duration::milliseconds(100), latency, behind)
+            .unwrap();
+        assert!((pred.origin().unwrap().x - 4.0).abs() < 1e-4);
+    }
+}
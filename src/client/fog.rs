@@ -0,0 +1,132 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Distance fog set by maps and the `fog` command.
+//!
+//! Maps specify fog in the worldspawn `fog` key as `"density r g b"`, and may change it during play
+//! by stuffing `fog` commands to the client. Densities use the same scale as other engines, so a
+//! map's fog looks the same here as it does elsewhere.
+
+// color used when only a density is given
+const DEFAULT_FOG_COLOR: [f32; 3] = [0.3, 0.3, 0.3];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fog {
+    /// How quickly the fog thickens with distance. A density of 0 disables fog.
+    pub density: f32,
+
+    /// The color of the fog, with each component in [0, 1].
+    pub color: [f32; 3],
+}
+
+impl Fog {
+    /// Applies the arguments of a `fog` command to `self`.
+    ///
+    /// The accepted forms are `density`, `r g b`, and `density r g b`. Any further argument (the
+    /// fade time used by some engines) is ignored and the change takes effect immediately. Returns
+    /// `None` if the arguments are malformed.
+    pub fn with_args(&self, args: &[&str]) -> Option<Fog> {
+        let values = args
+            .iter()
+            .map(|a| a.parse::<f32>().ok())
+            .collect::<Option<Vec<f32>>>()?;
+
+        let (density, color) = match values.len() {
+            1 => (values[0], self.color),
+            3 => (self.density, [values[0], values[1], values[2]]),
+            4 | 5 => (values[0], [values[1], values[2], values[3]]),
+            _ => return None,
+        };
+
+        Some(Fog {
+            density: density.max(0.0),
+            color: [
+                color[0].max(0.0).min(1.0),
+                color[1].max(0.0).min(1.0),
+                color[2].max(0.0).min(1.0),
+            ],
+        })
+    }
+
+    /// Parses the value of a worldspawn `fog` key.
+    pub fn from_worldspawn(value: &str) -> Option<Fog> {
+        Fog::default().with_args(&value.split_whitespace().collect::<Vec<_>>())
+    }
+
+    /// Returns `true` if the fog has any visible effect.
+    pub fn enabled(&self) -> bool {
+        self.density > 0.0
+    }
+}
+
+impl Default for Fog {
+    fn default() -> Fog {
+        Fog {
+            density: 0.0,
+            color: DEFAULT_FOG_COLOR,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_worldspawn() {
+        let fog = Fog::from_worldspawn("0.05 0.1 0.2 0.3").unwrap();
+        assert_eq!(fog.density, 0.05);
+        assert_eq!(fog.color, [0.1, 0.2, 0.3]);
+
+        let density_only = Fog::from_worldspawn("0.1").unwrap();
+        assert_eq!(density_only.color, DEFAULT_FOG_COLOR);
+
+        assert!(Fog::from_worldspawn("").is_none());
+        assert!(Fog::from_worldspawn("thick").is_none());
+    }
+
+    #[test]
+    fn test_with_args() {
+        let fog = Fog {
+            density: 0.5,
+            color: [1.0, 0.0, 0.0],
+        };
+
+        assert_eq!(
+            fog.with_args(&["0"]).unwrap(),
+            Fog {
+                density: 0.0,
+                color: [1.0, 0.0, 0.0],
+            }
+        );
+        assert_eq!(
+            fog.with_args(&["0", "2", "0.5"]).unwrap(),
+            Fog {
+                density: 0.5,
+                color: [0.0, 1.0, 0.5],
+            }
+        );
+        assert_eq!(
+            fog.with_args(&["0.1", "0", "0", "1", "5"]).unwrap().density,
+            0.1
+        );
+        assert!(fog.with_args(&["1", "2"]).is_none());
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! A camera that flies freely through the level.
+//!
+//! `freecam` detaches the camera from the player so that it can be flown anywhere, through walls,
+//! while the game keeps running. The movement and look controls steer the camera and the player
+//! stands still. Entities are still culled and sounds still heard from the player's position, so
+//! flying out of the player's view shows exactly what was drawn for them.
+
+use crate::common::{engine, math::Angles};
+
+use cgmath::Vector3;
+use chrono::Duration;
+
+pub struct FreeCam {
+    origin: Vector3<f32>,
+    angles: Angles,
+}
+
+impl FreeCam {
+    /// Creates a camera at the given position.
+    pub fn new(origin: Vector3<f32>, angles: Angles) -> FreeCam {
+        FreeCam { origin, angles }
+    }
+
+    pub fn origin(&self) -> Vector3<f32> {
+        self.origin
+    }
+
+    pub fn angles(&self) -> Angles {
+        self.angles
+    }
+
+    pub fn set_angles(&mut self, angles: Angles) {
+        self.angles = angles;
+    }
+
+    /// Moves the camera for `frame_time` at the given speeds in units per second.
+    ///
+    /// Forward movement follows the direction the camera faces, so moving forward while looking
+    /// up climbs. Upward movement is always along the world's vertical axis.
+    pub fn fly(&mut self, frame_time: Duration, forward: f32, side: f32, up: f32) {
+        let seconds = engine::duration_to_f32(frame_time);
+        let rotation = self.angles.mat3_quake();
+
+        // Quake's y axis points to the left
+        let velocity = rotation * Vector3::unit_x() * forward
+            + rotation * -Vector3::unit_y() * side
+            + Vector3::unit_z() * up;
+        self.origin += velocity * seconds;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use cgmath::{Deg, InnerSpace};
+
+    fn assert_near(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-3, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_fly() {
+        let mut freecam = FreeCam::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            Angles {
+                pitch: Deg(0.0),
+                roll: Deg(0.0),
+                yaw: Deg(90.0),
+            },
+        );
+
+        // facing +y, forward moves along +y and right along +x
+        freecam.fly(Duration::milliseconds(500), 200.0, 0.0, 0.0);
+        assert_near(freecam.origin(), Vector3::new(0.0, 100.0, 0.0));
+
+        freecam.fly(Duration::milliseconds(500), 0.0, 200.0, 100.0);
+        assert_near(freecam.origin(), Vector3::new(100.0, 100.0, 50.0));
+
+        // no time, no movement
+        freecam.fly(Duration::zero(), 200.0, 200.0, 200.0);
+        assert_near(freecam.origin(), Vector3::new(100.0, 100.0, 50.0));
+    }
+}
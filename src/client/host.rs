@@ -0,0 +1,291 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! The client's connection state machine.
+//!
+//! A client moves through a fixed sequence of states as it connects to a server, loads the level
+//! and plays. Every change of state goes through [`HostState::transition`], which rejects changes
+//! that make no sense from the current state, and the rest of the client asks the current state
+//! how to behave rather than checking the sign-on stage, intermission and demo playback
+//! separately.
+//!
+//! [`HostState::transition`]: enum.HostState.html#method.transition
+
+use std::{cell::Cell, fmt};
+
+use crate::common::net::SignOnStage;
+
+use thiserror::Error;
+
+/// The state of the client's connection to a game.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HostState {
+    /// Not connected to a server or playing a demo.
+    Disconnected,
+
+    /// Connected to a server, but the server hasn't begun sending the level.
+    Connecting,
+
+    /// Receiving the level from the server. The stage is between `Prespawn` and `Begin`.
+    SignOn(SignOnStage),
+
+    /// In the game.
+    Active,
+
+    /// In the game, but play has ended and the intermission or finale is shown.
+    Intermission,
+
+    /// Playing back a demo. The stage is that of the sign-on recorded in the demo.
+    DemoPlayback(SignOnStage),
+}
+
+/// Something that changes the client's state.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HostEvent {
+    /// A connection to a server was accepted.
+    Connect,
+
+    /// The server moved the client to a new sign-on stage.
+    SignOn(SignOnStage),
+
+    /// The server started the intermission, finale or a cutscene.
+    Intermission,
+
+    /// The server is changing levels and the client must load the new one.
+    Reconnect,
+
+    /// Demo playback started.
+    PlayDemo,
+
+    /// The connection closed or the demo ended.
+    Disconnect,
+}
+
+#[derive(Error, Debug, PartialEq)]
+#[error("Invalid transition from {from} on {event:?}")]
+pub struct HostStateError {
+    pub from: HostState,
+    pub event: HostEvent,
+}
+
+impl HostState {
+    /// Returns the state that follows `event`.
+    pub fn transition(self, event: HostEvent) -> Result<HostState, HostStateError> {
+        use HostEvent as E;
+        use HostState as S;
+
+        let next = match (self, event) {
+            (_, E::Disconnect) => Some(S::Disconnected),
+            (S::Disconnected, E::Connect) => Some(S::Connecting),
+            (S::Disconnected, E::PlayDemo) => Some(S::DemoPlayback(SignOnStage::Not)),
+
+            // demos replay the server's messages, sign-on included
+            (S::DemoPlayback(_), E::SignOn(stage)) => Some(S::DemoPlayback(stage)),
+            (S::DemoPlayback(_), E::Intermission) => Some(self),
+
+            (S::Connecting, E::SignOn(stage))
+            | (S::SignOn(_), E::SignOn(stage))
+            | (S::Active, E::SignOn(stage))
+            | (S::Intermission, E::SignOn(stage)) => match stage {
+                // stages only advance, except that the server may restart the sign-on to load a
+                // new level
+                SignOnStage::Not => None,
+                SignOnStage::Prespawn => Some(S::SignOn(stage)),
+                SignOnStage::Done => match self {
+                    S::SignOn(_) => Some(S::Active),
+                    _ => None,
+                },
+                _ => match self {
+                    S::SignOn(prev) if prev < stage => Some(S::SignOn(stage)),
+                    _ => None,
+                },
+            },
+
+            (S::Active, E::Intermission) | (S::Intermission, E::Intermission) => {
+                Some(S::Intermission)
+            }
+
+            (S::SignOn(_), E::Reconnect)
+            | (S::Active, E::Reconnect)
+            | (S::Intermission, E::Reconnect) => Some(S::Connecting),
+            (S::Connecting, E::Reconnect) => Some(S::Connecting),
+
+            _ => None,
+        };
+
+        next.ok_or(HostStateError { from: self, event })
+    }
+
+    /// Returns how far the level has been received.
+    pub fn signon_stage(self) -> SignOnStage {
+        match self {
+            HostState::Disconnected | HostState::Connecting => SignOnStage::Not,
+            HostState::SignOn(stage) | HostState::DemoPlayback(stage) => stage,
+            HostState::Active | HostState::Intermission => SignOnStage::Done,
+        }
+    }
+
+    /// Returns `true` if the level is loaded, so that the world can be simulated and drawn.
+    pub fn in_game(self) -> bool {
+        self.signon_stage() == SignOnStage::Done
+    }
+
+    /// Returns `true` if player input should be sent to the server.
+    ///
+    /// Input is still sent during the intermission, where it ends the intermission.
+    pub fn sends_input(self) -> bool {
+        match self {
+            HostState::Active | HostState::Intermission => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the client should wait for the server's next message rather than
+    /// carrying on without it.
+    pub fn awaits_server(self) -> bool {
+        match self {
+            HostState::Connecting | HostState::SignOn(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the player's movement and attacks should be predicted.
+    pub fn predicts(self) -> bool {
+        self == HostState::Active
+    }
+}
+
+impl fmt::Display for HostState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostState::Disconnected => write!(f, "disconnected"),
+            HostState::Connecting => write!(f, "connecting"),
+            HostState::SignOn(stage) => write!(f, "sign-on ({:?})", stage),
+            HostState::Active => write!(f, "active"),
+            HostState::Intermission => write!(f, "intermission"),
+            HostState::DemoPlayback(stage) => write!(f, "demo playback ({:?})", stage),
+        }
+    }
+}
+
+/// Applies `event` to a shared state, logging and ignoring it if the transition is invalid.
+///
+/// This is for console commands, which have no way to report an error.
+pub fn transition_shared(state: &Cell<HostState>, event: HostEvent) {
+    match state.get().transition(event) {
+        Ok(next) => state.set(next),
+        Err(e) => warn!("{}", e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(events: &[HostEvent]) -> Result<HostState, HostStateError> {
+        events
+            .iter()
+            .try_fold(HostState::Disconnected, |s, e| s.transition(*e))
+    }
+
+    #[test]
+    fn test_connect_and_sign_on() {
+        let state = run(&[
+            HostEvent::Connect,
+            HostEvent::SignOn(SignOnStage::Prespawn),
+            HostEvent::SignOn(SignOnStage::ClientInfo),
+            HostEvent::SignOn(SignOnStage::Begin),
+        ])
+        .unwrap();
+        assert_eq!(state, HostState::SignOn(SignOnStage::Begin));
+        assert!(state.awaits_server());
+        assert!(!state.sends_input());
+
+        let state = state
+            .transition(HostEvent::SignOn(SignOnStage::Done))
+            .unwrap();
+        assert_eq!(state, HostState::Active);
+        assert!(state.in_game());
+        assert!(state.sends_input());
+        assert!(state.predicts());
+    }
+
+    #[test]
+    fn test_invalid_transitions() {
+        // stages can't be skipped or repeated
+        assert!(run(&[HostEvent::Connect, HostEvent::SignOn(SignOnStage::Done)]).is_err());
+        assert!(run(&[
+            HostEvent::Connect,
+            HostEvent::SignOn(SignOnStage::Prespawn),
+            HostEvent::SignOn(SignOnStage::ClientInfo),
+            HostEvent::SignOn(SignOnStage::ClientInfo),
+        ])
+        .is_err());
+
+        // and nothing happens to a client that isn't connected
+        assert_eq!(
+            HostState::Disconnected.transition(HostEvent::Intermission),
+            Err(HostStateError {
+                from: HostState::Disconnected,
+                event: HostEvent::Intermission,
+            })
+        );
+    }
+
+    #[test]
+    fn test_level_change() {
+        let state = run(&[
+            HostEvent::Connect,
+            HostEvent::SignOn(SignOnStage::Prespawn),
+            HostEvent::SignOn(SignOnStage::ClientInfo),
+            HostEvent::SignOn(SignOnStage::Begin),
+            HostEvent::SignOn(SignOnStage::Done),
+            HostEvent::Intermission,
+        ])
+        .unwrap();
+        assert_eq!(state, HostState::Intermission);
+        assert!(state.sends_input());
+        assert!(!state.predicts());
+
+        let state = state.transition(HostEvent::Reconnect).unwrap();
+        assert_eq!(state, HostState::Connecting);
+        assert!(!state.in_game());
+    }
+
+    #[test]
+    fn test_demo_playback() {
+        let state = run(&[
+            HostEvent::PlayDemo,
+            HostEvent::SignOn(SignOnStage::Prespawn),
+            HostEvent::SignOn(SignOnStage::Done),
+            HostEvent::Intermission,
+        ])
+        .unwrap();
+        assert_eq!(state, HostState::DemoPlayback(SignOnStage::Done));
+        assert!(state.in_game());
+        assert!(!state.sends_input());
+        assert!(!state.predicts());
+
+        assert_eq!(
+            state.transition(HostEvent::Disconnect),
+            Ok(HostState::Disconnected)
+        );
+    }
+}
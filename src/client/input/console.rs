@@ -17,18 +17,39 @@
 
 use std::{cell::RefCell, rc::Rc};
 
-use crate::common::console::Console;
+use crate::{
+    client::input::{
+        context::Bindings,
+        game::{BindInput, BindTarget},
+    },
+    common::console::Console,
+};
 
 use failure::Error;
 use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode as Key, WindowEvent};
 
 pub struct ConsoleInput {
     console: Rc<RefCell<Console>>,
+    bindings: Rc<RefCell<Bindings>>,
 }
 
 impl ConsoleInput {
-    pub fn new(console: Rc<RefCell<Console>>) -> ConsoleInput {
-        ConsoleInput { console }
+    pub fn new(console: Rc<RefCell<Console>>, bindings: Rc<RefCell<Bindings>>) -> ConsoleInput {
+        ConsoleInput { console, bindings }
+    }
+
+    // runs the console context binding of `key`, if it has one
+    fn run_binding(&self, key: Key) -> bool {
+        match self.bindings.borrow().resolve(BindInput::from(key)) {
+            Some((_, BindTarget::ConsoleInput { text })) => {
+                self.console.borrow().stuff_text(text);
+                true
+            }
+
+            // actions can't be held while typing
+            Some((_, BindTarget::Action { .. })) => true,
+            None => false,
+        }
     }
 
     pub fn handle_event<T>(&self, event: Event<T>) -> Result<(), Error> {
@@ -36,6 +57,16 @@ impl ConsoleInput {
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::ReceivedCharacter(c) => self.console.borrow_mut().send_char(c)?,
 
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(key),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } if self.run_binding(key) => (),
+
                 WindowEvent::KeyboardInput {
                     input:
                         KeyboardInput {
@@ -0,0 +1,222 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Binding contexts.
+//!
+//! Each context has its own set of bindings, so the same key can do different things in the
+//! console, in menus, during demo playback and in the game. The active contexts form a stack: an
+//! input is looked up from the top of the stack down, and the first context with a binding for
+//! it wins. Modal contexts such as the console hide the contexts below them entirely.
+
+use std::{collections::HashMap, str::FromStr};
+
+use crate::client::input::game::{BindInput, BindTarget};
+
+use failure::Error;
+
+const CONTEXT_COUNT: usize = 4;
+
+/// A set of bindings that is active in some situation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BindContext {
+    /// Playing the game. This is always at the bottom of the stack.
+    Game = 0,
+
+    /// Watching a demo or spectating. Inputs without a binding here fall through to the game.
+    Demo = 1,
+
+    /// Navigating a menu.
+    Menu = 2,
+
+    /// Typing in the console.
+    Console = 3,
+}
+
+impl BindContext {
+    pub const ALL: [BindContext; CONTEXT_COUNT] = [
+        BindContext::Game,
+        BindContext::Demo,
+        BindContext::Menu,
+        BindContext::Console,
+    ];
+
+    /// Returns the name of this context as used by `in_bind`.
+    pub fn name(self) -> &'static str {
+        match self {
+            BindContext::Game => "game",
+            BindContext::Demo => "demo",
+            BindContext::Menu => "menu",
+            BindContext::Console => "console",
+        }
+    }
+
+    /// Returns `true` if this context hides the bindings of the contexts below it.
+    pub fn modal(self) -> bool {
+        match self {
+            BindContext::Game | BindContext::Demo => false,
+            BindContext::Menu | BindContext::Console => true,
+        }
+    }
+}
+
+impl FromStr for BindContext {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match BindContext::ALL
+            .iter()
+            .find(|c| c.name().eq_ignore_ascii_case(s))
+        {
+            Some(c) => Ok(*c),
+            None => bail!("\"{}\" isn't a binding context", s),
+        }
+    }
+}
+
+/// The bindings of every context, and the stack of contexts that are active.
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    maps: [HashMap<BindInput, BindTarget>; CONTEXT_COUNT],
+
+    // contexts above the game, lowest priority first
+    stack: Vec<BindContext>,
+}
+
+impl Bindings {
+    pub fn new() -> Bindings {
+        Bindings {
+            maps: Default::default(),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Binds `input` to `target` in `context`, returning the previous binding.
+    pub fn bind(
+        &mut self,
+        context: BindContext,
+        input: BindInput,
+        target: BindTarget,
+    ) -> Option<BindTarget> {
+        self.maps[context as usize].insert(input, target)
+    }
+
+    /// Removes the binding of `input` in `context`, returning it.
+    pub fn unbind(&mut self, context: BindContext, input: BindInput) -> Option<BindTarget> {
+        self.maps[context as usize].remove(&input)
+    }
+
+    /// Removes every binding in every context.
+    pub fn clear(&mut self) {
+        for map in self.maps.iter_mut() {
+            map.clear();
+        }
+    }
+
+    /// Returns the binding of `input` in `context`, ignoring the stack.
+    pub fn get(&self, context: BindContext, input: BindInput) -> Option<&BindTarget> {
+        self.maps[context as usize].get(&input)
+    }
+
+    /// Replaces the contexts above the game, lowest priority first.
+    pub fn set_stack(&mut self, contexts: &[BindContext]) {
+        self.stack.clear();
+        self.stack
+            .extend(contexts.iter().copied().filter(|c| *c != BindContext::Game));
+    }
+
+    /// Returns the context on top of the stack.
+    pub fn top(&self) -> BindContext {
+        self.stack.last().copied().unwrap_or(BindContext::Game)
+    }
+
+    /// Finds the binding of `input` in the active contexts.
+    ///
+    /// The stack is searched from the top down, stopping after the first modal context.
+    pub fn resolve(&self, input: BindInput) -> Option<(BindContext, &BindTarget)> {
+        for context in self.stack.iter().rev() {
+            if let Some(target) = self.get(*context, input) {
+                return Some((*context, target));
+            }
+
+            if context.modal() {
+                return None;
+            }
+        }
+
+        self.get(BindContext::Game, input)
+            .map(|t| (BindContext::Game, t))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use winit::event::VirtualKeyCode as Key;
+
+    fn target(s: &str) -> BindTarget {
+        BindTarget::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_bindings_resolve() {
+        let mut bindings = Bindings::new();
+        bindings.bind(BindContext::Game, Key::Space.into(), target("+jump"));
+        bindings.bind(
+            BindContext::Game,
+            Key::Grave.into(),
+            target("toggleconsole"),
+        );
+        bindings.bind(BindContext::Demo, Key::Space.into(), target("pause"));
+        bindings.bind(BindContext::Console, Key::F1.into(), target("clear"));
+
+        let resolve = |b: &Bindings, k: Key| b.resolve(k.into()).map(|(c, t)| (c, t.to_string()));
+
+        assert_eq!(
+            resolve(&bindings, Key::Space),
+            Some((BindContext::Game, "+jump".to_owned()))
+        );
+
+        // demo bindings take priority, but others fall through to the game
+        bindings.set_stack(&[BindContext::Demo]);
+        assert_eq!(
+            resolve(&bindings, Key::Space),
+            Some((BindContext::Demo, "\"pause\"".to_owned()))
+        );
+        assert_eq!(
+            resolve(&bindings, Key::Grave),
+            Some((BindContext::Game, "\"toggleconsole\"".to_owned()))
+        );
+
+        // the console hides everything below it
+        bindings.set_stack(&[BindContext::Demo, BindContext::Console]);
+        assert_eq!(bindings.top(), BindContext::Console);
+        assert_eq!(
+            resolve(&bindings, Key::F1),
+            Some((BindContext::Console, "\"clear\"".to_owned()))
+        );
+        assert_eq!(resolve(&bindings, Key::Space), None);
+    }
+
+    #[test]
+    fn test_bind_context_from_str() {
+        assert_eq!(
+            BindContext::from_str("Console").unwrap(),
+            BindContext::Console
+        );
+        assert!(BindContext::from_str("spectator").is_err());
+    }
+}
@@ -17,15 +17,21 @@
 
 use std::{
     cell::{Cell, RefCell},
-    collections::HashMap,
     rc::Rc,
     str::FromStr,
     string::ToString,
 };
 
-use crate::common::{
-    console::{CmdRegistry, Console},
-    parse,
+use crate::{
+    client::input::{
+        context::{BindContext, Bindings},
+        gamepad::{GamepadButton, GamepadSticks},
+        weapon::WeaponCycle,
+    },
+    common::{
+        console::{CmdRegistry, Console},
+        parse,
+    },
 };
 
 use failure::Error;
@@ -41,8 +47,9 @@ use winit::{
 
 const ACTION_COUNT: usize = 19;
 
-static INPUT_NAMES: [&'static str; 79] = [
+static INPUT_NAMES: [&'static str; 81] = [
     ",",
+    "-",
     ".",
     "/",
     "0",
@@ -55,6 +62,7 @@ static INPUT_NAMES: [&'static str; 79] = [
     "7",
     "8",
     "9",
+    "=",
     "A",
     "ALT",
     "B",
@@ -123,8 +131,9 @@ static INPUT_NAMES: [&'static str; 79] = [
     "`",
 ];
 
-static INPUT_VALUES: [BindInput; 79] = [
+static INPUT_VALUES: [BindInput; 81] = [
     BindInput::Key(Key::Comma),
+    BindInput::Key(Key::Minus),
     BindInput::Key(Key::Period),
     BindInput::Key(Key::Slash),
     BindInput::Key(Key::Key0),
@@ -137,6 +146,7 @@ static INPUT_VALUES: [BindInput; 79] = [
     BindInput::Key(Key::Key7),
     BindInput::Key(Key::Key8),
     BindInput::Key(Key::Key9),
+    BindInput::Key(Key::Equals),
     BindInput::Key(Key::A),
     BindInput::Key(Key::LAlt),
     BindInput::Key(Key::B),
@@ -366,6 +376,9 @@ pub enum BindInput {
 
     /// A direction scrolled on the mouse wheel.
     MouseWheel(MouseWheel),
+
+    /// A button pressed on a gamepad.
+    GamepadButton(GamepadButton),
 }
 
 impl ::std::convert::From<Key> for BindInput {
@@ -386,6 +399,12 @@ impl ::std::convert::From<MouseWheel> for BindInput {
     }
 }
 
+impl ::std::convert::From<GamepadButton> for BindInput {
+    fn from(src: GamepadButton) -> BindInput {
+        BindInput::GamepadButton(src)
+    }
+}
+
 impl ::std::convert::From<MouseScrollDelta> for BindInput {
     fn from(src: MouseScrollDelta) -> BindInput {
         BindInput::MouseWheel(MouseWheel::from(src))
@@ -404,12 +423,20 @@ impl FromStr for BindInput {
             }
         }
 
+        if let Ok(button) = GamepadButton::from_str(src) {
+            return Ok(BindInput::GamepadButton(button));
+        }
+
         bail!("\"{}\" isn't a valid key", src);
     }
 }
 
 impl ToString for BindInput {
     fn to_string(&self) -> String {
+        if let BindInput::GamepadButton(button) = *self {
+            return button.to_string();
+        }
+
         // this could be a binary search but it's unlikely to affect performance much
         for (i, input) in INPUT_VALUES.iter().enumerate() {
             if self == input {
@@ -477,20 +504,24 @@ impl ToString for BindTarget {
 #[derive(Clone)]
 pub struct GameInput {
     console: Rc<RefCell<Console>>,
-    bindings: Rc<RefCell<HashMap<BindInput, BindTarget>>>,
+    bindings: Rc<RefCell<Bindings>>,
     action_states: Rc<RefCell<[bool; ACTION_COUNT]>>,
     mouse_delta: (f64, f64),
+    gamepad_sticks: GamepadSticks,
     impulse: Rc<Cell<u8>>,
+    weapon_cycle: Rc<Cell<Option<WeaponCycle>>>,
 }
 
 impl GameInput {
     pub fn new(console: Rc<RefCell<Console>>) -> GameInput {
         GameInput {
             console,
-            bindings: Rc::new(RefCell::new(HashMap::new())),
+            bindings: Rc::new(RefCell::new(Bindings::new())),
             action_states: Rc::new(RefCell::new([false; ACTION_COUNT])),
             mouse_delta: (0.0, 0.0),
+            gamepad_sticks: GamepadSticks::zero(),
             impulse: Rc::new(Cell::new(0)),
+            weapon_cycle: Rc::new(Cell::new(None)),
         }
     }
 
@@ -498,10 +529,26 @@ impl GameInput {
         self.mouse_delta
     }
 
+    /// Returns the raw positions of the active gamepad's sticks.
+    pub fn gamepad_sticks(&self) -> GamepadSticks {
+        self.gamepad_sticks
+    }
+
+    pub fn set_gamepad_sticks(&mut self, sticks: GamepadSticks) {
+        self.gamepad_sticks = sticks;
+    }
+
     pub fn impulse(&self) -> u8 {
         self.impulse.get()
     }
 
+    /// Returns the weapon change requested by `weapnext` or `weapprev` this frame, if any.
+    ///
+    /// The client turns this into an impulse once it knows which weapons the player has.
+    pub fn weapon_cycle(&self) -> Option<WeaponCycle> {
+        self.weapon_cycle.get()
+    }
+
     /// Bind the default controls.
     pub fn bind_defaults(&mut self) {
         self.bind(Key::W, BindTarget::from_str("+forward").unwrap());
@@ -525,9 +572,25 @@ impl GameInput {
         self.bind(Key::Key7, BindTarget::from_str("impulse 7").unwrap());
         self.bind(Key::Key8, BindTarget::from_str("impulse 8").unwrap());
         self.bind(Key::Key9, BindTarget::from_str("impulse 9").unwrap());
+        self.bind(Key::Equals, BindTarget::from_str("sizeup").unwrap());
+        self.bind(Key::Minus, BindTarget::from_str("sizedown").unwrap());
+        self.bind(Key::F6, BindTarget::from_str("quicksave").unwrap());
+        self.bind(Key::F9, BindTarget::from_str("quickload").unwrap());
+        self.bind(
+            GamepadButton::new(1).unwrap(),
+            BindTarget::from_str("+jump").unwrap(),
+        );
+        self.bind(
+            GamepadButton::new(8).unwrap(),
+            BindTarget::from_str("+attack").unwrap(),
+        );
+        self.bind(
+            GamepadButton::new(6).unwrap(),
+            BindTarget::from_str("impulse 10").unwrap(),
+        );
     }
 
-    /// Bind a `BindInput` to a `BindTarget`.
+    /// Bind a `BindInput` to a `BindTarget` in the game context.
     pub fn bind<I, T>(&mut self, input: I, target: T) -> Option<BindTarget>
     where
         I: Into<BindInput>,
@@ -535,15 +598,24 @@ impl GameInput {
     {
         self.bindings
             .borrow_mut()
-            .insert(input.into(), target.into())
+            .bind(BindContext::Game, input.into(), target.into())
     }
 
-    /// Return the `BindTarget` that `input` is bound to, or `None` if `input` is not present.
+    /// Return the `BindTarget` that `input` is bound to in the game context, or `None` if `input`
+    /// is not present.
     pub fn binding<I>(&self, input: I) -> Option<BindTarget>
     where
         I: Into<BindInput>,
     {
-        self.bindings.borrow().get(&input.into()).map(|t| t.clone())
+        self.bindings
+            .borrow()
+            .get(BindContext::Game, input.into())
+            .cloned()
+    }
+
+    /// Returns the bindings of every context, which are shared with the console and menu input.
+    pub fn bindings(&self) -> Rc<RefCell<Bindings>> {
+        self.bindings.clone()
     }
 
     pub fn handle_event<T>(&mut self, outer_event: Event<T>) {
@@ -587,7 +659,7 @@ impl GameInput {
         let bind_input = input.into();
 
         // debug!("handle input {:?}: {:?}", &bind_input, state);
-        if let Some(target) = self.bindings.borrow().get(&bind_input) {
+        if let Some((_, target)) = self.bindings.borrow().resolve(bind_input) {
             match *target {
                 BindTarget::Action { trigger, action } => {
                     self.action_states.borrow_mut()[action as usize] = state == trigger;
@@ -618,8 +690,14 @@ impl GameInput {
             for (state_str, state_bool) in states.iter().cloned() {
                 let action_states = self.action_states.clone();
                 let cmd_name = format!("{}{}", state_str, action.to_string());
+                let help = format!(
+                    "{} the {} action",
+                    if state_bool { "start" } else { "stop" },
+                    action.to_string()
+                );
                 cmds.insert_or_replace(
                     &cmd_name,
+                    &help,
                     Box::new(move |_| {
                         action_states.borrow_mut()[action as usize] = state_bool;
                     }),
@@ -631,13 +709,14 @@ impl GameInput {
         let bindings = self.bindings.clone();
         cmds.insert_or_replace(
             "bind",
+            "bind [key] (command): attach a command to a key",
             Box::new(move |args| {
                 println!("args: {}", args.len());
                 match args.len() {
                     // bind (key)
                     // queries what (key) is bound to, if anything
                     1 => match BindInput::from_str(args[0]) {
-                        Ok(i) => match bindings.borrow().get(&i) {
+                        Ok(i) => match bindings.borrow().get(BindContext::Game, i) {
                             Some(t) => println!("\"{}\" = \"{}\"", i.to_string(), t.to_string()),
                             None => println!("\"{}\" is not bound", i.to_string()),
                         },
@@ -650,7 +729,7 @@ impl GameInput {
                         Ok(input) => {
                             match BindTarget::from_str(args[1]) {
                                 Ok(target) => {
-                                    bindings.borrow_mut().insert(input, target);
+                                    bindings.borrow_mut().bind(BindContext::Game, input, target);
                                     debug!("Bound {:?} to {:?}", input, args[1]);
                                 }
                                 Err(_) => {
@@ -667,14 +746,83 @@ impl GameInput {
             }),
         );
 
+        // "in_bind"
+        let bindings = self.bindings.clone();
+        cmds.insert_or_replace(
+            "in_bind",
+            "in_bind (context) (key) [command]: attach a command to a key in the game, demo, menu \
+             or console context",
+            Box::new(move |args| {
+                let (context, input) = match args {
+                    [context, key] | [context, key, _] => {
+                        match (BindContext::from_str(context), BindInput::from_str(key)) {
+                            (Ok(c), Ok(i)) => (c, i),
+                            (Err(e), _) | (_, Err(e)) => {
+                                println!("{}", e);
+                                return;
+                            }
+                        }
+                    }
+
+                    _ => {
+                        println!("in_bind (context) (key) [command]: attach a command to a key");
+                        return;
+                    }
+                };
+
+                match args.get(2) {
+                    Some(command) => match BindTarget::from_str(command) {
+                        Ok(target) => {
+                            bindings.borrow_mut().bind(context, input, target);
+                        }
+                        Err(_) => println!("\"{}\" isn't a valid bind target", command),
+                    },
+
+                    None => match bindings.borrow().get(context, input) {
+                        Some(t) => println!(
+                            "\"{}\" = \"{}\" in {}",
+                            input.to_string(),
+                            t.to_string(),
+                            context.name()
+                        ),
+                        None => println!(
+                            "\"{}\" is not bound in {}",
+                            input.to_string(),
+                            context.name()
+                        ),
+                    },
+                }
+            }),
+        );
+
+        // "in_unbind"
+        let bindings = self.bindings.clone();
+        cmds.insert_or_replace(
+            "in_unbind",
+            "in_unbind (context) (key): remove the binding of a key in a context",
+            Box::new(move |args| match args {
+                [context, key] => {
+                    match (BindContext::from_str(context), BindInput::from_str(key)) {
+                        (Ok(c), Ok(i)) => {
+                            bindings.borrow_mut().unbind(c, i);
+                        }
+                        (Err(e), _) | (_, Err(e)) => println!("{}", e),
+                    }
+                }
+
+                _ => {
+                    println!("in_unbind (context) (key): remove the binding of a key in a context")
+                }
+            }),
+        );
+
         // "unbindall"
         let bindings = self.bindings.clone();
         cmds.insert_or_replace(
             "unbindall",
+            "delete all keybindings",
             Box::new(move |args| match args.len() {
-                0 => {
-                    let _ = bindings.replace(HashMap::new());
-                }
+                0 => bindings.borrow_mut().clear(),
                 _ => println!("unbindall: delete all keybindings"),
             }),
         );
@@ -683,18 +831,34 @@ impl GameInput {
         let impulse = self.impulse.clone();
         cmds.insert_or_replace(
             "impulse",
-            Box::new(move |args| {
-                println!("args: {}", args.len());
-                match args.len() {
-                    1 => match u8::from_str(args[0]) {
-                        Ok(i) => impulse.set(i),
-                        Err(_) => println!("Impulse must be a number between 0 and 255"),
-                    },
+            "impulse (number): send an impulse to the server, e.g. to change weapons",
+            Box::new(move |args| match args.len() {
+                1 => match u8::from_str(args[0]) {
+                    Ok(i) => impulse.set(i),
+                    Err(_) => println!("Impulse must be a number between 0 and 255"),
+                },
 
-                    _ => println!("impulse [number]"),
-                }
+                _ => println!("impulse [number]"),
             }),
         );
+
+        // "weapnext", "weapprev"
+        for (name, help, cycle) in &[
+            (
+                "weapnext",
+                "switch to the next weapon that has ammo",
+                WeaponCycle::Next,
+            ),
+            (
+                "weapprev",
+                "switch to the previous weapon that has ammo",
+                WeaponCycle::Prev,
+            ),
+        ] {
+            let weapon_cycle = self.weapon_cycle.clone();
+            let cycle = *cycle;
+            cmds.insert_or_replace(name, help, Box::new(move |_| weapon_cycle.set(Some(cycle))));
+        }
     }
 
     // must be called every frame!
@@ -703,6 +867,15 @@ impl GameInput {
         self.clear_impulse();
     }
 
+    /// Releases every held action and discards accumulated mouse movement.
+    ///
+    /// Keys released while the window is unfocused never reach the game, so this is called when
+    /// focus is lost to keep actions from sticking.
+    pub fn release_all(&mut self) {
+        *self.action_states.borrow_mut() = [false; ACTION_COUNT];
+        self.clear_mouse();
+    }
+
     fn clear_mouse(&mut self) {
         self.handle_input(MouseWheel::Up, ElementState::Released);
         self.handle_input(MouseWheel::Down, ElementState::Released);
@@ -711,6 +884,7 @@ impl GameInput {
 
     fn clear_impulse(&mut self) {
         self.impulse.set(0);
+        self.weapon_cycle.set(None);
     }
 }
 
@@ -733,4 +907,30 @@ mod test {
 
         assert_eq!(target.to_string(), "+forward");
     }
+
+    #[test]
+    fn test_bind_input_gamepad_button() {
+        let input = BindInput::from_str("joy3").unwrap();
+        assert_eq!(input, BindInput::from(GamepadButton::new(3).unwrap()));
+        assert_eq!(input.to_string(), "JOY3");
+    }
+
+    #[test]
+    fn test_release_all() {
+        use crate::common::console::CvarRegistry;
+
+        let console = Rc::new(RefCell::new(Console::new(
+            Rc::new(RefCell::new(CmdRegistry::new())),
+            Rc::new(RefCell::new(CvarRegistry::new())),
+        )));
+        let mut input = GameInput::new(console);
+        input.bind(Key::W, BindTarget::from_str("+forward").unwrap());
+
+        input.handle_input(Key::W, ElementState::Pressed);
+        assert!(input.action_state(Action::Forward));
+
+        input.release_all();
+        assert!(!input.action_state(Action::Forward));
+        assert_eq!(input.mouse_delta(), (0.0, 0.0));
+    }
 }
@@ -0,0 +1,330 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Gamepad support.
+//!
+//! Gamepad buttons are exposed to the bind system as `JOY1` through `JOY17`, so they can be bound
+//! like any key (`bind JOY1 +jump`). Stick positions are stored on the `GameInput` each frame and
+//! shaped by `apply_response` before they're used for movement and looking.
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    string::ToString,
+};
+
+use crate::client::input::game::GameInput;
+
+use cgmath::{InnerSpace as _, Vector2, Zero as _};
+use failure::Error;
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+use winit::event::ElementState;
+
+/// The number of bindable gamepad buttons.
+pub const GAMEPAD_BUTTON_COUNT: u8 = 17;
+
+// bind numbers are assigned in this order, starting from JOY1
+static GAMEPAD_BUTTONS: [Button; GAMEPAD_BUTTON_COUNT as usize] = [
+    Button::South,
+    Button::East,
+    Button::West,
+    Button::North,
+    Button::LeftTrigger,
+    Button::RightTrigger,
+    Button::LeftTrigger2,
+    Button::RightTrigger2,
+    Button::Select,
+    Button::Start,
+    Button::LeftThumb,
+    Button::RightThumb,
+    Button::DPadUp,
+    Button::DPadDown,
+    Button::DPadLeft,
+    Button::DPadRight,
+    Button::Mode,
+];
+
+/// A button on a gamepad, named `JOY1` through `JOY17` for binding.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct GamepadButton(u8);
+
+impl GamepadButton {
+    /// Returns the button with the given 1-based bind number, if it exists.
+    pub fn new(number: u8) -> Option<GamepadButton> {
+        if number >= 1 && number <= GAMEPAD_BUTTON_COUNT {
+            Some(GamepadButton(number))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the bindable button corresponding to a `gilrs` button, if there is one.
+    pub fn from_gilrs(button: Button) -> Option<GamepadButton> {
+        GAMEPAD_BUTTONS
+            .iter()
+            .position(|b| *b == button)
+            .map(|i| GamepadButton(i as u8 + 1))
+    }
+
+    pub fn number(&self) -> u8 {
+        self.0
+    }
+}
+
+impl FromStr for GamepadButton {
+    type Err = Error;
+
+    fn from_str(src: &str) -> Result<GamepadButton, Error> {
+        let upper = src.to_uppercase();
+
+        if let Some(num_str) = upper.strip_prefix("JOY") {
+            if let Some(button) = u8::from_str(num_str).ok().and_then(GamepadButton::new) {
+                return Ok(button);
+            }
+        }
+
+        bail!("\"{}\" isn't a valid gamepad button", src);
+    }
+}
+
+impl ToString for GamepadButton {
+    fn to_string(&self) -> String {
+        format!("JOY{}", self.0)
+    }
+}
+
+/// The positions of both analog sticks, each axis in the range [-1, 1] with positive y pointing
+/// up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GamepadSticks {
+    pub left: Vector2<f32>,
+    pub right: Vector2<f32>,
+}
+
+impl GamepadSticks {
+    pub fn zero() -> GamepadSticks {
+        GamepadSticks {
+            left: Vector2::zero(),
+            right: Vector2::zero(),
+        }
+    }
+}
+
+/// Applies a radial dead zone and response curve to a raw stick position.
+///
+/// Positions inside `deadzone` are treated as centered. Outside it, the distance from the edge of
+/// the dead zone is rescaled to [0, 1] and raised to `exponent`, so values greater than 1 give
+/// finer control near the center.
+pub fn apply_response(raw: Vector2<f32>, deadzone: f32, exponent: f32) -> Vector2<f32> {
+    let deadzone = deadzone.max(0.0).min(0.99);
+    let magnitude = raw.magnitude();
+    if magnitude <= deadzone {
+        return Vector2::zero();
+    }
+
+    let scaled = ((magnitude.min(1.0) - deadzone) / (1.0 - deadzone)).powf(exponent.max(0.01));
+    raw / magnitude * scaled
+}
+
+/// Polls connected gamepads and forwards their input to the game.
+pub struct Gamepads {
+    // None if gamepads aren't supported on this platform
+    gilrs: Option<Gilrs>,
+
+    sticks: HashMap<GamepadId, GamepadSticks>,
+
+    // buttons currently held, so they can be released if their gamepad is unplugged
+    pressed: HashSet<(GamepadId, GamepadButton)>,
+
+    // the gamepad that most recently produced input
+    active: Option<GamepadId>,
+}
+
+impl Gamepads {
+    pub fn new() -> Gamepads {
+        let gilrs = match Gilrs::new() {
+            Ok(g) => {
+                for (id, gamepad) in g.gamepads() {
+                    info!("Gamepad {} connected: {}", id, gamepad.name());
+                }
+
+                Some(g)
+            }
+
+            Err(e) => {
+                warn!("Gamepad support unavailable: {}", e);
+                None
+            }
+        };
+
+        Gamepads {
+            gilrs,
+            sticks: HashMap::new(),
+            pressed: HashSet::new(),
+            active: None,
+        }
+    }
+
+    /// Handles all gamepad events since the last call.
+    ///
+    /// Button presses are only forwarded if `focused` is true, but releases are always forwarded so
+    /// that actions don't stick when focus changes. This must be called every frame to keep track
+    /// of gamepads being connected and disconnected.
+    pub fn poll(&mut self, game_input: &mut GameInput, focused: bool) {
+        let gilrs = match self.gilrs {
+            Some(ref mut g) => g,
+            None => return,
+        };
+
+        while let Some(event) = gilrs.next_event() {
+            let id = event.id;
+
+            match event.event {
+                EventType::ButtonPressed(button, _) => {
+                    self.active = Some(id);
+                    if let Some(b) = GamepadButton::from_gilrs(button) {
+                        if focused && self.pressed.insert((id, b)) {
+                            game_input.handle_input(b, ElementState::Pressed);
+                        }
+                    }
+                }
+
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(b) = GamepadButton::from_gilrs(button) {
+                        if self.pressed.remove(&(id, b)) {
+                            game_input.handle_input(b, ElementState::Released);
+                        }
+                    }
+                }
+
+                EventType::AxisChanged(axis, value, _) => {
+                    let sticks = self.sticks.entry(id).or_insert_with(GamepadSticks::zero);
+                    match axis {
+                        Axis::LeftStickX => sticks.left.x = value,
+                        Axis::LeftStickY => sticks.left.y = value,
+                        Axis::RightStickX => sticks.right.x = value,
+                        Axis::RightStickY => sticks.right.y = value,
+                        _ => continue,
+                    }
+
+                    self.active = Some(id);
+                }
+
+                EventType::Connected => {
+                    info!("Gamepad {} connected: {}", id, gilrs.gamepad(id).name());
+                }
+
+                EventType::Disconnected => {
+                    info!("Gamepad {} disconnected", id);
+                    self.sticks.remove(&id);
+                    if self.active == Some(id) {
+                        self.active = None;
+                    }
+
+                    let released: Vec<_> = self
+                        .pressed
+                        .iter()
+                        .filter(|(pressed_id, _)| *pressed_id == id)
+                        .cloned()
+                        .collect();
+                    for (pressed_id, button) in released {
+                        self.pressed.remove(&(pressed_id, button));
+                        game_input.handle_input(button, ElementState::Released);
+                    }
+                }
+
+                _ => (),
+            }
+        }
+
+        let sticks = match self.active {
+            Some(id) if focused => self
+                .sticks
+                .get(&id)
+                .cloned()
+                .unwrap_or_else(GamepadSticks::zero),
+            _ => GamepadSticks::zero(),
+        };
+
+        game_input.set_gamepad_sticks(sticks);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gamepad_button_from_str() {
+        assert_eq!(
+            GamepadButton::from_str("joy1").unwrap(),
+            GamepadButton::new(1).unwrap()
+        );
+        assert_eq!(
+            GamepadButton::from_str("JOY17").unwrap(),
+            GamepadButton::new(17).unwrap()
+        );
+        assert!(GamepadButton::from_str("JOY0").is_err());
+        assert!(GamepadButton::from_str("JOY18").is_err());
+        assert!(GamepadButton::from_str("JOY").is_err());
+    }
+
+    #[test]
+    fn test_gamepad_button_round_trip() {
+        for number in 1..=GAMEPAD_BUTTON_COUNT {
+            let button = GamepadButton::new(number).unwrap();
+            assert_eq!(
+                GamepadButton::from_str(&button.to_string()).unwrap(),
+                button
+            );
+        }
+    }
+
+    #[test]
+    fn test_gamepad_button_from_gilrs() {
+        assert_eq!(
+            GamepadButton::from_gilrs(Button::South),
+            GamepadButton::new(1)
+        );
+        assert_eq!(GamepadButton::from_gilrs(Button::Unknown), None);
+    }
+
+    #[test]
+    fn test_apply_response_deadzone() {
+        let out = apply_response(Vector2::new(0.1, 0.1), 0.2, 1.0);
+        assert_eq!(out, Vector2::zero());
+    }
+
+    #[test]
+    fn test_apply_response_full_deflection() {
+        let out = apply_response(Vector2::new(0.0, 1.0), 0.2, 2.0);
+        assert!((out.y - 1.0).abs() < 1e-6);
+        assert_eq!(out.x, 0.0);
+    }
+
+    #[test]
+    fn test_apply_response_curve() {
+        // halfway between the dead zone and the edge
+        let linear = apply_response(Vector2::new(0.6, 0.0), 0.2, 1.0);
+        let squared = apply_response(Vector2::new(0.6, 0.0), 0.2, 2.0);
+        assert!((linear.x - 0.5).abs() < 1e-6);
+        assert!((squared.x - 0.25).abs() < 1e-6);
+    }
+}
@@ -17,7 +17,16 @@
 
 use std::{cell::RefCell, rc::Rc};
 
-use crate::{client::menu::Menu, common::console::Console};
+use crate::{
+    client::{
+        input::{
+            context::Bindings,
+            game::{BindInput, BindTarget},
+        },
+        menu::Menu,
+    },
+    common::console::Console,
+};
 
 use failure::Error;
 use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode as Key, WindowEvent};
@@ -25,11 +34,34 @@ use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode as Key, Wi
 pub struct MenuInput {
     menu: Rc<RefCell<Menu>>,
     console: Rc<RefCell<Console>>,
+    bindings: Rc<RefCell<Bindings>>,
 }
 
 impl MenuInput {
-    pub fn new(menu: Rc<RefCell<Menu>>, console: Rc<RefCell<Console>>) -> MenuInput {
-        MenuInput { menu, console }
+    pub fn new(
+        menu: Rc<RefCell<Menu>>,
+        console: Rc<RefCell<Console>>,
+        bindings: Rc<RefCell<Bindings>>,
+    ) -> MenuInput {
+        MenuInput {
+            menu,
+            console,
+            bindings,
+        }
+    }
+
+    // runs the menu context binding of `key`, if it has one
+    fn run_binding(&self, key: Key) -> bool {
+        match self.bindings.borrow().resolve(BindInput::from(key)) {
+            Some((_, BindTarget::ConsoleInput { text })) => {
+                self.console.borrow().stuff_text(text);
+                true
+            }
+
+            // there's nothing for actions to do in a menu
+            Some((_, BindTarget::Action { .. })) => true,
+            None => false,
+        }
     }
 
     pub fn handle_event<T>(&self, event: Event<T>) -> Result<(), Error> {
@@ -37,6 +69,16 @@ impl MenuInput {
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::ReceivedCharacter(_) => (),
 
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(key),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } if self.run_binding(key) => (),
+
                 WindowEvent::KeyboardInput {
                     input:
                         KeyboardInput {
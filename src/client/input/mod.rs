@@ -16,8 +16,11 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 pub mod console;
+pub mod context;
 pub mod game;
+pub mod gamepad;
 pub mod menu;
+pub mod weapon;
 
 use std::{cell::RefCell, rc::Rc};
 
@@ -31,7 +34,9 @@ use winit::event::{Event, WindowEvent};
 
 use self::{
     console::ConsoleInput,
+    context::BindContext,
     game::{BindInput, BindTarget, GameInput},
+    gamepad::Gamepads,
     menu::MenuInput,
 };
 
@@ -45,10 +50,13 @@ pub enum InputFocus {
 pub struct Input {
     window_focused: bool,
     current_focus: InputFocus,
+    demo_playback: bool,
 
     game_input: GameInput,
     console_input: ConsoleInput,
     menu_input: MenuInput,
+
+    gamepads: Gamepads,
 }
 
 impl Input {
@@ -57,14 +65,22 @@ impl Input {
         console: Rc<RefCell<Console>>,
         menu: Rc<RefCell<Menu>>,
     ) -> Input {
-        Input {
+        let game_input = GameInput::new(console.clone());
+        let bindings = game_input.bindings();
+
+        let mut input = Input {
             window_focused: true,
             current_focus: init_focus,
+            demo_playback: false,
 
-            game_input: GameInput::new(console.clone()),
-            console_input: ConsoleInput::new(console.clone()),
-            menu_input: MenuInput::new(menu.clone(), console.clone()),
-        }
+            game_input,
+            console_input: ConsoleInput::new(console.clone(), bindings.clone()),
+            menu_input: MenuInput::new(menu.clone(), console.clone(), bindings),
+
+            gamepads: Gamepads::new(),
+        };
+        input.update_contexts();
+        input
     }
 
     pub fn handle_event<T>(&mut self, event: Event<T>) -> Result<(), Error> {
@@ -73,7 +89,14 @@ impl Input {
             Event::WindowEvent {
                 event: WindowEvent::Focused(focused),
                 ..
-            } => self.window_focused = focused,
+            } => {
+                self.window_focused = focused;
+
+                // keys released in another window never reach us, so let go of everything now
+                if !focused {
+                    self.game_input.release_all();
+                }
+            }
 
             _ => {
                 if self.window_focused {
@@ -89,16 +112,50 @@ impl Input {
         Ok(())
     }
 
+    /// Handles input from connected gamepads. This must be called once per frame.
+    pub fn poll_gamepads(&mut self) {
+        let focused = self.window_focused && self.current_focus == InputFocus::Game;
+        self.gamepads.poll(&mut self.game_input, focused);
+    }
+
+    /// Returns `true` if the window has keyboard focus.
+    pub fn window_focused(&self) -> bool {
+        self.window_focused
+    }
+
     pub fn current_focus(&self) -> InputFocus {
         self.current_focus
     }
 
     pub fn set_focus(&mut self, new_focus: InputFocus) -> Result<(), Error> {
         self.current_focus = new_focus;
+        self.update_contexts();
 
         Ok(())
     }
 
+    /// Sets whether a demo is playing, which activates the demo binding context.
+    pub fn set_demo_playback(&mut self, demo_playback: bool) {
+        self.demo_playback = demo_playback;
+        self.update_contexts();
+    }
+
+    // rebuilds the binding context stack from the focus and playback state
+    fn update_contexts(&mut self) {
+        let mut stack = Vec::new();
+        if self.demo_playback {
+            stack.push(BindContext::Demo);
+        }
+
+        match self.current_focus {
+            InputFocus::Game => (),
+            InputFocus::Console => stack.push(BindContext::Console),
+            InputFocus::Menu => stack.push(BindContext::Menu),
+        }
+
+        self.game_input.bindings().borrow_mut().set_stack(&stack);
+    }
+
     /// Bind a `BindInput` to a `BindTarget`.
     pub fn bind<I, T>(&mut self, input: I, target: T) -> Option<BindTarget>
     where
@@ -0,0 +1,171 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Weapon selection.
+//!
+//! The server changes weapons in response to impulses 1 through 8. `weapnext` and `weapprev`
+//! pick the impulse for the next or previous weapon the player can actually use, based on the
+//! items and ammo reported in the client stats.
+
+use crate::common::net::{ClientStat, ItemFlags};
+
+/// A direction to cycle through the player's weapons.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WeaponCycle {
+    Next,
+    Prev,
+}
+
+struct Weapon {
+    impulse: u8,
+    item: ItemFlags,
+    ammo: Option<(ClientStat, i32)>,
+}
+
+// in impulse order, with the ammo stat and the amount needed to fire
+const WEAPONS: [Weapon; 8] = [
+    Weapon {
+        impulse: 1,
+        item: ItemFlags::AXE,
+        ammo: None,
+    },
+    Weapon {
+        impulse: 2,
+        item: ItemFlags::SHOTGUN,
+        ammo: Some((ClientStat::Shells, 1)),
+    },
+    Weapon {
+        impulse: 3,
+        item: ItemFlags::SUPER_SHOTGUN,
+        ammo: Some((ClientStat::Shells, 2)),
+    },
+    Weapon {
+        impulse: 4,
+        item: ItemFlags::NAILGUN,
+        ammo: Some((ClientStat::Nails, 1)),
+    },
+    Weapon {
+        impulse: 5,
+        item: ItemFlags::SUPER_NAILGUN,
+        ammo: Some((ClientStat::Nails, 2)),
+    },
+    Weapon {
+        impulse: 6,
+        item: ItemFlags::GRENADE_LAUNCHER,
+        ammo: Some((ClientStat::Rockets, 1)),
+    },
+    Weapon {
+        impulse: 7,
+        item: ItemFlags::ROCKET_LAUNCHER,
+        ammo: Some((ClientStat::Rockets, 1)),
+    },
+    Weapon {
+        impulse: 8,
+        item: ItemFlags::LIGHTNING,
+        ammo: Some((ClientStat::Cells, 1)),
+    },
+];
+
+impl Weapon {
+    fn usable(&self, items: ItemFlags, stats: &[i32]) -> bool {
+        items.contains(self.item)
+            && match self.ammo {
+                Some((stat, amount)) => stats[stat as usize] >= amount,
+                None => true,
+            }
+    }
+}
+
+/// Returns the impulse that selects the weapon after (or before) the active one.
+///
+/// `active_weapon` is the item bit of the active weapon, as sent in
+/// `ClientStat::ActiveWeapon`. Weapons the player doesn't own or has no ammo for are skipped.
+/// Returns `None` if no other weapon is usable.
+pub fn cycle_impulse(
+    cycle: WeaponCycle,
+    items: ItemFlags,
+    active_weapon: i32,
+    stats: &[i32],
+) -> Option<u8> {
+    let count = WEAPONS.len();
+    let current = WEAPONS
+        .iter()
+        .position(|w| w.item.bits() as i32 == active_weapon);
+
+    // with an unknown weapon, start from whichever end makes the first step land on a weapon
+    let start = match (current, cycle) {
+        (Some(i), _) => i,
+        (None, WeaponCycle::Next) => count - 1,
+        (None, WeaponCycle::Prev) => 0,
+    };
+
+    (1..=count)
+        .map(|step| match cycle {
+            WeaponCycle::Next => (start + step) % count,
+            WeaponCycle::Prev => (start + count - step) % count,
+        })
+        .take_while(|i| Some(*i) != current)
+        .map(|i| &WEAPONS[i])
+        .find(|w| w.usable(items, stats))
+        .map(|w| w.impulse)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cycle_impulse() {
+        let items = ItemFlags::AXE
+            | ItemFlags::SHOTGUN
+            | ItemFlags::SUPER_SHOTGUN
+            | ItemFlags::ROCKET_LAUNCHER;
+        let mut stats = [0; 32];
+        stats[ClientStat::Shells as usize] = 1;
+        stats[ClientStat::Rockets as usize] = 5;
+
+        let shotgun = ItemFlags::SHOTGUN.bits() as i32;
+        let rockets = ItemFlags::ROCKET_LAUNCHER.bits() as i32;
+
+        // the super shotgun needs two shells
+        assert_eq!(
+            cycle_impulse(WeaponCycle::Next, items, shotgun, &stats),
+            Some(7)
+        );
+        assert_eq!(
+            cycle_impulse(WeaponCycle::Prev, items, rockets, &stats),
+            Some(2)
+        );
+
+        // wraps around to the axe
+        assert_eq!(
+            cycle_impulse(WeaponCycle::Next, items, rockets, &stats),
+            Some(1)
+        );
+
+        // nothing else to switch to
+        assert_eq!(
+            cycle_impulse(
+                WeaponCycle::Next,
+                ItemFlags::AXE,
+                ItemFlags::AXE.bits() as i32,
+                &stats
+            ),
+            None
+        );
+    }
+}
@@ -18,11 +18,18 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+mod clock;
 mod cvars;
-mod demo;
+pub mod demo;
 pub mod entity;
+pub mod fog;
+pub mod freecam;
+pub mod host;
 pub mod input;
 pub mod menu;
+pub mod practice;
+pub mod preset;
+mod qw;
 pub mod render;
 pub mod sound;
 pub mod trace;
@@ -31,45 +38,72 @@ pub mod view;
 pub use self::cvars::register_cvars;
 
 use std::{
-    cell::{Cell, RefCell},
-    collections::HashMap,
+    cell::{Cell, Ref, RefCell},
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fs,
     io::{BufReader, Read},
     net::ToSocketAddrs,
+    path::Path,
     rc::Rc,
 };
 
 use crate::{
     client::{
-        demo::{DemoServer, DemoServerError},
+        demo::{DemoMetadata, DemoRecorder, DemoServer, DemoServerError},
         entity::{
+            attack::{self, AttackPrediction},
+            beam::{self, BeamLoop},
+            effects,
             particle::{Particle, Particles, TrailKind, MAX_PARTICLES},
+            predict::{self, Prediction},
             Beam, ClientEntity, Light, LightDesc, Lights, MAX_BEAMS, MAX_LIGHTS,
             MAX_STATIC_ENTITIES, MAX_TEMP_ENTITIES,
         },
-        input::game::{Action, GameInput},
-        sound::{AudioSource, Channel, Listener, StaticSound},
+        fog::Fog,
+        freecam::FreeCam,
+        host::{HostEvent, HostState, HostStateError},
+        input::{
+            game::{Action, GameInput},
+            gamepad, weapon,
+        },
+        practice::{Practice, PracticeRequest, SavedPosition},
+        sound::{
+            effects::Reverb, music::MusicPlayer, AudioSource, Channel, Listener, Occlusion,
+            StaticSound,
+        },
         trace::{TraceEntity, TraceFrame},
-        view::{IdleVars, KickVars, MouseVars, RollVars, View},
+        view::{BobVars, GamepadVars, IdleVars, KickVars, MouseVars, RollVars, View},
     },
     common::{
-        bsp,
-        console::{CmdRegistry, Console, ConsoleError, CvarRegistry},
-        engine,
+        self, bsp,
+        console::{
+            files_with_extension, CmdRegistry, Console, ConsoleError, CvarRegistry, PrintLevel,
+        },
+        engine, frustum,
+        limits::LoadLimits,
+        mapconfig::{self, ConfigSide, MapConfig},
         math::Angles,
         model::{Model, ModelError, ModelFlags, ModelKind, SyncType},
         net::{
             self,
             connect::{ConnectSocket, Request, Response, CONNECT_PROTOCOL_VERSION},
+            loopback::SimHost,
             BeamEntityKind, BlockingMode, ButtonFlags, ClientCmd, ClientStat, ColorShift,
-            EntityEffects, EntityState, GameType, ItemFlags, NetError, PlayerColor,
+            EntityEffects, EntityState, EntityUpdate, GameType, ItemFlags, NetError, PlayerColor,
             PointEntityKind, QSocket, ServerCmd, SignOnStage, TempEntity,
         },
+        parse,
+        pmove::{MoveCmd, MoveVars, PlayerMove, PlayerState},
+        random::GameRng,
         vfs::{Vfs, VfsError},
+        vis::Pvs,
     },
 };
 
-use cgmath::{Angle, Deg, InnerSpace, Matrix4, Vector3, Zero};
-use chrono::Duration;
+use cgmath::{Angle, Deg, InnerSpace, Vector3, Zero};
+use chrono::{Duration, Local};
+use num::FromPrimitive;
 use rand::{
     distributions::{Distribution as _, Uniform},
     Rng,
@@ -80,6 +114,9 @@ use thiserror::Error;
 // connections are tried 3 times, see
 // https://github.com/id-Software/Quake/blob/master/WinQuake/net_dgrm.c#L1248
 const MAX_CONNECT_ATTEMPTS: usize = 3;
+
+// seconds of silence after which a keepalive is sent to the server, as in the original engine
+const KEEPALIVE_INTERVAL: i64 = 5;
 const MAX_STATS: usize = 32;
 
 const DEFAULT_SOUND_PACKET_VOLUME: u8 = 255;
@@ -87,6 +124,43 @@ const DEFAULT_SOUND_PACKET_ATTENUATION: f32 = 1.0;
 
 const MAX_CHANNELS: usize = 128;
 
+// how far from the listener walls are searched for when estimating room size
+const ROOM_PROBE_DISTANCE: f32 = 2048.0;
+
+// directions traced from the listener when estimating room size
+const ROOM_PROBE_DIRECTIONS: [[f32; 3]; 10] = [
+    [1.0, 0.0, 0.0],
+    [-1.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [0.0, -1.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [0.0, 0.0, -1.0],
+    [0.707, 0.707, 0.0],
+    [0.707, -0.707, 0.0],
+    [-0.707, 0.707, 0.0],
+    [-0.707, -0.707, 0.0],
+];
+
+// number of debug traces kept for r_showtraces
+const MAX_DEBUG_TRACES: usize = 64;
+
+// the darkest a lit model may be, so that models in unlit areas remain visible
+const MIN_MODEL_LIGHT: f32 = 0.1875;
+
+// the rate at which model lighting follows changes in the world light, per second
+const MODEL_LIGHT_RATE: f32 = 10.0;
+
+// how far around its origin a model's lighting is averaged, so it doesn't pop at luxel edges
+const MODEL_LIGHT_SPREAD: f32 = 8.0;
+
+// how far the debug_trace command traces from the view origin
+const DEBUG_TRACE_DISTANCE: f32 = 8192.0;
+
+lazy_static! {
+    // radius of muzzle flashes and dim lights
+    static ref MFLASH_DIMLIGHT_DISTRIBUTION: Uniform<f32> = Uniform::new(200.0, 232.0);
+}
+
 #[derive(Error, Debug)]
 pub enum ClientError {
     #[error("Connection rejected: {0}")]
@@ -101,6 +175,10 @@ pub enum ClientError {
     InvalidServerAddress,
     #[error("No response from server")]
     NoResponse,
+    #[error("Connection to server timed out")]
+    ConnectionTimedOut,
+    #[error("{0}")]
+    HostState(#[from] HostStateError),
     #[error("Unrecognized protocol: {0}")]
     UnrecognizedProtocol(i32),
     #[error("No client with ID {0}")]
@@ -121,6 +199,8 @@ pub enum ClientError {
     NoSuchLightmapAnimation(usize),
     #[error("Demo server error: {0}")]
     DemoServer(#[from] DemoServerError),
+    #[error("Failed to load map {0}: {1}")]
+    InvalidMap(String, String),
     #[error("Model error: {0}")]
     Model(#[from] ModelError),
     #[error("Network error: {0}")]
@@ -139,6 +219,49 @@ enum ColorShiftCode {
     Powerup = 3,
 }
 
+// how a color shift is scaled, layered and faded
+struct ColorShiftKind {
+    // cvar scaling the strength of the shift
+    cvar: &'static str,
+
+    // shifts are layered from lowest to highest priority, so higher priorities show on top
+    priority: u8,
+
+    // how fast the shift fades, in percent per second, or 0 if it's set every frame
+    decay_rate: f32,
+
+    // whether the shift comes and goes suddenly, and so is capped by v_flashlimit
+    flash: bool,
+}
+
+// the color shifts, in the order of `ColorShiftCode`
+const COLOR_SHIFT_KINDS: [ColorShiftKind; 4] = [
+    ColorShiftKind {
+        cvar: "v_contentblend",
+        priority: 0,
+        decay_rate: 0.0,
+        flash: false,
+    },
+    ColorShiftKind {
+        cvar: "v_damagecshift",
+        priority: 1,
+        decay_rate: 150.0,
+        flash: true,
+    },
+    ColorShiftKind {
+        cvar: "v_bonusflash",
+        priority: 2,
+        decay_rate: 100.0,
+        flash: true,
+    },
+    ColorShiftKind {
+        cvar: "v_powerupcshift",
+        priority: 3,
+        decay_rate: 0.0,
+        flash: false,
+    },
+];
+
 struct ServerInfo {
     max_clients: u8,
     game_type: GameType,
@@ -163,6 +286,9 @@ struct ClientChannel {
     ent_id: usize,
     ent_channel: i8,
     channel: Channel,
+
+    // the ID of a looped sound and the beam that keeps it playing
+    beam_loop: Option<(usize, BeamLoop)>,
 }
 
 struct Mixer {
@@ -251,8 +377,88 @@ impl Mixer {
             ent_id,
             ent_channel,
             channel: new_channel,
+            beam_loop: None,
+        })
+    }
+
+    /// Starts looping a sound for as long as its entity fires a beam.
+    ///
+    /// If the sound is already looping on the entity channel, it carries on uninterrupted.
+    pub fn start_beam_loop(
+        &mut self,
+        sound_id: usize,
+        src: AudioSource,
+        time: Duration,
+        ent_id: usize,
+        ent_channel: i8,
+        volume: f32,
+        attenuation: f32,
+        ents: &[ClientEntity],
+        listener: &Listener,
+    ) {
+        let looping = self.channels.iter().any(|chan| match chan {
+            Some(ref c) => {
+                c.ent_id == ent_id
+                    && c.ent_channel == ent_channel
+                    && c.beam_loop.map_or(false, |(id, _)| id == sound_id)
+            }
+            None => false,
+        });
+        if looping {
+            return;
+        }
+
+        let chan_id = self.find_free_channel(ent_id, ent_channel);
+        let new_channel = Channel::new(self.audio_device.clone());
+
+        new_channel.play_looping(src, ents[ent_id].origin, listener, volume, attenuation);
+        self.channels[chan_id] = Some(ClientChannel {
+            start_time: time,
+            ent_id,
+            ent_channel,
+            channel: new_channel,
+            beam_loop: Some((sound_id, BeamLoop::new(time))),
         })
     }
+
+    /// Stops looped sounds whose entities have stopped firing beams.
+    ///
+    /// `firing` returns whether the entity with the given ID has a beam.
+    pub fn update_beam_loops<F>(&mut self, time: Duration, firing: F)
+    where
+        F: Fn(usize) -> bool,
+    {
+        for chan in self.channels.iter_mut() {
+            let stop = match *chan {
+                Some(ClientChannel {
+                    ent_id,
+                    beam_loop: Some((_, ref mut beam_loop)),
+                    ..
+                }) => !beam_loop.update(time, firing(ent_id)),
+                _ => false,
+            };
+
+            if stop {
+                if let Some(ref c) = *chan {
+                    c.channel.stop();
+                }
+                *chan = None;
+            }
+        }
+    }
+
+    /// Stops the sound playing on the given entity channel, if any.
+    pub fn stop_sound(&mut self, ent_id: usize, ent_channel: i8) {
+        for chan in self.channels.iter_mut() {
+            if let Some(ref c) = *chan {
+                if c.ent_id == ent_id && c.ent_channel == ent_channel {
+                    c.channel.stop();
+                    *chan = None;
+                    return;
+                }
+            }
+        }
+    }
 }
 
 // client information regarding the current level
@@ -266,6 +472,8 @@ struct ClientState {
 
     // audio source precache
     sounds: Vec<AudioSource>,
+    // IDs of the sounds looped while a beam is fired
+    beam_sound_ids: Vec<usize>,
 
     // ambient sounds (infinite looping, static position)
     static_sounds: Vec<StaticSound>,
@@ -281,10 +489,19 @@ struct ClientState {
     // particle effects
     particles: Particles,
 
+    // the view weapon, kept between frames so that its animation and lighting carry over
+    viewmodel: ClientEntity,
+
+    // random numbers for client-side effects, reseeded by demos so that playback matches
+    rng: GameRng,
+
     // visible entities, rebuilt per-frame
     visible_entity_ids: Vec<usize>,
+    visible_static_entity_ids: Vec<usize>,
 
     light_styles: HashMap<u8, String>,
+    // if true, light styles hold steady at their average brightness, set by `r_flatlightstyles`
+    flat_light_styles: bool,
 
     // various values relevant to the player and level (see common::net::ClientStat)
     stats: [i32; MAX_STATS],
@@ -304,11 +521,15 @@ struct ClientState {
     item_get_time: [Duration; net::MAX_ITEMS],
     face_anim_time: Duration,
     color_shifts: [Rc<RefCell<ColorShift>>; 4],
+    // contents shift applied when the view is not submerged, set by `v_cshift`
+    empty_color_shift: Rc<RefCell<ColorShift>>,
     // prev_color_shifts: [ColorShift; 4],
     view: View,
 
     msg_velocity: [Vector3<f32>; 2],
     velocity: Vector3<f32>,
+    prediction: Prediction,
+    attack_prediction: AttackPrediction,
 
     // ideal_pitch: Deg<f32>,
     // pitch_velocity: f32,
@@ -316,7 +537,7 @@ struct ClientState {
     // drift_move: f32,
     // last_stop: f64,
 
-    // paused: bool,
+    paused: bool,
     on_ground: bool,
     in_water: bool,
     intermission: Option<IntermissionKind>,
@@ -329,9 +550,18 @@ struct ClientState {
     // worldmodel: Model,
     mixer: Mixer,
     listener: Listener,
+
+    // the leaf the listener was last in and its potentially hearable set
+    listener_phs: RefCell<Option<(usize, Pvs)>>,
 }
 
 impl ClientState {
+    // restarts the random numbers of client-side effects from `seed`
+    fn reseed(&mut self, seed: u64) {
+        self.rng = GameRng::new(seed);
+        self.particles.reseed(seed.rotate_left(32));
+    }
+
     // TODO: add parameter for number of player slots and reserve them in entity list
     pub fn new(vfs: Rc<Vfs>, audio_device: Rc<rodio::Device>) -> Result<ClientState, ClientError> {
         Ok(ClientState {
@@ -339,6 +569,7 @@ impl ClientState {
             models: vec![Model::none()],
             model_names: HashMap::new(),
             sounds: vec![AudioSource::load(&vfs, "misc/null.wav")?],
+            beam_sound_ids: Vec::new(),
             static_sounds: Vec::new(),
             entities: Vec::new(),
             static_entities: Vec::new(),
@@ -346,8 +577,12 @@ impl ClientState {
             lights: Lights::with_capacity(MAX_LIGHTS),
             beams: [None; MAX_BEAMS],
             particles: Particles::with_capacity(MAX_PARTICLES),
+            viewmodel: ClientEntity::uninitialized(),
+            rng: GameRng::from_entropy(),
             visible_entity_ids: Vec::new(),
+            visible_static_entity_ids: Vec::new(),
             light_styles: HashMap::new(),
+            flat_light_styles: false,
             stats: [0; MAX_STATS],
             max_players: 0,
             // TODO: for the love of god can the lang team hurry up (https://github.com/rust-lang/rfcs/pull/2203)
@@ -413,10 +648,17 @@ impl ClientState {
                     percent: 0,
                 })),
             ],
+            empty_color_shift: Rc::new(RefCell::new(ColorShift {
+                dest_color: [0; 3],
+                percent: 0,
+            })),
             view: View::new(),
             face_anim_time: Duration::zero(),
             msg_velocity: [Vector3::zero(), Vector3::zero()],
             velocity: Vector3::zero(),
+            prediction: Prediction::new(),
+            attack_prediction: AttackPrediction::new(),
+            paused: false,
             on_ground: false,
             in_water: false,
             intermission: None,
@@ -424,43 +666,152 @@ impl ClientState {
             completion_time: None,
             mixer: Mixer::new(audio_device.clone()),
             listener: Listener::new(),
+            listener_phs: RefCell::new(None),
         })
     }
 
+    // returns the current brightness of a light style in the range [0, 2], or `None` if the style
+    // hasn't been set
+    fn light_style_value(&self, style: u8) -> Option<f32> {
+        let ls = self.light_styles.get(&style)?;
+        if self.flat_light_styles {
+            Some(bsp::lightstyle_average(ls))
+        } else {
+            Some(bsp::lightstyle_value(ls, self.time))
+        }
+    }
+
     fn update_listener(&self) {
         // TODO: update to self.view_origin()
         let view_origin = self.entities[self.view.entity_id()].origin;
-        let world_translate = Matrix4::from_translation(view_origin);
-
-        let left_base = Vector3::new(0.0, 4.0, self.view.view_height());
-        let right_base = Vector3::new(0.0, -4.0, self.view.view_height());
+        let eye = view_origin + Vector3::unit_z() * self.view.view_height();
+        let (_, right_dir, _) = frustum::view_vectors(self.view.input_angles());
 
-        let rotate = self.view.input_angles().mat4_quake();
-
-        let left = (world_translate * rotate * left_base.extend(1.0)).truncate();
-        let right = (world_translate * rotate * right_base.extend(1.0)).truncate();
+        let left = eye - right_dir * 4.0;
+        let right = eye + right_dir * 4.0;
 
         self.listener.set_origin(view_origin);
         self.listener.set_left_ear(left);
         self.listener.set_right_ear(right);
     }
 
-    fn update_sound_spatialization(&self) {
+    fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+
+        for opt_chan in self.mixer.channels.iter() {
+            if let Some(ref chan) = opt_chan {
+                chan.channel.set_paused(paused);
+            }
+        }
+
+        for ss in self.static_sounds.iter() {
+            ss.set_paused(paused);
+        }
+    }
+
+    // returns the fraction of the segment from start to end that isn't blocked by world geometry
+    fn world_trace_ratio(&self, start: Vector3<f32>, end: Vector3<f32>) -> f32 {
+        let trace = match self.models[1].kind() {
+            ModelKind::Brush(ref bmodel) => bmodel.hull(0).and_then(|h| h.trace(start, end)),
+            _ => panic!("non-brush worldmodel"),
+        };
+
+        // a failed trace shouldn't silence anything, so treat it as unobstructed
+        trace.map(|t| t.ratio()).unwrap_or(1.0)
+    }
+
+    /// Estimates the size of the space around the listener as the mean distance to the
+    /// surrounding world geometry.
+    fn estimate_room_size(&self) -> f32 {
+        let origin = self.listener.origin();
+        let total: f32 = ROOM_PROBE_DIRECTIONS
+            .iter()
+            .map(|dir| {
+                let end = origin + Vector3::from(*dir) * ROOM_PROBE_DISTANCE;
+                self.world_trace_ratio(origin, end) * ROOM_PROBE_DISTANCE
+            })
+            .sum();
+
+        total / ROOM_PROBE_DIRECTIONS.len() as f32
+    }
+
+    /// Updates the underwater filter and the room reverb applied to world sounds.
+    ///
+    /// `underwater_cutoff` is the low-pass cutoff frequency to use, or `None` if the listener isn't
+    /// underwater. `reverb_mix` is the volume of the reverb relative to the dry sound.
+    fn update_sound_effects(&self, underwater_cutoff: Option<f32>, reverb_mix: f32) {
+        let effects = self.listener.effects();
+        effects.set_lowpass(underwater_cutoff);
+
+        let reverb = if reverb_mix > 0.0 {
+            Reverb::for_room_size(self.estimate_room_size(), reverb_mix)
+        } else {
+            Reverb::NONE
+        };
+        effects.set_reverb(reverb);
+    }
+
+    // recomputes the listener's potentially hearable set if it has moved to another leaf
+    fn update_listener_phs(&self) {
+        let bsp_data = match self.models[1].kind() {
+            ModelKind::Brush(ref bmodel) => bmodel.bsp_data(),
+            _ => panic!("non-brush worldmodel"),
+        };
+
+        let origin = self.listener.origin();
+        let leaf_id = bsp_data.find_leaf(origin);
+        let mut listener_phs = self.listener_phs.borrow_mut();
+        match *listener_phs {
+            Some((id, _)) if id == leaf_id => (),
+            _ => *listener_phs = Some((leaf_id, Pvs::hearable_from_point(&bsp_data, origin))),
+        }
+    }
+
+    // returns the occlusion of a sound at the given origin. `volume` and `cutoff` are the
+    // settings for a sound behind one wall, as in `Occlusion::new`.
+    fn sound_occlusion(&self, origin: Vector3<f32>, volume: f32, cutoff: f32) -> Occlusion {
+        let listener_origin = self.listener.origin();
+        if (volume >= 1.0 && cutoff <= 0.0) || (origin - listener_origin).magnitude2() < 1.0 {
+            return Occlusion::NONE;
+        }
+
+        let hearable = match (self.models[1].kind(), &*self.listener_phs.borrow()) {
+            (ModelKind::Brush(ref bmodel), Some((_, phs))) => {
+                phs.contains_point(&bmodel.bsp_data(), origin)
+            }
+            _ => true,
+        };
+
+        // sounds outside the PHS are certainly behind walls, so skip the trace
+        let line_of_sight = hearable && self.world_trace_ratio(listener_origin, origin) >= 1.0;
+
+        Occlusion::new(line_of_sight, hearable, volume, cutoff)
+    }
+
+    fn update_sound_spatialization(&self, occlusion: f32, occlusion_cutoff: f32) {
         self.update_listener();
+        self.update_listener_phs();
 
         // update entity sounds
         for opt_chan in self.mixer.channels.iter() {
             if let Some(ref chan) = opt_chan {
                 if chan.channel.in_use() {
-                    chan.channel
-                        .update(self.entities[chan.ent_id].origin, &self.listener);
+                    let origin = self.entities[chan.ent_id].origin;
+                    chan.channel.update(
+                        origin,
+                        &self.listener,
+                        self.sound_occlusion(origin, occlusion, occlusion_cutoff),
+                    );
                 }
             }
         }
 
         // update static sounds
         for ss in self.static_sounds.iter() {
-            ss.update(&self.listener);
+            ss.update(
+                &self.listener,
+                self.sound_occlusion(ss.origin(), occlusion, occlusion_cutoff),
+            );
         }
     }
 }
@@ -471,6 +822,18 @@ pub enum UpdateSource {
     Server(QSocket),
     /// A local server reading updates from a demo file.
     Demo(DemoServer),
+    /// A QuakeWorld server.
+    QuakeWorld(qw::Session),
+}
+
+/// A trace recorded for display by `r_showtraces`.
+#[derive(Clone, Debug)]
+pub struct DebugTrace {
+    pub start: Vector3<f32>,
+    pub end: Vector3<f32>,
+
+    // where the trace stopped, equal to `end` if nothing was hit
+    pub stop: Vector3<f32>,
 }
 
 pub struct Client {
@@ -479,18 +842,53 @@ pub struct Client {
     cmds: Rc<RefCell<CmdRegistry>>,
     console: Rc<RefCell<Console>>,
     audio_device: Rc<rodio::Device>,
+    music_player: Rc<RefCell<MusicPlayer>>,
+
+    // set from the map's worldspawn entity and by the `fog` command
+    fog: Rc<Cell<Fog>>,
+
+    // cvars set by the current map, restored when it ends
+    map_config: MapConfig,
 
     update_src: UpdateSource,
     compose: Vec<u8>,
-    signon: Rc<Cell<SignOnStage>>,
+    host_state: Rc<Cell<HostState>>,
+
+    // most recent traces first
+    debug_traces: VecDeque<DebugTrace>,
+    debug_trace_requested: Rc<Cell<bool>>,
+
+    // Some(highlight) when surface_info was run this frame
+    surface_info_requested: Rc<Cell<Option<bool>>>,
+    highlighted_surface: Option<usize>,
+
+    // the demo being recorded, if any
+    demo_recorder: Option<DemoRecorder>,
+
+    // the seed of the next level's client-side effects, recorded in the demo that starts with it
+    demo_seed: Option<u64>,
+    // true if the demo being recorded was started by cl_autodemo
+    autodemo: bool,
+    // Some(name) when record was run this frame
+    record_requested: Rc<RefCell<Option<String>>>,
+    stop_requested: Rc<Cell<bool>>,
+    // the offset requested by demoseek
+    demo_seek_requested: Rc<Cell<Option<Duration>>>,
+
+    // route recording and saved positions, kept across level changes
+    practice: Rc<RefCell<Practice>>,
+
+    // Some when the camera has been detached from the player by `freecam`
+    freecam: Option<FreeCam>,
+    freecam_requested: Rc<Cell<bool>>,
 
     state: ClientState,
 }
 
 impl Client {
     /// Implements the `reconnect` command.
-    fn cmd_reconnect(signon: Rc<Cell<SignOnStage>>) -> Box<dyn Fn(&[&str])> {
-        Box::new(move |_| signon.set(SignOnStage::Not))
+    fn cmd_reconnect(host_state: Rc<Cell<HostState>>) -> Box<dyn Fn(&[&str])> {
+        Box::new(move |_| host::transition_shared(&host_state, HostEvent::Reconnect))
     }
 
     pub fn play_demo<S>(
@@ -506,7 +904,9 @@ impl Client {
     {
         let mut demo_file = vfs.open(demo_path)?;
         let demo_server = DemoServer::new(&mut demo_file)?;
-        let signon = Rc::new(Cell::new(SignOnStage::Not));
+        let host_state = Rc::new(Cell::new(
+            HostState::Disconnected.transition(HostEvent::PlayDemo)?,
+        ));
 
         Ok(Client {
             vfs: vfs.clone(),
@@ -514,9 +914,28 @@ impl Client {
             cmds,
             console,
             audio_device: audio_device.clone(),
+            music_player: Rc::new(RefCell::new(MusicPlayer::new(
+                vfs.clone(),
+                audio_device.clone(),
+            ))),
+            fog: Rc::new(Cell::new(Fog::default())),
+            map_config: MapConfig::new(ConfigSide::Client),
             update_src: UpdateSource::Demo(demo_server),
             compose: Vec::new(),
-            signon,
+            host_state,
+            debug_traces: VecDeque::new(),
+            debug_trace_requested: Rc::new(Cell::new(false)),
+            surface_info_requested: Rc::new(Cell::new(None)),
+            highlighted_surface: None,
+            demo_recorder: None,
+            demo_seed: None,
+            autodemo: false,
+            record_requested: Rc::new(RefCell::new(None)),
+            stop_requested: Rc::new(Cell::new(false)),
+            demo_seek_requested: Rc::new(Cell::new(None)),
+            practice: Rc::new(RefCell::new(Practice::new())),
+            freecam: None,
+            freecam_requested: Rc::new(Cell::new(false)),
             state: ClientState::new(vfs.clone(), audio_device.clone())?,
         })
     }
@@ -532,11 +951,6 @@ impl Client {
     where
         A: ToSocketAddrs,
     {
-        // set up reconnect
-        let signon = Rc::new(Cell::new(SignOnStage::Not));
-        cmds.borrow_mut()
-            .insert_or_replace("reconnect", Client::cmd_reconnect(signon.clone()));
-
         let mut con_sock = ConnectSocket::bind("0.0.0.0:0")?;
         let server_addr = match server_addrs.to_socket_addrs() {
             Ok(ref mut a) => a.next().ok_or(ClientError::InvalidServerAddress),
@@ -545,6 +959,9 @@ impl Client {
 
         let mut response = None;
 
+        // servers that screen connections send a challenge in response to the first request
+        let mut challenge = None;
+
         for attempt in 0..MAX_CONNECT_ATTEMPTS {
             println!(
                 "Connecting...(attempt {} of {})",
@@ -552,7 +969,7 @@ impl Client {
                 MAX_CONNECT_ATTEMPTS
             );
             con_sock.send_request(
-                Request::connect(net::GAME_NAME, CONNECT_PROTOCOL_VERSION),
+                Request::connect(net::GAME_NAME, CONNECT_PROTOCOL_VERSION, challenge),
                 server_addr,
             )?;
 
@@ -571,11 +988,20 @@ impl Client {
 
                 Ok(opt) => {
                     if let Some((resp, remote)) = opt {
-                        // if this response came from the right server, we're done
-                        if remote == server_addr {
-                            response = Some(resp);
-                            break;
+                        if remote != server_addr {
+                            continue;
+                        }
+
+                        // answer the challenge with a new request
+                        if let Response::Challenge(c) = resp {
+                            debug!("Received challenge {:08X}", c.challenge);
+                            challenge = Some(c.challenge);
+                            continue;
                         }
+
+                        // any other response from the right server means we're done
+                        response = Some(resp);
+                        break;
                     }
                 }
             }
@@ -605,6 +1031,26 @@ impl Client {
 
         // we're done with the connection socket, so turn it into a QSocket with the new address
         let qsock = con_sock.into_qsocket(new_addr);
+        Client::with_connection(qsock, vfs, cvars, cmds, console, audio_device)
+    }
+
+    /// Creates a client for a server that has already accepted the connection `qsock`.
+    pub fn with_connection(
+        qsock: QSocket,
+        vfs: Rc<Vfs>,
+        cvars: Rc<RefCell<CvarRegistry>>,
+        cmds: Rc<RefCell<CmdRegistry>>,
+        console: Rc<RefCell<Console>>,
+        audio_device: Rc<rodio::Device>,
+    ) -> Result<Client, ClientError> {
+        // set up reconnect
+        let host_state = Rc::new(Cell::new(HostState::Disconnected));
+        cmds.borrow_mut().insert_or_replace(
+            "reconnect",
+            "reload the current level",
+            Client::cmd_reconnect(host_state.clone()),
+        );
+        host_state.set(host_state.get().transition(HostEvent::Connect)?);
 
         Ok(Client {
             vfs: vfs.clone(),
@@ -612,15 +1058,109 @@ impl Client {
             cmds,
             console,
             audio_device: audio_device.clone(),
+            music_player: Rc::new(RefCell::new(MusicPlayer::new(
+                vfs.clone(),
+                audio_device.clone(),
+            ))),
+            fog: Rc::new(Cell::new(Fog::default())),
+            map_config: MapConfig::new(ConfigSide::Client),
             update_src: UpdateSource::Server(qsock),
             compose: Vec::new(),
-            signon,
+            host_state,
+            debug_traces: VecDeque::new(),
+            debug_trace_requested: Rc::new(Cell::new(false)),
+            surface_info_requested: Rc::new(Cell::new(None)),
+            highlighted_surface: None,
+            demo_recorder: None,
+            demo_seed: None,
+            autodemo: false,
+            record_requested: Rc::new(RefCell::new(None)),
+            stop_requested: Rc::new(Cell::new(false)),
+            demo_seek_requested: Rc::new(Cell::new(None)),
+            practice: Rc::new(RefCell::new(Practice::new())),
+            freecam: None,
+            freecam_requested: Rc::new(Cell::new(false)),
+            state: ClientState::new(vfs.clone(), audio_device.clone())?,
+        })
+    }
+
+    /// Connects to a QuakeWorld server.
+    pub fn connect_qw<A>(
+        server_addrs: A,
+        vfs: Rc<Vfs>,
+        cvars: Rc<RefCell<CvarRegistry>>,
+        cmds: Rc<RefCell<CmdRegistry>>,
+        console: Rc<RefCell<Console>>,
+        audio_device: Rc<rodio::Device>,
+    ) -> Result<Client, ClientError>
+    where
+        A: ToSocketAddrs,
+    {
+        let server_addr = match server_addrs.to_socket_addrs() {
+            Ok(ref mut a) => a.next().ok_or(ClientError::InvalidServerAddress),
+            Err(_) => Err(ClientError::InvalidServerAddress),
+        }?;
+
+        let host_state = Rc::new(Cell::new(
+            HostState::Disconnected.transition(HostEvent::Connect)?,
+        ));
+        let session = qw::Session::connect(
+            server_addr,
+            vfs.clone(),
+            &cvars.borrow(),
+            &mut cmds.borrow_mut(),
+            host_state.clone(),
+        )?;
+
+        Ok(Client {
+            vfs: vfs.clone(),
+            cvars,
+            cmds,
+            console,
+            audio_device: audio_device.clone(),
+            music_player: Rc::new(RefCell::new(MusicPlayer::new(
+                vfs.clone(),
+                audio_device.clone(),
+            ))),
+            fog: Rc::new(Cell::new(Fog::default())),
+            map_config: MapConfig::new(ConfigSide::Client),
+            update_src: UpdateSource::QuakeWorld(session),
+            compose: Vec::new(),
+            host_state,
+            debug_traces: VecDeque::new(),
+            debug_trace_requested: Rc::new(Cell::new(false)),
+            surface_info_requested: Rc::new(Cell::new(None)),
+            highlighted_surface: None,
+            demo_recorder: None,
+            demo_seed: None,
+            autodemo: false,
+            record_requested: Rc::new(RefCell::new(None)),
+            stop_requested: Rc::new(Cell::new(false)),
+            demo_seek_requested: Rc::new(Cell::new(None)),
+            practice: Rc::new(RefCell::new(Practice::new())),
+            freecam: None,
+            freecam_requested: Rc::new(Cell::new(false)),
             state: ClientState::new(vfs.clone(), audio_device.clone())?,
         })
     }
 
+    /// Ends the game.
+    ///
+    /// The connection or demo file is closed when the client is dropped.
     pub fn disconnect(&self) {
-        unimplemented!();
+        host::transition_shared(&self.host_state, HostEvent::Disconnect);
+    }
+
+    /// Returns the state of the client's connection.
+    pub fn host_state(&self) -> HostState {
+        self.host_state.get()
+    }
+
+    // moves to the state following `event`
+    fn transition(&self, event: HostEvent) -> Result<(), ClientError> {
+        let next = self.host_state.get().transition(event)?;
+        self.host_state.set(next);
+        Ok(())
     }
 
     pub fn add_cmd(&mut self, cmd: ClientCmd) -> Result<(), ClientError> {
@@ -628,6 +1168,22 @@ impl Client {
         Ok(())
     }
 
+    /// Sends a console command to the server to be executed there.
+    ///
+    /// This has no effect during demo playback.
+    pub fn forward_cmd<S>(&mut self, cmd: S) -> Result<(), ClientError>
+    where
+        S: AsRef<str>,
+    {
+        if let UpdateSource::Demo(_) = self.update_src {
+            return Ok(());
+        }
+
+        self.add_cmd(ClientCmd::StringCmd {
+            cmd: cmd.as_ref().to_owned(),
+        })
+    }
+
     fn cvar_value<S>(&self, name: S) -> Result<f32, ClientError>
     where
         S: AsRef<str>,
@@ -643,12 +1199,20 @@ impl Client {
         game_input: &mut GameInput,
         frame_time: Duration,
     ) -> Result<(), ClientError> {
-        if let UpdateSource::Demo(_) = self.update_src {
-            // ignore game input during demo playback
+        // ignore game input during demo playback and while loading
+        if !self.host_state.get().sends_input() {
             return Ok(());
         }
 
         let mlook = game_input.action_state(Action::MLook);
+        let gamepad_vars = self.gamepad_vars()?;
+
+        // the free camera takes the look controls, leaving the player facing the same way
+        let player_angles = self.state.view.input_angles();
+        if let Some(ref freecam) = self.freecam {
+            self.state.view.update_input_angles(freecam.angles());
+        }
+
         self.state.view.handle_input(
             frame_time,
             game_input,
@@ -658,8 +1222,14 @@ impl Client {
             self.cvar_value("cl_pitchspeed")?,
             self.cvar_value("cl_yawspeed")?,
             self.mouse_vars()?,
+            gamepad_vars,
         );
 
+        if let Some(ref mut freecam) = self.freecam {
+            freecam.set_angles(self.state.view.input_angles());
+            self.state.view.update_input_angles(player_angles);
+        }
+
         let cl_sidespeed = self.cvar_value("cl_sidespeed")?;
         let cl_upspeed = self.cvar_value("cl_upspeed")?;
 
@@ -676,14 +1246,28 @@ impl Client {
             * (game_input.action_state(Action::MoveUp) as i32
                 - game_input.action_state(Action::MoveDown) as i32) as f32;
 
+        let cl_forwardspeed = self.cvar_value("cl_forwardspeed")?;
+        let cl_backspeed = self.cvar_value("cl_backspeed")?;
+
         let mut forwardmove = 0.0;
         if !game_input.action_state(Action::KLook) {
-            let cl_forwardspeed = self.cvar_value("cl_forwardspeed")?;
-            let cl_backspeed = self.cvar_value("cl_backspeed")?;
             forwardmove += cl_forwardspeed * game_input.action_state(Action::Forward) as i32 as f32;
             forwardmove -= cl_backspeed * game_input.action_state(Action::Back) as i32 as f32;
         }
 
+        // the left stick moves proportionally to how far it's pushed
+        let stick = gamepad::apply_response(
+            game_input.gamepad_sticks().left,
+            gamepad_vars.joy_deadzone,
+            gamepad_vars.joy_exponent,
+        );
+        sidemove += cl_sidespeed * stick.x;
+        if stick.y > 0.0 {
+            forwardmove += cl_forwardspeed * stick.y;
+        } else {
+            forwardmove += cl_backspeed * stick.y;
+        }
+
         if game_input.action_state(Action::Speed) {
             let cl_movespeedkey = self.cvar_value("cl_movespeedkey")?;
             sidemove *= cl_movespeedkey;
@@ -701,29 +1285,71 @@ impl Client {
             button_flags |= ButtonFlags::JUMP;
         }
 
+        // the player stands still while the camera flies
+        if let Some(ref mut freecam) = self.freecam {
+            freecam.fly(frame_time, forwardmove, sidemove, upmove);
+            forwardmove = 0.0;
+            sidemove = 0.0;
+            upmove = 0.0;
+            button_flags = ButtonFlags::empty();
+        }
+
+        self.predict_attack(button_flags.contains(ButtonFlags::ATTACK))?;
+
         if !mlook {
-            // TODO: IN_Move (mouse / joystick / gamepad)
+            // TODO: IN_Move (mouse)
         }
 
         let send_time = self.state.msg_times[0];
         // send "raw" angles without any pitch/roll from movement or damage
         let angles = self.state.view.input_angles();
-        let move_cmd = ClientCmd::Move {
-            send_time,
-            angles: Vector3::new(angles.pitch, angles.yaw, angles.roll),
-            fwd_move: forwardmove as i16,
-            side_move: sidemove as i16,
-            up_move: upmove as i16,
-            button_flags,
-            impulse: game_input.impulse(),
+        let angles = Vector3::new(angles.pitch, angles.yaw, angles.roll);
+        let impulse = match game_input.weapon_cycle() {
+            Some(cycle) => {
+                weapon::cycle_impulse(cycle, self.items(), self.active_weapon(), self.stats())
+                    .unwrap_or(0)
+            }
+            None => game_input.impulse(),
         };
-        // debug!("Sending move command: {:?}", move_cmd);
 
-        let mut msg = Vec::new();
-        move_cmd.serialize(&mut msg)?;
+        self.state.prediction.add_move(MoveCmd {
+            angles,
+            forward_move: forwardmove,
+            side_move: sidemove,
+            up_move: upmove,
+            jump: button_flags.contains(ButtonFlags::JUMP),
+            duration: engine::duration_to_f32(frame_time),
+        });
 
         match self.update_src {
-            UpdateSource::Server(ref mut qsock) => qsock.send_msg_unreliable(&msg)?,
+            UpdateSource::Server(ref mut qsock) => {
+                let move_cmd = ClientCmd::Move {
+                    send_time,
+                    angles,
+                    fwd_move: forwardmove as i16,
+                    side_move: sidemove as i16,
+                    up_move: upmove as i16,
+                    button_flags,
+                    impulse,
+                };
+                // debug!("Sending move command: {:?}", move_cmd);
+
+                let mut msg = Vec::new();
+                move_cmd.serialize(&mut msg)?;
+                qsock.send_msg_unreliable(&msg)?;
+            }
+
+            // QuakeWorld moves are sent along with the rest of the client's packet
+            UpdateSource::QuakeWorld(ref mut session) => session.set_move(net::qw::UserCmd {
+                msec: 0,
+                angles,
+                forward_move: forwardmove as i16,
+                side_move: sidemove as i16,
+                up_move: upmove as i16,
+                buttons: button_flags,
+                impulse,
+            }),
+
             UpdateSource::Demo(_) => unreachable!(),
         };
 
@@ -742,6 +1368,12 @@ impl Client {
                 }
             }
 
+            UpdateSource::QuakeWorld(ref mut session) => {
+                session.queue_reliable(&self.compose);
+                self.compose.clear();
+                session.transmit()?;
+            }
+
             // TODO: error here for strictness?
             UpdateSource::Demo(_) => warn!("Attempted send in demo"),
         }
@@ -749,6 +1381,39 @@ impl Client {
         Ok(())
     }
 
+    // keep an idle connection alive and detect when the server has stopped responding
+    fn update_connection(&mut self) -> Result<(), ClientError> {
+        if self.host_state.get() == HostState::Disconnected {
+            return Ok(());
+        }
+
+        let timeout = engine::duration_from_f32(self.cvar_value("net_messagetimeout")?);
+
+        if let UpdateSource::Server(ref mut qsock) = self.update_src {
+            // a dead server ends the game like any other disconnect
+            if qsock.timed_out(timeout) {
+                self.console.borrow().print(
+                    PrintLevel::Game,
+                    format!("{}\n", ClientError::ConnectionTimedOut),
+                );
+                self.disconnect();
+                return Ok(());
+            }
+
+            qsock.resend_unacked()?;
+
+            // moves are sent every frame in game, but nothing is sent while loading or paused,
+            // which would let NAT mappings expire
+            if self.compose.is_empty()
+                && qsock.time_since_send() > Duration::seconds(KEEPALIVE_INTERVAL)
+            {
+                ClientCmd::NoOp.serialize(&mut self.compose)?;
+            }
+        }
+
+        Ok(())
+    }
+
     // return an error if the given entity ID does not refer to a valid entity
     fn check_entity_id(&self, id: usize) -> Result<(), ClientError> {
         match id {
@@ -770,24 +1435,18 @@ impl Client {
 
     /// Spawn an entity with the given ID, also spawning any uninitialized
     /// entities between the former last entity and the new one.
+    ///
+    /// If an entity with this ID already exists, its baseline is replaced.
     // TODO: skipping entities indicates that the entities have been freed by
     // the server. it may make more sense to use a HashMap to store entities by
     // ID since the lookup table is relatively sparse.
-    pub fn spawn_entities(
-        &mut self,
-        ent_id: u16,
-        model_id: u8,
-        frame_id: u8,
-        colormap: u8,
-        skin_id: u8,
-        origin: Vector3<f32>,
-        angles: Vector3<Deg<f32>>,
-    ) -> Result<(), ClientError> {
+    pub fn spawn_entities(&mut self, ent_id: u16, baseline: EntityState) {
         let id = ent_id as usize;
 
-        // don't clobber existing entities
         if id < self.state.entities.len() {
-            Err(ClientError::EntityExists(id))?;
+            debug!("Replacing baseline of entity {} with {:?}", id, baseline);
+            self.state.entities[id].set_baseline(baseline);
+            return;
         }
 
         // spawn intermediate entities (uninitialized)
@@ -796,16 +1455,6 @@ impl Client {
             self.state.entities.push(ClientEntity::uninitialized());
         }
 
-        let baseline = EntityState {
-            origin,
-            angles,
-            model_id: model_id as usize,
-            frame_id: frame_id as usize,
-            colormap,
-            skin_id: skin_id as usize,
-            effects: EntityEffects::empty(),
-        };
-
         debug!(
             "Spawning entity with id {} from baseline {:?}",
             id, baseline
@@ -814,8 +1463,6 @@ impl Client {
         self.state
             .entities
             .push(ClientEntity::from_baseline(baseline));
-
-        Ok(())
     }
 
     pub fn get_entity(&self, id: usize) -> Result<&ClientEntity, ClientError> {
@@ -831,17 +1478,18 @@ impl Client {
     pub fn parse_server_msg(&mut self) -> Result<(), ClientError> {
         let (msg, demo_view_angles) = match self.update_src {
             UpdateSource::Server(ref mut qsock) => {
-                let msg = qsock.recv_msg(match self.signon.get() {
-                    // if we're in the game, don't block waiting for messages
-                    SignOnStage::Done => BlockingMode::NonBlocking,
-
-                    // otherwise, give the server some time to respond
+                let msg = qsock.recv_msg(if self.host_state.get().awaits_server() {
+                    // give the server some time to respond
                     // TODO: might make sense to make this a future or something
-                    _ => BlockingMode::Timeout(Duration::seconds(5)),
+                    BlockingMode::Timeout(Duration::seconds(5))
+                } else {
+                    // if we're in the game, don't block waiting for messages
+                    BlockingMode::NonBlocking
                 })?;
 
                 (msg, None)
             }
+            UpdateSource::QuakeWorld(ref mut session) => (session.recv(self.state.time)?, None),
             UpdateSource::Demo(ref mut demo_srv) => {
                 // only get the next update once we've made it all the way to
                 // the previous one
@@ -874,6 +1522,10 @@ impl Client {
         }
 
         let mut reader = BufReader::new(msg.as_slice());
+        let mut level_start = false;
+        let mut level_end = false;
+        let mut disconnected = false;
+        let mut autodemo_map = None;
 
         while let Some(cmd) = ServerCmd::deserialize(&mut reader)? {
             match cmd {
@@ -884,9 +1536,14 @@ impl Client {
 
                 ServerCmd::NoOp => (),
 
-                ServerCmd::CdTrack { .. } => {
-                    // TODO: play CD track
-                    warn!("CD tracks not yet implemented");
+                ServerCmd::CdTrack { track, .. } => {
+                    // missing music shouldn't interrupt the game
+                    if let Err(e) = self.music_player.borrow_mut().play_track(track) {
+                        self.console.borrow().print(
+                            PrintLevel::Dev,
+                            format!("Couldn't play CD track {}: {}", track, e),
+                        );
+                    }
                 }
 
                 ServerCmd::CenterPrint { text } => {
@@ -971,6 +1628,7 @@ impl Client {
                 ServerCmd::Cutscene { text } => {
                     self.state.intermission = Some(IntermissionKind::Cutscene { text });
                     self.state.completion_time = Some(self.state.time);
+                    self.transition(HostEvent::Intermission)?;
                 }
 
                 ServerCmd::Damage {
@@ -1013,35 +1671,22 @@ impl Client {
                     );
                 }
 
-                ServerCmd::Disconnect => self.disconnect(),
+                ServerCmd::Disconnect => {
+                    disconnected = true;
+                    self.disconnect();
+                }
 
                 ServerCmd::FastUpdate(ent_update) => {
                     // first update signals the last sign-on stage
-                    if self.signon.get() == SignOnStage::Begin {
-                        self.signon.set(SignOnStage::Done);
-                        let signon = self.signon.get();
-                        self.handle_signon(signon)?;
+                    if self.host_state.get().signon_stage() == SignOnStage::Begin {
+                        self.handle_signon(SignOnStage::Done)?;
                     }
 
+                    // the server deltas entities without a baseline against an empty state, so
+                    // the first update can't be used as the baseline
                     let ent_id = ent_update.ent_id as usize;
                     if ent_id >= self.state.entities.len() {
-                        self.spawn_entities(
-                            ent_id as u16,
-                            ent_update.model_id.unwrap_or(0),
-                            ent_update.frame_id.unwrap_or(0),
-                            ent_update.colormap.unwrap_or(0),
-                            ent_update.skin_id.unwrap_or(0),
-                            Vector3::new(
-                                ent_update.origin_x.unwrap_or(0.0),
-                                ent_update.origin_y.unwrap_or(0.0),
-                                ent_update.origin_z.unwrap_or(0.0),
-                            ),
-                            Vector3::new(
-                                ent_update.pitch.unwrap_or(Deg(0.0)),
-                                ent_update.yaw.unwrap_or(Deg(0.0)),
-                                ent_update.roll.unwrap_or(Deg(0.0)),
-                            ),
-                        )?;
+                        self.spawn_entities(ent_id as u16, EntityState::uninitialized());
                     }
 
                     self.state.entities[ent_id].update(self.state.msg_times, ent_update);
@@ -1083,12 +1728,16 @@ impl Client {
                 ServerCmd::Finale { text } => {
                     self.state.intermission = Some(IntermissionKind::Finale { text });
                     self.state.completion_time = Some(self.state.time);
+                    self.transition(HostEvent::Intermission)?;
+                    level_end = true;
                 }
 
                 ServerCmd::FoundSecret => self.state.stats[ClientStat::FoundSecrets as usize] += 1,
                 ServerCmd::Intermission => {
                     self.state.intermission = Some(IntermissionKind::Intermission);
                     self.state.completion_time = Some(self.state.time);
+                    self.transition(HostEvent::Intermission)?;
+                    level_end = true;
                 }
                 ServerCmd::KilledMonster => {
                     self.state.stats[ClientStat::KilledMonsters as usize] += 1
@@ -1123,10 +1772,7 @@ impl Client {
                     }
                 }
 
-                ServerCmd::Print { text } => {
-                    // TODO: print to in-game console
-                    println!("{}", text);
-                }
+                ServerCmd::Print { text } => self.console.borrow().print(PrintLevel::Game, text),
 
                 ServerCmd::ServerInfo {
                     protocol_version,
@@ -1136,6 +1782,8 @@ impl Client {
                     model_precache,
                     sound_precache,
                 } => {
+                    level_start = true;
+                    autodemo_map = model_precache.first().map(|m| demo::map_name(m).to_owned());
                     self.update_server_info(
                         protocol_version,
                         max_clients,
@@ -1157,6 +1805,11 @@ impl Client {
                     });
                 }
 
+                ServerCmd::SetPause { paused } => {
+                    self.state.set_paused(paused);
+                    self.music_player.borrow().set_paused(paused);
+                }
+
                 ServerCmd::SetView { ent_id } => {
                     // view entity may not have been spawned yet, so check
                     // against both max_players and the current number of
@@ -1201,17 +1854,32 @@ impl Client {
 
                     let volume = volume.unwrap_or(DEFAULT_SOUND_PACKET_VOLUME);
                     let attenuation = attenuation.unwrap_or(DEFAULT_SOUND_PACKET_ATTENUATION);
+                    let sound_id = sound_id as usize;
                     // TODO: apply volume, attenuation, spatialization
-                    self.state.mixer.start_sound(
-                        self.state.sounds[sound_id as usize].clone(),
-                        self.state.msg_times[0],
-                        entity_id as usize,
-                        channel,
-                        volume as f32 / 255.0,
-                        attenuation,
-                        &self.state.entities,
-                        &self.state.listener,
-                    );
+                    if self.state.beam_sound_ids.contains(&sound_id) {
+                        self.state.mixer.start_beam_loop(
+                            sound_id,
+                            self.state.sounds[sound_id].clone(),
+                            self.state.msg_times[0],
+                            entity_id as usize,
+                            channel,
+                            volume as f32 / 255.0,
+                            attenuation,
+                            &self.state.entities,
+                            &self.state.listener,
+                        );
+                    } else {
+                        self.state.mixer.start_sound(
+                            self.state.sounds[sound_id].clone(),
+                            self.state.msg_times[0],
+                            entity_id as usize,
+                            channel,
+                            volume as f32 / 255.0,
+                            attenuation,
+                            &self.state.entities,
+                            &self.state.listener,
+                        );
+                    }
                 }
 
                 ServerCmd::SpawnBaseline {
@@ -1224,15 +1892,24 @@ impl Client {
                     angles,
                 } => {
                     self.spawn_entities(
-                        ent_id, model_id, frame_id, colormap, skin_id, origin, angles,
-                    )?;
-                }
-
-                ServerCmd::SpawnStatic {
-                    model_id,
-                    frame_id,
-                    colormap,
-                    skin_id,
+                        ent_id,
+                        EntityState {
+                            origin,
+                            angles,
+                            model_id: model_id as usize,
+                            frame_id: frame_id as usize,
+                            colormap,
+                            skin_id: skin_id as usize,
+                            effects: EntityEffects::empty(),
+                        },
+                    );
+                }
+
+                ServerCmd::SpawnStatic {
+                    model_id,
+                    frame_id,
+                    colormap,
+                    skin_id,
                     origin,
                     angles,
                 } => {
@@ -1269,10 +1946,38 @@ impl Client {
                 }
 
                 ServerCmd::TempEntity { temp_entity } => {
-                    self.spawn_temp_entity(self.state.time, &temp_entity)
+                    // impacts of the player's own shots may have been predicted already
+                    let predicted = match &temp_entity {
+                        TempEntity::Point {
+                            kind: PointEntityKind::Gunshot,
+                            origin,
+                        } => self
+                            .state
+                            .attack_prediction
+                            .confirm_impact(self.state.time, *origin),
+                        _ => false,
+                    };
+
+                    if !predicted {
+                        self.spawn_temp_entity(self.state.time, &temp_entity)
+                    }
                 }
 
-                ServerCmd::StuffText { text } => self.console.borrow_mut().stuff_text(text),
+                ServerCmd::StopSound { entity_id, channel } => self
+                    .state
+                    .mixer
+                    .stop_sound(entity_id as usize, channel as i8),
+
+                ServerCmd::StuffText { text } => {
+                    // effects in a demo replay from the seed they were recorded with
+                    let seed = DemoMetadata::from_text(&text)
+                        .and_then(|metadata| metadata.get("seed")?.parse().ok());
+                    if let Some(seed) = seed {
+                        self.state.reseed(seed);
+                    }
+
+                    self.console.borrow_mut().stuff_text(text)
+                }
 
                 ServerCmd::Time { time } => {
                     self.state.msg_times[1] = self.state.msg_times[0];
@@ -1385,12 +2090,118 @@ impl Client {
             }
         }
 
+        if let Some(map) = autodemo_map {
+            if self.demo_recorder.is_none() && self.cvar_value("cl_autodemo")? != 0.0 {
+                self.start_autodemo(&map)?;
+            }
+        }
+
+        self.record_message(&msg, level_start)?;
+
+        // automatic demos cover a single level, but any demo ends with the connection
+        if disconnected || (level_end && self.autodemo) {
+            self.stop_recording();
+        }
+
+        Ok(())
+    }
+
+    // Writes a message from the server to the demo being recorded, followed by a keyframe if one
+    // is due.
+    fn record_message(&mut self, msg: &[u8], level_start: bool) -> Result<(), ClientError> {
+        let interval = self.cvar_value("cl_demokeyframe")?;
+        let angles = self.state.view.input_angles();
+        let view_angles = Vector3::new(angles.pitch, angles.yaw, angles.roll);
+        let time = engine::duration_to_f32(self.state.msg_times[0]);
+        let in_game = self.host_state.get().in_game();
+
+        let keyframe_due = match self.demo_recorder {
+            Some(ref mut recorder) => {
+                recorder.record(view_angles, msg, level_start)?;
+                in_game && recorder.keyframe_due(time, interval)
+            }
+            None => return Ok(()),
+        };
+
+        if keyframe_due {
+            let cmds = self.keyframe_cmds();
+            if let Some(ref mut recorder) = self.demo_recorder {
+                recorder.write_keyframe(view_angles, time, &cmds)?;
+            }
+        }
+
         Ok(())
     }
 
+    // Restates the client's state as server commands for a demo keyframe.
+    //
+    // Only state that persists between updates is included, along with the entities in the last
+    // update. Everything else is sent again with every update, and the keyframe takes the time of
+    // the update before it.
+    fn keyframe_cmds(&self) -> Vec<ServerCmd> {
+        let mut cmds = Vec::new();
+
+        for (stat_id, value) in self.state.stats.iter().enumerate() {
+            if let Some(stat) = ClientStat::from_usize(stat_id) {
+                cmds.push(ServerCmd::UpdateStat {
+                    stat,
+                    value: *value,
+                });
+            }
+        }
+
+        let mut light_styles: Vec<_> = self.state.light_styles.iter().collect();
+        light_styles.sort_by_key(|(id, _)| **id);
+        for (id, value) in light_styles {
+            cmds.push(ServerCmd::LightStyle {
+                id: *id,
+                value: value.clone(),
+            });
+        }
+
+        for (player_id, info) in self
+            .state
+            .player_info
+            .iter()
+            .enumerate()
+            .take(self.state.max_players)
+        {
+            if let Some(info) = info {
+                let player_id = player_id as u8;
+                cmds.push(ServerCmd::UpdateName {
+                    player_id,
+                    new_name: info.name.clone(),
+                });
+                cmds.push(ServerCmd::UpdateFrags {
+                    player_id,
+                    new_frags: info.frags as i16,
+                });
+                cmds.push(ServerCmd::UpdateColors {
+                    player_id,
+                    new_colors: info.colors,
+                });
+            }
+        }
+
+        // entity 0 is the world, which is never updated
+        for (ent_id, ent) in self.state.entities.iter().enumerate().skip(1) {
+            if ent.msg_time() == self.state.msg_times[0] {
+                cmds.push(ServerCmd::FastUpdate(EntityUpdate::from_entity_state(
+                    ent_id as u16,
+                    ent.msg_state(),
+                    &ent.baseline,
+                )));
+            }
+        }
+
+        cmds
+    }
+
     fn handle_signon(&mut self, stage: SignOnStage) -> Result<(), ClientError> {
+        self.transition(HostEvent::SignOn(stage))?;
+
         match stage {
-            SignOnStage::Not => (), // TODO this is an error (invalid value)
+            SignOnStage::Not => (), // rejected by the transition
             SignOnStage::Prespawn => {
                 self.add_cmd(ClientCmd::StringCmd {
                     cmd: String::from("prespawn"),
@@ -1421,8 +2232,6 @@ impl Client {
             }
         }
 
-        self.signon.set(stage);
-
         Ok(())
     }
 
@@ -1442,21 +2251,65 @@ impl Client {
             Err(ClientError::UnrecognizedProtocol(protocol_version))?;
         }
 
-        // TODO: print sign-on message to in-game console
-        println!("{}", message);
+        // announce the level in colored text, as in the original engine
+        {
+            let console = self.console.borrow();
+            console.print(PrintLevel::Game, "");
+            console.print(PrintLevel::Game, format!("\x02{}", message));
+        }
 
         // parse model precache
         // TODO: validate submodel names
+        let limits = LoadLimits::from_cvars(&self.cvars.borrow());
+        let mut map_fog = None;
+        let mut map_script = None;
         for mod_name in model_precache {
             if mod_name.ends_with(".bsp") {
                 let bsp_data = self.vfs.open(&mod_name)?;
-                let (mut brush_models, _) = bsp::load(bsp_data).unwrap();
+
+                // colored lightmaps are stored alongside the map, e.g. maps/e1m1.lit
+                let lit_name = format!("{}.lit", mod_name.trim_end_matches(".bsp"));
+                let lit_data = self.vfs.open(&lit_name).ok();
+                if lit_data.is_some() {
+                    debug!("Loading colored lightmaps from {}", lit_name);
+                }
+
+                let (mut brush_models, ent_string) =
+                    bsp::load_with_lit(bsp_data, lit_data, &limits)
+                        .map_err(|e| ClientError::InvalidMap(mod_name.clone(), e.to_string()))?;
                 new_client_state.models.append(&mut brush_models);
+
+                // the first map in the precache is the worldmodel
+                if map_fog.is_none() {
+                    let worldspawn = worldspawn(&ent_string).unwrap_or_default();
+                    map_fog = Some(
+                        worldspawn
+                            .get("fog")
+                            .and_then(|value| Fog::from_worldspawn(value))
+                            .unwrap_or_default(),
+                    );
+
+                    // the previous map's settings are put back before this one's are applied
+                    let cvars = self.cvars.borrow();
+                    self.map_config.begin(&cvars);
+                    self.map_config.apply_worldspawn(&cvars, &worldspawn);
+
+                    // only the parts of the script for the client are run here, the server runs
+                    // its own copy for the cvars it owns
+                    let script = mapconfig::script_path(&mod_name);
+                    if let Ok(mut file) = self.vfs.open(&script) {
+                        let mut text = String::new();
+                        if file.read_to_string(&mut text).is_ok() {
+                            map_script =
+                                Some((script, self.map_config.apply_script(&cvars, &text)));
+                        }
+                    }
+                }
             } else if !mod_name.starts_with("*") {
                 debug!("Loading model {}", mod_name);
                 new_client_state
                     .models
-                    .push(Model::load(&self.vfs, mod_name)?);
+                    .push(Model::load(&self.vfs, mod_name, &limits)?);
             }
 
             // TODO: send keepalive message?
@@ -1472,6 +2325,11 @@ impl Client {
         for ref snd_name in sound_precache {
             debug!("Loading sound {}", snd_name);
 
+            if beam::LOOPED_SOUNDS.contains(&snd_name.as_str()) {
+                let id = new_client_state.sounds.len();
+                new_client_state.beam_sound_ids.push(id);
+            }
+
             new_client_state
                 .sounds
                 .push(AudioSource::load(&self.vfs, snd_name)?);
@@ -1485,10 +2343,21 @@ impl Client {
         };
 
         new_client_state.max_players = server_info.max_clients as usize;
+        if let Some(seed) = self.demo_seed.take() {
+            new_client_state.reseed(seed);
+        }
 
         // TODO: set up rest of client state (R_NewMap)
 
         self.state = new_client_state;
+        self.fog.set(map_fog.unwrap_or_default());
+        if let Some((script, text)) = map_script {
+            debug!("Executing {}", script);
+            self.console.borrow().stuff_text(text);
+        }
+        self.debug_traces.clear();
+        self.highlighted_surface = None;
+        self.freecam = None;
 
         // TODO: replace console commands holding `Rc`s to the old ClientState
 
@@ -1496,31 +2365,38 @@ impl Client {
     }
 
     pub fn signon_stage(&self) -> SignOnStage {
-        self.signon.get()
+        self.host_state.get().signon_stage()
     }
 
     pub fn entities(&self) -> Option<&[ClientEntity]> {
-        match self.signon.get() {
-            SignOnStage::Done => Some(&self.state.entities),
-            _ => None,
+        match self.host_state.get().in_game() {
+            true => Some(&self.state.entities),
+            false => None,
         }
     }
 
     pub fn models(&self) -> Option<&[Model]> {
-        match self.signon.get() {
-            SignOnStage::Done => Some(&self.state.models),
-            _ => None,
+        match self.host_state.get().in_game() {
+            true => Some(&self.state.models),
+            false => None,
         }
     }
 
+    // the origin of the player entity, ahead of the server if movement is predicted
+    fn player_origin(&self) -> Vector3<f32> {
+        self.state
+            .prediction
+            .origin()
+            .unwrap_or(self.state.entities[self.state.view.entity_id()].origin)
+    }
+
     pub fn view_origin(&self) -> Vector3<f32> {
-        self.state.entities[self.state.view.entity_id()].origin
-            + Vector3::new(0.0, 0.0, self.state.view.view_height())
+        self.player_origin() + Vector3::new(0.0, 0.0, self.state.view.view_height())
     }
 
     pub fn view_angles(&self, time: Duration) -> Result<Angles, ClientError> {
         let angles = match self.update_src {
-            UpdateSource::Server(_) => self.state.view.angles(
+            UpdateSource::Server(_) | UpdateSource::QuakeWorld(_) => self.state.view.angles(
                 time,
                 self.state.intermission.as_ref(),
                 self.state.velocity,
@@ -1541,10 +2417,186 @@ impl Client {
         Ok(angles)
     }
 
+    /// Returns the position of the camera, which is the player's view unless `freecam` is on.
+    pub fn camera_origin(&self) -> Vector3<f32> {
+        match self.freecam {
+            Some(ref freecam) => freecam.origin(),
+            None => self.view_origin(),
+        }
+    }
+
+    /// Returns the orientation of the camera, which is the player's view unless `freecam` is on.
+    pub fn camera_angles(&self, time: Duration) -> Result<Angles, ClientError> {
+        match self.freecam {
+            Some(ref freecam) => Ok(freecam.angles()),
+            None => self.view_angles(time),
+        }
+    }
+
     pub fn view_ent(&self) -> usize {
         self.state.view.entity_id()
     }
 
+    /// Returns `true` if the client is playing back a demo.
+    pub fn demo_playback(&self) -> bool {
+        match self.update_src {
+            UpdateSource::Demo(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the client is playing back a demo and every message has been read.
+    pub fn demo_finished(&self) -> bool {
+        match self.update_src {
+            UpdateSource::Demo(ref demo_srv) => demo_srv.finished(),
+            _ => false,
+        }
+    }
+
+    // Starts recording a demo called `name` in the base directory.
+    fn start_recording(&mut self, name: String) {
+        if self.demo_playback() {
+            println!("Can't record during demo playback");
+            return;
+        }
+
+        let mut file_name = name;
+        if !file_name.ends_with(".dem") {
+            file_name.push_str(".dem");
+        }
+
+        let path = Path::new(common::DEFAULT_BASEDIR).join(file_name);
+        match DemoRecorder::create(&path) {
+            Ok(recorder) => {
+                self.stop_recording();
+                self.demo_recorder = Some(self.with_demo_metadata(recorder));
+                self.autodemo = false;
+                println!(
+                    "Recording to {}, starting with the next level",
+                    path.display()
+                );
+            }
+
+            Err(e) => println!("Couldn't record to {}: {}", path.display(), e),
+        }
+    }
+
+    // Adds a metadata block describing the player and client to a new demo, unless
+    // cl_demometadata is 0.
+    fn with_demo_metadata(&mut self, recorder: DemoRecorder) -> DemoRecorder {
+        let cvars = self.cvars.borrow();
+        if cvars.get_value("cl_demometadata").unwrap_or(1.0) == 0.0 {
+            return recorder;
+        }
+
+        let seed = rand::random();
+        self.demo_seed = Some(seed);
+
+        let mut metadata = DemoMetadata::new()
+            .with(
+                "client",
+                format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            )
+            .with(
+                "recorded",
+                Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            )
+            .with("seed", seed.to_string());
+        if let Ok(name) = cvars.get("_cl_name") {
+            metadata = metadata.with("player", name);
+        }
+
+        recorder.with_metadata(metadata)
+    }
+
+    // Starts recording the level that is loading on `map` to a timestamped demo, deleting the
+    // oldest automatic demos beyond cl_autodemo_keep.
+    fn start_autodemo(&mut self, map: &str) -> Result<(), ClientError> {
+        if self.demo_playback() {
+            return Ok(());
+        }
+
+        let base_dir = Path::new(common::DEFAULT_BASEDIR);
+        let path = base_dir.join(demo::autodemo_name(Local::now(), map));
+        match DemoRecorder::create(&path) {
+            Ok(recorder) => {
+                self.demo_recorder = Some(self.with_demo_metadata(recorder));
+                self.autodemo = true;
+                self.console.borrow().print(
+                    PrintLevel::Dev,
+                    format!("Recording to {}\n", path.display()),
+                );
+            }
+
+            Err(e) => {
+                println!("Couldn't record to {}: {}", path.display(), e);
+                return Ok(());
+            }
+        }
+
+        let keep = self.cvar_value("cl_autodemo_keep")?.max(0.0) as usize;
+        let file_names: Vec<String> = match fs::read_dir(base_dir) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .filter_map(|e| e.file_name().into_string().ok())
+                .collect(),
+            Err(_) => return Ok(()),
+        };
+
+        for name in demo::expired_autodemos(&file_names, keep) {
+            if let Err(e) = fs::remove_file(base_dir.join(name)) {
+                println!("Couldn't delete old demo {}: {}", name, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Finishes the demo being recorded, if there is one.
+    fn stop_recording(&mut self) {
+        let recorder = match self.demo_recorder.take() {
+            Some(r) => r,
+            None => return,
+        };
+        self.autodemo = false;
+
+        let angles = self.state.view.input_angles();
+        let view_angles = Vector3::new(angles.pitch, angles.yaw, angles.roll);
+        match recorder.stop(view_angles) {
+            Ok(()) => println!("Recording stopped"),
+            Err(e) => println!("Couldn't finish recording: {}", e),
+        }
+    }
+
+    /// Returns `true` if a demo is being recorded.
+    pub fn recording(&self) -> bool {
+        self.demo_recorder.is_some()
+    }
+
+    /// Moves demo playback to the last keyframe at or before `offset` from the start of the demo.
+    ///
+    /// The level is loaded again from the demo, so playback may resume a little before `offset`,
+    /// or from the beginning of a demo recorded without keyframes.
+    pub fn seek_demo(&mut self, offset: Duration) {
+        if let UpdateSource::Demo(ref mut demo_srv) = self.update_src {
+            let resumed = demo_srv.seek(offset);
+            println!(
+                "Resuming playback at {:.1} seconds",
+                engine::duration_to_f32(resumed)
+            );
+        }
+    }
+
+    /// Returns `true` if the server has paused the game.
+    pub fn paused(&self) -> bool {
+        self.state.paused
+    }
+
+    /// Returns the maximum number of players on the current server.
+    pub fn max_players(&self) -> usize {
+        self.state.max_players
+    }
+
     pub fn time(&self) -> Duration {
         self.state.time
     }
@@ -1582,71 +2634,52 @@ impl Client {
                 d => d,
             });
 
-        let frame_delta = engine::duration_to_f32(self.state.time - self.state.msg_times[1]);
-
-        // XXX lerp factor here outside [0, 1] seems to be causing stuttering
-        self.state.lerp_factor = match frame_delta / server_delta {
-            f if f < 0.0 => {
-                warn!("Negative lerp factor ({})", f);
-                if f < -0.01 {
-                    self.state.time = self.state.msg_times[1];
-                }
-
-                0.0
-            }
-
-            f if f > 1.0 => {
-                warn!("Lerp factor > 1 ({})", f);
-                if f > 1.01 {
-                    self.state.time = self.state.msg_times[0];
-                }
-
-                1.0
-            }
+        // drift back toward the server's clock rather than snapping to it, which would judder
+        self.state.time = self.state.time
+            + clock::correction(self.state.time, self.state.msg_times, frame_time);
 
-            f => f,
-        }
+        // while the clock is drifting back, hold entities at the nearest message
+        let frame_delta = engine::duration_to_f32(self.state.time - self.state.msg_times[1]);
+        self.state.lerp_factor = (frame_delta / server_delta).max(0.0).min(1.0);
     }
 
     pub fn get_lerp_factor(&mut self) -> f32 {
         self.state.lerp_factor
     }
 
-    pub fn update_temp_entities(&mut self) {
-        lazy_static! {
-            static ref ANGLE_DISTRIBUTION: Uniform<f32> = Uniform::new(0.0, 360.0);
-        }
+    pub fn update_temp_entities(&mut self) -> Result<(), ClientError> {
+        let jitter = self.cvar_value("cl_beamjitter")?;
+        let view_ent = self.view_ent();
+
+        // the bolt and its hum cut off as soon as the player's cells run out, rather than when the
+        // server stops sending the beam
+        let weapon = self.state.stats[ClientStat::ActiveWeapon as usize] as u32;
+        let out_of_cells = weapon == ItemFlags::LIGHTNING.bits()
+            && self.state.stats[ClientStat::Cells as usize] <= 0;
 
         self.state.temp_entities.clear();
         for id in 0..self.state.beams.len() {
             // remove beam if expired
-            if self.state.beams[id].map_or(false, |b| b.expire < self.state.time) {
+            if self.state.beams[id].map_or(false, |b| {
+                b.expire < self.state.time || (out_of_cells && b.entity_id == view_ent)
+            }) {
                 self.state.beams[id] = None;
                 continue;
             }
 
-            let view_ent = self.view_ent();
             if let Some(ref mut beam) = self.state.beams[id] {
                 // keep lightning gun bolts fixed to player
                 if beam.entity_id == view_ent {
                     beam.start = self.state.entities[view_ent].origin;
                 }
 
-                let vec = beam.end - beam.start;
-                let yaw = Deg::from(cgmath::Rad(vec.y.atan2(vec.x))).normalize();
-                let forward = (vec.x.powf(2.0) + vec.y.powf(2.0)).sqrt();
-                let pitch = Deg::from(cgmath::Rad(vec.z.atan2(forward))).normalize();
-
-                let len = vec.magnitude();
-                let direction = vec.normalize();
-                for interval in 0..(len / 30.0) as i32 {
+                // the jitter is redrawn every frame, so it would take a different number of values
+                // from the seeded generator at each frame rate and throw the other effects off
+                for segment in beam::segments(beam.start, beam.end, jitter, &mut rand::thread_rng())
+                {
                     let mut ent = ClientEntity::uninitialized();
-                    ent.origin = beam.start + 30.0 * interval as f32 * direction;
-                    ent.angles = Vector3::new(
-                        pitch,
-                        yaw,
-                        Deg(ANGLE_DISTRIBUTION.sample(&mut rand::thread_rng())),
-                    );
+                    ent.origin = segment.origin;
+                    ent.angles = segment.angles;
 
                     if self.state.temp_entities.len() < MAX_TEMP_ENTITIES {
                         self.state.temp_entities.push(ent);
@@ -1656,15 +2689,308 @@ impl Client {
                 }
             }
         }
+
+        let beams = &self.state.beams;
+        self.state
+            .mixer
+            .update_beam_loops(self.state.time, |ent_id| {
+                beams
+                    .iter()
+                    .any(|b| b.map_or(false, |b| b.entity_id == ent_id))
+            });
+
+        Ok(())
+    }
+
+    // predicts the player's position from the last server update and any moves sent since
+    fn update_prediction(&mut self) -> Result<(), ClientError> {
+        let enabled = self.host_state.get().predicts() && self.cvar_value("cl_nopred")? == 0.0;
+
+        // the server has full control of dead players and cameras
+        if !enabled
+            || self.state.stats[ClientStat::Health as usize] <= 0
+            || self.state.view.entity_id() > self.state.max_players
+        {
+            self.state.prediction.clear();
+            return Ok(());
+        }
+
+        let vars = MoveVars::from_cvars(&self.cvars.borrow()).map_err(ClientError::Cvar)?;
+        let latency = Duration::milliseconds(self.cvar_value("cl_predictping")? as i64);
+        let player = &self.state.entities[self.state.view.entity_id()];
+        let server = PlayerState::new(
+            player.msg_origins[0],
+            self.state.msg_velocity[0],
+            self.state.on_ground,
+        );
+
+        let platform = self.platform_offset();
+        let result = match self.state.models[1].kind() {
+            ModelKind::Brush(ref bmodel) => PlayerMove::for_world(bmodel, vars).and_then(|pmove| {
+                self.state.prediction.update(
+                    &pmove,
+                    server,
+                    self.state.msg_times[0],
+                    latency,
+                    platform,
+                )?;
+                Ok(pmove.take_traces())
+            }),
+            _ => panic!("non-brush worldmodel"),
+        };
+
+        match result {
+            Ok(traces) => {
+                for trace in traces {
+                    self.record_debug_trace(trace.start, trace.end, trace.stop);
+                }
+            }
+
+            // a failed prediction isn't fatal, the view just follows the server
+            Err(e) => {
+                warn!("Prediction failed: {}", e);
+                self.state.prediction.clear();
+            }
+        }
+
+        Ok(())
+    }
+
+    // if the player stood on a brush entity in the last update, returns how far that entity has
+    // been drawn from where it was then
+    fn platform_offset(&self) -> Vector3<f32> {
+        let view_ent = self.state.view.entity_id();
+        let player = self.state.entities[view_ent].msg_origins[0];
+
+        self.state
+            .entities
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(id, ent)| *id != view_ent && ent.model_id != 0)
+            .find_map(|(_, ent)| {
+                let model = &self.state.models[ent.model_id];
+                match model.kind() {
+                    ModelKind::Brush(_) => (),
+                    _ => return None,
+                }
+
+                let min = ent.msg_origins[0] + model.min();
+                let max = ent.msg_origins[0] + model.max();
+                if predict::stands_on(player, min, max) {
+                    Some(ent.origin - ent.msg_origins[0])
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(Vector3::zero())
+    }
+
+    // spawns the muzzle flash, bolt and impacts of the player's own attack without waiting for the
+    // server
+    fn predict_attack(&mut self, attack_held: bool) -> Result<(), ClientError> {
+        // range of hitscan weapons and the lightning gun in the standard progs
+        const HITSCAN_RANGE: f32 = 2048.0;
+        const LIGHTNING_RANGE: f32 = 600.0;
+
+        let enabled =
+            self.host_state.get().predicts() && self.cvar_value("cl_predictattack")? != 0.0;
+
+        if !enabled
+            || self.state.stats[ClientStat::Health as usize] <= 0
+            || self.state.view.entity_id() > self.state.max_players
+        {
+            self.state.attack_prediction.clear();
+            return Ok(());
+        }
+
+        let time = self.state.time;
+        let weapon = ItemFlags::from_bits_truncate(
+            self.state.stats[ClientStat::ActiveWeapon as usize] as u32,
+        );
+        let ammo = self.state.stats[ClientStat::Ammo as usize];
+        let attack = match self
+            .state
+            .attack_prediction
+            .fire(time, attack_held, weapon, ammo)
+        {
+            Some(a) => a,
+            None => return Ok(()),
+        };
+
+        let start = self.view_origin();
+        let (forward, _, _) = frustum::view_vectors(self.state.view.input_angles());
+
+        if attack.muzzle_flash {
+            let light = muzzle_flash_light(start + forward * 18.0, &mut self.state.rng);
+            self.state.lights.insert(time, light, None);
+        }
+
+        let range = if attack.tracer {
+            LIGHTNING_RANGE
+        } else if attack.pellets > 0 {
+            HITSCAN_RANGE
+        } else {
+            return Ok(());
+        };
+
+        let end = start + forward * range;
+        let hit = match self.state.models[1].kind() {
+            ModelKind::Brush(ref bmodel) => bmodel.bsp_data().trace_surface(start, end),
+            _ => panic!("non-brush worldmodel"),
+        };
+
+        self.record_debug_trace(start, end, hit.as_ref().map_or(end, |h| h.point));
+
+        if attack.tracer {
+            // the server's bolt for the player replaces this one when it arrives
+            if let Some(model_id) = self.state.model_names.get("progs/bolt2.mdl").copied() {
+                let end = hit.map_or(end, |h| h.point);
+                self.spawn_beam(time, self.state.view.entity_id(), model_id, start, end);
+            }
+            return Ok(());
+        }
+
+        let hit = match hit {
+            Some(h) => h.point,
+            None => return Ok(()),
+        };
+
+        // shots that hit an entity draw blood rather than leave an impact, and whether the
+        // entity takes damage is up to the server, so don't guess
+        let view_ent = self.state.view.entity_id();
+        let models = &self.state.models;
+        let blocked = self
+            .state
+            .entities
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(id, ent)| *id != view_ent && ent.model_id != 0)
+            .any(|(_, ent)| {
+                let model = &models[ent.model_id];
+                attack::segment_hits_box(
+                    start,
+                    hit,
+                    ent.origin + model.min(),
+                    ent.origin + model.max(),
+                )
+            });
+
+        if !blocked {
+            self.state
+                .particles
+                .create_projectile_impact(time, hit, Vector3::zero(), 0, 20);
+            self.state.attack_prediction.add_impact(time, start, hit);
+        }
+
+        Ok(())
+    }
+
+    // moves the view weapon to the view and advances its animation from the weapon frame stat
+    fn update_viewmodel(&mut self) -> Result<(), ClientError> {
+        let model_id = self.weapon();
+        if model_id <= 0 || model_id as usize >= self.state.models.len() {
+            self.state.viewmodel.set_model(0, 0);
+            return Ok(());
+        }
+
+        let model_id = model_id as usize;
+        let frame_id = self.state.stats[ClientStat::WeaponFrame as usize].max(0) as usize;
+        let angles = self.view_angles(self.state.time)?;
+        let bob = view::bob(self.state.time, self.state.velocity, self.bob_vars()?);
+        let forward = angles.mat3_quake() * Vector3::unit_x();
+        let origin = self.view_origin() + forward * bob * 0.4 + Vector3::new(0.0, 0.0, bob);
+        let player_light = self
+            .state
+            .entities
+            .get(self.view_ent())
+            .and_then(|e| e.light());
+
+        let ent = &mut self.state.viewmodel;
+        if ent.model_id() != model_id {
+            ent.set_model(model_id, frame_id);
+        } else {
+            ent.set_frame(frame_id, self.state.time);
+        }
+
+        ent.origin = origin;
+        ent.set_angles(Vector3::new(-angles.pitch, angles.yaw, -angles.roll));
+
+        // the weapon is lit like the player holding it
+        match player_light {
+            Some(light) => ent.update_light(light, 1.0),
+            None => ent.clear_light(),
+        }
+
+        Ok(())
+    }
+
+    // lights alias models from the world lightmap below them
+    fn update_model_lighting(&mut self, frame_time: Duration) -> Result<(), ClientError> {
+        let enabled = self.cvar_value("r_lightmodels")? != 0.0;
+        let colored = self.cvar_value("r_coloredlight")? != 0.0;
+        let state = &mut self.state;
+
+        let bsp_data = match state.models.get(1).map(|m| m.kind()) {
+            Some(ModelKind::Brush(ref bmodel)) if enabled => bmodel.bsp_data(),
+            _ => {
+                for ent in state.entities.iter_mut() {
+                    ent.clear_light();
+                }
+
+                for ent in state.static_entities.iter_mut() {
+                    ent.clear_light();
+                }
+
+                return Ok(());
+            }
+        };
+
+        let style_values: Vec<f32> = (0..64)
+            .map(|s| state.light_style_value(s).unwrap_or(1.0))
+            .collect();
+        let blend = 1.0 - (-MODEL_LIGHT_RATE * engine::duration_to_f32(frame_time)).exp();
+
+        let models = &state.models;
+        let update = |ent: &mut ClientEntity| {
+            match models.get(ent.model_id).map(|m| m.kind()) {
+                Some(ModelKind::Alias(_)) => (),
+                _ => return,
+            }
+
+            // keep models in dark corners visible, and don't overbright them past fullbright
+            let light = bsp_data
+                .light_near(ent.origin, MODEL_LIGHT_SPREAD, &style_values, colored)
+                .unwrap_or([1.0; 3]);
+            let target = [
+                light[0].max(MIN_MODEL_LIGHT).min(1.0),
+                light[1].max(MIN_MODEL_LIGHT).min(1.0),
+                light[2].max(MIN_MODEL_LIGHT).min(1.0),
+            ];
+
+            ent.update_light(target, blend);
+        };
+
+        for id in state.visible_entity_ids.iter() {
+            update(&mut state.entities[*id]);
+        }
+
+        for id in state.visible_static_entity_ids.iter() {
+            update(&mut state.static_entities[*id]);
+        }
+
+        Ok(())
     }
 
     pub fn relink_entities(&mut self) {
         lazy_static! {
-            static ref MFLASH_DIMLIGHT_DISTRIBUTION: Uniform<f32> = Uniform::new(200.0, 232.0);
             static ref BRIGHTLIGHT_DISTRIBUTION: Uniform<f32> = Uniform::new(400.0, 432.0);
         }
 
         let lerp_factor = self.get_lerp_factor();
+        let lerp_move = self.cvars.borrow().get_value("r_lerpmove").unwrap() != 0.0;
+        let view_ent = self.state.view.entity_id();
 
         self.state.velocity = self.state.msg_velocity[1]
             + lerp_factor * (self.state.msg_velocity[0] - self.state.msg_velocity[1]);
@@ -1682,6 +3008,14 @@ impl Client {
             return;
         }
 
+        // lights given off by entity effects flicker along with any animated light style on the
+        // surface below them, and keep a steady radius elsewhere
+        let world_bsp = match self.state.models.get(1).map(|m| m.kind()) {
+            Some(ModelKind::Brush(ref bmodel)) => Some(bmodel.bsp_data()),
+            _ => None,
+        };
+        let effect_style = |origin| world_bsp.as_ref()?.light_style_at(origin);
+
         // NOTE that we start at entity 1 since we don't need to link the world entity
         for (ent_id, ent) in self.state.entities.iter_mut().enumerate().skip(1) {
             if ent.model_id == 0 {
@@ -1708,6 +3042,9 @@ impl Client {
                     // if the entity moved more than 100 units in one frame,
                     // assume it was teleported and don't lerp anything
                     1.0
+                } else if !lerp_move && ent_id != view_ent {
+                    // r_lerpmove 0 snaps everything but the camera to the latest update
+                    1.0
                 } else {
                     lerp_factor
                 };
@@ -1742,135 +3079,380 @@ impl Client {
                     .create_entity_field(self.state.time, ent);
             }
 
-            // TODO: cache a SmallRng in Client
-            let mut rng = rand::thread_rng();
-
             // TODO: factor out EntityEffects->LightDesc mapping
-            if ent.effects.contains(EntityEffects::MUZZLE_FLASH) {
+            // the player's own muzzle flash may have been predicted already
+            let flash_predicted = ent_id == view_ent
+                && ent.effects.contains(EntityEffects::MUZZLE_FLASH)
+                && self
+                    .state
+                    .attack_prediction
+                    .confirm_muzzle_flash(self.state.time, ent.msg_time);
+
+            if ent.effects.contains(EntityEffects::MUZZLE_FLASH) && !flash_predicted {
                 // TODO: angle and move origin to muzzle
+                let light = muzzle_flash_light(
+                    ent.origin + Vector3::new(0.0, 0.0, 16.0),
+                    &mut self.state.rng,
+                );
+                ent.light_id = Some(
+                    self.state
+                        .lights
+                        .insert(self.state.time, light, ent.light_id),
+                );
+            }
+
+            if ent.effects.contains(EntityEffects::BRIGHT_LIGHT) {
                 ent.light_id = Some(self.state.lights.insert(
                     self.state.time,
                     LightDesc {
-                        origin: ent.origin + Vector3::new(0.0, 0.0, 16.0),
-                        init_radius: MFLASH_DIMLIGHT_DISTRIBUTION.sample(&mut rng),
+                        origin: ent.origin,
+                        init_radius: BRIGHTLIGHT_DISTRIBUTION.sample(&mut self.state.rng),
                         decay_rate: 0.0,
-                        min_radius: Some(32.0),
-                        ttl: Duration::milliseconds(100),
+                        min_radius: None,
+                        ttl: Duration::milliseconds(1),
+                        style: effect_style(ent.origin),
                     },
                     ent.light_id,
                 ));
             }
 
-            if ent.effects.contains(EntityEffects::BRIGHT_LIGHT) {
+            if ent.effects.contains(EntityEffects::DIM_LIGHT) {
                 ent.light_id = Some(self.state.lights.insert(
                     self.state.time,
                     LightDesc {
                         origin: ent.origin,
-                        init_radius: BRIGHTLIGHT_DISTRIBUTION.sample(&mut rng),
+                        init_radius: MFLASH_DIMLIGHT_DISTRIBUTION.sample(&mut self.state.rng),
                         decay_rate: 0.0,
                         min_radius: None,
                         ttl: Duration::milliseconds(1),
+                        style: effect_style(ent.origin),
                     },
                     ent.light_id,
                 ));
             }
 
-            if ent.effects.contains(EntityEffects::DIM_LIGHT) {
+            // check if this entity leaves a trail
+            let trail_kind = effects::trail_kind(model.flags());
+            if trail_kind == Some(TrailKind::Rocket) {
                 ent.light_id = Some(self.state.lights.insert(
                     self.state.time,
                     LightDesc {
                         origin: ent.origin,
-                        init_radius: MFLASH_DIMLIGHT_DISTRIBUTION.sample(&mut rng),
+                        init_radius: 200.0,
+                        decay_rate: 0.0,
+                        min_radius: None,
+                        ttl: Duration::milliseconds(10),
+                        style: None,
+                    },
+                    ent.light_id,
+                ));
+            }
+
+            // if the entity leaves a trail, generate it
+            if let Some(kind) = trail_kind {
+                self.state.particles.create_trail(
+                    self.state.time,
+                    prev_origin,
+                    ent.origin,
+                    kind,
+                    &mut ent.trail,
+                );
+            }
+
+            // mark entity for rendering
+            self.state.visible_entity_ids.push(ent_id);
+
+            // enable lerp for next frame
+            ent.force_link = false;
+        }
+
+        // apply effects to static entities as well
+        for ent in self.state.static_entities.iter_mut() {
+            if ent.effects.contains(EntityEffects::BRIGHT_LIGHT) {
+                debug!("spawn bright light on static entity");
+                ent.light_id = Some(self.state.lights.insert(
+                    self.state.time,
+                    LightDesc {
+                        origin: ent.origin,
+                        init_radius: BRIGHTLIGHT_DISTRIBUTION.sample(&mut self.state.rng),
                         decay_rate: 0.0,
                         min_radius: None,
                         ttl: Duration::milliseconds(1),
+                        style: effect_style(ent.origin),
                     },
                     ent.light_id,
                 ));
             }
 
-            // check if this entity leaves a trail
-            let trail_kind = if model.has_flag(ModelFlags::GIB) {
-                Some(TrailKind::Blood)
-            } else if model.has_flag(ModelFlags::ZOMGIB) {
-                Some(TrailKind::BloodSlight)
-            } else if model.has_flag(ModelFlags::TRACER) {
-                Some(TrailKind::TracerGreen)
-            } else if model.has_flag(ModelFlags::TRACER2) {
-                Some(TrailKind::TracerRed)
-            } else if model.has_flag(ModelFlags::ROCKET) {
+            if ent.effects.contains(EntityEffects::DIM_LIGHT) {
+                debug!("spawn dim light on static entity");
                 ent.light_id = Some(self.state.lights.insert(
                     self.state.time,
                     LightDesc {
                         origin: ent.origin,
-                        init_radius: 200.0,
+                        init_radius: MFLASH_DIMLIGHT_DISTRIBUTION.sample(&mut self.state.rng),
                         decay_rate: 0.0,
                         min_radius: None,
-                        ttl: Duration::milliseconds(10),
+                        ttl: Duration::milliseconds(1),
+                        style: effect_style(ent.origin),
                     },
                     ent.light_id,
                 ));
-                Some(TrailKind::Rocket)
-            } else if model.has_flag(ModelFlags::GRENADE) {
-                Some(TrailKind::Smoke)
-            } else if model.has_flag(ModelFlags::TRACER3) {
-                Some(TrailKind::Vore)
-            } else {
-                None
-            };
+            }
+        }
+
+        self.cull_entities();
+    }
+
+    // Removes entities outside the camera's PVS from the visible entity lists.
+    fn cull_entities(&mut self) {
+        let state = &mut self.state;
+        state.visible_static_entity_ids = (0..state.static_entities.len()).collect();
+
+        let bsp_data = match state.models.get(1).map(|m| m.kind()) {
+            Some(ModelKind::Brush(ref bmodel)) => bmodel.bsp_data(),
+            _ => return,
+        };
+
+        let view_ent_id = state.view.entity_id();
+        let pvs = match state.entities.get(view_ent_id) {
+            Some(view_ent) => Pvs::from_point(&bsp_data, view_ent.origin),
+            None => return,
+        };
+
+        let models = &state.models;
+        let is_visible = |ent: &ClientEntity| {
+            if ent.model_id == 0 {
+                return false;
+            }
+
+            let model = &models[ent.model_id];
+            pvs.contains_bounds(
+                &bsp_data,
+                ent.origin + model.min(),
+                ent.origin + model.max(),
+            )
+        };
+
+        let entities = &state.entities;
+        state
+            .visible_entity_ids
+            .retain(|&id| id == view_ent_id || is_visible(&entities[id]));
+
+        let static_entities = &state.static_entities;
+        state
+            .visible_static_entity_ids
+            .retain(|&id| is_visible(&static_entities[id]));
+    }
+
+    /// Records a trace for display by `r_showtraces`.
+    pub fn record_debug_trace(
+        &mut self,
+        start: Vector3<f32>,
+        end: Vector3<f32>,
+        stop: Vector3<f32>,
+    ) {
+        self.debug_traces
+            .push_front(DebugTrace { start, end, stop });
+        self.debug_traces.truncate(MAX_DEBUG_TRACES);
+    }
+
+    // implements the freecam command
+    fn toggle_freecam(&mut self) -> Result<(), ClientError> {
+        if self.freecam.take().is_some() {
+            println!("Free camera off");
+            return Ok(());
+        }
+
+        // flying through walls would be cheating anywhere but a local game
+        if self.demo_playback() || self.max_players() != 1 {
+            println!("freecam is only available in single player");
+            return Ok(());
+        }
+
+        let angles = self.view_angles(self.state.time)?;
+        self.freecam = Some(FreeCam::new(self.view_origin(), angles));
+        println!("Free camera on");
+        Ok(())
+    }
+
+    /// Returns the state of the route recorder and saved positions.
+    pub fn practice(&self) -> Ref<Practice> {
+        self.practice.borrow()
+    }
+
+    // carries out the practice commands run since the last frame and records the player's route
+    fn update_practice(&mut self, frame_time: Duration) -> Result<(), ClientError> {
+        let requests = self.practice.borrow_mut().take_requests();
+        for request in requests {
+            match request {
+                PracticeRequest::RecordRoute => {
+                    self.practice.borrow_mut().start_recording();
+                    println!("Recording route");
+                }
+
+                PracticeRequest::PlayRoute => {
+                    if !self.practice.borrow_mut().start_playback() {
+                        println!("No route recorded");
+                    }
+                }
+
+                PracticeRequest::StopRoute => {
+                    let mut practice = self.practice.borrow_mut();
+                    let recording = practice.recording();
+                    if practice.stop() && recording {
+                        println!(
+                            "Recorded route of {:.2} seconds",
+                            engine::duration_to_f32(practice.route().duration())
+                        );
+                    }
+                }
+
+                PracticeRequest::SavePosition => {
+                    let angles = self.state.view.input_angles();
+                    let position = SavedPosition {
+                        map: self.state.models[1].name().to_owned(),
+                        origin: self.player_origin(),
+                        angles: Vector3::new(angles.pitch, angles.yaw, angles.roll),
+                    };
+                    self.practice.borrow_mut().save_position(position);
+                    println!("Position saved");
+                }
+
+                PracticeRequest::LoadPosition => {
+                    // only the server can move the player, and only a local game will allow it
+                    if self.demo_playback() || self.max_players() != 1 {
+                        println!("Positions can only be loaded in single player");
+                        continue;
+                    }
+
+                    let cmd = match self.practice.borrow().saved_position() {
+                        Some(pos) if pos.map == self.state.models[1].name() => pos.setpos_cmd(),
+                        Some(_) => {
+                            println!("Saved position is on another map");
+                            continue;
+                        }
+                        None => {
+                            println!("No position saved");
+                            continue;
+                        }
+                    };
+                    self.forward_cmd(cmd)?;
+                }
+            }
+        }
+
+        let origin = self.player_origin();
+        self.practice.borrow_mut().update(frame_time, origin);
+
+        Ok(())
+    }
 
-            // if the entity leaves a trail, generate it
-            if let Some(kind) = trail_kind {
-                self.state.particles.create_trail(
-                    self.state.time,
-                    prev_origin,
-                    ent.origin,
-                    kind,
-                    false,
+    /// Returns the recorded debug traces, most recent first.
+    pub fn iter_debug_traces(&self) -> impl Iterator<Item = &DebugTrace> {
+        self.debug_traces.iter()
+    }
+
+    // implements the debug_trace command, tracing the point hull from the view origin along the
+    // view direction
+    fn debug_trace(&mut self) -> Result<(), ClientError> {
+        let start = self.view_origin();
+        let forward = self.view_angles(self.state.time)?.mat3_quake() * Vector3::unit_x();
+        let end = start + forward * DEBUG_TRACE_DISTANCE;
+
+        let trace = match self.state.models[1].kind() {
+            ModelKind::Brush(ref bmodel) => bmodel.hull(0).and_then(|h| h.trace(start, end)),
+            _ => panic!("non-brush worldmodel"),
+        };
+
+        match trace {
+            Ok(t) => {
+                let stop = t.end_point();
+                println!(
+                    "Trace stopped at {:?}, {:.1} units away",
+                    stop,
+                    (stop - start).magnitude()
                 );
+                self.record_debug_trace(start, end, stop);
             }
 
-            // mark entity for rendering
-            self.state.visible_entity_ids.push(ent_id);
-
-            // enable lerp for next frame
-            ent.force_link = false;
+            Err(e) => warn!("Debug trace failed: {}", e),
         }
 
-        // apply effects to static entities as well
-        for ent in self.state.static_entities.iter_mut() {
-            let mut rng = rand::thread_rng();
+        Ok(())
+    }
 
-            if ent.effects.contains(EntityEffects::BRIGHT_LIGHT) {
-                debug!("spawn bright light on static entity");
-                ent.light_id = Some(self.state.lights.insert(
-                    self.state.time,
-                    LightDesc {
-                        origin: ent.origin,
-                        init_radius: BRIGHTLIGHT_DISTRIBUTION.sample(&mut rng),
-                        decay_rate: 0.0,
-                        min_radius: None,
-                        ttl: Duration::milliseconds(1),
-                    },
-                    ent.light_id,
-                ));
-            }
+    /// Returns the ID of the world face highlighted by `surface_info highlight`, if any.
+    pub fn highlighted_surface(&self) -> Option<usize> {
+        self.highlighted_surface
+    }
 
-            if ent.effects.contains(EntityEffects::DIM_LIGHT) {
-                debug!("spawn dim light on static entity");
-                ent.light_id = Some(self.state.lights.insert(
-                    self.state.time,
-                    LightDesc {
-                        origin: ent.origin,
-                        init_radius: MFLASH_DIMLIGHT_DISTRIBUTION.sample(&mut rng),
-                        decay_rate: 0.0,
-                        min_radius: None,
-                        ttl: Duration::milliseconds(1),
-                    },
-                    ent.light_id,
-                ));
+    // implements the surface_info command, printing information about the world surface under
+    // the crosshair
+    fn surface_info(&mut self, highlight: bool) -> Result<(), ClientError> {
+        let start = self.view_origin();
+        let forward = self.view_angles(self.state.time)?.mat3_quake() * Vector3::unit_x();
+        let end = start + forward * DEBUG_TRACE_DISTANCE;
+
+        let bsp_data = match self.state.models[1].kind() {
+            ModelKind::Brush(ref bmodel) => bmodel.bsp_data(),
+            _ => panic!("non-brush worldmodel"),
+        };
+
+        self.highlighted_surface = None;
+
+        let hit = match bsp_data.trace_surface(start, end) {
+            Some(h) => h,
+            None => {
+                println!("No surface under crosshair");
+                return Ok(());
             }
+        };
+
+        let face = bsp_data.face(hit.face_id);
+        let texinfo = bsp_data.face_texinfo(hit.face_id);
+        let plane = &bsp_data.planes()[face.plane_id];
+
+        // step back off the surface so the point lands in the leaf the ray passed through
+        let leaf_id = bsp_data.find_leaf(hit.point - forward);
+        let leaf = &bsp_data.leaves()[leaf_id];
+
+        println!(
+            "Face {} at {:?}, {:.1} units away",
+            hit.face_id,
+            hit.point,
+            (hit.point - start).magnitude()
+        );
+        println!(
+            "  texture: {} (texinfo {}{})",
+            bsp_data.textures()[texinfo.tex_id].name(),
+            face.texinfo_id,
+            if texinfo.special { ", special" } else { "" }
+        );
+        match face.lightmap_id {
+            Some(lightmap_id) => println!(
+                "  lightmap: offset {}, {}x{} luxels, styles {:?}",
+                lightmap_id,
+                face.extents[0] / 16 + 1,
+                face.extents[1] / 16 + 1,
+                face.light_styles
+            ),
+            None => println!("  lightmap: none"),
         }
+        println!(
+            "  plane {}: normal {:?}, dist {}, side {:?}",
+            face.plane_id,
+            plane.normal_vector(),
+            plane.dist(),
+            face.side
+        );
+        println!("  leaf {}: {:?}", leaf_id, leaf.contents);
+
+        if highlight {
+            self.highlighted_surface = Some(hit.face_id);
+        }
+
+        Ok(())
     }
 
     fn view_leaf_contents(&self) -> bsp::BspLeafContents {
@@ -1885,16 +3467,25 @@ impl Client {
         }
     }
 
+    /// Returns whether the camera is submerged in a liquid.
+    pub fn view_underwater(&self) -> bool {
+        match self.view_leaf_contents() {
+            bsp::BspLeafContents::Water
+            | bsp::BspLeafContents::Slime
+            | bsp::BspLeafContents::Lava => true,
+            _ => false,
+        }
+    }
+
     fn update_color_shifts(&self, frame_time: Duration) {
         let float_time = engine::duration_to_f32(frame_time);
 
         // set color for leaf contents
         self.state.color_shifts[ColorShiftCode::Contents as usize].replace(
             match self.view_leaf_contents() {
-                bsp::BspLeafContents::Empty => ColorShift {
-                    dest_color: [0, 0, 0],
-                    percent: 0,
-                },
+                bsp::BspLeafContents::Empty | bsp::BspLeafContents::Solid => {
+                    *self.state.empty_color_shift.borrow()
+                }
                 bsp::BspLeafContents::Lava => ColorShift {
                     dest_color: [255, 80, 0],
                     percent: 150,
@@ -1911,13 +3502,13 @@ impl Client {
         );
 
         // decay damage and item pickup shifts
-        let mut dmg_shift = self.state.color_shifts[ColorShiftCode::Damage as usize].borrow_mut();
-        dmg_shift.percent -= (float_time * 150.0) as i32;
-        dmg_shift.percent = dmg_shift.percent.max(0);
-
-        let mut bonus_shift = self.state.color_shifts[ColorShiftCode::Bonus as usize].borrow_mut();
-        bonus_shift.percent -= (float_time * 100.0) as i32;
-        bonus_shift.percent = bonus_shift.percent.max(0);
+        for (shift, kind) in self.state.color_shifts.iter().zip(COLOR_SHIFT_KINDS.iter()) {
+            if kind.decay_rate > 0.0 {
+                let mut shift = shift.borrow_mut();
+                shift.percent -= (float_time * kind.decay_rate) as i32;
+                shift.percent = shift.percent.max(0);
+            }
+        }
 
         // set power-up overlay
         self.state.color_shifts[ColorShiftCode::Powerup as usize].replace(
@@ -1951,22 +3542,63 @@ impl Client {
     }
 
     pub fn frame(&mut self, frame_time: Duration) -> Result<(), ClientError> {
+        // freeze client-side simulation while the server is paused. demos keep playing so that
+        // the message that unpauses them can be read.
+        let frame_time = match self.update_src {
+            UpdateSource::Server(_) | UpdateSource::QuakeWorld(_) if self.state.paused => {
+                Duration::zero()
+            }
+            _ => frame_time,
+        };
+
         // advance client time by frame duration.
         // do this _before_ parsing server messages so that we know when to
         // request the next message from the demo server.
         self.state.time = self.state.time + frame_time;
 
+        let record_requested = self.record_requested.borrow_mut().take();
+        if let Some(name) = record_requested {
+            self.start_recording(name);
+        }
+
+        if self.stop_requested.replace(false) {
+            self.stop_recording();
+        }
+
+        if let Some(offset) = self.demo_seek_requested.take() {
+            self.seek_demo(offset);
+        }
+
         debug!("frame time: {}ms", frame_time.num_milliseconds());
         self.parse_server_msg()?;
 
+        // the map's script has run once the console has nothing left to execute
+        if self.map_config.pending() && self.console.borrow().idle() {
+            self.map_config.settle(&self.cvars.borrow());
+        }
+
         // update timing information
         self.update_time(frame_time);
+        self.state.flat_light_styles = self.cvar_value("r_flatlightstyles")? != 0.0;
+        self.state
+            .view
+            .set_motion_scale(self.cvar_value("v_motionscale")?);
 
         // interpolate entity data
         self.relink_entities();
 
+        // run unacknowledged moves ahead of the server's position
+        self.state.prediction.advance(frame_time);
+        self.update_prediction()?;
+
+        // sample world light for models at their new positions
+        self.update_model_lighting(frame_time)?;
+
+        // move the view weapon with the view
+        self.update_viewmodel()?;
+
         // update temp entities (lightning, etc.)
-        self.update_temp_entities();
+        self.update_temp_entities()?;
 
         // remove expired lights
         self.state.lights.update(self.state.time);
@@ -1976,18 +3608,53 @@ impl Client {
             .particles
             .update(self.state.time, frame_time, self.cvar_value("sv_gravity")?);
 
-        if let UpdateSource::Server(_) = self.update_src {
+        self.update_connection()?;
+
+        match self.update_src {
             // respond to the server
-            self.send()?;
+            UpdateSource::Server(_) | UpdateSource::QuakeWorld(_) => self.send()?,
+            UpdateSource::Demo(_) => (),
         }
 
+        self.music_player
+            .borrow()
+            .set_volume(self.cvar_value("bgmvolume")?);
+
         // these all require the player entity to have spawned
-        if self.signon.get() == SignOnStage::Done {
+        if self.host_state.get().in_game() {
+            if self.debug_trace_requested.replace(false) {
+                self.debug_trace()?;
+            }
+
+            if let Some(highlight) = self.surface_info_requested.take() {
+                self.surface_info(highlight)?;
+            }
+
+            self.update_practice(frame_time)?;
+
+            if self.freecam_requested.replace(false) {
+                self.toggle_freecam()?;
+            }
+
             // update ear positions
             self.state.update_listener();
 
+            // apply underwater filtering and room reverb
+            let underwater_cutoff = self.cvar_value("snd_underwater")?;
+            self.state.update_sound_effects(
+                if underwater_cutoff > 0.0 && self.view_underwater() {
+                    Some(underwater_cutoff)
+                } else {
+                    None
+                },
+                self.cvar_value("snd_reverb")?,
+            );
+
             // spatialize sounds for new ear positions
-            self.state.update_sound_spatialization();
+            self.state.update_sound_spatialization(
+                self.cvar_value("snd_occlusion")?,
+                self.cvar_value("snd_occlusion_cutoff")?,
+            );
 
             // update camera color shifts for new position/effects
             self.update_color_shifts(frame_time);
@@ -2002,7 +3669,12 @@ impl Client {
             .iter()
             .map(move |i| &self.state.entities[*i])
             .chain(self.state.temp_entities.iter())
-            .chain(self.state.static_entities.iter())
+            .chain(
+                self.state
+                    .visible_static_entity_ids
+                    .iter()
+                    .map(move |i| &self.state.static_entities[*i]),
+            )
     }
 
     pub fn iter_lights(&self) -> impl Iterator<Item = &Light> {
@@ -2017,6 +3689,7 @@ impl Client {
         let bonus_cshift = self.state.color_shifts[ColorShiftCode::Bonus as usize].clone();
         cmds.insert_or_replace(
             "bf",
+            "flash the screen as if an item was picked up",
             Box::new(move |_| {
                 bonus_cshift.replace(ColorShift {
                     dest_color: [215, 186, 69],
@@ -2025,10 +3698,188 @@ impl Client {
             }),
         );
 
+        let empty_cshift = self.state.empty_color_shift.clone();
+        cmds.insert_or_replace(
+            "v_cshift",
+            "v_cshift [r] [g] [b] [percent]: set the open-air color shift",
+            Box::new(move |args| {
+                if args.len() > 4 {
+                    println!("v_cshift [r] [g] [b] [percent]: set the open-air color shift");
+                    return;
+                }
+
+                // missing or malformed arguments are treated as 0, as in the original engine
+                let mut values = [0; 4];
+                for (i, arg) in args.iter().enumerate() {
+                    values[i] = arg.parse::<f32>().unwrap_or(0.0) as i32;
+                }
+
+                empty_cshift.replace(ColorShift {
+                    dest_color: [
+                        values[0].clamp(0, 255) as u8,
+                        values[1].clamp(0, 255) as u8,
+                        values[2].clamp(0, 255) as u8,
+                    ],
+                    percent: values[3].clamp(0, 255),
+                });
+            }),
+        );
+
+        let fog = self.fog.clone();
+        cmds.insert_or_replace(
+            "fog",
+            "fog [density] [r g b]: show or set the distance fog",
+            Box::new(move |args| {
+                if args.is_empty() {
+                    let current = fog.get();
+                    println!(
+                        "fog density {} color {} {} {}",
+                        current.density, current.color[0], current.color[1], current.color[2]
+                    );
+                    return;
+                }
+
+                match fog.get().with_args(args) {
+                    Some(f) => fog.set(f),
+                    None => println!("fog [density] [r g b]: set the distance fog"),
+                }
+            }),
+        );
+
+        let music_player = self.music_player.clone();
+        cmds.insert_or_replace(
+            "music",
+            "music [track number | name]: show the playing track or play a looping music track",
+            Box::new(move |args| match args.len() {
+                0 => match music_player.borrow().playing() {
+                    Some(name) => println!("Playing {}", name),
+                    None => println!("No music playing"),
+                },
+
+                // music (track number | name): play a CD track rip or a file in music/
+                1 => {
+                    let result = match args[0].parse::<u8>() {
+                        Ok(track) => music_player.borrow_mut().play_track(track),
+                        Err(_) => music_player.borrow_mut().play_named(args[0]),
+                    };
+
+                    if let Err(e) = result {
+                        println!("Couldn't play {}: {}", args[0], e);
+                    }
+                }
+
+                _ => println!("music [track number | name]: play a looping music track"),
+            }),
+        );
+
+        let music_player = self.music_player.clone();
+        cmds.insert_or_replace(
+            "music_stop",
+            "stop the music",
+            Box::new(move |_| music_player.borrow_mut().stop()),
+        );
+
+        let record_requested = self.record_requested.clone();
+        cmds.insert_or_replace(
+            "record",
+            "record (demoname): record the game to a demo, starting with the next level",
+            Box::new(move |args| match args {
+                [name] => {
+                    record_requested.replace(Some(name.to_string()));
+                }
+                _ => println!("record (demoname): record the game to a demo"),
+            }),
+        );
+
+        let stop_requested = self.stop_requested.clone();
+        cmds.insert_or_replace(
+            "stop",
+            "stop recording a demo",
+            Box::new(move |_| stop_requested.set(true)),
+        );
+
+        let demo_seek_requested = self.demo_seek_requested.clone();
+        cmds.insert_or_replace(
+            "demoseek",
+            "demoseek (seconds): move demo playback to the nearest keyframe before a time",
+            Box::new(move |args| match args {
+                [seconds] => match seconds.parse::<f32>() {
+                    Ok(s) if s >= 0.0 => {
+                        demo_seek_requested.set(Some(engine::duration_from_f32(s)))
+                    }
+                    _ => println!("demoseek (seconds): seconds must be a non-negative number"),
+                },
+                _ => println!("demoseek (seconds): move demo playback to a time"),
+            }),
+        );
+
+        let practice_cmds = [
+            (
+                "route_record",
+                "record the path you take, replacing the last route",
+                PracticeRequest::RecordRoute,
+            ),
+            (
+                "route_play",
+                "show the recorded route and race a marker along it",
+                PracticeRequest::PlayRoute,
+            ),
+            (
+                "route_stop",
+                "stop recording or playing a route",
+                PracticeRequest::StopRoute,
+            ),
+            (
+                "pos_save",
+                "save your position on the current map",
+                PracticeRequest::SavePosition,
+            ),
+            (
+                "pos_load",
+                "return to the saved position (single player only)",
+                PracticeRequest::LoadPosition,
+            ),
+        ];
+        for (name, help, request) in practice_cmds.iter() {
+            let practice = self.practice.clone();
+            let request = *request;
+            cmds.insert_or_replace(
+                name,
+                help,
+                Box::new(move |_| practice.borrow_mut().request(request)),
+            );
+        }
+
+        let freecam_requested = self.freecam_requested.clone();
+        cmds.insert_or_replace(
+            "freecam",
+            "detach the camera from the player and fly it freely (single player only)",
+            Box::new(move |_| freecam_requested.set(true)),
+        );
+
+        let debug_trace_requested = self.debug_trace_requested.clone();
+        cmds.insert_or_replace(
+            "debug_trace",
+            "trace along the view direction, shown with r_showtraces",
+            Box::new(move |_| debug_trace_requested.set(true)),
+        );
+
+        let surface_info_requested = self.surface_info_requested.clone();
+        cmds.insert_or_replace(
+            "surface_info",
+            "surface_info [highlight]: describe the surface under the crosshair",
+            Box::new(move |args| match args {
+                [] => surface_info_requested.set(Some(false)),
+                ["highlight"] => surface_info_requested.set(Some(true)),
+                _ => println!("surface_info [highlight]: describe the surface under the crosshair"),
+            }),
+        );
+
         let vfs = self.vfs.clone();
         let console = self.console.clone();
         cmds.insert_or_replace(
             "exec",
+            "exec (filename): execute a script file",
             Box::new(move |args| {
                 match args.len() {
                     // exec (filename): execute a script file
@@ -2051,6 +3902,12 @@ impl Client {
                 }
             }),
         );
+
+        let vfs = self.vfs.clone();
+        cmds.insert_completer(
+            "exec",
+            Box::new(move || files_with_extension(&vfs, "", "cfg", false)),
+        );
     }
 
     pub fn spawn_beam(
@@ -2068,9 +3925,10 @@ impl Client {
             if let Some(ref mut beam) = self.state.beams[i] {
                 if beam.entity_id == entity_id {
                     beam.model_id = model_id;
-                    beam.expire = time + Duration::milliseconds(200);
+                    beam.expire = time + Duration::milliseconds(beam::BEAM_LIFETIME_MS);
                     beam.start = start;
                     beam.end = end;
+                    return;
                 }
             } else if free.is_none() {
                 free = Some(i);
@@ -2081,7 +3939,7 @@ impl Client {
             self.state.beams[i] = Some(Beam {
                 entity_id,
                 model_id,
-                expire: time + Duration::milliseconds(200),
+                expire: time + Duration::milliseconds(beam::BEAM_LIFETIME_MS),
                 start,
                 end,
             });
@@ -2136,6 +3994,7 @@ impl Client {
                                 decay_rate: 300.0,
                                 min_radius: None,
                                 ttl: Duration::milliseconds(500),
+                                style: None,
                             },
                             None,
                         );
@@ -2159,6 +4018,7 @@ impl Client {
                                 decay_rate: 300.0,
                                 min_radius: None,
                                 ttl: Duration::milliseconds(500),
+                                style: None,
                             },
                             None,
                         );
@@ -2234,6 +4094,23 @@ impl Client {
         self.state.stats[ClientStat::ActiveWeapon as usize]
     }
 
+    /// Returns the view weapon, positioned at the view origin and angles.
+    ///
+    /// Returns `None` if no weapon should be drawn, e.g. during intermission, while dead or
+    /// while invisible.
+    pub fn viewmodel(&self) -> Option<&ClientEntity> {
+        if self.state.viewmodel.model_id() == 0
+            || self.state.intermission.is_some()
+            || self.state.stats[ClientStat::Health as usize] <= 0
+            || self.state.items.contains(ItemFlags::INVISIBILITY)
+            || self.freecam.is_some()
+        {
+            return None;
+        }
+
+        Some(&self.state.viewmodel)
+    }
+
     pub fn stats(&self) -> &[i32; MAX_STATS] {
         &self.state.stats
     }
@@ -2243,53 +4120,76 @@ impl Client {
     }
 
     pub fn lightstyle_values(&self) -> Result<Vec<f32>, ClientError> {
-        let mut values = Vec::new();
-
-        for lightstyle_id in 0..64 {
-            match self.state.light_styles.get(&lightstyle_id) {
-                Some(ls) => {
-                    let float_time = engine::duration_to_f32(self.state.time);
-                    let frame = if ls.len() == 0 {
-                        None
-                    } else {
-                        Some((float_time * 10.0) as usize % ls.len())
-                    };
-
-                    values.push(match frame {
-                        // 'z' - 'a' = 25, so divide by 12.5 to get range [0, 2]
-                        Some(f) => (ls.as_bytes()[f] - 'a' as u8) as f32 / 12.5,
-                        None => 1.0,
-                    })
-                }
+        (0..64)
+            .map(|lightstyle_id| {
+                self.state
+                    .light_style_value(lightstyle_id)
+                    .ok_or(ClientError::NoSuchLightmapAnimation(lightstyle_id as usize))
+            })
+            .collect()
+    }
 
-                None => Err(ClientError::NoSuchLightmapAnimation(lightstyle_id as usize))?,
-            }
-        }
+    /// Returns the radius of a dynamic light, scaled by the current value of its light style.
+    pub fn light_radius(&self, light: &Light) -> f32 {
+        let scale = light
+            .style()
+            .and_then(|s| self.state.light_style_value(s))
+            .unwrap_or(1.0);
+        light.radius(self.state.time) * scale
+    }
 
-        Ok(values)
+    /// Returns the current distance fog.
+    pub fn fog(&self) -> Fog {
+        self.fog.get()
     }
 
+    /// Returns the combined color and opacity of all active view blends.
+    ///
+    /// Shifts are layered in priority order: contents, damage, bonus flash, then powerup. Each
+    /// can be scaled or disabled by its own cvar, and `gl_polyblend 0` disables them all.
+    /// `v_flashlimit` caps the opacity of the damage and bonus flashes, which come and go
+    /// suddenly. See `COLOR_SHIFT_KINDS`.
     pub fn color_shift(&self) -> [f32; 4] {
-        self.state
+        if self.cvar_value("gl_polyblend").unwrap_or(1.0) == 0.0 {
+            return [0.0; 4];
+        }
+
+        let scale = self.cvar_value("gl_cshiftpercent").unwrap_or(100.0) / 100.0;
+        let flash_limit = self
+            .cvar_value("v_flashlimit")
+            .unwrap_or(1.0)
+            .max(0.0)
+            .min(1.0);
+
+        let mut layers: Vec<_> = self
+            .state
             .color_shifts
             .iter()
-            .fold([0.0; 4], |accum, elem| {
-                let elem_a = elem.borrow().percent as f32 / 255.0 / 2.0;
-                if elem_a == 0.0 {
-                    return accum;
-                }
-                let in_a = accum[3];
-                let out_a = in_a + elem_a * (1.0 - in_a);
-                let color_factor = elem_a / out_a;
-
-                let mut out = [0.0; 4];
-                for i in 0..3 {
-                    out[i] = accum[i] * (1.0 - color_factor)
-                        + elem.borrow().dest_color[i] as f32 / 255.0 * color_factor;
-                }
-                out[3] = out_a.min(1.0).max(0.0);
-                out
-            })
+            .zip(COLOR_SHIFT_KINDS.iter())
+            .collect();
+        layers.sort_by_key(|(_, kind)| kind.priority);
+
+        layers.into_iter().fold([0.0; 4], |accum, (elem, kind)| {
+            let elem_scale = self.cvar_value(kind.cvar).unwrap_or(1.0).max(0.0).min(1.0);
+            let mut elem_a = elem.borrow().percent as f32 * scale * elem_scale / 255.0 / 2.0;
+            if kind.flash {
+                elem_a = elem_a.min(flash_limit);
+            }
+            if elem_a == 0.0 {
+                return accum;
+            }
+            let in_a = accum[3];
+            let out_a = in_a + elem_a * (1.0 - in_a);
+            let color_factor = elem_a / out_a;
+
+            let mut out = [0.0; 4];
+            for i in 0..3 {
+                out[i] = accum[i] * (1.0 - color_factor)
+                    + elem.borrow().dest_color[i] as f32 / 255.0 * color_factor;
+            }
+            out[3] = out_a.min(1.0).max(0.0);
+            out
+        })
     }
 
     fn idle_vars(&self) -> Result<IdleVars, ClientError> {
@@ -2320,6 +4220,23 @@ impl Client {
         })
     }
 
+    fn gamepad_vars(&self) -> Result<GamepadVars, ClientError> {
+        Ok(GamepadVars {
+            joy_deadzone: self.cvar_value("joy_deadzone")?,
+            joy_exponent: self.cvar_value("joy_exponent")?,
+            joy_pitchspeed: self.cvar_value("joy_pitchspeed")?,
+            joy_yawspeed: self.cvar_value("joy_yawspeed")?,
+        })
+    }
+
+    fn bob_vars(&self) -> Result<BobVars, ClientError> {
+        Ok(BobVars {
+            cl_bob: self.cvar_value("cl_bob")?,
+            cl_bobcycle: self.cvar_value("cl_bobcycle")?,
+            cl_bobup: self.cvar_value("cl_bobup")?,
+        })
+    }
+
     fn roll_vars(&self) -> Result<RollVars, ClientError> {
         Ok(RollVars {
             cl_rollangle: self.cvar_value("cl_rollangle")?,
@@ -2376,5 +4293,179 @@ impl std::ops::Drop for Client {
     fn drop(&mut self) {
         // if this errors, it was already removed so we don't care
         let _ = self.cmds.borrow_mut().remove("reconnect");
+
+        // finish the demo so that it ends with a disconnect
+        self.stop_recording();
+
+        // the map's settings don't outlast the game
+        self.map_config.restore(&self.cvars.borrow());
+
+        match self.update_src {
+            // the server would otherwise keep the player until the connection timed out. like the
+            // original engine, send it a few times in case some are lost.
+            UpdateSource::Server(ref mut qsock) => {
+                let mut msg = Vec::new();
+                if ClientCmd::Disconnect.serialize(&mut msg).is_ok() {
+                    for _ in 0..3 {
+                        let _ = qsock.send_msg_unreliable(&msg);
+                    }
+                }
+            }
+
+            UpdateSource::QuakeWorld(_) => {
+                let _ = self.cmds.borrow_mut().remove("cmd");
+                let _ = self.cmds.borrow_mut().remove("skins");
+            }
+
+            UpdateSource::Demo(_) => (),
+        }
+    }
+}
+
+/// A client and its game input, run as the client of a loopback `Simulation`.
+///
+/// Each frame runs the console, then the client, then sends the input like the host loop does.
+/// Taking the client out disconnects it, as leaving the game does.
+pub struct SimClient {
+    pub client: Option<Client>,
+    pub input: GameInput,
+}
+
+impl SimHost for SimClient {
+    fn frame(&mut self, _time: Duration, frame_time: Duration) -> Result<(), Box<dyn Error>> {
+        if let Some(ref mut client) = self.client {
+            client.console.borrow().execute();
+            client.frame(frame_time)?;
+            client.handle_input(&mut self.input, frame_time)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Reads the keys of a map's worldspawn entity, if there is one.
+// the dim light of a muzzle flash at `origin`
+fn muzzle_flash_light(origin: Vector3<f32>, rng: &mut GameRng) -> LightDesc {
+    LightDesc {
+        origin,
+        init_radius: MFLASH_DIMLIGHT_DISTRIBUTION.sample(rng),
+        decay_rate: 0.0,
+        min_radius: Some(32.0),
+        ttl: Duration::milliseconds(100),
+        style: None,
+    }
+}
+
+fn worldspawn(ent_string: &str) -> Option<HashMap<&str, &str>> {
+    let (_, entities) = parse::entities(ent_string).ok()?;
+    entities
+        .into_iter()
+        .find(|ent| ent.get("classname") == Some(&"worldspawn"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        client::render,
+        common::net::loopback::{LoopbackNetwork, Simulation},
+        server::{
+            self, fixture,
+            level::{Level, SimServer},
+            ServerStatics,
+        },
+    };
+
+    fn ms(n: i64) -> Duration {
+        Duration::milliseconds(n)
+    }
+
+    // the real client connected to the fixture level, or None if there is no audio device
+    fn simulation(name: &str) -> Option<Simulation<SimServer, SimClient>> {
+        let audio_device = Rc::new(rodio::default_output_device()?);
+        let vfs = fixture::vfs(name);
+
+        let mut server_cvars = CvarRegistry::new();
+        server::register_cvars(&server_cvars).unwrap();
+        let mut level = Level::spawn(
+            vfs.clone(),
+            &mut server_cvars,
+            fixture::MAP_NAME,
+            ServerStatics::new(1),
+        )
+        .unwrap();
+
+        let network = LoopbackNetwork::new(ms(40));
+        let (server_socket, client_socket) = network.pair();
+        level
+            .connect_client(&mut server_cvars, server_socket)
+            .unwrap()
+            .unwrap();
+
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new()));
+        register_cvars(&cvars.borrow()).unwrap();
+        render::register_cvars(&cvars.borrow());
+        let cmds = Rc::new(RefCell::new(CmdRegistry::new()));
+        let console = Rc::new(RefCell::new(Console::new(cmds.clone(), cvars.clone())));
+
+        let input = GameInput::new(console.clone());
+        input.register_cmds(&mut cmds.borrow_mut());
+        let client = Client::with_connection(
+            client_socket,
+            vfs,
+            cvars,
+            cmds.clone(),
+            console,
+            audio_device,
+        )
+        .unwrap();
+        client.register_cmds(&mut cmds.borrow_mut());
+
+        Some(Simulation::new(
+            network,
+            SimServer {
+                level,
+                cvars: server_cvars,
+            },
+            SimClient {
+                client: Some(client),
+                input,
+            },
+        ))
+    }
+
+    #[test]
+    fn test_sim_signon_movement_and_disconnect() {
+        let mut sim = match simulation("client") {
+            Some(s) => s,
+            None => return,
+        };
+
+        let view_origin = |sim: &Simulation<SimServer, SimClient>| -> Vector3<f32> {
+            sim.client().client.as_ref().unwrap().view_origin()
+        };
+
+        sim.run_for(Duration::seconds(2), ms(14)).unwrap();
+        assert!(sim.client().client.as_ref().unwrap().host_state().in_game());
+        assert_eq!(sim.server().level.client_count(), 1);
+
+        // run forward, along +x at the player's starting yaw
+        let start = view_origin(&sim);
+        sim.client()
+            .client
+            .as_ref()
+            .unwrap()
+            .console
+            .borrow()
+            .stuff_text("+forward");
+        sim.run_for(Duration::seconds(2), ms(14)).unwrap();
+        let end = view_origin(&sim);
+        assert!(end.x - start.x > 100.0, "{:?} -> {:?}", start, end);
+
+        // leaving the game tells the server
+        sim.client_mut().client = None;
+        sim.run_for(Duration::seconds(1), ms(14)).unwrap();
+        assert_eq!(sim.server().level.client_count(), 0);
     }
 }
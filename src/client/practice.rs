@@ -0,0 +1,358 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Practice tools for speedrunning.
+//!
+//! `route_record` records the path the player takes, and `route_play` draws it in the world and
+//! moves a marker along it at the pace it was recorded, so that a run can be raced against an
+//! earlier one. Routes are timed by the frames the game was running, so pauses and level loads
+//! don't put the marker ahead.
+//!
+//! `pos_save` and `pos_load` return the player to a saved position on the same map. Moving the
+//! player is up to the server, so positions can only be loaded in single player.
+
+use cgmath::{Deg, InnerSpace, Vector3};
+use chrono::Duration;
+
+// points closer together than this are not recorded
+const MIN_POINT_DISTANCE: f32 = 8.0;
+
+// the longest route that can be recorded, at one point per frame
+const MAX_POINTS: usize = 100_000;
+
+/// A position reached while recording a route.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RoutePoint {
+    /// The time since recording started.
+    pub time: Duration,
+    pub origin: Vector3<f32>,
+}
+
+/// The path taken by the player.
+#[derive(Clone, Debug, Default)]
+pub struct Route {
+    points: Vec<RoutePoint>,
+}
+
+impl Route {
+    pub fn new() -> Route {
+        Route { points: Vec::new() }
+    }
+
+    pub fn points(&self) -> &[RoutePoint] {
+        &self.points
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Returns the time taken to follow the route.
+    pub fn duration(&self) -> Duration {
+        self.points
+            .last()
+            .map(|p| p.time)
+            .unwrap_or_else(Duration::zero)
+    }
+
+    /// Records that the player reached `origin` at `time`.
+    ///
+    /// While the last point is close to the one before it, it is replaced rather than followed by
+    /// a new point. This keeps the route short while the player stands still without losing the
+    /// time spent waiting.
+    pub fn record(&mut self, time: Duration, origin: Vector3<f32>) {
+        if self.points.len() >= MAX_POINTS {
+            return;
+        }
+
+        let len = self.points.len();
+        if len >= 2 {
+            let before = self.points[len - 2].origin;
+            if (origin - before).magnitude() < MIN_POINT_DISTANCE {
+                self.points[len - 1] = RoutePoint { time, origin };
+                return;
+            }
+        }
+
+        self.points.push(RoutePoint { time, origin });
+    }
+
+    /// Returns the position `time` after the start of the route.
+    ///
+    /// Positions between recorded points are interpolated. After the end of the route, this
+    /// returns the last point.
+    pub fn position_at(&self, time: Duration) -> Option<Vector3<f32>> {
+        let next = match self.points.iter().position(|p| p.time >= time) {
+            Some(0) => return self.points.first().map(|p| p.origin),
+            Some(i) => i,
+            None => return self.points.last().map(|p| p.origin),
+        };
+
+        let prev = &self.points[next - 1];
+        let next = &self.points[next];
+        let span = (next.time - prev.time).num_microseconds().unwrap_or(0);
+        if span <= 0 {
+            return Some(next.origin);
+        }
+
+        let elapsed = (time - prev.time).num_microseconds().unwrap_or(0);
+        let factor = elapsed as f32 / span as f32;
+        Some(prev.origin + (next.origin - prev.origin) * factor)
+    }
+}
+
+/// A player position saved by `pos_save`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SavedPosition {
+    /// The name of the map the position was saved on.
+    pub map: String,
+    pub origin: Vector3<f32>,
+    /// Pitch, yaw and roll.
+    pub angles: Vector3<Deg<f32>>,
+}
+
+impl SavedPosition {
+    /// Returns the server command that moves the player to this position.
+    pub fn setpos_cmd(&self) -> String {
+        format!(
+            "setpos {} {} {} {} {} {}",
+            self.origin.x,
+            self.origin.y,
+            self.origin.z,
+            self.angles.x.0,
+            self.angles.y.0,
+            self.angles.z.0
+        )
+    }
+}
+
+/// Requests made by the practice commands.
+///
+/// Commands don't have access to the player's position, so they are carried out by the client on
+/// the next frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PracticeRequest {
+    RecordRoute,
+    PlayRoute,
+    StopRoute,
+    SavePosition,
+    LoadPosition,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RouteState {
+    Idle,
+    Recording,
+    Playing,
+}
+
+/// State of the practice tools.
+pub struct Practice {
+    route: Route,
+    route_state: RouteState,
+    // time since recording or playback started
+    elapsed: Duration,
+    saved_position: Option<SavedPosition>,
+    requests: Vec<PracticeRequest>,
+}
+
+impl Practice {
+    pub fn new() -> Practice {
+        Practice {
+            route: Route::new(),
+            route_state: RouteState::Idle,
+            elapsed: Duration::zero(),
+            saved_position: None,
+            requests: Vec::new(),
+        }
+    }
+
+    pub fn request(&mut self, request: PracticeRequest) {
+        self.requests.push(request);
+    }
+
+    /// Removes and returns the requests made since the last call.
+    pub fn take_requests(&mut self) -> Vec<PracticeRequest> {
+        std::mem::replace(&mut self.requests, Vec::new())
+    }
+
+    /// Discards the current route and starts recording a new one.
+    pub fn start_recording(&mut self) {
+        self.route = Route::new();
+        self.route_state = RouteState::Recording;
+        self.elapsed = Duration::zero();
+    }
+
+    /// Starts moving the marker along the recorded route.
+    ///
+    /// Returns `false` if there is no route to play.
+    pub fn start_playback(&mut self) -> bool {
+        if self.route.is_empty() {
+            return false;
+        }
+
+        self.route_state = RouteState::Playing;
+        self.elapsed = Duration::zero();
+        true
+    }
+
+    /// Stops recording or playback, returning `true` if either was in progress.
+    pub fn stop(&mut self) -> bool {
+        let was_active = self.route_state != RouteState::Idle;
+        self.route_state = RouteState::Idle;
+        was_active
+    }
+
+    pub fn recording(&self) -> bool {
+        self.route_state == RouteState::Recording
+    }
+
+    pub fn route(&self) -> &Route {
+        &self.route
+    }
+
+    /// Advances recording or playback by `frame_time`, with the player at `origin`.
+    pub fn update(&mut self, frame_time: Duration, origin: Vector3<f32>) {
+        match self.route_state {
+            RouteState::Idle => (),
+            RouteState::Recording => {
+                self.elapsed = self.elapsed + frame_time;
+                self.route.record(self.elapsed, origin);
+            }
+            RouteState::Playing => {
+                self.elapsed = self.elapsed + frame_time;
+            }
+        }
+    }
+
+    /// Returns the position of the marker racing the player, if a route is being played.
+    ///
+    /// The marker stays at the end of the route once it gets there.
+    pub fn marker(&self) -> Option<Vector3<f32>> {
+        match self.route_state {
+            RouteState::Playing => self.route.position_at(self.elapsed),
+            _ => None,
+        }
+    }
+
+    /// Returns the route to draw in the world, if it is being recorded or played.
+    pub fn visible_route(&self) -> Option<&Route> {
+        match self.route_state {
+            RouteState::Idle => None,
+            _ => Some(&self.route),
+        }
+    }
+
+    pub fn save_position(&mut self, position: SavedPosition) {
+        self.saved_position = Some(position);
+    }
+
+    pub fn saved_position(&self) -> Option<&SavedPosition> {
+        self.saved_position.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ms(n: i64) -> Duration {
+        Duration::milliseconds(n)
+    }
+
+    #[test]
+    fn test_route_record_skips_small_moves() {
+        let mut route = Route::new();
+        route.record(ms(0), Vector3::new(0.0, 0.0, 0.0));
+        route.record(ms(10), Vector3::new(64.0, 0.0, 0.0));
+
+        // standing still extends the last point instead of adding new ones
+        route.record(ms(20), Vector3::new(65.0, 0.0, 0.0));
+        route.record(ms(30), Vector3::new(66.0, 0.0, 0.0));
+        assert_eq!(route.points().len(), 3);
+        assert_eq!(
+            route.points()[2],
+            RoutePoint {
+                time: ms(30),
+                origin: Vector3::new(66.0, 0.0, 0.0),
+            }
+        );
+
+        route.record(ms(40), Vector3::new(128.0, 0.0, 0.0));
+        assert_eq!(route.points().len(), 4);
+        assert_eq!(route.duration(), ms(40));
+    }
+
+    #[test]
+    fn test_route_position_at() {
+        let mut route = Route::new();
+        assert_eq!(route.position_at(ms(0)), None);
+
+        route.record(ms(100), Vector3::new(0.0, 0.0, 0.0));
+        route.record(ms(200), Vector3::new(100.0, 0.0, 0.0));
+        route.record(ms(400), Vector3::new(100.0, 200.0, 0.0));
+
+        assert_eq!(route.position_at(ms(0)), Some(Vector3::new(0.0, 0.0, 0.0)));
+        assert_eq!(
+            route.position_at(ms(150)),
+            Some(Vector3::new(50.0, 0.0, 0.0))
+        );
+        assert_eq!(
+            route.position_at(ms(300)),
+            Some(Vector3::new(100.0, 100.0, 0.0))
+        );
+        assert_eq!(
+            route.position_at(ms(1000)),
+            Some(Vector3::new(100.0, 200.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn test_practice_record_and_play() {
+        let mut practice = Practice::new();
+        assert!(!practice.start_playback());
+
+        practice.start_recording();
+        for i in 0..10 {
+            practice.update(ms(100), Vector3::new(i as f32 * 32.0, 0.0, 0.0));
+        }
+        assert!(practice.stop());
+        assert!(practice.visible_route().is_none());
+        assert_eq!(practice.route().duration(), ms(1000));
+
+        assert!(practice.start_playback());
+        assert_eq!(practice.marker(), Some(Vector3::new(0.0, 0.0, 0.0)));
+
+        // playback doesn't record the player's position
+        practice.update(ms(450), Vector3::new(-500.0, 0.0, 0.0));
+        assert_eq!(practice.marker(), Some(Vector3::new(112.0, 0.0, 0.0)));
+        assert_eq!(practice.route().points().len(), 10);
+    }
+
+    #[test]
+    fn test_setpos_cmd() {
+        let position = SavedPosition {
+            map: String::from("maps/e1m1.bsp"),
+            origin: Vector3::new(480.0, -352.5, 88.0),
+            angles: Vector3::new(Deg(10.0), Deg(90.0), Deg(0.0)),
+        };
+        assert_eq!(position.setpos_cmd(), "setpos 480 -352.5 88 10 90 0");
+    }
+}
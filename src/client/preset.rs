@@ -0,0 +1,319 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Bundles of cvar settings applied together.
+//!
+//! `preset <name>` sets every cvar in a preset at once. Four are built in: `competitive`, which
+//! clears the screen of anything that gets in the way of aiming, `classic`, which looks and sounds
+//! like the original engine, `quality`, which turns on every effect, and `accessible`, which
+//! calms flashes and view motion and makes text easier to read.
+//!
+//! Presets can also be defined in `presets/<name>.cfg` in the game directory, which replace a
+//! built-in preset of the same name. A preset file holds one `cvar value` pair per line, and a
+//! comment on the first line describes it:
+//!
+//! ```text
+//! // dark and quiet
+//! r_bloom 0
+//! bgmvolume 0.2
+//! ```
+//!
+//! A preset is applied whole or not at all: if any of its cvars doesn't exist, none are changed.
+
+use std::io::Read as _;
+
+use crate::common::{
+    console::{files_with_extension, CvarRegistry},
+    parse,
+    vfs::Vfs,
+};
+
+use thiserror::Error;
+
+/// The directory in the virtual filesystem that user presets are read from.
+pub const PRESET_DIR: &str = "presets";
+
+const COMPETITIVE: &str = "\
+// clear view: no bobbing, kicks, flashes or bloom
+cl_bob 0
+cl_rollangle 0
+v_kickpitch 0
+v_kickroll 0
+v_idlescale 0
+v_bonusflash 0
+gl_cshiftpercent 0
+r_bloom 0
+r_waterwarp 0
+r_shadows 0
+r_blobshadows 0
+scr_transition 0
+snd_occlusion 1
+snd_occlusion_cutoff 0
+";
+
+const CLASSIC: &str = "\
+// the look and sound of the original engine
+cl_beamjitter 0
+gl_texturemode GL_NEAREST_MIPMAP_LINEAR
+r_anisotropy 1
+r_bloom 0
+r_tonemap 0
+r_coloredlight 0
+r_externaltextures 0
+r_lerpmodels 0
+r_lerpmove 0
+r_particlestyle 1
+r_shadows 0
+r_blobshadows 0
+r_movershadows 0
+r_waterreflect 0
+r_waterwarp 1
+scr_transition 0
+snd_occlusion 1
+snd_occlusion_cutoff 0
+snd_reverb 0
+";
+
+const QUALITY: &str = "\
+// every effect at its best, for capable hardware
+gl_texturemode GL_LINEAR_MIPMAP_LINEAR
+r_anisotropy 16
+r_msaa_samples 4
+r_scale 1
+r_bloom 1
+r_tonemap 2
+r_coloredlight 1
+r_externaltextures 1
+r_lerpmodels 1
+r_lerpmove 1
+r_shadows 1
+r_blobshadows 0
+r_movershadows 1
+r_shadow_size 2048
+r_waterreflect 0.5
+snd_occlusion 0.5
+snd_reverb 0.25
+";
+
+const ACCESSIBLE: &str = "\
+// steady light, a calm view and large, high contrast text
+v_flashlimit 0.1
+r_flatlightstyles 1
+cl_beamjitter 0
+v_motionscale 0.25
+v_idlescale 0
+r_waterwarp 0
+scr_highcontrast 1
+scr_conscale 3
+scr_sbarscale 3
+";
+
+const BUILTIN: [(&str, &str); 4] = [
+    ("accessible", ACCESSIBLE),
+    ("classic", CLASSIC),
+    ("competitive", COMPETITIVE),
+    ("quality", QUALITY),
+];
+
+#[derive(Error, Debug)]
+pub enum PresetError {
+    #[error("couldn't parse preset \"{0}\"")]
+    Parse(String),
+    #[error("expected a cvar and a value, found \"{0}\"")]
+    Syntax(String),
+    #[error("no such cvar \"{0}\"")]
+    NoSuchCvar(String),
+    #[error("couldn't read preset: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// A named set of cvar values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Preset {
+    name: String,
+    description: String,
+    values: Vec<(String, String)>,
+}
+
+impl Preset {
+    /// Parses a preset from the contents of a preset file.
+    pub fn parse<S>(name: S, text: &str) -> Result<Preset, PresetError>
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref();
+
+        let description = text
+            .lines()
+            .next()
+            .and_then(|line| line.trim().strip_prefix("//"))
+            .map(|d| d.trim().to_owned())
+            .unwrap_or_default();
+
+        let (_, commands) =
+            parse::commands(text).map_err(|_| PresetError::Parse(name.to_owned()))?;
+        let values = commands
+            .into_iter()
+            .map(|args| match args.as_slice() {
+                [cvar, value] => Ok(((*cvar).to_owned(), (*value).to_owned())),
+                _ => Err(PresetError::Syntax(args.join(" "))),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Preset {
+            name: name.to_owned(),
+            description,
+            values,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Returns the cvars this preset sets and their values, in the order they are set.
+    pub fn values(&self) -> &[(String, String)] {
+        &self.values
+    }
+
+    /// Sets every cvar in the preset.
+    ///
+    /// If any of the cvars doesn't exist, or one can't be set, the cvars are left as they were.
+    pub fn apply(&self, cvars: &CvarRegistry) -> Result<(), PresetError> {
+        if let Some((cvar, _)) = self.values.iter().find(|(cvar, _)| !cvars.contains(cvar)) {
+            return Err(PresetError::NoSuchCvar(cvar.to_owned()));
+        }
+
+        let mut previous: Vec<(&str, String)> = Vec::with_capacity(self.values.len());
+        for (cvar, value) in self.values.iter() {
+            let old = cvars
+                .get(cvar)
+                .map_err(|_| PresetError::NoSuchCvar(cvar.to_owned()))?;
+
+            if cvars.set(cvar.as_str(), value.as_str()).is_err() {
+                for (cvar, old) in previous.iter().rev() {
+                    let _ = cvars.set(*cvar, old.as_str());
+                }
+                return Err(PresetError::NoSuchCvar(cvar.to_owned()));
+            }
+
+            previous.push((cvar.as_str(), old));
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the built-in preset with the given name.
+pub fn builtin<S>(name: S) -> Option<Preset>
+where
+    S: AsRef<str>,
+{
+    BUILTIN
+        .iter()
+        .find(|(n, _)| *n == name.as_ref())
+        .map(|(n, text)| Preset::parse(n, text).unwrap())
+}
+
+/// Finds the preset with the given name, preferring one defined in the game directory to a
+/// built-in one.
+pub fn find<S>(vfs: &Vfs, name: S) -> Result<Option<Preset>, PresetError>
+where
+    S: AsRef<str>,
+{
+    let name = name.as_ref();
+    match vfs.open(format!("{}/{}.cfg", PRESET_DIR, name)) {
+        Ok(mut file) => {
+            let mut text = String::new();
+            file.read_to_string(&mut text)?;
+            Preset::parse(name, &text).map(Some)
+        }
+        Err(_) => Ok(builtin(name)),
+    }
+}
+
+/// Returns the names of all presets, built-in or defined in the game directory, in alphabetical
+/// order.
+pub fn names(vfs: &Vfs) -> Vec<String> {
+    let mut names: Vec<String> = BUILTIN.iter().map(|(n, _)| (*n).to_owned()).collect();
+    names.extend(files_with_extension(vfs, PRESET_DIR, "cfg", true));
+    names.sort();
+    names.dedup();
+    names
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let preset = Preset::parse("dark", "// dark and quiet\nr_bloom 0\nbgmvolume \"0.2\"\n");
+        let preset = preset.unwrap();
+        assert_eq!(preset.name(), "dark");
+        assert_eq!(preset.description(), "dark and quiet");
+        assert_eq!(
+            preset.values(),
+            &[
+                ("r_bloom".to_owned(), "0".to_owned()),
+                ("bgmvolume".to_owned(), "0.2".to_owned()),
+            ]
+        );
+
+        assert!(matches!(
+            Preset::parse("bad", "r_bloom\n"),
+            Err(PresetError::Syntax(_))
+        ));
+    }
+
+    #[test]
+    fn test_builtin_presets_parse() {
+        for (name, _) in BUILTIN.iter() {
+            let preset = builtin(name).unwrap();
+            assert!(!preset.description().is_empty());
+            assert!(!preset.values().is_empty());
+        }
+        assert!(builtin("nonexistent").is_none());
+    }
+
+    #[test]
+    fn test_apply_is_all_or_nothing() {
+        let cvars = CvarRegistry::new();
+        cvars.register("r_bloom", "0", "").unwrap();
+        cvars.register("fov", "90", "").unwrap();
+
+        let preset = Preset::parse("p", "r_bloom 1\nfov 110\nnonexistent 1\n").unwrap();
+        assert!(matches!(
+            preset.apply(&cvars),
+            Err(PresetError::NoSuchCvar(ref name)) if name == "nonexistent"
+        ));
+        assert_eq!(cvars.get("r_bloom").unwrap(), "0");
+        assert_eq!(cvars.get("fov").unwrap(), "90");
+
+        let preset = Preset::parse("p", "r_bloom 1\nfov 110\n").unwrap();
+        preset.apply(&cvars).unwrap();
+        assert_eq!(cvars.get("r_bloom").unwrap(), "1");
+        assert_eq!(cvars.get("fov").unwrap(), "110");
+    }
+}
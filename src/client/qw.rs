@@ -0,0 +1,590 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! QuakeWorld client session.
+//!
+//! Messages from a QuakeWorld server are translated into their NetQuake equivalents so that the
+//! rest of the client handles both protocols the same way. The session also drives the
+//! QuakeWorld sign-on sequence, which is requested by the client rather than the server.
+
+use std::{
+    cell::{Cell, RefCell},
+    io::BufReader,
+    net::SocketAddr,
+    rc::Rc,
+    time::{Duration as StdDuration, Instant},
+};
+
+use crate::{
+    client::{
+        host::{self, HostEvent, HostState},
+        ClientError,
+    },
+    common::{
+        console::{CmdRegistry, CvarRegistry},
+        engine,
+        net::{
+            self,
+            qw::{self, Connection, PacketEntityFrames, PlayerInfo, UserCmd},
+            ClientStat, EntityEffects, EntityUpdate, GameType, ItemFlags, PlayerColor, ServerCmd,
+            SignOnStage,
+        },
+        vfs::Vfs,
+    },
+};
+
+use cgmath::Deg;
+use chrono::Duration;
+use num::FromPrimitive;
+
+// the original client sends at most 72 packets per second
+const MIN_TRANSMIT_INTERVAL: StdDuration = StdDuration::from_millis(13);
+
+// the longest command the server will run
+const MAX_CMD_MSEC: u128 = 250;
+
+const STAT_ITEMS: usize = 15;
+const MAX_STATS: usize = 32;
+
+/// A connection to a QuakeWorld server.
+pub struct Session {
+    vfs: Rc<Vfs>,
+    conn: Connection,
+
+    // string commands issued from the console, sent with the next packet
+    outgoing: Rc<RefCell<Vec<String>>>,
+    server_count: Rc<Cell<i32>>,
+    host_state: Rc<Cell<HostState>>,
+
+    player_id: u8,
+    level_name: String,
+    model_precache: Vec<String>,
+    sound_precache: Vec<String>,
+    player_model_id: Option<u8>,
+    stats: [i32; MAX_STATS],
+    frames: PacketEntityFrames,
+
+    // the last three commands sent, oldest first
+    cmds: [UserCmd; 3],
+    pending_cmd: Option<UserCmd>,
+    last_transmit: Instant,
+}
+
+impl Session {
+    pub fn connect(
+        server_addr: SocketAddr,
+        vfs: Rc<Vfs>,
+        cvars: &CvarRegistry,
+        cmds: &mut CmdRegistry,
+        host_state: Rc<Cell<HostState>>,
+    ) -> Result<Session, ClientError> {
+        let name = cvars.get("_cl_name").map_err(ClientError::Cvar)?;
+        let color = cvars.get_value("_cl_color").map_err(ClientError::Cvar)? as u8;
+        let userinfo = qw::info_string(&[
+            ("name", &name),
+            ("topcolor", &(color >> 4).to_string()),
+            ("bottomcolor", &(color & 0xF).to_string()),
+            ("rate", "2500"),
+        ]);
+
+        println!("Connecting to QuakeWorld server {}...", server_addr);
+        let conn = Connection::connect(server_addr, &userinfo)?;
+
+        let outgoing = Rc::new(RefCell::new(vec![String::from("new")]));
+        let server_count = Rc::new(Cell::new(0));
+
+        // the server uses `cmd` to make the client send commands back to it
+        let cmd_outgoing = outgoing.clone();
+        cmds.insert_or_replace(
+            "cmd",
+            "cmd (command): send a command to the server",
+            Box::new(move |args| {
+                if args.is_empty() {
+                    println!("cmd (command): send a command to the server");
+                    return;
+                }
+
+                cmd_outgoing.borrow_mut().push(args.join(" "));
+            }),
+        );
+
+        // sent by the server once the client has spawned. the original client loads player
+        // skins before entering the game.
+        let skins_outgoing = outgoing.clone();
+        let skins_server_count = server_count.clone();
+        let skins_host_state = host_state.clone();
+        cmds.insert_or_replace(
+            "skins",
+            "load player skins and enter the game",
+            Box::new(move |_| {
+                skins_outgoing
+                    .borrow_mut()
+                    .push(format!("begin {}", skins_server_count.get()));
+                host::transition_shared(&skins_host_state, HostEvent::SignOn(SignOnStage::Begin));
+            }),
+        );
+
+        // sent by the server when it changes levels
+        let reconnect_outgoing = outgoing.clone();
+        let reconnect_host_state = host_state.clone();
+        cmds.insert_or_replace(
+            "reconnect",
+            "reload the current level",
+            Box::new(move |_| {
+                reconnect_outgoing.borrow_mut().push(String::from("new"));
+                host::transition_shared(&reconnect_host_state, HostEvent::Reconnect);
+            }),
+        );
+
+        Ok(Session {
+            vfs,
+            conn,
+            outgoing,
+            server_count,
+            host_state,
+            player_id: 0,
+            level_name: String::new(),
+            model_precache: Vec::new(),
+            sound_precache: Vec::new(),
+            player_model_id: None,
+            stats: [0; MAX_STATS],
+            frames: PacketEntityFrames::new(),
+            cmds: [UserCmd::null(); 3],
+            pending_cmd: None,
+            last_transmit: Instant::now(),
+        })
+    }
+
+    /// Queues a NetQuake client message to be sent reliably.
+    ///
+    /// NetQuake and QuakeWorld share the code for string commands, which are the only commands
+    /// the client queues this way.
+    pub fn queue_reliable(&mut self, msg: &[u8]) {
+        self.conn.queue_reliable(msg);
+    }
+
+    /// Sets the movement command to be sent with the next packet.
+    pub fn set_move(&mut self, mut cmd: UserCmd) {
+        // don't drop an impulse if the packet carrying it hasn't been sent yet
+        if let Some(pending) = self.pending_cmd {
+            if cmd.impulse == 0 {
+                cmd.impulse = pending.impulse;
+            }
+        }
+
+        self.pending_cmd = Some(cmd);
+    }
+
+    /// Receives all waiting packets and translates their contents into a NetQuake server message.
+    ///
+    /// `time` is the current client time, which is used to timestamp entity updates since
+    /// QuakeWorld servers don't send their own time.
+    pub fn recv(&mut self, time: Duration) -> Result<Vec<u8>, ClientError> {
+        let mut msg = Vec::new();
+
+        while let Some(packet) = self.conn.recv()? {
+            let sequence = self.conn.incoming_sequence();
+            let mut reader = BufReader::new(packet.as_slice());
+            let mut time_sent = false;
+
+            while let Some(cmd) = qw::ServerCmd::deserialize(&mut reader)? {
+                // entity updates must be preceded by a time update
+                let is_frame = match cmd {
+                    qw::ServerCmd::PlayerInfo(_) | qw::ServerCmd::PacketEntities { .. } => true,
+                    _ => false,
+                };
+                if is_frame && !time_sent {
+                    ServerCmd::Time {
+                        time: engine::duration_to_f32(time),
+                    }
+                    .serialize(&mut msg)?;
+                    time_sent = true;
+                }
+
+                self.translate(sequence, cmd, &mut msg)?;
+            }
+        }
+
+        Ok(msg)
+    }
+
+    fn translate(
+        &mut self,
+        sequence: u32,
+        cmd: qw::ServerCmd,
+        msg: &mut Vec<u8>,
+    ) -> Result<(), ClientError> {
+        match cmd {
+            qw::ServerCmd::Common(cmd) => {
+                match cmd {
+                    ServerCmd::SpawnBaseline {
+                        ent_id,
+                        model_id,
+                        frame_id,
+                        colormap,
+                        skin_id,
+                        origin,
+                        angles,
+                    } => {
+                        self.frames.set_baseline(qw::EntityState {
+                            number: ent_id,
+                            model_id,
+                            frame_id,
+                            colormap,
+                            skin_id,
+                            effects: 0,
+                            origin,
+                            angles,
+                        })?;
+                    }
+
+                    ServerCmd::UpdateFrags { player_id, .. }
+                        if player_id as usize >= net::MAX_CLIENTS =>
+                    {
+                        return Ok(())
+                    }
+
+                    _ => (),
+                }
+
+                cmd.serialize(msg)?;
+            }
+
+            qw::ServerCmd::UpdateStat { stat, value } => {
+                if let Some(s) = self.stats.get_mut(stat as usize) {
+                    *s = value;
+                }
+
+                if let Some(stat) = ClientStat::from_u8(stat) {
+                    ServerCmd::UpdateStat { stat, value }.serialize(msg)?;
+                }
+            }
+
+            qw::ServerCmd::Sound {
+                volume,
+                attenuation,
+                entity_id,
+                channel,
+                sound_id,
+                position,
+            } => ServerCmd::Sound {
+                volume,
+                attenuation,
+                entity_id,
+                channel: channel as i8,
+                sound_id,
+                position,
+            }
+            .serialize(msg)?,
+
+            qw::ServerCmd::Print { text, .. } => ServerCmd::Print { text }.serialize(msg)?,
+
+            qw::ServerCmd::ServerData(data) => {
+                debug!("Server data: {:?}", data);
+
+                self.server_count.set(data.server_count);
+                self.player_id = data.player_id;
+                self.level_name = data.level_name;
+                self.model_precache.clear();
+                self.sound_precache.clear();
+                self.player_model_id = None;
+                self.stats = [0; MAX_STATS];
+                self.frames = PacketEntityFrames::new();
+                host::transition_shared(&self.host_state, HostEvent::Reconnect);
+
+                self.send_string(format!("soundlist {} 0", data.server_count));
+            }
+
+            qw::ServerCmd::SoundList { names, next, .. } => {
+                self.sound_precache.extend(names);
+                match next {
+                    0 => self.send_string(format!("modellist {} 0", self.server_count.get())),
+                    n => self.send_string(format!("soundlist {} {}", self.server_count.get(), n)),
+                }
+            }
+
+            qw::ServerCmd::ModelList { names, next, .. } => {
+                self.model_precache.extend(names);
+                match next {
+                    0 => self.prespawn(msg)?,
+                    n => self.send_string(format!("modellist {} {}", self.server_count.get(), n)),
+                }
+            }
+
+            qw::ServerCmd::TempEntity { temp_entity } => {
+                ServerCmd::TempEntity { temp_entity }.serialize(msg)?
+            }
+
+            // TODO: move the camera to the intermission spot
+            qw::ServerCmd::Intermission { angles, .. } => {
+                ServerCmd::SetAngle { angles }.serialize(msg)?;
+                ServerCmd::Intermission.serialize(msg)?;
+            }
+
+            qw::ServerCmd::CdTrack { track } => ServerCmd::CdTrack {
+                track,
+                loop_: track,
+            }
+            .serialize(msg)?,
+
+            qw::ServerCmd::UpdateUserInfo {
+                player_id, info, ..
+            } => {
+                if player_id as usize >= net::MAX_CLIENTS {
+                    return Ok(());
+                }
+
+                if let Some(name) = qw::info_value(&info, "name") {
+                    ServerCmd::UpdateName {
+                        player_id,
+                        new_name: name.to_owned(),
+                    }
+                    .serialize(msg)?;
+                }
+
+                let color = |key| -> u8 {
+                    qw::info_value(&info, key)
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(0)
+                };
+                ServerCmd::UpdateColors {
+                    player_id,
+                    new_colors: PlayerColor::new(color("topcolor"), color("bottomcolor")),
+                }
+                .serialize(msg)?;
+            }
+
+            qw::ServerCmd::SetInfo {
+                player_id,
+                key,
+                value,
+            } => {
+                if key == "name" && (player_id as usize) < net::MAX_CLIENTS {
+                    ServerCmd::UpdateName {
+                        player_id,
+                        new_name: value,
+                    }
+                    .serialize(msg)?;
+                }
+            }
+
+            qw::ServerCmd::PlayerInfo(info) => self.translate_player_info(info, msg)?,
+
+            qw::ServerCmd::PacketEntities { delta_from, deltas } => {
+                let entities = match self.frames.apply(sequence, delta_from, &deltas) {
+                    Ok(e) => e,
+                    Err(e) => {
+                        // the next update will be relative to the baselines
+                        warn!("Dropped packet entities: {}", e);
+                        return Ok(());
+                    }
+                };
+
+                for ent in entities {
+                    ServerCmd::FastUpdate(EntityUpdate {
+                        ent_id: ent.number,
+                        model_id: Some(ent.model_id),
+                        frame_id: Some(ent.frame_id),
+                        colormap: Some(ent.colormap),
+                        skin_id: Some(ent.skin_id),
+                        effects: Some(EntityEffects::from_bits_truncate(ent.effects)),
+                        origin_x: Some(ent.origin.x),
+                        pitch: Some(ent.angles.x),
+                        origin_y: Some(ent.origin.y),
+                        yaw: Some(ent.angles.y),
+                        origin_z: Some(ent.origin.z),
+                        roll: Some(ent.angles.z),
+                        no_lerp: false,
+                    })
+                    .serialize(msg)?;
+                }
+            }
+
+            // TODO: file downloads. received files must go through the store from Vfs::download_store
+            qw::ServerCmd::Download { .. } => warn!("Downloads not yet implemented"),
+
+            cmd => debug!("Ignoring {:?}", cmd),
+        }
+
+        Ok(())
+    }
+
+    // called once the model list is complete
+    fn prespawn(&mut self, msg: &mut Vec<u8>) -> Result<(), ClientError> {
+        let map_name = match self.model_precache.first() {
+            Some(m) => m.to_owned(),
+            None => return Err(ClientError::InvalidConnectResponse),
+        };
+
+        // the server checks that we have the same map it does
+        let checksum = qw::map_checksum2(&mut self.vfs.open(&map_name)?)?;
+
+        self.player_model_id = self
+            .model_precache
+            .iter()
+            .position(|m| m == "progs/player.mdl")
+            .map(|i| i as u8 + 1);
+
+        ServerCmd::ServerInfo {
+            protocol_version: net::PROTOCOL_VERSION as i32,
+            max_clients: net::MAX_CLIENTS as u8,
+            game_type: GameType::Deathmatch,
+            message: self.level_name.clone(),
+            model_precache: self.model_precache.clone(),
+            sound_precache: self.sound_precache.clone(),
+        }
+        .serialize(msg)?;
+
+        ServerCmd::SetView {
+            ent_id: self.player_id as i16 + 1,
+        }
+        .serialize(msg)?;
+
+        host::transition_shared(&self.host_state, HostEvent::SignOn(SignOnStage::Prespawn));
+        self.send_string(format!(
+            "prespawn {} 0 {}",
+            self.server_count.get(),
+            checksum as i32
+        ));
+
+        Ok(())
+    }
+
+    fn translate_player_info(
+        &mut self,
+        info: PlayerInfo,
+        msg: &mut Vec<u8>,
+    ) -> Result<(), ClientError> {
+        if info.player_id as usize >= net::MAX_CLIENTS {
+            return Ok(());
+        }
+
+        // other players face the direction of their last command, with the pitch reduced so the
+        // model doesn't lean over too far
+        let angles = info.command.map(|c| c.angles);
+        ServerCmd::FastUpdate(EntityUpdate {
+            ent_id: info.player_id as u16 + 1,
+            model_id: info.model_id.or(self.player_model_id),
+            frame_id: Some(info.frame_id),
+            colormap: Some(info.player_id + 1),
+            skin_id: Some(info.skin_id),
+            effects: Some(EntityEffects::from_bits_truncate(info.effects)),
+            origin_x: Some(info.origin.x),
+            pitch: angles.map(|a| -a.x / 3.0),
+            origin_y: Some(info.origin.y),
+            yaw: angles.map(|a| a.y),
+            origin_z: Some(info.origin.z),
+            roll: Some(Deg(0.0)),
+            no_lerp: false,
+        })
+        .serialize(msg)?;
+
+        // QuakeWorld sends the local player's state as stats, so build a client update from them
+        if info.player_id == self.player_id {
+            let stat = |s: ClientStat| self.stats[s as usize];
+            ServerCmd::ClientData {
+                view_height: None,
+                ideal_pitch: None,
+                punch_pitch: None,
+                velocity_x: Some(info.velocity.x),
+                punch_yaw: None,
+                velocity_y: Some(info.velocity.y),
+                punch_roll: None,
+                velocity_z: Some(info.velocity.z),
+                items: ItemFlags::from_bits_truncate(self.stats[STAT_ITEMS] as u32),
+                on_ground: false,
+                in_water: false,
+                weapon_frame: Some(info.weapon_frame),
+                armor: Some(stat(ClientStat::Armor) as u8),
+                weapon: Some(stat(ClientStat::Weapon) as u8),
+                health: stat(ClientStat::Health) as i16,
+                ammo: stat(ClientStat::Ammo) as u8,
+                ammo_shells: stat(ClientStat::Shells) as u8,
+                ammo_nails: stat(ClientStat::Nails) as u8,
+                ammo_rockets: stat(ClientStat::Rockets) as u8,
+                ammo_cells: stat(ClientStat::Cells) as u8,
+                active_weapon: stat(ClientStat::ActiveWeapon) as u8,
+            }
+            .serialize(msg)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_string(&mut self, cmd: String) {
+        self.outgoing.borrow_mut().push(cmd);
+    }
+
+    /// Sends the latest movement command along with any queued commands.
+    pub fn transmit(&mut self) -> Result<(), ClientError> {
+        let now = Instant::now();
+        let elapsed = now - self.last_transmit;
+        if elapsed < MIN_TRANSMIT_INTERVAL {
+            return Ok(());
+        }
+        self.last_transmit = now;
+
+        let mut cmd = match self.pending_cmd.take() {
+            Some(c) => c,
+
+            // keep facing the same way if there was no input
+            None => UserCmd {
+                angles: self.cmds[2].angles,
+                ..UserCmd::null()
+            },
+        };
+        cmd.msec = elapsed.as_millis().min(MAX_CMD_MSEC) as u8;
+        self.cmds = [self.cmds[1], self.cmds[2], cmd];
+
+        for text in self.outgoing.borrow_mut().drain(..) {
+            let mut reliable = Vec::new();
+            qw::ClientCmd::StringCmd { cmd: text }.serialize(&mut reliable)?;
+            self.conn.queue_reliable(&reliable);
+        }
+
+        let mut msg = Vec::new();
+
+        // ask for entity updates relative to the last complete frame, as long as the server still
+        // has it. this goes first so the server sees it even if it rejects the move.
+        if self.host_state.get().in_game() {
+            if let Some(seq) = self.frames.valid_sequence() {
+                if self.conn.outgoing_sequence() - seq < qw::UPDATE_BACKUP as u32 - 1 {
+                    qw::ClientCmd::Delta {
+                        sequence: seq as u8,
+                    }
+                    .serialize(&mut msg)?;
+                }
+            }
+        }
+
+        // TODO: the checksum is a CRC of the move and the packet sequence number. servers that
+        // verify it will ignore these moves.
+        qw::ClientCmd::Move {
+            checksum: 0,
+            loss: 0,
+            cmds: self.cmds,
+        }
+        .serialize(&mut msg)?;
+
+        self.conn.send(&msg)?;
+
+        Ok(())
+    }
+}
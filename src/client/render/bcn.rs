@@ -0,0 +1,372 @@
+//! Block compression of textures.
+//!
+//! With `r_texturecompression` set, world and model textures are compressed to BC1 (opaque) or
+//! BC3 (with alpha) when they're loaded, using a quarter or half of the memory of uncompressed
+//! RGBA. Compression is slow enough to be noticeable with large replacement textures, so the
+//! compressed mipmap chain is cached on disk under `cache/textures/`, named by a hash of the
+//! uncompressed texture.
+//!
+//! The encoder picks the endpoints of each block from the bounding box of its colors. This is
+//! much faster than a principal axis fit and good enough for Quake's low-contrast textures.
+
+use std::{
+    fs,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+// width and height of a block in texels
+const BLOCK_DIM: u32 = 4;
+
+/// A block-compressed texture format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BcFormat {
+    /// Opaque color, 8 bytes per block.
+    Bc1,
+
+    /// Color with smooth alpha, 16 bytes per block.
+    Bc3,
+}
+
+impl BcFormat {
+    /// Returns the format suited to `rgba`: BC3 if any texel isn't fully opaque, BC1 otherwise.
+    pub fn for_rgba(rgba: &[u8]) -> BcFormat {
+        if rgba.chunks_exact(4).any(|t| t[3] != 0xFF) {
+            BcFormat::Bc3
+        } else {
+            BcFormat::Bc1
+        }
+    }
+
+    /// Returns the size in bytes of one block.
+    pub fn block_size(self) -> u32 {
+        match self {
+            BcFormat::Bc1 => 8,
+            BcFormat::Bc3 => 16,
+        }
+    }
+
+    /// Returns the wgpu format for sRGB color data in this format.
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            BcFormat::Bc1 => wgpu::TextureFormat::Bc1RgbaUnormSrgb,
+            BcFormat::Bc3 => wgpu::TextureFormat::Bc3RgbaUnormSrgb,
+        }
+    }
+
+    /// Returns the size in bytes of a compressed image of the given dimensions.
+    pub fn image_size(self, width: u32, height: u32) -> usize {
+        let blocks = blocks(width) * blocks(height);
+        (blocks * self.block_size()) as usize
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            BcFormat::Bc1 => "bc1",
+            BcFormat::Bc3 => "bc3",
+        }
+    }
+}
+
+fn blocks(texels: u32) -> u32 {
+    (texels + BLOCK_DIM - 1) / BLOCK_DIM
+}
+
+/// Returns the number of mipmap levels of a `width` by `height` texture that can be compressed.
+///
+/// Every level must be made of whole blocks, so the chain stops before either dimension is no
+/// longer a multiple of 4. Returns 0 if the texture can't be compressed at all.
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    let (mut width, mut height) = (width, height);
+    let mut count = 0;
+    while width >= BLOCK_DIM
+        && height >= BLOCK_DIM
+        && width % BLOCK_DIM == 0
+        && height % BLOCK_DIM == 0
+    {
+        count += 1;
+        width /= 2;
+        height /= 2;
+    }
+
+    count
+}
+
+/// Compresses a `width` by `height` RGBA image.
+///
+/// Both dimensions must be multiples of 4.
+pub fn compress(rgba: &[u8], width: u32, height: u32, format: BcFormat) -> Vec<u8> {
+    assert!(width % BLOCK_DIM == 0 && height % BLOCK_DIM == 0);
+    assert_eq!(rgba.len(), (width * height * 4) as usize);
+
+    let mut out = Vec::with_capacity(format.image_size(width, height));
+    let mut block = [[0u8; 4]; 16];
+    for block_y in 0..height / BLOCK_DIM {
+        for block_x in 0..width / BLOCK_DIM {
+            for (i, texel) in block.iter_mut().enumerate() {
+                let x = block_x * BLOCK_DIM + i as u32 % BLOCK_DIM;
+                let y = block_y * BLOCK_DIM + i as u32 / BLOCK_DIM;
+                let ofs = ((y * width + x) * 4) as usize;
+                texel.copy_from_slice(&rgba[ofs..ofs + 4]);
+            }
+
+            if format == BcFormat::Bc3 {
+                encode_alpha_block(&block, &mut out);
+            }
+            encode_color_block(&block, &mut out);
+        }
+    }
+
+    out
+}
+
+fn to_565(c: [u8; 3]) -> u16 {
+    let r = (c[0] as u16 * 31 + 127) / 255;
+    let g = (c[1] as u16 * 63 + 127) / 255;
+    let b = (c[2] as u16 * 31 + 127) / 255;
+    r << 11 | g << 5 | b
+}
+
+fn from_565(c: u16) -> [i32; 3] {
+    let r = (c >> 11) as i32 & 0x1F;
+    let g = (c >> 5) as i32 & 0x3F;
+    let b = c as i32 & 0x1F;
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
+
+// encodes a four-color block, which both BC1 and BC3 can decode
+fn encode_color_block(block: &[[u8; 4]; 16], out: &mut Vec<u8>) {
+    let mut min = [0xFFu8; 3];
+    let mut max = [0u8; 3];
+    for texel in block.iter() {
+        for c in 0..3 {
+            min[c] = min[c].min(texel[c]);
+            max[c] = max[c].max(texel[c]);
+        }
+    }
+
+    // the bounding box has four diagonals. use the one along which the colors vary by flipping
+    // the channels that fall as the widest channel rises.
+    let widest = (0..3).max_by_key(|c| max[*c] - min[*c]).unwrap();
+    let mean = |c: usize| block.iter().map(|t| t[c] as i32).sum::<i32>() / 16;
+    let widest_mean = mean(widest);
+    for c in 0..3 {
+        let c_mean = mean(c);
+        let covariance: i32 = block
+            .iter()
+            .map(|t| (t[c] as i32 - c_mean) * (t[widest] as i32 - widest_mean))
+            .sum();
+        if covariance < 0 {
+            std::mem::swap(&mut min[c], &mut max[c]);
+        }
+    }
+
+    let mut c0 = to_565(max);
+    let mut c1 = to_565(min);
+
+    // BC1 decodes blocks with c0 <= c1 as three colors and transparent black
+    if c0 < c1 {
+        std::mem::swap(&mut c0, &mut c1);
+    }
+
+    let mut indices = 0u32;
+    if c0 != c1 {
+        let p0 = from_565(c0);
+        let p1 = from_565(c1);
+        let mut palette = [p0, p1, [0; 3], [0; 3]];
+        for c in 0..3 {
+            palette[2][c] = (2 * p0[c] + p1[c]) / 3;
+            palette[3][c] = (p0[c] + 2 * p1[c]) / 3;
+        }
+
+        for (i, texel) in block.iter().enumerate() {
+            let index = nearest(&palette, |p| {
+                (0..3).map(|c| (p[c] - texel[c] as i32).pow(2)).sum::<i32>()
+            });
+            indices |= (index as u32) << (2 * i);
+        }
+    }
+
+    out.extend_from_slice(&c0.to_le_bytes());
+    out.extend_from_slice(&c1.to_le_bytes());
+    out.extend_from_slice(&indices.to_le_bytes());
+}
+
+fn encode_alpha_block(block: &[[u8; 4]; 16], out: &mut Vec<u8>) {
+    let a0 = block.iter().map(|t| t[3]).max().unwrap();
+    let a1 = block.iter().map(|t| t[3]).min().unwrap();
+
+    let mut indices = 0u64;
+    if a0 != a1 {
+        // a0 > a1 selects eight interpolated values, including both endpoints
+        let mut palette = [0i32; 8];
+        palette[0] = a0 as i32;
+        palette[1] = a1 as i32;
+        for i in 1..7 {
+            palette[i + 1] = ((7 - i as i32) * a0 as i32 + i as i32 * a1 as i32) / 7;
+        }
+
+        for (i, texel) in block.iter().enumerate() {
+            let index = nearest(&palette, |p| (p - texel[3] as i32).abs());
+            indices |= (index as u64) << (3 * i);
+        }
+    }
+
+    out.push(a0);
+    out.push(a1);
+    out.extend_from_slice(&indices.to_le_bytes()[..6]);
+}
+
+// returns the index of the palette entry with the smallest error
+fn nearest<T, F>(palette: &[T], error: F) -> usize
+where
+    F: Fn(&T) -> i32,
+{
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, p)| error(p))
+        .map(|(i, _)| i)
+        .unwrap()
+}
+
+/// Returns a hash identifying an uncompressed image, stable between runs and platforms.
+pub fn content_hash(rgba: &[u8], width: u32, height: u32) -> u64 {
+    // 64-bit FNV-1a
+    const OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01B3;
+
+    width
+        .to_le_bytes()
+        .iter()
+        .chain(height.to_le_bytes().iter())
+        .chain(rgba.iter())
+        .fold(OFFSET_BASIS, |hash, b| {
+            (hash ^ *b as u64).wrapping_mul(PRIME)
+        })
+}
+
+/// A directory of compressed mipmap chains.
+pub struct BcCache {
+    dir: PathBuf,
+}
+
+impl BcCache {
+    pub fn new<P>(dir: P) -> BcCache
+    where
+        P: AsRef<Path>,
+    {
+        BcCache {
+            dir: dir.as_ref().to_owned(),
+        }
+    }
+
+    fn path(&self, hash: u64, format: BcFormat) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.{}", hash, format.extension()))
+    }
+
+    /// Returns the cached levels for an image, if they exist and have the expected size.
+    pub fn load(&self, hash: u64, format: BcFormat, expected_len: usize) -> Option<Vec<u8>> {
+        let data = fs::read(self.path(hash, format)).ok()?;
+        if data.len() == expected_len {
+            Some(data)
+        } else {
+            None
+        }
+    }
+
+    /// Stores the levels for an image.
+    pub fn store(&self, hash: u64, format: BcFormat, data: &[u8]) -> Result<(), io::Error> {
+        fs::create_dir_all(&self.dir)?;
+        let mut file = fs::File::create(self.path(hash, format))?;
+        file.write_all(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // decodes one texel of a four-color block, for checking the encoder
+    fn decode_color(block: &[u8], i: usize) -> [i32; 3] {
+        let c0 = u16::from_le_bytes([block[0], block[1]]);
+        let c1 = u16::from_le_bytes([block[2], block[3]]);
+        let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+        let (p0, p1) = (from_565(c0), from_565(c1));
+        let mut color = [0; 3];
+        for c in 0..3 {
+            color[c] = match (indices >> (2 * i)) & 3 {
+                0 => p0[c],
+                1 => p1[c],
+                2 => (2 * p0[c] + p1[c]) / 3,
+                _ => (p0[c] + 2 * p1[c]) / 3,
+            };
+        }
+        color
+    }
+
+    #[test]
+    fn test_compress_bc1() {
+        // left half red, right half blue
+        let mut rgba = Vec::new();
+        for _ in 0..4 {
+            for x in 0..4 {
+                rgba.extend_from_slice(if x < 2 {
+                    &[255, 0, 0, 255]
+                } else {
+                    &[0, 0, 255, 255]
+                });
+            }
+        }
+
+        assert_eq!(BcFormat::for_rgba(&rgba), BcFormat::Bc1);
+        let block = compress(&rgba, 4, 4, BcFormat::Bc1);
+        assert_eq!(block.len(), 8);
+
+        // four-color mode
+        assert!(
+            u16::from_le_bytes([block[0], block[1]]) > u16::from_le_bytes([block[2], block[3]])
+        );
+        assert_eq!(decode_color(&block, 0), [255, 0, 0]);
+        assert_eq!(decode_color(&block, 3), [0, 0, 255]);
+    }
+
+    #[test]
+    fn test_compress_bc3_alpha() {
+        let mut rgba = vec![128; 4 * 4 * 4];
+        // a masked texel
+        rgba[3] = 0;
+        rgba[7] = 255;
+
+        assert_eq!(BcFormat::for_rgba(&rgba), BcFormat::Bc3);
+        let block = compress(&rgba, 4, 4, BcFormat::Bc3);
+        assert_eq!(block.len(), 16);
+        assert_eq!(&block[..2], &[255, 0]);
+
+        let indices = u64::from_le_bytes([
+            block[2], block[3], block[4], block[5], block[6], block[7], 0, 0,
+        ]);
+        // texel 0 is the transparent endpoint, texel 1 the opaque one
+        assert_eq!(indices & 7, 1);
+        assert_eq!((indices >> 3) & 7, 0);
+    }
+
+    #[test]
+    fn test_mip_level_count() {
+        assert_eq!(mip_level_count(64, 64), 5);
+        assert_eq!(mip_level_count(64, 16), 3);
+        assert_eq!(mip_level_count(24, 8), 2);
+        assert_eq!(mip_level_count(20, 8), 1);
+        assert_eq!(mip_level_count(2, 2), 0);
+        assert_eq!(mip_level_count(30, 64), 0);
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let a = content_hash(&[1, 2, 3, 4], 1, 1);
+        assert_eq!(a, content_hash(&[1, 2, 3, 4], 1, 1));
+        assert_ne!(a, content_hash(&[1, 2, 3, 5], 1, 1));
+        assert_ne!(a, content_hash(&[1, 2, 3, 4], 2, 1));
+    }
+}
@@ -53,20 +53,7 @@ impl BlitPipeline {
             ..Default::default()
         });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("blit bind group"),
-            layout: &bind_group_layouts[0],
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(input),
-                },
-            ],
-        });
+        let bind_group = create_bind_group(device, &bind_group_layouts[0], &sampler, input);
 
         BlitPipeline {
             pipeline,
@@ -82,6 +69,12 @@ impl BlitPipeline {
         self.pipeline = pipeline;
     }
 
+    /// Point the blit at a new input texture, e.g. after the final pass target is recreated.
+    pub fn set_input(&mut self, device: &wgpu::Device, input: &wgpu::TextureView) {
+        self.bind_group =
+            create_bind_group(device, &self.bind_group_layouts[0], &self.sampler, input);
+    }
+
     pub fn pipeline(&self) -> &wgpu::RenderPipeline {
         &self.pipeline
     }
@@ -102,6 +95,28 @@ impl BlitPipeline {
     }
 }
 
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    input: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("blit bind group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(input),
+            },
+        ],
+    })
+}
+
 impl Pipeline for BlitPipeline {
     type VertexPushConstants = ();
     type SharedPushConstants = ();
@@ -21,6 +21,182 @@
 use crate::common::console::CvarRegistry;
 
 pub fn register_cvars(cvars: &CvarRegistry) {
-    cvars.register("r_lightmap", "0").unwrap();
-    cvars.register("r_msaa_samples", "4").unwrap();
+    cvars
+        .register_archive("r_bloom", "0", "if nonzero, make bright areas glow")
+        .unwrap();
+    cvars
+        .register_archive("r_bloom_intensity", "0.3", "strength of the bloom effect")
+        .unwrap();
+    cvars
+        .register_archive(
+            "r_coloredlight",
+            "1",
+            "if nonzero, use colored lightmaps from .lit files",
+        )
+        .unwrap();
+    cvars
+        .register_archive(
+            "r_externaltextures",
+            "1",
+            "if nonzero, load replacement textures from textures/ when available",
+        )
+        .unwrap();
+    cvars
+        .register(
+            "r_flatcolor",
+            "0",
+            "draw each world surface in a flat random color",
+        )
+        .unwrap();
+    cvars
+        .register(
+            "r_fullbright",
+            "0",
+            "draw world surfaces without their lightmaps",
+        )
+        .unwrap();
+    cvars
+        .register_archive("r_lerpmodels", "1", "blend alias model animation frames")
+        .unwrap();
+    cvars
+        .register(
+            "r_lightmap",
+            "0",
+            "draw world surfaces with their lightmaps only",
+        )
+        .unwrap();
+    cvars
+        .register_archive(
+            "r_lightmodels",
+            "1",
+            "light alias models from the world lightmap beneath them",
+        )
+        .unwrap();
+    cvars
+        .register(
+            "r_msaa_samples",
+            "4",
+            "multisample antialiasing samples, 2 or 4",
+        )
+        .unwrap();
+    cvars
+        .register_archive(
+            "r_particlestyle",
+            "0",
+            "0 = round sprites, 1 = squares sized like the software renderer",
+        )
+        .unwrap();
+    cvars
+        .register_archive(
+            "r_scale",
+            "1",
+            "fraction of the window resolution the scene is rendered at",
+        )
+        .unwrap();
+    cvars
+        .register("r_showbboxes", "0", "draw the bounding box of each entity")
+        .unwrap();
+    cvars
+        .register(
+            "r_showhull",
+            "0",
+            "draw a collision hull: 1 = point, 2 = player, 3 = large monster",
+        )
+        .unwrap();
+    cvars
+        .register("r_showtraces", "0", "number of recent traces to draw")
+        .unwrap();
+    cvars
+        .register_archive(
+            "r_shadows",
+            "1",
+            "if nonzero, render shadow maps for dynamic lights",
+        )
+        .unwrap();
+    cvars
+        .register_archive(
+            "r_blobshadows",
+            "0",
+            "if nonzero, draw blob shadows on the floor beneath entities",
+        )
+        .unwrap();
+    cvars
+        .register_archive(
+            "r_movershadows",
+            "0",
+            "if nonzero, darken the world beneath doors, lifts and other moving brush entities",
+        )
+        .unwrap();
+    cvars
+        .register_archive("r_shadow_size", "512", "resolution of shadow maps")
+        .unwrap();
+    cvars
+        .register_archive(
+            "r_skyfog",
+            "0.5",
+            "how strongly fog covers the sky, from 0 (clear) to 1 (fully fogged)",
+        )
+        .unwrap();
+    cvars
+        .register_archive(
+            "r_texturecompression",
+            "0",
+            "if nonzero, compress world and model textures as they load to save video memory",
+        )
+        .unwrap();
+    cvars
+        .register_archive("r_tonemap", "0", "0 = clamp, 1 = Reinhard, 2 = ACES")
+        .unwrap();
+    cvars
+        .register_archive(
+            "r_viewmodelfov",
+            "90",
+            "horizontal field of view of the view weapon in degrees, or 0 to use fov",
+        )
+        .unwrap();
+    cvars
+        .register_archive("r_wateralpha", "1", "opacity of liquid surfaces")
+        .unwrap();
+    cvars
+        .register_archive(
+            "r_waterreflect",
+            "0",
+            "how strongly translucent liquids reflect the sky, from 0 to 1",
+        )
+        .unwrap();
+    cvars
+        .register_archive(
+            "r_caustics",
+            "1",
+            "brightness of the rippling light on surfaces under water, 0 to disable",
+        )
+        .unwrap();
+    cvars
+        .register_archive(
+            "r_waterwarp",
+            "1",
+            "strength of the view distortion when the camera is submerged",
+        )
+        .unwrap();
+    cvars
+        .register_archive(
+            "gl_subdivide_size",
+            "32",
+            "grid size for tessellating liquid and sky surfaces, applied when a map is loaded",
+        )
+        .unwrap();
+    cvars
+        .register_archive(
+            "gl_texturemode",
+            "GL_NEAREST_MIPMAP_LINEAR",
+            "texture filtering mode, named like the OpenGL filter",
+        )
+        .unwrap();
+    cvars
+        .register_archive(
+            "r_anisotropy",
+            "1",
+            "level of anisotropic filtering, up to 16",
+        )
+        .unwrap();
 }
@@ -28,14 +28,25 @@
 ///     - `AliasPipeline`
 ///     - `BrushPipeline`
 ///     - `SpritePipeline`
+///     - `BlobShadowPipeline`
 ///   - Output: `InitialPassTarget`
 /// - Deferred lighting pass
 ///   - Inputs:
 ///     - `DeferredPipeline`
 ///   - Output: `DeferredPassTarget`
+/// - Translucent liquid pass (only if `r_wateralpha` is less than 1)
+///   - Inputs:
+///     - `WaterPipeline`
+///   - Output: `DeferredPassTarget`
+/// - Bloom passes (only if `r_bloom` is set)
+///   - Inputs:
+///     - `BloomBrightPipeline`
+///     - `BloomBlurPipeline`
+///   - Output: `BloomPassTarget`
 /// - Final pass
 ///   - Inputs:
 ///     - `PostProcessPipeline`
+///     - `DebugLinePipeline`
 ///     - `QuadPipeline`
 ///     - `GlyphPipeline`
 ///   - Output: `FinalPassTarget`
@@ -44,11 +55,13 @@
 ///     - `BlitPipeline`
 ///   - Output: `SwapChainTarget`
 // mod atlas;
+mod bcn;
 mod blit;
 mod cvars;
 mod error;
 mod palette;
 mod pipeline;
+mod replacement;
 mod target;
 mod ui;
 mod uniform;
@@ -59,11 +72,23 @@ pub use cvars::register_cvars;
 pub use error::{RenderError, RenderErrorKind};
 pub use palette::Palette;
 pub use pipeline::Pipeline;
-pub use postprocess::PostProcessRenderer;
+pub use postprocess::{PostProcessRenderer, PostProcessUniforms, Tonemap};
 pub use target::{RenderTarget, RenderTargetResolve, SwapChainTarget};
-pub use ui::{hud::HudState, UiOverlay, UiRenderer, UiState};
+pub use ui::{
+    console::{load_console_background, ConsoleBackground, ConsoleSettings},
+    hud::{HudSettings, HudState, HudStyle, StatusBarMode, ViewRect, VIEWSIZE_MAX, VIEWSIZE_MIN},
+    quad::QuadTexture,
+    Transition, UiOverlay, UiRenderer, UiState,
+};
 pub use world::{
+    bake::{self, BakeLights, LightBake},
+    blob::{self, BlobShadowVertex, BlobShadows},
+    bloom::BloomRenderer,
+    debug::{self, DebugLines, DebugVertex},
     deferred::{DeferredRenderer, DeferredUniforms, PointLight},
+    shadow::{
+        ShadowLight, ShadowRenderer, DEFAULT_SHADOW_SIZE, MAX_SHADOW_LIGHTS, SHADOW_FACE_COUNT,
+    },
     Camera, WorldRenderer,
 };
 
@@ -71,26 +96,34 @@ use std::{
     borrow::Cow,
     cell::{Cell, Ref, RefCell, RefMut},
     mem::size_of,
+    num::NonZeroU8,
+    path::Path,
     rc::Rc,
 };
 
 use crate::{
     client::render::{
+        bcn::{BcCache, BcFormat},
         blit::BlitPipeline,
-        target::{DeferredPassTarget, FinalPassTarget, InitialPassTarget},
+        target::{BloomPassTarget, DeferredPassTarget, FinalPassTarget, InitialPassTarget},
         ui::{glyph::GlyphPipeline, quad::QuadPipeline},
         uniform::DynamicUniformBuffer,
         world::{
             alias::AliasPipeline,
-            brush::BrushPipeline,
+            bake::LightBakePipeline,
+            blob::BlobShadowPipeline,
+            bloom::{BloomBlurPipeline, BloomBrightPipeline},
+            brush::{BrushPipeline, WaterPipeline},
+            debug::DebugLinePipeline,
             deferred::DeferredPipeline,
             particle::ParticlePipeline,
             postprocess::{self, PostProcessPipeline},
+            shadow::ShadowPipeline,
             sprite::SpritePipeline,
             EntityUniforms,
         },
     },
-    common::{vfs::Vfs, wad::Wad},
+    common::{self, vfs::Vfs, wad::Wad},
 };
 
 use failure::Error;
@@ -99,10 +132,11 @@ const DEPTH_ATTACHMENT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth3
 pub const DIFFUSE_ATTACHMENT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8UnormSrgb;
 const NORMAL_ATTACHMENT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
 const LIGHT_ATTACHMENT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+const HDR_ATTACHMENT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
 
 const DIFFUSE_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
 const FULLBRIGHT_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
-const LIGHTMAP_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+const LIGHTMAP_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
 
 /// Create a `wgpu::TextureDescriptor` appropriate for the provided texture data.
 pub fn texture_descriptor<'a>(
@@ -141,16 +175,84 @@ pub fn create_texture<'a>(
         height
     );
     let texture = device.create_texture(&texture_descriptor(label, width, height, data.format()));
+    write_texture_level(
+        queue,
+        &texture,
+        0,
+        width,
+        height,
+        data.data(),
+        data.stride(),
+    );
+
+    texture
+}
+
+/// Create a texture with a full mipmap chain generated from the provided texture data.
+pub fn create_texture_mipmapped<'a>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: Option<&'a str>,
+    width: u32,
+    height: u32,
+    data: &TextureData,
+) -> wgpu::Texture {
+    let mip_level_count = full_mip_level_count(width, height);
+    trace!(
+        "Creating mipmapped texture ({:?}: {}x{}, {} levels)",
+        data.format(),
+        width,
+        height,
+        mip_level_count
+    );
+
+    let mut desc = texture_descriptor(label, width, height, data.format());
+    desc.mip_level_count = mip_level_count;
+    let texture = device.create_texture(&desc);
+
+    let stride = data.stride();
+    let mut level_data = Cow::Borrowed(data.data());
+    let (mut level_width, mut level_height) = (width, height);
+    for level in 0..mip_level_count {
+        if level > 0 {
+            level_data = downsample(&level_data, level_width, level_height, stride).into();
+            level_width = (level_width / 2).max(1);
+            level_height = (level_height / 2).max(1);
+        }
+
+        write_texture_level(
+            queue,
+            &texture,
+            level,
+            level_width,
+            level_height,
+            &level_data,
+            stride,
+        );
+    }
+
+    texture
+}
+
+fn write_texture_level(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    mip_level: u32,
+    width: u32,
+    height: u32,
+    data: &[u8],
+    stride: u32,
+) {
     queue.write_texture(
         wgpu::TextureCopyView {
-            texture: &texture,
-            mip_level: 0,
+            texture,
+            mip_level,
             origin: wgpu::Origin3d::ZERO,
         },
-        data.data(),
+        data,
         wgpu::TextureDataLayout {
             offset: 0,
-            bytes_per_row: width * data.stride(),
+            bytes_per_row: width * stride,
             rows_per_image: 0,
         },
         wgpu::Extent3d {
@@ -159,8 +261,149 @@ pub fn create_texture<'a>(
             depth: 1,
         },
     );
+}
 
-    texture
+/// Returns the number of levels in a full mipmap chain for a texture of the given size.
+pub fn full_mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+// halves the size of an image with a box filter.
+// TODO: sRGB textures should be averaged in linear space
+fn downsample(data: &[u8], width: u32, height: u32, stride: u32) -> Vec<u8> {
+    let new_width = (width / 2).max(1);
+    let new_height = (height / 2).max(1);
+    let mut out = Vec::with_capacity((new_width * new_height * stride) as usize);
+
+    for y in 0..new_height {
+        for x in 0..new_width {
+            for c in 0..stride {
+                let mut sum = 0;
+                let mut count = 0;
+
+                // a dimension of 1 can't be halved, so those texels have no neighbor
+                for &(sx, sy) in &[
+                    (2 * x, 2 * y),
+                    (2 * x + 1, 2 * y),
+                    (2 * x, 2 * y + 1),
+                    (2 * x + 1, 2 * y + 1),
+                ] {
+                    if sx < width && sy < height {
+                        sum += data[((sy * width + sx) * stride + c) as usize] as u32;
+                        count += 1;
+                    }
+                }
+
+                out.push((sum / count) as u8);
+            }
+        }
+    }
+
+    out
+}
+
+/// Filtering applied to world textures, named after the OpenGL filter modes accepted by
+/// `gl_texturemode`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextureMode {
+    Nearest,
+    Linear,
+    NearestMipmapNearest,
+    LinearMipmapNearest,
+    NearestMipmapLinear,
+    LinearMipmapLinear,
+}
+
+impl TextureMode {
+    pub fn from_name<S>(name: S) -> Option<TextureMode>
+    where
+        S: AsRef<str>,
+    {
+        Some(match name.as_ref().to_uppercase().as_str() {
+            "GL_NEAREST" => TextureMode::Nearest,
+            "GL_LINEAR" => TextureMode::Linear,
+            "GL_NEAREST_MIPMAP_NEAREST" => TextureMode::NearestMipmapNearest,
+            "GL_LINEAR_MIPMAP_NEAREST" => TextureMode::LinearMipmapNearest,
+            "GL_NEAREST_MIPMAP_LINEAR" => TextureMode::NearestMipmapLinear,
+            "GL_LINEAR_MIPMAP_LINEAR" => TextureMode::LinearMipmapLinear,
+            _ => return None,
+        })
+    }
+
+    // texel filter, mipmap filter, and whether mipmaps are used at all
+    fn filters(&self) -> (wgpu::FilterMode, wgpu::FilterMode, bool) {
+        use wgpu::FilterMode::{Linear, Nearest};
+
+        match *self {
+            TextureMode::Nearest => (Nearest, Nearest, false),
+            TextureMode::Linear => (Linear, Nearest, false),
+            TextureMode::NearestMipmapNearest => (Nearest, Nearest, true),
+            TextureMode::LinearMipmapNearest => (Linear, Nearest, true),
+            TextureMode::NearestMipmapLinear => (Nearest, Linear, true),
+            TextureMode::LinearMipmapLinear => (Linear, Linear, true),
+        }
+    }
+}
+
+impl std::default::Default for TextureMode {
+    fn default() -> TextureMode {
+        TextureMode::NearestMipmapLinear
+    }
+}
+
+fn create_world_sampler(device: &wgpu::Device, mode: TextureMode, anisotropy: u8) -> wgpu::Sampler {
+    let (filter, mipmap_filter, mipmapped) = mode.filters();
+
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("world sampler"),
+        address_mode_u: wgpu::AddressMode::Repeat,
+        address_mode_v: wgpu::AddressMode::Repeat,
+        address_mode_w: wgpu::AddressMode::Repeat,
+        mag_filter: filter,
+        min_filter: filter,
+        mipmap_filter,
+        lod_min_clamp: -1000.0,
+        // only sample the full-size image if mipmapping is disabled
+        lod_max_clamp: if mipmapped { 1000.0 } else { 0.0 },
+        compare: None,
+        anisotropy_clamp: match anisotropy {
+            0 | 1 => None,
+            // the clamp must be a power of two
+            a => NonZeroU8::new(1 << (7 - a.min(16).leading_zeros())),
+        },
+        ..Default::default()
+    })
+}
+
+fn create_per_entity_bind_group(
+    device: &wgpu::Device,
+    layouts: &[wgpu::BindGroupLayout],
+    entity_uniform_buffer: &DynamicUniformBuffer<EntityUniforms>,
+    world_sampler: &wgpu::Sampler,
+    lightmap_sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("brush per-entity bind group"),
+        layout: &layouts[world::BindGroupLayoutId::PerEntity as usize],
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(
+                    entity_uniform_buffer
+                        .buffer()
+                        .slice(..size_of::<EntityUniforms>() as wgpu::BufferAddress),
+                ),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(world_sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(lightmap_sampler),
+            },
+        ],
+    })
 }
 
 pub struct DiffuseData<'a> {
@@ -171,6 +414,7 @@ pub struct FullbrightData<'a> {
     pub fullbright: Cow<'a, [u8]>,
 }
 
+/// Lightmap texels, stored as the colored light in RGB and the monochrome light in alpha.
 pub struct LightmapData<'a> {
     pub lightmap: Cow<'a, [u8]>,
 }
@@ -202,7 +446,7 @@ impl<'a> TextureData<'a> {
         (match self {
             TextureData::Diffuse(_) => size_of::<[u8; 4]>(),
             TextureData::Fullbright(_) => size_of::<u8>(),
-            TextureData::Lightmap(_) => size_of::<u8>(),
+            TextureData::Lightmap(_) => size_of::<[u8; 4]>(),
         }) as u32
     }
 
@@ -217,6 +461,18 @@ pub struct Extent2d {
     pub height: u32,
 }
 
+impl Extent2d {
+    /// Returns this extent scaled by `factor`, rounded to the nearest pixel.
+    ///
+    /// Neither dimension is allowed to drop below one pixel.
+    pub fn scaled(self, factor: f32) -> Extent2d {
+        Extent2d {
+            width: ((self.width as f32 * factor).round() as u32).max(1),
+            height: ((self.height as f32 * factor).round() as u32).max(1),
+        }
+    }
+}
+
 impl std::convert::Into<wgpu::Extent3d> for Extent2d {
     fn into(self) -> wgpu::Extent3d {
         wgpu::Extent3d {
@@ -242,6 +498,18 @@ pub struct GraphicsState {
     deferred_pass_target: DeferredPassTarget,
     final_pass_target: FinalPassTarget,
 
+    // bloom is computed by ping-ponging between these two targets, which only exist while bloom
+    // is enabled
+    bloom_pass_targets: Option<[BloomPassTarget; 2]>,
+
+    // bound as the bloom input of the postprocessing pass while bloom is disabled
+    no_bloom: wgpu::Texture,
+    no_bloom_view: wgpu::TextureView,
+
+    // incremented whenever the render targets are recreated so that renderers holding bind
+    // groups to them know to rebuild
+    target_generation: usize,
+
     world_bind_group_layouts: Vec<wgpu::BindGroupLayout>,
     world_bind_groups: Vec<wgpu::BindGroup>,
 
@@ -250,15 +518,28 @@ pub struct GraphicsState {
     entity_uniform_buffer: RefCell<DynamicUniformBuffer<EntityUniforms>>,
     diffuse_sampler: wgpu::Sampler,
     lightmap_sampler: wgpu::Sampler,
+    shadow_sampler: wgpu::Sampler,
+
+    // world textures are sampled according to gl_texturemode and r_anisotropy
+    world_sampler: wgpu::Sampler,
+    texture_mode: TextureMode,
+    anisotropy: u8,
 
     sample_count: Cell<u32>,
 
     alias_pipeline: AliasPipeline,
     brush_pipeline: BrushPipeline,
+    water_pipeline: WaterPipeline,
     sprite_pipeline: SpritePipeline,
     deferred_pipeline: DeferredPipeline,
     particle_pipeline: ParticlePipeline,
     postprocess_pipeline: PostProcessPipeline,
+    bloom_bright_pipeline: BloomBrightPipeline,
+    bloom_blur_pipeline: BloomBlurPipeline,
+    debug_line_pipeline: DebugLinePipeline,
+    blob_shadow_pipeline: BlobShadowPipeline,
+    shadow_pipeline: ShadowPipeline,
+    light_bake_pipeline: LightBakePipeline,
     glyph_pipeline: GlyphPipeline,
     quad_pipeline: QuadPipeline,
     blit_pipeline: BlitPipeline,
@@ -266,6 +547,12 @@ pub struct GraphicsState {
     default_lightmap: wgpu::Texture,
     default_lightmap_view: wgpu::TextureView,
 
+    // world and model textures are block-compressed while r_texturecompression is set, if the
+    // device supports it
+    texture_compression: Cell<bool>,
+    bc_supported: bool,
+    bc_cache: BcCache,
+
     vfs: Rc<Vfs>,
     palette: Palette,
     gfx_wad: Wad,
@@ -328,6 +615,24 @@ impl GraphicsState {
             ..Default::default()
         });
 
+        let shadow_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -1000.0,
+            lod_max_clamp: 1000.0,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let texture_mode = TextureMode::default();
+        let anisotropy = 1;
+        let world_sampler = create_world_sampler(&device, texture_mode, anisotropy);
+
         let world_bind_group_layouts: Vec<wgpu::BindGroupLayout> =
             world::BIND_GROUP_LAYOUT_DESCRIPTORS
                 .iter()
@@ -342,29 +647,13 @@ impl GraphicsState {
                     resource: wgpu::BindingResource::Buffer(frame_uniform_buffer.slice(..)),
                 }],
             }),
-            device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("brush per-entity bind group"),
-                layout: &world_bind_group_layouts[world::BindGroupLayoutId::PerEntity as usize],
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::Buffer(
-                            entity_uniform_buffer
-                                .borrow()
-                                .buffer()
-                                .slice(..size_of::<EntityUniforms>() as wgpu::BufferAddress),
-                        ),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_sampler),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: wgpu::BindingResource::Sampler(&lightmap_sampler),
-                    },
-                ],
-            }),
+            create_per_entity_bind_group(
+                &device,
+                &world_bind_group_layouts,
+                &entity_uniform_buffer.borrow(),
+                &world_sampler,
+                &lightmap_sampler,
+            ),
         ];
 
         let alias_pipeline = AliasPipeline::new(
@@ -380,6 +669,13 @@ impl GraphicsState {
             &world_bind_group_layouts,
             sample_count,
         );
+        let water_pipeline = WaterPipeline::new(
+            &device,
+            &mut compiler,
+            &world_bind_group_layouts,
+            &brush_pipeline,
+            sample_count,
+        );
         let sprite_pipeline = SpritePipeline::new(
             &device,
             &mut compiler,
@@ -390,6 +686,12 @@ impl GraphicsState {
         let particle_pipeline =
             ParticlePipeline::new(&device, &queue, &mut compiler, sample_count, &palette);
         let postprocess_pipeline = PostProcessPipeline::new(&device, &mut compiler, sample_count);
+        let bloom_bright_pipeline = BloomBrightPipeline::new(&device, &mut compiler);
+        let bloom_blur_pipeline = BloomBlurPipeline::new(&device, &mut compiler);
+        let debug_line_pipeline = DebugLinePipeline::new(&device, &mut compiler, sample_count);
+        let blob_shadow_pipeline = BlobShadowPipeline::new(&device, &mut compiler, sample_count);
+        let shadow_pipeline = ShadowPipeline::new(&device, &mut compiler);
+        let light_bake_pipeline = LightBakePipeline::new(&device, &mut compiler);
         let quad_pipeline = QuadPipeline::new(&device, &mut compiler, sample_count);
         let glyph_pipeline = GlyphPipeline::new(&device, &mut compiler, sample_count);
         let blit_pipeline =
@@ -402,17 +704,33 @@ impl GraphicsState {
             1,
             1,
             &TextureData::Lightmap(LightmapData {
-                lightmap: (&[0xFF][..]).into(),
+                lightmap: (&[0xFF; 4][..]).into(),
             }),
         );
         let default_lightmap_view = default_lightmap.create_default_view();
 
+        let no_bloom = create_texture(
+            &device,
+            &queue,
+            Some("no bloom"),
+            1,
+            1,
+            &TextureData::Diffuse(DiffuseData {
+                rgba: (&[0, 0, 0, 0xFF][..]).into(),
+            }),
+        );
+        let no_bloom_view = no_bloom.create_default_view();
+
         Ok(GraphicsState {
             device,
             queue,
             initial_pass_target,
             deferred_pass_target,
             final_pass_target,
+            bloom_pass_targets: None,
+            no_bloom,
+            no_bloom_view,
+            target_generation: 0,
             frame_uniform_buffer,
             entity_uniform_buffer,
 
@@ -423,18 +741,34 @@ impl GraphicsState {
 
             alias_pipeline,
             brush_pipeline,
+            water_pipeline,
             sprite_pipeline,
             deferred_pipeline,
             particle_pipeline,
             postprocess_pipeline,
+            bloom_bright_pipeline,
+            bloom_blur_pipeline,
+            debug_line_pipeline,
+            blob_shadow_pipeline,
+            shadow_pipeline,
+            light_bake_pipeline,
             glyph_pipeline,
             quad_pipeline,
             blit_pipeline,
 
             diffuse_sampler,
             lightmap_sampler,
+            shadow_sampler,
+            world_sampler,
+            texture_mode,
+            anisotropy,
             default_lightmap,
             default_lightmap_view,
+            texture_compression: Cell::new(false),
+            bc_supported: device
+                .features()
+                .contains(wgpu::Features::TEXTURE_COMPRESSION_BC),
+            bc_cache: BcCache::new(Path::new(common::DEFAULT_BASEDIR).join("cache/textures")),
             vfs,
             palette,
             gfx_wad,
@@ -452,29 +786,227 @@ impl GraphicsState {
         create_texture(&self.device, &self.queue, label, width, height, data)
     }
 
-    /// Update graphics state with the new framebuffer size and sample count.
+    pub fn create_texture_mipmapped<'a>(
+        &self,
+        label: Option<&'a str>,
+        width: u32,
+        height: u32,
+        data: &TextureData,
+    ) -> wgpu::Texture {
+        create_texture_mipmapped(&self.device, &self.queue, label, width, height, data)
+    }
+
+    /// Sets whether world and model textures created from now on are block-compressed.
+    pub fn set_texture_compression(&self, enabled: bool) {
+        self.texture_compression.set(enabled);
+    }
+
+    /// Creates a world or model texture, with a full mipmap chain if `mipmapped` is set.
+    ///
+    /// Diffuse textures are block-compressed if texture compression is enabled and supported and
+    /// their dimensions are multiples of 4. Otherwise this is the same as `create_texture` or
+    /// `create_texture_mipmapped`.
+    pub fn create_world_texture<'a>(
+        &self,
+        label: Option<&'a str>,
+        width: u32,
+        height: u32,
+        data: &TextureData,
+        mipmapped: bool,
+    ) -> wgpu::Texture {
+        if let TextureData::Diffuse(ref diffuse) = data {
+            if self.texture_compression.get() && self.bc_supported {
+                if let Some(texture) =
+                    self.create_compressed_texture(label, width, height, &diffuse.rgba, mipmapped)
+                {
+                    return texture;
+                }
+            }
+        }
+
+        if mipmapped {
+            self.create_texture_mipmapped(label, width, height, data)
+        } else {
+            self.create_texture(label, width, height, data)
+        }
+    }
+
+    // Compresses an RGBA texture, or loads it from the cache if it was compressed before.
+    //
+    // Returns None if the texture can't be compressed.
+    fn create_compressed_texture<'a>(
+        &self,
+        label: Option<&'a str>,
+        width: u32,
+        height: u32,
+        rgba: &[u8],
+        mipmapped: bool,
+    ) -> Option<wgpu::Texture> {
+        let level_count = match bcn::mip_level_count(width, height) {
+            0 => return None,
+            n if mipmapped => n,
+            _ => 1,
+        };
+
+        let format = BcFormat::for_rgba(rgba);
+        let level_sizes: Vec<(u32, u32)> = (0..level_count)
+            .map(|level| (width >> level, height >> level))
+            .collect();
+        let total_size = level_sizes
+            .iter()
+            .map(|&(w, h)| format.image_size(w, h))
+            .sum();
+
+        let hash = bcn::content_hash(rgba, width, height);
+        let data = match self.bc_cache.load(hash, format, total_size) {
+            Some(data) => data,
+            None => {
+                let mut data = Vec::with_capacity(total_size);
+                let mut level_data = Cow::Borrowed(rgba);
+                for (level, &(w, h)) in level_sizes.iter().enumerate() {
+                    if level > 0 {
+                        level_data = downsample(&level_data, w * 2, h * 2, 4).into();
+                    }
+                    data.extend(bcn::compress(&level_data, w, h, format));
+                }
+
+                if let Err(e) = self.bc_cache.store(hash, format, &data) {
+                    warn!("Couldn't cache compressed texture: {}", e);
+                }
+                data
+            }
+        };
+
+        trace!(
+            "Creating compressed texture ({:?}: {}x{}, {} levels)",
+            format,
+            width,
+            height,
+            level_count
+        );
+        let mut desc = texture_descriptor(label, width, height, format.texture_format());
+        desc.mip_level_count = level_count;
+        let texture = self.device.create_texture(&desc);
+
+        let mut offset = 0;
+        for (level, &(w, h)) in level_sizes.iter().enumerate() {
+            let size = format.image_size(w, h);
+            self.queue.write_texture(
+                wgpu::TextureCopyView {
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                &data[offset..offset + size],
+                wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: w / 4 * format.block_size(),
+                    rows_per_image: 0,
+                },
+                wgpu::Extent3d {
+                    width: w,
+                    height: h,
+                    depth: 1,
+                },
+            );
+            offset += size;
+        }
+
+        Some(texture)
+    }
+
+    /// Update graphics state with the new framebuffer size, render scale and sample count.
+    ///
+    /// The scene is rendered at the framebuffer size multiplied by `render_scale` and upscaled to
+    /// the framebuffer size by the postprocessing pass. If either size has changed, this recreates
+    /// the affected render targets, releasing the old ones. Renderers that bind the render targets
+    /// as inputs should compare `target_generation` to detect this and rebuild their bind groups.
     ///
-    /// If the framebuffer size has changed, this recreates all render targets with the new size.
+    /// The bloom targets are only kept while `bloom` is set, since at high resolutions they take
+    /// as much memory as a G-buffer attachment.
     ///
     /// If the framebuffer sample count has changed, this recreates all render targets with the
     /// new sample count and rebuilds the render pipelines to output that number of samples.
-    pub fn update(&mut self, size: Extent2d, sample_count: u32) {
+    ///
+    /// If more uniform blocks were allocated during the last frame than fit in the uniform
+    /// buffers, this grows the buffers and recreates the bind groups that refer to them.
+    pub fn update(&mut self, size: Extent2d, render_scale: f32, sample_count: u32, bloom: bool) {
         if self.sample_count.get() != sample_count {
             self.sample_count.set(sample_count);
             self.recreate_pipelines(sample_count);
         }
 
-        if self.initial_pass_target.size() != size
+        // the scene targets share the scaled size, so they're recreated together
+        let scene_size = size.scaled(render_scale);
+        if self.initial_pass_target.size() != scene_size
             || self.initial_pass_target.sample_count() != sample_count
         {
-            self.initial_pass_target = InitialPassTarget::new(self.device(), size, sample_count);
+            self.initial_pass_target =
+                InitialPassTarget::new(&self.device, scene_size, sample_count);
+            self.deferred_pass_target =
+                DeferredPassTarget::new(&self.device, scene_size, sample_count);
+            // recreated below if bloom is enabled
+            self.bloom_pass_targets = None;
+            self.target_generation += 1;
+        }
+
+        if bloom != self.bloom_pass_targets.is_some() {
+            self.bloom_pass_targets = if bloom {
+                Some([
+                    BloomPassTarget::new(&self.device, scene_size),
+                    BloomPassTarget::new(&self.device, scene_size),
+                ])
+            } else {
+                None
+            };
+            self.target_generation += 1;
         }
 
+        // the UI is always drawn at full resolution
         if self.final_pass_target.size() != size
             || self.final_pass_target.sample_count() != sample_count
         {
-            self.final_pass_target = FinalPassTarget::new(self.device(), size, sample_count);
+            self.final_pass_target = FinalPassTarget::new(&self.device, size, sample_count);
+            self.blit_pipeline
+                .set_input(&self.device, self.final_pass_target.resolve_view());
+            self.target_generation += 1;
         }
+
+        // grow any uniform buffers that overflowed during the last frame
+        if self.entity_uniform_buffer.get_mut().grow(&self.device) {
+            self.rebuild_per_entity_bind_group();
+        }
+        self.quad_pipeline.grow_uniform_buffer(&self.device);
+    }
+
+    /// Update the filtering applied to world textures.
+    ///
+    /// If the filtering has changed, this rebuilds the world sampler and the bind group that uses
+    /// it.
+    pub fn set_texture_filter(&mut self, texture_mode: TextureMode, anisotropy: u8) {
+        if self.texture_mode == texture_mode && self.anisotropy == anisotropy {
+            return;
+        }
+
+        debug!(
+            "Texture filter changed to {:?} with anisotropy {}",
+            texture_mode, anisotropy
+        );
+        self.texture_mode = texture_mode;
+        self.anisotropy = anisotropy;
+        self.world_sampler = create_world_sampler(&self.device, texture_mode, anisotropy);
+        self.rebuild_per_entity_bind_group();
+    }
+
+    fn rebuild_per_entity_bind_group(&mut self) {
+        self.world_bind_groups[world::BindGroupLayoutId::PerEntity as usize] =
+            create_per_entity_bind_group(
+                &self.device,
+                &self.world_bind_group_layouts,
+                &self.entity_uniform_buffer.borrow(),
+                &self.world_sampler,
+                &self.lightmap_sampler,
+            );
     }
 
     /// Rebuild all render pipelines using the new sample count.
@@ -494,6 +1026,13 @@ impl GraphicsState {
             &self.world_bind_group_layouts,
             sample_count,
         );
+        self.water_pipeline.rebuild(
+            &self.device,
+            &mut self.compiler.borrow_mut(),
+            &self.world_bind_group_layouts,
+            &self.brush_pipeline,
+            sample_count,
+        );
         self.sprite_pipeline.rebuild(
             &self.device,
             &mut self.compiler.borrow_mut(),
@@ -507,6 +1046,16 @@ impl GraphicsState {
             &mut self.compiler.borrow_mut(),
             sample_count,
         );
+        self.debug_line_pipeline.rebuild(
+            &self.device,
+            &mut self.compiler.borrow_mut(),
+            sample_count,
+        );
+        self.blob_shadow_pipeline.rebuild(
+            &self.device,
+            &mut self.compiler.borrow_mut(),
+            sample_count,
+        );
         self.glyph_pipeline
             .rebuild(&self.device, &mut self.compiler.borrow_mut(), sample_count);
         self.quad_pipeline
@@ -535,6 +1084,24 @@ impl GraphicsState {
         &self.final_pass_target
     }
 
+    /// Returns the bloom targets, or `None` if bloom is disabled.
+    pub fn bloom_pass_targets(&self) -> Option<&[BloomPassTarget; 2]> {
+        self.bloom_pass_targets.as_ref()
+    }
+
+    /// Returns the view holding the result of the bloom passes, or a black texture if bloom is
+    /// disabled.
+    pub fn bloom_view(&self) -> &wgpu::TextureView {
+        match self.bloom_pass_targets {
+            Some(ref targets) => targets[0].color_view(),
+            None => &self.no_bloom_view,
+        }
+    }
+
+    pub fn target_generation(&self) -> usize {
+        self.target_generation
+    }
+
     pub fn frame_uniform_buffer(&self) -> &wgpu::Buffer {
         &self.frame_uniform_buffer
     }
@@ -551,6 +1118,10 @@ impl GraphicsState {
         &self.diffuse_sampler
     }
 
+    pub fn world_sampler(&self) -> &wgpu::Sampler {
+        &self.world_sampler
+    }
+
     pub fn default_lightmap(&self) -> &wgpu::Texture {
         &self.default_lightmap
     }
@@ -563,6 +1134,10 @@ impl GraphicsState {
         &self.lightmap_sampler
     }
 
+    pub fn shadow_sampler(&self) -> &wgpu::Sampler {
+        &self.shadow_sampler
+    }
+
     pub fn world_bind_group_layouts(&self) -> &[wgpu::BindGroupLayout] {
         &self.world_bind_group_layouts
     }
@@ -581,6 +1156,10 @@ impl GraphicsState {
         &self.brush_pipeline
     }
 
+    pub fn water_pipeline(&self) -> &WaterPipeline {
+        &self.water_pipeline
+    }
+
     pub fn sprite_pipeline(&self) -> &SpritePipeline {
         &self.sprite_pipeline
     }
@@ -597,6 +1176,30 @@ impl GraphicsState {
         &self.postprocess_pipeline
     }
 
+    pub fn bloom_bright_pipeline(&self) -> &BloomBrightPipeline {
+        &self.bloom_bright_pipeline
+    }
+
+    pub fn bloom_blur_pipeline(&self) -> &BloomBlurPipeline {
+        &self.bloom_blur_pipeline
+    }
+
+    pub fn debug_line_pipeline(&self) -> &DebugLinePipeline {
+        &self.debug_line_pipeline
+    }
+
+    pub fn blob_shadow_pipeline(&self) -> &BlobShadowPipeline {
+        &self.blob_shadow_pipeline
+    }
+
+    pub fn shadow_pipeline(&self) -> &ShadowPipeline {
+        &self.shadow_pipeline
+    }
+
+    pub fn light_bake_pipeline(&self) -> &LightBakePipeline {
+        &self.light_bake_pipeline
+    }
+
     pub fn glyph_pipeline(&self) -> &GlyphPipeline {
         &self.glyph_pipeline
     }
@@ -621,3 +1224,68 @@ impl GraphicsState {
         &self.gfx_wad
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_full_mip_level_count() {
+        assert_eq!(full_mip_level_count(1, 1), 1);
+        assert_eq!(full_mip_level_count(64, 64), 7);
+        assert_eq!(full_mip_level_count(64, 16), 7);
+        assert_eq!(full_mip_level_count(24, 40), 6);
+    }
+
+    #[test]
+    fn test_downsample() {
+        #[rustfmt::skip]
+        let src = vec![
+            0,  4,  8,
+            4,  8, 12,
+            16, 16, 16,
+        ];
+
+        // odd dimensions drop the last row and column
+        assert_eq!(downsample(&src, 3, 3, 1), vec![4]);
+
+        let src = vec![0, 100, 50, 200];
+        assert_eq!(downsample(&src, 2, 1, 2), vec![25, 150]);
+    }
+
+    #[test]
+    fn test_texture_mode_from_name() {
+        assert_eq!(
+            TextureMode::from_name("gl_linear_mipmap_linear"),
+            Some(TextureMode::LinearMipmapLinear)
+        );
+        assert_eq!(
+            TextureMode::from_name("GL_NEAREST"),
+            Some(TextureMode::Nearest)
+        );
+        assert_eq!(TextureMode::from_name("GL_BILINEAR"), None);
+    }
+
+    #[test]
+    fn test_extent_scaled() {
+        let size = Extent2d {
+            width: 1920,
+            height: 1080,
+        };
+        assert_eq!(size.scaled(1.0), size);
+        assert_eq!(
+            size.scaled(0.5),
+            Extent2d {
+                width: 960,
+                height: 540,
+            }
+        );
+        assert_eq!(
+            size.scaled(0.0),
+            Extent2d {
+                width: 1,
+                height: 1,
+            }
+        );
+    }
+}
@@ -53,11 +53,10 @@ impl Palette {
 
         for index in indices {
             match *index {
+                // transparent, as in the masked textures whose names start with {
                 0xFF => {
-                    for _ in 0..4 {
-                        rgba.push(0);
-                        fullbright.push(0);
-                    }
+                    rgba.extend_from_slice(&[0; 4]);
+                    fullbright.push(0);
                 }
 
                 i => {
@@ -80,3 +79,23 @@ impl Palette {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate_masked() {
+        let palette = Palette::new(&[0x80; 768]);
+
+        // a masked texture, with fullbright texels after the transparent ones
+        let indices = [0xFF, 0xFF, 12, 0xFF, 240, 0xFF, 250];
+        let (diffuse, fullbright) = palette.translate(&indices);
+
+        assert_eq!(diffuse.rgba.len(), indices.len() * 4);
+        assert_eq!(fullbright.fullbright.len(), indices.len());
+        assert_eq!(&fullbright.fullbright[..], &[0, 0, 0, 0, 0xFF, 0, 0xFF]);
+        assert_eq!(&diffuse.rgba[..4], &[0; 4]);
+        assert_eq!(&diffuse.rgba[8..12], &[0x80, 0x80, 0x80, 0xFF]);
+    }
+}
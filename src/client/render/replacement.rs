@@ -0,0 +1,182 @@
+//! Replacement textures loaded from image files.
+//!
+//! Texture packs provide high-resolution versions of BSP textures as TGA or PNG files. For a
+//! texture named `name` in the map `mapname`, the following directories are searched in order:
+//!
+//! - `textures/<mapname>/`
+//! - `textures/`
+//!
+//! Within each directory `<name>.tga` is preferred over `<name>.png`. Since `*` isn't allowed in
+//! file names on some platforms, it's replaced with `#` (so `*water1` becomes `#water1`).
+//!
+//! A `<name>_luma` image in the same directory provides the fullbright mask for a replacement.
+//! Its brightness at each texel determines how much of the diffuse color is drawn fullbright.
+
+use std::io::Read;
+
+use crate::common::{tga, vfs::Vfs};
+
+const IMAGE_EXTENSIONS: &[&str] = &["tga", "png"];
+
+/// An RGBA image loaded from a replacement texture file.
+pub struct ReplacementImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+/// A replacement for a BSP texture.
+pub struct ReplacementTexture {
+    pub diffuse: ReplacementImage,
+
+    /// The fullbright mask, at the same dimensions as `diffuse`, if one was provided.
+    pub fullbright: Option<Vec<u8>>,
+}
+
+fn decode_png(data: &[u8]) -> Result<ReplacementImage, png::DecodingError> {
+    let mut decoder = png::Decoder::new(data);
+
+    // expand paletted and low bit depth images so every channel is 8 bits
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels)?;
+
+    let rgba = match info.color_type {
+        png::ColorType::RGBA => pixels,
+        png::ColorType::RGB => pixels
+            .chunks(3)
+            .flat_map(|p| vec![p[0], p[1], p[2], 0xFF])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks(2)
+            .flat_map(|p| vec![p[0], p[0], p[0], p[1]])
+            .collect(),
+        _ => pixels.iter().flat_map(|&p| vec![p, p, p, 0xFF]).collect(),
+    };
+
+    Ok(ReplacementImage {
+        width: info.width,
+        height: info.height,
+        rgba,
+    })
+}
+
+/// Loads a PNG or TGA image from the VFS, chosen by the extension of `path`.
+pub fn load_image(vfs: &Vfs, path: &str) -> Option<ReplacementImage> {
+    let mut data = Vec::new();
+    vfs.open(path).ok()?.read_to_end(&mut data).ok()?;
+
+    let image = if path.ends_with(".png") {
+        decode_png(&data).map_err(|e| e.to_string())
+    } else {
+        tga::decode(&data)
+            .map(|tga| ReplacementImage {
+                width: tga.width(),
+                height: tga.height(),
+                rgba: tga.into_rgba(),
+            })
+            .map_err(|e| e.to_string())
+    };
+
+    match image {
+        Ok(i) => Some(i),
+        Err(e) => {
+            warn!("Failed to load image {}: {}", path, e);
+            None
+        }
+    }
+}
+
+fn find_image(vfs: &Vfs, dir: &str, name: &str) -> Option<ReplacementImage> {
+    IMAGE_EXTENSIONS
+        .iter()
+        .find_map(|ext| load_image(vfs, &format!("{}/{}.{}", dir, name, ext)))
+}
+
+/// Searches the VFS for a replacement for the named texture.
+pub fn find(vfs: &Vfs, map_name: &str, tex_name: &str) -> Option<ReplacementTexture> {
+    let name = tex_name.replace('*', "#");
+    let dirs = [format!("textures/{}", map_name), "textures".to_owned()];
+
+    for dir in dirs.iter() {
+        if let Some(diffuse) = find_image(vfs, dir, &name) {
+            debug!("Using replacement texture {}/{}", dir, name);
+            let fullbright = find_image(vfs, dir, &format!("{}_luma", name)).map(|luma| {
+                resize_mask(
+                    &luma_mask(&luma.rgba),
+                    luma.width,
+                    luma.height,
+                    diffuse.width,
+                    diffuse.height,
+                )
+            });
+
+            return Some(ReplacementTexture {
+                diffuse,
+                fullbright,
+            });
+        }
+    }
+
+    None
+}
+
+/// Converts an RGBA luma image to a fullbright mask.
+///
+/// Each texel of the mask is the brightest channel of the corresponding pixel, scaled by its
+/// alpha.
+pub fn luma_mask(rgba: &[u8]) -> Vec<u8> {
+    rgba.chunks(4)
+        .map(|p| {
+            let brightness = p[0].max(p[1]).max(p[2]) as u32;
+            (brightness * p[3] as u32 / 0xFF) as u8
+        })
+        .collect()
+}
+
+/// Resizes a single-channel mask using nearest-neighbor sampling.
+///
+/// This is used to fit the fullbright mask of the original texture to a replacement with
+/// different dimensions.
+pub fn resize_mask(
+    mask: &[u8],
+    src_width: u32,
+    src_height: u32,
+    dst_width: u32,
+    dst_height: u32,
+) -> Vec<u8> {
+    if (src_width, src_height) == (dst_width, dst_height) {
+        return mask.to_vec();
+    }
+
+    let mut resized = Vec::with_capacity((dst_width * dst_height) as usize);
+    for y in 0..dst_height {
+        let src_y = (y as u64 * src_height as u64 / dst_height as u64) as usize;
+        for x in 0..dst_width {
+            let src_x = (x as u64 * src_width as u64 / dst_width as u64) as usize;
+            resized.push(mask[src_y * src_width as usize + src_x]);
+        }
+    }
+
+    resized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_luma_mask() {
+        let rgba = [0, 0, 0, 0xFF, 0xFF, 0x10, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0];
+        assert_eq!(luma_mask(&rgba), vec![0, 0xFF, 0]);
+    }
+
+    #[test]
+    fn test_resize_mask() {
+        let mask = [1, 2, 3, 4];
+        assert_eq!(resize_mask(&mask, 2, 2, 2, 2), mask.to_vec());
+        assert_eq!(resize_mask(&mask, 2, 2, 4, 2), vec![1, 1, 2, 2, 3, 3, 4, 4]);
+        assert_eq!(resize_mask(&mask, 2, 2, 1, 1), vec![1]);
+    }
+}
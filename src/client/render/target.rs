@@ -19,8 +19,8 @@
 // SOFTWARE.
 
 use crate::client::render::{
-    Extent2d, DEPTH_ATTACHMENT_FORMAT, DIFFUSE_ATTACHMENT_FORMAT, LIGHT_ATTACHMENT_FORMAT,
-    NORMAL_ATTACHMENT_FORMAT,
+    Extent2d, DEPTH_ATTACHMENT_FORMAT, DIFFUSE_ATTACHMENT_FORMAT, HDR_ATTACHMENT_FORMAT,
+    LIGHT_ATTACHMENT_FORMAT, NORMAL_ATTACHMENT_FORMAT,
 };
 
 // TODO: collapse these into a single definition
@@ -87,6 +87,27 @@ pub fn create_light_attachment(
     })
 }
 
+/// Create a texture suitable for use as a high dynamic range color attachment.
+///
+/// The resulting texture will have the OUTPUT_ATTACHMENT flag as well as
+/// any flags specified by `usage`.
+pub fn create_hdr_attachment(
+    device: &wgpu::Device,
+    size: Extent2d,
+    sample_count: u32,
+    usage: wgpu::TextureUsage,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("hdr attachment"),
+        size: size.into(),
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: HDR_ATTACHMENT_FORMAT,
+        usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | usage,
+    })
+}
+
 /// Create a texture suitable for use as a depth attachment.
 ///
 /// The underlying texture will have the OUTPUT_ATTACHMENT flag as well as
@@ -220,6 +241,42 @@ impl InitialPassTarget {
     pub fn depth_view(&self) -> &wgpu::TextureView {
         &self.depth_view
     }
+
+    /// Returns a builder for a pass that adds to the G-buffers without clearing them.
+    pub fn overlay_pass_builder<'a>(&'a self) -> RenderPassBuilder<'a> {
+        let load = wgpu::Operations {
+            load: wgpu::LoadOp::Load,
+            store: true,
+        };
+
+        RenderPassBuilder {
+            color_attachments: vec![
+                wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: self.diffuse_view(),
+                    resolve_target: None,
+                    ops: load,
+                },
+                wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: self.normal_view(),
+                    resolve_target: None,
+                    ops: load,
+                },
+                wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: self.light_view(),
+                    resolve_target: None,
+                    ops: load,
+                },
+            ],
+            depth_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: self.depth_view(),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        }
+    }
 }
 
 impl RenderTarget for InitialPassTarget {
@@ -272,8 +329,9 @@ pub struct DeferredPassTarget {
 
 impl DeferredPassTarget {
     pub fn new(device: &wgpu::Device, size: Extent2d, sample_count: u32) -> DeferredPassTarget {
+        // lighting can saturate up to 200%, so this is kept in HDR until tonemapping
         let color_attachment =
-            create_color_attachment(device, size, sample_count, wgpu::TextureUsage::SAMPLED);
+            create_hdr_attachment(device, size, sample_count, wgpu::TextureUsage::SAMPLED);
         let color_view = color_attachment.create_default_view();
 
         DeferredPassTarget {
@@ -299,6 +357,32 @@ impl DeferredPassTarget {
     pub fn color_view(&self) -> &wgpu::TextureView {
         &self.color_view
     }
+
+    /// Returns a builder for a pass that draws over the lit scene, depth tested against the
+    /// initial pass.
+    pub fn translucent_pass_builder<'a>(
+        &'a self,
+        depth_view: &'a wgpu::TextureView,
+    ) -> RenderPassBuilder<'a> {
+        RenderPassBuilder {
+            color_attachments: vec![wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: self.color_view(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        }
+    }
 }
 
 impl RenderTarget for DeferredPassTarget {
@@ -317,6 +401,61 @@ impl RenderTarget for DeferredPassTarget {
     }
 }
 
+/// Render target for one step of the bloom chain.
+///
+/// Bloom is computed at half the resolution of the framebuffer without multisampling.
+pub struct BloomPassTarget {
+    size: Extent2d,
+    color_attachment: wgpu::Texture,
+    color_view: wgpu::TextureView,
+}
+
+impl BloomPassTarget {
+    /// Create a bloom target for a framebuffer of the given size.
+    pub fn new(device: &wgpu::Device, framebuffer_size: Extent2d) -> BloomPassTarget {
+        let size = Extent2d {
+            width: (framebuffer_size.width / 2).max(1),
+            height: (framebuffer_size.height / 2).max(1),
+        };
+        let color_attachment = create_hdr_attachment(device, size, 1, wgpu::TextureUsage::SAMPLED);
+        let color_view = color_attachment.create_default_view();
+
+        BloomPassTarget {
+            size,
+            color_attachment,
+            color_view,
+        }
+    }
+
+    pub fn size(&self) -> Extent2d {
+        self.size
+    }
+
+    pub fn color_attachment(&self) -> &wgpu::Texture {
+        &self.color_attachment
+    }
+
+    pub fn color_view(&self) -> &wgpu::TextureView {
+        &self.color_view
+    }
+}
+
+impl RenderTarget for BloomPassTarget {
+    fn render_pass_builder<'a>(&'a self) -> RenderPassBuilder {
+        RenderPassBuilder {
+            color_attachments: vec![wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: self.color_view(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_attachment: None,
+        }
+    }
+}
+
 pub struct FinalPassTarget {
     size: Extent2d,
     sample_count: u32,
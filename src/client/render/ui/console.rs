@@ -1,21 +1,185 @@
 use crate::{
     client::render::{
+        replacement,
         ui::{
             glyph::{GlyphRendererCommand, GLYPH_HEIGHT, GLYPH_WIDTH},
+            hud::parse_color,
             layout::{Anchor, AnchorCoord, Layout, ScreenPosition, Size},
             quad::{QuadRendererCommand, QuadTexture},
         },
         GraphicsState,
     },
-    common::{console::Console, engine, wad::QPic},
+    common::{
+        console::{Console, ConsoleLine, CvarRegistry, PrintFilter, PrintLevel},
+        engine,
+        wad::QPic,
+    },
 };
 
-use chrono::Duration;
+use chrono::{Duration, Utc};
 
 const PAD_LEFT: i32 = GLYPH_WIDTH as i32;
+const PAD_RIGHT: i32 = GLYPH_WIDTH as i32;
+
+// the tint of each print level. chat is already drawn with the colored glyphs.
+const GAME_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+const CHAT_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+const DEV_COLOR: [f32; 3] = [0.6, 0.6, 0.6];
+
+// smallest allowed value of scr_conscale
+const MIN_CONSOLE_SCALE: f32 = 1.0;
+
+// opacity of the panel behind notify lines in high contrast mode
+const NOTIFY_BACKING_ALPHA: f32 = 0.75;
+
+fn level_color(level: PrintLevel, high_contrast: bool) -> [f32; 3] {
+    // dimmed lines are hard to read for some players, so high contrast mode keeps them all white
+    if high_contrast {
+        return GAME_COLOR;
+    }
+
+    match level {
+        PrintLevel::Game => GAME_COLOR,
+        PrintLevel::Chat => CHAT_COLOR,
+        PrintLevel::Dev => DEV_COLOR,
+    }
+}
+
+// Converts a line of output to glyphs, replacing any that aren't in the console font.
+fn line_text(line: &ConsoleLine) -> String {
+    line.chars()
+        .iter()
+        .map(|chr| {
+            if *chr as u32 > std::u8::MAX as u32 {
+                warn!(
+                    "char \"{}\" (U+{:4}) cannot be displayed in the console",
+                    *chr, *chr as u32
+                );
+                '?'
+            } else {
+                *chr
+            }
+        })
+        .collect()
+}
+
+/// What is drawn behind the console text.
+#[derive(Clone, Copy)]
+pub enum ConsoleBackground<'a> {
+    /// The standard `gfx/conback.lmp`.
+    Default,
+
+    /// A custom image, as loaded by [`load_console_background`].
+    Image(&'a QuadTexture),
+
+    /// A solid color.
+    Color([f32; 3]),
+}
+
+/// Console appearance settings, read from cvars each frame.
+#[derive(Clone, Copy)]
+pub struct ConsoleSettings<'a> {
+    pub background: ConsoleBackground<'a>,
+
+    /// Opacity of the background, from 0 to 1.
+    pub alpha: f32,
+
+    /// The print levels that are hidden.
+    pub filter: PrintFilter,
+
+    /// How long lines stay in the notify area, in seconds.
+    pub notify_time: f32,
+
+    /// The most lines shown in the notify area.
+    pub notify_lines: usize,
+
+    /// The scale of the console text.
+    pub scale: f32,
+
+    /// Whether text is drawn white on solid black, for players who find the usual console hard to
+    /// read.
+    pub high_contrast: bool,
+}
+
+impl<'a> ConsoleSettings<'a> {
+    /// Reads the console settings from `scr_conbackcolor`, `scr_conalpha`, `con_filter`,
+    /// `con_notifytime`, `con_notifylines`, `scr_conscale` and `scr_highcontrast`.
+    ///
+    /// `image` is the custom background named by `scr_conback`, if one is loaded. A valid
+    /// `scr_conbackcolor` takes precedence over any image, and `scr_highcontrast` over both.
+    pub fn from_cvars(cvars: &CvarRegistry, image: Option<&'a QuadTexture>) -> ConsoleSettings<'a> {
+        let color = cvars
+            .get("scr_conbackcolor")
+            .ok()
+            .and_then(|c| parse_color(&c));
+
+        let high_contrast = cvars.get_value("scr_highcontrast").unwrap_or(0.0) != 0.0;
+
+        let background = match (color, image) {
+            _ if high_contrast => ConsoleBackground::Color([0.0; 3]),
+            (Some([r, g, b, _]), _) => ConsoleBackground::Color([r, g, b]),
+            (None, Some(i)) => ConsoleBackground::Image(i),
+            (None, None) => ConsoleBackground::Default,
+        };
+
+        let alpha = if high_contrast {
+            1.0
+        } else {
+            cvars
+                .get_value("scr_conalpha")
+                .unwrap_or(1.0)
+                .max(0.0)
+                .min(1.0)
+        };
+
+        let filter = PrintFilter::parse(&cvars.get("con_filter").unwrap_or_default());
+        let notify_time = cvars.get_value("con_notifytime").unwrap_or(3.0).max(0.0);
+        let notify_lines = cvars.get_value("con_notifylines").unwrap_or(4.0).max(0.0) as usize;
+        let scale = cvars
+            .get_value("scr_conscale")
+            .unwrap_or(2.0)
+            .max(MIN_CONSOLE_SCALE);
+
+        ConsoleSettings {
+            background,
+            alpha,
+            filter,
+            notify_time,
+            notify_lines,
+            scale,
+            high_contrast,
+        }
+    }
+}
+
+/// Loads a custom console background from the VFS.
+///
+/// Files ending in `.lmp` are loaded as QPics, anything else as a PNG or TGA image.
+pub fn load_console_background(state: &GraphicsState, path: &str) -> Option<QuadTexture> {
+    let texture = if path.ends_with(".lmp") {
+        state
+            .vfs()
+            .open(path)
+            .ok()
+            .and_then(|f| QPic::load(f).ok())
+            .map(|qpic| QuadTexture::from_qpic(state, &qpic))
+    } else {
+        replacement::load_image(state.vfs(), path)
+            .map(|i| QuadTexture::from_rgba(state, i.width, i.height, &i.rgba))
+    };
+
+    if texture.is_none() {
+        warn!("Couldn't load console background {}", path);
+    }
+
+    texture
+}
 
 pub struct ConsoleRenderer {
     conback: QuadTexture,
+
+    // 1x1 white texture, tinted to draw solid color backgrounds
+    blank: QuadTexture,
 }
 
 impl ConsoleRenderer {
@@ -24,13 +188,15 @@ impl ConsoleRenderer {
             state,
             &QPic::load(state.vfs().open("gfx/conback.lmp").unwrap()).unwrap(),
         );
+        let blank = QuadTexture::from_rgba(state, 1, 1, &[0xFF; 4]);
 
-        ConsoleRenderer { conback }
+        ConsoleRenderer { conback, blank }
     }
 
     pub fn generate_commands<'a>(
         &'a self,
         console: &Console,
+        settings: &ConsoleSettings<'a>,
         time: Duration,
         quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
         glyph_cmds: &mut Vec<GlyphRendererCommand>,
@@ -38,28 +204,40 @@ impl ConsoleRenderer {
         // TODO: take screen proportion as a parameter or cvar
         let proportion = 0.33;
 
-        // TODO: take scale as cvar
-        let scale = 2.0;
+        let scale = settings.scale;
         let console_anchor = Anchor {
             x: AnchorCoord::Zero,
             y: AnchorCoord::Proportion(1.0 - proportion),
         };
 
         // draw console background
+        let (texture, [r, g, b]) = match settings.background {
+            ConsoleBackground::Default => (&self.conback, [1.0; 3]),
+            ConsoleBackground::Image(image) => (image, [1.0; 3]),
+            ConsoleBackground::Color(color) => (&self.blank, color),
+        };
         quad_cmds.push(QuadRendererCommand {
-            texture: &self.conback,
+            texture,
             layout: Layout {
                 position: ScreenPosition::Absolute(console_anchor),
                 anchor: Anchor::BOTTOM_LEFT,
                 size: Size::DisplayScale { ratio: 1.0 },
             },
+            tint: [r, g, b, settings.alpha],
         });
 
-        // draw version string
+        // draw version string in the bottom-right corner
         let version_string = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
         glyph_cmds.push(GlyphRendererCommand::Text {
             text: version_string,
-            position: ScreenPosition::Absolute(console_anchor),
+            position: ScreenPosition::Relative {
+                anchor: Anchor {
+                    x: AnchorCoord::Max,
+                    y: console_anchor.y,
+                },
+                x_ofs: -PAD_RIGHT,
+                y_ofs: 0,
+            },
             anchor: Anchor::BOTTOM_RIGHT,
             scale,
         });
@@ -101,36 +279,92 @@ impl ConsoleRenderer {
         }
 
         // draw previous output
-        for (line_id, line) in console.output().lines().enumerate() {
+        let output = console.output();
+        let lines = output
+            .entries()
+            .filter(|line| settings.filter.allows(line.level()));
+        for (line_id, line) in lines.enumerate() {
             // TODO: implement scrolling
             if line_id > 100 {
                 break;
             }
 
-            for (chr_id, chr) in line.iter().enumerate() {
-                let position = ScreenPosition::Relative {
+            glyph_cmds.push(GlyphRendererCommand::ColoredText {
+                text: line_text(line),
+                position: ScreenPosition::Relative {
                     anchor: console_anchor,
-                    x_ofs: PAD_LEFT + (1 + chr_id * GLYPH_WIDTH) as i32,
+                    x_ofs: PAD_LEFT + 1,
                     y_ofs: ((line_id + 1) * GLYPH_HEIGHT) as i32,
-                };
-
-                let c = if *chr as u32 > std::u8::MAX as u32 {
-                    warn!(
-                        "char \"{}\" (U+{:4}) cannot be displayed in the console",
-                        *chr, *chr as u32
-                    );
-                    '?'
-                } else {
-                    *chr
-                };
-
-                glyph_cmds.push(GlyphRendererCommand::Glyph {
-                    glyph_id: c as u8,
-                    position,
+                },
+                anchor: Anchor::BOTTOM_LEFT,
+                scale,
+                color: level_color(line.level(), settings.high_contrast),
+            });
+        }
+    }
+
+    /// Draws the most recent lines of output at the top of the screen while the console is
+    /// closed.
+    ///
+    /// In high contrast mode the lines are drawn on a dark panel, so that they stand out from the
+    /// scene behind them.
+    pub fn generate_notify_commands<'a>(
+        &'a self,
+        console: &Console,
+        settings: &ConsoleSettings,
+        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+        glyph_cmds: &mut Vec<GlyphRendererCommand>,
+    ) {
+        let scale = settings.scale;
+
+        let cutoff = Utc::now() - engine::duration_from_f32(settings.notify_time);
+        let output = console.output();
+        let mut recent: Vec<&ConsoleLine> = output
+            .entries()
+            .take_while(|line| line.time() >= cutoff)
+            .filter(|line| settings.filter.allows(line.level()))
+            .take(settings.notify_lines)
+            .collect();
+
+        if settings.high_contrast && !recent.is_empty() {
+            let columns = recent
+                .iter()
+                .map(|line| line.chars().len())
+                .max()
+                .unwrap_or(0);
+            let width = ((PAD_LEFT as usize + (columns + 1) * GLYPH_WIDTH) as f32 * scale) as u32;
+            let height = ((recent.len() * GLYPH_HEIGHT) as f32 * scale) as u32;
+
+            // absolute sizes are positioned in pixels rather than scaled units
+            quad_cmds.push(QuadRendererCommand {
+                texture: &self.blank,
+                layout: Layout {
+                    position: ScreenPosition::Relative {
+                        anchor: Anchor::TOP_LEFT,
+                        x_ofs: 0,
+                        y_ofs: -(height as i32),
+                    },
                     anchor: Anchor::BOTTOM_LEFT,
-                    scale,
-                });
-            }
+                    size: Size::Absolute { width, height },
+                },
+                tint: [0.0, 0.0, 0.0, NOTIFY_BACKING_ALPHA],
+            });
+        }
+
+        // output is stored newest first, but the newest line goes at the bottom
+        recent.reverse();
+        for (line_id, line) in recent.into_iter().enumerate() {
+            glyph_cmds.push(GlyphRendererCommand::ColoredText {
+                text: line_text(line),
+                position: ScreenPosition::Relative {
+                    anchor: Anchor::TOP_LEFT,
+                    x_ofs: PAD_LEFT,
+                    y_ofs: -((line_id * GLYPH_HEIGHT) as i32),
+                },
+                anchor: Anchor::TOP_LEFT,
+                scale,
+                color: level_color(line.level(), settings.high_contrast),
+            });
         }
     }
 }
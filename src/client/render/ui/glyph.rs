@@ -58,7 +58,8 @@ lazy_static! {
         wgpu::vertex_attr_array![
             2 => Float2, // a_instance_position
             3 => Float2, // a_instance_scale
-            4 => Uint // a_instance_layer
+            4 => Uint, // a_instance_layer
+            5 => Float3 // a_instance_color
         ].to_vec(),
     ];
 }
@@ -177,8 +178,12 @@ pub struct GlyphInstance {
     pub position: Vector2<f32>,
     pub scale: Vector2<f32>,
     pub layer: u32,
+    pub color: [f32; 3],
 }
 
+// glyphs are drawn in their original colors unless tinted
+const WHITE: [f32; 3] = [1.0; 3];
+
 pub enum GlyphRendererCommand {
     Glyph {
         glyph_id: u8,
@@ -192,6 +197,14 @@ pub enum GlyphRendererCommand {
         anchor: Anchor,
         scale: f32,
     },
+    /// Text whose glyphs are multiplied by `color`.
+    ColoredText {
+        text: String,
+        position: ScreenPosition,
+        anchor: Anchor,
+        scale: f32,
+        color: [f32; 3],
+    },
 }
 
 pub struct GlyphRenderer {
@@ -310,6 +323,7 @@ impl GlyphRenderer {
                             (GLYPH_HEIGHT as f32 * scale) as u32,
                         ),
                         layer: *glyph_id as u32,
+                        color: WHITE,
                     });
                 }
                 GlyphRendererCommand::Text {
@@ -317,41 +331,30 @@ impl GlyphRenderer {
                     position,
                     anchor,
                     scale,
-                } => {
-                    let (screen_x, screen_y) =
-                        position.to_xy(display_width, display_height, *scale);
-                    let (glyph_x, glyph_y) = anchor.to_xy(
-                        ((text.chars().count() * GLYPH_WIDTH) as f32 * scale) as u32,
-                        (GLYPH_HEIGHT as f32 * scale) as u32,
-                    );
-                    let x = screen_x - glyph_x;
-                    let y = screen_y - glyph_y;
-
-                    for (chr_id, chr) in text.as_str().chars().enumerate() {
-                        let abs_x = x + ((GLYPH_WIDTH * chr_id) as f32 * scale) as i32;
-
-                        if abs_x >= display_width as i32 {
-                            // don't render past the edge of the screen
-                            break;
-                        }
-
-                        instances.push(GlyphInstance {
-                            position: screen_space_vertex_translate(
-                                display_width,
-                                display_height,
-                                abs_x,
-                                y,
-                            ),
-                            scale: screen_space_vertex_scale(
-                                display_width,
-                                display_height,
-                                (GLYPH_WIDTH as f32 * scale) as u32,
-                                (GLYPH_HEIGHT as f32 * scale) as u32,
-                            ),
-                            layer: chr as u32,
-                        });
-                    }
-                }
+                } => push_text(
+                    &mut instances,
+                    target_size,
+                    text,
+                    position,
+                    anchor,
+                    *scale,
+                    WHITE,
+                ),
+                GlyphRendererCommand::ColoredText {
+                    text,
+                    position,
+                    anchor,
+                    scale,
+                    color,
+                } => push_text(
+                    &mut instances,
+                    target_size,
+                    text,
+                    position,
+                    anchor,
+                    *scale,
+                    *color,
+                ),
             }
         }
 
@@ -375,6 +378,50 @@ impl GlyphRenderer {
         pass.set_vertex_buffer(0, state.quad_pipeline().vertex_buffer().slice(..));
         pass.set_vertex_buffer(1, state.glyph_pipeline().instance_buffer().slice(..));
         pass.set_bind_group(0, &self.const_bind_group, &[]);
-        pass.draw(0..6, 0..commands.len() as u32);
+        pass.draw(0..6, 0..instances.len() as u32);
+    }
+}
+
+// Lays out a line of text as glyph instances tinted by `color`.
+fn push_text(
+    instances: &mut Vec<GlyphInstance>,
+    target_size: Extent2d,
+    text: &str,
+    position: &ScreenPosition,
+    anchor: &Anchor,
+    scale: f32,
+    color: [f32; 3],
+) {
+    let Extent2d {
+        width: display_width,
+        height: display_height,
+    } = target_size;
+    let (screen_x, screen_y) = position.to_xy(display_width, display_height, scale);
+    let (glyph_x, glyph_y) = anchor.to_xy(
+        ((text.chars().count() * GLYPH_WIDTH) as f32 * scale) as u32,
+        (GLYPH_HEIGHT as f32 * scale) as u32,
+    );
+    let x = screen_x - glyph_x;
+    let y = screen_y - glyph_y;
+
+    for (chr_id, chr) in text.chars().enumerate() {
+        let abs_x = x + ((GLYPH_WIDTH * chr_id) as f32 * scale) as i32;
+
+        if abs_x >= display_width as i32 {
+            // don't render past the edge of the screen
+            break;
+        }
+
+        instances.push(GlyphInstance {
+            position: screen_space_vertex_translate(display_width, display_height, abs_x, y),
+            scale: screen_space_vertex_scale(
+                display_width,
+                display_height,
+                (GLYPH_WIDTH as f32 * scale) as u32,
+                (GLYPH_HEIGHT as f32 * scale) as u32,
+            ),
+            layer: chr as u32,
+            color,
+        });
     }
 }
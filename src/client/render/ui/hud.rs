@@ -4,15 +4,16 @@ use crate::{
     client::{
         render::{
             ui::{
-                glyph::GlyphRendererCommand,
+                glyph::{GlyphRendererCommand, GLYPH_HEIGHT, GLYPH_WIDTH},
                 layout::{Anchor, Layout, ScreenPosition, Size},
-                quad::{QuadRendererCommand, QuadTexture},
+                quad::{QuadRendererCommand, QuadTexture, NO_TINT},
             },
-            GraphicsState,
+            Extent2d, GraphicsState,
         },
         IntermissionKind,
     },
     common::{
+        console::CvarRegistry,
         net::{ClientStat, ItemFlags},
         wad::QPic,
     },
@@ -32,17 +33,326 @@ const OVERLAY_Y_OFS: i32 = -OVERLAY_HEIGHT / 2;
 
 const OVERLAY_ANCHOR: Anchor = Anchor::CENTER;
 
+// smallest allowed value of scr_sbarscale and scr_crosshairscale
+const MIN_HUD_SCALE: f32 = 0.5;
+
+// space between the mini HUD and the edges of the screen, in unscaled pixels
+const MINI_HUD_MARGIN: i32 = 4;
+
+// distance of HUD messages below the top of the view, in unscaled pixels
+const MESSAGE_Y_OFS: i32 = 16;
+
+// widths of the left and right groups of the mini HUD, in unscaled pixels
+const MINI_HUD_LEFT_WIDTH: i32 = 208;
+const MINI_HUD_RIGHT_WIDTH: i32 = 96;
+
+// opacity of the panels drawn behind HUD elements in high contrast mode, and how far they extend
+// past the elements in unscaled pixels
+const BACKING_ALPHA: f32 = 0.75;
+const BACKING_PAD: i32 = 2;
+
+/// The glyphs selectable with the `crosshair` cvar, starting from 1.
+pub const CROSSHAIR_GLYPHS: [u8; 5] = [b'+', b'x', b'o', b'*', b'.'];
+
+/// The width and height of the generated crosshair textures in unscaled pixels.
+pub const CROSSHAIR_TEXTURE_SIZE: u32 = 9;
+
+/// A crosshair shape drawn from a generated texture, so that it can be colored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, EnumIter)]
+pub enum CrosshairShape {
+    Dot,
+    Cross,
+    Circle,
+}
+
+/// A crosshair selected by the `crosshair` cvar.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Crosshair {
+    /// A character from the console font, drawn in its own colors.
+    Glyph(u8),
+
+    /// A shape drawn in the color given by `crosshaircolor`.
+    Shape(CrosshairShape),
+}
+
+impl Crosshair {
+    /// Returns the crosshair selected by a value of `crosshair`, or `None` if it is disabled.
+    ///
+    /// Values 1 through 5 select the glyphs in `CROSSHAIR_GLYPHS`, and 6 through 8 select a dot,
+    /// a cross and a circle.
+    pub fn from_value(value: f32) -> Option<Crosshair> {
+        let glyphs = CROSSHAIR_GLYPHS.len() as i32;
+        match value as i32 {
+            n if n >= 1 && n <= glyphs => Some(Crosshair::Glyph(CROSSHAIR_GLYPHS[n as usize - 1])),
+            n if n == glyphs + 1 => Some(Crosshair::Shape(CrosshairShape::Dot)),
+            n if n == glyphs + 2 => Some(Crosshair::Shape(CrosshairShape::Cross)),
+            n if n == glyphs + 3 => Some(Crosshair::Shape(CrosshairShape::Circle)),
+            _ => None,
+        }
+    }
+}
+
+/// Generates the alpha mask of a crosshair shape.
+///
+/// The mask is `CROSSHAIR_TEXTURE_SIZE` pixels square, with 0xFF where the shape is drawn and 0
+/// elsewhere.
+pub fn crosshair_mask(shape: CrosshairShape) -> Vec<u8> {
+    let size = CROSSHAIR_TEXTURE_SIZE as i32;
+    let center = size / 2;
+
+    let mut mask = Vec::with_capacity((size * size) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let (dx, dy) = (x - center, y - center);
+            let drawn = match shape {
+                CrosshairShape::Dot => dx.abs() <= 1 && dy.abs() <= 1,
+
+                // leave a gap in the middle so the target isn't covered
+                CrosshairShape::Cross => (dx == 0 || dy == 0) && dx.abs() + dy.abs() >= 2,
+
+                CrosshairShape::Circle => {
+                    let dist = ((dx * dx + dy * dy) as f32).sqrt();
+                    dist >= 3.0 && dist < 4.0
+                }
+            };
+
+            mask.push(if drawn { 0xFF } else { 0 });
+        }
+    }
+
+    mask
+}
+
+/// Parses a color given as red, green and blue components from 0 to 255, e.g. `"255 0 0"`.
+pub fn parse_color(value: &str) -> Option<[f32; 4]> {
+    let components = value
+        .split_whitespace()
+        .map(|c| c.parse::<f32>().ok().map(|c| c.max(0.0).min(255.0) / 255.0))
+        .collect::<Option<Vec<_>>>()?;
+
+    match components.as_slice() {
+        &[r, g, b] => Some([r, g, b, 1.0]),
+        _ => None,
+    }
+}
+
+/// The smallest allowed value of `viewsize`.
+pub const VIEWSIZE_MIN: f32 = 30.0;
+
+/// The largest allowed value of `viewsize`.
+pub const VIEWSIZE_MAX: f32 = 120.0;
+
+// the scene is never narrower than this, in unscaled pixels
+const VIEW_MIN_WIDTH: f32 = 96.0;
+
+/// Which parts of the status bar are drawn, as determined by `viewsize`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusBarMode {
+    /// Draw the status bar and the inventory bar above it.
+    Full,
+
+    /// Draw only the status bar.
+    StatusOnly,
+
+    /// Draw neither bar.
+    Hidden,
+}
+
+impl StatusBarMode {
+    pub fn from_viewsize(viewsize: f32) -> StatusBarMode {
+        if viewsize >= 120.0 {
+            StatusBarMode::Hidden
+        } else if viewsize >= 110.0 {
+            StatusBarMode::StatusOnly
+        } else {
+            StatusBarMode::Full
+        }
+    }
+
+    /// Returns the height of the visible bars in unscaled pixels.
+    pub fn lines(&self) -> u32 {
+        match *self {
+            StatusBarMode::Full => 48,
+            StatusBarMode::StatusOnly => 24,
+            StatusBarMode::Hidden => 0,
+        }
+    }
+}
+
+/// The layout of the in-game HUD, as selected by `scr_hudstyle`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HudStyle {
+    /// The status bar and inventory bar along the bottom of the screen.
+    Classic = 0,
+
+    /// Armor, health and ammo drawn without a background in the bottom corners of the scene.
+    Mini = 1,
+}
+
+impl HudStyle {
+    /// Returns the style selected by a value of `scr_hudstyle`.
+    pub fn from_value(value: f32) -> Option<HudStyle> {
+        match value as i32 {
+            0 => Some(HudStyle::Classic),
+            1 => Some(HudStyle::Mini),
+            _ => None,
+        }
+    }
+}
+
+impl std::default::Default for HudStyle {
+    fn default() -> Self {
+        HudStyle::Classic
+    }
+}
+
+/// HUD options read from cvars.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HudSettings {
+    pub style: HudStyle,
+
+    /// The scale of the status bar, inventory bar and mini HUD.
+    pub sbar_scale: f32,
+
+    /// The crosshair to draw, if any.
+    pub crosshair: Option<Crosshair>,
+
+    /// The color of crosshair shapes.
+    pub crosshair_color: [f32; 4],
+
+    /// The scale of the crosshair, combining `scr_crosshairscale` and `crosshairsize`.
+    pub crosshair_scale: f32,
+
+    /// The offset of the crosshair from the center of the view, in unscaled pixels.
+    pub crosshair_offset: (i32, i32),
+
+    /// The widest aspect ratio of the area the mini HUD is drawn in, or 0 for the whole screen.
+    pub safe_aspect: f32,
+
+    /// Whether dark panels are drawn behind the mini HUD and messages, which otherwise sit
+    /// directly on the scene.
+    pub high_contrast: bool,
+}
+
+impl HudSettings {
+    pub fn from_cvars(cvars: &CvarRegistry) -> HudSettings {
+        let value = |name, default| cvars.get_value(name).unwrap_or(default);
+
+        HudSettings {
+            style: HudStyle::from_value(value("scr_hudstyle", 0.0)).unwrap_or_default(),
+            sbar_scale: value("scr_sbarscale", 2.0).max(MIN_HUD_SCALE),
+            crosshair: Crosshair::from_value(value("crosshair", 1.0)),
+            crosshair_color: cvars
+                .get("crosshaircolor")
+                .ok()
+                .and_then(|c| parse_color(&c))
+                .unwrap_or(NO_TINT),
+            crosshair_scale: value("scr_crosshairscale", 2.0).max(MIN_HUD_SCALE)
+                * value("crosshairsize", 1.0).max(MIN_HUD_SCALE),
+            crosshair_offset: (
+                value("cl_crossx", 0.0) as i32,
+                value("cl_crossy", 0.0) as i32,
+            ),
+            safe_aspect: value("scr_safearea", 0.0).max(0.0),
+            high_contrast: value("scr_highcontrast", 0.0) != 0.0,
+        }
+    }
+
+    /// Returns the distance in pixels that HUD elements in the corners are pulled in from each
+    /// side of `display` to stay within the safe area.
+    pub fn safe_inset(&self, display: Extent2d) -> u32 {
+        if self.safe_aspect <= 0.0 {
+            return 0;
+        }
+
+        let safe_width = (display.height as f32 * self.safe_aspect) as u32;
+        display.width.saturating_sub(safe_width) / 2
+    }
+
+    /// Returns the part of the screen bottom that the status bar keeps the scene out of.
+    ///
+    /// The mini HUD is drawn over the scene, so it never takes any space from it.
+    pub fn reserved_status_bar(&self, status_bar: StatusBarMode) -> StatusBarMode {
+        match self.style {
+            HudStyle::Classic => status_bar,
+            HudStyle::Mini => StatusBarMode::Hidden,
+        }
+    }
+}
+
+/// The region of the screen in which the scene is drawn.
+///
+/// Coordinates are in pixels, relative to the top-left corner of the screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ViewRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ViewRect {
+    /// Calculates the view rectangle for the given value of `viewsize`.
+    ///
+    /// At 100 and above, the scene fills the screen above the visible status bar. Below 100, the
+    /// scene shrinks toward the center of that space and is surrounded by a border.
+    pub fn from_viewsize(
+        display: Extent2d,
+        viewsize: f32,
+        status_bar: StatusBarMode,
+        scale: f32,
+    ) -> ViewRect {
+        let size = viewsize.max(VIEWSIZE_MIN).min(100.0) / 100.0;
+        let sbar_height = ((status_bar.lines() as f32 * scale) as u32).min(display.height);
+        let available_height = display.height - sbar_height;
+
+        let width = ((display.width as f32 * size) as u32)
+            .max((VIEW_MIN_WIDTH * scale) as u32)
+            .min(display.width);
+        let height = ((display.height as f32 * size) as u32).min(available_height);
+
+        ViewRect {
+            x: (display.width - width) / 2,
+            y: (available_height - height) / 2,
+            width,
+            height,
+        }
+    }
+
+    /// Returns a rectangle covering the entire display.
+    pub fn full(display: Extent2d) -> ViewRect {
+        ViewRect {
+            x: 0,
+            y: 0,
+            width: display.width,
+            height: display.height,
+        }
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width as f32 / self.height.max(1) as f32
+    }
+}
+
 pub enum HudState<'a> {
     InGame {
         items: ItemFlags,
         item_pickup_time: &'a [Duration],
         stats: &'a [i32],
         face_anim_time: Duration,
+        paused: bool,
+        status_bar: StatusBarMode,
+        view: ViewRect,
+        settings: HudSettings,
+
+        /// A short notice shown at the top of the view, such as a quicksave confirmation.
+        message: Option<&'a str>,
     },
     Intermission {
         kind: &'a IntermissionKind,
         completion_duration: Duration,
         stats: &'a [i32],
+        scale: f32,
     },
 }
 
@@ -61,10 +371,12 @@ enum HudTextureId {
     StatusBar,
     InvBar,
     ScoreBar,
+    BackTile,
 
     // these are not in gfx.wad
     Complete,
     Intermission,
+    Pause,
 }
 
 impl std::fmt::Display for HudTextureId {
@@ -84,10 +396,12 @@ impl std::fmt::Display for HudTextureId {
             StatusBar => write!(f, "SBAR"),
             InvBar => write!(f, "IBAR"),
             ScoreBar => write!(f, "SCOREBAR"),
+            BackTile => write!(f, "BACKTILE"),
 
             // these are not in gfx.wad
             Complete => write!(f, "gfx/complete.lmp"),
             Intermission => write!(f, "gfx/inter.lmp"),
+            Pause => write!(f, "gfx/pause.lmp"),
         }
     }
 }
@@ -187,6 +501,10 @@ impl std::fmt::Display for FaceId {
 
 pub struct HudRenderer {
     textures: HashMap<HudTextureId, QuadTexture>,
+    crosshairs: HashMap<CrosshairShape, QuadTexture>,
+
+    // 1x1 white texture, tinted to draw the panels behind HUD elements
+    blank: QuadTexture,
 }
 
 impl HudRenderer {
@@ -241,7 +559,7 @@ impl HudRenderer {
         );
 
         // unit variants
-        ids.extend(vec![Colon, Slash, StatusBar, InvBar, ScoreBar].into_iter());
+        ids.extend(vec![Colon, Slash, StatusBar, InvBar, ScoreBar, BackTile].into_iter());
 
         let mut textures = HashMap::new();
         for id in ids.into_iter() {
@@ -252,14 +570,74 @@ impl HudRenderer {
         }
 
         // new id list for textures not in gfx.wad
-        let ids = vec![Complete, Intermission];
+        let ids = vec![Complete, Intermission, Pause];
         for id in ids.into_iter() {
             debug!("Opening {}", id);
             let qpic = QPic::load(state.vfs().open(&format!("{}", id)).unwrap()).unwrap();
             textures.insert(id, QuadTexture::from_qpic(state, &qpic));
         }
 
-        HudRenderer { textures }
+        // crosshair shapes are white so they can be tinted
+        let crosshairs = CrosshairShape::iter()
+            .map(|shape| {
+                let rgba: Vec<u8> = crosshair_mask(shape)
+                    .into_iter()
+                    .flat_map(|a| vec![0xFF, 0xFF, 0xFF, a])
+                    .collect();
+                let size = CROSSHAIR_TEXTURE_SIZE;
+                (shape, QuadTexture::from_rgba(state, size, size, &rgba))
+            })
+            .collect();
+
+        HudRenderer {
+            textures,
+            crosshairs,
+            blank: QuadTexture::from_rgba(state, 1, 1, &[0xFF; 4]),
+        }
+    }
+
+    // Draw a dark panel behind other HUD elements.
+    //
+    // `x` and `y` are the bottom-left corner of the panel in pixels, from the bottom-left corner
+    // of the screen.
+    fn cmd_backing<'a>(
+        &'a self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+    ) {
+        quad_cmds.push(QuadRendererCommand {
+            texture: &self.blank,
+            layout: Layout {
+                position: ScreenPosition::Absolute(Anchor::absolute_xy(x, y)),
+                anchor: Anchor::BOTTOM_LEFT,
+                size: Size::Absolute { width, height },
+            },
+            tint: [0.0, 0.0, 0.0, BACKING_ALPHA],
+        });
+    }
+
+    // Draw panels behind both groups of the mini HUD.
+    fn cmd_mini_hud_backing<'a>(
+        &'a self,
+        display: Extent2d,
+        inset: i32,
+        scale: f32,
+        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+    ) {
+        let px = |units: i32| (units as f32 * scale) as i32;
+        let y = px(MINI_HUD_MARGIN - BACKING_PAD);
+        let height = px(24 + 2 * BACKING_PAD) as u32;
+        let h = MINI_HUD_MARGIN + inset - BACKING_PAD;
+
+        let left_width = px(MINI_HUD_LEFT_WIDTH + 2 * BACKING_PAD);
+        self.cmd_backing(px(h), y, left_width as u32, height, quad_cmds);
+
+        let right_width = px(MINI_HUD_RIGHT_WIDTH + 2 * BACKING_PAD);
+        let right_x = display.width as i32 - px(h) - right_width;
+        self.cmd_backing(right_x, y, right_width as u32, height, quad_cmds);
     }
 
     fn cmd_number<'a>(
@@ -308,6 +686,7 @@ impl HudRenderer {
                     anchor: quad_anchor,
                     size: Size::Scale { factor: scale },
                 },
+                tint: NO_TINT,
             });
         }
     }
@@ -335,6 +714,7 @@ impl HudRenderer {
                 anchor: Anchor::BOTTOM_LEFT,
                 size: Size::Scale { factor: scale },
             },
+            tint: NO_TINT,
         });
     }
 
@@ -370,11 +750,152 @@ impl HudRenderer {
         &'a self,
         time: Duration,
         items: ItemFlags,
-        item_pickup_time: &'a [Duration],
         stats: &'a [i32],
         face_anim_time: Duration,
         scale: f32,
         quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+    ) {
+        use HudTextureId::*;
+
+        // status bar background
+        self.cmd_sbar_quad(StatusBar, 0, 0, scale, quad_cmds);
+
+        // armor
+        let armor_width = self.textures.get(&Armor { id: 0 }).unwrap().width() as i32;
+        if items.contains(ItemFlags::INVULNERABILITY) {
+            self.cmd_sbar_number(666, true, 3, armor_width, 0, scale, quad_cmds);
+        // TODO draw_disc
+        } else {
+            let armor = stats[ClientStat::Armor as usize];
+            self.cmd_sbar_number(armor, armor <= 25, 3, armor_width, 0, scale, quad_cmds);
+
+            if let Some(a) = armor_texture_id(items) {
+                self.cmd_sbar_quad(a, 0, 0, scale, quad_cmds);
+            }
+        }
+
+        // health
+        let health = stats[ClientStat::Health as usize];
+        self.cmd_sbar_number(health, health <= 25, 3, 136, 0, scale, quad_cmds);
+
+        let ammo = stats[ClientStat::Ammo as usize];
+        self.cmd_sbar_number(ammo, ammo <= 10, 3, 248, 0, scale, quad_cmds);
+
+        let face = face_id(time, items, stats, face_anim_time);
+        self.cmd_sbar_quad(Face { id: face }, 112, 0, scale, quad_cmds);
+    }
+
+    // Draw a quad relative to a corner of the screen.
+    fn cmd_corner_quad<'a>(
+        &'a self,
+        texture_id: HudTextureId,
+        corner: Anchor,
+        x_ofs: i32,
+        y_ofs: i32,
+        scale: f32,
+        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+    ) {
+        quad_cmds.push(QuadRendererCommand {
+            texture: self.textures.get(&texture_id).unwrap(),
+            layout: Layout {
+                position: ScreenPosition::Relative {
+                    anchor: corner,
+                    x_ofs,
+                    y_ofs,
+                },
+                anchor: corner,
+                size: Size::Scale { factor: scale },
+            },
+            tint: NO_TINT,
+        });
+    }
+
+    // Draw armor and health in the bottom-left corner and ammo in the bottom-right corner,
+    // without the status bar background.
+    //
+    // `inset` moves both sides toward the center, in unscaled pixels.
+    fn cmd_mini_hud<'a>(
+        &'a self,
+        time: Duration,
+        items: ItemFlags,
+        stats: &'a [i32],
+        face_anim_time: Duration,
+        inset: i32,
+        scale: f32,
+        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+    ) {
+        use HudTextureId::*;
+
+        let m = MINI_HUD_MARGIN;
+        // horizontal margin
+        let h = MINI_HUD_MARGIN + inset;
+        let left = Anchor::BOTTOM_LEFT;
+        let right = Anchor::BOTTOM_RIGHT;
+
+        // armor
+        if items.contains(ItemFlags::INVULNERABILITY) {
+            self.cmd_number(666, true, 3, left, h + 24, m, left, scale, quad_cmds);
+        } else {
+            let armor = stats[ClientStat::Armor as usize];
+            if let Some(a) = armor_texture_id(items) {
+                self.cmd_corner_quad(a, left, h, m, scale, quad_cmds);
+                self.cmd_number(
+                    armor,
+                    armor <= 25,
+                    3,
+                    left,
+                    h + 24,
+                    m,
+                    left,
+                    scale,
+                    quad_cmds,
+                );
+            }
+        }
+
+        // health
+        let health = stats[ClientStat::Health as usize];
+        let face = face_id(time, items, stats, face_anim_time);
+        self.cmd_corner_quad(Face { id: face }, left, h + 112, m, scale, quad_cmds);
+        self.cmd_number(
+            health,
+            health <= 25,
+            3,
+            left,
+            h + 136,
+            m,
+            left,
+            scale,
+            quad_cmds,
+        );
+
+        // ammo for the current weapon
+        if let Some(id) = ammo_id(items) {
+            let ammo = stats[ClientStat::Ammo as usize];
+            self.cmd_number(
+                ammo,
+                ammo <= 10,
+                3,
+                right,
+                -h - 72,
+                m,
+                left,
+                scale,
+                quad_cmds,
+            );
+            self.cmd_corner_quad(Ammo { id }, right, -h - 72, m, scale, quad_cmds);
+        }
+    }
+
+    // Draw the inventory bar above the status bar.
+    fn cmd_inventory<'a>(
+        &'a self,
+        time: Duration,
+        items: ItemFlags,
+        item_pickup_time: &'a [Duration],
+        stats: &'a [i32],
+        scale: f32,
+        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
         glyph_cmds: &mut Vec<GlyphRendererCommand>,
     ) {
         use HudTextureId::*;
@@ -382,9 +903,6 @@ impl HudRenderer {
         let sbar = self.textures.get(&StatusBar).unwrap();
         let sbar_x_ofs = -(sbar.width() as i32) / 2;
 
-        // status bar background
-        self.cmd_sbar_quad(StatusBar, 0, 0, scale, quad_cmds);
-
         // inventory bar background
         self.cmd_sbar_quad(InvBar, 0, sbar.height() as i32, scale, quad_cmds);
 
@@ -456,6 +974,7 @@ impl HudRenderer {
                         anchor: Anchor::BOTTOM_LEFT,
                         size: Size::Scale { factor: scale },
                     },
+                    tint: NO_TINT,
                 })
             }
         }
@@ -474,69 +993,158 @@ impl HudRenderer {
                         anchor: Anchor::BOTTOM_LEFT,
                         size: Size::Scale { factor: scale },
                     },
+                    tint: NO_TINT,
                 });
             }
         }
+    }
 
-        // armor
-        let armor_width = self.textures.get(&Armor { id: 0 }).unwrap().width() as i32;
-        if items.contains(ItemFlags::INVULNERABILITY) {
-            self.cmd_sbar_number(666, true, 3, armor_width, 0, scale, quad_cmds);
-        // TODO draw_disc
-        } else {
-            let armor = stats[ClientStat::Armor as usize];
-            self.cmd_sbar_number(armor, armor <= 25, 3, armor_width, 0, scale, quad_cmds);
+    // Draw the crosshair at the center of the view.
+    fn cmd_crosshair<'a>(
+        &'a self,
+        view: ViewRect,
+        display: Extent2d,
+        settings: &HudSettings,
+        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+        glyph_cmds: &mut Vec<GlyphRendererCommand>,
+    ) {
+        // layout coordinates start from the bottom of the screen
+        let x = (view.x + view.width / 2) as i32;
+        let y = (display.height - (view.y + view.height / 2)) as i32;
+        let (x_ofs, y_ofs) = settings.crosshair_offset;
+        let position = ScreenPosition::Relative {
+            anchor: Anchor::absolute_xy(x, y),
+            x_ofs,
+            y_ofs: -y_ofs,
+        };
 
-            let mut armor_id = None;
-            for i in (0..3).rev() {
-                if items.contains(ItemFlags::from_bits(ItemFlags::ARMOR_1.bits() << i).unwrap()) {
-                    armor_id = Some(Armor { id: i });
-                    break;
-                }
-            }
+        match settings.crosshair {
+            None => (),
 
-            if let Some(a) = armor_id {
-                self.cmd_sbar_quad(a, 0, 0, scale, quad_cmds);
-            }
-        }
+            Some(Crosshair::Glyph(glyph_id)) => glyph_cmds.push(GlyphRendererCommand::Glyph {
+                glyph_id,
+                position,
+                anchor: Anchor::CENTER,
+                scale: settings.crosshair_scale,
+            }),
 
-        // health
-        let health = stats[ClientStat::Health as usize];
-        self.cmd_sbar_number(health, health <= 25, 3, 136, 0, scale, quad_cmds);
+            Some(Crosshair::Shape(shape)) => quad_cmds.push(QuadRendererCommand {
+                texture: self.crosshairs.get(&shape).unwrap(),
+                layout: Layout {
+                    position,
+                    anchor: Anchor::CENTER,
+                    size: Size::Scale {
+                        factor: settings.crosshair_scale,
+                    },
+                },
+                tint: settings.crosshair_color,
+            }),
+        }
+    }
 
-        let ammo = stats[ClientStat::Ammo as usize];
-        self.cmd_sbar_number(ammo, ammo <= 10, 3, 248, 0, scale, quad_cmds);
+    // Draw a message centered at the top of the view, on a panel in high contrast mode.
+    fn cmd_message<'a>(
+        &'a self,
+        message: &str,
+        view: ViewRect,
+        display: Extent2d,
+        settings: &HudSettings,
+        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+        glyph_cmds: &mut Vec<GlyphRendererCommand>,
+    ) {
+        let scale = settings.sbar_scale;
+
+        // layout coordinates start from the bottom of the screen
+        let x = (view.x + view.width / 2) as i32;
+        let y = (display.height - view.y) as i32;
+
+        if settings.high_contrast {
+            let columns = message
+                .lines()
+                .map(|l| l.chars().count())
+                .max()
+                .unwrap_or(0);
+            let rows = message.lines().count();
+            let px = |units: i32| (units as f32 * scale) as i32;
+
+            let width = px((columns * GLYPH_WIDTH) as i32 + 2 * BACKING_PAD);
+            let height = px((rows * GLYPH_HEIGHT) as i32 + 2 * BACKING_PAD);
+            let top = y - px(MESSAGE_Y_OFS - BACKING_PAD);
+            self.cmd_backing(
+                x - width / 2,
+                top - height,
+                width as u32,
+                height as u32,
+                quad_cmds,
+            );
+        }
 
-        let face = if items.contains(ItemFlags::INVISIBILITY | ItemFlags::INVULNERABILITY) {
-            FaceId::InvisibleInvulnerable
-        } else if items.contains(ItemFlags::QUAD) {
-            FaceId::QuadDamage
-        } else if items.contains(ItemFlags::INVISIBILITY) {
-            FaceId::Invisible
-        } else if items.contains(ItemFlags::INVULNERABILITY) {
-            FaceId::Invulnerable
-        } else {
-            let health = stats[ClientStat::Health as usize];
-            let frame = 4 - if health >= 100 {
-                4
-            } else {
-                health.max(0) as usize / 20
-            };
+        glyph_cmds.push(GlyphRendererCommand::Text {
+            text: message.to_owned(),
+            position: ScreenPosition::Relative {
+                anchor: Anchor::absolute_xy(x, y),
+                x_ofs: 0,
+                y_ofs: -MESSAGE_Y_OFS,
+            },
+            anchor: Anchor::TOP_CENTER,
+            scale,
+        });
+    }
 
-            FaceId::Normal {
-                pain: face_anim_time > time,
-                frame,
+    // Fill the area around the view with the background tile.
+    fn cmd_border<'a>(
+        &'a self,
+        view: ViewRect,
+        display: Extent2d,
+        scale: f32,
+        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+    ) {
+        let view_right = view.x + view.width;
+        let view_bottom = view.y + view.height;
+
+        // (x, y, width, height) from the top-left corner of the screen
+        let strips = [
+            (0, 0, display.width, view.y),
+            (0, view_bottom, display.width, display.height - view_bottom),
+            (0, view.y, view.x, view.height),
+            (view_right, view.y, display.width - view_right, view.height),
+        ];
+
+        let texture = self.textures.get(&HudTextureId::BackTile).unwrap();
+        for &(x, y, width, height) in strips.iter() {
+            if width == 0 || height == 0 {
+                continue;
             }
-        };
 
-        self.cmd_sbar_quad(Face { id: face }, 112, 0, scale, quad_cmds);
+            quad_cmds.push(QuadRendererCommand {
+                texture,
+                layout: Layout {
+                    position: ScreenPosition::Absolute(Anchor::absolute_xy(
+                        x as i32,
+                        (display.height - y) as i32,
+                    )),
+                    anchor: Anchor::TOP_LEFT,
+                    size: Size::Tiled {
+                        width,
+                        height,
+                        factor: scale,
+                    },
+                },
+                tint: NO_TINT,
+            });
+        }
+    }
 
-        // crosshair
-        glyph_cmds.push(GlyphRendererCommand::Glyph {
-            glyph_id: '+' as u8,
-            position: ScreenPosition::Absolute(Anchor::CENTER),
-            anchor: Anchor::TOP_LEFT,
-            scale,
+    // Draw the pause plaque in the center of the screen.
+    fn cmd_pause<'a>(&'a self, scale: f32, quad_cmds: &mut Vec<QuadRendererCommand<'a>>) {
+        quad_cmds.push(QuadRendererCommand {
+            texture: self.textures.get(&HudTextureId::Pause).unwrap(),
+            layout: Layout {
+                position: ScreenPosition::Absolute(Anchor::CENTER),
+                anchor: Anchor::CENTER,
+                size: Size::Scale { factor: scale },
+            },
+            tint: NO_TINT,
         });
     }
 
@@ -563,6 +1171,7 @@ impl HudRenderer {
                 anchor: Anchor::TOP_LEFT,
                 size: Size::Scale { factor: scale },
             },
+            tint: NO_TINT,
         });
     }
 
@@ -638,33 +1247,243 @@ impl HudRenderer {
         &'a self,
         hud_state: &HudState<'a>,
         time: Duration,
+        display: Extent2d,
         quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
         glyph_cmds: &mut Vec<GlyphRendererCommand>,
     ) {
-        // TODO: get from cvar
-        let scale = 2.0;
-
         match hud_state {
             HudState::InGame {
                 items,
                 item_pickup_time,
                 stats,
                 face_anim_time,
-            } => self.cmd_sbar(
-                time,
-                *items,
-                item_pickup_time,
-                stats,
-                *face_anim_time,
-                scale,
-                quad_cmds,
-                glyph_cmds,
-            ),
+                paused,
+                status_bar,
+                view,
+                settings,
+                message,
+            } => {
+                let scale = settings.sbar_scale;
+                self.cmd_border(*view, display, scale, quad_cmds);
+
+                if settings.style == HudStyle::Mini {
+                    if *status_bar != StatusBarMode::Hidden {
+                        let inset = (settings.safe_inset(display) as f32 / scale) as i32;
+                        if settings.high_contrast {
+                            self.cmd_mini_hud_backing(display, inset, scale, quad_cmds);
+                        }
+                        self.cmd_mini_hud(
+                            time,
+                            *items,
+                            stats,
+                            *face_anim_time,
+                            inset,
+                            scale,
+                            quad_cmds,
+                        );
+                    }
+                } else if *status_bar != StatusBarMode::Hidden {
+                    self.cmd_sbar(time, *items, stats, *face_anim_time, scale, quad_cmds);
+                }
+
+                if settings.style == HudStyle::Classic && *status_bar == StatusBarMode::Full {
+                    self.cmd_inventory(
+                        time,
+                        *items,
+                        item_pickup_time,
+                        stats,
+                        scale,
+                        quad_cmds,
+                        glyph_cmds,
+                    );
+                }
+
+                self.cmd_crosshair(*view, display, settings, quad_cmds, glyph_cmds);
+
+                if let Some(m) = message {
+                    self.cmd_message(m, *view, display, settings, quad_cmds, glyph_cmds);
+                }
+
+                if *paused {
+                    self.cmd_pause(scale, quad_cmds);
+                }
+            }
             HudState::Intermission {
                 kind,
                 completion_duration,
                 stats,
-            } => self.cmd_intermission_overlay(kind, *completion_duration, stats, scale, quad_cmds),
+                scale,
+            } => {
+                self.cmd_intermission_overlay(kind, *completion_duration, stats, *scale, quad_cmds)
+            }
+        }
+    }
+}
+
+// Returns the armor icon for the best armor the player has, if any.
+fn armor_texture_id(items: ItemFlags) -> Option<HudTextureId> {
+    (0..3).rev().find_map(|i| {
+        if items.contains(ItemFlags::from_bits(ItemFlags::ARMOR_1.bits() << i).unwrap()) {
+            Some(HudTextureId::Armor { id: i })
+        } else {
+            None
         }
+    })
+}
+
+// Returns the type of ammo used by the current weapon, if any.
+fn ammo_id(items: ItemFlags) -> Option<AmmoId> {
+    (0..4).find_map(|i| {
+        if items.contains(ItemFlags::from_bits(ItemFlags::SHELLS.bits() << i).unwrap()) {
+            AmmoId::from_usize(i)
+        } else {
+            None
+        }
+    })
+}
+
+// Returns the face to draw for the player's health and powerups.
+fn face_id(time: Duration, items: ItemFlags, stats: &[i32], face_anim_time: Duration) -> FaceId {
+    if items.contains(ItemFlags::INVISIBILITY | ItemFlags::INVULNERABILITY) {
+        FaceId::InvisibleInvulnerable
+    } else if items.contains(ItemFlags::QUAD) {
+        FaceId::QuadDamage
+    } else if items.contains(ItemFlags::INVISIBILITY) {
+        FaceId::Invisible
+    } else if items.contains(ItemFlags::INVULNERABILITY) {
+        FaceId::Invulnerable
+    } else {
+        let health = stats[ClientStat::Health as usize];
+        let frame = 4 - if health >= 100 {
+            4
+        } else {
+            health.max(0) as usize / 20
+        };
+
+        FaceId::Normal {
+            pain: face_anim_time > time,
+            frame,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safe_inset() {
+        let cvars = CvarRegistry::new();
+        cvars.register("scr_safearea", "0", "").unwrap();
+
+        let display = Extent2d {
+            width: 5120,
+            height: 1440,
+        };
+        let mut settings = HudSettings::from_cvars(&cvars);
+        assert_eq!(settings.safe_inset(display), 0);
+
+        // a 32:9 display with a 16:9 safe area
+        cvars.set("scr_safearea", "1.7777778").unwrap();
+        settings = HudSettings::from_cvars(&cvars);
+        assert_eq!(settings.safe_inset(display), 1280);
+
+        // never pushed outward on narrower displays
+        let display = Extent2d {
+            width: 1024,
+            height: 768,
+        };
+        assert_eq!(settings.safe_inset(display), 0);
+    }
+
+    #[test]
+    fn test_view_rect_from_viewsize() {
+        let display = Extent2d {
+            width: 640,
+            height: 400,
+        };
+
+        // full width, above the status and inventory bars
+        assert_eq!(
+            ViewRect::from_viewsize(display, 100.0, StatusBarMode::Full, 1.0),
+            ViewRect {
+                x: 0,
+                y: 0,
+                width: 640,
+                height: 352,
+            }
+        );
+
+        // centered in the space above the bars
+        assert_eq!(
+            ViewRect::from_viewsize(display, 50.0, StatusBarMode::Full, 1.0),
+            ViewRect {
+                x: 160,
+                y: 76,
+                width: 320,
+                height: 200,
+            }
+        );
+
+        // clamped to the minimum size
+        assert_eq!(
+            ViewRect::from_viewsize(display, 0.0, StatusBarMode::Full, 2.0),
+            ViewRect::from_viewsize(display, VIEWSIZE_MIN, StatusBarMode::Full, 2.0)
+        );
+
+        assert_eq!(
+            ViewRect::from_viewsize(display, 120.0, StatusBarMode::Hidden, 2.0),
+            ViewRect::full(display)
+        );
+    }
+
+    #[test]
+    fn test_crosshair_from_value() {
+        assert_eq!(Crosshair::from_value(0.0), None);
+        assert_eq!(Crosshair::from_value(-1.0), None);
+        assert_eq!(Crosshair::from_value(1.0), Some(Crosshair::Glyph(b'+')));
+        assert_eq!(Crosshair::from_value(5.0), Some(Crosshair::Glyph(b'.')));
+        assert_eq!(
+            Crosshair::from_value(6.0),
+            Some(Crosshair::Shape(CrosshairShape::Dot))
+        );
+        assert_eq!(
+            Crosshair::from_value(8.0),
+            Some(Crosshair::Shape(CrosshairShape::Circle))
+        );
+        assert_eq!(Crosshair::from_value(9.0), None);
+    }
+
+    #[test]
+    fn test_crosshair_mask() {
+        let size = CROSSHAIR_TEXTURE_SIZE as usize;
+        let center = size / 2 * size + size / 2;
+        let count = |mask: &[u8]| mask.iter().filter(|&&a| a != 0).count();
+
+        let dot = crosshair_mask(CrosshairShape::Dot);
+        assert_eq!(count(&dot), 9);
+        assert_eq!(dot[center], 0xFF);
+
+        // four arms of three pixels around an empty center
+        let cross = crosshair_mask(CrosshairShape::Cross);
+        assert_eq!(count(&cross), 12);
+        assert_eq!(cross[center], 0);
+
+        // the circle is symmetric and hollow
+        let circle = crosshair_mask(CrosshairShape::Circle);
+        assert_eq!(circle[center], 0);
+        for y in 0..size {
+            for x in 0..size {
+                assert_eq!(circle[y * size + x], circle[x * size + (size - 1 - y)]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("255 0 51"), Some([1.0, 0.0, 0.2, 1.0]));
+        assert_eq!(parse_color("300 -5 0"), Some([1.0, 0.0, 0.0, 1.0]));
+        assert_eq!(parse_color("255 255"), None);
+        assert_eq!(parse_color("red"), None);
     }
 }
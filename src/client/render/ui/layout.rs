@@ -171,6 +171,18 @@ pub enum Size {
         /// The ratio of the display size at which to render the quad.
         ratio: f32,
     },
+
+    /// Render the quad at an exact size in pixels, repeating its texture to fill it.
+    Tiled {
+        /// The width of the quad in pixels.
+        width: u32,
+
+        /// The height of the quad in pixels.
+        height: u32,
+
+        /// The factor to multiply by the texture dimensions to determine the size of each tile.
+        factor: f32,
+    },
 }
 
 impl Size {
@@ -182,7 +194,7 @@ impl Size {
         display_height: u32,
     ) -> (u32, u32) {
         match *self {
-            Size::Absolute { width, height } => (width, height),
+            Size::Absolute { width, height } | Size::Tiled { width, height, .. } => (width, height),
             Size::Scale { factor } => (
                 (texture_width as f32 * factor) as u32,
                 (texture_height as f32 * factor) as u32,
@@ -7,7 +7,7 @@ use crate::{
             ui::{
                 glyph::{GlyphRendererCommand, GLYPH_HEIGHT, GLYPH_WIDTH},
                 layout::{Anchor, Layout, ScreenPosition, Size},
-                quad::{QuadRendererCommand, QuadTexture},
+                quad::{QuadRendererCommand, QuadTexture, NO_TINT},
             },
             GraphicsState,
         },
@@ -119,6 +119,7 @@ impl MenuRenderer {
                 anchor: align.anchor(),
                 size: Size::Scale { factor: scale },
             },
+            tint: NO_TINT,
         });
     }
 
@@ -12,11 +12,12 @@ use crate::{
         menu::Menu,
         render::{
             ui::{
-                console::ConsoleRenderer,
+                console::{ConsoleRenderer, ConsoleSettings},
                 glyph::{GlyphRenderer, GlyphRendererCommand},
                 hud::{HudRenderer, HudState},
+                layout::{Anchor, Layout, ScreenPosition, Size},
                 menu::MenuRenderer,
-                quad::{QuadRenderer, QuadRendererCommand, QuadUniforms},
+                quad::{QuadRenderer, QuadRendererCommand, QuadTexture, QuadUniforms},
             },
             uniform::{self, DynamicUniformBufferBlock},
             Extent2d, GraphicsState,
@@ -75,7 +76,38 @@ pub fn screen_space_vertex_transform(
 
 pub enum UiOverlay<'a> {
     Menu(&'a Menu),
-    Console(&'a Console),
+    Console(&'a Console, ConsoleSettings<'a>),
+}
+
+/// The effect used to reveal a new level, as selected by `scr_transition`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transition {
+    /// The new level appears immediately.
+    None = 0,
+
+    /// The screen fades in from black.
+    Fade = 1,
+
+    /// A black curtain is raised from the bottom of the screen to the top.
+    Wipe = 2,
+}
+
+impl Transition {
+    /// Returns the transition selected by a value of `scr_transition`.
+    pub fn from_value(value: f32) -> Option<Transition> {
+        match value as i32 {
+            0 => Some(Transition::None),
+            1 => Some(Transition::Fade),
+            2 => Some(Transition::Wipe),
+            _ => None,
+        }
+    }
+}
+
+impl std::default::Default for Transition {
+    fn default() -> Self {
+        Transition::Fade
+    }
 }
 
 pub enum UiState<'a> {
@@ -85,6 +117,12 @@ pub enum UiState<'a> {
     InGame {
         hud: HudState<'a>,
         overlay: Option<UiOverlay<'a>>,
+
+        /// The level transition in progress and the fraction of it remaining, from 1 to 0.
+        transition: Option<(Transition, f32)>,
+
+        /// The console whose recent output is shown at the top of the screen.
+        notify: Option<(&'a Console, ConsoleSettings<'a>)>,
     },
 }
 
@@ -94,6 +132,9 @@ pub struct UiRenderer {
     hud_renderer: HudRenderer,
     glyph_renderer: GlyphRenderer,
     quad_renderer: QuadRenderer,
+
+    // 1x1 white texture, tinted black to cover the screen during transitions
+    blank: QuadTexture,
 }
 
 impl UiRenderer {
@@ -104,6 +145,7 @@ impl UiRenderer {
             hud_renderer: HudRenderer::new(state),
             glyph_renderer: GlyphRenderer::new(state),
             quad_renderer: QuadRenderer::new(state),
+            blank: QuadTexture::from_rgba(state, 1, 1, &[0xFF; 4]),
         }
     }
 
@@ -117,14 +159,56 @@ impl UiRenderer {
         quad_commands: &'pass mut Vec<QuadRendererCommand<'pass>>,
         glyph_commands: &'pass mut Vec<GlyphRendererCommand>,
     ) {
-        let (hud_state, overlay) = match ui_state {
-            UiState::Title { overlay } => (None, Some(overlay)),
-            UiState::InGame { hud, overlay } => (Some(hud), overlay.as_ref()),
+        let (hud_state, overlay, transition, notify) = match ui_state {
+            UiState::Title { overlay } => (None, Some(overlay), None, None),
+            UiState::InGame {
+                hud,
+                overlay,
+                transition,
+                notify,
+            } => (Some(hud), overlay.as_ref(), *transition, notify.as_ref()),
         };
 
         if let Some(hstate) = hud_state {
-            self.hud_renderer
-                .generate_commands(hstate, time, quad_commands, glyph_commands);
+            self.hud_renderer.generate_commands(
+                hstate,
+                time,
+                target_size,
+                quad_commands,
+                glyph_commands,
+            );
+        }
+
+        if let Some((console, settings)) = notify {
+            self.console_renderer.generate_notify_commands(
+                console,
+                settings,
+                quad_commands,
+                glyph_commands,
+            );
+        }
+
+        // cover the scene and HUD, but not the console or menu
+        if let Some((kind, remaining)) = transition {
+            let remaining = remaining.max(0.0).min(1.0);
+            let Extent2d { width, height } = target_size;
+            let (height, alpha) = match kind {
+                Transition::None => (0, 0.0),
+                Transition::Fade => (height, remaining),
+                Transition::Wipe => ((height as f32 * remaining) as u32, 1.0),
+            };
+
+            if height > 0 && alpha > 0.0 {
+                quad_commands.push(QuadRendererCommand {
+                    texture: &self.blank,
+                    layout: Layout {
+                        position: ScreenPosition::Absolute(Anchor::TOP_LEFT),
+                        anchor: Anchor::TOP_LEFT,
+                        size: Size::Absolute { width, height },
+                    },
+                    tint: [0.0, 0.0, 0.0, alpha],
+                });
+            }
         }
 
         if let Some(o) = overlay {
@@ -133,10 +217,13 @@ impl UiRenderer {
                     self.menu_renderer
                         .generate_commands(menu, time, quad_commands, glyph_commands)
                 }
-                UiOverlay::Console(console) => {
-                    self.console_renderer
-                        .generate_commands(console, time, quad_commands, glyph_commands)
-                }
+                UiOverlay::Console(console, settings) => self.console_renderer.generate_commands(
+                    console,
+                    settings,
+                    time,
+                    quad_commands,
+                    glyph_commands,
+                ),
             }
         }
 
@@ -1,4 +1,5 @@
 use std::{
+    borrow::Cow,
     cell::{Ref, RefCell, RefMut},
     mem::size_of,
 };
@@ -10,12 +11,15 @@ use crate::{
             screen_space_vertex_transform,
         },
         uniform::{self, DynamicUniformBuffer, DynamicUniformBufferBlock},
-        Extent2d, GraphicsState, Pipeline, TextureData, DIFFUSE_ATTACHMENT_FORMAT,
+        DiffuseData, Extent2d, GraphicsState, Pipeline, TextureData, DIFFUSE_ATTACHMENT_FORMAT,
     },
     common::{util::any_slice_as_bytes, wad::QPic},
 };
 
-use cgmath::Matrix4;
+use cgmath::{Matrix4, Vector2, Vector4};
+
+/// The tint of a quad drawn with its texture's own colors.
+pub const NO_TINT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
 
 pub const VERTICES: [QuadVertex; 6] = [
     QuadVertex {
@@ -115,6 +119,7 @@ pub struct QuadPipeline {
     vertex_buffer: wgpu::Buffer,
     uniform_buffer: RefCell<DynamicUniformBuffer<QuadUniforms>>,
     uniform_buffer_blocks: RefCell<Vec<DynamicUniformBufferBlock<QuadUniforms>>>,
+    transform_bind_group: wgpu::BindGroup,
 }
 
 impl QuadPipeline {
@@ -133,6 +138,11 @@ impl QuadPipeline {
 
         let uniform_buffer = RefCell::new(DynamicUniformBuffer::new(device));
         let uniform_buffer_blocks = RefCell::new(Vec::new());
+        let transform_bind_group = QuadPipeline::create_transform_bind_group(
+            device,
+            &bind_group_layouts,
+            &uniform_buffer.borrow(),
+        );
 
         QuadPipeline {
             pipeline,
@@ -140,6 +150,37 @@ impl QuadPipeline {
             vertex_buffer,
             uniform_buffer,
             uniform_buffer_blocks,
+            transform_bind_group,
+        }
+    }
+
+    fn create_transform_bind_group(
+        device: &wgpu::Device,
+        layouts: &[wgpu::BindGroupLayout],
+        uniform_buffer: &DynamicUniformBuffer<QuadUniforms>,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("quad transform bind group"),
+            layout: &layouts[2],
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(
+                    uniform_buffer
+                        .buffer()
+                        .slice(..size_of::<QuadUniforms>() as wgpu::BufferAddress),
+                ),
+            }],
+        })
+    }
+
+    /// Grows the uniform buffer if the last frame's quads didn't fit in it.
+    pub fn grow_uniform_buffer(&mut self, device: &wgpu::Device) {
+        if self.uniform_buffer.get_mut().grow(device) {
+            self.transform_bind_group = QuadPipeline::create_transform_bind_group(
+                device,
+                &self.bind_group_layouts,
+                &self.uniform_buffer.borrow(),
+            );
         }
     }
 
@@ -182,6 +223,10 @@ impl QuadPipeline {
     ) -> RefMut<Vec<DynamicUniformBufferBlock<QuadUniforms>>> {
         self.uniform_buffer_blocks.borrow_mut()
     }
+
+    pub fn transform_bind_group(&self) -> &wgpu::BindGroup {
+        &self.transform_bind_group
+    }
 }
 
 impl Pipeline for QuadPipeline {
@@ -239,7 +284,11 @@ impl Pipeline for QuadPipeline {
         vec![wgpu::ColorStateDescriptor {
             format: DIFFUSE_ATTACHMENT_FORMAT,
             alpha_blend: wgpu::BlendDescriptor::REPLACE,
-            color_blend: wgpu::BlendDescriptor::REPLACE,
+            color_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
             write_mask: wgpu::ColorWrite::ALL,
         }]
     }
@@ -262,6 +311,8 @@ impl Pipeline for QuadPipeline {
 #[derive(Clone, Copy, Debug)]
 pub struct QuadUniforms {
     transform: Matrix4<f32>,
+    tint: Vector4<f32>,
+    texcoord_scale: Vector2<f32>,
 }
 
 pub struct QuadTexture {
@@ -277,12 +328,29 @@ pub struct QuadTexture {
 impl QuadTexture {
     pub fn from_qpic(state: &GraphicsState, qpic: &QPic) -> QuadTexture {
         let (diffuse_data, _) = state.palette().translate(qpic.indices());
-        let texture = state.create_texture(
-            None,
-            qpic.width(),
-            qpic.height(),
-            &TextureData::Diffuse(diffuse_data),
-        );
+        QuadTexture::from_diffuse(state, qpic.width(), qpic.height(), diffuse_data)
+    }
+
+    /// Creates a texture from RGBA pixel data.
+    pub fn from_rgba(state: &GraphicsState, width: u32, height: u32, rgba: &[u8]) -> QuadTexture {
+        QuadTexture::from_diffuse(
+            state,
+            width,
+            height,
+            DiffuseData {
+                rgba: Cow::Borrowed(rgba),
+            },
+        )
+    }
+
+    fn from_diffuse(
+        state: &GraphicsState,
+        width: u32,
+        height: u32,
+        diffuse_data: DiffuseData,
+    ) -> QuadTexture {
+        let texture =
+            state.create_texture(None, width, height, &TextureData::Diffuse(diffuse_data));
         let texture_view = texture.create_default_view();
         let bind_group = state
             .device()
@@ -299,8 +367,8 @@ impl QuadTexture {
             texture,
             texture_view,
             bind_group,
-            width: qpic.width(),
-            height: qpic.height(),
+            width,
+            height,
         }
     }
 
@@ -328,11 +396,14 @@ pub struct QuadRendererCommand<'a> {
 
     /// The layout specifying the size and position of the quad on the screen.
     pub layout: Layout,
+
+    /// The color multiplied with the texture, including its alpha. Use `NO_TINT` to draw the
+    /// texture unchanged.
+    pub tint: [f32; 4],
 }
 
 pub struct QuadRenderer {
     sampler_bind_group: wgpu::BindGroup,
-    transform_bind_group: wgpu::BindGroup,
 }
 
 impl QuadRenderer {
@@ -347,27 +418,8 @@ impl QuadRenderer {
                     resource: wgpu::BindingResource::Sampler(state.diffuse_sampler()),
                 }],
             });
-        let transform_bind_group = state
-            .device()
-            .create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("quad transform bind group"),
-                layout: &state.quad_pipeline().bind_group_layouts()[2],
-                entries: &[wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(
-                        state
-                            .quad_pipeline()
-                            .uniform_buffer()
-                            .buffer()
-                            .slice(..size_of::<QuadUniforms>() as wgpu::BufferAddress),
-                    ),
-                }],
-            });
 
-        QuadRenderer {
-            sampler_bind_group,
-            transform_bind_group,
-        }
+        QuadRenderer { sampler_bind_group }
     }
 
     fn generate_uniforms<'cmds>(
@@ -386,6 +438,7 @@ impl QuadRenderer {
                         anchor,
                         size,
                     },
+                tint,
             } = *cmd;
 
             let scale = match size {
@@ -405,6 +458,15 @@ impl QuadRenderer {
             let (quad_width, quad_height) =
                 size.to_wh(texture.width, texture.height, display_width, display_height);
 
+            // tiled quads repeat their texture once per tile
+            let texcoord_scale = match size {
+                Size::Tiled { factor, .. } => Vector2::new(
+                    quad_width as f32 / (texture.width as f32 * factor),
+                    quad_height as f32 / (texture.height as f32 * factor),
+                ),
+                _ => Vector2::new(1.0, 1.0),
+            };
+
             uniforms.push(QuadUniforms {
                 transform: screen_space_vertex_transform(
                     display_width,
@@ -414,6 +476,8 @@ impl QuadRenderer {
                     x,
                     y,
                 ),
+                tint: tint.into(),
+                texcoord_scale,
             });
         }
 
@@ -439,12 +503,22 @@ impl QuadRenderer {
         pass.set_pipeline(state.quad_pipeline().pipeline());
         pass.set_vertex_buffer(0, state.quad_pipeline().vertex_buffer().slice(..));
         pass.set_bind_group(0, &self.sampler_bind_group, &[]);
+        let uniform_buffer = state.quad_pipeline().uniform_buffer();
         for (cmd, block) in commands
             .iter()
             .zip(state.quad_pipeline().uniform_buffer_blocks().iter())
         {
+            // skip quads that overflowed the uniform buffer until it grows
+            if !uniform_buffer.contains(block) {
+                continue;
+            }
+
             pass.set_bind_group(1, &cmd.texture.bind_group, &[]);
-            pass.set_bind_group(2, &self.transform_bind_group, &[block.offset()]);
+            pass.set_bind_group(
+                2,
+                state.quad_pipeline().transform_bind_group(),
+                &[block.offset()],
+            );
             pass.draw(0..6, 0..1);
         }
     }
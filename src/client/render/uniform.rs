@@ -9,10 +9,9 @@ use crate::common::util::{any_as_bytes, Pod};
 
 use failure::Error;
 
-// minimum limit is 16384:
+// initial size of a dynamic uniform buffer. each binding only covers a single block, so the buffer
+// itself is not subject to maxUniformBufferRange:
 // https://www.khronos.org/registry/vulkan/specs/1.2-extensions/html/vkspec.html#limits-maxUniformBufferRange
-// but https://vulkan.gpuinfo.org/displaydevicelimit.php?name=maxUniformBufferRange&platform=windows
-// indicates that a limit of 65536 or higher is more common
 const DYNAMIC_UNIFORM_BUFFER_SIZE: wgpu::BufferAddress = 65536;
 
 // https://www.khronos.org/registry/vulkan/specs/1.2-extensions/html/vkspec.html#limits-minUniformBufferOffsetAlignment
@@ -48,6 +47,10 @@ impl UniformArrayFloat {
 /// A handle to a dynamic uniform buffer on the GPU.
 ///
 /// Allows allocation and updating of individual blocks of memory.
+///
+/// Allocation never fails. If the allocated blocks no longer fit in the buffer on the GPU, the
+/// blocks that don't fit are not uploaded until [`grow`](#method.grow) is called to replace the
+/// underlying buffer with a larger one.
 pub struct DynamicUniformBuffer<T>
 where
     T: Pod,
@@ -60,6 +63,7 @@ where
     _phantom: PhantomData<T>,
 
     inner: wgpu::Buffer,
+    capacity: wgpu::BufferAddress,
     allocated: Cell<u64>,
     update_buf: Vec<u8>,
 }
@@ -72,12 +76,7 @@ where
         // TODO: is this something we can enforce at compile time?
         assert!(align_of::<T>() % DYNAMIC_UNIFORM_BUFFER_ALIGNMENT == 0);
 
-        let inner = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("dynamic uniform buffer"),
-            size: DYNAMIC_UNIFORM_BUFFER_SIZE,
-            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
-            mapped_at_creation: false,
-        });
+        let inner = create_buffer(device, DYNAMIC_UNIFORM_BUFFER_SIZE);
 
         let mut update_buf = Vec::with_capacity(DYNAMIC_UNIFORM_BUFFER_SIZE as usize);
         update_buf.resize(DYNAMIC_UNIFORM_BUFFER_SIZE as usize, 0);
@@ -86,6 +85,7 @@ where
             _rc: RefCell::new(Rc::new(())),
             _phantom: PhantomData,
             inner,
+            capacity: DYNAMIC_UNIFORM_BUFFER_SIZE,
             allocated: Cell::new(0),
             update_buf,
         }
@@ -108,11 +108,14 @@ where
             "Allocating dynamic uniform block (allocated: {})",
             allocated
         );
-        if allocated + size > DYNAMIC_UNIFORM_BUFFER_SIZE {
-            panic!(
-                "Not enough space to allocate {} bytes in dynamic uniform buffer",
-                size
+        if allocated + size > self.update_buf.len() as u64 {
+            let new_len = (2 * self.update_buf.len()).max((allocated + size) as usize);
+            debug!(
+                "Growing dynamic uniform update buffer from {} to {} bytes",
+                self.update_buf.len(),
+                new_len
             );
+            self.update_buf.resize(new_len, 0);
         }
 
         let addr = allocated;
@@ -154,8 +157,39 @@ where
         }
     }
 
+    /// Uploads all blocks that fit in the underlying buffer.
     pub fn flush(&self, queue: &wgpu::Queue) {
-        queue.write_buffer(&self.inner, 0, &self.update_buf);
+        let len = self.capacity.min(self.update_buf.len() as u64) as usize;
+        queue.write_buffer(&self.inner, 0, &self.update_buf[..len]);
+    }
+
+    /// Returns `true` if the block fits in the underlying buffer.
+    ///
+    /// Blocks that don't fit are not uploaded and must not be bound.
+    pub fn contains(&self, block: &DynamicUniformBufferBlock<T>) -> bool {
+        block.addr + self.block_size().get() <= self.capacity
+    }
+
+    /// Replaces the underlying buffer with a larger one if the allocated blocks don't fit in it.
+    ///
+    /// The buffer is also grown once it is three-quarters full so that blocks allocated during the
+    /// next frame are less likely to overflow it. Returns `true` if the buffer was replaced, in
+    /// which case any bind groups that refer to it must be recreated.
+    pub fn grow(&mut self, device: &wgpu::Device) -> bool {
+        let required = (self.update_buf.len() as u64).max(self.allocated.get() * 4 / 3);
+        if required <= self.capacity {
+            return false;
+        }
+
+        let capacity = required.next_power_of_two();
+        debug!(
+            "Growing dynamic uniform buffer from {} to {} bytes",
+            self.capacity, capacity
+        );
+
+        self.inner = create_buffer(device, capacity);
+        self.capacity = capacity;
+        true
     }
 
     pub fn buffer(&self) -> &wgpu::Buffer {
@@ -163,6 +197,15 @@ where
     }
 }
 
+fn create_buffer(device: &wgpu::Device, size: wgpu::BufferAddress) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("dynamic uniform buffer"),
+        size,
+        usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
 /// An address into a dynamic uniform buffer.
 #[derive(Debug)]
 pub struct DynamicUniformBufferBlock<T> {
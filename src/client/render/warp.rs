@@ -4,10 +4,13 @@ use crate::common::math;
 
 use cgmath::{InnerSpace, Vector2, Vector3};
 
-// TODO: make this a cvar
-const SUBDIVIDE_SIZE: f32 = 32.0;
+/// The default grid size used to subdivide warped surfaces, in world units.
+pub const DEFAULT_SUBDIVIDE_SIZE: f32 = 32.0;
 
-/// Subdivide the given polygon on a grid.
+// smallest allowed grid size. polygons are only split when both halves are at least this large.
+const MIN_SUBDIVIDE_SIZE: f32 = 8.0;
+
+/// Subdivide the given polygon on a grid of size `subdivide_size`.
 ///
 /// The algorithm is described as follows:
 /// Given a polygon *P*,
@@ -19,13 +22,17 @@ const SUBDIVIDE_SIZE: f32 = 32.0;
 ///       the next axis.
 ///    1. For each vertex *v*...
 /// TODO...
-pub fn subdivide(verts: Vec<Vector3<f32>>) -> Vec<Vector3<f32>> {
+pub fn subdivide(verts: Vec<Vector3<f32>>, subdivide_size: f32) -> Vec<Vector3<f32>> {
     let mut out = Vec::new();
-    subdivide_impl(verts, &mut out);
+    subdivide_impl(verts, subdivide_size.max(MIN_SUBDIVIDE_SIZE), &mut out);
     out
 }
 
-fn subdivide_impl(mut verts: Vec<Vector3<f32>>, output: &mut Vec<Vector3<f32>>) {
+fn subdivide_impl(
+    mut verts: Vec<Vector3<f32>>,
+    subdivide_size: f32,
+    output: &mut Vec<Vector3<f32>>,
+) {
     let (min, max) = math::bounds(&verts);
 
     let mut front = Vec::new();
@@ -36,10 +43,10 @@ fn subdivide_impl(mut verts: Vec<Vector3<f32>>, output: &mut Vec<Vector3<f32>>)
         // find the midpoint of the polygon bounds
         let mid = {
             let m = (min[ax] + max[ax]) / 2.0;
-            SUBDIVIDE_SIZE * (m / SUBDIVIDE_SIZE).round()
+            subdivide_size * (m / subdivide_size).round()
         };
 
-        if max[ax] - mid < 8.0 || mid - min[ax] < 8.0 {
+        if max[ax] - mid < MIN_SUBDIVIDE_SIZE || mid - min[ax] < MIN_SUBDIVIDE_SIZE {
             // this component doesn't need to be subdivided further.
             // if no components need to be subdivided further, this breaks the loop.
             continue;
@@ -78,12 +85,12 @@ fn subdivide_impl(mut verts: Vec<Vector3<f32>>, output: &mut Vec<Vector3<f32>>)
             }
         }
 
-        subdivide_impl(front, output);
-        subdivide_impl(back, output);
+        subdivide_impl(front, subdivide_size, output);
+        subdivide_impl(back, subdivide_size, output);
         return;
     }
 
-    // polygon is smaller than SUBDIVIDE_SIZE along all three axes
+    // polygon is smaller than subdivide_size along all three axes
     assert!(verts.len() >= 3);
     let v1 = verts[0];
     let mut v2 = verts[1];
@@ -94,3 +101,33 @@ fn subdivide_impl(mut verts: Vec<Vector3<f32>>, output: &mut Vec<Vector3<f32>>)
         v2 = *v3;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a square in the XY plane with sides of length `size` and a corner at the origin
+    fn square(size: f32) -> Vec<Vector3<f32>> {
+        vec![
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(size, 0.0, 0.0),
+            Vector3::new(size, size, 0.0),
+            Vector3::new(0.0, size, 0.0),
+        ]
+    }
+
+    #[test]
+    fn test_subdivide_size() {
+        // two triangles per grid cell
+        assert_eq!(subdivide(square(64.0), 32.0).len(), 2 * 4 * 3);
+        assert_eq!(subdivide(square(64.0), 128.0).len(), 2 * 3);
+    }
+
+    #[test]
+    fn test_subdivide_min_size() {
+        assert_eq!(
+            subdivide(square(64.0), 0.0).len(),
+            subdivide(square(64.0), MIN_SUBDIVIDE_SIZE).len()
+        );
+    }
+}
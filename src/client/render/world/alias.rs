@@ -76,10 +76,24 @@ impl AliasPipeline {
     }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct VertexPushConstants {
+    /// How far to blend from the previous pose to the current one, from 0 to 1.
+    pub blend: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FragmentPushConstants {
+    /// The light falling on the model, where 1.0 shows its skin at its original brightness.
+    pub light: [f32; 3],
+}
+
 impl Pipeline for AliasPipeline {
-    type VertexPushConstants = ();
+    type VertexPushConstants = VertexPushConstants;
     type SharedPushConstants = ();
-    type FragmentPushConstants = ();
+    type FragmentPushConstants = FragmentPushConstants;
 
     fn name() -> &'static str {
         "alias"
@@ -120,21 +134,31 @@ impl Pipeline for AliasPipeline {
     }
 
     // NOTE: if the vertex format is changed, this descriptor must also be changed accordingly.
+    //
+    // both buffers are bound to the same vertex data, offset to the current and previous keyframes.
     fn vertex_buffer_descriptors() -> Vec<wgpu::VertexBufferDescriptor<'static>> {
-        vec![wgpu::VertexBufferDescriptor {
-            stride: size_of::<AliasVertex>() as u64,
-            step_mode: wgpu::InputStepMode::Vertex,
-            attributes: &wgpu::vertex_attr_array![
-                // frame 0 position
-                0 => Float3,
-                // frame 1 position
-                // 1 => Float3,
-                // normal
-                2 => Float3,
-                // texcoord
-                3 => Float2,
-            ],
-        }]
+        vec![
+            wgpu::VertexBufferDescriptor {
+                stride: size_of::<AliasVertex>() as u64,
+                step_mode: wgpu::InputStepMode::Vertex,
+                attributes: &wgpu::vertex_attr_array![
+                    // current frame position
+                    0 => Float3,
+                    // normal
+                    2 => Float3,
+                    // texcoord
+                    3 => Float2,
+                ],
+            },
+            wgpu::VertexBufferDescriptor {
+                stride: size_of::<AliasVertex>() as u64,
+                step_mode: wgpu::InputStepMode::Vertex,
+                attributes: &wgpu::vertex_attr_array![
+                    // previous frame position
+                    1 => Float3,
+                ],
+            },
+        ]
     }
 }
 
@@ -335,8 +359,13 @@ impl AliasRenderer {
             match *texture {
                 mdl::Texture::Static(ref tex) => {
                     let (diffuse_data, _fullbright_data) = state.palette.translate(tex.indices());
-                    let diffuse_texture =
-                        state.create_texture(None, w, h, &TextureData::Diffuse(diffuse_data));
+                    let diffuse_texture = state.create_world_texture(
+                        None,
+                        w,
+                        h,
+                        &TextureData::Diffuse(diffuse_data),
+                        false,
+                    );
                     let diffuse_view = diffuse_texture.create_default_view();
                     let bind_group = state
                         .device()
@@ -369,8 +398,13 @@ impl AliasRenderer {
 
                         let (diffuse_data, _fullbright_data) =
                             state.palette.translate(frame.indices());
-                        let diffuse_texture =
-                            state.create_texture(None, w, h, &TextureData::Diffuse(diffuse_data));
+                        let diffuse_texture = state.create_world_texture(
+                            None,
+                            w,
+                            h,
+                            &TextureData::Diffuse(diffuse_data),
+                            false,
+                        );
                         let diffuse_view = diffuse_texture.create_default_view();
                         let bind_group =
                             state
@@ -408,22 +442,36 @@ impl AliasRenderer {
         })
     }
 
+    /// Draws the model blended from `prev_keyframe_id` to `keyframe_id`.
+    ///
+    /// The blend factor is taken from the vertex push constants, which must be set beforehand.
     pub fn record_draw<'a>(
         &'a self,
         state: &'a GraphicsState,
         pass: &mut wgpu::RenderPass<'a>,
         time: Duration,
         keyframe_id: usize,
+        prev_keyframe_id: usize,
         texture_id: usize,
     ) {
+        let keyframe = &self.keyframes[keyframe_id];
+        let current = keyframe.animate(time);
+        let prev = self
+            .keyframes
+            .get(prev_keyframe_id)
+            .unwrap_or(keyframe)
+            .animate(time);
+        let stride = size_of::<AliasVertex>() as u64;
+
         pass.set_pipeline(state.alias_pipeline().pipeline());
-        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(current.start as u64 * stride..));
+        pass.set_vertex_buffer(1, self.vertex_buffer.slice(prev.start as u64 * stride..));
 
         pass.set_bind_group(
             BindGroupLayoutId::PerTexture as u32,
             self.textures[texture_id].animate(time),
             &[],
         );
-        pass.draw(self.keyframes[keyframe_id].animate(time), 0..1)
+        pass.draw(0..current.end - current.start, 0..1)
     }
 }
@@ -0,0 +1,688 @@
+//! Quick previews of map lighting.
+//!
+//! `r_bakelights` recomputes the direct lighting of the loaded map on the GPU from its light
+//! entities and swaps the result in for the map's lightmaps. The entities are read from
+//! `maps/<map>.ent` if it exists, or else from the map itself, so mappers can move, recolor and
+//! retune lights in the `.ent` file and see the change at once, without running the light tool
+//! and reloading the map.
+//!
+//! The bake approximates the classic light tool. Each texel receives the light of every lamp of its
+//! light style that isn't blocked by the world, with the same falloff and angle scaling, but there
+//! is no bounced light, no extra sampling and no shadows from brush entities. Only faces the light
+//! tool gave lightmaps are baked, and reloading the map restores its own lighting.
+
+use std::{collections::HashMap, io::Read as _, rc::Rc};
+
+use crate::{
+    client::render::{
+        pipeline::{Pipeline, PushConstantUpdate},
+        ui::quad::QuadPipeline,
+        GraphicsState, LIGHTMAP_TEXTURE_FORMAT,
+    },
+    common::{
+        bsp::{
+            self, BspData, BspFace, BspFaceSide, BspLeafContents, BspRenderNodeChild, BspTexInfo,
+        },
+        math::Hyperplane,
+        parse,
+        util::any_slice_as_bytes,
+        vfs::Vfs,
+    },
+};
+
+use cgmath::{Angle as _, Deg, InnerSpace as _, Matrix as _, Matrix3, SquareMatrix as _, Vector3};
+use failure::Error;
+
+// the light level of lamps without a "light" key
+const DEFAULT_LIGHT: f32 = 300.0;
+
+// the cone of spotlights without an "angle" key, in degrees
+const DEFAULT_SPOT_ANGLE: f32 = 20.0;
+
+// the first light style given to lamps that are switched on and off by name
+const FIRST_SWITCHABLE_STYLE: u8 = 32;
+
+// the cosine given to lamps that aren't spotlights, which any direction passes
+const NO_SPOT: f32 = -2.0;
+
+// node children below zero are leaves (see light_bake.frag)
+const SOLID_LEAF: i32 = -1;
+const OPEN_LEAF: i32 = -2;
+
+// the size of a lightmap texel in texture space
+const TEXEL_SIZE: f32 = 16.0;
+
+/// How a lamp's light fades with distance, from the lamp's `delay` key.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Falloff {
+    Linear = 0,
+    Inverse = 1,
+    InverseSquare = 2,
+    None = 3,
+}
+
+/// A light entity.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lamp {
+    pub origin: Vector3<f32>,
+    pub light: f32,
+
+    /// The color of the light, scaled so that its brightest component is 1.
+    pub color: Vector3<f32>,
+
+    /// Scales the distance over which the light fades.
+    pub wait: f32,
+    pub falloff: Falloff,
+    pub style: u8,
+
+    /// The direction of a spotlight and the cosine of half its cone angle.
+    pub spot: Option<(Vector3<f32>, f32)>,
+}
+
+/// The light entities of a map and its minimum light level.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BakeLights {
+    pub lamps: Vec<Lamp>,
+    pub minlight: f32,
+}
+
+impl BakeLights {
+    /// Reads the lights from a map's entity string.
+    ///
+    /// Returns `None` if the entity string can't be parsed.
+    pub fn from_entities<S>(ent_string: S) -> Option<BakeLights>
+    where
+        S: AsRef<str>,
+    {
+        // hand-edited .ent files may start with blank lines
+        let (_, entities) = parse::entities(ent_string.as_ref().trim_start()).ok()?;
+
+        let minlight = entities
+            .iter()
+            .find(|ent| ent.get("classname") == Some(&"worldspawn"))
+            .and_then(|ent| ent.get("light").or_else(|| ent.get("_minlight")))
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0.0);
+
+        // spotlights point at the entity they target
+        let targets: HashMap<&str, Vector3<f32>> = entities
+            .iter()
+            .filter_map(|ent| Some((*ent.get("targetname")?, parse::vector3(ent.get("origin")?)?)))
+            .collect();
+
+        // lamps switched by name get a style of their own per name, in the order they appear
+        let mut switchable_styles: HashMap<&str, u8> = HashMap::new();
+
+        let mut lamps = Vec::new();
+        for ent in entities.iter() {
+            if !ent
+                .get("classname")
+                .map_or(false, |c| c.starts_with("light"))
+            {
+                continue;
+            }
+
+            let origin = match ent.get("origin").and_then(parse::vector3) {
+                Some(o) => o,
+                None => continue,
+            };
+
+            let number = |key: &str, default: f32| {
+                ent.get(key)
+                    .and_then(|v| v.parse::<f32>().ok())
+                    .unwrap_or(default)
+            };
+
+            let mut style = number("style", 0.0) as u8;
+            if let Some(name) = ent.get("targetname") {
+                if style == 0 {
+                    let next = FIRST_SWITCHABLE_STYLE + switchable_styles.len() as u8;
+                    style = *switchable_styles.entry(*name).or_insert(next);
+                }
+            }
+
+            let spot = ent
+                .get("target")
+                .and_then(|target| targets.get(target))
+                .map(|target| *target - origin)
+                .filter(|dir| dir.magnitude2() > 0.0)
+                .map(|dir| {
+                    let angle = number("angle", DEFAULT_SPOT_ANGLE);
+                    let cone = if angle > 0.0 {
+                        angle
+                    } else {
+                        DEFAULT_SPOT_ANGLE
+                    };
+                    (dir.normalize(), Deg(cone / 2.0).cos())
+                });
+
+            lamps.push(Lamp {
+                origin,
+                light: number("light", number("_light", DEFAULT_LIGHT)),
+                color: ent
+                    .get("_color")
+                    .and_then(parse::vector3)
+                    .and_then(normalize_color)
+                    .unwrap_or(Vector3::new(1.0, 1.0, 1.0)),
+                wait: match number("wait", 1.0) {
+                    w if w > 0.0 => w,
+                    _ => 1.0,
+                },
+                falloff: match number("delay", 0.0) as i32 {
+                    1 => Falloff::Inverse,
+                    2 => Falloff::InverseSquare,
+                    3 => Falloff::None,
+                    _ => Falloff::Linear,
+                },
+                style,
+                spot,
+            });
+        }
+
+        Some(BakeLights { lamps, minlight })
+    }
+}
+
+// colors may be given from 0 to 1 or from 0 to 255
+fn normalize_color(color: Vector3<f32>) -> Option<Vector3<f32>> {
+    let max = color.x.max(color.y).max(color.z);
+    if max <= 0.0 {
+        None
+    } else {
+        Some(color / max)
+    }
+}
+
+/// Returns the path of the entity file that overrides the entities of the map at `map_path`.
+pub fn ent_path<S>(map_path: S) -> String
+where
+    S: AsRef<str>,
+{
+    format!("{}.ent", map_path.as_ref().trim_end_matches(".bsp"))
+}
+
+/// Loads the entity string to bake the map at `map_path` from, preferring its `.ent` file.
+///
+/// Returns the path the entities were read from along with the entities.
+pub fn load_entities(vfs: &Vfs, map_path: &str) -> Result<(String, String), Error> {
+    let path = ent_path(map_path);
+    if let Ok(mut file) = vfs.open(&path) {
+        let mut ent_string = String::new();
+        file.read_to_string(&mut ent_string)?;
+        return Ok((path, ent_string));
+    }
+
+    let ent_string = bsp::load_entities(vfs.open(map_path)?)?;
+    Ok((map_path.to_owned(), ent_string))
+}
+
+/// The world positions of a face's lightmap texels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TexelBasis {
+    /// The position of the first texel.
+    pub origin: Vector3<f32>,
+
+    /// The offset from one texel to the next in a row.
+    pub s_step: Vector3<f32>,
+
+    /// The offset from one row to the next.
+    pub t_step: Vector3<f32>,
+
+    /// The unit normal of the front of the face.
+    pub normal: Vector3<f32>,
+
+    /// The size of the face's lightmaps in texels.
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TexelBasis {
+    /// Finds the texel positions of a face lying on `plane`.
+    ///
+    /// Returns `None` if the face's texture axes are parallel to each other or to the plane.
+    pub fn new(face: &BspFace, texinfo: &BspTexInfo, plane: &Hyperplane) -> Option<TexelBasis> {
+        // each texel lies on the plane at its texture coordinates, so its position solves
+        //   s_vector . p = s - s_offset
+        //   t_vector . p = t - t_offset
+        //     normal . p = dist
+        let to_world =
+            Matrix3::from_cols(texinfo.s_vector, texinfo.t_vector, plane.normal_vector())
+                .transpose()
+                .invert()?;
+
+        let s_min = (face.texture_mins[0] as f32 / TEXEL_SIZE).floor() * TEXEL_SIZE;
+        let t_min = (face.texture_mins[1] as f32 / TEXEL_SIZE).floor() * TEXEL_SIZE;
+        let position = |s: f32, t: f32| {
+            to_world * Vector3::new(s - texinfo.s_offset, t - texinfo.t_offset, plane.dist())
+        };
+
+        let origin = position(s_min, t_min);
+        Some(TexelBasis {
+            origin,
+            s_step: position(s_min + TEXEL_SIZE, t_min) - origin,
+            t_step: position(s_min, t_min + TEXEL_SIZE) - origin,
+            normal: match face.side {
+                BspFaceSide::Front => plane.normal_vector(),
+                BspFaceSide::Back => -plane.normal_vector(),
+            },
+            width: face.extents[0] as u32 / TEXEL_SIZE as u32 + 1,
+            height: face.extents[1] as u32 / TEXEL_SIZE as u32 + 1,
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct GpuLamp {
+    origin: [f32; 4],
+    color: [f32; 4],
+    spot: [f32; 4],
+    params: [u32; 4],
+}
+
+impl<'a> std::convert::From<&'a Lamp> for GpuLamp {
+    fn from(lamp: &'a Lamp) -> GpuLamp {
+        let (spot_dir, spot_cos) = lamp.spot.unwrap_or((Vector3::new(0.0, 0.0, 0.0), NO_SPOT));
+        GpuLamp {
+            origin: lamp.origin.extend(lamp.light).into(),
+            color: lamp.color.extend(lamp.wait).into(),
+            spot: spot_dir.extend(spot_cos).into(),
+            params: [lamp.style as u32, lamp.falloff as u32, 0, 0],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct GpuNode {
+    plane: [f32; 4],
+    children: [i32; 4],
+}
+
+// flatten the world's render nodes, marking each leaf child as solid or open
+fn gpu_nodes(bsp_data: &BspData) -> Vec<GpuNode> {
+    bsp_data
+        .render_nodes()
+        .iter()
+        .map(|node| {
+            let plane = &bsp_data.planes()[node.plane_id];
+            let child = |c: BspRenderNodeChild| match c {
+                BspRenderNodeChild::Node(id) => id as i32,
+                BspRenderNodeChild::Leaf(id) => match bsp_data.leaves()[id].contents {
+                    BspLeafContents::Solid => SOLID_LEAF,
+                    _ => OPEN_LEAF,
+                },
+            };
+
+            GpuNode {
+                plane: plane.normal_vector().extend(plane.dist()).into(),
+                children: [child(node.children[0]), child(node.children[1]), 0, 0],
+            }
+        })
+        .collect()
+}
+
+lazy_static! {
+    static ref BIND_GROUP_LAYOUT_DESCRIPTOR_BINDINGS: [Vec<wgpu::BindGroupLayoutEntry>; 1] = [
+        vec![
+            // lamps
+            wgpu::BindGroupLayoutEntry::new(
+                0,
+                wgpu::ShaderStage::FRAGMENT,
+                wgpu::BindingType::StorageBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                    readonly: true,
+                },
+            ),
+            // world nodes
+            wgpu::BindGroupLayoutEntry::new(
+                1,
+                wgpu::ShaderStage::FRAGMENT,
+                wgpu::BindingType::StorageBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                    readonly: true,
+                },
+            ),
+        ]
+    ];
+}
+
+pub struct LightBakePipeline {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+}
+
+impl LightBakePipeline {
+    pub fn new(device: &wgpu::Device, compiler: &mut shaderc::Compiler) -> LightBakePipeline {
+        // lightmaps are never multisampled
+        let (pipeline, bind_group_layouts) = LightBakePipeline::create(device, compiler, &[], 1);
+
+        LightBakePipeline {
+            pipeline,
+            bind_group_layouts,
+        }
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn bind_group_layouts(&self) -> &[wgpu::BindGroupLayout] {
+        &self.bind_group_layouts
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FragmentPushConstants {
+    origin: [f32; 4],
+    s_step: [f32; 4],
+    t_step: [f32; 4],
+    /// The face normal (xyz) and minimum light level (w).
+    normal: [f32; 4],
+    style: u32,
+    lamp_count: u32,
+}
+
+impl Pipeline for LightBakePipeline {
+    type VertexPushConstants = ();
+    type SharedPushConstants = ();
+    type FragmentPushConstants = FragmentPushConstants;
+
+    fn name() -> &'static str {
+        "light_bake"
+    }
+
+    fn bind_group_layout_descriptors() -> Vec<wgpu::BindGroupLayoutDescriptor<'static>> {
+        vec![wgpu::BindGroupLayoutDescriptor {
+            label: Some("light bake bind group"),
+            entries: &BIND_GROUP_LAYOUT_DESCRIPTOR_BINDINGS[0],
+        }]
+    }
+
+    // the bake covers the whole lightmap, as the blit covers the screen
+    fn vertex_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/blit.vert"))
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/light_bake.frag"
+        ))
+    }
+
+    fn rasterization_state_descriptor() -> Option<wgpu::RasterizationStateDescriptor> {
+        QuadPipeline::rasterization_state_descriptor()
+    }
+
+    fn primitive_topology() -> wgpu::PrimitiveTopology {
+        QuadPipeline::primitive_topology()
+    }
+
+    fn color_state_descriptors() -> Vec<wgpu::ColorStateDescriptor> {
+        vec![wgpu::ColorStateDescriptor {
+            format: LIGHTMAP_TEXTURE_FORMAT,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        }]
+    }
+
+    fn depth_stencil_state_descriptor() -> Option<wgpu::DepthStencilStateDescriptor> {
+        None
+    }
+
+    fn vertex_buffer_descriptors() -> Vec<wgpu::VertexBufferDescriptor<'static>> {
+        QuadPipeline::vertex_buffer_descriptors()
+    }
+}
+
+/// The lights and world geometry of a bake, uploaded to the GPU.
+pub struct LightBake {
+    bsp_data: Rc<BspData>,
+    _lamp_buffer: wgpu::Buffer,
+    _node_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    lamp_count: u32,
+    minlight: f32,
+}
+
+impl LightBake {
+    /// Prepares to bake the faces of `bsp_data` with `lights`, using its world nodes to cast
+    /// shadows.
+    pub fn new(state: &GraphicsState, bsp_data: Rc<BspData>, lights: &BakeLights) -> LightBake {
+        let mut lamps: Vec<GpuLamp> = lights.lamps.iter().map(GpuLamp::from).collect();
+        let lamp_count = lamps.len() as u32;
+
+        // storage buffers can't be empty
+        if lamps.is_empty() {
+            lamps.push(GpuLamp::from(&Lamp {
+                origin: Vector3::new(0.0, 0.0, 0.0),
+                light: 0.0,
+                color: Vector3::new(0.0, 0.0, 0.0),
+                wait: 1.0,
+                falloff: Falloff::None,
+                style: 0,
+                spot: None,
+            }));
+        }
+
+        let lamp_buffer = state.device().create_buffer_with_data(
+            unsafe { any_slice_as_bytes(&lamps) },
+            wgpu::BufferUsage::STORAGE,
+        );
+        let node_buffer = state.device().create_buffer_with_data(
+            unsafe { any_slice_as_bytes(&gpu_nodes(&bsp_data)) },
+            wgpu::BufferUsage::STORAGE,
+        );
+
+        let bind_group = state
+            .device()
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("light bake bind group"),
+                layout: &state.light_bake_pipeline().bind_group_layouts()[0],
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::Buffer(lamp_buffer.slice(..)),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Buffer(node_buffer.slice(..)),
+                    },
+                ],
+            });
+
+        LightBake {
+            bsp_data,
+            _lamp_buffer: lamp_buffer,
+            _node_buffer: node_buffer,
+            bind_group,
+            lamp_count,
+            minlight: lights.minlight,
+        }
+    }
+
+    /// Returns whether this bake was prepared for `bsp_data`.
+    ///
+    /// Faces of other BSP files, such as ammo box models, can't be baked against this map.
+    pub fn is_for(&self, bsp_data: &Rc<BspData>) -> bool {
+        Rc::ptr_eq(&self.bsp_data, bsp_data)
+    }
+
+    /// Creates a lightmap of a face and records the commands to bake one of its light styles into
+    /// it.
+    pub fn bake_lightmap(
+        &self,
+        state: &GraphicsState,
+        encoder: &mut wgpu::CommandEncoder,
+        basis: &TexelBasis,
+        style: u8,
+    ) -> wgpu::Texture {
+        let texture = state.device().create_texture(&wgpu::TextureDescriptor {
+            label: Some("baked lightmap"),
+            size: wgpu::Extent3d {
+                width: basis.width,
+                height: basis.height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: LIGHTMAP_TEXTURE_FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        });
+        let view = texture.create_default_view();
+
+        let push_constants = FragmentPushConstants {
+            origin: basis.origin.extend(0.0).into(),
+            s_step: basis.s_step.extend(0.0).into(),
+            t_step: basis.t_step.extend(0.0).into(),
+            normal: basis.normal.extend(self.minlight).into(),
+            style: style as u32,
+            lamp_count: self.lamp_count,
+        };
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(state.light_bake_pipeline().pipeline());
+        pass.set_vertex_buffer(0, state.quad_pipeline().vertex_buffer().slice(..));
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        LightBakePipeline::set_push_constants(
+            &mut pass,
+            PushConstantUpdate::Clear,
+            PushConstantUpdate::Clear,
+            PushConstantUpdate::Update(&push_constants),
+        );
+        pass.draw(0..6, 0..1);
+        drop(pass);
+
+        texture
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lights_from_entities() {
+        let ent_string = r#"
+{
+"classname" "worldspawn"
+"light" "20"
+}
+{
+"classname" "light"
+"origin" "0 0 64"
+}
+{
+"classname" "light_fluoro"
+"origin" "128 0 64"
+"light" "200"
+"_color" "255 128 0"
+"delay" "2"
+"wait" "0.5"
+}
+{
+"classname" "light"
+"origin" "0 128 64"
+"targetname" "t1"
+"target" "spot"
+"angle" "60"
+}
+{
+"classname" "info_null"
+"origin" "0 128 0"
+"targetname" "spot"
+}
+{
+"classname" "light"
+"origin" "0 256 64"
+"targetname" "t2"
+"style" "5"
+}
+{
+"classname" "info_player_start"
+"origin" "0 0 0"
+}
+"#;
+
+        let lights = BakeLights::from_entities(ent_string).unwrap();
+        assert_eq!(lights.minlight, 20.0);
+        assert_eq!(lights.lamps.len(), 4);
+
+        let plain = &lights.lamps[0];
+        assert_eq!(plain.light, DEFAULT_LIGHT);
+        assert_eq!(plain.color, Vector3::new(1.0, 1.0, 1.0));
+        assert_eq!(plain.falloff, Falloff::Linear);
+        assert_eq!(plain.style, 0);
+        assert_eq!(plain.spot, None);
+
+        let colored = &lights.lamps[1];
+        assert_eq!(colored.light, 200.0);
+        assert_eq!(colored.color, Vector3::new(1.0, 128.0 / 255.0, 0.0));
+        assert_eq!(colored.falloff, Falloff::InverseSquare);
+        assert_eq!(colored.wait, 0.5);
+
+        // switched lamps get their own style unless they have one
+        let spot = &lights.lamps[2];
+        assert_eq!(spot.style, FIRST_SWITCHABLE_STYLE);
+        let (dir, cos) = spot.spot.unwrap();
+        assert_eq!(dir, Vector3::new(0.0, 0.0, -1.0));
+        assert!((cos - Deg(30.0).cos()).abs() < 1e-6);
+        assert_eq!(lights.lamps[3].style, 5);
+    }
+
+    #[test]
+    fn test_texel_basis() {
+        // a floor at z = 64 with the default texture alignment
+        let face = BspFace {
+            plane_id: 0,
+            side: BspFaceSide::Front,
+            edge_id: 0,
+            edge_count: 0,
+            texinfo_id: 0,
+            light_styles: [0, 255, 255, 255],
+            lightmap_id: Some(0),
+            texture_mins: [-40, 8],
+            extents: [64, 32],
+        };
+        let texinfo = BspTexInfo {
+            s_vector: Vector3::new(1.0, 0.0, 0.0),
+            s_offset: 8.0,
+            t_vector: Vector3::new(0.0, -1.0, 0.0),
+            t_offset: 0.0,
+            tex_id: 0,
+            special: false,
+        };
+        let plane = Hyperplane::new(Vector3::new(0.0, 0.0, 1.0), 64.0);
+
+        let basis = TexelBasis::new(&face, &texinfo, &plane).unwrap();
+        // s = -48 and t = 0 at the first texel
+        assert_eq!(basis.origin, Vector3::new(-56.0, 0.0, 64.0));
+        assert_eq!(basis.s_step, Vector3::new(16.0, 0.0, 0.0));
+        assert_eq!(basis.t_step, Vector3::new(0.0, -16.0, 0.0));
+        assert_eq!(basis.normal, Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!((basis.width, basis.height), (5, 3));
+
+        // texture axes along the plane normal can't be placed on it
+        let edge_on = BspTexInfo {
+            s_vector: Vector3::new(0.0, 0.0, 1.0),
+            ..texinfo
+        };
+        assert_eq!(TexelBasis::new(&face, &edge_on, &plane), None);
+    }
+}
@@ -0,0 +1,404 @@
+//! Blob shadows beneath entities.
+//!
+//! A cheaper alternative to shadow maps, enabled by `r_blobshadows`. Each shadow is a dark disc
+//! laid over the surface below an entity, fading out as the entity rises above it. Shadows are
+//! drawn into the light attachment after the world and entities, so they only darken the static
+//! lighting and are hidden by any geometry in front of the surface.
+//!
+//! The same pipeline draws the soft rectangular shadows that `r_movershadows` lays beneath doors,
+//! lifts and other moving brush entities. The map's lightmaps were baked without them, so without
+//! a shadow they look pasted over the level as they move.
+
+use std::mem::size_of;
+
+use crate::{
+    client::render::{
+        pipeline::{Pipeline, PushConstantUpdate},
+        world::{Camera, WorldPipelineBase},
+        GraphicsState, DEPTH_ATTACHMENT_FORMAT, DIFFUSE_ATTACHMENT_FORMAT, LIGHT_ATTACHMENT_FORMAT,
+        NORMAL_ATTACHMENT_FORMAT,
+    },
+    common::{frustum, util::any_slice_as_bytes},
+};
+
+use bumpalo::Bump;
+use cgmath::{InnerSpace as _, Matrix4, Vector3};
+
+/// How far below an entity to look for a surface to cast its shadow on.
+pub const BLOB_SHADOW_DISTANCE: f32 = 128.0;
+
+/// The darkness of a shadow directly beneath its entity, from 0 (invisible) to 1 (black).
+pub const BLOB_SHADOW_ALPHA: f32 = 0.5;
+
+/// How far below a brush entity to look for a surface to cast its shadow on.
+pub const MOVER_SHADOW_DISTANCE: f32 = 256.0;
+
+/// The darkness of a shadow directly beneath a brush entity.
+pub const MOVER_SHADOW_ALPHA: f32 = 0.4;
+
+// how far a brush entity's shadow extends past its edges, plus the spread per unit of height
+const MOVER_SHADOW_MARGIN: f32 = 8.0;
+const MOVER_SHADOW_SPREAD: f32 = 0.25;
+
+// distance to raise shadows off their surface to avoid z-fighting
+const BLOB_SHADOW_OFFSET: f32 = 0.5;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct BlobShadowVertex {
+    position: [f32; 3],
+    // position relative to the center of the shadow, from -1 to 1 along each axis
+    texcoord: [f32; 2],
+    alpha: f32,
+    // 0 for a round shadow, 1 for a rectangular one
+    squareness: f32,
+}
+
+/// Add a shadow of the given radius on the surface with normal `normal` at `origin`.
+pub fn push_blob_shadow<V>(
+    vertices: &mut V,
+    origin: Vector3<f32>,
+    normal: Vector3<f32>,
+    radius: f32,
+    alpha: f32,
+) where
+    V: Extend<BlobShadowVertex>,
+{
+    // any two axes perpendicular to the normal will do, since the shadow is round
+    let tangent = if normal.z.abs() < 0.9 {
+        normal.cross(Vector3::unit_z()).normalize()
+    } else {
+        normal.cross(Vector3::unit_x()).normalize()
+    };
+    let bitangent = normal.cross(tangent);
+
+    let center = origin + normal * BLOB_SHADOW_OFFSET;
+    let corner = |s: f32, t: f32| {
+        let p = center + (tangent * s + bitangent * t) * radius;
+        BlobShadowVertex {
+            position: frustum::quake_to_wgpu(p).into(),
+            texcoord: [s, t],
+            alpha,
+            squareness: 0.0,
+        }
+    };
+
+    push_quad(vertices, corner);
+}
+
+/// Add the shadow of a brush entity bounded by `mins` and `maxs` on the surface with normal
+/// `normal` at `hit`, below the entity.
+///
+/// The shadow covers the entity's horizontal extent, spreading and fading as the entity rises
+/// above the surface. Nothing is added for surfaces that face sideways or down.
+pub fn push_mover_shadow<V>(
+    vertices: &mut V,
+    mins: Vector3<f32>,
+    maxs: Vector3<f32>,
+    hit: Vector3<f32>,
+    normal: Vector3<f32>,
+) where
+    V: Extend<BlobShadowVertex>,
+{
+    let height = mins.z - hit.z;
+    if normal.z < 0.7 || height < 0.0 || height >= MOVER_SHADOW_DISTANCE {
+        return;
+    }
+
+    let alpha = MOVER_SHADOW_ALPHA * (1.0 - height / MOVER_SHADOW_DISTANCE);
+    let margin = MOVER_SHADOW_MARGIN + height * MOVER_SHADOW_SPREAD;
+    let center = (mins + maxs) / 2.0;
+    let half_x = (maxs.x - mins.x) / 2.0 + margin;
+    let half_y = (maxs.y - mins.y) / 2.0 + margin;
+
+    // lay the corners on the surface's plane
+    let corner = |s: f32, t: f32| {
+        let x = center.x + s * half_x;
+        let y = center.y + t * half_y;
+        let z = hit.z - (normal.x * (x - hit.x) + normal.y * (y - hit.y)) / normal.z;
+        let p = Vector3::new(x, y, z) + normal * BLOB_SHADOW_OFFSET;
+        BlobShadowVertex {
+            position: frustum::quake_to_wgpu(p).into(),
+            texcoord: [s, t],
+            alpha,
+            squareness: 1.0,
+        }
+    };
+
+    push_quad(vertices, corner);
+}
+
+// add two triangles covering the square from (-1, -1) to (1, 1)
+fn push_quad<V, F>(vertices: &mut V, corner: F)
+where
+    V: Extend<BlobShadowVertex>,
+    F: Fn(f32, f32) -> BlobShadowVertex,
+{
+    vertices.extend(
+        [
+            corner(-1.0, -1.0),
+            corner(1.0, -1.0),
+            corner(1.0, 1.0),
+            corner(-1.0, -1.0),
+            corner(1.0, 1.0),
+            corner(-1.0, 1.0),
+        ]
+        .iter()
+        .copied(),
+    );
+}
+
+pub struct BlobShadowPipeline {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+}
+
+impl BlobShadowPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        sample_count: u32,
+    ) -> BlobShadowPipeline {
+        let (pipeline, bind_group_layouts) =
+            BlobShadowPipeline::create(device, compiler, &[], sample_count);
+
+        BlobShadowPipeline {
+            pipeline,
+            bind_group_layouts,
+        }
+    }
+
+    pub fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        sample_count: u32,
+    ) {
+        let layout_refs: Vec<_> = self.bind_group_layouts.iter().collect();
+        let pipeline = BlobShadowPipeline::recreate(device, compiler, &layout_refs, sample_count);
+        self.pipeline = pipeline;
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn bind_group_layouts(&self) -> &[wgpu::BindGroupLayout] {
+        &self.bind_group_layouts
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct VertexPushConstants {
+    pub transform: Matrix4<f32>,
+}
+
+impl Pipeline for BlobShadowPipeline {
+    type VertexPushConstants = VertexPushConstants;
+    type SharedPushConstants = ();
+    type FragmentPushConstants = ();
+
+    fn name() -> &'static str {
+        "blob_shadow"
+    }
+
+    fn vertex_shader() -> &'static str {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/blob_shadow.vert"
+        ))
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/blob_shadow.frag"
+        ))
+    }
+
+    fn bind_group_layout_descriptors() -> Vec<wgpu::BindGroupLayoutDescriptor<'static>> {
+        Vec::new()
+    }
+
+    fn rasterization_state_descriptor() -> Option<wgpu::RasterizationStateDescriptor> {
+        WorldPipelineBase::rasterization_state_descriptor()
+    }
+
+    fn primitive_topology() -> wgpu::PrimitiveTopology {
+        wgpu::PrimitiveTopology::TriangleList
+    }
+
+    // only the light attachment is written, scaled by one minus the shadow's alpha
+    fn color_state_descriptors() -> Vec<wgpu::ColorStateDescriptor> {
+        let masked = |format| wgpu::ColorStateDescriptor {
+            format,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::empty(),
+        };
+
+        vec![
+            masked(DIFFUSE_ATTACHMENT_FORMAT),
+            masked(NORMAL_ATTACHMENT_FORMAT),
+            wgpu::ColorStateDescriptor {
+                format: LIGHT_ATTACHMENT_FORMAT,
+                alpha_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                write_mask: wgpu::ColorWrite::ALL,
+            },
+        ]
+    }
+
+    // shadows are tested against the scene but don't occlude anything themselves
+    fn depth_stencil_state_descriptor() -> Option<wgpu::DepthStencilStateDescriptor> {
+        Some(wgpu::DepthStencilStateDescriptor {
+            format: DEPTH_ATTACHMENT_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+        })
+    }
+
+    // NOTE: if the vertex format is changed, this descriptor must also be changed accordingly.
+    fn vertex_buffer_descriptors() -> Vec<wgpu::VertexBufferDescriptor<'static>> {
+        vec![wgpu::VertexBufferDescriptor {
+            stride: size_of::<BlobShadowVertex>() as u64,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &wgpu::vertex_attr_array![
+                // position
+                0 => Float3,
+                // texcoord
+                1 => Float2,
+                // alpha
+                2 => Float,
+                // squareness
+                3 => Float,
+            ],
+        }]
+    }
+}
+
+/// A set of blob shadows uploaded for drawing this frame.
+pub struct BlobShadows {
+    vertex_buffer: wgpu::Buffer,
+    vertex_count: u32,
+}
+
+impl BlobShadows {
+    pub fn new(state: &GraphicsState, vertices: &[BlobShadowVertex]) -> BlobShadows {
+        let vertex_buffer = state.device().create_buffer_with_data(
+            unsafe { any_slice_as_bytes(vertices) },
+            wgpu::BufferUsage::VERTEX,
+        );
+
+        BlobShadows {
+            vertex_buffer,
+            vertex_count: vertices.len() as u32,
+        }
+    }
+
+    pub fn record_draw<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut wgpu::RenderPass<'a>,
+        bump: &'a Bump,
+        camera: &Camera,
+    ) {
+        use PushConstantUpdate::*;
+
+        if self.vertex_count == 0 {
+            return;
+        }
+
+        pass.set_pipeline(state.blob_shadow_pipeline().pipeline());
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        BlobShadowPipeline::set_push_constants(
+            pass,
+            Update(bump.alloc(VertexPushConstants {
+                transform: camera.view_projection(),
+            })),
+            Retain,
+            Retain,
+        );
+        pass.draw(0..self.vertex_count, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::Zero as _;
+
+    #[test]
+    fn test_push_blob_shadow_lies_on_surface() {
+        let mut vertices = Vec::new();
+        let origin = Vector3::new(16.0, 32.0, 64.0);
+        let normal = Vector3::new(0.0, 0.6, 0.8);
+        push_blob_shadow(&mut vertices, origin, normal, 24.0, 0.5);
+        assert_eq!(vertices.len(), 6);
+
+        for v in vertices.iter() {
+            let p = frustum::wgpu_to_quake(v.position.into());
+            let offset = p - origin;
+            assert!((offset.dot(normal) - BLOB_SHADOW_OFFSET).abs() < 1e-3);
+            assert!(
+                (offset.magnitude2() - BLOB_SHADOW_OFFSET.powi(2) - 2.0 * 24.0 * 24.0).abs() < 1e-2
+            );
+        }
+    }
+
+    #[test]
+    fn test_push_mover_shadow() {
+        let mins = Vector3::new(0.0, 0.0, 64.0);
+        let maxs = Vector3::new(128.0, 64.0, 72.0);
+        let up = Vector3::unit_z();
+
+        let mut vertices = Vec::new();
+        push_mover_shadow(&mut vertices, mins, maxs, Vector3::new(64.0, 32.0, 0.0), up);
+        assert_eq!(vertices.len(), 6);
+
+        // the shadow covers the mover's footprint plus a margin that grows with height
+        let margin = MOVER_SHADOW_MARGIN + 64.0 * MOVER_SHADOW_SPREAD;
+        let corners: Vec<_> = vertices
+            .iter()
+            .map(|v| frustum::wgpu_to_quake(v.position.into()))
+            .collect();
+        for p in corners.iter() {
+            assert!((p.z - BLOB_SHADOW_OFFSET).abs() < 1e-3);
+        }
+        assert!((corners[0].x - (0.0 - margin)).abs() < 1e-3);
+        assert!((corners[2].y - (64.0 + margin)).abs() < 1e-3);
+        assert!((vertices[0].alpha - MOVER_SHADOW_ALPHA * 0.75).abs() < 1e-6);
+
+        // no shadows on walls, ceilings or surfaces too far below
+        let mut vertices = Vec::new();
+        push_mover_shadow(
+            &mut vertices,
+            mins,
+            maxs,
+            Vector3::zero(),
+            Vector3::unit_x(),
+        );
+        push_mover_shadow(&mut vertices, mins, maxs, Vector3::zero(), -up);
+        push_mover_shadow(
+            &mut vertices,
+            mins,
+            maxs,
+            Vector3::new(0.0, 0.0, -512.0),
+            up,
+        );
+        assert!(vertices.is_empty());
+    }
+}
@@ -0,0 +1,366 @@
+use std::{mem::size_of, num::NonZeroU64};
+
+use crate::{
+    client::render::{
+        pipeline::Pipeline, target::RenderTarget as _, ui::quad::QuadPipeline, GraphicsState,
+        HDR_ATTACHMENT_FORMAT,
+    },
+    common::util::any_as_bytes,
+};
+
+/// The number of horizontal/vertical blur pairs applied to the bright pass.
+const BLUR_ITERATIONS: usize = 2;
+
+lazy_static! {
+    static ref BRIGHT_BIND_GROUP_LAYOUT_DESCRIPTOR_BINDINGS: [Vec<wgpu::BindGroupLayoutEntry>; 1] = [
+        vec![
+            // sampler
+            wgpu::BindGroupLayoutEntry::new(
+                0,
+                wgpu::ShaderStage::FRAGMENT,
+                wgpu::BindingType::Sampler { comparison: false },
+            ),
+
+            // color buffer
+            wgpu::BindGroupLayoutEntry::new(
+                1,
+                wgpu::ShaderStage::FRAGMENT,
+                wgpu::BindingType::SampledTexture {
+                    dimension: wgpu::TextureViewDimension::D2,
+                    component_type: wgpu::TextureComponentType::Float,
+                    multisampled: true,
+                },
+            ),
+        ]
+    ];
+
+    static ref BLUR_BIND_GROUP_LAYOUT_DESCRIPTOR_BINDINGS: [Vec<wgpu::BindGroupLayoutEntry>; 1] = [
+        vec![
+            // sampler
+            wgpu::BindGroupLayoutEntry::new(
+                0,
+                wgpu::ShaderStage::FRAGMENT,
+                wgpu::BindingType::Sampler { comparison: false },
+            ),
+
+            // bloom buffer
+            wgpu::BindGroupLayoutEntry::new(
+                1,
+                wgpu::ShaderStage::FRAGMENT,
+                wgpu::BindingType::SampledTexture {
+                    dimension: wgpu::TextureViewDimension::D2,
+                    component_type: wgpu::TextureComponentType::Float,
+                    multisampled: false,
+                },
+            ),
+
+            // BlurUniforms
+            wgpu::BindGroupLayoutEntry::new(
+                2,
+                wgpu::ShaderStage::FRAGMENT,
+                wgpu::BindingType::UniformBuffer {
+                    dynamic: false,
+                    min_binding_size: Some(
+                        NonZeroU64::new(size_of::<BlurUniforms>() as u64).unwrap()
+                    ),
+                },
+            ),
+        ]
+    ];
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct BlurUniforms {
+    /// Texel offset between blur taps, either (1, 0) or (0, 1).
+    pub direction: [i32; 2],
+}
+
+/// Extracts the over-bright part of the deferred output at half resolution.
+pub struct BloomBrightPipeline {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+}
+
+impl BloomBrightPipeline {
+    pub fn new(device: &wgpu::Device, compiler: &mut shaderc::Compiler) -> BloomBrightPipeline {
+        let (pipeline, bind_group_layouts) = BloomBrightPipeline::create(device, compiler, &[], 1);
+
+        BloomBrightPipeline {
+            pipeline,
+            bind_group_layouts,
+        }
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn bind_group_layouts(&self) -> &[wgpu::BindGroupLayout] {
+        &self.bind_group_layouts
+    }
+}
+
+impl Pipeline for BloomBrightPipeline {
+    type VertexPushConstants = ();
+    type SharedPushConstants = ();
+    type FragmentPushConstants = ();
+
+    fn name() -> &'static str {
+        "bloom_bright"
+    }
+
+    fn bind_group_layout_descriptors() -> Vec<wgpu::BindGroupLayoutDescriptor<'static>> {
+        vec![wgpu::BindGroupLayoutDescriptor {
+            label: Some("bloom bright pass bind group"),
+            entries: &BRIGHT_BIND_GROUP_LAYOUT_DESCRIPTOR_BINDINGS[0],
+        }]
+    }
+
+    fn vertex_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/bloom.vert"))
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/bloom_bright.frag"
+        ))
+    }
+
+    fn rasterization_state_descriptor() -> Option<wgpu::RasterizationStateDescriptor> {
+        QuadPipeline::rasterization_state_descriptor()
+    }
+
+    fn primitive_topology() -> wgpu::PrimitiveTopology {
+        QuadPipeline::primitive_topology()
+    }
+
+    fn color_state_descriptors() -> Vec<wgpu::ColorStateDescriptor> {
+        bloom_color_state_descriptors()
+    }
+
+    fn depth_stencil_state_descriptor() -> Option<wgpu::DepthStencilStateDescriptor> {
+        None
+    }
+
+    fn vertex_buffer_descriptors() -> Vec<wgpu::VertexBufferDescriptor<'static>> {
+        QuadPipeline::vertex_buffer_descriptors()
+    }
+}
+
+/// Applies one direction of a separable gaussian blur to a bloom target.
+pub struct BloomBlurPipeline {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+    horizontal_uniform_buffer: wgpu::Buffer,
+    vertical_uniform_buffer: wgpu::Buffer,
+}
+
+impl BloomBlurPipeline {
+    pub fn new(device: &wgpu::Device, compiler: &mut shaderc::Compiler) -> BloomBlurPipeline {
+        let (pipeline, bind_group_layouts) = BloomBlurPipeline::create(device, compiler, &[], 1);
+
+        // the blur directions never change, so these are written once here
+        let horizontal_uniform_buffer = device.create_buffer_with_data(
+            unsafe { any_as_bytes(&BlurUniforms { direction: [1, 0] }) },
+            wgpu::BufferUsage::UNIFORM,
+        );
+        let vertical_uniform_buffer = device.create_buffer_with_data(
+            unsafe { any_as_bytes(&BlurUniforms { direction: [0, 1] }) },
+            wgpu::BufferUsage::UNIFORM,
+        );
+
+        BloomBlurPipeline {
+            pipeline,
+            bind_group_layouts,
+            horizontal_uniform_buffer,
+            vertical_uniform_buffer,
+        }
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn bind_group_layouts(&self) -> &[wgpu::BindGroupLayout] {
+        &self.bind_group_layouts
+    }
+
+    pub fn horizontal_uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.horizontal_uniform_buffer
+    }
+
+    pub fn vertical_uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.vertical_uniform_buffer
+    }
+}
+
+impl Pipeline for BloomBlurPipeline {
+    type VertexPushConstants = ();
+    type SharedPushConstants = ();
+    type FragmentPushConstants = ();
+
+    fn name() -> &'static str {
+        "bloom_blur"
+    }
+
+    fn bind_group_layout_descriptors() -> Vec<wgpu::BindGroupLayoutDescriptor<'static>> {
+        vec![wgpu::BindGroupLayoutDescriptor {
+            label: Some("bloom blur bind group"),
+            entries: &BLUR_BIND_GROUP_LAYOUT_DESCRIPTOR_BINDINGS[0],
+        }]
+    }
+
+    fn vertex_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/bloom.vert"))
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/bloom_blur.frag"
+        ))
+    }
+
+    fn rasterization_state_descriptor() -> Option<wgpu::RasterizationStateDescriptor> {
+        QuadPipeline::rasterization_state_descriptor()
+    }
+
+    fn primitive_topology() -> wgpu::PrimitiveTopology {
+        QuadPipeline::primitive_topology()
+    }
+
+    fn color_state_descriptors() -> Vec<wgpu::ColorStateDescriptor> {
+        bloom_color_state_descriptors()
+    }
+
+    fn depth_stencil_state_descriptor() -> Option<wgpu::DepthStencilStateDescriptor> {
+        None
+    }
+
+    fn vertex_buffer_descriptors() -> Vec<wgpu::VertexBufferDescriptor<'static>> {
+        QuadPipeline::vertex_buffer_descriptors()
+    }
+}
+
+fn bloom_color_state_descriptors() -> Vec<wgpu::ColorStateDescriptor> {
+    vec![wgpu::ColorStateDescriptor {
+        format: HDR_ATTACHMENT_FORMAT,
+        alpha_blend: wgpu::BlendDescriptor::REPLACE,
+        color_blend: wgpu::BlendDescriptor::REPLACE,
+        write_mask: wgpu::ColorWrite::ALL,
+    }]
+}
+
+/// Renders bloom from the deferred output into the first bloom target.
+///
+/// The bright pass writes to target 0, then each blur iteration blurs horizontally from target 0
+/// into target 1 and vertically from target 1 back into target 0.
+pub struct BloomRenderer {
+    bright_bind_group: wgpu::BindGroup,
+    horizontal_bind_group: wgpu::BindGroup,
+    vertical_bind_group: wgpu::BindGroup,
+}
+
+impl BloomRenderer {
+    /// Creates a renderer that reads from `color_buffer` into the bloom targets.
+    ///
+    /// Returns `None` if the bloom targets don't exist because bloom is disabled.
+    pub fn new(state: &GraphicsState, color_buffer: &wgpu::TextureView) -> Option<BloomRenderer> {
+        let targets = state.bloom_pass_targets()?;
+        let bright_bind_group = state
+            .device()
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("bloom bright pass bind group"),
+                layout: &state.bloom_bright_pipeline().bind_group_layouts()[0],
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::Sampler(state.diffuse_sampler()),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(color_buffer),
+                    },
+                ],
+            });
+
+        let horizontal_bind_group = create_blur_bind_group(
+            state,
+            targets[0].color_view(),
+            state.bloom_blur_pipeline().horizontal_uniform_buffer(),
+        );
+        let vertical_bind_group = create_blur_bind_group(
+            state,
+            targets[1].color_view(),
+            state.bloom_blur_pipeline().vertical_uniform_buffer(),
+        );
+
+        Some(BloomRenderer {
+            bright_bind_group,
+            horizontal_bind_group,
+            vertical_bind_group,
+        })
+    }
+
+    /// Records the bright pass and blur passes into `encoder`.
+    pub fn render_bloom(&self, state: &GraphicsState, encoder: &mut wgpu::CommandEncoder) {
+        let targets = match state.bloom_pass_targets() {
+            Some(t) => t,
+            None => return,
+        };
+
+        {
+            let bright_pass_builder = targets[0].render_pass_builder();
+            let mut pass = encoder.begin_render_pass(&bright_pass_builder.descriptor());
+            pass.set_pipeline(state.bloom_bright_pipeline().pipeline());
+            pass.set_vertex_buffer(0, state.quad_pipeline().vertex_buffer().slice(..));
+            pass.set_bind_group(0, &self.bright_bind_group, &[]);
+            pass.draw(0..6, 0..1);
+        }
+
+        for _ in 0..BLUR_ITERATIONS {
+            for (target, bind_group) in [
+                (&targets[1], &self.horizontal_bind_group),
+                (&targets[0], &self.vertical_bind_group),
+            ]
+            .iter()
+            {
+                let blur_pass_builder = target.render_pass_builder();
+                let mut pass = encoder.begin_render_pass(&blur_pass_builder.descriptor());
+                pass.set_pipeline(state.bloom_blur_pipeline().pipeline());
+                pass.set_vertex_buffer(0, state.quad_pipeline().vertex_buffer().slice(..));
+                pass.set_bind_group(0, bind_group, &[]);
+                pass.draw(0..6, 0..1);
+            }
+        }
+    }
+}
+
+fn create_blur_bind_group(
+    state: &GraphicsState,
+    input: &wgpu::TextureView,
+    uniform_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    state
+        .device()
+        .create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("bloom blur bind group"),
+            layout: &state.bloom_blur_pipeline().bind_group_layouts()[0],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Sampler(state.diffuse_sampler()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(input),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(uniform_buffer.slice(..)),
+                },
+            ],
+        })
+}
@@ -30,24 +30,29 @@ use std::{
 use crate::{
     client::render::{
         pipeline::PushConstantUpdate,
-        warp,
-        world::{BindGroupLayoutId, WorldPipelineBase},
-        Camera, GraphicsState, LightmapData, Pipeline, TextureData,
+        replacement, warp,
+        world::{
+            bake::{LightBake, TexelBasis},
+            BindGroupLayoutId, WorldPipelineBase,
+        },
+        Camera, DiffuseData, FullbrightData, GraphicsState, LightmapData, Pipeline, TextureData,
+        DEPTH_ATTACHMENT_FORMAT, HDR_ATTACHMENT_FORMAT,
     },
     common::{
         bsp::{
-            self, BspData, BspFace, BspLeaf, BspModel, BspTexInfo, BspTexture, BspTextureKind,
-            BspTextureMipmap,
+            self, BspData, BspFace, BspLeaf, BspLeafContents, BspLightmap, BspModel, BspTexInfo,
+            BspTexture, BspTextureFrame, BspTextureKind, BspTextureMipmap,
         },
         math,
         util::any_slice_as_bytes,
     },
 };
 
-use bumpalo::Bump;
+use bumpalo::{collections::Vec as BumpVec, Bump};
 use cgmath::{InnerSpace as _, Matrix4, Vector3};
 use chrono::Duration;
 use failure::Error;
+use rayon::prelude::*;
 
 lazy_static! {
     static ref BIND_GROUP_LAYOUT_DESCRIPTOR_BINDINGS: [Vec<wgpu::BindGroupLayoutEntry>; 2] = [
@@ -89,6 +94,21 @@ lazy_static! {
             },
         ],
     ];
+
+    static ref WATER_BIND_GROUP_LAYOUT_DESCRIPTOR_BINDINGS: [Vec<wgpu::BindGroupLayoutEntry>; 1] = [
+        vec![
+            // sky texture, reflected by liquid surfaces
+            wgpu::BindGroupLayoutEntry::new(
+                0,
+                wgpu::ShaderStage::FRAGMENT,
+                wgpu::BindingType::SampledTexture {
+                    dimension: wgpu::TextureViewDimension::D2,
+                    component_type: wgpu::TextureComponentType::Float,
+                    multisampled: false,
+                },
+            ),
+        ],
+    ];
 }
 
 pub struct BrushPipeline {
@@ -153,6 +173,8 @@ pub struct VertexPushConstants {
 #[derive(Copy, Clone, Debug)]
 pub struct SharedPushConstants {
     pub texture_kind: u32,
+    /// Nonzero if the surface is under water and shows caustics.
+    pub caustics: u32,
 }
 
 impl Pipeline for BrushPipeline {
@@ -226,6 +248,182 @@ impl Pipeline for BrushPipeline {
     }
 }
 
+/// Draws translucent liquid surfaces over the output of the deferred lighting pass.
+///
+/// This shares the vertex shader and per-texture bind group layout of the `BrushPipeline`, so the
+/// brush renderer's texture bind groups can be used as-is. Liquids aren't lightmapped, so the
+/// per-face bind group is replaced by one holding the sky texture for reflections.
+pub struct WaterPipeline {
+    pipeline: wgpu::RenderPipeline,
+    sky_bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl WaterPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        world_bind_group_layouts: &[wgpu::BindGroupLayout],
+        brush_pipeline: &BrushPipeline,
+        sample_count: u32,
+    ) -> WaterPipeline {
+        let sky_bind_group_layout =
+            device.create_bind_group_layout(&WaterPipeline::bind_group_layout_descriptors()[0]);
+        let pipeline = WaterPipeline::create_pipeline(
+            device,
+            compiler,
+            world_bind_group_layouts,
+            brush_pipeline,
+            &sky_bind_group_layout,
+            sample_count,
+        );
+
+        WaterPipeline {
+            pipeline,
+            sky_bind_group_layout,
+        }
+    }
+
+    pub fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        world_bind_group_layouts: &[wgpu::BindGroupLayout],
+        brush_pipeline: &BrushPipeline,
+        sample_count: u32,
+    ) {
+        // keep the sky layout so existing sky bind groups remain valid
+        self.pipeline = WaterPipeline::create_pipeline(
+            device,
+            compiler,
+            world_bind_group_layouts,
+            brush_pipeline,
+            &self.sky_bind_group_layout,
+            sample_count,
+        );
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        world_bind_group_layouts: &[wgpu::BindGroupLayout],
+        brush_pipeline: &BrushPipeline,
+        sky_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let layout_refs: Vec<_> = world_bind_group_layouts
+            .iter()
+            .chain(std::iter::once(
+                brush_pipeline.bind_group_layout(BindGroupLayoutId::PerTexture),
+            ))
+            .chain(std::iter::once(sky_bind_group_layout))
+            .collect();
+        WaterPipeline::recreate(device, compiler, &layout_refs, sample_count)
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn sky_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.sky_bind_group_layout
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct WaterPushConstants {
+    pub alpha: f32,
+
+    /// How strongly liquids reflect the sky, from 0 to 1.
+    pub reflection: f32,
+}
+
+impl Pipeline for WaterPipeline {
+    type VertexPushConstants = VertexPushConstants;
+    type SharedPushConstants = SharedPushConstants;
+    type FragmentPushConstants = WaterPushConstants;
+
+    fn name() -> &'static str {
+        "water"
+    }
+
+    fn vertex_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/brush.vert"))
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/water.frag"))
+    }
+
+    // only the sky layout, the others are the world and brush pipelines' layouts
+    fn bind_group_layout_descriptors() -> Vec<wgpu::BindGroupLayoutDescriptor<'static>> {
+        vec![wgpu::BindGroupLayoutDescriptor {
+            label: Some("water sky bind group"),
+            entries: &WATER_BIND_GROUP_LAYOUT_DESCRIPTOR_BINDINGS[0],
+        }]
+    }
+
+    fn rasterization_state_descriptor() -> Option<wgpu::RasterizationStateDescriptor> {
+        WorldPipelineBase::rasterization_state_descriptor()
+    }
+
+    fn primitive_topology() -> wgpu::PrimitiveTopology {
+        wgpu::PrimitiveTopology::TriangleList
+    }
+
+    fn color_state_descriptors() -> Vec<wgpu::ColorStateDescriptor> {
+        vec![wgpu::ColorStateDescriptor {
+            format: HDR_ATTACHMENT_FORMAT,
+            color_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        }]
+    }
+
+    // test against the geometry pass depth, but don't occlude anything behind other liquids
+    fn depth_stencil_state_descriptor() -> Option<wgpu::DepthStencilStateDescriptor> {
+        Some(wgpu::DepthStencilStateDescriptor {
+            format: DEPTH_ATTACHMENT_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+        })
+    }
+
+    fn vertex_buffer_descriptors() -> Vec<wgpu::VertexBufferDescriptor<'static>> {
+        BrushPipeline::vertex_buffer_descriptors()
+    }
+}
+
+// Interleave a lightmap's colored and monochrome light into RGBA texels. Lightmaps without colored
+// light are expanded to gray.
+fn lightmap_rgba(lightmap: &BspLightmap) -> Vec<u8> {
+    let mono = lightmap.data();
+    let mut rgba = Vec::with_capacity(4 * mono.len());
+
+    match lightmap.rgb() {
+        Some(rgb) => {
+            for (texel, &m) in rgb.chunks_exact(3).zip(mono.iter()) {
+                rgba.extend_from_slice(&[texel[0], texel[1], texel[2], m]);
+            }
+        }
+        None => {
+            for &m in mono {
+                rgba.extend_from_slice(&[m, m, m, m]);
+            }
+        }
+    }
+
+    rgba
+}
+
 fn calculate_lightmap_texcoords(
     position: Vector3<f32>,
     face: &BspFace,
@@ -251,7 +449,7 @@ type LightmapAnim = [u8; 4];
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
-struct BrushVertex {
+pub struct BrushVertex {
     position: Position,
     normal: Normal,
     diffuse_texcoord: DiffuseTexcoord,
@@ -260,11 +458,15 @@ struct BrushVertex {
 }
 
 #[repr(u32)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TextureKind {
     Normal = 0,
     Warp = 1,
     Sky = 2,
+
+    /// A lightmapped texture whose pixels of palette index 255 are see-through, used for grates
+    /// and foliage. Marked by a `{` at the start of the texture name.
+    Masked = 3,
 }
 
 /// A single frame of a brush texture.
@@ -303,6 +505,9 @@ impl BrushTexture {
 
 #[derive(Debug)]
 struct BrushFace {
+    // the id of the face in the BSP data
+    bsp_face_id: usize,
+
     vertices: Range<u32>,
     min: Vector3<f32>,
     max: Vector3<f32>,
@@ -312,6 +517,9 @@ struct BrushFace {
     lightmap_ids: Vec<usize>,
     light_styles: [u8; 4],
 
+    /// Whether the face lies under water or slime and should show caustics.
+    caustics: bool,
+
     /// Indicates whether the face should be drawn this frame.
     ///
     /// This is set to false by default, and will be set to true if the model is
@@ -322,6 +530,9 @@ struct BrushFace {
 
 struct BrushLeaf {
     facelist_ids: Range<usize>,
+
+    // whether the leaf is full of a liquid that light ripples through
+    underwater: bool,
 }
 
 impl<B> std::convert::From<B> for BrushLeaf
@@ -332,16 +543,168 @@ where
         let bsp_leaf = bsp_leaf.borrow();
         BrushLeaf {
             facelist_ids: bsp_leaf.facelist_id..bsp_leaf.facelist_id + bsp_leaf.facelist_count,
+            underwater: match bsp_leaf.contents {
+                BspLeafContents::Water | BspLeafContents::Slime => true,
+                _ => false,
+            },
         }
     }
 }
 
+// iterate over every frame of a texture, primary frames first
+fn texture_frames<'a>(tex: &'a BspTexture) -> Box<dyn Iterator<Item = &'a BspTextureFrame> + 'a> {
+    match tex.kind() {
+        BspTextureKind::Static(frame) => Box::new(std::iter::once(frame)),
+        BspTextureKind::Animated { primary, alternate } => Box::new(
+            primary
+                .iter()
+                .chain(alternate.iter().flat_map(|a| a.iter())),
+        ),
+    }
+}
+
+// the vertices and lightmap texels of a face, which can be built independently of the others
+struct FaceMesh {
+    face_id: usize,
+    vertices: Vec<BrushVertex>,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+    texture_id: usize,
+    light_styles: [u8; 4],
+    // width, height and RGBA texels of each lightmap
+    lightmaps: Vec<(u32, u32, Vec<u8>)>,
+}
+
+fn build_face_mesh(bsp_data: &BspData, face_id: usize, subdivide_size: f32) -> FaceMesh {
+    let face = &bsp_data.faces()[face_id];
+    let texinfo = &bsp_data.texinfo()[face.texinfo_id];
+    let tex = &bsp_data.textures()[texinfo.tex_id];
+    let mut vertices = Vec::new();
+
+    let mut min = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    let mut max = Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+
+    let no_collinear = math::remove_collinear(bsp_data.face_iter_vertices(face_id).collect());
+
+    for vert in no_collinear.iter() {
+        for component in 0..3 {
+            min[component] = min[component].min(vert[component]);
+            max[component] = max[component].max(vert[component]);
+        }
+    }
+
+    if tex.name().starts_with("*") || tex.name().starts_with("sky") {
+        // tessellate the surface so we can do texcoord warping. the sky projection is
+        // calculated per vertex, so it needs the extra vertices too.
+        let verts = warp::subdivide(no_collinear, subdivide_size);
+        let normal = (verts[0] - verts[1]).cross(verts[2] - verts[1]).normalize();
+        for vert in verts.into_iter() {
+            vertices.push(BrushVertex {
+                position: vert.into(),
+                normal: normal.into(),
+                diffuse_texcoord: [
+                    ((vert.dot(texinfo.s_vector) + texinfo.s_offset) / tex.width() as f32),
+                    ((vert.dot(texinfo.t_vector) + texinfo.t_offset) / tex.height() as f32),
+                ],
+                lightmap_texcoord: calculate_lightmap_texcoords(vert.into(), face, texinfo),
+                lightmap_anim: face.light_styles,
+            })
+        }
+    } else {
+        // expand the vertices into a triangle list.
+        // the vertices are guaranteed to be in valid triangle fan order (that's
+        // how GLQuake renders them) so we expand from triangle fan to triangle
+        // list order.
+        //
+        // v1 is the base vertex, so it remains constant.
+        // v2 takes the previous value of v3.
+        // v3 is the newest vertex.
+        let verts = no_collinear;
+        let normal = (verts[0] - verts[1]).cross(verts[2] - verts[1]).normalize();
+        let mut vert_iter = verts.into_iter();
+
+        let v1 = vert_iter.next().unwrap();
+        let mut v2 = vert_iter.next().unwrap();
+        for v3 in vert_iter {
+            let tri = &[v1, v2, v3];
+
+            // skip collinear points
+            for vert in tri.iter() {
+                vertices.push(BrushVertex {
+                    position: (*vert).into(),
+                    normal: normal.into(),
+                    diffuse_texcoord: [
+                        ((vert.dot(texinfo.s_vector) + texinfo.s_offset) / tex.width() as f32),
+                        ((vert.dot(texinfo.t_vector) + texinfo.t_offset) / tex.height() as f32),
+                    ],
+                    lightmap_texcoord: calculate_lightmap_texcoords((*vert).into(), face, texinfo),
+                    lightmap_anim: face.light_styles,
+                });
+            }
+
+            v2 = v3;
+        }
+    }
+
+    // build the lightmaps
+    let lightmaps = if !texinfo.special {
+        bsp_data
+            .face_lightmaps(face_id)
+            .iter()
+            .map(|lightmap| (lightmap.width(), lightmap.height(), lightmap_rgba(lightmap)))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    FaceMesh {
+        face_id,
+        vertices,
+        min,
+        max,
+        texture_id: texinfo.tex_id as usize,
+        light_styles: face.light_styles,
+        lightmaps,
+    }
+}
+
+fn create_per_face_bind_group(
+    state: &GraphicsState,
+    lightmaps: &[wgpu::Texture],
+    face: &BrushFace,
+) -> wgpu::BindGroup {
+    let mut lightmap_views: Vec<_> = face
+        .lightmap_ids
+        .iter()
+        .map(|id| lightmaps[*id].create_default_view())
+        .collect();
+    lightmap_views.resize_with(4, || state.default_lightmap().create_default_view());
+    let layout = &state
+        .brush_pipeline()
+        .bind_group_layout(BindGroupLayoutId::PerFace);
+    let desc = wgpu::BindGroupDescriptor {
+        label: Some("per-face bind group"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureViewArray(&lightmap_views[..]),
+        }],
+    };
+    state.device().create_bind_group(&desc)
+}
+
 pub struct BrushRendererBuilder {
     bsp_data: Rc<BspData>,
     face_range: Range<usize>,
 
     leaves: Option<Vec<BrushLeaf>>,
 
+    // the map whose replacement textures should be used, if any
+    replacement_map: Option<String>,
+
+    // grid size used to tessellate liquid and sky surfaces
+    subdivide_size: f32,
+
     per_texture_bind_groups: RefCell<Vec<wgpu::BindGroup>>,
     per_face_bind_groups: Vec<wgpu::BindGroup>,
 
@@ -368,6 +731,8 @@ impl BrushRendererBuilder {
             } else {
                 None
             },
+            replacement_map: None,
+            subdivide_size: warp::DEFAULT_SUBDIVIDE_SIZE,
             per_texture_bind_groups: RefCell::new(Vec::new()),
             per_face_bind_groups: Vec::new(),
             vertices: Vec::new(),
@@ -379,96 +744,39 @@ impl BrushRendererBuilder {
         }
     }
 
-    fn create_face(&mut self, state: &GraphicsState, face_id: usize) -> BrushFace {
-        let face = &self.bsp_data.faces()[face_id];
-        let face_vert_id = self.vertices.len();
-        let texinfo = &self.bsp_data.texinfo()[face.texinfo_id];
-        let tex = &self.bsp_data.textures()[texinfo.tex_id];
-
-        let mut min = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
-        let mut max = Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
-
-        let no_collinear =
-            math::remove_collinear(self.bsp_data.face_iter_vertices(face_id).collect());
-
-        for vert in no_collinear.iter() {
-            for component in 0..3 {
-                min[component] = min[component].min(vert[component]);
-                max[component] = max[component].max(vert[component]);
-            }
-        }
-
-        if tex.name().starts_with("*") {
-            // tessellate the surface so we can do texcoord warping
-            let verts = warp::subdivide(no_collinear);
-            let normal = (verts[0] - verts[1]).cross(verts[2] - verts[1]).normalize();
-            for vert in verts.into_iter() {
-                self.vertices.push(BrushVertex {
-                    position: vert.into(),
-                    normal: normal.into(),
-                    diffuse_texcoord: [
-                        ((vert.dot(texinfo.s_vector) + texinfo.s_offset) / tex.width() as f32),
-                        ((vert.dot(texinfo.t_vector) + texinfo.t_offset) / tex.height() as f32),
-                    ],
-                    lightmap_texcoord: calculate_lightmap_texcoords(vert.into(), face, texinfo),
-                    lightmap_anim: face.light_styles,
-                })
-            }
-        } else {
-            // expand the vertices into a triangle list.
-            // the vertices are guaranteed to be in valid triangle fan order (that's
-            // how GLQuake renders them) so we expand from triangle fan to triangle
-            // list order.
-            //
-            // v1 is the base vertex, so it remains constant.
-            // v2 takes the previous value of v3.
-            // v3 is the newest vertex.
-            let verts = no_collinear;
-            let normal = (verts[0] - verts[1]).cross(verts[2] - verts[1]).normalize();
-            let mut vert_iter = verts.into_iter();
-
-            let v1 = vert_iter.next().unwrap();
-            let mut v2 = vert_iter.next().unwrap();
-            for v3 in vert_iter {
-                let tri = &[v1, v2, v3];
-
-                // skip collinear points
-                for vert in tri.iter() {
-                    self.vertices.push(BrushVertex {
-                        position: (*vert).into(),
-                        normal: normal.into(),
-                        diffuse_texcoord: [
-                            ((vert.dot(texinfo.s_vector) + texinfo.s_offset) / tex.width() as f32),
-                            ((vert.dot(texinfo.t_vector) + texinfo.t_offset) / tex.height() as f32),
-                        ],
-                        lightmap_texcoord: calculate_lightmap_texcoords(
-                            (*vert).into(),
-                            face,
-                            texinfo,
-                        ),
-                        lightmap_anim: face.light_styles,
-                    });
-                }
+    /// Use replacement textures from the VFS in place of the BSP textures where available.
+    ///
+    /// `map_name` selects the map-specific replacement directory. See the `replacement` module
+    /// for the search order.
+    pub fn replacement_textures<S>(mut self, map_name: S) -> BrushRendererBuilder
+    where
+        S: AsRef<str>,
+    {
+        self.replacement_map = Some(map_name.as_ref().to_owned());
+        self
+    }
 
-                v2 = v3;
-            }
-        }
+    /// Tessellate liquid and sky surfaces on a grid of the given size, in world units.
+    ///
+    /// Smaller sizes make texture warping and the sky projection smoother at the cost of more
+    /// vertices.
+    pub fn subdivide_size(mut self, size: f32) -> BrushRendererBuilder {
+        self.subdivide_size = size;
+        self
+    }
 
-        // build the lightmaps
-        let lightmaps = if !texinfo.special {
-            self.bsp_data.face_lightmaps(face_id)
-        } else {
-            Vec::new()
-        };
+    // upload the lightmaps of a face built by `build_face_mesh()` and append its vertices
+    fn create_face(&mut self, state: &GraphicsState, mesh: FaceMesh) -> BrushFace {
+        let face_vert_id = self.vertices.len();
+        self.vertices.extend(mesh.vertices);
 
         let mut lightmap_ids = Vec::new();
-        for lightmap in lightmaps {
+        for (width, height, rgba) in mesh.lightmaps {
             let lightmap_data = TextureData::Lightmap(LightmapData {
-                lightmap: Cow::Borrowed(lightmap.data()),
+                lightmap: Cow::Owned(rgba),
             });
 
-            let texture =
-                state.create_texture(None, lightmap.width(), lightmap.height(), &lightmap_data);
+            let texture = state.create_texture(None, width, height, &lightmap_data);
 
             let id = self.lightmaps.len();
             self.lightmaps.push(texture);
@@ -478,12 +786,14 @@ impl BrushRendererBuilder {
         }
 
         BrushFace {
+            bsp_face_id: mesh.face_id,
             vertices: face_vert_id as u32..self.vertices.len() as u32,
-            min,
-            max,
-            texture_id: texinfo.tex_id as usize,
+            min: mesh.min,
+            max: mesh.max,
+            texture_id: mesh.texture_id,
             lightmap_ids,
-            light_styles: face.light_styles,
+            light_styles: mesh.light_styles,
+            caustics: false,
             draw_flag: Cell::new(true),
         }
     }
@@ -513,31 +823,39 @@ impl BrushRendererBuilder {
         state.device().create_bind_group(&desc)
     }
 
-    fn create_per_face_bind_group(&self, state: &GraphicsState, face_id: usize) -> wgpu::BindGroup {
-        let mut lightmap_views: Vec<_> = self.faces[face_id]
-            .lightmap_ids
+    // liquids reflect the first sky texture of the model, or nothing if it has no sky
+    fn create_sky_bind_group(&self, state: &GraphicsState) -> (wgpu::BindGroup, bool) {
+        let sky_frame = self
+            .textures
             .iter()
-            .map(|id| self.lightmaps[*id].create_default_view())
-            .collect();
-        lightmap_views.resize_with(4, || state.default_lightmap().create_default_view());
-        let layout = &state
-            .brush_pipeline()
-            .bind_group_layout(BindGroupLayoutId::PerFace);
+            .filter_map(|tex| match tex {
+                BrushTexture::Static(ref frame) => Some(frame),
+                BrushTexture::Animated { ref primary, .. } => primary.first(),
+            })
+            .find(|frame| frame.kind == TextureKind::Sky);
+
+        let view = match sky_frame {
+            Some(frame) => &frame.diffuse_view,
+            None => state.default_lightmap_view(),
+        };
+
         let desc = wgpu::BindGroupDescriptor {
-            label: Some("per-face bind group"),
-            layout,
+            label: Some("water sky bind group"),
+            layout: state.water_pipeline().sky_bind_group_layout(),
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
-                resource: wgpu::BindingResource::TextureViewArray(&lightmap_views[..]),
+                resource: wgpu::BindingResource::TextureView(view),
             }],
         };
-        state.device().create_bind_group(&desc)
+        (state.device().create_bind_group(&desc), sky_frame.is_some())
     }
 
+    // `translated` is the frame's full-size mipmap as translated by the palette
     fn create_brush_texture_frame<S>(
         &self,
         state: &GraphicsState,
-        mipmap: &[u8],
+        frame: &BspTextureFrame,
+        translated: (DiffuseData<'static>, FullbrightData<'static>),
         width: u32,
         height: u32,
         name: S,
@@ -547,10 +865,50 @@ impl BrushRendererBuilder {
     {
         let name = name.as_ref();
 
-        let (diffuse_data, fullbright_data) = state.palette().translate(mipmap);
-        let diffuse =
-            state.create_texture(None, width, height, &TextureData::Diffuse(diffuse_data));
-        let fullbright = state.create_texture(
+        let (diffuse_data, fullbright_data) = translated;
+
+        let replacement = self
+            .replacement_map
+            .as_ref()
+            .and_then(|map| replacement::find(state.vfs(), map, frame.name()));
+
+        // replacements may have any dimensions since texcoords are normalized
+        let (width, height, diffuse_data, fullbright_data) = match replacement {
+            Some(r) => {
+                // without a luma image, scale the fullbright mask of the original texture
+                let fullbright = r.fullbright.unwrap_or_else(|| {
+                    replacement::resize_mask(
+                        &fullbright_data.fullbright,
+                        width,
+                        height,
+                        r.diffuse.width,
+                        r.diffuse.height,
+                    )
+                });
+
+                (
+                    r.diffuse.width,
+                    r.diffuse.height,
+                    DiffuseData {
+                        rgba: Cow::Owned(r.diffuse.rgba),
+                    },
+                    FullbrightData {
+                        fullbright: Cow::Owned(fullbright),
+                    },
+                )
+            }
+
+            None => (width, height, diffuse_data, fullbright_data),
+        };
+
+        let diffuse = state.create_world_texture(
+            None,
+            width,
+            height,
+            &TextureData::Diffuse(diffuse_data),
+            true,
+        );
+        let fullbright = state.create_texture_mipmapped(
             None,
             width,
             height,
@@ -564,6 +922,8 @@ impl BrushRendererBuilder {
             TextureKind::Sky
         } else if name.starts_with("*") {
             TextureKind::Warp
+        } else if name.starts_with("{") {
+            TextureKind::Masked
         } else {
             TextureKind::Normal
         };
@@ -588,39 +948,33 @@ impl BrushRendererBuilder {
         frame
     }
 
-    pub fn create_brush_texture(&self, state: &GraphicsState, tex: &BspTexture) -> BrushTexture {
-        // TODO: upload mipmaps
+    /// Create the textures for all frames of `tex`.
+    ///
+    /// `translated` holds the palette translation of each frame, in the order returned by
+    /// `texture_frames()`.
+    fn create_brush_texture<I>(
+        &self,
+        state: &GraphicsState,
+        tex: &BspTexture,
+        translated: I,
+    ) -> BrushTexture
+    where
+        I: IntoIterator<Item = (DiffuseData<'static>, FullbrightData<'static>)>,
+    {
         let (width, height) = tex.dimensions();
+        let mut translated = translated.into_iter();
+        let mut create_frame = |f: &BspTextureFrame| {
+            let t = translated.next().unwrap();
+            self.create_brush_texture_frame(state, f, t, width, height, tex.name())
+        };
 
         match tex.kind() {
             // sequence animated textures
             BspTextureKind::Animated { primary, alternate } => {
-                let primary_frames: Vec<_> = primary
-                    .iter()
-                    .map(|f| {
-                        self.create_brush_texture_frame(
-                            state,
-                            f.mipmap(BspTextureMipmap::Full),
-                            width,
-                            height,
-                            tex.name(),
-                        )
-                    })
-                    .collect();
-
-                let alternate_frames: Option<Vec<_>> = alternate.as_ref().map(|a| {
-                    a.iter()
-                        .map(|f| {
-                            self.create_brush_texture_frame(
-                                state,
-                                f.mipmap(BspTextureMipmap::Full),
-                                width,
-                                height,
-                                tex.name(),
-                            )
-                        })
-                        .collect()
-                });
+                let primary_frames: Vec<_> = primary.iter().map(&mut create_frame).collect();
+                let alternate_frames: Option<Vec<_>> = alternate
+                    .as_ref()
+                    .map(|a| a.iter().map(&mut create_frame).collect());
 
                 BrushTexture::Animated {
                     primary: primary_frames,
@@ -628,30 +982,45 @@ impl BrushRendererBuilder {
                 }
             }
 
-            BspTextureKind::Static(bsp_tex) => {
-                BrushTexture::Static(self.create_brush_texture_frame(
-                    state,
-                    bsp_tex.mipmap(BspTextureMipmap::Full),
-                    tex.width(),
-                    tex.height(),
-                    tex.name(),
-                ))
-            }
+            BspTextureKind::Static(bsp_tex) => BrushTexture::Static(create_frame(bsp_tex)),
         }
     }
 
     pub fn build(mut self, state: &GraphicsState) -> Result<BrushRenderer, Error> {
+        // palette translation and face meshes don't touch the GPU, so they're built on the thread
+        // pool. textures and buffers are then created from the results in order.
+        let bsp_rc = self.bsp_data.clone();
+        let bsp_data: &BspData = &bsp_rc;
+        let palette = state.palette();
+        let translated: Vec<Vec<_>> = bsp_data
+            .textures()
+            .par_iter()
+            .map(|tex| {
+                texture_frames(tex)
+                    .map(|f| palette.translate(f.mipmap(BspTextureMipmap::Full)))
+                    .collect()
+            })
+            .collect();
+
+        let subdivide_size = self.subdivide_size;
+        let meshes: Vec<_> = self
+            .face_range
+            .clone()
+            .into_par_iter()
+            .map(|face_id| build_face_mesh(bsp_data, face_id, subdivide_size))
+            .collect();
+
         // create the diffuse and fullbright textures
-        for tex in self.bsp_data.textures().iter() {
-            self.textures.push(self.create_brush_texture(state, tex));
+        for (tex, frames) in bsp_data.textures().iter().zip(translated) {
+            let texture = self.create_brush_texture(state, tex, frames);
+            self.textures.push(texture);
         }
 
         // generate faces, vertices and lightmaps
-        // bsp_face_id is the id of the face in the bsp data
-        // face_id is the new id of the face in the renderer
-        for bsp_face_id in self.face_range.start..self.face_range.end {
+        // face_id is the id of the face in the renderer, not in the bsp data
+        for mesh in meshes {
             let face_id = self.faces.len();
-            let face = self.create_face(state, bsp_face_id);
+            let face = self.create_face(state, mesh);
             self.faces.push(face);
 
             let face_tex_id = self.faces[face_id].texture_id;
@@ -662,21 +1031,40 @@ impl BrushRendererBuilder {
                 .push(face_id);
 
             // generate face bind group
-            let per_face_bind_group = self.create_per_face_bind_group(state, face_id);
+            let per_face_bind_group =
+                create_per_face_bind_group(state, &self.lightmaps, &self.faces[face_id]);
             self.per_face_bind_groups.push(per_face_bind_group);
         }
 
+        // the faces bounding liquid leaves are those under the surface. the liquid surfaces
+        // themselves are warped rather than lit, so they're left alone.
+        if let Some(ref leaves) = self.leaves {
+            for leaf in leaves.iter().filter(|l| l.underwater) {
+                for facelist_id in leaf.facelist_ids.clone() {
+                    let face = &mut self.faces[bsp_data.facelist()[facelist_id]];
+                    face.caustics = match self.textures[face.texture_id].kind() {
+                        TextureKind::Normal | TextureKind::Masked => true,
+                        _ => false,
+                    };
+                }
+            }
+        }
+
         let vertex_buffer = state.device().create_buffer_with_data(
             unsafe { any_slice_as_bytes(self.vertices.as_slice()) },
             wgpu::BufferUsage::VERTEX,
         );
 
+        let (sky_bind_group, has_sky) = self.create_sky_bind_group(state);
+
         Ok(BrushRenderer {
             bsp_data: self.bsp_data,
             vertex_buffer,
             leaves: self.leaves,
             per_texture_bind_groups: self.per_texture_bind_groups.into_inner(),
             per_face_bind_groups: self.per_face_bind_groups,
+            sky_bind_group,
+            has_sky,
             texture_chains: self.texture_chains,
             faces: self.faces,
             textures: self.textures,
@@ -695,6 +1083,10 @@ pub struct BrushRenderer {
     per_texture_bind_groups: Vec<wgpu::BindGroup>,
     per_face_bind_groups: Vec<wgpu::BindGroup>,
 
+    // bound in place of the per-face bind groups when drawing liquids
+    sky_bind_group: wgpu::BindGroup,
+    has_sky: bool,
+
     // faces are grouped by texture to reduce the number of texture rebinds
     // texture_chains maps texture ids to face ids
     texture_chains: HashMap<usize, Vec<usize>>,
@@ -705,7 +1097,101 @@ pub struct BrushRenderer {
 }
 
 impl BrushRenderer {
+    // if this is a worldmodel, mark the faces in the camera's PVS and frustum to be drawn
+    fn mark_visible_faces(&self, bump: &Bump, camera: &Camera) {
+        if let Some(ref leaves) = self.leaves {
+            let mut pvs = BumpVec::new_in(bump);
+            self.bsp_data.get_pvs_into(
+                self.bsp_data.find_leaf(camera.origin),
+                leaves.len(),
+                &mut pvs,
+            );
+
+            // only draw faces in pvs
+            for leaf_id in pvs {
+                for facelist_id in leaves[leaf_id].facelist_ids.clone() {
+                    let face = &self.faces[self.bsp_data.facelist()[facelist_id]];
+
+                    if camera.frustum().intersects_bounds(face.min, face.max) {
+                        face.draw_flag.set(true);
+                    }
+                }
+            }
+        }
+    }
+
+    fn texture_bind_group_id(&self, tex_id: usize, time: Duration, frame_id: usize) -> usize {
+        match &self.textures[tex_id] {
+            BrushTexture::Static(ref frame) => frame.bind_group_id,
+            BrushTexture::Animated { primary, alternate } => {
+                // if frame is not zero and this texture has an alternate
+                // animation, use it
+                let anim = if frame_id == 0 {
+                    primary
+                } else if let Some(a) = alternate {
+                    a
+                } else {
+                    primary
+                };
+
+                let time_ms = time.num_milliseconds();
+                let total_ms = (bsp::frame_duration() * anim.len() as i32).num_milliseconds();
+                let anim_ms = if total_ms == 0 { 0 } else { time_ms % total_ms };
+                anim[(anim_ms / bsp::frame_duration().num_milliseconds()) as usize].bind_group_id
+            }
+        }
+    }
+
+    // `lightmapped` is Some with the chain's texture kind when drawing with the brush pipeline,
+    // which has per-face bind groups and caustics. the chain must start with caustics off.
+    fn record_chain_draw<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        bump: &'a Bump,
+        face_ids: &[usize],
+        lightmapped: Option<TextureKind>,
+    ) {
+        let mut caustics = false;
+
+        for face_id in face_ids.iter() {
+            let face = &self.faces[*face_id];
+
+            // only skip the face if we have visibility data but it's not marked
+            if self.leaves.is_some() && !face.draw_flag.replace(false) {
+                continue;
+            }
+
+            if let Some(kind) = lightmapped {
+                pass.set_bind_group(
+                    BindGroupLayoutId::PerFace as u32,
+                    &self.per_face_bind_groups[*face_id],
+                    &[],
+                );
+
+                if face.caustics != caustics {
+                    use PushConstantUpdate::*;
+                    caustics = face.caustics;
+                    BrushPipeline::set_push_constants(
+                        pass,
+                        Retain,
+                        Update(bump.alloc(SharedPushConstants {
+                            texture_kind: kind as u32,
+                            caustics: caustics as u32,
+                        })),
+                        Retain,
+                    );
+                }
+            }
+
+            // the instance index identifies the face for r_flatcolor
+            pass.draw(face.vertices.clone(), *face_id as u32..*face_id as u32 + 1);
+        }
+    }
+
     /// Record the draw commands for this brush model to the given `wgpu::RenderPass`.
+    ///
+    /// If `draw_liquids` is false, liquid surfaces are skipped so that they can be drawn
+    /// translucent by `record_water_draw()`.
     pub fn record_draw<'a>(
         &'a self,
         state: &'a GraphicsState,
@@ -714,81 +1200,187 @@ impl BrushRenderer {
         time: Duration,
         camera: &Camera,
         frame_id: usize,
+        draw_liquids: bool,
     ) {
         pass.set_pipeline(state.brush_pipeline().pipeline());
         pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
 
-        // if this is a worldmodel, mark faces to be drawn
-        if let Some(ref leaves) = self.leaves {
-            let pvs = self
-                .bsp_data
-                .get_pvs(self.bsp_data.find_leaf(camera.origin), leaves.len());
+        self.mark_visible_faces(bump, camera);
 
-            // only draw faces in pvs
-            for leaf_id in pvs {
-                for facelist_id in leaves[leaf_id].facelist_ids.clone() {
-                    let face = &self.faces[self.bsp_data.facelist()[facelist_id]];
+        for (tex_id, face_ids) in self.texture_chains.iter() {
+            use PushConstantUpdate::*;
 
-                    // TODO: frustum culling
-                    face.draw_flag.set(true);
-                }
+            let kind = self.textures[*tex_id].kind();
+            if !draw_liquids && kind == TextureKind::Warp {
+                continue;
             }
-        }
 
-        for (tex_id, face_ids) in self.texture_chains.iter() {
-            use PushConstantUpdate::*;
             BrushPipeline::set_push_constants(
                 pass,
                 Retain,
                 Update(bump.alloc(SharedPushConstants {
-                    texture_kind: self.textures[*tex_id].kind() as u32,
+                    texture_kind: kind as u32,
+                    caustics: 0,
                 })),
                 Retain,
             );
 
-            let bind_group_id = match &self.textures[*tex_id] {
-                BrushTexture::Static(ref frame) => frame.bind_group_id,
-                BrushTexture::Animated { primary, alternate } => {
-                    // if frame is not zero and this texture has an alternate
-                    // animation, use it
-                    let anim = if frame_id == 0 {
-                        primary
-                    } else if let Some(a) = alternate {
-                        a
-                    } else {
-                        primary
-                    };
+            pass.set_bind_group(
+                BindGroupLayoutId::PerTexture as u32,
+                &self.per_texture_bind_groups[self.texture_bind_group_id(*tex_id, time, frame_id)],
+                &[],
+            );
 
-                    let time_ms = time.num_milliseconds();
-                    let total_ms = (bsp::frame_duration() * anim.len() as i32).num_milliseconds();
-                    let anim_ms = if total_ms == 0 { 0 } else { time_ms % total_ms };
-                    anim[(anim_ms / bsp::frame_duration().num_milliseconds()) as usize]
-                        .bind_group_id
-                }
-            };
+            self.record_chain_draw(pass, bump, face_ids, Some(kind));
+        }
+
+        // clear the marks left on skipped liquid faces
+        for face in self.faces.iter() {
+            face.draw_flag.set(false);
+        }
+    }
+
+    /// Record the draw commands for the liquid surfaces of this brush model, blended over the
+    /// existing scene with the given opacity.
+    ///
+    /// `reflection` sets how strongly the surfaces reflect the sky, and has no effect if the model
+    /// has no sky texture. The water pipeline, its vertex push constants and the per-frame and
+    /// per-entity bind groups must already be set on the given pass.
+    pub fn record_water_draw<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        bump: &'a Bump,
+        time: Duration,
+        camera: &Camera,
+        alpha: f32,
+        reflection: f32,
+    ) {
+        use PushConstantUpdate::*;
+
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_bind_group(BindGroupLayoutId::PerFace as u32, &self.sky_bind_group, &[]);
+
+        self.mark_visible_faces(bump, camera);
+
+        WaterPipeline::set_push_constants(
+            pass,
+            Retain,
+            Update(bump.alloc(SharedPushConstants {
+                texture_kind: TextureKind::Warp as u32,
+                caustics: 0,
+            })),
+            Update(bump.alloc(WaterPushConstants {
+                alpha,
+                reflection: if self.has_sky { reflection } else { 0.0 },
+            })),
+        );
+
+        for (tex_id, face_ids) in self.texture_chains.iter() {
+            if self.textures[*tex_id].kind() != TextureKind::Warp {
+                continue;
+            }
 
             pass.set_bind_group(
                 BindGroupLayoutId::PerTexture as u32,
-                &self.per_texture_bind_groups[bind_group_id],
+                &self.per_texture_bind_groups[self.texture_bind_group_id(*tex_id, time, 0)],
                 &[],
             );
 
-            for face_id in face_ids.iter() {
-                let face = &self.faces[*face_id];
+            self.record_chain_draw(pass, bump, face_ids, None);
+        }
 
-                // only skip the face if we have visibility data but it's not marked
-                if self.leaves.is_some() && !face.draw_flag.replace(false) {
-                    continue;
+        for face in self.faces.iter() {
+            face.draw_flag.set(false);
+        }
+    }
+    /// Record the draw commands for the shadow-casting faces of this brush model.
+    ///
+    /// Only faces potentially visible from `origin` are drawn. The shadow pipeline and its push
+    /// constants must already be set on the given pass.
+    pub fn record_shadow_draw<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        bump: &Bump,
+        origin: Vector3<f32>,
+    ) {
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+
+        if let Some(ref leaves) = self.leaves {
+            let mut pvs = BumpVec::new_in(bump);
+            self.bsp_data
+                .get_pvs_into(self.bsp_data.find_leaf(origin), leaves.len(), &mut pvs);
+
+            for leaf_id in pvs {
+                for facelist_id in leaves[leaf_id].facelist_ids.clone() {
+                    self.faces[self.bsp_data.facelist()[facelist_id]]
+                        .draw_flag
+                        .set(true);
                 }
+            }
+        }
 
-                pass.set_bind_group(
-                    BindGroupLayoutId::PerFace as u32,
-                    &self.per_face_bind_groups[*face_id],
-                    &[],
-                );
+        for face in self.faces.iter() {
+            if self.leaves.is_some() && !face.draw_flag.replace(false) {
+                continue;
+            }
 
+            // sky and liquid surfaces don't cast shadows, and masked surfaces would cast them
+            // without their holes since the shadow pass has no texture
+            if let TextureKind::Normal = self.textures[face.texture_id].kind() {
                 pass.draw(face.vertices.clone(), 0..1);
             }
         }
     }
+
+    /// Replaces the lightmaps of this model's faces with new ones from `bake`.
+    ///
+    /// Returns the number of lightmaps baked, which is zero if the bake is for another BSP file.
+    pub fn bake_lights(&mut self, state: &GraphicsState, bake: &LightBake) -> usize {
+        if !bake.is_for(&self.bsp_data) {
+            return 0;
+        }
+
+        let mut encoder = state
+            .device()
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("light bake"),
+            });
+
+        let mut baked_face_ids = Vec::new();
+        for (face_id, face) in self.faces.iter().enumerate() {
+            if face.lightmap_ids.is_empty() {
+                continue;
+            }
+
+            let bsp_face = self.bsp_data.face(face.bsp_face_id);
+            let basis = match TexelBasis::new(
+                bsp_face,
+                self.bsp_data.face_texinfo(face.bsp_face_id),
+                &self.bsp_data.planes()[bsp_face.plane_id],
+            ) {
+                Some(b) => b,
+                None => continue,
+            };
+
+            // each lightmap holds the light of one of the face's styles
+            for (lightmap_id, style) in face.lightmap_ids.iter().zip(face.light_styles.iter()) {
+                self.lightmaps[*lightmap_id] =
+                    bake.bake_lightmap(state, &mut encoder, &basis, *style);
+            }
+
+            baked_face_ids.push(face_id);
+        }
+
+        state.queue().submit(vec![encoder.finish()]);
+
+        for face_id in baked_face_ids.iter() {
+            self.per_face_bind_groups[*face_id] =
+                create_per_face_bind_group(state, &self.lightmaps, &self.faces[*face_id]);
+        }
+
+        baked_face_ids
+            .iter()
+            .map(|id| self.faces[*id].lightmap_ids.len())
+            .sum()
+    }
 }
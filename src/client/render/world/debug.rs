@@ -0,0 +1,240 @@
+use std::mem::size_of;
+
+use crate::{
+    client::render::{
+        pipeline::{Pipeline, PushConstantUpdate},
+        ui::quad::QuadPipeline,
+        world::Camera,
+        GraphicsState,
+    },
+    common::{frustum, util::any_slice_as_bytes},
+};
+
+use bumpalo::Bump;
+use cgmath::{Matrix4, Vector3};
+
+/// Color of clipping hull wireframes.
+pub const HULL_COLOR: [f32; 4] = [0.0, 1.0, 1.0, 1.0];
+
+/// Color of entity bounding boxes.
+pub const BBOX_COLOR: [f32; 4] = [1.0, 1.0, 0.0, 1.0];
+
+/// Color of the unobstructed part of a trace.
+pub const TRACE_COLOR: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+
+/// Color of the part of a trace past the point where it hit something.
+pub const TRACE_BLOCKED_COLOR: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+/// Color of the surface highlighted by `surface_info highlight`.
+pub const SURFACE_COLOR: [f32; 4] = [1.0, 0.0, 1.0, 1.0];
+
+/// Color of the route drawn by `route_play`, translucent so it doesn't hide the level.
+pub const ROUTE_COLOR: [f32; 4] = [1.0, 0.5, 0.0, 0.5];
+
+/// Color of the marker racing the player along a route.
+pub const ROUTE_MARKER_COLOR: [f32; 4] = [1.0, 0.5, 0.0, 0.75];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+/// Add a line segment between two points in Quake coordinates.
+pub fn push_line(
+    vertices: &mut Vec<DebugVertex>,
+    start: Vector3<f32>,
+    end: Vector3<f32>,
+    color: [f32; 4],
+) {
+    for p in [start, end].iter() {
+        vertices.push(DebugVertex {
+            position: frustum::quake_to_wgpu(*p).into(),
+            color,
+        });
+    }
+}
+
+/// Add the outline of a polygon.
+pub fn push_polygon(vertices: &mut Vec<DebugVertex>, polygon: &[Vector3<f32>], color: [f32; 4]) {
+    for i in 0..polygon.len() {
+        push_line(
+            vertices,
+            polygon[i],
+            polygon[(i + 1) % polygon.len()],
+            color,
+        );
+    }
+}
+
+/// Add the edges of an axis-aligned box.
+pub fn push_box(
+    vertices: &mut Vec<DebugVertex>,
+    mins: Vector3<f32>,
+    maxs: Vector3<f32>,
+    color: [f32; 4],
+) {
+    let corner = |i: usize| {
+        Vector3::new(
+            if i & 1 == 0 { mins.x } else { maxs.x },
+            if i & 2 == 0 { mins.y } else { maxs.y },
+            if i & 4 == 0 { mins.z } else { maxs.z },
+        )
+    };
+
+    // connect each pair of corners that differ along exactly one axis
+    for i in 0..8 {
+        for axis in [1, 2, 4].iter() {
+            if i & axis == 0 {
+                push_line(vertices, corner(i), corner(i | axis), color);
+            }
+        }
+    }
+}
+
+/// Draws colored wireframes over the scene for collision debugging.
+pub struct DebugLinePipeline {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+}
+
+impl DebugLinePipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        sample_count: u32,
+    ) -> DebugLinePipeline {
+        let (pipeline, bind_group_layouts) =
+            DebugLinePipeline::create(device, compiler, &[], sample_count);
+
+        DebugLinePipeline {
+            pipeline,
+            bind_group_layouts,
+        }
+    }
+
+    pub fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        sample_count: u32,
+    ) {
+        let layout_refs: Vec<_> = self.bind_group_layouts.iter().collect();
+        let pipeline = DebugLinePipeline::recreate(device, compiler, &layout_refs, sample_count);
+        self.pipeline = pipeline;
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn bind_group_layouts(&self) -> &[wgpu::BindGroupLayout] {
+        &self.bind_group_layouts
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct VertexPushConstants {
+    pub transform: Matrix4<f32>,
+}
+
+impl Pipeline for DebugLinePipeline {
+    type VertexPushConstants = VertexPushConstants;
+    type SharedPushConstants = ();
+    type FragmentPushConstants = ();
+
+    fn name() -> &'static str {
+        "debug"
+    }
+
+    fn vertex_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/debug.vert"))
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/debug.frag"))
+    }
+
+    fn bind_group_layout_descriptors() -> Vec<wgpu::BindGroupLayoutDescriptor<'static>> {
+        Vec::new()
+    }
+
+    fn rasterization_state_descriptor() -> Option<wgpu::RasterizationStateDescriptor> {
+        QuadPipeline::rasterization_state_descriptor()
+    }
+
+    fn primitive_topology() -> wgpu::PrimitiveTopology {
+        wgpu::PrimitiveTopology::LineList
+    }
+
+    // drawn in the final pass, so this matches the final pass target
+    fn color_state_descriptors() -> Vec<wgpu::ColorStateDescriptor> {
+        QuadPipeline::color_state_descriptors()
+    }
+
+    // lines are drawn over everything so that geometry inside walls is visible
+    fn depth_stencil_state_descriptor() -> Option<wgpu::DepthStencilStateDescriptor> {
+        None
+    }
+
+    // NOTE: if the vertex format is changed, this descriptor must also be changed accordingly.
+    fn vertex_buffer_descriptors() -> Vec<wgpu::VertexBufferDescriptor<'static>> {
+        vec![wgpu::VertexBufferDescriptor {
+            stride: size_of::<DebugVertex>() as u64,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &wgpu::vertex_attr_array![
+                // position
+                0 => Float3,
+                // color
+                1 => Float4,
+            ],
+        }]
+    }
+}
+
+/// A set of line segments uploaded for drawing this frame.
+pub struct DebugLines {
+    vertex_buffer: wgpu::Buffer,
+    vertex_count: u32,
+}
+
+impl DebugLines {
+    pub fn new(state: &GraphicsState, vertices: &[DebugVertex]) -> DebugLines {
+        let vertex_buffer = state.device().create_buffer_with_data(
+            unsafe { any_slice_as_bytes(vertices) },
+            wgpu::BufferUsage::VERTEX,
+        );
+
+        DebugLines {
+            vertex_buffer,
+            vertex_count: vertices.len() as u32,
+        }
+    }
+
+    pub fn record_draw<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut wgpu::RenderPass<'a>,
+        bump: &'a Bump,
+        camera: &Camera,
+    ) {
+        use PushConstantUpdate::*;
+
+        if self.vertex_count == 0 {
+            return;
+        }
+
+        pass.set_pipeline(state.debug_line_pipeline().pipeline());
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        DebugLinePipeline::set_push_constants(
+            pass,
+            Update(bump.alloc(VertexPushConstants {
+                transform: camera.view_projection(),
+            })),
+            Retain,
+            Retain,
+        );
+        pass.draw(0..self.vertex_count, 0..1);
+    }
+}
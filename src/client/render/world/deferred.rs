@@ -5,7 +5,12 @@ use cgmath::{Matrix4, SquareMatrix as _, Vector3, Zero as _};
 use crate::{
     client::{
         entity::MAX_LIGHTS,
-        render::{pipeline::Pipeline, ui::quad::QuadPipeline, GraphicsState},
+        render::{
+            pipeline::Pipeline,
+            ui::quad::QuadPipeline,
+            world::shadow::{MAX_SHADOW_LIGHTS, SHADOW_FACE_COUNT},
+            GraphicsState, HDR_ATTACHMENT_FORMAT,
+        },
     },
     common::util::any_as_bytes,
 };
@@ -75,6 +80,24 @@ lazy_static! {
                     ),
                 }
             ),
+
+            // shadow map array
+            wgpu::BindGroupLayoutEntry::new(
+                6,
+                wgpu::ShaderStage::FRAGMENT,
+                wgpu::BindingType::SampledTexture {
+                    dimension: wgpu::TextureViewDimension::D2Array,
+                    component_type: wgpu::TextureComponentType::Float,
+                    multisampled: false,
+                },
+            ),
+
+            // shadow comparison sampler
+            wgpu::BindGroupLayoutEntry::new(
+                7,
+                wgpu::ShaderStage::FRAGMENT,
+                wgpu::BindingType::Sampler { comparison: true },
+            ),
         ]
     ];
 }
@@ -91,8 +114,20 @@ pub struct PointLight {
 pub struct DeferredUniforms {
     pub inv_projection: [[f32; 4]; 4],
     pub light_count: u32,
-    pub _pad: [u32; 3],
+
+    /// The number of lights, starting from the first, which cast shadows.
+    pub shadow_count: u32,
+
+    /// How much of the fog color covers the sky, from 0 to 1.
+    pub sky_fog: f32,
+    pub _pad: u32,
+
+    /// The fog color in `xyz` and its density in `w`.
+    pub fog: [f32; 4],
     pub lights: [PointLight; MAX_LIGHTS],
+
+    /// Transforms from view space to the clip space of each shadow cube face.
+    pub shadow_transforms: [[[f32; 4]; 4]; MAX_SHADOW_LIGHTS * SHADOW_FACE_COUNT],
 }
 
 pub struct DeferredPipeline {
@@ -114,11 +149,16 @@ impl DeferredPipeline {
                 any_as_bytes(&DeferredUniforms {
                     inv_projection: Matrix4::identity().into(),
                     light_count: 0,
-                    _pad: [0; 3],
+                    shadow_count: 0,
+                    sky_fog: 0.0,
+                    _pad: 0,
+                    fog: [0.0; 4],
                     lights: [PointLight {
                         origin: Vector3::zero(),
                         radius: 0.0,
                     }; MAX_LIGHTS],
+                    shadow_transforms: [Matrix4::identity().into();
+                        MAX_SHADOW_LIGHTS * SHADOW_FACE_COUNT],
                 })
             },
             wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
@@ -194,7 +234,12 @@ impl Pipeline for DeferredPipeline {
     }
 
     fn color_state_descriptors() -> Vec<wgpu::ColorStateDescriptor> {
-        QuadPipeline::color_state_descriptors()
+        vec![wgpu::ColorStateDescriptor {
+            format: HDR_ATTACHMENT_FORMAT,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        }]
     }
 
     fn depth_stencil_state_descriptor() -> Option<wgpu::DepthStencilStateDescriptor> {
@@ -217,6 +262,7 @@ impl DeferredRenderer {
         normal_buffer: &wgpu::TextureView,
         light_buffer: &wgpu::TextureView,
         depth_buffer: &wgpu::TextureView,
+        shadow_map: &wgpu::TextureView,
     ) -> DeferredRenderer {
         let bind_group = state
             .device()
@@ -256,6 +302,16 @@ impl DeferredRenderer {
                             state.deferred_pipeline().uniform_buffer().slice(..),
                         ),
                     },
+                    // shadow map array
+                    wgpu::BindGroupEntry {
+                        binding: 6,
+                        resource: wgpu::BindingResource::TextureView(shadow_map),
+                    },
+                    // shadow comparison sampler
+                    wgpu::BindGroupEntry {
+                        binding: 7,
+                        resource: wgpu::BindingResource::Sampler(state.shadow_sampler()),
+                    },
                 ],
             });
 
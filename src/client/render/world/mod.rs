@@ -1,11 +1,16 @@
 pub mod alias;
+pub mod bake;
+pub mod blob;
+pub mod bloom;
 pub mod brush;
+pub mod debug;
 pub mod deferred;
 pub mod particle;
 pub mod postprocess;
+pub mod shadow;
 pub mod sprite;
 
-use std::{cell::RefCell, mem::size_of};
+use std::{cell::RefCell, mem::size_of, path::Path};
 
 use crate::{
     client::{
@@ -13,9 +18,12 @@ use crate::{
         render::{
             pipeline::{Pipeline, PushConstantUpdate},
             uniform::{DynamicUniformBufferBlock, UniformArrayFloat, UniformBool},
+            warp,
             world::{
                 alias::{AliasPipeline, AliasRenderer},
-                brush::{BrushPipeline, BrushRenderer, BrushRendererBuilder},
+                bake::LightBake,
+                brush::{BrushPipeline, BrushRenderer, BrushRendererBuilder, WaterPipeline},
+                particle::ParticleStyle,
                 sprite::{SpritePipeline, SpriteRenderer},
             },
             GraphicsState, DEPTH_ATTACHMENT_FORMAT, DIFFUSE_ATTACHMENT_FORMAT,
@@ -26,6 +34,7 @@ use crate::{
     common::{
         console::CvarRegistry,
         engine,
+        frustum::{self, Frustum},
         math::Angles,
         model::{Model, ModelKind},
         sprite::SpriteKind,
@@ -34,9 +43,12 @@ use crate::{
 };
 
 use bumpalo::Bump;
-use cgmath::{Euler, InnerSpace, Matrix4, SquareMatrix as _, Vector3, Vector4};
+use cgmath::{Euler, Matrix4, SquareMatrix as _, Vector3, Vector4};
 use chrono::Duration;
 
+// light for models without a world light sample, matching their brightness before r_lightmodels
+const UNLIT_MODEL_LIGHT: f32 = 4.0;
+
 lazy_static! {
     static ref BIND_GROUP_LAYOUT_DESCRIPTOR_BINDINGS: [Vec<wgpu::BindGroupLayoutEntry>; 2] = [
         vec![
@@ -175,6 +187,14 @@ impl Pipeline for WorldPipelineBase {
     }
 }
 
+fn water_alpha(cvars: &CvarRegistry) -> f32 {
+    cvars
+        .get_value("r_wateralpha")
+        .unwrap_or(1.0)
+        .max(0.0)
+        .min(1.0)
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum BindGroupLayoutId {
     PerFrame = 0,
@@ -188,42 +208,20 @@ pub struct Camera {
     angles: Angles,
     view: Matrix4<f32>,
     view_projection: Matrix4<f32>,
-    clipping_planes: [Vector4<f32>; 6],
+    frustum: Frustum,
 }
 
 impl Camera {
     pub fn new(origin: Vector3<f32>, angles: Angles, projection: Matrix4<f32>) -> Camera {
-        // convert coordinates
-        let converted_origin = Vector3::new(-origin.y, origin.z, -origin.x);
-
-        // translate the world by inverse of camera position
-        let translation = Matrix4::from_translation(-converted_origin);
-        let rotation = angles.mat4_wgpu();
-        let view = rotation * translation;
+        let view = frustum::view(origin, angles);
         let view_projection = projection * view;
 
-        // see https://www.gamedevs.org/uploads/fast-extraction-viewing-frustum-planes-from-world-view-projection-matrix.pdf
-        let clipping_planes = [
-            // left
-            view_projection.w + view_projection.x,
-            // right
-            view_projection.w - view_projection.x,
-            // bottom
-            view_projection.w + view_projection.y,
-            // top
-            view_projection.w - view_projection.y,
-            // near
-            view_projection.w + view_projection.z,
-            // far
-            view_projection.w - view_projection.z,
-        ];
-
         Camera {
             origin,
             angles,
             view,
             view_projection,
-            clipping_planes,
+            frustum: Frustum::from_matrix(view_projection * frustum::QUAKE_TO_WGPU),
         }
     }
 
@@ -243,16 +241,9 @@ impl Camera {
         self.view_projection
     }
 
-    // TODO: this seems to be too lenient
-    /// Determines whether a point falls outside the viewing frustum.
-    pub fn cull_point(&self, p: Vector3<f32>) -> bool {
-        for plane in self.clipping_planes.iter() {
-            if (self.view_projection() * p.extend(1.0)).dot(*plane) < 0.0 {
-                return true;
-            }
-        }
-
-        false
+    /// Returns the viewing frustum in Quake coordinates.
+    pub fn frustum(&self) -> &Frustum {
+        &self.frustum
     }
 }
 
@@ -267,6 +258,10 @@ pub struct FrameUniforms {
 
     // TODO: pack flags into a bit string
     r_lightmap: UniformBool,
+    r_coloredlight: UniformBool,
+    r_fullbright: UniformBool,
+    r_flatcolor: UniformBool,
+    r_caustics: f32,
 }
 
 #[repr(C, align(256))]
@@ -292,6 +287,7 @@ pub struct WorldRenderer {
     entity_renderers: Vec<EntityRenderer>,
 
     world_uniform_block: DynamicUniformBufferBlock<EntityUniforms>,
+    viewmodel_uniform_block: DynamicUniformBufferBlock<EntityUniforms>,
     entity_uniform_blocks: RefCell<Vec<DynamicUniformBufferBlock<EntityUniforms>>>,
 }
 
@@ -309,16 +305,37 @@ impl WorldRenderer {
             transform: Matrix4::identity(),
             model: Matrix4::identity(),
         });
+        let viewmodel_uniform_block = state.entity_uniform_buffer_mut().allocate(EntityUniforms {
+            transform: Matrix4::identity(),
+            model: Matrix4::identity(),
+        });
+
+        // replacement textures are found by map name, e.g. "e1m1" for "maps/e1m1.bsp"
+        let replacement_map = match cvars.get_value("r_externaltextures").unwrap() {
+            v if v != 0.0 => Path::new(models[worldmodel_id].name())
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(|stem| stem.to_owned()),
+            _ => None,
+        };
+        let subdivide_size = cvars
+            .get_value("gl_subdivide_size")
+            .unwrap_or(warp::DEFAULT_SUBDIVIDE_SIZE);
+        let brush_builder = |bmodel, worldmodel| {
+            let builder =
+                BrushRendererBuilder::new(bmodel, worldmodel).subdivide_size(subdivide_size);
+            match replacement_map {
+                Some(ref map) => builder.replacement_textures(map),
+                None => builder,
+            }
+        };
 
         for (i, model) in models.iter().enumerate() {
             if i == worldmodel_id {
                 match *model.kind() {
                     ModelKind::Brush(ref bmodel) => {
-                        worldmodel_renderer = Some(
-                            BrushRendererBuilder::new(bmodel, true)
-                                .build(state)
-                                .unwrap(),
-                        );
+                        worldmodel_renderer =
+                            Some(brush_builder(bmodel, true).build(state).unwrap());
                     }
                     _ => panic!("Invalid worldmodel"),
                 }
@@ -330,9 +347,7 @@ impl WorldRenderer {
 
                     ModelKind::Brush(ref bmodel) => {
                         entity_renderers.push(EntityRenderer::Brush(
-                            BrushRendererBuilder::new(bmodel, false)
-                                .build(state)
-                                .unwrap(),
+                            brush_builder(bmodel, false).build(state).unwrap(),
                         ));
                     }
 
@@ -353,6 +368,7 @@ impl WorldRenderer {
             worldmodel_renderer: worldmodel_renderer.unwrap(),
             entity_renderers,
             world_uniform_block,
+            viewmodel_uniform_block,
             entity_uniform_blocks: RefCell::new(Vec::new()),
         }
     }
@@ -383,6 +399,12 @@ impl WorldRenderer {
                     camera_pos: camera.origin.extend(1.0),
                     time: engine::duration_to_f32(time),
                     r_lightmap: UniformBool::new(cvars.get_value("r_lightmap").unwrap() != 0.0),
+                    r_coloredlight: UniformBool::new(
+                        cvars.get_value("r_coloredlight").unwrap_or(1.0) != 0.0,
+                    ),
+                    r_fullbright: UniformBool::new(cvars.get_value("r_fullbright").unwrap() != 0.0),
+                    r_flatcolor: UniformBool::new(cvars.get_value("r_flatcolor").unwrap() != 0.0),
+                    r_caustics: cvars.get_value("r_caustics").unwrap_or(0.0).max(0.0),
                 })
             });
 
@@ -464,15 +486,30 @@ impl WorldRenderer {
             &state.world_bind_groups()[BindGroupLayoutId::PerEntity as usize],
             &[self.world_uniform_block.offset()],
         );
-        self.worldmodel_renderer.record_draw(state, pass, &bump, time, camera, 0);
+        // translucent liquids are drawn after the lighting pass by render_water_pass()
+        let draw_liquids = water_alpha(cvars) >= 1.0;
+        self.worldmodel_renderer
+            .record_draw(state, pass, &bump, time, camera, 0, draw_liquids);
 
         // draw entities
         info!("Drawing entities");
+        let lerp_models = cvars.get_value("r_lerpmodels").unwrap_or(1.0) != 0.0;
         for (ent_pos, ent) in entities.enumerate() {
+            let offset = {
+                let block = &self.entity_uniform_blocks.borrow()[ent_pos];
+
+                // skip entities that overflowed the uniform buffer until it grows
+                if !state.entity_uniform_buffer().contains(block) {
+                    continue;
+                }
+
+                block.offset()
+            };
+
             pass.set_bind_group(
                 BindGroupLayoutId::PerEntity as u32,
                 &state.world_bind_groups()[BindGroupLayoutId::PerEntity as usize],
-                &[self.entity_uniform_blocks.borrow()[ent_pos].offset()],
+                &[offset],
             );
 
             match self.renderer_for_entity(&ent) {
@@ -487,12 +524,32 @@ impl WorldRenderer {
                         Retain,
                         Retain,
                     );
-                    bmodel.record_draw(state, pass, &bump, time, camera, ent.frame_id);
+                    bmodel.record_draw(state, pass, &bump, time, camera, ent.frame_id, true);
                 }
                 EntityRenderer::Alias(ref alias) => {
+                    let (prev_frame_id, blend) = if lerp_models {
+                        ent.frame_blend(time)
+                    } else {
+                        (ent.get_frame_id(), 1.0)
+                    };
+
                     pass.set_pipeline(state.alias_pipeline().pipeline());
-                    AliasPipeline::set_push_constants(pass, Clear, Clear, Clear);
-                    alias.record_draw(state, pass, time, ent.get_frame_id(), ent.get_skin_id())
+                    AliasPipeline::set_push_constants(
+                        pass,
+                        Update(bump.alloc(alias::VertexPushConstants { blend })),
+                        Clear,
+                        Update(bump.alloc(alias::FragmentPushConstants {
+                            light: ent.light().unwrap_or([UNLIT_MODEL_LIGHT; 3]),
+                        })),
+                    );
+                    alias.record_draw(
+                        state,
+                        pass,
+                        time,
+                        ent.get_frame_id(),
+                        prev_frame_id,
+                        ent.get_skin_id(),
+                    )
                 }
                 EntityRenderer::Sprite(ref sprite) => {
                     pass.set_pipeline(state.sprite_pipeline().pipeline());
@@ -504,9 +561,174 @@ impl WorldRenderer {
             }
         }
 
-        state
-            .particle_pipeline()
-            .record_draw(pass, &bump, camera, particles);
+        let particle_style = cvars
+            .get_value("r_particlestyle")
+            .ok()
+            .and_then(ParticleStyle::from_value)
+            .unwrap_or_default();
+        state.particle_pipeline().record_draw(
+            state.queue(),
+            pass,
+            &bump,
+            camera,
+            particles,
+            particle_style,
+            state.initial_pass_target().size(),
+        );
+    }
+
+    /// Draws the view weapon over the output of `render_pass()`.
+    ///
+    /// `camera` should share the world camera's origin, angles and clip planes so that the
+    /// weapon is depth tested against the world, but may have its own field of view. It must be
+    /// called after `render_pass()` in the same frame.
+    pub fn render_viewmodel_pass<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut wgpu::RenderPass<'a>,
+        bump: &'a Bump,
+        camera: &Camera,
+        time: Duration,
+        viewmodel: &ClientEntity,
+        cvars: &CvarRegistry,
+    ) {
+        use PushConstantUpdate::*;
+
+        let alias = match self.renderer_for_entity(viewmodel) {
+            EntityRenderer::Alias(ref alias) => alias,
+            _ => return,
+        };
+
+        if !state
+            .entity_uniform_buffer()
+            .contains(&self.viewmodel_uniform_block)
+        {
+            return;
+        }
+
+        state.entity_uniform_buffer_mut().write_block(
+            &self.viewmodel_uniform_block,
+            EntityUniforms {
+                transform: self.calculate_mvp_transform(camera, viewmodel),
+                model: self.calculate_model_transform(camera, viewmodel),
+            },
+        );
+        state.entity_uniform_buffer().flush(state.queue());
+
+        pass.set_bind_group(
+            BindGroupLayoutId::PerFrame as u32,
+            &state.world_bind_groups()[BindGroupLayoutId::PerFrame as usize],
+            &[],
+        );
+        pass.set_bind_group(
+            BindGroupLayoutId::PerEntity as u32,
+            &state.world_bind_groups()[BindGroupLayoutId::PerEntity as usize],
+            &[self.viewmodel_uniform_block.offset()],
+        );
+
+        let (prev_frame_id, blend) = if cvars.get_value("r_lerpmodels").unwrap_or(1.0) != 0.0 {
+            viewmodel.frame_blend(time)
+        } else {
+            (viewmodel.get_frame_id(), 1.0)
+        };
+
+        pass.set_pipeline(state.alias_pipeline().pipeline());
+        AliasPipeline::set_push_constants(
+            pass,
+            Update(bump.alloc(alias::VertexPushConstants { blend })),
+            Clear,
+            Update(bump.alloc(alias::FragmentPushConstants {
+                light: viewmodel.light().unwrap_or([UNLIT_MODEL_LIGHT; 3]),
+            })),
+        );
+        alias.record_draw(
+            state,
+            pass,
+            time,
+            viewmodel.get_frame_id(),
+            prev_frame_id,
+            viewmodel.get_skin_id(),
+        );
+    }
+
+    /// Returns `true` if liquids are translucent and so need `render_water_pass()`.
+    pub fn has_translucent_water(&self, cvars: &CvarRegistry) -> bool {
+        water_alpha(cvars) < 1.0
+    }
+
+    /// Draws the world's liquid surfaces over the lit scene according to `r_wateralpha`.
+    ///
+    /// This does nothing if `r_wateralpha` is 1, since liquids are then drawn opaque by
+    /// `render_pass()`, so the pass need not be begun at all; see `has_translucent_water()`. It must be called after `render_pass()` in the same frame so that the
+    /// uniform buffers are up to date.
+    pub fn render_water_pass<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut wgpu::RenderPass<'a>,
+        bump: &'a Bump,
+        camera: &Camera,
+        time: Duration,
+        cvars: &CvarRegistry,
+    ) {
+        use PushConstantUpdate::*;
+
+        let alpha = water_alpha(cvars);
+        if alpha >= 1.0 {
+            return;
+        }
+
+        pass.set_pipeline(state.water_pipeline().pipeline());
+        pass.set_bind_group(
+            BindGroupLayoutId::PerFrame as u32,
+            &state.world_bind_groups()[BindGroupLayoutId::PerFrame as usize],
+            &[],
+        );
+        pass.set_bind_group(
+            BindGroupLayoutId::PerEntity as u32,
+            &state.world_bind_groups()[BindGroupLayoutId::PerEntity as usize],
+            &[self.world_uniform_block.offset()],
+        );
+        WaterPipeline::set_push_constants(
+            pass,
+            Update(bump.alloc(brush::VertexPushConstants {
+                transform: camera.view_projection(),
+                model_view: camera.view(),
+            })),
+            Clear,
+            Clear,
+        );
+        let reflection = cvars
+            .get_value("r_waterreflect")
+            .unwrap_or(0.0)
+            .max(0.0)
+            .min(1.0);
+        self.worldmodel_renderer
+            .record_water_draw(pass, bump, time, camera, alpha, reflection);
+    }
+
+    /// Replaces the lightmaps of the world and its brush entities with new ones from `bake`.
+    ///
+    /// Returns the number of lightmaps baked.
+    pub fn bake_lights(&mut self, state: &GraphicsState, bake: &LightBake) -> usize {
+        let mut count = self.worldmodel_renderer.bake_lights(state, bake);
+        for renderer in self.entity_renderers.iter_mut() {
+            if let EntityRenderer::Brush(ref mut brush) = renderer {
+                count += brush.bake_lights(state, bake);
+            }
+        }
+
+        count
+    }
+
+    /// Record the draw commands for the shadow-casting world geometry visible from `origin`.
+    pub fn record_shadow_draw<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        bump: &Bump,
+        origin: Vector3<f32>,
+    ) {
+        self.worldmodel_renderer
+            .record_shadow_draw(pass, bump, origin);
     }
 
     fn renderer_for_entity(&self, ent: &ClientEntity) -> &EntityRenderer {
@@ -550,6 +772,6 @@ impl WorldRenderer {
             _ => Matrix4::from(Euler::new(angles.x, angles.y, angles.z)),
         };
 
-        Matrix4::from_translation(Vector3::new(-origin.y, origin.z, -origin.x)) * rotation
+        Matrix4::from_translation(frustum::quake_to_wgpu(origin)) * rotation
     }
 }
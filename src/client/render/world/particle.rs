@@ -2,19 +2,19 @@ use std::mem::size_of;
 
 use crate::{
     client::{
-        entity::particle::Particle,
+        entity::particle::{Particle, MAX_PARTICLES},
         render::{
             create_texture,
             pipeline::{Pipeline, PushConstantUpdate},
             world::{Camera, WorldPipelineBase},
-            Palette, TextureData,
+            Extent2d, Palette, TextureData,
         },
     },
-    common::{math::Angles, util::any_slice_as_bytes},
+    common::{frustum, math::Angles, util::any_slice_as_bytes},
 };
 
 use bumpalo::Bump;
-use cgmath::Matrix4;
+use cgmath::{Matrix4, Vector2};
 
 lazy_static! {
     static ref BIND_GROUP_LAYOUT_DESCRIPTOR_BINDINGS: [Vec<wgpu::BindGroupLayoutEntry>; 1] = [
@@ -48,8 +48,10 @@ lazy_static! {
             1 => Float2,
         ].to_vec(),
         wgpu::vertex_attr_array![
+            // instance position
+            2 => Float3,
             // instance color (index)
-            2 => Uint,
+            3 => Uint,
         ].to_vec(),
     ];
 }
@@ -66,10 +68,52 @@ const PARTICLE_TEXTURE_PIXELS: [u8; 64] = [
     0, 0, 1, 1, 1, 1, 0, 0,
 ];
 
+/// How particles are drawn, as selected by `r_particlestyle`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParticleStyle {
+    /// Round sprites with a fixed size in world units.
+    Round = 0,
+
+    /// Solid squares sized in pixels according to their distance, like the software renderer.
+    Square = 1,
+}
+
+impl ParticleStyle {
+    /// Returns the style selected by a value of `r_particlestyle`.
+    pub fn from_value(value: f32) -> Option<ParticleStyle> {
+        match value as i32 {
+            0 => Some(ParticleStyle::Round),
+            1 => Some(ParticleStyle::Square),
+            _ => None,
+        }
+    }
+}
+
+impl std::default::Default for ParticleStyle {
+    fn default() -> Self {
+        ParticleStyle::Round
+    }
+}
+
+/// Returns the side length in pixels of a square particle `depth` units in front of the camera.
+///
+/// This follows the software renderer, which sizes particles inversely to their depth and clamps
+/// the result to a range that grows with the view width, with 320 pixels as the reference.
+///
+/// Square particles are sized in the vertex shader, which must be kept in sync with this.
+pub fn square_particle_size(depth: f32, view_width: u32) -> f32 {
+    let scale = view_width as f32 / 320.0;
+    let min = scale.floor().max(1.0);
+    let max = (scale * 4.0 + 0.5).floor();
+
+    (256.0 * scale / depth).floor().max(min).min(max)
+}
+
 pub struct ParticlePipeline {
     pipeline: wgpu::RenderPipeline,
     bind_group_layouts: Vec<wgpu::BindGroupLayout>,
     vertex_buffer: wgpu::Buffer,
+    instance_buffer: wgpu::Buffer,
     sampler: wgpu::Sampler,
     textures: Vec<wgpu::Texture>,
     texture_views: Vec<wgpu::TextureView>,
@@ -92,6 +136,13 @@ impl ParticlePipeline {
             wgpu::BufferUsage::VERTEX,
         );
 
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("particle instance buffer"),
+            size: (MAX_PARTICLES * size_of::<ParticleInstance>()) as u64,
+            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             label: Some("particle sampler"),
             address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -157,6 +208,7 @@ impl ParticlePipeline {
             texture_views,
             bind_group,
             vertex_buffer,
+            instance_buffer,
         }
     }
 
@@ -182,19 +234,48 @@ impl ParticlePipeline {
         &self.vertex_buffer
     }
 
+    pub fn instance_buffer(&self) -> &wgpu::Buffer {
+        &self.instance_buffer
+    }
+
+    /// Draws particles in the given style.
+    ///
+    /// All particles are uploaded to the instance buffer and drawn with a single instanced draw
+    /// call. `viewport` is the size of the render target, which determines the size of square
+    /// particles.
     pub fn record_draw<'a, 'b, P>(
         &'a self,
+        queue: &wgpu::Queue,
         pass: &mut wgpu::RenderPass<'a>,
         bump: &'a Bump,
         camera: &Camera,
         particles: P,
+        style: ParticleStyle,
+        viewport: Extent2d,
     ) where
         P: Iterator<Item = &'b Particle>,
     {
         use PushConstantUpdate::*;
 
+        let instances: Vec<ParticleInstance> = particles
+            .take(MAX_PARTICLES)
+            .map(|particle| ParticleInstance {
+                position: frustum::quake_to_wgpu(particle.origin()).into(),
+                color: particle.color() as u32,
+            })
+            .collect();
+
+        if instances.is_empty() {
+            return;
+        }
+
+        queue.write_buffer(&self.instance_buffer, 0, unsafe {
+            any_slice_as_bytes(&instances)
+        });
+
         pass.set_pipeline(self.pipeline());
         pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
         pass.set_bind_group(0, &self.bind_group, &[]);
 
         // face toward camera
@@ -206,40 +287,44 @@ impl ParticlePipeline {
         }
         .mat4_wgpu();
 
-        for particle in particles {
-            let q_origin = particle.origin();
-            let translation =
-                Matrix4::from_translation([-q_origin.y, q_origin.z, -q_origin.x].into());
-            Self::set_push_constants(
-                pass,
-                Update(bump.alloc(VertexPushConstants {
-                    transform: camera.view_projection() * translation * rotation,
-                })),
-                Retain,
-                Update(bump.alloc(FragmentPushConstants {
-                    color: particle.color() as u32,
-                })),
-            );
-
-            pass.draw(0..6, 0..1);
-        }
+        Self::set_push_constants(
+            pass,
+            Update(bump.alloc(VertexPushConstants {
+                view_projection: camera.view_projection(),
+                billboard: camera.view_projection() * rotation,
+                viewport_size: Vector2::new(viewport.width as f32, viewport.height as f32),
+            })),
+            Update(bump.alloc(SharedPushConstants {
+                style: style as u32,
+            })),
+            Retain,
+        );
+
+        pass.draw(0..6, 0..instances.len() as u32);
     }
 }
 
+#[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct VertexPushConstants {
-    pub transform: Matrix4<f32>,
+    pub view_projection: Matrix4<f32>,
+
+    /// Transforms quad vertices to clip space offsets facing the camera.
+    pub billboard: Matrix4<f32>,
+
+    pub viewport_size: Vector2<f32>,
 }
 
+#[repr(C)]
 #[derive(Copy, Clone, Debug)]
-pub struct FragmentPushConstants {
-    pub color: u32,
+pub struct SharedPushConstants {
+    pub style: u32,
 }
 
 impl Pipeline for ParticlePipeline {
     type VertexPushConstants = VertexPushConstants;
-    type SharedPushConstants = ();
-    type FragmentPushConstants = FragmentPushConstants;
+    type SharedPushConstants = SharedPushConstants;
+    type FragmentPushConstants = ();
 
     fn name() -> &'static str {
         "particle"
@@ -295,22 +380,12 @@ impl Pipeline for ParticlePipeline {
             wgpu::VertexBufferDescriptor {
                 stride: size_of::<ParticleVertex>() as u64,
                 step_mode: wgpu::InputStepMode::Vertex,
-                attributes: &wgpu::vertex_attr_array![
-                    // position
-                    0 => Float3,
-                    // texcoord
-                    1 => Float2,
-                ],
+                attributes: &VERTEX_BUFFER_DESCRIPTOR_ATTRIBUTES[0],
             },
             wgpu::VertexBufferDescriptor {
                 stride: size_of::<ParticleInstance>() as u64,
                 step_mode: wgpu::InputStepMode::Instance,
-                attributes: &wgpu::vertex_attr_array![
-                    // instance position
-                    2 => Float3,
-                    // color index
-                    3 => Uint,
-                ],
+                attributes: &VERTEX_BUFFER_DESCRIPTOR_ATTRIBUTES[1],
             },
         ]
     }
@@ -351,6 +426,26 @@ pub const VERTICES: [ParticleVertex; 6] = [
 ];
 
 #[repr(C)]
+#[derive(Copy, Clone, Debug)]
 pub struct ParticleInstance {
+    position: [f32; 3],
     color: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_square_particle_size() {
+        // at the reference width, particles are 256 / depth pixels, clamped to [1, 4]
+        assert_eq!(square_particle_size(32.0, 320), 4.0);
+        assert_eq!(square_particle_size(128.0, 320), 2.0);
+        assert_eq!(square_particle_size(1000.0, 320), 1.0);
+
+        // the size range scales with the view width
+        assert_eq!(square_particle_size(10.0, 1280), 16.0);
+        assert_eq!(square_particle_size(10000.0, 1280), 4.0);
+        assert_eq!(square_particle_size(256.0, 1280), 4.0);
+    }
+}
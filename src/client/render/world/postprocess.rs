@@ -37,14 +37,63 @@ lazy_static! {
                     ).unwrap()),
                 },
             ),
+
+            // bloom buffer
+            wgpu::BindGroupLayoutEntry::new(
+                3,
+                wgpu::ShaderStage::FRAGMENT,
+                wgpu::BindingType::SampledTexture {
+                    dimension: wgpu::TextureViewDimension::D2,
+                    component_type: wgpu::TextureComponentType::Float,
+                    multisampled: false,
+                },
+            ),
+
+            // bloom sampler
+            wgpu::BindGroupLayoutEntry::new(
+                4,
+                wgpu::ShaderStage::FRAGMENT,
+                wgpu::BindingType::Sampler { comparison: false },
+            ),
         ]
     ];
 }
 
+/// The operator used to map the HDR scene color into displayable range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tonemap {
+    /// Clamp each channel to 1.0.
+    None = 0,
+    Reinhard = 1,
+    Aces = 2,
+}
+
+impl Tonemap {
+    /// Returns the operator selected by a value of `r_tonemap`.
+    pub fn from_value(value: f32) -> Option<Tonemap> {
+        match value as i32 {
+            0 => Some(Tonemap::None),
+            1 => Some(Tonemap::Reinhard),
+            2 => Some(Tonemap::Aces),
+            _ => None,
+        }
+    }
+}
+
+impl std::default::Default for Tonemap {
+    fn default() -> Self {
+        Tonemap::None
+    }
+}
+
 #[repr(C, align(256))]
 #[derive(Clone, Copy, Debug)]
 pub struct PostProcessUniforms {
     pub color_shift: [f32; 4],
+    pub bloom_intensity: f32,
+    pub tonemap: u32,
+    pub time: f32,
+    pub water_warp: f32,
 }
 
 pub struct PostProcessPipeline {
@@ -65,6 +114,10 @@ impl PostProcessPipeline {
             unsafe {
                 any_as_bytes(&PostProcessUniforms {
                     color_shift: [0.0; 4],
+                    bloom_intensity: 0.0,
+                    tonemap: Tonemap::None as u32,
+                    time: 0.0,
+                    water_warp: 0.0,
                 })
             },
             wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
@@ -157,7 +210,11 @@ pub struct PostProcessRenderer {
 }
 
 impl PostProcessRenderer {
-    pub fn new(state: &GraphicsState, color_buffer: &wgpu::TextureView) -> PostProcessRenderer {
+    pub fn new(
+        state: &GraphicsState,
+        color_buffer: &wgpu::TextureView,
+        bloom_buffer: &wgpu::TextureView,
+    ) -> PostProcessRenderer {
         let bind_group = state
             .device()
             .create_bind_group(&wgpu::BindGroupDescriptor {
@@ -182,18 +239,27 @@ impl PostProcessRenderer {
                             state.postprocess_pipeline().uniform_buffer().slice(..),
                         ),
                     },
+                    // bloom buffer
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(bloom_buffer),
+                    },
+                    // bloom sampler, filtered since the bloom buffer is half resolution
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::Sampler(state.lightmap_sampler()),
+                    },
                 ],
             });
 
         PostProcessRenderer { bind_group }
     }
 
-    pub fn update_uniform_buffers(&self, state: &GraphicsState, color_shift: [f32; 4]) {
-        // update color shift
+    pub fn update_uniform_buffers(&self, state: &GraphicsState, uniforms: PostProcessUniforms) {
         state
             .queue()
             .write_buffer(state.postprocess_pipeline().uniform_buffer(), 0, unsafe {
-                any_as_bytes(&PostProcessUniforms { color_shift })
+                any_as_bytes(&uniforms)
             });
     }
 
@@ -201,9 +267,9 @@ impl PostProcessRenderer {
         &'pass self,
         state: &'pass GraphicsState,
         pass: &mut wgpu::RenderPass<'pass>,
-        color_shift: [f32; 4],
+        uniforms: PostProcessUniforms,
     ) {
-        self.update_uniform_buffers(state, color_shift);
+        self.update_uniform_buffers(state, uniforms);
         pass.set_pipeline(state.postprocess_pipeline().pipeline());
         pass.set_vertex_buffer(0, state.quad_pipeline().vertex_buffer().slice(..));
         pass.set_bind_group(0, &self.bind_group, &[]);
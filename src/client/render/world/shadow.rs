@@ -0,0 +1,309 @@
+//! Omnidirectional shadow maps for dynamic point lights.
+//!
+//! Each shadowed light is rendered into six layers of a depth texture array, one per face of an
+//! axis-aligned cube centered on the light. Rather than the usual hyperbolic depth, each layer
+//! stores the distance from the light to the nearest occluder divided by the light's radius. This
+//! allows the deferred pass to compare against the same quantity it uses for attenuation.
+
+use std::mem::size_of;
+
+use crate::{
+    client::render::{
+        pipeline::{Pipeline, PushConstantUpdate},
+        world::{brush::BrushVertex, WorldPipelineBase, WorldRenderer},
+        GraphicsState, DEPTH_ATTACHMENT_FORMAT,
+    },
+    common::frustum,
+};
+
+use bumpalo::Bump;
+use cgmath::{Deg, Matrix4, Point3, SquareMatrix as _, Vector3, Vector4};
+
+// if this is changed, it must also be changed in deferred.frag
+/// The maximum number of lights which may cast shadows in a single frame.
+pub const MAX_SHADOW_LIGHTS: usize = 4;
+
+/// The number of shadow map layers used by each light.
+pub const SHADOW_FACE_COUNT: usize = 6;
+
+/// The shadow map resolution used if `r_shadow_size` is invalid.
+pub const DEFAULT_SHADOW_SIZE: u32 = 512;
+
+// distance from the light to the near plane of each face frustum
+const SHADOW_NEAR_PLANE: f32 = 1.0;
+
+// converts OpenGL clip space depth ([-1, 1]) to wgpu clip space depth ([0, 1])
+#[rustfmt::skip]
+const DEPTH_RANGE_CORRECTION: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+/// Calculates the view-projection transforms for each face of a light's shadow cube.
+///
+/// `origin` is given in world (i.e. already converted from Quake) coordinates.
+pub fn shadow_face_transforms(
+    origin: Vector3<f32>,
+    radius: f32,
+) -> [Matrix4<f32>; SHADOW_FACE_COUNT] {
+    let projection = DEPTH_RANGE_CORRECTION
+        * cgmath::perspective(
+            Deg(90.0),
+            1.0,
+            SHADOW_NEAR_PLANE,
+            radius.max(SHADOW_NEAR_PLANE + 1.0),
+        );
+    let eye = Point3::new(origin.x, origin.y, origin.z);
+
+    let faces = [
+        (Vector3::unit_x(), -Vector3::unit_y()),
+        (-Vector3::unit_x(), -Vector3::unit_y()),
+        (Vector3::unit_y(), Vector3::unit_z()),
+        (-Vector3::unit_y(), -Vector3::unit_z()),
+        (Vector3::unit_z(), -Vector3::unit_y()),
+        (-Vector3::unit_z(), -Vector3::unit_y()),
+    ];
+
+    let mut transforms = [Matrix4::identity(); SHADOW_FACE_COUNT];
+    for (face_id, (dir, up)) in faces.iter().enumerate() {
+        transforms[face_id] = projection * Matrix4::look_at_dir(eye, *dir, *up);
+    }
+
+    transforms
+}
+
+pub struct ShadowPipeline {
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowPipeline {
+    pub fn new(device: &wgpu::Device, compiler: &mut shaderc::Compiler) -> ShadowPipeline {
+        // shadow maps are never multisampled
+        let (pipeline, _) = ShadowPipeline::create(device, compiler, &[], 1);
+
+        ShadowPipeline { pipeline }
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct VertexPushConstants {
+    /// View-projection transform of the current shadow cube face.
+    pub transform: Matrix4<f32>,
+
+    /// Light origin in world coordinates (xyz) and radius (w).
+    pub light: Vector4<f32>,
+}
+
+impl Pipeline for ShadowPipeline {
+    type VertexPushConstants = VertexPushConstants;
+    type SharedPushConstants = ();
+    type FragmentPushConstants = ();
+
+    fn name() -> &'static str {
+        "shadow"
+    }
+
+    fn vertex_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/shadow.vert"))
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/shadow.frag"))
+    }
+
+    fn bind_group_layout_descriptors() -> Vec<wgpu::BindGroupLayoutDescriptor<'static>> {
+        Vec::new()
+    }
+
+    fn rasterization_state_descriptor() -> Option<wgpu::RasterizationStateDescriptor> {
+        WorldPipelineBase::rasterization_state_descriptor()
+    }
+
+    fn primitive_topology() -> wgpu::PrimitiveTopology {
+        wgpu::PrimitiveTopology::TriangleList
+    }
+
+    fn color_state_descriptors() -> Vec<wgpu::ColorStateDescriptor> {
+        // depth only
+        Vec::new()
+    }
+
+    fn depth_stencil_state_descriptor() -> Option<wgpu::DepthStencilStateDescriptor> {
+        WorldPipelineBase::depth_stencil_state_descriptor()
+    }
+
+    // only the position attribute of the brush vertex is used
+    fn vertex_buffer_descriptors() -> Vec<wgpu::VertexBufferDescriptor<'static>> {
+        vec![wgpu::VertexBufferDescriptor {
+            stride: size_of::<BrushVertex>() as u64,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &wgpu::vertex_attr_array![
+                // position
+                0 => Float3,
+            ],
+        }]
+    }
+}
+
+/// A depth texture array holding the shadow cubes of all shadowed lights.
+pub struct ShadowMapTarget {
+    size: u32,
+    _texture: wgpu::Texture,
+    array_view: wgpu::TextureView,
+    face_views: Vec<wgpu::TextureView>,
+}
+
+impl ShadowMapTarget {
+    pub fn new(device: &wgpu::Device, size: u32) -> ShadowMapTarget {
+        let layer_count = (MAX_SHADOW_LIGHTS * SHADOW_FACE_COUNT) as u32;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow map"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth: layer_count,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DEPTH_ATTACHMENT_FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        });
+
+        let array_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("shadow map array view"),
+            format: Some(DEPTH_ATTACHMENT_FORMAT),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            aspect: wgpu::TextureAspect::DepthOnly,
+            base_mip_level: 0,
+            level_count: None,
+            base_array_layer: 0,
+            array_layer_count: std::num::NonZeroU32::new(layer_count),
+        });
+
+        let face_views = (0..layer_count)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("shadow map face view"),
+                    format: Some(DEPTH_ATTACHMENT_FORMAT),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    aspect: wgpu::TextureAspect::DepthOnly,
+                    base_mip_level: 0,
+                    level_count: None,
+                    base_array_layer: layer,
+                    array_layer_count: std::num::NonZeroU32::new(1),
+                })
+            })
+            .collect();
+
+        ShadowMapTarget {
+            size,
+            _texture: texture,
+            array_view,
+            face_views,
+        }
+    }
+
+    /// Returns the width and height of each shadow map layer.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Returns a view of the entire shadow map array, for sampling in the deferred pass.
+    pub fn array_view(&self) -> &wgpu::TextureView {
+        &self.array_view
+    }
+
+    /// Returns a view of a single face of the shadow cube for the given light.
+    pub fn face_view(&self, light_id: usize, face_id: usize) -> &wgpu::TextureView {
+        assert!(light_id < MAX_SHADOW_LIGHTS && face_id < SHADOW_FACE_COUNT);
+        &self.face_views[light_id * SHADOW_FACE_COUNT + face_id]
+    }
+}
+
+/// A light selected to cast shadows this frame.
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowLight {
+    /// Origin of the light in Quake coordinates.
+    pub origin: Vector3<f32>,
+    pub radius: f32,
+}
+
+pub struct ShadowRenderer {
+    target: ShadowMapTarget,
+}
+
+impl ShadowRenderer {
+    pub fn new(state: &GraphicsState, size: u32) -> ShadowRenderer {
+        ShadowRenderer {
+            target: ShadowMapTarget::new(state.device(), size),
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.target.size()
+    }
+
+    pub fn shadow_map_view(&self) -> &wgpu::TextureView {
+        self.target.array_view()
+    }
+
+    /// Records one depth pass per shadow cube face for each of the given lights.
+    ///
+    /// Returns the face transforms of each light in the order the lights were given. At most
+    /// `MAX_SHADOW_LIGHTS` lights are rendered; any additional lights are ignored.
+    pub fn render_shadow_maps<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        encoder: &mut wgpu::CommandEncoder,
+        bump: &'a Bump,
+        world_renderer: &'a WorldRenderer,
+        lights: &[ShadowLight],
+    ) -> Vec<[Matrix4<f32>; SHADOW_FACE_COUNT]> {
+        let mut transforms = Vec::with_capacity(lights.len().min(MAX_SHADOW_LIGHTS));
+
+        for (light_id, light) in lights.iter().take(MAX_SHADOW_LIGHTS).enumerate() {
+            let converted_origin = frustum::quake_to_wgpu(light.origin);
+            let face_transforms = shadow_face_transforms(converted_origin, light.radius);
+
+            for (face_id, face_transform) in face_transforms.iter().enumerate() {
+                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    color_attachments: &[],
+                    depth_stencil_attachment: Some(
+                        wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                            attachment: self.target.face_view(light_id, face_id),
+                            depth_ops: Some(wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(1.0),
+                                store: true,
+                            }),
+                            stencil_ops: None,
+                        },
+                    ),
+                });
+
+                pass.set_pipeline(state.shadow_pipeline().pipeline());
+                ShadowPipeline::set_push_constants(
+                    &mut pass,
+                    PushConstantUpdate::Update(bump.alloc(VertexPushConstants {
+                        transform: *face_transform,
+                        light: converted_origin.extend(light.radius),
+                    })),
+                    PushConstantUpdate::Clear,
+                    PushConstantUpdate::Clear,
+                );
+                world_renderer.record_shadow_draw(&mut pass, bump, light.origin);
+            }
+
+            transforms.push(face_transforms);
+        }
+
+        transforms
+    }
+}
@@ -0,0 +1,448 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Environmental effects applied to world sounds after spatialization.
+//!
+//! Every entity and static sound is played through an [`Effects`] stage that applies a one-pole
+//! low-pass filter (used while the listener is underwater) and a feedback-delay reverb whose delay
+//! and decay are derived from the estimated size of the room around the listener. The parameters
+//! are shared with the mixer thread through [`EffectParams`] and updated by the client each frame.
+//!
+//! Each sound also has its own [`SoundFilter`], which muffles it while walls stand between it and
+//! the listener.
+
+use std::{
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rodio::Source;
+
+/// Speed of sound in Quake units (roughly inches) per second.
+pub const SPEED_OF_SOUND: f32 = 13500.0;
+
+/// The shortest reverb delay, used for cramped spaces.
+pub const MIN_REVERB_DELAY: f32 = 0.02;
+
+/// The longest reverb delay, used for very large rooms.
+pub const MAX_REVERB_DELAY: f32 = 0.25;
+
+// rooms at least this large (in Quake units) get the maximum reverb decay
+const LARGE_ROOM_SIZE: f32 = 1024.0;
+
+// decay range of the reverb feedback loop
+const MIN_REVERB_FEEDBACK: f32 = 0.2;
+const MAX_REVERB_FEEDBACK: f32 = 0.6;
+
+// how many samples are produced between reads of the shared parameters
+const PARAM_REFRESH_SAMPLES: usize = 512;
+
+/// Returns the smoothing coefficient of a one-pole low-pass filter.
+///
+/// A cutoff of zero or at or above the Nyquist frequency disables filtering, which yields a
+/// coefficient of 1.
+pub fn lowpass_coefficient(cutoff: f32, sample_rate: u32) -> f32 {
+    let sample_rate = sample_rate as f32;
+    if cutoff <= 0.0 || cutoff >= sample_rate / 2.0 {
+        return 1.0;
+    }
+
+    1.0 - (-2.0 * PI * cutoff / sample_rate).exp()
+}
+
+/// Returns the cutoff of two low-pass filters applied one after the other.
+///
+/// This is approximated by the lower cutoff. A cutoff of zero means no filtering.
+pub fn combine_cutoffs(a: f32, b: f32) -> f32 {
+    match (a > 0.0, b > 0.0) {
+        (true, true) => a.min(b),
+        (true, false) => a,
+        (false, _) => b.max(0.0),
+    }
+}
+
+/// Parameters of the feedback-delay reverb.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reverb {
+    /// Delay between echoes in seconds.
+    pub delay: f32,
+
+    /// Fraction of each echo fed back into the delay line.
+    pub feedback: f32,
+
+    /// Volume of the echoes relative to the dry signal.
+    pub mix: f32,
+}
+
+impl Reverb {
+    /// No reverb at all.
+    pub const NONE: Reverb = Reverb {
+        delay: 0.0,
+        feedback: 0.0,
+        mix: 0.0,
+    };
+
+    /// Returns reverb parameters for a room of the given size.
+    ///
+    /// `room_size` is the average distance from the listener to the surrounding walls. Larger rooms
+    /// produce longer delays and slower decay.
+    pub fn for_room_size(room_size: f32, mix: f32) -> Reverb {
+        if mix <= 0.0 {
+            return Reverb::NONE;
+        }
+
+        let room_size = room_size.max(0.0);
+        let delay = (2.0 * room_size / SPEED_OF_SOUND)
+            .max(MIN_REVERB_DELAY)
+            .min(MAX_REVERB_DELAY);
+        let feedback = MIN_REVERB_FEEDBACK
+            + (MAX_REVERB_FEEDBACK - MIN_REVERB_FEEDBACK) * (room_size / LARGE_ROOM_SIZE).min(1.0);
+
+        Reverb {
+            delay,
+            feedback,
+            mix: mix.min(1.0),
+        }
+    }
+}
+
+/// Effect parameters shared between the client and every playing sound.
+#[derive(Debug)]
+pub struct EffectParams {
+    // f32 values stored as bits so they can be updated from the client thread
+    cutoff: AtomicU32,
+    reverb_delay: AtomicU32,
+    reverb_feedback: AtomicU32,
+    reverb_mix: AtomicU32,
+}
+
+impl EffectParams {
+    pub fn new() -> EffectParams {
+        EffectParams {
+            cutoff: AtomicU32::new(0.0f32.to_bits()),
+            reverb_delay: AtomicU32::new(0.0f32.to_bits()),
+            reverb_feedback: AtomicU32::new(0.0f32.to_bits()),
+            reverb_mix: AtomicU32::new(0.0f32.to_bits()),
+        }
+    }
+
+    /// Returns the low-pass cutoff frequency in Hz, or 0 if the filter is disabled.
+    pub fn lowpass(&self) -> f32 {
+        f32::from_bits(self.cutoff.load(Ordering::Relaxed))
+    }
+
+    /// Sets the low-pass cutoff frequency in Hz. `None` disables the filter.
+    pub fn set_lowpass(&self, cutoff: Option<f32>) {
+        self.cutoff
+            .store(cutoff.unwrap_or(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn reverb(&self) -> Reverb {
+        Reverb {
+            delay: f32::from_bits(self.reverb_delay.load(Ordering::Relaxed)),
+            feedback: f32::from_bits(self.reverb_feedback.load(Ordering::Relaxed)),
+            mix: f32::from_bits(self.reverb_mix.load(Ordering::Relaxed)),
+        }
+    }
+
+    pub fn set_reverb(&self, reverb: Reverb) {
+        self.reverb_delay
+            .store(reverb.delay.to_bits(), Ordering::Relaxed);
+        self.reverb_feedback
+            .store(reverb.feedback.to_bits(), Ordering::Relaxed);
+        self.reverb_mix
+            .store(reverb.mix.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// The low-pass filter of a single sound, shared between the client and the sound's `Effects`.
+#[derive(Debug)]
+pub struct SoundFilter {
+    // f32 stored as bits so it can be updated from the client thread
+    cutoff: AtomicU32,
+}
+
+impl SoundFilter {
+    pub fn new() -> SoundFilter {
+        SoundFilter {
+            cutoff: AtomicU32::new(0.0f32.to_bits()),
+        }
+    }
+
+    /// Returns the low-pass cutoff frequency in Hz, or 0 if the filter is disabled.
+    pub fn lowpass(&self) -> f32 {
+        f32::from_bits(self.cutoff.load(Ordering::Relaxed))
+    }
+
+    /// Sets the low-pass cutoff frequency in Hz. `None` disables the filter.
+    pub fn set_lowpass(&self, cutoff: Option<f32>) {
+        self.cutoff
+            .store(cutoff.unwrap_or(0.0).to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// A `Source` adapter applying the low-pass filter and reverb described by an `EffectParams`,
+/// along with the sound's own `SoundFilter`.
+///
+/// The reverb tail is cut off when the wrapped source ends.
+pub struct Effects<S> {
+    src: S,
+    params: Arc<EffectParams>,
+    filter: Arc<SoundFilter>,
+    channels: usize,
+    sample_rate: u32,
+
+    // channel of the next sample
+    channel: usize,
+
+    // low-pass filter state for each channel
+    lowpass: Vec<f32>,
+    alpha: f32,
+
+    // interleaved delay line and its write position
+    delay_line: Vec<f32>,
+    delay_pos: usize,
+    delay_len: usize,
+    feedback: f32,
+    mix: f32,
+
+    until_refresh: usize,
+}
+
+impl<S> Effects<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(src: S, params: Arc<EffectParams>, filter: Arc<SoundFilter>) -> Effects<S> {
+        let channels = (src.channels() as usize).max(1);
+        let sample_rate = src.sample_rate();
+        let max_frames = (MAX_REVERB_DELAY * sample_rate as f32).ceil() as usize;
+
+        Effects {
+            src,
+            params,
+            filter,
+            channels,
+            sample_rate,
+            channel: 0,
+            lowpass: vec![0.0; channels],
+            alpha: 1.0,
+            delay_line: vec![0.0; max_frames.max(1) * channels],
+            delay_pos: 0,
+            delay_len: 0,
+            feedback: 0.0,
+            mix: 0.0,
+            until_refresh: 0,
+        }
+    }
+
+    fn refresh_params(&mut self) {
+        let cutoff = combine_cutoffs(self.params.lowpass(), self.filter.lowpass());
+        self.alpha = lowpass_coefficient(cutoff, self.sample_rate);
+
+        let reverb = self.params.reverb();
+        if reverb.mix > 0.0 && reverb.delay > 0.0 {
+            // keep the delay a whole number of frames so channels stay aligned
+            let frames = (reverb.delay * self.sample_rate as f32) as usize;
+            self.delay_len = (frames.max(1) * self.channels).min(self.delay_line.len());
+            self.feedback = reverb.feedback;
+            self.mix = reverb.mix;
+        } else {
+            self.delay_len = 0;
+        }
+
+        self.until_refresh = PARAM_REFRESH_SAMPLES;
+    }
+}
+
+impl<S> Iterator for Effects<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.src.next()?;
+
+        if self.until_refresh == 0 {
+            self.refresh_params();
+        }
+        self.until_refresh -= 1;
+
+        let ch = self.channel;
+        self.channel = (self.channel + 1) % self.channels;
+
+        self.lowpass[ch] += self.alpha * (sample - self.lowpass[ch]);
+        let dry = self.lowpass[ch];
+
+        let cap = self.delay_line.len();
+        let echo = if self.delay_len > 0 {
+            self.delay_line[(self.delay_pos + cap - self.delay_len) % cap]
+        } else {
+            0.0
+        };
+
+        // keep the delay line current while reverb is off so enabling it won't replay old audio
+        self.delay_line[self.delay_pos] = dry + echo * self.feedback;
+        self.delay_pos = (self.delay_pos + 1) % cap;
+
+        Some(dry + echo * self.mix)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.src.size_hint()
+    }
+}
+
+impl<S> Source for Effects<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.src.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.src.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.src.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.src.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rodio::buffer::SamplesBuffer;
+
+    #[test]
+    fn test_lowpass_coefficient() {
+        assert_eq!(lowpass_coefficient(0.0, 11025), 1.0);
+        assert_eq!(lowpass_coefficient(6000.0, 11025), 1.0);
+
+        let low = lowpass_coefficient(500.0, 11025);
+        let high = lowpass_coefficient(2000.0, 11025);
+        assert!(0.0 < low && low < high && high < 1.0);
+    }
+
+    #[test]
+    fn test_reverb_for_room_size() {
+        assert_eq!(Reverb::for_room_size(512.0, 0.0), Reverb::NONE);
+
+        let small = Reverb::for_room_size(0.0, 0.5);
+        assert_eq!(small.delay, MIN_REVERB_DELAY);
+        assert_eq!(small.feedback, MIN_REVERB_FEEDBACK);
+
+        let large = Reverb::for_room_size(1.0e6, 0.5);
+        assert_eq!(large.delay, MAX_REVERB_DELAY);
+        assert_eq!(large.feedback, MAX_REVERB_FEEDBACK);
+
+        let medium = Reverb::for_room_size(512.0, 0.5);
+        assert!(small.delay < medium.delay && medium.delay < large.delay);
+        assert!(small.feedback < medium.feedback && medium.feedback < large.feedback);
+    }
+
+    #[test]
+    fn test_effects_passthrough() {
+        let samples = vec![0.5, -0.25, 1.0, 0.0];
+        let params = Arc::new(EffectParams::new());
+        let out: Vec<f32> = Effects::new(
+            SamplesBuffer::new(1, 11025, samples.clone()),
+            params,
+            Arc::new(SoundFilter::new()),
+        )
+        .collect();
+        assert_eq!(out, samples);
+    }
+
+    #[test]
+    fn test_effects_reverb() {
+        let params = Arc::new(EffectParams::new());
+        params.set_reverb(Reverb {
+            delay: 0.05,
+            feedback: 0.5,
+            mix: 1.0,
+        });
+
+        // at 100 Hz a 50ms delay is 5 samples
+        let mut impulse = vec![0.0; 12];
+        impulse[0] = 1.0;
+        let out: Vec<f32> = Effects::new(
+            SamplesBuffer::new(1, 100, impulse),
+            params,
+            Arc::new(SoundFilter::new()),
+        )
+        .collect();
+        assert_eq!(out[0], 1.0);
+        assert_eq!(out[5], 1.0);
+        assert_eq!(out[10], 0.5);
+        assert_eq!(out[3], 0.0);
+    }
+
+    #[test]
+    fn test_effects_lowpass() {
+        let params = Arc::new(EffectParams::new());
+        params.set_lowpass(Some(500.0));
+
+        let out: Vec<f32> = Effects::new(
+            SamplesBuffer::new(1, 11025, vec![1.0; 32]),
+            params,
+            Arc::new(SoundFilter::new()),
+        )
+        .collect();
+        assert!(out[0] < 1.0);
+        assert!(out.windows(2).all(|w| w[0] < w[1]));
+        assert!(out[31] <= 1.0);
+    }
+
+    #[test]
+    fn test_combine_cutoffs() {
+        assert_eq!(combine_cutoffs(0.0, 0.0), 0.0);
+        assert_eq!(combine_cutoffs(800.0, 0.0), 800.0);
+        assert_eq!(combine_cutoffs(0.0, 1200.0), 1200.0);
+        assert_eq!(combine_cutoffs(800.0, 1200.0), 800.0);
+    }
+
+    #[test]
+    fn test_effects_sound_filter() {
+        let filter = Arc::new(SoundFilter::new());
+        filter.set_lowpass(Some(500.0));
+
+        // the sound's own filter muffles it without the shared parameters
+        let out: Vec<f32> = Effects::new(
+            SamplesBuffer::new(1, 11025, vec![1.0; 32]),
+            Arc::new(EffectParams::new()),
+            filter,
+        )
+        .collect();
+        assert!(out[0] < 1.0);
+        assert!(out.windows(2).all(|w| w[0] < w[1]));
+    }
+}
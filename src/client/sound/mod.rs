@@ -18,13 +18,20 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+pub mod effects;
+pub mod music;
+
 use std::{
     cell::{Cell, RefCell},
     io::{self, BufReader, BufWriter, Cursor, Read},
     rc::Rc,
+    sync::Arc,
 };
 
-use crate::common::vfs::{Vfs, VfsError};
+use crate::{
+    client::sound::effects::{EffectParams, Effects, SoundFilter},
+    common::vfs::{Vfs, VfsError},
+};
 
 use cgmath::{InnerSpace, Vector3};
 use hound::{WavReader, WavWriter};
@@ -46,6 +53,8 @@ pub enum SoundError {
     Wav(#[from] hound::Error),
     #[error("WAV decoder error: {0}")]
     Decoder(#[from] rodio::decoder::DecoderError),
+    #[error("No such music track: {0}")]
+    NoSuchMusicTrack(String),
 }
 
 /// Data needed for sound spatialization.
@@ -56,6 +65,7 @@ pub struct Listener {
     origin: Cell<Vector3<f32>>,
     left_ear: Cell<Vector3<f32>>,
     right_ear: Cell<Vector3<f32>>,
+    effects: Arc<EffectParams>,
 }
 
 impl Listener {
@@ -64,6 +74,7 @@ impl Listener {
             origin: Cell::new(Vector3::new(0.0, 0.0, 0.0)),
             left_ear: Cell::new(Vector3::new(0.0, 0.0, 0.0)),
             right_ear: Cell::new(Vector3::new(0.0, 0.0, 0.0)),
+            effects: Arc::new(EffectParams::new()),
         }
     }
 
@@ -79,6 +90,11 @@ impl Listener {
         self.right_ear.get()
     }
 
+    /// Returns the environmental effect parameters applied to every world sound.
+    pub fn effects(&self) -> &Arc<EffectParams> {
+        &self.effects
+    }
+
     pub fn set_origin(&self, new_origin: Vector3<f32>) {
         self.origin.set(new_origin);
     }
@@ -105,6 +121,47 @@ impl Listener {
     }
 }
 
+/// How world geometry between a sound and the listener changes what is heard.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Occlusion {
+    /// Scale applied to the sound's volume.
+    pub volume: f32,
+
+    /// Cutoff frequency in Hz of the low-pass filter muffling the sound, if any.
+    pub cutoff: Option<f32>,
+}
+
+impl Occlusion {
+    /// The sound is heard unobstructed.
+    pub const NONE: Occlusion = Occlusion {
+        volume: 1.0,
+        cutoff: None,
+    };
+
+    /// Returns the occlusion of a sound.
+    ///
+    /// `line_of_sight` is `true` if nothing blocks the line from the listener to the sound, and
+    /// `hearable` is `true` if the sound is in the listener's potentially hearable set. A sound
+    /// behind a wall but within the PHS is heard at `volume` through a low-pass filter at `cutoff`
+    /// Hz. A sound outside the PHS is heard as if through two walls. A `cutoff` of 0 disables
+    /// filtering.
+    pub fn new(line_of_sight: bool, hearable: bool, volume: f32, cutoff: f32) -> Occlusion {
+        if line_of_sight {
+            return Occlusion::NONE;
+        }
+
+        let cutoff = if cutoff > 0.0 { Some(cutoff) } else { None };
+        if hearable {
+            Occlusion { volume, cutoff }
+        } else {
+            Occlusion {
+                volume: volume * volume,
+                cutoff: cutoff.map(|c| c / 2.0),
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct AudioSource(Buffered<SamplesConverter<Decoder<BufReader<Cursor<Vec<u8>>>>, f32>>);
 
@@ -119,6 +176,11 @@ impl AudioSource {
         let mut data = Vec::new();
         file.read_to_end(&mut data)?;
 
+        AudioSource::from_bytes(data)
+    }
+
+    /// Decodes a sound from the contents of a WAV file.
+    pub fn from_bytes(mut data: Vec<u8>) -> Result<AudioSource, SoundError> {
         let spec = {
             let wav_reader = WavReader::new(Cursor::new(&mut data))?;
             wav_reader.spec()
@@ -127,9 +189,11 @@ impl AudioSource {
         // have to convert from 8- to 16-bit here because rodio chokes on 8-bit PCM
         // TODO: update to new rodio version (or master)
         if spec.bits_per_sample == 8 {
+            let data_len = data.len();
             let mut wav_reader = WavReader::new(Cursor::new(&mut data))?;
-            let len = wav_reader.len();
-            let mut data_16bit: Vec<i16> = Vec::with_capacity(len as usize);
+            // the sample count comes from the header, so don't trust it beyond the data present
+            let len = (wav_reader.len() as usize).min(data_len);
+            let mut data_16bit: Vec<i16> = Vec::with_capacity(len);
             for sample in wav_reader.samples::<i8>() {
                 data_16bit.push(sample? as i16 * 256);
             }
@@ -139,7 +203,7 @@ impl AudioSource {
             let mut spec16 = spec;
             spec16.bits_per_sample = 16;
             let mut wav_writer = WavWriter::new(w, spec16)?;
-            let mut i16_writer = wav_writer.get_i16_writer(len);
+            let mut i16_writer = wav_writer.get_i16_writer(data_16bit.len() as u32);
             for s in data_16bit {
                 i16_writer.write_sample(s);
             }
@@ -157,6 +221,7 @@ impl AudioSource {
 pub struct StaticSound {
     origin: Vector3<f32>,
     sink: RefCell<Sink>,
+    filter: Arc<SoundFilter>,
     volume: f32,
     attenuation: f32,
 }
@@ -171,22 +236,49 @@ impl StaticSound {
         listener: &Listener,
     ) -> StaticSound {
         let sink = Sink::new(device);
+        let filter = Arc::new(SoundFilter::new());
         let infinite = src.0.clone().repeat_infinite();
-        sink.append(infinite);
+        sink.append(Effects::new(
+            infinite,
+            listener.effects().clone(),
+            filter.clone(),
+        ));
         sink.set_volume(listener.attenuate(origin, volume, attenuation));
 
         StaticSound {
             origin,
             sink: RefCell::new(sink),
+            filter,
             volume,
             attenuation,
         }
     }
 
-    pub fn update(&self, listener: &Listener) {
+    pub fn origin(&self) -> Vector3<f32> {
+        self.origin
+    }
+
+    /// Update the volume of this sound for the new listener position.
+    ///
+    /// `occlusion` accounts for world geometry between the sound and the listener.
+    pub fn update(&self, listener: &Listener, occlusion: Occlusion) {
         let sink = self.sink.borrow_mut();
 
-        sink.set_volume(listener.attenuate(self.origin, self.volume, self.attenuation));
+        sink.set_volume(
+            listener.attenuate(self.origin, self.volume, self.attenuation) * occlusion.volume,
+        );
+        self.filter.set_lowpass(occlusion.cutoff);
+    }
+
+    /// Pause or resume playback of this sound.
+    pub fn set_paused(&self, paused: bool) {
+        let sink = self.sink.borrow();
+
+        if paused {
+            sink.pause();
+        } else {
+            sink.play();
+        }
     }
 }
 
@@ -194,6 +286,7 @@ impl StaticSound {
 pub struct Channel {
     device: Rc<Device>,
     sink: RefCell<Option<Sink>>,
+    filter: Arc<SoundFilter>,
     master_vol: Cell<f32>,
     attenuation: Cell<f32>,
 }
@@ -204,6 +297,7 @@ impl Channel {
         Channel {
             device,
             sink: RefCell::new(None),
+            filter: Arc::new(SoundFilter::new()),
             master_vol: Cell::new(0.0),
             attenuation: Cell::new(0.0),
         }
@@ -218,15 +312,52 @@ impl Channel {
         volume: f32,
         attenuation: f32,
     ) {
+        self.start(src.0, ent_pos, listener, volume, attenuation);
+    }
+
+    /// Play a sound on this channel over and over until it is stopped, cutting off any sound that
+    /// was previously playing.
+    pub fn play_looping(
+        &self,
+        src: AudioSource,
+        ent_pos: Vector3<f32>,
+        listener: &Listener,
+        volume: f32,
+        attenuation: f32,
+    ) {
+        self.start(
+            src.0.repeat_infinite(),
+            ent_pos,
+            listener,
+            volume,
+            attenuation,
+        );
+    }
+
+    fn start<S>(
+        &self,
+        src: S,
+        ent_pos: Vector3<f32>,
+        listener: &Listener,
+        volume: f32,
+        attenuation: f32,
+    ) where
+        S: Source<Item = f32> + Send + 'static,
+    {
         self.master_vol.set(volume);
         self.attenuation.set(attenuation);
 
         // stop the old sound
         self.sink.replace(None);
 
-        // start the new sound
+        // start the new sound, unfiltered until its occlusion is known
+        self.filter.set_lowpass(None);
         let new_sink = Sink::new(&self.device);
-        new_sink.append(src.0);
+        new_sink.append(Effects::new(
+            src,
+            listener.effects().clone(),
+            self.filter.clone(),
+        ));
         new_sink.set_volume(listener.attenuate(
             ent_pos,
             self.master_vol.get(),
@@ -236,17 +367,32 @@ impl Channel {
         self.sink.replace(Some(new_sink));
     }
 
-    pub fn update(&self, ent_pos: Vector3<f32>, listener: &Listener) {
+    /// Update the volume of the sound playing on this channel for new entity and listener
+    /// positions.
+    ///
+    /// `occlusion` is applied as in [`StaticSound::update`].
+    pub fn update(&self, ent_pos: Vector3<f32>, listener: &Listener, occlusion: Occlusion) {
         if let Some(ref sink) = *self.sink.borrow_mut() {
             // attenuate using quake coordinates since distance is the same either way
-            sink.set_volume(listener.attenuate(
-                ent_pos,
-                self.master_vol.get(),
-                self.attenuation.get(),
-            ));
+            sink.set_volume(
+                listener.attenuate(ent_pos, self.master_vol.get(), self.attenuation.get())
+                    * occlusion.volume,
+            );
+            self.filter.set_lowpass(occlusion.cutoff);
         };
     }
 
+    /// Pause or resume the sound currently playing on this channel, if there is one.
+    pub fn set_paused(&self, paused: bool) {
+        if let Some(ref sink) = *self.sink.borrow() {
+            if paused {
+                sink.pause();
+            } else {
+                sink.play();
+            }
+        }
+    }
+
     /// Stop the sound currently playing on this channel, if there is one.
     pub fn stop(&self) {
         self.sink.replace(None);
@@ -269,3 +415,28 @@ impl Channel {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_occlusion() {
+        assert_eq!(Occlusion::new(true, false, 0.5, 1000.0), Occlusion::NONE);
+        assert_eq!(
+            Occlusion::new(false, true, 0.5, 1000.0),
+            Occlusion {
+                volume: 0.5,
+                cutoff: Some(1000.0),
+            }
+        );
+        assert_eq!(
+            Occlusion::new(false, false, 0.5, 1000.0),
+            Occlusion {
+                volume: 0.25,
+                cutoff: Some(500.0),
+            }
+        );
+        assert_eq!(Occlusion::new(false, true, 0.5, 0.0).cutoff, None);
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Music playback from ripped CD tracks.
+//!
+//! The original game played music from the CD audio tracks. Ports conventionally read rips of
+//! those tracks from the `music/` directory as `trackNN.ogg`, `.mp3` or `.flac`, where `NN` is the
+//! CD track number.
+
+use std::{
+    cell::Cell,
+    io::{Cursor, Read},
+    rc::Rc,
+    time::Duration,
+};
+
+use crate::{client::sound::SoundError, common::vfs::Vfs};
+
+use rodio::{Decoder, Device, Sink, Source};
+
+// extensions tried, in order, for tracks named without one
+const MUSIC_EXTENSIONS: [&str; 3] = ["ogg", "mp3", "flac"];
+
+// a decoded track that starts over from the beginning when it ends
+struct LoopingTrack {
+    data: Vec<u8>,
+    decoder: Decoder<Cursor<Vec<u8>>>,
+}
+
+impl LoopingTrack {
+    fn new(data: Vec<u8>) -> Result<LoopingTrack, SoundError> {
+        let decoder = Decoder::new(Cursor::new(data.clone()))?;
+        Ok(LoopingTrack { data, decoder })
+    }
+}
+
+impl Iterator for LoopingTrack {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if let Some(sample) = self.decoder.next() {
+            return Some(sample);
+        }
+
+        // the data decoded successfully once, so this should never fail
+        self.decoder = Decoder::new(Cursor::new(self.data.clone())).ok()?;
+        self.decoder.next()
+    }
+}
+
+impl Source for LoopingTrack {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.decoder.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.decoder.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.decoder.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Plays one looping music track at a time.
+pub struct MusicPlayer {
+    vfs: Rc<Vfs>,
+    device: Rc<Device>,
+
+    // name of the current track and the sink playing it
+    playing: Option<(String, Sink)>,
+
+    volume: Cell<f32>,
+    paused: Cell<bool>,
+}
+
+impl MusicPlayer {
+    pub fn new(vfs: Rc<Vfs>, device: Rc<Device>) -> MusicPlayer {
+        MusicPlayer {
+            vfs,
+            device,
+            playing: None,
+            volume: Cell::new(1.0),
+            paused: Cell::new(false),
+        }
+    }
+
+    /// Plays the rip of the given CD track, looping until stopped.
+    ///
+    /// Track 0 stops the music, since there's no such CD track.
+    pub fn play_track(&mut self, track: u8) -> Result<(), SoundError> {
+        if track == 0 {
+            self.stop();
+            return Ok(());
+        }
+
+        self.play_named(format!("track{:02}", track))
+    }
+
+    /// Plays `music/<name>`, looping until stopped.
+    ///
+    /// If `name` has no extension, each supported extension is tried in turn. If the track is
+    /// already playing, it continues uninterrupted.
+    pub fn play_named<S>(&mut self, name: S) -> Result<(), SoundError>
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref();
+        if let Some((ref current, _)) = self.playing {
+            if current == name {
+                return Ok(());
+            }
+        }
+
+        let data = self.load(name)?;
+        let track = LoopingTrack::new(data)?;
+
+        let sink = Sink::new(&self.device);
+        sink.set_volume(self.volume.get());
+        if self.paused.get() {
+            sink.pause();
+        }
+        sink.append(track);
+
+        debug!("Playing music track {}", name);
+        self.playing = Some((name.to_owned(), sink));
+
+        Ok(())
+    }
+
+    fn load(&self, name: &str) -> Result<Vec<u8>, SoundError> {
+        let mut file = if name.contains('.') {
+            self.vfs.open(format!("music/{}", name))?
+        } else {
+            MUSIC_EXTENSIONS
+                .iter()
+                .find_map(|ext| self.vfs.open(format!("music/{}.{}", name, ext)).ok())
+                .ok_or_else(|| SoundError::NoSuchMusicTrack(name.to_owned()))?
+        };
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Stops the current track, if there is one.
+    pub fn stop(&mut self) {
+        self.playing = None;
+    }
+
+    /// Returns the name of the track currently playing, if there is one.
+    pub fn playing(&self) -> Option<&str> {
+        self.playing.as_ref().map(|(name, _)| name.as_str())
+    }
+
+    /// Pause or resume playback of the current track.
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.set(paused);
+
+        if let Some((_, ref sink)) = self.playing {
+            if paused {
+                sink.pause();
+            } else {
+                sink.play();
+            }
+        }
+    }
+
+    /// Sets the music volume, independent of sound effect volume.
+    pub fn set_volume(&self, volume: f32) {
+        self.volume.set(volume);
+
+        if let Some((_, ref sink)) = self.playing {
+            sink.set_volume(volume);
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use std::f32::consts::PI;
 
 use crate::{
-    client::input::game::{Action, GameInput},
+    client::input::{
+        game::{Action, GameInput},
+        gamepad,
+    },
     common::{
         engine::{duration_to_f32, duration_from_f32},
         math::{self, Angles},
@@ -36,6 +39,9 @@ pub struct View {
 
     // punch angles from server
     punch_angles: Angles,
+
+    // scale of all view motion not caused by input, set by `v_motionscale`
+    motion_scale: f32,
 }
 
 impl View {
@@ -49,6 +55,7 @@ impl View {
             damage_angles: Angles::zero(),
             damage_time: Duration::zero(),
             punch_angles: Angles::zero(),
+            motion_scale: 1.0,
         }
     }
 
@@ -84,6 +91,12 @@ impl View {
         self.punch_angles = punch_angles;
     }
 
+    /// Sets the scale of the roll, kicks, punches and idle sway added to the input angles, where
+    /// 0 holds the view still.
+    pub fn set_motion_scale(&mut self, motion_scale: f32) {
+        self.motion_scale = motion_scale.max(0.0).min(1.0);
+    }
+
     pub fn input_angles(&self) -> Angles {
         self.input_angles
     }
@@ -102,7 +115,8 @@ impl View {
         cl_anglespeedkey: f32,
         cl_pitchspeed: f32,
         cl_yawspeed: f32,
-        mouse_vars: MouseVars
+        mouse_vars: MouseVars,
+        gamepad_vars: GamepadVars,
     ) {
         let frame_time_f32 = duration_to_f32(frame_time);
         let speed = if game_input.action_state(Action::Speed) {
@@ -134,6 +148,16 @@ impl View {
             self.input_angles.yaw -= Deg(game_input.mouse_delta().0 as f32 * yaw_factor);
         }
 
+        // the right stick turns at a rate like the arrow keys, independent of mouselook
+        let look = gamepad::apply_response(
+            game_input.gamepad_sticks().right,
+            gamepad_vars.joy_deadzone,
+            gamepad_vars.joy_exponent,
+        );
+        self.input_angles.yaw -= Deg(speed * gamepad_vars.joy_yawspeed * look.x);
+        self.input_angles.yaw = self.input_angles.yaw.normalize();
+        self.input_angles.pitch -= Deg(speed * gamepad_vars.joy_pitchspeed * look.y);
+
         if lookup_factor != 0.0 || lookdown_factor != 0.0 {
             // TODO: V_StopPitchDrift
         }
@@ -191,7 +215,8 @@ impl View {
         }
         let idle_angles = idle(time, idle_vars);
 
-        self.input_angles + move_angles + damage_angles + self.punch_angles + idle_angles
+        let motion = move_angles + damage_angles + self.punch_angles + idle_angles;
+        self.input_angles + motion * self.motion_scale
     }
 
     pub fn origin(&self) {
@@ -207,6 +232,14 @@ pub struct MouseVars {
     pub sensitivity: f32,
 }
 
+#[derive(Clone, Copy, Debug)]
+pub struct GamepadVars {
+    pub joy_deadzone: f32,
+    pub joy_exponent: f32,
+    pub joy_pitchspeed: f32,
+    pub joy_yawspeed: f32,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct KickVars {
     pub v_kickpitch: f32,
@@ -0,0 +1,119 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Memory for data that lives no longer than a single frame.
+//!
+//! Every frame builds a number of short-lived lists: the leaves visible from the camera and from
+//! each shadow-casting light, the lights themselves, shadow geometry and so on. Allocating these
+//! from the heap means several allocations and frees per frame, which adds up on large maps. A
+//! [`FrameArena`] instead hands out memory from a bump allocator which is reset all at once when
+//! the frame is done.
+
+use std::{cell::Cell, ops::Deref};
+
+use bumpalo::{collections::Vec as BumpVec, Bump};
+
+/// A bump allocator reset at the start of every frame.
+///
+/// `FrameArena` dereferences to [`Bump`](bumpalo::Bump), so it can be passed anywhere a `&Bump`
+/// is expected.
+pub struct FrameArena {
+    bump: Bump,
+    last_frame_bytes: Cell<usize>,
+    peak_bytes: Cell<usize>,
+}
+
+impl FrameArena {
+    pub fn new() -> FrameArena {
+        FrameArena {
+            bump: Bump::new(),
+            last_frame_bytes: Cell::new(0),
+            peak_bytes: Cell::new(0),
+        }
+    }
+
+    /// Frees everything allocated during the last frame.
+    ///
+    /// The arena keeps its largest chunk of memory, so once it has grown to fit a typical frame,
+    /// later frames don't need to allocate from the heap at all.
+    pub fn reset(&mut self) {
+        let used = self.bump.allocated_bytes();
+        self.last_frame_bytes.set(used);
+        if used > self.peak_bytes.get() {
+            debug!("Frame arena grew to {} bytes", used);
+            self.peak_bytes.set(used);
+        }
+        self.bump.reset();
+    }
+
+    /// Returns the number of bytes the arena held when it was last reset.
+    pub fn last_frame_bytes(&self) -> usize {
+        self.last_frame_bytes.get()
+    }
+
+    /// Returns the largest number of bytes the arena has held at a reset.
+    pub fn peak_bytes(&self) -> usize {
+        self.peak_bytes.get()
+    }
+
+    /// Returns an empty vector which allocates from this arena.
+    pub fn vec<T>(&self) -> BumpVec<T> {
+        BumpVec::new_in(&self.bump)
+    }
+
+    /// Returns an empty vector which allocates from this arena, with space for `capacity` values.
+    pub fn vec_with_capacity<T>(&self, capacity: usize) -> BumpVec<T> {
+        BumpVec::with_capacity_in(capacity, &self.bump)
+    }
+}
+
+impl Deref for FrameArena {
+    type Target = Bump;
+
+    fn deref(&self) -> &Bump {
+        &self.bump
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reset_tracks_usage() {
+        let mut arena = FrameArena::new();
+
+        let mut v = arena.vec_with_capacity(1024);
+        v.extend(0..1024u32);
+        assert_eq!(v.iter().sum::<u32>(), 1023 * 1024 / 2);
+        drop(v);
+
+        arena.reset();
+        let first = arena.last_frame_bytes();
+        assert!(first >= 4096);
+        assert_eq!(arena.peak_bytes(), first);
+
+        // a quieter frame doesn't lower the peak
+        arena.alloc(0u64);
+        arena.reset();
+        assert!(arena.last_frame_bytes() <= first);
+        assert_eq!(arena.peak_bytes(), first);
+    }
+}
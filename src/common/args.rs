@@ -0,0 +1,131 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Quake-style command lines.
+//!
+//! The original engine takes options with a single dash, as in `-game hipnotic`, and console
+//! commands prefixed with a plus, as in `+map e1m1`. A command runs until the next option or
+//! command, so `+bind f "say hi" +map e1m1` is two commands. Options are converted to the
+//! double-dash form so they can be parsed with `structopt` like any other, and the commands are
+//! kept aside to be run once the game has started.
+
+/// A command line split into options and console commands.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommandLine {
+    /// The program name followed by the options, with single dashes converted to double.
+    pub options: Vec<String>,
+
+    /// The console commands, without their leading `+`, in the order given.
+    pub commands: Vec<String>,
+}
+
+// whether `arg` is an option rather than a value, which may be a negative number
+fn is_option(arg: &str) -> bool {
+    let mut chars = arg.chars();
+    chars.next() == Some('-') && chars.next().map_or(false, |c| c.is_ascii_alphabetic())
+}
+
+fn is_command(arg: &str) -> bool {
+    arg.starts_with('+') && arg.len() > 1
+}
+
+// quotes an argument that the console would otherwise split
+fn quote(arg: &str) -> String {
+    if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == ';') {
+        format!("\"{}\"", arg)
+    } else {
+        arg.to_owned()
+    }
+}
+
+/// Splits a command line, including the program name, into options and console commands.
+pub fn split<I>(args: I) -> CommandLine
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter();
+    let mut options: Vec<String> = args.next().into_iter().collect();
+    let mut commands: Vec<String> = Vec::new();
+    let mut in_command = false;
+
+    for arg in args {
+        if is_command(&arg) {
+            commands.push(arg[1..].to_owned());
+            in_command = true;
+        } else if is_option(&arg) {
+            in_command = false;
+            options.push(format!("-{}", arg));
+        } else if arg.starts_with("--") {
+            in_command = false;
+            options.push(arg);
+        } else if in_command {
+            let command = commands.last_mut().unwrap();
+            command.push(' ');
+            command.push_str(&quote(&arg));
+        } else {
+            options.push(arg);
+        }
+    }
+
+    CommandLine { options, commands }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn split_str(args: &[&str]) -> CommandLine {
+        split(args.iter().map(|a| (*a).to_owned()))
+    }
+
+    #[test]
+    fn test_split_options() {
+        let command_line = split_str(&["quake", "-game", "hipnotic", "-width", "800", "--trace"]);
+        assert_eq!(
+            command_line.options,
+            vec!["quake", "--game", "hipnotic", "--width", "800", "--trace"]
+        );
+        assert!(command_line.commands.is_empty());
+    }
+
+    #[test]
+    fn test_split_commands() {
+        let command_line = split_str(&[
+            "quake",
+            "+bind",
+            "f",
+            "say hi",
+            "+sensitivity",
+            "-1.5",
+            "-condebug",
+            "+map",
+            "e1m1",
+        ]);
+        assert_eq!(command_line.options, vec!["quake", "--condebug"]);
+        assert_eq!(
+            command_line.commands,
+            vec!["bind f \"say hi\"", "sensitivity -1.5", "map e1m1"]
+        );
+    }
+
+    #[test]
+    fn test_split_lone_plus() {
+        // a lone `+` isn't a command, so it's left for the option parser to reject
+        let command_line = split_str(&["quake", "+", "-window"]);
+        assert_eq!(command_line.options, vec!["quake", "+", "--window"]);
+    }
+}
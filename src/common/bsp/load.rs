@@ -20,6 +20,7 @@ use std::{
     io::{BufRead, BufReader, Read, Seek, SeekFrom},
     mem::size_of,
     rc::Rc,
+    sync::Arc,
 };
 
 use crate::common::{
@@ -29,6 +30,7 @@ use crate::common::{
         BspRenderNode, BspRenderNodeChild, BspTexInfo, BspTexture, MAX_HULLS, MAX_LIGHTSTYLES,
         MIPLEVELS,
     },
+    limits::{self, LimitError, LoadLimits},
     math::{Axis, Hyperplane},
     model::Model,
     util::read_f32_3,
@@ -44,6 +46,13 @@ use thiserror::Error;
 
 const VERSION: i32 = 29;
 
+// BSP2 files identify themselves with a magic number in place of the version
+const BSP2_VERSION: i32 = i32::from_le_bytes(*b"BSP2");
+const BSP2_RMQ_VERSION: i32 = i32::from_le_bytes(*b"2PSB");
+
+const LIT_MAGIC: &[u8; 4] = b"QLIT";
+const LIT_VERSION: i32 = 1;
+
 pub const MAX_MODELS: usize = 256;
 const MAX_LEAVES: usize = 32767;
 
@@ -78,24 +87,55 @@ const ASCII_SMALL_J: usize = 'j' as usize;
 pub enum BspFileError {
     #[error("I/O error")]
     Io(#[from] std::io::Error),
-    #[error("unsupported BSP format version (expected {}, found {0})", VERSION)]
+    #[error(
+        "unsupported BSP format version (expected {}, BSP2 or 2PSB, found {0})",
+        VERSION
+    )]
     UnsupportedVersion(i32),
     #[error("negative BSP file section offset: {0}")]
     NegativeSectionOffset(i32),
     #[error("negative BSP file section size: {0}")]
     NegativeSectionSize(i32),
     #[error(
-        "invalid BSP file section size: section {section:?} size is {size}, must be multiple of {}",
-        section.element_size(),
+        "invalid BSP file section size: section {section:?} size is {size}, must be multiple of {element_size}"
     )]
     InvalidSectionSize {
         section: BspFileSectionId,
         size: usize,
+        element_size: usize,
+    },
+    #[error(
+        "BSP file section {section:?} (offset {offset}, size {size}) extends past the end of the file ({file_len} bytes)"
+    )]
+    SectionOutOfBounds {
+        section: BspFileSectionId,
+        offset: u64,
+        size: usize,
+        file_len: u64,
     },
+    #[error("invalid {element} index {index} (only {count} present)")]
+    IndexOutOfRange {
+        element: &'static str,
+        index: usize,
+        count: usize,
+    },
+    #[error("limit exceeded: {0}")]
+    Limit(#[from] LimitError),
+    #[error("texture data truncated: {0}")]
+    TruncatedTexture(String),
     #[error("invalid BSP texture frame specifier: {0}")]
     InvalidTextureFrameSpecifier(String),
     #[error("texture has primary animation with 0 frames: {0}")]
     EmptyPrimaryAnimation(String),
+    #[error("invalid .lit file header")]
+    InvalidLitHeader,
+    #[error(
+        "unsupported .lit format version (expected {}, found {0})",
+        LIT_VERSION
+    )]
+    UnsupportedLitVersion(i32),
+    #[error("invalid .lit file size (expected {expected} bytes of light data, found {found})")]
+    InvalidLitSize { expected: usize, found: usize },
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -155,36 +195,122 @@ const EDGELIST_SIZE: usize = 4;
 const MODEL_SIZE: usize = 64;
 const VERTEX_SIZE: usize = 12;
 
-impl BspFileSectionId {
+// BSP2 widens node, leaf, face and edge indices to 32 bits
+const BSP2_RMQ_RENDER_NODE_SIZE: usize = 32;
+const BSP2_RMQ_LEAF_SIZE: usize = 32;
+const BSP2_FACE_SIZE: usize = 28;
+const BSP2_COLLISION_NODE_SIZE: usize = 12;
+const BSP2_FACELIST_SIZE: usize = 4;
+const BSP2_EDGE_SIZE: usize = 8;
+
+// the final BSP2 revision also stores node and leaf bounds as floats
+const BSP2_RENDER_NODE_SIZE: usize = 44;
+const BSP2_LEAF_SIZE: usize = 44;
+
+/// The layout of a BSP file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum BspFormat {
+    /// The original Quake format, version 29.
+    Bsp29,
+
+    /// The first revision of BSP2 ("2PSB"), with 32-bit indices and 16-bit bounds.
+    Bsp2Rmq,
+
+    /// BSP2, with 32-bit indices and floating-point bounds.
+    Bsp2,
+}
+
+impl BspFormat {
+    fn from_version(version: i32) -> Result<BspFormat, BspFileError> {
+        match version {
+            VERSION => Ok(BspFormat::Bsp29),
+            BSP2_RMQ_VERSION => Ok(BspFormat::Bsp2Rmq),
+            BSP2_VERSION => Ok(BspFormat::Bsp2),
+            other => Err(BspFileError::UnsupportedVersion(other)),
+        }
+    }
+
+    fn read_from<R>(reader: &mut R) -> Result<BspFormat, BspFileError>
+    where
+        R: ReadBytesExt,
+    {
+        BspFormat::from_version(reader.read_i32::<LittleEndian>()?)
+    }
+
     // the size on disk of one element of a BSP file section.
-    fn element_size(&self) -> usize {
+    fn element_size(&self, section_id: &BspFileSectionId) -> usize {
         use BspFileSectionId::*;
+        match (section_id, self) {
+            (Entities, _) => size_of::<u8>(),
+            (Planes, _) => PLANE_SIZE,
+            (Textures, _) => size_of::<u8>(),
+            (Vertices, _) => VERTEX_SIZE,
+            (Visibility, _) => size_of::<u8>(),
+            (RenderNodes, BspFormat::Bsp29) => RENDER_NODE_SIZE,
+            (RenderNodes, BspFormat::Bsp2Rmq) => BSP2_RMQ_RENDER_NODE_SIZE,
+            (RenderNodes, BspFormat::Bsp2) => BSP2_RENDER_NODE_SIZE,
+            (TextureInfo, _) => TEXTURE_INFO_SIZE,
+            (Faces, BspFormat::Bsp29) => FACE_SIZE,
+            (Faces, _) => BSP2_FACE_SIZE,
+            (Lightmaps, _) => size_of::<u8>(),
+            (CollisionNodes, BspFormat::Bsp29) => COLLISION_NODE_SIZE,
+            (CollisionNodes, _) => BSP2_COLLISION_NODE_SIZE,
+            (Leaves, BspFormat::Bsp29) => LEAF_SIZE,
+            (Leaves, BspFormat::Bsp2Rmq) => BSP2_RMQ_LEAF_SIZE,
+            (Leaves, BspFormat::Bsp2) => BSP2_LEAF_SIZE,
+            (FaceList, BspFormat::Bsp29) => FACELIST_SIZE,
+            (FaceList, _) => BSP2_FACELIST_SIZE,
+            (Edges, BspFormat::Bsp29) => EDGE_SIZE,
+            (Edges, _) => BSP2_EDGE_SIZE,
+            (EdgeList, _) => EDGELIST_SIZE,
+            (Models, _) => MODEL_SIZE,
+        }
+    }
+
+    // read a signed index, stored as a short in BSP29 and an int in BSP2.
+    fn read_signed<R>(&self, reader: &mut R) -> Result<i32, std::io::Error>
+    where
+        R: ReadBytesExt,
+    {
+        match self {
+            BspFormat::Bsp29 => Ok(reader.read_i16::<LittleEndian>()? as i32),
+            _ => reader.read_i32::<LittleEndian>(),
+        }
+    }
+
+    // read an unsigned index, stored as a short in BSP29 and an int in BSP2.
+    fn read_unsigned<R>(&self, reader: &mut R) -> Result<u32, std::io::Error>
+    where
+        R: ReadBytesExt,
+    {
+        match self {
+            BspFormat::Bsp29 => Ok(reader.read_u16::<LittleEndian>()? as u32),
+            _ => reader.read_u32::<LittleEndian>(),
+        }
+    }
+
+    // read the bounds of a node or leaf, stored as shorts except in the final BSP2 revision.
+    fn read_bounds<R>(&self, reader: &mut R) -> Result<[f32; 3], std::io::Error>
+    where
+        R: ReadBytesExt,
+    {
         match self {
-            Entities => size_of::<u8>(),
-            Planes => PLANE_SIZE,
-            Textures => size_of::<u8>(),
-            Vertices => VERTEX_SIZE,
-            Visibility => size_of::<u8>(),
-            RenderNodes => RENDER_NODE_SIZE,
-            TextureInfo => TEXTURE_INFO_SIZE,
-            Faces => FACE_SIZE,
-            Lightmaps => size_of::<u8>(),
-            CollisionNodes => COLLISION_NODE_SIZE,
-            Leaves => LEAF_SIZE,
-            FaceList => FACELIST_SIZE,
-            Edges => EDGE_SIZE,
-            EdgeList => EDGELIST_SIZE,
-            Models => MODEL_SIZE,
+            BspFormat::Bsp2 => read_f32_3(reader),
+            _ => {
+                let b = read_i16_3(reader)?;
+                Ok([b[0] as f32, b[1] as f32, b[2] as f32])
+            }
         }
     }
 }
 
 struct BspFileTable {
+    format: BspFormat,
     sections: [BspFileSection; SECTION_COUNT],
 }
 
 impl BspFileTable {
-    fn read_from<R>(reader: &mut R) -> Result<BspFileTable, BspFileError>
+    fn read_from<R>(reader: &mut R, format: BspFormat) -> Result<BspFileTable, BspFileError>
     where
         R: ReadBytesExt,
     {
@@ -193,15 +319,41 @@ impl BspFileTable {
         for (id, section) in sections.iter_mut().enumerate() {
             *section = BspFileSection::read_from(reader)?;
             let section_id = BspFileSectionId::from_usize(id).unwrap();
-            if section.size % section_id.element_size() != 0 {
+            let element_size = format.element_size(&section_id);
+            if section.size % element_size != 0 {
                 Err(BspFileError::InvalidSectionSize {
                     section: section_id,
                     size: section.size,
+                    element_size,
                 })?
             }
         }
 
-        Ok(BspFileTable { sections })
+        Ok(BspFileTable { format, sections })
+    }
+
+    // make sure every section lies within the file and within the lump size limit, so that section
+    // sizes can be trusted when allocating space for their contents.
+    fn check_bounds(&self, file_len: u64, limits: &LoadLimits) -> Result<(), BspFileError> {
+        for (id, section) in self.sections.iter().enumerate() {
+            limits.check_lump_size(section.size)?;
+            if section.offset + section.size as u64 > file_len {
+                Err(BspFileError::SectionOutOfBounds {
+                    section: BspFileSectionId::from_usize(id).unwrap(),
+                    offset: section.offset,
+                    size: section.size,
+                    file_len,
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // the number of elements in a BSP file section.
+    fn element_count(&self, section_id: BspFileSectionId) -> usize {
+        let element_size = self.format.element_size(&section_id);
+        self.section(section_id).size / element_size
     }
 
     fn section(&self, section_id: BspFileSectionId) -> BspFileSection {
@@ -227,6 +379,29 @@ impl BspFileTable {
     }
 }
 
+// check that `index` refers to one of `count` elements.
+fn check_index(element: &'static str, index: usize, count: usize) -> Result<usize, BspFileError> {
+    if index < count {
+        Ok(index)
+    } else {
+        Err(BspFileError::IndexOutOfRange {
+            element,
+            index,
+            count,
+        })
+    }
+}
+
+// determine the length of a file, leaving the stream at its start.
+fn stream_len<S>(seeker: &mut S) -> Result<u64, std::io::Error>
+where
+    S: Seek,
+{
+    let len = seeker.seek(SeekFrom::End(0))?;
+    seeker.seek(SeekFrom::Start(0))?;
+    Ok(len)
+}
+
 fn read_hyperplane<R>(reader: &mut R) -> Result<Hyperplane, failure::Error>
 where
     R: ReadBytesExt,
@@ -261,13 +436,14 @@ fn load_texture<R>(
     mut reader: &mut R,
     tex_section_ofs: u64,
     tex_ofs: u64,
+    limits: &LoadLimits,
 ) -> Result<BspFileTexture, failure::Error>
 where
     R: ReadBytesExt + Seek,
 {
     // convert texture name from NUL-terminated to str
     let mut tex_name_bytes = [0u8; TEX_NAME_MAX];
-    reader.read(&mut tex_name_bytes)?;
+    reader.read_exact(&mut tex_name_bytes)?;
     let len = tex_name_bytes
         .iter()
         .enumerate()
@@ -278,6 +454,9 @@ where
 
     let width = reader.read_u32::<LittleEndian>()?;
     let height = reader.read_u32::<LittleEndian>()?;
+    limits
+        .check_texture_size(width, height)
+        .map_err(BspFileError::from)?;
 
     let mut mip_offsets = [0usize; MIPLEVELS];
     for m in 0..MIPLEVELS {
@@ -286,13 +465,17 @@ where
 
     let mut mipmaps = [Vec::new(), Vec::new(), Vec::new(), Vec::new()];
     for m in 0..MIPLEVELS {
-        let factor = 2usize.pow(m as u32);
-        let mipmap_size = (width as usize / factor) * (height as usize / factor);
+        let factor = 2u64.pow(m as u32);
+        let mipmap_size = (width as u64 / factor) * (height as u64 / factor);
         let offset = tex_section_ofs + tex_ofs + mip_offsets[m] as u64;
         reader.seek(SeekFrom::Start(offset))?;
         (&mut reader)
-            .take(mipmap_size as u64)
+            .take(mipmap_size)
             .read_to_end(&mut mipmaps[m])?;
+
+        if mipmaps[m].len() as u64 != mipmap_size {
+            Err(BspFileError::TruncatedTexture(tex_name.clone()))?;
+        }
     }
 
     Ok(BspFileTexture {
@@ -303,7 +486,7 @@ where
     })
 }
 
-fn load_render_node<R>(reader: &mut R) -> Result<BspRenderNode, failure::Error>
+fn load_render_node<R>(reader: &mut R, format: BspFormat) -> Result<BspRenderNode, failure::Error>
 where
     R: ReadBytesExt,
 {
@@ -315,26 +498,26 @@ where
     // If the child ID is positive, it points to another internal node. If it is negative, its
     // bitwise negation points to a leaf node.
 
-    let front = match reader.read_i16::<LittleEndian>()? {
+    let front = match format.read_signed(reader)? {
         f if f < 0 => BspRenderNodeChild::Leaf((!f) as usize),
         f => BspRenderNodeChild::Node(f as usize),
     };
 
-    let back = match reader.read_i16::<LittleEndian>()? {
+    let back = match format.read_signed(reader)? {
         b if b < 0 => BspRenderNodeChild::Leaf((!b) as usize),
         b => BspRenderNodeChild::Node(b as usize),
     };
 
-    let min = read_i16_3(reader)?;
-    let max = read_i16_3(reader)?;
+    let min = format.read_bounds(reader)?;
+    let max = format.read_bounds(reader)?;
 
-    let face_id = reader.read_i16::<LittleEndian>()?;
+    let face_id = format.read_signed(reader)?;
     if face_id < 0 {
         bail!("Invalid face id");
     }
 
-    let face_count = reader.read_u16::<LittleEndian>()?;
-    if face_count as usize > MAX_FACES {
+    let face_count = format.read_unsigned(reader)?;
+    if format == BspFormat::Bsp29 && face_count as usize > MAX_FACES {
         bail!("Invalid face count");
     }
 
@@ -358,7 +541,7 @@ where
     let t_offset = reader.read_f32::<LittleEndian>()?;
 
     let tex_id = match reader.read_i32::<LittleEndian>()? {
-        t if t < 0 || t as usize > texture_count => bail!("Invalid texture ID"),
+        t if t < 0 || t as usize >= texture_count => bail!("Invalid texture ID"),
         t => t as usize,
     };
 
@@ -379,22 +562,117 @@ where
     })
 }
 
+fn read_entity_string<R>(
+    reader: &mut R,
+    table: &BspFileTable,
+    limits: &LoadLimits,
+) -> Result<String, failure::Error>
+where
+    R: BufRead + Seek,
+{
+    let ent_section = table.section(BspFileSectionId::Entities);
+    reader.seek(SeekFrom::Start(ent_section.offset))?;
+    let mut ent_data = Vec::with_capacity(MAX_ENTSTRING);
+    reader.read_until(0x00, &mut ent_data)?;
+    ensure!(
+        ent_data.len() <= MAX_ENTSTRING,
+        "Entity data exceeds MAX_ENTSTRING"
+    );
+    let ent_string =
+        String::from_utf8(ent_data).context("Failed to create string from entity data")?;
+    table.check_end_position(reader, BspFileSectionId::Entities)?;
+    limits
+        .check_entity_count(limits::count_entities(&ent_string))
+        .map_err(BspFileError::from)?;
+
+    Ok(ent_string)
+}
+
+/// Read the colored lightmaps from a `.lit` file.
+///
+/// A `.lit` file consists of the magic number `QLIT`, a 32-bit version number, and an RGB triple
+/// for each byte of the monochrome lightmap data in the BSP file.
+fn read_lit<L>(lit: L, lightmap_len: usize) -> Result<Vec<u8>, BspFileError>
+where
+    L: Read,
+{
+    let mut reader = BufReader::new(lit);
+
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != LIT_MAGIC {
+        Err(BspFileError::InvalidLitHeader)?;
+    }
+
+    match reader.read_i32::<LittleEndian>()? {
+        LIT_VERSION => (),
+        other => Err(BspFileError::UnsupportedLitVersion(other))?,
+    }
+
+    let expected = 3 * lightmap_len;
+    let mut rgb = Vec::with_capacity(expected);
+    reader.read_to_end(&mut rgb)?;
+    if rgb.len() != expected {
+        Err(BspFileError::InvalidLitSize {
+            expected,
+            found: rgb.len(),
+        })?;
+    }
+
+    Ok(rgb)
+}
+
+/// Load only the entity string of a BSP file.
+///
+/// This is much cheaper than a full `load` and is intended for tools which only need map
+/// metadata, such as the worldspawn message.
+pub fn load_entities<R>(data: R) -> Result<String, failure::Error>
+where
+    R: Read + Seek,
+{
+    let mut reader = BufReader::new(data);
+    let file_len = stream_len(&mut reader)?;
+
+    let format = BspFormat::read_from(&mut reader)?;
+    let table = BspFileTable::read_from(&mut reader, format)?;
+    let limits = LoadLimits::default();
+    table.check_bounds(file_len, &limits)?;
+    read_entity_string(&mut reader, &table, &limits)
+}
+
 /// Load a BSP file, returning the models it contains and a `String` describing the entities
 /// it contains.
+///
+/// The file is checked against the default [`LoadLimits`](../limits/struct.LoadLimits.html).
 pub fn load<R>(data: R) -> Result<(Vec<Model>, String), failure::Error>
 where
     R: Read + Seek,
+{
+    load_with_lit(data, None::<&[u8]>, &LoadLimits::default())
+}
+
+/// Load a BSP file along with the colored lightmaps in the corresponding `.lit` file.
+///
+/// If the `.lit` data is invalid or doesn't match the BSP file, a warning is logged and only the
+/// monochrome lightmaps are loaded. Files exceeding `limits` are rejected.
+pub fn load_with_lit<R, L>(
+    data: R,
+    lit: Option<L>,
+    limits: &LoadLimits,
+) -> Result<(Vec<Model>, String), failure::Error>
+where
+    R: Read + Seek,
+    L: Read,
 {
     let mut reader = BufReader::new(data);
+    let file_len = stream_len(&mut reader)?;
 
-    let _version = match reader.read_i32::<LittleEndian>()? {
-        VERSION => Ok(VERSION),
-        other => Err(BspFileError::UnsupportedVersion(other)),
-    }?;
+    let format = BspFormat::read_from(&mut reader)?;
+    debug!("BSP format: {:?}", format);
 
-    let table = BspFileTable::read_from(&mut reader)?;
+    let table = BspFileTable::read_from(&mut reader, format)?;
+    table.check_bounds(file_len, limits)?;
 
-    let ent_section = table.section(BspFileSectionId::Entities);
     let plane_section = table.section(BspFileSectionId::Planes);
     let tex_section = table.section(BspFileSectionId::Textures);
     let vert_section = table.section(BspFileSectionId::Vertices);
@@ -410,58 +688,51 @@ where
     let model_section = table.section(BspFileSectionId::Models);
     let render_node_section = table.section(BspFileSectionId::RenderNodes);
 
-    let plane_count = plane_section.size / PLANE_SIZE;
-    let vert_count = vert_section.size / VERTEX_SIZE;
-    let render_node_count = render_node_section.size / RENDER_NODE_SIZE;
-    let texinfo_count = texinfo_section.size / TEXTURE_INFO_SIZE;
-    let face_count = face_section.size / FACE_SIZE;
-    let collision_node_count = collision_node_section.size / COLLISION_NODE_SIZE;
-    let leaf_count = leaf_section.size / LEAF_SIZE;
-    let facelist_count = facelist_section.size / FACELIST_SIZE;
-    let edge_count = edge_section.size / EDGE_SIZE;
-    let edgelist_count = edgelist_section.size / EDGELIST_SIZE;
-    let model_count = model_section.size / MODEL_SIZE;
-
-    // check limits
-    ensure!(plane_count <= MAX_PLANES, "Plane count exceeds MAX_PLANES");
-    ensure!(
-        vert_count <= MAX_VERTICES,
-        "Vertex count exceeds MAX_VERTICES"
-    );
-    ensure!(
-        vis_section.size <= MAX_VISLIST,
-        "Visibility data size exceeds MAX_VISLIST"
-    );
-    ensure!(
-        render_node_count <= MAX_RENDER_NODES,
-        "Render node count exceeds MAX_RENDER_NODES"
-    );
-    ensure!(
-        collision_node_count <= MAX_COLLISION_NODES,
-        "Collision node count exceeds MAX_COLLISION_NODES"
-    );
-    ensure!(leaf_count <= MAX_LEAVES, "Leaf count exceeds MAX_LEAVES");
-    ensure!(edge_count <= MAX_EDGES, "Edge count exceeds MAX_EDGES");
-    ensure!(
-        edgelist_count <= MAX_EDGELIST,
-        "Edge list count exceeds MAX_EDGELIST"
-    );
+    let plane_count = table.element_count(BspFileSectionId::Planes);
+    let vert_count = table.element_count(BspFileSectionId::Vertices);
+    let render_node_count = table.element_count(BspFileSectionId::RenderNodes);
+    let texinfo_count = table.element_count(BspFileSectionId::TextureInfo);
+    let face_count = table.element_count(BspFileSectionId::Faces);
+    let collision_node_count = table.element_count(BspFileSectionId::CollisionNodes);
+    let leaf_count = table.element_count(BspFileSectionId::Leaves);
+    let facelist_count = table.element_count(BspFileSectionId::FaceList);
+    let edge_count = table.element_count(BspFileSectionId::Edges);
+    let edgelist_count = table.element_count(BspFileSectionId::EdgeList);
+    let model_count = table.element_count(BspFileSectionId::Models);
+
+    // check limits. these follow from the 16-bit indices of BSP29, so BSP2 maps may exceed them.
+    if format == BspFormat::Bsp29 {
+        ensure!(plane_count <= MAX_PLANES, "Plane count exceeds MAX_PLANES");
+        ensure!(
+            vert_count <= MAX_VERTICES,
+            "Vertex count exceeds MAX_VERTICES"
+        );
+        ensure!(
+            vis_section.size <= MAX_VISLIST,
+            "Visibility data size exceeds MAX_VISLIST"
+        );
+        ensure!(
+            render_node_count <= MAX_RENDER_NODES,
+            "Render node count exceeds MAX_RENDER_NODES"
+        );
+        ensure!(
+            collision_node_count <= MAX_COLLISION_NODES,
+            "Collision node count exceeds MAX_COLLISION_NODES"
+        );
+        ensure!(leaf_count <= MAX_LEAVES, "Leaf count exceeds MAX_LEAVES");
+        ensure!(edge_count <= MAX_EDGES, "Edge count exceeds MAX_EDGES");
+        ensure!(
+            edgelist_count <= MAX_EDGELIST,
+            "Edge list count exceeds MAX_EDGELIST"
+        );
+    }
     ensure!(
         model_count > 0,
         "No brush models (need at least 1 for worldmodel)"
     );
     ensure!(model_count <= MAX_MODELS, "Model count exceeds MAX_MODELS");
 
-    reader.seek(SeekFrom::Start(ent_section.offset))?;
-    let mut ent_data = Vec::with_capacity(MAX_ENTSTRING);
-    reader.read_until(0x00, &mut ent_data)?;
-    ensure!(
-        ent_data.len() <= MAX_ENTSTRING,
-        "Entity data exceeds MAX_ENTSTRING"
-    );
-    let ent_string =
-        String::from_utf8(ent_data).context("Failed to create string from entity data")?;
-    table.check_end_position(&mut reader, BspFileSectionId::Entities)?;
+    let ent_string = read_entity_string(&mut reader, &table, limits)?;
 
     // load planes
     reader.seek(SeekFrom::Start(plane_section.offset))?;
@@ -469,7 +740,7 @@ where
     for _ in 0..plane_count {
         planes.push(read_hyperplane(&mut reader)?);
     }
-    let planes_rc = Rc::new(planes.into_boxed_slice());
+    let planes_rc = Arc::new(planes.into_boxed_slice());
 
     table.check_end_position(&mut reader, BspFileSectionId::Planes)?;
 
@@ -477,7 +748,9 @@ where
     reader.seek(SeekFrom::Start(tex_section.offset))?;
     let tex_count = reader.read_i32::<LittleEndian>()?;
     ensure!(
-        tex_count >= 0 && tex_count as usize <= MAX_TEXTURES,
+        tex_count >= 0
+            && tex_count as usize <= MAX_TEXTURES
+            && (tex_count as usize + 1) * size_of::<i32>() <= tex_section.size,
         "Invalid texture count"
     );
     let tex_count = tex_count as usize;
@@ -498,7 +771,8 @@ where
         match tex_ofs {
             Some(ofs) => {
                 reader.seek(SeekFrom::Start(tex_section.offset + ofs as u64))?;
-                let texture = load_texture(&mut reader, tex_section.offset as u64, ofs as u64)?;
+                let texture =
+                    load_texture(&mut reader, tex_section.offset as u64, ofs as u64, limits)?;
                 debug!(
                     "Texture {id:>width$}: {name}",
                     id = id,
@@ -547,7 +821,9 @@ where
         // - stem is the remainder of the string
         match file_texture.name.strip_prefix("+") {
             Some(rest) => {
-                let (frame, stem) = rest.split_at(1);
+                let mut chars = rest.chars();
+                let frame = chars.next();
+                let stem = chars.as_str();
 
                 debug!(
                     "Sequencing texture {}: {}",
@@ -562,10 +838,10 @@ where
                             alternate: Vec::new(),
                         });
 
-                match frame.chars().nth(0).unwrap() {
-                    '0'..='9' => anims.primary.push((file_texture_id, file_texture)),
+                match frame {
+                    Some('0'..='9') => anims.primary.push((file_texture_id, file_texture)),
                     // guaranteed to be lowercase by load_texture
-                    'a'..='j' => anims.alternate.push((file_texture_id, file_texture)),
+                    Some('a'..='j') => anims.alternate.push((file_texture_id, file_texture)),
                     _ => Err(BspFileError::InvalidTextureFrameSpecifier(
                         file_texture.name.clone(),
                     ))?,
@@ -585,10 +861,10 @@ where
                 static_texture_ids.insert(file_texture_id, texture_id);
 
                 textures.push(BspTexture {
-                    name,
+                    name: name.clone(),
                     width,
                     height,
-                    kind: BspTextureKind::Static(BspTextureFrame { mipmaps }),
+                    kind: BspTextureKind::Static(BspTextureFrame { name, mipmaps }),
                 });
             }
         };
@@ -625,6 +901,7 @@ where
             );
             corresponding_file_ids.push(file_id);
             primary.push(BspTextureFrame {
+                name: file_texture.name,
                 mipmaps: file_texture.mipmaps,
             });
         }
@@ -638,6 +915,7 @@ where
                 for (file_id, file_texture) in alt {
                     alt_corresp_file_ids.push(file_id);
                     alternate.push(BspTextureFrame {
+                        name: file_texture.name,
                         mipmaps: file_texture.mipmaps,
                     });
                 }
@@ -675,7 +953,7 @@ where
         } else if let Some(id) = animated_texture_ids.get(&file_texture_id) {
             *id
         } else {
-            panic!(
+            bail!(
                 "Texture sequencing failed: texture with id {} unaccounted for",
                 file_texture_id
             );
@@ -703,7 +981,7 @@ where
     debug!("Render node count = {}", render_node_count);
     let mut render_nodes = Vec::with_capacity(render_node_count);
     for _ in 0..render_node_count {
-        render_nodes.push(load_render_node(&mut reader)?);
+        render_nodes.push(load_render_node(&mut reader, format)?);
     }
     table.check_end_position(&mut reader, BspFileSectionId::RenderNodes)?;
 
@@ -722,12 +1000,12 @@ where
     reader.seek(SeekFrom::Start(face_section.offset))?;
     let mut faces = Vec::with_capacity(face_count);
     for _ in 0..face_count {
-        let plane_id = reader.read_i16::<LittleEndian>()?;
-        if plane_id < 0 || plane_id as usize > plane_count {
-            bail!("Invalid plane count");
+        let plane_id = format.read_signed(&mut reader)?;
+        if plane_id < 0 || plane_id as usize >= plane_count {
+            bail!("Invalid plane ID");
         }
 
-        let side = match reader.read_i16::<LittleEndian>()? {
+        let side = match format.read_signed(&mut reader)? {
             0 => BspFaceSide::Front,
             1 => BspFaceSide::Back,
             _ => bail!("Invalid face side"),
@@ -738,13 +1016,18 @@ where
             bail!("Invalid edge ID");
         }
 
-        let edge_count = reader.read_i16::<LittleEndian>()?;
+        let edge_count = format.read_signed(&mut reader)?;
         if edge_count < 3 {
             bail!("Invalid edge count");
         }
-
-        let texinfo_id = reader.read_i16::<LittleEndian>()?;
-        if texinfo_id < 0 || texinfo_id as usize > texinfo_count {
+        check_index(
+            "edge list",
+            edge_id as usize + edge_count as usize - 1,
+            edgelist_count,
+        )?;
+
+        let texinfo_id = format.read_signed(&mut reader)?;
+        if texinfo_id < 0 || texinfo_id as usize >= texinfo_count {
             bail!("Invalid texinfo ID");
         }
 
@@ -780,29 +1063,46 @@ where
         .read_to_end(&mut lightmaps)?;
     table.check_end_position(&mut reader, BspFileSectionId::Lightmaps)?;
 
+    let colored_lightmaps = match lit.map(|l| read_lit(l, lightmaps.len())) {
+        Some(Ok(rgb)) => Some(rgb.into_boxed_slice()),
+        Some(Err(e)) => {
+            warn!("Ignoring colored lightmaps: {}", e);
+            None
+        }
+        None => None,
+    };
+
     reader.seek(SeekFrom::Start(collision_node_section.offset))?;
 
     let mut collision_nodes = Vec::with_capacity(collision_node_count);
     for _ in 0..collision_node_count {
         let plane_id = match reader.read_i32::<LittleEndian>()? {
             x if x < 0 => bail!("Invalid plane id"),
-            x => x as usize,
+            x => check_index("plane", x as usize, plane_count)?,
         };
 
-        let front = match reader.read_i16::<LittleEndian>()? {
-            x if x < 0 => match BspLeafContents::from_i16(-x) {
+        let front = match format.read_signed(&mut reader)? {
+            x if x < 0 => match BspLeafContents::from_i32(-x) {
                 Some(c) => BspCollisionNodeChild::Contents(c),
                 None => bail!("Invalid leaf contents ({})", -x),
             },
-            x => BspCollisionNodeChild::Node(x as usize),
+            x => BspCollisionNodeChild::Node(check_index(
+                "collision node",
+                x as usize,
+                collision_node_count,
+            )?),
         };
 
-        let back = match reader.read_i16::<LittleEndian>()? {
-            x if x < 0 => match BspLeafContents::from_i16(-x) {
+        let back = match format.read_signed(&mut reader)? {
+            x if x < 0 => match BspLeafContents::from_i32(-x) {
                 Some(c) => BspCollisionNodeChild::Contents(c),
                 None => bail!("Invalid leaf contents ({})", -x),
             },
-            x => BspCollisionNodeChild::Node(x as usize),
+            x => BspCollisionNodeChild::Node(check_index(
+                "collision node",
+                x as usize,
+                collision_node_count,
+            )?),
         };
 
         collision_nodes.push(BspCollisionNode {
@@ -811,7 +1111,7 @@ where
         });
     }
 
-    let collision_nodes_rc = Rc::new(collision_nodes.into_boxed_slice());
+    let collision_nodes_rc = Arc::new(collision_nodes.into_boxed_slice());
 
     let hull_1 = BspCollisionHull {
         planes: planes_rc.clone(),
@@ -865,16 +1165,24 @@ where
         let vis_offset = match reader.read_i32::<LittleEndian>()? {
             x if x < -1 => bail!("Invalid visibility data offset"),
             -1 => None,
-            x => Some(x as usize),
+            x => Some(check_index("visibility data", x as usize, vis_data.len())?),
         };
 
-        let min = read_i16_3(&mut reader)?;
-        let max = read_i16_3(&mut reader)?;
+        let min = format.read_bounds(&mut reader)?;
+        let max = format.read_bounds(&mut reader)?;
+
+        let facelist_id = format.read_unsigned(&mut reader)? as usize;
+        let facelist_count = format.read_unsigned(&mut reader)? as usize;
+        if facelist_count > 0 {
+            check_index(
+                "face list",
+                facelist_id + facelist_count - 1,
+                facelist_section.size / format.element_size(&BspFileSectionId::FaceList),
+            )?;
+        }
 
-        let facelist_id = reader.read_u16::<LittleEndian>()? as usize;
-        let facelist_count = reader.read_u16::<LittleEndian>()? as usize;
         let mut sounds = [0u8; NUM_AMBIENTS];
-        reader.read(&mut sounds)?;
+        reader.read_exact(&mut sounds)?;
         leaves.push(BspLeaf {
             contents,
             vis_offset,
@@ -890,7 +1198,8 @@ where
     reader.seek(SeekFrom::Start(facelist_section.offset))?;
     let mut facelist = Vec::with_capacity(facelist_count);
     for _ in 0..facelist_count {
-        facelist.push(reader.read_u16::<LittleEndian>()? as usize);
+        let face_id = format.read_unsigned(&mut reader)? as usize;
+        facelist.push(check_index("face", face_id, face_count)?);
     }
     if reader.seek(SeekFrom::Current(0))?
         != reader.seek(SeekFrom::Start(
@@ -905,8 +1214,16 @@ where
     for _ in 0..edge_count {
         edges.push(BspEdge {
             vertex_ids: [
-                reader.read_u16::<LittleEndian>()?,
-                reader.read_u16::<LittleEndian>()?,
+                check_index(
+                    "vertex",
+                    format.read_unsigned(&mut reader)? as usize,
+                    vert_count,
+                )? as u32,
+                check_index(
+                    "vertex",
+                    format.read_unsigned(&mut reader)? as usize,
+                    vert_count,
+                )? as u32,
             ],
         });
     }
@@ -918,15 +1235,13 @@ where
         edgelist.push(match reader.read_i32::<LittleEndian>()? {
             x if x >= 0 => BspEdgeIndex {
                 direction: BspEdgeDirection::Forward,
-                index: x as usize,
+                index: check_index("edge", x as usize, edge_count)?,
             },
 
-            x if x < 0 => BspEdgeIndex {
+            x => BspEdgeIndex {
                 direction: BspEdgeDirection::Backward,
-                index: -x as usize,
+                index: check_index("edge", (x as i64).abs() as usize, edge_count)?,
             },
-
-            x => bail!(format!("Invalid edge index {}", x)),
         });
     }
     if reader.seek(SeekFrom::Current(0))?
@@ -964,8 +1279,8 @@ where
         let b_maxs = [(s_max / 16.0).ceil(), (t_max / 16.0).ceil()];
 
         for i in 0..2 {
-            face.texture_mins[i] = b_mins[i] as i16 * 16;
-            face.extents[i] = (b_maxs[i] - b_mins[i]) as i16 * 16;
+            face.texture_mins[i] = (b_mins[i] as i32).saturating_mul(16);
+            face.extents[i] = ((b_maxs[i] - b_mins[i]) as i32).saturating_mul(16);
 
             if !texinfo.special && face.extents[i] > 2000 {
                 bail!(
@@ -976,6 +1291,39 @@ where
                 );
             }
         }
+
+        // make sure the lightmaps for each style are present (see BspData::face_lightmaps)
+        if let Some(lightmap_id) = face.lightmap_id {
+            ensure!(
+                face.extents[0] >= 0 && face.extents[1] >= 0,
+                "Bad face extents: face {} has a lightmap but extents {:?}",
+                face_id,
+                face.extents
+            );
+            let lightmap_size =
+                (face.extents[0] as usize / 16 + 1) * (face.extents[1] as usize / 16 + 1);
+            let style_count = face.light_styles.iter().take_while(|s| **s != 255).count();
+            if style_count > 0 {
+                check_index(
+                    "lightmap data",
+                    lightmap_id + lightmap_size * style_count - 1,
+                    lightmaps.len(),
+                )?;
+            }
+        }
+    }
+
+    for node in render_nodes.iter() {
+        check_index("plane", node.plane_id, plane_count)?;
+        for child in node.children.iter() {
+            match *child {
+                BspRenderNodeChild::Node(n) => check_index("render node", n, render_node_count)?,
+                BspRenderNodeChild::Leaf(l) => check_index("leaf", l, leaf_count)?,
+            };
+        }
+        if node.face_count > 0 {
+            check_index("face", node.face_id + node.face_count - 1, face_count)?;
+        }
     }
 
     // see Mod_MakeHull0,
@@ -1002,7 +1350,7 @@ where
             ],
         })
     }
-    let render_as_collision_nodes_rc = Rc::new(render_as_collision_nodes.into_boxed_slice());
+    let render_as_collision_nodes_rc = Arc::new(render_as_collision_nodes.into_boxed_slice());
 
     let hull_0 = BspCollisionHull {
         planes: planes_rc.clone(),
@@ -1022,6 +1370,7 @@ where
         texinfo: texinfo.into_boxed_slice(),
         faces: faces.into_boxed_slice(),
         lightmaps: lightmaps.into_boxed_slice(),
+        colored_lightmaps,
         hulls: [hull_0, hull_1, hull_2],
         leaves: leaves.into_boxed_slice(),
         facelist: facelist.into_boxed_slice(),
@@ -1047,7 +1396,9 @@ where
         for i in 0..collision_node_ids.len() {
             collision_node_ids[i] = match reader.read_i32::<LittleEndian>()? {
                 r if r < 0 => bail!("Invalid collision tree root node"),
-                r => r as usize,
+                // hull 0 is made from the render nodes
+                r if i == 0 => check_index("render node", r as usize, render_node_count)?,
+                r => check_index("collision node", r as usize, collision_node_count)?,
             };
         }
 
@@ -1066,6 +1417,10 @@ where
         };
 
         total_leaf_count += leaf_count;
+        ensure!(
+            total_leaf_count <= bsp_data.leaves.len(),
+            "Invalid leaf count"
+        );
 
         debug!("model[{}].leaf_count = {:?}", i, leaf_count);
 
@@ -1078,10 +1433,14 @@ where
             x if x < 0 => bail!("Invalid face count"),
             x => x as usize,
         };
+        ensure!(
+            face_id + face_count <= bsp_data.faces.len(),
+            "Invalid face count"
+        );
 
         let mut collision_node_counts = [0; MAX_HULLS];
         for i in 0..collision_node_counts.len() {
-            collision_node_counts[i] = collision_node_count - collision_node_ids[i];
+            collision_node_counts[i] = collision_node_count.saturating_sub(collision_node_ids[i]);
         }
 
         brush_models.push(BspModel {
@@ -1117,3 +1476,132 @@ where
     reader.read_i16_into::<LittleEndian>(&mut ar)?;
     Ok(ar)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lit_file(version: i32, rgb: &[u8]) -> Vec<u8> {
+        let mut lit = LIT_MAGIC.to_vec();
+        lit.extend_from_slice(&version.to_le_bytes());
+        lit.extend_from_slice(rgb);
+        lit
+    }
+
+    #[test]
+    fn test_read_lit() {
+        let rgb = [1, 2, 3, 4, 5, 6];
+        let lit = lit_file(LIT_VERSION, &rgb);
+        assert_eq!(read_lit(&lit[..], 2).unwrap(), rgb.to_vec());
+    }
+
+    #[test]
+    fn test_read_lit_invalid() {
+        let rgb = [1, 2, 3, 4, 5, 6];
+
+        let mut bad_magic = lit_file(LIT_VERSION, &rgb);
+        bad_magic[0] = b'X';
+        match read_lit(&bad_magic[..], 2) {
+            Err(BspFileError::InvalidLitHeader) => (),
+            other => panic!("expected InvalidLitHeader, got {:?}", other),
+        }
+
+        let bad_version = lit_file(2, &rgb);
+        match read_lit(&bad_version[..], 2) {
+            Err(BspFileError::UnsupportedLitVersion(2)) => (),
+            other => panic!("expected UnsupportedLitVersion, got {:?}", other),
+        }
+
+        let short = lit_file(LIT_VERSION, &rgb);
+        match read_lit(&short[..], 3) {
+            Err(BspFileError::InvalidLitSize {
+                expected: 9,
+                found: 6,
+            }) => (),
+            other => panic!("expected InvalidLitSize, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_format_from_version() {
+        assert_eq!(BspFormat::from_version(29).unwrap(), BspFormat::Bsp29);
+        assert_eq!(
+            BspFormat::read_from(&mut &b"BSP2"[..]).unwrap(),
+            BspFormat::Bsp2
+        );
+        assert_eq!(
+            BspFormat::read_from(&mut &b"2PSB"[..]).unwrap(),
+            BspFormat::Bsp2Rmq
+        );
+        match BspFormat::from_version(30) {
+            Err(BspFileError::UnsupportedVersion(30)) => (),
+            other => panic!("expected UnsupportedVersion, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_format_widened_fields() {
+        // a leaf's bounds followed by its first face list entry
+        let mut bsp29 = Vec::new();
+        for b in &[-1i16, 2, 3] {
+            bsp29.extend_from_slice(&b.to_le_bytes());
+        }
+        bsp29.extend_from_slice(&40000u16.to_le_bytes());
+
+        let mut reader = &bsp29[..];
+        assert_eq!(
+            BspFormat::Bsp29.read_bounds(&mut reader).unwrap(),
+            [-1.0, 2.0, 3.0]
+        );
+        assert_eq!(BspFormat::Bsp29.read_unsigned(&mut reader).unwrap(), 40000);
+
+        let mut bsp2 = Vec::new();
+        for b in &[-1.5f32, 2.0, 3.0] {
+            bsp2.extend_from_slice(&b.to_le_bytes());
+        }
+        bsp2.extend_from_slice(&100000u32.to_le_bytes());
+
+        let mut reader = &bsp2[..];
+        assert_eq!(
+            BspFormat::Bsp2.read_bounds(&mut reader).unwrap(),
+            [-1.5, 2.0, 3.0]
+        );
+        assert_eq!(BspFormat::Bsp2.read_unsigned(&mut reader).unwrap(), 100000);
+    }
+
+    #[test]
+    fn test_section_out_of_bounds() {
+        // a header whose entity section claims more data than the file contains
+        let mut bsp = VERSION.to_le_bytes().to_vec();
+        let header_len = (4 + SECTION_COUNT * 8) as i32;
+        bsp.extend_from_slice(&header_len.to_le_bytes());
+        bsp.extend_from_slice(&1024i32.to_le_bytes());
+        for _ in 1..SECTION_COUNT {
+            bsp.extend_from_slice(&header_len.to_le_bytes());
+            bsp.extend_from_slice(&0i32.to_le_bytes());
+        }
+
+        let err = load(std::io::Cursor::new(bsp)).unwrap_err();
+        match err.downcast_ref::<BspFileError>() {
+            Some(BspFileError::SectionOutOfBounds {
+                section: BspFileSectionId::Entities,
+                size: 1024,
+                ..
+            }) => (),
+            other => panic!("expected SectionOutOfBounds, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_check_index() {
+        assert_eq!(check_index("edge", 3, 4).unwrap(), 3);
+        match check_index("edge", 4, 4) {
+            Err(BspFileError::IndexOutOfRange {
+                element: "edge",
+                index: 4,
+                count: 4,
+            }) => (),
+            other => panic!("expected IndexOutOfRange, got {:?}", other),
+        }
+    }
+}
@@ -118,17 +118,17 @@
 
 mod load;
 
-use std::{collections::HashSet, error::Error, fmt, iter::Iterator, rc::Rc};
+use std::{collections::HashSet, error::Error, fmt, iter::Iterator, rc::Rc, sync::Arc};
 
-use crate::common::math::{Hyperplane, HyperplaneSide, LinePlaneIntersect};
+use crate::common::math::{self, Hyperplane, HyperplaneSide, LinePlaneIntersect};
 
 // TODO: Either Trace should be moved into common or the functions requiring it should be moved into server
-use crate::server::world::{Trace, TraceEnd, TraceStart};
+use crate::server::world::{Trace, TraceEnd, TraceFlags, TraceStart};
 
-use cgmath::Vector3;
+use cgmath::{InnerSpace, Vector3};
 use chrono::Duration;
 
-pub use self::load::{load, BspFileError};
+pub use self::load::{load, load_entities, load_with_lit, BspFileError};
 
 // this is 4 in the original source, but the 4th hull is never used.
 const MAX_HULLS: usize = 3;
@@ -139,10 +139,49 @@ pub const MAX_SOUNDS: usize = 4;
 pub const MIPLEVELS: usize = 4;
 const DIST_EPSILON: f32 = 0.03125;
 
+// half the size of the polygons used to extract hull surfaces, larger than any map
+const HULL_POLYGON_EXTENT: f32 = 32768.0;
+
+// how far below a point light_at() looks for a lit surface, as in the original engine
+const LIGHT_SAMPLE_DISTANCE: f32 = 2048.0;
+
+// offsets of the extra points averaged by light_near(), as multiples of the spread
+const LIGHT_SAMPLE_OFFSETS: [[f32; 2]; 4] = [[1.0, 0.0], [-1.0, 0.0], [0.0, 1.0], [0.0, -1.0]];
+
 pub fn frame_duration() -> Duration {
     Duration::milliseconds(200)
 }
 
+/// Returns the brightness of a lightstyle pattern at `time`, in the range [0, 2].
+///
+/// Patterns advance one character every tenth of a second, from `a` (dark) through `m` (normal)
+/// to `z` (double brightness). An empty pattern is always normal brightness.
+pub fn lightstyle_value(pattern: &str, time: Duration) -> f32 {
+    if pattern.is_empty() {
+        return 1.0;
+    }
+
+    let frame = (time.num_milliseconds().max(0) / 100) as usize % pattern.len();
+
+    // 'z' - 'a' = 25, so divide by 12.5 to get range [0, 2]
+    pattern.as_bytes()[frame].saturating_sub(b'a') as f32 / 12.5
+}
+
+/// Returns the average brightness of a lightstyle pattern over its whole cycle.
+///
+/// This is used in place of [`lightstyle_value`] to hold flickering and flashing lights steady.
+pub fn lightstyle_average(pattern: &str) -> f32 {
+    if pattern.is_empty() {
+        return 1.0;
+    }
+
+    let total: f32 = pattern
+        .bytes()
+        .map(|b| b.saturating_sub(b'a') as f32 / 12.5)
+        .sum();
+    total / pattern.len() as f32
+}
+
 #[derive(Debug)]
 pub enum BspError {
     Io(::std::io::Error),
@@ -192,10 +231,18 @@ pub enum BspTextureMipmap {
 
 #[derive(Debug)]
 pub struct BspTextureFrame {
+    name: String,
     mipmaps: [Vec<u8>; MIPLEVELS],
 }
 
 impl BspTextureFrame {
+    /// Returns the name of this frame as stored in the BSP file.
+    ///
+    /// For animated textures this includes the `+` prefix and frame specifier.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn mipmap(&self, level: BspTextureMipmap) -> &[u8] {
         &self.mipmaps[level as usize]
     }
@@ -243,7 +290,7 @@ impl BspTexture {
     }
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 pub enum BspRenderNodeChild {
     Node(usize),
     Leaf(usize),
@@ -253,8 +300,8 @@ pub enum BspRenderNodeChild {
 pub struct BspRenderNode {
     pub plane_id: usize,
     pub children: [BspRenderNodeChild; 2],
-    pub min: [i16; 3],
-    pub max: [i16; 3],
+    pub min: [f32; 3],
+    pub max: [f32; 3],
     pub face_id: usize,
     pub face_count: usize,
 }
@@ -285,8 +332,8 @@ pub struct BspFace {
     pub light_styles: [u8; MAX_LIGHTSTYLES],
     pub lightmap_id: Option<usize>,
 
-    pub texture_mins: [i16; 2],
-    pub extents: [i16; 2],
+    pub texture_mins: [i32; 2],
+    pub extents: [i32; 2],
 }
 
 /// The contents of a leaf in the BSP tree, specifying how it should look and behave.
@@ -355,10 +402,21 @@ pub struct BspCollisionNode {
     children: [BspCollisionNodeChild; 2],
 }
 
+// the state of a hull check in progress
+struct HullCheck {
+    flags: TraceFlags,
+
+    // contents of the last leaf the move entered
+    contents: BspLeafContents,
+
+    // the point, ratio and plane of impact, if the move hit something
+    end: Option<(Vector3<f32>, f32, Hyperplane)>,
+}
+
 #[derive(Debug)]
 pub struct BspCollisionHull {
-    planes: Rc<Box<[Hyperplane]>>,
-    nodes: Rc<Box<[BspCollisionNode]>>,
+    planes: Arc<Box<[Hyperplane]>>,
+    nodes: Arc<Box<[BspCollisionNode]>>,
     node_id: usize,
     node_count: usize,
     mins: Vector3<f32>,
@@ -380,7 +438,7 @@ impl BspCollisionHull {
             mins, maxs
         );
 
-        if mins.x >= maxs.x || mins.y >= maxs.y || mins.z >= maxs.z {
+        if mins.x > maxs.x || mins.y > maxs.y || mins.z > maxs.z {
             return Err(BspError::with_msg("min bound exceeds max bound"));
         }
 
@@ -448,8 +506,8 @@ impl BspCollisionHull {
         });
 
         Ok(BspCollisionHull {
-            planes: Rc::new(planes.into_boxed_slice()),
-            nodes: Rc::new(nodes.into_boxed_slice()),
+            planes: Arc::new(planes.into_boxed_slice()),
+            nodes: Arc::new(nodes.into_boxed_slice()),
             node_id: 0,
             node_count: 6,
             mins,
@@ -581,6 +639,237 @@ impl BspCollisionHull {
         }
     }
 
+    /// Traces a move from `start` to `end`, stopping just short of the first solid surface.
+    ///
+    /// Unlike `trace`, this passes through boundaries between open space and liquids, and
+    /// reports every kind of space the move passed through. This is the collision test used by
+    /// the server, ported from the original engine's `SV_RecursiveHullCheck`.
+    pub fn trace_move(&self, start: Vector3<f32>, end: Vector3<f32>) -> Result<Trace, BspError> {
+        let mut check = HullCheck {
+            flags: TraceFlags {
+                all_solid: true,
+                ..Default::default()
+            },
+            contents: BspLeafContents::Solid,
+            end: None,
+        };
+
+        self.hull_check(
+            &BspCollisionNodeChild::Node(self.node_id),
+            0.0,
+            1.0,
+            start,
+            end,
+            &mut check,
+        )?;
+
+        let trace_end = match check.end {
+            Some((point, ratio, plane)) => TraceEnd::boundary(point, ratio, plane),
+            None => TraceEnd::terminal(end),
+        };
+
+        Ok(Trace::from_hull_check(
+            TraceStart::new(start, 0.0),
+            trace_end,
+            check.contents,
+            check.flags,
+        ))
+    }
+
+    // returns false once the move has hit something
+    fn hull_check(
+        &self,
+        child: &BspCollisionNodeChild,
+        start_ratio: f32,
+        end_ratio: f32,
+        start: Vector3<f32>,
+        end: Vector3<f32>,
+        check: &mut HullCheck,
+    ) -> Result<bool, BspError> {
+        let node = match *child {
+            BspCollisionNodeChild::Contents(c) => {
+                if c == BspLeafContents::Solid {
+                    check.flags.start_solid = true;
+                } else {
+                    check.flags.all_solid = false;
+                    if c == BspLeafContents::Empty {
+                        check.flags.in_open = true;
+                    } else {
+                        check.flags.in_water = true;
+                    }
+                }
+
+                check.contents = c;
+                return Ok(true);
+            }
+
+            BspCollisionNodeChild::Node(n) => &self.nodes[n],
+        };
+
+        let plane = &self.planes[node.plane_id];
+        let start_dist = plane.point_dist(start);
+        let end_dist = plane.point_dist(end);
+
+        if start_dist >= 0.0 && end_dist >= 0.0 {
+            return self.hull_check(&node.children[0], start_ratio, end_ratio, start, end, check);
+        }
+
+        if start_dist < 0.0 && end_dist < 0.0 {
+            return self.hull_check(&node.children[1], start_ratio, end_ratio, start, end, check);
+        }
+
+        // put the crossing point DIST_EPSILON units on the near side of the plane
+        let mut frac = if start_dist < 0.0 {
+            (start_dist + DIST_EPSILON) / (start_dist - end_dist)
+        } else {
+            (start_dist - DIST_EPSILON) / (start_dist - end_dist)
+        };
+        frac = frac.max(0.0).min(1.0);
+
+        let mut mid_ratio = start_ratio + (end_ratio - start_ratio) * frac;
+        let mut mid = start + (end - start) * frac;
+        let side = (start_dist < 0.0) as usize;
+
+        // move up to the plane
+        if !self.hull_check(
+            &node.children[side],
+            start_ratio,
+            mid_ratio,
+            start,
+            mid,
+            check,
+        )? {
+            return Ok(false);
+        }
+
+        // go past the plane if the far side isn't solid
+        if self.child_contents_at_point(&node.children[side ^ 1], mid)? != BspLeafContents::Solid {
+            return self.hull_check(
+                &node.children[side ^ 1],
+                mid_ratio,
+                end_ratio,
+                mid,
+                end,
+                check,
+            );
+        }
+
+        // never got out of the solid area
+        if check.flags.all_solid {
+            return Ok(false);
+        }
+
+        // the far side is solid, so this is the impact point
+        let impact_plane = match side {
+            0 => plane.to_owned(),
+            _ => -plane.to_owned(),
+        };
+
+        // the crossing point can still round into solid space, so back it up until it doesn't
+        while self.contents_at_point(mid)? == BspLeafContents::Solid {
+            frac -= 0.1;
+            if frac < 0.0 {
+                debug!("Hull check backed up past the start of the move");
+                break;
+            }
+
+            mid_ratio = start_ratio + (end_ratio - start_ratio) * frac;
+            mid = start + (end - start) * frac;
+        }
+
+        check.end = Some((mid, mid_ratio, impact_plane));
+        Ok(false)
+    }
+
+    fn child_contents_at_point(
+        &self,
+        child: &BspCollisionNodeChild,
+        point: Vector3<f32>,
+    ) -> Result<BspLeafContents, BspError> {
+        match *child {
+            BspCollisionNodeChild::Contents(c) => Ok(c),
+            BspCollisionNodeChild::Node(n) => self.contents_at_point_node(n, point),
+        }
+    }
+
+    /// Returns the polygons making up the solid surfaces of this hull.
+    ///
+    /// Each polygon lies in a node plane and separates solid space on one side from non-solid
+    /// space on the other, so together they outline exactly what the hull collides with.
+    pub fn surface_polygons(&self) -> Vec<Vec<Vector3<f32>>> {
+        let mut polygons = Vec::new();
+        self.surface_polygons_recursive(self.node_id, &mut Vec::new(), &mut polygons);
+        polygons
+    }
+
+    fn surface_polygons_recursive(
+        &self,
+        node_id: usize,
+        bounds: &mut Vec<Hyperplane>,
+        polygons: &mut Vec<Vec<Vector3<f32>>>,
+    ) {
+        let node = &self.nodes[node_id];
+        let plane = &self.planes[node.plane_id];
+
+        // clip the node plane to the region of space covered by this node
+        let mut polygon = Some(plane.polygon(HULL_POLYGON_EXTENT));
+        for bound in bounds.iter() {
+            polygon = polygon.and_then(|p| math::split_polygon(&p, bound).0);
+        }
+
+        if let Some(polygon) = polygon {
+            let mut front_fragments = Vec::new();
+            self.classify_polygon(&node.children[0], polygon, &mut front_fragments);
+
+            // keep the parts of the plane with solid space on exactly one side
+            for (front, front_contents) in front_fragments {
+                let mut back_fragments = Vec::new();
+                self.classify_polygon(&node.children[1], front, &mut back_fragments);
+
+                for (back, back_contents) in back_fragments {
+                    if (front_contents == BspLeafContents::Solid)
+                        != (back_contents == BspLeafContents::Solid)
+                    {
+                        polygons.push(back);
+                    }
+                }
+            }
+        }
+
+        for (child, bound) in node.children.iter().zip(&[plane.clone(), -plane.clone()]) {
+            if let BspCollisionNodeChild::Node(n) = *child {
+                bounds.push(bound.clone());
+                self.surface_polygons_recursive(n, bounds, polygons);
+                bounds.pop();
+            }
+        }
+    }
+
+    // splits a polygon along the subtree under `child`, collecting each fragment along with the
+    // contents of the leaf it lands in
+    fn classify_polygon(
+        &self,
+        child: &BspCollisionNodeChild,
+        polygon: Vec<Vector3<f32>>,
+        fragments: &mut Vec<(Vec<Vector3<f32>>, BspLeafContents)>,
+    ) {
+        match *child {
+            BspCollisionNodeChild::Contents(c) => fragments.push((polygon, c)),
+            BspCollisionNodeChild::Node(n) => {
+                let node = &self.nodes[n];
+                let (front, back) = math::split_polygon(&polygon, &self.planes[node.plane_id]);
+
+                if let Some(f) = front {
+                    self.classify_polygon(&node.children[0], f, fragments);
+                }
+
+                if let Some(b) = back {
+                    self.classify_polygon(&node.children[1], b, fragments);
+                }
+            }
+        }
+    }
+
     pub fn gen_dot_graph(&self) -> String {
         let mut dot = String::new();
         dot += "digraph hull {\n";
@@ -649,12 +938,19 @@ impl BspCollisionHull {
     }
 }
 
+/// The point where a line segment hits a rendered surface.
+#[derive(Clone, Debug)]
+pub struct SurfaceHit {
+    pub face_id: usize,
+    pub point: Vector3<f32>,
+}
+
 #[derive(Debug)]
 pub struct BspLeaf {
     pub contents: BspLeafContents,
     pub vis_offset: Option<usize>,
-    pub min: [i16; 3],
-    pub max: [i16; 3],
+    pub min: [f32; 3],
+    pub max: [f32; 3],
     pub facelist_id: usize,
     pub facelist_count: usize,
     pub sounds: [u8; MAX_SOUNDS],
@@ -662,7 +958,7 @@ pub struct BspLeaf {
 
 #[derive(Debug)]
 pub struct BspEdge {
-    pub vertex_ids: [u16; 2],
+    pub vertex_ids: [u32; 2],
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -682,6 +978,7 @@ pub struct BspLightmap<'a> {
     width: u32,
     height: u32,
     data: &'a [u8],
+    rgb: Option<&'a [u8]>,
 }
 
 impl<'a> BspLightmap<'a> {
@@ -696,11 +993,16 @@ impl<'a> BspLightmap<'a> {
     pub fn data(&self) -> &[u8] {
         self.data
     }
+
+    /// Returns the colored version of this lightmap as RGB triples, if the map has one.
+    pub fn rgb(&self) -> Option<&[u8]> {
+        self.rgb
+    }
 }
 
 #[derive(Debug)]
 pub struct BspData {
-    pub(crate) planes: Rc<Box<[Hyperplane]>>,
+    pub(crate) planes: Arc<Box<[Hyperplane]>>,
     pub(crate) textures: Box<[BspTexture]>,
     pub(crate) vertices: Box<[Vector3<f32>]>,
     pub(crate) visibility: Box<[u8]>,
@@ -708,6 +1010,7 @@ pub struct BspData {
     pub(crate) texinfo: Box<[BspTexInfo]>,
     pub(crate) faces: Box<[BspFace]>,
     pub(crate) lightmaps: Box<[u8]>,
+    pub(crate) colored_lightmaps: Option<Box<[u8]>>,
     pub(crate) leaves: Box<[BspLeaf]>,
     pub(crate) facelist: Box<[usize]>,
     pub(crate) edges: Box<[BspEdge]>,
@@ -753,6 +1056,16 @@ impl BspData {
         &self.texinfo[self.faces[face_id].texinfo_id]
     }
 
+    /// Returns the unit normal of the front of a face.
+    pub fn face_normal(&self, face_id: usize) -> Vector3<f32> {
+        let face = &self.faces[face_id];
+        let normal = self.planes[face.plane_id].normal_vector();
+        match face.side {
+            BspFaceSide::Front => normal,
+            BspFaceSide::Back => -normal,
+        }
+    }
+
     pub fn face_lightmaps(&self, face_id: usize) -> Vec<BspLightmap> {
         let face = &self.faces[face_id];
         match face.lightmap_id {
@@ -772,6 +1085,10 @@ impl BspData {
                             width: lightmap_w,
                             height: lightmap_h,
                             data: &self.lightmaps[start..end],
+                            rgb: self
+                                .colored_lightmaps
+                                .as_ref()
+                                .map(|rgb| &rgb[3 * start..3 * end]),
                         }
                     })
                     .collect()
@@ -788,6 +1105,11 @@ impl BspData {
         &self.lightmaps
     }
 
+    /// Returns the colored lightmap data loaded from the map's `.lit` file, if there was one.
+    pub fn colored_lightmaps(&self) -> Option<&[u8]> {
+        self.colored_lightmaps.as_deref()
+    }
+
     pub fn leaves(&self) -> &[BspLeaf] {
         &self.leaves
     }
@@ -828,16 +1150,205 @@ impl BspData {
         }
     }
 
+    /// Finds the first face hit by the line segment from `start` to `end`.
+    ///
+    /// This walks the render nodes front to back in the same way as `R_LightPoint` in the
+    /// original engine, so only faces of the world model are considered.
+    pub fn trace_surface(&self, start: Vector3<f32>, end: Vector3<f32>) -> Option<SurfaceHit> {
+        self.trace_surface_node(BspRenderNodeChild::Node(0), start, end)
+    }
+
+    fn trace_surface_node(
+        &self,
+        child: BspRenderNodeChild,
+        start: Vector3<f32>,
+        end: Vector3<f32>,
+    ) -> Option<SurfaceHit> {
+        let node = match child {
+            BspRenderNodeChild::Node(n) => &self.render_nodes[n],
+            BspRenderNodeChild::Leaf(_) => return None,
+        };
+
+        let plane = &self.planes[node.plane_id];
+        let start_side = plane.point_side(start);
+        let end_side = plane.point_side(end);
+
+        if start_side == end_side {
+            return self.trace_surface_node(node.children[start_side as usize], start, end);
+        }
+
+        let start_dist = plane.point_dist(start);
+        let end_dist = plane.point_dist(end);
+        let mid = start + (end - start) * (start_dist / (start_dist - end_dist));
+
+        // check the near side before any faces on this node
+        if let Some(hit) = self.trace_surface_node(node.children[start_side as usize], start, mid) {
+            return Some(hit);
+        }
+
+        for face_id in node.face_id..node.face_id + node.face_count {
+            let face = &self.faces[face_id];
+            let texinfo = &self.texinfo[face.texinfo_id];
+            let s = mid.dot(texinfo.s_vector) + texinfo.s_offset;
+            let t = mid.dot(texinfo.t_vector) + texinfo.t_offset;
+            let ds = s - face.texture_mins[0] as f32;
+            let dt = t - face.texture_mins[1] as f32;
+
+            if ds >= 0.0
+                && dt >= 0.0
+                && ds <= face.extents[0] as f32
+                && dt <= face.extents[1] as f32
+            {
+                return Some(SurfaceHit {
+                    face_id,
+                    point: mid,
+                });
+            }
+        }
+
+        self.trace_surface_node(node.children[end_side as usize], mid, end)
+    }
+
+    /// Samples the world lighting at `point` from the lightmap of the surface below it.
+    ///
+    /// Light is measured on the same scale as the lightmaps, where 1.0 shows a texture at its
+    /// original brightness. Each lightmap is scaled by the value of its style in `style_values`.
+    /// If `colored` is false or the map has no colored lighting, all three components are equal.
+    /// Returns `None` if there is no lit surface within range below the point.
+    pub fn light_at(
+        &self,
+        point: Vector3<f32>,
+        style_values: &[f32],
+        colored: bool,
+    ) -> Option<[f32; 3]> {
+        let hit = self.trace_surface(point, point - Vector3::unit_z() * LIGHT_SAMPLE_DISTANCE)?;
+        let face = &self.faces[hit.face_id];
+        if face.lightmap_id.is_none() {
+            return None;
+        }
+
+        // position within the lightmap, which has one luxel per 16 texels
+        let texinfo = &self.texinfo[face.texinfo_id];
+        let s = hit.point.dot(texinfo.s_vector) + texinfo.s_offset - face.texture_mins[0] as f32;
+        let t = hit.point.dot(texinfo.t_vector) + texinfo.t_offset - face.texture_mins[1] as f32;
+        let w = face.extents[0] as usize / 16 + 1;
+        let h = face.extents[1] as usize / 16 + 1;
+        let x = (s / 16.0).max(0.0).min((w - 1) as f32);
+        let y = (t / 16.0).max(0.0).min((h - 1) as f32);
+        let (x0, y0) = (x as usize, y as usize);
+        let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+        let (fx, fy) = (x.fract(), y.fract());
+
+        // bilinear filter between the four nearest luxels
+        let corners = [
+            (y0 * w + x0, (1.0 - fx) * (1.0 - fy)),
+            (y0 * w + x1, fx * (1.0 - fy)),
+            (y1 * w + x0, (1.0 - fx) * fy),
+            (y1 * w + x1, fx * fy),
+        ];
+
+        let mut light = [0.0; 3];
+        for (lightmap, style) in self
+            .face_lightmaps(hit.face_id)
+            .iter()
+            .zip(face.light_styles.iter())
+        {
+            let style_value = style_values.get(*style as usize).cloned().unwrap_or(1.0);
+
+            for (i, weight) in corners.iter() {
+                let scale = weight * style_value * 2.0 / 255.0;
+                match lightmap.rgb() {
+                    Some(rgb) if colored => {
+                        for c in 0..3 {
+                            light[c] += rgb[3 * i + c] as f32 * scale;
+                        }
+                    }
+
+                    _ => {
+                        for c in 0..3 {
+                            light[c] += lightmap.data()[*i] as f32 * scale;
+                        }
+                    }
+                }
+            }
+        }
+
+        Some(light)
+    }
+
+    /// Returns the first animated light style of the lit surface below `point`.
+    ///
+    /// Style 0 is the map's constant normal lighting and is skipped. Returns `None` if there is
+    /// no lit surface within range below the point or if none of its lightmaps are animated.
+    pub fn light_style_at(&self, point: Vector3<f32>) -> Option<u8> {
+        let hit = self.trace_surface(point, point - Vector3::unit_z() * LIGHT_SAMPLE_DISTANCE)?;
+        let face = &self.faces[hit.face_id];
+        face.lightmap_id?;
+        face.light_styles
+            .iter()
+            .take_while(|s| **s != 255)
+            .find(|s| **s != 0)
+            .cloned()
+    }
+
+    /// Samples the world lighting around `point`, averaging the floor below it and the floor at
+    /// four points `spread` units away horizontally.
+    ///
+    /// This smooths over luxel boundaries and ledges for objects larger than a point, such as
+    /// models. Samples with no lit surface below them are left out of the average. Returns
+    /// `None` if none of the samples hit a lit surface.
+    pub fn light_near(
+        &self,
+        point: Vector3<f32>,
+        spread: f32,
+        style_values: &[f32],
+        colored: bool,
+    ) -> Option<[f32; 3]> {
+        let mut total = self.light_at(point, style_values, colored);
+        if spread <= 0.0 {
+            return total;
+        }
+
+        let mut count = if total.is_some() { 1 } else { 0 };
+        for offset in LIGHT_SAMPLE_OFFSETS.iter() {
+            let sample_point = point + Vector3::new(offset[0], offset[1], 0.0) * spread;
+            if let Some(light) = self.light_at(sample_point, style_values, colored) {
+                let sum = total.get_or_insert([0.0; 3]);
+                for c in 0..3 {
+                    sum[c] += light[c];
+                }
+
+                count += 1;
+            }
+        }
+
+        total.map(|sum| {
+            let n = count as f32;
+            [sum[0] / n, sum[1] / n, sum[2] / n]
+        })
+    }
+
     pub fn get_pvs(&self, leaf_id: usize, leaf_count: usize) -> Vec<usize> {
+        let mut visleaf_list = Vec::new();
+        self.get_pvs_into(leaf_id, leaf_count, &mut visleaf_list);
+        visleaf_list
+    }
+
+    /// Appends the leaves in the PVS of `leaf_id` to `visleaf_list`.
+    ///
+    /// This is `get_pvs()` for callers that supply their own storage, such as a frame arena.
+    pub fn get_pvs_into<E>(&self, leaf_id: usize, leaf_count: usize, visleaf_list: &mut E)
+    where
+        E: Extend<usize>,
+    {
         // leaf 0 is outside the map, everything is visible
         if leaf_id == 0 {
-            return Vec::new();
+            return;
         }
 
         match self.leaves[leaf_id].vis_offset {
             Some(o) => {
                 let mut visleaf = 1;
-                let mut visleaf_list = Vec::new();
                 let mut it = (&self.visibility[o..]).iter();
 
                 while visleaf < leaf_count {
@@ -849,7 +1360,7 @@ impl BspData {
                         bits => {
                             for shift in 0..8 {
                                 if bits & 1 << shift != 0 {
-                                    visleaf_list.push(visleaf);
+                                    visleaf_list.extend(Some(visleaf));
                                 }
 
                                 visleaf += 1;
@@ -857,11 +1368,9 @@ impl BspData {
                         }
                     }
                 }
-
-                visleaf_list
             }
 
-            None => Vec::new(),
+            None => (),
         }
     }
 
@@ -1068,4 +1577,76 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn test_hull_surface_polygons() {
+        let hull =
+            BspCollisionHull::for_bounds(Vector3::zero(), Vector3::new(1.0, 2.0, 3.0)).unwrap();
+        let polygons = hull.surface_polygons();
+
+        // one quad per face of the box, with every vertex on a corner
+        assert_eq!(polygons.len(), 6);
+        for polygon in polygons {
+            assert_eq!(polygon.len(), 4);
+            for v in polygon {
+                for (c, max) in [v.x, v.y, v.z].iter().zip(&[1.0, 2.0, 3.0]) {
+                    assert!(c.abs() < 0.001 || (c - max).abs() < 0.001);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_hull_trace_move() {
+        let hull =
+            BspCollisionHull::for_bounds(Vector3::zero(), Vector3::new(1.0, 1.0, 1.0)).unwrap();
+
+        // a move that misses the box covers the whole distance
+        let trace = hull
+            .trace_move(Vector3::new(-1.0, 2.0, 0.5), Vector3::new(2.0, 2.0, 0.5))
+            .unwrap();
+        assert_eq!(trace.ratio(), 1.0);
+        assert!(trace.in_open());
+        assert!(!trace.start_solid());
+
+        // a move into the box stops just short of its surface
+        let trace = hull
+            .trace_move(Vector3::new(-1.0, 0.5, 0.5), Vector3::new(2.0, 0.5, 0.5))
+            .unwrap();
+        assert!(trace.ratio() < 1.0 / 3.0);
+        assert!(trace.end_point().x < 0.0 && trace.end_point().x > -0.1);
+        assert_eq!(
+            trace.plane().unwrap().normal_vector(),
+            Vector3::new(-1.0, 0.0, 0.0)
+        );
+
+        // a move starting inside the box is flagged
+        let trace = hull
+            .trace_move(Vector3::new(0.5, 0.5, 0.5), Vector3::new(2.0, 0.5, 0.5))
+            .unwrap();
+        assert!(trace.start_solid());
+        assert!(!trace.all_solid());
+
+        let trace = hull
+            .trace_move(Vector3::new(0.25, 0.5, 0.5), Vector3::new(0.75, 0.5, 0.5))
+            .unwrap();
+        assert!(trace.all_solid());
+    }
+
+    #[test]
+    fn test_lightstyle_value() {
+        assert_eq!(lightstyle_value("", Duration::seconds(5)), 1.0);
+        assert_eq!(lightstyle_value("m", Duration::seconds(5)), 0.96);
+        assert_eq!(lightstyle_value("az", Duration::zero()), 0.0);
+        assert_eq!(lightstyle_value("az", Duration::milliseconds(150)), 2.0);
+        assert_eq!(lightstyle_value("az", Duration::milliseconds(250)), 0.0);
+    }
+
+    #[test]
+    fn test_lightstyle_average() {
+        assert_eq!(lightstyle_average(""), 1.0);
+        assert_eq!(lightstyle_average("m"), 0.96);
+        assert_eq!(lightstyle_average("az"), 1.0);
+        assert_eq!(lightstyle_average("aaaz"), 0.5);
+    }
 }
@@ -0,0 +1,233 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tab completion for console input.
+//!
+//! The first word of a line is completed from command, cvar and alias names. Later words are
+//! completed from the candidates supplied by the command's completer, if it has one. When more
+//! than one candidate matches, the word is extended to their longest common prefix, and further
+//! presses of Tab cycle through the matches in order.
+
+use crate::common::vfs::Vfs;
+
+/// Returns the sorted, deduplicated candidates that begin with `prefix`.
+///
+/// Matching ignores ASCII case, as command and cvar names do.
+pub fn matches<I, S>(prefix: &str, candidates: I) -> Vec<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let prefix = prefix.to_ascii_lowercase();
+    let mut found: Vec<String> = candidates
+        .into_iter()
+        .filter(|c| c.as_ref().to_ascii_lowercase().starts_with(&prefix))
+        .map(|c| c.as_ref().to_owned())
+        .collect();
+    found.sort();
+    found.dedup();
+    found
+}
+
+/// Returns the longest prefix shared by all of `words`.
+pub fn common_prefix<S>(words: &[S]) -> String
+where
+    S: AsRef<str>,
+{
+    let first = match words.first() {
+        Some(f) => f.as_ref(),
+        None => return String::new(),
+    };
+
+    let mut len = first.len();
+    for word in words[1..].iter() {
+        len = first
+            .char_indices()
+            .zip(word.as_ref().chars())
+            .take_while(|((i, a), b)| *i < len && a.eq_ignore_ascii_case(b))
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0);
+    }
+
+    first[..len].to_owned()
+}
+
+/// Splits a line into the text before the word being completed and the word itself.
+///
+/// If the line ends with whitespace, the word is empty.
+pub fn split_last_word(line: &str) -> (&str, &str) {
+    match line.rfind(char::is_whitespace) {
+        Some(i) => {
+            let split = i + line[i..].chars().next().unwrap().len_utf8();
+            (&line[..split], &line[split..])
+        }
+        None => ("", line),
+    }
+}
+
+/// Lists the files in a VFS directory with the given extension.
+///
+/// The returned paths are relative to `dir`, which makes this suitable for completing arguments
+/// like `map <name>` that are looked up in a fixed directory. The extension is stripped if
+/// `strip_extension` is set.
+pub fn files_with_extension(
+    vfs: &Vfs,
+    dir: &str,
+    extension: &str,
+    strip_extension: bool,
+) -> Vec<String> {
+    let dir = dir.trim_end_matches('/');
+    let suffix = format!(".{}", extension);
+
+    vfs.list(dir)
+        .into_iter()
+        .filter_map(|path| {
+            let name = if dir.is_empty() {
+                path.as_str()
+            } else {
+                path.get(dir.len() + 1..)?
+            };
+
+            if !name.to_ascii_lowercase().ends_with(&suffix) {
+                return None;
+            }
+
+            Some(match strip_extension {
+                true => name[..name.len() - suffix.len()].to_owned(),
+                false => name.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// An in-progress completion that further presses of Tab cycle through.
+#[derive(Debug)]
+pub struct Completion {
+    // the input before the word being completed
+    head: String,
+    matches: Vec<String>,
+
+    // index of the match currently shown, or None if the common prefix is shown
+    current: Option<usize>,
+}
+
+impl Completion {
+    /// Begins completing the last word of `line` from `candidates`.
+    ///
+    /// Returns the completed line and, if more than one candidate matched, a `Completion` to
+    /// cycle through them. Returns `None` if nothing matched.
+    pub fn start<I, S>(line: &str, candidates: I) -> Option<(String, Option<Completion>)>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let (head, word) = split_last_word(line);
+        let found = matches(word, candidates);
+
+        match found.len() {
+            0 => None,
+
+            // a unique match is complete, so move on to the next word
+            1 => Some((format!("{}{} ", head, found[0]), None)),
+
+            _ => {
+                let line = format!("{}{}", head, common_prefix(&found));
+                Some((
+                    line,
+                    Some(Completion {
+                        head: head.to_owned(),
+                        matches: found,
+                        current: None,
+                    }),
+                ))
+            }
+        }
+    }
+
+    /// Returns the candidates that matched.
+    pub fn matches(&self) -> &[String] {
+        &self.matches
+    }
+
+    /// Advances to the next match and returns the completed line.
+    pub fn cycle(&mut self) -> String {
+        let next = match self.current {
+            Some(i) => (i + 1) % self.matches.len(),
+            None => 0,
+        };
+        self.current = Some(next);
+
+        format!("{}{}", self.head, self.matches[next])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let candidates = ["cl_forwardspeed", "cl_backspeed", "color", "CL_Bob", "echo"];
+        assert_eq!(
+            matches("cl_", &candidates),
+            vec!["CL_Bob", "cl_backspeed", "cl_forwardspeed"]
+        );
+        assert_eq!(matches("x", &candidates), Vec::<String>::new());
+        assert_eq!(matches("", &["b", "a", "a"]), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_common_prefix() {
+        assert_eq!(common_prefix(&["cl_forwardspeed", "cl_backspeed"]), "cl_");
+        assert_eq!(common_prefix(&["sizeup", "sizedown", "size"]), "size");
+        assert_eq!(common_prefix(&["echo"]), "echo");
+        assert_eq!(common_prefix(&["abc", "xyz"]), "");
+        assert_eq!(common_prefix::<&str>(&[]), "");
+    }
+
+    #[test]
+    fn test_split_last_word() {
+        assert_eq!(split_last_word("map e1"), ("map ", "e1"));
+        assert_eq!(split_last_word("map "), ("map ", ""));
+        assert_eq!(split_last_word("ma"), ("", "ma"));
+    }
+
+    #[test]
+    fn test_completion_unique() {
+        let (line, completion) = Completion::start("ex", &["exec", "echo"]).unwrap();
+        assert_eq!(line, "exec ");
+        assert!(completion.is_none());
+        assert!(Completion::start("zz", &["exec"]).is_none());
+    }
+
+    #[test]
+    fn test_completion_cycle() {
+        let (line, completion) =
+            Completion::start("map e1", &["e1m1", "e1m2", "e2m1", "start"]).unwrap();
+        assert_eq!(line, "map e1m");
+
+        let mut completion = completion.unwrap();
+        assert_eq!(completion.matches(), &["e1m1", "e1m2"]);
+        assert_eq!(completion.cycle(), "map e1m1");
+        assert_eq!(completion.cycle(), "map e1m2");
+        assert_eq!(completion.cycle(), "map e1m1");
+    }
+}
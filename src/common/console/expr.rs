@@ -0,0 +1,419 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Cvar substitution and arithmetic in console arguments.
+//!
+//! Before a command is run, each `$name` in its arguments is replaced with the value of the cvar
+//! `name`. A run of arguments enclosed in parentheses is then evaluated as an expression and
+//! replaced with the result, so that e.g. `set x ($y + 10)` sets `x` to ten more than `y`. Quoted
+//! arguments and text that doesn't parse as an expression are left as they are.
+//!
+//! Expressions support `+`, `-`, `*`, `/` and `%` with the usual precedence, unary negation and
+//! nested parentheses. Operands that aren't numbers are strings, and `+` concatenates if either
+//! operand is a string.
+
+use std::fmt;
+
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ExprError {
+    #[error("Unbalanced parentheses")]
+    UnbalancedParens,
+    #[error("Expected a value")]
+    ExpectedValue,
+    #[error("Unexpected \"{0}\"")]
+    Unexpected(String),
+    #[error("Can't apply \"{op}\" to non-number \"{value}\"")]
+    NotANumber { op: char, value: String },
+}
+
+/// The result of evaluating an expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Number(f32),
+    String(String),
+}
+
+impl Value {
+    fn from_atom(atom: &str) -> Value {
+        match atom.parse() {
+            Ok(n) => Value::Number(n),
+            Err(_) => Value::String(atom.to_owned()),
+        }
+    }
+
+    fn number(&self, op: char) -> Result<f32, ExprError> {
+        match self {
+            Value::Number(n) => Ok(*n),
+            Value::String(s) => Err(ExprError::NotANumber {
+                op,
+                value: s.to_owned(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Op(char),
+    Atom(String),
+}
+
+const OPERATORS: &str = "+-*/%";
+
+// split arguments into tokens. anything between operators is an operand, so quoted arguments
+// containing spaces remain a single string.
+fn lex<S>(args: &[S]) -> Vec<Token>
+where
+    S: AsRef<str>,
+{
+    let mut tokens = Vec::new();
+
+    for arg in args {
+        let mut atom = String::new();
+        for c in arg.as_ref().chars() {
+            let token = match c {
+                '(' => Token::Open,
+                ')' => Token::Close,
+                c if OPERATORS.contains(c) => Token::Op(c),
+                c => {
+                    atom.push(c);
+                    continue;
+                }
+            };
+
+            if !atom.trim().is_empty() {
+                tokens.push(Token::Atom(atom.trim().to_owned()));
+            }
+            atom.clear();
+            tokens.push(token);
+        }
+
+        if !atom.trim().is_empty() {
+            tokens.push(Token::Atom(atom.trim().to_owned()));
+        }
+    }
+
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    // expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Result<Value, ExprError> {
+        let mut lhs = self.term()?;
+
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            if op != '+' && op != '-' {
+                break;
+            }
+            self.pos += 1;
+
+            let rhs = self.term()?;
+            lhs = match (op, lhs, rhs) {
+                ('+', Value::Number(l), Value::Number(r)) => Value::Number(l + r),
+                ('+', l, r) => Value::String(format!("{}{}", l, r)),
+                (_, l, r) => Value::Number(l.number(op)? - r.number(op)?),
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    // term := unary (('*' | '/' | '%') unary)*
+    fn term(&mut self) -> Result<Value, ExprError> {
+        let mut lhs = self.unary()?;
+
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            if op != '*' && op != '/' && op != '%' {
+                break;
+            }
+            self.pos += 1;
+
+            let l = lhs.number(op)?;
+            let r = self.unary()?.number(op)?;
+            lhs = Value::Number(match op {
+                '*' => l * r,
+                '/' => l / r,
+                _ => l % r,
+            });
+        }
+
+        Ok(lhs)
+    }
+
+    // unary := '-' unary | primary
+    fn unary(&mut self) -> Result<Value, ExprError> {
+        if let Some(Token::Op('-')) = self.peek() {
+            self.pos += 1;
+            return Ok(Value::Number(-self.unary()?.number('-')?));
+        }
+
+        self.primary()
+    }
+
+    // primary := atom | '(' expr ')'
+    fn primary(&mut self) -> Result<Value, ExprError> {
+        match self.next() {
+            Some(Token::Atom(a)) => Ok(Value::from_atom(&a)),
+            Some(Token::Open) => {
+                let value = self.expr()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(value),
+                    _ => Err(ExprError::UnbalancedParens),
+                }
+            }
+            Some(Token::Close) => Err(ExprError::UnbalancedParens),
+            Some(Token::Op(op)) => Err(ExprError::Unexpected(op.to_string())),
+            None => Err(ExprError::ExpectedValue),
+        }
+    }
+}
+
+/// Evaluates the expression formed by a sequence of arguments.
+pub fn evaluate<S>(args: &[S]) -> Result<Value, ExprError>
+where
+    S: AsRef<str>,
+{
+    let mut parser = Parser {
+        tokens: lex(args),
+        pos: 0,
+    };
+
+    let value = parser.expr()?;
+    match parser.next() {
+        None => Ok(value),
+        Some(Token::Close) => Err(ExprError::UnbalancedParens),
+        Some(Token::Open) => Err(ExprError::Unexpected("(".to_owned())),
+        Some(Token::Op(op)) => Err(ExprError::Unexpected(op.to_string())),
+        Some(Token::Atom(a)) => Err(ExprError::Unexpected(a)),
+    }
+}
+
+/// Replaces each `$name` in `arg` with the value of the cvar `name`.
+///
+/// `lookup` returns the value of a cvar, or `None` if it doesn't exist, in which case the
+/// reference is left as-is. `$$` produces a literal `$`.
+pub fn expand_cvars<F>(arg: &str, lookup: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut expanded = String::with_capacity(arg.len());
+    let mut rest = arg;
+
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        if rest.starts_with('$') {
+            expanded.push('$');
+            rest = &rest[1..];
+            continue;
+        }
+
+        let name_len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let name = &rest[..name_len];
+
+        match lookup(name) {
+            Some(value) if !name.is_empty() => expanded.push_str(&value),
+            _ => {
+                expanded.push('$');
+                expanded.push_str(name);
+            }
+        }
+
+        rest = &rest[name_len..];
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Evaluates each parenthesized run of arguments and replaces it with its value.
+///
+/// A run begins with an unquoted argument starting with `(` and ends with the argument that
+/// closes it. `quoted[i]` is `true` if `args[i]` was quoted in the source text. If a run is
+/// unbalanced or fails to evaluate, its arguments are passed through unchanged.
+pub fn evaluate_args<S>(args: &[S], quoted: &[bool]) -> Vec<String>
+where
+    S: AsRef<str>,
+{
+    let mut evaluated = Vec::with_capacity(args.len());
+
+    let mut i = 0;
+    while i < args.len() {
+        if quoted[i] || !args[i].as_ref().starts_with('(') {
+            evaluated.push(args[i].as_ref().to_owned());
+            i += 1;
+            continue;
+        }
+
+        // find the argument that balances the opening parenthesis
+        let mut depth = 0;
+        let mut end = None;
+        for (j, arg) in args.iter().enumerate().skip(i) {
+            for c in arg.as_ref().chars() {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => (),
+                }
+            }
+
+            if depth <= 0 {
+                end = Some(j);
+                break;
+            }
+        }
+
+        let end = match end {
+            Some(e) => e,
+            None => {
+                evaluated.push(args[i].as_ref().to_owned());
+                i += 1;
+                continue;
+            }
+        };
+
+        match evaluate(&args[i..=end]) {
+            Ok(value) => evaluated.push(value.to_string()),
+            Err(e) => {
+                debug!("Not evaluating {:?}: {}", args[i].as_ref(), e);
+                evaluated.extend(args[i..=end].iter().map(|a| a.as_ref().to_owned()));
+            }
+        }
+        i = end + 1;
+    }
+
+    evaluated
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_evaluate_arithmetic() {
+        assert_eq!(evaluate(&["1 + 2 * 3"]), Ok(Value::Number(7.0)));
+        assert_eq!(evaluate(&["(1 + 2) * 3"]), Ok(Value::Number(9.0)));
+        assert_eq!(
+            evaluate(&["-", "(4", "-", "6)", "%", "3"]),
+            Ok(Value::Number(2.0))
+        );
+        assert_eq!(evaluate(&["10 / 4"]), Ok(Value::Number(2.5)));
+    }
+
+    #[test]
+    fn test_evaluate_concatenation() {
+        assert_eq!(
+            evaluate(&["hello world", "+", "1"]),
+            Ok(Value::String("hello world1".to_owned()))
+        );
+        assert_eq!(
+            evaluate(&["abc", "*", "2"]),
+            Err(ExprError::NotANumber {
+                op: '*',
+                value: "abc".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn test_evaluate_errors() {
+        assert_eq!(evaluate(&["(1 + 2"]), Err(ExprError::UnbalancedParens));
+        assert_eq!(evaluate(&["1 +"]), Err(ExprError::ExpectedValue));
+        assert_eq!(evaluate(&["1 2"]), Ok(Value::String("1 2".to_owned())));
+    }
+
+    #[test]
+    fn test_expand_cvars() {
+        let lookup = |name: &str| match name {
+            "y" => Some("5".to_owned()),
+            "name" => Some("player".to_owned()),
+            _ => None,
+        };
+
+        assert_eq!(expand_cvars("($y+10)", lookup), "(5+10)");
+        assert_eq!(expand_cvars("hi $name!", lookup), "hi player!");
+        assert_eq!(expand_cvars("$missing $", lookup), "$missing $");
+        assert_eq!(expand_cvars("$$y", lookup), "$y");
+    }
+
+    #[test]
+    fn test_evaluate_args() {
+        let args = ["set", "x", "(5", "+", "10)"];
+        assert_eq!(
+            evaluate_args(&args, &[false; 5]),
+            vec!["set".to_owned(), "x".to_owned(), "15".to_owned()]
+        );
+
+        let args = ["echo", "(a", "+", "(1", "+", "2))", "done"];
+        assert_eq!(
+            evaluate_args(&args, &[false; 7]),
+            vec!["echo".to_owned(), "a3".to_owned(), "done".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_evaluate_args_literal() {
+        // quoted arguments are never evaluated
+        let args = ["say", "(1 + 2)"];
+        assert_eq!(
+            evaluate_args(&args, &[false, true]),
+            vec!["say".to_owned(), "(1 + 2)".to_owned()]
+        );
+
+        // unbalanced or invalid expressions are passed through
+        let args = ["say", "(1", "+"];
+        assert_eq!(evaluate_args(&args, &[false; 3]), args.to_vec());
+        let args = ["say", "(hi", "*", "2)", ":)"];
+        assert_eq!(evaluate_args(&args, &[false; 5]), args.to_vec());
+    }
+}
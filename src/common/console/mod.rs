@@ -18,36 +18,60 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+mod complete;
 mod error;
-pub use self::error::{ConsoleError, ConsoleErrorKind};
+mod expr;
+mod print;
+pub use self::{
+    complete::files_with_extension,
+    error::{ConsoleError, ConsoleErrorKind},
+    print::{colored, PrintFilter, PrintLevel},
+};
 
 use std::{
     cell::{Ref, RefCell},
     collections::{HashMap, VecDeque},
+    fs,
+    io::{self, BufRead, Write},
     iter::FromIterator,
-    rc::Rc,
+    path::Path,
+    rc::{Rc, Weak},
 };
 
 use crate::common::parse;
 
+use chrono::{DateTime, Utc};
 use failure::{Error, ResultExt};
 
 /// Stores console commands.
 pub struct CmdRegistry {
     cmds: HashMap<String, Box<dyn Fn(&[&str])>>,
+
+    // descriptions shown by `help` and `find`, by command name
+    help: HashMap<String, String>,
+
+    // argument candidates for tab completion, by command name
+    completers: HashMap<String, Box<dyn Fn() -> Vec<String>>>,
 }
 
 impl CmdRegistry {
     pub fn new() -> CmdRegistry {
         CmdRegistry {
             cmds: HashMap::new(),
+            help: HashMap::new(),
+            completers: HashMap::new(),
         }
     }
 
-    /// Registers a new command with the given name.
+    /// Registers a new command with the given name and a short description of what it does.
     ///
     /// Returns an error if a command with the specified name already exists.
-    pub fn insert<S>(&mut self, name: S, cmd: Box<dyn Fn(&[&str])>) -> Result<(), ConsoleError>
+    pub fn insert<S>(
+        &mut self,
+        name: S,
+        help: &str,
+        cmd: Box<dyn Fn(&[&str])>,
+    ) -> Result<(), ConsoleError>
     where
         S: AsRef<str>,
     {
@@ -55,20 +79,20 @@ impl CmdRegistry {
             Some(_) => Err(ConsoleErrorKind::DuplicateCommand {
                 name: name.as_ref().to_string(),
             })?,
-            None => {
-                self.cmds.insert(name.as_ref().to_owned(), cmd);
-            }
+            None => self.insert_or_replace(name, help, cmd),
         }
 
         Ok(())
     }
 
-    /// Registers a new command with the given name, or replaces one if the name is in use.
-    pub fn insert_or_replace<S>(&mut self, name: S, cmd: Box<dyn Fn(&[&str])>)
+    /// Registers a new command with the given name and description, or replaces one if the name
+    /// is in use.
+    pub fn insert_or_replace<S>(&mut self, name: S, help: &str, cmd: Box<dyn Fn(&[&str])>)
     where
         S: AsRef<str>,
     {
         self.cmds.insert(name.as_ref().to_owned(), cmd);
+        self.help.insert(name.as_ref().to_owned(), help.to_owned());
     }
 
     /// Removes the command with the given name.
@@ -79,7 +103,10 @@ impl CmdRegistry {
         S: AsRef<str>,
     {
         match self.cmds.remove(name.as_ref()) {
-            Some(_) => Ok(()),
+            Some(_) => {
+                self.help.remove(name.as_ref());
+                Ok(())
+            }
             None => Err(ConsoleErrorKind::NoSuchCommand {
                 name: name.as_ref().to_owned(),
             })?,
@@ -89,7 +116,7 @@ impl CmdRegistry {
     /// Executes a command.
     ///
     /// Returns an error if no command with the specified name exists.
-    pub fn exec<S>(&mut self, name: S, args: &[&str]) -> Result<(), ConsoleError>
+    pub fn exec<S>(&self, name: S, args: &[&str]) -> Result<(), ConsoleError>
     where
         S: AsRef<str>,
     {
@@ -109,6 +136,46 @@ impl CmdRegistry {
     {
         self.cmds.contains_key(name.as_ref())
     }
+
+    /// Returns the names of all registered commands.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.cmds.keys().map(|k| k.as_str())
+    }
+
+    /// Returns the description the command was registered with.
+    pub fn help<S>(&self, name: S) -> Result<&str, ConsoleError>
+    where
+        S: AsRef<str>,
+    {
+        match self.help.get(name.as_ref()) {
+            Some(help) => Ok(help),
+            None => Err(ConsoleErrorKind::NoSuchCommand {
+                name: name.as_ref().to_owned(),
+            })?,
+        }
+    }
+
+    /// Sets the function that lists tab completion candidates for the arguments of a command.
+    ///
+    /// Completers are kept separately from commands, so they survive a command being removed and
+    /// registered again.
+    pub fn insert_completer<S>(&mut self, name: S, completer: Box<dyn Fn() -> Vec<String>>)
+    where
+        S: AsRef<str>,
+    {
+        self.completers.insert(name.as_ref().to_owned(), completer);
+    }
+
+    /// Returns the tab completion candidates for the arguments of a command.
+    pub fn arg_candidates<S>(&self, name: S) -> Vec<String>
+    where
+        S: AsRef<str>,
+    {
+        match self.completers.get(name.as_ref()) {
+            Some(completer) => completer(),
+            None => Vec::new(),
+        }
+    }
 }
 
 /// A configuration variable.
@@ -128,6 +195,9 @@ struct Cvar {
 
     // The default value of this variable
     default: String,
+
+    // A short description shown by `help` and `find`
+    help: String,
 }
 
 pub struct CvarRegistry {
@@ -146,6 +216,7 @@ impl CvarRegistry {
         &self,
         name: S,
         default: S,
+        help: &str,
         archive: bool,
         notify: bool,
     ) -> Result<(), ConsoleError>
@@ -168,6 +239,7 @@ impl CvarRegistry {
                         archive,
                         notify,
                         default: default.to_owned(),
+                        help: help.to_owned(),
                     },
                 );
             }
@@ -176,23 +248,23 @@ impl CvarRegistry {
         Ok(())
     }
 
-    /// Register a new `Cvar` with the given name.
-    pub fn register<S>(&self, name: S, default: S) -> Result<(), ConsoleError>
+    /// Register a new `Cvar` with the given name, default value and description.
+    pub fn register<S>(&self, name: S, default: S, help: &str) -> Result<(), ConsoleError>
     where
         S: AsRef<str>,
     {
-        self.register_impl(name, default, false, false)
+        self.register_impl(name, default, help, false, false)
     }
 
     /// Register a new archived `Cvar` with the given name.
     ///
     /// The value of this `Cvar` should be written to `vars.rc` whenever the game is closed or
     /// `host_writeconfig` is issued.
-    pub fn register_archive<S>(&self, name: S, default: S) -> Result<(), ConsoleError>
+    pub fn register_archive<S>(&self, name: S, default: S, help: &str) -> Result<(), ConsoleError>
     where
         S: AsRef<str>,
     {
-        self.register_impl(name, default, true, false)
+        self.register_impl(name, default, help, true, false)
     }
 
     /// Register a new notify `Cvar` with the given name.
@@ -200,11 +272,11 @@ impl CvarRegistry {
     /// When this `Cvar` is set:
     /// - If the host is a server, broadcast that the variable has been changed to all clients.
     /// - If the host is a client, update the clientinfo string.
-    pub fn register_notify<S>(&self, name: S, default: S) -> Result<(), ConsoleError>
+    pub fn register_notify<S>(&self, name: S, default: S, help: &str) -> Result<(), ConsoleError>
     where
         S: AsRef<str>,
     {
-        self.register_impl(name, default, false, true)
+        self.register_impl(name, default, help, false, true)
     }
 
     /// Register a new notify + archived `Cvar` with the given name.
@@ -215,11 +287,16 @@ impl CvarRegistry {
     /// Additionally, when this `Cvar` is set:
     /// - If the host is a server, broadcast that the variable has been changed to all clients.
     /// - If the host is a client, update the clientinfo string.
-    pub fn register_archive_notify<S>(&mut self, name: S, default: S) -> Result<(), ConsoleError>
+    pub fn register_archive_notify<S>(
+        &mut self,
+        name: S,
+        default: S,
+        help: &str,
+    ) -> Result<(), ConsoleError>
     where
         S: AsRef<str>,
     {
-        self.register_impl(name, default, true, true)
+        self.register_impl(name, default, help, true, true)
     }
 
     pub fn get<S>(&self, name: S) -> Result<String, ConsoleError>
@@ -291,6 +368,66 @@ impl CvarRegistry {
     {
         self.cvars.borrow().contains_key(name.as_ref())
     }
+
+    /// Returns the names of all registered cvars.
+    pub fn names(&self) -> Vec<String> {
+        self.cvars.borrow().keys().cloned().collect()
+    }
+
+    /// Returns the description the cvar was registered with.
+    pub fn help<S>(&self, name: S) -> Result<String, ConsoleError>
+    where
+        S: AsRef<str>,
+    {
+        Ok(self
+            .cvars
+            .borrow()
+            .get(name.as_ref())
+            .ok_or(ConsoleErrorKind::NoSuchCvar {
+                name: name.as_ref().to_owned(),
+            })?
+            .help
+            .clone())
+    }
+
+    /// Returns the default value of the cvar.
+    pub fn default_value<S>(&self, name: S) -> Result<String, ConsoleError>
+    where
+        S: AsRef<str>,
+    {
+        Ok(self
+            .cvars
+            .borrow()
+            .get(name.as_ref())
+            .ok_or(ConsoleErrorKind::NoSuchCvar {
+                name: name.as_ref().to_owned(),
+            })?
+            .default
+            .clone())
+    }
+
+    /// Writes the values of all archived cvars as console commands, sorted by name.
+    ///
+    /// Cvars with empty values are left out, since the console can't parse an empty quoted
+    /// string.
+    pub fn write_archived<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        let cvars = self.cvars.borrow();
+        let mut names: Vec<_> = cvars
+            .iter()
+            .filter(|(_, cvar)| cvar.archive && !cvar.val.is_empty())
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+
+        for name in names {
+            writeln!(writer, "{} \"{}\"", name, cvars[name].val)?;
+        }
+
+        Ok(())
+    }
 }
 
 /// The line of text currently being edited in the console.
@@ -385,6 +522,9 @@ impl ConsoleInput {
     }
 }
 
+/// The maximum number of lines kept in the console history.
+pub const MAX_HISTORY_LINES: usize = 64;
+
 pub struct History {
     lines: VecDeque<Vec<char>>,
     curs: usize,
@@ -400,9 +540,40 @@ impl History {
 
     pub fn add_line(&mut self, line: Vec<char>) {
         self.lines.push_front(line);
+        self.lines.truncate(MAX_HISTORY_LINES);
         self.curs = 0;
     }
 
+    /// Reads a history written by `History::write`.
+    ///
+    /// Empty lines are skipped.
+    pub fn read<R>(reader: R) -> io::Result<History>
+    where
+        R: BufRead,
+    {
+        let mut hist = History::new();
+        for line in reader.lines() {
+            let line = line?;
+            if !line.is_empty() {
+                hist.add_line(line.chars().collect());
+            }
+        }
+
+        Ok(hist)
+    }
+
+    /// Writes the history one line at a time, oldest first.
+    pub fn write<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        for line in self.lines.iter().rev() {
+            writeln!(writer, "{}", String::from_iter(line.iter()))?;
+        }
+
+        Ok(())
+    }
+
     // TODO: handle case where history is empty
     pub fn line_up(&mut self) -> Option<Vec<char>> {
         if self.lines.len() == 0 || self.curs >= self.lines.len() {
@@ -426,24 +597,113 @@ impl History {
     }
 }
 
+/// A line of console output.
+pub struct ConsoleLine {
+    chars: Vec<char>,
+    level: PrintLevel,
+    time: DateTime<Utc>,
+}
+
+impl ConsoleLine {
+    /// Returns the characters of this line, where those from 128 to 255 are colored.
+    pub fn chars(&self) -> &[char] {
+        &self.chars
+    }
+
+    /// Returns the print level of this line.
+    pub fn level(&self) -> PrintLevel {
+        self.level
+    }
+
+    /// Returns the time at which this line was printed.
+    pub fn time(&self) -> DateTime<Utc> {
+        self.time
+    }
+}
+
 pub struct ConsoleOutput {
-    lines: VecDeque<Vec<char>>,
+    lines: VecDeque<ConsoleLine>,
+
+    // if Some, every line is also appended to this file
+    log: Option<fs::File>,
 }
 
 impl ConsoleOutput {
     pub fn new() -> ConsoleOutput {
         ConsoleOutput {
             lines: VecDeque::new(),
+            log: None,
         }
     }
 
+    /// Appends every line printed from now on to the file at `path`.
+    ///
+    /// Colored characters are written in plain text.
+    pub fn log_to<P>(&mut self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        self.log = Some(
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?,
+        );
+        Ok(())
+    }
+
+    /// Adds a line at the `Game` print level.
     pub fn push(&mut self, chars: Vec<char>) {
-        self.lines.push_front(chars);
+        self.push_level(PrintLevel::Game, chars);
+    }
+
+    /// Adds a line at the given print level.
+    pub fn push_level(&mut self, level: PrintLevel, chars: Vec<char>) {
+        if let Some(ref mut log) = self.log {
+            let text: String = chars
+                .iter()
+                .map(|c| match *c as u32 {
+                    128..=255 => (*c as u8 - 128) as char,
+                    _ => *c,
+                })
+                .collect();
+            if let Err(e) = writeln!(log, "{}", text) {
+                warn!("Couldn't write to console log: {}", e);
+                self.log = None;
+            }
+        }
+
+        self.lines.push_front(ConsoleLine {
+            chars,
+            level,
+            time: Utc::now(),
+        });
         // TODO: set maximum capacity and pop_back when we reach it
     }
 
+    /// Adds text printed by the game, which may span several lines.
+    ///
+    /// The text follows the Quake convention where a leading `\x01` marks a colored chat
+    /// message and a leading `\x02` colors the text without changing its level.
+    pub fn print(&mut self, level: PrintLevel, text: &str) {
+        let (level, chars) = print::decode(text, level);
+        let text = match chars.last() {
+            Some('\n') => &chars[..chars.len() - 1],
+            _ => &chars[..],
+        };
+        for line in text.split(|c| *c == '\n') {
+            self.push_level(level, line.to_vec());
+        }
+    }
+
+    /// Returns the text of each line, newest first.
     pub fn lines(&self) -> impl Iterator<Item = &[char]> {
-        self.lines.iter().map(|v| v.as_slice())
+        self.lines.iter().map(|l| l.chars())
+    }
+
+    /// Returns each line with its level and time, newest first.
+    pub fn entries(&self) -> impl Iterator<Item = &ConsoleLine> {
+        self.lines.iter()
     }
 }
 
@@ -454,6 +714,7 @@ pub struct Console {
 
     input: ConsoleInput,
     hist: History,
+    completion: Option<complete::Completion>,
     buffer: RefCell<String>,
     output: Rc<RefCell<ConsoleOutput>>,
 }
@@ -465,6 +726,7 @@ impl Console {
         cmds.borrow_mut()
             .insert(
                 "echo",
+                "echo (text): print text to the console",
                 Box::new(move |args| {
                     let msg = match args.len() {
                         0 => "",
@@ -481,6 +743,7 @@ impl Console {
         cmds.borrow_mut()
             .insert(
                 "alias",
+                "alias [name] [script]: list aliases, or run a script when name is entered",
                 Box::new(move |args| match args.len() {
                     0 => {
                         for (name, script) in cmd_aliases.borrow().iter() {
@@ -500,18 +763,96 @@ impl Console {
             )
             .unwrap();
 
+        let set_cvars = cvars.clone();
+        cmds.borrow_mut()
+            .insert(
+                "set",
+                "set (cvar) (value): set a cvar, creating it if necessary",
+                Box::new(move |args| match args {
+                    [name, value] => {
+                        let cvars = set_cvars.borrow();
+                        let result = if cvars.contains(name) {
+                            cvars.set(name, value)
+                        } else {
+                            cvars.register(*name, *value, "")
+                        };
+
+                        if let Err(e) = result {
+                            println!("{}", e);
+                        }
+                    }
+
+                    _ => println!("set (cvar) (value): set a cvar, creating it if necessary"),
+                }),
+            )
+            .unwrap();
+
+        // commands are run while the registry is borrowed, so these only borrow it immutably. a
+        // weak reference keeps the registry from owning itself.
+        let find_cmds = Rc::downgrade(&cmds);
+        let find_cvars = cvars.clone();
+        let find_output = output.clone();
+        cmds.borrow_mut()
+            .insert(
+                "find",
+                "find (text): list commands and cvars whose names contain text",
+                Box::new(move |args| {
+                    let mut output = find_output.borrow_mut();
+                    match args {
+                        [text] => {
+                            let lines = find_lines(&find_cmds, &find_cvars.borrow(), text);
+                            let count = lines.len();
+                            for line in lines {
+                                output.push(line.chars().collect());
+                            }
+                            output.push(format!("{} matching name(s)", count).chars().collect());
+                        }
+
+                        _ => output.push(
+                            "find (text): list commands and cvars whose names contain text"
+                                .chars()
+                                .collect(),
+                        ),
+                    }
+                }),
+            )
+            .unwrap();
+
+        let help_cmds = Rc::downgrade(&cmds);
+        let help_cvars = cvars.clone();
+        let help_output = output.clone();
+        cmds.borrow_mut()
+            .insert(
+                "help",
+                "help (name): describe a command or cvar",
+                Box::new(move |args| {
+                    let line = match args {
+                        [name] => help_line(&help_cmds, &help_cvars.borrow(), name),
+                        _ => "help (name): describe a command or cvar".to_owned(),
+                    };
+                    help_output.borrow_mut().push(line.chars().collect());
+                }),
+            )
+            .unwrap();
+
         Console {
             cmds,
             cvars,
             aliases: aliases.clone(),
             input: ConsoleInput::new(),
             hist: History::new(),
+            completion: None,
             buffer: RefCell::new(String::new()),
             output: output.clone(),
         }
     }
 
     pub fn send_char(&mut self, c: char) -> Result<(), Error> {
+        // any input other than Tab ends the current completion
+        if c != '\t' {
+            self.completion = None;
+        }
+
         match c {
             // ignore grave and escape keys
             '`' | '\x1b' => (),
@@ -537,7 +878,7 @@ impl Console {
             '\x08' => self.input.backspace(),
             '\x7f' => self.input.delete(),
 
-            '\t' => warn!("Tab completion not implemented"), // TODO: tab completion
+            '\t' => self.complete(),
 
             // TODO: we should probably restrict what characters are allowed
             c => self.input.insert(c),
@@ -551,25 +892,108 @@ impl Console {
     }
 
     pub fn cursor_right(&mut self) {
+        self.completion = None;
         self.input.cursor_right()
     }
 
     pub fn cursor_left(&mut self) {
+        self.completion = None;
         self.input.cursor_left()
     }
 
     pub fn history_up(&mut self) {
+        self.completion = None;
         if let Some(line) = self.hist.line_up() {
             self.input.set_text(&line);
         }
     }
 
     pub fn history_down(&mut self) {
+        self.completion = None;
         if let Some(line) = self.hist.line_down() {
             self.input.set_text(&line);
         }
     }
 
+    /// Completes the last word of the input line.
+    ///
+    /// If a previous completion had more than one match, this cycles to the next match instead.
+    fn complete(&mut self) {
+        if let Some(ref mut completion) = self.completion {
+            let line = completion.cycle();
+            self.input.set_text(&line.chars().collect());
+            return;
+        }
+
+        let line = self.get_string();
+        let (head, _) = complete::split_last_word(&line);
+        let candidates = match head.split_whitespace().next() {
+            // complete the first word from everything that can be executed
+            None => {
+                let mut names: Vec<String> =
+                    self.cmds.borrow().names().map(|n| n.to_owned()).collect();
+                names.extend(self.cvars.borrow().names());
+                names.extend(self.aliases.borrow().keys().cloned());
+                names
+            }
+
+            Some(cmd) => self.cmds.borrow().arg_candidates(cmd),
+        };
+
+        if let Some((line, completion)) = complete::Completion::start(&line, candidates) {
+            if let Some(ref c) = completion {
+                let mut output = self.output.borrow_mut();
+                for m in c.matches() {
+                    output.push(format!("  {}", m).chars().collect());
+                }
+            }
+
+            self.input.set_text(&line.chars().collect());
+            self.completion = completion;
+        }
+    }
+
+    /// Replaces the command history with the contents of a history file.
+    pub fn load_history<P>(&mut self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let file = fs::File::open(path)?;
+        self.hist = History::read(io::BufReader::new(file))?;
+        Ok(())
+    }
+
+    /// Executes the cvar settings saved by `save_config`.
+    pub fn load_config<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let config = fs::read_to_string(path)?;
+        self.stuff_text(config);
+        self.execute();
+        Ok(())
+    }
+
+    /// Writes the values of archived cvars to a file so they can be restored with `load_config`.
+    pub fn save_config<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut writer = io::BufWriter::new(fs::File::create(path)?);
+        self.cvars.borrow().write_archived(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Writes the command history to a file so it can be restored with `load_history`.
+    pub fn save_history<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut writer = io::BufWriter::new(fs::File::create(path)?);
+        self.hist.write(&mut writer)?;
+        writer.flush()
+    }
+
     /// Interprets the contents of the execution buffer.
     pub fn execute(&self) {
         let text = self.buffer.borrow().to_owned();
@@ -591,15 +1015,23 @@ impl Console {
                     }
 
                     None => {
+                        let quoted: Vec<bool> = args
+                            .iter()
+                            .map(|a| parse::console::is_quoted(&text, a))
+                            .collect();
+                        let args = self.expand_args(&args, &quoted);
+                        let arg_0 = args[0].as_str();
                         let tail_args: Vec<&str> =
                             args.iter().map(|s| s.as_ref()).skip(1).collect();
 
                         if self.cmds.borrow().contains(arg_0) {
-                            self.cmds.borrow_mut().exec(arg_0, &tail_args).unwrap();
+                            self.cmds.borrow().exec(arg_0, &tail_args).unwrap();
                         } else if self.cvars.borrow().contains(arg_0) {
                             // TODO error handling on cvar set
                             match args.get(1) {
-                                Some(arg_1) => self.cvars.borrow_mut().set(arg_0, arg_1).unwrap(),
+                                Some(arg_1) => {
+                                    self.cvars.borrow_mut().set(arg_0, arg_1.as_str()).unwrap()
+                                }
                                 None => {
                                     let msg = format!(
                                         "\"{}\" is \"{}\"",
@@ -626,6 +1058,25 @@ impl Console {
         }
     }
 
+    /// Substitutes cvar values and evaluates expressions in a command's arguments.
+    ///
+    /// The arguments to `alias` and `bind` are left untouched so that substitution happens when
+    /// the bound script runs rather than when it is defined.
+    fn expand_args(&self, args: &[&str], quoted: &[bool]) -> Vec<String> {
+        match args[0] {
+            "alias" | "bind" => return args.iter().map(|a| (*a).to_owned()).collect(),
+            _ => (),
+        }
+
+        let cvars = self.cvars.borrow();
+        let expanded: Vec<String> = args
+            .iter()
+            .map(|arg| expr::expand_cvars(arg, |name| cvars.get(name).ok()))
+            .collect();
+
+        expr::evaluate_args(&expanded, quoted)
+    }
+
     pub fn get_string(&self) -> String {
         String::from_iter(self.input.text.clone().into_iter())
     }
@@ -649,7 +1100,189 @@ impl Console {
         self.buffer.borrow_mut().push_str("\n");
     }
 
+    /// Returns `true` if no commands are waiting to be executed.
+    pub fn idle(&self) -> bool {
+        self.buffer.borrow().trim().is_empty()
+    }
+
     pub fn output(&self) -> Ref<ConsoleOutput> {
         self.output.borrow()
     }
+
+    /// Appends all console output to the file at `path`. See `ConsoleOutput::log_to`.
+    pub fn log_to<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        self.output.borrow_mut().log_to(path)
+    }
+
+    /// Prints text from the game to the console output. See `ConsoleOutput::print`.
+    pub fn print<S>(&self, level: PrintLevel, text: S)
+    where
+        S: AsRef<str>,
+    {
+        self.output.borrow_mut().print(level, text.as_ref());
+    }
+}
+
+// Lists the commands and cvars whose names contain `text`, with their descriptions.
+fn find_lines(cmds: &Weak<RefCell<CmdRegistry>>, cvars: &CvarRegistry, text: &str) -> Vec<String> {
+    let text = text.to_lowercase();
+    let mut lines = Vec::new();
+
+    if let Some(cmds) = cmds.upgrade() {
+        let cmds = cmds.borrow();
+        for name in cmds.names().filter(|n| n.to_lowercase().contains(&text)) {
+            lines.push(describe(name, &cmds.help(name).unwrap_or_default()));
+        }
+    }
+
+    for name in cvars.names() {
+        if name.to_lowercase().contains(&text) {
+            lines.push(describe(&name, &cvars.help(&name).unwrap_or_default()));
+        }
+    }
+
+    lines.sort();
+    lines
+}
+
+// Describes the command or cvar called `name`.
+fn help_line(cmds: &Weak<RefCell<CmdRegistry>>, cvars: &CvarRegistry, name: &str) -> String {
+    if let Some(cmds) = cmds.upgrade() {
+        if let Ok(help) = cmds.borrow().help(name) {
+            return describe(name, help);
+        }
+    }
+
+    match (cvars.get(name), cvars.default_value(name), cvars.help(name)) {
+        (Ok(val), Ok(default), Ok(help)) => format!(
+            "{} (\"{}\", default \"{}\")",
+            describe(name, &help),
+            val,
+            default
+        ),
+        _ => format!("No command or cvar named \"{}\"", name),
+    }
+}
+
+fn describe(name: &str, help: &str) -> String {
+    if help.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}: {}", name, help)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_history_round_trip() {
+        let mut hist = History::new();
+        hist.add_line("map e1m1".chars().collect());
+        hist.add_line("".chars().collect());
+        hist.add_line("god".chars().collect());
+
+        let mut written = Vec::new();
+        hist.write(&mut written).unwrap();
+        assert_eq!(written, b"map e1m1\n\ngod\n");
+
+        let mut restored = History::read(written.as_slice()).unwrap();
+        assert_eq!(restored.line_up(), Some("god".chars().collect()));
+        assert_eq!(restored.line_up(), Some("map e1m1".chars().collect()));
+        assert_eq!(restored.line_up(), None);
+    }
+
+    #[test]
+    fn test_write_archived() {
+        let cvars = CvarRegistry::new();
+        cvars.register_archive("vid_width", "1366", "").unwrap();
+        cvars.register("developer", "0", "").unwrap();
+        cvars.register_archive("vid_monitor", "", "").unwrap();
+        cvars.register_archive("_cl_name", "player", "").unwrap();
+        cvars.set("_cl_name", "ranger").unwrap();
+
+        let mut written = Vec::new();
+        cvars.write_archived(&mut written).unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "_cl_name \"ranger\"\nvid_width \"1366\"\n"
+        );
+    }
+
+    #[test]
+    fn test_find_and_help() {
+        let cmds = Rc::new(RefCell::new(CmdRegistry::new()));
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new()));
+        cvars
+            .borrow()
+            .register_archive("vid_width", "1366", "width of the window")
+            .unwrap();
+        cmds.borrow_mut()
+            .insert("vid_restart", "recreate the window", Box::new(|_| ()))
+            .unwrap();
+        let console = Console::new(cmds, cvars);
+
+        console.stuff_text("find VID\nhelp vid_width\nhelp nothing\n");
+        console.execute();
+        // output is stored newest first
+        let mut lines: Vec<String> = console
+            .output()
+            .lines()
+            .map(|l| l.iter().collect())
+            .collect();
+        lines.reverse();
+        assert_eq!(
+            lines,
+            vec![
+                "vid_restart: recreate the window",
+                "vid_width: width of the window",
+                "2 matching name(s)",
+                "vid_width: width of the window (\"1366\", default \"1366\")",
+                "No command or cvar named \"nothing\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_history_capacity() {
+        let mut hist = History::new();
+        for i in 0..MAX_HISTORY_LINES + 10 {
+            hist.add_line(i.to_string().chars().collect());
+        }
+
+        assert_eq!(hist.lines.len(), MAX_HISTORY_LINES);
+        assert_eq!(
+            hist.line_up(),
+            Some((MAX_HISTORY_LINES + 9).to_string().chars().collect())
+        );
+    }
+
+    #[test]
+    fn test_output_print() {
+        let mut output = ConsoleOutput::new();
+        output.print(PrintLevel::Game, "first\nsecond\n");
+        output.print(PrintLevel::Game, "\x01ranger: hi\n");
+        output.print(PrintLevel::Dev, "loading");
+
+        let levels: Vec<PrintLevel> = output.entries().map(|l| l.level()).collect();
+        assert_eq!(
+            levels,
+            vec![
+                PrintLevel::Dev,
+                PrintLevel::Chat,
+                PrintLevel::Game,
+                PrintLevel::Game
+            ]
+        );
+
+        let lines: Vec<String> = output.lines().map(|l| l.iter().collect()).collect();
+        assert_eq!(lines[0], "loading");
+        assert_eq!(lines[1].chars().count(), "ranger: hi".len());
+        assert_eq!(lines[2], "second");
+        assert_eq!(lines[3], "first");
+    }
 }
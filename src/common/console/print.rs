@@ -0,0 +1,160 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Print levels and the Quake colored text convention.
+//!
+//! The console character set has two copies of the printable characters: the normal white ones
+//! and a colored (bronze) set at the same positions plus 128. Text printed by the game may start
+//! with a marker byte that colors the whole line, which is how the original engine shows chat.
+
+// marks a chat message, which also plays the talk sound
+const CHAT_MARKER: char = '\x01';
+
+// colors the rest of the line without playing a sound
+const COLOR_MARKER: char = '\x02';
+
+// offset of the colored copy of each glyph
+const COLOR_OFFSET: u32 = 128;
+
+/// The kind of a line of console output, used to color and filter it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PrintLevel {
+    /// Ordinary messages from the game.
+    Game,
+
+    /// Messages sent by other players.
+    Chat,
+
+    /// Diagnostics that are only interesting while developing maps and mods.
+    Dev,
+}
+
+impl PrintLevel {
+    /// All print levels, in the order they are listed by `con_filter`.
+    pub const ALL: [PrintLevel; 3] = [PrintLevel::Game, PrintLevel::Chat, PrintLevel::Dev];
+
+    /// Returns the name of this level as used by `con_filter`.
+    pub fn name(self) -> &'static str {
+        match self {
+            PrintLevel::Game => "game",
+            PrintLevel::Chat => "chat",
+            PrintLevel::Dev => "dev",
+        }
+    }
+
+    /// Returns the level with the given name, ignoring case.
+    pub fn from_name(name: &str) -> Option<PrintLevel> {
+        PrintLevel::ALL
+            .iter()
+            .copied()
+            .find(|l| l.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// The print levels hidden from the console and notify area, as selected by `con_filter`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PrintFilter {
+    hidden: [bool; 3],
+}
+
+impl PrintFilter {
+    /// Parses a whitespace- or comma-separated list of level names to hide.
+    ///
+    /// Unknown names are ignored.
+    pub fn parse(value: &str) -> PrintFilter {
+        let mut hidden = [false; 3];
+        for name in value.split(|c: char| c.is_whitespace() || c == ',') {
+            if let Some(level) = PrintLevel::from_name(name) {
+                hidden[level as usize] = true;
+            }
+        }
+
+        PrintFilter { hidden }
+    }
+
+    /// Returns `true` if lines of the given level should be shown.
+    pub fn allows(&self, level: PrintLevel) -> bool {
+        !self.hidden[level as usize]
+    }
+}
+
+/// Returns the colored (bronze) form of `text`.
+///
+/// Characters that have no colored form are left as they are.
+pub fn colored<S>(text: S) -> Vec<char>
+where
+    S: AsRef<str>,
+{
+    text.as_ref().chars().map(color_char).collect()
+}
+
+fn color_char(c: char) -> char {
+    match c as u32 {
+        // control characters have no colored form
+        0x20..=0x7F => std::char::from_u32(c as u32 + COLOR_OFFSET).unwrap_or(c),
+        _ => c,
+    }
+}
+
+/// Splits text printed by the game into its level and the characters to display.
+///
+/// A leading chat marker makes the line a colored chat message. A leading color marker colors
+/// the line but leaves its level as `default`.
+pub fn decode(text: &str, default: PrintLevel) -> (PrintLevel, Vec<char>) {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(CHAT_MARKER) => (PrintLevel::Chat, colored(chars.as_str())),
+        Some(COLOR_MARKER) => (default, colored(chars.as_str())),
+        _ => (default, text.chars().collect()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let (level, chars) = decode("\x01ranger: hi\n", PrintLevel::Game);
+        assert_eq!(level, PrintLevel::Chat);
+        assert_eq!(chars[0], std::char::from_u32('r' as u32 + 128).unwrap());
+        // newlines stay as they are
+        assert_eq!(chars[chars.len() - 1], '\n');
+
+        let (level, chars) = decode("\x02bronze", PrintLevel::Dev);
+        assert_eq!(level, PrintLevel::Dev);
+        assert_eq!(chars.len(), 6);
+
+        let (level, chars) = decode("plain", PrintLevel::Game);
+        assert_eq!(level, PrintLevel::Game);
+        assert_eq!(chars, "plain".chars().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_print_filter() {
+        let filter = PrintFilter::parse("DEV, bogus");
+        assert!(filter.allows(PrintLevel::Game));
+        assert!(filter.allows(PrintLevel::Chat));
+        assert!(!filter.allows(PrintLevel::Dev));
+
+        let filter = PrintFilter::parse("");
+        assert!(PrintLevel::ALL.iter().all(|l| filter.allows(*l)));
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Verification and storage of files downloaded from servers.
+//!
+//! Any server can send any file, so a download is only stored if its path names a content file
+//! inside the game directory and its size and checksum match what the server advertised before
+//! sending it. Downloads are kept apart from installed content in the `downloads` subdirectory of
+//! the game directory, which the virtual filesystem searches last, so a download can never replace
+//! a file the user installed.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use thiserror::Error;
+
+/// The subdirectory of the game directory that downloads are written to.
+pub const DOWNLOAD_DIR: &str = "downloads";
+
+// file types that are only ever read as data. configs, progs and anything the operating system
+// might run are refused.
+const ALLOWED_EXTENSIONS: [&str; 11] = [
+    "bsp", "lit", "loc", "lmp", "mdl", "ogg", "pcx", "spr", "tga", "wad", "wav",
+];
+
+#[derive(Error, Debug)]
+pub enum DownloadError {
+    #[error("unsafe download path \"{0}\"")]
+    UnsafePath(String),
+    #[error("file type of \"{0}\" may not be downloaded")]
+    ForbiddenType(String),
+    #[error("expected {expected} bytes, received {actual}")]
+    SizeMismatch { expected: u64, actual: u64 },
+    #[error("expected checksum {expected:04x}, received {actual:04x}")]
+    ChecksumMismatch { expected: u16, actual: u16 },
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Computes the 16-bit CRC used by the original engine to checksum files.
+///
+/// This is CRC-16-CCITT with an initial value of `0xFFFF`.
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, byte| {
+        (0..8).fold(crc ^ (*byte as u16) << 8, |crc, _| {
+            if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// The size and checksum a server advertises for a file before sending it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Advertised {
+    pub size: u64,
+    pub crc: u16,
+}
+
+impl Advertised {
+    /// Checks that `data` is the file that was advertised.
+    pub fn verify(&self, data: &[u8]) -> Result<(), DownloadError> {
+        if data.len() as u64 != self.size {
+            return Err(DownloadError::SizeMismatch {
+                expected: self.size,
+                actual: data.len() as u64,
+            });
+        }
+
+        let crc = crc16(data);
+        if crc != self.crc {
+            return Err(DownloadError::ChecksumMismatch {
+                expected: self.crc,
+                actual: crc,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that a path sent by a server names a permitted file inside the game directory.
+///
+/// The path must be relative, use `/` as its only separator, contain no `.` or `..` components
+/// and have the extension of a content file.
+pub fn check_path(path: &str) -> Result<PathBuf, DownloadError> {
+    let unsafe_path = || DownloadError::UnsafePath(path.to_owned());
+
+    // reject anything a platform might interpret as a separator or drive
+    if path.is_empty() || path.contains(|c| c == '\\' || c == ':' || c == '\0') {
+        return Err(unsafe_path());
+    }
+
+    if path
+        .split('/')
+        .any(|part| part.is_empty() || part == "." || part == "..")
+    {
+        return Err(unsafe_path());
+    }
+
+    let relative = PathBuf::from(path);
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(unsafe_path());
+    }
+
+    let allowed = relative
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| {
+            ALLOWED_EXTENSIONS
+                .iter()
+                .any(|allowed| ext.eq_ignore_ascii_case(allowed))
+        })
+        .unwrap_or(false);
+    if !allowed {
+        return Err(DownloadError::ForbiddenType(path.to_owned()));
+    }
+
+    Ok(relative)
+}
+
+/// The directory downloaded files are written to.
+pub struct DownloadStore {
+    root: PathBuf,
+}
+
+impl DownloadStore {
+    /// Creates a store for downloads to the given game directory.
+    pub fn new<P>(game_dir: P) -> DownloadStore
+    where
+        P: AsRef<Path>,
+    {
+        DownloadStore {
+            root: game_dir.as_ref().join(DOWNLOAD_DIR),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Verifies a downloaded file and writes it to the store, returning its location.
+    ///
+    /// The file is written under a temporary name and renamed into place once complete, so an
+    /// interrupted write never leaves a partial file where the virtual filesystem would find it.
+    pub fn store(
+        &self,
+        path: &str,
+        data: &[u8],
+        advertised: Advertised,
+    ) -> Result<PathBuf, DownloadError> {
+        let relative = check_path(path)?;
+        advertised.verify(data)?;
+
+        let full_path = self.root.join(&relative);
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut temp_path = full_path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+
+        let result = fs::File::create(&temp_path)
+            .and_then(|mut file| file.write_all(data).and_then(|_| file.sync_all()))
+            .and_then(|_| fs::rename(&temp_path, &full_path));
+        if let Err(e) = result {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e.into());
+        }
+
+        Ok(full_path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+
+    #[test]
+    fn test_crc16() {
+        assert_eq!(crc16(b""), 0xFFFF);
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn test_check_path() {
+        assert_eq!(
+            check_path("maps/e1m1.bsp").unwrap(),
+            PathBuf::from("maps/e1m1.bsp")
+        );
+        assert!(check_path("progs/Player.MDL").is_ok());
+
+        for path in &[
+            "",
+            "/etc/passwd.wav",
+            "../id1/pak0.pak.wav",
+            "maps/../../x.bsp",
+            "maps//e1m1.bsp",
+            "./maps/e1m1.bsp",
+            "maps\\e1m1.bsp",
+            "C:maps/e1m1.bsp",
+        ] {
+            assert!(
+                matches!(check_path(path), Err(DownloadError::UnsafePath(_))),
+                "{}",
+                path
+            );
+        }
+
+        for path in &["autoexec.cfg", "progs.dat", "maps/e1m1.exe", "maps/e1m1"] {
+            assert!(
+                matches!(check_path(path), Err(DownloadError::ForbiddenType(_))),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let data = b"123456789";
+        let advertised = Advertised {
+            size: 9,
+            crc: 0x29B1,
+        };
+        assert!(advertised.verify(data).is_ok());
+
+        assert!(matches!(
+            advertised.verify(b"12345678"),
+            Err(DownloadError::SizeMismatch {
+                expected: 9,
+                actual: 8
+            })
+        ));
+        assert!(matches!(
+            advertised.verify(b"123456780"),
+            Err(DownloadError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_store() {
+        let game_dir = env::temp_dir().join(format!("richter-download-{}", std::process::id()));
+        let store = DownloadStore::new(&game_dir);
+        let data = b"123456789";
+        let advertised = Advertised {
+            size: 9,
+            crc: 0x29B1,
+        };
+
+        let stored = store.store("sound/misc/x.wav", data, advertised).unwrap();
+        assert_eq!(stored, game_dir.join("downloads/sound/misc/x.wav"));
+        assert_eq!(fs::read(&stored).unwrap(), data);
+
+        // nothing is written for rejected files
+        assert!(store
+            .store("sound/misc/y.wav", b"12345678", advertised)
+            .is_err());
+        assert!(!game_dir.join("downloads/sound/misc/y.wav").exists());
+        assert!(!game_dir.join("downloads/sound/misc/y.wav.tmp").exists());
+
+        fs::remove_dir_all(&game_dir).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! View and projection transforms, and the view frustum they define.
+//!
+//! Quake places the x-axis forward, the y-axis left and the z-axis up. The renderer works in a
+//! right-handed system with the x-axis right, the y-axis up and the camera looking down the
+//! negative z-axis. Unless stated otherwise, positions here are in Quake coordinates.
+
+use crate::common::math::{self, Angles, Hyperplane};
+
+use cgmath::{Angle as _, Deg, InnerSpace as _, Matrix as _, Matrix4, Vector3, Vector4};
+
+/// Converts Quake coordinates to renderer coordinates.
+#[rustfmt::skip]
+pub const QUAKE_TO_WGPU: Matrix4<f32> = Matrix4::new(
+    0.0, 0.0, -1.0, 0.0,
+    -1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.0, 1.0,
+);
+
+/// Converts a position or direction from Quake coordinates to renderer coordinates.
+pub fn quake_to_wgpu(v: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(-v.y, v.z, -v.x)
+}
+
+/// Converts a position or direction from renderer coordinates to Quake coordinates.
+pub fn wgpu_to_quake(v: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(-v.z, -v.x, v.y)
+}
+
+/// Returns the forward, right and up vectors for a set of view angles.
+///
+/// Positive pitch looks down, as in the original engine.
+pub fn view_vectors(angles: Angles) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+    let (sp, cp) = angles.pitch.sin_cos();
+    let (sy, cy) = angles.yaw.sin_cos();
+    let (sr, cr) = angles.roll.sin_cos();
+
+    (
+        Vector3::new(cp * cy, cp * sy, -sp),
+        Vector3::new(-sr * sp * cy + cr * sy, -sr * sp * sy - cr * cy, -sr * cp),
+        Vector3::new(cr * sp * cy + sr * sy, cr * sp * sy - sr * cy, cr * cp),
+    )
+}
+
+/// Returns the view transform of a camera at `origin` facing along `angles`.
+///
+/// The transform takes renderer coordinates to view space.
+pub fn view(origin: Vector3<f32>, angles: Angles) -> Matrix4<f32> {
+    angles.mat4_wgpu() * Matrix4::from_translation(-quake_to_wgpu(origin))
+}
+
+/// Returns a perspective projection with the horizontal field of view `fov_x`.
+///
+/// Returns `None` if `fov_x` isn't between 0 and 360 degrees.
+pub fn perspective(fov_x: Deg<f32>, aspect: f32, near: f32, far: f32) -> Option<Matrix4<f32>> {
+    let fov_y = math::fov_x_to_fov_y(fov_x, aspect)?;
+    Some(cgmath::perspective(fov_y, aspect, near, far))
+}
+
+/// The region of space visible to a camera, bounded by six planes.
+#[derive(Clone, Debug)]
+pub struct Frustum {
+    // left, right, bottom, top, near, far. each faces into the frustum.
+    planes: [Hyperplane; 6],
+}
+
+impl Frustum {
+    /// Extracts the frustum of a combined view and projection transform.
+    ///
+    /// The frustum is in whichever coordinate system the transform takes as input, so the
+    /// frustum of `view_projection * QUAKE_TO_WGPU` is in Quake coordinates.
+    ///
+    /// See Gribb and Hartmann, "Fast Extraction of Viewing Frustum Planes from the World-View-
+    /// Projection Matrix".
+    pub fn from_matrix(m: Matrix4<f32>) -> Frustum {
+        let rows = [m.row(0), m.row(1), m.row(2), m.row(3)];
+        let plane = |v: Vector4<f32>| {
+            let normal = v.truncate();
+            let len = normal.magnitude();
+            Hyperplane::normal(normal / len, -v.w / len)
+        };
+
+        Frustum {
+            planes: [
+                plane(rows[3] + rows[0]),
+                plane(rows[3] - rows[0]),
+                plane(rows[3] + rows[1]),
+                plane(rows[3] - rows[1]),
+                plane(rows[3] + rows[2]),
+                plane(rows[3] - rows[2]),
+            ],
+        }
+    }
+
+    /// Returns `true` if `point` is inside the frustum.
+    pub fn contains_point(&self, point: Vector3<f32>) -> bool {
+        self.planes.iter().all(|p| p.point_dist(point) >= 0.0)
+    }
+
+    /// Returns `true` if the axis-aligned box from `min` to `max` may be inside the frustum.
+    ///
+    /// Boxes near the frustum's corners may pass even though they're outside it, but no box that
+    /// is even partly inside will fail.
+    pub fn intersects_bounds(&self, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+        self.planes.iter().all(|p| {
+            // test the corner furthest along the plane normal
+            let n = p.normal_vector();
+            let corner = Vector3::new(
+                if n.x >= 0.0 { max.x } else { min.x },
+                if n.y >= 0.0 { max.y } else { min.y },
+                if n.z >= 0.0 { max.z } else { min.z },
+            );
+            p.point_dist(corner) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use cgmath::{SquareMatrix as _, Zero as _};
+
+    fn assert_near(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    // a camera at the origin looking along the x-axis with a 90 degree field of view
+    fn frustum(angles: Angles) -> Frustum {
+        let projection = perspective(Deg(90.0), 1.0, 4.0, 4096.0).unwrap();
+        Frustum::from_matrix(projection * view(Vector3::zero(), angles) * QUAKE_TO_WGPU)
+    }
+
+    #[test]
+    fn test_coordinate_conversion() {
+        let v = Vector3::new(1.0, 2.0, 3.0);
+        assert_eq!(quake_to_wgpu(v), Vector3::new(-2.0, 3.0, -1.0));
+        assert_eq!(wgpu_to_quake(quake_to_wgpu(v)), v);
+        assert_eq!((QUAKE_TO_WGPU * v.extend(1.0)).truncate(), quake_to_wgpu(v));
+    }
+
+    #[test]
+    fn test_view_vectors() {
+        let (forward, right, up) = view_vectors(Angles::zero());
+        assert_near(forward, Vector3::unit_x());
+        assert_near(right, -Vector3::unit_y());
+        assert_near(up, Vector3::unit_z());
+
+        let (forward, right, up) = view_vectors(Angles {
+            pitch: Deg(90.0),
+            roll: Deg(0.0),
+            yaw: Deg(90.0),
+        });
+        assert_near(forward, -Vector3::unit_z());
+        assert_near(right, Vector3::unit_x());
+        assert_near(up, Vector3::unit_y());
+    }
+
+    #[test]
+    fn test_view_looks_forward() {
+        let origin = Vector3::new(64.0, -32.0, 16.0);
+        for angles in [
+            Angles::zero(),
+            Angles {
+                pitch: Deg(30.0),
+                roll: Deg(0.0),
+                yaw: Deg(135.0),
+            },
+        ]
+        .iter()
+        {
+            let (forward, _, _) = view_vectors(*angles);
+            let ahead = quake_to_wgpu(origin + forward * 10.0);
+            let view_pos = (view(origin, *angles) * ahead.extend(1.0)).truncate();
+            assert_near(view_pos, Vector3::new(0.0, 0.0, -10.0));
+        }
+    }
+
+    #[test]
+    fn test_perspective_invalid_fov() {
+        assert!(perspective(Deg(-1.0), 1.0, 4.0, 4096.0).is_none());
+        assert!(perspective(Deg(361.0), 1.0, 4.0, 4096.0).is_none());
+        assert!(perspective(Deg(90.0), 1.0, 4.0, 4096.0)
+            .unwrap()
+            .invert()
+            .is_some());
+    }
+
+    #[test]
+    fn test_frustum_contains_point() {
+        let frustum = frustum(Angles::zero());
+        assert!(frustum.contains_point(Vector3::new(100.0, 0.0, 0.0)));
+        assert!(frustum.contains_point(Vector3::new(100.0, 90.0, -90.0)));
+
+        // behind, outside the sides, and past the near and far planes
+        assert!(!frustum.contains_point(Vector3::new(-100.0, 0.0, 0.0)));
+        assert!(!frustum.contains_point(Vector3::new(100.0, 110.0, 0.0)));
+        assert!(!frustum.contains_point(Vector3::new(100.0, 0.0, -110.0)));
+        assert!(!frustum.contains_point(Vector3::new(2.0, 0.0, 0.0)));
+        assert!(!frustum.contains_point(Vector3::new(5000.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_frustum_rotates_with_view() {
+        let frustum = frustum(Angles {
+            pitch: Deg(0.0),
+            roll: Deg(0.0),
+            yaw: Deg(90.0),
+        });
+        assert!(frustum.contains_point(Vector3::new(0.0, 100.0, 0.0)));
+        assert!(!frustum.contains_point(Vector3::new(100.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_frustum_intersects_bounds() {
+        let frustum = frustum(Angles::zero());
+
+        // straddling the left plane
+        assert!(frustum.intersects_bounds(
+            Vector3::new(90.0, 90.0, -10.0),
+            Vector3::new(110.0, 130.0, 10.0),
+        ));
+
+        // containing the camera
+        assert!(frustum.intersects_bounds(
+            Vector3::new(-10.0, -10.0, -10.0),
+            Vector3::new(10.0, 10.0, 10.0),
+        ));
+
+        // entirely behind
+        assert!(!frustum.intersects_bounds(
+            Vector3::new(-100.0, -10.0, -10.0),
+            Vector3::new(-50.0, 10.0, 10.0),
+        ));
+    }
+}
@@ -28,6 +28,12 @@ use winit::{
     event_loop::{ControlFlow, EventLoopWindowTarget},
 };
 
+// the longest frame that will be simulated in one step
+const MAX_FRAME_DURATION_MS: i64 = 100;
+
+// how long before the next frame is due to stop sleeping and start yielding
+const SPIN_DURATION_US: i64 = 2000;
+
 pub trait Program: Sized {
     fn handle_event<T>(
         &mut self,
@@ -40,6 +46,28 @@ pub trait Program: Sized {
     fn shutdown(&mut self);
     fn cvars(&self) -> Ref<CvarRegistry>;
     fn cvars_mut(&self) -> RefMut<CvarRegistry>;
+
+    /// Returns the rate at which the program's clock runs relative to real time.
+    ///
+    /// Frame durations are scaled by this value before being passed to `frame`.
+    fn timescale(&self) -> f32 {
+        1.0
+    }
+
+    /// Returns `true` if the program's window has focus.
+    ///
+    /// While this is false, frames are capped at `host_backgroundfps` instead of `host_maxfps`.
+    fn focused(&self) -> bool {
+        true
+    }
+
+    /// Returns `true` if the program wants to exit.
+    ///
+    /// This is checked after every frame. The program is shut down and the event loop exits just
+    /// as if the window had been closed.
+    fn exit_requested(&self) -> bool {
+        false
+    }
 }
 
 pub struct Host<P>
@@ -61,7 +89,35 @@ where
         let init_time = Utc::now();
         program
             .cvars_mut()
-            .register_archive("host_maxfps", "72")
+            .register_archive(
+                "host_maxfps",
+                "72",
+                "maximum frames per second, or 0 for no limit",
+            )
+            .unwrap();
+        program
+            .cvars_mut()
+            .register_archive(
+                "host_backgroundfps",
+                "20",
+                "maximum frames per second while the window is unfocused, or 0 for no change",
+            )
+            .unwrap();
+        program
+            .cvars_mut()
+            .register(
+                "host_framerate",
+                "0",
+                "if nonzero, simulate frames of this many seconds regardless of real time",
+            )
+            .unwrap();
+        program
+            .cvars_mut()
+            .register(
+                "host_timescale",
+                "0",
+                "if nonzero, the rate the game clock runs relative to real time",
+            )
             .unwrap();
 
         Host {
@@ -87,7 +143,13 @@ where
                 *control_flow = ControlFlow::Exit;
             }
 
-            Event::MainEventsCleared => self.frame(),
+            Event::MainEventsCleared => {
+                self.frame();
+                if self.program.exit_requested() {
+                    self.program.shutdown();
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
             Event::Suspended | Event::Resumed => unimplemented!(),
             Event::LoopDestroyed => {
                 // TODO:
@@ -105,31 +167,92 @@ where
         self.prev_frame_duration = new_frame_time.signed_duration_since(self.prev_frame_time);
 
         // if the time elapsed since the last frame is too low, don't run this one yet
-        let prev_frame_duration = self.prev_frame_duration;
-        if !self.check_frame_duration(prev_frame_duration) {
-            // avoid busy waiting if we're running at a really high framerate
-            std::thread::yield_now();
+        let remaining = self.min_frame_duration() - self.prev_frame_duration;
+        if remaining > Duration::zero() {
+            wait(remaining);
             return;
         }
 
         // we're running this frame, so update the frame time
         self.prev_frame_time = new_frame_time;
 
-        self.program.frame(self.prev_frame_duration);
+        let frame_duration = self.filter_frame_duration(self.prev_frame_duration);
+        self.program.frame(frame_duration);
     }
 
-    // Returns whether enough time has elapsed to run the next frame.
-    fn check_frame_duration(&mut self, frame_duration: Duration) -> bool {
-        let host_maxfps = self
+    // Returns the minimum time between frames allowed by host_maxfps, or by host_backgroundfps if
+    // the program is in the background.
+    fn min_frame_duration(&self) -> Duration {
+        let cvars = self.program.cvars();
+        let host_maxfps = cvars.get_value("host_maxfps").unwrap_or(72.0);
+        let host_backgroundfps = cvars.get_value("host_backgroundfps").unwrap_or(0.0);
+
+        let cap = if !self.program.focused() && host_backgroundfps > 0.0 {
+            // a background cap of 0 leaves the framerate alone
+            if host_maxfps > 0.0 {
+                host_maxfps.min(host_backgroundfps)
+            } else {
+                host_backgroundfps
+            }
+        } else {
+            host_maxfps
+        };
+
+        // a cap of 0 runs frames as fast as possible
+        if cap <= 0.0 {
+            return Duration::zero();
+        }
+
+        engine::duration_from_f32(1.0 / cap)
+    }
+
+    // Returns the length of time to be simulated by a frame that took `real_duration` to arrive.
+    fn filter_frame_duration(&self, real_duration: Duration) -> Duration {
+        let host_framerate = self
             .program
             .cvars()
-            .get_value("host_maxfps")
-            .unwrap_or(72.0);
-        let min_frame_duration = engine::duration_from_f32(1.0 / host_maxfps);
-        frame_duration >= min_frame_duration
+            .get_value("host_framerate")
+            .unwrap_or(0.0);
+
+        let duration = if host_framerate > 0.0 {
+            // run fixed-length frames regardless of the real frame time
+            engine::duration_from_f32(host_framerate)
+        } else {
+            // keep long frames (e.g. after loading a level) from destabilizing physics
+            real_duration.min(Duration::milliseconds(MAX_FRAME_DURATION_MS))
+        };
+
+        let timescale = self.program.timescale();
+        if timescale == 1.0 {
+            duration
+        } else {
+            engine::duration_from_f32(engine::duration_to_f32(duration) * timescale)
+        }
+    }
+
+    /// Shuts down the program.
+    ///
+    /// This is only needed when the host is driven without an event loop; otherwise the program
+    /// is shut down when its window is closed.
+    pub fn shutdown(&mut self) {
+        self.program.shutdown();
     }
 
     pub fn uptime(&self) -> Duration {
         self.prev_frame_time.signed_duration_since(self.init_time)
     }
 }
+
+// Waits for up to `remaining` before returning.
+//
+// Sleep granularity can be a millisecond or more depending on the OS, which is too coarse to hold a
+// steady framerate, so this sleeps until shortly before the next frame is due and yields the
+// thread after that.
+fn wait(remaining: Duration) {
+    let spin_duration = Duration::microseconds(SPIN_DURATION_US);
+    if remaining > spin_duration {
+        std::thread::sleep((remaining - spin_duration).to_std().unwrap());
+    } else {
+        std::thread::yield_now();
+    }
+}
@@ -0,0 +1,186 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Sanity limits on the contents of game data files.
+//!
+//! Maps and models may be downloaded from any server, so the loaders can't assume that the sizes
+//! and counts they read are reasonable. Besides checking that the data is consistent, they reject
+//! files which exceed the limits here, which are generous enough for any real map or model but
+//! keep a malicious file from exhausting memory.
+
+use crate::common::console::CvarRegistry;
+
+use thiserror::Error;
+
+/// The default maximum size of a single lump, in bytes.
+pub const DEFAULT_MAX_LUMP_SIZE: usize = 64 * 1024 * 1024;
+
+/// The default maximum number of entities in a map.
+pub const DEFAULT_MAX_ENTITIES: usize = 32768;
+
+/// The default maximum width or height of a texture.
+pub const DEFAULT_MAX_TEXTURE_SIZE: u32 = 4096;
+
+const BYTES_PER_MEGABYTE: f32 = 1024.0 * 1024.0;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum LimitError {
+    #[error("lump size ({size} bytes) exceeds the limit of {max} bytes")]
+    LumpTooLarge { size: usize, max: usize },
+    #[error("entity count ({count}) exceeds the limit of {max}")]
+    TooManyEntities { count: usize, max: usize },
+    #[error("texture dimensions ({width}x{height}) exceed the limit of {max}x{max}")]
+    TextureTooLarge { width: u32, height: u32, max: u32 },
+}
+
+/// Limits enforced when loading BSP, PAK and MDL files.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoadLimits {
+    /// The maximum size in bytes of a single lump: a section of a BSP file or a file in a PAK
+    /// archive.
+    pub max_lump_size: usize,
+
+    /// The maximum number of entities described by a map's entity string.
+    pub max_entities: usize,
+
+    /// The maximum width or height of a map texture or model skin.
+    pub max_texture_size: u32,
+}
+
+impl LoadLimits {
+    /// Reads the limits from the `cl_maxlumpsize` (in megabytes), `cl_maxentities` and
+    /// `cl_maxtexturesize` cvars.
+    pub fn from_cvars(cvars: &CvarRegistry) -> LoadLimits {
+        let value = |name, default| cvars.get_value(name).unwrap_or(default).max(0.0);
+
+        LoadLimits {
+            max_lump_size: (value(
+                "cl_maxlumpsize",
+                DEFAULT_MAX_LUMP_SIZE as f32 / BYTES_PER_MEGABYTE,
+            ) * BYTES_PER_MEGABYTE) as usize,
+            max_entities: value("cl_maxentities", DEFAULT_MAX_ENTITIES as f32) as usize,
+            max_texture_size: value("cl_maxtexturesize", DEFAULT_MAX_TEXTURE_SIZE as f32) as u32,
+        }
+    }
+
+    pub fn check_lump_size(&self, size: usize) -> Result<(), LimitError> {
+        if size > self.max_lump_size {
+            Err(LimitError::LumpTooLarge {
+                size,
+                max: self.max_lump_size,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    pub fn check_entity_count(&self, count: usize) -> Result<(), LimitError> {
+        if count > self.max_entities {
+            Err(LimitError::TooManyEntities {
+                count,
+                max: self.max_entities,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    pub fn check_texture_size(&self, width: u32, height: u32) -> Result<(), LimitError> {
+        if width > self.max_texture_size || height > self.max_texture_size {
+            Err(LimitError::TextureTooLarge {
+                width,
+                height,
+                max: self.max_texture_size,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for LoadLimits {
+    fn default() -> LoadLimits {
+        LoadLimits {
+            max_lump_size: DEFAULT_MAX_LUMP_SIZE,
+            max_entities: DEFAULT_MAX_ENTITIES,
+            max_texture_size: DEFAULT_MAX_TEXTURE_SIZE,
+        }
+    }
+}
+
+/// Counts the entities in an entity string, i.e. the opening braces outside of quoted strings.
+pub fn count_entities(ent_string: &str) -> usize {
+    let mut in_quotes = false;
+    let mut count = 0;
+    for c in ent_string.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '{' if !in_quotes => count += 1,
+            _ => (),
+        }
+    }
+
+    count
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_count_entities() {
+        let ents = r#"{
+"classname" "worldspawn"
+"message" "{braces} in a string"
+}
+{
+"classname" "info_player_start"
+}
+"#;
+        assert_eq!(count_entities(ents), 2);
+    }
+
+    #[test]
+    fn test_from_cvars() {
+        let cvars = CvarRegistry::new();
+        cvars.register("cl_maxlumpsize", "2", "").unwrap();
+        cvars.register("cl_maxentities", "100", "").unwrap();
+        cvars.register("cl_maxtexturesize", "512", "").unwrap();
+
+        let limits = LoadLimits::from_cvars(&cvars);
+        assert_eq!(limits.max_lump_size, 2 * 1024 * 1024);
+        assert_eq!(
+            limits.check_texture_size(1024, 256),
+            Err(LimitError::TextureTooLarge {
+                width: 1024,
+                height: 256,
+                max: 512,
+            })
+        );
+        assert!(limits.check_entity_count(100).is_ok());
+        assert!(limits.check_entity_count(101).is_err());
+
+        // unregistered cvars fall back to the defaults
+        assert_eq!(
+            LoadLimits::from_cvars(&CvarRegistry::new()),
+            LoadLimits::default()
+        );
+    }
+}
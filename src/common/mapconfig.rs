@@ -0,0 +1,336 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Cvar settings that last for a single map.
+//!
+//! Custom maps often depend on settings of their own, such as low gravity or translucent water.
+//! These come from two places: keys on the map's worldspawn entity, like `"gravity" "200"`, and a
+//! script alongside the map, e.g. `maps/e1m1.cfg` for `maps/e1m1.bsp`, which is executed when the
+//! map loads.
+//!
+//! Whatever these change is put back when the map ends, so one map's settings don't leak into the
+//! next. A cvar the player changes during the map keeps the player's value. Only the cvars named
+//! by worldspawn keys and by the script's own `cvar value` and `set cvar value` lines are tracked;
+//! cvars changed some other way while the map loads, such as by an alias the script runs, are
+//! left alone.
+//!
+//! Server and client each apply the settings to their own cvars. Cvars that the server owns, such
+//! as `sv_gravity`, are set by the server when it loads the level, since that is the value the
+//! game follows. The client only applies settings for its own cvars, and leaves its copies of the
+//! server's alone.
+
+use std::collections::HashMap;
+
+use crate::common::{console::CvarRegistry, parse};
+
+/// Worldspawn keys, the cvars they set and the side that owns each cvar.
+pub const WORLDSPAWN_CVARS: [(&str, &str, ConfigSide); 2] = [
+    ("gravity", "sv_gravity", ConfigSide::Server),
+    ("wateralpha", "r_wateralpha", ConfigSide::Client),
+];
+
+/// Server cvars that the client keeps copies of, e.g. for movement prediction.
+pub const SHARED_SERVER_CVARS: [&str; 7] = [
+    "edgefriction",
+    "skill",
+    "sv_accelerate",
+    "sv_friction",
+    "sv_gravity",
+    "sv_maxspeed",
+    "sv_stopspeed",
+];
+
+/// Which program a `MapConfig` applies settings for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigSide {
+    Server,
+    Client,
+}
+
+impl ConfigSide {
+    // whether a map setting for the registered cvar `name` belongs to this side
+    fn owns(self, name: &str) -> bool {
+        match self {
+            ConfigSide::Server => true,
+            ConfigSide::Client => !SHARED_SERVER_CVARS.contains(&name),
+        }
+    }
+}
+
+/// Returns the path of the config script for the map at `map_path`.
+pub fn script_path<S>(map_path: S) -> String
+where
+    S: AsRef<str>,
+{
+    format!("{}.cfg", map_path.as_ref().trim_end_matches(".bsp"))
+}
+
+// a cvar changed by the map
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Change {
+    name: String,
+    old: String,
+    new: String,
+}
+
+/// Tracks the cvars changed by the current map so they can be restored when it ends.
+#[derive(Debug)]
+pub struct MapConfig {
+    side: ConfigSide,
+
+    // the values from before the map's settings were applied of the cvars it sets, while they are
+    // being applied
+    before: Option<HashMap<String, String>>,
+
+    changes: Vec<Change>,
+}
+
+impl MapConfig {
+    pub fn new(side: ConfigSide) -> MapConfig {
+        MapConfig {
+            side,
+            before: None,
+            changes: Vec::new(),
+        }
+    }
+
+    /// Starts applying a new map's settings, restoring those of the previous map first.
+    pub fn begin(&mut self, cvars: &CvarRegistry) {
+        self.restore(cvars);
+        self.before = Some(HashMap::new());
+    }
+
+    // remembers the value of a cvar the map is about to set, unless it was already remembered
+    fn record(&mut self, cvars: &CvarRegistry, name: &str) {
+        if let Some(ref mut before) = self.before {
+            if !before.contains_key(name) {
+                if let Ok(value) = cvars.get(name) {
+                    before.insert(name.to_owned(), value);
+                }
+            }
+        }
+    }
+
+    /// Returns whether the map's settings are still being applied.
+    pub fn pending(&self) -> bool {
+        self.before.is_some()
+    }
+
+    /// Sets the cvars named by keys on the map's worldspawn entity.
+    ///
+    /// Keys for cvars that aren't registered, or that belong to the other side, are ignored.
+    pub fn apply_worldspawn(&mut self, cvars: &CvarRegistry, worldspawn: &HashMap<&str, &str>) {
+        for (key, cvar, side) in WORLDSPAWN_CVARS.iter() {
+            if *side != self.side {
+                continue;
+            }
+
+            if let Some(value) = worldspawn.get(key) {
+                if cvars.contains(cvar) {
+                    debug!("worldspawn sets {} to {}", cvar, value);
+                    self.record(cvars, cvar);
+                    let _ = cvars.set(*cvar, value);
+                }
+            }
+        }
+    }
+
+    /// Notes the cvars set by the map's script and returns the part of it this side should
+    /// execute.
+    ///
+    /// Lines that set cvars belonging to the other side are left out. The server only runs the
+    /// lines that set its cvars, while the client also runs any other commands, such as binds.
+    /// A script that can't be parsed is ignored.
+    pub fn apply_script(&mut self, cvars: &CvarRegistry, script: &str) -> String {
+        let commands = match parse::commands(script) {
+            Ok((_, c)) => c,
+            Err(_) => return String::new(),
+        };
+
+        let mut text = String::new();
+        for args in commands {
+            let name = match args.as_slice() {
+                ["set", name, _] => Some(*name),
+                [name, _] if cvars.contains(name) => Some(*name),
+                _ => None,
+            };
+
+            let keep = match name {
+                Some(name) if self.side.owns(name) => {
+                    self.record(cvars, name);
+                    true
+                }
+                Some(_) => false,
+                None => self.side == ConfigSide::Client,
+            };
+
+            if keep && !args.is_empty() {
+                let quoted: Vec<String> = args.iter().map(|arg| format!("\"{}\"", arg)).collect();
+                text.push_str(&quoted.join(" "));
+                text.push('\n');
+            }
+        }
+
+        text
+    }
+
+    /// Finishes applying the map's settings, which should be called once the map's script has
+    /// run.
+    ///
+    /// Any cvar set by the map that changed since `begin` is recorded as changed by the map.
+    pub fn settle(&mut self, cvars: &CvarRegistry) {
+        let before = match self.before.take() {
+            Some(b) => b,
+            None => return,
+        };
+
+        let mut names: Vec<&String> = before.keys().collect();
+        names.sort();
+
+        for name in names {
+            let old = &before[name];
+            if let Ok(new) = cvars.get(name) {
+                if new != *old {
+                    self.changes.push(Change {
+                        name: name.to_owned(),
+                        old: old.to_owned(),
+                        new,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Restores the cvars changed by the map, except those changed again since.
+    pub fn restore(&mut self, cvars: &CvarRegistry) {
+        self.settle(cvars);
+
+        for change in self.changes.drain(..) {
+            if cvars.get(&change.name).map_or(false, |v| v == change.new) {
+                debug!("restoring {} to {}", change.name, change.old);
+                let _ = cvars.set(change.name.as_str(), change.old.as_str());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cvars() -> CvarRegistry {
+        let cvars = CvarRegistry::new();
+        cvars.register("sv_gravity", "800", "").unwrap();
+        cvars.register("r_wateralpha", "1", "").unwrap();
+        cvars.register("fov", "90", "").unwrap();
+        cvars
+    }
+
+    #[test]
+    fn test_script_path() {
+        assert_eq!(script_path("maps/e1m1.bsp"), "maps/e1m1.cfg");
+    }
+
+    #[test]
+    fn test_restore() {
+        let cvars = cvars();
+        let mut config = MapConfig::new(ConfigSide::Client);
+
+        config.begin(&cvars);
+        let worldspawn: HashMap<_, _> = vec![
+            ("classname", "worldspawn"),
+            ("gravity", "200"),
+            ("wateralpha", "0.3"),
+        ]
+        .into_iter()
+        .collect();
+        config.apply_worldspawn(&cvars, &worldspawn);
+
+        // gravity is the server's to set
+        assert_eq!(cvars.get("sv_gravity").unwrap(), "800");
+        assert_eq!(cvars.get("r_wateralpha").unwrap(), "0.3");
+
+        config.apply_script(&cvars, "r_wateralpha 0.5\nset fov 100\n");
+        // as if the script had run
+        cvars.set("r_wateralpha", "0.5").unwrap();
+        cvars.set("fov", "100").unwrap();
+        assert!(config.pending());
+        config.settle(&cvars);
+        assert!(!config.pending());
+
+        // the player's own change outlasts the map
+        cvars.set("fov", "110").unwrap();
+
+        config.restore(&cvars);
+        assert_eq!(cvars.get("r_wateralpha").unwrap(), "1");
+        assert_eq!(cvars.get("fov").unwrap(), "110");
+    }
+
+    #[test]
+    fn test_apply_script_sides() {
+        let cvars = cvars();
+        let script = "sv_gravity 100\nr_wateralpha 0.5\nbind k kill\n";
+
+        let mut client = MapConfig::new(ConfigSide::Client);
+        client.begin(&cvars);
+        assert_eq!(
+            client.apply_script(&cvars, script),
+            "\"r_wateralpha\" \"0.5\"\n\"bind\" \"k\" \"kill\"\n"
+        );
+
+        let mut server = MapConfig::new(ConfigSide::Server);
+        server.begin(&cvars);
+        assert_eq!(
+            server.apply_script(&cvars, script),
+            "\"sv_gravity\" \"100\"\n\"r_wateralpha\" \"0.5\"\n"
+        );
+    }
+
+    #[test]
+    fn test_next_map_restores_previous() {
+        let cvars = cvars();
+        let mut config = MapConfig::new(ConfigSide::Server);
+
+        config.begin(&cvars);
+        let worldspawn: HashMap<_, _> = vec![("gravity", "100")].into_iter().collect();
+        config.apply_worldspawn(&cvars, &worldspawn);
+
+        // the next map loads before the first one's settings have settled
+        config.begin(&cvars);
+        assert_eq!(cvars.get("sv_gravity").unwrap(), "800");
+        config.settle(&cvars);
+        config.restore(&cvars);
+        assert_eq!(cvars.get("sv_gravity").unwrap(), "800");
+    }
+
+    #[test]
+    fn test_untracked_cvars_kept() {
+        let cvars = cvars();
+        let mut config = MapConfig::new(ConfigSide::Server);
+
+        config.begin(&cvars);
+        config.apply_script(&cvars, "sv_gravity 400\n");
+        cvars.set("sv_gravity", "400").unwrap();
+        // changed while the map loads, but not by the map
+        cvars.set("fov", "100").unwrap();
+        config.settle(&cvars);
+
+        config.restore(&cvars);
+        assert_eq!(cvars.get("sv_gravity").unwrap(), "800");
+        assert_eq!(cvars.get("fov").unwrap(), "100");
+    }
+}
@@ -436,6 +436,23 @@ impl Hyperplane {
         }
     }
 
+    /// Returns the unit normal of this hyperplane.
+    pub fn normal_vector(&self) -> Vector3<f32> {
+        match self.alignment {
+            Alignment::Axis(a) => {
+                let mut n = Vector3::zero();
+                n[a as usize] = 1.0;
+                n
+            }
+            Alignment::Normal(n) => n,
+        }
+    }
+
+    /// Returns the distance of this hyperplane from the origin along its normal.
+    pub fn dist(&self) -> f32 {
+        self.dist
+    }
+
     /// Calculates the shortest distance between this hyperplane and the given point.
     pub fn point_dist(&self, point: Vector3<f32>) -> f32 {
         match self.alignment {
@@ -459,6 +476,61 @@ impl Hyperplane {
         }
     }
 
+    /// Calculates the distances between this hyperplane and the nearest and farthest corners of
+    /// the axis-aligned box bounded by `min` and `max`.
+    ///
+    /// The first value is the lowest signed distance and the second value is the highest. If they
+    /// have different signs, the box crosses the hyperplane.
+    pub fn box_dist_range(&self, min: Vector3<f32>, max: Vector3<f32>) -> (f32, f32) {
+        match self.alignment {
+            Alignment::Axis(a) => (min[a as usize] - self.dist, max[a as usize] - self.dist),
+            Alignment::Normal(n) => {
+                let mut near = Vector3::zero();
+                let mut far = Vector3::zero();
+                for i in 0..3 {
+                    if n[i] >= 0.0 {
+                        near[i] = min[i];
+                        far[i] = max[i];
+                    } else {
+                        near[i] = max[i];
+                        far[i] = min[i];
+                    }
+                }
+
+                (near.dot(n) - self.dist, far.dot(n) - self.dist)
+            }
+        }
+    }
+
+    /// Returns a square polygon lying in this hyperplane.
+    ///
+    /// The polygon is centered on the point of the hyperplane nearest the origin and extends
+    /// `extent` units along each of its axes. Its vertices wind counterclockwise when viewed from
+    /// the positive side.
+    pub fn polygon(&self, extent: f32) -> Vec<Vector3<f32>> {
+        let normal = self.normal_vector();
+
+        // pick the world axis least aligned with the normal to build a basis from
+        let axis = if normal.x.abs() <= normal.y.abs() && normal.x.abs() <= normal.z.abs() {
+            Vector3::unit_x()
+        } else if normal.y.abs() <= normal.z.abs() {
+            Vector3::unit_y()
+        } else {
+            Vector3::unit_z()
+        };
+
+        let u = normal.cross(axis).normalize() * extent;
+        let v = normal.cross(u);
+        let center = normal * self.dist;
+
+        vec![
+            center - u - v,
+            center + u - v,
+            center + u + v,
+            center - u + v,
+        ]
+    }
+
     /// Calculates the intersection of a line segment with this hyperplane.
     pub fn line_segment_intersection(
         &self,
@@ -512,6 +584,24 @@ pub fn fov_x_to_fov_y(fov_x: Deg<f32>, aspect: f32) -> Option<Deg<f32>> {
     }
 }
 
+/// The aspect ratio at which the `fov` cvar is measured when it's adapted to the screen.
+pub const FOV_REFERENCE_ASPECT: f32 = 4.0 / 3.0;
+
+/// Widens or narrows the horizontal field of view `fov_x`, measured at a 4:3 aspect ratio, so
+/// that the vertical field of view stays the same at `aspect` (Hor+).
+///
+/// Wider screens see more at the sides instead of less at the top and bottom. The result is
+/// always less than 180 degrees.
+pub fn adapt_fov_x(fov_x: Deg<f32>, aspect: f32) -> Deg<f32> {
+    let half_tan = (fov_x / 2.0).tan() * aspect / FOV_REFERENCE_ASPECT;
+    let adapted = Deg::atan(half_tan) * 2.0;
+    if adapted.0 < 179.0 {
+        adapted
+    } else {
+        Deg(179.0)
+    }
+}
+
 // see https://github.com/id-Software/Quake/blob/master/WinQuake/gl_rsurf.c#L1544
 const COLLINEAR_EPSILON: f32 = 0.001;
 
@@ -575,6 +665,57 @@ pub fn remove_collinear(vs: Vec<Vector3<f32>>) -> Vec<Vector3<f32>> {
     out
 }
 
+// vertices this close to a hyperplane are considered to lie on it
+const SPLIT_EPSILON: f32 = 0.01;
+
+/// Splits a convex polygon along a hyperplane.
+///
+/// Returns the parts of the polygon on the positive and negative sides of the hyperplane, either
+/// of which may be `None` if the polygon lies entirely on the other side. A polygon lying in the
+/// hyperplane is considered to be on the positive side.
+pub fn split_polygon(
+    polygon: &[Vector3<f32>],
+    plane: &Hyperplane,
+) -> (Option<Vec<Vector3<f32>>>, Option<Vec<Vector3<f32>>>) {
+    let dists: Vec<f32> = polygon.iter().map(|p| plane.point_dist(*p)).collect();
+
+    if dists.iter().all(|d| *d >= -SPLIT_EPSILON) {
+        return (Some(polygon.to_vec()), None);
+    }
+
+    if dists.iter().all(|d| *d <= SPLIT_EPSILON) {
+        return (None, Some(polygon.to_vec()));
+    }
+
+    let mut front = Vec::new();
+    let mut back = Vec::new();
+    for i in 0..polygon.len() {
+        let j = (i + 1) % polygon.len();
+        let (p, p_dist) = (polygon[i], dists[i]);
+        let (q, q_dist) = (polygon[j], dists[j]);
+
+        // vertices on the hyperplane belong to both halves
+        if p_dist >= -SPLIT_EPSILON {
+            front.push(p);
+        }
+        if p_dist <= SPLIT_EPSILON {
+            back.push(p);
+        }
+
+        // add a vertex where the edge crosses the hyperplane
+        if (p_dist > SPLIT_EPSILON && q_dist < -SPLIT_EPSILON)
+            || (p_dist < -SPLIT_EPSILON && q_dist > SPLIT_EPSILON)
+        {
+            let mid = p + (q - p) * (p_dist / (p_dist - q_dist));
+            front.push(mid);
+            back.push(mid);
+        }
+    }
+
+    let keep = |v: Vec<Vector3<f32>>| if v.len() >= 3 { Some(v) } else { None };
+    (keep(front), keep(back))
+}
+
 pub fn bounds<'a, I>(points: I) -> (Vector3<f32>, Vector3<f32>)
 where
     I: IntoIterator<Item = &'a Vector3<f32>>,
@@ -856,4 +997,72 @@ mod test {
             assert_eq!(remove_collinear(input), output);
         }
     }
+
+    #[test]
+    fn test_hyperplane_box_dist_range() {
+        let min = Vector3::new(-1.0, -1.0, -1.0);
+        let max = Vector3::new(1.0, 1.0, 1.0);
+
+        let plane = Hyperplane::axis_z(2.0);
+        assert_eq!(plane.box_dist_range(min, max), (-3.0, -1.0));
+
+        let plane = Hyperplane::normal(Vector3::new(-1.0, 0.0, 0.0), 0.5);
+        assert_eq!(plane.box_dist_range(min, max), (-1.5, 0.5));
+    }
+
+    #[test]
+    fn test_hyperplane_polygon() {
+        let plane = Hyperplane::normal(Vector3::new(1.0, 1.0, 0.0), 2.0);
+        let polygon = plane.polygon(8.0);
+        assert_eq!(polygon.len(), 4);
+        for p in polygon {
+            assert!(plane.point_dist(p).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn test_split_polygon() {
+        let square: Vec<Vector3<f32>> = vec![
+            [0.0, 0.0, 0.0].into(),
+            [2.0, 0.0, 0.0].into(),
+            [2.0, 2.0, 0.0].into(),
+            [0.0, 2.0, 0.0].into(),
+        ];
+
+        let (front, back) = split_polygon(&square, &Hyperplane::axis_x(1.0));
+        let front = front.unwrap();
+        let back = back.unwrap();
+        assert_eq!(front.len(), 4);
+        assert_eq!(back.len(), 4);
+        assert!(front.iter().all(|p| p.x >= 1.0));
+        assert!(back.iter().all(|p| p.x <= 1.0));
+
+        // entirely on one side
+        let (front, back) = split_polygon(&square, &Hyperplane::axis_x(3.0));
+        assert!(front.is_none());
+        assert_eq!(back.unwrap(), square);
+
+        // lying in the plane
+        let (front, back) = split_polygon(&square, &Hyperplane::axis_z(0.0));
+        assert_eq!(front.unwrap(), square);
+        assert!(back.is_none());
+    }
+
+    #[test]
+    fn test_adapt_fov_x() {
+        // unchanged at the reference aspect ratio
+        let fov = adapt_fov_x(Deg(90.0), 4.0 / 3.0);
+        assert!((fov.0 - 90.0).abs() < 0.001);
+
+        // 16:9 at fov 90 is the familiar 106 degrees
+        let fov = adapt_fov_x(Deg(90.0), 16.0 / 9.0);
+        assert!((fov.0 - 106.26).abs() < 0.01);
+
+        // the vertical field of view stays the same
+        let fov_y = fov_x_to_fov_y(Deg(90.0), 4.0 / 3.0).unwrap();
+        let wide_fov_y = fov_x_to_fov_y(adapt_fov_x(Deg(90.0), 32.0 / 9.0), 32.0 / 9.0).unwrap();
+        assert!((fov_y.0 - wide_fov_y.0).abs() < 0.001);
+
+        assert!(adapt_fov_x(Deg(170.0), 10.0).0 <= 179.0);
+    }
 }
@@ -19,6 +19,7 @@ use std::io::{self, BufReader, Read, Seek, SeekFrom};
 
 use crate::common::{
     engine,
+    limits::{LimitError, LoadLimits},
     model::{ModelFlags, SyncType},
     util::read_f32_3,
 };
@@ -35,6 +36,13 @@ pub const VERSION: i32 = 6;
 
 const HEADER_SIZE: u64 = 84;
 
+// the smallest possible size on disk of each element, used to reject counts which couldn't
+// possibly fit in the file before allocating space for them
+const TEXCOORD_SIZE: u64 = 12;
+const POLYGON_SIZE: u64 = 16;
+const KEYFRAME_MIN_SIZE: u64 = 24;
+const DURATION_SIZE: u64 = 4;
+
 #[derive(Error, Debug)]
 pub enum MdlFileError {
     #[error("I/O error: {0}")]
@@ -47,6 +55,12 @@ pub enum MdlFileError {
     InvalidTextureWidth(i32),
     #[error("Invalid texture height: {0}")]
     InvalidTextureHeight(i32),
+    #[error("Invalid texture count: {0}")]
+    InvalidTextureCount(i32),
+    #[error("Invalid texture dimensions: {0}x{1}")]
+    InvalidTextureSize(i32, i32),
+    #[error("Invalid texture frame count: {0}")]
+    InvalidTextureFrameCount(i32),
     #[error("Invalid vertex count: {0}")]
     InvalidVertexCount(i32),
     #[error("Invalid polygon count: {0}")]
@@ -63,6 +77,16 @@ pub enum MdlFileError {
     InvalidTexcoord([i32; 2]),
     #[error("Invalid front-facing flag: {0}")]
     InvalidFrontFacing(i32),
+    #[error("Invalid vertex index: {0}")]
+    InvalidVertexIndex(i32),
+    #[error("Invalid keyframe kind: {0}")]
+    InvalidKeyframeKind(i32),
+    #[error("Invalid subframe count: {0}")]
+    InvalidSubframeCount(i32),
+    #[error("Unexpected data at end of MDL file")]
+    TrailingData,
+    #[error("Limit exceeded: {0}")]
+    Limit(#[from] LimitError),
     #[error("Keyframe name too long: {0:?}")]
     KeyframeNameTooLong([u8; 16]),
     #[error("Non-UTF-8 keyframe name: {0}")]
@@ -300,11 +324,24 @@ impl AliasModel {
     }
 }
 
+/// Loads an MDL file, checking it against the default [`LoadLimits`].
+///
+/// [`LoadLimits`]: ../limits/struct.LoadLimits.html
 pub fn load<R>(data: R) -> Result<AliasModel, MdlFileError>
+where
+    R: Read + Seek,
+{
+    load_with_limits(data, &LoadLimits::default())
+}
+
+/// Loads an MDL file, rejecting it if it exceeds `limits`.
+pub fn load_with_limits<R>(data: R, limits: &LoadLimits) -> Result<AliasModel, MdlFileError>
 where
     R: Read + Seek,
 {
     let mut reader = BufReader::new(data);
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
 
     // struct MdlHeader {
     //     magic: i32
@@ -346,16 +383,27 @@ where
     if texture_height <= 0 {
         Err(MdlFileError::InvalidTextureHeight(texture_height))?;
     }
+    limits.check_texture_size(texture_width as u32, texture_height as u32)?;
+    let texture_size = texture_width as u64 * texture_height as u64;
+    if texture_size > file_len {
+        Err(MdlFileError::InvalidTextureSize(
+            texture_width,
+            texture_height,
+        ))?;
+    }
+    if texture_count < 0 || texture_count as u64 * texture_size > file_len {
+        Err(MdlFileError::InvalidTextureCount(texture_count))?;
+    }
     let vertex_count = reader.read_i32::<LittleEndian>()?;
-    if vertex_count <= 0 {
+    if vertex_count <= 0 || vertex_count as u64 * TEXCOORD_SIZE > file_len {
         Err(MdlFileError::InvalidVertexCount(vertex_count))?;
     }
     let poly_count = reader.read_i32::<LittleEndian>()?;
-    if poly_count <= 0 {
+    if poly_count <= 0 || poly_count as u64 * POLYGON_SIZE > file_len {
         Err(MdlFileError::InvalidPolygonCount(poly_count))?;
     }
     let keyframe_count = reader.read_i32::<LittleEndian>()?;
-    if keyframe_count <= 0 {
+    if keyframe_count <= 0 || keyframe_count as u64 * KEYFRAME_MIN_SIZE > file_len {
         Err(MdlFileError::InvalidKeyframeCount(keyframe_count))?;
     }
 
@@ -384,11 +432,8 @@ where
         let texture = match reader.read_i32::<LittleEndian>()? {
             // Static
             0 => {
-                let mut indices: Vec<u8> =
-                    Vec::with_capacity((texture_width * texture_height) as usize);
-                (&mut reader)
-                    .take((texture_width * texture_height) as u64)
-                    .read_to_end(&mut indices)?;
+                let mut indices = vec![0; texture_size as usize];
+                reader.read_exact(&mut indices)?;
                 Texture::Static(StaticTexture {
                     indices: indices.into_boxed_slice(),
                 })
@@ -396,8 +441,12 @@ where
 
             // Animated
             1 => {
-                // TODO: sanity check this value
-                let texture_frame_count = reader.read_i32::<LittleEndian>()? as usize;
+                let texture_frame_count = match reader.read_i32::<LittleEndian>()? {
+                    c if c <= 0 || c as u64 * (DURATION_SIZE + texture_size) > file_len => {
+                        Err(MdlFileError::InvalidTextureFrameCount(c))?
+                    }
+                    c => c as usize,
+                };
 
                 let mut durations = Vec::with_capacity(texture_frame_count);
                 for _ in 0..texture_frame_count {
@@ -408,11 +457,8 @@ where
 
                 let mut frames = Vec::with_capacity(texture_frame_count);
                 for frame_id in 0..texture_frame_count {
-                    let mut indices: Vec<u8> =
-                        Vec::with_capacity((texture_width * texture_height) as usize);
-                    (&mut reader)
-                        .take((texture_width * texture_height) as u64)
-                        .read_to_end(&mut indices)?;
+                    let mut indices = vec![0; texture_size as usize];
+                    reader.read_exact(&mut indices)?;
                     frames.push(AnimatedTextureFrame {
                         duration: durations[frame_id as usize],
                         indices: indices.into_boxed_slice(),
@@ -461,7 +507,10 @@ where
 
         let mut indices = [0; 3];
         for i in 0..3 {
-            indices[i] = reader.read_i32::<LittleEndian>()? as u32;
+            indices[i] = match reader.read_i32::<LittleEndian>()? {
+                x if x < 0 || x >= vertex_count => Err(MdlFileError::InvalidVertexIndex(x))?,
+                x => x as u32,
+            };
         }
 
         polygons.push(IndexedPolygon {
@@ -481,7 +530,7 @@ where
 
                 let name = {
                     let mut bytes: [u8; 16] = [0; 16];
-                    reader.read(&mut bytes)?;
+                    reader.read_exact(&mut bytes)?;
                     let len = bytes
                         .iter()
                         .position(|b| *b == 0)
@@ -507,7 +556,9 @@ where
 
             1 => {
                 let subframe_count = match reader.read_i32::<LittleEndian>()? {
-                    s if s <= 0 => panic!("Invalid subframe count: {}", s),
+                    s if s <= 0 || s as u64 * KEYFRAME_MIN_SIZE > file_len => {
+                        Err(MdlFileError::InvalidSubframeCount(s))?
+                    }
                     s => s,
                 };
 
@@ -532,7 +583,7 @@ where
 
                     let name = {
                         let mut bytes: [u8; 16] = [0; 16];
-                        reader.read(&mut bytes)?;
+                        reader.read_exact(&mut bytes)?;
                         let len = bytes
                             .iter()
                             .position(|b| *b == 0)
@@ -564,12 +615,12 @@ where
                 })
             }
 
-            x => panic!("Bad frame kind value: {}", x),
+            x => Err(MdlFileError::InvalidKeyframeKind(x))?,
         });
     }
 
     if reader.seek(SeekFrom::Current(0))? != reader.seek(SeekFrom::End(0))? {
-        panic!("Misaligned read on MDL file");
+        Err(MdlFileError::TrailingData)?;
     }
 
     Ok(AliasModel {
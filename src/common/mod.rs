@@ -19,19 +19,30 @@
 // SOFTWARE.
 
 pub mod alloc;
+pub mod arena;
+pub mod args;
 pub mod bsp;
 pub mod console;
+pub mod download;
 pub mod engine;
+pub mod frustum;
 pub mod host;
+pub mod limits;
+pub mod mapconfig;
 pub mod math;
 pub mod mdl;
 pub mod model;
 pub mod net;
 pub mod pak;
 pub mod parse;
+pub mod pmove;
+pub mod pk3;
+pub mod random;
 pub mod sprite;
+pub mod tga;
 pub mod util;
 pub mod vfs;
+pub mod vis;
 pub mod wad;
 
 pub static DEFAULT_BASEDIR: &'static str = "id1";
@@ -17,6 +17,7 @@
 
 use crate::common::{
     bsp::{BspFileError, BspModel},
+    limits::LoadLimits,
     mdl::{self, AliasModel, MdlFileError},
     sprite::{self, SpriteModel},
     vfs::{Vfs, VfsError},
@@ -85,7 +86,7 @@ impl Model {
         &self.kind
     }
 
-    pub fn load<S>(vfs: &Vfs, name: S) -> Result<Model, ModelError>
+    pub fn load<S>(vfs: &Vfs, name: S, limits: &LoadLimits) -> Result<Model, ModelError>
     where
         S: AsRef<str>,
     {
@@ -96,7 +97,7 @@ impl Model {
         } else if name.ends_with(".mdl") {
             Ok(Model::from_alias_model(
                 name.to_owned(),
-                mdl::load(vfs.open(name)?)?,
+                mdl::load_with_limits(vfs.open(name)?, limits)?,
             ))
         } else if name.ends_with(".spr") {
             Ok(Model::from_sprite_model(
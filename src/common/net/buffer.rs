@@ -0,0 +1,249 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::io;
+
+use crate::common::net::{codec::WireCodec, NetError};
+
+use cgmath::Deg;
+
+/// A fixed-capacity buffer for composing network messages.
+///
+/// The storage is allocated once and reused after `clear`, so composing a message doesn't
+/// allocate. Values are written directly into the storage, in little-endian order unless noted
+/// otherwise. A write that doesn't fit fails and leaves the buffer unchanged.
+///
+/// `MessageBuffer` also implements `io::Write`, so `ServerCmd` and `ClientCmd` can be serialized
+/// into it.
+pub struct MessageBuffer {
+    data: Box<[u8]>,
+    len: usize,
+}
+
+impl MessageBuffer {
+    /// Creates an empty buffer that can hold `capacity` bytes.
+    pub fn with_capacity(capacity: usize) -> MessageBuffer {
+        MessageBuffer {
+            data: vec![0; capacity].into_boxed_slice(),
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of bytes that can still be written.
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.len
+    }
+
+    /// Empties the buffer, keeping its storage.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Returns the bytes written so far.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    // Fails if fewer than `count` bytes can be written.
+    fn check_space(&self, count: usize) -> Result<(), NetError> {
+        if count > self.remaining() {
+            return Err(NetError::with_msg(format!(
+                "Message buffer overflow ({} bytes written, {} more requested, capacity {})",
+                self.len,
+                count,
+                self.capacity()
+            )));
+        }
+
+        Ok(())
+    }
+
+    // Claims the next `count` bytes of storage for writing.
+    fn claim(&mut self, count: usize) -> Result<&mut [u8], NetError> {
+        self.check_space(count)?;
+
+        let start = self.len;
+        self.len += count;
+        Ok(&mut self.data[start..self.len])
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), NetError> {
+        self.claim(bytes.len())?.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn write_u8(&mut self, value: u8) -> Result<(), NetError> {
+        self.claim(1)?[0] = value;
+        Ok(())
+    }
+
+    pub fn write_i8(&mut self, value: i8) -> Result<(), NetError> {
+        self.write_u8(value as u8)
+    }
+
+    pub fn write_u16(&mut self, value: u16) -> Result<(), NetError> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    pub fn write_i16(&mut self, value: i16) -> Result<(), NetError> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    pub fn write_u32(&mut self, value: u32) -> Result<(), NetError> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    pub fn write_i32(&mut self, value: i32) -> Result<(), NetError> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    pub fn write_f32(&mut self, value: f32) -> Result<(), NetError> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    /// Writes a `u16` in network (big-endian) order, as used by packet headers.
+    pub fn write_u16_network(&mut self, value: u16) -> Result<(), NetError> {
+        self.write_bytes(&value.to_be_bytes())
+    }
+
+    /// Writes a `u32` in network (big-endian) order, as used by packet headers.
+    pub fn write_u32_network(&mut self, value: u32) -> Result<(), NetError> {
+        self.write_bytes(&value.to_be_bytes())
+    }
+
+    /// Writes a coordinate encoded by `codec`.
+    pub fn write_coord(&mut self, codec: WireCodec, coord: f32) -> Result<(), NetError> {
+        // check up front so a failed write leaves the buffer unchanged
+        self.check_space(codec.coord.size())?;
+        codec.write_coord(self, coord)
+    }
+
+    /// Writes an angle encoded by `codec`.
+    pub fn write_angle(&mut self, codec: WireCodec, angle: Deg<f32>) -> Result<(), NetError> {
+        self.check_space(codec.angle.size())?;
+        codec.write_angle(self, angle)
+    }
+
+    /// Writes a null-terminated string.
+    pub fn write_string(&mut self, string: &str) -> Result<(), NetError> {
+        // check up front so a failed write leaves the buffer unchanged
+        self.check_space(string.len() + 1)?;
+
+        self.write_bytes(string.as_bytes())?;
+        self.write_u8(0)
+    }
+}
+
+impl io::Write for MessageBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_bytes(buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::WriteZero, e.to_string()))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::BufReader;
+
+    use crate::common::net::ServerCmd;
+
+    use cgmath::Vector3;
+
+    #[test]
+    fn test_write_values() {
+        let mut buf = MessageBuffer::with_capacity(16);
+        buf.write_u8(1).unwrap();
+        buf.write_i16(-2).unwrap();
+        buf.write_u16_network(0x0304).unwrap();
+        buf.write_coord(WireCodec::NETQUAKE, 1.5).unwrap();
+        buf.write_angle(WireCodec::NETQUAKE, Deg(90.0)).unwrap();
+        buf.write_string("ab").unwrap();
+
+        assert_eq!(
+            buf.as_slice(),
+            &[1, 0xFE, 0xFF, 0x03, 0x04, 12, 0, 64, b'a', b'b', 0]
+        );
+        assert_eq!(buf.remaining(), 5);
+    }
+
+    #[test]
+    fn test_overflow_leaves_buffer_unchanged() {
+        let mut buf = MessageBuffer::with_capacity(4);
+        buf.write_u16(7).unwrap();
+
+        assert!(buf.write_u32(8).is_err());
+        assert!(buf.write_string("abc").is_err());
+        assert_eq!(buf.as_slice(), &[7, 0]);
+
+        buf.write_u16(9).unwrap();
+        assert_eq!(buf.remaining(), 0);
+        assert!(buf.write_u8(10).is_err());
+    }
+
+    #[test]
+    fn test_clear_reuses_storage() {
+        let mut buf = MessageBuffer::with_capacity(4);
+        buf.write_u32(1).unwrap();
+        buf.clear();
+        assert!(buf.is_empty());
+
+        buf.write_u32(2).unwrap();
+        assert_eq!(buf.as_slice(), &[2, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_serialize_into_buffer_eq() {
+        let cmd = ServerCmd::Particle {
+            origin: Vector3::new(8.0, -16.0, 32.0),
+            direction: Vector3::new(0.0, 0.0, 1.0),
+            count: 20,
+            color: 73,
+        };
+
+        let mut expected = Vec::new();
+        cmd.serialize(&mut expected).unwrap();
+
+        let mut buf = MessageBuffer::with_capacity(64);
+        cmd.serialize(&mut buf).unwrap();
+        assert_eq!(buf.as_slice(), expected.as_slice());
+
+        let mut reader = BufReader::new(buf.as_slice());
+        assert_eq!(ServerCmd::deserialize(&mut reader).unwrap(), Some(cmd));
+    }
+}
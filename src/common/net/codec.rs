@@ -0,0 +1,296 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Wire encodings of coordinates and angles.
+//!
+//! Protocols differ in how precisely they send positions and orientations. Rather than branching
+//! on the protocol wherever a coordinate or angle is read or written, commands are serialized
+//! with a [`WireCodec`](struct.WireCodec.html) chosen once for the connection.
+
+use crate::common::net::NetError;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use cgmath::{Deg, Vector3};
+
+/// The encoding of a coordinate.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CoordFormat {
+    /// 13.3 fixed-point in 16 bits, limited to ±4096 units at a precision of 1/8 unit.
+    Fixed16,
+
+    /// A 32-bit float.
+    Float,
+}
+
+impl CoordFormat {
+    /// Returns the number of bytes taken by an encoded coordinate.
+    pub fn size(self) -> usize {
+        match self {
+            CoordFormat::Fixed16 => 2,
+            CoordFormat::Float => 4,
+        }
+    }
+
+    /// Returns the largest absolute coordinate this format can represent.
+    pub fn max_coord(self) -> f32 {
+        match self {
+            CoordFormat::Fixed16 => 4096.0,
+            CoordFormat::Float => ::std::f32::MAX,
+        }
+    }
+
+    pub fn read<R>(self, reader: &mut R) -> Result<f32, NetError>
+    where
+        R: ReadBytesExt,
+    {
+        Ok(match self {
+            CoordFormat::Fixed16 => reader.read_i16::<LittleEndian>()? as f32 / 8.0,
+            CoordFormat::Float => reader.read_f32::<LittleEndian>()?,
+        })
+    }
+
+    pub fn write<W>(self, writer: &mut W, coord: f32) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        match self {
+            CoordFormat::Fixed16 => writer.write_i16::<LittleEndian>((coord * 8.0) as i16)?,
+            CoordFormat::Float => writer.write_f32::<LittleEndian>(coord)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// The encoding of an angle.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AngleFormat {
+    /// A single byte, at a precision of 360/256 degrees.
+    Byte,
+
+    /// 16 bits, at a precision of 360/65536 degrees.
+    Short,
+
+    /// A 32-bit float in degrees.
+    Float,
+}
+
+impl AngleFormat {
+    /// Returns the number of bytes taken by an encoded angle.
+    pub fn size(self) -> usize {
+        match self {
+            AngleFormat::Byte => 1,
+            AngleFormat::Short => 2,
+            AngleFormat::Float => 4,
+        }
+    }
+
+    pub fn read<R>(self, reader: &mut R) -> Result<Deg<f32>, NetError>
+    where
+        R: ReadBytesExt,
+    {
+        Ok(Deg(match self {
+            AngleFormat::Byte => reader.read_i8()? as f32 * (360.0 / 256.0),
+            AngleFormat::Short => reader.read_i16::<LittleEndian>()? as f32 * (360.0 / 65536.0),
+            AngleFormat::Float => reader.read_f32::<LittleEndian>()?,
+        }))
+    }
+
+    pub fn write<W>(self, writer: &mut W, angle: Deg<f32>) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        match self {
+            AngleFormat::Byte => writer.write_u8(((angle.0 as i32 * 256 / 360) & 0xFF) as u8)?,
+            AngleFormat::Short => writer
+                .write_u16::<LittleEndian>(((angle.0 * 65536.0 / 360.0) as i32 & 0xFFFF) as u16)?,
+            AngleFormat::Float => writer.write_f32::<LittleEndian>(angle.0)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// The encodings of coordinates and angles used by a connection.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct WireCodec {
+    pub coord: CoordFormat,
+    pub angle: AngleFormat,
+}
+
+impl WireCodec {
+    /// The encodings of the original protocol, which QuakeWorld also uses.
+    pub const NETQUAKE: WireCodec = WireCodec {
+        coord: CoordFormat::Fixed16,
+        angle: AngleFormat::Byte,
+    };
+
+    pub fn read_coord<R>(self, reader: &mut R) -> Result<f32, NetError>
+    where
+        R: ReadBytesExt,
+    {
+        self.coord.read(reader)
+    }
+
+    pub fn read_coord_vector3<R>(self, reader: &mut R) -> Result<Vector3<f32>, NetError>
+    where
+        R: ReadBytesExt,
+    {
+        Ok(Vector3::new(
+            self.read_coord(reader)?,
+            self.read_coord(reader)?,
+            self.read_coord(reader)?,
+        ))
+    }
+
+    pub fn write_coord<W>(self, writer: &mut W, coord: f32) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        self.coord.write(writer, coord)
+    }
+
+    pub fn write_coord_vector3<W>(
+        self,
+        writer: &mut W,
+        coords: Vector3<f32>,
+    ) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        for coord in &coords[..] {
+            self.write_coord(writer, *coord)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_angle<R>(self, reader: &mut R) -> Result<Deg<f32>, NetError>
+    where
+        R: ReadBytesExt,
+    {
+        self.angle.read(reader)
+    }
+
+    pub fn read_angle_vector3<R>(self, reader: &mut R) -> Result<Vector3<Deg<f32>>, NetError>
+    where
+        R: ReadBytesExt,
+    {
+        Ok(Vector3::new(
+            self.read_angle(reader)?,
+            self.read_angle(reader)?,
+            self.read_angle(reader)?,
+        ))
+    }
+
+    pub fn write_angle<W>(self, writer: &mut W, angle: Deg<f32>) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        self.angle.write(writer, angle)
+    }
+
+    pub fn write_angle_vector3<W>(
+        self,
+        writer: &mut W,
+        angles: Vector3<Deg<f32>>,
+    ) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        for angle in &angles[..] {
+            self.write_angle(writer, *angle)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for WireCodec {
+    fn default() -> WireCodec {
+        WireCodec::NETQUAKE
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn round_trip_coord(format: CoordFormat, coord: f32) -> f32 {
+        let mut data = Vec::new();
+        format.write(&mut data, coord).unwrap();
+        assert_eq!(data.len(), format.size());
+        format.read(&mut Cursor::new(data)).unwrap()
+    }
+
+    fn round_trip_angle(format: AngleFormat, angle: f32) -> f32 {
+        let mut data = Vec::new();
+        format.write(&mut data, Deg(angle)).unwrap();
+        assert_eq!(data.len(), format.size());
+        format.read(&mut Cursor::new(data)).unwrap().0
+    }
+
+    #[test]
+    fn test_coord_precision() {
+        assert_eq!(round_trip_coord(CoordFormat::Fixed16, 100.125), 100.125);
+        assert_eq!(round_trip_coord(CoordFormat::Fixed16, -100.1), -100.0);
+        assert_eq!(round_trip_coord(CoordFormat::Float, 5000.3), 5000.3);
+    }
+
+    #[test]
+    fn test_angle_precision() {
+        assert_eq!(round_trip_angle(AngleFormat::Byte, 90.0), 90.0);
+        assert_eq!(round_trip_angle(AngleFormat::Byte, 91.0), 90.0);
+        assert!((round_trip_angle(AngleFormat::Short, 91.0) - 91.0).abs() < 0.01);
+        assert_eq!(round_trip_angle(AngleFormat::Float, 91.25), 91.25);
+
+        // angles past 180 degrees wrap around to negative values
+        assert_eq!(round_trip_angle(AngleFormat::Byte, 270.0), -90.0);
+    }
+
+    #[test]
+    fn test_codec_vectors() {
+        let codec = WireCodec {
+            coord: CoordFormat::Float,
+            angle: AngleFormat::Short,
+        };
+
+        let mut data = Vec::new();
+        codec
+            .write_coord_vector3(&mut data, Vector3::new(1.5, -2.0, 8192.0))
+            .unwrap();
+        codec
+            .write_angle_vector3(&mut data, Vector3::new(Deg(0.0), Deg(45.0), Deg(-90.0)))
+            .unwrap();
+        assert_eq!(data.len(), 3 * 4 + 3 * 2);
+
+        let mut reader = Cursor::new(data);
+        assert_eq!(
+            codec.read_coord_vector3(&mut reader).unwrap(),
+            Vector3::new(1.5, -2.0, 8192.0)
+        );
+        assert_eq!(
+            codec.read_angle_vector3(&mut reader).unwrap(),
+            Vector3::new(Deg(0.0), Deg(45.0), Deg(-90.0))
+        );
+    }
+}
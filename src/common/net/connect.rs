@@ -19,7 +19,7 @@
 // SOFTWARE.
 
 use std::{
-    io::{BufReader, Cursor, ErrorKind},
+    io::{BufRead, BufReader, Cursor, ErrorKind},
     mem::size_of,
     net::{SocketAddr, ToSocketAddrs, UdpSocket},
 };
@@ -37,6 +37,10 @@ pub const CONNECT_PROTOCOL_VERSION: u8 = 3;
 const CONNECT_CONTROL: i32 = 1 << 31;
 const CONNECT_LENGTH_MASK: i32 = 0x0000FFFF;
 
+/// The control value that begins connectionless packets, such as those exchanged with master
+/// servers.
+pub const OUT_OF_BAND_CONTROL: i32 = -1;
+
 pub trait ConnectPacket {
     /// Returns the numeric value of this packet's code.
     fn code(&self) -> u8;
@@ -92,6 +96,11 @@ pub enum RequestCode {
 pub struct RequestConnect {
     pub game_name: String,
     pub proto_ver: u8,
+
+    /// The challenge sent by the server in response to an earlier request, if any.
+    ///
+    /// This is appended to the original request, so servers that don't issue challenges ignore it.
+    pub challenge: Option<i32>,
 }
 
 impl ConnectPacket for RequestConnect {
@@ -108,6 +117,11 @@ impl ConnectPacket for RequestConnect {
         // protocol version
         len += size_of::<u8>();
 
+        // challenge
+        if self.challenge.is_some() {
+            len += size_of::<i32>();
+        }
+
         len
     }
 
@@ -118,6 +132,9 @@ impl ConnectPacket for RequestConnect {
         writer.write(self.game_name.as_bytes())?;
         writer.write_u8(0)?;
         writer.write_u8(self.proto_ver)?;
+        if let Some(challenge) = self.challenge {
+            writer.write_i32::<LittleEndian>(challenge)?;
+        }
         Ok(())
     }
 }
@@ -206,13 +223,14 @@ pub enum Request {
 }
 
 impl Request {
-    pub fn connect<S>(game_name: S, proto_ver: u8) -> Request
+    pub fn connect<S>(game_name: S, proto_ver: u8, challenge: Option<i32>) -> Request
     where
         S: AsRef<str>,
     {
         Request::Connect(RequestConnect {
             game_name: game_name.as_ref().to_owned(),
             proto_ver,
+            challenge,
         })
     }
 
@@ -281,6 +299,7 @@ pub enum ResponseCode {
     ServerInfo = 0x83,
     PlayerInfo = 0x84,
     RuleInfo = 0x85,
+    Challenge = 0x86,
 }
 
 #[derive(Debug)]
@@ -307,6 +326,34 @@ impl ConnectPacket for ResponseAccept {
     }
 }
 
+/// A challenge the client must include in its connection request before it will be accepted.
+///
+/// Only a client that can receive packets at the address it claims can learn the challenge, so
+/// this prevents connections from spoofed addresses.
+#[derive(Debug)]
+pub struct ResponseChallenge {
+    pub challenge: i32,
+}
+
+impl ConnectPacket for ResponseChallenge {
+    fn code(&self) -> u8 {
+        ResponseCode::Challenge as u8
+    }
+
+    fn content_len(&self) -> usize {
+        // challenge
+        size_of::<i32>()
+    }
+
+    fn write_content<W>(&self, writer: &mut W) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        writer.write_i32::<LittleEndian>(self.challenge)?;
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct ResponseReject {
     pub message: String,
@@ -443,6 +490,10 @@ impl ConnectPacket for ResponsePlayerInfo {
     }
 }
 
+/// The name and value of a server rule.
+///
+/// A response with an empty `cvar_name` marks the end of the rule list, and is sent without either
+/// string, as in the original engine.
 #[derive(Debug)]
 pub struct ResponseRuleInfo {
     pub cvar_name: String,
@@ -455,6 +506,10 @@ impl ConnectPacket for ResponseRuleInfo {
     }
 
     fn content_len(&self) -> usize {
+        if self.cvar_name.is_empty() {
+            return 0;
+        }
+
         let mut len = 0;
 
         // cvar name and terminating zero byte
@@ -470,6 +525,10 @@ impl ConnectPacket for ResponseRuleInfo {
     where
         W: WriteBytesExt,
     {
+        if self.cvar_name.is_empty() {
+            return Ok(());
+        }
+
         writer.write(self.cvar_name.as_bytes())?;
         writer.write_u8(0)?;
         writer.write(self.cvar_val.as_bytes())?;
@@ -485,6 +544,7 @@ pub enum Response {
     ServerInfo(ResponseServerInfo),
     PlayerInfo(ResponsePlayerInfo),
     RuleInfo(ResponseRuleInfo),
+    Challenge(ResponseChallenge),
 }
 
 impl ConnectPacket for Response {
@@ -496,6 +556,7 @@ impl ConnectPacket for Response {
             ServerInfo(ref s) => s.code(),
             PlayerInfo(ref p) => p.code(),
             RuleInfo(ref r) => r.code(),
+            Challenge(ref c) => c.code(),
         }
     }
 
@@ -507,6 +568,7 @@ impl ConnectPacket for Response {
             ServerInfo(ref s) => s.content_len(),
             PlayerInfo(ref p) => p.content_len(),
             RuleInfo(ref r) => r.content_len(),
+            Challenge(ref c) => c.content_len(),
         }
     }
 
@@ -521,10 +583,21 @@ impl ConnectPacket for Response {
             ServerInfo(ref s) => s.write_content(writer),
             PlayerInfo(ref p) => p.write_content(writer),
             RuleInfo(ref r) => r.write_content(writer),
+            Challenge(ref c) => c.write_content(writer),
         }
     }
 }
 
+/// A packet received by a `ConnectListener`.
+#[derive(Debug)]
+pub enum ListenerPacket {
+    /// A connection request.
+    Request(Request),
+
+    /// A connectionless packet, with its control value removed.
+    OutOfBand(Vec<u8>),
+}
+
 /// A socket that listens for new connections or queries.
 pub struct ConnectListener {
     socket: UdpSocket,
@@ -541,8 +614,32 @@ impl ConnectListener {
         Ok(ConnectListener { socket })
     }
 
+    /// Returns the local address this listener is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, NetError> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Sets whether `recv_request` should block until a request arrives.
+    ///
+    /// In nonblocking mode, `recv_request` returns an I/O error of kind `WouldBlock` if no request
+    /// is pending.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), NetError> {
+        self.socket.set_nonblocking(nonblocking)?;
+        Ok(())
+    }
+
     /// Receives a request and returns it along with its remote address.
+    ///
+    /// Connectionless packets are returned as errors. Use `recv_packet` to receive them.
     pub fn recv_request(&self) -> Result<(Request, SocketAddr), NetError> {
+        match self.recv_packet()? {
+            (ListenerPacket::Request(request), remote) => Ok((request, remote)),
+            (ListenerPacket::OutOfBand(_), _) => Err(NetError::with_msg("Control value is -1")),
+        }
+    }
+
+    /// Receives a request or connectionless packet and returns it along with its remote address.
+    pub fn recv_packet(&self) -> Result<(ListenerPacket, SocketAddr), NetError> {
         // Original engine receives connection requests in `net_message`,
         // allocated at https://github.com/id-Software/Quake/blob/master/WinQuake/net_main.c#L851
         let mut recv_buf = [0u8; MAX_MESSAGE];
@@ -551,9 +648,11 @@ impl ConnectListener {
 
         let control = reader.read_i32::<NetworkEndian>()?;
 
-        // TODO: figure out what a control value of -1 means
-        if control == -1 {
-            return Err(NetError::with_msg("Control value is -1"));
+        if control == OUT_OF_BAND_CONTROL {
+            return Ok((
+                ListenerPacket::OutOfBand(recv_buf[size_of::<i32>()..len].to_vec()),
+                remote,
+            ));
         }
 
         // high 4 bits must be 0x8000 (CONNECT_CONTROL)
@@ -589,9 +688,14 @@ impl ConnectListener {
             RequestCode::Connect => {
                 let game_name = util::read_cstring(&mut reader).unwrap();
                 let proto_ver = reader.read_u8()?;
+                let challenge = match reader.fill_buf()?.len() {
+                    0 => None,
+                    _ => Some(reader.read_i32::<LittleEndian>()?),
+                };
                 Request::Connect(RequestConnect {
                     game_name,
                     proto_ver,
+                    challenge,
                 })
             }
 
@@ -611,13 +715,22 @@ impl ConnectListener {
             }
         };
 
-        Ok((request, remote))
+        Ok((ListenerPacket::Request(request), remote))
     }
 
     pub fn send_response(&self, response: Response, remote: SocketAddr) -> Result<(), NetError> {
         self.socket.send_to(&response.to_bytes()?, remote)?;
         Ok(())
     }
+
+    /// Sends a connectionless packet, prefixing `data` with the out-of-band control value.
+    pub fn send_out_of_band(&self, data: &[u8], remote: SocketAddr) -> Result<(), NetError> {
+        let mut packet = Vec::with_capacity(size_of::<i32>() + data.len());
+        packet.write_i32::<NetworkEndian>(OUT_OF_BAND_CONTROL)?;
+        packet.extend_from_slice(data);
+        self.socket.send_to(&packet, remote)?;
+        Ok(())
+    }
 }
 
 pub struct ConnectSocket {
@@ -732,8 +845,45 @@ impl ConnectSocket {
                 })
             }
 
-            ResponseCode::PlayerInfo => unimplemented!(),
-            ResponseCode::RuleInfo => unimplemented!(),
+            ResponseCode::PlayerInfo => {
+                let player_id = reader.read_u8()?;
+                let player_name = util::read_cstring(&mut reader).unwrap();
+                let colors = reader.read_i32::<LittleEndian>()?;
+                let frags = reader.read_i32::<LittleEndian>()?;
+                let connect_duration = reader.read_i32::<LittleEndian>()?;
+                let address = util::read_cstring(&mut reader).unwrap();
+
+                Response::PlayerInfo(ResponsePlayerInfo {
+                    player_id,
+                    player_name,
+                    colors,
+                    frags,
+                    connect_duration,
+                    address,
+                })
+            }
+
+            ResponseCode::RuleInfo => {
+                // an empty response marks the end of the rule list
+                if reader.fill_buf()?.is_empty() {
+                    Response::RuleInfo(ResponseRuleInfo {
+                        cvar_name: String::new(),
+                        cvar_val: String::new(),
+                    })
+                } else {
+                    let cvar_name = util::read_cstring(&mut reader).unwrap();
+                    let cvar_val = util::read_cstring(&mut reader).unwrap();
+                    Response::RuleInfo(ResponseRuleInfo {
+                        cvar_name,
+                        cvar_val,
+                    })
+                }
+            }
+
+            ResponseCode::Challenge => {
+                let challenge = reader.read_i32::<LittleEndian>()?;
+                Response::Challenge(ResponseChallenge { challenge })
+            }
         };
 
         Ok(Some((response, remote)))
@@ -753,6 +903,16 @@ mod test {
         let request_connect = RequestConnect {
             game_name: String::from("QUAKE"),
             proto_ver: CONNECT_PROTOCOL_VERSION,
+            challenge: None,
+        };
+
+        let packet_len = request_connect.packet_len() as usize;
+        let packet = request_connect.to_bytes().unwrap();
+        assert_eq!(packet_len, packet.len());
+
+        let request_connect = RequestConnect {
+            challenge: Some(0x1234_5678),
+            ..request_connect
         };
 
         let packet_len = request_connect.packet_len() as usize;
@@ -796,6 +956,16 @@ mod test {
         assert_eq!(packet_len, packet.len());
     }
 
+    #[test]
+    fn test_response_challenge_packet_len() {
+        let response_challenge = ResponseChallenge {
+            challenge: 0x1234_5678,
+        };
+        let packet_len = response_challenge.packet_len() as usize;
+        let packet = response_challenge.to_bytes().unwrap();
+        assert_eq!(packet_len, packet.len());
+    }
+
     #[test]
     fn test_response_reject_packet_len() {
         let response_reject = ResponseReject {
@@ -836,8 +1006,44 @@ mod test {
         assert_eq!(packet_len, packet.len());
     }
 
+    #[test]
+    fn test_response_rule_info_packet_len() {
+        let response_rule_info = ResponseRuleInfo {
+            cvar_name: String::from("sv_gravity"),
+            cvar_val: String::from("800"),
+        };
+        let packet_len = response_rule_info.packet_len() as usize;
+        let packet = response_rule_info.to_bytes().unwrap();
+        assert_eq!(packet_len, packet.len());
+
+        // the end of the list is just the header and response code
+        let response_rule_info = ResponseRuleInfo {
+            cvar_name: String::new(),
+            cvar_val: String::new(),
+        };
+        assert_eq!(response_rule_info.to_bytes().unwrap().len(), 5);
+    }
+
     #[test]
     fn test_connect_listener_bind() {
         let _listener = ConnectListener::bind("127.0.0.1:26000").unwrap();
     }
+
+    #[test]
+    fn test_connect_listener_out_of_band() {
+        let listener = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let sender = ConnectListener::bind("127.0.0.1:0").unwrap();
+
+        sender
+            .send_out_of_band(b"getinfo abc", listener.local_addr().unwrap())
+            .unwrap();
+
+        match listener.recv_packet().unwrap() {
+            (ListenerPacket::OutOfBand(data), remote) => {
+                assert_eq!(data, b"getinfo abc");
+                assert_eq!(remote, sender.local_addr().unwrap());
+            }
+            (p, _) => panic!("expected out-of-band packet, got {:?}", p),
+        }
+    }
 }
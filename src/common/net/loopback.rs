@@ -0,0 +1,589 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! An in-memory network for testing.
+//!
+//! A `LoopbackNetwork` carries datagrams between `QSocket`s without touching real sockets. It
+//! runs on a simulated clock that only moves when `advance` is called, so a test can run many
+//! seconds of play in an instant and get the same result every time. Packets can be delayed and
+//! dropped to test how the protocol copes with a bad connection.
+//!
+//! A `Simulation` runs a server and a client against each other over a loopback network, calling
+//! each host's frame function at a fixed rate the way the host loop does. The hosts can be the
+//! real server and client, or the scripted hosts used to test one of them alone.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    error::Error,
+    io,
+    net::SocketAddr,
+    rc::Rc,
+    time::{Duration as StdDuration, Instant},
+};
+
+use crate::common::net::{BlockingMode, QSocket, Transport};
+
+use chrono::Duration;
+
+/// The address of the server end of a socket pair.
+pub const SERVER_ADDR: &str = "10.0.0.1:26000";
+
+/// The address of the client end of a socket pair.
+pub const CLIENT_ADDR: &str = "10.0.0.2:27001";
+
+struct Packet {
+    from: SocketAddr,
+    to: SocketAddr,
+    data: Vec<u8>,
+    arrival: StdDuration,
+}
+
+struct Network {
+    start: Instant,
+    elapsed: StdDuration,
+    latency: StdDuration,
+
+    // if Some(n), every nth packet sent is lost
+    drop_every: Option<usize>,
+    sent: usize,
+    dropped: usize,
+
+    in_flight: VecDeque<Packet>,
+}
+
+/// A simulated network on which packets take a fixed time to arrive.
+#[derive(Clone)]
+pub struct LoopbackNetwork {
+    network: Rc<RefCell<Network>>,
+}
+
+impl LoopbackNetwork {
+    /// Creates a network on which each packet takes `latency` to arrive.
+    pub fn new(latency: Duration) -> LoopbackNetwork {
+        LoopbackNetwork {
+            network: Rc::new(RefCell::new(Network {
+                start: Instant::now(),
+                elapsed: StdDuration::from_secs(0),
+                latency: latency.to_std().unwrap(),
+                drop_every: None,
+                sent: 0,
+                dropped: 0,
+                in_flight: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Loses every `n`th packet sent from now on, or none if `n` is `None`.
+    ///
+    /// Losing packets on a fixed schedule rather than at random keeps tests repeatable.
+    pub fn set_loss(&self, n: Option<usize>) {
+        self.network.borrow_mut().drop_every = n.filter(|n| *n > 0);
+    }
+
+    /// Returns the number of packets lost so far.
+    pub fn dropped(&self) -> usize {
+        self.network.borrow().dropped
+    }
+
+    /// Returns the time since the network was created.
+    pub fn time(&self) -> Duration {
+        Duration::from_std(self.network.borrow().elapsed).unwrap()
+    }
+
+    /// Moves the clock forward, delivering the packets that arrive in that time.
+    pub fn advance(&self, duration: Duration) {
+        self.network.borrow_mut().elapsed += duration.to_std().unwrap();
+    }
+
+    /// Returns a socket bound to `addr`.
+    pub fn socket(&self, addr: SocketAddr) -> LoopbackSocket {
+        LoopbackSocket {
+            addr,
+            network: self.network.clone(),
+        }
+    }
+
+    /// Returns a connected pair of sockets, the first at `SERVER_ADDR` and the second at
+    /// `CLIENT_ADDR`.
+    pub fn pair(&self) -> (QSocket, QSocket) {
+        let server_addr: SocketAddr = SERVER_ADDR.parse().unwrap();
+        let client_addr: SocketAddr = CLIENT_ADDR.parse().unwrap();
+
+        (
+            QSocket::with_transport(Box::new(self.socket(server_addr)), client_addr),
+            QSocket::with_transport(Box::new(self.socket(client_addr)), server_addr),
+        )
+    }
+}
+
+/// One end of a `LoopbackNetwork`.
+pub struct LoopbackSocket {
+    addr: SocketAddr,
+    network: Rc<RefCell<Network>>,
+}
+
+impl Transport for LoopbackSocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let mut network = self.network.borrow_mut();
+        network.sent += 1;
+
+        if let Some(n) = network.drop_every {
+            if network.sent % n == 0 {
+                network.dropped += 1;
+                return Ok(buf.len());
+            }
+        }
+
+        let arrival = network.elapsed + network.latency;
+        network.in_flight.push_back(Packet {
+            from: self.addr,
+            to: addr,
+            data: buf.to_vec(),
+            arrival,
+        });
+
+        Ok(buf.len())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut network = self.network.borrow_mut();
+        let elapsed = network.elapsed;
+        let index = network
+            .in_flight
+            .iter()
+            .position(|p| p.to == self.addr && p.arrival <= elapsed);
+
+        // there's nothing to wait for, since time only passes between frames
+        let packet = match index.and_then(|i| network.in_flight.remove(i)) {
+            Some(p) => p,
+            None => return Err(io::ErrorKind::WouldBlock.into()),
+        };
+
+        let len = packet.data.len().min(buf.len());
+        buf[..len].copy_from_slice(&packet.data[..len]);
+        Ok((len, packet.from))
+    }
+
+    fn set_blocking_mode(&self, _mode: &BlockingMode) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn now(&self) -> Instant {
+        let network = self.network.borrow();
+        network.start + network.elapsed
+    }
+}
+
+/// One side of a `Simulation`.
+///
+/// A host owns its end of the connection, which it is given before the simulation starts.
+pub trait SimHost {
+    /// Runs one frame of `frame_time` at simulated time `time`.
+    fn frame(&mut self, time: Duration, frame_time: Duration) -> Result<(), Box<dyn Error>>;
+}
+
+/// A server and a client connected over a loopback network.
+pub struct Simulation<S, C> {
+    network: LoopbackNetwork,
+    server: S,
+    client: C,
+}
+
+impl<S, C> Simulation<S, C>
+where
+    S: SimHost,
+    C: SimHost,
+{
+    /// Creates a simulation of `server` and `client`, which should be connected by a socket pair
+    /// from `network`.
+    pub fn new(network: LoopbackNetwork, server: S, client: C) -> Simulation<S, C> {
+        Simulation {
+            network,
+            server,
+            client,
+        }
+    }
+
+    pub fn network(&self) -> &LoopbackNetwork {
+        &self.network
+    }
+
+    pub fn server(&self) -> &S {
+        &self.server
+    }
+
+    pub fn server_mut(&mut self) -> &mut S {
+        &mut self.server
+    }
+
+    pub fn client(&self) -> &C {
+        &self.client
+    }
+
+    pub fn client_mut(&mut self) -> &mut C {
+        &mut self.client
+    }
+
+    /// Runs both hosts for `duration`, one frame every `frame_time`.
+    ///
+    /// Each frame the server runs first, then the client, then the clock moves on.
+    pub fn run_for(
+        &mut self,
+        duration: Duration,
+        frame_time: Duration,
+    ) -> Result<(), Box<dyn Error>> {
+        let end = self.network.time() + duration;
+        while self.network.time() < end {
+            let time = self.network.time();
+            self.server.frame(time, frame_time)?;
+            self.client.frame(time, frame_time)?;
+            self.network.advance(frame_time);
+        }
+
+        Ok(())
+    }
+}
+
+/// Hosts that follow a script, to run against a real host in tests.
+#[cfg(test)]
+pub(crate) mod script {
+    use super::*;
+
+    use std::io::{BufReader, Cursor};
+
+    use crate::common::net::{
+        ButtonFlags, ClientCmd, GameType, NetError, ServerCmd, SignOnStage, PROTOCOL_VERSION,
+    };
+
+    use cgmath::{Deg, Vector3};
+
+    fn read_server_cmds(msg: &[u8]) -> Vec<ServerCmd> {
+        let mut reader = BufReader::new(msg);
+        let mut cmds = Vec::new();
+        while let Some(cmd) = ServerCmd::deserialize(&mut reader).unwrap() {
+            cmds.push(cmd);
+        }
+        cmds
+    }
+
+    fn read_client_cmds(msg: &[u8]) -> Vec<ClientCmd> {
+        let mut reader = Cursor::new(msg);
+        let mut cmds = Vec::new();
+        while (reader.position() as usize) < msg.len() {
+            cmds.push(ClientCmd::deserialize(&mut reader).unwrap());
+        }
+        cmds
+    }
+
+    /// A server that signs the client on, moves its player and notices when it leaves.
+    pub struct ScriptedServer {
+        pub socket: QSocket,
+        pub stage: SignOnStage,
+        // reliable messages waiting for the previous one to be acknowledged
+        queue: VecDeque<Vec<u8>>,
+        pub position: Vector3<f32>,
+        pub moves: usize,
+        pub disconnected: bool,
+    }
+
+    impl ScriptedServer {
+        pub fn new(socket: QSocket) -> ScriptedServer {
+            // a server info message long enough to be split across several packets
+            let mut msg = Vec::new();
+            ServerCmd::ServerInfo {
+                protocol_version: PROTOCOL_VERSION as i32,
+                max_clients: 1,
+                game_type: GameType::CoOp,
+                message: String::from("Loopback"),
+                model_precache: (0..200).map(|i| format!("progs/model{}.mdl", i)).collect(),
+                sound_precache: vec![String::from("misc/null.wav")],
+            }
+            .serialize(&mut msg)
+            .unwrap();
+            ServerCmd::SignOnStage {
+                stage: SignOnStage::Prespawn,
+            }
+            .serialize(&mut msg)
+            .unwrap();
+
+            ScriptedServer {
+                socket,
+                stage: SignOnStage::Not,
+                queue: vec![msg].into(),
+                position: Vector3::new(0.0, 0.0, 0.0),
+                moves: 0,
+                disconnected: false,
+            }
+        }
+
+        fn send_stage(&mut self, stage: SignOnStage) {
+            let mut msg = Vec::new();
+            ServerCmd::SignOnStage { stage }
+                .serialize(&mut msg)
+                .unwrap();
+            self.queue.push_back(msg);
+            self.stage = stage;
+        }
+    }
+
+    impl SimHost for ScriptedServer {
+        fn frame(&mut self, _time: Duration, _frame_time: Duration) -> Result<(), Box<dyn Error>> {
+            loop {
+                let msg = self.socket.recv_msg(BlockingMode::NonBlocking)?;
+                if msg.is_empty() {
+                    break;
+                }
+
+                for cmd in read_client_cmds(&msg) {
+                    match cmd {
+                        ClientCmd::StringCmd { cmd } => match cmd.split_whitespace().next() {
+                            Some("prespawn") => self.send_stage(SignOnStage::ClientInfo),
+                            Some("spawn") => self.send_stage(SignOnStage::Begin),
+                            Some("begin") => self.stage = SignOnStage::Done,
+                            _ => (),
+                        },
+
+                        ClientCmd::Move {
+                            fwd_move, angles, ..
+                        } if self.stage == SignOnStage::Done => {
+                            // 72 frames per second at the requested speed, along the yaw
+                            let yaw = cgmath::Rad::from(angles.y);
+                            let step = fwd_move as f32 / 72.0;
+                            self.position += Vector3::new(yaw.0.cos(), yaw.0.sin(), 0.0) * step;
+                            self.moves += 1;
+                        }
+
+                        ClientCmd::Disconnect => self.disconnected = true,
+                        _ => (),
+                    }
+                }
+            }
+
+            if self.socket.can_send() {
+                if let Some(msg) = self.queue.pop_front() {
+                    self.socket.begin_send_msg(&msg)?;
+                }
+            }
+
+            self.socket.resend_unacked()?;
+            Ok(())
+        }
+    }
+
+    /// A client that answers the server's sign-on stages like the real one and then runs forward,
+    /// along +y.
+    pub struct ScriptedClient {
+        pub socket: QSocket,
+        pub stage: SignOnStage,
+        pub model_count: usize,
+        // reliable commands waiting to be sent
+        queue: VecDeque<Vec<u8>>,
+        pub disconnect_at: Option<Duration>,
+        // if set, the client stops running frames, as if it had crashed
+        pub crash_at: Option<Duration>,
+    }
+
+    impl ScriptedClient {
+        pub fn new(socket: QSocket) -> ScriptedClient {
+            ScriptedClient {
+                socket,
+                stage: SignOnStage::Not,
+                model_count: 0,
+                queue: VecDeque::new(),
+                disconnect_at: None,
+                crash_at: None,
+            }
+        }
+
+        fn send_string(&mut self, cmd: &str) {
+            let mut msg = Vec::new();
+            ClientCmd::StringCmd {
+                cmd: cmd.to_owned(),
+            }
+            .serialize(&mut msg)
+            .unwrap();
+            self.queue.push_back(msg);
+        }
+
+        fn send_move(&mut self, time: Duration) -> Result<(), NetError> {
+            let mut msg = Vec::new();
+            ClientCmd::Move {
+                send_time: time,
+                angles: Vector3::new(Deg(0.0), Deg(90.0), Deg(0.0)),
+                fwd_move: 320,
+                side_move: 0,
+                up_move: 0,
+                button_flags: ButtonFlags::empty(),
+                impulse: 0,
+            }
+            .serialize(&mut msg)
+            .unwrap();
+            self.socket.send_msg_unreliable(&msg)
+        }
+    }
+
+    impl SimHost for ScriptedClient {
+        fn frame(&mut self, time: Duration, _frame_time: Duration) -> Result<(), Box<dyn Error>> {
+            if self.crash_at.map_or(false, |t| time >= t) {
+                return Ok(());
+            }
+
+            loop {
+                let msg = self.socket.recv_msg(BlockingMode::NonBlocking)?;
+                if msg.is_empty() {
+                    break;
+                }
+
+                for cmd in read_server_cmds(&msg) {
+                    match cmd {
+                        ServerCmd::ServerInfo { model_precache, .. } => {
+                            self.model_count = model_precache.len();
+                        }
+
+                        ServerCmd::SignOnStage { stage } => {
+                            self.stage = stage;
+                            match stage {
+                                SignOnStage::Prespawn => self.send_string("prespawn"),
+                                SignOnStage::ClientInfo => self.send_string("spawn "),
+                                SignOnStage::Begin => {
+                                    self.send_string("begin");
+                                    self.stage = SignOnStage::Done;
+                                }
+                                _ => (),
+                            }
+                        }
+
+                        _ => (),
+                    }
+                }
+            }
+
+            if self.disconnect_at.map_or(false, |t| time >= t) {
+                self.disconnect_at = None;
+                let mut msg = Vec::new();
+                ClientCmd::Disconnect.serialize(&mut msg).unwrap();
+                self.queue.push_back(msg);
+            }
+
+            if self.socket.can_send() {
+                if let Some(msg) = self.queue.pop_front() {
+                    self.socket.begin_send_msg(&msg)?;
+                }
+            }
+
+            if self.stage == SignOnStage::Done {
+                self.send_move(time)?;
+            }
+
+            self.socket.resend_unacked()?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        script::{ScriptedClient, ScriptedServer},
+        *,
+    };
+
+    use crate::common::net::SignOnStage;
+
+    fn ms(n: i64) -> Duration {
+        Duration::milliseconds(n)
+    }
+
+    fn scripted(network: LoopbackNetwork) -> Simulation<ScriptedServer, ScriptedClient> {
+        let (server_socket, client_socket) = network.pair();
+        Simulation::new(
+            network,
+            ScriptedServer::new(server_socket),
+            ScriptedClient::new(client_socket),
+        )
+    }
+
+    #[test]
+    fn test_loopback_delivers_after_latency() {
+        let network = LoopbackNetwork::new(ms(50));
+        let (mut server, mut client) = network.pair();
+
+        client.send_msg_unreliable(b"hello").unwrap();
+        assert!(server.recv_msg(BlockingMode::Blocking).unwrap().is_empty());
+
+        network.advance(ms(50));
+        assert_eq!(server.recv_msg(BlockingMode::Blocking).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_signon_and_movement() {
+        let mut sim = scripted(LoopbackNetwork::new(ms(40)));
+        sim.run_for(Duration::seconds(2), ms(14)).unwrap();
+
+        assert_eq!(sim.client().model_count, 200);
+        assert_eq!(sim.client().stage, SignOnStage::Done);
+        assert_eq!(sim.server().stage, SignOnStage::Done);
+
+        // the player ran along +y once it was in the game
+        let position = sim.server().position;
+        assert!(sim.server().moves > 50);
+        assert!(position.x.abs() < 1.0);
+        assert!(position.y > 200.0, "{:?}", position);
+    }
+
+    #[test]
+    fn test_signon_survives_packet_loss() {
+        let network = LoopbackNetwork::new(ms(40));
+        network.set_loss(Some(3));
+        let mut sim = scripted(network);
+        sim.run_for(Duration::seconds(20), ms(14)).unwrap();
+
+        assert!(sim.network().dropped() > 0);
+        assert_eq!(sim.client().model_count, 200);
+        assert_eq!(sim.server().stage, SignOnStage::Done);
+        assert!(sim.server().moves > 0);
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let mut sim = scripted(LoopbackNetwork::new(ms(40)));
+        sim.client_mut().disconnect_at = Some(Duration::seconds(2));
+
+        sim.run_for(Duration::seconds(1), ms(14)).unwrap();
+        assert!(!sim.server().disconnected);
+
+        sim.run_for(Duration::seconds(2), ms(14)).unwrap();
+        assert!(sim.server().disconnected);
+    }
+
+    #[test]
+    fn test_crashed_client_times_out() {
+        let mut sim = scripted(LoopbackNetwork::new(ms(40)));
+        sim.client_mut().crash_at = Some(Duration::seconds(2));
+
+        sim.run_for(Duration::seconds(3), ms(14)).unwrap();
+        assert!(!sim.server().socket.timed_out(Duration::seconds(5)));
+
+        // no real time passes, but the simulated clock still times the client out
+        sim.run_for(Duration::seconds(5), ms(14)).unwrap();
+        assert!(sim.server().socket.timed_out(Duration::seconds(5)));
+    }
+}
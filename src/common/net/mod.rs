@@ -18,19 +18,31 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-// TODO: need to figure out an equivalence relation for read_/write_coord and read_/write_angle
-
+pub mod buffer;
+pub mod codec;
 pub mod connect;
+pub mod loopback;
+pub mod qw;
+
+#[cfg(test)]
+mod roundtrip;
 
 use std::{
-    collections::VecDeque,
     error::Error,
     fmt,
-    io::{BufRead, BufReader, Cursor, Read, Write},
+    io::{self, BufRead, BufReader, Cursor, Read},
     net::{SocketAddr, UdpSocket},
+    time::{Duration as StdDuration, Instant},
 };
 
-use crate::common::{engine, util};
+use crate::common::{
+    engine,
+    net::{
+        buffer::MessageBuffer,
+        codec::{AngleFormat, CoordFormat, WireCodec},
+    },
+    util,
+};
 
 use byteorder::{LittleEndian, NetworkEndian, ReadBytesExt, WriteBytesExt};
 use cgmath::{Deg, Vector3, Zero};
@@ -44,6 +56,78 @@ const MAX_PACKET: usize = HEADER_SIZE + MAX_DATAGRAM;
 
 pub const PROTOCOL_VERSION: u8 = 15;
 
+// how long to wait for a reliable packet to be acknowledged before sending it again
+const RESEND_INTERVAL: StdDuration = StdDuration::from_secs(1);
+
+/// Limits imposed by a network protocol on the levels it can transmit.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ProtocolLimits {
+    /// The maximum number of precached models.
+    pub max_models: usize,
+
+    /// The maximum number of precached sounds.
+    pub max_sounds: usize,
+
+    /// The maximum number of entities.
+    pub max_entities: usize,
+
+    /// The maximum absolute value of a coordinate on any axis.
+    pub max_coord: f32,
+}
+
+/// Network protocols understood by the engine.
+///
+/// The original protocol (15) sends model and sound indices as single bytes and coordinates as
+/// 13.3 fixed-point values, which limits levels to 256 models and sounds and coordinates within
+/// ±4096 units. FitzQuake (666) extends model, sound and entity indices to 16 bits, and RMQ (999)
+/// additionally sends coordinates as floats and angles as 16-bit values.
+#[derive(Copy, Clone, Debug, Eq, FromPrimitive, Ord, PartialEq, PartialOrd)]
+pub enum Protocol {
+    NetQuake = 15,
+    FitzQuake = 666,
+    Rmq = 999,
+}
+
+impl Protocol {
+    /// All supported protocols, from least to most capable.
+    pub const ALL: [Protocol; 3] = [Protocol::NetQuake, Protocol::FitzQuake, Protocol::Rmq];
+
+    pub fn version(&self) -> i32 {
+        *self as i32
+    }
+
+    /// Returns the encodings of coordinates and angles used by this protocol.
+    pub fn codec(&self) -> WireCodec {
+        match self {
+            Protocol::NetQuake | Protocol::FitzQuake => WireCodec::NETQUAKE,
+            Protocol::Rmq => WireCodec {
+                coord: CoordFormat::Float,
+                angle: AngleFormat::Short,
+            },
+        }
+    }
+
+    pub fn limits(&self) -> ProtocolLimits {
+        let max_coord = self.codec().coord.max_coord();
+
+        match self {
+            Protocol::NetQuake => ProtocolLimits {
+                max_models: 256,
+                max_sounds: 256,
+                max_entities: 600,
+                max_coord,
+            },
+
+            Protocol::FitzQuake | Protocol::Rmq => ProtocolLimits {
+                max_models: 65536,
+                max_sounds: 65536,
+                max_entities: 32768,
+                max_coord,
+            },
+        }
+    }
+}
+
 const NAME_LEN: usize = 64;
 
 const FAST_UPDATE_FLAG: u8 = 0x80;
@@ -330,7 +414,7 @@ pub enum TempEntity {
 }
 
 impl TempEntity {
-    pub fn read_temp_entity<R>(reader: &mut R) -> Result<TempEntity, NetError>
+    pub fn read_temp_entity<R>(reader: &mut R, codec: WireCodec) -> Result<TempEntity, NetError>
     where
         R: BufRead + ReadBytesExt,
     {
@@ -370,10 +454,10 @@ impl TempEntity {
                     Code::Teleport => PointEntityKind::Teleport,
                     _ => unreachable!(),
                 },
-                origin: read_coord_vector3(reader)?,
+                origin: codec.read_coord_vector3(reader)?,
             },
             Code::ColorExplosion => {
-                let origin = read_coord_vector3(reader)?;
+                let origin = codec.read_coord_vector3(reader)?;
                 let color_start = reader.read_u8()?;
                 let color_len = reader.read_u8()?;
 
@@ -395,19 +479,19 @@ impl TempEntity {
                     },
                 },
                 entity_id: reader.read_i16::<LittleEndian>()?,
-                start: read_coord_vector3(reader)?,
-                end: read_coord_vector3(reader)?,
+                start: codec.read_coord_vector3(reader)?,
+                end: codec.read_coord_vector3(reader)?,
             },
             Code::Grapple => Beam {
                 kind: BeamEntityKind::Grapple,
                 entity_id: reader.read_i16::<LittleEndian>()?,
-                start: read_coord_vector3(reader)?,
-                end: read_coord_vector3(reader)?,
+                start: codec.read_coord_vector3(reader)?,
+                end: codec.read_coord_vector3(reader)?,
             },
         })
     }
 
-    pub fn write_temp_entity<W>(&self, writer: &mut W) -> Result<(), NetError>
+    pub fn write_temp_entity<W>(&self, writer: &mut W, codec: WireCodec) -> Result<(), NetError>
     where
         W: WriteBytesExt,
     {
@@ -446,14 +530,16 @@ impl TempEntity {
                         color_start,
                         color_len,
                     } => {
-                        // write code and colors
+                        // colors follow the origin
                         writer.write_u8(Code::ColorExplosion as u8)?;
+                        codec.write_coord_vector3(writer, origin)?;
                         writer.write_u8(color_start)?;
                         writer.write_u8(color_len)?;
+                        return Ok(());
                     }
                 };
 
-                write_coord_vector3(writer, origin)?;
+                codec.write_coord_vector3(writer, origin)?;
             }
 
             TempEntity::Beam {
@@ -472,10 +558,10 @@ impl TempEntity {
                     },
                     BeamEntityKind::Grapple => Code::Grapple,
                 };
-                writer.write_i16::<LittleEndian>(entity_id)?;
                 writer.write_u8(code as u8)?;
-                write_coord_vector3(writer, start)?;
-                write_coord_vector3(writer, end)?;
+                writer.write_i16::<LittleEndian>(entity_id)?;
+                codec.write_coord_vector3(writer, start)?;
+                codec.write_coord_vector3(writer, end)?;
             }
         }
 
@@ -501,7 +587,7 @@ bitflags! {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct EntityState {
     pub origin: Vector3<f32>,
     pub angles: Vector3<Deg<f32>>,
@@ -567,6 +653,97 @@ impl EntityUpdate {
             colormap: self.colormap.unwrap_or(baseline.colormap),
         }
     }
+
+    /// Create an update that changes an entity from the specified baseline
+    /// state to `state`, leaving out any values that match the baseline.
+    pub fn from_entity_state(
+        ent_id: u16,
+        state: &EntityState,
+        baseline: &EntityState,
+    ) -> EntityUpdate {
+        fn changed<T>(value: T, baseline: T) -> Option<T>
+        where
+            T: PartialEq,
+        {
+            if value != baseline {
+                Some(value)
+            } else {
+                None
+            }
+        }
+
+        EntityUpdate {
+            ent_id,
+            model_id: changed(state.model_id, baseline.model_id).map(|m| m as u8),
+            frame_id: changed(state.frame_id, baseline.frame_id).map(|f| f as u8),
+            colormap: changed(state.colormap, baseline.colormap),
+            skin_id: changed(state.skin_id, baseline.skin_id).map(|s| s as u8),
+            effects: changed(state.effects, baseline.effects),
+            origin_x: changed(state.origin.x, baseline.origin.x),
+            pitch: changed(state.angles.x, baseline.angles.x),
+            origin_y: changed(state.origin.y, baseline.origin.y),
+            yaw: changed(state.angles.y, baseline.angles.y),
+            origin_z: changed(state.origin.z, baseline.origin.z),
+            roll: changed(state.angles.z, baseline.angles.z),
+            no_lerp: false,
+        }
+    }
+
+    fn serialize<W>(&self, writer: &mut W, codec: WireCodec) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        let mut flags = UpdateFlags::empty();
+        flags.set(UpdateFlags::LONG_ENTITY, self.ent_id > 0xFF);
+        flags.set(UpdateFlags::MODEL, self.model_id.is_some());
+        flags.set(UpdateFlags::FRAME, self.frame_id.is_some());
+        flags.set(UpdateFlags::COLORMAP, self.colormap.is_some());
+        flags.set(UpdateFlags::SKIN, self.skin_id.is_some());
+        flags.set(UpdateFlags::EFFECTS, self.effects.is_some());
+        flags.set(UpdateFlags::ORIGIN_X, self.origin_x.is_some());
+        flags.set(UpdateFlags::PITCH, self.pitch.is_some());
+        flags.set(UpdateFlags::ORIGIN_Y, self.origin_y.is_some());
+        flags.set(UpdateFlags::YAW, self.yaw.is_some());
+        flags.set(UpdateFlags::ORIGIN_Z, self.origin_z.is_some());
+        flags.set(UpdateFlags::ROLL, self.roll.is_some());
+        flags.set(UpdateFlags::NO_LERP, self.no_lerp);
+        flags.set(UpdateFlags::MORE_BITS, flags.bits() & 0xFF00 != 0);
+
+        writer.write_u8(FAST_UPDATE_FLAG | flags.bits() as u8)?;
+        if flags.contains(UpdateFlags::MORE_BITS) {
+            writer.write_u8((flags.bits() >> 8) as u8)?;
+        }
+
+        if flags.contains(UpdateFlags::LONG_ENTITY) {
+            writer.write_u16::<LittleEndian>(self.ent_id)?;
+        } else {
+            writer.write_u8(self.ent_id as u8)?;
+        }
+
+        for value in [self.model_id, self.frame_id, self.colormap, self.skin_id]
+            .iter()
+            .flatten()
+        {
+            writer.write_u8(*value)?;
+        }
+
+        if let Some(e) = self.effects {
+            writer.write_u8(e.bits())?;
+        }
+
+        let origin = [self.origin_x, self.origin_y, self.origin_z];
+        let angles = [self.pitch, self.yaw, self.roll];
+        for i in 0..3 {
+            if let Some(o) = origin[i] {
+                codec.write_coord(writer, o)?;
+            }
+            if let Some(a) = angles[i] {
+                codec.write_angle(writer, a)?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 /// A trait for in-game server and client network commands.
@@ -825,7 +1002,21 @@ impl ServerCmd {
         code as u8
     }
 
+    /// Reads a command encoded by the original protocol, returning `None` at the end of the
+    /// message.
     pub fn deserialize<R>(reader: &mut R) -> Result<Option<ServerCmd>, NetError>
+    where
+        R: BufRead + ReadBytesExt,
+    {
+        ServerCmd::deserialize_with(reader, WireCodec::NETQUAKE)
+    }
+
+    /// Reads a command whose coordinates and angles are encoded by `codec`, returning `None` at
+    /// the end of the message.
+    pub fn deserialize_with<R>(
+        reader: &mut R,
+        codec: WireCodec,
+    ) -> Result<Option<ServerCmd>, NetError>
     where
         R: BufRead + ReadBytesExt,
     {
@@ -908,42 +1099,42 @@ impl ServerCmd {
 
             let origin_x;
             if update_flags.contains(UpdateFlags::ORIGIN_X) {
-                origin_x = Some(read_coord(reader)?);
+                origin_x = Some(codec.read_coord(reader)?);
             } else {
                 origin_x = None;
             }
 
             let pitch;
             if update_flags.contains(UpdateFlags::PITCH) {
-                pitch = Some(read_angle(reader)?);
+                pitch = Some(codec.read_angle(reader)?);
             } else {
                 pitch = None;
             }
 
             let origin_y;
             if update_flags.contains(UpdateFlags::ORIGIN_Y) {
-                origin_y = Some(read_coord(reader)?);
+                origin_y = Some(codec.read_coord(reader)?);
             } else {
                 origin_y = None;
             }
 
             let yaw;
             if update_flags.contains(UpdateFlags::YAW) {
-                yaw = Some(read_angle(reader)?);
+                yaw = Some(codec.read_angle(reader)?);
             } else {
                 yaw = None;
             }
 
             let origin_z;
             if update_flags.contains(UpdateFlags::ORIGIN_Z) {
-                origin_z = Some(read_coord(reader)?);
+                origin_z = Some(codec.read_coord(reader)?);
             } else {
                 origin_z = None;
             }
 
             let roll;
             if update_flags.contains(UpdateFlags::ROLL) {
-                roll = Some(read_angle(reader)?);
+                roll = Some(codec.read_angle(reader)?);
             } else {
                 roll = None;
             }
@@ -1035,9 +1226,9 @@ impl ServerCmd {
                 let channel = (entity_channel & 0b111) as i8;
                 let sound_id = reader.read_u8()?;
                 let position = Vector3::new(
-                    read_coord(reader)?,
-                    read_coord(reader)?,
-                    read_coord(reader)?,
+                    codec.read_coord(reader)?,
+                    codec.read_coord(reader)?,
+                    codec.read_coord(reader)?,
                 );
 
                 ServerCmd::Sound {
@@ -1075,9 +1266,9 @@ impl ServerCmd {
 
             ServerCmdCode::SetAngle => {
                 let angles = Vector3::new(
-                    read_angle(reader)?,
-                    read_angle(reader)?,
-                    read_angle(reader)?,
+                    codec.read_angle(reader)?,
+                    codec.read_angle(reader)?,
+                    codec.read_angle(reader)?,
                 );
 
                 ServerCmd::SetAngle { angles }
@@ -1286,7 +1477,7 @@ impl ServerCmd {
             }
 
             ServerCmdCode::Particle => {
-                let origin = read_coord_vector3(reader)?;
+                let origin = codec.read_coord_vector3(reader)?;
 
                 let mut direction = Vector3::zero();
                 for i in 0..3 {
@@ -1307,7 +1498,7 @@ impl ServerCmd {
             ServerCmdCode::Damage => {
                 let armor = reader.read_u8()?;
                 let blood = reader.read_u8()?;
-                let source = read_coord_vector3(reader)?;
+                let source = codec.read_coord_vector3(reader)?;
 
                 ServerCmd::Damage {
                     armor,
@@ -1325,8 +1516,8 @@ impl ServerCmd {
                 let mut origin = Vector3::zero();
                 let mut angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
                 for i in 0..3 {
-                    origin[i] = read_coord(reader)?;
-                    angles[i] = read_angle(reader)?;
+                    origin[i] = codec.read_coord(reader)?;
+                    angles[i] = codec.read_angle(reader)?;
                 }
 
                 ServerCmd::SpawnStatic {
@@ -1349,8 +1540,8 @@ impl ServerCmd {
                 let mut origin = Vector3::zero();
                 let mut angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
                 for i in 0..3 {
-                    origin[i] = read_coord(reader)?;
-                    angles[i] = read_angle(reader)?;
+                    origin[i] = codec.read_coord(reader)?;
+                    angles[i] = codec.read_angle(reader)?;
                 }
 
                 ServerCmd::SpawnBaseline {
@@ -1365,7 +1556,7 @@ impl ServerCmd {
             }
 
             ServerCmdCode::TempEntity => {
-                let temp_entity = TempEntity::read_temp_entity(reader)?;
+                let temp_entity = TempEntity::read_temp_entity(reader, codec)?;
 
                 ServerCmd::TempEntity { temp_entity }
             }
@@ -1408,7 +1599,7 @@ impl ServerCmd {
             ServerCmdCode::FoundSecret => ServerCmd::FoundSecret,
 
             ServerCmdCode::SpawnStaticSound => {
-                let origin = read_coord_vector3(reader)?;
+                let origin = codec.read_coord_vector3(reader)?;
                 let sound_id = reader.read_u8()?;
                 let volume = reader.read_u8()?;
                 let attenuation = reader.read_u8()?;
@@ -1453,10 +1644,24 @@ impl ServerCmd {
         Ok(Some(cmd))
     }
 
+    /// Writes this command as encoded by the original protocol.
     pub fn serialize<W>(&self, writer: &mut W) -> Result<(), NetError>
     where
         W: WriteBytesExt,
     {
+        self.serialize_with(writer, WireCodec::NETQUAKE)
+    }
+
+    /// Writes this command, encoding its coordinates and angles with `codec`.
+    pub fn serialize_with<W>(&self, writer: &mut W, codec: WireCodec) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        // fast updates store their flags in the code byte
+        if let ServerCmd::FastUpdate(ref update) = *self {
+            return update.serialize(writer, codec);
+        }
+
         writer.write_u8(self.code())?;
 
         match *self {
@@ -1510,7 +1715,7 @@ impl ServerCmd {
                 writer.write_u8(sound_id)?;
 
                 for component in 0..3 {
-                    write_coord(writer, position[component])?;
+                    codec.write_coord(writer, position[component])?;
                 }
             }
 
@@ -1526,7 +1731,7 @@ impl ServerCmd {
                 writer.write_u8(0)?;
             }
 
-            ServerCmd::SetAngle { angles } => write_angle_vector3(writer, angles)?,
+            ServerCmd::SetAngle { angles } => codec.write_angle_vector3(writer, angles)?,
 
             ServerCmd::ServerInfo {
                 protocol_version,
@@ -1712,7 +1917,7 @@ impl ServerCmd {
                 count,
                 color,
             } => {
-                write_coord_vector3(writer, origin)?;
+                codec.write_coord_vector3(writer, origin)?;
 
                 for i in 0..3 {
                     writer.write_i8(match direction[i] * PARTICLE_DIRECTION_WRITE_FACTOR {
@@ -1733,7 +1938,7 @@ impl ServerCmd {
             } => {
                 writer.write_u8(armor)?;
                 writer.write_u8(blood)?;
-                write_coord_vector3(writer, source)?;
+                codec.write_coord_vector3(writer, source)?;
             }
 
             ServerCmd::SpawnStatic {
@@ -1750,8 +1955,8 @@ impl ServerCmd {
                 writer.write_u8(skin_id)?;
 
                 for i in 0..3 {
-                    write_coord(writer, origin[i])?;
-                    write_angle(writer, angles[i])?;
+                    codec.write_coord(writer, origin[i])?;
+                    codec.write_angle(writer, angles[i])?;
                 }
             }
 
@@ -1771,13 +1976,13 @@ impl ServerCmd {
                 writer.write_u8(skin_id)?;
 
                 for i in 0..3 {
-                    write_coord(writer, origin[i])?;
-                    write_angle(writer, angles[i])?;
+                    codec.write_coord(writer, origin[i])?;
+                    codec.write_angle(writer, angles[i])?;
                 }
             }
 
             ServerCmd::TempEntity { ref temp_entity } => {
-                temp_entity.write_temp_entity(writer)?;
+                temp_entity.write_temp_entity(writer, codec)?;
             }
 
             ServerCmd::SetPause { paused } => {
@@ -1804,7 +2009,7 @@ impl ServerCmd {
                 volume,
                 attenuation,
             } => {
-                write_coord_vector3(writer, origin)?;
+                codec.write_coord_vector3(writer, origin)?;
                 writer.write_u8(sound_id)?;
                 writer.write_u8(volume)?;
                 writer.write_u8(attenuation)?;
@@ -1829,8 +2034,7 @@ impl ServerCmd {
                 writer.write_u8(0)?;
             }
 
-            // TODO
-            ServerCmd::FastUpdate(_) => unimplemented!(),
+            ServerCmd::FastUpdate(_) => unreachable!(),
         }
 
         Ok(())
@@ -1876,7 +2080,16 @@ impl ClientCmd {
         }
     }
 
+    /// Reads a command encoded by the original protocol.
     pub fn deserialize<R>(reader: &mut R) -> Result<ClientCmd, NetError>
+    where
+        R: ReadBytesExt + BufRead,
+    {
+        ClientCmd::deserialize_with(reader, WireCodec::NETQUAKE)
+    }
+
+    /// Reads a command whose angles are encoded by `codec`.
+    pub fn deserialize_with<R>(reader: &mut R, codec: WireCodec) -> Result<ClientCmd, NetError>
     where
         R: ReadBytesExt + BufRead,
     {
@@ -1898,9 +2111,9 @@ impl ClientCmd {
             ClientCmdCode::Move => {
                 let send_time = engine::duration_from_f32(reader.read_f32::<LittleEndian>()?);
                 let angles = Vector3::new(
-                    read_angle(reader)?,
-                    read_angle(reader)?,
-                    read_angle(reader)?,
+                    codec.read_angle(reader)?,
+                    codec.read_angle(reader)?,
+                    codec.read_angle(reader)?,
                 );
                 let fwd_move = reader.read_i16::<LittleEndian>()?;
                 let side_move = reader.read_i16::<LittleEndian>()?;
@@ -1935,7 +2148,16 @@ impl ClientCmd {
         Ok(cmd)
     }
 
+    /// Writes this command as encoded by the original protocol.
     pub fn serialize<W>(&self, writer: &mut W) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        self.serialize_with(writer, WireCodec::NETQUAKE)
+    }
+
+    /// Writes this command, encoding its angles with `codec`.
+    pub fn serialize_with<W>(&self, writer: &mut W, codec: WireCodec) -> Result<(), NetError>
     where
         W: WriteBytesExt,
     {
@@ -1955,7 +2177,7 @@ impl ClientCmd {
                 impulse,
             } => {
                 writer.write_f32::<LittleEndian>(engine::duration_to_f32(send_time))?;
-                write_angle_vector3(writer, angles)?;
+                codec.write_angle_vector3(writer, angles)?;
                 writer.write_i16::<LittleEndian>(fwd_move)?;
                 writer.write_i16::<LittleEndian>(side_move)?;
                 writer.write_i16::<LittleEndian>(up_move)?;
@@ -1979,8 +2201,56 @@ pub enum BlockingMode {
     Timeout(Duration),
 }
 
+/// A way of sending and receiving datagrams.
+///
+/// This is implemented by `UdpSocket`, and by `loopback::LoopbackSocket` for testing without
+/// real sockets. The transport also keeps the time, so that a simulated network can run on a
+/// simulated clock.
+pub trait Transport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    /// Sets how `recv_from` waits for a datagram to arrive.
+    fn set_blocking_mode(&self, mode: &BlockingMode) -> io::Result<()>;
+
+    /// Returns the current time.
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl Transport for UdpSocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+
+    fn set_blocking_mode(&self, mode: &BlockingMode) -> io::Result<()> {
+        match mode {
+            BlockingMode::Blocking => {
+                self.set_nonblocking(false)?;
+                self.set_read_timeout(None)
+            }
+
+            BlockingMode::NonBlocking => {
+                self.set_nonblocking(true)?;
+                self.set_read_timeout(None)
+            }
+
+            BlockingMode::Timeout(d) => {
+                self.set_nonblocking(false)?;
+                self.set_read_timeout(Some(d.to_std().unwrap()))
+            }
+        }
+    }
+}
+
 pub struct QSocket {
-    socket: UdpSocket,
+    socket: Box<dyn Transport>,
     remote: SocketAddr,
 
     unreliable_send_sequence: u32,
@@ -1989,18 +2259,40 @@ pub struct QSocket {
     ack_sequence: u32,
 
     send_sequence: u32,
-    send_queue: VecDeque<Box<[u8]>>,
-    send_cache: Box<[u8]>,
+    // the reliable message being sent, and the offset of its next unsent chunk
+    send_msg: MessageBuffer,
+    send_offset: usize,
+    // the last reliable packet sent, kept until it's acknowledged
+    send_cache: MessageBuffer,
+    // reused to compose unreliable packets
+    compose: MessageBuffer,
     send_next: bool,
     send_count: usize,
     resend_count: usize,
 
     recv_sequence: u32,
     recv_buf: [u8; MAX_MESSAGE],
+    // the chunks received so far of a reliable message that spans several packets
+    recv_msg: Vec<u8>,
+
+    last_send_time: Instant,
+    last_recv_time: Instant,
+
+    // when the current reliable packet was last sent
+    reliable_send_time: Instant,
+
+    // when the unacknowledged reliable message was first sent, if there is one
+    reliable_start_time: Option<Instant>,
 }
 
 impl QSocket {
     pub fn new(socket: UdpSocket, remote: SocketAddr) -> QSocket {
+        QSocket::with_transport(Box::new(socket), remote)
+    }
+
+    /// Creates a socket that sends and receives over any transport.
+    pub fn with_transport(socket: Box<dyn Transport>, remote: SocketAddr) -> QSocket {
+        let now = socket.now();
         QSocket {
             socket,
             remote,
@@ -2011,25 +2303,43 @@ impl QSocket {
             ack_sequence: 0,
 
             send_sequence: 0,
-            send_queue: VecDeque::new(),
-            send_cache: Box::new([]),
+            send_msg: MessageBuffer::with_capacity(MAX_MESSAGE),
+            send_offset: 0,
+            send_cache: MessageBuffer::with_capacity(MAX_PACKET),
+            compose: MessageBuffer::with_capacity(MAX_PACKET),
             send_count: 0,
             send_next: false,
             resend_count: 0,
 
             recv_sequence: 0,
             recv_buf: [0; MAX_MESSAGE],
+            recv_msg: Vec::new(),
+
+            last_send_time: now,
+            last_recv_time: now,
+            reliable_send_time: now,
+            reliable_start_time: None,
         }
     }
 
+    /// Returns the address of the other end of the connection.
+    pub fn remote(&self) -> SocketAddr {
+        self.remote
+    }
+
     pub fn can_send(&self) -> bool {
-        self.send_queue.is_empty() && self.send_cache.is_empty()
+        self.send_chunks_done() && self.send_cache.is_empty()
+    }
+
+    // Returns `true` if every chunk of the current reliable message has been sent.
+    fn send_chunks_done(&self) -> bool {
+        self.send_offset >= self.send_msg.len()
     }
 
     /// Begin sending a reliable message over this socket.
     pub fn begin_send_msg(&mut self, msg: &[u8]) -> Result<(), NetError> {
         // make sure all reliable messages have been ACKed in their entirety
-        if !self.send_queue.is_empty() {
+        if !self.send_chunks_done() {
             return Err(NetError::with_msg(
                 "begin_send_msg: previous message unacknowledged",
             ));
@@ -2049,13 +2359,13 @@ impl QSocket {
             ));
         }
 
-        // split the message into chunks and enqueue them
-        for chunk in msg.chunks(MAX_DATAGRAM) {
-            self.send_queue
-                .push_back(chunk.to_owned().into_boxed_slice());
-        }
+        // keep the message to be sent in chunks
+        self.send_msg.clear();
+        self.send_msg.write_bytes(msg)?;
+        self.send_offset = 0;
 
         // send the first chunk
+        self.reliable_start_time = Some(self.socket.now());
         self.send_msg_next()?;
 
         Ok(())
@@ -2066,44 +2376,55 @@ impl QSocket {
         if self.send_cache.is_empty() {
             Err(NetError::with_msg("Attempted resend with empty send cache"))
         } else {
-            self.socket.send_to(&self.send_cache, self.remote)?;
+            self.socket
+                .send_to(self.send_cache.as_slice(), self.remote)?;
             self.resend_count += 1;
 
+            let now = self.socket.now();
+            self.reliable_send_time = now;
+            self.last_send_time = now;
+
             Ok(())
         }
     }
 
     /// Send the next segment of a reliable message.
     pub fn send_msg_next(&mut self) -> Result<(), NetError> {
-        // grab the first chunk in the queue
-        let content = self
-            .send_queue
-            .pop_front()
-            .expect("Send queue is empty (this is a bug)");
+        // grab the next chunk of the message
+        assert!(
+            !self.send_chunks_done(),
+            "Send queue is empty (this is a bug)"
+        );
+        let start = self.send_offset;
+        let end = (start + MAX_DATAGRAM).min(self.send_msg.len());
+        self.send_offset = end;
 
         // if this was the last chunk, set the EOM flag
-        let msg_kind = match self.send_queue.is_empty() {
+        let msg_kind = match self.send_chunks_done() {
             true => MsgKind::ReliableEom,
             false => MsgKind::Reliable,
         };
 
-        // compose the packet
-        let mut compose = Vec::with_capacity(MAX_PACKET);
-        compose.write_u16::<NetworkEndian>(msg_kind as u16)?;
-        compose.write_u16::<NetworkEndian>((HEADER_SIZE + content.len()) as u16)?;
-        compose.write_u32::<NetworkEndian>(self.send_sequence)?;
-        compose.write_all(&content)?;
-
-        // store packet to send cache
-        self.send_cache = compose.into_boxed_slice();
+        // compose the packet in the send cache
+        self.send_cache.clear();
+        self.send_cache.write_u16_network(msg_kind as u16)?;
+        self.send_cache
+            .write_u16_network((HEADER_SIZE + end - start) as u16)?;
+        self.send_cache.write_u32_network(self.send_sequence)?;
+        self.send_cache
+            .write_bytes(&self.send_msg.as_slice()[start..end])?;
 
         // increment send sequence
         self.send_sequence += 1;
 
         // send the composed packet
-        self.socket.send_to(&self.send_cache, self.remote)?;
+        self.socket
+            .send_to(self.send_cache.as_slice(), self.remote)?;
+
+        let now = self.socket.now();
+        self.reliable_send_time = now;
+        self.last_send_time = now;
 
-        // TODO: update send time
         // bump send count
         self.send_count += 1;
 
@@ -2127,17 +2448,19 @@ impl QSocket {
         let packet_len = HEADER_SIZE + content.len();
 
         // compose the packet
-        let mut packet = Vec::with_capacity(MAX_PACKET);
-        packet.write_u16::<NetworkEndian>(MsgKind::Unreliable as u16)?;
-        packet.write_u16::<NetworkEndian>(packet_len as u16)?;
-        packet.write_u32::<NetworkEndian>(self.unreliable_send_sequence)?;
-        packet.write_all(content)?;
+        self.compose.clear();
+        self.compose.write_u16_network(MsgKind::Unreliable as u16)?;
+        self.compose.write_u16_network(packet_len as u16)?;
+        self.compose
+            .write_u32_network(self.unreliable_send_sequence)?;
+        self.compose.write_bytes(content)?;
 
         // increment unreliable send sequence
         self.unreliable_send_sequence += 1;
 
         // send the message
-        self.socket.send_to(&packet, self.remote)?;
+        self.socket.send_to(self.compose.as_slice(), self.remote)?;
+        self.last_send_time = self.socket.now();
 
         // bump send count
         self.send_count += 1;
@@ -2145,27 +2468,53 @@ impl QSocket {
         Ok(())
     }
 
+    /// Resends the pending reliable packet if it hasn't been acknowledged recently.
+    ///
+    /// This should be called regularly, since lost reliable packets are not otherwise resent.
+    pub fn resend_unacked(&mut self) -> Result<(), NetError> {
+        if self.send_cache.is_empty() || self.send_next {
+            return Ok(());
+        }
+
+        if self.socket.now() - self.reliable_send_time > RESEND_INTERVAL {
+            debug!("Resending unacknowledged reliable packet");
+            self.resend_msg()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the time since a packet was last sent to the remote.
+    pub fn time_since_send(&self) -> Duration {
+        Duration::from_std(self.socket.now() - self.last_send_time).unwrap()
+    }
+
+    /// Returns the time since a packet was last received from the remote.
+    pub fn time_since_recv(&self) -> Duration {
+        Duration::from_std(self.socket.now() - self.last_recv_time).unwrap()
+    }
+
+    /// Returns `true` if the remote should be considered disconnected.
+    ///
+    /// This is the case if nothing has been received from the remote within `timeout`, or if a
+    /// reliable message has gone unacknowledged for that long. The latter catches half-open
+    /// connections, where the remote is still sending but no longer processing our messages.
+    pub fn timed_out(&self, timeout: Duration) -> bool {
+        let now = self.socket.now();
+        let unacked_time = self
+            .reliable_start_time
+            .map(|t| Duration::from_std(now - t).unwrap())
+            .unwrap_or_else(Duration::zero);
+
+        self.time_since_recv() > timeout || unacked_time > timeout
+    }
+
     /// Receive a message on this socket.
     // TODO: the flow control in this function is completely baffling, make it a little less awful
     pub fn recv_msg(&mut self, block: BlockingMode) -> Result<Vec<u8>, NetError> {
         let mut msg = Vec::new();
 
-        match block {
-            BlockingMode::Blocking => {
-                self.socket.set_nonblocking(false)?;
-                self.socket.set_read_timeout(None)?;
-            }
-
-            BlockingMode::NonBlocking => {
-                self.socket.set_nonblocking(true)?;
-                self.socket.set_read_timeout(None)?;
-            }
-
-            BlockingMode::Timeout(d) => {
-                self.socket.set_nonblocking(false)?;
-                self.socket.set_read_timeout(Some(d.to_std().unwrap()))?;
-            }
-        }
+        self.socket.set_blocking_mode(&block)?;
 
         loop {
             let (packet_len, src_addr) = match self.socket.recv_from(&mut self.recv_buf) {
@@ -2174,7 +2523,7 @@ impl QSocket {
                     use std::io::ErrorKind;
                     match e.kind() {
                         // these errors are expected in nonblocking mode
-                        ErrorKind::WouldBlock | ErrorKind::TimedOut => return Ok(Vec::new()),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut => break,
                         _ => return Err(NetError::from(e)),
                     }
                 }
@@ -2216,6 +2565,8 @@ impl QSocket {
                 )));
             }
 
+            self.last_recv_time = self.socket.now();
+
             let sequence;
             if msg_kind != MsgKind::Ctl {
                 sequence = reader.read_u32::<NetworkEndian>()?;
@@ -2247,7 +2598,7 @@ impl QSocket {
 
                     // copy the rest of the packet into the message buffer and return
                     reader.read_to_end(&mut msg)?;
-                    return Ok(msg);
+                    break;
                 }
 
                 MsgKind::Ack => {
@@ -2262,9 +2613,10 @@ impl QSocket {
                         }
 
                         // our last reliable message has been acked
-                        if self.send_queue.is_empty() {
+                        if self.send_chunks_done() {
                             // the whole message is through, clear the send cache
-                            self.send_cache = Box::new([]);
+                            self.send_cache.clear();
+                            self.reliable_start_time = None;
                         } else {
                             // send the next chunk before returning
                             self.send_next = true;
@@ -2282,6 +2634,7 @@ impl QSocket {
                     ack_curs.write_u16::<NetworkEndian>(HEADER_SIZE as u16)?;
                     ack_curs.write_u32::<NetworkEndian>(sequence)?;
                     self.socket.send_to(ack_curs.into_inner(), self.remote)?;
+                    self.last_send_time = self.socket.now();
 
                     // if this was a duplicate, drop it
                     if sequence != self.recv_sequence {
@@ -2290,10 +2643,14 @@ impl QSocket {
                     }
 
                     self.recv_sequence += 1;
-                    reader.read_to_end(&mut msg)?;
+
+                    // keep the chunk until the rest of the message arrives, which may not be
+                    // until a later call
+                    reader.read_to_end(&mut self.recv_msg)?;
 
                     // if this is the last chunk of a reliable message, break out and return
                     if msg_kind == MsgKind::ReliableEom {
+                        msg = std::mem::replace(&mut self.recv_msg, Vec::new());
                         break;
                     }
                 }
@@ -2308,80 +2665,6 @@ impl QSocket {
     }
 }
 
-fn read_coord<R>(reader: &mut R) -> Result<f32, NetError>
-where
-    R: BufRead + ReadBytesExt,
-{
-    Ok(reader.read_i16::<LittleEndian>()? as f32 / 8.0)
-}
-
-fn read_coord_vector3<R>(reader: &mut R) -> Result<Vector3<f32>, NetError>
-where
-    R: BufRead + ReadBytesExt,
-{
-    Ok(Vector3::new(
-        read_coord(reader)?,
-        read_coord(reader)?,
-        read_coord(reader)?,
-    ))
-}
-
-fn write_coord<W>(writer: &mut W, coord: f32) -> Result<(), NetError>
-where
-    W: WriteBytesExt,
-{
-    writer.write_i16::<LittleEndian>((coord * 8.0) as i16)?;
-    Ok(())
-}
-
-fn write_coord_vector3<W>(writer: &mut W, coords: Vector3<f32>) -> Result<(), NetError>
-where
-    W: WriteBytesExt,
-{
-    for coord in &coords[..] {
-        write_coord(writer, *coord)?;
-    }
-
-    Ok(())
-}
-
-fn read_angle<R>(reader: &mut R) -> Result<Deg<f32>, NetError>
-where
-    R: BufRead + ReadBytesExt,
-{
-    Ok(Deg(reader.read_i8()? as f32 * (360.0 / 256.0)))
-}
-
-fn read_angle_vector3<R>(reader: &mut R) -> Result<Vector3<Deg<f32>>, NetError>
-where
-    R: BufRead + ReadBytesExt,
-{
-    Ok(Vector3::new(
-        read_angle(reader)?,
-        read_angle(reader)?,
-        read_angle(reader)?,
-    ))
-}
-
-fn write_angle<W>(writer: &mut W, angle: Deg<f32>) -> Result<(), NetError>
-where
-    W: WriteBytesExt,
-{
-    writer.write_u8(((angle.0 as i32 * 256 / 360) & 0xFF) as u8)?;
-    Ok(())
-}
-
-fn write_angle_vector3<W>(writer: &mut W, angles: Vector3<Deg<f32>>) -> Result<(), NetError>
-where
-    W: WriteBytesExt,
-{
-    for angle in &angles[..] {
-        write_angle(writer, *angle)?;
-    }
-
-    Ok(())
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
@@ -2635,6 +2918,98 @@ mod test {
         assert_eq!(src, dst);
     }
 
+    #[test]
+    fn test_server_cmd_fast_update_read_write_eq() {
+        let src = ServerCmd::FastUpdate(EntityUpdate {
+            ent_id: 300,
+            model_id: Some(12),
+            frame_id: None,
+            colormap: Some(1),
+            skin_id: None,
+            effects: Some(EntityEffects::MUZZLE_FLASH),
+            origin_x: Some(128.5),
+            pitch: None,
+            origin_y: Some(-64.0),
+            yaw: Some(Deg(90.0)),
+            origin_z: None,
+            roll: None,
+            no_lerp: true,
+        });
+        let mut packet = Vec::new();
+        src.serialize(&mut packet).unwrap();
+        let mut reader = BufReader::new(packet.as_slice());
+        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+
+        assert_eq!(src, dst);
+    }
+
+    #[test]
+    fn test_entity_update_from_entity_state() {
+        let baseline = EntityState {
+            origin: Vector3::new(16.0, 32.0, 64.0),
+            angles: Vector3::new(Deg(0.0), Deg(90.0), Deg(0.0)),
+            model_id: 3,
+            frame_id: 0,
+            colormap: 0,
+            skin_id: 0,
+            effects: EntityEffects::empty(),
+        };
+        let state = EntityState {
+            origin: Vector3::new(16.0, 48.0, 64.0),
+            frame_id: 2,
+            effects: EntityEffects::MUZZLE_FLASH,
+            ..baseline.clone()
+        };
+
+        let update = EntityUpdate::from_entity_state(7, &state, &baseline);
+        assert_eq!(update.model_id, None);
+        assert_eq!(update.origin_x, None);
+        assert_eq!(update.origin_y, Some(48.0));
+        assert_eq!(update.frame_id, Some(2));
+        assert_eq!(update.to_entity_state(&baseline), state);
+    }
+
+    #[test]
+    fn test_server_cmd_read_write_with_codec_eq() {
+        // outside the range of the original protocol's coordinates
+        let src = ServerCmd::SpawnStatic {
+            model_id: 1,
+            frame_id: 2,
+            colormap: 0,
+            skin_id: 0,
+            origin: Vector3::new(-6000.5, 8000.25, 12.0),
+            angles: Vector3::new(Deg(0.0), Deg(45.0), Deg(-90.0)),
+        };
+
+        let codec = Protocol::Rmq.codec();
+        let mut packet = Vec::new();
+        src.serialize_with(&mut packet, codec).unwrap();
+        let mut reader = BufReader::new(packet.as_slice());
+        let dst = ServerCmd::deserialize_with(&mut reader, codec)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(src, dst);
+    }
+
+    #[test]
+    fn test_server_cmd_temp_entity_beam_read_write_eq() {
+        let src = ServerCmd::TempEntity {
+            temp_entity: TempEntity::Beam {
+                kind: BeamEntityKind::Lightning { model_id: 2 },
+                entity_id: 7,
+                start: Vector3::new(0.0, 16.0, 32.0),
+                end: Vector3::new(256.0, 16.0, 32.0),
+            },
+        };
+        let mut packet = Vec::new();
+        src.serialize(&mut packet).unwrap();
+        let mut reader = BufReader::new(packet.as_slice());
+        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+
+        assert_eq!(src, dst);
+    }
+
     #[test]
     fn test_client_cmd_string_cmd_read_write_eq() {
         let src = ClientCmd::StringCmd {
@@ -2704,6 +3079,74 @@ mod test {
         assert_eq!(message, received);
     }
 
+    #[test]
+    fn test_qsocket_timed_out() {
+        let (mut src, mut dst) = gen_qsocket_pair();
+        assert!(!src.timed_out(Duration::seconds(60)));
+
+        // an unacknowledged reliable message counts towards the timeout
+        let message = String::from("test message").into_bytes();
+        src.begin_send_msg(&message).unwrap();
+        std::thread::sleep(StdDuration::from_millis(20));
+        assert!(src.timed_out(Duration::milliseconds(10)));
+
+        // the acknowledgement resets both timers
+        dst.recv_msg(BlockingMode::Blocking).unwrap();
+        std::thread::sleep(StdDuration::from_millis(5));
+        src.recv_msg(BlockingMode::NonBlocking).unwrap();
+        assert!(src.can_send());
+        assert!(!src.timed_out(Duration::seconds(1)));
+    }
+
+    // a reliable message three packets long
+    fn gen_long_message() -> Vec<u8> {
+        (0..MAX_DATAGRAM * 2 + 100).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn test_qsocket_recv_msg_keeps_partial_reliable_message() {
+        let network = loopback::LoopbackNetwork::new(Duration::zero());
+        let (mut src, mut dst) = network.pair();
+
+        let message = gen_long_message();
+        src.begin_send_msg(&message).unwrap();
+        src.send_msg_unreliable(b"ping").unwrap();
+
+        // the first chunk is kept while the unreliable message is returned
+        assert_eq!(dst.recv_msg(BlockingMode::NonBlocking).unwrap(), b"ping");
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            src.recv_msg(BlockingMode::NonBlocking).unwrap();
+            received = dst.recv_msg(BlockingMode::NonBlocking).unwrap();
+            if !received.is_empty() {
+                break;
+            }
+        }
+        assert_eq!(received, message);
+    }
+
+    #[test]
+    fn test_qsocket_recv_msg_sends_next_chunk_on_ack() {
+        let network = loopback::LoopbackNetwork::new(Duration::zero());
+        let (mut src, mut dst) = network.pair();
+
+        let message = gen_long_message();
+        src.begin_send_msg(&message).unwrap();
+
+        // each chunk is sent as soon as the last one is acknowledged, even though the call that
+        // reads the acknowledgement has no message to return
+        for _ in 0..2 {
+            assert!(dst.recv_msg(BlockingMode::NonBlocking).unwrap().is_empty());
+            assert!(src.recv_msg(BlockingMode::NonBlocking).unwrap().is_empty());
+            assert!(!src.can_send());
+        }
+
+        assert_eq!(dst.recv_msg(BlockingMode::NonBlocking).unwrap(), message);
+        src.recv_msg(BlockingMode::NonBlocking).unwrap();
+        assert!(src.can_send());
+    }
+
     #[test]
     #[should_panic]
     fn test_qsocket_send_msg_unreliable_zero_length_fails() {
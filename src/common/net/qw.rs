@@ -0,0 +1,1965 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! QuakeWorld network protocol.
+//!
+//! QuakeWorld servers don't use NetQuake's connection-oriented protocol. A client first requests a
+//! challenge with a connectionless (out-of-band) packet, then connects by echoing that challenge
+//! back along with its user info. In-game packets are sequenced by a `Netchan`, which carries at
+//! most one reliable message at a time alongside unreliable data. Entity updates are
+//! delta-compressed against a previous frame acknowledged by the client, and each movement packet
+//! carries the client's last three commands, each delta-compressed against the one before it.
+
+use std::{
+    io::{BufRead, ErrorKind, Read, Seek, SeekFrom},
+    mem,
+    net::{SocketAddr, UdpSocket},
+    time::{Duration as StdDuration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::common::{
+    net::{
+        codec::{AngleFormat, WireCodec},
+        BeamEntityKind, ButtonFlags, NetError, PointEntityKind, ServerCmd as NetQuakeServerCmd,
+        TempEntity,
+    },
+    util,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use cgmath::{Deg, Vector3, Zero};
+use num::FromPrimitive;
+
+pub const PROTOCOL_VERSION: i32 = 28;
+pub const DEFAULT_PORT: u16 = 27500;
+
+pub const MAX_CLIENTS: usize = 32;
+pub const MAX_EDICTS: usize = 512;
+pub const MAX_PACKET_ENTITIES: usize = 64;
+
+// QuakeWorld encodes coordinates and angles like the original protocol, except for the more
+// precise angles of move commands
+const CODEC: WireCodec = WireCodec::NETQUAKE;
+const MOVE_ANGLE: AngleFormat = AngleFormat::Short;
+
+/// The number of entity frames kept for delta compression.
+pub const UPDATE_BACKUP: usize = 64;
+const UPDATE_MASK: u32 = UPDATE_BACKUP as u32 - 1;
+
+const MAX_MSGLEN: usize = 1450;
+const PACKET_HEADER: usize = 8;
+
+// sequenced packets never use this value, so it marks connectionless packets
+const OOB_SEQUENCE: u32 = 0xFFFF_FFFF;
+const RELIABLE_FLAG: u32 = 1 << 31;
+
+const ENTITY_NUMBER_MASK: u16 = 0x01FF;
+
+const CONNECT_ATTEMPTS: usize = 4;
+const CONNECT_TIMEOUT: StdDuration = StdDuration::from_millis(2500);
+
+/// Wraps a connectionless text message in an out-of-band packet.
+pub fn oob_packet<S>(text: S) -> Vec<u8>
+where
+    S: AsRef<str>,
+{
+    let mut packet = OOB_SEQUENCE.to_le_bytes().to_vec();
+    packet.extend_from_slice(text.as_ref().as_bytes());
+    packet
+}
+
+/// Returns the contents of an out-of-band packet, or `None` if `packet` is sequenced.
+pub fn oob_contents(packet: &[u8]) -> Option<&[u8]> {
+    if packet.len() >= 4 && packet[..4] == OOB_SEQUENCE.to_le_bytes() {
+        Some(&packet[4..])
+    } else {
+        None
+    }
+}
+
+/// Constructs a request for a connection challenge.
+pub fn challenge_request() -> Vec<u8> {
+    oob_packet("getchallenge\n")
+}
+
+/// Constructs a connection request using a challenge obtained from the server.
+pub fn connect_request(qport: u16, challenge: i32, userinfo: &str) -> Vec<u8> {
+    oob_packet(format!(
+        "connect {} {} {} \"{}\"\n",
+        PROTOCOL_VERSION, qport, challenge, userinfo
+    ))
+}
+
+/// Builds an info string (e.g. `\name\player\rate\2500`) from a list of key-value pairs.
+pub fn info_string(pairs: &[(&str, &str)]) -> String {
+    pairs
+        .iter()
+        .map(|(k, v)| format!("\\{}\\{}", k, v))
+        .collect()
+}
+
+/// Looks up the value associated with `key` in an info string.
+pub fn info_value<'a>(info: &'a str, key: &str) -> Option<&'a str> {
+    let mut fields = info.trim_start_matches('\\').split('\\');
+
+    while let Some(k) = fields.next() {
+        let v = fields.next()?;
+        if k == key {
+            return Some(v);
+        }
+    }
+
+    None
+}
+
+/// A connectionless response from a QuakeWorld server.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OobResponse {
+    /// A challenge to be echoed back in the connection request.
+    Challenge(i32),
+
+    /// The connection was accepted.
+    Connection,
+
+    /// A message to display to the user, usually explaining why a connection was rejected.
+    Print(String),
+
+    Ping,
+    Ack,
+}
+
+impl OobResponse {
+    pub fn parse(contents: &[u8]) -> Result<OobResponse, NetError> {
+        let (code, rest) = match contents.split_first() {
+            Some(s) => s,
+            None => return Err(NetError::with_msg("Empty out-of-band message")),
+        };
+
+        let text = String::from_utf8_lossy(rest);
+
+        Ok(match code {
+            b'c' => {
+                // newer servers append protocol extensions after the challenge
+                let challenge: String = text
+                    .trim_start()
+                    .chars()
+                    .take_while(|c| *c == '-' || c.is_ascii_digit())
+                    .collect();
+                OobResponse::Challenge(challenge.parse().map_err(|_| {
+                    NetError::InvalidData(format!("challenge: {}", text.trim_end_matches('\0')))
+                })?)
+            }
+            b'j' => OobResponse::Connection,
+            b'n' => OobResponse::Print(text.trim_end_matches('\0').to_owned()),
+            b'k' => OobResponse::Ping,
+            b'l' => OobResponse::Ack,
+            c => {
+                return Err(NetError::InvalidData(format!(
+                    "out-of-band message code {}",
+                    c
+                )))
+            }
+        })
+    }
+}
+
+/// A sequenced channel over an unreliable transport.
+///
+/// Every packet carries the sender's outgoing sequence number and the last sequence number it
+/// received. At most one reliable message is in flight at a time; it is retransmitted until the
+/// remote acknowledges it, and any reliable data queued in the meantime is held until then.
+#[derive(Debug)]
+pub struct Netchan {
+    // identifies a client to the server even if its port changes behind a NAT.
+    // only sent by clients.
+    qport: Option<u16>,
+
+    outgoing_sequence: u32,
+    incoming_sequence: u32,
+    incoming_acknowledged: u32,
+
+    incoming_reliable_acknowledged: bool,
+    incoming_reliable_sequence: bool,
+
+    reliable_sequence: bool,
+    last_reliable_sequence: u32,
+
+    // the reliable message in flight
+    reliable_buf: Vec<u8>,
+
+    // reliable data waiting for the message in flight to be acknowledged
+    message: Vec<u8>,
+}
+
+impl Netchan {
+    fn new(qport: Option<u16>) -> Netchan {
+        Netchan {
+            qport,
+            // the remote discards sequence numbers it has already seen, including 0
+            outgoing_sequence: 1,
+            incoming_sequence: 0,
+            incoming_acknowledged: 0,
+            incoming_reliable_acknowledged: false,
+            incoming_reliable_sequence: false,
+            reliable_sequence: false,
+            last_reliable_sequence: 0,
+            reliable_buf: Vec::new(),
+            message: Vec::new(),
+        }
+    }
+
+    /// Creates the client end of a channel.
+    pub fn client(qport: u16) -> Netchan {
+        Netchan::new(Some(qport))
+    }
+
+    /// Creates the server end of a channel.
+    pub fn server() -> Netchan {
+        Netchan::new(None)
+    }
+
+    pub fn outgoing_sequence(&self) -> u32 {
+        self.outgoing_sequence
+    }
+
+    pub fn incoming_sequence(&self) -> u32 {
+        self.incoming_sequence
+    }
+
+    /// Queues data to be delivered reliably.
+    pub fn queue_reliable(&mut self, data: &[u8]) {
+        self.message.extend_from_slice(data);
+    }
+
+    /// Constructs the next packet, containing any reliable data due to be sent followed by
+    /// `unreliable` if there is room for it.
+    pub fn transmit(&mut self, unreliable: &[u8]) -> Vec<u8> {
+        // if the remote side dropped the last reliable message, resend it
+        let mut send_reliable = self.incoming_acknowledged > self.last_reliable_sequence
+            && self.incoming_reliable_acknowledged != self.reliable_sequence;
+
+        // if nothing is in flight, send the queued reliable data
+        if self.reliable_buf.is_empty() && !self.message.is_empty() {
+            self.reliable_buf = mem::take(&mut self.message);
+            self.reliable_sequence = !self.reliable_sequence;
+            send_reliable = true;
+        }
+
+        let mut packet = Vec::with_capacity(PACKET_HEADER + MAX_MSGLEN);
+        packet
+            .write_u32::<LittleEndian>(
+                self.outgoing_sequence | if send_reliable { RELIABLE_FLAG } else { 0 },
+            )
+            .unwrap();
+        packet
+            .write_u32::<LittleEndian>(
+                self.incoming_sequence
+                    | if self.incoming_reliable_sequence {
+                        RELIABLE_FLAG
+                    } else {
+                        0
+                    },
+            )
+            .unwrap();
+        self.outgoing_sequence += 1;
+
+        if let Some(qport) = self.qport {
+            packet.write_u16::<LittleEndian>(qport).unwrap();
+        }
+
+        if send_reliable {
+            packet.extend_from_slice(&self.reliable_buf);
+            self.last_reliable_sequence = self.outgoing_sequence;
+        }
+
+        if PACKET_HEADER + MAX_MSGLEN - packet.len() >= unreliable.len() {
+            packet.extend_from_slice(unreliable);
+        } else {
+            debug!("Dropped unreliable message ({} bytes)", unreliable.len());
+        }
+
+        packet
+    }
+
+    /// Processes the header of a sequenced packet.
+    ///
+    /// Returns the message contained in the packet, or `None` if the packet is stale or
+    /// duplicated.
+    pub fn process<'a>(&mut self, packet: &'a [u8]) -> Result<Option<&'a [u8]>, NetError> {
+        let mut reader = packet;
+        let sequence = reader.read_u32::<LittleEndian>()?;
+        let sequence_ack = reader.read_u32::<LittleEndian>()?;
+
+        // only clients send a qport
+        if self.qport.is_none() {
+            reader.read_u16::<LittleEndian>()?;
+        }
+
+        let reliable_message = sequence & RELIABLE_FLAG != 0;
+        let reliable_ack = sequence_ack & RELIABLE_FLAG != 0;
+        let sequence = sequence & !RELIABLE_FLAG;
+        let sequence_ack = sequence_ack & !RELIABLE_FLAG;
+
+        if sequence <= self.incoming_sequence {
+            debug!(
+                "Out of order packet {} at {}",
+                sequence, self.incoming_sequence
+            );
+            return Ok(None);
+        }
+
+        // the reliable message in flight has been received
+        if reliable_ack == self.reliable_sequence {
+            self.reliable_buf.clear();
+        }
+
+        self.incoming_sequence = sequence;
+        self.incoming_acknowledged = sequence_ack;
+        self.incoming_reliable_acknowledged = reliable_ack;
+        if reliable_message {
+            self.incoming_reliable_sequence = !self.incoming_reliable_sequence;
+        }
+
+        Ok(Some(reader))
+    }
+}
+
+/// A client's connection to a QuakeWorld server.
+pub struct Connection {
+    socket: UdpSocket,
+    remote: SocketAddr,
+    netchan: Netchan,
+}
+
+impl Connection {
+    /// Performs the challenge handshake with the server at `remote`.
+    pub fn connect(remote: SocketAddr, userinfo: &str) -> Result<Connection, NetError> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+
+        let challenge = match Connection::request(&socket, remote, &challenge_request())? {
+            OobResponse::Challenge(c) => c,
+            r => return Err(NetError::Other(format!("Unexpected response: {:?}", r))),
+        };
+        debug!("Received challenge {} from {}", challenge, remote);
+
+        // the original client derives this from the current time as well
+        let qport = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_millis() as u16 ^ d.as_secs() as u16)
+            .unwrap_or(0);
+
+        match Connection::request(
+            &socket,
+            remote,
+            &connect_request(qport, challenge, userinfo),
+        )? {
+            OobResponse::Connection => (),
+            r => return Err(NetError::Other(format!("Unexpected response: {:?}", r))),
+        }
+
+        socket.set_nonblocking(true)?;
+
+        Ok(Connection {
+            socket,
+            remote,
+            netchan: Netchan::client(qport),
+        })
+    }
+
+    // sends an out-of-band request and waits for the response, retrying a few times
+    fn request(
+        socket: &UdpSocket,
+        remote: SocketAddr,
+        packet: &[u8],
+    ) -> Result<OobResponse, NetError> {
+        let mut recv_buf = [0u8; PACKET_HEADER + MAX_MSGLEN];
+
+        for attempt in 0..CONNECT_ATTEMPTS {
+            debug!("Sending request to {} (attempt {})", remote, attempt + 1);
+            socket.send_to(packet, remote)?;
+
+            let (len, from) = match socket.recv_from(&mut recv_buf) {
+                Ok(r) => r,
+                Err(ref e)
+                    if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            if from != remote {
+                continue;
+            }
+
+            if let Some(contents) = oob_contents(&recv_buf[..len]) {
+                match OobResponse::parse(contents)? {
+                    OobResponse::Print(msg) => return Err(NetError::Other(msg)),
+                    r => return Ok(r),
+                }
+            }
+        }
+
+        Err(NetError::with_msg("No response from server"))
+    }
+
+    pub fn outgoing_sequence(&self) -> u32 {
+        self.netchan.outgoing_sequence()
+    }
+
+    pub fn incoming_sequence(&self) -> u32 {
+        self.netchan.incoming_sequence()
+    }
+
+    /// Queues data to be delivered reliably with a later packet.
+    pub fn queue_reliable(&mut self, data: &[u8]) {
+        self.netchan.queue_reliable(data);
+    }
+
+    /// Sends a packet containing any pending reliable data and `unreliable`.
+    pub fn send(&mut self, unreliable: &[u8]) -> Result<(), NetError> {
+        let packet = self.netchan.transmit(unreliable);
+        self.socket.send_to(&packet, self.remote)?;
+        Ok(())
+    }
+
+    /// Returns the message contained in the next sequenced packet from the server, or `None` if
+    /// no packets are waiting.
+    pub fn recv(&mut self) -> Result<Option<Vec<u8>>, NetError> {
+        let mut recv_buf = [0u8; PACKET_HEADER + MAX_MSGLEN];
+
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut recv_buf) {
+                Ok(r) => r,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+
+            if from != self.remote {
+                continue;
+            }
+
+            let packet = &recv_buf[..len];
+            if let Some(contents) = oob_contents(packet) {
+                debug!(
+                    "Out-of-band packet in game: {:?}",
+                    OobResponse::parse(contents)
+                );
+                continue;
+            }
+
+            if let Some(msg) = self.netchan.process(packet)? {
+                return Ok(Some(msg.to_owned()));
+            }
+        }
+    }
+}
+
+bitflags! {
+    pub struct UserCmdFlags: u8 {
+        const ANGLE1 = 1 << 0;
+        const ANGLE3 = 1 << 1;
+        const FORWARD = 1 << 2;
+        const SIDE = 1 << 3;
+        const UP = 1 << 4;
+        const BUTTONS = 1 << 5;
+        const IMPULSE = 1 << 6;
+        const ANGLE2 = 1 << 7;
+    }
+}
+
+/// A single frame of player input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UserCmd {
+    /// The duration of this command in milliseconds.
+    pub msec: u8,
+    pub angles: Vector3<Deg<f32>>,
+    pub forward_move: i16,
+    pub side_move: i16,
+    pub up_move: i16,
+    pub buttons: ButtonFlags,
+    pub impulse: u8,
+}
+
+impl UserCmd {
+    pub fn null() -> UserCmd {
+        UserCmd {
+            msec: 0,
+            angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+            forward_move: 0,
+            side_move: 0,
+            up_move: 0,
+            buttons: ButtonFlags::empty(),
+            impulse: 0,
+        }
+    }
+
+    /// Writes the fields of this command that differ from `from`.
+    pub fn write_delta<W>(&self, from: &UserCmd, writer: &mut W) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        let mut flags = UserCmdFlags::empty();
+        flags.set(UserCmdFlags::ANGLE1, self.angles.x != from.angles.x);
+        flags.set(UserCmdFlags::ANGLE2, self.angles.y != from.angles.y);
+        flags.set(UserCmdFlags::ANGLE3, self.angles.z != from.angles.z);
+        flags.set(
+            UserCmdFlags::FORWARD,
+            self.forward_move != from.forward_move,
+        );
+        flags.set(UserCmdFlags::SIDE, self.side_move != from.side_move);
+        flags.set(UserCmdFlags::UP, self.up_move != from.up_move);
+        flags.set(UserCmdFlags::BUTTONS, self.buttons != from.buttons);
+        flags.set(UserCmdFlags::IMPULSE, self.impulse != from.impulse);
+
+        writer.write_u8(flags.bits())?;
+
+        if flags.contains(UserCmdFlags::ANGLE1) {
+            MOVE_ANGLE.write(writer, self.angles.x)?;
+        }
+        if flags.contains(UserCmdFlags::ANGLE2) {
+            MOVE_ANGLE.write(writer, self.angles.y)?;
+        }
+        if flags.contains(UserCmdFlags::ANGLE3) {
+            MOVE_ANGLE.write(writer, self.angles.z)?;
+        }
+        if flags.contains(UserCmdFlags::FORWARD) {
+            writer.write_i16::<LittleEndian>(self.forward_move)?;
+        }
+        if flags.contains(UserCmdFlags::SIDE) {
+            writer.write_i16::<LittleEndian>(self.side_move)?;
+        }
+        if flags.contains(UserCmdFlags::UP) {
+            writer.write_i16::<LittleEndian>(self.up_move)?;
+        }
+        if flags.contains(UserCmdFlags::BUTTONS) {
+            writer.write_u8(self.buttons.bits())?;
+        }
+        if flags.contains(UserCmdFlags::IMPULSE) {
+            writer.write_u8(self.impulse)?;
+        }
+
+        writer.write_u8(self.msec)?;
+
+        Ok(())
+    }
+
+    /// Reads a command whose unchanged fields are taken from `from`.
+    pub fn read_delta<R>(from: &UserCmd, reader: &mut R) -> Result<UserCmd, NetError>
+    where
+        R: BufRead + ReadBytesExt,
+    {
+        let flags = UserCmdFlags::from_bits_truncate(reader.read_u8()?);
+        let mut cmd = *from;
+
+        if flags.contains(UserCmdFlags::ANGLE1) {
+            cmd.angles.x = MOVE_ANGLE.read(reader)?;
+        }
+        if flags.contains(UserCmdFlags::ANGLE2) {
+            cmd.angles.y = MOVE_ANGLE.read(reader)?;
+        }
+        if flags.contains(UserCmdFlags::ANGLE3) {
+            cmd.angles.z = MOVE_ANGLE.read(reader)?;
+        }
+        if flags.contains(UserCmdFlags::FORWARD) {
+            cmd.forward_move = reader.read_i16::<LittleEndian>()?;
+        }
+        if flags.contains(UserCmdFlags::SIDE) {
+            cmd.side_move = reader.read_i16::<LittleEndian>()?;
+        }
+        if flags.contains(UserCmdFlags::UP) {
+            cmd.up_move = reader.read_i16::<LittleEndian>()?;
+        }
+        if flags.contains(UserCmdFlags::BUTTONS) {
+            cmd.buttons = ButtonFlags::from_bits_truncate(reader.read_u8()?);
+        }
+        if flags.contains(UserCmdFlags::IMPULSE) {
+            cmd.impulse = reader.read_u8()?;
+        }
+
+        cmd.msec = reader.read_u8()?;
+
+        Ok(cmd)
+    }
+}
+
+bitflags! {
+    pub struct EntityUpdateFlags: u16 {
+        // sent in a second byte if MORE_BITS is set
+        const ANGLE1 = 1 << 0;
+        const ANGLE3 = 1 << 1;
+        const MODEL = 1 << 2;
+        const COLORMAP = 1 << 3;
+        const SKIN = 1 << 4;
+        const EFFECTS = 1 << 5;
+        const SOLID = 1 << 6;
+
+        // the low 9 bits of the first word hold the entity number
+        const ORIGIN1 = 1 << 9;
+        const ORIGIN2 = 1 << 10;
+        const ORIGIN3 = 1 << 11;
+        const ANGLE2 = 1 << 12;
+        const FRAME = 1 << 13;
+        const REMOVE = 1 << 14;
+        const MORE_BITS = 1 << 15;
+    }
+}
+
+/// The state of a non-player entity in a single frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityState {
+    pub number: u16,
+    pub model_id: u8,
+    pub frame_id: u8,
+    pub colormap: u8,
+    pub skin_id: u8,
+    pub effects: u8,
+    pub origin: Vector3<f32>,
+    pub angles: Vector3<Deg<f32>>,
+}
+
+impl EntityState {
+    pub fn uninitialized(number: u16) -> EntityState {
+        EntityState {
+            number,
+            model_id: 0,
+            frame_id: 0,
+            colormap: 0,
+            skin_id: 0,
+            effects: 0,
+            origin: Vector3::zero(),
+            angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+        }
+    }
+
+    /// Returns this state with the fields present in `delta` replaced.
+    pub fn apply(&self, delta: &EntityDelta) -> EntityState {
+        EntityState {
+            number: delta.number,
+            model_id: delta.model_id.unwrap_or(self.model_id),
+            frame_id: delta.frame_id.unwrap_or(self.frame_id),
+            colormap: delta.colormap.unwrap_or(self.colormap),
+            skin_id: delta.skin_id.unwrap_or(self.skin_id),
+            effects: delta.effects.unwrap_or(self.effects),
+            origin: Vector3::new(
+                delta.origin_x.unwrap_or(self.origin.x),
+                delta.origin_y.unwrap_or(self.origin.y),
+                delta.origin_z.unwrap_or(self.origin.z),
+            ),
+            angles: Vector3::new(
+                delta.pitch.unwrap_or(self.angles.x),
+                delta.yaw.unwrap_or(self.angles.y),
+                delta.roll.unwrap_or(self.angles.z),
+            ),
+        }
+    }
+}
+
+/// A change to a single entity in a packet entities update.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityDelta {
+    pub number: u16,
+    pub remove: bool,
+    pub model_id: Option<u8>,
+    pub frame_id: Option<u8>,
+    pub colormap: Option<u8>,
+    pub skin_id: Option<u8>,
+    pub effects: Option<u8>,
+    pub origin_x: Option<f32>,
+    pub pitch: Option<Deg<f32>>,
+    pub origin_y: Option<f32>,
+    pub yaw: Option<Deg<f32>>,
+    pub origin_z: Option<f32>,
+    pub roll: Option<Deg<f32>>,
+}
+
+impl EntityDelta {
+    /// Reads a single entity delta, or `None` if the end of the update was reached.
+    pub fn read<R>(reader: &mut R) -> Result<Option<EntityDelta>, NetError>
+    where
+        R: BufRead + ReadBytesExt,
+    {
+        let word = reader.read_u16::<LittleEndian>()?;
+        if word == 0 {
+            return Ok(None);
+        }
+
+        let number = word & ENTITY_NUMBER_MASK;
+        let mut flags = EntityUpdateFlags::from_bits_truncate(word & !ENTITY_NUMBER_MASK);
+        if flags.contains(EntityUpdateFlags::MORE_BITS) {
+            flags |= EntityUpdateFlags::from_bits_truncate(reader.read_u8()? as u16);
+        }
+
+        let model_id = match flags.contains(EntityUpdateFlags::MODEL) {
+            true => Some(reader.read_u8()?),
+            false => None,
+        };
+        let frame_id = match flags.contains(EntityUpdateFlags::FRAME) {
+            true => Some(reader.read_u8()?),
+            false => None,
+        };
+        let colormap = match flags.contains(EntityUpdateFlags::COLORMAP) {
+            true => Some(reader.read_u8()?),
+            false => None,
+        };
+        let skin_id = match flags.contains(EntityUpdateFlags::SKIN) {
+            true => Some(reader.read_u8()?),
+            false => None,
+        };
+        let effects = match flags.contains(EntityUpdateFlags::EFFECTS) {
+            true => Some(reader.read_u8()?),
+            false => None,
+        };
+
+        let origin_x = match flags.contains(EntityUpdateFlags::ORIGIN1) {
+            true => Some(CODEC.read_coord(reader)?),
+            false => None,
+        };
+        let pitch = match flags.contains(EntityUpdateFlags::ANGLE1) {
+            true => Some(CODEC.read_angle(reader)?),
+            false => None,
+        };
+        let origin_y = match flags.contains(EntityUpdateFlags::ORIGIN2) {
+            true => Some(CODEC.read_coord(reader)?),
+            false => None,
+        };
+        let yaw = match flags.contains(EntityUpdateFlags::ANGLE2) {
+            true => Some(CODEC.read_angle(reader)?),
+            false => None,
+        };
+        let origin_z = match flags.contains(EntityUpdateFlags::ORIGIN3) {
+            true => Some(CODEC.read_coord(reader)?),
+            false => None,
+        };
+        let roll = match flags.contains(EntityUpdateFlags::ANGLE3) {
+            true => Some(CODEC.read_angle(reader)?),
+            false => None,
+        };
+
+        Ok(Some(EntityDelta {
+            number,
+            remove: flags.contains(EntityUpdateFlags::REMOVE),
+            model_id,
+            frame_id,
+            colormap,
+            skin_id,
+            effects,
+            origin_x,
+            pitch,
+            origin_y,
+            yaw,
+            origin_z,
+            roll,
+        }))
+    }
+
+    /// Writes this delta.
+    pub fn write<W>(&self, writer: &mut W) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        let mut flags = EntityUpdateFlags::empty();
+        flags.set(EntityUpdateFlags::REMOVE, self.remove);
+        flags.set(EntityUpdateFlags::MODEL, self.model_id.is_some());
+        flags.set(EntityUpdateFlags::FRAME, self.frame_id.is_some());
+        flags.set(EntityUpdateFlags::COLORMAP, self.colormap.is_some());
+        flags.set(EntityUpdateFlags::SKIN, self.skin_id.is_some());
+        flags.set(EntityUpdateFlags::EFFECTS, self.effects.is_some());
+        flags.set(EntityUpdateFlags::ORIGIN1, self.origin_x.is_some());
+        flags.set(EntityUpdateFlags::ANGLE1, self.pitch.is_some());
+        flags.set(EntityUpdateFlags::ORIGIN2, self.origin_y.is_some());
+        flags.set(EntityUpdateFlags::ANGLE2, self.yaw.is_some());
+        flags.set(EntityUpdateFlags::ORIGIN3, self.origin_z.is_some());
+        flags.set(EntityUpdateFlags::ANGLE3, self.roll.is_some());
+
+        let more_bits = flags.bits() & 0xFF;
+        flags.set(EntityUpdateFlags::MORE_BITS, more_bits != 0);
+
+        writer.write_u16::<LittleEndian>(
+            flags.bits() & !ENTITY_NUMBER_MASK | self.number & ENTITY_NUMBER_MASK,
+        )?;
+        if more_bits != 0 {
+            writer.write_u8(more_bits as u8)?;
+        }
+
+        for value in [
+            self.model_id,
+            self.frame_id,
+            self.colormap,
+            self.skin_id,
+            self.effects,
+        ]
+        .iter()
+        .flatten()
+        {
+            writer.write_u8(*value)?;
+        }
+
+        let origin = [self.origin_x, self.origin_y, self.origin_z];
+        let angles = [self.pitch, self.yaw, self.roll];
+        for i in 0..3 {
+            if let Some(o) = origin[i] {
+                CODEC.write_coord(writer, o)?;
+            }
+            if let Some(a) = angles[i] {
+                CODEC.write_angle(writer, a)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reconstructs complete entity frames from delta-compressed packet entity updates.
+pub struct PacketEntityFrames {
+    baselines: Vec<EntityState>,
+
+    // recently received frames and the sequence numbers they arrived in
+    frames: Vec<Option<(u32, Vec<EntityState>)>>,
+
+    // the most recent frame that can be used as a delta source
+    valid_sequence: Option<u32>,
+}
+
+impl PacketEntityFrames {
+    pub fn new() -> PacketEntityFrames {
+        PacketEntityFrames {
+            baselines: (0..MAX_EDICTS as u16)
+                .map(EntityState::uninitialized)
+                .collect(),
+            frames: vec![None; UPDATE_BACKUP],
+            valid_sequence: None,
+        }
+    }
+
+    pub fn set_baseline(&mut self, state: EntityState) -> Result<(), NetError> {
+        match self.baselines.get_mut(state.number as usize) {
+            Some(b) => *b = state,
+            None => return Err(NetError::InvalidData(format!("baseline {}", state.number))),
+        }
+
+        Ok(())
+    }
+
+    /// Returns the sequence number of the most recent complete frame.
+    ///
+    /// The client asks the server to compress future updates against this frame.
+    pub fn valid_sequence(&self) -> Option<u32> {
+        self.valid_sequence
+    }
+
+    /// Applies an update received in the packet with sequence number `sequence`, returning the
+    /// complete frame.
+    ///
+    /// `delta_from` is the low byte of the sequence number of the frame the update is relative to,
+    /// or `None` if the update is relative to the baselines.
+    pub fn apply(
+        &mut self,
+        sequence: u32,
+        delta_from: Option<u8>,
+        deltas: &[EntityDelta],
+    ) -> Result<&[EntityState], NetError> {
+        let empty = Vec::new();
+        let old = match delta_from {
+            Some(from) => match self.frames[(from as u32 & UPDATE_MASK) as usize] {
+                Some((seq, ref entities)) if seq & 0xFF == from as u32 => entities,
+                _ => {
+                    // request an uncompressed update
+                    self.valid_sequence = None;
+                    return Err(NetError::InvalidData(format!(
+                        "delta from unknown frame {}",
+                        from
+                    )));
+                }
+            },
+            None => &empty,
+        };
+
+        let mut new = Vec::with_capacity(MAX_PACKET_ENTITIES);
+        let mut old_index = 0;
+        for delta in deltas {
+            // entities absent from the update are unchanged
+            while old_index < old.len() && old[old_index].number < delta.number {
+                new.push(old[old_index].clone());
+                old_index += 1;
+            }
+
+            let from = if old_index < old.len() && old[old_index].number == delta.number {
+                old_index += 1;
+                &old[old_index - 1]
+            } else {
+                match self.baselines.get(delta.number as usize) {
+                    Some(b) => b,
+                    None => return Err(NetError::InvalidData(format!("entity {}", delta.number))),
+                }
+            };
+
+            if !delta.remove {
+                new.push(from.apply(delta));
+            }
+        }
+        new.extend_from_slice(&old[old_index..]);
+
+        if new.len() > MAX_PACKET_ENTITIES {
+            return Err(NetError::InvalidData(format!(
+                "{} packet entities",
+                new.len()
+            )));
+        }
+
+        let slot = (sequence & UPDATE_MASK) as usize;
+        self.frames[slot] = Some((sequence, new));
+        self.valid_sequence = Some(sequence);
+
+        Ok(&self.frames[slot].as_ref().unwrap().1)
+    }
+}
+
+bitflags! {
+    pub struct PlayerInfoFlags: u16 {
+        const MSEC = 1 << 0;
+        const COMMAND = 1 << 1;
+        const VELOCITY1 = 1 << 2;
+        const VELOCITY2 = 1 << 3;
+        const VELOCITY3 = 1 << 4;
+        const MODEL = 1 << 5;
+        const SKIN = 1 << 6;
+        const EFFECTS = 1 << 7;
+        const WEAPON_FRAME = 1 << 8;
+        const DEAD = 1 << 9;
+        const GIB = 1 << 10;
+        const NO_GRAVITY = 1 << 11;
+    }
+}
+
+/// The state of a player in a single frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayerInfo {
+    pub player_id: u8,
+    pub flags: PlayerInfoFlags,
+    pub origin: Vector3<f32>,
+    pub frame_id: u8,
+
+    /// How long ago the player's last command was run, in milliseconds.
+    pub msec: Option<u8>,
+
+    /// The player's last command, used to predict other players' movement.
+    pub command: Option<UserCmd>,
+
+    pub velocity: Vector3<f32>,
+
+    /// The player's model, or `None` for the default player model.
+    pub model_id: Option<u8>,
+    pub skin_id: u8,
+    pub effects: u8,
+    pub weapon_frame: u8,
+}
+
+impl PlayerInfo {
+    fn read<R>(reader: &mut R) -> Result<PlayerInfo, NetError>
+    where
+        R: BufRead + ReadBytesExt,
+    {
+        let player_id = reader.read_u8()?;
+        let flags = PlayerInfoFlags::from_bits_truncate(reader.read_u16::<LittleEndian>()?);
+        let origin = CODEC.read_coord_vector3(reader)?;
+        let frame_id = reader.read_u8()?;
+
+        let msec = match flags.contains(PlayerInfoFlags::MSEC) {
+            true => Some(reader.read_u8()?),
+            false => None,
+        };
+
+        let command = match flags.contains(PlayerInfoFlags::COMMAND) {
+            true => Some(UserCmd::read_delta(&UserCmd::null(), reader)?),
+            false => None,
+        };
+
+        let mut velocity = Vector3::zero();
+        for (i, flag) in [
+            PlayerInfoFlags::VELOCITY1,
+            PlayerInfoFlags::VELOCITY2,
+            PlayerInfoFlags::VELOCITY3,
+        ]
+        .iter()
+        .enumerate()
+        {
+            if flags.contains(*flag) {
+                velocity[i] = reader.read_i16::<LittleEndian>()? as f32;
+            }
+        }
+
+        let model_id = match flags.contains(PlayerInfoFlags::MODEL) {
+            true => Some(reader.read_u8()?),
+            false => None,
+        };
+
+        let mut byte_if = |flag| -> Result<u8, NetError> {
+            Ok(match flags.contains(flag) {
+                true => reader.read_u8()?,
+                false => 0,
+            })
+        };
+        let skin_id = byte_if(PlayerInfoFlags::SKIN)?;
+        let effects = byte_if(PlayerInfoFlags::EFFECTS)?;
+        let weapon_frame = byte_if(PlayerInfoFlags::WEAPON_FRAME)?;
+
+        Ok(PlayerInfo {
+            player_id,
+            flags,
+            origin,
+            frame_id,
+            msec,
+            command,
+            velocity,
+            model_id,
+            skin_id,
+            effects,
+            weapon_frame,
+        })
+    }
+}
+
+/// Physics parameters sent to clients for movement prediction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MoveVars {
+    pub gravity: f32,
+    pub stop_speed: f32,
+    pub max_speed: f32,
+    pub spectator_max_speed: f32,
+    pub accelerate: f32,
+    pub air_accelerate: f32,
+    pub water_accelerate: f32,
+    pub friction: f32,
+    pub water_friction: f32,
+    pub ent_gravity: f32,
+}
+
+/// The first message sent to a client when it joins a level.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerData {
+    pub protocol_version: i32,
+
+    /// Identifies the level. Changes every time the server loads a map.
+    pub server_count: i32,
+    pub game_dir: String,
+    pub player_id: u8,
+    pub spectator: bool,
+    pub level_name: String,
+    pub move_vars: MoveVars,
+}
+
+impl ServerData {
+    fn read<R>(reader: &mut R) -> Result<ServerData, NetError>
+    where
+        R: BufRead + ReadBytesExt,
+    {
+        let protocol_version = reader.read_i32::<LittleEndian>()?;
+        if protocol_version != PROTOCOL_VERSION {
+            return Err(NetError::InvalidData(format!(
+                "protocol version {}",
+                protocol_version
+            )));
+        }
+
+        let server_count = reader.read_i32::<LittleEndian>()?;
+        let game_dir = read_string(reader)?;
+        let player_byte = reader.read_u8()?;
+        let level_name = read_string(reader)?;
+
+        let mut vars = [0.0; 10];
+        for v in vars.iter_mut() {
+            *v = reader.read_f32::<LittleEndian>()?;
+        }
+
+        Ok(ServerData {
+            protocol_version,
+            server_count,
+            game_dir,
+            player_id: player_byte & 0x7F,
+            spectator: player_byte & 0x80 != 0,
+            level_name,
+            move_vars: MoveVars {
+                gravity: vars[0],
+                stop_speed: vars[1],
+                max_speed: vars[2],
+                spectator_max_speed: vars[3],
+                accelerate: vars[4],
+                air_accelerate: vars[5],
+                water_accelerate: vars[6],
+                friction: vars[7],
+                water_friction: vars[8],
+                ent_gravity: vars[9],
+            },
+        })
+    }
+}
+
+// codes of commands whose format is the same as in NetQuake
+const COMMON_CODES: [u8; 18] = [
+    1, 2, 5, 9, 10, 12, 14, 16, 19, 20, 22, 24, 26, 27, 28, 29, 31, 33,
+];
+
+#[derive(Debug, FromPrimitive)]
+pub enum ServerCmdCode {
+    UpdateStat = 3,
+    Sound = 6,
+    Print = 8,
+    ServerData = 11,
+    TempEntity = 23,
+    Intermission = 30,
+    CdTrack = 32,
+    SmallKick = 34,
+    BigKick = 35,
+    UpdatePing = 36,
+    UpdateEnterTime = 37,
+    UpdateStatLong = 38,
+    MuzzleFlash = 39,
+    UpdateUserInfo = 40,
+    Download = 41,
+    PlayerInfo = 42,
+    Nails = 43,
+    ChokeCount = 44,
+    ModelList = 45,
+    SoundList = 46,
+    PacketEntities = 47,
+    DeltaPacketEntities = 48,
+    MaxSpeed = 49,
+    EntGravity = 50,
+    SetInfo = 51,
+    ServerInfo = 52,
+    UpdatePl = 53,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ServerCmd {
+    /// A command whose format is the same as in NetQuake.
+    Common(NetQuakeServerCmd),
+    UpdateStat {
+        stat: u8,
+        value: i32,
+    },
+    Sound {
+        volume: Option<u8>,
+        attenuation: Option<f32>,
+        entity_id: u16,
+        channel: u8,
+        sound_id: u8,
+        position: Vector3<f32>,
+    },
+    Print {
+        level: u8,
+        text: String,
+    },
+    ServerData(ServerData),
+    TempEntity {
+        temp_entity: TempEntity,
+    },
+    Intermission {
+        origin: Vector3<f32>,
+        angles: Vector3<Deg<f32>>,
+    },
+    CdTrack {
+        track: u8,
+    },
+    SmallKick,
+    BigKick,
+    UpdatePing {
+        player_id: u8,
+        ping: i16,
+    },
+    UpdateEnterTime {
+        player_id: u8,
+        seconds_ago: f32,
+    },
+    MuzzleFlash {
+        entity_id: u16,
+    },
+    UpdateUserInfo {
+        player_id: u8,
+        user_id: i32,
+        info: String,
+    },
+    Download {
+        size: i16,
+        percent: u8,
+        data: Vec<u8>,
+    },
+    PlayerInfo(PlayerInfo),
+    Nails {
+        projectiles: Vec<[u8; 6]>,
+    },
+    ChokeCount {
+        count: u8,
+    },
+    ModelList {
+        start: u8,
+        names: Vec<String>,
+        next: u8,
+    },
+    SoundList {
+        start: u8,
+        names: Vec<String>,
+        next: u8,
+    },
+    PacketEntities {
+        delta_from: Option<u8>,
+        deltas: Vec<EntityDelta>,
+    },
+    MaxSpeed {
+        speed: f32,
+    },
+    EntGravity {
+        gravity: f32,
+    },
+    SetInfo {
+        player_id: u8,
+        key: String,
+        value: String,
+    },
+    ServerInfo {
+        key: String,
+        value: String,
+    },
+    UpdatePl {
+        player_id: u8,
+        loss: u8,
+    },
+}
+
+impl ServerCmd {
+    pub fn deserialize<R>(reader: &mut R) -> Result<Option<ServerCmd>, NetError>
+    where
+        R: BufRead + ReadBytesExt,
+    {
+        let code_num = match reader.fill_buf()?.first() {
+            Some(c) => *c,
+            None => return Ok(None),
+        };
+
+        if COMMON_CODES.contains(&code_num) {
+            return Ok(NetQuakeServerCmd::deserialize(reader)?.map(ServerCmd::Common));
+        }
+
+        reader.consume(1);
+        let code = match ServerCmdCode::from_u8(code_num) {
+            Some(c) => c,
+            None => {
+                return Err(NetError::InvalidData(format!(
+                    "Invalid server command code: {}",
+                    code_num
+                )))
+            }
+        };
+
+        let cmd = match code {
+            ServerCmdCode::UpdateStat => ServerCmd::UpdateStat {
+                stat: reader.read_u8()?,
+                value: reader.read_u8()? as i32,
+            },
+
+            ServerCmdCode::UpdateStatLong => ServerCmd::UpdateStat {
+                stat: reader.read_u8()?,
+                value: reader.read_i32::<LittleEndian>()?,
+            },
+
+            ServerCmdCode::Sound => {
+                let ent_channel = reader.read_u16::<LittleEndian>()?;
+                let volume = match ent_channel & SOUND_VOLUME != 0 {
+                    true => Some(reader.read_u8()?),
+                    false => None,
+                };
+                let attenuation = match ent_channel & SOUND_ATTENUATION != 0 {
+                    true => Some(reader.read_u8()? as f32 / 64.0),
+                    false => None,
+                };
+
+                ServerCmd::Sound {
+                    volume,
+                    attenuation,
+                    entity_id: (ent_channel >> 3) & 1023,
+                    channel: (ent_channel & 7) as u8,
+                    sound_id: reader.read_u8()?,
+                    position: CODEC.read_coord_vector3(reader)?,
+                }
+            }
+
+            ServerCmdCode::Print => ServerCmd::Print {
+                level: reader.read_u8()?,
+                text: read_string(reader)?,
+            },
+
+            ServerCmdCode::ServerData => ServerCmd::ServerData(ServerData::read(reader)?),
+
+            ServerCmdCode::TempEntity => ServerCmd::TempEntity {
+                temp_entity: read_temp_entity(reader)?,
+            },
+
+            ServerCmdCode::Intermission => ServerCmd::Intermission {
+                origin: CODEC.read_coord_vector3(reader)?,
+                angles: CODEC.read_angle_vector3(reader)?,
+            },
+
+            ServerCmdCode::CdTrack => ServerCmd::CdTrack {
+                track: reader.read_u8()?,
+            },
+
+            ServerCmdCode::SmallKick => ServerCmd::SmallKick,
+            ServerCmdCode::BigKick => ServerCmd::BigKick,
+
+            ServerCmdCode::UpdatePing => ServerCmd::UpdatePing {
+                player_id: reader.read_u8()?,
+                ping: reader.read_i16::<LittleEndian>()?,
+            },
+
+            ServerCmdCode::UpdateEnterTime => ServerCmd::UpdateEnterTime {
+                player_id: reader.read_u8()?,
+                seconds_ago: reader.read_f32::<LittleEndian>()?,
+            },
+
+            ServerCmdCode::MuzzleFlash => ServerCmd::MuzzleFlash {
+                entity_id: reader.read_u16::<LittleEndian>()?,
+            },
+
+            ServerCmdCode::UpdateUserInfo => ServerCmd::UpdateUserInfo {
+                player_id: reader.read_u8()?,
+                user_id: reader.read_i32::<LittleEndian>()?,
+                info: read_string(reader)?,
+            },
+
+            ServerCmdCode::Download => {
+                let size = reader.read_i16::<LittleEndian>()?;
+                let percent = reader.read_u8()?;
+                let mut data = vec![0; size.max(0) as usize];
+                reader.read_exact(&mut data)?;
+                ServerCmd::Download {
+                    size,
+                    percent,
+                    data,
+                }
+            }
+
+            ServerCmdCode::PlayerInfo => ServerCmd::PlayerInfo(PlayerInfo::read(reader)?),
+
+            ServerCmdCode::Nails => {
+                let count = reader.read_u8()?;
+                let mut projectiles = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let mut bits = [0; 6];
+                    reader.read_exact(&mut bits)?;
+                    projectiles.push(bits);
+                }
+                ServerCmd::Nails { projectiles }
+            }
+
+            ServerCmdCode::ChokeCount => ServerCmd::ChokeCount {
+                count: reader.read_u8()?,
+            },
+
+            ServerCmdCode::ModelList | ServerCmdCode::SoundList => {
+                let start = reader.read_u8()?;
+                let mut names = Vec::new();
+                loop {
+                    let name = read_string(reader)?;
+                    if name.is_empty() {
+                        break;
+                    }
+                    names.push(name);
+                }
+                let next = reader.read_u8()?;
+
+                match code {
+                    ServerCmdCode::ModelList => ServerCmd::ModelList { start, names, next },
+                    _ => ServerCmd::SoundList { start, names, next },
+                }
+            }
+
+            ServerCmdCode::PacketEntities | ServerCmdCode::DeltaPacketEntities => {
+                let delta_from = match code {
+                    ServerCmdCode::DeltaPacketEntities => Some(reader.read_u8()?),
+                    _ => None,
+                };
+
+                let mut deltas = Vec::new();
+                while let Some(delta) = EntityDelta::read(reader)? {
+                    deltas.push(delta);
+                }
+
+                ServerCmd::PacketEntities { delta_from, deltas }
+            }
+
+            ServerCmdCode::MaxSpeed => ServerCmd::MaxSpeed {
+                speed: reader.read_f32::<LittleEndian>()?,
+            },
+
+            ServerCmdCode::EntGravity => ServerCmd::EntGravity {
+                gravity: reader.read_f32::<LittleEndian>()?,
+            },
+
+            ServerCmdCode::SetInfo => ServerCmd::SetInfo {
+                player_id: reader.read_u8()?,
+                key: read_string(reader)?,
+                value: read_string(reader)?,
+            },
+
+            ServerCmdCode::ServerInfo => ServerCmd::ServerInfo {
+                key: read_string(reader)?,
+                value: read_string(reader)?,
+            },
+
+            ServerCmdCode::UpdatePl => ServerCmd::UpdatePl {
+                player_id: reader.read_u8()?,
+                loss: reader.read_u8()?,
+            },
+        };
+
+        Ok(Some(cmd))
+    }
+}
+
+const SOUND_VOLUME: u16 = 1 << 15;
+const SOUND_ATTENUATION: u16 = 1 << 14;
+
+#[derive(FromPrimitive)]
+pub enum ClientCmdCode {
+    NoOp = 1,
+    Move = 3,
+    StringCmd = 4,
+    Delta = 5,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ClientCmd {
+    NoOp,
+
+    /// The client's last three commands, oldest first.
+    Move {
+        checksum: u8,
+        loss: u8,
+        cmds: [UserCmd; 3],
+    },
+
+    StringCmd {
+        cmd: String,
+    },
+
+    /// Requests that entity updates be compressed against the frame received in the packet with
+    /// this sequence number.
+    Delta {
+        sequence: u8,
+    },
+}
+
+impl ClientCmd {
+    pub fn code(&self) -> u8 {
+        match *self {
+            ClientCmd::NoOp => ClientCmdCode::NoOp as u8,
+            ClientCmd::Move { .. } => ClientCmdCode::Move as u8,
+            ClientCmd::StringCmd { .. } => ClientCmdCode::StringCmd as u8,
+            ClientCmd::Delta { .. } => ClientCmdCode::Delta as u8,
+        }
+    }
+
+    pub fn deserialize<R>(reader: &mut R) -> Result<ClientCmd, NetError>
+    where
+        R: BufRead + ReadBytesExt,
+    {
+        let code_val = reader.read_u8()?;
+        let code = match ClientCmdCode::from_u8(code_val) {
+            Some(c) => c,
+            None => {
+                return Err(NetError::InvalidData(format!(
+                    "Invalid client command code: {}",
+                    code_val
+                )))
+            }
+        };
+
+        Ok(match code {
+            ClientCmdCode::NoOp => ClientCmd::NoOp,
+            ClientCmdCode::Move => {
+                let checksum = reader.read_u8()?;
+                let loss = reader.read_u8()?;
+                let oldest = UserCmd::read_delta(&UserCmd::null(), reader)?;
+                let older = UserCmd::read_delta(&oldest, reader)?;
+                let newest = UserCmd::read_delta(&older, reader)?;
+                ClientCmd::Move {
+                    checksum,
+                    loss,
+                    cmds: [oldest, older, newest],
+                }
+            }
+            ClientCmdCode::StringCmd => ClientCmd::StringCmd {
+                cmd: read_string(reader)?,
+            },
+            ClientCmdCode::Delta => ClientCmd::Delta {
+                sequence: reader.read_u8()?,
+            },
+        })
+    }
+
+    pub fn serialize<W>(&self, writer: &mut W) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        writer.write_u8(self.code())?;
+
+        match *self {
+            ClientCmd::NoOp => (),
+            ClientCmd::Move {
+                checksum,
+                loss,
+                ref cmds,
+            } => {
+                writer.write_u8(checksum)?;
+                writer.write_u8(loss)?;
+                cmds[0].write_delta(&UserCmd::null(), writer)?;
+                cmds[1].write_delta(&cmds[0], writer)?;
+                cmds[2].write_delta(&cmds[1], writer)?;
+            }
+            ClientCmd::StringCmd { ref cmd } => {
+                writer.write_all(cmd.as_bytes())?;
+                writer.write_u8(0)?;
+            }
+            ClientCmd::Delta { sequence } => writer.write_u8(sequence)?,
+        }
+
+        Ok(())
+    }
+}
+
+// QuakeWorld sends a particle count with gunshots and blood, and replaces NetQuake's colored
+// explosion and grappling hook cable with blood effects
+fn read_temp_entity<R>(reader: &mut R) -> Result<TempEntity, NetError>
+where
+    R: BufRead + ReadBytesExt,
+{
+    let code = reader.read_u8()?;
+
+    let point_kind = match code {
+        0 => Some(PointEntityKind::Spike),
+        1 => Some(PointEntityKind::SuperSpike),
+        3 => Some(PointEntityKind::Explosion),
+        4 => Some(PointEntityKind::TarExplosion),
+        7 => Some(PointEntityKind::WizSpike),
+        8 => Some(PointEntityKind::KnightSpike),
+        10 => Some(PointEntityKind::LavaSplash),
+        11 => Some(PointEntityKind::Teleport),
+
+        // TODO: blood particles
+        2 | 12 => {
+            let _count = reader.read_u8()?;
+            Some(PointEntityKind::Gunshot)
+        }
+        13 => Some(PointEntityKind::Gunshot),
+
+        _ => None,
+    };
+
+    if let Some(kind) = point_kind {
+        return Ok(TempEntity::Point {
+            kind,
+            origin: CODEC.read_coord_vector3(reader)?,
+        });
+    }
+
+    Ok(match code {
+        5 | 6 | 9 => TempEntity::Beam {
+            kind: BeamEntityKind::Lightning {
+                model_id: match code {
+                    5 => 1,
+                    6 => 2,
+                    _ => 3,
+                },
+            },
+            entity_id: reader.read_i16::<LittleEndian>()?,
+            start: CODEC.read_coord_vector3(reader)?,
+            end: CODEC.read_coord_vector3(reader)?,
+        },
+
+        c => return Err(NetError::InvalidData(format!("Temp entity code {}", c))),
+    })
+}
+
+fn read_string<R>(reader: &mut R) -> Result<String, NetError>
+where
+    R: BufRead,
+{
+    util::read_cstring(reader).map_err(|e| NetError::InvalidData(format!("{}", e)))
+}
+
+// BSP lumps excluded from the checksum so that maps with rebuilt visibility still match
+const CHECKSUM2_SKIPPED_LUMPS: [usize; 4] = [0, 4, 5, 10];
+const BSP_LUMP_COUNT: usize = 15;
+
+/// Computes the checksum of a BSP file's collision data that clients send to prove they have
+/// the same map as the server.
+pub fn map_checksum2<R>(reader: &mut R) -> Result<u32, NetError>
+where
+    R: Read + Seek,
+{
+    reader.seek(SeekFrom::Start(0))?;
+    let _version = reader.read_i32::<LittleEndian>()?;
+
+    let mut lumps = Vec::with_capacity(BSP_LUMP_COUNT);
+    for _ in 0..BSP_LUMP_COUNT {
+        let offset = reader.read_u32::<LittleEndian>()?;
+        let size = reader.read_u32::<LittleEndian>()?;
+        lumps.push((offset, size));
+    }
+
+    let mut checksum = 0;
+    for (id, (offset, size)) in lumps.into_iter().enumerate() {
+        if CHECKSUM2_SKIPPED_LUMPS.contains(&id) {
+            continue;
+        }
+
+        let mut data = vec![0; size as usize];
+        reader.seek(SeekFrom::Start(offset as u64))?;
+        reader.read_exact(&mut data)?;
+        checksum ^= block_checksum(&data);
+    }
+
+    Ok(checksum)
+}
+
+// folds the MD4 digest of `data` into a single word
+fn block_checksum(data: &[u8]) -> u32 {
+    let digest = md4(data);
+    let mut checksum = 0;
+    for word in digest.chunks(4) {
+        checksum ^= u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    checksum
+}
+
+// MD4 message digest (RFC 1320)
+fn md4(data: &[u8]) -> [u8; 16] {
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+    for block in message.chunks(64) {
+        let mut x = [0u32; 16];
+        for (i, word) in block.chunks(4).enumerate() {
+            x[i] = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+        }
+
+        let [mut a, mut b, mut c, mut d] = state;
+
+        let f = |x: u32, y: u32, z: u32| (x & y) | (!x & z);
+        let g = |x: u32, y: u32, z: u32| (x & y) | (x & z) | (y & z);
+        let h = |x: u32, y: u32, z: u32| x ^ y ^ z;
+
+        for &i in &[0, 4, 8, 12] {
+            a = a.wrapping_add(f(b, c, d)).wrapping_add(x[i]).rotate_left(3);
+            d = d
+                .wrapping_add(f(a, b, c))
+                .wrapping_add(x[i + 1])
+                .rotate_left(7);
+            c = c
+                .wrapping_add(f(d, a, b))
+                .wrapping_add(x[i + 2])
+                .rotate_left(11);
+            b = b
+                .wrapping_add(f(c, d, a))
+                .wrapping_add(x[i + 3])
+                .rotate_left(19);
+        }
+
+        for &i in &[0, 1, 2, 3] {
+            let k = 0x5A827999;
+            a = a
+                .wrapping_add(g(b, c, d))
+                .wrapping_add(x[i])
+                .wrapping_add(k)
+                .rotate_left(3);
+            d = d
+                .wrapping_add(g(a, b, c))
+                .wrapping_add(x[i + 4])
+                .wrapping_add(k)
+                .rotate_left(5);
+            c = c
+                .wrapping_add(g(d, a, b))
+                .wrapping_add(x[i + 8])
+                .wrapping_add(k)
+                .rotate_left(9);
+            b = b
+                .wrapping_add(g(c, d, a))
+                .wrapping_add(x[i + 12])
+                .wrapping_add(k)
+                .rotate_left(13);
+        }
+
+        for &i in &[0, 2, 1, 3] {
+            let k = 0x6ED9EBA1;
+            a = a
+                .wrapping_add(h(b, c, d))
+                .wrapping_add(x[i])
+                .wrapping_add(k)
+                .rotate_left(3);
+            d = d
+                .wrapping_add(h(a, b, c))
+                .wrapping_add(x[i + 8])
+                .wrapping_add(k)
+                .rotate_left(9);
+            c = c
+                .wrapping_add(h(d, a, b))
+                .wrapping_add(x[i + 4])
+                .wrapping_add(k)
+                .rotate_left(11);
+            b = b
+                .wrapping_add(h(c, d, a))
+                .wrapping_add(x[i + 12])
+                .wrapping_add(k)
+                .rotate_left(15);
+        }
+
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+    }
+
+    let mut digest = [0; 16];
+    for (i, word) in state.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::BufReader;
+
+    fn empty_delta(number: u16) -> EntityDelta {
+        EntityDelta {
+            number,
+            remove: false,
+            model_id: None,
+            frame_id: None,
+            colormap: None,
+            skin_id: None,
+            effects: None,
+            origin_x: None,
+            pitch: None,
+            origin_y: None,
+            yaw: None,
+            origin_z: None,
+            roll: None,
+        }
+    }
+
+    #[test]
+    fn test_md4() {
+        let hex = |d: [u8; 16]| -> String { d.iter().map(|b| format!("{:02x}", b)).collect() };
+
+        assert_eq!(hex(md4(b"")), "31d6cfe0d16ae931b73c59d7e0c089c0");
+        assert_eq!(hex(md4(b"abc")), "a448017aaf21d8525fc10ae87aa6729d");
+    }
+
+    #[test]
+    fn test_oob_response_parse() {
+        let packet = oob_packet("c12345\0");
+        let contents = oob_contents(&packet).unwrap();
+        assert_eq!(
+            OobResponse::parse(contents).unwrap(),
+            OobResponse::Challenge(12345)
+        );
+
+        let packet = oob_packet("nServer is full.\n");
+        let contents = oob_contents(&packet).unwrap();
+        assert_eq!(
+            OobResponse::parse(contents).unwrap(),
+            OobResponse::Print(String::from("Server is full.\n"))
+        );
+    }
+
+    #[test]
+    fn test_info_string() {
+        let info = info_string(&[("name", "player"), ("rate", "2500")]);
+        assert_eq!(info, "\\name\\player\\rate\\2500");
+        assert_eq!(info_value(&info, "rate"), Some("2500"));
+        assert_eq!(info_value(&info, "topcolor"), None);
+    }
+
+    #[test]
+    fn test_netchan_reliable_acknowledged() {
+        let mut client = Netchan::client(1234);
+        let mut server = Netchan::server();
+
+        client.queue_reliable(b"new");
+        let packet = client.transmit(b"move");
+        assert_eq!(server.process(&packet).unwrap().unwrap(), b"newmove");
+
+        // the reliable message is resent until the server acknowledges it
+        let packet = server.transmit(&[]);
+        let packet2 = client.transmit(b"move");
+        assert_eq!(server.process(&packet2).unwrap().unwrap(), b"move");
+        client.process(&packet).unwrap();
+        let packet = server.transmit(&[]);
+        client.process(&packet).unwrap();
+
+        client.queue_reliable(b"begin");
+        let packet = client.transmit(&[]);
+        assert_eq!(server.process(&packet).unwrap().unwrap(), b"begin");
+
+        // duplicated packets are discarded
+        assert!(server.process(&packet).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_user_cmd_delta_read_write_eq() {
+        let first = UserCmd {
+            msec: 13,
+            angles: Vector3::new(Deg(-45.0), Deg(90.0), Deg(0.0)),
+            forward_move: 400,
+            side_move: 0,
+            up_move: 0,
+            buttons: ButtonFlags::ATTACK,
+            impulse: 0,
+        };
+        let second = UserCmd {
+            msec: 14,
+            side_move: -350,
+            impulse: 5,
+            ..first
+        };
+
+        let mut packet = Vec::new();
+        first.write_delta(&UserCmd::null(), &mut packet).unwrap();
+        second.write_delta(&first, &mut packet).unwrap();
+
+        let mut reader = BufReader::new(packet.as_slice());
+        let first_read = UserCmd::read_delta(&UserCmd::null(), &mut reader).unwrap();
+        let second_read = UserCmd::read_delta(&first_read, &mut reader).unwrap();
+        assert_eq!(first, first_read);
+        assert_eq!(second, second_read);
+    }
+
+    #[test]
+    fn test_entity_delta_read_write_eq() {
+        let src = EntityDelta {
+            model_id: Some(3),
+            skin_id: Some(1),
+            origin_x: Some(-128.0),
+            yaw: Some(Deg(90.0)),
+            origin_z: Some(24.5),
+            ..empty_delta(400)
+        };
+
+        let mut packet = Vec::new();
+        src.write(&mut packet).unwrap();
+        packet.extend_from_slice(&[0, 0]);
+
+        let mut reader = BufReader::new(packet.as_slice());
+        assert_eq!(EntityDelta::read(&mut reader).unwrap(), Some(src));
+        assert_eq!(EntityDelta::read(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_packet_entity_frames_delta() {
+        let mut frames = PacketEntityFrames::new();
+        frames
+            .set_baseline(EntityState {
+                model_id: 7,
+                ..EntityState::uninitialized(20)
+            })
+            .unwrap();
+
+        let full = [
+            EntityDelta {
+                model_id: Some(2),
+                ..empty_delta(10)
+            },
+            EntityDelta {
+                origin_x: Some(64.0),
+                ..empty_delta(20)
+            },
+        ];
+        let entities = frames.apply(300, None, &full).unwrap().to_vec();
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[1].model_id, 7);
+        assert_eq!(entities[1].origin.x, 64.0);
+        assert_eq!(frames.valid_sequence(), Some(300));
+
+        // remove entity 10, move entity 20 and add entity 30
+        let delta = [
+            EntityDelta {
+                remove: true,
+                ..empty_delta(10)
+            },
+            EntityDelta {
+                origin_y: Some(32.0),
+                ..empty_delta(20)
+            },
+            empty_delta(30),
+        ];
+        let entities = frames.apply(301, Some((300 & 0xFF) as u8), &delta).unwrap();
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[0].number, 20);
+        assert_eq!(entities[0].origin, Vector3::new(64.0, 32.0, 0.0));
+        assert_eq!(entities[1].number, 30);
+
+        // a delta from a frame that was never received can't be applied
+        assert!(frames.apply(302, Some(200), &delta).is_err());
+        assert_eq!(frames.valid_sequence(), None);
+    }
+
+    #[test]
+    fn test_server_cmd_temp_entity_gunshot() {
+        let packet = [23, 2, 4, 0x80, 0, 0, 0, 0, 1];
+        let mut reader = BufReader::new(&packet[..]);
+
+        assert_eq!(
+            ServerCmd::deserialize(&mut reader).unwrap().unwrap(),
+            ServerCmd::TempEntity {
+                temp_entity: TempEntity::Point {
+                    kind: PointEntityKind::Gunshot,
+                    origin: Vector3::new(16.0, 0.0, 32.0),
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_server_cmd_common_passes_through() {
+        let src = NetQuakeServerCmd::StuffText {
+            text: String::from("reconnect\n"),
+        };
+        let mut packet = Vec::new();
+        src.serialize(&mut packet).unwrap();
+
+        let mut reader = BufReader::new(packet.as_slice());
+        assert_eq!(
+            ServerCmd::deserialize(&mut reader).unwrap().unwrap(),
+            ServerCmd::Common(src)
+        );
+        assert!(ServerCmd::deserialize(&mut reader).unwrap().is_none());
+    }
+}
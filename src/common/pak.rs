@@ -24,6 +24,8 @@ use std::{
     path::Path,
 };
 
+use crate::common::limits::{LimitError, LoadLimits};
+
 use byteorder::{LittleEndian, ReadBytesExt};
 use thiserror::Error;
 
@@ -40,6 +42,14 @@ pub enum PakError {
     InvalidTableOffset(i32),
     #[error("Invalid file table size: {0}")]
     InvalidTableSize(i32),
+    #[error("File table (offset {offset}, size {size}) extends past the end of the archive")]
+    TableOutOfBounds { offset: u32, size: u32 },
+    #[error("File {path} (offset {offset}, size {size}) extends past the end of the archive")]
+    FileOutOfBounds {
+        path: String,
+        offset: u32,
+        size: u32,
+    },
     #[error("Invalid file offset: {0}")]
     InvalidFileOffset(i32),
     #[error("Invalid file size: {0}")]
@@ -50,6 +60,8 @@ pub enum PakError {
     NonUtf8FileName(#[from] std::string::FromUtf8Error),
     #[error("No such file in PAK archive: {0}")]
     NoSuchFile(String),
+    #[error("Limit exceeded: {0}")]
+    Limit(#[from] LimitError),
 }
 
 /// An open Pak archive.
@@ -57,15 +69,28 @@ pub struct Pak(HashMap<String, Box<[u8]>>);
 
 impl Pak {
     // TODO: rename to from_path or similar
-    pub fn new<P>(path: P) -> Result<Pak, PakError>
+    pub fn new<P>(path: P, limits: &LoadLimits) -> Result<Pak, PakError>
     where
         P: AsRef<Path>,
     {
-        debug!("Opening {}", path.as_ref().to_str().unwrap());
+        debug!("Opening {}", path.as_ref().display());
+
+        Pak::from_reader(fs::File::open(path)?, limits)
+    }
+
+    /// Reads a Pak archive from `infile`.
+    ///
+    /// The file table and the files it describes must lie within the archive, and no file may be
+    /// larger than the lump size limit.
+    pub fn from_reader<R>(mut infile: R, limits: &LoadLimits) -> Result<Pak, PakError>
+    where
+        R: Read + Seek,
+    {
+        let archive_len = infile.seek(SeekFrom::End(0))?;
+        infile.seek(SeekFrom::Start(0))?;
 
-        let mut infile = fs::File::open(path)?;
         let mut magic = [0u8; 4];
-        infile.read(&mut magic)?;
+        infile.read_exact(&mut magic)?;
 
         if magic != PAK_MAGIC {
             Err(PakError::InvalidMagicNumber(magic))?;
@@ -82,6 +107,14 @@ impl Pak {
             s => s as u32,
         };
 
+        limits.check_lump_size(table_size as usize)?;
+        if table_offset as u64 + table_size as u64 > archive_len {
+            Err(PakError::TableOutOfBounds {
+                offset: table_offset,
+                size: table_size,
+            })?;
+        }
+
         let mut map = HashMap::new();
 
         for i in 0..(table_size as usize / PAK_ENTRY_SIZE) {
@@ -89,7 +122,7 @@ impl Pak {
             infile.seek(SeekFrom::Start(entry_offset))?;
 
             let mut path_bytes = [0u8; 56];
-            infile.read(&mut path_bytes)?;
+            infile.read_exact(&mut path_bytes)?;
 
             let file_offset = match infile.read_i32::<LittleEndian>()? {
                 o if o <= 0 => Err(PakError::InvalidFileOffset(o))?,
@@ -108,6 +141,16 @@ impl Pak {
                     String::from_utf8_lossy(&path_bytes).into_owned(),
                 ))?;
             let path = String::from_utf8(path_bytes[0..last].to_vec())?;
+            limits.check_lump_size(file_size as usize)?;
+
+            if file_offset as u64 + file_size as u64 > archive_len {
+                return Err(PakError::FileOutOfBounds {
+                    path,
+                    offset: file_offset,
+                    size: file_size,
+                });
+            }
+
             infile.seek(SeekFrom::Start(file_offset as u64))?;
 
             let mut data: Vec<u8> = Vec::with_capacity(file_size as usize);
@@ -126,10 +169,10 @@ impl Pak {
     /// # Examples
     /// ```no_run
     /// # extern crate richter;
-    /// use richter::common::pak::Pak;
+    /// use richter::common::{limits::LoadLimits, pak::Pak};
     ///
     /// # fn main() {
-    /// let mut pak = Pak::new("pak0.pak").unwrap();
+    /// let mut pak = Pak::new("pak0.pak", &LoadLimits::default()).unwrap();
     /// let progs_dat = pak.open("progs.dat").unwrap();
     /// # }
     /// ```
@@ -24,6 +24,7 @@ use nom::{
     combinator::{opt, recognize},
     multi::{many0, many1},
     sequence::{delimited, preceded, terminated, tuple},
+    Offset,
 };
 
 /// Match a line comment.
@@ -99,6 +100,12 @@ pub fn arg(input: &str) -> nom::IResult<&str, &str> {
     alt((quoted, basic_arg))(input)
 }
 
+/// Returns `true` if `arg`, an argument matched from `input`, was a quoted string.
+pub fn is_quoted(input: &str, arg: &str) -> bool {
+    let start = input.offset(arg);
+    start > 0 && input[..start].ends_with('"')
+}
+
 /// Match a command terminator.
 ///
 /// Commands can be terminated by either:
@@ -172,6 +179,14 @@ mod test {
         assert_eq!(result, Ok((";\n", "quoted argument")));
     }
 
+    #[test]
+    fn test_is_quoted() {
+        let input = "say \"(1 + 2)\" (3);\n";
+        let (_, args) = command(input).unwrap();
+        let quoted: Vec<bool> = args.iter().map(|a| is_quoted(input, a)).collect();
+        assert_eq!(quoted, vec![false, true, false]);
+    }
+
     #[test]
     fn test_command_basic() {
         let result = command("arg_0 arg_1;\n");
@@ -0,0 +1,182 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! PK3 (ZIP) archive support.
+//!
+//! PK3 files are ordinary ZIP archives with a different extension. Unlike PAK files, they are
+//! frequently authored on case-insensitive filesystems, so all lookups are case-insensitive.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    io::{self, Read, Seek},
+    path::Path,
+};
+
+use thiserror::Error;
+use zip::{result::ZipError, ZipArchive};
+
+#[derive(Error, Debug)]
+pub enum Pk3Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("ZIP error: {0}")]
+    Zip(#[from] ZipError),
+    #[error("No such file in PK3 archive: {0}")]
+    NoSuchFile(String),
+}
+
+// the source of an archive's data, which is kept open so that files can be read on demand
+trait ArchiveSource: Read + Seek {}
+
+impl<R> ArchiveSource for R where R: Read + Seek {}
+
+/// An open PK3 archive.
+///
+/// Only the archive's directory is read when it is opened. Files are decompressed each time they
+/// are opened. File names are stored in lowercase.
+pub struct Pk3 {
+    archive: RefCell<ZipArchive<Box<dyn ArchiveSource>>>,
+
+    // index of each file in the archive, by lowercase name
+    files: HashMap<String, usize>,
+}
+
+impl Pk3 {
+    pub fn new<P>(path: P) -> Result<Pk3, Pk3Error>
+    where
+        P: AsRef<Path>,
+    {
+        debug!("Opening {}", path.as_ref().to_str().unwrap());
+        Pk3::from_reader(fs::File::open(path)?)
+    }
+
+    /// Reads the directory of a ZIP archive.
+    pub fn from_reader<R>(reader: R) -> Result<Pk3, Pk3Error>
+    where
+        R: Read + Seek + 'static,
+    {
+        let source: Box<dyn ArchiveSource> = Box::new(reader);
+        let mut archive = ZipArchive::new(source)?;
+        let mut files = HashMap::new();
+
+        for i in 0..archive.len() {
+            let file = archive.by_index(i)?;
+
+            // directories are implied by file paths
+            if file.is_dir() {
+                continue;
+            }
+
+            // some archivers emit Windows-style path separators
+            let name = file.name().replace('\\', "/").to_lowercase();
+            files.insert(name, i);
+        }
+
+        Ok(Pk3 {
+            archive: RefCell::new(archive),
+            files,
+        })
+    }
+
+    /// Returns `true` if the archive contains a file, ignoring case.
+    pub fn contains<S>(&self, path: S) -> bool
+    where
+        S: AsRef<str>,
+    {
+        self.files.contains_key(&path.as_ref().to_lowercase())
+    }
+
+    /// Decompresses a file in the archive, ignoring case.
+    pub fn open<S>(&self, path: S) -> Result<Vec<u8>, Pk3Error>
+    where
+        S: AsRef<str>,
+    {
+        let path = path.as_ref();
+        let index = *self
+            .files
+            .get(&path.to_lowercase())
+            .ok_or_else(|| Pk3Error::NoSuchFile(path.to_owned()))?;
+
+        let mut archive = self.archive.borrow_mut();
+        let mut file = archive.by_index(index)?;
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Returns the lowercase names of the files in the archive, in no particular order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(|k| k.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::{Cursor, Write as _};
+
+    use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+    fn build_archive(files: &[(&str, &[u8])]) -> Cursor<Vec<u8>> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+
+        for (name, data) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+
+        let mut cursor = writer.finish().unwrap();
+        cursor.set_position(0);
+        cursor
+    }
+
+    #[test]
+    fn test_pk3_open_case_insensitive() {
+        let pk3 = Pk3::from_reader(build_archive(&[
+            ("maps/E1M1.bsp", b"world"),
+            ("Progs/Player.MDL", b"player"),
+        ]))
+        .unwrap();
+
+        assert_eq!(pk3.open("maps/e1m1.bsp").unwrap(), b"world");
+        assert_eq!(pk3.open("MAPS/E1M1.BSP").unwrap(), b"world");
+        assert_eq!(pk3.open("progs/player.mdl").unwrap(), b"player");
+        assert!(pk3.open("maps/e1m2.bsp").is_err());
+    }
+
+    #[test]
+    fn test_pk3_skips_directories() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .add_directory("sound/", FileOptions::default())
+            .unwrap();
+        writer
+            .start_file("sound/misc.wav", FileOptions::default())
+            .unwrap();
+        writer.write_all(b"wav").unwrap();
+        let mut cursor = writer.finish().unwrap();
+        cursor.set_position(0);
+
+        let pk3 = Pk3::from_reader(cursor).unwrap();
+        let names: Vec<_> = pk3.names().collect();
+        assert_eq!(names, vec!["sound/misc.wav"]);
+    }
+}
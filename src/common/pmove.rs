@@ -0,0 +1,727 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Player movement physics.
+//!
+//! This is the walking, swimming and jumping code run by the server for each client, kept free of
+//! server state so that the client can run it too when predicting the player's movement. Movement
+//! is only tested against the world; brush entities such as doors and lifts are left to the server.
+
+use std::cell::RefCell;
+
+use crate::common::{
+    bsp::{BspCollisionHull, BspError, BspLeafContents, BspModel},
+    console::{ConsoleError, CvarRegistry},
+    frustum,
+    math::Angles,
+};
+
+use cgmath::{Angle, Deg, InnerSpace, Vector3, Zero};
+
+// the tallest ledge the player can walk up without jumping
+const STEP_SIZE: f32 = 18.0;
+
+// velocity components smaller than this are zeroed after clipping against a plane
+const STOP_EPSILON: f32 = 0.1;
+
+// how far short of a solid surface traces stop, so that the next move doesn't start inside it
+const DIST_EPSILON: f32 = 0.03125;
+
+// the number of times a single move may be deflected before giving up
+const MAX_BUMPS: usize = 4;
+const MAX_CLIP_PLANES: usize = 5;
+
+// limits the number of liquid boundaries a single trace may pass through
+const MAX_TRACE_SEGMENTS: usize = 8;
+
+// planes with a shallower normal than this are too steep to stand on
+const MIN_GROUND_NORMAL_Z: f32 = 0.7;
+
+// upward speed given by a jump, as set by the game logic
+const JUMP_VELOCITY: f32 = 270.0;
+
+// upward speed given by holding jump while swimming
+const SWIM_UP_VELOCITY: f32 = 100.0;
+
+// how fast a swimming player with no input sinks
+pub(crate) const SINK_SPEED: f32 = 60.0;
+
+// swimming is slower than walking by this factor
+pub(crate) const SWIM_SPEED_SCALE: f32 = 0.7;
+
+// the highest speed that acceleration can add while in the air
+pub(crate) const MAX_AIR_WISH_SPEED: f32 = 30.0;
+
+// the bottom of the player's bounding box relative to its origin
+const PLAYER_MINS_Z: f32 = -24.0;
+
+// how far ahead of the player to look for a drop when applying edge friction
+pub(crate) const EDGE_LOOKAHEAD: f32 = 16.0;
+pub(crate) const EDGE_DROP: f32 = 34.0;
+
+/// Tuning values for player movement, normally taken from the server's cvars.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MoveVars {
+    pub gravity: f32,
+    pub stop_speed: f32,
+    pub max_speed: f32,
+    pub accelerate: f32,
+    pub friction: f32,
+
+    /// Multiplies friction when the player is near a drop, making it harder to run off ledges.
+    pub edge_friction: f32,
+}
+
+impl MoveVars {
+    pub fn from_cvars(cvars: &CvarRegistry) -> Result<MoveVars, ConsoleError> {
+        Ok(MoveVars {
+            gravity: cvars.get_value("sv_gravity")?,
+            stop_speed: cvars.get_value("sv_stopspeed")?,
+            max_speed: cvars.get_value("sv_maxspeed")?,
+            accelerate: cvars.get_value("sv_accelerate")?,
+            friction: cvars.get_value("sv_friction")?,
+            edge_friction: cvars.get_value("edgefriction")?,
+        })
+    }
+}
+
+impl Default for MoveVars {
+    fn default() -> MoveVars {
+        MoveVars {
+            gravity: 800.0,
+            stop_speed: 100.0,
+            max_speed: 320.0,
+            accelerate: 10.0,
+            friction: 4.0,
+            edge_friction: 2.0,
+        }
+    }
+}
+
+/// A single frame of player input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MoveCmd {
+    /// The view angles as pitch, yaw and roll.
+    pub angles: Vector3<Deg<f32>>,
+    pub forward_move: f32,
+    pub side_move: f32,
+    pub up_move: f32,
+    pub jump: bool,
+
+    /// How long the input was held, in seconds.
+    pub duration: f32,
+}
+
+/// The physical state of the player.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlayerState {
+    pub origin: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+    pub on_ground: bool,
+
+    /// Whether the jump button has been released since the last jump. Holding jump does not
+    /// repeatedly jump.
+    pub jump_released: bool,
+}
+
+impl PlayerState {
+    pub fn new(origin: Vector3<f32>, velocity: Vector3<f32>, on_ground: bool) -> PlayerState {
+        PlayerState {
+            origin,
+            velocity,
+            on_ground,
+            jump_released: true,
+        }
+    }
+}
+
+// the result of a player-sized trace through the world
+#[derive(Debug)]
+struct MoveTrace {
+    // how much of the move was completed
+    fraction: f32,
+    end: Vector3<f32>,
+    // the normal of the surface that was hit, if any
+    normal: Option<Vector3<f32>>,
+    start_solid: bool,
+}
+
+/// A trace made while moving the player, kept for debugging.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoggedTrace {
+    pub start: Vector3<f32>,
+    pub end: Vector3<f32>,
+
+    // where the trace stopped, equal to `end` if nothing was hit
+    pub stop: Vector3<f32>,
+}
+
+/// Runs player movement against a world model.
+pub struct PlayerMove {
+    // point-sized hull used to test for liquids
+    point_hull: BspCollisionHull,
+    // hull expanded by the player's bounding box, used for collision
+    player_hull: BspCollisionHull,
+    vars: MoveVars,
+
+    // the traces made since log_traces was called, if it was
+    trace_log: RefCell<Option<Vec<LoggedTrace>>>,
+}
+
+impl PlayerMove {
+    pub fn new(
+        point_hull: BspCollisionHull,
+        player_hull: BspCollisionHull,
+        vars: MoveVars,
+    ) -> PlayerMove {
+        PlayerMove {
+            point_hull,
+            player_hull,
+            vars,
+            trace_log: RefCell::new(None),
+        }
+    }
+
+    /// Constructs a `PlayerMove` using the point and player hulls of the given world model.
+    pub fn for_world(world: &BspModel, vars: MoveVars) -> Result<PlayerMove, BspError> {
+        Ok(PlayerMove::new(world.hull(0)?, world.hull(1)?, vars))
+    }
+
+    /// Starts keeping the traces made by each move, discarding any already kept.
+    pub fn log_traces(&self) {
+        self.trace_log.replace(Some(Vec::new()));
+    }
+
+    /// Stops keeping traces and returns those made since `log_traces` was called.
+    pub fn take_traces(&self) -> Vec<LoggedTrace> {
+        self.trace_log.replace(None).unwrap_or_default()
+    }
+
+    /// Returns the state of the player after carrying out `cmd`.
+    pub fn run(&self, state: &PlayerState, cmd: &MoveCmd) -> Result<PlayerState, BspError> {
+        let mut state = *state;
+
+        // a player stuck inside the world can't be moved sensibly, so leave it to the server
+        if self.player_hull.contents_at_point(state.origin)? == BspLeafContents::Solid {
+            return Ok(state);
+        }
+
+        let swimming = self.in_liquid(state.origin)?;
+
+        if swimming {
+            self.water_move(&mut state, cmd);
+        } else {
+            self.air_move(&mut state, cmd)?;
+        }
+
+        if cmd.jump {
+            if swimming {
+                state.velocity.z = SWIM_UP_VELOCITY;
+            } else if state.on_ground && state.jump_released {
+                state.velocity.z += JUMP_VELOCITY;
+                state.on_ground = false;
+                state.jump_released = false;
+            }
+        } else {
+            state.jump_released = true;
+        }
+
+        if !swimming {
+            state.velocity.z -= self.vars.gravity * cmd.duration;
+        }
+
+        self.walk_move(&mut state, cmd.duration, swimming)?;
+
+        Ok(state)
+    }
+
+    fn trace(
+        &self,
+        hull: &BspCollisionHull,
+        start: Vector3<f32>,
+        end: Vector3<f32>,
+    ) -> Result<MoveTrace, BspError> {
+        let result = trace(hull, start, end)?;
+        if let Some(ref mut log) = *self.trace_log.borrow_mut() {
+            log.push(LoggedTrace {
+                start,
+                end,
+                stop: result.end,
+            });
+        }
+
+        Ok(result)
+    }
+
+    fn in_liquid(&self, point: Vector3<f32>) -> Result<bool, BspError> {
+        Ok(match self.point_hull.contents_at_point(point)? {
+            BspLeafContents::Water | BspLeafContents::Slime | BspLeafContents::Lava => true,
+            _ => false,
+        })
+    }
+
+    // applies friction and acceleration for walking or falling
+    fn air_move(&self, state: &mut PlayerState, cmd: &MoveCmd) -> Result<(), BspError> {
+        let (forward, right) = flat_vectors(cmd.angles.y);
+        let wish_vel = forward * cmd.forward_move + right * cmd.side_move;
+        let (wish_dir, wish_speed) = wish_dir_speed(wish_vel, self.vars.max_speed);
+
+        let accel_speed = self.vars.accelerate * wish_speed * cmd.duration;
+        if state.on_ground {
+            self.friction(state, cmd.duration)?;
+            accelerate(&mut state.velocity, wish_dir, wish_speed, accel_speed);
+        } else {
+            // air control can't add much speed, but can still turn quickly
+            accelerate(
+                &mut state.velocity,
+                wish_dir,
+                wish_speed.min(MAX_AIR_WISH_SPEED),
+                accel_speed,
+            );
+        }
+
+        Ok(())
+    }
+
+    // applies friction and acceleration for swimming
+    fn water_move(&self, state: &mut PlayerState, cmd: &MoveCmd) {
+        let (forward, right, _) = frustum::view_vectors(Angles {
+            pitch: cmd.angles.x,
+            roll: cmd.angles.z,
+            yaw: cmd.angles.y,
+        });
+        let mut wish_vel = forward * cmd.forward_move + right * cmd.side_move;
+        if cmd.forward_move == 0.0 && cmd.side_move == 0.0 && cmd.up_move == 0.0 {
+            wish_vel.z -= SINK_SPEED;
+        } else {
+            wish_vel.z += cmd.up_move;
+        }
+
+        let (wish_dir, wish_speed) = wish_dir_speed(wish_vel, self.vars.max_speed);
+        let wish_speed = wish_speed * SWIM_SPEED_SCALE;
+
+        // water friction slows movement in every direction
+        let speed = state.velocity.magnitude();
+        let new_speed = if speed > 0.0 {
+            let new_speed = (speed - cmd.duration * speed * self.vars.friction).max(0.0);
+            state.velocity *= new_speed / speed;
+            new_speed
+        } else {
+            0.0
+        };
+
+        if wish_speed == 0.0 {
+            return;
+        }
+
+        let add_speed = wish_speed - new_speed;
+        if add_speed <= 0.0 {
+            return;
+        }
+
+        let accel_speed = (self.vars.accelerate * wish_speed * cmd.duration).min(add_speed);
+        state.velocity += wish_dir * accel_speed;
+    }
+
+    fn friction(&self, state: &mut PlayerState, duration: f32) -> Result<(), BspError> {
+        let speed = state.velocity.x.hypot(state.velocity.y);
+        if speed == 0.0 {
+            return Ok(());
+        }
+
+        // if there's a drop just ahead, apply extra friction
+        let mut start = state.origin + state.velocity / speed * EDGE_LOOKAHEAD;
+        start.z = state.origin.z + PLAYER_MINS_Z;
+        let stop = start - Vector3::unit_z() * EDGE_DROP;
+        let friction = match self.trace(&self.point_hull, start, stop)?.fraction {
+            f if f == 1.0 => self.vars.friction * self.vars.edge_friction,
+            _ => self.vars.friction,
+        };
+
+        let control = speed.max(self.vars.stop_speed);
+        let new_speed = (speed - duration * control * friction).max(0.0);
+        state.velocity *= new_speed / speed;
+
+        Ok(())
+    }
+
+    // moves the player along its velocity, stepping up onto ledges where possible
+    fn walk_move(
+        &self,
+        state: &mut PlayerState,
+        duration: f32,
+        swimming: bool,
+    ) -> Result<(), BspError> {
+        let was_on_ground = state.on_ground;
+        let start = *state;
+
+        state.on_ground = false;
+        let blocked = self.fly_move(state, duration)?;
+
+        // only try stepping up if a wall was hit, and never while jumping
+        if !blocked.wall || (!was_on_ground && !swimming) {
+            return Ok(());
+        }
+
+        let no_step = *state;
+
+        // retry the move from the top of a step
+        *state = start;
+        state.on_ground = false;
+        state.origin = self
+            .trace(
+                &self.player_hull,
+                state.origin,
+                state.origin + Vector3::unit_z() * STEP_SIZE,
+            )?
+            .end;
+        state.velocity.z = 0.0;
+        self.fly_move(state, duration)?;
+
+        // then move back down onto the step
+        let down = self.trace(
+            &self.player_hull,
+            state.origin,
+            state.origin - Vector3::unit_z() * (STEP_SIZE - start.velocity.z * duration),
+        )?;
+        state.origin = down.end;
+
+        // if the step didn't end up on solid ground, use the move without it
+        match down.normal {
+            Some(n) if n.z > MIN_GROUND_NORMAL_Z => state.on_ground = true,
+            _ => *state = no_step,
+        }
+
+        Ok(())
+    }
+
+    // moves the player along its velocity, sliding along any surfaces it hits
+    fn fly_move(&self, state: &mut PlayerState, duration: f32) -> Result<Blocked, BspError> {
+        let mut blocked = Blocked::default();
+        let primal_velocity = state.velocity;
+        let mut original_velocity = state.velocity;
+        let mut planes: Vec<Vector3<f32>> = Vec::with_capacity(MAX_CLIP_PLANES);
+        let mut time_left = duration;
+
+        for _ in 0..MAX_BUMPS {
+            if state.velocity == Vector3::zero() {
+                break;
+            }
+
+            let end = state.origin + state.velocity * time_left;
+            let trace = self.trace(&self.player_hull, state.origin, end)?;
+
+            if trace.start_solid {
+                state.velocity = Vector3::zero();
+                blocked.floor = true;
+                blocked.wall = true;
+                return Ok(blocked);
+            }
+
+            if trace.fraction > 0.0 {
+                state.origin = trace.end;
+                original_velocity = state.velocity;
+                planes.clear();
+            }
+
+            let normal = match trace.normal {
+                Some(n) => n,
+                None => break,
+            };
+
+            if normal.z > MIN_GROUND_NORMAL_Z {
+                blocked.floor = true;
+                state.on_ground = true;
+            }
+
+            if normal.z == 0.0 {
+                blocked.wall = true;
+            }
+
+            time_left -= time_left * trace.fraction;
+
+            if planes.len() >= MAX_CLIP_PLANES {
+                state.velocity = Vector3::zero();
+                return Ok(blocked);
+            }
+
+            planes.push(normal);
+
+            // find a velocity that runs parallel to every plane hit so far
+            let clipped = planes.iter().enumerate().find_map(|(i, plane)| {
+                let v = clip_velocity(original_velocity, *plane, 1.0);
+                let clear = planes
+                    .iter()
+                    .enumerate()
+                    .all(|(j, other)| i == j || v.dot(*other) >= 0.0);
+                if clear {
+                    Some(v)
+                } else {
+                    None
+                }
+            });
+
+            state.velocity = match clipped {
+                Some(v) => v,
+
+                // go along the crease between two planes
+                None if planes.len() == 2 => {
+                    let dir = planes[0].cross(planes[1]);
+                    dir * dir.dot(state.velocity)
+                }
+
+                None => {
+                    state.velocity = Vector3::zero();
+                    return Ok(blocked);
+                }
+            };
+
+            // stop dead rather than bounce back and forth in corners
+            if state.velocity.dot(primal_velocity) <= 0.0 {
+                state.velocity = Vector3::zero();
+                break;
+            }
+        }
+
+        Ok(blocked)
+    }
+}
+
+// the kinds of surfaces a move ran into
+#[derive(Default)]
+struct Blocked {
+    floor: bool,
+    wall: bool,
+}
+
+// traces from start to end, passing through liquid boundaries and stopping short of solid ones
+fn trace(
+    hull: &BspCollisionHull,
+    start: Vector3<f32>,
+    end: Vector3<f32>,
+) -> Result<MoveTrace, BspError> {
+    let length = (end - start).magnitude();
+    if length == 0.0 {
+        return Ok(MoveTrace {
+            fraction: 1.0,
+            end,
+            normal: None,
+            start_solid: hull.contents_at_point(start)? == BspLeafContents::Solid,
+        });
+    }
+
+    let dir = (end - start) / length;
+    let mut from = start;
+    for _ in 0..MAX_TRACE_SEGMENTS {
+        let trace = hull.trace(from, end)?;
+        if trace.start_solid() && from == start {
+            return Ok(MoveTrace {
+                fraction: 0.0,
+                end: start,
+                normal: None,
+                start_solid: true,
+            });
+        }
+
+        let normal = match trace.plane() {
+            Some(p) => p.normal_vector(),
+            None => break,
+        };
+
+        // boundaries between open space and liquids don't block movement
+        let hit = trace.end_point();
+        if !trace.all_solid()
+            && hull.contents_at_point(hit + dir * DIST_EPSILON)? != BspLeafContents::Solid
+        {
+            from = hit + dir * DIST_EPSILON;
+            continue;
+        }
+
+        // back off so that the end point lies just in front of the plane
+        let approach = -dir.dot(normal);
+        let backoff = if approach > 0.0 {
+            DIST_EPSILON / approach
+        } else {
+            0.0
+        };
+        let dist = ((hit - start).magnitude() - backoff).max(0.0);
+
+        return Ok(MoveTrace {
+            fraction: dist / length,
+            end: start + dir * dist,
+            normal: Some(normal),
+            start_solid: false,
+        });
+    }
+
+    Ok(MoveTrace {
+        fraction: 1.0,
+        end,
+        normal: None,
+        start_solid: false,
+    })
+}
+
+// removes the component of velocity running into a plane
+fn clip_velocity(velocity: Vector3<f32>, normal: Vector3<f32>, overbounce: f32) -> Vector3<f32> {
+    let backoff = velocity.dot(normal) * overbounce;
+    let mut out = velocity - normal * backoff;
+    for i in 0..3 {
+        if out[i].abs() < STOP_EPSILON {
+            out[i] = 0.0;
+        }
+    }
+
+    out
+}
+
+// adds up to accel_speed to the velocity along wish_dir without exceeding wish_speed in that direction
+pub(crate) fn accelerate(
+    velocity: &mut Vector3<f32>,
+    wish_dir: Vector3<f32>,
+    wish_speed: f32,
+    accel_speed: f32,
+) {
+    let add_speed = wish_speed - velocity.dot(wish_dir);
+    if add_speed <= 0.0 {
+        return;
+    }
+
+    *velocity += wish_dir * accel_speed.min(add_speed);
+}
+
+// returns the direction of a desired velocity and its speed, limited to max_speed
+pub(crate) fn wish_dir_speed(wish_vel: Vector3<f32>, max_speed: f32) -> (Vector3<f32>, f32) {
+    let speed = wish_vel.magnitude();
+    if speed == 0.0 {
+        (Vector3::zero(), 0.0)
+    } else {
+        (wish_vel / speed, speed.min(max_speed))
+    }
+}
+
+// returns the horizontal forward and right vectors for a yaw angle
+fn flat_vectors(yaw: Deg<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let (sin, cos) = yaw.sin_cos();
+    (Vector3::new(cos, sin, 0.0), Vector3::new(sin, -cos, 0.0))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// Returns a world that is solid below z = 0 and open above.
+    pub(crate) fn floor_move() -> PlayerMove {
+        let mins = Vector3::new(-4096.0, -4096.0, -4096.0);
+        let maxs = Vector3::new(4096.0, 4096.0, 0.0);
+        PlayerMove::new(
+            BspCollisionHull::for_bounds(mins, maxs).unwrap(),
+            BspCollisionHull::for_bounds(mins, maxs).unwrap(),
+            MoveVars::default(),
+        )
+    }
+
+    fn cmd(forward_move: f32, jump: bool) -> MoveCmd {
+        MoveCmd {
+            angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+            forward_move,
+            side_move: 0.0,
+            up_move: 0.0,
+            jump,
+            duration: 0.02,
+        }
+    }
+
+    fn run_frames(pmove: &PlayerMove, state: PlayerState, cmd: &MoveCmd, n: usize) -> PlayerState {
+        (0..n).fold(state, |s, _| pmove.run(&s, cmd).unwrap())
+    }
+
+    #[test]
+    fn test_fall_to_floor() {
+        let pmove = floor_move();
+        let state = PlayerState::new(Vector3::new(0.0, 0.0, 64.0), Vector3::zero(), false);
+        let state = run_frames(&pmove, state, &cmd(0.0, false), 100);
+
+        assert!(state.on_ground);
+        assert!(state.origin.z >= 0.0 && state.origin.z < 1.0);
+        assert_eq!(state.velocity.z, 0.0);
+    }
+
+    #[test]
+    fn test_accelerate_to_max_speed() {
+        let pmove = floor_move();
+        let state = PlayerState::new(Vector3::new(0.0, 0.0, 1.0), Vector3::zero(), false);
+        let state = run_frames(&pmove, state, &cmd(400.0, false), 100);
+
+        // forward is +x at a yaw of 0, and speed is limited to sv_maxspeed
+        assert!((state.velocity.x - 320.0).abs() < 1.0);
+        assert!(state.origin.x > 0.0);
+        assert_eq!(state.origin.y, 0.0);
+    }
+
+    #[test]
+    fn test_friction_stops_player() {
+        let pmove = floor_move();
+        let state = PlayerState::new(
+            Vector3::new(0.0, 0.0, 1.0),
+            Vector3::new(320.0, 0.0, 0.0),
+            true,
+        );
+        let state = run_frames(&pmove, state, &cmd(0.0, false), 100);
+
+        assert_eq!(state.velocity, Vector3::zero());
+    }
+
+    #[test]
+    fn test_log_traces() {
+        let pmove = floor_move();
+        let state = PlayerState::new(Vector3::new(0.0, 0.0, 64.0), Vector3::zero(), false);
+        pmove.run(&state, &cmd(0.0, false)).unwrap();
+        assert!(pmove.take_traces().is_empty());
+
+        pmove.log_traces();
+        let state = pmove.run(&state, &cmd(0.0, false)).unwrap();
+        let traces = pmove.take_traces();
+        assert_eq!(traces.len(), 1);
+        assert_eq!(traces[0].start, Vector3::new(0.0, 0.0, 64.0));
+        assert_eq!(traces[0].stop, state.origin);
+
+        // logging stops once the traces are taken
+        pmove.run(&state, &cmd(0.0, false)).unwrap();
+        assert!(pmove.take_traces().is_empty());
+    }
+
+    #[test]
+    fn test_jump_requires_release() {
+        let pmove = floor_move();
+        let state = PlayerState::new(Vector3::new(0.0, 0.0, 1.0), Vector3::zero(), false);
+        let state = run_frames(&pmove, state, &cmd(0.0, false), 10);
+        assert!(state.on_ground);
+
+        let jumped = pmove.run(&state, &cmd(0.0, true)).unwrap();
+        assert!(!jumped.on_ground);
+        assert!(jumped.velocity.z > 0.0);
+
+        // holding jump after landing doesn't jump again
+        let landed = run_frames(&pmove, jumped, &cmd(0.0, true), 100);
+        assert!(landed.on_ground);
+        assert_eq!(landed.velocity.z, 0.0);
+    }
+}
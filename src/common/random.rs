@@ -0,0 +1,172 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Reproducible random numbers.
+//!
+//! Randomness in the game, from QuakeC's `random()` to the spread of particles, comes from a
+//! [`GameRng`] rather than the thread's generator. Its whole state is a single number, which saved
+//! games and demos record so that loading a save or playing back a demo draws the same numbers
+//! as the original game. Setting `sv_seed` starts every game from the same state, which makes test
+//! runs repeatable.
+//!
+//! Security-sensitive values, such as connection challenges, must not come from a `GameRng`.
+
+use crate::common::console::CvarRegistry;
+
+use rand::RngCore;
+
+// constants of the PCG32 generator
+const MULTIPLIER: u64 = 6364136223846793005;
+const INCREMENT: u64 = 1442695040888963407;
+
+/// A small, fast generator whose state can be saved and restored.
+///
+/// This is a PCG32 generator, which is statistically sound but not cryptographically secure.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameRng {
+    state: u64,
+}
+
+impl GameRng {
+    /// Creates a generator from a seed. The same seed always gives the same numbers.
+    pub fn new(seed: u64) -> GameRng {
+        let mut rng = GameRng { state: 0 };
+        rng.step();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.step();
+        rng
+    }
+
+    /// Creates a generator with a seed chosen at random.
+    pub fn from_entropy() -> GameRng {
+        GameRng::new(rand::random())
+    }
+
+    /// Creates a generator from a state returned by [`GameRng::state`].
+    pub fn from_state(state: u64) -> GameRng {
+        GameRng { state }
+    }
+
+    /// Returns the state of the generator, from which it can be restored.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn step(&mut self) {
+        self.state = self.state.wrapping_mul(MULTIPLIER).wrapping_add(INCREMENT);
+    }
+
+    /// Returns a number between 0 and 1 inclusive, with the same precision as the original
+    /// engine's `random()`.
+    pub fn random(&mut self) -> f32 {
+        (self.next_u32() >> 17) as f32 / 0x7fff as f32
+    }
+}
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.step();
+
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let low = self.next_u32() as u64;
+        let high = self.next_u32() as u64;
+        high << 32 | low
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Returns the seed for a new game: the value of `sv_seed` if it is set, or otherwise a seed
+/// chosen at random.
+pub fn game_seed(cvars: &CvarRegistry) -> u64 {
+    match cvars
+        .get("sv_seed")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+    {
+        Some(seed) if seed != 0 => seed,
+        _ => rand::random(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_numbers() {
+        let mut a = GameRng::new(42);
+        let mut b = GameRng::new(42);
+        let mut c = GameRng::new(43);
+
+        let a_nums: Vec<u32> = (0..16).map(|_| a.next_u32()).collect();
+        let b_nums: Vec<u32> = (0..16).map(|_| b.next_u32()).collect();
+        let c_nums: Vec<u32> = (0..16).map(|_| c.next_u32()).collect();
+        assert_eq!(a_nums, b_nums);
+        assert_ne!(a_nums, c_nums);
+    }
+
+    #[test]
+    fn test_restore_state() {
+        let mut rng = GameRng::new(7);
+        for _ in 0..100 {
+            rng.random();
+        }
+
+        let mut restored = GameRng::from_state(rng.state());
+        for _ in 0..100 {
+            assert_eq!(rng.random(), restored.random());
+        }
+    }
+
+    #[test]
+    fn test_random_range() {
+        let mut rng = GameRng::new(0);
+        let mut sum = 0.0;
+        for _ in 0..10000 {
+            let r = rng.random();
+            assert!(r >= 0.0 && r <= 1.0);
+            sum += r;
+        }
+
+        // roughly uniform
+        assert!((sum / 10000.0 - 0.5).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_game_seed() {
+        let cvars = CvarRegistry::new();
+        cvars.register("sv_seed", "1234", "").unwrap();
+        assert_eq!(game_seed(&cvars), 1234);
+    }
+}
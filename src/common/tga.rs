@@ -0,0 +1,279 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//! Truevision TGA image decoding and encoding.
+//!
+//! Only the image types used by texture packs are supported for decoding: uncompressed and
+//! run-length encoded truecolor (24- or 32-bit) and grayscale (8-bit) images. Encoding always
+//! produces uncompressed 32-bit images.
+
+use thiserror::Error;
+
+const HEADER_SIZE: usize = 18;
+
+// image descriptor bit set when the first row is the top of the image
+const TOP_LEFT_ORIGIN: u8 = 0x20;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TgaError {
+    #[error("TGA data is truncated")]
+    Truncated,
+    #[error("Unsupported TGA image type {0}")]
+    UnsupportedType(u8),
+    #[error("Unsupported TGA pixel depth {depth} for image type {image_type}")]
+    UnsupportedDepth { image_type: u8, depth: u8 },
+}
+
+/// A decoded TGA image.
+#[derive(Debug)]
+pub struct TgaImage {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+impl TgaImage {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The pixels of the image as RGBA, row by row from the top left.
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    pub fn into_rgba(self) -> Vec<u8> {
+        self.rgba
+    }
+}
+
+// convert one pixel of the given depth to RGBA. truecolor pixels are stored as BGR(A).
+fn pixel_to_rgba(pixel: &[u8]) -> [u8; 4] {
+    match pixel.len() {
+        1 => [pixel[0], pixel[0], pixel[0], 0xFF],
+        3 => [pixel[2], pixel[1], pixel[0], 0xFF],
+        _ => [pixel[2], pixel[1], pixel[0], pixel[3]],
+    }
+}
+
+/// Decodes a TGA image to RGBA.
+pub fn decode(data: &[u8]) -> Result<TgaImage, TgaError> {
+    if data.len() < HEADER_SIZE {
+        return Err(TgaError::Truncated);
+    }
+
+    let id_len = data[0] as usize;
+    let colormap_type = data[1];
+    let image_type = data[2];
+    let colormap_len = u16::from_le_bytes([data[5], data[6]]) as usize;
+    let colormap_entry_bits = data[7] as usize;
+    let width = u16::from_le_bytes([data[12], data[13]]) as u32;
+    let height = u16::from_le_bytes([data[14], data[15]]) as u32;
+    let depth = data[16];
+    let descriptor = data[17];
+
+    let (rle, grayscale) = match image_type {
+        2 => (false, false),
+        3 => (false, true),
+        10 => (true, false),
+        11 => (true, true),
+        t => return Err(TgaError::UnsupportedType(t)),
+    };
+
+    let bytes_per_pixel = match (grayscale, depth) {
+        (true, 8) => 1,
+        (false, 24) => 3,
+        (false, 32) => 4,
+        _ => return Err(TgaError::UnsupportedDepth { image_type, depth }),
+    };
+
+    // skip the image ID and any (unused) color map
+    let mut pos = HEADER_SIZE + id_len;
+    if colormap_type != 0 {
+        pos += colormap_len * ((colormap_entry_bits + 7) / 8);
+    }
+
+    let pixel_count = (width * height) as usize;
+    let mut rgba = Vec::with_capacity(pixel_count * 4);
+
+    let read_pixel = |pos: &mut usize| -> Result<[u8; 4], TgaError> {
+        let pixel = data
+            .get(*pos..*pos + bytes_per_pixel)
+            .ok_or(TgaError::Truncated)?;
+        *pos += bytes_per_pixel;
+        Ok(pixel_to_rgba(pixel))
+    };
+
+    while rgba.len() < pixel_count * 4 {
+        if rle {
+            // each packet is a header byte followed by either one pixel repeated or a run of
+            // literal pixels
+            let header = *data.get(pos).ok_or(TgaError::Truncated)?;
+            pos += 1;
+            let count = (header & 0x7F) as usize + 1;
+
+            if header & 0x80 != 0 {
+                let pixel = read_pixel(&mut pos)?;
+                for _ in 0..count {
+                    rgba.extend_from_slice(&pixel);
+                }
+            } else {
+                for _ in 0..count {
+                    let pixel = read_pixel(&mut pos)?;
+                    rgba.extend_from_slice(&pixel);
+                }
+            }
+        } else {
+            let pixel = read_pixel(&mut pos)?;
+            rgba.extend_from_slice(&pixel);
+        }
+    }
+
+    // a run may extend past the last pixel of a malformed image
+    rgba.truncate(pixel_count * 4);
+
+    // rows are stored bottom to top unless the descriptor says otherwise. an empty image has no
+    // rows to flip.
+    if descriptor & TOP_LEFT_ORIGIN == 0 && pixel_count > 0 {
+        let row_len = width as usize * 4;
+        let rows: Vec<&[u8]> = rgba.chunks(row_len).rev().collect();
+        rgba = rows.concat();
+    }
+
+    Ok(TgaImage {
+        width,
+        height,
+        rgba,
+    })
+}
+
+/// Encodes RGBA pixel data, row by row from the top left, as an uncompressed 32-bit TGA image.
+///
+/// Panics if `rgba` does not hold exactly `width * height` pixels.
+pub fn encode(width: u16, height: u16, rgba: &[u8]) -> Vec<u8> {
+    assert_eq!(rgba.len(), width as usize * height as usize * 4);
+
+    let mut data = vec![0; HEADER_SIZE];
+    data[2] = 2;
+    data[12..14].copy_from_slice(&width.to_le_bytes());
+    data[14..16].copy_from_slice(&height.to_le_bytes());
+    data[16] = 32;
+    // 8 alpha bits, rows stored top to bottom
+    data[17] = TOP_LEFT_ORIGIN | 8;
+
+    data.reserve(rgba.len());
+    for pixel in rgba.chunks(4) {
+        data.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+    }
+
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(image_type: u8, width: u16, height: u16, depth: u8, descriptor: u8) -> Vec<u8> {
+        let mut h = vec![0; HEADER_SIZE];
+        h[2] = image_type;
+        h[12..14].copy_from_slice(&width.to_le_bytes());
+        h[14..16].copy_from_slice(&height.to_le_bytes());
+        h[16] = depth;
+        h[17] = descriptor;
+        h
+    }
+
+    #[test]
+    fn test_decode_uncompressed_bottom_up() {
+        let mut data = header(2, 1, 2, 24, 0);
+        // bottom row (blue), then top row (red)
+        data.extend_from_slice(&[0xFF, 0, 0, 0, 0, 0xFF]);
+
+        let image = decode(&data).unwrap();
+        assert_eq!((image.width(), image.height()), (1, 2));
+        assert_eq!(image.rgba(), &[0xFF, 0, 0, 0xFF, 0, 0, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_decode_rle_top_down() {
+        let mut data = header(10, 3, 1, 32, TOP_LEFT_ORIGIN);
+        // a run of two green pixels, then one literal half-transparent white pixel
+        data.extend_from_slice(&[0x81, 0, 0xFF, 0, 0xFF]);
+        data.extend_from_slice(&[0x00, 0xFF, 0xFF, 0xFF, 0x80]);
+
+        let image = decode(&data).unwrap();
+        assert_eq!(
+            image.rgba(),
+            &[0, 0xFF, 0, 0xFF, 0, 0xFF, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0x80]
+        );
+    }
+
+    #[test]
+    fn test_decode_grayscale() {
+        let mut data = header(3, 2, 1, 8, TOP_LEFT_ORIGIN);
+        data.extend_from_slice(&[0x10, 0x20]);
+
+        let image = decode(&data).unwrap();
+        assert_eq!(
+            image.rgba(),
+            &[0x10, 0x10, 0x10, 0xFF, 0x20, 0x20, 0x20, 0xFF]
+        );
+    }
+
+    #[test]
+    fn test_decode_empty_bottom_up() {
+        let image = decode(&header(2, 0, 4, 24, 0)).unwrap();
+        assert_eq!((image.width(), image.height()), (0, 4));
+        assert!(image.rgba().is_empty());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(decode(&[0; 4]).unwrap_err(), TgaError::Truncated);
+        assert_eq!(
+            decode(&header(1, 1, 1, 8, 0)).unwrap_err(),
+            TgaError::UnsupportedType(1)
+        );
+        assert_eq!(
+            decode(&header(2, 1, 1, 16, 0)).unwrap_err(),
+            TgaError::UnsupportedDepth {
+                image_type: 2,
+                depth: 16
+            }
+        );
+        assert_eq!(
+            decode(&header(2, 1, 1, 24, 0)).unwrap_err(),
+            TgaError::Truncated
+        );
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let rgba = [
+            0xFF, 0, 0, 0xFF, 0, 0xFF, 0, 0x80, 0, 0, 0xFF, 0, 0x10, 0x20, 0x30, 0x40,
+        ];
+        let data = encode(2, 2, &rgba);
+        assert_eq!(data.len(), HEADER_SIZE + rgba.len());
+
+        let image = decode(&data).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 2));
+        assert_eq!(image.rgba(), &rgba[..]);
+    }
+}
@@ -16,12 +16,20 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 use std::{
-    fs::File,
+    collections::BTreeSet,
+    fmt,
+    fs::{self, File},
     io::{self, Cursor, Read, Seek, SeekFrom},
     path::{Path, PathBuf},
 };
 
-use crate::common::pak::{Pak, PakError};
+use crate::common::{
+    download::{DownloadStore, DOWNLOAD_DIR},
+    limits::LoadLimits,
+    pak::{Pak, PakError},
+    pk3::{Pk3, Pk3Error},
+    MAX_PAKFILES,
+};
 
 use thiserror::Error;
 
@@ -29,32 +37,181 @@ use thiserror::Error;
 pub enum VfsError {
     #[error("Couldn't load pakfile: {0}")]
     Pak(#[from] PakError),
+    #[error("Couldn't load PK3 archive: {0}")]
+    Pk3(#[from] Pk3Error),
     #[error("File does not exist: {0}")]
     NoSuchFile(String),
 }
 
 enum VfsComponent {
-    Pak(Pak),
+    Pak(PathBuf, Pak),
+    Pk3(PathBuf, Pk3),
     Directory(PathBuf),
 }
 
+impl VfsComponent {
+    fn contains(&self, virtual_path: &str) -> bool {
+        match self {
+            VfsComponent::Pak(_, pak) => pak.open(virtual_path).is_ok(),
+            VfsComponent::Pk3(_, pk3) => pk3.contains(virtual_path),
+            VfsComponent::Directory(path) => path.join(virtual_path).is_file(),
+        }
+    }
+
+    fn source(&self) -> VfsSource {
+        match self {
+            VfsComponent::Pak(path, _) => VfsSource::Pak(path.clone()),
+            VfsComponent::Pk3(path, _) => VfsSource::Pk3(path.clone()),
+            VfsComponent::Directory(path) => VfsSource::Directory(path.clone()),
+        }
+    }
+}
+
+/// A location in the real filesystem that a virtual file can be found in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VfsSource {
+    Pak(PathBuf),
+    Pk3(PathBuf),
+    Directory(PathBuf),
+}
+
+impl fmt::Display for VfsSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VfsSource::Pak(path) => write!(f, "PAK file {}", path.display()),
+            VfsSource::Pk3(path) => write!(f, "PK3 archive {}", path.display()),
+            VfsSource::Directory(path) => write!(f, "directory {}", path.display()),
+        }
+    }
+}
+
 pub struct Vfs {
     components: Vec<VfsComponent>,
+
+    // the last game directory added, which files written by the game go in
+    write_dir: Option<PathBuf>,
+
+    // the limits PAK archives are checked against as they are added
+    limits: LoadLimits,
 }
 
 impl Vfs {
     pub fn new() -> Vfs {
+        Vfs::with_limits(LoadLimits::default())
+    }
+
+    /// Creates an empty virtual filesystem whose PAK archives are checked against `limits`.
+    pub fn with_limits(limits: LoadLimits) -> Vfs {
         Vfs {
             components: Vec::new(),
+            write_dir: None,
+            limits,
         }
     }
 
+    /// Returns the directory of the active game, the last one added with `add_game_dir`.
+    ///
+    /// Files written by the game, such as those written by progs, belong here.
+    pub fn write_dir(&self) -> Option<&Path> {
+        self.write_dir.as_deref()
+    }
+
+    /// Returns the store for files downloaded from servers, in the active game directory.
+    ///
+    /// Returns `None` if no game directory has been added.
+    pub fn download_store(&self) -> Option<DownloadStore> {
+        self.write_dir().map(DownloadStore::new)
+    }
+
+    /// Creates a virtual filesystem containing a game directory and its archives.
+    ///
+    /// See `add_game_dir` for the order in which they are searched.
+    pub fn with_base_dir<P>(base_dir: P) -> Result<Vfs, VfsError>
+    where
+        P: AsRef<Path>,
+    {
+        let mut vfs = Vfs::new();
+        vfs.add_game_dir(base_dir)?;
+        Ok(vfs)
+    }
+
+    /// Adds a game directory and its archives, which take precedence over those already added.
+    ///
+    /// The directory itself is searched first, followed by `pak0.pak`, `pak1.pak` and so on until
+    /// a PAK file is missing or `MAX_PAKFILES` is reached, followed by any PK3 archives in
+    /// alphabetical order. Later components take precedence over earlier ones. Files downloaded
+    /// from servers are only used when none of these has a copy.
+    ///
+    /// A mod is loaded by adding its directory after `id1`, so that its files replace the
+    /// originals.
+    pub fn add_game_dir<P>(&mut self, game_dir: P) -> Result<(), VfsError>
+    where
+        P: AsRef<Path>,
+    {
+        let game_dir = game_dir.as_ref();
+
+        // downloads never shadow installed files
+        self.add_fallback_directory(game_dir.join(DOWNLOAD_DIR))?;
+
+        // then add the directory itself
+        self.add_directory(game_dir)?;
+
+        // then add PAK archives
+        for vfs_id in 0..MAX_PAKFILES {
+            let path = game_dir.join(format!("pak{}.pak", vfs_id));
+
+            // keep adding PAKs until we don't find one or we hit MAX_PAKFILES
+            if !path.exists() {
+                break;
+            }
+
+            self.add_pakfile(path)?;
+        }
+
+        // then add PK3 archives in alphabetical order
+        let mut pk3_paths: Vec<_> = fs::read_dir(game_dir)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.path())
+                    .filter(|path| {
+                        path.extension()
+                            .and_then(|ext| ext.to_str())
+                            .map(|ext| ext.eq_ignore_ascii_case("pk3"))
+                            .unwrap_or(false)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        pk3_paths.sort();
+
+        for path in pk3_paths {
+            self.add_pk3file(path)?;
+        }
+
+        self.write_dir = Some(game_dir.to_path_buf());
+        Ok(())
+    }
+
     pub fn add_pakfile<P>(&mut self, path: P) -> Result<(), VfsError>
     where
         P: AsRef<Path>,
     {
         let path = path.as_ref();
-        self.components.push(VfsComponent::Pak(Pak::new(path)?));
+        self.components.push(VfsComponent::Pak(
+            path.to_path_buf(),
+            Pak::new(path, &self.limits)?,
+        ));
+        Ok(())
+    }
+
+    pub fn add_pk3file<P>(&mut self, path: P) -> Result<(), VfsError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        self.components
+            .push(VfsComponent::Pk3(path.to_path_buf(), Pk3::new(path)?));
         Ok(())
     }
 
@@ -67,6 +224,16 @@ impl Vfs {
         Ok(())
     }
 
+    /// Adds a directory that is searched after every other component.
+    pub fn add_fallback_directory<P>(&mut self, path: P) -> Result<(), VfsError>
+    where
+        P: AsRef<Path>,
+    {
+        self.components
+            .insert(0, VfsComponent::Directory(path.as_ref().to_path_buf()));
+        Ok(())
+    }
+
     pub fn open<S>(&self, virtual_path: S) -> Result<VirtualFile, VfsError>
     where
         S: AsRef<str>,
@@ -76,12 +243,18 @@ impl Vfs {
         // iterate in reverse so later PAKs overwrite earlier ones
         for c in self.components.iter().rev() {
             match c {
-                VfsComponent::Pak(pak) => {
+                VfsComponent::Pak(_, pak) => {
                     if let Ok(f) = pak.open(vp) {
                         return Ok(VirtualFile::PakBacked(Cursor::new(f)));
                     }
                 }
 
+                VfsComponent::Pk3(_, pk3) => {
+                    if let Ok(f) = pk3.open(vp) {
+                        return Ok(VirtualFile::Pk3Backed(Cursor::new(f)));
+                    }
+                }
+
                 VfsComponent::Directory(path) => {
                     let mut full_path = path.to_owned();
                     full_path.push(vp);
@@ -95,10 +268,95 @@ impl Vfs {
 
         Err(VfsError::NoSuchFile(vp.to_owned()))
     }
+
+    /// Returns every location that contains a copy of the given virtual file.
+    ///
+    /// The first location is the one `open()` reads from; the rest hold copies it shadows, in
+    /// decreasing order of precedence.
+    pub fn sources<S>(&self, virtual_path: S) -> Vec<VfsSource>
+    where
+        S: AsRef<str>,
+    {
+        let vp = virtual_path.as_ref();
+        self.components
+            .iter()
+            .rev()
+            .filter(|c| c.contains(vp))
+            .map(|c| c.source())
+            .collect()
+    }
+
+    /// Lists the files located directly in the given virtual directory.
+    ///
+    /// The returned paths are relative to the root of the virtual filesystem, sorted and free of
+    /// duplicates. Subdirectories are not included. An empty `virtual_dir` lists the root.
+    pub fn list<S>(&self, virtual_dir: S) -> Vec<String>
+    where
+        S: AsRef<str>,
+    {
+        let dir = virtual_dir.as_ref().trim_end_matches('/');
+
+        // PK3 file names are stored in lowercase
+        let pk3_prefix = if dir.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", dir.to_lowercase())
+        };
+        let prefix = if dir.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", dir)
+        };
+
+        let mut files = BTreeSet::new();
+        for c in self.components.iter() {
+            match c {
+                VfsComponent::Pak(_, pak) => {
+                    for (name, _) in pak.iter() {
+                        if let Some(file_name) = name.strip_prefix(prefix.as_str()) {
+                            if !file_name.is_empty() && !file_name.contains('/') {
+                                files.insert(name.to_owned());
+                            }
+                        }
+                    }
+                }
+
+                VfsComponent::Pk3(_, pk3) => {
+                    for name in pk3.names() {
+                        if let Some(file_name) = name.strip_prefix(pk3_prefix.as_str()) {
+                            if !file_name.is_empty() && !file_name.contains('/') {
+                                files.insert(format!("{}{}", prefix, file_name));
+                            }
+                        }
+                    }
+                }
+
+                VfsComponent::Directory(path) => {
+                    let entries = match fs::read_dir(path.join(dir)) {
+                        Ok(e) => e,
+                        Err(_) => continue,
+                    };
+
+                    for entry in entries.filter_map(Result::ok) {
+                        if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+                            continue;
+                        }
+
+                        if let Some(file_name) = entry.file_name().to_str() {
+                            files.insert(format!("{}{}", prefix, file_name));
+                        }
+                    }
+                }
+            }
+        }
+
+        files.into_iter().collect()
+    }
 }
 
 pub enum VirtualFile<'a> {
     PakBacked(Cursor<&'a [u8]>),
+    Pk3Backed(Cursor<Vec<u8>>),
     FileBacked(File),
 }
 
@@ -106,6 +364,7 @@ impl<'a> Read for VirtualFile<'a> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self {
             VirtualFile::PakBacked(curs) => curs.read(buf),
+            VirtualFile::Pk3Backed(curs) => curs.read(buf),
             VirtualFile::FileBacked(file) => file.read(buf),
         }
     }
@@ -115,7 +374,113 @@ impl<'a> Seek for VirtualFile<'a> {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
         match self {
             VirtualFile::PakBacked(curs) => curs.seek(pos),
+            VirtualFile::Pk3Backed(curs) => curs.seek(pos),
             VirtualFile::FileBacked(file) => file.seek(pos),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write as _;
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    fn pk3(files: &[(&str, &[u8])]) -> Pk3 {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+
+        let mut cursor = writer.finish().unwrap();
+        cursor.set_position(0);
+        Pk3::from_reader(cursor).unwrap()
+    }
+
+    #[test]
+    fn test_sources_in_precedence_order() {
+        let mut vfs = Vfs::new();
+        vfs.add_directory("nonexistent").unwrap();
+        vfs.components.push(VfsComponent::Pk3(
+            PathBuf::from("id1/a.pk3"),
+            pk3(&[("progs/player.mdl", b"a"), ("gfx.wad", b"a")]),
+        ));
+        vfs.components.push(VfsComponent::Pk3(
+            PathBuf::from("id1/b.pk3"),
+            pk3(&[("progs/player.mdl", b"b")]),
+        ));
+
+        assert_eq!(
+            vfs.sources("progs/player.mdl"),
+            vec![
+                VfsSource::Pk3(PathBuf::from("id1/b.pk3")),
+                VfsSource::Pk3(PathBuf::from("id1/a.pk3")),
+            ]
+        );
+        assert_eq!(
+            vfs.sources("gfx.wad"),
+            vec![VfsSource::Pk3(PathBuf::from("id1/a.pk3"))]
+        );
+        assert!(vfs.sources("maps/e1m1.bsp").is_empty());
+
+        // the first source is the one that is opened
+        let mut data = Vec::new();
+        vfs.open("progs/player.mdl")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"b");
+    }
+
+    #[test]
+    fn test_fallback_directory_searched_last() {
+        let mut vfs = Vfs::new();
+        vfs.components.push(VfsComponent::Pk3(
+            PathBuf::from("id1/a.pk3"),
+            pk3(&[("progs/player.mdl", b"a")]),
+        ));
+        vfs.add_fallback_directory("id1/downloads").unwrap();
+
+        match vfs.components.first() {
+            Some(VfsComponent::Directory(path)) => assert_eq!(path, Path::new("id1/downloads")),
+            _ => panic!("fallback directory is not searched last"),
+        }
+        assert_eq!(
+            vfs.sources("progs/player.mdl")[0],
+            VfsSource::Pk3(PathBuf::from("id1/a.pk3"))
+        );
+    }
+
+    #[test]
+    fn test_mod_overrides_base_game() {
+        let base_dir = std::env::temp_dir().join(format!("richter-vfs-{}", std::process::id()));
+        for (dir, data) in &[("id1", "base"), ("mymod", "mod")] {
+            fs::create_dir_all(base_dir.join(dir)).unwrap();
+            fs::write(base_dir.join(dir).join("autoexec.cfg"), data).unwrap();
+        }
+        fs::write(base_dir.join("id1/quake.rc"), "base").unwrap();
+
+        let mut vfs = Vfs::with_base_dir(base_dir.join("id1")).unwrap();
+        vfs.add_game_dir(base_dir.join("mymod")).unwrap();
+
+        let read = |path| {
+            let mut data = String::new();
+            vfs.open(path).unwrap().read_to_string(&mut data).unwrap();
+            data
+        };
+        assert_eq!(read("autoexec.cfg"), "mod");
+        assert_eq!(read("quake.rc"), "base");
+        assert_eq!(vfs.write_dir(), Some(base_dir.join("mymod").as_path()));
+
+        // downloads are written to the mod, not the base game
+        assert_eq!(
+            vfs.download_store().unwrap().root(),
+            base_dir.join("mymod/downloads")
+        );
+
+        fs::remove_dir_all(&base_dir).unwrap();
+    }
+}
@@ -0,0 +1,397 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Potentially visible set (PVS) queries.
+//!
+//! The PVS of a leaf in the world BSP tree is the set of leaves that can possibly be seen from
+//! anywhere inside it, as computed by the map compiler. The server uses it to decide which
+//! entities are sent to each client, and the client uses it to skip rendering entities that
+//! can't be seen from the camera.
+//!
+//! The potentially hearable set (PHS) of a leaf is the union of the PVS of every leaf in its PVS,
+//! approximating the leaves that sound can travel to around one corner.
+
+use crate::common::bsp::{BspData, BspRenderNodeChild};
+
+use cgmath::Vector3;
+
+// number of leaves in each word of a leaf bit set
+const LEAVES_PER_WORD: usize = 64;
+
+fn words_for(leaf_count: usize) -> usize {
+    (leaf_count + LEAVES_PER_WORD - 1) / LEAVES_PER_WORD
+}
+
+fn set_bit(bits: &mut [u64], leaf_id: usize) {
+    if let Some(word) = bits.get_mut(leaf_id / LEAVES_PER_WORD) {
+        *word |= 1 << (leaf_id % LEAVES_PER_WORD);
+    }
+}
+
+fn get_bit(bits: &[u64], leaf_id: usize) -> bool {
+    match bits.get(leaf_id / LEAVES_PER_WORD) {
+        Some(word) => word & 1 << (leaf_id % LEAVES_PER_WORD) != 0,
+        None => false,
+    }
+}
+
+/// The set of world leaves that are potentially visible from a point.
+#[derive(Clone, Debug)]
+pub struct Pvs {
+    // one bit per leaf, indexed by leaf ID. if None, every leaf is visible.
+    leaves: Option<Vec<u64>>,
+}
+
+impl Pvs {
+    /// Returns a PVS in which every leaf is visible.
+    pub fn all() -> Pvs {
+        Pvs { leaves: None }
+    }
+
+    /// Returns a set containing the leaves whose entries in `leaves` are `true`.
+    pub fn from_leaves(leaves: Vec<bool>) -> Pvs {
+        let mut bits = vec![0; words_for(leaves.len())];
+        for (leaf_id, _) in leaves.iter().enumerate().filter(|(_, v)| **v) {
+            set_bit(&mut bits, leaf_id);
+        }
+
+        Pvs { leaves: Some(bits) }
+    }
+
+    /// Computes the PVS of the leaf containing `point`.
+    ///
+    /// Points outside the map and maps without visibility data can see every leaf.
+    ///
+    /// This decompresses the visibility data on every call; the server keeps a `VisCache` instead.
+    pub fn from_point(bsp: &BspData, point: Vector3<f32>) -> Pvs {
+        let leaf_id = bsp.find_leaf(point);
+
+        // leaf 0 is outside the map
+        if leaf_id == 0 || bsp.leaves()[leaf_id].vis_offset.is_none() {
+            return Pvs::all();
+        }
+
+        let leaf_count = bsp.leaves().len();
+        let mut leaves = vec![0; words_for(leaf_count)];
+        for visible_id in bsp.get_pvs(leaf_id, leaf_count) {
+            set_bit(&mut leaves, visible_id);
+        }
+
+        // a leaf can always see itself, even if the map compiler didn't record it
+        set_bit(&mut leaves, leaf_id);
+
+        Pvs {
+            leaves: Some(leaves),
+        }
+    }
+
+    /// Computes the potentially hearable set of the leaf containing `point`.
+    ///
+    /// Like the PVS, points outside the map and maps without visibility data can hear every leaf.
+    pub fn hearable_from_point(bsp: &BspData, point: Vector3<f32>) -> Pvs {
+        let pvs = Pvs::from_point(bsp, point);
+        let visible = match pvs.leaves {
+            Some(ref v) => v,
+            None => return pvs,
+        };
+
+        let leaf_count = bsp.leaves().len();
+        let mut leaves = visible.clone();
+        for leaf_id in (0..leaf_count).filter(|l| get_bit(visible, *l)) {
+            for hearable_id in bsp.get_pvs(leaf_id, leaf_count) {
+                set_bit(&mut leaves, hearable_id);
+            }
+        }
+
+        Pvs {
+            leaves: Some(leaves),
+        }
+    }
+
+    /// Returns `true` if the leaf containing `point` is in this set.
+    pub fn contains_point(&self, bsp: &BspData, point: Vector3<f32>) -> bool {
+        self.leaves.is_none() || self.contains_leaf(bsp.find_leaf(point))
+    }
+
+    /// Returns `true` if the leaf with the given ID is potentially visible.
+    pub fn contains_leaf(&self, leaf_id: usize) -> bool {
+        match self.leaves {
+            Some(ref leaves) => get_bit(leaves, leaf_id),
+            None => true,
+        }
+    }
+
+    /// Returns `true` if any of the leaves in `leaf_ids` is potentially visible.
+    pub fn contains_any(&self, leaf_ids: &[usize]) -> bool {
+        self.leaves.is_none() || leaf_ids.iter().any(|l| self.contains_leaf(*l))
+    }
+
+    /// Returns `true` if any part of the box bounded by `min` and `max` is potentially visible.
+    pub fn contains_bounds(&self, bsp: &BspData, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+        if self.leaves.is_none() {
+            return true;
+        }
+
+        leaves_in_bounds(bsp, min, max)
+            .into_iter()
+            .any(|leaf_id| self.contains_leaf(leaf_id))
+    }
+}
+
+/// The decompressed PVS and PHS of every leaf in a map.
+///
+/// Decompressing a leaf's visibility data walks its run-length encoded row, which is too slow to
+/// repeat for every client and multicast message each frame. A `VisCache` does it once when the
+/// map is loaded and answers queries from bit sets.
+#[derive(Clone, Debug)]
+pub struct VisCache {
+    leaf_count: usize,
+    words_per_row: usize,
+
+    // whether each leaf has visibility data. leaves without it can see and hear everything.
+    has_vis: Vec<bool>,
+
+    // one row of `words_per_row` words per leaf
+    pvs: Vec<u64>,
+    phs: Vec<u64>,
+}
+
+impl VisCache {
+    /// Decompresses the visibility data of `bsp`.
+    pub fn new(bsp: &BspData) -> VisCache {
+        let leaf_count = bsp.leaves().len();
+        VisCache::build(leaf_count, |leaf_id| {
+            // leaf 0 is outside the map
+            if leaf_id == 0 || bsp.leaves()[leaf_id].vis_offset.is_none() {
+                None
+            } else {
+                Some(bsp.get_pvs(leaf_id, leaf_count))
+            }
+        })
+    }
+
+    /// Builds the cache from the visible leaves of each leaf, or `None` for leaves that can see
+    /// everything.
+    fn build<F>(leaf_count: usize, mut visible_from: F) -> VisCache
+    where
+        F: FnMut(usize) -> Option<Vec<usize>>,
+    {
+        let words_per_row = words_for(leaf_count);
+        let mut has_vis = vec![false; leaf_count];
+        let mut pvs = vec![0; words_per_row * leaf_count];
+
+        for leaf_id in 0..leaf_count {
+            let visible = match visible_from(leaf_id) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            has_vis[leaf_id] = true;
+            let row = &mut pvs[leaf_id * words_per_row..(leaf_id + 1) * words_per_row];
+            for visible_id in visible {
+                set_bit(row, visible_id);
+            }
+
+            // a leaf can always see itself, even if the map compiler didn't record it
+            set_bit(row, leaf_id);
+        }
+
+        // the PHS of a leaf is the union of the PVS rows of every leaf it can see
+        let mut phs = pvs.clone();
+        for leaf_id in (0..leaf_count).filter(|l| has_vis[*l]) {
+            let row_start = leaf_id * words_per_row;
+            let row = &pvs[row_start..row_start + words_per_row];
+            for visible_id in (0..leaf_count).filter(|l| has_vis[*l] && get_bit(row, *l)) {
+                let src = visible_id * words_per_row;
+                for w in 0..words_per_row {
+                    phs[row_start + w] |= pvs[src + w];
+                }
+            }
+        }
+
+        VisCache {
+            leaf_count,
+            words_per_row,
+            has_vis,
+            pvs,
+            phs,
+        }
+    }
+
+    /// Returns a cache for a map without visibility data, in which every leaf can see every other.
+    pub fn none() -> VisCache {
+        VisCache::build(0, |_| None)
+    }
+
+    /// Returns the number of leaves in the map, including the solid leaf 0.
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    fn row<'a>(&self, table: &'a [u64], leaf_id: usize) -> Option<&'a [u64]> {
+        if leaf_id >= self.leaf_count || !self.has_vis[leaf_id] {
+            return None;
+        }
+
+        let start = leaf_id * self.words_per_row;
+        Some(&table[start..start + self.words_per_row])
+    }
+
+    /// Returns the PVS of the given leaf.
+    pub fn pvs(&self, leaf_id: usize) -> Pvs {
+        Pvs {
+            leaves: self.row(&self.pvs, leaf_id).map(|r| r.to_vec()),
+        }
+    }
+
+    /// Returns the potentially hearable set of the given leaf.
+    pub fn phs(&self, leaf_id: usize) -> Pvs {
+        Pvs {
+            leaves: self.row(&self.phs, leaf_id).map(|r| r.to_vec()),
+        }
+    }
+
+    /// Returns `true` if leaf `to` is potentially visible from leaf `from`.
+    pub fn leaf_visible(&self, from: usize, to: usize) -> bool {
+        match self.row(&self.pvs, from) {
+            Some(row) => get_bit(row, to),
+            None => true,
+        }
+    }
+
+    /// Returns `true` if leaf `to` is potentially hearable from leaf `from`.
+    pub fn leaf_hearable(&self, from: usize, to: usize) -> bool {
+        match self.row(&self.phs, from) {
+            Some(row) => get_bit(row, to),
+            None => true,
+        }
+    }
+}
+
+/// Returns the IDs of all world leaves touched by the box bounded by `min` and `max`.
+pub fn leaves_in_bounds(bsp: &BspData, min: Vector3<f32>, max: Vector3<f32>) -> Vec<usize> {
+    let mut leaves = Vec::new();
+    let mut stack = vec![BspRenderNodeChild::Node(0)];
+
+    while let Some(child) = stack.pop() {
+        match child {
+            BspRenderNodeChild::Leaf(leaf_id) => {
+                // leaf 0 is the solid space outside the map
+                if leaf_id != 0 {
+                    leaves.push(leaf_id);
+                }
+            }
+
+            BspRenderNodeChild::Node(node_id) => {
+                let node = &bsp.render_nodes()[node_id];
+                let (near, far) = bsp.planes()[node.plane_id].box_dist_range(min, max);
+
+                if far >= 0.0 {
+                    stack.push(node.children[0]);
+                }
+
+                if near < 0.0 {
+                    stack.push(node.children[1]);
+                }
+            }
+        }
+    }
+
+    leaves
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pvs_all() {
+        let pvs = Pvs::all();
+        assert!(pvs.contains_leaf(0));
+        assert!(pvs.contains_leaf(1000));
+    }
+
+    #[test]
+    fn test_pvs_contains_leaf() {
+        let pvs = Pvs::from_leaves(vec![false, true, false]);
+        assert!(!pvs.contains_leaf(0));
+        assert!(pvs.contains_leaf(1));
+        assert!(!pvs.contains_leaf(2));
+
+        // out-of-range leaves aren't visible
+        assert!(!pvs.contains_leaf(3));
+    }
+
+    // five leaves in a row, each seeing its neighbors. leaf 0 is outside the map.
+    fn corridor() -> VisCache {
+        VisCache::build(6, |leaf_id| match leaf_id {
+            0 => None,
+            l => Some(
+                vec![l - 1, l + 1]
+                    .into_iter()
+                    .filter(|n| (1..6).contains(n))
+                    .collect(),
+            ),
+        })
+    }
+
+    #[test]
+    fn test_vis_cache_pvs() {
+        let cache = corridor();
+        let pvs = cache.pvs(3);
+        assert!(!pvs.contains_leaf(1));
+        assert!(pvs.contains_leaf(2));
+        assert!(pvs.contains_leaf(3));
+        assert!(pvs.contains_leaf(4));
+        assert!(!pvs.contains_leaf(5));
+
+        assert!(cache.leaf_visible(3, 4));
+        assert!(!cache.leaf_visible(3, 5));
+
+        // leaves without visibility data see everything
+        assert!(cache.pvs(0).contains_leaf(5));
+        assert!(cache.leaf_visible(0, 5));
+        assert!(cache.leaf_visible(100, 5));
+    }
+
+    #[test]
+    fn test_vis_cache_phs() {
+        let cache = corridor();
+        let phs = cache.phs(3);
+        for leaf_id in 1..6 {
+            assert!(phs.contains_leaf(leaf_id));
+        }
+
+        let phs = cache.phs(1);
+        assert!(phs.contains_leaf(3));
+        assert!(!phs.contains_leaf(4));
+        assert!(cache.leaf_hearable(1, 3));
+        assert!(!cache.leaf_hearable(1, 4));
+    }
+
+    #[test]
+    fn test_pvs_contains_any() {
+        let pvs = Pvs::from_leaves((0..130).map(|l| l == 129).collect());
+        assert!(pvs.contains_leaf(129));
+        assert!(!pvs.contains_any(&[1, 64, 128]));
+        assert!(pvs.contains_any(&[1, 129]));
+        assert!(Pvs::all().contains_any(&[]));
+    }
+}
@@ -90,8 +90,12 @@ impl Display for WadError {
 pub enum WadErrorKind {
     #[fail(display = "CONCHARS must be loaded with the dedicated function")]
     ConcharsUseDedicatedFunction,
+    #[fail(display = "Invalid lump table or lump outside of WAD")]
+    InvalidLump,
     #[fail(display = "Invalid magic number")]
     InvalidMagicNumber,
+    #[fail(display = "Invalid QPic dimensions")]
+    InvalidQPicSize,
     #[fail(display = "I/O error")]
     Io,
     #[fail(display = "No such file in WAD")]
@@ -118,10 +122,15 @@ impl QPic {
         let width = reader.read_u32::<LittleEndian>()?;
         let height = reader.read_u32::<LittleEndian>()?;
 
+        let size = width
+            .checked_mul(height)
+            .ok_or(WadErrorKind::InvalidQPicSize)?;
+
         let mut indices = Vec::new();
-        (&mut reader)
-            .take((width * height) as u64)
-            .read_to_end(&mut indices)?;
+        (&mut reader).take(size as u64).read_to_end(&mut indices)?;
+        if indices.len() != size as usize {
+            Err(WadErrorKind::UnexpectedEof)?;
+        }
 
         Ok(QPic {
             width,
@@ -159,6 +168,8 @@ impl Wad {
         R: Read + Seek,
     {
         let mut reader = BufReader::new(data);
+        let wad_len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
 
         let magic = reader.read_u32::<LittleEndian>()?;
         if magic != MAGIC {
@@ -167,16 +178,22 @@ impl Wad {
 
         let lump_count = reader.read_u32::<LittleEndian>()?;
         let lumpinfo_ofs = reader.read_u32::<LittleEndian>()?;
+        if lumpinfo_ofs as u64 + lump_count as u64 * LUMPINFO_SIZE as u64 > wad_len {
+            return Err(WadErrorKind::InvalidLump.into());
+        }
 
         reader.seek(SeekFrom::Start(lumpinfo_ofs as u64))?;
 
         let mut lump_infos = Vec::new();
 
         for _ in 0..lump_count {
-            // TODO sanity check these values
             let offset = reader.read_u32::<LittleEndian>()?;
             let _size_on_disk = reader.read_u32::<LittleEndian>()?;
             let size = reader.read_u32::<LittleEndian>()?;
+            if offset as u64 + size as u64 > wad_len {
+                return Err(WadErrorKind::InvalidLump.into());
+            }
+
             let _type = reader.read_u8()?;
             let _compression = reader.read_u8()?;
             let _pad = reader.read_u16::<LittleEndian>()?;
@@ -208,7 +225,10 @@ impl Wad {
             Some(ref data) => {
                 let width = 128;
                 let height = 128;
-                let indices = Vec::from(&data[..(width * height) as usize]);
+                let indices = match data.get(..(width * height) as usize) {
+                    Some(d) => Vec::from(d),
+                    None => Err(WadErrorKind::UnexpectedEof)?,
+                };
 
                 Ok(QPic {
                     width,
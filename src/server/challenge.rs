@@ -0,0 +1,255 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Screening of connection requests.
+//!
+//! Connection requests arrive over UDP, so their source address is trivially forged. Before a
+//! connection is accepted, the server replies with a random challenge which the client must send
+//! back with a second request, proving that it can receive packets at the address it claims.
+//! Requests from each host are also rate-limited, and the number of outstanding challenges is
+//! capped so that a flood of requests can't exhaust memory.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+/// The maximum number of challenges awaiting a reply.
+///
+/// Once this is reached, the oldest challenge is discarded to make room for a new one.
+pub const MAX_PENDING_CHALLENGES: usize = 1024;
+
+// how long a client has to reply to a challenge
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// the minimum time between connection requests from the same host. replies to challenges are
+// exempt, since they follow the original request within a round trip.
+const MIN_REQUEST_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The outcome of screening a connection request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConnectVerdict {
+    /// The request should be dropped without a reply.
+    Ignore,
+
+    /// The client should be sent this challenge and asked to try again.
+    Challenge(i32),
+
+    /// The client has proven its address and may connect.
+    Accept,
+}
+
+struct PendingChallenge {
+    challenge: i32,
+    issued: Instant,
+}
+
+/// Tracks challenges and request rates for connecting clients.
+pub struct ConnectGuard {
+    challenges: HashMap<SocketAddr, PendingChallenge>,
+    last_request: HashMap<IpAddr, Instant>,
+}
+
+impl ConnectGuard {
+    pub fn new() -> ConnectGuard {
+        ConnectGuard {
+            challenges: HashMap::new(),
+            last_request: HashMap::new(),
+        }
+    }
+
+    /// Screens a connection request from `remote` carrying the challenge `challenge`.
+    ///
+    /// If `require_challenge` is false, requests are only rate-limited. This allows clients that
+    /// don't understand challenges to connect.
+    pub fn check(
+        &mut self,
+        remote: SocketAddr,
+        challenge: Option<i32>,
+        require_challenge: bool,
+        now: Instant,
+    ) -> ConnectVerdict {
+        self.expire(now);
+
+        if require_challenge {
+            if let Some(c) = challenge {
+                return self.check_reply(remote, c);
+            }
+        }
+
+        if let Some(last) = self.last_request.insert(remote.ip(), now) {
+            if now.duration_since(last) < MIN_REQUEST_INTERVAL {
+                return ConnectVerdict::Ignore;
+            }
+        }
+
+        if !require_challenge {
+            return ConnectVerdict::Accept;
+        }
+
+        self.issue_challenge(remote, now)
+    }
+
+    /// Returns the number of challenges awaiting a reply.
+    pub fn pending_count(&self) -> usize {
+        self.challenges.len()
+    }
+
+    fn check_reply(&mut self, remote: SocketAddr, challenge: i32) -> ConnectVerdict {
+        match self.challenges.get(&remote) {
+            Some(pending) if pending.challenge == challenge => {
+                self.challenges.remove(&remote);
+                ConnectVerdict::Accept
+            }
+
+            // a wrong challenge is most likely a guess, so don't help by issuing another
+            _ => ConnectVerdict::Ignore,
+        }
+    }
+
+    fn issue_challenge(&mut self, remote: SocketAddr, now: Instant) -> ConnectVerdict {
+        if self.challenges.len() >= MAX_PENDING_CHALLENGES && !self.challenges.contains_key(&remote)
+        {
+            if let Some(oldest) = self
+                .challenges
+                .iter()
+                .min_by_key(|(_, pending)| pending.issued)
+                .map(|(addr, _)| *addr)
+            {
+                self.challenges.remove(&oldest);
+            }
+        }
+
+        let challenge = rand::random();
+        self.challenges.insert(
+            remote,
+            PendingChallenge {
+                challenge,
+                issued: now,
+            },
+        );
+        ConnectVerdict::Challenge(challenge)
+    }
+
+    // forget challenges that have gone unanswered and hosts that have stopped sending requests
+    fn expire(&mut self, now: Instant) {
+        self.challenges
+            .retain(|_, pending| now.duration_since(pending.issued) < CHALLENGE_TIMEOUT);
+        self.last_request
+            .retain(|_, last| now.duration_since(*last) < MIN_REQUEST_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_challenge_handshake() {
+        let mut guard = ConnectGuard::new();
+        let client = addr("192.168.0.2:27001");
+        let start = Instant::now();
+
+        let challenge = match guard.check(client, None, true, start) {
+            ConnectVerdict::Challenge(c) => c,
+            v => panic!("expected challenge, got {:?}", v),
+        };
+        assert_eq!(guard.pending_count(), 1);
+
+        // the reply arrives well within the rate limit
+        let reply = start + Duration::from_millis(50);
+        assert_eq!(
+            guard.check(client, Some(challenge), true, reply),
+            ConnectVerdict::Accept
+        );
+        assert_eq!(guard.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_wrong_challenge_ignored() {
+        let mut guard = ConnectGuard::new();
+        let client = addr("192.168.0.2:27001");
+        let start = Instant::now();
+
+        let challenge = match guard.check(client, None, true, start) {
+            ConnectVerdict::Challenge(c) => c,
+            v => panic!("expected challenge, got {:?}", v),
+        };
+
+        // the same challenge from a different port doesn't count
+        let spoofed = addr("192.168.0.2:27002");
+        let reply = start + Duration::from_secs(1);
+        assert_eq!(
+            guard.check(spoofed, Some(challenge), true, reply),
+            ConnectVerdict::Ignore
+        );
+
+        // nor does a challenge that has expired
+        let late = start + CHALLENGE_TIMEOUT + Duration::from_secs(1);
+        assert_eq!(
+            guard.check(client, Some(challenge), true, late),
+            ConnectVerdict::Ignore
+        );
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut guard = ConnectGuard::new();
+        let start = Instant::now();
+
+        assert_eq!(
+            guard.check(addr("10.0.0.1:27001"), None, false, start),
+            ConnectVerdict::Accept
+        );
+        assert_eq!(
+            guard.check(
+                addr("10.0.0.1:27002"),
+                None,
+                false,
+                start + Duration::from_millis(100)
+            ),
+            ConnectVerdict::Ignore
+        );
+        assert_eq!(
+            guard.check(
+                addr("10.0.0.1:27001"),
+                None,
+                false,
+                start + Duration::from_secs(1)
+            ),
+            ConnectVerdict::Accept
+        );
+    }
+
+    #[test]
+    fn test_pending_challenges_capped() {
+        let mut guard = ConnectGuard::new();
+        let start = Instant::now();
+
+        for i in 0..MAX_PENDING_CHALLENGES + 16 {
+            let client = SocketAddr::new(IpAddr::from((i as u32).to_be_bytes()), 27001);
+            guard.check(client, None, true, start);
+        }
+
+        assert_eq!(guard.pending_count(), MAX_PENDING_CHALLENGES);
+    }
+}
@@ -0,0 +1,221 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::common::console::{ConsoleError, CvarRegistry};
+
+/// The cvars reported to server browsers as rules, in the order they're listed.
+///
+/// These are the cvars the original engine marks as server variables.
+pub const RULE_CVARS: &[&str] = &[
+    "teamplay",
+    "fraglimit",
+    "timelimit",
+    "sv_friction",
+    "sv_gravity",
+    "sv_maxspeed",
+];
+
+/// Returns the name and value of the rule following `prev` in `RULE_CVARS`.
+///
+/// If `prev` is empty, this returns the first rule. Returns `None` once the list is exhausted or if
+/// `prev` isn't a rule. Rules that aren't registered are skipped.
+pub fn next_rule(cvars: &CvarRegistry, prev: &str) -> Option<(String, String)> {
+    let start = if prev.is_empty() {
+        0
+    } else {
+        RULE_CVARS.iter().position(|name| *name == prev)? + 1
+    };
+
+    RULE_CVARS[start..]
+        .iter()
+        .find_map(|name| cvars.get(name).ok().map(|val| (name.to_string(), val)))
+}
+
+pub fn register_cvars(cvars: &CvarRegistry) -> Result<(), ConsoleError> {
+    cvars.register("coop", "0", "if nonzero, run a cooperative game")?;
+    cvars.register(
+        "deathmatch",
+        "0",
+        "deathmatch mode, or 0 for a cooperative or single-player game",
+    )?;
+    cvars.register(
+        "fraglimit",
+        "0",
+        "frags needed to end a deathmatch level, or 0 for no limit",
+    )?;
+    cvars.register(
+        "hostname",
+        "UNNAMED",
+        "name of the server shown to server browsers",
+    )?;
+    cvars.register("pausable", "1", "if nonzero, players may pause the game")?;
+    cvars.register(
+        "pr_checkextension",
+        "1",
+        "tells progs they can call checkextension to probe for engine extensions",
+    )?;
+    // not archived, so that the password is never written to a config file
+    cvars.register(
+        "rcon_password",
+        "",
+        "password for running console commands remotely, or empty to refuse them",
+    )?;
+    cvars.register(
+        "sv_cheats",
+        "0",
+        "if nonzero, allow cheat commands and settings",
+    )?;
+    cvars.register(
+        "sv_aim",
+        "0.93",
+        "how closely shots must point at a target to be steered toward it, as the cosine of the \
+         angle between them",
+    )?;
+    cvars.register_archive(
+        "sv_autosave",
+        "1",
+        "if nonzero, autosave single-player games when each level starts",
+    )?;
+    cvars.register_archive(
+        "sv_autosave_interval",
+        "300",
+        "seconds between autosaves within a level, or 0 to only save at the start",
+    )?;
+    cvars.register(
+        "sv_challenge",
+        "0",
+        "require connecting clients to answer a challenge, which clients predating the challenge \
+         can't do",
+    )?;
+    cvars.register(
+        "sv_masters",
+        "dpmaster.deathmask.net dpmaster.tchr.no",
+        "master servers to advertise to when sv_public is set, as addresses with optional ports",
+    )?;
+    cvars.register(
+        "sv_idleaction",
+        "0",
+        "what happens to a player who reaches sv_idlelimit: 0 to move them to spectator, 1 to \
+         drop them",
+    )?;
+    cvars.register(
+        "sv_idledeadzone",
+        "1",
+        "degrees the view must turn to count as input for sv_idlelimit",
+    )?;
+    cvars.register(
+        "sv_idlelimit",
+        "0",
+        "seconds a player can go without input before sv_idleaction, or 0 for no limit",
+    )?;
+    cvars.register(
+        "sv_maxvelocity",
+        "2000",
+        "maximum speed of any entity along each axis",
+    )?;
+    cvars.register(
+        "sv_protocol",
+        "0",
+        "network protocol to use (15, 666 or 999), or 0 to choose automatically",
+    )?;
+    cvars.register(
+        "sv_public",
+        "0",
+        "if nonzero, advertise the server to the master servers in sv_masters",
+    )?;
+    cvars.register(
+        "sv_seed",
+        "0",
+        "seed for gameplay randomness, so that games can be repeated exactly, or 0 for a new seed \
+         every game",
+    )?;
+    cvars.register(
+        "sv_timeout",
+        "300",
+        "seconds a client can go without sending anything before it is dropped",
+    )?;
+    cvars.register(
+        "sys_ticrate",
+        "0.05",
+        "seconds between frames of a dedicated server",
+    )?;
+    cvars.register(
+        "teamplay",
+        "0",
+        "if nonzero, players on the same team can't hurt each other",
+    )?;
+    cvars.register(
+        "timelimit",
+        "0",
+        "minutes until a deathmatch level ends, or 0 for no limit",
+    )?;
+
+    // these are also registered by the client, so if both are running in the
+    // same process we can ignore the duplicate cvar error
+    let _ = cvars.register("edgefriction", "2", "extra friction applied near ledges");
+    let _ = cvars.register("skill", "1", "difficulty from 0 (easy) to 3 (nightmare)");
+    let _ = cvars.register("sv_accelerate", "10", "player acceleration on the ground");
+    let _ = cvars.register("sv_friction", "4", "player friction on the ground");
+    let _ = cvars.register(
+        "sv_gravity",
+        "800",
+        "downward acceleration in units per second",
+    );
+    let _ = cvars.register("sv_maxspeed", "320", "maximum player movement speed");
+    let _ = cvars.register(
+        "sv_stopspeed",
+        "100",
+        "speed below which friction brings players to a stop",
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_next_rule() {
+        let cvars = CvarRegistry::new();
+        register_cvars(&cvars).unwrap();
+        cvars.set("fraglimit", "30").unwrap();
+
+        let mut rules = Vec::new();
+        let mut prev = String::new();
+        while let Some((name, val)) = next_rule(&cvars, &prev) {
+            rules.push(format!("{}={}", name, val));
+            prev = name;
+        }
+
+        assert_eq!(
+            rules,
+            vec![
+                "teamplay=0",
+                "fraglimit=30",
+                "timelimit=0",
+                "sv_friction=4",
+                "sv_gravity=800",
+                "sv_maxspeed=320",
+            ]
+        );
+        assert_eq!(next_rule(&cvars, "hostname"), None);
+    }
+}
@@ -0,0 +1,394 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! A tiny game for tests that run the real server.
+//!
+//! The game data can't be shipped with the source, so this builds the least a level needs to run:
+//! a `progs.dat` whose only work is to put the player in the game as a walking player, and a map
+//! that is nothing but a floor at height 0, `maps/floor.bsp`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    common::vfs::Vfs,
+    server::{
+        progs::{BuiltinFunctionId, GlobalAddrEntity, GlobalAddrFunction, Opcode},
+        world::{FieldAddrFloat, MoveKind},
+    },
+};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+/// The name of the map in the fixture.
+pub const MAP_NAME: &str = "floor";
+
+/// Where `PutClientInServer` puts the player, just above the floor.
+pub const SPAWN_ORIGIN: [f32; 3] = [0.0, 0.0, 32.0];
+
+/// The size of the player, whose origin is 24 units above its feet.
+pub const PLAYER_MINS: [f32; 3] = [-16.0, -16.0, -24.0];
+pub const PLAYER_MAXS: [f32; 3] = [16.0, 16.0, 32.0];
+
+const PROGS_VERSION: i32 = 6;
+const PROGS_CRC: i32 = 5927;
+const PROGS_GLOBAL_COUNT: usize = 116;
+const PROGS_FIELD_COUNT: i32 = 105;
+
+// where PutClientInServer keeps its constants, past the static globals
+const CONST_WALK: i16 = 100;
+const CONST_HEALTH: i16 = 101;
+const CONST_FIELD_MOVE_KIND: i16 = 102;
+const CONST_FIELD_HEALTH: i16 = 103;
+const TEMP_POINTER: i16 = 104;
+const CONST_ORIGIN: i16 = 105;
+const CONST_SET_ORIGIN: i16 = 108;
+const CONST_MINS: i16 = 109;
+const CONST_MAXS: i16 = 112;
+const CONST_SET_SIZE: i16 = 115;
+
+const ARG_0: i16 = 4;
+const ARG_1: i16 = 7;
+const ARG_2: i16 = 10;
+
+const BSP_VERSION: i32 = 29;
+const BSP_SECTION_COUNT: usize = 15;
+
+// how far the floor extends from the origin in each direction, well within the coordinates of
+// the original protocol
+const FLOOR_EXTENT: i16 = 2048;
+
+/// Writes the game to a new directory named after `name` and returns a `Vfs` that reads it.
+pub fn vfs(name: &str) -> Rc<Vfs> {
+    let dir = game_dir(name);
+    let mut vfs = Vfs::new();
+    vfs.add_directory(&dir).unwrap();
+    Rc::new(vfs)
+}
+
+/// Writes the game to a new directory named after `name`.
+pub fn game_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("richter-fixture-{}-{}", std::process::id(), name));
+    fs::create_dir_all(dir.join("maps")).unwrap();
+    fs::create_dir_all(dir.join("misc")).unwrap();
+
+    fs::write(dir.join("progs.dat"), progs()).unwrap();
+    fs::write(dir.join("maps").join(format!("{}.bsp", MAP_NAME)), bsp()).unwrap();
+    write_null_sound(&dir.join("misc/null.wav"));
+
+    dir
+}
+
+// the silent sound every client loads first
+fn write_null_sound(path: &Path) {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 11025,
+        bits_per_sample: 8,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec).unwrap();
+    for _ in 0..16 {
+        writer.write_sample(0i8).unwrap();
+    }
+    writer.finalize().unwrap();
+}
+
+struct Strings {
+    data: Vec<u8>,
+}
+
+impl Strings {
+    fn new() -> Strings {
+        Strings { data: vec![0] }
+    }
+
+    fn insert(&mut self, s: &str) -> i32 {
+        let ofs = self.data.len() as i32;
+        self.data.extend_from_slice(s.as_bytes());
+        self.data.push(0);
+        ofs
+    }
+}
+
+/// Returns a `progs.dat` with the functions the server calls.
+///
+/// `PutClientInServer` makes the player a walking entity with 100 health, moves it to
+/// `SPAWN_ORIGIN` and gives it the size of a player. Every other function returns at once.
+pub fn progs() -> Vec<u8> {
+    let statements: Vec<[i16; 4]> = vec![
+        // the null function
+        [Opcode::Done as i16, 0, 0, 0],
+        // every function that does nothing
+        [Opcode::Done as i16, 0, 0, 0],
+        // PutClientInServer
+        [
+            Opcode::Address as i16,
+            GlobalAddrEntity::Self_ as i16,
+            CONST_FIELD_MOVE_KIND,
+            TEMP_POINTER,
+        ],
+        [Opcode::StorePF as i16, CONST_WALK, TEMP_POINTER, 0],
+        [
+            Opcode::Address as i16,
+            GlobalAddrEntity::Self_ as i16,
+            CONST_FIELD_HEALTH,
+            TEMP_POINTER,
+        ],
+        [Opcode::StorePF as i16, CONST_HEALTH, TEMP_POINTER, 0],
+        [
+            Opcode::StoreEnt as i16,
+            GlobalAddrEntity::Self_ as i16,
+            ARG_0,
+            0,
+        ],
+        [Opcode::StoreV as i16, CONST_ORIGIN, ARG_1, 0],
+        [Opcode::Call2 as i16, CONST_SET_ORIGIN, 0, 0],
+        [
+            Opcode::StoreEnt as i16,
+            GlobalAddrEntity::Self_ as i16,
+            ARG_0,
+            0,
+        ],
+        [Opcode::StoreV as i16, CONST_MINS, ARG_1, 0],
+        [Opcode::StoreV as i16, CONST_MAXS, ARG_2, 0],
+        [Opcode::Call3 as i16, CONST_SET_SIZE, 0, 0],
+        [Opcode::Done as i16, 0, 0, 0],
+    ];
+    const NOTHING: i32 = 1;
+    const PUT_CLIENT_IN_SERVER: i32 = 2;
+
+    let mut strings = Strings::new();
+
+    // (first statement, name), with function 0 as the null function
+    let functions = vec![
+        (0, strings.insert("")),
+        (
+            -(BuiltinFunctionId::SetOrigin as i32),
+            strings.insert("setorigin"),
+        ),
+        (
+            -(BuiltinFunctionId::SetSize as i32),
+            strings.insert("setsize"),
+        ),
+        (NOTHING, strings.insert("worldspawn")),
+        (NOTHING, strings.insert("main")),
+        (NOTHING, strings.insert("StartFrame")),
+        (NOTHING, strings.insert("PlayerPreThink")),
+        (NOTHING, strings.insert("PlayerPostThink")),
+        (NOTHING, strings.insert("ClientKill")),
+        (NOTHING, strings.insert("ClientConnect")),
+        (PUT_CLIENT_IN_SERVER, strings.insert("PutClientInServer")),
+        (NOTHING, strings.insert("ClientDisconnect")),
+        (NOTHING, strings.insert("SetNewParms")),
+        (NOTHING, strings.insert("SetChangeParms")),
+    ];
+    let function_id = |name: &str| {
+        functions
+            .iter()
+            .position(|&(_, ofs)| {
+                let end = strings.data[ofs as usize..]
+                    .iter()
+                    .position(|b| *b == 0)
+                    .unwrap();
+                &strings.data[ofs as usize..ofs as usize + end] == name.as_bytes()
+            })
+            .unwrap() as i32
+    };
+
+    let mut globals = vec![[0u8; 4]; PROGS_GLOBAL_COUNT];
+    let mut put_int = |addr: i16, val: i32| globals[addr as usize] = val.to_le_bytes();
+    for &(addr, name) in &[
+        (GlobalAddrFunction::Main as i16, "main"),
+        (GlobalAddrFunction::StartFrame as i16, "StartFrame"),
+        (GlobalAddrFunction::PlayerPreThink as i16, "PlayerPreThink"),
+        (
+            GlobalAddrFunction::PlayerPostThink as i16,
+            "PlayerPostThink",
+        ),
+        (GlobalAddrFunction::ClientKill as i16, "ClientKill"),
+        (GlobalAddrFunction::ClientConnect as i16, "ClientConnect"),
+        (
+            GlobalAddrFunction::PutClientInServer as i16,
+            "PutClientInServer",
+        ),
+        (
+            GlobalAddrFunction::ClientDisconnect as i16,
+            "ClientDisconnect",
+        ),
+        (GlobalAddrFunction::SetNewArgs as i16, "SetNewParms"),
+        (GlobalAddrFunction::SetChangeArgs as i16, "SetChangeParms"),
+    ] {
+        put_int(addr, function_id(name));
+    }
+    put_int(CONST_FIELD_MOVE_KIND, FieldAddrFloat::MoveKind as i32);
+    put_int(CONST_FIELD_HEALTH, FieldAddrFloat::Health as i32);
+    put_int(CONST_SET_ORIGIN, function_id("setorigin"));
+    put_int(CONST_SET_SIZE, function_id("setsize"));
+
+    let mut put_float = |addr: i16, val: f32| globals[addr as usize] = val.to_le_bytes();
+    put_float(CONST_WALK, MoveKind::Walk as i32 as f32);
+    put_float(CONST_HEALTH, 100.0);
+    for &(addr, v) in &[
+        (CONST_ORIGIN, SPAWN_ORIGIN),
+        (CONST_MINS, PLAYER_MINS),
+        (CONST_MAXS, PLAYER_MAXS),
+    ] {
+        for (i, c) in v.iter().enumerate() {
+            put_float(addr + i as i16, *c);
+        }
+    }
+
+    let file_name = strings.insert("fixture.qc");
+
+    // lumps follow the header in the order of their IDs: statements, global definitions, field
+    // definitions, functions, strings and globals
+    let header_len = 8 + 6 * 8 + 4;
+    let statements_ofs = header_len;
+    let functions_ofs = statements_ofs + statements.len() * 8;
+    let strings_ofs = functions_ofs + functions.len() * 36;
+    let globals_ofs = strings_ofs + strings.data.len();
+
+    let mut progs = Vec::new();
+    progs.write_i32::<LittleEndian>(PROGS_VERSION).unwrap();
+    progs.write_i32::<LittleEndian>(PROGS_CRC).unwrap();
+    for &(ofs, count) in &[
+        (statements_ofs, statements.len()),
+        (functions_ofs, 0),
+        (functions_ofs, 0),
+        (functions_ofs, functions.len()),
+        (strings_ofs, strings.data.len()),
+        (globals_ofs, globals.len()),
+    ] {
+        progs.write_i32::<LittleEndian>(ofs as i32).unwrap();
+        progs.write_i32::<LittleEndian>(count as i32).unwrap();
+    }
+    progs.write_i32::<LittleEndian>(PROGS_FIELD_COUNT).unwrap();
+
+    for statement in statements.iter() {
+        for arg in statement.iter() {
+            progs.write_i16::<LittleEndian>(*arg).unwrap();
+        }
+    }
+
+    for &(first_statement, name) in functions.iter() {
+        progs.write_i32::<LittleEndian>(first_statement).unwrap();
+        // arguments and locals start past the globals in use
+        progs
+            .write_i32::<LittleEndian>(PROGS_GLOBAL_COUNT as i32)
+            .unwrap();
+        progs.write_i32::<LittleEndian>(0).unwrap();
+        progs.write_i32::<LittleEndian>(0).unwrap();
+        progs.write_i32::<LittleEndian>(name).unwrap();
+        progs.write_i32::<LittleEndian>(file_name).unwrap();
+        progs.write_i32::<LittleEndian>(0).unwrap();
+        progs.extend_from_slice(&[0; 8]);
+    }
+
+    progs.extend_from_slice(&strings.data);
+    for global in globals.iter() {
+        progs.extend_from_slice(global);
+    }
+
+    progs
+}
+
+fn write_bounds(data: &mut Vec<u8>, min: [i16; 3], max: [i16; 3]) {
+    for c in min.iter().chain(max.iter()) {
+        data.write_i16::<LittleEndian>(*c).unwrap();
+    }
+}
+
+/// Returns a BSP29 map with an empty world above a solid floor at height 0.
+///
+/// The world has a single node splitting the empty leaf from the solid one. The player-sized hulls
+/// use the same split, moved up by the height of the hull below its origin.
+pub fn bsp() -> Vec<u8> {
+    let min = [-FLOOR_EXTENT, -FLOOR_EXTENT, -FLOOR_EXTENT];
+    let max = [FLOOR_EXTENT, FLOOR_EXTENT, FLOOR_EXTENT];
+
+    let mut sections: Vec<Vec<u8>> = vec![Vec::new(); BSP_SECTION_COUNT];
+
+    // entities
+    sections[0].extend_from_slice(b"{\n\"classname\" \"worldspawn\"\n}\n\0");
+
+    // planes: the floor, and the floor raised for the player hulls
+    for &dist in &[0.0f32, 24.0] {
+        for c in &[0.0f32, 0.0, 1.0] {
+            sections[1].write_f32::<LittleEndian>(*c).unwrap();
+        }
+        sections[1].write_f32::<LittleEndian>(dist).unwrap();
+        sections[1].write_i32::<LittleEndian>(2).unwrap();
+    }
+
+    // textures: none
+    sections[2].write_i32::<LittleEndian>(0).unwrap();
+
+    // render node: empty leaf 1 in front of the floor, solid leaf 0 behind it
+    sections[5].write_i32::<LittleEndian>(0).unwrap();
+    sections[5].write_i16::<LittleEndian>(!1).unwrap();
+    sections[5].write_i16::<LittleEndian>(!0).unwrap();
+    write_bounds(&mut sections[5], min, max);
+    sections[5].write_u16::<LittleEndian>(0).unwrap();
+    sections[5].write_u16::<LittleEndian>(0).unwrap();
+
+    // collision node for the player hulls: empty in front, solid behind
+    sections[9].write_i32::<LittleEndian>(1).unwrap();
+    sections[9].write_i16::<LittleEndian>(-1).unwrap();
+    sections[9].write_i16::<LittleEndian>(-2).unwrap();
+
+    // leaves: the solid leaf outside the map, and the empty space above the floor
+    for &(contents, min, max) in &[(-2, min, max), (-1, [min[0], min[1], 0], max)] {
+        sections[10].write_i32::<LittleEndian>(contents).unwrap();
+        sections[10].write_i32::<LittleEndian>(-1).unwrap();
+        write_bounds(&mut sections[10], min, max);
+        sections[10].write_u16::<LittleEndian>(0).unwrap();
+        sections[10].write_u16::<LittleEndian>(0).unwrap();
+        sections[10].extend_from_slice(&[0; 4]);
+    }
+
+    // the world model
+    for c in min.iter().chain(max.iter()) {
+        sections[14].write_f32::<LittleEndian>(*c as f32).unwrap();
+    }
+    for _ in 0..3 {
+        sections[14].write_f32::<LittleEndian>(0.0).unwrap();
+    }
+    for _ in 0..4 {
+        sections[14].write_i32::<LittleEndian>(0).unwrap();
+    }
+    sections[14].write_i32::<LittleEndian>(1).unwrap();
+    sections[14].write_i32::<LittleEndian>(0).unwrap();
+    sections[14].write_i32::<LittleEndian>(0).unwrap();
+
+    let mut bsp = Vec::new();
+    bsp.write_i32::<LittleEndian>(BSP_VERSION).unwrap();
+    let mut ofs = 4 + BSP_SECTION_COUNT * 8;
+    for section in sections.iter() {
+        bsp.write_i32::<LittleEndian>(ofs as i32).unwrap();
+        bsp.write_i32::<LittleEndian>(section.len() as i32).unwrap();
+        ofs += section.len();
+    }
+    for section in sections.iter() {
+        bsp.extend_from_slice(section);
+    }
+
+    bsp
+}
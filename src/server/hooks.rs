@@ -0,0 +1,138 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Callbacks for programs embedding the server.
+//!
+//! Game rules live in QuakeC, but a program embedding the server may want to observe the game
+//! without modifying the progs, for example to track statistics. Such a program implements
+//! [`ServerHooks`](trait.ServerHooks.html) and registers it with
+//! [`Server::add_hooks`](../struct.Server.html#method.add_hooks).
+//!
+//! The progs don't report damage to the engine, so damage and deaths are inferred from changes to
+//! the `health` field of each entity. They are reported once per server frame, so several hits in
+//! the same frame are combined into one.
+
+use crate::server::{progs::EntityId, world::World};
+
+/// A set of callbacks invoked by the server as the game progresses.
+///
+/// Every method has an empty default implementation, so implementors only need to provide the
+/// ones they're interested in. The world is passed in its current state and may be inspected, but
+/// not modified.
+pub trait ServerHooks {
+    /// Called after an entity placed by the map has been spawned.
+    ///
+    /// This is not called for entities that remove themselves in their spawn function.
+    fn entity_spawned(&mut self, _world: &World, _entity_id: EntityId, _classname: &str) {}
+
+    /// Called when an entity loses health without dying.
+    ///
+    /// `inflictor` is the value of the entity's `dmg_inflictor` field, which the standard progs
+    /// only set for players.
+    fn entity_damaged(
+        &mut self,
+        _world: &World,
+        _entity_id: EntityId,
+        _damage: f32,
+        _inflictor: Option<EntityId>,
+    ) {
+    }
+
+    /// Called when an entity's health drops to zero or below.
+    fn entity_killed(
+        &mut self,
+        _world: &World,
+        _entity_id: EntityId,
+        _inflictor: Option<EntityId>,
+    ) {
+    }
+
+    /// Called when the progs request a change to the level `map_name`.
+    fn level_changed(&mut self, _map_name: &str) {}
+}
+
+/// A change in an entity's health since the last server frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HealthEvent {
+    Damaged(f32),
+    Killed,
+}
+
+impl HealthEvent {
+    /// Classifies a change in health from `old` to `new`.
+    ///
+    /// Returns `None` if the entity was already dead or didn't lose any health.
+    pub fn from_change(old: f32, new: f32) -> Option<HealthEvent> {
+        if old <= 0.0 || new >= old {
+            None
+        } else if new <= 0.0 {
+            Some(HealthEvent::Killed)
+        } else {
+            Some(HealthEvent::Damaged(old - new))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::server::{progs::StringTable, Server, ServerStatics};
+
+    struct LevelRecorder(Rc<RefCell<Vec<String>>>);
+
+    impl ServerHooks for LevelRecorder {
+        fn level_changed(&mut self, map_name: &str) {
+            self.0.borrow_mut().push(map_name.to_owned());
+        }
+    }
+
+    #[test]
+    fn test_health_event_from_change() {
+        assert_eq!(
+            HealthEvent::from_change(100.0, 75.0),
+            Some(HealthEvent::Damaged(25.0))
+        );
+        assert_eq!(
+            HealthEvent::from_change(25.0, 0.0),
+            Some(HealthEvent::Killed)
+        );
+        assert_eq!(
+            HealthEvent::from_change(25.0, -40.0),
+            Some(HealthEvent::Killed)
+        );
+
+        // healing, no change, and damage to a corpse
+        assert_eq!(HealthEvent::from_change(50.0, 75.0), None);
+        assert_eq!(HealthEvent::from_change(50.0, 50.0), None);
+        assert_eq!(HealthEvent::from_change(-10.0, -50.0), None);
+    }
+
+    #[test]
+    fn test_request_level_change() {
+        let levels = Rc::new(RefCell::new(Vec::new()));
+        let mut server = Server::new(Rc::new(StringTable::new(Vec::new())), ServerStatics::new(1));
+        server.add_hooks(Box::new(LevelRecorder(levels.clone())));
+
+        // only the first request on a level is honored
+        assert!(server.request_level_change("e1m2"));
+        assert!(!server.request_level_change("e1m3"));
+        assert_eq!(*levels.borrow(), vec!["e1m2".to_string()]);
+    }
+}
@@ -0,0 +1,333 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Detection of idle players.
+//!
+//! A player who stops playing without disconnecting leaves a body standing around to be fragged,
+//! which spoils a small deathmatch game. When `sv_idlelimit` is set, a player who sends no input
+//! for that many seconds is warned and then, depending on `sv_idleaction`, moved to spectator or
+//! dropped from the server.
+//!
+//! Only deliberate input counts. A resting mouse or a worn gamepad stick can send a steady stream
+//! of tiny view changes, so turns smaller than `sv_idledeadzone` degrees are ignored, as is slight
+//! movement. Pressing or releasing a button, an impulse or a chat message always counts.
+
+use crate::common::{
+    console::CvarRegistry,
+    net::{ButtonFlags, ClientCmd},
+};
+
+use cgmath::{Deg, Vector3};
+use chrono::Duration;
+
+// how long before the limit a player is warned
+const WARNING_SECS: i64 = 10;
+
+// the movement speed below which a move isn't counted as input. keyboard movement is at least 200.
+const MOVE_DEADZONE: i16 = 50;
+
+/// What happens to a player who reaches the idle limit.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IdleAction {
+    /// The player is moved to spectator, and can rejoin by sending input.
+    Spectate,
+
+    /// The player is disconnected.
+    Drop,
+}
+
+/// The idle limit and what to do when it is reached.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IdleSettings {
+    pub limit: Duration,
+    pub action: IdleAction,
+
+    /// The smallest turn counted as input.
+    pub deadzone: Deg<f32>,
+}
+
+impl IdleSettings {
+    /// Reads the settings from `sv_idlelimit`, `sv_idleaction` and `sv_idledeadzone`.
+    ///
+    /// Returns `None` if idle players are left alone.
+    pub fn from_cvars(cvars: &CvarRegistry) -> Option<IdleSettings> {
+        let limit = cvars.get_value("sv_idlelimit").unwrap_or(0.0);
+        if limit <= 0.0 {
+            return None;
+        }
+
+        let action = match cvars.get_value("sv_idleaction").unwrap_or(0.0) as i32 {
+            0 => IdleAction::Spectate,
+            _ => IdleAction::Drop,
+        };
+
+        Some(IdleSettings {
+            limit: Duration::milliseconds((limit * 1000.0) as i64),
+            action,
+            deadzone: Deg(cvars.get_value("sv_idledeadzone").unwrap_or(0.0).max(0.0)),
+        })
+    }
+
+    // the idle time at which a player is warned
+    fn warning_time(&self) -> Duration {
+        let warning = Duration::seconds(WARNING_SECS);
+        if self.limit > warning * 2 {
+            self.limit - warning
+        } else {
+            self.limit / 2
+        }
+    }
+}
+
+/// Something to be done about an idle player.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IdleEvent {
+    /// The player should be told that the limit is near.
+    Warn { remaining: Duration },
+
+    /// The limit has been reached, and the player should be dealt with.
+    Act(IdleAction),
+}
+
+// the smallest angle between two angles in degrees
+fn angle_between(a: Deg<f32>, b: Deg<f32>) -> f32 {
+    let d = (a.0 - b.0).rem_euclid(360.0);
+    d.min(360.0 - d)
+}
+
+/// Tracks how long one client has gone without input.
+#[derive(Clone, Debug)]
+pub struct IdleMonitor {
+    last_input: Duration,
+
+    // the view angles and buttons when input was last seen
+    angles: Option<Vector3<Deg<f32>>>,
+    buttons: ButtonFlags,
+
+    warned: bool,
+    acted: bool,
+}
+
+impl IdleMonitor {
+    /// Starts tracking a client that has just joined at `time`.
+    pub fn new(time: Duration) -> IdleMonitor {
+        IdleMonitor {
+            last_input: time,
+            angles: None,
+            buttons: ButtonFlags::empty(),
+            warned: false,
+            acted: false,
+        }
+    }
+
+    /// Returns how long the client has gone without input at `time`.
+    pub fn idle_time(&self, time: Duration) -> Duration {
+        time - self.last_input
+    }
+
+    /// Returns whether the idle limit has been acted on, e.g. whether the client was moved to
+    /// spectator, since its last input.
+    pub fn acted(&self) -> bool {
+        self.acted
+    }
+
+    /// Records a command received from the client at `time`.
+    ///
+    /// Returns `true` if the command counts as input. If the client was moved to spectator, this
+    /// is the cue to return it to the game.
+    pub fn record(&mut self, time: Duration, cmd: &ClientCmd, deadzone: Deg<f32>) -> bool {
+        let input = match cmd {
+            ClientCmd::Move {
+                angles,
+                fwd_move,
+                side_move,
+                up_move,
+                button_flags,
+                impulse,
+                ..
+            } => {
+                let turned = match self.angles {
+                    // compared to the angles at the last input, so that jitter never adds up
+                    Some(prev) => {
+                        angle_between(angles.x, prev.x) >= deadzone.0
+                            || angle_between(angles.y, prev.y) >= deadzone.0
+                    }
+                    None => {
+                        self.angles = Some(*angles);
+                        false
+                    }
+                };
+                let moved = [fwd_move, side_move, up_move]
+                    .iter()
+                    .any(|m| m.saturating_abs() >= MOVE_DEADZONE);
+                let pressed = *button_flags != self.buttons;
+                self.buttons = *button_flags;
+
+                if turned {
+                    self.angles = Some(*angles);
+                }
+
+                turned || moved || pressed || *impulse != 0
+            }
+
+            ClientCmd::StringCmd { cmd } => {
+                let name = cmd.split_whitespace().next().unwrap_or("");
+                name == "say" || name == "say_team"
+            }
+
+            _ => false,
+        };
+
+        if input {
+            self.last_input = time;
+            self.warned = false;
+            self.acted = false;
+        }
+
+        input
+    }
+
+    /// Checks the client against the idle limit at `time`.
+    ///
+    /// Each event is returned once per idle period: the warning, then the action.
+    pub fn update(&mut self, time: Duration, settings: &IdleSettings) -> Option<IdleEvent> {
+        let idle = self.idle_time(time);
+
+        if idle >= settings.limit {
+            if self.acted {
+                return None;
+            }
+
+            self.acted = true;
+            self.warned = true;
+            return Some(IdleEvent::Act(settings.action));
+        }
+
+        if idle >= settings.warning_time() && !self.warned {
+            self.warned = true;
+            return Some(IdleEvent::Warn {
+                remaining: settings.limit - idle,
+            });
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings() -> IdleSettings {
+        IdleSettings {
+            limit: Duration::seconds(60),
+            action: IdleAction::Spectate,
+            deadzone: Deg(1.0),
+        }
+    }
+
+    fn move_cmd(yaw: f32, fwd_move: i16, button_flags: ButtonFlags) -> ClientCmd {
+        ClientCmd::Move {
+            send_time: Duration::zero(),
+            angles: Vector3::new(Deg(0.0), Deg(yaw), Deg(0.0)),
+            fwd_move,
+            side_move: 0,
+            up_move: 0,
+            button_flags,
+            impulse: 0,
+        }
+    }
+
+    #[test]
+    fn test_from_cvars() {
+        let cvars = CvarRegistry::new();
+        cvars.register("sv_idlelimit", "0", "").unwrap();
+        cvars.register("sv_idleaction", "1", "").unwrap();
+        cvars.register("sv_idledeadzone", "2", "").unwrap();
+        assert_eq!(IdleSettings::from_cvars(&cvars), None);
+
+        cvars.set("sv_idlelimit", "90").unwrap();
+        assert_eq!(
+            IdleSettings::from_cvars(&cvars),
+            Some(IdleSettings {
+                limit: Duration::seconds(90),
+                action: IdleAction::Drop,
+                deadzone: Deg(2.0),
+            })
+        );
+    }
+
+    #[test]
+    fn test_warn_then_act() {
+        let s = settings();
+        let mut monitor = IdleMonitor::new(Duration::zero());
+
+        assert_eq!(monitor.update(Duration::seconds(49), &s), None);
+        assert_eq!(
+            monitor.update(Duration::seconds(50), &s),
+            Some(IdleEvent::Warn {
+                remaining: Duration::seconds(10)
+            })
+        );
+        assert_eq!(monitor.update(Duration::seconds(55), &s), None);
+        assert_eq!(
+            monitor.update(Duration::seconds(60), &s),
+            Some(IdleEvent::Act(IdleAction::Spectate))
+        );
+        assert!(monitor.acted());
+        assert_eq!(monitor.update(Duration::seconds(120), &s), None);
+
+        // input returns the player to the game and starts the count again
+        let cmd = move_cmd(0.0, 400, ButtonFlags::empty());
+        assert!(monitor.record(Duration::seconds(121), &cmd, s.deadzone));
+        assert!(!monitor.acted());
+        assert_eq!(monitor.update(Duration::seconds(170), &s), None);
+    }
+
+    #[test]
+    fn test_deadzone() {
+        let s = settings();
+        let mut monitor = IdleMonitor::new(Duration::zero());
+        let time = Duration::seconds;
+
+        // jitter around the resting angle, including across the wrap at 0, isn't input
+        for (i, yaw) in [0.0, 0.5, -0.5, 359.5, 0.9, 0.0].iter().enumerate() {
+            let cmd = move_cmd(*yaw, 10, ButtonFlags::empty());
+            assert!(!monitor.record(time(i as i64), &cmd, s.deadzone));
+        }
+        assert_eq!(monitor.idle_time(time(10)), time(10));
+
+        // a real turn, a button press and its release are
+        let turn = move_cmd(5.0, 0, ButtonFlags::empty());
+        assert!(monitor.record(time(11), &turn, s.deadzone));
+        let attack = move_cmd(5.0, 0, ButtonFlags::ATTACK);
+        assert!(monitor.record(time(12), &attack, s.deadzone));
+        assert!(!monitor.record(time(13), &attack, s.deadzone));
+        let release = move_cmd(5.0, 0, ButtonFlags::empty());
+        assert!(monitor.record(time(14), &release, s.deadzone));
+
+        let say = ClientCmd::StringCmd {
+            cmd: "say brb".to_owned(),
+        };
+        assert!(monitor.record(time(15), &say, s.deadzone));
+        let ping = ClientCmd::StringCmd {
+            cmd: "ping".to_owned(),
+        };
+        assert!(!monitor.record(time(16), &ping, s.deadzone));
+        assert_eq!(monitor.idle_time(time(16)), time(1));
+    }
+}
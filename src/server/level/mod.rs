@@ -0,0 +1,1410 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! A running level.
+//!
+//! A `Level` ties together the pieces of the server that live for one map: the world and its
+//! entities, the progs that run them and the `Server` state shared with the progs. It loads the
+//! map, runs each server frame and handles the connection and signon of clients, in the same
+//! order as the original engine so that the unmodified game logic behaves as expected.
+
+mod user;
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    error::Error,
+    io::{self, Read},
+    mem,
+    net::SocketAddr,
+    rc::Rc,
+};
+
+use crate::{
+    common::{
+        bsp,
+        console::CvarRegistry,
+        engine, frustum, mapconfig,
+        math::Angles,
+        net::{
+            self, loopback::SimHost, EntityState, GameType, ItemFlags, PlayerColor, QSocket,
+            ServerCmd, SignOnStage, MAX_MESSAGE,
+        },
+        parse, random,
+        vfs::{Vfs, VfsError},
+    },
+    server::{
+        multicast::ClientMessages,
+        priority::{Candidate, Relevance},
+        progs::{
+            self, EntityId, ExecutionContext, FunctionId, Functions, GlobalAddrEntity,
+            GlobalAddrFloat, GlobalAddrFunction, GlobalAddrString, Globals, ProgsError,
+        },
+        protocol::{self, MapRequirements},
+        save::{self, AutosaveTimer, SaveError, SaveGame, NUM_SPAWN_PARMS},
+        world::{
+            EntityFlags, FieldAddrEntityId, FieldAddrFloat, FieldAddrStringId, FieldAddrVector,
+            PhysicsContext, World,
+        },
+        ClientInGame, ClientSlot, Server, ServerFlags, ServerStatics, MAX_DATAGRAM,
+    },
+};
+
+use cgmath::{Deg, InnerSpace, Vector3};
+use chrono::Duration;
+use thiserror::Error;
+
+// entities are given two frames of this length to settle before clients see them
+const SETTLE_FRAME_MS: i64 = 100;
+
+// entities with these spawnflags are left out at each skill level or in deathmatch
+const SPAWNFLAG_NOT_EASY: i32 = 256;
+const SPAWNFLAG_NOT_MEDIUM: i32 = 512;
+const SPAWNFLAG_NOT_HARD: i32 = 1024;
+const SPAWNFLAG_NOT_DEATHMATCH: i32 = 2048;
+
+// how often a client that is still signing on is sent a message to keep its connection open
+const KEEPALIVE_SECS: i64 = 5;
+
+// the model given to client entities, which have no model until they are put in the game
+const PLAYER_MODEL: &str = "progs/player.mdl";
+
+#[derive(Error, Debug)]
+pub enum LevelError {
+    #[error("Couldn't load {0}: {1}")]
+    Load(String, String),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Progs error: {0}")]
+    Progs(#[from] ProgsError),
+    #[error("{0}")]
+    Save(#[from] SaveError),
+    #[error("{0}")]
+    CantSave(&'static str),
+}
+
+impl From<VfsError> for LevelError {
+    fn from(error: VfsError) -> Self {
+        match error {
+            VfsError::NoSuchFile(path) => LevelError::Load(path, String::from("no such file")),
+            e => LevelError::Load(String::from("file"), e.to_string()),
+        }
+    }
+}
+
+/// A connected client, as listed by the `status` command and server browsers.
+#[derive(Clone, Debug)]
+pub struct ClientStatus {
+    pub entity_id: EntityId,
+    pub name: String,
+    pub colors: PlayerColor,
+    pub frags: i32,
+    pub address: SocketAddr,
+}
+
+pub struct Level {
+    vfs: Rc<Vfs>,
+    map_name: String,
+    server: Server,
+    world: Rc<RefCell<World>>,
+    execution_context: ExecutionContext,
+    globals: Globals,
+
+    // multicast messages routed to each spawned client, kept between frames to reuse the buffers
+    multicast: Vec<ClientMessages>,
+
+    // the state of each entity as of the last frame, indexed by entity ID
+    entity_changes: Vec<Option<EntityChange>>,
+
+    // the skill the level was started at
+    skill: i32,
+
+    // if true, the level was restored from a saved game, which already has the player in it
+    loaded_game: bool,
+
+    autosave: AutosaveTimer,
+}
+
+// how long an entity has gone without changing, which makes its updates less urgent
+struct EntityChange {
+    state: EntityState,
+    frames_since_change: u32,
+}
+
+impl Level {
+    /// Loads the map `maps/<map_name>.bsp` and spawns its entities.
+    ///
+    /// Clients already in `statics` are sent the new level, and will sign on again once they have
+    /// loaded it.
+    pub fn spawn(
+        vfs: Rc<Vfs>,
+        cvars: &mut CvarRegistry,
+        map_name: &str,
+        statics: ServerStatics,
+    ) -> Result<Level, LevelError> {
+        info!("Spawning server for {}", map_name);
+
+        let mut progs_data = Vec::new();
+        vfs.open("progs.dat")?.read_to_end(&mut progs_data)?;
+        let (execution_context, globals, type_def, string_table) = progs::load(&progs_data)?;
+
+        let map_path = format!("maps/{}.bsp", map_name);
+        let (brush_models, entity_text) = bsp::load(vfs.open(&map_path)?)
+            .map_err(|e| LevelError::Load(map_path.clone(), e.to_string()))?;
+
+        let mut server = Server::new(string_table.clone(), statics);
+        server.seed_rng(random::game_seed(cvars));
+
+        // the world and its submodels are precached first, in the same order as the world's
+        // models
+        let map_path_id = string_table.insert(&map_path);
+        server.precache_model(map_path_id);
+        for model in brush_models.iter().skip(1) {
+            server.precache_model(string_table.insert(model.name()));
+        }
+
+        let maps = match parse::entities(&entity_text) {
+            Ok((_, maps)) => maps,
+            Err(e) => {
+                return Err(LevelError::Load(
+                    map_path,
+                    format!("couldn't parse entities: {}", e),
+                ))
+            }
+        };
+
+        // every message is encoded for the protocol, so it has to be chosen before anything is
+        // spawned
+        let requirements = MapRequirements::measure(
+            &brush_models,
+            &server.model_precache,
+            &server.sound_precache,
+            maps.len(),
+        );
+        let protocol =
+            protocol::select_protocol(protocol::preferred_protocol(cvars), &requirements);
+        info!("Using protocol {}", protocol.version());
+        server.set_protocol(protocol);
+
+        // the map's own settings, such as its gravity, have to be in place before anything moves.
+        // its script is run by the host once the level is up.
+        let map_config = &mut server.statics.map_config;
+        map_config.begin(cvars);
+        if let Some(worldspawn) = maps
+            .iter()
+            .find(|ent| ent.get("classname") == Some(&"worldspawn"))
+        {
+            map_config.apply_worldspawn(cvars, worldspawn);
+        }
+        let mut map_script = String::new();
+        if let Ok(mut file) = vfs.open(&mapconfig::script_path(&map_path)) {
+            let mut text = String::new();
+            if file.read_to_string(&mut text).is_ok() {
+                map_script = map_config.apply_script(cvars, &text);
+            }
+        }
+        server.queue_local_cmd(map_script);
+
+        let max_clients = server.max_clients();
+        let mut world = World::create(brush_models, type_def, string_table.clone(), max_clients)?;
+        world
+            .try_get_entity_mut(EntityId(0))?
+            .put_string_id(map_path_id, FieldAddrStringId::ModelName as i16)
+            .map_err(ProgsError::from)?;
+
+        let mut level = Level {
+            vfs,
+            map_name: map_name.to_owned(),
+            server,
+            world: Rc::new(RefCell::new(world)),
+            execution_context,
+            globals,
+            multicast: Vec::new(),
+            entity_changes: Vec::new(),
+            skill: 0,
+            loaded_game: false,
+            autosave: AutosaveTimer::new(),
+        };
+
+        let (deathmatch, skill) = level.set_game_globals(cvars)?;
+        level.skill = skill;
+        level.load_entities(cvars, maps, deathmatch, skill)?;
+
+        // give doors and items a chance to drop to the floor before anyone sees them
+        for _ in 0..2 {
+            level.physics(cvars, Duration::milliseconds(SETTLE_FRAME_MS))?;
+        }
+
+        level.create_baselines()?;
+
+        // clients are sent the whole state of the level when they spawn, so anything broadcast
+        // while loading is redundant
+        level.server.clear_datagram();
+        level.server.clear_reliable_datagram();
+        level.server.route_multicast(&*level.world.borrow(), &[]);
+
+        for e_id in level.client_ids() {
+            level.send_server_info(e_id)?;
+        }
+
+        // the first autosave is written once the player has entered the new level
+        level.autosave.level_started();
+
+        info!("Server spawned");
+        Ok(level)
+    }
+
+    /// Returns the name of the map, e.g. `e1m1`.
+    pub fn map_name(&self) -> &str {
+        &self.map_name
+    }
+
+    pub fn world(&self) -> Rc<RefCell<World>> {
+        self.world.clone()
+    }
+
+    pub fn functions(&self) -> Rc<Functions> {
+        self.execution_context.functions().clone()
+    }
+
+    pub fn max_clients(&self) -> usize {
+        self.server.max_clients()
+    }
+
+    /// Returns the number of connected clients, including those still signing on.
+    pub fn client_count(&self) -> usize {
+        self.client_ids().len()
+    }
+
+    /// Returns the entity IDs of the connected clients.
+    pub fn client_ids(&self) -> Vec<EntityId> {
+        (1..=self.server.max_clients())
+            .map(EntityId)
+            .filter(|e_id| self.server.client_active(*e_id))
+            .collect()
+    }
+
+    /// Returns the status of each connected client, in slot order.
+    pub fn client_statuses(&self) -> Result<Vec<ClientStatus>, ProgsError> {
+        let world = self.world.borrow();
+        let mut statuses = Vec::new();
+        for e_id in self.client_ids() {
+            let client = match self.server.client(e_id) {
+                Some(c) => c,
+                None => continue,
+            };
+
+            statuses.push(ClientStatus {
+                entity_id: e_id,
+                name: client.name.clone(),
+                colors: client.colors,
+                frags: world.get_float(e_id, FieldAddrFloat::Frags)? as i32,
+                address: client.connection.remote(),
+            });
+        }
+
+        Ok(statuses)
+    }
+
+    /// Returns the console commands queued by the progs since the last call, such as
+    /// `changelevel`.
+    pub fn take_local_cmds(&mut self) -> String {
+        self.server.take_local_cmds()
+    }
+
+    /// Puts a newly connected client in the first free slot and starts its signon.
+    ///
+    /// Returns `None` if every slot is taken.
+    pub fn connect_client(
+        &mut self,
+        cvars: &mut CvarRegistry,
+        connection: QSocket,
+    ) -> Result<Option<EntityId>, ProgsError> {
+        let slot_id = match self
+            .server
+            .statics
+            .client_slots
+            .iter()
+            .position(|s| match s {
+                ClientSlot::Disconnected => true,
+                ClientSlot::InGame(_) => false,
+            }) {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+
+        let e_id = EntityId(slot_id + 1);
+        let mut client = ClientInGame::new(e_id, connection, self.server.time());
+        client.spawn_parms = self.new_spawn_parms(cvars)?;
+        self.server.statics.client_slots[slot_id] = ClientSlot::InGame(client);
+
+        info!("Client {} connected", e_id.0);
+        self.send_server_info(e_id)?;
+
+        Ok(Some(e_id))
+    }
+
+    /// Disconnects a client, running the progs' `ClientDisconnect` if its player was in the game.
+    ///
+    /// Unless `crash` is set, the client is told that it has been disconnected.
+    pub fn drop_client(
+        &mut self,
+        cvars: &mut CvarRegistry,
+        e_id: EntityId,
+        crash: bool,
+    ) -> Result<(), ProgsError> {
+        let spawned = match self.server.client_mut(e_id) {
+            Some(client) => {
+                if !crash {
+                    // the client won't be around to acknowledge a reliable message
+                    let mut msg = Vec::new();
+                    ServerCmd::Disconnect.serialize(&mut msg).unwrap();
+                    if let Err(e) = client.connection.send_msg_unreliable(&msg) {
+                        debug!("Couldn't send disconnect to client {}: {}", e_id.0, e);
+                    }
+                }
+
+                client.spawned
+            }
+
+            None => return Ok(()),
+        };
+
+        if spawned {
+            let saved_self = self.globals.get_entity_id(GlobalAddrEntity::Self_ as i16)?;
+            self.globals
+                .put_entity_id(e_id, GlobalAddrEntity::Self_ as i16)?;
+            let f = self
+                .globals
+                .get_function_id(GlobalAddrFunction::ClientDisconnect as i16)?;
+            self.execute(cvars, f)?;
+            self.globals
+                .put_entity_id(saved_self, GlobalAddrEntity::Self_ as i16)?;
+        }
+
+        let slot_id = e_id.0 - 1;
+        if let ClientSlot::InGame(client) = mem::replace(
+            &mut self.server.statics.client_slots[slot_id],
+            ClientSlot::Disconnected,
+        ) {
+            info!("Client {} removed", client.name);
+        }
+
+        // the slot is empty until someone else connects
+        let player_id = slot_id as u8;
+        self.server.broadcast(&ServerCmd::UpdateName {
+            player_id,
+            new_name: String::new(),
+        });
+        self.server.broadcast(&ServerCmd::UpdateFrags {
+            player_id,
+            new_frags: 0,
+        });
+        self.server.broadcast(&ServerCmd::UpdateColors {
+            player_id,
+            new_colors: PlayerColor::from_bits(0),
+        });
+
+        Ok(())
+    }
+
+    /// Runs one server frame: reads input from each client, runs the physics of every entity
+    /// and sends each client the new state of the world.
+    ///
+    /// An error means the game can't continue, and the level should be shut down.
+    pub fn frame(
+        &mut self,
+        cvars: &mut CvarRegistry,
+        frame_time: Duration,
+    ) -> Result<(), ProgsError> {
+        self.server.clear_datagram();
+
+        self.run_clients(cvars, frame_time)?;
+        self.physics(cvars, frame_time)?;
+        self.send_client_messages(cvars)?;
+
+        Ok(())
+    }
+
+    /// Ends the level for a move to `map_name`, keeping the connected clients.
+    ///
+    /// Each client's spawn parameters are saved with the progs' `SetChangeParms`, and the client
+    /// is told to load the new level.
+    pub fn change_level(
+        mut self,
+        cvars: &mut CvarRegistry,
+        map_name: &str,
+    ) -> Result<Level, LevelError> {
+        self.save_spawn_parms(cvars)?;
+
+        let vfs = self.vfs.clone();
+        Level::spawn(vfs, cvars, map_name, self.server.into_statics())
+    }
+
+    /// Records the state of a single-player game.
+    ///
+    /// As in the original engine, multiplayer games and games whose player is dead can't be saved.
+    pub fn save_game(&self) -> Result<SaveGame, LevelError> {
+        let spawn_parms = self.check_can_save()?;
+
+        let world = self.world.borrow();
+        let message_id = world
+            .try_get_entity(EntityId(0))?
+            .get_string_id(FieldAddrStringId::Message as i16)
+            .map_err(ProgsError::from)?;
+        let kills = self
+            .globals
+            .get_float(GlobalAddrFloat::KilledMonsters as i16)
+            .map_err(ProgsError::from)?;
+        let total_kills = self
+            .globals
+            .get_float(GlobalAddrFloat::TotalMonsters as i16)
+            .map_err(ProgsError::from)?;
+        let comment = save::save_comment(
+            self.server.string_table.get(message_id).unwrap_or_default(),
+            kills as i32,
+            total_kills as i32,
+        );
+
+        let mut save = SaveGame::new(
+            comment,
+            self.map_name.clone(),
+            self.skill,
+            self.server.time(),
+            spawn_parms,
+        );
+        save.capture(
+            &self.server,
+            &world,
+            &self.globals,
+            self.execution_context.functions(),
+        )?;
+
+        Ok(save)
+    }
+
+    /// Returns a saved game if an autosave is due this frame.
+    ///
+    /// Autosaves are controlled by `sv_autosave` and `sv_autosave_interval`. The caller is
+    /// responsible for writing the save to the next autosave slot.
+    pub fn autosave(&mut self, cvars: &CvarRegistry) -> Option<SaveGame> {
+        if cvars.get_value("sv_autosave").unwrap_or(1.0) == 0.0 {
+            return None;
+        }
+
+        let interval =
+            engine::duration_from_f32(cvars.get_value("sv_autosave_interval").unwrap_or(300.0));
+        let can_save = self.check_can_save().is_ok();
+        if !self.autosave.check(self.server.time(), interval, can_save) {
+            return None;
+        }
+
+        match self.save_game() {
+            Ok(save) => Some(save),
+            Err(e) => {
+                warn!("Autosave failed: {}", e);
+                None
+            }
+        }
+    }
+
+    // returns the player's spawn parameters if the game can be saved
+    fn check_can_save(&self) -> Result<[f32; NUM_SPAWN_PARMS], LevelError> {
+        if self.server.max_clients() != 1 {
+            return Err(LevelError::CantSave("Can't save multiplayer games"));
+        }
+
+        let player_id = EntityId(1);
+        let spawn_parms = match self.server.client(player_id) {
+            Some(c) if c.spawned => c.spawn_parms,
+            _ => {
+                return Err(LevelError::CantSave(
+                    "Can't save without a player in the game",
+                ))
+            }
+        };
+
+        if self
+            .world
+            .borrow()
+            .get_float(player_id, FieldAddrFloat::Health)?
+            <= 0.0
+        {
+            return Err(LevelError::CantSave("Can't savegame with a dead player"));
+        }
+
+        Ok(spawn_parms)
+    }
+
+    /// Loads the level of a saved game and restores its state.
+    ///
+    /// The game is paused until the client in the first slot spawns and takes over the saved
+    /// player.
+    pub fn restore_game(
+        vfs: Rc<Vfs>,
+        cvars: &mut CvarRegistry,
+        save: &SaveGame,
+        statics: ServerStatics,
+    ) -> Result<Level, LevelError> {
+        if let Err(e) = cvars.set("skill", save.skill.to_string().as_str()) {
+            warn!("Couldn't set skill: {}", e);
+        }
+
+        // the entities are spawned as usual first, so that everything they precache has the
+        // same index as when the game was saved
+        let mut level = Level::spawn(vfs, cvars, &save.map, statics)?;
+        {
+            let functions = level.execution_context.functions().clone();
+            let mut world = level.world.borrow_mut();
+            save.restore(
+                &mut level.server,
+                &mut world,
+                &mut level.globals,
+                &functions,
+            )?;
+        }
+
+        level.server.time = save.time;
+        if let Some(client) = level.server.client_mut(EntityId(1)) {
+            client.spawn_parms = save.spawn_parms;
+        }
+        level.server.paused = true;
+        level.loaded_game = true;
+
+        Ok(level)
+    }
+
+    /// Ends the level and loads a saved game, keeping the connected clients.
+    pub fn load_game(
+        mut self,
+        cvars: &mut CvarRegistry,
+        save: &SaveGame,
+    ) -> Result<Level, LevelError> {
+        self.send_reconnect();
+
+        let vfs = self.vfs.clone();
+        Level::restore_game(vfs, cvars, save, self.server.into_statics())
+    }
+
+    /// Ends the level, disconnecting every client.
+    pub fn shutdown(mut self, cvars: &mut CvarRegistry) {
+        for e_id in self.client_ids() {
+            if let Err(e) = self.drop_client(cvars, e_id, false) {
+                warn!("Error while dropping client {}: {}", e_id.0, e);
+            }
+        }
+
+        self.server.statics.map_config.restore(cvars);
+    }
+
+    /// Records the cvars set by the map's script, which should be called once the host has run
+    /// the commands from `take_local_cmds`.
+    pub fn settle_map_config(&mut self, cvars: &CvarRegistry) {
+        self.server.statics.map_config.settle(cvars);
+    }
+
+    // saves the state carried into the next level and tells each client to reconnect
+    fn save_spawn_parms(&mut self, cvars: &mut CvarRegistry) -> Result<(), ProgsError> {
+        let server_flags = self
+            .globals
+            .get_float(GlobalAddrFloat::ServerFlags as i16)?;
+        self.server.statics.server_flags = ServerFlags::from_bits_truncate(server_flags as i32);
+
+        for e_id in self.client_ids() {
+            // players who never made it into the game keep the parms they connected with
+            if self.server.client(e_id).map_or(false, |c| c.spawned) {
+                self.globals
+                    .put_entity_id(e_id, GlobalAddrEntity::Self_ as i16)?;
+                let f = self
+                    .globals
+                    .get_function_id(GlobalAddrFunction::SetChangeArgs as i16)?;
+                self.execute(cvars, f)?;
+                let spawn_parms = self.read_spawn_parms()?;
+                if let Some(client) = self.server.client_mut(e_id) {
+                    client.spawn_parms = spawn_parms;
+                }
+            }
+        }
+
+        self.send_reconnect();
+        Ok(())
+    }
+
+    // tells each client to reconnect for a new level
+    fn send_reconnect(&mut self) {
+        for e_id in self.client_ids() {
+            if let Some(client) = self.server.client_mut(e_id) {
+                // the reconnect is sent on its own, so that the client has acted on it by the
+                // time the new level arrives
+                ServerCmd::StuffText {
+                    text: String::from("reconnect\n"),
+                }
+                .serialize(&mut client.message)
+                .unwrap();
+
+                if client.connection.can_send() {
+                    if let Err(e) = client.connection.begin_send_msg(&client.message) {
+                        warn!("Couldn't send reconnect to client {}: {}", e_id.0, e);
+                    }
+                    client.message.clear();
+                }
+            }
+        }
+    }
+
+    fn execute(&mut self, cvars: &mut CvarRegistry, f: FunctionId) -> Result<(), ProgsError> {
+        let mut world = self.world.borrow_mut();
+        self.execution_context.execute_program(
+            &mut self.globals,
+            &mut world,
+            cvars,
+            &mut self.server,
+            &self.vfs,
+            f,
+        )
+    }
+
+    fn physics(
+        &mut self,
+        cvars: &mut CvarRegistry,
+        frame_time: Duration,
+    ) -> Result<(), ProgsError> {
+        self.globals.put_float(
+            engine::duration_to_f32(frame_time),
+            GlobalAddrFloat::FrameTime as i16,
+        )?;
+
+        let mut ctx = PhysicsContext {
+            execution_context: &mut self.execution_context,
+            globals: &mut self.globals,
+            cvars,
+            server: &mut self.server,
+            vfs: &self.vfs,
+        };
+
+        self.world.borrow_mut().physics(&mut ctx, frame_time)
+    }
+
+    // sets the globals describing the game, returning whether this is a deathmatch and the skill
+    fn set_game_globals(&mut self, cvars: &mut CvarRegistry) -> Result<(bool, i32), ProgsError> {
+        let skill = (cvars.get_value("skill").unwrap_or(1.0) + 0.5)
+            .floor()
+            .max(0.0)
+            .min(3.0);
+        if let Err(e) = cvars.set("skill", skill.to_string().as_str()) {
+            warn!("Couldn't set skill: {}", e);
+        }
+
+        // coop takes precedence over deathmatch
+        let coop = cvars.get_value("coop").unwrap_or(0.0);
+        let deathmatch = if coop != 0.0 {
+            0.0
+        } else {
+            cvars.get_value("deathmatch").unwrap_or(0.0)
+        };
+        let teamplay = cvars.get_value("teamplay").unwrap_or(0.0);
+
+        let map_name_id = self.server.string_table.insert(&self.map_name);
+        self.globals
+            .put_string_id(map_name_id, GlobalAddrString::MapName as i16)?;
+        self.globals.put_float(coop, GlobalAddrFloat::Coop as i16)?;
+        self.globals
+            .put_float(deathmatch, GlobalAddrFloat::Deathmatch as i16)?;
+        self.globals
+            .put_float(teamplay, GlobalAddrFloat::TeamPlay as i16)?;
+        self.globals.put_float(
+            self.server.statics.server_flags.bits() as f32,
+            GlobalAddrFloat::ServerFlags as i16,
+        )?;
+        self.globals.put_float(
+            engine::duration_to_f32(self.server.time()),
+            GlobalAddrFloat::Time as i16,
+        )?;
+
+        Ok((deathmatch != 0.0, skill as i32))
+    }
+
+    // spawns the entities described by the map, the first of which is the world itself
+    fn load_entities(
+        &mut self,
+        cvars: &mut CvarRegistry,
+        maps: Vec<HashMap<&str, &str>>,
+        deathmatch: bool,
+        skill: i32,
+    ) -> Result<(), ProgsError> {
+        let mut world = self.world.borrow_mut();
+        let mut inhibited = 0;
+        for (i, map) in maps.into_iter().enumerate() {
+            let classname = match map.get("classname") {
+                Some(c) => c.to_string(),
+                None => {
+                    warn!("No classname for entity {}", i);
+                    continue;
+                }
+            };
+
+            if i > 0 && spawn_inhibited(spawnflags(&map), deathmatch, skill) {
+                inhibited += 1;
+                continue;
+            }
+
+            if self
+                .execution_context
+                .functions()
+                .find_function_by_name(&classname)
+                .is_err()
+            {
+                warn!("No spawn function for {}", classname);
+                continue;
+            }
+
+            if i == 0 {
+                world.load_entity_from_map(EntityId(0), &map)?;
+                self.globals
+                    .put_entity_id(EntityId(0), GlobalAddrEntity::Self_ as i16)?;
+                self.execution_context.execute_program_by_name(
+                    &mut self.globals,
+                    &mut world,
+                    cvars,
+                    &mut self.server,
+                    &self.vfs,
+                    classname,
+                )?;
+            } else {
+                world.spawn_entity_from_map(
+                    &mut self.execution_context,
+                    &mut self.globals,
+                    cvars,
+                    &mut self.server,
+                    map,
+                    &self.vfs,
+                )?;
+            }
+        }
+
+        debug!("{} entities inhibited", inhibited);
+        Ok(())
+    }
+
+    // records the initial state of every visible entity, which updates are sent relative to
+    fn create_baselines(&mut self) -> Result<(), ProgsError> {
+        let player_model = self.server.model_precache_index(PLAYER_MODEL).unwrap_or(0);
+        let mut world = self.world.borrow_mut();
+        let max_clients = world.max_clients();
+
+        let mut next = Some(EntityId(0));
+        while let Some(e_id) = next {
+            next = world.next_entity(e_id);
+
+            let is_client = e_id.0 > 0 && e_id.0 <= max_clients;
+            if !is_client && world.try_get_entity(e_id)?.model_index()? == 0 {
+                continue;
+            }
+
+            let mut baseline = world.entity_state(e_id)?;
+            if is_client {
+                baseline.colormap = e_id.0 as u8;
+                baseline.model_id = player_model;
+            } else {
+                baseline.colormap = 0;
+            }
+
+            self.server.add_signon_cmd(ServerCmd::SpawnBaseline {
+                ent_id: e_id.0 as u16,
+                model_id: baseline.model_id as u8,
+                frame_id: baseline.frame_id as u8,
+                colormap: baseline.colormap,
+                skin_id: baseline.skin_id as u8,
+                origin: baseline.origin,
+                angles: baseline.angles,
+            });
+            world.set_baseline(e_id, baseline)?;
+        }
+
+        Ok(())
+    }
+
+    // runs SetNewParms and returns the spawn parameters it chose for a player joining the game
+    fn new_spawn_parms(
+        &mut self,
+        cvars: &mut CvarRegistry,
+    ) -> Result<[f32; NUM_SPAWN_PARMS], ProgsError> {
+        let f = self
+            .globals
+            .get_function_id(GlobalAddrFunction::SetNewArgs as i16)?;
+        self.execute(cvars, f)?;
+        self.read_spawn_parms()
+    }
+
+    fn read_spawn_parms(&self) -> Result<[f32; NUM_SPAWN_PARMS], ProgsError> {
+        let mut spawn_parms = [0.0; NUM_SPAWN_PARMS];
+        for (i, parm) in spawn_parms.iter_mut().enumerate() {
+            *parm = self
+                .globals
+                .get_float(GlobalAddrFloat::Arg0 as i16 + i as i16)?;
+        }
+
+        Ok(spawn_parms)
+    }
+
+    // sends the first signon message, which tells the client what to load
+    fn send_server_info(&mut self, e_id: EntityId) -> Result<(), ProgsError> {
+        let (message, sounds) = {
+            let world = self.world.borrow();
+            let ent = world.try_get_entity(EntityId(0))?;
+            let message_id = ent.get_string_id(FieldAddrStringId::Message as i16)?;
+            (
+                self.server.string_table.get(message_id).unwrap_or_default(),
+                ent.get_float(FieldAddrFloat::Sounds as i16)?,
+            )
+        };
+
+        let deathmatch = self.globals.get_float(GlobalAddrFloat::Deathmatch as i16)?;
+        let game_type = if deathmatch != 0.0 {
+            GameType::Deathmatch
+        } else {
+            GameType::CoOp
+        };
+
+        let cmds = [
+            ServerCmd::ServerInfo {
+                protocol_version: self.server.protocol().version(),
+                max_clients: self.server.max_clients() as u8,
+                game_type,
+                message,
+                model_precache: self.server.model_precache[1..].to_vec(),
+                sound_precache: self.server.sound_precache[1..].to_vec(),
+            },
+            ServerCmd::CdTrack {
+                track: sounds as u8,
+                loop_: sounds as u8,
+            },
+            ServerCmd::SetView {
+                ent_id: e_id.0 as i16,
+            },
+            ServerCmd::SignOnStage {
+                stage: SignOnStage::Prespawn,
+            },
+        ];
+
+        for cmd in cmds.iter() {
+            self.server.send_to_client(e_id, cmd);
+        }
+
+        if let Some(client) = self.server.client_mut(e_id) {
+            client.spawned = false;
+        }
+
+        Ok(())
+    }
+
+    // sends the datagram for this frame to each client in the game, and any reliable messages
+    // that are ready to go
+    fn send_client_messages(&mut self, cvars: &mut CvarRegistry) -> Result<(), ProgsError> {
+        self.update_frags()?;
+        self.track_entity_changes()?;
+
+        // messages for every client
+        let reliable = self.server.reliable_datagram().to_vec();
+        self.server.clear_reliable_datagram();
+
+        // messages for the clients in range of an event
+        let spawned = self.spawned_client_ids();
+        let mut multicast = mem::take(&mut self.multicast);
+        {
+            let world = self.world.borrow();
+            let mut view_origins = Vec::with_capacity(spawned.len());
+            for e_id in spawned.iter() {
+                view_origins.push(view_origin(&world, *e_id)?);
+            }
+            self.server
+                .route_multicast_into(&*world, &view_origins, &mut multicast);
+        }
+
+        let mut dropped = Vec::new();
+        for e_id in self.client_ids() {
+            let routed = spawned
+                .iter()
+                .position(|id| *id == e_id)
+                .map(|i| &multicast[i]);
+
+            let datagram = match routed {
+                Some(routed) => Some(self.client_datagram(e_id, &routed.unreliable)?),
+                None => None,
+            };
+
+            let client = match self.server.client_mut(e_id) {
+                Some(c) => c,
+                None => continue,
+            };
+
+            client.message.extend_from_slice(&reliable);
+            if let Some(routed) = routed {
+                client.message.extend_from_slice(&routed.reliable);
+            }
+            if client.message.len() > MAX_MESSAGE {
+                warn!("Reliable message overflowed for {}", client.name);
+                dropped.push(e_id);
+                continue;
+            }
+
+            let result = match datagram {
+                Some(datagram) => client.connection.send_msg_unreliable(&datagram),
+
+                // keep a client that is still loading the level from timing out
+                None if client.connection.time_since_send() > Duration::seconds(KEEPALIVE_SECS) => {
+                    let mut msg = Vec::new();
+                    ServerCmd::NoOp.serialize(&mut msg).unwrap();
+                    client.connection.send_msg_unreliable(&msg)
+                }
+
+                None => Ok(()),
+            };
+
+            let result = result.and_then(|_| {
+                if !client.message.is_empty() && client.connection.can_send() {
+                    client.connection.begin_send_msg(&client.message)?;
+                    client.message.clear();
+                }
+
+                client.connection.resend_unacked()
+            });
+
+            if let Err(e) = result {
+                warn!("Lost connection to {}: {}", client.name, e);
+                dropped.push(e_id);
+            }
+        }
+
+        self.multicast = multicast;
+
+        for e_id in dropped {
+            self.drop_client(cvars, e_id, true)?;
+        }
+
+        Ok(())
+    }
+
+    // counts the frames since each entity last changed
+    fn track_entity_changes(&mut self) -> Result<(), ProgsError> {
+        let world = self.world.borrow();
+        let mut changes = Vec::with_capacity(self.entity_changes.len());
+        let mut next = world.next_entity(EntityId(0));
+        while let Some(id) = next {
+            let state = world.entity_state(id)?;
+            let frames_since_change = match self.entity_changes.get(id.0) {
+                Some(Some(old)) if old.state == state => old.frames_since_change + 1,
+                _ => 0,
+            };
+
+            changes.resize_with(id.0 + 1, || None);
+            changes[id.0] = Some(EntityChange {
+                state,
+                frames_since_change,
+            });
+            next = world.next_entity(id);
+        }
+
+        self.entity_changes = changes;
+        Ok(())
+    }
+
+    // returns the entity IDs of the clients whose players are in the game
+    fn spawned_client_ids(&self) -> Vec<EntityId> {
+        self.client_ids()
+            .into_iter()
+            .filter(|e_id| self.server.client(*e_id).map_or(false, |c| c.spawned))
+            .collect()
+    }
+
+    // tells every client about changes to frag counts
+    fn update_frags(&mut self) -> Result<(), ProgsError> {
+        let world = self.world.borrow();
+        for e_id in self.client_ids() {
+            let frags = world
+                .try_get_entity(e_id)?
+                .get_float(FieldAddrFloat::Frags as i16)? as i32;
+
+            let changed = match self.server.client_mut(e_id) {
+                Some(client) if client.old_frags != frags => {
+                    client.old_frags = frags;
+                    true
+                }
+                _ => false,
+            };
+
+            if changed {
+                self.server.broadcast(&ServerCmd::UpdateFrags {
+                    player_id: (e_id.0 - 1) as u8,
+                    new_frags: frags as i16,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // builds the unreliable message describing the world as seen by a client, followed by the
+    // events it was in range of
+    fn client_datagram(&mut self, e_id: EntityId, multicast: &[u8]) -> Result<Vec<u8>, ProgsError> {
+        let mut msg = Vec::with_capacity(MAX_DATAGRAM);
+        ServerCmd::Time {
+            time: engine::duration_to_f32(self.server.time()),
+        }
+        .serialize_with(&mut msg, self.server.protocol().codec())
+        .unwrap();
+
+        self.write_client_data(e_id, &mut msg)?;
+        self.write_entities(e_id, &mut msg)?;
+
+        // the server datagram and events are dropped for this client if they don't fit
+        if msg.len() + self.server.datagram().len() <= MAX_DATAGRAM {
+            msg.extend_from_slice(self.server.datagram());
+        }
+        if msg.len() + multicast.len() <= MAX_DATAGRAM {
+            msg.extend_from_slice(multicast);
+        }
+
+        Ok(msg)
+    }
+
+    // writes the state of a client's own player, which is only sent to that client
+    fn write_client_data(&mut self, e_id: EntityId, msg: &mut Vec<u8>) -> Result<(), ProgsError> {
+        let codec = self.server.protocol().codec();
+        let server_flags = self
+            .globals
+            .get_float(GlobalAddrFloat::ServerFlags as i16)? as u32;
+        let mut world = self.world.borrow_mut();
+
+        // damage taken since the last frame, for the view kick and blend
+        let dmg_take = world.get_float(e_id, FieldAddrFloat::DmgTake)?;
+        let dmg_save = world.get_float(e_id, FieldAddrFloat::DmgSave)?;
+        if dmg_take != 0.0 || dmg_save != 0.0 {
+            let inflictor = world
+                .try_get_entity(e_id)?
+                .get_entity_id(FieldAddrEntityId::DmgInflictor as i16)?;
+            let source_id = if world.entity_exists(inflictor) {
+                inflictor
+            } else {
+                e_id
+            };
+            let source = world.try_get_entity(source_id)?;
+            let source = source.origin()? + 0.5 * (source.min()? + source.max()?);
+
+            ServerCmd::Damage {
+                armor: dmg_save as u8,
+                blood: dmg_take as u8,
+                source,
+            }
+            .serialize_with(msg, codec)
+            .unwrap();
+
+            world.put_float(e_id, FieldAddrFloat::DmgTake, 0.0)?;
+            world.put_float(e_id, FieldAddrFloat::DmgSave, 0.0)?;
+        }
+
+        // the progs turned the player, e.g. on teleporting
+        if world.get_float(e_id, FieldAddrFloat::FixAngle)? != 0.0 {
+            let angles = world.get_vector(e_id, FieldAddrVector::Angles)?;
+            ServerCmd::SetAngle {
+                angles: Vector3::new(Deg(angles.x), Deg(angles.y), Deg(angles.z)),
+            }
+            .serialize_with(msg, codec)
+            .unwrap();
+            world.put_float(e_id, FieldAddrFloat::FixAngle, 0.0)?;
+        }
+
+        let ent = world.try_get_entity(e_id)?;
+        let get_float = |field: FieldAddrFloat| ent.get_float(field as i16);
+        let nonzero = |value: f32| if value != 0.0 { Some(value) } else { None };
+
+        let view_height = ent.get_vector(FieldAddrVector::ViewOffset as i16)?[2];
+        let punch = ent.get_vector(FieldAddrVector::PunchAngle as i16)?;
+        let velocity = ent.get_vector(FieldAddrVector::Velocity as i16)?;
+        let items = get_float(FieldAddrFloat::Items)? as u32 | server_flags << 28;
+        let weapon_model_id = ent.get_string_id(FieldAddrStringId::WeaponModelName as i16)?;
+        let weapon_model = self
+            .server
+            .string_table
+            .get(weapon_model_id)
+            .unwrap_or_default();
+
+        ServerCmd::ClientData {
+            view_height: if view_height != net::DEFAULT_VIEWHEIGHT {
+                Some(view_height)
+            } else {
+                None
+            },
+            ideal_pitch: nonzero(get_float(FieldAddrFloat::IdealPitch)?).map(Deg),
+            punch_pitch: nonzero(punch[0]).map(Deg),
+            velocity_x: nonzero(velocity[0]),
+            punch_yaw: nonzero(punch[1]).map(Deg),
+            velocity_y: nonzero(velocity[1]),
+            punch_roll: nonzero(punch[2]).map(Deg),
+            velocity_z: nonzero(velocity[2]),
+            items: ItemFlags::from_bits_truncate(items),
+            on_ground: ent.flags()?.contains(EntityFlags::ON_GROUND),
+            in_water: get_float(FieldAddrFloat::WaterLevel)? >= 2.0,
+            weapon_frame: nonzero(get_float(FieldAddrFloat::WeaponFrame)?).map(|f| f as u8),
+            armor: nonzero(get_float(FieldAddrFloat::ArmorValue)?).map(|a| a as u8),
+            weapon: Some(self.server.model_precache_index(&weapon_model).unwrap_or(0) as u8),
+            health: get_float(FieldAddrFloat::Health)? as i16,
+            ammo: get_float(FieldAddrFloat::CurrentAmmo)? as u8,
+            ammo_shells: get_float(FieldAddrFloat::AmmoShells)? as u8,
+            ammo_nails: get_float(FieldAddrFloat::AmmoNails)? as u8,
+            ammo_rockets: get_float(FieldAddrFloat::AmmoRockets)? as u8,
+            ammo_cells: get_float(FieldAddrFloat::AmmoCells)? as u8,
+            active_weapon: get_float(FieldAddrFloat::Weapon)? as u8,
+        }
+        .serialize_with(msg, codec)
+        .unwrap();
+
+        Ok(())
+    }
+
+    // writes updates for the entities a client can potentially see, the most important first
+    // if they don't all fit in the datagram
+    fn write_entities(&mut self, e_id: EntityId, msg: &mut Vec<u8>) -> Result<(), ProgsError> {
+        let world = self.world.borrow();
+        let view = view_origin(&world, e_id)?;
+        let pvs = world.pvs_at(view);
+        let mut visible = world.visible_entities(&pvs)?;
+
+        // the client's own entity is always sent, even if it is outside the world
+        if !visible.contains(&e_id) {
+            visible.push(e_id);
+        }
+
+        let (forward, _, _) = frustum::view_vectors(to_angles(
+            world.get_vector(e_id, FieldAddrVector::ViewAngle)?,
+        ));
+
+        let codec = self.server.protocol().codec();
+        let mut updates = HashMap::new();
+        let mut candidates = Vec::new();
+        for id in visible {
+            let ent = world.try_get_entity(id)?;
+            if id != e_id && ent.model_index()? == 0 {
+                continue;
+            }
+
+            let mut update = Vec::new();
+            ServerCmd::FastUpdate(world.entity_update(id)?)
+                .serialize_with(&mut update, codec)
+                .unwrap();
+
+            // brush entities are positioned by their bounds, their origin is usually the world's
+            let center = (ent.abs_min()? + ent.abs_max()?) / 2.0;
+            let offset = center - view;
+            let relevance = if id == e_id || ent.owner()? == e_id {
+                Relevance::Owned
+            } else if offset.dot(forward) > 0.0 {
+                Relevance::Visible
+            } else {
+                Relevance::Background
+            };
+
+            candidates.push(Candidate {
+                entity_id: id.0,
+                distance: offset.magnitude(),
+                frames_since_change: match self.entity_changes.get(id.0) {
+                    Some(Some(change)) => change.frames_since_change,
+                    _ => 0,
+                },
+                relevance,
+                size: update.len(),
+            });
+            updates.insert(id.0, update);
+        }
+
+        let budget = MAX_DATAGRAM.saturating_sub(msg.len());
+        let selected = match self.server.client_mut(e_id) {
+            Some(client) => client.priority.select(&candidates, budget),
+            None => return Ok(()),
+        };
+
+        if selected.len() < candidates.len() {
+            debug!(
+                "Datagram overflowed for client {}, sent {} of {} entities",
+                e_id.0,
+                selected.len(),
+                candidates.len()
+            );
+        }
+
+        for id in selected {
+            msg.extend_from_slice(&updates[&id]);
+        }
+
+        Ok(())
+    }
+}
+
+fn to_angles(v: Vector3<f32>) -> Angles {
+    Angles {
+        pitch: Deg(v.x),
+        yaw: Deg(v.y),
+        roll: Deg(v.z),
+    }
+}
+
+// the point a player views the world from
+fn view_origin(world: &World, e_id: EntityId) -> Result<Vector3<f32>, ProgsError> {
+    let ent = world.try_get_entity(e_id)?;
+    Ok(ent.origin()? + Vector3::from(ent.get_vector(FieldAddrVector::ViewOffset as i16)?))
+}
+
+// reads an entity's spawnflags from the map, which are zero if absent
+fn spawnflags(map: &HashMap<&str, &str>) -> i32 {
+    map.get("spawnflags")
+        .and_then(|s| s.parse::<f32>().ok())
+        .unwrap_or(0.0) as i32
+}
+
+// returns true if an entity's spawnflags leave it out at this skill level or in deathmatch
+fn spawn_inhibited(spawnflags: i32, deathmatch: bool, skill: i32) -> bool {
+    if deathmatch {
+        return spawnflags & SPAWNFLAG_NOT_DEATHMATCH != 0;
+    }
+
+    let flag = match skill {
+        0 => SPAWNFLAG_NOT_EASY,
+        1 => SPAWNFLAG_NOT_MEDIUM,
+        _ => SPAWNFLAG_NOT_HARD,
+    };
+
+    spawnflags & flag != 0
+}
+
+/// A level and its console variables, run as the server of a loopback `Simulation`.
+pub struct SimServer {
+    pub level: Level,
+    pub cvars: CvarRegistry,
+}
+
+impl SimHost for SimServer {
+    fn frame(&mut self, _time: Duration, frame_time: Duration) -> Result<(), Box<dyn Error>> {
+        self.level.frame(&mut self.cvars, frame_time)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        common::net::loopback::{script::ScriptedClient, LoopbackNetwork, Simulation},
+        server::fixture,
+    };
+
+    fn ms(n: i64) -> Duration {
+        Duration::milliseconds(n)
+    }
+
+    // the fixture level with a scripted client connected to it
+    fn simulation(name: &str) -> Simulation<SimServer, ScriptedClient> {
+        let mut cvars = CvarRegistry::new();
+        crate::server::register_cvars(&cvars).unwrap();
+        let mut level = Level::spawn(
+            fixture::vfs(name),
+            &mut cvars,
+            fixture::MAP_NAME,
+            ServerStatics::new(1),
+        )
+        .unwrap();
+
+        let network = LoopbackNetwork::new(ms(40));
+        let (server_socket, client_socket) = network.pair();
+        assert_eq!(
+            level.connect_client(&mut cvars, server_socket).unwrap(),
+            Some(EntityId(1))
+        );
+
+        Simulation::new(
+            network,
+            SimServer { level, cvars },
+            ScriptedClient::new(client_socket),
+        )
+    }
+
+    fn player_origin(level: &Level) -> Vector3<f32> {
+        let world = level.world();
+        let world = world.borrow();
+        world
+            .try_get_entity(EntityId(1))
+            .unwrap()
+            .get_vector(FieldAddrVector::Origin as i16)
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn test_spawn_inhibited_by_skill() {
+        let flags = SPAWNFLAG_NOT_EASY | SPAWNFLAG_NOT_HARD;
+        assert!(spawn_inhibited(flags, false, 0));
+        assert!(!spawn_inhibited(flags, false, 1));
+        assert!(spawn_inhibited(flags, false, 2));
+        assert!(spawn_inhibited(flags, false, 3));
+    }
+
+    #[test]
+    fn test_spawn_inhibited_in_deathmatch() {
+        // skill flags don't apply in deathmatch
+        assert!(!spawn_inhibited(SPAWNFLAG_NOT_EASY, true, 0));
+        assert!(spawn_inhibited(SPAWNFLAG_NOT_DEATHMATCH, true, 1));
+        assert!(!spawn_inhibited(SPAWNFLAG_NOT_DEATHMATCH, false, 1));
+    }
+
+    #[test]
+    fn test_sim_signon() {
+        let mut sim = simulation("signon");
+        sim.run_for(Duration::seconds(2), ms(14)).unwrap();
+
+        assert_eq!(sim.client().stage, SignOnStage::Done);
+        let level = &sim.server().level;
+        assert_eq!(level.client_count(), 1);
+        assert!(level.server.client(EntityId(1)).unwrap().spawned);
+    }
+
+    #[test]
+    fn test_sim_movement() {
+        let mut sim = simulation("movement");
+        sim.run_for(Duration::seconds(1), ms(14)).unwrap();
+        let start = player_origin(&sim.server().level);
+
+        sim.run_for(Duration::seconds(2), ms(14)).unwrap();
+        let end = player_origin(&sim.server().level);
+
+        // the client runs along +y and the floor keeps the player from falling
+        assert!(end.y - start.y > 100.0, "{:?} -> {:?}", start, end);
+        assert!((end.x - start.x).abs() < 1.0, "{:?} -> {:?}", start, end);
+        assert!((end.z - 24.0).abs() < 1.0, "{:?}", end);
+    }
+
+    #[test]
+    fn test_sim_disconnect() {
+        let mut sim = simulation("disconnect");
+        sim.client_mut().disconnect_at = Some(Duration::seconds(2));
+
+        sim.run_for(Duration::seconds(1), ms(14)).unwrap();
+        assert_eq!(sim.server().level.client_count(), 1);
+
+        sim.run_for(Duration::seconds(2), ms(14)).unwrap();
+        assert_eq!(sim.server().level.client_count(), 0);
+    }
+}
@@ -0,0 +1,1137 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Client input: the commands clients send and the movement of their players.
+
+use std::io::Cursor;
+
+use super::{to_angles, Level};
+
+use crate::{
+    common::{
+        console::CvarRegistry,
+        engine, frustum,
+        net::{
+            BlockingMode, ButtonFlags, ClientCmd, ClientStat, PlayerColor, ServerCmd, SignOnStage,
+        },
+        parse,
+        pmove::{self, MoveVars},
+    },
+    server::{
+        idle::{IdleAction, IdleEvent, IdleMonitor, IdleSettings},
+        progs::{
+            EntityId, GlobalAddrEntity, GlobalAddrFloat, GlobalAddrFunction, ProgsError, StringId,
+        },
+        save::{self, NUM_SPAWN_PARMS},
+        world::{
+            CollideKind, EntityFlags, EntitySolid, FieldAddrFloat, FieldAddrStringId,
+            FieldAddrVector, MoveKind, World,
+        },
+    },
+};
+
+use cgmath::{Deg, InnerSpace, Vector3, Zero};
+use chrono::Duration;
+
+// the longest name a player may have
+const MAX_NAME_LEN: usize = 15;
+
+// the highest color index players may use for their shirts and pants
+const MAX_PLAYER_COLOR: u8 = 13;
+
+// how far the body leans when strafing, and the sideways speed at which the lean is greatest
+const ROLL_ANGLE: f32 = 2.0;
+const ROLL_SPEED: f32 = 200.0;
+
+// the lean of the player model is exaggerated compared to the lean of the view
+const BODY_ROLL_SCALE: f32 = 4.0;
+
+// how fast the view kick from damage or firing wears off, in degrees per second
+const PUNCH_DECAY: f32 = 10.0;
+
+impl Level {
+    /// Reads the messages from each client and moves the players in the game.
+    pub(crate) fn run_clients(
+        &mut self,
+        cvars: &mut CvarRegistry,
+        frame_time: Duration,
+    ) -> Result<(), ProgsError> {
+        let timeout = engine::duration_from_f32(cvars.get_value("sv_timeout").unwrap_or(300.0));
+        let idle = IdleSettings::from_cvars(cvars);
+
+        for e_id in self.client_ids() {
+            if !self.read_client_messages(cvars, e_id)? {
+                self.drop_client(cvars, e_id, false)?;
+                continue;
+            }
+
+            // the client crashed or its connection went away without a word
+            let timed_out = match self.server.client(e_id) {
+                Some(client) if client.connection.timed_out(timeout) => {
+                    warn!("{} timed out", client.name);
+                    true
+                }
+                _ => false,
+            };
+            if timed_out {
+                self.drop_client(cvars, e_id, false)?;
+                continue;
+            }
+
+            if let Some(ref settings) = idle {
+                if !self.check_idle(cvars, e_id, settings)? {
+                    continue;
+                }
+            }
+
+            let spawned = match self.server.client_mut(e_id) {
+                Some(client) if client.spawned => true,
+
+                // players still signing on don't move
+                Some(client) => {
+                    client.movement = Vector3::zero();
+                    false
+                }
+
+                None => continue,
+            };
+
+            if spawned && !self.server.paused() {
+                self.client_think(cvars, e_id, frame_time)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // returns false if the client disconnected or its connection failed
+    fn read_client_messages(
+        &mut self,
+        cvars: &mut CvarRegistry,
+        e_id: EntityId,
+    ) -> Result<bool, ProgsError> {
+        let deadzone = IdleSettings::from_cvars(cvars).map_or(Deg(0.0), |s| s.deadzone);
+
+        loop {
+            let msg = match self.server.client_mut(e_id) {
+                Some(client) => match client.connection.recv_msg(BlockingMode::NonBlocking) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        warn!("Lost connection to {}: {}", client.name, e);
+                        return Ok(false);
+                    }
+                },
+
+                None => return Ok(false),
+            };
+
+            if msg.is_empty() {
+                return Ok(true);
+            }
+
+            let codec = self.server.protocol().codec();
+            let mut reader = Cursor::new(msg.as_slice());
+            while (reader.position() as usize) < msg.len() {
+                let cmd = match ClientCmd::deserialize_with(&mut reader, codec) {
+                    Ok(cmd) => cmd,
+                    Err(e) => {
+                        warn!("Bad message from client {}: {}", e_id.0, e);
+                        return Ok(false);
+                    }
+                };
+
+                // a player moved to spectator for idling returns with its first input
+                let time = self.server.time();
+                let returned = match self.server.client_mut(e_id) {
+                    Some(client) => {
+                        let away = client.spawned && client.idle.acted();
+                        client.idle.record(time, &cmd, deadzone) && away
+                    }
+                    None => false,
+                };
+                if returned {
+                    self.rejoin(cvars, e_id)?;
+                }
+
+                match cmd {
+                    ClientCmd::Bad => {
+                        warn!("Bad command from client {}", e_id.0);
+                        return Ok(false);
+                    }
+
+                    ClientCmd::NoOp => (),
+
+                    ClientCmd::Disconnect => return Ok(false),
+
+                    ClientCmd::Move {
+                        angles,
+                        fwd_move,
+                        side_move,
+                        up_move,
+                        button_flags,
+                        impulse,
+                        ..
+                    } => {
+                        {
+                            let mut world = self.world.borrow_mut();
+                            world.put_vector(
+                                e_id,
+                                FieldAddrVector::ViewAngle,
+                                Vector3::new(angles.x.0, angles.y.0, angles.z.0),
+                            )?;
+
+                            let button = |flag| {
+                                if button_flags.contains(flag) {
+                                    1.0
+                                } else {
+                                    0.0
+                                }
+                            };
+                            world.put_float(
+                                e_id,
+                                FieldAddrFloat::Button0,
+                                button(ButtonFlags::ATTACK),
+                            )?;
+                            world.put_float(
+                                e_id,
+                                FieldAddrFloat::Button2,
+                                button(ButtonFlags::JUMP),
+                            )?;
+
+                            // an impulse stays set until the progs act on it
+                            if impulse != 0 {
+                                world.put_float(e_id, FieldAddrFloat::Impulse, impulse as f32)?;
+                            }
+                        }
+
+                        if let Some(client) = self.server.client_mut(e_id) {
+                            client.movement =
+                                Vector3::new(fwd_move as f32, side_move as f32, up_move as f32);
+                        }
+                    }
+
+                    ClientCmd::StringCmd { cmd } => self.execute_client_cmd(cvars, e_id, &cmd)?,
+                }
+            }
+        }
+    }
+
+    // runs the commands a client sends as text, such as the signon replies and chat
+    fn execute_client_cmd(
+        &mut self,
+        cvars: &mut CvarRegistry,
+        e_id: EntityId,
+        text: &str,
+    ) -> Result<(), ProgsError> {
+        let text = format!("{}\n", text);
+        let lines = match parse::commands(&text) {
+            Ok((_, lines)) => lines,
+            Err(e) => {
+                debug!("Couldn't parse command from client {}: {:?}", e_id.0, e);
+                return Ok(());
+            }
+        };
+
+        for args in lines {
+            let (name, args) = match args.split_first() {
+                Some(split) => split,
+                None => continue,
+            };
+
+            match *name {
+                "prespawn" => self.cmd_prespawn(e_id),
+                "spawn" => self.cmd_spawn(cvars, e_id)?,
+                "begin" => {
+                    let time = self.server.time();
+                    if let Some(client) = self.server.client_mut(e_id) {
+                        client.spawned = true;
+
+                        // time spent loading the level doesn't count as idling
+                        client.idle = IdleMonitor::new(time);
+                    }
+                }
+                "name" => self.cmd_name(e_id, args.first().copied().unwrap_or_default())?,
+                "color" => self.cmd_color(e_id, args)?,
+                "say" => self.cmd_say(cvars, e_id, &args.join(" "), false)?,
+                "say_team" => self.cmd_say(cvars, e_id, &args.join(" "), true)?,
+                "kill" => self.cmd_kill(cvars, e_id)?,
+                "pause" => self.cmd_pause(cvars, e_id),
+                "god" | "notarget" | "noclip" | "fly" => self.cmd_cheat(e_id, name)?,
+                "setpos" => self.cmd_setpos(e_id, args)?,
+                "save" | "load" => self.cmd_save_load(e_id, name, args),
+                cmd => debug!("Client {} sent unknown command {}", e_id.0, cmd),
+            }
+        }
+
+        Ok(())
+    }
+
+    // sends a client the messages every client receives on signon, such as baselines
+    fn cmd_prespawn(&mut self, e_id: EntityId) {
+        let signon = self.server.signon().to_vec();
+        let client = match self.server.client_mut(e_id) {
+            Some(c) => c,
+            None => return,
+        };
+
+        if client.spawned {
+            debug!("prespawn not valid -- already spawned");
+            return;
+        }
+
+        client.message.extend_from_slice(&signon);
+        ServerCmd::SignOnStage {
+            stage: SignOnStage::ClientInfo,
+        }
+        .serialize(&mut client.message)
+        .unwrap();
+    }
+
+    // puts a client's player in the game and sends it the state of the other players
+    fn cmd_spawn(&mut self, cvars: &mut CvarRegistry, e_id: EntityId) -> Result<(), ProgsError> {
+        let (name, colors, spawn_parms) = match self.server.client(e_id) {
+            Some(c) if c.spawned => {
+                debug!("spawn not valid -- already spawned");
+                return Ok(());
+            }
+            Some(c) => (c.name.clone(), c.colors, c.spawn_parms),
+            None => return Ok(()),
+        };
+
+        // a restored game already has the player in it, and only waited for it to come back
+        if self.loaded_game {
+            self.server.paused = false;
+        } else {
+            self.enter_game(cvars, e_id, &name, colors, spawn_parms)?;
+        }
+
+        // everything the client needs to join the game in progress
+        let codec = self.server.protocol().codec();
+        let mut msg = Vec::new();
+        ServerCmd::Time {
+            time: engine::duration_to_f32(self.server.time()),
+        }
+        .serialize_with(&mut msg, codec)
+        .unwrap();
+
+        {
+            let world = self.world.borrow();
+            for slot_id in 0..self.server.max_clients() {
+                let other_id = EntityId(slot_id + 1);
+                let (other_name, other_colors) = match self.server.client(other_id) {
+                    Some(c) => (c.name.clone(), c.colors),
+                    None => (String::new(), PlayerColor::from_bits(0)),
+                };
+                let frags = world
+                    .try_get_entity(other_id)?
+                    .get_float(FieldAddrFloat::Frags as i16)?;
+
+                let player_id = slot_id as u8;
+                let cmds = [
+                    ServerCmd::UpdateName {
+                        player_id,
+                        new_name: other_name,
+                    },
+                    ServerCmd::UpdateFrags {
+                        player_id,
+                        new_frags: frags as i16,
+                    },
+                    ServerCmd::UpdateColors {
+                        player_id,
+                        new_colors: other_colors,
+                    },
+                ];
+                for cmd in cmds.iter() {
+                    cmd.serialize_with(&mut msg, codec).unwrap();
+                }
+            }
+        }
+
+        for (id, value) in self.server.lightstyles().into_iter().enumerate() {
+            ServerCmd::LightStyle {
+                id: id as u8,
+                value,
+            }
+            .serialize_with(&mut msg, codec)
+            .unwrap();
+        }
+
+        let stats = [
+            (
+                ClientStat::TotalSecrets,
+                GlobalAddrFloat::TotalSecrets as i16,
+            ),
+            (
+                ClientStat::TotalMonsters,
+                GlobalAddrFloat::TotalMonsters as i16,
+            ),
+            (
+                ClientStat::FoundSecrets,
+                GlobalAddrFloat::FoundSecrets as i16,
+            ),
+            (
+                ClientStat::KilledMonsters,
+                GlobalAddrFloat::KilledMonsters as i16,
+            ),
+        ];
+        for &(stat, global) in stats.iter() {
+            ServerCmd::UpdateStat {
+                stat,
+                value: self.globals.get_float(global)? as i32,
+            }
+            .serialize_with(&mut msg, codec)
+            .unwrap();
+        }
+
+        // a leftover roll would tilt the view for good, since nothing corrects it
+        let angles = self
+            .world
+            .borrow()
+            .get_vector(e_id, FieldAddrVector::Angles)?;
+        ServerCmd::SetAngle {
+            angles: Vector3::new(Deg(angles.x), Deg(angles.y), Deg(0.0)),
+        }
+        .serialize_with(&mut msg, codec)
+        .unwrap();
+
+        self.write_client_data(e_id, &mut msg)?;
+
+        ServerCmd::SignOnStage {
+            stage: SignOnStage::Begin,
+        }
+        .serialize_with(&mut msg, codec)
+        .unwrap();
+
+        if let Some(message) = self.server.client_message_mut(e_id) {
+            message.extend_from_slice(&msg);
+        }
+
+        Ok(())
+    }
+
+    // puts a new player in the game with the progs' `ClientConnect` and `PutClientInServer`
+    fn enter_game(
+        &mut self,
+        cvars: &mut CvarRegistry,
+        e_id: EntityId,
+        name: &str,
+        colors: PlayerColor,
+        spawn_parms: [f32; NUM_SPAWN_PARMS],
+    ) -> Result<(), ProgsError> {
+        {
+            let mut world = self.world.borrow_mut();
+            world.clear_entity(e_id)?;
+            world.put_float(e_id, FieldAddrFloat::Colormap, e_id.0 as f32)?;
+            world.put_float(
+                e_id,
+                FieldAddrFloat::Team,
+                ((colors.bits() & 15) + 1) as f32,
+            )?;
+            let name_id = self.server.string_table.insert(name);
+            world
+                .try_get_entity_mut(e_id)?
+                .put_string_id(name_id, FieldAddrStringId::NetName as i16)?;
+        }
+
+        for (i, parm) in spawn_parms.iter().enumerate() {
+            self.globals
+                .put_float(*parm, GlobalAddrFloat::Arg0 as i16 + i as i16)?;
+        }
+
+        self.globals.put_float(
+            engine::duration_to_f32(self.server.time()),
+            GlobalAddrFloat::Time as i16,
+        )?;
+        self.globals
+            .put_entity_id(e_id, GlobalAddrEntity::Self_ as i16)?;
+        let f = self
+            .globals
+            .get_function_id(GlobalAddrFunction::ClientConnect as i16)?;
+        self.execute(cvars, f)?;
+
+        info!("{} entered the game", name);
+
+        let f = self
+            .globals
+            .get_function_id(GlobalAddrFunction::PutClientInServer as i16)?;
+        self.execute(cvars, f)?;
+
+        Ok(())
+    }
+
+    fn cmd_name(&mut self, e_id: EntityId, name: &str) -> Result<(), ProgsError> {
+        let new_name: String = name.chars().take(MAX_NAME_LEN).collect();
+
+        let client = match self.server.client_mut(e_id) {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+
+        if client.name == new_name {
+            return Ok(());
+        }
+
+        if client.name != "unconnected" {
+            info!("{} renamed to {}", client.name, new_name);
+        }
+
+        client.name = new_name.clone();
+
+        let name_id = self.server.string_table.insert(&new_name);
+        self.world
+            .borrow_mut()
+            .try_get_entity_mut(e_id)?
+            .put_string_id(name_id, FieldAddrStringId::NetName as i16)?;
+
+        self.server.broadcast(&ServerCmd::UpdateName {
+            player_id: (e_id.0 - 1) as u8,
+            new_name,
+        });
+
+        Ok(())
+    }
+
+    // sets the shirt and pants colors of a player, or both from a single value
+    fn cmd_color(&mut self, e_id: EntityId, args: &[&str]) -> Result<(), ProgsError> {
+        let parse_color = |arg: &str| {
+            arg.parse::<u8>()
+                .map(|c| (c & 15).min(MAX_PLAYER_COLOR))
+                .ok()
+        };
+
+        let (top, bottom) = match args {
+            [both] => match parse_color(both) {
+                Some(c) => (c, c),
+                None => return Ok(()),
+            },
+            [top, bottom, ..] => match (parse_color(top), parse_color(bottom)) {
+                (Some(t), Some(b)) => (t, b),
+                _ => return Ok(()),
+            },
+            [] => return Ok(()),
+        };
+
+        let new_colors = PlayerColor::new(top, bottom);
+        match self.server.client_mut(e_id) {
+            Some(client) => client.colors = new_colors,
+            None => return Ok(()),
+        }
+
+        self.world
+            .borrow_mut()
+            .put_float(e_id, FieldAddrFloat::Team, (bottom + 1) as f32)?;
+
+        self.server.broadcast(&ServerCmd::UpdateColors {
+            player_id: (e_id.0 - 1) as u8,
+            new_colors,
+        });
+
+        Ok(())
+    }
+
+    // sends a chat message to every client, or only to teammates in a team game
+    fn cmd_say(
+        &mut self,
+        cvars: &CvarRegistry,
+        e_id: EntityId,
+        text: &str,
+        team_only: bool,
+    ) -> Result<(), ProgsError> {
+        let name = match self.server.client(e_id) {
+            Some(c) => c.name.clone(),
+            None => return Ok(()),
+        };
+
+        // the leading 1 tells clients to print the message in color and play the chat sound
+        let text = format!("\x01{}: {}\n", name, text);
+        let team_only = team_only && cvars.get_value("teamplay").unwrap_or(0.0) != 0.0;
+
+        let world = self.world.borrow();
+        let team = world.get_float(e_id, FieldAddrFloat::Team)?;
+        for other_id in self.client_ids() {
+            if team_only && world.get_float(other_id, FieldAddrFloat::Team)? != team {
+                continue;
+            }
+
+            self.server
+                .send_to_client(other_id, &ServerCmd::Print { text: text.clone() });
+        }
+
+        info!("{}", text.trim_end());
+        Ok(())
+    }
+
+    fn cmd_kill(&mut self, cvars: &mut CvarRegistry, e_id: EntityId) -> Result<(), ProgsError> {
+        let health = self
+            .world
+            .borrow()
+            .get_float(e_id, FieldAddrFloat::Health)?;
+        if health <= 0.0 {
+            self.server.send_to_client(
+                e_id,
+                &ServerCmd::Print {
+                    text: String::from("Can't suicide -- already dead!\n"),
+                },
+            );
+            return Ok(());
+        }
+
+        self.globals.put_float(
+            engine::duration_to_f32(self.server.time()),
+            GlobalAddrFloat::Time as i16,
+        )?;
+        self.globals
+            .put_entity_id(e_id, GlobalAddrEntity::Self_ as i16)?;
+        let f = self
+            .globals
+            .get_function_id(GlobalAddrFunction::ClientKill as i16)?;
+        self.execute(cvars, f)
+    }
+
+    fn cmd_pause(&mut self, cvars: &CvarRegistry, e_id: EntityId) {
+        let pausable = cvars.get_value("pausable").unwrap_or(1.0) != 0.0;
+        let name = match self.server.client(e_id) {
+            Some(c) => c.name.clone(),
+            None => return,
+        };
+
+        let text = match self.server.toggle_pause(pausable) {
+            Some(true) => format!("{} paused the game\n", name),
+            Some(false) => format!("{} unpaused the game\n", name),
+            None => {
+                self.server.send_to_client(
+                    e_id,
+                    &ServerCmd::Print {
+                        text: String::from("Pause not allowed.\n"),
+                    },
+                );
+                return;
+            }
+        };
+
+        self.server.broadcast(&ServerCmd::Print { text });
+    }
+
+    // toggles one of the cheats, which are only allowed outside deathmatch
+    fn cmd_cheat(&mut self, e_id: EntityId, cheat: &str) -> Result<(), ProgsError> {
+        let privileged = match self.server.client(e_id) {
+            Some(c) => c.privileged,
+            None => return Ok(()),
+        };
+
+        if self.globals.get_float(GlobalAddrFloat::Deathmatch as i16)? != 0.0 && !privileged {
+            return Ok(());
+        }
+
+        let mut world = self.world.borrow_mut();
+        let toggle_flag = |world: &mut World, flag| -> Result<bool, ProgsError> {
+            let ent = world.try_get_entity_mut(e_id)?;
+            if ent.flags()?.contains(flag) {
+                ent.remove_flags(flag)?;
+                Ok(false)
+            } else {
+                ent.add_flags(flag)?;
+                Ok(true)
+            }
+        };
+        let toggle_move_kind = |world: &mut World, kind| -> Result<bool, ProgsError> {
+            let on = world.try_get_entity(e_id)?.move_kind()? != kind;
+            let new_kind = if on { kind } else { MoveKind::Walk };
+            world.put_float(e_id, FieldAddrFloat::MoveKind, new_kind as u32 as f32)?;
+            Ok(on)
+        };
+
+        let (label, on) = match cheat {
+            "god" => ("godmode", toggle_flag(&mut world, EntityFlags::GOD_MODE)?),
+            "notarget" => ("notarget", toggle_flag(&mut world, EntityFlags::NO_TARGET)?),
+            "noclip" => ("noclip", toggle_move_kind(&mut world, MoveKind::NoClip)?),
+            _ => ("flymode", toggle_move_kind(&mut world, MoveKind::Fly)?),
+        };
+
+        self.server.send_to_client(
+            e_id,
+            &ServerCmd::Print {
+                text: format!("{} {}\n", label, if on { "ON" } else { "OFF" }),
+            },
+        );
+
+        Ok(())
+    }
+
+    // moves the player to a position saved by pos_save, optionally facing a saved direction
+    fn cmd_setpos(&mut self, e_id: EntityId, args: &[&str]) -> Result<(), ProgsError> {
+        let privileged = match self.server.client(e_id) {
+            Some(c) => c.privileged,
+            None => return Ok(()),
+        };
+
+        // moving around at will is practice, not play
+        if self.server.max_clients() != 1 && !privileged {
+            self.server.send_to_client(
+                e_id,
+                &ServerCmd::Print {
+                    text: String::from("setpos is only allowed in single player\n"),
+                },
+            );
+            return Ok(());
+        }
+
+        let values: Option<Vec<f32>> = args.iter().map(|a| a.parse().ok()).collect();
+        let values = match values {
+            Some(v) if v.len() == 3 || v.len() == 6 => v,
+            _ => {
+                self.server.send_to_client(
+                    e_id,
+                    &ServerCmd::Print {
+                        text: String::from("usage: setpos x y z [pitch yaw roll]\n"),
+                    },
+                );
+                return Ok(());
+            }
+        };
+
+        let mut world = self.world.borrow_mut();
+        world.set_entity_origin(e_id, Vector3::new(values[0], values[1], values[2]))?;
+        world.put_vector(e_id, FieldAddrVector::Velocity, Vector3::zero())?;
+
+        if values.len() == 6 {
+            world.put_vector(
+                e_id,
+                FieldAddrVector::Angles,
+                Vector3::new(values[3], values[4], values[5]),
+            )?;
+
+            // turn the client's view as well
+            world.put_float(e_id, FieldAddrFloat::FixAngle, 1.0)?;
+        }
+
+        Ok(())
+    }
+
+    // passes a quick save or load from the single-player client to the host, which owns the save
+    // files and is the only one that can replace the level
+    fn cmd_save_load(&mut self, e_id: EntityId, name: &str, args: &[&str]) {
+        let error = if self.server.max_clients() != 1 {
+            Some(format!("{} is only allowed in single player\n", name))
+        } else {
+            match args {
+                [slot] if is_valid_slot(slot) => None,
+                _ => Some(format!("usage: {} (savename)\n", name)),
+            }
+        };
+
+        match error {
+            Some(text) => {
+                self.server.send_to_client(e_id, &ServerCmd::Print { text });
+            }
+            None => self
+                .server
+                .queue_local_cmd(format!("{} {}\n", name, args[0])),
+        }
+    }
+
+    // warns a player who has gone without input and acts once sv_idlelimit is reached, returning
+    // false if the client was dropped
+    fn check_idle(
+        &mut self,
+        cvars: &mut CvarRegistry,
+        e_id: EntityId,
+        settings: &IdleSettings,
+    ) -> Result<bool, ProgsError> {
+        // the game is frozen while paused, so no one can be expected to move
+        if self.server.paused() {
+            return Ok(true);
+        }
+
+        let time = self.server.time();
+        let (name, event) = match self.server.client_mut(e_id) {
+            Some(client) if client.spawned => {
+                (client.name.clone(), client.idle.update(time, settings))
+            }
+            _ => return Ok(true),
+        };
+
+        match event {
+            Some(IdleEvent::Warn { remaining }) => {
+                let fate = match settings.action {
+                    IdleAction::Spectate => "moved to spectator",
+                    IdleAction::Drop => "disconnected",
+                };
+                self.server.send_to_client(
+                    e_id,
+                    &ServerCmd::Print {
+                        text: format!(
+                            "You will be {} in {} seconds unless you move\n",
+                            fate,
+                            remaining.num_seconds()
+                        ),
+                    },
+                );
+            }
+
+            Some(IdleEvent::Act(IdleAction::Spectate)) => {
+                self.server.broadcast(&ServerCmd::Print {
+                    text: format!("{} is idle and was moved to spectator\n", name),
+                });
+                self.spectate(e_id)?;
+            }
+
+            Some(IdleEvent::Act(IdleAction::Drop)) => {
+                self.server.broadcast(&ServerCmd::Print {
+                    text: format!("{} was dropped for idling\n", name),
+                });
+                self.drop_client(cvars, e_id, false)?;
+                return Ok(false);
+            }
+
+            None => (),
+        }
+
+        Ok(true)
+    }
+
+    // takes an idle player out of the game, leaving it free to look around
+    fn spectate(&mut self, e_id: EntityId) -> Result<(), ProgsError> {
+        let mut world = self.world.borrow_mut();
+        world.put_float(e_id, FieldAddrFloat::ModelIndex, 0.0)?;
+        world.put_float(e_id, FieldAddrFloat::Solid, EntitySolid::Not as u32 as f32)?;
+        world.put_float(
+            e_id,
+            FieldAddrFloat::MoveKind,
+            MoveKind::NoClip as u32 as f32,
+        )?;
+        world.put_float(e_id, FieldAddrFloat::TakeDamage, 0.0)?;
+        world
+            .try_get_entity_mut(e_id)?
+            .put_string_id(StringId(0), FieldAddrStringId::WeaponModelName as i16)?;
+
+        Ok(())
+    }
+
+    // puts a spectating player back in the game, as if it had just respawned
+    fn rejoin(&mut self, cvars: &mut CvarRegistry, e_id: EntityId) -> Result<(), ProgsError> {
+        let functions = [
+            GlobalAddrFunction::SetNewArgs as i16,
+            GlobalAddrFunction::PutClientInServer as i16,
+        ];
+        for &function in functions.iter() {
+            self.globals
+                .put_entity_id(e_id, GlobalAddrEntity::Self_ as i16)?;
+            let f = self.globals.get_function_id(function)?;
+            self.execute(cvars, f)?;
+        }
+
+        let name = self
+            .server
+            .client(e_id)
+            .map(|c| c.name.clone())
+            .unwrap_or_default();
+        self.server.broadcast(&ServerCmd::Print {
+            text: format!("{} is back in the game\n", name),
+        });
+
+        Ok(())
+    }
+
+    // turns a player's input into a change of velocity, which entity physics then carries out
+    fn client_think(
+        &mut self,
+        cvars: &CvarRegistry,
+        e_id: EntityId,
+        frame_time: Duration,
+    ) -> Result<(), ProgsError> {
+        let vars = MoveVars::from_cvars(cvars).unwrap_or_default();
+        let frame_time = engine::duration_to_f32(frame_time);
+        let time = engine::duration_to_f32(self.server.time());
+        let movement = match self.server.client(e_id) {
+            Some(c) => c.movement,
+            None => return Ok(()),
+        };
+
+        let mut world = self.world.borrow_mut();
+        if world.try_get_entity(e_id)?.move_kind()? == MoveKind::None {
+            return Ok(());
+        }
+
+        let punch = world.get_vector(e_id, FieldAddrVector::PunchAngle)?;
+        world.put_vector(
+            e_id,
+            FieldAddrVector::PunchAngle,
+            drop_punch_angle(punch, frame_time),
+        )?;
+
+        // the dead can't move
+        if world.get_float(e_id, FieldAddrFloat::Health)? <= 0.0 {
+            return Ok(());
+        }
+
+        // the body turns to face where the player is looking, but pitches less than the view
+        let v_angle = world.get_vector(e_id, FieldAddrVector::ViewAngle)?
+            + world.get_vector(e_id, FieldAddrVector::PunchAngle)?;
+        let velocity = world.get_vector(e_id, FieldAddrVector::Velocity)?;
+        let mut angles = world.get_vector(e_id, FieldAddrVector::Angles)?;
+        angles.z = calc_roll(angles, velocity) * BODY_ROLL_SCALE;
+        if world.get_float(e_id, FieldAddrFloat::FixAngle)? == 0.0 {
+            angles.x = -v_angle.x / 3.0;
+            angles.y = v_angle.y;
+        }
+        world.put_vector(e_id, FieldAddrVector::Angles, angles)?;
+
+        if world
+            .try_get_entity(e_id)?
+            .flags()?
+            .contains(EntityFlags::WATER_JUMP)
+        {
+            return water_jump(&mut world, e_id, time);
+        }
+
+        let move_kind = world.try_get_entity(e_id)?.move_kind()?;
+        if world.get_float(e_id, FieldAddrFloat::WaterLevel)? >= 2.0
+            && move_kind != MoveKind::NoClip
+        {
+            return water_move(&mut world, e_id, &vars, movement, frame_time);
+        }
+
+        air_move(&mut world, e_id, &vars, movement, frame_time, time)
+    }
+}
+
+// returns how far a player leans when moving sideways at `velocity`
+fn calc_roll(angles: Vector3<f32>, velocity: Vector3<f32>) -> f32 {
+    let (_, right, _) = frustum::view_vectors(to_angles(angles));
+    let side = velocity.dot(right);
+    let roll = if side.abs() < ROLL_SPEED {
+        side.abs() * ROLL_ANGLE / ROLL_SPEED
+    } else {
+        ROLL_ANGLE
+    };
+
+    if side < 0.0 {
+        -roll
+    } else {
+        roll
+    }
+}
+
+// shrinks the view kick toward zero over `frame_time`
+fn drop_punch_angle(punch: Vector3<f32>, frame_time: f32) -> Vector3<f32> {
+    let len = punch.magnitude();
+    if len == 0.0 {
+        return punch;
+    }
+
+    let new_len = (len - PUNCH_DECAY * frame_time).max(0.0);
+    punch * (new_len / len)
+}
+
+// carries a player climbing out of water over the edge
+fn water_jump(world: &mut World, e_id: EntityId, time: f32) -> Result<(), ProgsError> {
+    if time > world.get_float(e_id, FieldAddrFloat::TeleportTime)?
+        || world.get_float(e_id, FieldAddrFloat::WaterLevel)? == 0.0
+    {
+        world
+            .try_get_entity_mut(e_id)?
+            .remove_flags(EntityFlags::WATER_JUMP)?;
+        world.put_float(e_id, FieldAddrFloat::TeleportTime, 0.0)?;
+    }
+
+    let move_dir = world.get_vector(e_id, FieldAddrVector::MoveDirection)?;
+    let mut velocity = world.get_vector(e_id, FieldAddrVector::Velocity)?;
+    velocity.x = move_dir.x;
+    velocity.y = move_dir.y;
+    world.put_vector(e_id, FieldAddrVector::Velocity, velocity)
+}
+
+fn water_move(
+    world: &mut World,
+    e_id: EntityId,
+    vars: &MoveVars,
+    movement: Vector3<f32>,
+    frame_time: f32,
+) -> Result<(), ProgsError> {
+    let v_angle = world.get_vector(e_id, FieldAddrVector::ViewAngle)?;
+    let (forward, right, _) = frustum::view_vectors(to_angles(v_angle));
+    let mut wish_vel = forward * movement.x + right * movement.y;
+    if movement == Vector3::zero() {
+        wish_vel.z -= pmove::SINK_SPEED;
+    } else {
+        wish_vel.z += movement.z;
+    }
+
+    let (wish_dir, wish_speed) = pmove::wish_dir_speed(wish_vel, vars.max_speed);
+    let wish_speed = wish_speed * pmove::SWIM_SPEED_SCALE;
+
+    // water friction slows movement in every direction
+    let mut velocity = world.get_vector(e_id, FieldAddrVector::Velocity)?;
+    let speed = velocity.magnitude();
+    let new_speed = if speed > 0.0 {
+        let new_speed = (speed - frame_time * speed * vars.friction).max(0.0);
+        velocity *= new_speed / speed;
+        new_speed
+    } else {
+        0.0
+    };
+
+    let add_speed = wish_speed - new_speed;
+    if wish_speed > 0.0 && add_speed > 0.0 {
+        let accel_speed = (vars.accelerate * wish_speed * frame_time).min(add_speed);
+        velocity += wish_dir * accel_speed;
+    }
+
+    world.put_vector(e_id, FieldAddrVector::Velocity, velocity)
+}
+
+fn air_move(
+    world: &mut World,
+    e_id: EntityId,
+    vars: &MoveVars,
+    movement: Vector3<f32>,
+    frame_time: f32,
+    time: f32,
+) -> Result<(), ProgsError> {
+    let angles = world.get_vector(e_id, FieldAddrVector::Angles)?;
+    let (forward, right, _) = frustum::view_vectors(to_angles(angles));
+
+    // players can't back into a teleporter they just came out of
+    let mut forward_move = movement.x;
+    if time < world.get_float(e_id, FieldAddrFloat::TeleportTime)? && forward_move < 0.0 {
+        forward_move = 0.0;
+    }
+
+    let move_kind = world.try_get_entity(e_id)?.move_kind()?;
+    let mut wish_vel = forward * forward_move + right * movement.y;
+    wish_vel.z = if move_kind == MoveKind::Walk {
+        0.0
+    } else {
+        movement.z
+    };
+
+    let (wish_dir, wish_speed) = pmove::wish_dir_speed(wish_vel, vars.max_speed);
+    let accel_speed = vars.accelerate * wish_speed * frame_time;
+
+    let mut velocity = world.get_vector(e_id, FieldAddrVector::Velocity)?;
+    if move_kind == MoveKind::NoClip {
+        velocity = wish_dir * wish_speed;
+    } else if world
+        .try_get_entity(e_id)?
+        .flags()?
+        .contains(EntityFlags::ON_GROUND)
+    {
+        velocity = user_friction(world, e_id, vars, velocity, frame_time)?;
+        pmove::accelerate(&mut velocity, wish_dir, wish_speed, accel_speed);
+    } else {
+        // air control can't add much speed, but can still turn quickly
+        pmove::accelerate(
+            &mut velocity,
+            wish_dir,
+            wish_speed.min(pmove::MAX_AIR_WISH_SPEED),
+            accel_speed,
+        );
+    }
+
+    world.put_vector(e_id, FieldAddrVector::Velocity, velocity)
+}
+
+// slows a player on the ground, more so at the edge of a drop
+fn user_friction(
+    world: &World,
+    e_id: EntityId,
+    vars: &MoveVars,
+    velocity: Vector3<f32>,
+    frame_time: f32,
+) -> Result<Vector3<f32>, ProgsError> {
+    let speed = velocity.x.hypot(velocity.y);
+    if speed == 0.0 {
+        return Ok(velocity);
+    }
+
+    let origin = world.get_vector(e_id, FieldAddrVector::Origin)?;
+    let mins = world.get_vector(e_id, FieldAddrVector::Mins)?;
+    let mut start = origin + velocity / speed * pmove::EDGE_LOOKAHEAD;
+    start.z = origin.z + mins.z;
+    let stop = start - Vector3::unit_z() * pmove::EDGE_DROP;
+
+    let (trace, _) = world.move_entity(
+        e_id,
+        start,
+        Vector3::zero(),
+        Vector3::zero(),
+        stop,
+        CollideKind::NoMonsters,
+    )?;
+    let friction = if trace.ratio() == 1.0 {
+        vars.friction * vars.edge_friction
+    } else {
+        vars.friction
+    };
+
+    let control = speed.max(vars.stop_speed);
+    let new_speed = (speed - frame_time * control * friction).max(0.0);
+    Ok(velocity * (new_speed / speed))
+}
+
+// the slot is run as part of a console command, so it's limited to characters that can't end or
+// extend the command
+fn is_valid_slot(slot: &str) -> bool {
+    !slot.is_empty()
+        && slot
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+        && save::save_file_name(slot).is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_drop_punch_angle() {
+        let punch = Vector3::new(-3.0, 4.0, 0.0);
+        let dropped = drop_punch_angle(punch, 0.1);
+        assert!((dropped.magnitude() - 4.0).abs() < 1e-5);
+        assert!((dropped.normalize() - punch.normalize()).magnitude() < 1e-5);
+
+        assert_eq!(drop_punch_angle(punch, 1.0), Vector3::zero());
+    }
+
+    #[test]
+    fn test_is_valid_slot() {
+        assert!(is_valid_slot("quick"));
+        assert!(is_valid_slot("s0.sav"));
+        assert!(!is_valid_slot(""));
+        assert!(!is_valid_slot("../config"));
+        assert!(!is_valid_slot("a;quit"));
+    }
+
+    #[test]
+    fn test_calc_roll() {
+        // strafing right leans right, up to the maximum
+        assert!((calc_roll(Vector3::zero(), Vector3::new(0.0, -100.0, 0.0)) - 1.0).abs() < 1e-5);
+        assert_eq!(
+            calc_roll(Vector3::zero(), Vector3::new(0.0, -400.0, 0.0)),
+            ROLL_ANGLE
+        );
+        assert_eq!(
+            calc_roll(Vector3::zero(), Vector3::new(0.0, 400.0, 0.0)),
+            -ROLL_ANGLE
+        );
+        assert_eq!(
+            calc_roll(Vector3::zero(), Vector3::new(100.0, 0.0, 0.0)),
+            0.0
+        );
+    }
+}
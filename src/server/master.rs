@@ -0,0 +1,210 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Advertising to master servers.
+//!
+//! A public server sends a heartbeat to each master server in `sv_masters` when it becomes public,
+//! periodically after that, and once more when it shuts down. A master answers a heartbeat by
+//! sending `getinfo` to the address it came from, and lists the server if the reply describes a
+//! game it knows. After the final heartbeat, the server no longer answers, so the master drops it.
+//!
+//! This is the protocol spoken by dpmaster, which keeps the server lists of most current Quake
+//! clients. All packets are connectionless and sent from the server's listening socket.
+
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    str,
+    time::{Duration, Instant},
+};
+
+/// The port master servers listen on if `sv_masters` doesn't give one.
+pub const DEFAULT_MASTER_PORT: u16 = 27950;
+
+/// The time between heartbeats.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(300);
+
+// the game name and protocol number under which Quake servers are listed
+const GAME_NAME: &str = "DarkPlaces-Quake";
+const MASTER_PROTOCOL: u32 = 3;
+
+/// The details a server reports in response to `getinfo`.
+#[derive(Clone, Debug)]
+pub struct ServerDetails {
+    pub hostname: String,
+    pub map: String,
+    pub client_count: u8,
+    pub client_max: u8,
+}
+
+/// Returns the contents of a heartbeat packet.
+pub fn heartbeat() -> &'static [u8] {
+    b"heartbeat DarkPlaces\n"
+}
+
+/// If `packet` is a `getinfo` request, returns the challenge it carries.
+///
+/// The challenge must be echoed in the reply. `packet` should not include the out-of-band
+/// control value.
+pub fn parse_getinfo(packet: &[u8]) -> Option<&str> {
+    let text = str::from_utf8(packet)
+        .ok()?
+        .trim_end_matches(|c| c == '\n' || c == '\0');
+    let mut parts = text.splitn(2, ' ');
+
+    match (parts.next(), parts.next()) {
+        (Some("getinfo"), challenge) => Some(challenge.unwrap_or("")),
+        _ => None,
+    }
+}
+
+/// Returns the reply to a `getinfo` request carrying `challenge`.
+pub fn info_response(challenge: &str, details: &ServerDetails) -> Vec<u8> {
+    let fields = [
+        ("gamename", GAME_NAME.to_owned()),
+        ("protocol", MASTER_PROTOCOL.to_string()),
+        ("clients", details.client_count.to_string()),
+        ("sv_maxclients", details.client_max.to_string()),
+        ("mapname", details.map.clone()),
+        ("hostname", details.hostname.clone()),
+        ("challenge", challenge.to_owned()),
+    ];
+
+    let mut reply = String::from("infoResponse\n");
+    for (key, value) in fields.iter() {
+        // backslashes separate the fields, so they can't appear in values
+        reply.push_str(&format!("\\{}\\{}", key, value.replace('\\', "")));
+    }
+
+    reply.into_bytes()
+}
+
+/// Resolves the space-separated list of master servers in `masters`.
+///
+/// Each entry is a host name or address with an optional port. Entries that can't be resolved are
+/// logged and skipped.
+pub fn resolve_masters(masters: &str) -> Vec<SocketAddr> {
+    masters
+        .split_whitespace()
+        .filter_map(|master| {
+            let resolved = match master.to_socket_addrs() {
+                Ok(addrs) => Ok(addrs),
+                // no port was given
+                Err(_) => (master, DEFAULT_MASTER_PORT).to_socket_addrs(),
+            };
+
+            match resolved.map(|mut addrs| addrs.next()) {
+                Ok(Some(addr)) => Some(addr),
+                _ => {
+                    warn!("Couldn't resolve master server {}", master);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Decides when heartbeats should be sent.
+pub struct Heartbeat {
+    last_sent: Option<Instant>,
+}
+
+impl Heartbeat {
+    pub fn new() -> Heartbeat {
+        Heartbeat { last_sent: None }
+    }
+
+    /// Returns `true` if a heartbeat should be sent at `now`.
+    ///
+    /// Heartbeats are due as soon as the server becomes public and every `HEARTBEAT_INTERVAL`
+    /// after that.
+    pub fn due(&mut self, public: bool, now: Instant) -> bool {
+        if !public {
+            self.last_sent = None;
+            return false;
+        }
+
+        match self.last_sent {
+            Some(last) if now.duration_since(last) < HEARTBEAT_INTERVAL => false,
+            _ => {
+                self.last_sent = Some(now);
+                true
+            }
+        }
+    }
+
+    /// Returns `true` if the masters have been told about the server and should be told that it
+    /// is shutting down.
+    pub fn announced(&self) -> bool {
+        self.last_sent.is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_heartbeat_due() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new();
+
+        assert!(!heartbeat.due(false, start));
+        assert!(!heartbeat.announced());
+
+        assert!(heartbeat.due(true, start));
+        assert!(heartbeat.announced());
+        assert!(!heartbeat.due(true, start + Duration::from_secs(10)));
+        assert!(heartbeat.due(true, start + HEARTBEAT_INTERVAL));
+
+        // going private and public again sends a heartbeat straight away
+        assert!(!heartbeat.due(false, start + HEARTBEAT_INTERVAL + Duration::from_secs(1)));
+        assert!(!heartbeat.announced());
+        assert!(heartbeat.due(true, start + HEARTBEAT_INTERVAL + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_getinfo() {
+        assert_eq!(parse_getinfo(b"getinfo A1b2C3\n"), Some("A1b2C3"));
+        assert_eq!(parse_getinfo(b"getinfo"), Some(""));
+        assert_eq!(parse_getinfo(b"getstatus abc"), None);
+        assert_eq!(parse_getinfo(b"\x80\x00"), None);
+
+        let details = ServerDetails {
+            hostname: String::from("back\\slash"),
+            map: String::from("e1m1"),
+            client_count: 2,
+            client_max: 8,
+        };
+        let reply = String::from_utf8(info_response("A1b2C3", &details)).unwrap();
+        assert!(reply.starts_with("infoResponse\n\\gamename\\DarkPlaces-Quake\\protocol\\3"));
+        assert!(reply.contains("\\clients\\2\\sv_maxclients\\8\\mapname\\e1m1"));
+        assert!(reply.contains("\\hostname\\backslash"));
+        assert!(reply.ends_with("\\challenge\\A1b2C3"));
+    }
+
+    #[test]
+    fn test_resolve_masters() {
+        assert_eq!(
+            resolve_masters("127.0.0.1:27951 127.0.0.1"),
+            vec![
+                "127.0.0.1:27951".parse().unwrap(),
+                "127.0.0.1:27950".parse().unwrap(),
+            ]
+        );
+        assert!(resolve_masters("").is_empty());
+    }
+}
@@ -15,20 +15,148 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+pub mod challenge;
+mod cvars;
+#[cfg(test)]
+pub(crate) mod fixture;
+pub mod hooks;
+pub mod idle;
+pub mod level;
+pub mod master;
+pub mod multicast;
+pub mod priority;
 pub mod progs;
+pub mod protocol;
+pub mod rcon;
+pub mod save;
 pub mod world;
 
-use std::{
-    io::{Cursor, Seek, SeekFrom},
-    rc::Rc,
+pub use self::cvars::{next_rule, register_cvars, RULE_CVARS};
+
+use std::{cell::RefCell, rc::Rc};
+
+use self::{
+    hooks::ServerHooks,
+    idle::IdleMonitor,
+    multicast::{ClientMessages, Multicast, MulticastScope, MulticastWorld},
+    priority::EntityPriority,
+    progs::{EntityId, Functions, ProgsError, StringId, StringTable},
+    save::NUM_SPAWN_PARMS,
+    world::{EntityFlags, FieldAddrFloat, FieldAddrVector, World},
 };
 
-use self::progs::{EntityId, StringId, StringTable};
+use crate::common::{
+    bsp,
+    console::{CmdRegistry, CvarRegistry},
+    mapconfig::{ConfigSide, MapConfig},
+    net::{PlayerColor, Protocol, QSocket, ServerCmd},
+    random::GameRng,
+};
 
-use byteorder::WriteBytesExt;
+use cgmath::{Vector3, Zero};
+use chrono::Duration;
 
 const MAX_DATAGRAM: usize = 1024;
-const MAX_LIGHTSTYLES: usize = 64;
+pub const MAX_LIGHTSTYLES: usize = 64;
+
+/// Sound channel flag: deliver the sound to every client, even out of earshot.
+pub const SOUND_CHANNEL_NO_PHS: i32 = 8;
+
+/// Sound channel flag: deliver the sound reliably.
+pub const SOUND_CHANNEL_RELIABLE: i32 = 16;
+
+// the values clients assume when a sound message omits them
+const DEFAULT_SOUND_VOLUME: u8 = 255;
+const DEFAULT_SOUND_ATTENUATION: f32 = 1.0;
+
+// how often monsters switch the client they look for
+const CHECK_CLIENT_INTERVAL_MS: i64 = 100;
+
+/// Returns the rate at which the simulation clock runs relative to real time.
+///
+/// This is controlled by `host_timescale`, where a value of 0 runs the simulation in real time.
+/// Multiplayer games always run in real time unless `sv_cheats` is set.
+pub fn timescale(cvars: &CvarRegistry, max_clients: usize) -> f32 {
+    let timescale = cvars.get_value("host_timescale").unwrap_or(0.0);
+    if timescale <= 0.0 {
+        return 1.0;
+    }
+
+    let cheats = cvars.get_value("sv_cheats").unwrap_or(0.0) != 0.0;
+    if max_clients > 1 && !cheats {
+        return 1.0;
+    }
+
+    timescale
+}
+
+/// Registers the `edict`, `edicts` and `edictcount` commands, which print the entities of the
+/// given world.
+///
+/// These replace any previously registered versions, so they should be registered again whenever
+/// a new world is loaded.
+pub fn register_edict_cmds(
+    cmds: &mut CmdRegistry,
+    world: Rc<RefCell<World>>,
+    functions: Rc<Functions>,
+) {
+    let edict_world = world.clone();
+    let edict_functions = functions.clone();
+    cmds.insert_or_replace(
+        "edict",
+        "edict (number): print the fields of an entity",
+        Box::new(move |args| {
+            let entity_id = match args {
+                [id] => match id.parse() {
+                    Ok(i) => EntityId(i),
+                    Err(_) => {
+                        println!("Bad entity number: {}", id);
+                        return;
+                    }
+                },
+                _ => {
+                    println!("edict (number): print the fields of an entity");
+                    return;
+                }
+            };
+
+            match edict_world
+                .borrow()
+                .describe_entity(entity_id, &edict_functions)
+            {
+                Ok(desc) => print!("{}", desc),
+                Err(e) => println!("{}", e),
+            }
+        }),
+    );
+
+    let edicts_world = world.clone();
+    cmds.insert_or_replace(
+        "edicts",
+        "print the fields of every entity",
+        Box::new(
+            move |_| match edicts_world.borrow().describe_entities(&functions) {
+                Ok(desc) => print!("{}", desc),
+                Err(e) => println!("{}", e),
+            },
+        ),
+    );
+
+    cmds.insert_or_replace(
+        "edictcount",
+        "print the number of entities of each kind",
+        Box::new(move |_| match world.borrow().entity_counts() {
+            Ok(counts) => {
+                println!("num_edicts:{:3}", counts.slots);
+                println!("active    :{:3}", counts.active);
+                println!("view      :{:3}", counts.view);
+                println!("touch     :{:3}", counts.touch);
+                println!("step      :{:3}", counts.step);
+            }
+            Err(e) => println!("{}", e),
+        }),
+    );
+}
 
 pub enum ClientSlot {
     Disconnected,
@@ -38,6 +166,68 @@ pub enum ClientSlot {
 pub struct ClientInGame {
     privileged: bool,
     entity_id: EntityId,
+
+    connection: QSocket,
+    name: String,
+    colors: PlayerColor,
+
+    // set once the client has received the level and its player has been put in the game
+    spawned: bool,
+
+    // the frag count last sent to clients, so that changes can be broadcast
+    old_frags: i32,
+
+    // the forward, side and up speeds requested by the client's last move
+    movement: Vector3<f32>,
+
+    // how long the client has gone without input, checked against sv_idlelimit
+    idle: IdleMonitor,
+
+    // chooses the entity updates sent to this client when they don't all fit in a datagram
+    priority: EntityPriority,
+
+    // messages to be sent reliably to this client only
+    message: Vec<u8>,
+
+    // carried between levels, e.g. the player's health and weapons
+    spawn_parms: [f32; NUM_SPAWN_PARMS],
+}
+
+impl ClientInGame {
+    /// Creates a client that has just connected over `connection` at `time` and has yet to sign
+    /// on.
+    pub fn new(entity_id: EntityId, connection: QSocket, time: Duration) -> ClientInGame {
+        ClientInGame {
+            privileged: false,
+            entity_id,
+            connection,
+            name: String::from("unconnected"),
+            colors: PlayerColor::from_bits(0),
+            spawned: false,
+            old_frags: 0,
+            movement: Vector3::zero(),
+            idle: IdleMonitor::new(time),
+            priority: EntityPriority::new(),
+            message: Vec::new(),
+            spawn_parms: [0.0; NUM_SPAWN_PARMS],
+        }
+    }
+}
+
+/// The destination of a message written by the progs with the `Write*` builtins.
+#[derive(Clone, Copy, Debug, Eq, FromPrimitive, PartialEq)]
+pub enum MsgDest {
+    /// Sent unreliably to every client.
+    Broadcast = 0,
+
+    /// Sent reliably to the client in the `msg_entity` global.
+    One = 1,
+
+    /// Sent reliably to every client.
+    All = 2,
+
+    /// Sent to each client during signon.
+    Init = 3,
 }
 
 bitflags! {
@@ -55,8 +245,32 @@ bitflags! {
 pub struct ServerStatics {
     client_slot_limit: usize,
 
+    // progress through the episodes, kept across level changes
+    server_flags: ServerFlags,
+
     client_slot_count: usize,
     client_slots: Vec<ClientSlot>,
+
+    // the cvars set by the current map, which are restored when the next one loads
+    map_config: MapConfig,
+}
+
+impl ServerStatics {
+    /// Creates `client_slot_count` disconnected client slots.
+    pub fn new(client_slot_count: usize) -> ServerStatics {
+        let mut client_slots = Vec::with_capacity(client_slot_count);
+        for _ in 0..client_slot_count {
+            client_slots.push(ClientSlot::Disconnected);
+        }
+
+        ServerStatics {
+            client_slot_limit: client_slot_count,
+            server_flags: ServerFlags::empty(),
+            client_slot_count,
+            client_slots,
+            map_config: MapConfig::new(ConfigSide::Server),
+        }
+    }
 }
 
 pub struct Server {
@@ -64,11 +278,52 @@ pub struct Server {
     sound_precache: Vec<String>,
     model_precache: Vec<String>,
     lightstyles: [StringId; MAX_LIGHTSTYLES],
-    datagram: Cursor<Box<[u8]>>,
+
+    // messages to be sent unreliably to all clients
+    datagram: Vec<u8>,
+
+    // messages to be sent reliably to all clients
+    reliable_datagram: Vec<u8>,
+
+    // messages sent to each client during signon, such as static entities and sounds
+    signon: Vec<u8>,
+
+    // messages about events at a point, sent only to clients in range
+    multicast: Multicast,
+
+    // if true, entity physics is not run
+    paused: bool,
+
+    // callbacks registered by the program embedding the server
+    hooks: Vec<Box<dyn ServerHooks>>,
+
+    // the progs may only request one level change per level
+    changelevel_issued: bool,
+
+    // the source of all gameplay randomness, saved with the game
+    rng: GameRng,
+
+    // the simulation time of the current level
+    time: Duration,
+
+    // the client monsters are currently looking for, the leaf it views from and when it was
+    // chosen
+    check_client: EntityId,
+    check_leaf: usize,
+    check_time: Duration,
+
+    // console commands queued by the progs for the host to run
+    local_cmds: String,
+
+    // the protocol every message of the current level is encoded with
+    protocol: Protocol,
+
+    // client slots, which outlive the level
+    statics: ServerStatics,
 }
 
 impl Server {
-    pub fn new(string_table: Rc<StringTable>) -> Server {
+    pub fn new(string_table: Rc<StringTable>, statics: ServerStatics) -> Server {
         let mut sound_precache = Vec::new();
         sound_precache.push(String::new()); // sound 0 is none
 
@@ -80,10 +335,87 @@ impl Server {
             sound_precache,
             model_precache,
             lightstyles: [StringId(0); MAX_LIGHTSTYLES],
-            datagram: Cursor::new(Box::new([0; MAX_DATAGRAM])),
+            datagram: Vec::with_capacity(MAX_DATAGRAM),
+            reliable_datagram: Vec::new(),
+            signon: Vec::new(),
+            multicast: Multicast::new(),
+            paused: false,
+            hooks: Vec::new(),
+            changelevel_issued: false,
+            rng: GameRng::from_entropy(),
+
+            // levels start at one second so that entities spawned with a `nextthink` of zero
+            // don't think
+            time: Duration::seconds(1),
+            check_client: EntityId(0),
+            check_leaf: 0,
+            check_time: Duration::zero(),
+            local_cmds: String::new(),
+            protocol: Protocol::NetQuake,
+            statics,
         }
     }
 
+    /// Ends the level, returning the client slots for the next one.
+    pub fn into_statics(self) -> ServerStatics {
+        self.statics
+    }
+
+    /// Returns the protocol used by the current level.
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Sets the protocol used by the current level.
+    ///
+    /// This must be called before any messages are queued, since they are encoded as they are
+    /// queued.
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+        self.multicast.set_codec(protocol.codec());
+    }
+
+    /// Returns the simulation time of the current level.
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    /// Advances the simulation time by one frame.
+    pub fn advance_time(&mut self, frame_time: Duration) {
+        self.time = self.time + frame_time;
+    }
+
+    /// Returns the number of client slots, which occupy entities 1 through `max_clients`.
+    pub fn max_clients(&self) -> usize {
+        self.statics.client_slot_count
+    }
+
+    /// Returns `true` if a client is connected to the slot with entity ID `entity_id`.
+    pub fn client_active(&self, entity_id: EntityId) -> bool {
+        match entity_id
+            .0
+            .checked_sub(1)
+            .and_then(|i| self.statics.client_slots.get(i))
+        {
+            Some(ClientSlot::InGame(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Restarts gameplay randomness from `seed`, e.g. from `random::game_seed`.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = GameRng::new(seed);
+    }
+
+    /// Returns the generator used for gameplay randomness, such as QuakeC's `random()`.
+    pub fn rng(&self) -> &GameRng {
+        &self.rng
+    }
+
+    pub fn rng_mut(&mut self) -> &mut GameRng {
+        &mut self.rng
+    }
+
     pub fn precache_sound(&mut self, name_id: StringId) {
         let name = self.string_table.get(name_id).unwrap();
 
@@ -109,6 +441,16 @@ impl Server {
         }
     }
 
+    /// Returns the precache index of the sound with the given name.
+    pub fn sound_precache_index(&self, name: &str) -> Option<usize> {
+        self.sound_precache.iter().position(|s| s == name)
+    }
+
+    /// Returns the precache index of the model with the given name.
+    pub fn model_precache_index(&self, name: &str) -> Option<usize> {
+        self.model_precache.iter().position(|m| m == name)
+    }
+
     pub fn precache_model(&mut self, name_id: StringId) {
         let name = self.string_table.get(name_id).unwrap();
 
@@ -138,15 +480,363 @@ impl Server {
         }
     }
 
+    /// Returns the messages queued for unreliable delivery to all clients.
+    pub fn datagram(&self) -> &[u8] {
+        &self.datagram
+    }
+
     pub fn clear_datagram(&mut self) {
-        self.datagram.seek(SeekFrom::Start(0)).unwrap();
-        for _ in 0..self.datagram.get_ref().len() {
-            self.datagram.write_u8(0).unwrap();
-        }
-        self.datagram.seek(SeekFrom::Start(0)).unwrap();
+        self.datagram.clear();
     }
 
+    /// Sets the pattern of a lightstyle and sends it to all clients.
     pub fn set_lightstyle(&mut self, lightstyle_index: usize, lightstyle_val_id: StringId) {
         self.lightstyles[lightstyle_index] = lightstyle_val_id;
+
+        let value = self.string_table.get(lightstyle_val_id).unwrap_or_default();
+        self.broadcast(&ServerCmd::LightStyle {
+            id: lightstyle_index as u8,
+            value,
+        });
+    }
+
+    /// Returns `true` if the server is paused.
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Toggles the pause state of the server on behalf of a client.
+    ///
+    /// If `pausable` is false, the pause state is left unchanged and this returns `None`.
+    /// Otherwise it returns the new pause state, and a `SetPause` message is queued on the
+    /// reliable datagram so that clients can freeze their own simulation and sound.
+    pub fn toggle_pause(&mut self, pausable: bool) -> Option<bool> {
+        if !pausable {
+            return None;
+        }
+
+        self.paused = !self.paused;
+        ServerCmd::SetPause {
+            paused: self.paused,
+        }
+        .serialize_with(&mut self.reliable_datagram, self.protocol.codec())
+        .unwrap();
+
+        Some(self.paused)
+    }
+
+    /// Returns the messages queued for reliable delivery to all clients.
+    pub fn reliable_datagram(&self) -> &[u8] {
+        &self.reliable_datagram
+    }
+
+    /// Queues `cmd` for reliable delivery to all clients.
+    pub fn broadcast(&mut self, cmd: &ServerCmd) {
+        cmd.serialize_with(&mut self.reliable_datagram, self.protocol.codec())
+            .unwrap();
+    }
+
+    /// Empties the reliable datagram once it has been sent, keeping its storage for the next
+    /// frame.
+    pub fn clear_reliable_datagram(&mut self) {
+        self.reliable_datagram.clear();
+    }
+
+    /// Queues a message to be sent to every client that connects to the current level.
+    ///
+    /// This is used for objects that never change once spawned, like static entities and ambient
+    /// sounds, so they don't need to be sent in every update.
+    pub fn add_signon_cmd(&mut self, cmd: ServerCmd) {
+        cmd.serialize_with(&mut self.signon, self.protocol.codec())
+            .unwrap();
+    }
+
+    /// Returns the messages sent to each client during signon.
+    pub fn signon(&self) -> &[u8] {
+        &self.signon
+    }
+
+    fn client(&self, entity_id: EntityId) -> Option<&ClientInGame> {
+        match entity_id
+            .0
+            .checked_sub(1)
+            .and_then(|i| self.statics.client_slots.get(i))
+        {
+            Some(ClientSlot::InGame(ref client)) => Some(client),
+            _ => None,
+        }
+    }
+
+    fn client_mut(&mut self, entity_id: EntityId) -> Option<&mut ClientInGame> {
+        match entity_id
+            .0
+            .checked_sub(1)
+            .and_then(move |i| self.statics.client_slots.get_mut(i))
+        {
+            Some(ClientSlot::InGame(ref mut client)) => Some(client),
+            _ => None,
+        }
+    }
+
+    /// Returns the reliable messages queued for the client with entity ID `entity_id`, or `None`
+    /// if no client is connected to that slot.
+    pub fn client_message_mut(&mut self, entity_id: EntityId) -> Option<&mut Vec<u8>> {
+        self.client_mut(entity_id).map(|c| &mut c.message)
+    }
+
+    /// Queues `cmd` for reliable delivery to the client with entity ID `entity_id`.
+    ///
+    /// Returns `false` if no client is connected to that slot.
+    pub fn send_to_client(&mut self, entity_id: EntityId, cmd: &ServerCmd) -> bool {
+        let codec = self.protocol.codec();
+        match self.client_message_mut(entity_id) {
+            Some(message) => {
+                cmd.serialize_with(message, codec).unwrap();
+                true
+            }
+
+            None => false,
+        }
+    }
+
+    /// Returns the spawn parameters of the client with entity ID `entity_id`.
+    pub fn client_spawn_parms(&self, entity_id: EntityId) -> Option<[f32; NUM_SPAWN_PARMS]> {
+        match entity_id
+            .0
+            .checked_sub(1)
+            .and_then(|i| self.statics.client_slots.get(i))
+        {
+            Some(ClientSlot::InGame(ref client)) => Some(client.spawn_parms),
+            _ => None,
+        }
+    }
+
+    /// Returns the buffer that messages written to `dest` are queued in.
+    ///
+    /// Messages for `MsgDest::One` go to the client with entity ID `msg_entity`. Returns `None`
+    /// if no client is connected to that slot.
+    pub fn message_dest_mut(
+        &mut self,
+        dest: MsgDest,
+        msg_entity: EntityId,
+    ) -> Option<&mut Vec<u8>> {
+        match dest {
+            MsgDest::Broadcast => Some(&mut self.datagram),
+            MsgDest::One => self.client_message_mut(msg_entity),
+            MsgDest::All => Some(&mut self.reliable_datagram),
+            MsgDest::Init => Some(&mut self.signon),
+        }
+    }
+
+    /// Queues a console command from the progs for the host to run.
+    pub fn queue_local_cmd<S>(&mut self, cmd: S)
+    where
+        S: AsRef<str>,
+    {
+        self.local_cmds.push_str(cmd.as_ref());
+    }
+
+    /// Returns the console commands queued by the progs since the last call.
+    pub fn take_local_cmds(&mut self) -> String {
+        std::mem::take(&mut self.local_cmds)
+    }
+
+    /// Returns the client that monsters should look for, and the leaf it is viewing from.
+    ///
+    /// A different living client is chosen every tenth of a second, so that monsters spread
+    /// their attention across all players. Returns `None` if the chosen client is dead or gone.
+    pub fn check_client(&mut self, world: &World) -> Result<Option<(EntityId, usize)>, ProgsError> {
+        if self.time - self.check_time >= Duration::milliseconds(CHECK_CLIENT_INTERVAL_MS) {
+            self.new_check_client(world)?;
+            self.check_time = self.time;
+        }
+
+        let e_id = self.check_client;
+        if !world.entity_exists(e_id) {
+            return Ok(None);
+        }
+
+        let ent = world.try_get_entity(e_id)?;
+        if ent.get_float(FieldAddrFloat::Health as i16)? <= 0.0 {
+            return Ok(None);
+        }
+
+        Ok(Some((e_id, self.check_leaf)))
+    }
+
+    // cycles to the next client that is alive and can be targeted, keeping the current one if
+    // there is no other
+    fn new_check_client(&mut self, world: &World) -> Result<(), ProgsError> {
+        let max_clients = world.max_clients();
+        if max_clients == 0 {
+            return Ok(());
+        }
+
+        let current = self.check_client.0.max(1).min(max_clients);
+        let mut i = current;
+        loop {
+            i = if i == max_clients { 1 } else { i + 1 };
+
+            if i == current {
+                break;
+            }
+
+            let e_id = EntityId(i);
+            if !world.entity_exists(e_id) {
+                continue;
+            }
+
+            let ent = world.try_get_entity(e_id)?;
+            if ent.get_float(FieldAddrFloat::Health as i16)? <= 0.0
+                || ent.flags()?.contains(EntityFlags::NO_TARGET)
+            {
+                continue;
+            }
+
+            break;
+        }
+
+        self.check_client = EntityId(i);
+        if world.entity_exists(self.check_client) {
+            let ent = world.try_get_entity(self.check_client)?;
+            let view =
+                ent.origin()? + Vector3::from(ent.get_vector(FieldAddrVector::ViewOffset as i16)?);
+            self.check_leaf = world.leaf_at(view);
+        }
+
+        Ok(())
+    }
+
+    /// Queues `cmd` for delivery to the clients in `scope` of `origin`.
+    ///
+    /// Unreliable messages may be lost, so they should only be used for effects that don't
+    /// affect the game.
+    pub fn multicast(
+        &mut self,
+        origin: Vector3<f32>,
+        scope: MulticastScope,
+        reliable: bool,
+        cmd: &ServerCmd,
+    ) {
+        self.multicast.queue(origin, scope, reliable, cmd);
+    }
+
+    /// Routes queued multicast messages to clients viewing the world from `view_origins`.
+    ///
+    /// The returned messages are in the same order as `view_origins`.
+    pub fn route_multicast<W>(
+        &mut self,
+        world: &W,
+        view_origins: &[Vector3<f32>],
+    ) -> Vec<ClientMessages>
+    where
+        W: MulticastWorld,
+    {
+        self.multicast.route(world, view_origins)
+    }
+
+    /// Routes queued multicast messages into `routed`, reusing its buffers. See
+    /// `Multicast::route_into`.
+    pub fn route_multicast_into<W>(
+        &mut self,
+        world: &W,
+        view_origins: &[Vector3<f32>],
+        routed: &mut Vec<ClientMessages>,
+    ) where
+        W: MulticastWorld,
+    {
+        self.multicast.route_into(world, view_origins, routed)
+    }
+
+    /// Starts a sound on a channel of an entity.
+    ///
+    /// The sound is sent to clients that can hear `origin`, or to every client if `attenuation`
+    /// is zero or `channel` includes `SOUND_CHANNEL_NO_PHS`. It is sent unreliably unless
+    /// `channel` includes `SOUND_CHANNEL_RELIABLE`. A new sound on a nonzero channel replaces any
+    /// sound already playing on that channel of the entity.
+    pub fn start_sound(
+        &mut self,
+        origin: Vector3<f32>,
+        entity_id: u16,
+        channel: i32,
+        sound_id: u8,
+        volume: f32,
+        attenuation: f32,
+    ) {
+        let volume = (volume * 255.0).max(0.0).min(255.0) as u8;
+        let scope = if attenuation == 0.0 || channel & SOUND_CHANNEL_NO_PHS != 0 {
+            MulticastScope::All
+        } else {
+            MulticastScope::Phs
+        };
+        let reliable = channel & SOUND_CHANNEL_RELIABLE != 0;
+
+        let cmd = ServerCmd::Sound {
+            volume: match volume {
+                DEFAULT_SOUND_VOLUME => None,
+                v => Some(v),
+            },
+            attenuation: if attenuation == DEFAULT_SOUND_ATTENUATION {
+                None
+            } else {
+                Some(attenuation)
+            },
+            entity_id,
+            channel: (channel & 0b111) as i8,
+            sound_id,
+            position: origin,
+        };
+
+        self.multicast(origin, scope, reliable, &cmd);
+    }
+
+    /// Registers a set of callbacks to be invoked as the game progresses.
+    ///
+    /// Hooks are invoked in the order they were added.
+    pub fn add_hooks(&mut self, hooks: Box<dyn ServerHooks>) {
+        self.hooks.push(hooks);
+    }
+
+    /// Invokes `f` on each set of registered hooks.
+    pub(crate) fn dispatch_hooks<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut dyn ServerHooks),
+    {
+        for hooks in self.hooks.iter_mut() {
+            f(hooks.as_mut());
+        }
+    }
+
+    /// Records a level change requested by the progs and notifies any hooks.
+    ///
+    /// Returns `false` if a level change has already been requested on this level, in which case
+    /// the request should be ignored.
+    pub fn request_level_change<S>(&mut self, map_name: S) -> bool
+    where
+        S: AsRef<str>,
+    {
+        if self.changelevel_issued {
+            return false;
+        }
+
+        self.changelevel_issued = true;
+        self.dispatch_hooks(|hooks| hooks.level_changed(map_name.as_ref()));
+        true
+    }
+
+    /// Returns the brightness of every lightstyle at `time`, in the range [0, 2].
+    pub fn lightstyle_values(&self, time: Duration) -> Vec<f32> {
+        self.lightstyles()
+            .iter()
+            .map(|pattern| bsp::lightstyle_value(pattern, time))
+            .collect()
+    }
+
+    /// Returns the current value of every lightstyle.
+    pub fn lightstyles(&self) -> Vec<String> {
+        self.lightstyles
+            .iter()
+            .map(|id| self.string_table.get(*id).unwrap_or_default())
+            .collect()
     }
 }
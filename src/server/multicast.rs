@@ -0,0 +1,346 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Routing of messages about events at a point in the world.
+//!
+//! Sounds, particles and temporary entities happen at a point, and only matter to clients that
+//! could see or hear that point. Rather than broadcasting them, the server queues them with their
+//! origin and a [`MulticastScope`](enum.MulticastScope.html), and once per frame routes each one to
+//! the clients whose view is in range.
+
+use std::{collections::HashMap, ops::Range};
+
+use crate::{
+    common::{
+        net::{codec::WireCodec, ServerCmd},
+        vis::Pvs,
+    },
+    server::world::World,
+};
+
+use cgmath::Vector3;
+
+/// The set of clients a multicast message is delivered to.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MulticastScope {
+    /// Every client, regardless of position.
+    All,
+
+    /// Clients whose view is potentially visible from the origin.
+    Pvs,
+
+    /// Clients whose view is potentially hearable from the origin.
+    Phs,
+}
+
+/// Answers the leaf queries needed to route multicast messages.
+pub trait MulticastWorld {
+    /// Returns the leaves reached by a message sent from `origin` with the given scope.
+    fn leaves_reached(&self, origin: Vector3<f32>, scope: MulticastScope) -> Pvs;
+
+    /// Returns the ID of the leaf containing `point`.
+    fn leaf_at(&self, point: Vector3<f32>) -> usize;
+}
+
+impl MulticastWorld for World {
+    fn leaves_reached(&self, origin: Vector3<f32>, scope: MulticastScope) -> Pvs {
+        match scope {
+            MulticastScope::All => Pvs::all(),
+            MulticastScope::Pvs => self.pvs_at(origin),
+            MulticastScope::Phs => self.phs_at(origin),
+        }
+    }
+
+    fn leaf_at(&self, point: Vector3<f32>) -> usize {
+        self.leaf_at(point)
+    }
+}
+
+/// The messages routed to one client in a frame.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientMessages {
+    /// Messages which must be delivered.
+    pub reliable: Vec<u8>,
+
+    /// Messages which may be dropped if the client's datagram is full or the packet is lost.
+    pub unreliable: Vec<u8>,
+}
+
+struct MulticastMsg {
+    origin: Vector3<f32>,
+    scope: MulticastScope,
+    reliable: bool,
+
+    // location of the serialized message in the queue's data
+    range: Range<usize>,
+}
+
+/// A queue of messages awaiting routing.
+///
+/// Messages are serialized into a single buffer which keeps its storage between frames.
+pub struct Multicast {
+    messages: Vec<MulticastMsg>,
+    data: Vec<u8>,
+    codec: WireCodec,
+
+    // leaves reached from each source leaf and scope, cleared every time the queue is routed
+    reached: HashMap<(usize, MulticastScope), Pvs>,
+}
+
+impl Multicast {
+    pub fn new() -> Multicast {
+        Multicast {
+            messages: Vec::new(),
+            data: Vec::new(),
+            codec: WireCodec::NETQUAKE,
+            reached: HashMap::new(),
+        }
+    }
+
+    /// Sets the encoding of coordinates and angles in messages queued from now on.
+    pub fn set_codec(&mut self, codec: WireCodec) {
+        self.codec = codec;
+    }
+
+    /// Queues `cmd` for delivery to the clients in `scope` of `origin`.
+    pub fn queue(
+        &mut self,
+        origin: Vector3<f32>,
+        scope: MulticastScope,
+        reliable: bool,
+        cmd: &ServerCmd,
+    ) {
+        let start = self.data.len();
+        cmd.serialize_with(&mut self.data, self.codec).unwrap();
+        self.messages.push(MulticastMsg {
+            origin,
+            scope,
+            reliable,
+            range: start..self.data.len(),
+        });
+    }
+
+    /// Returns `true` if no messages are queued.
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Routes all queued messages to clients viewing the world from `view_origins`, emptying the
+    /// queue.
+    ///
+    /// The returned messages are in the same order as `view_origins`.
+    pub fn route<W>(&mut self, world: &W, view_origins: &[Vector3<f32>]) -> Vec<ClientMessages>
+    where
+        W: MulticastWorld,
+    {
+        let mut routed = Vec::new();
+        self.route_into(world, view_origins, &mut routed);
+        routed
+    }
+
+    /// Routes all queued messages like `route`, but into `routed`.
+    ///
+    /// `routed` is resized to one entry per view origin and its existing messages are cleared, so
+    /// passing the same `Vec` each frame avoids reallocating the clients' buffers.
+    pub fn route_into<W>(
+        &mut self,
+        world: &W,
+        view_origins: &[Vector3<f32>],
+        routed: &mut Vec<ClientMessages>,
+    ) where
+        W: MulticastWorld,
+    {
+        let view_leaves: Vec<usize> = view_origins.iter().map(|o| world.leaf_at(*o)).collect();
+        routed.resize_with(view_origins.len(), ClientMessages::default);
+        for client in routed.iter_mut() {
+            client.reliable.clear();
+            client.unreliable.clear();
+        }
+
+        // the visible and audible sets are the same everywhere in a leaf, so only look them up
+        // once per source leaf rather than once per message or client
+        self.reached.clear();
+        for msg in self.messages.drain(..) {
+            let source_leaf = world.leaf_at(msg.origin);
+            let reached = self
+                .reached
+                .entry((source_leaf, msg.scope))
+                .or_insert_with(|| world.leaves_reached(msg.origin, msg.scope));
+
+            for (view_leaf, client) in view_leaves.iter().zip(routed.iter_mut()) {
+                if !reached.contains_leaf(*view_leaf) {
+                    continue;
+                }
+
+                let dest = if msg.reliable {
+                    &mut client.reliable
+                } else {
+                    &mut client.unreliable
+                };
+                dest.extend_from_slice(&self.data[msg.range.clone()]);
+            }
+        }
+
+        self.data.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::net::Protocol;
+    use std::cell::Cell;
+
+    // a world of four leaves along the x-axis, 100 units wide. each leaf can see its neighbors,
+    // and hear one leaf further.
+    struct Corridor;
+
+    impl MulticastWorld for Corridor {
+        fn leaves_reached(&self, origin: Vector3<f32>, scope: MulticastScope) -> Pvs {
+            let range = match scope {
+                MulticastScope::All => return Pvs::all(),
+                MulticastScope::Pvs => 1,
+                MulticastScope::Phs => 2,
+            };
+
+            let leaf = self.leaf_at(origin) as isize;
+            Pvs::from_leaves((0..4).map(|l: isize| (l - leaf).abs() <= range).collect())
+        }
+
+        fn leaf_at(&self, point: Vector3<f32>) -> usize {
+            (point.x / 100.0) as usize
+        }
+    }
+
+    // counts the leaf set lookups made through a corridor
+    struct CountingCorridor(Cell<usize>);
+
+    impl MulticastWorld for CountingCorridor {
+        fn leaves_reached(&self, origin: Vector3<f32>, scope: MulticastScope) -> Pvs {
+            self.0.set(self.0.get() + 1);
+            Corridor.leaves_reached(origin, scope)
+        }
+
+        fn leaf_at(&self, point: Vector3<f32>) -> usize {
+            Corridor.leaf_at(point)
+        }
+    }
+
+    fn at(x: f32) -> Vector3<f32> {
+        Vector3::new(x, 0.0, 0.0)
+    }
+
+    fn cmd() -> ServerCmd {
+        ServerCmd::Particle {
+            origin: at(50.0),
+            direction: Vector3::new(0.0, 0.0, 0.0),
+            count: 1,
+            color: 0,
+        }
+    }
+
+    #[test]
+    fn test_route_by_scope() {
+        let mut mc = Multicast::new();
+        let clients = [at(50.0), at(150.0), at(250.0), at(350.0)];
+
+        mc.queue(at(50.0), MulticastScope::Pvs, false, &cmd());
+        let routed = mc.route(&Corridor, &clients);
+        let received: Vec<bool> = routed.iter().map(|c| !c.unreliable.is_empty()).collect();
+        assert_eq!(received, vec![true, true, false, false]);
+
+        mc.queue(at(50.0), MulticastScope::Phs, false, &cmd());
+        let routed = mc.route(&Corridor, &clients);
+        let received: Vec<bool> = routed.iter().map(|c| !c.unreliable.is_empty()).collect();
+        assert_eq!(received, vec![true, true, true, false]);
+
+        mc.queue(at(50.0), MulticastScope::All, false, &cmd());
+        let routed = mc.route(&Corridor, &clients);
+        assert!(routed.iter().all(|c| !c.unreliable.is_empty()));
+    }
+
+    #[test]
+    fn test_route_into_reuses_buffers() {
+        let mut mc = Multicast::new();
+        let mut routed = Vec::new();
+
+        mc.queue(at(50.0), MulticastScope::All, false, &cmd());
+        mc.route_into(&Corridor, &[at(50.0), at(350.0)], &mut routed);
+        assert_eq!(routed.len(), 2);
+        let capacity = routed[0].unreliable.capacity();
+        assert!(capacity > 0);
+
+        // messages from the previous frame are cleared
+        mc.queue(at(50.0), MulticastScope::Pvs, false, &cmd());
+        mc.route_into(&Corridor, &[at(50.0), at(350.0)], &mut routed);
+        assert_eq!(routed[0].unreliable.capacity(), capacity);
+        assert!(!routed[0].unreliable.is_empty());
+        assert!(routed[1].unreliable.is_empty());
+    }
+
+    #[test]
+    fn test_route_caches_leaves_per_source_leaf() {
+        let mut mc = Multicast::new();
+        let world = CountingCorridor(Cell::new(0));
+        let clients = [at(50.0), at(350.0)];
+
+        mc.queue(at(10.0), MulticastScope::Phs, false, &cmd());
+        mc.queue(at(90.0), MulticastScope::Phs, false, &cmd());
+        mc.queue(at(50.0), MulticastScope::Pvs, false, &cmd());
+        mc.queue(at(150.0), MulticastScope::Phs, false, &cmd());
+        mc.route(&world, &clients);
+        assert_eq!(world.0.get(), 3);
+
+        // the cache doesn't outlive the frame
+        mc.queue(at(10.0), MulticastScope::Phs, false, &cmd());
+        mc.route(&world, &clients);
+        assert_eq!(world.0.get(), 4);
+    }
+
+    #[test]
+    fn test_route_reliability() {
+        let mut mc = Multicast::new();
+        let mut expected = Vec::new();
+        cmd().serialize(&mut expected).unwrap();
+
+        mc.queue(at(50.0), MulticastScope::All, true, &cmd());
+        mc.queue(at(50.0), MulticastScope::All, false, &cmd());
+        mc.queue(at(50.0), MulticastScope::All, false, &cmd());
+        assert!(!mc.is_empty());
+
+        let routed = mc.route(&Corridor, &[at(50.0)]);
+        assert!(mc.is_empty());
+        assert_eq!(routed[0].reliable, expected);
+        assert_eq!(
+            routed[0].unreliable,
+            [&expected[..], &expected[..]].concat()
+        );
+    }
+
+    #[test]
+    fn test_queue_uses_codec() {
+        let codec = Protocol::Rmq.codec();
+        let mut mc = Multicast::new();
+        mc.set_codec(codec);
+        let mut expected = Vec::new();
+        cmd().serialize_with(&mut expected, codec).unwrap();
+
+        mc.queue(at(50.0), MulticastScope::All, true, &cmd());
+        let routed = mc.route(&Corridor, &[at(50.0)]);
+        assert_eq!(routed[0].reliable, expected);
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Prioritization of entity updates.
+//!
+//! The original engine writes entity updates in entity order and stops when the datagram is full,
+//! so in a busy level the entities with the highest numbers vanish, however close or important
+//! they are. Instead, each entity in a client's PVS is given a priority and updates are written
+//! from the highest priority down until the budget is spent:
+//!
+//! - the client's own entity and anything it owns, such as its missiles, come first, however far
+//!   away they are;
+//! - entities the client is facing come before those behind it;
+//! - nearer entities come before farther ones;
+//! - entities that have just changed come before those that have been still for a while;
+//! - every frame an entity is left out raises its priority, so nothing is starved for long.
+//!
+//! Under load, far away decorations that aren't changing are the first to be dropped.
+
+/// How much an entity matters to a particular client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Relevance {
+    /// An entity the client isn't facing.
+    Background,
+
+    /// An entity in front of the client.
+    Visible,
+
+    /// The client's own entity or an entity it owns.
+    Owned,
+}
+
+impl Relevance {
+    fn weight(&self) -> f32 {
+        match self {
+            Relevance::Background => 1.0,
+            Relevance::Visible => 2.0,
+            Relevance::Owned => 8.0,
+        }
+    }
+}
+
+// distance at which an entity's priority is halved
+const HALF_PRIORITY_DISTANCE: f32 = 512.0;
+
+/// An entity that could be sent to a client this frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Candidate {
+    pub entity_id: usize,
+
+    /// The distance from the client's view to the entity.
+    pub distance: f32,
+
+    /// The number of frames since the entity last changed. 0 means it changed this frame.
+    pub frames_since_change: u32,
+
+    pub relevance: Relevance,
+
+    /// The size in bytes of the entity's update.
+    pub size: usize,
+}
+
+/// Chooses the entity updates sent to one client.
+///
+/// Each client needs its own `EntityPriority`, since it remembers how long each entity has gone
+/// unsent.
+#[derive(Clone, Debug, Default)]
+pub struct EntityPriority {
+    // frames since each entity was last sent, indexed by entity ID
+    frames_unsent: Vec<u32>,
+}
+
+impl EntityPriority {
+    pub fn new() -> EntityPriority {
+        EntityPriority {
+            frames_unsent: Vec::new(),
+        }
+    }
+
+    /// Returns the number of frames `entity_id` has been left out of the client's updates.
+    pub fn frames_unsent(&self, entity_id: usize) -> u32 {
+        self.frames_unsent.get(entity_id).copied().unwrap_or(0)
+    }
+
+    /// Returns the priority of sending `candidate` this frame.
+    pub fn priority(&self, candidate: &Candidate) -> f32 {
+        // the client follows its own missiles wherever they go
+        let distance = match candidate.relevance {
+            Relevance::Owned => 0.0,
+            _ => candidate.distance.max(0.0) / HALF_PRIORITY_DISTANCE,
+        };
+        let change = 1.0 + 1.0 / (1.0 + candidate.frames_since_change as f32);
+        let starvation = 1.0 + self.frames_unsent(candidate.entity_id) as f32;
+
+        candidate.relevance.weight() * change * starvation / (1.0 + distance)
+    }
+
+    /// Chooses which candidates to send in `budget` bytes, returning their entity IDs in
+    /// ascending order.
+    ///
+    /// Candidates are taken in order of priority. One that doesn't fit in the remaining budget is
+    /// skipped, but smaller ones after it may still be sent. The client's own entity should be
+    /// among the candidates with `Relevance::Owned`, so that it is chosen ahead of the rest.
+    pub fn select(&mut self, candidates: &[Candidate], budget: usize) -> Vec<usize> {
+        let mut ranked: Vec<(f32, &Candidate)> =
+            candidates.iter().map(|c| (self.priority(c), c)).collect();
+
+        // highest priority first, lower entity IDs first among equals
+        ranked.sort_by(|(pa, a), (pb, b)| {
+            pb.partial_cmp(pa)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.entity_id.cmp(&b.entity_id))
+        });
+
+        let mut remaining = budget;
+        let mut selected = Vec::new();
+        for (_, candidate) in ranked {
+            if candidate.size <= remaining {
+                remaining -= candidate.size;
+                selected.push(candidate.entity_id);
+            }
+        }
+
+        // entities that left the PVS start afresh if they come back
+        let mut frames_unsent = vec![0; self.frames_unsent.len()];
+        for candidate in candidates {
+            let id = candidate.entity_id;
+            if id >= frames_unsent.len() {
+                frames_unsent.resize(id + 1, 0);
+            }
+            frames_unsent[id] = self.frames_unsent(id) + 1;
+        }
+        for id in selected.iter() {
+            frames_unsent[*id] = 0;
+        }
+        self.frames_unsent = frames_unsent;
+
+        selected.sort();
+        selected
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidate(entity_id: usize, distance: f32, relevance: Relevance) -> Candidate {
+        Candidate {
+            entity_id,
+            distance,
+            frames_since_change: 10,
+            relevance,
+            size: 10,
+        }
+    }
+
+    #[test]
+    fn test_priority_order() {
+        let priority = EntityPriority::new();
+        let near = candidate(1, 64.0, Relevance::Visible);
+        let far = candidate(2, 2048.0, Relevance::Visible);
+        let behind = candidate(3, 64.0, Relevance::Background);
+        let missile = candidate(4, 2048.0, Relevance::Owned);
+        let changed = Candidate {
+            frames_since_change: 0,
+            ..far
+        };
+
+        assert!(priority.priority(&near) > priority.priority(&far));
+        assert!(priority.priority(&near) > priority.priority(&behind));
+        assert!(priority.priority(&missile) > priority.priority(&near));
+        assert!(priority.priority(&changed) > priority.priority(&far));
+    }
+
+    #[test]
+    fn test_select_drops_far_decorations_first() {
+        let mut priority = EntityPriority::new();
+        let candidates = [
+            candidate(1, 0.0, Relevance::Owned),
+            candidate(2, 3000.0, Relevance::Background),
+            candidate(3, 100.0, Relevance::Visible),
+            candidate(4, 200.0, Relevance::Background),
+        ];
+
+        assert_eq!(priority.select(&candidates, 30), vec![1, 3, 4]);
+        assert_eq!(priority.frames_unsent(2), 1);
+        assert_eq!(priority.frames_unsent(3), 0);
+
+        // everything fits
+        assert_eq!(priority.select(&candidates, 1000), vec![1, 2, 3, 4]);
+        assert_eq!(priority.frames_unsent(2), 0);
+    }
+
+    #[test]
+    fn test_select_fills_budget_with_smaller_updates() {
+        let mut priority = EntityPriority::new();
+        let big = Candidate {
+            size: 50,
+            ..candidate(1, 0.0, Relevance::Visible)
+        };
+        let small = candidate(2, 1000.0, Relevance::Background);
+
+        assert_eq!(priority.select(&[big, small], 40), vec![2]);
+    }
+
+    #[test]
+    fn test_select_avoids_starvation() {
+        let mut priority = EntityPriority::new();
+        let candidates = [
+            candidate(1, 100.0, Relevance::Visible),
+            candidate(2, 3000.0, Relevance::Background),
+        ];
+
+        // the far entity is left out at first, but not forever
+        let mut sent_far = false;
+        for _ in 0..100 {
+            if priority.select(&candidates, 10).contains(&2) {
+                sent_far = true;
+                break;
+            }
+        }
+        assert!(sent_far);
+    }
+}
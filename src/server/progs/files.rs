@@ -0,0 +1,217 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+
+//! Files opened by progs with the FRIK_FILE extension.
+//!
+//! Progs can only reach files under `data/`. Files opened for reading are found through the
+//! virtual filesystem, so they can come from a PAK, while files opened for writing are created in
+//! the active game directory.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+};
+
+use crate::common::vfs::Vfs;
+
+use num::FromPrimitive;
+
+/// The most files progs can have open at once.
+pub const MAX_FILES: usize = 16;
+
+// the directory progs files live in, relative to the game directory
+const DATA_DIR: &str = "data";
+
+#[derive(Copy, Clone, Debug, Eq, FromPrimitive, PartialEq)]
+pub enum FileMode {
+    Read = 0,
+    Append = 1,
+    Write = 2,
+}
+
+enum ProgsFile {
+    // the lines not yet read
+    Read(VecDeque<String>),
+    Write(File),
+}
+
+pub struct ProgsFiles {
+    files: Vec<Option<ProgsFile>>,
+}
+
+impl ProgsFiles {
+    pub fn new() -> ProgsFiles {
+        ProgsFiles { files: Vec::new() }
+    }
+
+    /// Opens `data/<name>` and returns its handle.
+    ///
+    /// Returns `None` if the name reaches outside `data/`, the file can't be opened or too many
+    /// files are open.
+    pub fn open(&mut self, vfs: &Vfs, name: &str, mode: f32) -> Option<usize> {
+        if !valid_name(name) {
+            warn!("fopen: bad file name {:?}", name);
+            return None;
+        }
+
+        let mode = match FileMode::from_i32(mode as i32) {
+            Some(m) => m,
+            None => {
+                warn!("fopen: bad mode {}", mode);
+                return None;
+            }
+        };
+
+        let handle = match self.files.iter().position(Option::is_none) {
+            Some(h) => h,
+            None if self.files.len() < MAX_FILES => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+            None => {
+                warn!("fopen: too many open files");
+                return None;
+            }
+        };
+
+        let file = match open_file(vfs, name, mode) {
+            Ok(f) => f,
+            Err(e) => {
+                debug!("fopen: couldn't open {}: {}", name, e);
+                return None;
+            }
+        };
+
+        self.files[handle] = Some(file);
+        Some(handle)
+    }
+
+    /// Closes a file. Returns `false` if `handle` isn't open.
+    pub fn close(&mut self, handle: usize) -> bool {
+        match self.files.get_mut(handle) {
+            Some(f @ Some(_)) => {
+                *f = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the next line of a file opened for reading, without its line ending.
+    ///
+    /// Returns `None` at the end of the file or if `handle` isn't open for reading.
+    pub fn read_line(&mut self, handle: usize) -> Option<String> {
+        match self.files.get_mut(handle) {
+            Some(Some(ProgsFile::Read(lines))) => lines.pop_front(),
+            _ => None,
+        }
+    }
+
+    /// Writes `text` to a file opened for writing or appending.
+    pub fn write(&mut self, handle: usize, text: &str) -> io::Result<()> {
+        match self.files.get_mut(handle) {
+            Some(Some(ProgsFile::Write(file))) => file.write_all(text.as_bytes()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "file not open for writing",
+            )),
+        }
+    }
+}
+
+// returns false if the name is empty or could reach outside the data directory
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('/')
+        && !name.contains('\\')
+        && !name.contains(':')
+        && !name.split('/').any(|part| part == "..")
+}
+
+fn open_file(vfs: &Vfs, name: &str, mode: FileMode) -> io::Result<ProgsFile> {
+    if mode == FileMode::Read {
+        let mut text = String::new();
+        vfs.open(format!("{}/{}", DATA_DIR, name))
+            .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e.to_string()))?
+            .read_to_string(&mut text)?;
+        return Ok(ProgsFile::Read(text.lines().map(String::from).collect()));
+    }
+
+    let dir = vfs
+        .write_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no game directory to write to"))?;
+    let path = dir.join(DATA_DIR).join(name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(mode == FileMode::Append)
+        .truncate(mode == FileMode::Write)
+        .open(path)?;
+    Ok(ProgsFile::Write(file))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_valid_name() {
+        assert!(valid_name("scores.txt"));
+        assert!(valid_name("bots/names.txt"));
+        assert!(!valid_name(""));
+        assert!(!valid_name("/etc/passwd"));
+        assert!(!valid_name("../config.cfg"));
+        assert!(!valid_name("c:\\autoexec.bat"));
+    }
+
+    #[test]
+    fn test_write_then_read() {
+        let dir = std::env::temp_dir().join(format!("richter-progs-files-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let vfs = Vfs::with_base_dir(&dir).unwrap();
+        let mut files = ProgsFiles::new();
+
+        let h = files
+            .open(&vfs, "test.txt", FileMode::Write as i32 as f32)
+            .unwrap();
+        files.write(h, "first\n").unwrap();
+        assert!(files.close(h));
+        let h = files
+            .open(&vfs, "test.txt", FileMode::Append as i32 as f32)
+            .unwrap();
+        files.write(h, "second\n").unwrap();
+        assert!(files.close(h));
+
+        let h = files
+            .open(&vfs, "test.txt", FileMode::Read as i32 as f32)
+            .unwrap();
+        assert!(files.write(h, "third\n").is_err());
+        assert_eq!(files.read_line(h).as_deref(), Some("first"));
+        assert_eq!(files.read_line(h).as_deref(), Some("second"));
+        assert_eq!(files.read_line(h), None);
+        assert!(files.close(h));
+        assert!(!files.close(h));
+
+        assert!(files.open(&vfs, "missing.txt", 0.0).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
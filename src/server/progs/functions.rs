@@ -67,6 +67,13 @@ impl TryInto<i32> for FunctionId {
 #[derive(Debug)]
 pub enum FunctionKind {
     BuiltIn(BuiltinFunctionId),
+
+    /// A built-in function this engine does not provide.
+    ///
+    /// Extension builtins are often declared by progs that only call them after probing with
+    /// `checkextension`, so these are accepted at load time and only fail if actually called.
+    UnknownBuiltIn(i32),
+
     QuakeC(usize),
 }
 
@@ -145,6 +152,48 @@ pub enum BuiltinFunctionId {
     PrecacheSound2 = 76,
     PrecacheFile2 = 77,
     SetSpawnArgs = 78,
+
+    // pr_builtin[79] and up are engine extensions. These use the numbering established by
+    // DarkPlaces and FTE so that mod progs compiled against their extension headers work as-is.
+    StoF = 81,
+    TraceBox = 90,
+    GetLight = 92,
+    FindFloat = 98,
+    CheckExtension = 99,
+    FOpen = 110,
+    FClose = 111,
+    FGetS = 112,
+    FPutS = 113,
+    StrLen = 114,
+    StrCat = 115,
+    Substring = 116,
+    StoV = 117,
+    StrZone = 118,
+    StrUnzone = 119,
+}
+
+/// Engine extensions reported as available by the `checkextension` builtin.
+///
+/// Only extensions whose builtins and behavior are fully implemented belong here, since progs
+/// use these to decide which code paths are safe to run.
+pub const EXTENSIONS: &[&str] = &[
+    "DP_QC_FINDFLOAT",
+    "DP_QC_GETLIGHT",
+    "DP_QC_TRACEBOX",
+    "DP_SV_ROTATINGBMODEL",
+    "FRIK_FILE",
+];
+
+/// Returns `true` if the named extension is supported.
+///
+/// Extension names are matched case-insensitively.
+pub fn extension_supported<S>(name: S) -> bool
+where
+    S: AsRef<str>,
+{
+    EXTENSIONS
+        .iter()
+        .any(|e| e.eq_ignore_ascii_case(name.as_ref()))
 }
 
 #[derive(Debug)]
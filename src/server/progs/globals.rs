@@ -17,8 +17,9 @@
 
 use std::{convert::TryInto, error::Error, fmt, rc::Rc};
 
-use crate::server::progs::{
-    EntityId, FieldAddr, FunctionId, GlobalDef, StringId, StringTable, Type,
+use crate::server::{
+    progs::{EntityId, FieldAddr, FunctionId, GlobalDef, StringId, StringTable, Type},
+    save,
 };
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
@@ -425,6 +426,75 @@ impl Globals {
         Ok(())
     }
 
+    fn find_def<S>(&self, name: S) -> Option<&GlobalDef>
+    where
+        S: AsRef<str>,
+    {
+        self.defs
+            .iter()
+            .find(|def| self.string_table.get(def.name_id).as_deref() == Some(name.as_ref()))
+    }
+
+    /// Returns the name and value of every global which is preserved in saved games.
+    ///
+    /// As in the original engine, only globals flagged for saving with string, float or entity
+    /// type are included. Values are formatted as they appear in save files.
+    pub fn save_values(&self) -> Result<Vec<(String, String)>, GlobalsError> {
+        let mut values = Vec::new();
+
+        for def in self.defs.iter().filter(|def| def.save) {
+            let addr = def.offset as i16;
+            let value = match def.type_ {
+                Type::QString => self
+                    .string_table
+                    .get(self.get_string_id(addr)?)
+                    .unwrap_or_default(),
+                Type::QFloat => save::format_float(self.get_float(addr)?),
+                Type::QEntity => self.get_entity_id(addr)?.0.to_string(),
+                _ => continue,
+            };
+
+            values.push((self.string_table.get(def.name_id).unwrap(), value));
+        }
+
+        Ok(values)
+    }
+
+    /// Sets the global with the given name from a value in the save file format.
+    pub fn restore_value<S>(&mut self, name: S, value: S) -> Result<(), GlobalsError>
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref();
+        let value = value.as_ref();
+
+        let (type_, addr) = match self.find_def(name) {
+            Some(def) => (def.type_, def.offset as i16),
+            None => {
+                return Err(GlobalsError::with_msg(format!(
+                    "'{}' is not a global",
+                    name
+                )))
+            }
+        };
+
+        let invalid = || GlobalsError::with_msg(format!("Invalid value for {}: {}", name, value));
+
+        match type_ {
+            Type::QString => {
+                let s_id = self.string_table.insert(save::unescape_string(value));
+                self.put_string_id(s_id, addr)?;
+            }
+            Type::QFloat => self.put_float(value.parse().map_err(|_| invalid())?, addr)?,
+            Type::QEntity => {
+                self.put_entity_id(EntityId(value.parse().map_err(|_| invalid())?), addr)?
+            }
+            _ => return Err(invalid()),
+        }
+
+        Ok(())
+    }
+
     /// Copies the data at `src_addr` to `dst_addr` without type checking.
     pub fn untyped_copy(&mut self, src_addr: i16, dst_addr: i16) -> Result<(), GlobalsError> {
         let src = self.get_addr(src_addr)?.to_owned();
@@ -488,6 +558,28 @@ impl Globals {
         Ok(())
     }
 
+    /// Calculate the unit vector in the direction of a vector.
+    ///
+    /// Loads the vector from `GLOBAL_ADDR_ARG_0` and stores the unit vector at
+    /// `GLOBAL_ADDR_RETURN`. The zero vector is returned unchanged.
+    pub fn normalize(&mut self) -> Result<(), GlobalsError> {
+        let v = Vector3::from(self.get_vector(GLOBAL_ADDR_ARG_0 as i16)?);
+        let len = v.magnitude();
+        let n = if len == 0.0 { v } else { v / len };
+        self.put_vector(n.into(), GLOBAL_ADDR_RETURN as i16)?;
+        Ok(())
+    }
+
+    /// Calculate pitch, yaw and roll angles from a direction vector.
+    ///
+    /// Loads the direction vector from `GLOBAL_ADDR_ARG_0` and stores the angles at
+    /// `GLOBAL_ADDR_RETURN`.
+    pub fn vec_to_angles(&mut self) -> Result<(), GlobalsError> {
+        let v = self.get_vector(GLOBAL_ADDR_ARG_0 as i16)?;
+        self.put_vector(vec_to_angles(v), GLOBAL_ADDR_RETURN as i16)?;
+        Ok(())
+    }
+
     /// Round a float to the nearest integer.
     ///
     /// Loads the float from `GLOBAL_ADDR_ARG_0` and stores the rounded value at
@@ -537,6 +629,23 @@ pub fn make_vectors(angles: [f32; 3]) -> Matrix3<f32> {
     Matrix3::from(Euler::new(roll, pitch, yaw))
 }
 
+/// Calculate the `[pitch, yaw, 0]` angles pointing along `v`.
+///
+/// As in the original engine, both angles are truncated to whole degrees and lie in `[0, 360)`.
+pub fn vec_to_angles(v: [f32; 3]) -> [f32; 3] {
+    if v[0] == 0.0 && v[1] == 0.0 {
+        let pitch = if v[2] > 0.0 { 90.0 } else { 270.0 };
+        return [pitch, 0.0, 0.0];
+    }
+
+    let wrap = |a: f32| if a < 0.0 { a + 360.0 } else { a };
+    let yaw = wrap(v[1].atan2(v[0]).to_degrees().trunc());
+    let forward = (v[0] * v[0] + v[1] * v[1]).sqrt();
+    let pitch = wrap(v[2].atan2(forward).to_degrees().trunc());
+
+    [pitch, yaw, 0.0]
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -570,4 +679,12 @@ mod test {
         let result = make_vectors(roll_90);
         assert_eq!(Matrix3::from_angle_x(Deg(90.0)), result);
     }
+
+    #[test]
+    fn test_vec_to_angles() {
+        assert_eq!(vec_to_angles([1.0, 0.0, 0.0]), [0.0, 0.0, 0.0]);
+        assert_eq!(vec_to_angles([0.0, -1.0, 0.0]), [0.0, 270.0, 0.0]);
+        assert_eq!(vec_to_angles([0.0, 0.0, 1.0]), [90.0, 0.0, 0.0]);
+        assert_eq!(vec_to_angles([1.0, 0.0, -1.0]), [315.0, 0.0, 0.0]);
+    }
 }
@@ -93,6 +93,7 @@
 //! arg_sizes: [u8; 8],    // sizes of each argument
 //! ```
 
+mod files;
 mod functions;
 mod globals;
 mod ops;
@@ -108,31 +109,36 @@ use std::{
 };
 
 use crate::{
-    common::{console::CvarRegistry, vfs::Vfs},
+    common::{console::CvarRegistry, engine, net::ServerCmd, vfs::Vfs},
     server::{
-        world::{EntityError, EntityTypeDef, FieldAddrFloat, World},
-        Server,
+        multicast::MulticastScope,
+        world::{
+            CollideKind, EntityError, EntitySolid, EntityTypeDef, FieldAddrEntityId,
+            FieldAddrFloat, FieldAddrVector, PhysicsContext, Trace, World,
+        },
+        MsgDest, Server,
     },
 };
 
-use byteorder::{LittleEndian, ReadBytesExt};
-use cgmath::Vector3;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use cgmath::{Deg, InnerSpace, Vector3, Zero};
 use num::FromPrimitive;
-use rand;
 
 use self::{
-    functions::{BuiltinFunctionId, FunctionDef, FunctionKind, Statement, MAX_ARGS},
+    files::ProgsFiles,
+    functions::{extension_supported, FunctionDef, FunctionKind, Statement, MAX_ARGS},
     globals::{
         GLOBAL_ADDR_ARG_0, GLOBAL_ADDR_ARG_1, GLOBAL_ADDR_ARG_2, GLOBAL_ADDR_ARG_3,
-        GLOBAL_ADDR_RETURN, GLOBAL_STATIC_COUNT, GLOBAL_STATIC_START,
+        GLOBAL_ADDR_ARG_4, GLOBAL_ADDR_ARG_5, GLOBAL_ADDR_RETURN, GLOBAL_STATIC_COUNT,
+        GLOBAL_STATIC_START,
     },
-    ops::Opcode,
 };
+pub(crate) use self::{functions::BuiltinFunctionId, ops::Opcode};
 pub use self::{
     functions::{FunctionId, Functions},
     globals::{
-        GlobalAddrEntity, GlobalAddrFloat, GlobalAddrFunction, GlobalAddrVector, Globals,
-        GlobalsError,
+        GlobalAddrEntity, GlobalAddrFloat, GlobalAddrFunction, GlobalAddrString, GlobalAddrVector,
+        Globals, GlobalsError,
     },
 };
 
@@ -142,6 +148,10 @@ const MAX_CALL_STACK_DEPTH: usize = 32;
 const MAX_LOCAL_STACK_DEPTH: usize = 2048;
 const LUMP_COUNT: usize = 6;
 const SAVE_GLOBAL: u16 = 1 << 15;
+const MAX_RUNAWAY: usize = 100000;
+
+// the value of `takedamage` for entities that AIM should steer shots toward
+const DAMAGE_AIM: f32 = 2.0;
 
 // the on-disk size of a bytecode statement
 const STATEMENT_SIZE: usize = 8;
@@ -376,7 +386,9 @@ impl StringTable {
             None => (),
         }
 
-        self.byte_count.set(self.byte_count.get() + len);
+        // leave room for a terminator like the strings in the lump, so that inserting an empty
+        // string doesn't give the next string the same ID
+        self.byte_count.set(self.byte_count.get() + len + 1);
 
         id
     }
@@ -465,10 +477,8 @@ pub fn load(
             x if x < 0 => match BuiltinFunctionId::from_i32(-x) {
                 Some(f) => FunctionKind::BuiltIn(f),
                 None => {
-                    return Err(ProgsError::with_msg(format!(
-                        "Invalid built-in function ID {}",
-                        -x
-                    )))
+                    warn!("Unknown built-in function ID {}", -x);
+                    FunctionKind::UnknownBuiltIn(-x)
                 }
             },
             x => FunctionKind::QuakeC(x as usize),
@@ -635,6 +645,12 @@ pub struct ExecutionContext {
     current_function: FunctionId,
     call_stack: Vec<StackFrame>,
     local_stack: Vec<[u8; 4]>,
+
+    // if true, each statement is printed as it is executed
+    trace: bool,
+
+    // files opened with fopen
+    files: ProgsFiles,
 }
 
 impl ExecutionContext {
@@ -646,9 +662,15 @@ impl ExecutionContext {
             current_function: FunctionId(0),
             call_stack: Vec::with_capacity(MAX_CALL_STACK_DEPTH),
             local_stack: Vec::with_capacity(MAX_LOCAL_STACK_DEPTH),
+            trace: false,
+            files: ProgsFiles::new(),
         }
     }
 
+    pub fn functions(&self) -> &Rc<Functions> {
+        &self.functions
+    }
+
     fn enter_function(&mut self, globals: &mut Globals, f: FunctionId) -> Result<(), ProgsError> {
         let def = self.functions.get_def(f)?;
         debug!(
@@ -678,17 +700,20 @@ impl ExecutionContext {
                 .push(globals.get_bytes((def.arg_start + i) as i16)?);
         }
 
+        // copy the arguments into the function's locals, which start with its parameters
+        let mut local_addr = def.arg_start;
         for arg in 0..def.argc {
             for component in 0..def.argsz[arg] as usize {
                 let val = globals.get_bytes((GLOBAL_ADDR_ARG_0 + arg * 3 + component) as i16)?;
-                globals.put_bytes(val, def.arg_start as i16)?;
+                globals.put_bytes(val, local_addr as i16)?;
+                local_addr += 1;
             }
         }
 
         self.current_function = f;
 
         match def.kind {
-            FunctionKind::BuiltIn(_) => {
+            FunctionKind::BuiltIn(_) | FunctionKind::UnknownBuiltIn(_) => {
                 panic!("built-in functions should not be called with enter_function()")
             }
             FunctionKind::QuakeC(pc) => self.pc = pc,
@@ -719,6 +744,32 @@ impl ExecutionContext {
         Ok(())
     }
 
+    // returns the name of the function being executed, for error messages
+    fn current_function_name(&self) -> String {
+        self.functions
+            .get_def(self.current_function)
+            .ok()
+            .and_then(|def| self.string_table.get(def.name_id))
+            .unwrap_or_default()
+    }
+
+    // concatenates the string arguments from `first` on, like the original engine's
+    // PF_VarString
+    fn var_string(
+        &self,
+        globals: &Globals,
+        first: usize,
+        arg_count: usize,
+    ) -> Result<String, ProgsError> {
+        let mut s = String::new();
+        for i in first..arg_count {
+            let s_id = globals.get_string_id((GLOBAL_ADDR_ARG_0 + i * 3) as i16)?;
+            s.push_str(&self.string_table.get(s_id).unwrap_or_default());
+        }
+
+        Ok(s)
+    }
+
     pub fn execute_program(
         &mut self,
         globals: &mut Globals,
@@ -728,7 +779,7 @@ impl ExecutionContext {
         vfs: &Vfs,
         f: FunctionId,
     ) -> Result<(), ProgsError> {
-        let mut runaway = 100000;
+        let mut runaway = MAX_RUNAWAY;
 
         // this allows us to call execute_program() recursively with the same local and call stacks
         let exit_depth = self.call_stack.len();
@@ -739,7 +790,10 @@ impl ExecutionContext {
             runaway -= 1;
 
             if runaway == 0 {
-                panic!("runaway program");
+                return Err(ProgsError::with_msg(format!(
+                    "runaway loop error in {}",
+                    self.current_function_name()
+                )));
             }
 
             let op = self.functions.statements[self.pc].opcode;
@@ -756,6 +810,17 @@ impl ExecutionContext {
                 c
             );
 
+            if self.trace {
+                println!(
+                    "{:>6} {:<9} {:>5} {:>5} {:>5}",
+                    self.pc,
+                    format!("{:?}", op),
+                    a,
+                    b,
+                    c
+                );
+            }
+
             use self::Opcode::*;
             match op {
                 MulF => mul_f(globals, a, b, c)?,
@@ -785,7 +850,7 @@ impl ExecutionContext {
                 LoadV => load_v(globals, world, a, b, c)?,
                 LoadS => load_s(globals, world, a, b, c)?,
                 LoadEnt => load_ent(globals, world, a, b, c)?,
-                LoadFld => panic!("load_fld not implemented"),
+                LoadFld => load_fld(globals, world, a, b, c)?,
                 LoadFnc => load_fnc(globals, world, a, b, c)?,
                 Address => address(globals, world, a, b, c)?,
                 StoreF => store_f(globals, a, b, c)?,
@@ -798,7 +863,7 @@ impl ExecutionContext {
                 StorePV => storep_v(globals, world, a, b, c)?,
                 StorePS => storep_s(globals, world, a, b, c)?,
                 StorePEnt => storep_ent(globals, world, a, b, c)?,
-                StorePFld => panic!("storep_fld not implemented"),
+                StorePFld => storep_fld(globals, world, a, b, c)?,
                 StorePFnc => storep_fnc(globals, world, a, b, c)?,
                 NotF => not_f(globals, a, b, c)?,
                 NotV => not_v(globals, a, b, c)?,
@@ -848,17 +913,28 @@ impl ExecutionContext {
                 }
 
                 Call0 | Call1 | Call2 | Call3 | Call4 | Call5 | Call6 | Call7 | Call8 => {
-                    // TODO: pass to equivalent of PF_VarString
-                    let _arg_count = op as usize - Opcode::Call0 as usize;
+                    let arg_count = op as usize - Opcode::Call0 as usize;
 
                     let f_to_call = globals.get_function_id(a)?;
                     if f_to_call.0 == 0 {
-                        panic!("NULL function");
+                        return Err(ProgsError::with_msg(format!(
+                            "NULL function called from {}",
+                            self.current_function_name()
+                        )));
                     }
 
                     let name_id = self.functions.get_def(f_to_call)?.name_id;
                     let name = self.string_table.get(name_id).unwrap();
 
+                    if let FunctionKind::UnknownBuiltIn(id) =
+                        self.functions.get_def(f_to_call)?.kind
+                    {
+                        return Err(ProgsError::with_msg(format!(
+                            "Built-in function {} (#{}) is not implemented",
+                            name, id
+                        )));
+                    }
+
                     if let FunctionKind::BuiltIn(b) = self.functions.get_def(f_to_call)?.kind {
                         debug!("Calling built-in function {}", name);
                         use self::functions::BuiltinFunctionId::*;
@@ -887,14 +963,70 @@ impl ExecutionContext {
                                 let maxs = globals.get_vector(GLOBAL_ADDR_ARG_2 as i16)?;
                                 world.set_entity_size(e_id, mins.into(), maxs.into())?;
                             }
-                            Break => unimplemented!(),
+                            Break => return Err(ProgsError::with_msg("break statement")),
                             Random => {
-                                globals.put_float(rand::random(), GLOBAL_ADDR_RETURN as i16)?;
+                                let r = server.rng_mut().random();
+                                globals.put_float(r, GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            Sound => {
+                                let e_id = globals.get_entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let channel = globals.get_float(GLOBAL_ADDR_ARG_1 as i16)?;
+                                let name = globals.get_string_id(GLOBAL_ADDR_ARG_2 as i16)?;
+                                let volume = globals.get_float(GLOBAL_ADDR_ARG_3 as i16)?;
+                                let attenuation = globals.get_float(GLOBAL_ADDR_ARG_4 as i16)?;
+
+                                let sound_index = match server.sound_precache_lookup(name) {
+                                    Ok(i) => i,
+                                    Err(_) => {
+                                        return Err(ProgsError::with_msg("sound not precached"))
+                                    }
+                                };
+
+                                // sounds come from the center of the entity's bounding box, so
+                                // brush entities are heard from where they are and not the map
+                                // origin
+                                let ent = world.try_get_entity(e_id)?;
+                                let origin = ent.origin()? + (ent.min()? + ent.max()?) * 0.5;
+
+                                server.start_sound(
+                                    origin,
+                                    e_id.0 as u16,
+                                    channel as i32,
+                                    sound_index as u8,
+                                    volume,
+                                    attenuation,
+                                );
+                            }
+                            Normalize => globals.normalize()?,
+                            Error => {
+                                let msg = self.var_string(globals, 0, arg_count)?;
+                                let self_id =
+                                    globals.get_entity_id(GlobalAddrEntity::Self_ as i16)?;
+                                if let Ok(desc) = world.describe_entity(self_id, &self.functions) {
+                                    print!("{}", desc);
+                                }
+
+                                return Err(ProgsError::with_msg(format!(
+                                    "Program error in {}: {}",
+                                    self.current_function_name(),
+                                    msg
+                                )));
+                            }
+                            ObjError => {
+                                let msg = self.var_string(globals, 0, arg_count)?;
+                                let self_id =
+                                    globals.get_entity_id(GlobalAddrEntity::Self_ as i16)?;
+                                if let Ok(desc) = world.describe_entity(self_id, &self.functions) {
+                                    print!("{}", desc);
+                                }
+                                world.remove_entity(self_id)?;
+
+                                return Err(ProgsError::with_msg(format!(
+                                    "Object error in {}: {}",
+                                    self.current_function_name(),
+                                    msg
+                                )));
                             }
-                            Sound => unimplemented!(),
-                            Normalize => unimplemented!(),
-                            Error => unimplemented!(),
-                            ObjError => unimplemented!(),
                             VLen => globals.v_len()?,
                             VecToYaw => globals.vec_to_yaw()?,
 
@@ -910,18 +1042,68 @@ impl ExecutionContext {
                                     globals.get_entity_id(GLOBAL_ADDR_ARG_0 as i16)?,
                                 )?;
                             }
-                            TraceLine => unimplemented!(),
-                            CheckClient => unimplemented!(),
+                            TraceLine => {
+                                let start = globals.get_vector(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let end = globals.get_vector(GLOBAL_ADDR_ARG_1 as i16)?;
+                                let nomonsters = globals.get_float(GLOBAL_ADDR_ARG_2 as i16)?;
+                                let e_id = globals.get_entity_id(GLOBAL_ADDR_ARG_3 as i16)?;
+                                let (trace, hit_id) = world.move_entity(
+                                    e_id,
+                                    start.into(),
+                                    Vector3::zero(),
+                                    Vector3::zero(),
+                                    end.into(),
+                                    collide_kind(nomonsters),
+                                )?;
+                                put_trace(globals, &trace, hit_id)?;
+                            }
+                            CheckClient => {
+                                let self_id =
+                                    globals.get_entity_id(GlobalAddrEntity::Self_ as i16)?;
+                                let found = match server.check_client(world)? {
+                                    // the client is only found if self can see where it is
+                                    Some((client_id, client_leaf)) => {
+                                        let ent = world.try_get_entity(self_id)?;
+                                        let view = ent.origin()?
+                                            + Vector3::from(
+                                                ent.get_vector(FieldAddrVector::ViewOffset as i16)?,
+                                            );
+                                        let leaf = world.leaf_at(view);
+                                        if leaf != 0 && world.vis().leaf_visible(client_leaf, leaf)
+                                        {
+                                            client_id
+                                        } else {
+                                            EntityId(0)
+                                        }
+                                    }
+
+                                    None => EntityId(0),
+                                };
+                                globals.put_entity_id(found, GLOBAL_ADDR_RETURN as i16)?;
+                            }
 
-                            // goal: `world.find_entity(e_id)`
-                            Find => unimplemented!(),
-                            PrecacheSound => {
+                            Find => {
+                                let start = globals.get_entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let field = globals.get_field_addr(GLOBAL_ADDR_ARG_1 as i16)?;
+                                let target_id = globals.get_string_id(GLOBAL_ADDR_ARG_2 as i16)?;
+                                let target = self.string_table.get(target_id).unwrap();
+
+                                let found = find_entity(world, start, |e_id| {
+                                    let s_id = world
+                                        .try_get_entity(e_id)?
+                                        .get_string_id(field.0 as i16)?;
+                                    Ok(self.string_table.get(s_id).as_deref()
+                                        == Some(target.as_str()))
+                                })?;
+                                globals.put_entity_id(found, GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            PrecacheSound | PrecacheSound2 => {
                                 // TODO: disable precaching after server is active
                                 // TODO: precaching doesn't actually load yet
                                 let s_id = globals.get_string_id(GLOBAL_ADDR_ARG_0 as i16)?;
                                 server.precache_sound(s_id);
                             }
-                            PrecacheModel => {
+                            PrecacheModel | PrecacheModel2 => {
                                 // TODO: disable precaching after server is active
                                 // TODO: precaching doesn't actually load yet
                                 let s_id = globals.get_string_id(GLOBAL_ADDR_ARG_0 as i16)?;
@@ -930,22 +1112,75 @@ impl ExecutionContext {
                                     world.add_model(vfs, s_id)?;
                                 }
                             }
-                            StuffCmd => unimplemented!(),
-                            FindRadius => unimplemented!(),
-                            BPrint => unimplemented!(),
-                            SPrint => unimplemented!(),
+                            StuffCmd => {
+                                let e_id = globals.get_entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let s_id = globals.get_string_id(GLOBAL_ADDR_ARG_1 as i16)?;
+                                let text = self.string_table.get(s_id).unwrap_or_default();
+                                if !server.send_to_client(e_id, &ServerCmd::StuffText { text }) {
+                                    return Err(ProgsError::with_msg("stuffcmd: not a client"));
+                                }
+                            }
+                            FindRadius => {
+                                let origin = globals.get_vector(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let radius = globals.get_float(GLOBAL_ADDR_ARG_1 as i16)?;
+                                let chain = find_radius(world, origin.into(), radius)?;
+                                globals.put_entity_id(chain, GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            BPrint => {
+                                let text = self.var_string(globals, 0, arg_count)?;
+                                server.broadcast(&ServerCmd::Print { text });
+                            }
+                            SPrint => {
+                                let e_id = globals.get_entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let text = self.var_string(globals, 1, arg_count)?;
+                                if !server.send_to_client(e_id, &ServerCmd::Print { text }) {
+                                    warn!("sprint: entity {} is not a client", e_id.0);
+                                }
+                            }
                             DPrint => {
                                 let s_id = globals.get_string_id(GLOBAL_ADDR_ARG_0 as i16)?;
                                 let string = self.string_table.get(s_id).unwrap();
                                 debug!("DPRINT: {}", string);
                             }
-                            FToS => unimplemented!(),
-                            VToS => unimplemented!(),
-                            CoreDump => unimplemented!(),
-                            TraceOn => unimplemented!(),
-                            TraceOff => unimplemented!(),
-                            EPrint => unimplemented!(),
-                            WalkMove => unimplemented!(),
+                            FToS => {
+                                let f = globals.get_float(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let s_id = self.string_table.insert(ftos(f));
+                                globals.put_string_id(s_id, GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            VToS => {
+                                let v = globals.get_vector(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let s_id = self.string_table.insert(vtos(v));
+                                globals.put_string_id(s_id, GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            CoreDump => print!("{}", world.describe_entities(&self.functions)?),
+                            TraceOn => self.trace = true,
+                            TraceOff => self.trace = false,
+                            EPrint => {
+                                let e_id = globals.get_entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                print!("{}", world.describe_entity(e_id, &self.functions)?);
+                            }
+                            WalkMove => {
+                                let self_id =
+                                    globals.get_entity_id(GlobalAddrEntity::Self_ as i16)?;
+                                let yaw = globals.get_float(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let dist = globals.get_float(GLOBAL_ADDR_ARG_1 as i16)?;
+                                let moved = world.walk_move(
+                                    &mut PhysicsContext {
+                                        execution_context: self,
+                                        globals,
+                                        cvars,
+                                        server,
+                                        vfs,
+                                    },
+                                    self_id,
+                                    yaw,
+                                    dist,
+                                )?;
+                                globals.put_float(
+                                    if moved { 1.0 } else { 0.0 },
+                                    GLOBAL_ADDR_RETURN as i16,
+                                )?;
+                            }
 
                             DropToFloor => {
                                 let e_id = globals.get_entity_id(GlobalAddrEntity::Self_ as i16)?;
@@ -970,78 +1205,345 @@ impl ExecutionContext {
                             RInt => globals.r_int()?,
                             Floor => globals.floor()?,
                             Ceil => globals.ceil()?,
-                            CheckBottom => unimplemented!(),
-                            PointContents => unimplemented!(),
+                            CheckBottom => {
+                                let e_id = globals.get_entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let supported = if world.check_bottom(e_id)? { 1.0 } else { 0.0 };
+                                globals.put_float(supported, GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            PointContents => {
+                                let point = globals.get_vector(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let contents = world.point_contents(point.into())?;
+
+                                // the progs use the negative contents values of the BSP format
+                                globals.put_float(
+                                    -(contents as i32) as f32,
+                                    GLOBAL_ADDR_RETURN as i16,
+                                )?;
+                            }
                             FAbs => globals.f_abs()?,
-                            Aim => unimplemented!(),
+                            Aim => {
+                                let e_id = globals.get_entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let forward =
+                                    globals.get_vector(GlobalAddrVector::VForward as i16)?;
+                                let dir = aim(world, cvars, e_id, forward.into())?;
+                                globals.put_vector(dir.into(), GLOBAL_ADDR_RETURN as i16)?;
+                            }
                             Cvar => {
                                 let s_id = globals.get_string_id(GLOBAL_ADDR_ARG_0 as i16)?;
                                 let s = self.string_table.get(s_id).unwrap();
-                                let f = cvars.get_value(s).unwrap();
+                                // like the original engine, unknown cvars read as zero
+                                let f = cvars.get_value(s).unwrap_or(0.0);
                                 globals.put_float(f, GLOBAL_ADDR_RETURN as i16)?;
                             }
-                            LocalCmd => unimplemented!(),
-                            NextEnt => unimplemented!(),
-                            Particle => unimplemented!(),
-                            ChangeYaw => unimplemented!(),
-                            VecToAngles => unimplemented!(),
-
-                            // goal: `server.write_byte(b)`
-                            WriteByte => unimplemented!(),
-
-                            // goal: `server.write_char(c)`
-                            WriteChar => unimplemented!(),
-
-                            // goal: `server.write_short(s)`
-                            WriteShort => unimplemented!(),
-
-                            // goal: `server.write_long(l)`
-                            WriteLong => unimplemented!(),
-
-                            // goal: `server.write_coord(v)`
-                            WriteCoord => unimplemented!(),
-
-                            // goal: `server.write_angle(a)`
-                            WriteAngle => unimplemented!(),
+                            LocalCmd => {
+                                let s_id = globals.get_string_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let cmd = self.string_table.get(s_id).unwrap_or_default();
+                                server.queue_local_cmd(cmd);
+                            }
+                            NextEnt => {
+                                let e_id = globals.get_entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let next = world.next_entity(e_id).unwrap_or(EntityId(0));
+                                globals.put_entity_id(next, GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            Particle => {
+                                let origin = globals.get_vector(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let direction = globals.get_vector(GLOBAL_ADDR_ARG_1 as i16)?;
+                                let color = globals.get_float(GLOBAL_ADDR_ARG_2 as i16)?;
+                                let count = globals.get_float(GLOBAL_ADDR_ARG_3 as i16)?;
+
+                                server.multicast(
+                                    origin.into(),
+                                    MulticastScope::Pvs,
+                                    false,
+                                    &ServerCmd::Particle {
+                                        origin: origin.into(),
+                                        direction: direction.into(),
+                                        count: count.max(0.0).min(255.0) as u8,
+                                        color: color as u8,
+                                    },
+                                );
+                            }
+                            ChangeYaw => {
+                                let self_id =
+                                    globals.get_entity_id(GlobalAddrEntity::Self_ as i16)?;
+                                world.change_yaw(self_id)?;
+                            }
+                            VecToAngles => globals.vec_to_angles()?,
+                            WriteByte => {
+                                let f = globals.get_float(GLOBAL_ADDR_ARG_1 as i16)?;
+                                write_dest(globals, server)?.write_u8(f as i32 as u8)?;
+                            }
+                            WriteChar => {
+                                let f = globals.get_float(GLOBAL_ADDR_ARG_1 as i16)?;
+                                write_dest(globals, server)?.write_i8(f as i32 as i8)?;
+                            }
+                            WriteShort => {
+                                let f = globals.get_float(GLOBAL_ADDR_ARG_1 as i16)?;
+                                write_dest(globals, server)?
+                                    .write_i16::<LittleEndian>(f as i32 as i16)?;
+                            }
+                            WriteLong => {
+                                let f = globals.get_float(GLOBAL_ADDR_ARG_1 as i16)?;
+                                write_dest(globals, server)?.write_i32::<LittleEndian>(f as i32)?;
+                            }
+                            WriteCoord => {
+                                let f = globals.get_float(GLOBAL_ADDR_ARG_1 as i16)?;
+                                let codec = server.protocol().codec();
+                                codec.write_coord(write_dest(globals, server)?, f).unwrap();
+                            }
+                            WriteAngle => {
+                                let f = globals.get_float(GLOBAL_ADDR_ARG_1 as i16)?;
+                                let codec = server.protocol().codec();
+                                codec
+                                    .write_angle(write_dest(globals, server)?, Deg(f))
+                                    .unwrap();
+                            }
+                            WriteString => {
+                                let s_id = globals.get_string_id(GLOBAL_ADDR_ARG_1 as i16)?;
+                                let s = self.string_table.get(s_id).unwrap_or_default();
+                                let dest = write_dest(globals, server)?;
+                                dest.extend_from_slice(s.as_bytes());
+                                dest.push(0);
+                            }
+                            WriteEntity => {
+                                let e_id = globals.get_entity_id(GLOBAL_ADDR_ARG_1 as i16)?;
+                                write_dest(globals, server)?
+                                    .write_i16::<LittleEndian>(e_id.0 as i16)?;
+                            }
 
-                            // goal: `server.write_string(s_id)`
-                            WriteString => unimplemented!(),
+                            MoveToGoal => {
+                                let self_id =
+                                    globals.get_entity_id(GlobalAddrEntity::Self_ as i16)?;
+                                let dist = globals.get_float(GLOBAL_ADDR_ARG_0 as i16)?;
+                                world.move_to_goal(
+                                    &mut PhysicsContext {
+                                        execution_context: self,
+                                        globals,
+                                        cvars,
+                                        server,
+                                        vfs,
+                                    },
+                                    self_id,
+                                    dist,
+                                )?;
+                            }
 
-                            // goal: `server.write_entity(e_id)`
-                            WriteEntity => unimplemented!(),
+                            // files are only precached by the QuakeC compiler, which uses them to
+                            // decide what goes in the pak files
+                            PrecacheFile | PrecacheFile2 => {
+                                let s_id = globals.get_string_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                globals.put_string_id(s_id, GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            MakeStatic => {
+                                let e_id = globals.get_entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let ent = world.try_get_entity(e_id)?;
+                                let angles = ent.get_vector(FieldAddrVector::Angles as i16)?;
+
+                                // static entities are sent once during signon and never updated,
+                                // so the server-side entity is no longer needed
+                                server.add_signon_cmd(ServerCmd::SpawnStatic {
+                                    model_id: ent.model_index()? as u8,
+                                    frame_id: ent.get_float(FieldAddrFloat::FrameId as i16)? as u8,
+                                    colormap: ent.get_float(FieldAddrFloat::Colormap as i16)? as u8,
+                                    skin_id: ent.get_float(FieldAddrFloat::SkinId as i16)? as u8,
+                                    origin: ent.origin()?,
+                                    angles: Vector3::new(
+                                        Deg(angles[0]),
+                                        Deg(angles[1]),
+                                        Deg(angles[2]),
+                                    ),
+                                });
+
+                                world.remove_entity(e_id)?;
+                            }
+                            ChangeLevel => {
+                                let map_id = globals.get_string_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let map_name = self.string_table.get(map_id).unwrap();
 
-                            MoveToGoal => unimplemented!(),
-                            PrecacheFile => unimplemented!(),
-                            MakeStatic => unimplemented!(),
-                            ChangeLevel => unimplemented!(),
+                                if server.request_level_change(&map_name) {
+                                    server.queue_local_cmd(format!("changelevel {}\n", map_name));
+                                }
+                            }
                             CvarSet => {
                                 let var_id = globals.get_string_id(GLOBAL_ADDR_ARG_0 as i16)?;
                                 let var = self.string_table.get(var_id).unwrap();
                                 let val_id = globals.get_string_id(GLOBAL_ADDR_ARG_1 as i16)?;
                                 let val = self.string_table.get(val_id).unwrap();
-                                cvars.set(var, val).unwrap();
+                                if let Err(e) = cvars.set(var.as_str(), val.as_str()) {
+                                    warn!("cvar_set: couldn't set {}: {}", var, e);
+                                }
+                            }
+                            CenterPrint => {
+                                let e_id = globals.get_entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let text = self.var_string(globals, 1, arg_count)?;
+                                if !server.send_to_client(e_id, &ServerCmd::CenterPrint { text }) {
+                                    warn!("centerprint: entity {} is not a client", e_id.0);
+                                }
                             }
-                            CenterPrint => unimplemented!(),
                             AmbientSound => {
-                                let _pos = globals.get_vector(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let pos = globals.get_vector(GLOBAL_ADDR_ARG_0 as i16)?;
                                 let name = globals.get_string_id(GLOBAL_ADDR_ARG_1 as i16)?;
-                                let _volume = globals.get_float(GLOBAL_ADDR_ARG_2 as i16)?;
-                                let _attenuation = globals.get_float(GLOBAL_ADDR_ARG_3 as i16)?;
+                                let volume = globals.get_float(GLOBAL_ADDR_ARG_2 as i16)?;
+                                let attenuation = globals.get_float(GLOBAL_ADDR_ARG_3 as i16)?;
 
                                 // TODO: replace with `?` syntax once `server` has a proper error type
-                                let _sound_index = match server.sound_precache_lookup(name) {
+                                let sound_index = match server.sound_precache_lookup(name) {
                                     Ok(i) => i,
                                     Err(_) => {
                                         return Err(ProgsError::with_msg("sound not precached"))
                                     }
                                 };
 
-                                // TODO: write to server signon packet
+                                server.add_signon_cmd(ServerCmd::SpawnStaticSound {
+                                    origin: pos.into(),
+                                    sound_id: sound_index as u8,
+                                    volume: (volume * 255.0) as u8,
+                                    attenuation: (attenuation * 64.0) as u8,
+                                });
+                            }
+                            SetSpawnArgs => {
+                                let e_id = globals.get_entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let parms = match server.client_spawn_parms(e_id) {
+                                    Some(p) => p,
+                                    None => {
+                                        return Err(ProgsError::with_msg(
+                                            "setspawnparms: not a client",
+                                        ))
+                                    }
+                                };
+
+                                for (i, parm) in parms.iter().enumerate() {
+                                    globals.put_float(
+                                        *parm,
+                                        GlobalAddrFloat::Arg0 as i16 + i as i16,
+                                    )?;
+                                }
+                            }
+
+                            TraceBox => {
+                                let start = globals.get_vector(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let min = globals.get_vector(GLOBAL_ADDR_ARG_1 as i16)?;
+                                let max = globals.get_vector(GLOBAL_ADDR_ARG_2 as i16)?;
+                                let end = globals.get_vector(GLOBAL_ADDR_ARG_3 as i16)?;
+                                let nomonsters = globals.get_float(GLOBAL_ADDR_ARG_4 as i16)?;
+                                let e_id = globals.get_entity_id(GLOBAL_ADDR_ARG_5 as i16)?;
+                                let (trace, hit_id) = world.move_entity(
+                                    e_id,
+                                    start.into(),
+                                    min.into(),
+                                    max.into(),
+                                    end.into(),
+                                    collide_kind(nomonsters),
+                                )?;
+                                put_trace(globals, &trace, hit_id)?;
+                            }
+                            GetLight => {
+                                let point = globals.get_vector(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let time = globals.get_float(GlobalAddrFloat::Time as i16)?;
+                                let style_values =
+                                    server.lightstyle_values(engine::duration_from_f32(time));
+
+                                // on the lightmap scale, where 255 is double brightness. points
+                                // with no floor below them are dark, as in the original engine.
+                                let light = world
+                                    .light_at(point.into(), 0.0, &style_values)
+                                    .unwrap_or([0.0; 3]);
+                                let light = [light[0] * 127.5, light[1] * 127.5, light[2] * 127.5];
+                                globals.put_vector(light, GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            FindFloat => {
+                                let start = globals.get_entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let field = globals.get_field_addr(GLOBAL_ADDR_ARG_1 as i16)?;
+                                let target = globals.get_float(GLOBAL_ADDR_ARG_2 as i16)?;
+
+                                let found = find_entity(world, start, |e_id| {
+                                    let f =
+                                        world.try_get_entity(e_id)?.get_float(field.0 as i16)?;
+                                    Ok(f == target)
+                                })?;
+                                globals.put_entity_id(found, GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            CheckExtension => {
+                                let s_id = globals.get_string_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let s = self.string_table.get(s_id).unwrap();
+                                let supported = if extension_supported(s) { 1.0 } else { 0.0 };
+                                globals.put_float(supported, GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            StoF => {
+                                let s_id = globals.get_string_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let s = self.string_table.get(s_id).unwrap();
+                                globals.put_float(stof(&s), GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            FOpen => {
+                                let s_id = globals.get_string_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let name = self.string_table.get(s_id).unwrap();
+                                let mode = globals.get_float(GLOBAL_ADDR_ARG_1 as i16)?;
+                                let handle = match self.files.open(vfs, &name, mode) {
+                                    Some(h) => h as f32,
+                                    None => -1.0,
+                                };
+                                globals.put_float(handle, GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            FClose => {
+                                let handle = globals.get_float(GLOBAL_ADDR_ARG_0 as i16)?;
+                                if !self.files.close(handle as usize) {
+                                    warn!("fclose: no file with handle {}", handle);
+                                }
+                            }
+                            FGetS => {
+                                let handle = globals.get_float(GLOBAL_ADDR_ARG_0 as i16)?;
+
+                                // the null string marks the end of the file
+                                let s_id = match self.files.read_line(handle as usize) {
+                                    Some(line) => self.string_table.insert(line),
+                                    None => StringId(0),
+                                };
+                                globals.put_string_id(s_id, GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            FPutS => {
+                                let handle = globals.get_float(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let text = self.var_string(globals, 1, arg_count)?;
+                                if let Err(e) = self.files.write(handle as usize, &text) {
+                                    warn!("fputs: couldn't write to file {}: {}", handle, e);
+                                }
+                            }
+                            StrLen => {
+                                let s_id = globals.get_string_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let s = self.string_table.get(s_id).unwrap();
+                                globals.put_float(
+                                    s.chars().count() as f32,
+                                    GLOBAL_ADDR_RETURN as i16,
+                                )?;
+                            }
+                            StrCat => {
+                                let cat = self.var_string(globals, 0, arg_count)?;
+                                let s_id = self.string_table.insert(cat);
+                                globals.put_string_id(s_id, GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            Substring => {
+                                let s_id = globals.get_string_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let s = self.string_table.get(s_id).unwrap();
+                                let start = globals.get_float(GLOBAL_ADDR_ARG_1 as i16)?;
+                                let len = globals.get_float(GLOBAL_ADDR_ARG_2 as i16)?;
+
+                                let sub_id = self.string_table.insert(substring(&s, start, len));
+                                globals.put_string_id(sub_id, GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            StoV => {
+                                let s_id = globals.get_string_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let s = self.string_table.get(s_id).unwrap();
+                                globals.put_vector(stov(&s), GLOBAL_ADDR_RETURN as i16)?;
+                            }
+                            StrZone => {
+                                let s_id = globals.get_string_id(GLOBAL_ADDR_ARG_0 as i16)?;
+                                let s = self.string_table.get(s_id).unwrap();
+                                let zone_id = self.string_table.insert(s);
+                                globals.put_string_id(zone_id, GLOBAL_ADDR_RETURN as i16)?;
                             }
-                            PrecacheModel2 => unimplemented!(),
-                            PrecacheSound2 => unimplemented!(),
-                            PrecacheFile2 => unimplemented!(),
-                            SetSpawnArgs => unimplemented!(),
+
+                            // strings are never removed from the string table, so there's nothing
+                            // to free
+                            StrUnzone => (),
                         }
                         debug!("Returning from built-in function {}", name);
                     } else {
@@ -1086,6 +1588,249 @@ impl ExecutionContext {
     }
 }
 
+// TRACELINE, TRACEBOX: Convert the `nomonsters` argument to a collision kind
+fn collide_kind(nomonsters: f32) -> CollideKind {
+    CollideKind::from_i32(nomonsters as i32).unwrap_or(CollideKind::Normal)
+}
+
+// TRACELINE, TRACEBOX: Store the result of a trace in the trace globals
+fn put_trace(globals: &mut Globals, trace: &Trace, hit_id: EntityId) -> Result<(), ProgsError> {
+    let flag = |b: bool| if b { 1.0 } else { 0.0 };
+    let (normal, dist) = match trace.plane() {
+        Some(p) => (p.normal_vector(), p.dist()),
+        None => (Vector3::zero(), 0.0),
+    };
+
+    globals.put_float(
+        flag(trace.all_solid()),
+        GlobalAddrFloat::TraceAllSolid as i16,
+    )?;
+    globals.put_float(
+        flag(trace.start_solid()),
+        GlobalAddrFloat::TraceStartSolid as i16,
+    )?;
+    globals.put_float(trace.ratio(), GlobalAddrFloat::TraceFraction as i16)?;
+    globals.put_vector(
+        trace.end_point().into(),
+        GlobalAddrVector::TraceEndPos as i16,
+    )?;
+    globals.put_vector(normal.into(), GlobalAddrVector::TracePlaneNormal as i16)?;
+    globals.put_float(dist, GlobalAddrFloat::TracePlaneDist as i16)?;
+    globals.put_entity_id(hit_id, GlobalAddrEntity::TraceEntity as i16)?;
+    globals.put_float(flag(trace.in_open()), GlobalAddrFloat::TraceInOpen as i16)?;
+    globals.put_float(flag(trace.in_water()), GlobalAddrFloat::TraceInWater as i16)?;
+
+    Ok(())
+}
+
+// WRITE*: Return the message buffer for the destination in the first argument
+fn write_dest<'a>(
+    globals: &Globals,
+    server: &'a mut Server,
+) -> Result<&'a mut Vec<u8>, ProgsError> {
+    let dest = globals.get_float(GLOBAL_ADDR_ARG_0 as i16)?;
+    let dest = match MsgDest::from_i32(dest as i32) {
+        Some(d) => d,
+        None => {
+            return Err(ProgsError::with_msg(format!(
+                "bad message destination ({})",
+                dest
+            )))
+        }
+    };
+
+    let msg_entity = globals.get_entity_id(GlobalAddrEntity::MsgEntity as i16)?;
+    match server.message_dest_mut(dest, msg_entity) {
+        Some(buf) => Ok(buf),
+        None => Err(ProgsError::with_msg(format!(
+            "msg_entity ({}) is not a client",
+            msg_entity.0
+        ))),
+    }
+}
+
+// FINDRADIUS: Chain together all solid entities whose centers are within `radius` of `origin`,
+// returning the head of the chain
+fn find_radius(
+    world: &mut World,
+    origin: Vector3<f32>,
+    radius: f32,
+) -> Result<EntityId, ProgsError> {
+    let mut chain = EntityId(0);
+    let mut e_id = EntityId(0);
+    while let Some(next) = world.next_entity(e_id) {
+        e_id = next;
+
+        let ent = world.try_get_entity_mut(e_id)?;
+        if ent.solid()? == EntitySolid::Not {
+            continue;
+        }
+
+        let center = ent.origin()? + (ent.min()? + ent.max()?) * 0.5;
+        if (center - origin).magnitude() > radius {
+            continue;
+        }
+
+        ent.put_entity_id(chain, FieldAddrEntityId::Chain as i16)?;
+        chain = e_id;
+    }
+
+    Ok(chain)
+}
+
+// AIM: Return the direction `e_id` should fire in to hit the target nearest its forward vector
+//
+// Targets must be within `sv_aim` of the forward vector, which is used as-is if there are none.
+// Only the vertical angle of the forward vector is adjusted.
+fn aim(
+    world: &World,
+    cvars: &CvarRegistry,
+    e_id: EntityId,
+    forward: Vector3<f32>,
+) -> Result<Vector3<f32>, ProgsError> {
+    let ent = world.try_get_entity(e_id)?;
+    let start = ent.origin()? + Vector3::new(0.0, 0.0, 20.0);
+
+    // in teamplay, don't aim at teammates
+    let team = ent.get_float(FieldAddrFloat::Team as i16)?;
+    let teamplay = cvars.get_value("teamplay").unwrap_or(0.0) != 0.0;
+    let is_target = |target_id: EntityId| -> Result<bool, ProgsError> {
+        let target = world.try_get_entity(target_id)?;
+        Ok(
+            target.get_float(FieldAddrFloat::TakeDamage as i16)? == DAMAGE_AIM
+                && !(teamplay
+                    && team > 0.0
+                    && target.get_float(FieldAddrFloat::Team as i16)? == team),
+        )
+    };
+
+    // if a target is straight ahead, fire straight ahead
+    let (_, hit_id) = world.move_entity(
+        e_id,
+        start,
+        Vector3::zero(),
+        Vector3::zero(),
+        start + forward * 2048.0,
+        CollideKind::Normal,
+    )?;
+    if is_target(hit_id)? {
+        return Ok(forward);
+    }
+
+    let mut best_dist = cvars.get_value("sv_aim").unwrap_or(0.93);
+    let mut best = None;
+    let mut check_id = EntityId(0);
+    while let Some(next) = world.next_entity(check_id) {
+        check_id = next;
+        if check_id == e_id || !is_target(check_id)? {
+            continue;
+        }
+
+        let check = world.try_get_entity(check_id)?;
+        let end = check.origin()? + (check.min()? + check.max()?) * 0.5;
+        let dir = end - start;
+        if dir.magnitude2() == 0.0 {
+            continue;
+        }
+
+        let dist = dir.normalize().dot(forward);
+        if dist < best_dist {
+            continue;
+        }
+
+        // only aim at targets that aren't behind something
+        let (_, hit_id) = world.move_entity(
+            e_id,
+            start,
+            Vector3::zero(),
+            Vector3::zero(),
+            end,
+            CollideKind::Normal,
+        )?;
+        if hit_id == check_id {
+            best_dist = dist;
+            best = Some(check_id);
+        }
+    }
+
+    let best_id = match best {
+        Some(b) => b,
+        None => return Ok(forward),
+    };
+
+    let dir = world.try_get_entity(best_id)?.origin()? - ent.origin()?;
+    let mut aim = forward * dir.dot(forward);
+    aim.z = dir.z;
+    if aim.magnitude2() == 0.0 {
+        return Ok(forward);
+    }
+
+    Ok(aim.normalize())
+}
+
+// FIND, FINDFLOAT: Return the first entity after `start` that satisfies `matches`, or the world
+fn find_entity<F>(world: &World, start: EntityId, mut matches: F) -> Result<EntityId, ProgsError>
+where
+    F: FnMut(EntityId) -> Result<bool, ProgsError>,
+{
+    let mut e_id = start;
+    while let Some(next) = world.next_entity(e_id) {
+        if matches(next)? {
+            return Ok(next);
+        }
+
+        e_id = next;
+    }
+
+    Ok(EntityId(0))
+}
+
+// FTOS: Format a float as a string, omitting the fractional part of integers
+fn ftos(f: f32) -> String {
+    if f == f.trunc() {
+        format!("{}", f as i32)
+    } else {
+        format!("{:5.1}", f)
+    }
+}
+
+// VTOS: Format a vector as a string
+fn vtos(v: [f32; 3]) -> String {
+    format!("'{:5.1} {:5.1} {:5.1}'", v[0], v[1], v[2])
+}
+
+// SUBSTRING: Take `len` characters of `s` starting from `start`
+fn substring(s: &str, start: f32, len: f32) -> String {
+    s.chars()
+        .skip(start.max(0.0) as usize)
+        .take(len.max(0.0) as usize)
+        .collect()
+}
+
+// STOF: Parse the number at the start of a string, or 0 if there isn't one, like atof
+fn stof(s: &str) -> f32 {
+    let s = s.trim_start();
+    (1..=s.len())
+        .rev()
+        .filter(|end| s.is_char_boundary(*end))
+        .find_map(|end| s[..end].parse().ok())
+        .unwrap_or(0.0)
+}
+
+// STOV: Parse a vector from a string formatted like the output of VTOS
+fn stov(s: &str) -> [f32; 3] {
+    let mut v = [0.0; 3];
+    let components = s
+        .trim_matches(|c: char| c == '\'' || c.is_whitespace())
+        .split_whitespace();
+
+    for (c, component) in v.iter_mut().zip(components) {
+        *c = component.parse().unwrap_or(0.0);
+    }
+
+    v
+}
+
 // MUL_F: Float multiplication
 fn mul_f(globals: &mut Globals, f1_id: i16, f2_id: i16, prod_id: i16) -> Result<(), ProgsError> {
     let f1 = globals.get_float(f1_id)?;
@@ -1479,6 +2224,22 @@ fn load_ent(
     Ok(())
 }
 
+// LOAD_FLD: load field address field from entity
+fn load_fld(
+    globals: &mut Globals,
+    world: &World,
+    ent_id_addr: i16,
+    ent_fld_addr_addr: i16,
+    dest_addr: i16,
+) -> Result<(), ProgsError> {
+    let ent_id = globals.get_entity_id(ent_id_addr)?;
+    let fld_addr = globals.get_field_addr(ent_fld_addr_addr)?;
+    let val = world.try_get_entity(ent_id)?.get_bytes(fld_addr.0 as i16)?;
+    globals.put_bytes(val, dest_addr)?;
+
+    Ok(())
+}
+
 fn load_fnc(
     globals: &mut Globals,
     world: &World,
@@ -1705,6 +2466,26 @@ fn storep_ent(
     Ok(())
 }
 
+fn storep_fld(
+    globals: &Globals,
+    world: &mut World,
+    src_fld_addr_addr: i16,
+    dst_ent_fld_addr: i16,
+    unused: i16,
+) -> Result<(), ProgsError> {
+    if unused != 0 {
+        return Err(ProgsError::with_msg("storep_fld: nonzero arg3"));
+    }
+
+    let val = globals.get_bytes(src_fld_addr_addr)?;
+    let ent_fld_addr = world.ent_fld_addr_from_i32(globals.get_entity_field(dst_ent_fld_addr)?);
+    world
+        .try_get_entity_mut(ent_fld_addr.entity_id)?
+        .put_bytes(val, ent_fld_addr.field_addr.0 as i16)?;
+
+    Ok(())
+}
+
 fn storep_fnc(
     globals: &Globals,
     world: &mut World,
@@ -1888,3 +2669,61 @@ fn bit_or(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ftos() {
+        assert_eq!(ftos(3.0), "3");
+        assert_eq!(ftos(-12.0), "-12");
+        assert_eq!(ftos(0.75), "  0.8");
+    }
+
+    #[test]
+    fn test_vtos_stov_round_trip() {
+        let v = [1.5, -2.0, 300.0];
+        assert_eq!(vtos(v), "'  1.5  -2.0 300.0'");
+        assert_eq!(stov(&vtos(v)), v);
+        assert_eq!(stov("4 5"), [4.0, 5.0, 0.0]);
+    }
+
+    #[test]
+    fn test_stof() {
+        assert_eq!(stof("42"), 42.0);
+        assert_eq!(stof("  -1.5 frags"), -1.5);
+        assert_eq!(stof(".25"), 0.25);
+        assert_eq!(stof("1e3x"), 1000.0);
+        assert_eq!(stof("2e"), 2.0);
+        assert_eq!(stof("skill"), 0.0);
+        assert_eq!(stof(""), 0.0);
+    }
+
+    #[test]
+    fn test_substring() {
+        assert_eq!(substring("mission pack", 8.0, 4.0), "pack");
+        assert_eq!(substring("rogue", 3.0, 100.0), "ue");
+        assert_eq!(substring("hipnotic", -1.0, 3.0), "hip");
+    }
+
+    #[test]
+    fn test_extension_supported() {
+        assert!(extension_supported("DP_QC_TRACEBOX"));
+        assert!(extension_supported("dp_qc_findfloat"));
+        assert!(extension_supported("DP_QC_GETLIGHT"));
+        assert!(extension_supported("DP_SV_ROTATINGBMODEL"));
+        assert!(extension_supported("frik_file"));
+        assert!(!extension_supported("DP_QC_STRINGBUFFERS"));
+    }
+
+    #[test]
+    fn test_string_table_insert_empty() {
+        let table = StringTable::new(b"\0".to_vec());
+        let empty = table.insert("");
+        let other = table.insert("other");
+        assert_ne!(empty, other);
+        assert_eq!(table.get(empty).unwrap(), "");
+        assert_eq!(table.get(other).unwrap(), "other");
+    }
+}
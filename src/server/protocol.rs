@@ -0,0 +1,193 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Network protocol selection.
+//!
+//! The `sv_protocol` cvar selects the protocol used by the server. A value of 0 lets the server
+//! choose the oldest protocol that can represent the current level, so classic maps remain playable
+//! by clients that only understand protocol 15. An explicit protocol is used as long as the level
+//! fits within its limits; if it does not, the server upgrades to the next protocol that can
+//! represent it.
+
+use crate::common::{
+    console::CvarRegistry,
+    model::Model,
+    net::{Protocol, ProtocolLimits},
+};
+
+use num::FromPrimitive;
+
+/// The resources a level needs the network protocol to be able to represent.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MapRequirements {
+    pub models: usize,
+    pub sounds: usize,
+    pub entities: usize,
+
+    /// The largest absolute coordinate on any axis.
+    pub max_coord: f32,
+}
+
+impl MapRequirements {
+    /// Measures the requirements of a level from its brush models and precache lists.
+    pub fn measure(
+        brush_models: &[Model],
+        model_precache: &[String],
+        sound_precache: &[String],
+        entities: usize,
+    ) -> MapRequirements {
+        let max_coord = brush_models
+            .iter()
+            .flat_map(|model| {
+                let (min, max) = (model.min(), model.max());
+                vec![min.x, min.y, min.z, max.x, max.y, max.z]
+            })
+            .fold(0.0f32, |acc, c| acc.max(c.abs()));
+
+        MapRequirements {
+            models: model_precache.len().max(brush_models.len()),
+            sounds: sound_precache.len(),
+            entities,
+            max_coord,
+        }
+    }
+
+    /// Returns `true` if these requirements fall within the given protocol limits.
+    pub fn fits(&self, limits: &ProtocolLimits) -> bool {
+        self.models <= limits.max_models
+            && self.sounds <= limits.max_sounds
+            && self.entities <= limits.max_entities
+            && self.max_coord <= limits.max_coord
+    }
+}
+
+/// Returns the protocol requested by `sv_protocol`, or `None` if it should be chosen automatically.
+pub fn preferred_protocol(cvars: &CvarRegistry) -> Option<Protocol> {
+    let value = cvars.get_value("sv_protocol").unwrap_or(0.0) as i32;
+    if value == 0 {
+        return None;
+    }
+
+    match Protocol::from_i32(value) {
+        Some(p) => Some(p),
+        None => {
+            warn!("Unrecognized protocol {}, selecting automatically", value);
+            None
+        }
+    }
+}
+
+/// Selects the protocol to use for a level.
+///
+/// If `preferred` is `None`, this is the oldest protocol whose limits the level fits within.
+/// Otherwise it is `preferred` or, if the level exceeds its limits, the oldest newer protocol
+/// that can represent it.
+pub fn select_protocol(preferred: Option<Protocol>, requirements: &MapRequirements) -> Protocol {
+    let minimum = preferred.unwrap_or(Protocol::NetQuake);
+
+    let selected = Protocol::ALL
+        .iter()
+        .cloned()
+        .filter(|p| *p >= minimum)
+        .find(|p| requirements.fits(&p.limits()))
+        .unwrap_or(Protocol::Rmq);
+
+    if let Some(p) = preferred {
+        if selected != p {
+            warn!(
+                "Level exceeds the limits of protocol {}, using protocol {}",
+                p.version(),
+                selected.version()
+            );
+        }
+    }
+
+    selected
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn classic() -> MapRequirements {
+        MapRequirements {
+            models: 100,
+            sounds: 80,
+            entities: 400,
+            max_coord: 3000.0,
+        }
+    }
+
+    #[test]
+    fn test_select_protocol_auto_classic() {
+        assert_eq!(select_protocol(None, &classic()), Protocol::NetQuake);
+    }
+
+    #[test]
+    fn test_select_protocol_auto_many_models() {
+        let req = MapRequirements {
+            models: 300,
+            ..classic()
+        };
+        assert_eq!(select_protocol(None, &req), Protocol::FitzQuake);
+    }
+
+    #[test]
+    fn test_select_protocol_auto_large_coords() {
+        let req = MapRequirements {
+            max_coord: 6000.0,
+            ..classic()
+        };
+        assert_eq!(select_protocol(None, &req), Protocol::Rmq);
+    }
+
+    #[test]
+    fn test_select_protocol_explicit_kept() {
+        assert_eq!(
+            select_protocol(Some(Protocol::FitzQuake), &classic()),
+            Protocol::FitzQuake
+        );
+    }
+
+    #[test]
+    fn test_select_protocol_explicit_upgraded() {
+        let req = MapRequirements {
+            entities: 1000,
+            ..classic()
+        };
+        assert_eq!(
+            select_protocol(Some(Protocol::NetQuake), &req),
+            Protocol::FitzQuake
+        );
+    }
+
+    #[test]
+    fn test_preferred_protocol() {
+        let cvars = CvarRegistry::new();
+        cvars.register("sv_protocol", "0", "").unwrap();
+        assert_eq!(preferred_protocol(&cvars), None);
+
+        cvars.set("sv_protocol", "666").unwrap();
+        assert_eq!(preferred_protocol(&cvars), Some(Protocol::FitzQuake));
+
+        cvars.set("sv_protocol", "42").unwrap();
+        assert_eq!(preferred_protocol(&cvars), None);
+    }
+}
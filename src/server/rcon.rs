@@ -0,0 +1,192 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Remote console.
+//!
+//! An administrator can run console commands on a server by sending the connectionless packet
+//! `rcon <password> <command>`, the format used by QuakeWorld servers and existing rcon tools. If
+//! the password matches `rcon_password`, the command is executed and its console output is sent
+//! back in a print packet. Remote commands are refused while `rcon_password` is empty.
+//!
+//! Since the password is sent in the clear, each host may only send a few remote commands in a
+//! short time, so that passwords can't be guessed quickly.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    str,
+    time::{Duration, Instant},
+};
+
+/// The number of remote commands a host may send within `RCON_WINDOW`.
+pub const MAX_RCON_ATTEMPTS: usize = 5;
+
+// the period over which remote commands are counted
+const RCON_WINDOW: Duration = Duration::from_secs(10);
+
+// the connectionless packet type of a print, as in QuakeWorld's A2C_PRINT
+const PRINT_HEADER: u8 = b'n';
+
+/// A console command sent by a remote administrator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RconRequest<'a> {
+    pub password: &'a str,
+    pub command: &'a str,
+}
+
+/// If `packet` is a remote console request, returns its password and command.
+///
+/// `packet` should not include the out-of-band control value. As in QuakeWorld, the password may
+/// be quoted.
+pub fn parse_rcon(packet: &[u8]) -> Option<RconRequest> {
+    let text = str::from_utf8(packet)
+        .ok()?
+        .trim_end_matches(|c| c == '\n' || c == '\0');
+    let rest = text.strip_prefix("rcon ")?.trim_start();
+
+    let (password, command) = match rest.strip_prefix('"') {
+        Some(quoted) => {
+            let end = quoted.find('"')?;
+            (&quoted[..end], &quoted[end + 1..])
+        }
+        None => {
+            let end = rest.find(' ')?;
+            (&rest[..end], &rest[end..])
+        }
+    };
+
+    let command = command.trim();
+    if command.is_empty() {
+        return None;
+    }
+
+    Some(RconRequest { password, command })
+}
+
+/// Returns `true` if `given` matches the server's `rcon_password`.
+///
+/// An empty `rcon_password` matches nothing. Every byte of the longer password is compared, so
+/// the time taken reveals neither where the passwords differ nor how long the real one is.
+pub fn check_password(rcon_password: &str, given: &str) -> bool {
+    let (expected, given) = (rcon_password.as_bytes(), given.as_bytes());
+    let len = expected.len().max(given.len());
+
+    let diff = (0..len).fold(expected.len() ^ given.len(), |diff, i| {
+        let a = expected.get(i).copied().unwrap_or(0);
+        let b = given.get(i).copied().unwrap_or(0);
+        diff | (a ^ b) as usize
+    });
+
+    !expected.is_empty() && diff == 0
+}
+
+/// Returns a packet that prints `text` on the administrator's console.
+pub fn print_response(text: &str) -> Vec<u8> {
+    let mut packet = vec![PRINT_HEADER];
+    packet.extend_from_slice(text.as_bytes());
+    packet
+}
+
+/// Limits how often each host may send remote commands.
+pub struct RconLimiter {
+    attempts: HashMap<IpAddr, Vec<Instant>>,
+}
+
+impl RconLimiter {
+    pub fn new() -> RconLimiter {
+        RconLimiter {
+            attempts: HashMap::new(),
+        }
+    }
+
+    /// Records a remote command from `ip`, returning `false` if it should be ignored.
+    pub fn allow(&mut self, ip: IpAddr, now: Instant) -> bool {
+        // forget attempts that have left the window, and hosts with none left
+        self.attempts.retain(|_, times| {
+            times.retain(|t| now.duration_since(*t) < RCON_WINDOW);
+            !times.is_empty()
+        });
+
+        let times = self.attempts.entry(ip).or_insert_with(Vec::new);
+        if times.len() >= MAX_RCON_ATTEMPTS {
+            return false;
+        }
+
+        times.push(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_rcon() {
+        assert_eq!(
+            parse_rcon(b"rcon hunter2 map e1m1\n"),
+            Some(RconRequest {
+                password: "hunter2",
+                command: "map e1m1",
+            })
+        );
+        assert_eq!(
+            parse_rcon(b"rcon \"hunter 2\" status"),
+            Some(RconRequest {
+                password: "hunter 2",
+                command: "status",
+            })
+        );
+        assert_eq!(parse_rcon(b"rcon hunter2"), None);
+        assert_eq!(parse_rcon(b"getinfo abc"), None);
+    }
+
+    #[test]
+    fn test_check_password() {
+        assert!(check_password("hunter2", "hunter2"));
+        assert!(!check_password("hunter2", "hunter3"));
+        assert!(!check_password("hunter2", "hunter"));
+        assert!(!check_password("hunter2", "hunter22"));
+
+        // rcon is disabled without a password
+        assert!(!check_password("", ""));
+    }
+
+    #[test]
+    fn test_print_response() {
+        assert_eq!(print_response("ok\n"), b"nok\n".to_vec());
+    }
+
+    #[test]
+    fn test_rcon_limiter() {
+        let mut limiter = RconLimiter::new();
+        let host: IpAddr = "192.168.0.2".parse().unwrap();
+        let other: IpAddr = "192.168.0.3".parse().unwrap();
+        let start = Instant::now();
+
+        for _ in 0..MAX_RCON_ATTEMPTS {
+            assert!(limiter.allow(host, start));
+        }
+        assert!(!limiter.allow(host, start));
+
+        // other hosts aren't affected
+        assert!(limiter.allow(other, start));
+
+        // the host may try again once its attempts have aged out
+        assert!(limiter.allow(host, start + RCON_WINDOW));
+    }
+}
@@ -0,0 +1,711 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Saved games.
+//!
+//! Saved games are stored in the plain text format used by the original engine, so save files
+//! are interchangeable between the two. A save file consists of a header followed by a block of
+//! saved globals and one block per entity slot:
+//!
+//! ```text
+//! 5                                        // version
+//! The_Slipgate_Complex__kills:__3/_33____  // comment, spaces replaced with underscores
+//! 100.000000                               // 16 spawn parameters, one per line
+//! ...
+//! 1                                        // skill
+//! e1m1                                     // map name
+//! 34.500000                                // level time in seconds
+//! m                                        // 64 lightstyles, one per line
+//! ...
+//! {                                        // globals
+//! "serverflags" "0.000000"
+//! }
+//! {                                        // entity 0 (the world)
+//! "modelindex" "1.000000"
+//! }
+//! {                                        // vacant entity slot
+//! }
+//! ```
+//!
+//! Blocks are tokenized the same way as in the original engine: quoted strings may span multiple
+//! lines, and a block with no fields represents a vacant entity slot.
+
+use std::{
+    io::{self, Read, Write},
+    time::SystemTime,
+};
+
+use crate::{
+    common::{engine, random::GameRng},
+    server::{
+        progs::{Functions, Globals, ProgsError},
+        world::World,
+        Server, MAX_LIGHTSTYLES,
+    },
+};
+
+use chrono::Duration;
+use thiserror::Error;
+
+pub const SAVEGAME_VERSION: i32 = 5;
+pub const SAVEGAME_COMMENT_LENGTH: usize = 39;
+pub const NUM_SPAWN_PARMS: usize = 16;
+
+/// The slot written by `quicksave` and read by `quickload`.
+pub const QUICKSAVE_SLOT: &str = "quick";
+
+/// The number of autosave slots, which are overwritten in rotation.
+pub const AUTOSAVE_SLOTS: usize = 3;
+
+// the lightstyle written in place of an empty one
+const DEFAULT_LIGHTSTYLE: &str = "m";
+
+// the global that stores the state of the random number generator. the leading underscore keeps it
+// clear of any global defined by the progs.
+const RNG_STATE_GLOBAL: &str = "_rng_state";
+
+#[derive(Error, Debug)]
+pub enum SaveError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Progs error: {0}")]
+    Progs(#[from] ProgsError),
+    #[error("Savegame is version {0}, not {}", SAVEGAME_VERSION)]
+    UnsupportedVersion(i32),
+    #[error("Unexpected end of save file")]
+    UnexpectedEof,
+    #[error("Invalid {field} in save file: {value}")]
+    Invalid { field: &'static str, value: String },
+    #[error("Relative pathnames are not allowed")]
+    RelativePath,
+}
+
+/// Returns the file name of the save in the given slot.
+///
+/// As in the original engine, `.sav` is appended if the slot name has no extension.
+pub fn save_file_name<S>(slot: S) -> Result<String, SaveError>
+where
+    S: AsRef<str>,
+{
+    let slot = slot.as_ref();
+
+    if slot.contains("..") {
+        return Err(SaveError::RelativePath);
+    }
+
+    let file_name = slot.rsplit('/').next().unwrap_or(slot);
+    if file_name.contains('.') {
+        Ok(slot.to_owned())
+    } else {
+        Ok(format!("{}.sav", slot))
+    }
+}
+
+/// Formats the comment shown in the load and save menus.
+///
+/// The comment consists of the level name followed by the player's kill count, padded to
+/// `SAVEGAME_COMMENT_LENGTH` characters.
+pub fn save_comment<S>(level_name: S, kills: i32, total_kills: i32) -> String
+where
+    S: AsRef<str>,
+{
+    let comment = format!(
+        "{:<22.22}kills:{:>3}/{:>3}",
+        level_name.as_ref(),
+        kills,
+        total_kills
+    );
+
+    format!("{:<1$.1$}", comment, SAVEGAME_COMMENT_LENGTH)
+}
+
+/// Returns the name of the autosave slot with the given index, starting from `autosave1`.
+pub fn autosave_slot_name(index: usize) -> String {
+    format!("autosave{}", index + 1)
+}
+
+/// Chooses the autosave slot to overwrite next.
+///
+/// `modified` holds the modification time of each autosave slot, or `None` if the slot hasn't
+/// been written. The first unwritten slot is chosen if there is one, otherwise the slot written
+/// longest ago.
+pub fn next_autosave_slot(modified: &[Option<SystemTime>]) -> usize {
+    if let Some(unwritten) = modified.iter().position(Option::is_none) {
+        return unwritten;
+    }
+
+    modified
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, time)| *time)
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+/// Decides when a single-player game should be autosaved.
+///
+/// An autosave is due when a level starts and then every `sv_autosave_interval` seconds of
+/// server time. Autosaves are skipped while the game can't be saved (e.g. the player is dead),
+/// and happen as soon as it can be saved again.
+#[derive(Debug, Default)]
+pub struct AutosaveTimer {
+    // server time of the last autosave in this level, or None if the level just started
+    last_save: Option<Duration>,
+}
+
+impl AutosaveTimer {
+    pub fn new() -> AutosaveTimer {
+        AutosaveTimer { last_save: None }
+    }
+
+    /// Requests an autosave at the next opportunity, as when a new level starts.
+    pub fn level_started(&mut self) {
+        self.last_save = None;
+    }
+
+    /// Returns whether an autosave should be written now.
+    ///
+    /// `time` is the current server time. An `interval` of zero disables periodic autosaves, but
+    /// levels are still saved when they start. If this returns `true`, the save is assumed to
+    /// have been written.
+    pub fn check(&mut self, time: Duration, interval: Duration, can_save: bool) -> bool {
+        if !can_save {
+            return false;
+        }
+
+        let due = match self.last_save {
+            None => true,
+            Some(last) => interval > Duration::zero() && time - last >= interval,
+        };
+
+        if due {
+            self.last_save = Some(time);
+        }
+
+        due
+    }
+}
+
+pub(crate) fn format_float(f: f32) -> String {
+    format!("{:.6}", f)
+}
+
+pub(crate) fn format_vector(v: [f32; 3]) -> String {
+    format!("{:.6} {:.6} {:.6}", v[0], v[1], v[2])
+}
+
+/// Converts `\n` escape sequences to newlines the same way the original engine does when
+/// loading strings from entity data.
+pub(crate) fn unescape_string<S>(s: S) -> String
+where
+    S: AsRef<str>,
+{
+    let mut result = String::with_capacity(s.as_ref().len());
+    let mut chars = s.as_ref().chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.peek().is_some() {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                _ => result.push('\\'),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+// splits save data into tokens in the same manner as COM_Parse
+struct Tokens<'a> {
+    src: &'a str,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(src: &'a str) -> Tokens<'a> {
+        Tokens { src }
+    }
+
+    fn expect(&mut self) -> Result<&'a str, SaveError> {
+        self.next().ok_or(SaveError::UnexpectedEof)
+    }
+
+    // the header is read with scanf, so its tokens are only delimited by whitespace
+    fn word(&mut self) -> Result<&'a str, SaveError> {
+        self.src = self.src.trim_start();
+        if self.src.is_empty() {
+            return Err(SaveError::UnexpectedEof);
+        }
+
+        let end = self.src.find(char::is_whitespace).unwrap_or(self.src.len());
+        let word = &self.src[..end];
+        self.src = &self.src[end..];
+        Ok(word)
+    }
+
+    fn parse<T>(&mut self, field: &'static str) -> Result<T, SaveError>
+    where
+        T: std::str::FromStr,
+    {
+        let token = self.word()?;
+        token.parse().map_err(|_| SaveError::Invalid {
+            field,
+            value: token.to_owned(),
+        })
+    }
+
+    // parses a block of key-value pairs, or returns None at the end of the data
+    fn block(&mut self) -> Result<Option<Vec<(String, String)>>, SaveError> {
+        match self.next() {
+            None => return Ok(None),
+            Some("{") => (),
+            Some(other) => {
+                return Err(SaveError::Invalid {
+                    field: "block",
+                    value: other.to_owned(),
+                })
+            }
+        }
+
+        let mut fields = Vec::new();
+        loop {
+            let key = match self.expect()? {
+                "}" => break,
+                k => k,
+            };
+
+            let value = self.expect()?;
+            fields.push((key.to_owned(), value.to_owned()));
+        }
+
+        Ok(Some(fields))
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        loop {
+            self.src = self.src.trim_start();
+
+            // skip // comments
+            if self.src.starts_with("//") {
+                let end = self.src.find('\n').unwrap_or(self.src.len());
+                self.src = &self.src[end..];
+            } else {
+                break;
+            }
+        }
+
+        if self.src.is_empty() {
+            return None;
+        }
+
+        // quoted strings may contain any character except a quote
+        if let Some(rest) = self.src.strip_prefix('"') {
+            let end = rest.find('"').unwrap_or(rest.len());
+            let token = &rest[..end];
+            self.src = rest.get(end + 1..).unwrap_or("");
+            return Some(token);
+        }
+
+        if self.src.starts_with(|c| "{})(':".contains(c)) {
+            let token = &self.src[..1];
+            self.src = &self.src[1..];
+            return Some(token);
+        }
+
+        let end = self
+            .src
+            .find(|c: char| c.is_whitespace() || "{})(':".contains(c))
+            .unwrap_or(self.src.len());
+        let token = &self.src[..end];
+        self.src = &self.src[end..];
+        Some(token)
+    }
+}
+
+/// The complete state of a single-player game.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveGame {
+    /// Description shown in the load and save menus.
+    pub comment: String,
+
+    /// Spawn parameters of the player, which carry stats such as health and ammo.
+    pub spawn_parms: [f32; NUM_SPAWN_PARMS],
+
+    pub skill: i32,
+    pub map: String,
+
+    /// Server time elapsed since the level was loaded.
+    pub time: Duration,
+
+    pub lightstyles: Vec<String>,
+    pub globals: Vec<(String, String)>,
+
+    /// Saved fields of each entity slot. Vacant slots have no fields.
+    pub entities: Vec<Vec<(String, String)>>,
+
+    /// The state of the server's random number generator, so that the game continues as it
+    /// would have. Saves from the original engine don't have one.
+    pub rng_state: Option<u64>,
+}
+
+impl SaveGame {
+    /// Creates a saved game with no lightstyles, globals or entities.
+    ///
+    /// Use `capture` to fill in the state of a running server.
+    pub fn new<S>(
+        comment: S,
+        map: S,
+        skill: i32,
+        time: Duration,
+        spawn_parms: [f32; NUM_SPAWN_PARMS],
+    ) -> SaveGame
+    where
+        S: AsRef<str>,
+    {
+        SaveGame {
+            comment: comment.as_ref().to_owned(),
+            spawn_parms,
+            skill,
+            map: map.as_ref().to_owned(),
+            time,
+            lightstyles: Vec::new(),
+            globals: Vec::new(),
+            entities: Vec::new(),
+            rng_state: None,
+        }
+    }
+
+    /// Records the lightstyles, globals and entities of a running server.
+    pub fn capture(
+        &mut self,
+        server: &Server,
+        world: &World,
+        globals: &Globals,
+        functions: &Functions,
+    ) -> Result<(), ProgsError> {
+        self.lightstyles = server.lightstyles();
+        self.globals = globals.save_values()?;
+        self.entities = world.save_entities(functions)?;
+        self.rng_state = Some(server.rng().state());
+        Ok(())
+    }
+
+    /// Restores the lightstyles, globals and entities of this save to a running server.
+    ///
+    /// The level named by `map` must have just been spawned, so that the models and sounds its
+    /// entities precached have the same indices as when the game was saved. Its entities are
+    /// replaced by the saved ones. Unknown globals and fields are reported and skipped.
+    pub fn restore(
+        &self,
+        server: &mut Server,
+        world: &mut World,
+        globals: &mut Globals,
+        functions: &Functions,
+    ) -> Result<(), ProgsError> {
+        for (i, style) in self.lightstyles.iter().take(MAX_LIGHTSTYLES).enumerate() {
+            let style_id = server.string_table.insert(style);
+            server.set_lightstyle(i, style_id);
+        }
+
+        for (name, value) in self.globals.iter() {
+            if let Err(e) = globals.restore_value(name, value) {
+                warn!("{}", e);
+            }
+        }
+
+        if let Some(state) = self.rng_state {
+            *server.rng_mut() = GameRng::from_state(state);
+        }
+
+        world.restore_entities(&self.entities, functions)
+    }
+
+    /// Reads a saved game in the original engine's format.
+    pub fn read<R>(mut reader: R) -> Result<SaveGame, SaveError>
+    where
+        R: Read,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        // strings may contain characters from the Quake character set
+        let data = String::from_utf8_lossy(&data);
+        let mut tokens = Tokens::new(&data);
+
+        let version = tokens.parse("version")?;
+        if version != SAVEGAME_VERSION {
+            return Err(SaveError::UnsupportedVersion(version));
+        }
+
+        let comment = tokens.word()?.to_owned();
+
+        let mut spawn_parms = [0.0; NUM_SPAWN_PARMS];
+        for parm in spawn_parms.iter_mut() {
+            *parm = tokens.parse("spawn parameter")?;
+        }
+
+        // skill is written as an integer but read as a float
+        let skill = (tokens.parse::<f32>("skill")? + 0.1) as i32;
+        let map = tokens.word()?.to_owned();
+        let time = engine::duration_from_f32(tokens.parse("time")?);
+
+        let mut lightstyles = Vec::with_capacity(MAX_LIGHTSTYLES);
+        for _ in 0..MAX_LIGHTSTYLES {
+            lightstyles.push(tokens.word()?.to_owned());
+        }
+
+        let mut globals = tokens.block()?.ok_or(SaveError::UnexpectedEof)?;
+        let rng_state = globals
+            .iter()
+            .position(|(name, _)| name == RNG_STATE_GLOBAL)
+            .and_then(|i| globals.remove(i).1.parse().ok());
+
+        let mut entities = Vec::new();
+        while let Some(entity) = tokens.block()? {
+            entities.push(entity);
+        }
+
+        Ok(SaveGame {
+            comment,
+            spawn_parms,
+            skill,
+            map,
+            time,
+            lightstyles,
+            globals,
+            entities,
+            rng_state,
+        })
+    }
+
+    /// Writes this saved game in the original engine's format.
+    pub fn write<W>(&self, writer: &mut W) -> Result<(), SaveError>
+    where
+        W: Write,
+    {
+        writeln!(writer, "{}", SAVEGAME_VERSION)?;
+
+        // the comment must be a single token
+        let comment: String = self
+            .comment
+            .chars()
+            .take(SAVEGAME_COMMENT_LENGTH)
+            .map(|c| if c.is_whitespace() { '_' } else { c })
+            .collect();
+        writeln!(writer, "{}", comment)?;
+
+        for parm in self.spawn_parms.iter() {
+            writeln!(writer, "{}", format_float(*parm))?;
+        }
+
+        writeln!(writer, "{}", self.skill)?;
+        writeln!(writer, "{}", self.map)?;
+        writeln!(
+            writer,
+            "{}",
+            format_float(engine::duration_to_f32(self.time))
+        )?;
+
+        for i in 0..MAX_LIGHTSTYLES {
+            match self.lightstyles.get(i) {
+                Some(style) if !style.is_empty() => writeln!(writer, "{}", style)?,
+                _ => writeln!(writer, "{}", DEFAULT_LIGHTSTYLE)?,
+            }
+        }
+
+        // the original engine warns about the unknown global and carries on
+        match self.rng_state {
+            Some(state) => {
+                let mut globals = self.globals.clone();
+                globals.push((RNG_STATE_GLOBAL.to_owned(), state.to_string()));
+                write_block(writer, &globals)?;
+            }
+            None => write_block(writer, &self.globals)?,
+        }
+        for entity in self.entities.iter() {
+            write_block(writer, entity)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_block<W>(writer: &mut W, fields: &[(String, String)]) -> Result<(), SaveError>
+where
+    W: Write,
+{
+    writeln!(writer, "{{")?;
+    for (key, value) in fields.iter() {
+        writeln!(writer, "\"{}\" \"{}\"", key, value)?;
+    }
+    writeln!(writer, "}}")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn test_save() -> SaveGame {
+        let mut spawn_parms = [0.0; NUM_SPAWN_PARMS];
+        spawn_parms[1] = 100.0;
+        spawn_parms[3] = 25.0;
+
+        let mut save = SaveGame::new(
+            save_comment("the Slipgate Complex", 3, 33),
+            "e1m1".to_string(),
+            1,
+            Duration::milliseconds(34500),
+            spawn_parms,
+        );
+        save.lightstyles = vec!["m".to_string(), "mmnmmommommnonmmonqnmmo".to_string()];
+        save.lightstyles
+            .resize(MAX_LIGHTSTYLES, DEFAULT_LIGHTSTYLE.to_string());
+        save.globals = fields(&[("serverflags", "0.000000"), ("mapname", "e1m1")]);
+        save.rng_state = Some(0x0123_4567_89ab_cdef);
+        save.entities = vec![
+            fields(&[
+                ("modelindex", "1.000000"),
+                ("message", "the Slipgate Complex"),
+            ]),
+            Vec::new(),
+            fields(&[
+                ("classname", "info_player_start"),
+                ("origin", "480.000000 -352.000000 88.000000"),
+                ("think", "SUB_Remove"),
+            ]),
+        ];
+
+        save
+    }
+
+    #[test]
+    fn test_save_comment() {
+        let comment = save_comment("the Slipgate Complex", 3, 33);
+        assert_eq!(comment.len(), SAVEGAME_COMMENT_LENGTH);
+        assert_eq!(comment, "the Slipgate Complex  kills:  3/ 33    ");
+    }
+
+    #[test]
+    fn test_save_file_name() {
+        assert_eq!(save_file_name("s0").unwrap(), "s0.sav");
+        assert_eq!(save_file_name("quick.sav").unwrap(), "quick.sav");
+        assert_eq!(save_file_name(QUICKSAVE_SLOT).unwrap(), "quick.sav");
+        assert!(save_file_name("../s0").is_err());
+    }
+
+    #[test]
+    fn test_unescape_string() {
+        assert_eq!(unescape_string("one\\ntwo"), "one\ntwo");
+        assert_eq!(unescape_string("back\\slash"), "back\\lash");
+        assert_eq!(unescape_string("trailing\\"), "trailing\\");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let save = test_save();
+
+        let mut data = Vec::new();
+        save.write(&mut data).unwrap();
+        let mut loaded = SaveGame::read(data.as_slice()).unwrap();
+
+        // spaces in the comment are replaced when writing
+        assert_eq!(loaded.comment, save.comment.replace(' ', "_"));
+        loaded.comment = save.comment.clone();
+
+        assert_eq!(loaded, save);
+    }
+
+    #[test]
+    fn test_read_original_format() {
+        let mut data = String::new();
+        data.push_str("5\r\nStart_________________kills:__0/__0____\r\n");
+        for _ in 0..NUM_SPAWN_PARMS {
+            data.push_str("0.000000\r\n");
+        }
+        data.push_str("2\r\nstart\r\n1.500000\r\n");
+        for _ in 0..MAX_LIGHTSTYLES {
+            data.push_str("m\r\n");
+        }
+        data.push_str("{\r\n\"deathmatch\" \"0.000000\"\r\n}\r\n");
+        data.push_str("{\r\n\"message\" \"Introduction\r\nsecond line\"\r\n\"wad\" \"\"\r\n}\r\n");
+        data.push_str("{\r\n}\r\n");
+
+        let save = SaveGame::read(data.as_bytes()).unwrap();
+        assert_eq!(save.skill, 2);
+        assert_eq!(save.map, "start");
+        assert_eq!(save.time, Duration::milliseconds(1500));
+        assert_eq!(save.globals, fields(&[("deathmatch", "0.000000")]));
+        assert_eq!(save.rng_state, None);
+        assert_eq!(save.entities.len(), 2);
+        assert_eq!(
+            save.entities[0],
+            fields(&[("message", "Introduction\r\nsecond line"), ("wad", "")])
+        );
+        assert!(save.entities[1].is_empty());
+    }
+
+    #[test]
+    fn test_read_unsupported_version() {
+        match SaveGame::read("6\n".as_bytes()) {
+            Err(SaveError::UnsupportedVersion(6)) => (),
+            other => panic!("expected UnsupportedVersion, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_next_autosave_slot() {
+        let t0 = SystemTime::UNIX_EPOCH;
+        let t1 = t0 + std::time::Duration::from_secs(10);
+        let t2 = t0 + std::time::Duration::from_secs(20);
+
+        assert_eq!(next_autosave_slot(&[None, None, None]), 0);
+        assert_eq!(next_autosave_slot(&[Some(t0), None, Some(t1)]), 1);
+        assert_eq!(next_autosave_slot(&[Some(t1), Some(t0), Some(t2)]), 1);
+        assert_eq!(autosave_slot_name(0), "autosave1");
+    }
+
+    #[test]
+    fn test_autosave_timer() {
+        let interval = Duration::seconds(60);
+        let mut timer = AutosaveTimer::new();
+
+        // save when the level starts, but not while the player is dead
+        assert!(!timer.check(Duration::seconds(1), interval, false));
+        assert!(timer.check(Duration::seconds(2), interval, true));
+        assert!(!timer.check(Duration::seconds(30), interval, true));
+        assert!(timer.check(Duration::seconds(62), interval, true));
+
+        // periodic saves can be disabled
+        assert!(!timer.check(Duration::seconds(500), Duration::zero(), true));
+        timer.level_started();
+        assert!(timer.check(Duration::seconds(501), Duration::zero(), true));
+    }
+}
@@ -18,9 +18,12 @@
 use std::{convert::TryInto, error::Error, fmt, rc::Rc};
 
 use crate::{
-    common::net::EntityState,
+    common::{net::EntityState, parse},
     server::{
-        progs::{EntityId, FieldDef, FunctionId, ProgsError, StringId, StringTable, Type},
+        progs::{
+            EntityId, FieldDef, FunctionId, Functions, ProgsError, StringId, StringTable, Type,
+        },
+        save,
         world::phys::MoveKind,
     },
 };
@@ -596,24 +599,150 @@ impl Entity {
         }
     }
 
+    /// Returns the engine flags of this entity.
+    ///
+    /// Mods may store their own flags in the same field, so unknown bits are ignored.
     pub fn flags(&self) -> Result<EntityFlags, EntityError> {
         let flags_i = self.get_float(FieldAddrFloat::Flags as i16)? as u16;
-        match EntityFlags::from_bits(flags_i) {
-            Some(f) => Ok(f),
-            None => Err(EntityError::with_msg(format!(
-                "Invalid internal flags value ({})",
-                flags_i
-            ))),
-        }
+        Ok(EntityFlags::from_bits_truncate(flags_i))
     }
 
     pub fn add_flags(&mut self, flags: EntityFlags) -> Result<(), EntityError> {
-        let result = self.flags()? | flags;
-        self.put_float(result.bits() as f32, FieldAddrFloat::Flags as i16)?;
+        let result = self.get_float(FieldAddrFloat::Flags as i16)? as u16 | flags.bits();
+        self.put_float(result as f32, FieldAddrFloat::Flags as i16)?;
+        Ok(())
+    }
+
+    pub fn remove_flags(&mut self, flags: EntityFlags) -> Result<(), EntityError> {
+        let result = self.get_float(FieldAddrFloat::Flags as i16)? as u16 & !flags.bits();
+        self.put_float(result as f32, FieldAddrFloat::Flags as i16)?;
         Ok(())
     }
 
     pub fn owner(&self) -> Result<EntityId, EntityError> {
         Ok(self.get_entity_id(FieldAddrEntityId::Owner as i16)?)
     }
+
+    fn find_field_def<S>(&self, name: S) -> Option<&FieldDef>
+    where
+        S: AsRef<str>,
+    {
+        self.type_def
+            .field_defs
+            .iter()
+            .find(|def| self.string_table.get(def.name_id).as_deref() == Some(name.as_ref()))
+    }
+
+    /// Returns the name and value of every nonzero field of this entity.
+    ///
+    /// Values are formatted as they appear in the original engine's save files. Vector component
+    /// fields (e.g. `origin_x`) are omitted since they are saved as part of the vector.
+    pub fn save_values(&self, functions: &Functions) -> Result<Vec<(String, String)>, EntityError> {
+        let mut values = Vec::new();
+
+        for def in self.type_def.field_defs.iter() {
+            let name = self.string_table.get(def.name_id).unwrap();
+            if name.len() >= 2 && name.as_bytes()[name.len() - 2] == b'_' {
+                continue;
+            }
+
+            let addr = def.offset as i16;
+            let size = match def.type_ {
+                Type::QVector => 3,
+                _ => 1,
+            };
+
+            let mut zero = true;
+            for i in 0..size {
+                if self.get_bytes(addr + i)? != [0; 4] {
+                    zero = false;
+                }
+            }
+
+            if zero {
+                continue;
+            }
+
+            let value = match def.type_ {
+                Type::QVoid | Type::QPointer => continue,
+                Type::QString => self
+                    .string_table
+                    .get(self.get_string_id(addr)?)
+                    .unwrap_or_default(),
+                Type::QFloat => save::format_float(self.get_float(addr)?),
+                Type::QVector => save::format_vector(self.get_vector(addr)?),
+                Type::QEntity => self.get_entity_id(addr)?.0.to_string(),
+                Type::QField => {
+                    let offset = self.get_int(addr)?;
+                    match self
+                        .type_def
+                        .field_defs
+                        .iter()
+                        .find(|d| d.offset as i32 == offset)
+                    {
+                        Some(d) => self.string_table.get(d.name_id).unwrap(),
+                        None => continue,
+                    }
+                }
+                Type::QFunction => {
+                    let f_def = functions
+                        .get_def(self.get_function_id(addr)?)
+                        .map_err(|e| EntityError::with_msg(e.to_string()))?;
+                    self.string_table.get(f_def.name_id).unwrap()
+                }
+            };
+
+            values.push((name, value));
+        }
+
+        Ok(values)
+    }
+
+    /// Sets the field with the given name from a value in the save file format.
+    pub fn restore_value<S>(
+        &mut self,
+        name: S,
+        value: S,
+        functions: &Functions,
+    ) -> Result<(), EntityError>
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref();
+        let value = value.as_ref();
+
+        let (type_, addr) = match self.find_field_def(name) {
+            Some(def) => (def.type_, def.offset as i16),
+            None => return Err(EntityError::with_msg(format!("'{}' is not a field", name))),
+        };
+
+        let invalid = || EntityError::with_msg(format!("Invalid value for {}: {}", name, value));
+
+        match type_ {
+            Type::QVoid | Type::QPointer => (),
+            Type::QString => {
+                let s_id = self.string_table.insert(save::unescape_string(value));
+                self.put_string_id(s_id, addr)?;
+            }
+            Type::QFloat => self.put_float(value.parse().map_err(|_| invalid())?, addr)?,
+            Type::QVector => {
+                self.put_vector(parse::vector3_components(value).ok_or_else(invalid)?, addr)?
+            }
+            Type::QEntity => {
+                self.put_entity_id(EntityId(value.parse().map_err(|_| invalid())?), addr)?
+            }
+            Type::QField => {
+                let offset = self.find_field_def(value).ok_or_else(invalid)?.offset;
+                self.put_int(offset as i32, addr)?;
+            }
+            Type::QFunction => {
+                let f_id = functions
+                    .find_function_by_name(value)
+                    .map_err(|_| invalid())?;
+                self.put_function_id(f_id, addr)?;
+            }
+        }
+
+        Ok(())
+    }
 }
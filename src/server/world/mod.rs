@@ -16,6 +16,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 mod entity;
+mod monster;
 mod phys;
 
 use std::{
@@ -24,42 +25,73 @@ use std::{
 };
 
 use self::{
-    entity::{Entity, EntityFlags, EntitySolid},
-    phys::{Collide, CollideKind, MoveKind},
+    entity::{Entity, MAX_ENT_LEAVES},
+    phys::Collide,
 };
 pub use self::{
     entity::{
-        EntityError, EntityTypeDef, FieldAddrEntityId, FieldAddrFloat, FieldAddrFunctionId,
-        FieldAddrStringId, FieldAddrVector,
+        EntityError, EntityFlags, EntitySolid, EntityTypeDef, FieldAddrEntityId, FieldAddrFloat,
+        FieldAddrFunctionId, FieldAddrStringId, FieldAddrVector,
     },
-    phys::{Trace, TraceEnd, TraceStart},
+    monster::angle_mod,
+    phys::{CollideKind, MoveKind, PhysicsContext, Trace, TraceEnd, TraceFlags, TraceStart},
 };
 
 use crate::{
     common::{
         bsp,
-        bsp::{BspCollisionHull, BspLeafContents},
+        bsp::BspCollisionHull,
         console::CvarRegistry,
         engine, mdl,
         model::{Model, ModelKind},
+        net::{EntityEffects, EntityState, EntityUpdate},
         parse, sprite,
         vfs::Vfs,
+        vis::{self, Pvs, VisCache},
     },
     server::{
+        hooks::HealthEvent,
         progs::{
-            EntityFieldAddr, EntityId, ExecutionContext, FieldAddr, FieldDef, GlobalAddrEntity,
-            GlobalAddrFloat, GlobalAddrFunction, Globals, ProgsError, StringId, StringTable, Type,
+            EntityFieldAddr, EntityId, ExecutionContext, FieldAddr, FieldDef, Functions,
+            GlobalAddrEntity, GlobalAddrFloat, Globals, ProgsError, StringId, StringTable, Type,
         },
         Server,
     },
 };
 
-use cgmath::{InnerSpace, Vector3, Zero};
-use chrono::Duration;
+use cgmath::{Deg, InnerSpace, Matrix, Vector3, Zero};
 
 const AREA_DEPTH: usize = 4;
 const MAX_ENTITIES: usize = 600;
 
+// boxes narrower than this collide with brush models using the point hull
+const MAX_POINT_HULL_SIZE: f32 = 3.0;
+
+// boxes up to this wide collide with brush models using the player-sized hull
+const MAX_PLAYER_HULL_SIZE: f32 = 32.0;
+
+// width of the field name column printed by describe_entity()
+const FIELD_NAME_WIDTH: usize = 15;
+
+/// Entity statistics reported by the `edictcount` command.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct EntityCounts {
+    /// The number of entity slots up to and including the last one in use.
+    pub slots: usize,
+
+    /// The number of slots in use.
+    pub active: usize,
+
+    /// The number of entities with a model.
+    pub view: usize,
+
+    /// The number of entities that are not `SOLID_NOT`.
+    pub touch: usize,
+
+    /// The number of entities using `MOVETYPE_STEP`.
+    pub step: usize,
+}
+
 enum AreaNodeKind {
     Branch(AreaBranch),
     Leaf,
@@ -210,6 +242,23 @@ struct AreaBranch {
 struct AreaEntity {
     entity: Entity,
     area_id: Option<usize>,
+
+    // health as of the last call to dispatch_health_events()
+    last_health: f32,
+}
+
+impl AreaEntity {
+    fn new(entity: Entity) -> AreaEntity {
+        let last_health = entity
+            .get_float(FieldAddrFloat::Health as i16)
+            .unwrap_or(0.0);
+
+        AreaEntity {
+            entity,
+            area_id: None,
+            last_health,
+        }
+    }
 }
 
 enum AreaEntitySlot {
@@ -225,6 +274,12 @@ pub struct World {
     area_nodes: Box<[AreaNode]>,
     slots: Box<[AreaEntitySlot]>,
     models: Vec<Model>,
+
+    // decompressed visibility data of the world model
+    vis: VisCache,
+
+    // entities 1 through max_clients are reserved for the players
+    max_clients: usize,
 }
 
 impl World {
@@ -232,6 +287,7 @@ impl World {
         mut brush_models: Vec<Model>,
         type_def: Rc<EntityTypeDef>,
         string_table: Rc<StringTable>,
+        max_clients: usize,
     ) -> Result<World, ProgsError> {
         // generate area tree for world model
         let area_nodes = AreaNode::generate(brush_models[0].min(), brush_models[0].max());
@@ -244,12 +300,17 @@ impl World {
         // take ownership of all brush models
         models.append(&mut brush_models);
 
+        let vis = match models[1].kind() {
+            ModelKind::Brush(ref bmodel) => VisCache::new(&bmodel.bsp_data()),
+            _ => VisCache::none(),
+        };
+
         // generate world entity
         let mut world_entity = Entity::new(string_table.clone(), type_def.clone());
-        world_entity.put_string_id(
-            string_table.find(models[1].name()).unwrap(),
-            FieldAddrStringId::ModelName as i16,
-        )?;
+        let model_name_id = string_table
+            .find(models[1].name())
+            .unwrap_or_else(|| string_table.insert(models[1].name()));
+        world_entity.put_string_id(model_name_id, FieldAddrStringId::ModelName as i16)?;
         world_entity.put_float(1.0, FieldAddrFloat::ModelIndex as i16)?;
         world_entity.put_float(EntitySolid::Bsp as u32 as f32, FieldAddrFloat::Solid as i16)?;
         world_entity.put_float(
@@ -257,12 +318,25 @@ impl World {
             FieldAddrFloat::MoveKind as i16,
         )?;
 
+        if max_clients + 1 >= MAX_ENTITIES {
+            return Err(ProgsError::with_msg(format!(
+                "Too many clients ({})",
+                max_clients
+            )));
+        }
+
         let mut slots = Vec::with_capacity(MAX_ENTITIES);
-        slots.push(AreaEntitySlot::Occupied(AreaEntity {
-            entity: world_entity,
-            area_id: None,
-        }));
-        for _ in 0..MAX_ENTITIES - 1 {
+        slots.push(AreaEntitySlot::Occupied(AreaEntity::new(world_entity)));
+
+        // client entities always exist, even for empty slots
+        for _ in 0..max_clients {
+            slots.push(AreaEntitySlot::Occupied(AreaEntity::new(Entity::new(
+                string_table.clone(),
+                type_def.clone(),
+            ))));
+        }
+
+        for _ in max_clients + 1..MAX_ENTITIES {
             slots.push(AreaEntitySlot::Vacant);
         }
 
@@ -272,6 +346,8 @@ impl World {
             type_def,
             slots: slots.into_boxed_slice(),
             models,
+            vis,
+            max_clients,
         })
     }
 
@@ -356,23 +432,23 @@ impl World {
         }
     }
 
-    fn find_vacant_slot(&self) -> Result<usize, ()> {
-        for (i, slot) in self.slots.iter().enumerate() {
+    fn find_vacant_slot(&self) -> Result<usize, ProgsError> {
+        for (i, slot) in self.slots.iter().enumerate().skip(self.max_clients + 1) {
             if let &AreaEntitySlot::Vacant = slot {
                 return Ok(i);
             }
         }
 
-        panic!("no vacant slots");
+        Err(ProgsError::with_msg("No free entity slots"))
     }
 
     pub fn alloc_uninitialized(&mut self) -> Result<EntityId, ProgsError> {
-        let slot_id = self.find_vacant_slot().unwrap();
+        let slot_id = self.find_vacant_slot()?;
 
-        self.slots[slot_id] = AreaEntitySlot::Occupied(AreaEntity {
-            entity: Entity::new(self.string_table.clone(), self.type_def.clone()),
-            area_id: None,
-        });
+        self.slots[slot_id] = AreaEntitySlot::Occupied(AreaEntity::new(Entity::new(
+            self.string_table.clone(),
+            self.type_def.clone(),
+        )));
 
         Ok(EntityId(slot_id))
     }
@@ -380,8 +456,8 @@ impl World {
     /// Allocate a new entity and initialize it with the data in the given map.
     ///
     /// For each entry in `map`, this will locate a field definition for the entry key, parse the
-    /// entry value to the correct type, and store it at that field. It will then locate the spawn
-    /// method for the entity's `classname` and execute it.
+    /// entry value to the correct type, and store it at that field. Entries naming unknown fields
+    /// or holding invalid values are reported and skipped, as in the original engine.
     ///
     /// ## Special cases
     ///
@@ -392,7 +468,53 @@ impl World {
     /// - `light`: This is simply an alias for `light_lev`.
     pub fn alloc_from_map(&mut self, map: HashMap<&str, &str>) -> Result<EntityId, ProgsError> {
         let mut ent = Entity::new(self.string_table.clone(), self.type_def.clone());
+        self.load_map_fields(&mut ent, &map)?;
+
+        let entry_id = self.find_vacant_slot()?;
+
+        self.slots[entry_id] = AreaEntitySlot::Occupied(AreaEntity::new(ent));
+
+        Ok(EntityId(entry_id))
+    }
+
+    /// Sets the fields of an existing entity from the data in the given map.
+    ///
+    /// This is used for the world entity, which always exists but takes its fields from the first
+    /// entity in the map. See `alloc_from_map`.
+    pub fn load_entity_from_map(
+        &mut self,
+        entity_id: EntityId,
+        map: &HashMap<&str, &str>,
+    ) -> Result<(), ProgsError> {
+        // take the entity out of its slot while its fields are set
+        let mut area_ent = match self.slots.get_mut(entity_id.0) {
+            Some(slot) => match std::mem::replace(slot, AreaEntitySlot::Vacant) {
+                AreaEntitySlot::Occupied(e) => e,
+                AreaEntitySlot::Vacant => {
+                    return Err(ProgsError::with_msg(format!(
+                        "No entity at list entry {}",
+                        entity_id.0
+                    )))
+                }
+            },
+            None => {
+                return Err(ProgsError::with_msg(format!(
+                    "Invalid entity ID ({})",
+                    entity_id.0
+                )))
+            }
+        };
 
+        let result = self.load_map_fields(&mut area_ent.entity, map);
+        self.slots[entity_id.0] = AreaEntitySlot::Occupied(area_ent);
+        result
+    }
+
+    fn load_map_fields(
+        &self,
+        ent: &mut Entity,
+        map: &HashMap<&str, &str>,
+    ) -> Result<(), ProgsError> {
         for (key, val) in map.iter() {
             debug!(".{} = {}", key, val);
             match *key {
@@ -404,50 +526,60 @@ impl World {
                     // only the yaw (Y) value is given. see
                     // https://github.com/id-Software/Quake/blob/master/WinQuake/pr_edict.c#L826-L834
                     let def = self.find_def("angles")?.clone();
-                    ent.put_vector([0.0, val.parse().unwrap(), 0.0], def.offset as i16)?;
+                    match val.parse() {
+                        Ok(yaw) => ent.put_vector([0.0, yaw, 0.0], def.offset as i16)?,
+                        Err(_) => warn!("Bad angle: {}", val),
+                    }
                 }
 
                 "light" => {
                     // more fun hacks brought to you by Carmack & Friends
                     let def = self.find_def("light_lev")?.clone();
-                    ent.put_float(val.parse().unwrap(), def.offset as i16)?;
+                    match val.parse() {
+                        Ok(light) => ent.put_float(light, def.offset as i16)?,
+                        Err(_) => warn!("Bad light: {}", val),
+                    }
                 }
 
                 k => {
-                    let def = self.find_def(k)?.clone();
+                    let def = match self.find_def(k) {
+                        Ok(d) => d.clone(),
+                        Err(_) => {
+                            warn!("'{}' is not a field", k);
+                            continue;
+                        }
+                    };
 
                     match def.type_ {
                         // void has no value, skip it
                         Type::QVoid => (),
 
-                        // TODO: figure out if this ever happens
-                        Type::QPointer => unimplemented!(),
-
                         Type::QString => {
                             let s_id = self.string_table.insert(val);
                             ent.put_string_id(s_id, def.offset as i16)?;
                         }
 
-                        Type::QFloat => ent.put_float(val.parse().unwrap(), def.offset as i16)?,
-                        Type::QVector => ent.put_vector(
-                            parse::vector3_components(val).unwrap(),
-                            def.offset as i16,
-                        )?,
-                        Type::QEntity => {
-                            let id: usize = val.parse().unwrap();
+                        Type::QFloat => match val.parse() {
+                            Ok(f) => ent.put_float(f, def.offset as i16)?,
+                            Err(_) => warn!("Bad value for {}: {}", k, val),
+                        },
 
-                            if id > MAX_ENTITIES {
-                                panic!("out-of-bounds entity access");
-                            }
+                        Type::QVector => match parse::vector3_components(val) {
+                            Some(v) => ent.put_vector(v, def.offset as i16)?,
+                            None => warn!("Bad value for {}: {}", k, val),
+                        },
 
-                            match self.slots[id] {
-                                AreaEntitySlot::Vacant => panic!("no entity with id {}", id),
-                                AreaEntitySlot::Occupied(_) => (),
+                        Type::QEntity => match val.parse::<usize>() {
+                            Ok(id) if self.entity_exists(EntityId(id)) => {
+                                ent.put_entity_id(EntityId(id), def.offset as i16)?
                             }
+                            _ => warn!("Bad entity for {}: {}", k, val),
+                        },
 
-                            ent.put_entity_id(EntityId(id), def.offset as i16)?
+                        Type::QPointer | Type::QField => {
+                            warn!("Can't set field {} of type {:?} from a map", k, def.type_)
                         }
-                        Type::QField => panic!("attempted to store field of type Field in entity"),
+
                         Type::QFunction => {
                             // TODO: need to validate this against function table
                         }
@@ -456,20 +588,13 @@ impl World {
             }
         }
 
-        let entry_id = self.find_vacant_slot().unwrap();
-
-        self.slots[entry_id] = AreaEntitySlot::Occupied(AreaEntity {
-            entity: ent,
-            area_id: None,
-        });
-
-        Ok(EntityId(entry_id))
+        Ok(())
     }
 
     pub fn free(&mut self, entity_id: EntityId) -> Result<(), ProgsError> {
         // TODO: unlink entity from world
 
-        if entity_id.0 as usize > self.slots.len() {
+        if entity_id.0 as usize >= self.slots.len() {
             return Err(ProgsError::with_msg(format!(
                 "Invalid entity ID ({:?})",
                 entity_id
@@ -484,8 +609,146 @@ impl World {
         Ok(())
     }
 
+    /// Returns the set of world leaves potentially visible from `point`.
+    pub fn pvs_at(&self, point: Vector3<f32>) -> Pvs {
+        self.vis.pvs(self.leaf_at(point))
+    }
+
+    /// Returns the set of world leaves potentially hearable from `point`.
+    pub fn phs_at(&self, point: Vector3<f32>) -> Pvs {
+        self.vis.phs(self.leaf_at(point))
+    }
+
+    /// Samples the world lighting at `point` from the floor below it.
+    ///
+    /// If `spread` is nonzero, the floor is also sampled that far around the point and the
+    /// results averaged, as for the lighting of models. Each lightmap is scaled by the value of
+    /// its style in `style_values`. Returns `None` if there is no lit floor below the point.
+    pub fn light_at(
+        &self,
+        point: Vector3<f32>,
+        spread: f32,
+        style_values: &[f32],
+    ) -> Option<[f32; 3]> {
+        match self.models[1].kind() {
+            ModelKind::Brush(ref bmodel) => {
+                bmodel
+                    .bsp_data()
+                    .light_near(point, spread, style_values, true)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the decompressed visibility data of the world.
+    pub fn vis(&self) -> &VisCache {
+        &self.vis
+    }
+
+    /// Returns the ID of the world leaf containing `point`.
+    pub fn leaf_at(&self, point: Vector3<f32>) -> usize {
+        match self.models[1].kind() {
+            ModelKind::Brush(ref bmodel) => bmodel.bsp_data().find_leaf(point),
+            _ => 0,
+        }
+    }
+
+    /// Returns the IDs of all entities that are potentially visible in `pvs`.
+    ///
+    /// The world entity is always visible and is not included.
+    pub fn visible_entities(&self, pvs: &Pvs) -> Result<Vec<EntityId>, ProgsError> {
+        let bsp_data = match self.models[1].kind() {
+            ModelKind::Brush(ref bmodel) => bmodel.bsp_data(),
+            _ => return Ok(Vec::new()),
+        };
+
+        let mut visible = Vec::new();
+        for (i, slot) in self.slots.iter().enumerate().skip(1) {
+            if let AreaEntitySlot::Occupied(ref e) = slot {
+                let ent = &e.entity;
+                let in_pvs = if ent.leaf_count == 0 || ent.leaf_count > MAX_ENT_LEAVES {
+                    // not linked, or touching too many leaves to record
+                    pvs.contains_bounds(&bsp_data, ent.abs_min()?, ent.abs_max()?)
+                } else {
+                    pvs.contains_any(&ent.leaf_ids[..ent.leaf_count])
+                };
+
+                if in_pvs {
+                    visible.push(EntityId(i));
+                }
+            }
+        }
+
+        Ok(visible)
+    }
+
+    /// Returns the ID of the first entity after `entity_id`, if there is one.
+    pub fn next_entity(&self, entity_id: EntityId) -> Option<EntityId> {
+        (entity_id.0 + 1..self.slots.len())
+            .find(|&i| match self.slots[i] {
+                AreaEntitySlot::Occupied(_) => true,
+                AreaEntitySlot::Vacant => false,
+            })
+            .map(EntityId)
+    }
+
+    /// Returns `true` if there is an entity with the given ID.
+    pub fn entity_exists(&self, entity_id: EntityId) -> bool {
+        match self.slots.get(entity_id.0) {
+            Some(AreaEntitySlot::Occupied(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the number of entity slots reserved for clients.
+    pub fn max_clients(&self) -> usize {
+        self.max_clients
+    }
+
+    /// Returns the state of an entity as it is sent to clients.
+    pub fn entity_state(&self, entity_id: EntityId) -> Result<EntityState, ProgsError> {
+        let ent = self.try_get_entity(entity_id)?;
+        let angles = ent.get_vector(FieldAddrVector::Angles as i16)?;
+
+        Ok(EntityState {
+            origin: ent.origin()?,
+            angles: Vector3::new(Deg(angles[0]), Deg(angles[1]), Deg(angles[2])),
+            model_id: ent.model_index()?,
+            frame_id: ent.get_float(FieldAddrFloat::FrameId as i16)? as usize,
+            colormap: ent.get_float(FieldAddrFloat::Colormap as i16)? as u8,
+            skin_id: ent.get_float(FieldAddrFloat::SkinId as i16)? as usize,
+            effects: EntityEffects::from_bits_truncate(
+                ent.get_float(FieldAddrFloat::Effects as i16)? as u8,
+            ),
+        })
+    }
+
+    /// Records the state that updates to an entity are sent relative to.
+    pub fn set_baseline(
+        &mut self,
+        entity_id: EntityId,
+        baseline: EntityState,
+    ) -> Result<(), ProgsError> {
+        self.try_get_entity_mut(entity_id)?.baseline = baseline;
+        Ok(())
+    }
+
+    /// Returns the update that brings a client's copy of an entity from its baseline to its
+    /// current state.
+    pub fn entity_update(&self, entity_id: EntityId) -> Result<EntityUpdate, ProgsError> {
+        let ent = self.try_get_entity(entity_id)?;
+        let state = self.entity_state(entity_id)?;
+
+        let mut update = EntityUpdate::from_entity_state(entity_id.0 as u16, &state, &ent.baseline);
+
+        // monsters move in steps, which clients shouldn't smooth out
+        update.no_lerp = ent.move_kind()? == MoveKind::Step;
+
+        Ok(update)
+    }
+
     pub fn try_get_entity(&self, entity_id: EntityId) -> Result<&Entity, ProgsError> {
-        if entity_id.0 as usize > self.slots.len() {
+        if entity_id.0 as usize >= self.slots.len() {
             return Err(ProgsError::with_msg(format!(
                 "Invalid entity ID ({})",
                 entity_id.0 as usize
@@ -502,7 +765,7 @@ impl World {
     }
 
     pub fn try_get_entity_mut(&mut self, entity_id: EntityId) -> Result<&mut Entity, ProgsError> {
-        if entity_id.0 as usize > self.slots.len() {
+        if entity_id.0 as usize >= self.slots.len() {
             return Err(ProgsError::with_msg(format!(
                 "Invalid entity ID ({})",
                 entity_id.0 as usize
@@ -519,7 +782,7 @@ impl World {
     }
 
     fn try_get_area_entity(&self, entity_id: EntityId) -> Result<&AreaEntity, ProgsError> {
-        if entity_id.0 as usize > self.slots.len() {
+        if entity_id.0 as usize >= self.slots.len() {
             return Err(ProgsError::with_msg(format!(
                 "Invalid entity ID ({})",
                 entity_id.0 as usize
@@ -539,7 +802,7 @@ impl World {
         &mut self,
         entity_id: EntityId,
     ) -> Result<&mut AreaEntity, ProgsError> {
-        if entity_id.0 as usize > self.slots.len() {
+        if entity_id.0 as usize >= self.slots.len() {
             return Err(ProgsError::with_msg(format!(
                 "Invalid entity ID ({})",
                 entity_id.0 as usize
@@ -555,12 +818,168 @@ impl World {
         }
     }
 
+    /// Returns the saved fields of every entity slot up to the last one in use.
+    ///
+    /// Vacant slots are represented by an empty list of fields.
+    pub fn save_entities(
+        &self,
+        functions: &Functions,
+    ) -> Result<Vec<Vec<(String, String)>>, ProgsError> {
+        let end = self
+            .slots
+            .iter()
+            .rposition(|slot| match slot {
+                AreaEntitySlot::Occupied(_) => true,
+                AreaEntitySlot::Vacant => false,
+            })
+            .map(|last| last + 1)
+            .unwrap_or(0);
+
+        let mut entities = Vec::with_capacity(end);
+        for slot in self.slots[..end].iter() {
+            entities.push(match slot {
+                AreaEntitySlot::Vacant => Vec::new(),
+                AreaEntitySlot::Occupied(ref e) => e.entity.save_values(functions)?,
+            });
+        }
+
+        Ok(entities)
+    }
+
+    /// Formats the nonzero fields of an entity in the style of the original engine's `edict`
+    /// command.
+    pub fn describe_entity(
+        &self,
+        entity_id: EntityId,
+        functions: &Functions,
+    ) -> Result<String, ProgsError> {
+        let slot = self
+            .slots
+            .get(entity_id.0)
+            .ok_or_else(|| ProgsError::with_msg(format!("Invalid entity ID ({})", entity_id.0)))?;
+
+        let mut desc = format!("\nEDICT {}:\n", entity_id.0);
+        match slot {
+            AreaEntitySlot::Vacant => desc.push_str("FREE\n"),
+            AreaEntitySlot::Occupied(ref e) => {
+                for (name, value) in e.entity.save_values(functions)? {
+                    desc.push_str(&format!(
+                        "{:width$}{}\n",
+                        name,
+                        value,
+                        width = FIELD_NAME_WIDTH
+                    ));
+                }
+            }
+        }
+
+        Ok(desc)
+    }
+
+    /// Formats every entity slot up to the last one in use, as printed by the `edicts` command.
+    pub fn describe_entities(&self, functions: &Functions) -> Result<String, ProgsError> {
+        let counts = self.entity_counts()?;
+
+        let mut desc = String::new();
+        for slot_id in 0..counts.slots {
+            desc.push_str(&self.describe_entity(EntityId(slot_id), functions)?);
+        }
+        desc.push_str(&format!("{} entities\n", counts.slots));
+
+        Ok(desc)
+    }
+
+    /// Counts the entities in the world by how they participate in the simulation.
+    pub fn entity_counts(&self) -> Result<EntityCounts, ProgsError> {
+        let mut counts = EntityCounts::default();
+
+        for (slot_id, slot) in self.slots.iter().enumerate() {
+            let entity = match slot {
+                AreaEntitySlot::Vacant => continue,
+                AreaEntitySlot::Occupied(ref e) => &e.entity,
+            };
+
+            counts.slots = slot_id + 1;
+            counts.active += 1;
+
+            if entity.model_index()? != 0 {
+                counts.view += 1;
+            }
+
+            if entity.solid()? != EntitySolid::Not {
+                counts.touch += 1;
+            }
+
+            if entity.move_kind()? == MoveKind::Step {
+                counts.step += 1;
+            }
+        }
+
+        Ok(counts)
+    }
+
+    /// Replaces every entity in the world with the given saved entities and links them.
+    ///
+    /// Entities with no fields are left vacant, except for the world and client entities. Invalid
+    /// fields are reported and skipped, as in the original engine.
+    pub fn restore_entities(
+        &mut self,
+        entities: &[Vec<(String, String)>],
+        functions: &Functions,
+    ) -> Result<(), ProgsError> {
+        if entities.len() > self.slots.len() {
+            return Err(ProgsError::with_msg(format!(
+                "Too many entities in saved game ({})",
+                entities.len()
+            )));
+        }
+
+        for slot_id in 0..self.slots.len() {
+            self.unlink_entity(EntityId(slot_id))?;
+
+            let fields = match entities.get(slot_id) {
+                Some(f) if !f.is_empty() || slot_id <= self.max_clients => f,
+                _ => {
+                    self.slots[slot_id] = AreaEntitySlot::Vacant;
+                    continue;
+                }
+            };
+
+            let mut entity = Entity::new(self.string_table.clone(), self.type_def.clone());
+            for (name, value) in fields.iter() {
+                // keys starting with an underscore are editor-only
+                if name.starts_with('_') {
+                    continue;
+                }
+
+                if let Err(e) = entity.restore_value(name, value, functions) {
+                    warn!("Entity {}: {}", slot_id, e);
+                }
+            }
+
+            self.slots[slot_id] = AreaEntitySlot::Occupied(AreaEntity::new(entity));
+            self.link_entity(EntityId(slot_id))?;
+        }
+
+        Ok(())
+    }
+
     pub fn spawn_entity(&mut self) -> Result<EntityId, ProgsError> {
         let e_id = self.alloc_uninitialized()?;
-        self.link_entity(e_id, false)?;
+        self.link_entity(e_id)?;
         Ok(e_id)
     }
 
+    /// Resets every field of an entity to zero, leaving its slot in use.
+    ///
+    /// This is used to prepare a client's entity before it is put into the game.
+    pub fn clear_entity(&mut self, e_id: EntityId) -> Result<(), ProgsError> {
+        self.unlink_entity(e_id)?;
+        let entity = Entity::new(self.string_table.clone(), self.type_def.clone());
+        *self.try_get_area_entity_mut(e_id)? = AreaEntity::new(entity);
+        Ok(())
+    }
+
     pub fn spawn_entity_from_map(
         &mut self,
         execution_context: &mut ExecutionContext,
@@ -585,11 +1004,56 @@ impl World {
         execution_context.execute_program_by_name(globals, self, cvars, server, vfs, classname)?;
 
         // TODO: should touch triggers?
-        self.link_entity(e_id, false)?;
+        self.link_entity(e_id)?;
+
+        // spawn functions may remove their entity, e.g. for deathmatch-only items
+        if let AreaEntitySlot::Occupied(_) = self.slots[e_id.0] {
+            let world = &*self;
+            server.dispatch_hooks(|hooks| hooks.entity_spawned(world, e_id, classname));
+        }
 
         Ok(e_id)
     }
 
+    /// Reports any damage or deaths since the last call to the hooks registered with `server`.
+    ///
+    /// Damage is inferred from the change in each entity's `health` field, so this should be
+    /// called once per server frame after entity physics has run.
+    pub fn dispatch_health_events(&mut self, server: &mut Server) -> Result<(), ProgsError> {
+        for slot_id in 0..self.slots.len() {
+            let area_ent = match self.slots[slot_id] {
+                AreaEntitySlot::Occupied(ref mut e) => e,
+                AreaEntitySlot::Vacant => continue,
+            };
+
+            let health = area_ent.entity.get_float(FieldAddrFloat::Health as i16)?;
+            let old_health = std::mem::replace(&mut area_ent.last_health, health);
+            let event = match HealthEvent::from_change(old_health, health) {
+                Some(e) => e,
+                None => continue,
+            };
+
+            let inflictor = match area_ent
+                .entity
+                .get_entity_id(FieldAddrEntityId::DmgInflictor as i16)?
+            {
+                EntityId(0) => None,
+                id => Some(id),
+            };
+
+            let e_id = EntityId(slot_id);
+            let world = &*self;
+            server.dispatch_hooks(|hooks| match event {
+                HealthEvent::Damaged(damage) => {
+                    hooks.entity_damaged(world, e_id, damage, inflictor)
+                }
+                HealthEvent::Killed => hooks.entity_killed(world, e_id, inflictor),
+            });
+        }
+
+        Ok(())
+    }
+
     fn unlink_entity(&mut self, e_id: EntityId) -> Result<(), ProgsError> {
         // if this entity has been removed or freed, do nothing
         if let AreaEntitySlot::Vacant = self.slots[e_id.0 as usize] {
@@ -614,7 +1078,7 @@ impl World {
         Ok(())
     }
 
-    fn link_entity(&mut self, e_id: EntityId, touch_triggers: bool) -> Result<(), ProgsError> {
+    fn link_entity(&mut self, e_id: EntityId) -> Result<(), ProgsError> {
         // don't link the world entity
         if e_id.0 == 0 {
             return Ok(());
@@ -629,6 +1093,7 @@ impl World {
 
         let mut abs_min;
         let mut abs_max;
+        let model_index;
         let solid;
         {
             let ent = self.try_get_entity_mut(e_id)?;
@@ -640,9 +1105,20 @@ impl World {
             abs_min = origin + mins;
             abs_max = origin + maxs;
 
-            let flags_f = ent.get_float(FieldAddrFloat::Flags as i16)?;
-            let flags = EntityFlags::from_bits(flags_f as u16).unwrap();
-            if flags.contains(EntityFlags::ITEM) {
+            // a turned brush model can reach as far as its farthest corner in any direction
+            let angles = Vector3::from(ent.get_vector(FieldAddrVector::Angles as i16)?);
+            if ent.solid()? == EntitySolid::Bsp && angles != Vector3::zero() {
+                let corner = Vector3::new(
+                    mins.x.abs().max(maxs.x.abs()),
+                    mins.y.abs().max(maxs.y.abs()),
+                    mins.z.abs().max(maxs.z.abs()),
+                );
+                let radius = corner.magnitude();
+                abs_min = origin - Vector3::new(radius, radius, radius);
+                abs_max = origin + Vector3::new(radius, radius, radius);
+            }
+
+            if ent.flags()?.contains(EntityFlags::ITEM) {
                 abs_min.x -= 15.0;
                 abs_min.y -= 15.0;
                 abs_max.x += 15.0;
@@ -660,19 +1136,31 @@ impl World {
             ent.put_vector(abs_max.into(), FieldAddrVector::AbsMax as i16)?;
 
             ent.leaf_count = 0;
-            let model_index = ent.get_float(FieldAddrFloat::ModelIndex as i16)?;
-            if model_index != 0.0 {
-                // TODO: SV_FindTouchedLeafs
-            }
-
+            model_index = ent.get_float(FieldAddrFloat::ModelIndex as i16)?;
             solid = ent.solid()?;
+        }
 
-            if solid == EntitySolid::Not {
-                // this entity has no touch interaction, we're done
-                return Ok(());
+        // record the world leaves the entity touches so visibility checks can skip the BSP walk
+        if model_index != 0.0 {
+            let leaves = match self.models[1].kind() {
+                ModelKind::Brush(ref bmodel) => {
+                    vis::leaves_in_bounds(&bmodel.bsp_data(), abs_min, abs_max)
+                }
+                _ => Vec::new(),
+            };
+
+            let ent = self.try_get_entity_mut(e_id)?;
+            ent.leaf_count = leaves.len();
+            for (slot, leaf_id) in ent.leaf_ids.iter_mut().zip(leaves) {
+                *slot = leaf_id;
             }
         }
 
+        if solid == EntitySolid::Not {
+            // this entity has no touch interaction, we're done
+            return Ok(());
+        }
+
         let mut node_id = 0;
         loop {
             match self.area_nodes[node_id].kind {
@@ -705,13 +1193,82 @@ impl World {
             self.try_get_area_entity_mut(e_id)?.area_id = Some(node_id);
         }
 
-        if touch_triggers {
-            unimplemented!();
+        Ok(())
+    }
+
+    /// Links an entity into the world and runs the touch functions of the triggers it overlaps.
+    pub fn link_entity_and_touch(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+    ) -> Result<(), ProgsError> {
+        self.link_entity(e_id)?;
+
+        // only linked entities can touch triggers
+        match self.slots[e_id.0] {
+            AreaEntitySlot::Occupied(AreaEntity {
+                area_id: Some(_), ..
+            }) => (),
+            _ => return Ok(()),
+        }
+
+        let abs_min = self.try_get_entity(e_id)?.abs_min()?;
+        let abs_max = self.try_get_entity(e_id)?.abs_max()?;
+
+        // touch functions can link and remove entities, so find the triggers before running any
+        let mut triggers = Vec::new();
+        self.find_triggers(0, abs_min, abs_max, &mut triggers);
+        triggers.sort_by_key(|t| t.0);
+
+        let time = engine::duration_to_f32(ctx.server.time());
+
+        for trigger in triggers {
+            if trigger == e_id || !self.entity_exists(trigger) || !self.entity_exists(e_id) {
+                continue;
+            }
+
+            let trigger_ent = self.try_get_entity(trigger)?;
+            let touch = trigger_ent.get_function_id(FieldAddrFunctionId::Touch as i16)?;
+            if touch.0 == 0 || trigger_ent.solid()? != EntitySolid::Trigger {
+                continue;
+            }
+
+            let ent = self.try_get_entity(e_id)?;
+            let (min, max) = (ent.abs_min()?, ent.abs_max()?);
+            let (trigger_min, trigger_max) = (trigger_ent.abs_min()?, trigger_ent.abs_max()?);
+            if (0..3).any(|i| min[i] > trigger_max[i] || max[i] < trigger_min[i]) {
+                continue;
+            }
+
+            ctx.globals.put_float(time, GlobalAddrFloat::Time as i16)?;
+            ctx.call(self, touch, trigger, e_id)?;
         }
 
         Ok(())
     }
 
+    // collects the triggers linked into the area tree that may overlap the given bounds
+    fn find_triggers(
+        &self,
+        area_id: usize,
+        abs_min: Vector3<f32>,
+        abs_max: Vector3<f32>,
+        triggers: &mut Vec<EntityId>,
+    ) {
+        let area = &self.area_nodes[area_id];
+        triggers.extend(area.triggers.iter().cloned());
+
+        if let AreaNodeKind::Branch(ref b) = area.kind {
+            if abs_max[b.axis as usize] > b.dist {
+                self.find_triggers(b.front, abs_min, abs_max, triggers);
+            }
+
+            if abs_min[b.axis as usize] < b.dist {
+                self.find_triggers(b.back, abs_min, abs_max, triggers);
+            }
+        }
+    }
+
     /// Update this entity's position and relink it into the world.
     pub fn set_entity_origin(
         &mut self,
@@ -723,7 +1280,7 @@ impl World {
             ent.put_vector(origin.into(), FieldAddrVector::Origin as i16)?;
         }
 
-        self.link_entity(e_id, false)?;
+        self.link_entity(e_id)?;
         Ok(())
     }
 
@@ -807,7 +1364,7 @@ impl World {
             // entity hit the floor. update origin, relink and set ON_GROUND flag.
             self.try_get_entity_mut(e_id)?
                 .put_vector(trace.end_point().into(), FieldAddrVector::Origin as i16)?;
-            self.link_entity(e_id, false)?;
+            self.link_entity(e_id)?;
             self.try_get_entity_mut(e_id)?
                 .add_flags(EntityFlags::ON_GROUND)?;
             self.try_get_entity_mut(e_id)?
@@ -817,45 +1374,48 @@ impl World {
         }
     }
 
-    // TODO: handle the offset return value internally
+    /// Returns the collision hull of an entity for a box with the given bounds, and the offset
+    /// of the hull from the world origin.
+    ///
+    /// Brush entities use the hull of their model that best fits the box. Other entities use a
+    /// box hull expanded by the bounds of the moving box.
     pub fn hull_for_entity(
         &self,
         e_id: EntityId,
         min: Vector3<f32>,
         max: Vector3<f32>,
     ) -> Result<(BspCollisionHull, Vector3<f32>), ProgsError> {
-        let solid = self.try_get_entity(e_id)?.solid()?;
+        let ent = self.try_get_entity(e_id)?;
+        let solid = ent.solid()?;
         debug!("Entity solid type: {:?}", solid);
 
         match solid {
             EntitySolid::Bsp => {
-                if self.try_get_entity(e_id)?.move_kind()? != MoveKind::Push {
+                if ent.move_kind()? != MoveKind::Push {
                     return Err(ProgsError::with_msg(format!(
                         "Brush entities must have MoveKind::Push (has {:?})",
-                        self.try_get_entity(e_id)?.move_kind()?
+                        ent.move_kind()?
                     )));
                 }
 
                 let size = max - min;
-                match self.models[self.try_get_entity(e_id)?.model_index()?].kind() {
+                match self.models[ent.model_index()?].kind() {
                     &ModelKind::Brush(ref bmodel) => {
-                        let hull_index;
-
-                        // TODO: replace these magic constants
-                        if size[0] < 3.0 {
-                            debug!("Using hull 0");
-                            hull_index = 0;
-                        } else if size[0] <= 32.0 {
-                            debug!("Using hull 1");
-                            hull_index = 1;
+                        let hull_index = if size[0] < MAX_POINT_HULL_SIZE {
+                            0
+                        } else if size[0] <= MAX_PLAYER_HULL_SIZE {
+                            1
                         } else {
-                            debug!("Using hull 2");
-                            hull_index = 2;
-                        }
+                            2
+                        };
+                        debug!("Using hull {}", hull_index);
 
-                        let hull = bmodel.hull(hull_index).unwrap();
+                        let hull = bmodel
+                            .hull(hull_index)
+                            .map_err(|e| ProgsError::with_msg(format!("{}", e)))?;
 
-                        let offset = hull.min() - min + self.try_get_entity(e_id)?.origin()?;
+                        // center the hull on the origin of the moving box
+                        let offset = hull.min() - min + ent.origin()?;
 
                         Ok((hull, offset))
                     }
@@ -866,82 +1426,23 @@ impl World {
             }
 
             _ => {
-                let hull = BspCollisionHull::for_bounds(
-                    self.try_get_entity(e_id)?.min()?,
-                    self.try_get_entity(e_id)?.max()?,
-                )
-                .unwrap();
-                let offset = self.try_get_entity(e_id)?.origin()?;
+                let hull = BspCollisionHull::for_bounds(ent.min()? - max, ent.max()? - min)
+                    .map_err(|e| ProgsError::with_msg(format!("{}", e)))?;
+                let offset = ent.origin()?;
 
                 Ok((hull, offset))
             }
         }
     }
 
-    pub fn physics(
-        &mut self,
-        globals: &mut Globals,
-        execution_context: &mut ExecutionContext,
-        cvars: &mut CvarRegistry,
-        server: &mut Server,
-        vfs: &Vfs,
-        sv_time: Duration,
-    ) -> Result<(), ProgsError> {
-        globals.put_entity_id(EntityId(0), GlobalAddrEntity::Self_ as i16)?;
-        globals.put_entity_id(EntityId(0), GlobalAddrEntity::Other as i16)?;
-        globals.put_float(
-            engine::duration_to_f32(sv_time),
-            GlobalAddrFloat::Time as i16,
-        )?;
-        let start_frame = globals.get_function_id(GlobalAddrFunction::StartFrame as i16)?;
-        execution_context.execute_program(globals, self, cvars, server, vfs, start_frame)?;
-
-        for i in 0..self.slots.len() {
-            if let AreaEntitySlot::Vacant = self.slots[i] {
-                continue;
-            }
-
-            // check force_retouch
-            if globals.get_float(GlobalAddrFloat::ForceRetouch as i16)? != 0.0 {
-                self.link_entity(EntityId(i), true)?;
-            }
-
-            if unimplemented!() {
-                // TODO: process client entities
-            } else {
-                match self.try_get_entity(EntityId(i))?.move_kind()? {
-                    MoveKind::Push => unimplemented!(),
-                    MoveKind::None => unimplemented!(),
-                    MoveKind::NoClip => unimplemented!(),
-                    MoveKind::Step => unimplemented!(),
-
-                    // all airborne entities have the same physics
-                    _ => unimplemented!(),
-                }
-            }
-
-            match globals.get_float(GlobalAddrFloat::ForceRetouch as i16)? {
-                f if f > 0.0 => globals.put_float(f - 1.0, GlobalAddrFloat::ForceRetouch as i16)?,
-                _ => (),
-            }
-        }
-
-        // TODO: increase sv.time by host_frametime
-        unimplemented!();
-    }
-
-    // TODO: rename arguments when implementing
-    pub fn physics_player(
-        &mut self,
-        _globals: &mut Globals,
-        _server: &Server,
-        _ent_id: EntityId,
-    ) -> Result<(), ProgsError> {
-        unimplemented!();
-    }
-
+    /// Traces the movement of a box from `start` to `end`, ported from the original engine's
+    /// `SV_Move`.
+    ///
+    /// The box is clipped against the world and every solid entity except `e_id`, its owner and
+    /// the entities it owns. Returns the trace and the entity that was hit, which is the world
+    /// entity if nothing was.
     pub fn move_entity(
-        &mut self,
+        &self,
         e_id: EntityId,
         start: Vector3<f32>,
         min: Vector3<f32>,
@@ -954,19 +1455,11 @@ impl World {
             start, min, max, end
         );
 
-        debug!("Collision test: Entity {} with world entity", e_id.0);
-        let trace = self.collide_move_with_entity(EntityId(0), start, min, max, end)?;
-
-        debug!(
-            "End position after collision test with world hull: {:?}",
-            trace.end_point()
-        );
-
         // if this is a rocket or a grenade, expand the monster collision box
         let (monster_min, monster_max) = match kind {
             CollideKind::Missile => (
-                min - Vector3::new(15.0, 15.0, 15.0),
-                max + Vector3::new(15.0, 15.0, 15.0),
+                Vector3::new(-15.0, -15.0, -15.0),
+                Vector3::new(15.0, 15.0, 15.0),
             ),
             _ => (min, max),
         };
@@ -987,40 +1480,38 @@ impl World {
             kind,
         };
 
-        self.collide(&collide)?;
-
-        // XXX: set this to the right entity
-        Ok((trace, EntityId(0)))
+        self.collide(&collide)
     }
 
-    pub fn collide(&self, collide: &Collide) -> Result<(Trace, Option<EntityId>), ProgsError> {
-        self.collide_area(0, collide)
+    pub fn collide(&self, collide: &Collide) -> Result<(Trace, EntityId), ProgsError> {
+        debug!("Collision test: world entity");
+        let mut trace = self.collide_move_with_entity(
+            EntityId(0),
+            collide.start,
+            collide.min,
+            collide.max,
+            collide.end,
+        )?;
+
+        let mut collide_entity = EntityId(0);
+        self.collide_area(0, collide, &mut trace, &mut collide_entity)?;
+
+        Ok((trace, collide_entity))
     }
 
     fn collide_area(
         &self,
         area_id: usize,
         collide: &Collide,
-    ) -> Result<(Trace, Option<EntityId>), ProgsError> {
-        let mut trace = Trace::new(
-            TraceStart::new(Vector3::zero(), 0.0),
-            TraceEnd::terminal(Vector3::zero()),
-            BspLeafContents::Empty,
-        );
-
-        let mut collide_entity = None;
-
+        trace: &mut Trace,
+        collide_entity: &mut EntityId,
+    ) -> Result<(), ProgsError> {
         let area = &self.area_nodes[area_id];
 
         for touch in area.solids.iter() {
-            // don't collide an entity with itself
-            if let Some(e) = collide.e_id {
-                if e == *touch {
-                    continue;
-                }
-            }
+            let touch_ent = self.try_get_entity(*touch)?;
 
-            match self.try_get_entity(*touch)?.solid()? {
+            match touch_ent.solid()? {
                 // if the other entity has no collision, skip it
                 EntitySolid::Not => continue,
 
@@ -1040,85 +1531,81 @@ impl World {
                 }
             }
 
+            // don't collide an entity with itself
+            if collide.e_id == Some(*touch) {
+                continue;
+            }
+
             // if bounding boxes never intersect, skip this entity
-            for i in 0..3 {
-                if collide.move_min[i] > self.try_get_entity(*touch)?.abs_max()?[i]
-                    || collide.move_max[i] < self.try_get_entity(*touch)?.abs_min()?[i]
-                {
-                    continue;
-                }
+            let abs_min = touch_ent.abs_min()?;
+            let abs_max = touch_ent.abs_max()?;
+            if (0..3).any(|i| collide.move_min[i] > abs_max[i] || collide.move_max[i] < abs_min[i])
+            {
+                continue;
             }
 
             if let Some(e) = collide.e_id {
-                if self.try_get_entity(e)?.size()?[0] != 0.0
-                    && self.try_get_entity(*touch)?.size()?[0] == 0.0
-                {
+                // points never interact
+                if self.try_get_entity(e)?.size()?[0] != 0.0 && touch_ent.size()?[0] == 0.0 {
                     continue;
                 }
             }
 
             if trace.all_solid() {
-                return Ok((trace, collide_entity));
+                return Ok(());
             }
 
             if let Some(e) = collide.e_id {
                 // don't collide against owner or owned entities
-                if self.try_get_entity(*touch)?.owner()? == e
-                    || self.try_get_entity(e)?.owner()? == *touch
-                {
+                if touch_ent.owner()? == e || self.try_get_entity(e)?.owner()? == *touch {
                     continue;
                 }
             }
 
             // select bounding boxes based on whether or not candidate is a monster
-            let tmp_trace;
-            if self
-                .try_get_entity(*touch)?
-                .flags()?
-                .contains(EntityFlags::MONSTER)
-            {
-                tmp_trace = self.collide_move_with_entity(
+            let tmp_trace = if touch_ent.flags()?.contains(EntityFlags::MONSTER) {
+                self.collide_move_with_entity(
                     *touch,
                     collide.start,
                     collide.monster_min,
                     collide.monster_max,
                     collide.end,
-                )?;
+                )?
             } else {
-                tmp_trace = self.collide_move_with_entity(
+                self.collide_move_with_entity(
                     *touch,
                     collide.start,
                     collide.min,
                     collide.max,
                     collide.end,
-                )?;
-            }
-
-            let old_dist = (trace.end_point() - collide.start).magnitude();
-            let new_dist = (tmp_trace.end_point() - collide.start).magnitude();
+                )?
+            };
 
             // check to see if this candidate is the closest yet and update trace if so
-            if tmp_trace.all_solid() || tmp_trace.start_solid() || new_dist < old_dist {
-                collide_entity = Some(*touch);
-                trace = tmp_trace;
+            if tmp_trace.all_solid() || tmp_trace.start_solid() || tmp_trace.ratio() < trace.ratio()
+            {
+                let start_solid = trace.start_solid();
+                *trace = tmp_trace;
+                if start_solid {
+                    trace.set_start_solid();
+                }
+                *collide_entity = *touch;
+            } else if tmp_trace.start_solid() {
+                trace.set_start_solid();
             }
         }
 
-        match area.kind {
-            AreaNodeKind::Leaf => (),
-
-            AreaNodeKind::Branch(ref b) => {
-                if collide.move_max[b.axis as usize] > b.dist {
-                    self.collide_area(b.front, collide)?;
-                }
+        if let AreaNodeKind::Branch(ref b) = area.kind {
+            if collide.move_max[b.axis as usize] > b.dist {
+                self.collide_area(b.front, collide, trace, collide_entity)?;
+            }
 
-                if collide.move_min[b.axis as usize] < b.dist {
-                    self.collide_area(b.back, collide)?;
-                }
+            if collide.move_min[b.axis as usize] < b.dist {
+                self.collide_area(b.back, collide, trace, collide_entity)?;
             }
         }
 
-        Ok((trace, collide_entity))
+        Ok(())
     }
 
     pub fn collide_move_with_entity(
@@ -1131,14 +1618,30 @@ impl World {
     ) -> Result<Trace, ProgsError> {
         let (hull, offset) = self.hull_for_entity(e_id, min, max)?;
         debug!("hull offset: {:?}", offset);
-        debug!(
-            "hull contents at start: {:?}",
-            hull.contents_at_point(start).unwrap()
-        );
 
-        Ok(hull
-            .trace(start - offset, end - offset)
-            .unwrap()
-            .adjust(offset))
+        // a turned brush model is traced in its own frame
+        let ent = self.try_get_entity(e_id)?;
+        let angles = Vector3::from(ent.get_vector(FieldAddrVector::Angles as i16)?);
+        let rotation = if ent.solid()? == EntitySolid::Bsp && angles != Vector3::zero() {
+            Some(phys::brush_rotation(angles))
+        } else {
+            None
+        };
+
+        let (start, end) = match rotation {
+            Some(r) => (r * (start - offset), r * (end - offset)),
+            None => (start - offset, end - offset),
+        };
+
+        let trace = hull
+            .trace_move(start, end)
+            .map_err(|e| ProgsError::with_msg(format!("{}", e)))?;
+
+        let trace = match rotation {
+            Some(r) => trace.rotate(r.transpose()),
+            None => trace,
+        };
+
+        Ok(trace.adjust(offset))
     }
 }
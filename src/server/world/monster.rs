@@ -0,0 +1,460 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Movement for monsters, which walk in steps rather than sliding along their velocity.
+
+use crate::{
+    common::bsp::BspLeafContents,
+    server::{
+        progs::{EntityId, GlobalAddrEntity, ProgsError},
+        world::{
+            entity::EntityFlags,
+            phys::{CollideKind, PhysicsContext},
+            FieldAddrEntityId, FieldAddrFloat, FieldAddrVector, World,
+        },
+    },
+};
+
+use cgmath::{Vector3, Zero};
+use rand::RngCore;
+
+// the tallest step a monster can climb
+const STEP_SIZE: f32 = 18.0;
+
+// the direction of an axis a monster has no reason to move along
+const NO_DIRECTION: f32 = -1.0;
+
+/// Reduces an angle in degrees to the range [0, 360) with 16-bit precision.
+pub fn angle_mod(angle: f32) -> f32 {
+    (360.0 / 65536.0) * (((angle * (65536.0 / 360.0)) as i32) & 65535) as f32
+}
+
+impl World {
+    /// Returns `true` if the entity's bounding box is supported by the ground under all of its
+    /// corners.
+    pub fn check_bottom(&self, e_id: EntityId) -> Result<bool, ProgsError> {
+        let ent = self.try_get_entity(e_id)?;
+        let mins = ent.origin()? + ent.min()?;
+        let maxs = ent.origin()? + ent.max()?;
+
+        // if all of the points under the corners are solid world, don't bother with the tougher
+        // checks
+        let mut all_solid = true;
+        for &x in [mins.x, maxs.x].iter() {
+            for &y in [mins.y, maxs.y].iter() {
+                let point = Vector3::new(x, y, mins.z - 1.0);
+                if self.point_contents(point)? != BspLeafContents::Solid {
+                    all_solid = false;
+                }
+            }
+        }
+
+        if all_solid {
+            return Ok(true);
+        }
+
+        // the midpoint must be within a step of the bottom
+        let mut start = Vector3::new((mins.x + maxs.x) * 0.5, (mins.y + maxs.y) * 0.5, mins.z);
+        let mut stop = Vector3::new(start.x, start.y, start.z - 2.0 * STEP_SIZE);
+        let (trace, _) = self.move_entity(
+            e_id,
+            start,
+            Vector3::zero(),
+            Vector3::zero(),
+            stop,
+            CollideKind::NoMonsters,
+        )?;
+        if trace.ratio() == 1.0 {
+            return Ok(false);
+        }
+
+        let mid = trace.end_point().z;
+
+        // the corners must be within a step of the midpoint
+        for &x in [mins.x, maxs.x].iter() {
+            for &y in [mins.y, maxs.y].iter() {
+                start.x = x;
+                start.y = y;
+                stop.x = x;
+                stop.y = y;
+
+                let (trace, _) = self.move_entity(
+                    e_id,
+                    start,
+                    Vector3::zero(),
+                    Vector3::zero(),
+                    stop,
+                    CollideKind::NoMonsters,
+                )?;
+                if trace.ratio() == 1.0 || mid - trace.end_point().z > STEP_SIZE {
+                    return Ok(false);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Tries to move a monster by `move_`, stepping up and down stairs if it walks.
+    ///
+    /// Returns `false` and leaves the entity in place if the move would leave it stuck, unsupported
+    /// or (for swimming monsters) out of the water.
+    pub fn move_step(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        move_: Vector3<f32>,
+        relink: bool,
+    ) -> Result<bool, ProgsError> {
+        let ent = self.try_get_entity(e_id)?;
+        let old_origin = ent.origin()?;
+        let (min, max) = (ent.min()?, ent.max()?);
+        let flags = ent.flags()?;
+        let enemy = ent.get_entity_id(FieldAddrEntityId::Enemy as i16)?;
+
+        // flying monsters don't step up
+        if flags.intersects(EntityFlags::SWIM | EntityFlags::FLY) {
+            // try one move with vertical motion, then one without
+            for i in 0..2 {
+                let mut new_origin = old_origin + move_;
+                if i == 0 && enemy != EntityId(0) {
+                    let dz = old_origin.z - self.try_get_entity(enemy)?.origin()?.z;
+                    if dz > 40.0 {
+                        new_origin.z -= 8.0;
+                    }
+                    if dz < 30.0 {
+                        new_origin.z += 8.0;
+                    }
+                }
+
+                let (trace, _) =
+                    self.move_entity(e_id, old_origin, min, max, new_origin, CollideKind::Normal)?;
+
+                if trace.ratio() == 1.0 {
+                    // swimming monsters can't leave the water
+                    if flags.contains(EntityFlags::SWIM)
+                        && self.point_contents(trace.end_point())? == BspLeafContents::Empty
+                    {
+                        return Ok(false);
+                    }
+
+                    self.put_vector(e_id, FieldAddrVector::Origin, trace.end_point())?;
+                    if relink {
+                        self.link_entity_and_touch(ctx, e_id)?;
+                    }
+                    return Ok(true);
+                }
+
+                if enemy == EntityId(0) {
+                    break;
+                }
+            }
+
+            return Ok(false);
+        }
+
+        // push down from a step height above the wished position
+        let mut new_origin = old_origin + move_;
+        new_origin.z += STEP_SIZE;
+        let mut end = new_origin;
+        end.z -= STEP_SIZE * 2.0;
+
+        let (mut trace, mut ground) =
+            self.move_entity(e_id, new_origin, min, max, end, CollideKind::Normal)?;
+        if trace.all_solid() {
+            return Ok(false);
+        }
+
+        if trace.start_solid() {
+            new_origin.z -= STEP_SIZE;
+            let (t, g) = self.move_entity(e_id, new_origin, min, max, end, CollideKind::Normal)?;
+            if t.all_solid() || t.start_solid() {
+                return Ok(false);
+            }
+            trace = t;
+            ground = g;
+        }
+
+        if trace.ratio() == 1.0 {
+            // if the monster had the ground pulled out, go ahead and fall
+            if flags.contains(EntityFlags::PARTIAL_GROUND) {
+                self.put_vector(e_id, FieldAddrVector::Origin, old_origin + move_)?;
+                if relink {
+                    self.link_entity_and_touch(ctx, e_id)?;
+                }
+                self.try_get_entity_mut(e_id)?
+                    .remove_flags(EntityFlags::ON_GROUND)?;
+                return Ok(true);
+            }
+
+            // walked off an edge
+            return Ok(false);
+        }
+
+        // check point traces down for dangling corners
+        self.put_vector(e_id, FieldAddrVector::Origin, trace.end_point())?;
+        if !self.check_bottom(e_id)? {
+            if flags.contains(EntityFlags::PARTIAL_GROUND) {
+                // the entity had its floor mostly pulled out from underneath it and is trying to
+                // correct
+                if relink {
+                    self.link_entity_and_touch(ctx, e_id)?;
+                }
+                return Ok(true);
+            }
+
+            self.put_vector(e_id, FieldAddrVector::Origin, old_origin)?;
+            return Ok(false);
+        }
+
+        let ent = self.try_get_entity_mut(e_id)?;
+        ent.remove_flags(EntityFlags::PARTIAL_GROUND)?;
+        ent.put_entity_id(ground, FieldAddrEntityId::Ground as i16)?;
+
+        // the move is ok
+        if relink {
+            self.link_entity_and_touch(ctx, e_id)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Turns an entity toward its ideal yaw, by no more than its yaw speed.
+    pub fn change_yaw(&mut self, e_id: EntityId) -> Result<(), ProgsError> {
+        let mut angles = self.get_vector(e_id, FieldAddrVector::Angles)?;
+        let current = angle_mod(angles.y);
+        let ideal = self.get_float(e_id, FieldAddrFloat::IdealYaw)?;
+        let speed = self.get_float(e_id, FieldAddrFloat::YawSpeed)?;
+
+        if current == ideal {
+            return Ok(());
+        }
+
+        let mut move_ = ideal - current;
+        if ideal > current {
+            if move_ >= 180.0 {
+                move_ -= 360.0;
+            }
+        } else if move_ <= -180.0 {
+            move_ += 360.0;
+        }
+
+        let move_ = move_.max(-speed).min(speed);
+        angles.y = angle_mod(current + move_);
+        self.put_vector(e_id, FieldAddrVector::Angles, angles)
+    }
+
+    /// Moves a monster `dist` units along `yaw` if it is standing on something or can fly or
+    /// swim.
+    ///
+    /// This is the implementation of the `walkmove` builtin.
+    pub fn walk_move(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        yaw: f32,
+        dist: f32,
+    ) -> Result<bool, ProgsError> {
+        let flags = EntityFlags::ON_GROUND | EntityFlags::FLY | EntityFlags::SWIM;
+        if !self.has_flags(e_id, flags)? {
+            return Ok(false);
+        }
+
+        let yaw = yaw.to_radians();
+        let move_ = Vector3::new(yaw.cos() * dist, yaw.sin() * dist, 0.0);
+
+        // touch functions run by the move may change self
+        let old_self = ctx.globals.get_entity_id(GlobalAddrEntity::Self_ as i16)?;
+        let moved = self.move_step(ctx, e_id, move_, true)?;
+        ctx.globals
+            .put_entity_id(old_self, GlobalAddrEntity::Self_ as i16)?;
+
+        Ok(moved)
+    }
+
+    // turns toward yaw and takes a step in that direction
+    fn step_direction(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        yaw: f32,
+        dist: f32,
+    ) -> Result<bool, ProgsError> {
+        self.put_float(e_id, FieldAddrFloat::IdealYaw, yaw)?;
+        self.change_yaw(e_id)?;
+
+        let rad = yaw.to_radians();
+        let move_ = Vector3::new(rad.cos() * dist, rad.sin() * dist, 0.0);
+        let old_origin = self.get_vector(e_id, FieldAddrVector::Origin)?;
+
+        let moved = self.move_step(ctx, e_id, move_, false)?;
+        if moved {
+            let delta = self.get_vector(e_id, FieldAddrVector::Angles)?.y
+                - self.get_float(e_id, FieldAddrFloat::IdealYaw)?;
+
+            // not turned far enough, so don't take the step
+            if delta > 45.0 && delta < 315.0 {
+                self.put_vector(e_id, FieldAddrVector::Origin, old_origin)?;
+            }
+        }
+
+        self.link_entity_and_touch(ctx, e_id)?;
+        Ok(moved)
+    }
+
+    // picks a new direction to move toward the goal in when the current one is blocked
+    fn new_chase_dir(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        goal: EntityId,
+        dist: f32,
+    ) -> Result<(), ProgsError> {
+        let ideal_yaw = self.get_float(e_id, FieldAddrFloat::IdealYaw)?;
+        let old_dir = angle_mod((ideal_yaw / 45.0) as i32 as f32 * 45.0);
+        let turnaround = angle_mod(old_dir - 180.0);
+
+        let origin = self.get_vector(e_id, FieldAddrVector::Origin)?;
+        let goal_origin = self.get_vector(goal, FieldAddrVector::Origin)?;
+        let delta_x = goal_origin.x - origin.x;
+        let delta_y = goal_origin.y - origin.y;
+
+        let mut d1 = if delta_x > 10.0 {
+            0.0
+        } else if delta_x < -10.0 {
+            180.0
+        } else {
+            NO_DIRECTION
+        };
+
+        let mut d2 = if delta_y < -10.0 {
+            270.0
+        } else if delta_y > 10.0 {
+            90.0
+        } else {
+            NO_DIRECTION
+        };
+
+        // try the direct route
+        if d1 != NO_DIRECTION && d2 != NO_DIRECTION {
+            // the original engine uses 215 rather than 225 for the southwest diagonal
+            let dir = match (d1 == 0.0, d2 == 90.0) {
+                (true, true) => 45.0,
+                (true, false) => 315.0,
+                (false, true) => 135.0,
+                (false, false) => 215.0,
+            };
+
+            if dir != turnaround && self.step_direction(ctx, e_id, dir, dist)? {
+                return Ok(());
+            }
+        }
+
+        // try the other directions
+        if ctx.server.rng_mut().next_u32() & 1 != 0
+            || (delta_y as i32).abs() > (delta_x as i32).abs()
+        {
+            std::mem::swap(&mut d1, &mut d2);
+        }
+
+        for &dir in [d1, d2].iter() {
+            if dir != NO_DIRECTION
+                && dir != turnaround
+                && self.step_direction(ctx, e_id, dir, dist)?
+            {
+                return Ok(());
+            }
+        }
+
+        // there is no direct path to the goal, so pick another direction
+        if old_dir != NO_DIRECTION && self.step_direction(ctx, e_id, old_dir, dist)? {
+            return Ok(());
+        }
+
+        // randomly determine the direction of the search
+        let mut dirs: Vec<f32> = (0..8).map(|i| i as f32 * 45.0).collect();
+        if ctx.server.rng_mut().next_u32() & 1 == 0 {
+            dirs.reverse();
+        }
+
+        for dir in dirs {
+            if dir != turnaround && self.step_direction(ctx, e_id, dir, dist)? {
+                return Ok(());
+            }
+        }
+
+        if turnaround != NO_DIRECTION && self.step_direction(ctx, e_id, turnaround, dist)? {
+            return Ok(());
+        }
+
+        // can't move
+        self.put_float(e_id, FieldAddrFloat::IdealYaw, old_dir)?;
+
+        // if a bridge was pulled out from underneath a monster, it may not have a valid standing
+        // position at all
+        if !self.check_bottom(e_id)? {
+            self.try_get_entity_mut(e_id)?
+                .add_flags(EntityFlags::PARTIAL_GROUND)?;
+        }
+
+        Ok(())
+    }
+
+    // returns true if the entity's bounding box is within dist of the goal's
+    fn close_enough(&self, e_id: EntityId, goal: EntityId, dist: f32) -> Result<bool, ProgsError> {
+        let ent = self.try_get_entity(e_id)?;
+        let goal = self.try_get_entity(goal)?;
+        let (abs_min, abs_max) = (ent.abs_min()?, ent.abs_max()?);
+        let (goal_min, goal_max) = (goal.abs_min()?, goal.abs_max()?);
+
+        Ok((0..3).all(|i| goal_min[i] <= abs_max[i] + dist && goal_max[i] >= abs_min[i] - dist))
+    }
+
+    /// Moves a monster `dist` units toward its goal entity, finding a way around obstacles.
+    ///
+    /// This is the implementation of the `movetogoal` builtin.
+    pub fn move_to_goal(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        dist: f32,
+    ) -> Result<(), ProgsError> {
+        let flags = EntityFlags::ON_GROUND | EntityFlags::FLY | EntityFlags::SWIM;
+        if !self.has_flags(e_id, flags)? {
+            return Ok(());
+        }
+
+        let ent = self.try_get_entity(e_id)?;
+        let goal = ent.get_entity_id(FieldAddrEntityId::Goal as i16)?;
+        let enemy = ent.get_entity_id(FieldAddrEntityId::Enemy as i16)?;
+
+        // if the next step hits the enemy, return immediately
+        if enemy != EntityId(0) && self.close_enough(e_id, goal, dist)? {
+            return Ok(());
+        }
+
+        // bump around
+        let ideal_yaw = self.get_float(e_id, FieldAddrFloat::IdealYaw)?;
+        if ctx.server.rng_mut().next_u32() & 3 == 1
+            || !self.step_direction(ctx, e_id, ideal_yaw, dist)?
+        {
+            self.new_chase_dir(ctx, e_id, goal, dist)?;
+        }
+
+        Ok(())
+    }
+}
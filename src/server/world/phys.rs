@@ -16,11 +16,71 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 use crate::{
-    common::{bsp::BspLeafContents, math::Hyperplane},
-    server::progs::EntityId,
+    common::{
+        bsp::BspLeafContents,
+        console::CvarRegistry,
+        engine, frustum,
+        math::{Angles, Hyperplane},
+        model::ModelKind,
+        vfs::Vfs,
+    },
+    server::{
+        progs::{
+            EntityId, ExecutionContext, FunctionId, GlobalAddrEntity, GlobalAddrFloat,
+            GlobalAddrFunction, Globals, ProgsError,
+        },
+        world::{
+            entity::{EntityFlags, EntitySolid},
+            FieldAddrEntityId, FieldAddrFloat, FieldAddrFunctionId, FieldAddrVector, World,
+        },
+        Server,
+    },
 };
 
-use cgmath::{Vector3, Zero};
+use cgmath::{Deg, InnerSpace, Matrix, Matrix3, Vector3, Zero};
+use chrono::Duration;
+
+// the most planes a single move can be clipped against
+const MAX_CLIP_PLANES: usize = 5;
+
+// the most times a single move can be redirected along a plane
+const MAX_BUMPS: usize = 4;
+
+// velocity components smaller than this are zeroed after clipping
+const STOP_EPSILON: f32 = 0.1;
+
+// surfaces with a normal steeper than this can be stood on
+const MIN_GROUND_NORMAL_Z: f32 = 0.7;
+
+// the tallest step a walking entity can climb
+const STEP_SIZE: f32 = 18.0;
+
+// horizontal moves shorter than this are considered to have made no progress
+const DIST_EPSILON: f32 = 0.03125;
+
+// how far above a stuck player to look for a free position
+const MAX_UNSTICK_HEIGHT: i32 = 18;
+
+// the offsets used to nudge a player that is stuck on a step
+const UNSTICK_DIRECTIONS: [(f32, f32); 8] = [
+    (2.0, 0.0),
+    (0.0, 2.0),
+    (-2.0, 0.0),
+    (0.0, -2.0),
+    (2.0, 2.0),
+    (-2.0, 2.0),
+    (2.0, -2.0),
+    (-2.0, -2.0),
+];
+
+// the length of the retried move after a nudge, in seconds
+const UNSTICK_MOVE_TIME: f32 = 0.1;
+
+// bouncing entities slower than this come to rest on the ground
+const BOUNCE_STOP_SPEED: f32 = 60.0;
+
+const WATER_SPLASH_SOUND: &str = "misc/h2ohit1.wav";
+const LAND_SOUND: &str = "demon/dland2.wav";
 
 #[derive(Copy, Clone, Debug, Eq, FromPrimitive, PartialEq)]
 pub enum MoveKind {
@@ -126,22 +186,62 @@ impl TraceEnd {
     }
 }
 
+/// The kinds of space a hull check passed through.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TraceFlags {
+    /// The move never left solid space.
+    pub all_solid: bool,
+
+    /// The move started in solid space.
+    pub start_solid: bool,
+
+    /// The move passed through open space.
+    pub in_open: bool,
+
+    /// The move passed through a liquid.
+    pub in_water: bool,
+}
+
 #[derive(Debug)]
 pub struct Trace {
     start: TraceStart,
     end: TraceEnd,
     contents: BspLeafContents,
-    start_solid: bool,
+    flags: TraceFlags,
 }
 
 impl Trace {
     pub fn new(start: TraceStart, end: TraceEnd, contents: BspLeafContents) -> Trace {
-        let start_solid = contents == BspLeafContents::Solid;
+        let solid = contents == BspLeafContents::Solid;
+        let flags = TraceFlags {
+            all_solid: solid,
+            start_solid: solid,
+            in_open: contents == BspLeafContents::Empty,
+            in_water: !solid && contents != BspLeafContents::Empty,
+        };
+
+        Trace {
+            start,
+            end,
+            contents,
+            flags,
+        }
+    }
+
+    /// Constructs a trace from the result of a hull check.
+    ///
+    /// `contents` is the contents of the last leaf the move entered.
+    pub fn from_hull_check(
+        start: TraceStart,
+        end: TraceEnd,
+        contents: BspLeafContents,
+        flags: TraceFlags,
+    ) -> Trace {
         Trace {
             start,
             end,
             contents,
-            start_solid,
+            flags,
         }
     }
 
@@ -179,7 +279,7 @@ impl Trace {
                 start: self.start,
                 end: other.end,
                 contents: self.contents,
-                start_solid: self.start_solid,
+                flags: self.flags,
             };
         }
 
@@ -188,7 +288,10 @@ impl Trace {
                 start: self.start,
                 end: other.end,
                 contents: other.contents,
-                start_solid: true,
+                flags: TraceFlags {
+                    start_solid: true,
+                    ..other.flags
+                },
             };
         }
 
@@ -206,10 +309,42 @@ impl Trace {
                 kind: self.end.kind,
             },
             contents: self.contents,
-            start_solid: self.start_solid,
+            flags: self.flags,
+        }
+    }
+
+    /// Rotates this trace about the origin.
+    ///
+    /// A trace through a rotated brush model is made in the model's own frame, and rotated back
+    /// into the world's frame with this.
+    pub fn rotate(self, rotation: Matrix3<f32>) -> Trace {
+        let kind = match self.end.kind {
+            TraceEndKind::Terminal => TraceEndKind::Terminal,
+            TraceEndKind::Boundary(b) => TraceEndKind::Boundary(TraceEndBoundary {
+                ratio: b.ratio,
+                plane: Hyperplane::new(rotation * b.plane.normal_vector(), b.plane.dist()),
+            }),
+        };
+
+        Trace {
+            start: TraceStart {
+                point: rotation * self.start.point,
+                ratio: self.start.ratio,
+            },
+            end: TraceEnd {
+                point: rotation * self.end.point,
+                kind,
+            },
+            contents: self.contents,
+            flags: self.flags,
         }
     }
 
+    /// Marks this trace as having started in solid space.
+    pub fn set_start_solid(&mut self) {
+        self.flags.start_solid = true;
+    }
+
     pub fn start_point(&self) -> Vector3<f32> {
         self.start.point
     }
@@ -218,20 +353,36 @@ impl Trace {
         self.end.point
     }
 
+    /// The fraction of the move completed before the trace ended.
+    pub fn ratio(&self) -> f32 {
+        match self.end.kind {
+            TraceEndKind::Terminal => 1.0,
+            TraceEndKind::Boundary(ref b) => b.ratio,
+        }
+    }
+
+    /// The plane on which the trace ended, if it hit one.
+    pub fn plane(&self) -> Option<&Hyperplane> {
+        match self.end.kind {
+            TraceEndKind::Terminal => None,
+            TraceEndKind::Boundary(ref b) => Some(&b.plane),
+        }
+    }
+
     pub fn all_solid(&self) -> bool {
-        self.contents == BspLeafContents::Solid
+        self.flags.all_solid
     }
 
     pub fn start_solid(&self) -> bool {
-        self.start_solid
+        self.flags.start_solid
     }
 
     pub fn in_open(&self) -> bool {
-        self.contents == BspLeafContents::Empty
+        self.flags.in_open
     }
 
     pub fn in_water(&self) -> bool {
-        self.contents != BspLeafContents::Empty && self.contents != BspLeafContents::Solid
+        self.flags.in_water
     }
 
     pub fn is_terminal(&self) -> bool {
@@ -243,6 +394,21 @@ impl Trace {
     }
 }
 
+/// Returns the rotation that takes a point in the world's frame into the frame of a brush model
+/// turned by `angles`, as in the original engine's rotating pushers.
+///
+/// The inverse rotation is the transpose of this one.
+pub fn brush_rotation(angles: Vector3<f32>) -> Matrix3<f32> {
+    let (forward, right, up) = frustum::view_vectors(Angles {
+        pitch: Deg(angles.x),
+        roll: Deg(angles.z),
+        yaw: Deg(angles.y),
+    });
+
+    // the rows are the model's forward, left and up axes
+    Matrix3::from_cols(forward, -right, up).transpose()
+}
+
 pub fn bounds_for_move(
     start: Vector3<f32>,
     min: Vector3<f32>,
@@ -264,3 +430,1358 @@ pub fn bounds_for_move(
 
     (box_min, box_max)
 }
+
+/// The parts of the server that entity physics runs QuakeC functions with.
+pub struct PhysicsContext<'a> {
+    pub execution_context: &'a mut ExecutionContext,
+    pub globals: &'a mut Globals,
+    pub cvars: &'a mut CvarRegistry,
+    pub server: &'a mut Server,
+    pub vfs: &'a Vfs,
+}
+
+impl<'a> PhysicsContext<'a> {
+    /// Runs the QuakeC function `f` with `self` and `other` set to the given entities, then
+    /// restores their previous values.
+    pub fn call(
+        &mut self,
+        world: &mut World,
+        f: FunctionId,
+        self_id: EntityId,
+        other_id: EntityId,
+    ) -> Result<(), ProgsError> {
+        let old_self = self.globals.get_entity_id(GlobalAddrEntity::Self_ as i16)?;
+        let old_other = self.globals.get_entity_id(GlobalAddrEntity::Other as i16)?;
+
+        self.globals
+            .put_entity_id(self_id, GlobalAddrEntity::Self_ as i16)?;
+        self.globals
+            .put_entity_id(other_id, GlobalAddrEntity::Other as i16)?;
+        self.execution_context.execute_program(
+            self.globals,
+            world,
+            self.cvars,
+            self.server,
+            self.vfs,
+            f,
+        )?;
+
+        self.globals
+            .put_entity_id(old_self, GlobalAddrEntity::Self_ as i16)?;
+        self.globals
+            .put_entity_id(old_other, GlobalAddrEntity::Other as i16)?;
+        Ok(())
+    }
+
+    /// Returns the simulation time of the current level in seconds.
+    pub fn time(&self) -> f32 {
+        engine::duration_to_f32(self.server.time())
+    }
+}
+
+// the surfaces a move was blocked by
+#[derive(Default)]
+struct Blocked {
+    floor: bool,
+    step: bool,
+
+    // the normal of the last vertical wall that blocked the move
+    wall_normal: Option<Vector3<f32>>,
+}
+
+impl Blocked {
+    // the entity is stuck and couldn't move at all
+    fn stuck() -> Blocked {
+        Blocked {
+            floor: true,
+            step: true,
+            wall_normal: None,
+        }
+    }
+
+    fn any(&self) -> bool {
+        self.floor || self.step
+    }
+}
+
+/// Returns `true` if a trace from `World::move_entity` ran into something.
+fn trace_hit(trace: &Trace) -> bool {
+    trace.ratio() < 1.0 || trace.start_solid()
+}
+
+/// Returns the normal of the plane a trace ended on, or the zero vector if it didn't hit one.
+fn trace_normal(trace: &Trace) -> Vector3<f32> {
+    trace
+        .plane()
+        .map(|p| p.normal_vector())
+        .unwrap_or_else(Vector3::zero)
+}
+
+// removes the component of velocity running into a plane
+fn clip_velocity(velocity: Vector3<f32>, normal: Vector3<f32>, overbounce: f32) -> Vector3<f32> {
+    let backoff = velocity.dot(normal) * overbounce;
+    let mut out = velocity - normal * backoff;
+    for i in 0..3 {
+        if out[i].abs() < STOP_EPSILON {
+            out[i] = 0.0;
+        }
+    }
+
+    out
+}
+
+// returns true if an entity in a leaf with the given contents is in a liquid
+fn contents_liquid(contents: BspLeafContents) -> bool {
+    contents as i32 >= BspLeafContents::Water as i32
+}
+
+impl World {
+    pub(crate) fn get_vector(
+        &self,
+        e_id: EntityId,
+        field: FieldAddrVector,
+    ) -> Result<Vector3<f32>, ProgsError> {
+        Ok(self.try_get_entity(e_id)?.get_vector(field as i16)?.into())
+    }
+
+    pub(crate) fn put_vector(
+        &mut self,
+        e_id: EntityId,
+        field: FieldAddrVector,
+        val: Vector3<f32>,
+    ) -> Result<(), ProgsError> {
+        self.try_get_entity_mut(e_id)?
+            .put_vector(val.into(), field as i16)?;
+        Ok(())
+    }
+
+    pub(crate) fn get_float(
+        &self,
+        e_id: EntityId,
+        field: FieldAddrFloat,
+    ) -> Result<f32, ProgsError> {
+        Ok(self.try_get_entity(e_id)?.get_float(field as i16)?)
+    }
+
+    pub(crate) fn put_float(
+        &mut self,
+        e_id: EntityId,
+        field: FieldAddrFloat,
+        val: f32,
+    ) -> Result<(), ProgsError> {
+        self.try_get_entity_mut(e_id)?
+            .put_float(val, field as i16)?;
+        Ok(())
+    }
+
+    pub(crate) fn has_flags(&self, e_id: EntityId, flags: EntityFlags) -> Result<bool, ProgsError> {
+        Ok(self.try_get_entity(e_id)?.flags()?.intersects(flags))
+    }
+
+    /// Returns the contents of the world at `point`.
+    ///
+    /// Brush entities are not considered, and currents are reported as water.
+    pub fn point_contents(&self, point: Vector3<f32>) -> Result<BspLeafContents, ProgsError> {
+        let contents = match self.models[1].kind() {
+            ModelKind::Brush(ref bmodel) => bmodel
+                .hull(0)
+                .and_then(|hull| hull.contents_at_point(point))
+                .map_err(|e| ProgsError::with_msg(format!("{}", e)))?,
+            _ => BspLeafContents::Empty,
+        };
+
+        Ok(match contents {
+            BspLeafContents::Current0
+            | BspLeafContents::Current90
+            | BspLeafContents::Current180
+            | BspLeafContents::Current270
+            | BspLeafContents::CurrentUp
+            | BspLeafContents::CurrentDown => BspLeafContents::Water,
+            c => c,
+        })
+    }
+
+    /// Traces the move of an entity's bounding box from its origin to `end`.
+    pub fn move_entity_to(
+        &self,
+        e_id: EntityId,
+        end: Vector3<f32>,
+        kind: CollideKind,
+    ) -> Result<(Trace, EntityId), ProgsError> {
+        let ent = self.try_get_entity(e_id)?;
+        self.move_entity(e_id, ent.origin()?, ent.min()?, ent.max()?, end, kind)
+    }
+
+    /// Runs one frame of physics for every entity in the world, then advances the level time by
+    /// `frame_time`.
+    pub fn physics(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        frame_time: Duration,
+    ) -> Result<(), ProgsError> {
+        // the simulation is frozen while the server is paused
+        if ctx.server.paused() {
+            return Ok(());
+        }
+
+        let frame_time_f = engine::duration_to_f32(frame_time);
+
+        ctx.globals
+            .put_float(ctx.time(), GlobalAddrFloat::Time as i16)?;
+        let start_frame = ctx
+            .globals
+            .get_function_id(GlobalAddrFunction::StartFrame as i16)?;
+        ctx.call(self, start_frame, EntityId(0), EntityId(0))?;
+
+        for i in 0..self.slots.len() {
+            let e_id = EntityId(i);
+            if !self.entity_exists(e_id) {
+                continue;
+            }
+
+            // force_retouch relinks even stationary entities, e.g. after a teleporter is enabled
+            if ctx
+                .globals
+                .get_float(GlobalAddrFloat::ForceRetouch as i16)?
+                != 0.0
+            {
+                self.link_entity_and_touch(ctx, e_id)?;
+            }
+
+            if i > 0 && i <= self.max_clients {
+                self.physics_client(ctx, e_id, frame_time_f)?;
+                continue;
+            }
+
+            match self.try_get_entity(e_id)?.move_kind()? {
+                MoveKind::Push => self.physics_pusher(ctx, e_id, frame_time_f)?,
+                MoveKind::None => {
+                    self.run_think(ctx, e_id, frame_time_f)?;
+                }
+                MoveKind::NoClip => self.physics_noclip(ctx, e_id, frame_time_f)?,
+                MoveKind::Step => self.physics_step(ctx, e_id, frame_time_f)?,
+                MoveKind::Toss | MoveKind::Bounce | MoveKind::Fly | MoveKind::FlyMissile => {
+                    self.physics_toss(ctx, e_id, frame_time_f)?
+                }
+                k => {
+                    return Err(ProgsError::with_msg(format!(
+                        "Bad move type for entity {}: {:?}",
+                        i, k
+                    )))
+                }
+            }
+        }
+
+        let force_retouch = ctx
+            .globals
+            .get_float(GlobalAddrFloat::ForceRetouch as i16)?;
+        if force_retouch > 0.0 {
+            ctx.globals
+                .put_float(force_retouch - 1.0, GlobalAddrFloat::ForceRetouch as i16)?;
+        }
+
+        self.dispatch_health_events(ctx.server)?;
+
+        ctx.server.advance_time(frame_time);
+        Ok(())
+    }
+
+    // clamps an entity's velocity to sv_maxvelocity and discards invalid values
+    fn check_velocity(&mut self, ctx: &PhysicsContext, e_id: EntityId) -> Result<(), ProgsError> {
+        let max_velocity = ctx.cvars.get_value("sv_maxvelocity").unwrap_or(2000.0);
+
+        let mut velocity = self.get_vector(e_id, FieldAddrVector::Velocity)?;
+        let mut origin = self.get_vector(e_id, FieldAddrVector::Origin)?;
+        for i in 0..3 {
+            if velocity[i].is_nan() {
+                warn!("Entity {} has a NaN velocity", e_id.0);
+                velocity[i] = 0.0;
+            }
+
+            if origin[i].is_nan() {
+                warn!("Entity {} has a NaN origin", e_id.0);
+                origin[i] = 0.0;
+            }
+
+            velocity[i] = velocity[i].max(-max_velocity).min(max_velocity);
+        }
+
+        self.put_vector(e_id, FieldAddrVector::Velocity, velocity)?;
+        self.put_vector(e_id, FieldAddrVector::Origin, origin)?;
+        Ok(())
+    }
+
+    // runs the entity's think function if it is due this frame. returns false if the entity was
+    // removed.
+    fn run_think(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        frame_time: f32,
+    ) -> Result<bool, ProgsError> {
+        let time = ctx.time();
+        let mut think_time = self.get_float(e_id, FieldAddrFloat::NextThink)?;
+        if think_time <= 0.0 || think_time > time + frame_time {
+            return Ok(true);
+        }
+
+        // don't let things stay in the past
+        if think_time < time {
+            think_time = time;
+        }
+
+        self.put_float(e_id, FieldAddrFloat::NextThink, 0.0)?;
+        ctx.globals
+            .put_float(think_time, GlobalAddrFloat::Time as i16)?;
+        let think = self
+            .try_get_entity(e_id)?
+            .get_function_id(FieldAddrFunctionId::Think as i16)?;
+        ctx.call(self, think, e_id, EntityId(0))?;
+
+        Ok(self.entity_exists(e_id))
+    }
+
+    // runs the touch functions of two entities that collided
+    fn impact(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e1: EntityId,
+        e2: EntityId,
+    ) -> Result<(), ProgsError> {
+        ctx.globals
+            .put_float(ctx.time(), GlobalAddrFloat::Time as i16)?;
+
+        for &(toucher, other) in [(e1, e2), (e2, e1)].iter() {
+            if !self.entity_exists(toucher) || !self.entity_exists(other) {
+                continue;
+            }
+
+            let ent = self.try_get_entity(toucher)?;
+            let touch = ent.get_function_id(FieldAddrFunctionId::Touch as i16)?;
+            if touch.0 != 0 && ent.solid()? != EntitySolid::Not {
+                ctx.call(self, touch, toucher, other)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // moves an entity along its velocity for `time` seconds, sliding along anything it hits
+    fn fly_move(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        time: f32,
+    ) -> Result<Blocked, ProgsError> {
+        let mut blocked = Blocked::default();
+        let mut original_velocity = self.get_vector(e_id, FieldAddrVector::Velocity)?;
+        let primal_velocity = original_velocity;
+        let mut planes: Vec<Vector3<f32>> = Vec::with_capacity(MAX_CLIP_PLANES);
+        let mut time_left = time;
+
+        for _ in 0..MAX_BUMPS {
+            let velocity = self.get_vector(e_id, FieldAddrVector::Velocity)?;
+            if velocity == Vector3::zero() {
+                break;
+            }
+
+            let origin = self.get_vector(e_id, FieldAddrVector::Origin)?;
+            let end = origin + velocity * time_left;
+            let (trace, hit_id) = self.move_entity_to(e_id, end, CollideKind::Normal)?;
+
+            // entity is trapped in another solid
+            if trace.all_solid() {
+                self.put_vector(e_id, FieldAddrVector::Velocity, Vector3::zero())?;
+                return Ok(Blocked::stuck());
+            }
+
+            // actually covered some distance
+            if trace.ratio() > 0.0 {
+                self.put_vector(e_id, FieldAddrVector::Origin, trace.end_point())?;
+                original_velocity = velocity;
+                planes.clear();
+            }
+
+            // moved the entire distance
+            if trace.ratio() == 1.0 {
+                break;
+            }
+
+            let normal = trace_normal(&trace);
+            if normal.z > MIN_GROUND_NORMAL_Z {
+                blocked.floor = true;
+                if self.try_get_entity(hit_id)?.solid()? == EntitySolid::Bsp {
+                    let ent = self.try_get_entity_mut(e_id)?;
+                    ent.add_flags(EntityFlags::ON_GROUND)?;
+                    ent.put_entity_id(hit_id, FieldAddrEntityId::Ground as i16)?;
+                }
+            }
+
+            if normal.z == 0.0 {
+                blocked.step = true;
+                blocked.wall_normal = Some(normal);
+            }
+
+            self.impact(ctx, e_id, hit_id)?;
+
+            // removed by the impact function
+            if !self.entity_exists(e_id) {
+                break;
+            }
+
+            time_left -= time_left * trace.ratio();
+
+            // this shouldn't really happen
+            if planes.len() >= MAX_CLIP_PLANES {
+                self.put_vector(e_id, FieldAddrVector::Velocity, Vector3::zero())?;
+                return Ok(Blocked::stuck());
+            }
+
+            planes.push(normal);
+
+            // find a velocity that runs parallel to all of the clip planes
+            let parallel = planes.iter().enumerate().find_map(|(i, plane)| {
+                let new_velocity = clip_velocity(original_velocity, *plane, 1.0);
+                let ok = planes
+                    .iter()
+                    .enumerate()
+                    .all(|(j, other)| j == i || new_velocity.dot(*other) >= 0.0);
+                if ok {
+                    Some(new_velocity)
+                } else {
+                    None
+                }
+            });
+
+            let new_velocity = match parallel {
+                // go along this plane
+                Some(v) => v,
+
+                // go along the crease
+                None if planes.len() == 2 => {
+                    let dir = planes[0].cross(planes[1]);
+                    let velocity = self.get_vector(e_id, FieldAddrVector::Velocity)?;
+                    dir * dir.dot(velocity)
+                }
+
+                None => {
+                    self.put_vector(e_id, FieldAddrVector::Velocity, Vector3::zero())?;
+                    return Ok(Blocked::stuck());
+                }
+            };
+            self.put_vector(e_id, FieldAddrVector::Velocity, new_velocity)?;
+
+            // stop dead rather than bounce back and forth in sloping corners
+            if new_velocity.dot(primal_velocity) <= 0.0 {
+                self.put_vector(e_id, FieldAddrVector::Velocity, Vector3::zero())?;
+                return Ok(blocked);
+            }
+        }
+
+        Ok(blocked)
+    }
+
+    fn add_gravity(
+        &mut self,
+        ctx: &PhysicsContext,
+        e_id: EntityId,
+        frame_time: f32,
+    ) -> Result<(), ProgsError> {
+        // the gravity field is optional, and a value of zero means normal gravity
+        let ent_gravity = match self.find_def("gravity") {
+            Ok(def) => match self.try_get_entity(e_id)?.get_float(def.offset as i16)? {
+                g if g != 0.0 => g,
+                _ => 1.0,
+            },
+            Err(_) => 1.0,
+        };
+
+        let gravity = ctx.cvars.get_value("sv_gravity").unwrap_or(800.0);
+        let mut velocity = self.get_vector(e_id, FieldAddrVector::Velocity)?;
+        velocity.z -= ent_gravity * gravity * frame_time;
+        self.put_vector(e_id, FieldAddrVector::Velocity, velocity)
+    }
+
+    // moves an entity by `push` without sliding, touching whatever it runs into
+    fn push_entity(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        push: Vector3<f32>,
+    ) -> Result<(Trace, EntityId), ProgsError> {
+        let ent = self.try_get_entity(e_id)?;
+        let end = ent.origin()? + push;
+
+        let kind = if ent.move_kind()? == MoveKind::FlyMissile {
+            CollideKind::Missile
+        } else {
+            match ent.solid()? {
+                // only clip against brush models
+                EntitySolid::Trigger | EntitySolid::Not => CollideKind::NoMonsters,
+                _ => CollideKind::Normal,
+            }
+        };
+
+        let (trace, hit_id) = self.move_entity_to(e_id, end, kind)?;
+        self.put_vector(e_id, FieldAddrVector::Origin, trace.end_point())?;
+        self.link_entity_and_touch(ctx, e_id)?;
+
+        if trace_hit(&trace) {
+            self.impact(ctx, e_id, hit_id)?;
+        }
+
+        Ok((trace, hit_id))
+    }
+
+    // returns true if the entity's bounding box is stuck in something solid
+    fn test_entity_position(&self, e_id: EntityId) -> Result<bool, ProgsError> {
+        let origin = self.try_get_entity(e_id)?.origin()?;
+        let (trace, _) = self.move_entity_to(e_id, origin, CollideKind::Normal)?;
+        Ok(trace.start_solid())
+    }
+
+    // moves a brush entity along its velocity, pushing other entities out of the way
+    fn push_move(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        pusher: EntityId,
+        move_time: f32,
+    ) -> Result<(), ProgsError> {
+        let velocity = self.get_vector(pusher, FieldAddrVector::Velocity)?;
+        let local_time = self.get_float(pusher, FieldAddrFloat::LocalTime)?;
+        if velocity == Vector3::zero() {
+            self.put_float(pusher, FieldAddrFloat::LocalTime, local_time + move_time)?;
+            return Ok(());
+        }
+
+        let push = velocity * move_time;
+        let min = self.try_get_entity(pusher)?.abs_min()? + push;
+        let max = self.try_get_entity(pusher)?.abs_max()? + push;
+        let push_origin = self.get_vector(pusher, FieldAddrVector::Origin)?;
+
+        // move the pusher to its final position
+        self.put_vector(pusher, FieldAddrVector::Origin, push_origin + push)?;
+        self.put_float(pusher, FieldAddrFloat::LocalTime, local_time + move_time)?;
+        self.link_entity(pusher)?;
+
+        // see if any solid entities are inside the final position
+        let mut moved: Vec<(EntityId, Vector3<f32>)> = Vec::new();
+        for i in 1..self.slots.len() {
+            let check = EntityId(i);
+            if !self.entity_exists(check) {
+                continue;
+            }
+
+            let check_ent = self.try_get_entity(check)?;
+            match check_ent.move_kind()? {
+                MoveKind::Push | MoveKind::None | MoveKind::NoClip => continue,
+                _ => (),
+            }
+
+            // entities standing on the pusher are always moved
+            let on_pusher = check_ent.flags()?.contains(EntityFlags::ON_GROUND)
+                && check_ent.get_entity_id(FieldAddrEntityId::Ground as i16)? == pusher;
+            if !on_pusher {
+                let (abs_min, abs_max) = (check_ent.abs_min()?, check_ent.abs_max()?);
+                if (0..3).any(|j| abs_min[j] >= max[j] || abs_max[j] <= min[j]) {
+                    continue;
+                }
+
+                // see if the entity's bounding box is inside the pusher's final position
+                if !self.test_entity_position(check)? {
+                    continue;
+                }
+            }
+
+            // remove the onground flag for non-players
+            if check_ent.move_kind()? != MoveKind::Walk {
+                self.try_get_entity_mut(check)?
+                    .remove_flags(EntityFlags::ON_GROUND)?;
+            }
+
+            let check_origin = self.get_vector(check, FieldAddrVector::Origin)?;
+            moved.push((check, check_origin));
+
+            // try moving the contacted entity
+            self.put_float(
+                pusher,
+                FieldAddrFloat::Solid,
+                EntitySolid::Not as u32 as f32,
+            )?;
+            self.push_entity(ctx, check, push)?;
+            self.put_float(
+                pusher,
+                FieldAddrFloat::Solid,
+                EntitySolid::Bsp as u32 as f32,
+            )?;
+
+            if !self.entity_exists(check) || !self.test_entity_position(check)? {
+                continue;
+            }
+
+            // if it is still inside the pusher, block
+            let check_ent = self.try_get_entity(check)?;
+            if check_ent.min()?.x == check_ent.max()?.x {
+                continue;
+            }
+
+            match check_ent.solid()? {
+                // corpses are crushed flat instead
+                EntitySolid::Not | EntitySolid::Trigger => {
+                    let mut min = check_ent.min()?;
+                    min.x = 0.0;
+                    min.y = 0.0;
+                    self.put_vector(check, FieldAddrVector::Mins, min)?;
+                    self.put_vector(check, FieldAddrVector::Maxs, min)?;
+                    continue;
+                }
+                _ => (),
+            }
+
+            self.put_vector(check, FieldAddrVector::Origin, check_origin)?;
+            self.link_entity_and_touch(ctx, check)?;
+
+            self.put_vector(pusher, FieldAddrVector::Origin, push_origin)?;
+            self.link_entity(pusher)?;
+            self.put_float(pusher, FieldAddrFloat::LocalTime, local_time)?;
+
+            // if the pusher has a blocked function, call it, otherwise just stay in place until
+            // the obstacle is gone
+            let blocked = self
+                .try_get_entity(pusher)?
+                .get_function_id(FieldAddrFunctionId::Blocked as i16)?;
+            if blocked.0 != 0 {
+                ctx.call(self, blocked, pusher, check)?;
+            }
+
+            // move back any entities we already moved
+            for (moved_id, moved_from) in moved {
+                if self.entity_exists(moved_id) {
+                    self.put_vector(moved_id, FieldAddrVector::Origin, moved_from)?;
+                    self.link_entity(moved_id)?;
+                }
+            }
+
+            return Ok(());
+        }
+
+        Ok(())
+    }
+
+    // turns a brush entity by its angular velocity, carrying other entities around with it. ported
+    // from the original engine's SV_PushRotate.
+    fn push_rotate(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        pusher: EntityId,
+        move_time: f32,
+    ) -> Result<(), ProgsError> {
+        let turn = self.get_vector(pusher, FieldAddrVector::AngularVelocity)? * move_time;
+        let local_time = self.get_float(pusher, FieldAddrFloat::LocalTime)?;
+        let push_angles = self.get_vector(pusher, FieldAddrVector::Angles)?;
+        let push_origin = self.get_vector(pusher, FieldAddrVector::Origin)?;
+
+        // the rotation of a point around the pusher, from its frame before the turn to after
+        let rotation = brush_rotation(-turn);
+
+        // turn the pusher to its final angles
+        self.put_vector(pusher, FieldAddrVector::Angles, push_angles + turn)?;
+        self.put_float(pusher, FieldAddrFloat::LocalTime, local_time + move_time)?;
+        self.link_entity(pusher)?;
+
+        let min = self.try_get_entity(pusher)?.abs_min()?;
+        let max = self.try_get_entity(pusher)?.abs_max()?;
+
+        // see if any solid entities are inside the final position
+        let mut moved: Vec<(EntityId, Vector3<f32>)> = Vec::new();
+        for i in 1..self.slots.len() {
+            let check = EntityId(i);
+            if !self.entity_exists(check) {
+                continue;
+            }
+
+            let check_ent = self.try_get_entity(check)?;
+            match check_ent.move_kind()? {
+                MoveKind::Push | MoveKind::None | MoveKind::NoClip => continue,
+                _ => (),
+            }
+
+            // entities standing on the pusher are always moved
+            let on_pusher = check_ent.flags()?.contains(EntityFlags::ON_GROUND)
+                && check_ent.get_entity_id(FieldAddrEntityId::Ground as i16)? == pusher;
+            if !on_pusher {
+                let (abs_min, abs_max) = (check_ent.abs_min()?, check_ent.abs_max()?);
+                if (0..3).any(|j| abs_min[j] >= max[j] || abs_max[j] <= min[j]) {
+                    continue;
+                }
+
+                // see if the entity's bounding box is inside the pusher's final position
+                if !self.test_entity_position(check)? {
+                    continue;
+                }
+            }
+
+            // remove the onground flag for non-players
+            if check_ent.move_kind()? != MoveKind::Walk {
+                self.try_get_entity_mut(check)?
+                    .remove_flags(EntityFlags::ON_GROUND)?;
+            }
+
+            let check_origin = self.get_vector(check, FieldAddrVector::Origin)?;
+            moved.push((check, check_origin));
+
+            // carry the entity around the pusher's origin
+            let offset = check_origin - push_origin;
+            let push = rotation * offset - offset;
+
+            self.put_float(
+                pusher,
+                FieldAddrFloat::Solid,
+                EntitySolid::Not as u32 as f32,
+            )?;
+            self.push_entity(ctx, check, push)?;
+            self.put_float(
+                pusher,
+                FieldAddrFloat::Solid,
+                EntitySolid::Bsp as u32 as f32,
+            )?;
+
+            if !self.entity_exists(check) {
+                continue;
+            }
+
+            if !self.test_entity_position(check)? {
+                // the entity turns with the pusher
+                let angles = self.get_vector(check, FieldAddrVector::Angles)?;
+                self.put_vector(check, FieldAddrVector::Angles, angles + turn)?;
+                continue;
+            }
+
+            // if it is still inside the pusher, block
+            let check_ent = self.try_get_entity(check)?;
+            if check_ent.min()?.x == check_ent.max()?.x {
+                continue;
+            }
+
+            match check_ent.solid()? {
+                // corpses are crushed flat instead
+                EntitySolid::Not | EntitySolid::Trigger => {
+                    let mut min = check_ent.min()?;
+                    min.x = 0.0;
+                    min.y = 0.0;
+                    self.put_vector(check, FieldAddrVector::Mins, min)?;
+                    self.put_vector(check, FieldAddrVector::Maxs, min)?;
+                    continue;
+                }
+                _ => (),
+            }
+
+            self.put_vector(check, FieldAddrVector::Origin, check_origin)?;
+            self.link_entity_and_touch(ctx, check)?;
+
+            self.put_vector(pusher, FieldAddrVector::Angles, push_angles)?;
+            self.link_entity(pusher)?;
+            self.put_float(pusher, FieldAddrFloat::LocalTime, local_time)?;
+
+            // if the pusher has a blocked function, call it, otherwise just stay in place until
+            // the obstacle is gone
+            let blocked = self
+                .try_get_entity(pusher)?
+                .get_function_id(FieldAddrFunctionId::Blocked as i16)?;
+            if blocked.0 != 0 {
+                ctx.call(self, blocked, pusher, check)?;
+            }
+
+            // move back any entities we already moved, and turn back the ones that turned
+            for (moved_id, moved_from) in moved {
+                if !self.entity_exists(moved_id) {
+                    continue;
+                }
+
+                self.put_vector(moved_id, FieldAddrVector::Origin, moved_from)?;
+                if moved_id != check {
+                    let angles = self.get_vector(moved_id, FieldAddrVector::Angles)?;
+                    self.put_vector(moved_id, FieldAddrVector::Angles, angles - turn)?;
+                }
+                self.link_entity(moved_id)?;
+            }
+
+            return Ok(());
+        }
+
+        Ok(())
+    }
+
+    fn physics_pusher(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        frame_time: f32,
+    ) -> Result<(), ProgsError> {
+        let old_local_time = self.get_float(e_id, FieldAddrFloat::LocalTime)?;
+        let think_time = self.get_float(e_id, FieldAddrFloat::NextThink)?;
+
+        let move_time = if think_time < old_local_time + frame_time {
+            (think_time - old_local_time).max(0.0)
+        } else {
+            frame_time
+        };
+
+        // advances the local time if not blocked. as in the original engine, a pusher that turns
+        // doesn't also move.
+        if move_time != 0.0 {
+            if self.get_vector(e_id, FieldAddrVector::AngularVelocity)? != Vector3::zero() {
+                self.push_rotate(ctx, e_id, move_time)?;
+            } else {
+                self.push_move(ctx, e_id, move_time)?;
+            }
+        }
+
+        if !self.entity_exists(e_id) {
+            return Ok(());
+        }
+
+        let local_time = self.get_float(e_id, FieldAddrFloat::LocalTime)?;
+        if think_time > old_local_time && think_time <= local_time {
+            self.put_float(e_id, FieldAddrFloat::NextThink, 0.0)?;
+            ctx.globals
+                .put_float(ctx.time(), GlobalAddrFloat::Time as i16)?;
+            let think = self
+                .try_get_entity(e_id)?
+                .get_function_id(FieldAddrFunctionId::Think as i16)?;
+            ctx.call(self, think, e_id, EntityId(0))?;
+        }
+
+        Ok(())
+    }
+
+    fn physics_noclip(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        frame_time: f32,
+    ) -> Result<(), ProgsError> {
+        if !self.run_think(ctx, e_id, frame_time)? {
+            return Ok(());
+        }
+
+        let angles = self.get_vector(e_id, FieldAddrVector::Angles)?
+            + self.get_vector(e_id, FieldAddrVector::AngularVelocity)? * frame_time;
+        let origin = self.get_vector(e_id, FieldAddrVector::Origin)?
+            + self.get_vector(e_id, FieldAddrVector::Velocity)? * frame_time;
+        self.put_vector(e_id, FieldAddrVector::Angles, angles)?;
+        self.put_vector(e_id, FieldAddrVector::Origin, origin)?;
+        self.link_entity(e_id)
+    }
+
+    // sets the water level of an entity from the contents at its feet, waist and eyes. returns
+    // true if the entity is at least waist deep.
+    fn check_water(&mut self, e_id: EntityId) -> Result<bool, ProgsError> {
+        let origin = self.get_vector(e_id, FieldAddrVector::Origin)?;
+        let min = self.get_vector(e_id, FieldAddrVector::Mins)?;
+        let max = self.get_vector(e_id, FieldAddrVector::Maxs)?;
+        let view_offset = self.get_vector(e_id, FieldAddrVector::ViewOffset)?;
+
+        let heights = [min.z + 1.0, (min.z + max.z) * 0.5, view_offset.z];
+        let mut level = 0;
+        let mut water_type = BspLeafContents::Empty;
+        for (i, height) in heights.iter().enumerate() {
+            let point = Vector3::new(origin.x, origin.y, origin.z + height);
+            let contents = self.point_contents(point)?;
+            if !contents_liquid(contents) {
+                break;
+            }
+
+            if i == 0 {
+                water_type = contents;
+            }
+            level = i + 1;
+        }
+
+        self.put_float(e_id, FieldAddrFloat::WaterLevel, level as f32)?;
+        self.put_float(e_id, FieldAddrFloat::Contents, -(water_type as i32) as f32)?;
+        Ok(level > 1)
+    }
+
+    // plays the sound of an entity entering or leaving a liquid
+    fn check_water_transition(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+    ) -> Result<(), ProgsError> {
+        let origin = self.get_vector(e_id, FieldAddrVector::Origin)?;
+        let contents = self.point_contents(origin)?;
+        let water_type = self.get_float(e_id, FieldAddrFloat::Contents)?;
+        let empty = -(BspLeafContents::Empty as i32) as f32;
+
+        // just spawned here
+        if water_type == 0.0 {
+            self.put_float(e_id, FieldAddrFloat::Contents, -(contents as i32) as f32)?;
+            self.put_float(e_id, FieldAddrFloat::WaterLevel, 1.0)?;
+            return Ok(());
+        }
+
+        if contents_liquid(contents) {
+            if water_type == empty {
+                self.start_sound(ctx, e_id, WATER_SPLASH_SOUND)?;
+            }
+
+            self.put_float(e_id, FieldAddrFloat::Contents, -(contents as i32) as f32)?;
+            self.put_float(e_id, FieldAddrFloat::WaterLevel, 1.0)?;
+        } else {
+            if water_type != empty {
+                self.start_sound(ctx, e_id, WATER_SPLASH_SOUND)?;
+            }
+
+            // the original engine stores the contents in the water level here
+            self.put_float(e_id, FieldAddrFloat::Contents, empty)?;
+            self.put_float(e_id, FieldAddrFloat::WaterLevel, -(contents as i32) as f32)?;
+        }
+
+        Ok(())
+    }
+
+    // plays a sound from the center of an entity at full volume
+    fn start_sound(
+        &self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        name: &str,
+    ) -> Result<(), ProgsError> {
+        let sound_id = match ctx.server.sound_precache_index(name) {
+            Some(i) => i,
+            None => {
+                warn!("Sound {} not precached", name);
+                return Ok(());
+            }
+        };
+
+        let ent = self.try_get_entity(e_id)?;
+        let origin = ent.origin()? + (ent.min()? + ent.max()?) * 0.5;
+        ctx.server
+            .start_sound(origin, e_id.0 as u16, 0, sound_id as u8, 1.0, 1.0);
+        Ok(())
+    }
+
+    fn physics_toss(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        frame_time: f32,
+    ) -> Result<(), ProgsError> {
+        if !self.run_think(ctx, e_id, frame_time)? {
+            return Ok(());
+        }
+
+        // if on the ground, don't move
+        if self.has_flags(e_id, EntityFlags::ON_GROUND)? {
+            return Ok(());
+        }
+
+        self.check_velocity(ctx, e_id)?;
+
+        let move_kind = self.try_get_entity(e_id)?.move_kind()?;
+        if move_kind != MoveKind::Fly && move_kind != MoveKind::FlyMissile {
+            self.add_gravity(ctx, e_id, frame_time)?;
+        }
+
+        let angles = self.get_vector(e_id, FieldAddrVector::Angles)?
+            + self.get_vector(e_id, FieldAddrVector::AngularVelocity)? * frame_time;
+        self.put_vector(e_id, FieldAddrVector::Angles, angles)?;
+
+        let push = self.get_vector(e_id, FieldAddrVector::Velocity)? * frame_time;
+        let (trace, hit_id) = self.push_entity(ctx, e_id, push)?;
+        if trace.ratio() == 1.0 || !self.entity_exists(e_id) {
+            return Ok(());
+        }
+
+        let backoff = if move_kind == MoveKind::Bounce {
+            1.5
+        } else {
+            1.0
+        };
+        let normal = trace_normal(&trace);
+        let velocity = clip_velocity(
+            self.get_vector(e_id, FieldAddrVector::Velocity)?,
+            normal,
+            backoff,
+        );
+        self.put_vector(e_id, FieldAddrVector::Velocity, velocity)?;
+
+        // stop if on ground
+        if normal.z > MIN_GROUND_NORMAL_Z
+            && (velocity.z < BOUNCE_STOP_SPEED || move_kind != MoveKind::Bounce)
+        {
+            let ent = self.try_get_entity_mut(e_id)?;
+            ent.add_flags(EntityFlags::ON_GROUND)?;
+            ent.put_entity_id(hit_id, FieldAddrEntityId::Ground as i16)?;
+            self.put_vector(e_id, FieldAddrVector::Velocity, Vector3::zero())?;
+            self.put_vector(e_id, FieldAddrVector::AngularVelocity, Vector3::zero())?;
+        }
+
+        self.check_water_transition(ctx, e_id)
+    }
+
+    // monsters only move through their think functions, but fall if they aren't standing on
+    // anything
+    fn physics_step(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        frame_time: f32,
+    ) -> Result<(), ProgsError> {
+        let flags = EntityFlags::ON_GROUND | EntityFlags::FLY | EntityFlags::SWIM;
+        if !self.has_flags(e_id, flags)? {
+            let gravity = ctx.cvars.get_value("sv_gravity").unwrap_or(800.0);
+            let hit_sound = self.get_vector(e_id, FieldAddrVector::Velocity)?.z < gravity * -0.1;
+
+            self.add_gravity(ctx, e_id, frame_time)?;
+            self.check_velocity(ctx, e_id)?;
+            self.fly_move(ctx, e_id, frame_time)?;
+            if !self.entity_exists(e_id) {
+                return Ok(());
+            }
+
+            self.link_entity_and_touch(ctx, e_id)?;
+
+            // just hit the ground
+            if self.entity_exists(e_id)
+                && self.has_flags(e_id, EntityFlags::ON_GROUND)?
+                && hit_sound
+            {
+                self.start_sound(ctx, e_id, LAND_SOUND)?;
+            }
+        }
+
+        if !self.entity_exists(e_id) || !self.run_think(ctx, e_id, frame_time)? {
+            return Ok(());
+        }
+
+        self.check_water_transition(ctx, e_id)
+    }
+
+    // moves a stuck player back to its last good position, or nudges it free
+    fn check_stuck(&mut self, ctx: &mut PhysicsContext, e_id: EntityId) -> Result<(), ProgsError> {
+        if !self.test_entity_position(e_id)? {
+            let origin = self.get_vector(e_id, FieldAddrVector::Origin)?;
+            return self.put_vector(e_id, FieldAddrVector::OldOrigin, origin);
+        }
+
+        let origin = self.get_vector(e_id, FieldAddrVector::Origin)?;
+        let old_origin = self.get_vector(e_id, FieldAddrVector::OldOrigin)?;
+        self.put_vector(e_id, FieldAddrVector::Origin, old_origin)?;
+        if !self.test_entity_position(e_id)? {
+            debug!("Entity {} unstuck", e_id.0);
+            return self.link_entity_and_touch(ctx, e_id);
+        }
+
+        for z in 0..MAX_UNSTICK_HEIGHT {
+            for x in -1..=1 {
+                for y in -1..=1 {
+                    let nudge = Vector3::new(x as f32, y as f32, z as f32);
+                    self.put_vector(e_id, FieldAddrVector::Origin, origin + nudge)?;
+                    if !self.test_entity_position(e_id)? {
+                        debug!("Entity {} unstuck", e_id.0);
+                        return self.link_entity_and_touch(ctx, e_id);
+                    }
+                }
+            }
+        }
+
+        debug!("Entity {} is stuck", e_id.0);
+        self.put_vector(e_id, FieldAddrVector::Origin, origin)
+    }
+
+    // slows a player running along a wall it is facing into
+    fn wall_friction(&mut self, e_id: EntityId, normal: Vector3<f32>) -> Result<(), ProgsError> {
+        let view_angle = self.get_vector(e_id, FieldAddrVector::ViewAngle)?;
+        let (forward, _, _) = frustum::view_vectors(Angles {
+            pitch: Deg(view_angle.x),
+            roll: Deg(view_angle.z),
+            yaw: Deg(view_angle.y),
+        });
+
+        let d = normal.dot(forward) + 0.5;
+        if d >= 0.0 {
+            return Ok(());
+        }
+
+        // cut the tangential velocity
+        let mut velocity = self.get_vector(e_id, FieldAddrVector::Velocity)?;
+        let side = velocity - normal * normal.dot(velocity);
+        velocity.x = side.x * (1.0 + d);
+        velocity.y = side.y * (1.0 + d);
+        self.put_vector(e_id, FieldAddrVector::Velocity, velocity)
+    }
+
+    // nudges a player that can't step up in each horizontal direction and retries the move
+    fn try_unstick(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        old_velocity: Vector3<f32>,
+    ) -> Result<Blocked, ProgsError> {
+        let old_origin = self.get_vector(e_id, FieldAddrVector::Origin)?;
+
+        for &(x, y) in UNSTICK_DIRECTIONS.iter() {
+            self.push_entity(ctx, e_id, Vector3::new(x, y, 0.0))?;
+            if !self.entity_exists(e_id) {
+                return Ok(Blocked::default());
+            }
+
+            // retry the original move
+            let velocity = Vector3::new(old_velocity.x, old_velocity.y, 0.0);
+            self.put_vector(e_id, FieldAddrVector::Velocity, velocity)?;
+            let blocked = self.fly_move(ctx, e_id, UNSTICK_MOVE_TIME)?;
+            if !self.entity_exists(e_id) {
+                return Ok(blocked);
+            }
+
+            let origin = self.get_vector(e_id, FieldAddrVector::Origin)?;
+            if (old_origin.y - origin.y).abs() > 4.0 || (old_origin.x - origin.x).abs() > 4.0 {
+                return Ok(blocked);
+            }
+
+            // go back to the original position and try again
+            self.put_vector(e_id, FieldAddrVector::Origin, old_origin)?;
+        }
+
+        self.put_vector(e_id, FieldAddrVector::Velocity, Vector3::zero())?;
+        Ok(Blocked::stuck())
+    }
+
+    // moves a walking player, stepping up stairs
+    fn physics_walk(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        frame_time: f32,
+    ) -> Result<(), ProgsError> {
+        // do a regular slide move unless it looks like we ran into a step
+        let old_on_ground = self.has_flags(e_id, EntityFlags::ON_GROUND)?;
+        self.try_get_entity_mut(e_id)?
+            .remove_flags(EntityFlags::ON_GROUND)?;
+
+        let old_origin = self.get_vector(e_id, FieldAddrVector::Origin)?;
+        let old_velocity = self.get_vector(e_id, FieldAddrVector::Velocity)?;
+        let blocked = self.fly_move(ctx, e_id, frame_time)?;
+        if !blocked.step || !self.entity_exists(e_id) {
+            return Ok(());
+        }
+
+        // don't climb stairs while jumping
+        if !old_on_ground && self.get_float(e_id, FieldAddrFloat::WaterLevel)? == 0.0 {
+            return Ok(());
+        }
+
+        // gibbed by a trigger
+        if self.try_get_entity(e_id)?.move_kind()? != MoveKind::Walk {
+            return Ok(());
+        }
+
+        if self.has_flags(e_id, EntityFlags::WATER_JUMP)? {
+            return Ok(());
+        }
+
+        let no_step_origin = self.get_vector(e_id, FieldAddrVector::Origin)?;
+        let no_step_velocity = self.get_vector(e_id, FieldAddrVector::Velocity)?;
+
+        // try moving up and forward to go up a step
+        self.put_vector(e_id, FieldAddrVector::Origin, old_origin)?;
+        let up = Vector3::new(0.0, 0.0, STEP_SIZE);
+        let down = Vector3::new(0.0, 0.0, -STEP_SIZE + old_velocity.z * frame_time);
+
+        self.push_entity(ctx, e_id, up)?;
+        if !self.entity_exists(e_id) {
+            return Ok(());
+        }
+
+        let velocity = Vector3::new(old_velocity.x, old_velocity.y, 0.0);
+        self.put_vector(e_id, FieldAddrVector::Velocity, velocity)?;
+        let mut blocked = self.fly_move(ctx, e_id, frame_time)?;
+        if !self.entity_exists(e_id) {
+            return Ok(());
+        }
+
+        // check for stuckness, possibly due to the limited precision of floats in the clipping
+        // hulls
+        if blocked.any() {
+            let origin = self.get_vector(e_id, FieldAddrVector::Origin)?;
+            if (old_origin.y - origin.y).abs() < DIST_EPSILON
+                && (old_origin.x - origin.x).abs() < DIST_EPSILON
+            {
+                // stepping up didn't make any progress
+                let wall_normal = blocked.wall_normal;
+                blocked = self.try_unstick(ctx, e_id, old_velocity)?;
+                blocked.wall_normal = wall_normal;
+                if !self.entity_exists(e_id) {
+                    return Ok(());
+                }
+            }
+        }
+
+        // extra friction based on view angle
+        if blocked.step {
+            if let Some(normal) = blocked.wall_normal {
+                self.wall_friction(e_id, normal)?;
+            }
+        }
+
+        // move down
+        let (down_trace, down_id) = self.push_entity(ctx, e_id, down)?;
+        if !self.entity_exists(e_id) {
+            return Ok(());
+        }
+
+        if trace_normal(&down_trace).z > MIN_GROUND_NORMAL_Z {
+            // as in the original engine, this only applies to brush entities, which never walk
+            if self.try_get_entity(e_id)?.solid()? == EntitySolid::Bsp {
+                let ent = self.try_get_entity_mut(e_id)?;
+                ent.add_flags(EntityFlags::ON_GROUND)?;
+                ent.put_entity_id(down_id, FieldAddrEntityId::Ground as i16)?;
+            }
+        } else {
+            // if the push down didn't end up on good ground, use the move without the step up.
+            // this happens near wall / slope combinations, and can cause the player to hop up
+            // higher on a slope too steep to climb
+            self.put_vector(e_id, FieldAddrVector::Origin, no_step_origin)?;
+            self.put_vector(e_id, FieldAddrVector::Velocity, no_step_velocity)?;
+        }
+
+        Ok(())
+    }
+
+    fn physics_client(
+        &mut self,
+        ctx: &mut PhysicsContext,
+        e_id: EntityId,
+        frame_time: f32,
+    ) -> Result<(), ProgsError> {
+        // unconnected slot
+        if !ctx.server.client_active(e_id) {
+            return Ok(());
+        }
+
+        ctx.globals
+            .put_float(ctx.time(), GlobalAddrFloat::Time as i16)?;
+        let pre_think = ctx
+            .globals
+            .get_function_id(GlobalAddrFunction::PlayerPreThink as i16)?;
+        ctx.globals
+            .put_entity_id(e_id, GlobalAddrEntity::Self_ as i16)?;
+        ctx.execution_context.execute_program(
+            ctx.globals,
+            self,
+            ctx.cvars,
+            ctx.server,
+            ctx.vfs,
+            pre_think,
+        )?;
+
+        self.check_velocity(ctx, e_id)?;
+
+        match self.try_get_entity(e_id)?.move_kind()? {
+            MoveKind::None => {
+                if !self.run_think(ctx, e_id, frame_time)? {
+                    return Ok(());
+                }
+            }
+
+            MoveKind::Walk => {
+                if !self.run_think(ctx, e_id, frame_time)? {
+                    return Ok(());
+                }
+
+                if !self.check_water(e_id)? && !self.has_flags(e_id, EntityFlags::WATER_JUMP)? {
+                    self.add_gravity(ctx, e_id, frame_time)?;
+                }
+
+                self.check_stuck(ctx, e_id)?;
+                self.physics_walk(ctx, e_id, frame_time)?;
+            }
+
+            MoveKind::Toss | MoveKind::Bounce => self.physics_toss(ctx, e_id, frame_time)?,
+
+            MoveKind::Fly => {
+                if !self.run_think(ctx, e_id, frame_time)? {
+                    return Ok(());
+                }
+
+                self.fly_move(ctx, e_id, frame_time)?;
+            }
+
+            MoveKind::NoClip => {
+                if !self.run_think(ctx, e_id, frame_time)? {
+                    return Ok(());
+                }
+
+                let origin = self.get_vector(e_id, FieldAddrVector::Origin)?
+                    + self.get_vector(e_id, FieldAddrVector::Velocity)? * frame_time;
+                self.put_vector(e_id, FieldAddrVector::Origin, origin)?;
+            }
+
+            k => {
+                return Err(ProgsError::with_msg(format!(
+                    "Bad move type for client {}: {:?}",
+                    e_id.0, k
+                )))
+            }
+        }
+
+        if !self.entity_exists(e_id) {
+            return Ok(());
+        }
+
+        self.link_entity_and_touch(ctx, e_id)?;
+
+        ctx.globals
+            .put_float(ctx.time(), GlobalAddrFloat::Time as i16)?;
+        let post_think = ctx
+            .globals
+            .get_function_id(GlobalAddrFunction::PlayerPostThink as i16)?;
+        ctx.globals
+            .put_entity_id(e_id, GlobalAddrEntity::Self_ as i16)?;
+        ctx.execution_context.execute_program(
+            ctx.globals,
+            self,
+            ctx.cvars,
+            ctx.server,
+            ctx.vfs,
+            post_think,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_near(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_brush_rotation_yaw() {
+        // a model turned 90 degrees to the left sees the world's +x axis off to its right
+        let rotation = brush_rotation(Vector3::new(0.0, 90.0, 0.0));
+        assert_near(rotation * Vector3::unit_x(), -Vector3::unit_y());
+        assert_near(rotation.transpose() * -Vector3::unit_y(), Vector3::unit_x());
+    }
+
+    #[test]
+    fn test_trace_rotate() {
+        let trace = Trace::from_hull_check(
+            TraceStart::new(Vector3::new(0.0, 0.0, 0.0), 0.0),
+            TraceEnd::boundary(Vector3::new(8.0, 0.0, 0.0), 0.5, Hyperplane::axis_x(8.0)),
+            BspLeafContents::Empty,
+            TraceFlags::default(),
+        );
+
+        let rotation = brush_rotation(Vector3::new(0.0, -90.0, 0.0));
+        let trace = trace.rotate(rotation);
+        assert_near(trace.end_point(), Vector3::new(0.0, 8.0, 0.0));
+        assert_near(trace.plane().unwrap().normal_vector(), Vector3::unit_y());
+        assert_eq!(trace.plane().unwrap().dist(), 8.0);
+        assert_eq!(trace.ratio(), 0.5);
+    }
+}